@@ -409,6 +409,51 @@ pub enum ServerCommands {
     /// Perform database maintenance
     DatabaseMaintenance {},
 
+    /// Recalculate cached mailbox counters
+    RepairMailboxCounters {
+        /// Account name to repair, defaults to all accounts
+        account: Option<String>,
+    },
+
+    /// Rebuild the full-text search index using the current language settings
+    ReindexMessages {
+        /// Account name to reindex, defaults to all accounts
+        account: Option<String>,
+    },
+
+    /// Precompute the IMAP body structure details of messages stored in earlier versions
+    BackfillStructure {
+        /// Account name to backfill, defaults to all accounts
+        account: Option<String>,
+    },
+
+    /// Verify stored blobs against their checksums and list the damaged ones
+    ScrubBlobs {},
+
+    /// Move an account to another node of the cluster
+    MoveAccount {
+        /// Account name to move
+        account: String,
+
+        /// Id of the destination node
+        node: u64,
+    },
+
+    /// Spread accounts evenly across the nodes of the cluster
+    RebalanceCluster {},
+
+    /// Export the contents of the data and blob stores to a backup file
+    Backup {
+        /// Path on the server where the backup file will be written
+        path: String,
+    },
+
+    /// Import a backup file into the data and blob stores
+    Restore {
+        /// Path on the server of the backup file to import
+        path: String,
+    },
+
     /// Reload TLS certificates
     ReloadCertificates {},
 
@@ -36,6 +36,163 @@ impl ServerCommands {
                     .await;
                 eprintln!("Success.");
             }
+            ServerCommands::RepairMailboxCounters { account } => {
+                let result = client
+                    .http_request::<Value, String>(
+                        Method::GET,
+                        &format!("/api/store/repair/{}", account.unwrap_or_default()),
+                        None,
+                    )
+                    .await;
+                eprintln!(
+                    "Repaired counters of {} mailbox(es).",
+                    result
+                        .get("mailboxes")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_default()
+                );
+            }
+            ServerCommands::ReindexMessages { account } => {
+                let result = client
+                    .http_request::<Value, String>(
+                        Method::GET,
+                        &format!("/api/store/reindex/{}", account.unwrap_or_default()),
+                        None,
+                    )
+                    .await;
+                eprintln!(
+                    "Queued {} message(s) for reindexing.",
+                    result
+                        .get("messages")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_default()
+                );
+            }
+            ServerCommands::BackfillStructure { account } => {
+                let result = client
+                    .http_request::<Value, String>(
+                        Method::GET,
+                        &format!("/api/store/backfill/{}", account.unwrap_or_default()),
+                        None,
+                    )
+                    .await;
+                eprintln!(
+                    "Computed the body structure of {} message(s).",
+                    result
+                        .get("messages")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_default()
+                );
+            }
+            ServerCommands::ScrubBlobs {} => {
+                let mut verified = 0;
+                let mut repaired = 0;
+                loop {
+                    let result = client
+                        .http_request::<Value, String>(Method::POST, "/api/store/scrub", None)
+                        .await;
+                    verified += result
+                        .get("verified")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_default();
+                    repaired += result
+                        .get("repaired")
+                        .and_then(|v| v.as_array())
+                        .map_or(0, |v| v.len());
+                    if result
+                        .get("complete")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(true)
+                    {
+                        break;
+                    }
+                }
+                eprintln!("Verified {verified} blob(s), repaired {repaired} blob(s).");
+
+                let results = client
+                    .http_request::<Vec<Value>, String>(Method::GET, "/api/store/quarantine", None)
+                    .await;
+                if !results.is_empty() {
+                    let mut table = Table::new();
+                    table.add_row(Row::new(vec![
+                        Cell::new("Blob Id").with_style(Attr::Bold),
+                        Cell::new("Detected").with_style(Attr::Bold),
+                    ]));
+
+                    for result in &results {
+                        table.add_row(Row::new(vec![
+                            Cell::new(
+                                result
+                                    .get("blobId")
+                                    .and_then(|v| v.as_str())
+                                    .unwrap_or_default(),
+                            ),
+                            Cell::new(
+                                &result
+                                    .get("detected")
+                                    .and_then(|v| v.as_u64())
+                                    .unwrap_or_default()
+                                    .to_string(),
+                            ),
+                        ]));
+                    }
+
+                    eprintln!();
+                    table.printstd();
+                    eprintln!();
+                }
+            }
+            ServerCommands::MoveAccount { account, node } => {
+                let result = client
+                    .http_request::<Value, String>(
+                        Method::POST,
+                        &format!("/api/cluster/placement/{account}/{node}"),
+                        None,
+                    )
+                    .await;
+                match result.get("migratingFrom").and_then(|v| v.as_u64()) {
+                    Some(from) => {
+                        eprintln!("Moving account {account} from node {from} to node {node}.")
+                    }
+                    None => eprintln!("Account {account} is placed on node {node}."),
+                }
+            }
+            ServerCommands::RebalanceCluster {} => {
+                let result = client
+                    .http_request::<Value, String>(Method::POST, "/api/cluster/rebalance", None)
+                    .await;
+                eprintln!(
+                    "Moved {} account(s).",
+                    result
+                        .get("moved")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_default()
+                );
+            }
+            ServerCommands::Backup { path } => {
+                let stats = client
+                    .http_request::<Value, String>(
+                        Method::POST,
+                        &form_urlencoded::Serializer::new("/api/store/backup?".to_string())
+                            .append_pair("path", &path)
+                            .finish(),
+                        None,
+                    )
+                    .await;
+                print_backup_stats("Exported", &stats);
+            }
+            ServerCommands::Restore { path } => {
+                let stats = client
+                    .http_request::<Value, String>(
+                        Method::POST,
+                        &form_urlencoded::Serializer::new("/api/store/restore?".to_string())
+                            .append_pair("path", &path)
+                            .finish(),
+                        None,
+                    )
+                    .await;
+                print_backup_stats("Imported", &stats);
+            }
             ServerCommands::ReloadCertificates {} => {
                 client
                     .http_request::<Value, String>(Method::GET, "/api/reload/certificates", None)
@@ -43,10 +200,10 @@ impl ServerCommands {
                 eprintln!("Success.");
             }
             ServerCommands::ReloadConfig {} => {
-                client
-                    .http_request::<Value, String>(Method::GET, "/api/reload/config", None)
+                let reloaded = client
+                    .http_request::<Vec<String>, String>(Method::GET, "/api/reload/config", None)
                     .await;
-                eprintln!("Success.");
+                eprintln!("Successfully reloaded {}.", reloaded.join(", "));
             }
             ServerCommands::AddConfig { key, value } => {
                 client
@@ -102,3 +259,16 @@ impl ServerCommands {
         }
     }
 }
+
+fn print_backup_stats(action: &str, stats: &Value) {
+    let count = |name: &str| stats.get(name).and_then(|v| v.as_u64()).unwrap_or_default();
+    eprintln!(
+        "{action} {} values, {} counters, {} index keys, {} bitmaps, {} log entries and {} blobs.",
+        count("values"),
+        count("counters"),
+        count("indexes"),
+        count("bitmaps"),
+        count("logs"),
+        count("blobs")
+    );
+}
@@ -54,6 +54,22 @@ pub struct Domain {
     pub next_notify: Option<DateTime>,
     #[serde(deserialize_with = "deserialize_datetime")]
     pub expires: DateTime,
+    #[serde(default)]
+    pub tls_trace: Vec<TlsTrace>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct TlsTrace {
+    pub attempted_at: u64,
+    pub entries: Vec<TlsTraceEntry>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct TlsTraceEntry {
+    pub source: String,
+    pub mx: Option<String>,
+    pub requires: String,
+    pub outcome: String,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -257,6 +273,31 @@ impl QueueCommands {
                                 Cell::new("Expires").with_style(Attr::Bold),
                                 Cell::new(&domain.expires.to_rfc822()),
                             ]));
+                            if let Some(trace) = domain.tls_trace.last() {
+                                table.add_row(Row::new(vec![
+                                    Cell::new("TLS Trace").with_style(Attr::Bold),
+                                    Cell::new(
+                                        &trace
+                                            .entries
+                                            .iter()
+                                            .map(|entry| {
+                                                format!(
+                                                    "{}{}: {} -> {}",
+                                                    entry.source,
+                                                    entry
+                                                        .mx
+                                                        .as_ref()
+                                                        .map(|mx| format!(" [{mx}]"))
+                                                        .unwrap_or_default(),
+                                                    entry.requires,
+                                                    entry.outcome
+                                                )
+                                            })
+                                            .collect::<Vec<_>>()
+                                            .join("\n"),
+                                    ),
+                                ]));
+                            }
 
                             let mut rcpts = Table::new();
                             rcpts.add_row(Row::new(vec![
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc};
+
+use hyper::{body, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use jmap::JMAP;
+use utils::listener::{SessionData, SessionManager, SessionStream};
+
+pub mod request;
+pub mod store;
+pub mod xml;
+
+/// Serves CalDAV and CardDAV clients from the same store used by JMAP.
+#[derive(Clone)]
+pub struct DavSessionManager {
+    pub inner: Arc<JMAP>,
+}
+
+impl DavSessionManager {
+    pub fn new(inner: Arc<JMAP>) -> Self {
+        Self { inner }
+    }
+}
+
+impl SessionManager for DavSessionManager {
+    fn handle<T: SessionStream>(
+        self,
+        session: SessionData<T>,
+    ) -> impl std::future::Future<Output = ()> + Send {
+        handle_session(self.inner, session)
+    }
+
+    #[allow(clippy::manual_async_fn)]
+    fn shutdown(&self) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
+
+    fn is_ip_blocked(&self, addr: &IpAddr) -> bool {
+        self.inner.directory.blocked_ips.is_blocked(addr)
+    }
+}
+
+async fn handle_session<T: SessionStream>(jmap: Arc<JMAP>, session: SessionData<T>) {
+    let span = session.span;
+    let _in_flight = session.in_flight;
+
+    if let Err(http_err) = http1::Builder::new()
+        .keep_alive(true)
+        .serve_connection(
+            TokioIo::new(session.stream),
+            service_fn(|req: hyper::Request<body::Incoming>| {
+                let jmap = jmap.clone();
+                let span = span.clone();
+
+                async move {
+                    tracing::debug!(
+                        parent: &span,
+                        event = "request",
+                        method = req.method().as_str(),
+                        uri = req.uri().to_string(),
+                    );
+
+                    let mut response =
+                        request::parse_dav_request(&jmap, req, session.remote_ip).await;
+
+                    // Add custom headers
+                    if !jmap.config.http_headers.is_empty() {
+                        let headers = response.headers_mut();
+
+                        for (header, value) in &jmap.config.http_headers {
+                            headers.insert(header.clone(), value.clone());
+                        }
+                    }
+
+                    Ok::<_, hyper::Error>(response)
+                }
+            }),
+        )
+        .await
+    {
+        tracing::debug!(
+            parent: &span,
+            event = "error",
+            context = "dav",
+            reason = %http_err,
+        );
+    }
+}
@@ -0,0 +1,985 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc};
+
+use directory::QueryBy;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{self, HeaderName, HeaderValue},
+    Method, StatusCode,
+};
+use jmap::{
+    api::{
+        http::{fetch_body, ToHttpResponse},
+        HttpRequest, HttpResponse,
+    },
+    auth::AccessToken,
+    JMAP,
+};
+use jmap_proto::error::method::MethodError;
+
+use crate::{
+    store::{DavCollection, DavKind, DavResource, DavStore},
+    xml::{
+        escape, Element, MultiStatus, PropStat, Request, NS_CALDAV, NS_CALSERVER, NS_CARDDAV,
+        NS_DAV,
+    },
+};
+
+const DAV_ALLOW: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, REPORT, MKCOL, MKCALENDAR";
+const DAV_COMPLIANCE: &str = "1, 3, calendar-access, addressbook";
+const MAX_DEFAULT_RETRIES: usize = 3;
+
+enum DavPath {
+    Root,
+    Principal(String),
+    Home(DavKind, String),
+    Collection(DavKind, String, String),
+    Resource(DavKind, String, String, String),
+}
+
+struct DavContext<'x> {
+    jmap: &'x JMAP,
+    access_token: &'x AccessToken,
+    account_id: u32,
+    account_name: String,
+}
+
+struct DavResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, String)>,
+    body: Vec<u8>,
+}
+
+pub async fn parse_dav_request(
+    jmap: &Arc<JMAP>,
+    mut req: HttpRequest,
+    remote_ip: IpAddr,
+) -> HttpResponse {
+    let path = req.uri().path().to_string();
+
+    // Service discovery (RFC 6764)
+    if path.starts_with("/.well-known/caldav") || path.starts_with("/.well-known/carddav") {
+        return DavResponse::new(StatusCode::MOVED_PERMANENTLY)
+            .with_header(header::LOCATION, "/dav/")
+            .into_http_response();
+    } else if path != "/dav" && !path.starts_with("/dav/") {
+        return DavResponse::new(StatusCode::NOT_FOUND).into_http_response();
+    } else if req.method() == Method::OPTIONS {
+        return DavResponse::new(StatusCode::OK)
+            .with_header(HeaderName::from_static("dav"), DAV_COMPLIANCE)
+            .with_header(header::ALLOW, DAV_ALLOW)
+            .into_http_response();
+    }
+
+    // Authenticate request
+    let (_in_flight, access_token) = match jmap.authenticate_headers(&req, remote_ip).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            return DavResponse::new(StatusCode::UNAUTHORIZED)
+                .with_header(header::WWW_AUTHENTICATE, "Basic realm=\"Stalwart DAV\"")
+                .into_http_response();
+        }
+        Err(err) => return err.into_http_response(),
+    };
+
+    let path = match DavPath::parse(&path) {
+        Some(path) => path,
+        None => return DavResponse::new(StatusCode::NOT_FOUND).into_http_response(),
+    };
+    let body = match fetch_body(&mut req, jmap.config.upload_max_size, &access_token).await {
+        Some(body) => body,
+        None => return DavResponse::new(StatusCode::PAYLOAD_TOO_LARGE).into_http_response(),
+    };
+
+    // Resolve the account being accessed
+    let ctx = match path.account_name() {
+        Some(name) if name != access_token.name => {
+            match jmap.directory.query(QueryBy::Name(name), false).await {
+                Ok(Some(principal)) if access_token.is_member(principal.id) => DavContext {
+                    jmap,
+                    access_token: &access_token,
+                    account_id: principal.id,
+                    account_name: principal.name,
+                },
+                Ok(_) => return DavResponse::new(StatusCode::FORBIDDEN).into_http_response(),
+                Err(_) => {
+                    return DavResponse::new(StatusCode::SERVICE_UNAVAILABLE).into_http_response()
+                }
+            }
+        }
+        _ => DavContext {
+            jmap,
+            access_token: &access_token,
+            account_id: access_token.primary_id(),
+            account_name: access_token.name.clone(),
+        },
+    };
+
+    let depth = req
+        .headers()
+        .get("Depth")
+        .and_then(|h| h.to_str().ok())
+        .map_or(1, |h| if h.trim() == "0" { 0 } else { 1 });
+    let if_match = req
+        .headers()
+        .get(header::IF_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+    let if_none_match = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+
+    let result =
+        match (req.method().as_str(), path) {
+            ("PROPFIND", path) => {
+                ctx.handle_propfind(
+                    path,
+                    depth,
+                    &Request::parse(&String::from_utf8_lossy(&body)),
+                )
+                .await
+            }
+            ("REPORT", DavPath::Collection(kind, _, name)) => {
+                ctx.handle_report(
+                    kind,
+                    &name,
+                    &Request::parse(&String::from_utf8_lossy(&body)),
+                )
+                .await
+            }
+            ("PROPPATCH", DavPath::Collection(kind, _, name)) => {
+                ctx.handle_proppatch(
+                    kind,
+                    &name,
+                    &Request::parse(&String::from_utf8_lossy(&body)),
+                )
+                .await
+            }
+            ("MKCALENDAR" | "MKCOL", DavPath::Collection(kind, _, name)) => {
+                ctx.handle_mkcol(kind, name, &Request::parse(&String::from_utf8_lossy(&body)))
+                    .await
+            }
+            ("GET" | "HEAD", DavPath::Resource(kind, _, collection, name)) => {
+                ctx.handle_get(kind, &collection, &name, req.method() == Method::HEAD)
+                    .await
+            }
+            ("PUT", DavPath::Resource(kind, _, collection, name)) => {
+                ctx.handle_put(
+                    kind,
+                    &collection,
+                    &name,
+                    body,
+                    if_match.as_deref(),
+                    if_none_match.as_deref(),
+                )
+                .await
+            }
+            ("DELETE", DavPath::Collection(kind, _, name)) => {
+                ctx.handle_delete_collection(kind, &name).await
+            }
+            ("DELETE", DavPath::Resource(kind, _, collection, name)) => {
+                ctx.handle_delete(kind, &collection, &name, if_match.as_deref())
+                    .await
+            }
+            ("GET" | "HEAD", _) => Ok(DavResponse::new(StatusCode::OK)
+                .with_header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .with_body(b"Stalwart CalDAV/CardDAV server".to_vec())),
+            _ => Ok(DavResponse::new(StatusCode::METHOD_NOT_ALLOWED)
+                .with_header(header::ALLOW, DAV_ALLOW)),
+        };
+
+    match result {
+        Ok(response) => response,
+        Err(_) => DavResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+    .into_http_response()
+}
+
+impl DavContext<'_> {
+    async fn handle_propfind(
+        &self,
+        path: DavPath,
+        depth: u32,
+        request: &Request,
+    ) -> Result<DavResponse, MethodError> {
+        let mut response = MultiStatus::new();
+
+        match path {
+            DavPath::Root => {
+                let props = vec![
+                    (
+                        Element::new(NS_DAV, "resourcetype"),
+                        "<D:collection/>".into(),
+                    ),
+                    self.current_user_principal(),
+                ];
+                response.add_response("/dav/", select_props(props, request));
+            }
+            DavPath::Principal(_) => {
+                let href = format!("/dav/principal/{}/", encode(&self.account_name));
+                let props = vec![
+                    (
+                        Element::new(NS_DAV, "resourcetype"),
+                        "<D:collection/><D:principal/>".into(),
+                    ),
+                    (
+                        Element::new(NS_DAV, "displayname"),
+                        escape(&self.account_name),
+                    ),
+                    self.current_user_principal(),
+                    (
+                        Element::new(NS_DAV, "principal-URL"),
+                        format!("<D:href>{}</D:href>", escape(&href)),
+                    ),
+                    (
+                        Element::new(NS_CALDAV, "calendar-home-set"),
+                        format!(
+                            "<D:href>{}</D:href>",
+                            escape(&self.home_href(DavKind::Calendar))
+                        ),
+                    ),
+                    (
+                        Element::new(NS_CARDDAV, "addressbook-home-set"),
+                        format!(
+                            "<D:href>{}</D:href>",
+                            escape(&self.home_href(DavKind::AddressBook))
+                        ),
+                    ),
+                ];
+                response.add_response(&href, select_props(props, request));
+            }
+            DavPath::Home(kind, _) => {
+                let props = vec![
+                    (
+                        Element::new(NS_DAV, "resourcetype"),
+                        "<D:collection/>".into(),
+                    ),
+                    self.current_user_principal(),
+                    self.owner(),
+                ];
+                response.add_response(&self.home_href(kind), select_props(props, request));
+
+                if depth > 0 {
+                    let state = self.jmap.dav_state(self.account_id).await?;
+                    for (_, collection) in self.collections(kind).await? {
+                        response.add_response(
+                            &self.collection_href(kind, &collection.name),
+                            select_props(self.collection_props(&collection, &state), request),
+                        );
+                    }
+                }
+            }
+            DavPath::Collection(kind, _, name) => {
+                let (document_id, collection) = match self.collection(kind, &name).await? {
+                    Some(collection) => collection,
+                    None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+                };
+                let state = self.jmap.dav_state(self.account_id).await?;
+                response.add_response(
+                    &self.collection_href(kind, &collection.name),
+                    select_props(self.collection_props(&collection, &state), request),
+                );
+
+                if depth > 0 {
+                    for (_, resource) in self
+                        .jmap
+                        .dav_resources(self.account_id, document_id)
+                        .await?
+                    {
+                        response.add_response(
+                            &self.resource_href(kind, &collection.name, &resource.name),
+                            select_props(self.resource_props(kind, &resource, None), request),
+                        );
+                    }
+                }
+            }
+            DavPath::Resource(kind, _, collection, name) => {
+                match self.resource(kind, &collection, &name).await? {
+                    Some((_, _, resource)) => {
+                        response.add_response(
+                            &self.resource_href(kind, &collection, &resource.name),
+                            select_props(self.resource_props(kind, &resource, None), request),
+                        );
+                    }
+                    None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+                }
+            }
+        }
+
+        Ok(DavResponse::multi_status(response))
+    }
+
+    async fn handle_report(
+        &self,
+        kind: DavKind,
+        name: &str,
+        request: &Request,
+    ) -> Result<DavResponse, MethodError> {
+        let (document_id, collection) = match self.collection(kind, name).await? {
+            Some(collection) => collection,
+            None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+        };
+        let resources = self
+            .jmap
+            .dav_resources(self.account_id, document_id)
+            .await?;
+        let (data_ns, data_name) = match kind {
+            DavKind::Calendar => (NS_CALDAV, "calendar-data"),
+            DavKind::AddressBook => (NS_CARDDAV, "address-data"),
+        };
+        let mut response = MultiStatus::new();
+
+        match request.root.as_ref() {
+            Some(root)
+                if root.is(NS_CALDAV, "calendar-multiget")
+                    || root.is(NS_CARDDAV, "addressbook-multiget") =>
+            {
+                for href in &request.hrefs {
+                    let resource_name = href
+                        .trim_end_matches('/')
+                        .rsplit('/')
+                        .next()
+                        .map(decode)
+                        .unwrap_or_default();
+                    if let Some((_, resource)) =
+                        resources.iter().find(|(_, r)| r.name == resource_name)
+                    {
+                        let data = self.resource_data(resource).await?;
+                        response.add_response(
+                            href,
+                            select_props(self.resource_props(kind, resource, data), request),
+                        );
+                    } else {
+                        response.add_status(href, "404 Not Found");
+                    }
+                }
+            }
+            Some(root)
+                if root.is(NS_CALDAV, "calendar-query")
+                    || root.is(NS_CARDDAV, "addressbook-query") =>
+            {
+                // Filters are not evaluated, clients receive the whole collection
+                let wants_data = request.props.as_ref().map_or(true, |props| {
+                    props.iter().any(|prop| prop.is(data_ns, data_name))
+                });
+                for (_, resource) in &resources {
+                    let data = if wants_data {
+                        self.resource_data(resource).await?
+                    } else {
+                        None
+                    };
+                    response.add_response(
+                        &self.resource_href(kind, &collection.name, &resource.name),
+                        select_props(self.resource_props(kind, resource, data), request),
+                    );
+                }
+            }
+            _ => return Ok(DavResponse::new(StatusCode::FORBIDDEN)),
+        }
+
+        Ok(DavResponse::multi_status(response))
+    }
+
+    async fn handle_proppatch(
+        &self,
+        kind: DavKind,
+        name: &str,
+        request: &Request,
+    ) -> Result<DavResponse, MethodError> {
+        let (document_id, mut collection) = match self.collection(kind, name).await? {
+            Some(collection) => collection,
+            None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+        };
+        let mut updated = Vec::new();
+        let mut forbidden = Vec::new();
+        for prop in request.props.iter().flatten() {
+            if prop.is(NS_DAV, "displayname")
+                || prop.is(NS_CALDAV, "calendar-description")
+                || prop.is(NS_CARDDAV, "addressbook-description")
+            {
+                updated.push(prop);
+            } else {
+                forbidden.push(prop);
+            }
+        }
+
+        // Changes are applied atomically, nothing is updated if a property is rejected
+        if forbidden.is_empty() {
+            if let Some(display_name) = &request.display_name {
+                collection.display_name = display_name.clone();
+            }
+            if request.description.is_some() {
+                collection.description = request.description.clone();
+            }
+            self.jmap
+                .dav_collection_update(self.account_id, document_id, collection)
+                .await?;
+        }
+
+        let mut response = MultiStatus::new();
+        response.add_proppatch(&self.collection_href(kind, name), updated, forbidden);
+        Ok(DavResponse::multi_status(response))
+    }
+
+    async fn handle_mkcol(
+        &self,
+        kind: DavKind,
+        name: String,
+        request: &Request,
+    ) -> Result<DavResponse, MethodError> {
+        if self.collection(kind, &name).await?.is_some() {
+            return Ok(DavResponse::new(StatusCode::METHOD_NOT_ALLOWED)
+                .with_header(header::ALLOW, DAV_ALLOW));
+        }
+
+        self.jmap
+            .dav_collection_create(
+                self.account_id,
+                DavCollection {
+                    kind,
+                    display_name: request.display_name.clone().unwrap_or_else(|| name.clone()),
+                    description: request.description.clone(),
+                    name,
+                },
+            )
+            .await?;
+
+        Ok(DavResponse::new(StatusCode::CREATED))
+    }
+
+    async fn handle_get(
+        &self,
+        kind: DavKind,
+        collection: &str,
+        name: &str,
+        is_head: bool,
+    ) -> Result<DavResponse, MethodError> {
+        let resource = match self.resource(kind, collection, name).await? {
+            Some((_, _, resource)) => resource,
+            None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+        };
+        let response = DavResponse::new(StatusCode::OK)
+            .with_header(header::CONTENT_TYPE, kind.content_type())
+            .with_header(header::ETAG, resource.etag());
+
+        if !is_head {
+            match self.resource_data(&resource).await? {
+                Some(data) => Ok(response.with_body(data.into_bytes())),
+                None => Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+            }
+        } else {
+            Ok(response.with_header(header::CONTENT_LENGTH, resource.size.to_string()))
+        }
+    }
+
+    async fn handle_put(
+        &self,
+        kind: DavKind,
+        collection: &str,
+        name: &str,
+        body: Vec<u8>,
+        if_match: Option<&str>,
+        if_none_match: Option<&str>,
+    ) -> Result<DavResponse, MethodError> {
+        let parent_id = match self.collection(kind, collection).await? {
+            Some((parent_id, _)) => parent_id,
+            None => return Ok(DavResponse::new(StatusCode::CONFLICT)),
+        };
+        if !kind.is_valid_object(&body) {
+            return Ok(DavResponse::new(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        }
+
+        let current = self
+            .jmap
+            .dav_resource_by_name(self.account_id, parent_id, name)
+            .await?;
+        let etag = current
+            .as_ref()
+            .map(|(_, resource)| resource.inner.inner.etag());
+        if !matches_precondition(etag.as_deref(), if_match, if_none_match) {
+            return Ok(DavResponse::new(StatusCode::PRECONDITION_FAILED));
+        }
+
+        // Check quota
+        let quota = self
+            .jmap
+            .get_quota(self.access_token, self.account_id)
+            .await?;
+        let size_delta = body.len() as i64
+            - current
+                .as_ref()
+                .map_or(0, |(_, resource)| resource.inner.inner.size as i64);
+        if quota > 0
+            && size_delta > 0
+            && size_delta + self.jmap.get_used_quota(self.account_id).await? > quota
+        {
+            return Ok(DavResponse::new(StatusCode::INSUFFICIENT_STORAGE));
+        }
+
+        let status = if current.is_some() {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        };
+        match self
+            .jmap
+            .dav_resource_put(self.account_id, parent_id, name, current, &body)
+            .await?
+        {
+            Some(resource) => {
+                Ok(DavResponse::new(status).with_header(header::ETAG, resource.etag()))
+            }
+            None => Ok(DavResponse::new(StatusCode::PRECONDITION_FAILED)),
+        }
+    }
+
+    async fn handle_delete(
+        &self,
+        kind: DavKind,
+        collection: &str,
+        name: &str,
+        if_match: Option<&str>,
+    ) -> Result<DavResponse, MethodError> {
+        let (document_id, resource) = match self.resource(kind, collection, name).await? {
+            Some((_, document_id, resource)) => (document_id, resource),
+            None => return Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+        };
+        if !matches_precondition(Some(&resource.etag()), if_match, None) {
+            return Ok(DavResponse::new(StatusCode::PRECONDITION_FAILED));
+        }
+
+        self.jmap
+            .dav_resource_delete(self.account_id, document_id, resource)
+            .await?;
+        Ok(DavResponse::new(StatusCode::NO_CONTENT))
+    }
+
+    async fn handle_delete_collection(
+        &self,
+        kind: DavKind,
+        name: &str,
+    ) -> Result<DavResponse, MethodError> {
+        match self.collection(kind, name).await? {
+            Some((document_id, _)) => {
+                self.jmap
+                    .dav_collection_delete(self.account_id, document_id)
+                    .await?;
+                Ok(DavResponse::new(StatusCode::NO_CONTENT))
+            }
+            None => Ok(DavResponse::new(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    /// Returns the calendars or address books of the account, creating a
+    /// default one the first time they are accessed.
+    async fn collections(&self, kind: DavKind) -> Result<Vec<(u32, DavCollection)>, MethodError> {
+        for _ in 0..MAX_DEFAULT_RETRIES {
+            let collections = self.jmap.dav_collections(self.account_id, kind).await?;
+            if !collections.is_empty() || !self.access_token.is_primary_id(self.account_id) {
+                return Ok(collections);
+            }
+
+            // Retry if a concurrent request created the default collections first
+            let collection = DavCollection {
+                kind,
+                name: "default".to_string(),
+                display_name: match kind {
+                    DavKind::Calendar => "Calendar",
+                    DavKind::AddressBook => "Contacts",
+                }
+                .to_string(),
+                description: None,
+            };
+            if let Some(document_id) = self
+                .jmap
+                .dav_default_collection_create(self.account_id, collection.clone())
+                .await?
+            {
+                return Ok(vec![(document_id, collection)]);
+            }
+        }
+
+        Err(MethodError::ServerUnavailable)
+    }
+
+    async fn collection(
+        &self,
+        kind: DavKind,
+        name: &str,
+    ) -> Result<Option<(u32, DavCollection)>, MethodError> {
+        Ok(self
+            .collections(kind)
+            .await?
+            .into_iter()
+            .find(|(_, collection)| collection.name == name))
+    }
+
+    async fn resource(
+        &self,
+        kind: DavKind,
+        collection: &str,
+        name: &str,
+    ) -> Result<Option<(u32, u32, DavResource)>, MethodError> {
+        if let Some((parent_id, _)) = self.collection(kind, collection).await? {
+            Ok(self
+                .jmap
+                .dav_resource_by_name(self.account_id, parent_id, name)
+                .await?
+                .map(|(document_id, resource)| (parent_id, document_id, resource.inner.inner)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn resource_data(&self, resource: &DavResource) -> Result<Option<String>, MethodError> {
+        Ok(self
+            .jmap
+            .get_blob(&resource.blob_hash, 0..u32::MAX)
+            .await?
+            .map(|data| String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn collection_props(&self, collection: &DavCollection, state: &str) -> Vec<(Element, String)> {
+        let mut props = vec![
+            (
+                Element::new(NS_DAV, "displayname"),
+                escape(&collection.display_name),
+            ),
+            (Element::new(NS_CALSERVER, "getctag"), escape(state)),
+            self.current_user_principal(),
+            self.owner(),
+            (
+                Element::new(NS_DAV, "current-user-privilege-set"),
+                "<D:privilege><D:read/></D:privilege><D:privilege><D:write/></D:privilege>".into(),
+            ),
+        ];
+        let description = collection
+            .description
+            .as_deref()
+            .map(escape)
+            .unwrap_or_default();
+
+        match collection.kind {
+            DavKind::Calendar => {
+                props.extend([
+                    (
+                        Element::new(NS_DAV, "resourcetype"),
+                        "<D:collection/><C:calendar/>".into(),
+                    ),
+                    (
+                        Element::new(NS_CALDAV, "calendar-description"),
+                        description,
+                    ),
+                    (
+                        Element::new(NS_CALDAV, "supported-calendar-component-set"),
+                        concat!(
+                            "<C:comp name=\"VEVENT\"/>",
+                            "<C:comp name=\"VTODO\"/>",
+                            "<C:comp name=\"VJOURNAL\"/>"
+                        )
+                        .into(),
+                    ),
+                    (
+                        Element::new(NS_DAV, "supported-report-set"),
+                        concat!(
+                            "<D:supported-report><D:report><C:calendar-multiget/></D:report></D:supported-report>",
+                            "<D:supported-report><D:report><C:calendar-query/></D:report></D:supported-report>"
+                        )
+                        .into(),
+                    ),
+                ]);
+            }
+            DavKind::AddressBook => {
+                props.extend([
+                    (
+                        Element::new(NS_DAV, "resourcetype"),
+                        "<D:collection/><CR:addressbook/>".into(),
+                    ),
+                    (
+                        Element::new(NS_CARDDAV, "addressbook-description"),
+                        description,
+                    ),
+                    (
+                        Element::new(NS_DAV, "supported-report-set"),
+                        concat!(
+                            "<D:supported-report><D:report><CR:addressbook-multiget/></D:report></D:supported-report>",
+                            "<D:supported-report><D:report><CR:addressbook-query/></D:report></D:supported-report>"
+                        )
+                        .into(),
+                    ),
+                ]);
+            }
+        }
+
+        props
+    }
+
+    fn resource_props(
+        &self,
+        kind: DavKind,
+        resource: &DavResource,
+        data: Option<String>,
+    ) -> Vec<(Element, String)> {
+        let mut props = vec![
+            (Element::new(NS_DAV, "resourcetype"), String::new()),
+            (Element::new(NS_DAV, "getetag"), escape(&resource.etag())),
+            (
+                Element::new(NS_DAV, "getcontenttype"),
+                kind.content_type().to_string(),
+            ),
+            (
+                Element::new(NS_DAV, "getcontentlength"),
+                resource.size.to_string(),
+            ),
+        ];
+        if let Some(data) = data {
+            props.push(match kind {
+                DavKind::Calendar => (Element::new(NS_CALDAV, "calendar-data"), escape(&data)),
+                DavKind::AddressBook => (Element::new(NS_CARDDAV, "address-data"), escape(&data)),
+            });
+        }
+        props
+    }
+
+    fn current_user_principal(&self) -> (Element, String) {
+        (
+            Element::new(NS_DAV, "current-user-principal"),
+            format!(
+                "<D:href>/dav/principal/{}/</D:href>",
+                escape(&encode(&self.access_token.name))
+            ),
+        )
+    }
+
+    fn owner(&self) -> (Element, String) {
+        (
+            Element::new(NS_DAV, "owner"),
+            format!(
+                "<D:href>/dav/principal/{}/</D:href>",
+                escape(&encode(&self.account_name))
+            ),
+        )
+    }
+
+    fn home_href(&self, kind: DavKind) -> String {
+        format!("/dav/{}/{}/", kind.path(), encode(&self.account_name))
+    }
+
+    fn collection_href(&self, kind: DavKind, collection: &str) -> String {
+        format!(
+            "/dav/{}/{}/{}/",
+            kind.path(),
+            encode(&self.account_name),
+            encode(collection)
+        )
+    }
+
+    fn resource_href(&self, kind: DavKind, collection: &str, name: &str) -> String {
+        format!(
+            "/dav/{}/{}/{}/{}",
+            kind.path(),
+            encode(&self.account_name),
+            encode(collection),
+            encode(name)
+        )
+    }
+}
+
+/// Returns the requested properties, or all of them for `allprop` requests.
+fn select_props(props: Vec<(Element, String)>, request: &Request) -> PropStat {
+    match &request.props {
+        Some(requested) => {
+            let mut propstat = PropStat::default();
+            for element in requested {
+                if let Some((_, value)) = props.iter().find(|(prop, _)| prop == element) {
+                    propstat.found.push((element.clone(), value.clone()));
+                } else {
+                    propstat.not_found.push(element.clone());
+                }
+            }
+            propstat
+        }
+        None => PropStat {
+            found: props
+                .into_iter()
+                .filter(|(prop, _)| {
+                    !prop.is(NS_CALDAV, "calendar-data") && !prop.is(NS_CARDDAV, "address-data")
+                })
+                .collect(),
+            not_found: vec![],
+        },
+    }
+}
+
+fn matches_precondition(
+    etag: Option<&str>,
+    if_match: Option<&str>,
+    if_none_match: Option<&str>,
+) -> bool {
+    let matches_any = |header: &str| {
+        header.split(',').any(|tag| {
+            let tag = tag.trim();
+            (tag == "*" && etag.is_some()) || etag == Some(tag.trim_start_matches("W/"))
+        })
+    };
+
+    if_match.map_or(true, matches_any) && !if_none_match.map_or(false, matches_any)
+}
+
+impl DavPath {
+    fn parse(path: &str) -> Option<Self> {
+        let mut segments = path
+            .trim_start_matches("/dav")
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(decode);
+        let path = match segments.next() {
+            None => DavPath::Root,
+            Some(root) if root == "principal" => DavPath::Principal(segments.next()?),
+            Some(root) => {
+                let kind = match root.as_str() {
+                    "cal" => DavKind::Calendar,
+                    "card" => DavKind::AddressBook,
+                    _ => return None,
+                };
+                let account = segments.next()?;
+                match (segments.next(), segments.next()) {
+                    (None, _) => DavPath::Home(kind, account),
+                    (Some(collection), None) => DavPath::Collection(kind, account, collection),
+                    (Some(collection), Some(name)) => {
+                        DavPath::Resource(kind, account, collection, name)
+                    }
+                }
+            }
+        };
+
+        if segments.next().is_none() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn account_name(&self) -> Option<&str> {
+        match self {
+            DavPath::Root => None,
+            DavPath::Principal(account)
+            | DavPath::Home(_, account)
+            | DavPath::Collection(_, account, _)
+            | DavPath::Resource(_, account, _, _) => Some(account),
+        }
+    }
+}
+
+impl DavKind {
+    fn path(&self) -> &'static str {
+        match self {
+            DavKind::Calendar => "cal",
+            DavKind::AddressBook => "card",
+        }
+    }
+}
+
+impl DavResponse {
+    fn new(status: StatusCode) -> Self {
+        DavResponse {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn multi_status(response: MultiStatus) -> Self {
+        DavResponse::new(StatusCode::MULTI_STATUS)
+            .with_header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .with_body(response.finish().into_bytes())
+    }
+
+    fn with_header(mut self, name: HeaderName, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+impl ToHttpResponse for DavResponse {
+    fn into_http_response(self) -> HttpResponse {
+        let mut response = hyper::Response::builder().status(self.status);
+        for (name, value) in self.headers {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response = response.header(name, value);
+            }
+        }
+        response
+            .body(
+                Full::new(Bytes::from(self.body))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+fn encode(segment: &str) -> String {
+    let mut result = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~@+".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            result.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    result
+}
+
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] == b'%' {
+            if let Some(byte) = segment
+                .get(pos + 1..pos + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                result.push(byte);
+                pos += 3;
+                continue;
+            }
+        }
+        result.push(bytes[pos]);
+        pos += 1;
+    }
+    String::from_utf8(result)
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
@@ -0,0 +1,499 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::JMAP;
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, property::Property, state::State},
+};
+use store::{
+    query::Filter,
+    write::{
+        assert::HashedValue, now, BatchBuilder, Bincode, BlobOp, DirectoryClass, F_CLEAR, F_INDEX,
+        F_VALUE,
+    },
+};
+use utils::BlobHash;
+
+// Holds a generation counter that is bumped every time default collections
+// are created, so that concurrent requests do not create them twice
+const DEFAULT_COLLECTIONS_ID: u32 = u32::MAX;
+
+pub type DavResourceVersion = HashedValue<Bincode<DavResource>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DavKind {
+    Calendar,
+    AddressBook,
+}
+
+/// A calendar or address book.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DavCollection {
+    pub kind: DavKind,
+    pub name: String,
+    pub display_name: String,
+    pub description: Option<String>,
+}
+
+/// An iCalendar object or vCard stored inside a calendar or address book.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DavResource {
+    pub parent_id: u32,
+    pub name: String,
+    pub blob_hash: BlobHash,
+    pub size: u32,
+    pub modified: u64,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait DavStore: Sync + Send {
+    async fn dav_collections(
+        &self,
+        account_id: u32,
+        kind: DavKind,
+    ) -> Result<Vec<(u32, DavCollection)>, MethodError>;
+    async fn dav_collection_create(
+        &self,
+        account_id: u32,
+        collection: DavCollection,
+    ) -> Result<u32, MethodError>;
+    async fn dav_default_collection_create(
+        &self,
+        account_id: u32,
+        collection: DavCollection,
+    ) -> Result<Option<u32>, MethodError>;
+    async fn dav_collection_update(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        collection: DavCollection,
+    ) -> Result<(), MethodError>;
+    async fn dav_collection_delete(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<(), MethodError>;
+    async fn dav_resources(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+    ) -> Result<Vec<(u32, DavResource)>, MethodError>;
+    async fn dav_resource_by_name(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+        name: &str,
+    ) -> Result<Option<(u32, DavResourceVersion)>, MethodError>;
+    async fn dav_resource_put(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+        name: &str,
+        current: Option<(u32, DavResourceVersion)>,
+        data: &[u8],
+    ) -> Result<Option<DavResource>, MethodError>;
+    async fn dav_resource_delete(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        resource: DavResource,
+    ) -> Result<(), MethodError>;
+    async fn dav_state(&self, account_id: u32) -> Result<String, MethodError>;
+}
+
+impl DavStore for JMAP {
+    async fn dav_collections(
+        &self,
+        account_id: u32,
+        kind: DavKind,
+    ) -> Result<Vec<(u32, DavCollection)>, MethodError> {
+        let document_ids = self
+            .get_document_ids(account_id, Collection::DavCollection)
+            .await?
+            .unwrap_or_default();
+        let collections = self
+            .get_properties::<Bincode<DavCollection>>(
+                account_id,
+                Collection::DavCollection,
+                document_ids.iter(),
+                Property::Value,
+            )
+            .await?;
+
+        Ok(document_ids
+            .into_iter()
+            .zip(collections)
+            .filter_map(|(document_id, collection)| {
+                collection
+                    .map(|collection| collection.inner)
+                    .filter(|collection| collection.kind == kind)
+                    .map(|collection| (document_id, collection))
+            })
+            .collect())
+    }
+
+    async fn dav_collection_create(
+        &self,
+        account_id: u32,
+        collection: DavCollection,
+    ) -> Result<u32, MethodError> {
+        let document_id = self
+            .assign_document_id(account_id, Collection::DavCollection)
+            .await?;
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_insert(Collection::DavCollection, document_id);
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavCollection)
+            .create_document(document_id)
+            .value(Property::Value, &Bincode::new(collection), F_VALUE)
+            .custom(changes);
+        self.write_batch(batch).await?;
+
+        Ok(document_id)
+    }
+
+    async fn dav_default_collection_create(
+        &self,
+        account_id: u32,
+        collection: DavCollection,
+    ) -> Result<Option<u32>, MethodError> {
+        let generation = self
+            .get_property::<u64>(
+                account_id,
+                Collection::DavCollection,
+                DEFAULT_COLLECTIONS_ID,
+                Property::Value,
+            )
+            .await?;
+        let document_id = self
+            .assign_document_id(account_id, Collection::DavCollection)
+            .await?;
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_insert(Collection::DavCollection, document_id);
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavCollection)
+            .update_document(DEFAULT_COLLECTIONS_ID);
+        if let Some(generation) = generation {
+            batch.assert_value(Property::Value, generation);
+        } else {
+            batch.assert_value(Property::Value, ());
+        }
+        batch
+            .value(Property::Value, generation.unwrap_or_default() + 1, F_VALUE)
+            .create_document(document_id)
+            .value(Property::Value, &Bincode::new(collection), F_VALUE)
+            .custom(changes);
+        match self.store.write(batch.build()).await {
+            Ok(_) => Ok(Some(document_id)),
+            Err(store::Error::AssertValueFailed) => Ok(None),
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "dav_collection_create",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to create default collection."
+                );
+                Err(MethodError::ServerPartialFail)
+            }
+        }
+    }
+
+    async fn dav_collection_update(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        collection: DavCollection,
+    ) -> Result<(), MethodError> {
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_update(Collection::DavCollection, document_id);
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavCollection)
+            .update_document(document_id)
+            .value(Property::Value, &Bincode::new(collection), F_VALUE)
+            .custom(changes);
+        self.write_batch(batch).await
+    }
+
+    async fn dav_collection_delete(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<(), MethodError> {
+        // Delete the contents first
+        for (resource_id, resource) in self.dav_resources(account_id, document_id).await? {
+            self.dav_resource_delete(account_id, resource_id, resource)
+                .await?;
+        }
+
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_delete(Collection::DavCollection, document_id);
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavCollection);
+
+        // Reset the default collections marker once the last collection is gone
+        if self
+            .get_document_ids(account_id, Collection::DavCollection)
+            .await?
+            .map_or(true, |ids| ids.len() <= 1)
+        {
+            batch.update_document(DEFAULT_COLLECTIONS_ID).value(
+                Property::Value,
+                (),
+                F_VALUE | F_CLEAR,
+            );
+        }
+        batch
+            .delete_document(document_id)
+            .value(Property::Value, (), F_VALUE | F_CLEAR)
+            .custom(changes);
+        self.write_batch(batch).await
+    }
+
+    async fn dav_resources(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+    ) -> Result<Vec<(u32, DavResource)>, MethodError> {
+        let document_ids = self
+            .get_tag(
+                account_id,
+                Collection::DavResource,
+                Property::ParentId,
+                parent_id,
+            )
+            .await?
+            .unwrap_or_default();
+        let resources = self
+            .get_properties::<Bincode<DavResource>>(
+                account_id,
+                Collection::DavResource,
+                document_ids.iter(),
+                Property::Value,
+            )
+            .await?;
+
+        Ok(document_ids
+            .into_iter()
+            .zip(resources)
+            .filter_map(|(document_id, resource)| {
+                resource.map(|resource| (document_id, resource.inner))
+            })
+            .collect())
+    }
+
+    async fn dav_resource_by_name(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+        name: &str,
+    ) -> Result<Option<(u32, DavResourceVersion)>, MethodError> {
+        for document_id in self
+            .filter(
+                account_id,
+                Collection::DavResource,
+                vec![
+                    Filter::is_in_bitmap(Property::ParentId, parent_id),
+                    Filter::eq(Property::Name, name),
+                ],
+            )
+            .await?
+            .results
+        {
+            if let Some(resource) = self
+                .get_property::<DavResourceVersion>(
+                    account_id,
+                    Collection::DavResource,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+                .filter(|resource| resource.inner.inner.name == name)
+            {
+                return Ok(Some((document_id, resource)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn dav_resource_put(
+        &self,
+        account_id: u32,
+        parent_id: u32,
+        name: &str,
+        current: Option<(u32, DavResourceVersion)>,
+        data: &[u8],
+    ) -> Result<Option<DavResource>, MethodError> {
+        let (document_id, prev_resource) = match current {
+            Some((document_id, resource)) => (document_id, Some(resource)),
+            None => (
+                self.assign_document_id(account_id, Collection::DavResource)
+                    .await?,
+                None,
+            ),
+        };
+
+        // Store blob
+        let blob_hash = self.put_blob(account_id, data, false).await?.hash;
+        let resource = DavResource {
+            parent_id,
+            name: name.to_string(),
+            blob_hash: blob_hash.clone(),
+            size: data.len() as u32,
+            modified: now(),
+        };
+
+        let mut changes = self.begin_changes(account_id).await?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavResource);
+        if let Some(prev_resource) = prev_resource {
+            // Fail if another request modified the resource in the meantime
+            changes.log_update(Collection::DavResource, document_id);
+            batch
+                .update_document(document_id)
+                .assert_value(Property::Value, &prev_resource)
+                .clear(BlobOp::Link {
+                    hash: prev_resource.inner.inner.blob_hash,
+                })
+                .add(
+                    DirectoryClass::UsedQuota(account_id),
+                    data.len() as i64 - prev_resource.inner.inner.size as i64,
+                );
+        } else {
+            changes.log_insert(Collection::DavResource, document_id);
+            batch
+                .create_document(document_id)
+                .tag(Property::ParentId, parent_id, 0)
+                .value(Property::Name, name, F_INDEX)
+                .add(DirectoryClass::UsedQuota(account_id), data.len() as i64);
+        }
+        batch
+            .set(BlobOp::Link { hash: blob_hash }, Vec::new())
+            .value(Property::Value, &Bincode::new(resource.clone()), F_VALUE)
+            .custom(changes);
+        match self.store.write(batch.build()).await {
+            Ok(_) => Ok(Some(resource)),
+            Err(store::Error::AssertValueFailed) => Ok(None),
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "dav_resource_put",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to store resource."
+                );
+                Err(MethodError::ServerPartialFail)
+            }
+        }
+    }
+
+    async fn dav_resource_delete(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        resource: DavResource,
+    ) -> Result<(), MethodError> {
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_delete(Collection::DavResource, document_id);
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::DavResource)
+            .delete_document(document_id)
+            .tag(Property::ParentId, resource.parent_id, F_CLEAR)
+            .value(Property::Name, resource.name.as_str(), F_INDEX | F_CLEAR)
+            .clear(BlobOp::Link {
+                hash: resource.blob_hash,
+            })
+            .add(
+                DirectoryClass::UsedQuota(account_id),
+                -(resource.size as i64),
+            )
+            .value(Property::Value, (), F_VALUE | F_CLEAR)
+            .custom(changes);
+        self.write_batch(batch).await
+    }
+
+    async fn dav_state(&self, account_id: u32) -> Result<String, MethodError> {
+        Ok(
+            match self.get_state(account_id, Collection::DavResource).await? {
+                State::Exact(change_id) => change_id.to_string(),
+                _ => "0".to_string(),
+            },
+        )
+    }
+}
+
+impl DavResource {
+    pub fn etag(&self) -> String {
+        let mut etag = String::with_capacity(34);
+        etag.push('"');
+        for byte in &self.blob_hash.as_slice()[..16] {
+            etag.push_str(&format!("{byte:02x}"));
+        }
+        etag.push('"');
+        etag
+    }
+}
+
+impl DavKind {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DavKind::Calendar => "text/calendar; charset=utf-8",
+            DavKind::AddressBook => "text/vcard; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DavKind::Calendar => "ics",
+            DavKind::AddressBook => "vcf",
+        }
+    }
+
+    pub fn is_valid_object(&self, data: &[u8]) -> bool {
+        let data = std::str::from_utf8(data).unwrap_or_default().trim_start();
+        let begin = match self {
+            DavKind::Calendar => "BEGIN:VCALENDAR",
+            DavKind::AddressBook => "BEGIN:VCARD",
+        };
+        data.len() >= begin.len() && data[..begin.len()].eq_ignore_ascii_case(begin)
+    }
+}
@@ -0,0 +1,426 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+pub const NS_DAV: &str = "DAV:";
+pub const NS_CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+pub const NS_CARDDAV: &str = "urn:ietf:params:xml:ns:carddav";
+pub const NS_CALSERVER: &str = "http://calendarserver.org/ns/";
+
+/// Element of a request body, with its namespace already resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub namespace: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'x> {
+    Start {
+        name: &'x str,
+        attributes: &'x str,
+        is_empty: bool,
+    },
+    End {
+        name: &'x str,
+    },
+    Text(&'x str),
+}
+
+/// Minimal reader for the small WebDAV request bodies sent by clients,
+/// it only understands elements, attributes and text.
+struct Tokenizer<'x> {
+    body: &'x str,
+    pos: usize,
+}
+
+impl<'x> Iterator for Tokenizer<'x> {
+    type Item = Token<'x>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = self.body.get(self.pos..)?;
+            if rest.is_empty() {
+                return None;
+            }
+            if let Some(rest) = rest.strip_prefix('<') {
+                let end = rest.find('>')?;
+                let tag = &rest[..end];
+                self.pos += end + 2;
+                if tag.starts_with('?') || tag.starts_with('!') {
+                    continue;
+                } else if let Some(name) = tag.strip_prefix('/') {
+                    return Some(Token::End { name: name.trim() });
+                } else {
+                    let (tag, is_empty) = match tag.strip_suffix('/') {
+                        Some(tag) => (tag, true),
+                        None => (tag, false),
+                    };
+                    let (name, attributes) = tag
+                        .split_once(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or((tag, ""));
+                    return Some(Token::Start {
+                        name,
+                        attributes,
+                        is_empty,
+                    });
+                }
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.pos += end;
+                let text = rest[..end].trim();
+                if !text.is_empty() {
+                    return Some(Token::Text(text));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Request {
+    pub root: Option<Element>,
+    /// Children of the first `prop` element, `None` when the request has
+    /// no `prop` element and all properties are returned.
+    pub props: Option<Vec<Element>>,
+    pub hrefs: Vec<String>,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Request {
+    pub fn parse(body: &str) -> Self {
+        let mut request = Request::default();
+        let mut namespaces: Vec<(String, String)> = Vec::new();
+        let mut stack: Vec<Element> = Vec::new();
+        let mut props: Option<Vec<Element>> = None;
+
+        for token in (Tokenizer { body, pos: 0 }) {
+            match token {
+                Token::Start {
+                    name,
+                    attributes,
+                    is_empty,
+                } => {
+                    for (prefix, uri) in parse_namespaces(attributes) {
+                        namespaces.push((prefix.to_string(), uri.to_string()));
+                    }
+                    let element = resolve_name(name, &namespaces);
+                    let parent = stack.last();
+
+                    if request.root.is_none() {
+                        request.root = element.clone().into();
+                    }
+                    match parent {
+                        Some(parent) if parent.is(NS_DAV, "prop") && request.props.is_none() => {
+                            props.get_or_insert_with(Vec::new).push(element.clone());
+                        }
+                        _ => {}
+                    }
+                    if !is_empty {
+                        stack.push(element);
+                    } else if element.is(NS_DAV, "prop") && request.props.is_none() {
+                        request.props = Some(Vec::new());
+                    }
+                }
+                Token::End { name } => {
+                    let element = resolve_name(name, &namespaces);
+                    if let Some(pos) = stack.iter().rposition(|item| item == &element) {
+                        stack.truncate(pos);
+                    }
+                    if element.is(NS_DAV, "prop") && request.props.is_none() {
+                        request.props = props.take().unwrap_or_default().into();
+                    }
+                }
+                Token::Text(text) => match stack.last() {
+                    Some(element) if element.is(NS_DAV, "href") => {
+                        request.hrefs.push(unescape(text));
+                    }
+                    Some(element) if element.is(NS_DAV, "displayname") => {
+                        request.display_name = unescape(text).into();
+                    }
+                    Some(element)
+                        if element.is(NS_CALDAV, "calendar-description")
+                            || element.is(NS_CARDDAV, "addressbook-description") =>
+                    {
+                        request.description = unescape(text).into();
+                    }
+                    _ => {}
+                },
+            }
+        }
+
+        request
+    }
+}
+
+impl Element {
+    pub fn new(namespace: &str, name: &str) -> Self {
+        Element {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+}
+
+fn parse_namespaces(attributes: &str) -> impl Iterator<Item = (&str, &str)> {
+    attributes
+        .split(|c: char| c.is_ascii_whitespace())
+        .filter_map(|attribute| {
+            let (name, value) = attribute.split_once('=')?;
+            let prefix = if name == "xmlns" {
+                ""
+            } else {
+                name.strip_prefix("xmlns:")?
+            };
+            Some((prefix, value.trim_matches(|c| c == '"' || c == '\'')))
+        })
+}
+
+fn resolve_name(name: &str, namespaces: &[(String, String)]) -> Element {
+    let (prefix, name) = name.split_once(':').unwrap_or(("", name));
+    Element {
+        namespace: namespaces
+            .iter()
+            .rev()
+            .find(|(p, _)| p == prefix)
+            .map(|(_, uri)| uri.clone())
+            .unwrap_or_default(),
+        name: name.to_string(),
+    }
+}
+
+/// Builds a `207 Multi-Status` response body.
+pub struct MultiStatus {
+    body: String,
+}
+
+#[derive(Debug, Default)]
+pub struct PropStat {
+    pub found: Vec<(Element, String)>,
+    pub not_found: Vec<Element>,
+}
+
+impl MultiStatus {
+    pub fn new() -> Self {
+        let mut body = String::with_capacity(1024);
+        let _ = write!(
+            body,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                "<D:multistatus xmlns:D=\"{}\" xmlns:C=\"{}\" xmlns:CR=\"{}\" xmlns:CS=\"{}\">"
+            ),
+            NS_DAV, NS_CALDAV, NS_CARDDAV, NS_CALSERVER
+        );
+        MultiStatus { body }
+    }
+
+    pub fn add_response(&mut self, href: &str, propstat: PropStat) {
+        let _ = write!(self.body, "<D:response><D:href>{}</D:href>", escape(href));
+        if !propstat.found.is_empty() {
+            self.body.push_str("<D:propstat><D:prop>");
+            for (element, value) in propstat.found {
+                write_element(&mut self.body, &element, &value);
+            }
+            self.body
+                .push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>");
+        }
+        if !propstat.not_found.is_empty() {
+            self.body.push_str("<D:propstat><D:prop>");
+            for element in propstat.not_found {
+                write_element(&mut self.body, &element, "");
+            }
+            self.body
+                .push_str("</D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>");
+        }
+        self.body.push_str("</D:response>");
+    }
+
+    /// Adds the result of a PROPPATCH, where either all properties are
+    /// updated or none are and the accepted ones fail as a dependency.
+    pub fn add_proppatch(&mut self, href: &str, updated: Vec<&Element>, forbidden: Vec<&Element>) {
+        let _ = write!(self.body, "<D:response><D:href>{}</D:href>", escape(href));
+        for (elements, status) in [
+            (&forbidden, "403 Forbidden"),
+            (
+                &updated,
+                if forbidden.is_empty() {
+                    "200 OK"
+                } else {
+                    "424 Failed Dependency"
+                },
+            ),
+        ] {
+            if !elements.is_empty() {
+                self.body.push_str("<D:propstat><D:prop>");
+                for element in elements {
+                    write_element(&mut self.body, element, "");
+                }
+                let _ = write!(
+                    self.body,
+                    "</D:prop><D:status>HTTP/1.1 {status}</D:status></D:propstat>"
+                );
+            }
+        }
+        self.body.push_str("</D:response>");
+    }
+
+    pub fn add_status(&mut self, href: &str, status: &str) {
+        let _ = write!(
+            self.body,
+            "<D:response><D:href>{}</D:href><D:status>HTTP/1.1 {}</D:status></D:response>",
+            escape(href),
+            status
+        );
+    }
+
+    pub fn finish(mut self) -> String {
+        self.body.push_str("</D:multistatus>");
+        self.body
+    }
+}
+
+impl Default for MultiStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_element(body: &mut String, element: &Element, value: &str) {
+    let prefix = match element.namespace.as_str() {
+        NS_DAV => "D",
+        NS_CALDAV => "C",
+        NS_CARDDAV => "CR",
+        NS_CALSERVER => "CS",
+        _ => "",
+    };
+    if !prefix.is_empty() {
+        if value.is_empty() {
+            let _ = write!(body, "<{prefix}:{}/>", element.name);
+        } else {
+            let _ = write!(body, "<{prefix}:{0}>{value}</{prefix}:{0}>", element.name);
+        }
+    } else if value.is_empty() {
+        let _ = write!(
+            body,
+            "<X:{} xmlns:X=\"{}\"/>",
+            element.name,
+            escape(&element.namespace)
+        );
+    } else {
+        let _ = write!(
+            body,
+            "<X:{0} xmlns:X=\"{1}\">{value}</X:{0}>",
+            element.name,
+            escape(&element.namespace)
+        );
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' => result.push_str("&amp;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, Request, NS_CALDAV, NS_CALSERVER, NS_DAV};
+
+    #[test]
+    fn parse_request() {
+        let request = Request::parse(concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n",
+            "<d:propfind xmlns:d=\"DAV:\" xmlns:cs=\"http://calendarserver.org/ns/\">\n",
+            "  <d:prop>\n",
+            "    <d:displayname />\n",
+            "    <cs:getctag />\n",
+            "    <x:color xmlns:x=\"http://apple.com/ns/ical/\"/>\n",
+            "  </d:prop>\n",
+            "</d:propfind>"
+        ));
+        assert_eq!(request.root, Some(Element::new(NS_DAV, "propfind")));
+        assert_eq!(
+            request.props,
+            Some(vec![
+                Element::new(NS_DAV, "displayname"),
+                Element::new(NS_CALSERVER, "getctag"),
+                Element::new("http://apple.com/ns/ical/", "color"),
+            ])
+        );
+
+        let request = Request::parse(concat!(
+            "<C:calendar-multiget xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\">",
+            "<D:prop><D:getetag/><C:calendar-data/></D:prop>",
+            "<D:href>/dav/cal/jdoe/default/a.ics</D:href>",
+            "<D:href>/dav/cal/jdoe/default/b&amp;c.ics</D:href>",
+            "</C:calendar-multiget>"
+        ));
+        assert_eq!(
+            request.root,
+            Some(Element::new(NS_CALDAV, "calendar-multiget"))
+        );
+        assert_eq!(
+            request.hrefs,
+            vec![
+                "/dav/cal/jdoe/default/a.ics".to_string(),
+                "/dav/cal/jdoe/default/b&c.ics".to_string()
+            ]
+        );
+        assert_eq!(request.props.unwrap().len(), 2);
+
+        let request = Request::parse("<D:propfind xmlns:D=\"DAV:\"><D:allprop/></D:propfind>");
+        assert_eq!(request.props, None);
+
+        let request = Request::parse(concat!(
+            "<C:mkcalendar xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\">",
+            "<D:set><D:prop><D:displayname>Work &amp; Meetings</D:displayname>",
+            "<C:calendar-description>Team calendar</C:calendar-description>",
+            "</D:prop></D:set></C:mkcalendar>"
+        ));
+        assert_eq!(request.display_name.as_deref(), Some("Work & Meetings"));
+        assert_eq!(request.description.as_deref(), Some("Team calendar"));
+    }
+}
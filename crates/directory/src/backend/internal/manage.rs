@@ -21,22 +21,27 @@
  * for more details.
 */
 
+use std::collections::BTreeMap;
+
 use jmap_proto::types::collection::Collection;
 use pwhash::sha512_crypt;
 use store::{
     rand::{distributions::Alphanumeric, thread_rng, Rng},
     write::{
-        assert::HashedValue, key::DeserializeBigEndian, BatchBuilder, BitmapClass, DirectoryClass,
-        ValueClass,
+        assert::HashedValue,
+        key::{DeserializeBigEndian, KeySerializer},
+        now, BatchBuilder, BitmapClass, DirectoryClass, ValueClass,
     },
-    BitmapKey, Deserialize, IterateParams, Serialize, Store, ValueKey, U32_LEN,
+    BitmapKey, Deserialize, IterateParams, Serialize, Store, ValueKey, U32_LEN, U64_LEN,
 };
 
-use crate::{DirectoryError, ManagementError, Principal, QueryBy, Type};
+use crate::{
+    core::scram::remove_stale_verifiers, DirectoryError, ManagementError, Principal, QueryBy, Type,
+};
 
 use super::{
     lookup::DirectoryStore, PrincipalAction, PrincipalField, PrincipalIdType, PrincipalUpdate,
-    PrincipalValue,
+    PrincipalValue, PurgeStage, RenamedAddress, Tombstone,
 };
 
 #[allow(async_fn_in_trait)]
@@ -67,6 +72,23 @@ pub trait ManageDirectory: Sized {
     async fn create_domain(&self, domain: &str) -> crate::Result<()>;
     async fn delete_domain(&self, domain: &str) -> crate::Result<()>;
     async fn list_domains(&self, filter: Option<&str>) -> crate::Result<Vec<String>>;
+    async fn count_password_schemes(&self) -> crate::Result<BTreeMap<&'static str, u64>>;
+    async fn rename_account(
+        &self,
+        by: QueryBy<'_>,
+        new_email: &str,
+        grace_period: u64,
+    ) -> crate::Result<Vec<RenamedAddress>>;
+    async fn rename_domain(
+        &self,
+        domain: &str,
+        new_domain: &str,
+        grace_period: u64,
+    ) -> crate::Result<Vec<RenamedAddress>>;
+    async fn purge_expired_aliases(&self) -> crate::Result<usize>;
+    async fn list_tombstones(&self) -> crate::Result<Vec<Tombstone>>;
+    async fn list_orphaned_accounts(&self) -> crate::Result<Vec<u32>>;
+    async fn advance_tombstone(&self, tombstone: Tombstone) -> crate::Result<Option<Tombstone>>;
     async fn init(self) -> crate::Result<Self>;
 }
 
@@ -274,22 +296,22 @@ impl ManageDirectory for Store {
                 DirectoryError::Management(ManagementError::NotFound(account_id.to_string()))
             })?;
 
-        // Unlink all account's blobs
-        self.blob_hash_unlink_account(account_id).await?;
-
-        // Revoke ACLs
-        self.acl_revoke_all(account_id).await?;
-
-        // Delete account data
-        self.purge_account(account_id).await?;
-
-        // Delete account
+        // Delete account and leave a tombstone, the account's data is
+        // purged in stages by the housekeeper
         let mut batch = BatchBuilder::new();
         batch
             .with_account_id(account_id)
             .clear(DirectoryClass::NameToId(principal.name.into_bytes()))
             .clear(DirectoryClass::Principal(account_id))
-            .clear(DirectoryClass::UsedQuota(account_id));
+            .set(
+                DirectoryClass::Tombstone(account_id),
+                (&Tombstone {
+                    account_id,
+                    deleted_at: now(),
+                    stage: PurgeStage::Index,
+                })
+                    .serialize(),
+            );
 
         for email in principal.emails {
             batch.clear(DirectoryClass::EmailToId(email.into_bytes()));
@@ -322,6 +344,352 @@ impl ManageDirectory for Store {
         Ok(())
     }
 
+    async fn rename_account(
+        &self,
+        by: QueryBy<'_>,
+        new_email: &str,
+        grace_period: u64,
+    ) -> crate::Result<Vec<RenamedAddress>> {
+        let account_id = match by {
+            QueryBy::Name(name) => self.get_account_id(name).await?.ok_or_else(|| {
+                DirectoryError::Management(ManagementError::NotFound(name.to_string()))
+            })?,
+            QueryBy::Id(account_id) => account_id,
+            QueryBy::Credentials(_) => unreachable!(),
+        };
+        let principal = self
+            .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                DirectoryClass::Principal(account_id),
+            )))
+            .await?
+            .ok_or_else(|| {
+                DirectoryError::Management(ManagementError::NotFound(account_id.to_string()))
+            })?;
+        let new_email = new_email.to_lowercase();
+        if !new_email.contains('@') {
+            return Err(DirectoryError::Management(ManagementError::MissingField(
+                PrincipalField::Emails,
+            )));
+        }
+
+        // Replace the primary address, the previous one is kept as
+        // an alias until the grace period expires
+        let mut emails = principal.emails;
+        if emails.first() == Some(&new_email) {
+            return Ok(vec![]);
+        }
+        let old_email = (!emails.is_empty()).then(|| emails.remove(0));
+        emails.retain(|email| email != &new_email);
+        emails.insert(0, new_email.clone());
+        let mut changes = Vec::with_capacity(2);
+        if let Some(old_email) = &old_email {
+            if grace_period > 0 {
+                emails.push(old_email.clone());
+            }
+            if &principal.name == old_email {
+                changes.push(PrincipalUpdate::set(
+                    PrincipalField::Name,
+                    PrincipalValue::String(new_email.clone()),
+                ));
+            }
+        }
+        changes.push(PrincipalUpdate::set(
+            PrincipalField::Emails,
+            PrincipalValue::StringList(emails),
+        ));
+        self.update_account(QueryBy::Id(account_id), changes)
+            .await?;
+
+        let old_email = if let Some(old_email) = old_email {
+            old_email
+        } else {
+            return Ok(vec![]);
+        };
+        if grace_period > 0 {
+            let mut batch = BatchBuilder::new();
+            batch.set(
+                ValueClass::Directory(DirectoryClass::TemporaryAlias(
+                    old_email.as_bytes().to_vec(),
+                )),
+                temporary_alias(account_id, grace_period),
+            );
+            self.write(batch.build()).await?;
+        }
+
+        Ok(vec![RenamedAddress {
+            account_id,
+            old: old_email,
+            new: new_email,
+        }])
+    }
+
+    async fn rename_domain(
+        &self,
+        domain: &str,
+        new_domain: &str,
+        grace_period: u64,
+    ) -> crate::Result<Vec<RenamedAddress>> {
+        let domain = domain.to_lowercase();
+        let new_domain = new_domain.to_lowercase();
+        if !self.is_local_domain(&domain).await? {
+            return Err(DirectoryError::Management(ManagementError::NotFound(
+                domain,
+            )));
+        } else if domain == new_domain {
+            return Ok(vec![]);
+        } else if !self.is_local_domain(&new_domain).await? {
+            self.create_domain(&new_domain).await?;
+        }
+
+        // Obtain all accounts with addresses in the domain
+        let suffix = format!("@{domain}");
+        let mut account_ids = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::EmailToId(vec![]))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::EmailToId(vec![
+                    u8::MAX;
+                    10
+                ]))),
+            )
+            .ascending(),
+            |key, value| {
+                if key
+                    .get(1..)
+                    .unwrap_or_default()
+                    .ends_with(suffix.as_bytes())
+                {
+                    let account_id = PrincipalIdType::deserialize(value)?.account_id;
+                    if !account_ids.contains(&account_id) {
+                        account_ids.push(account_id);
+                    }
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut renamed = Vec::new();
+        for account_id in account_ids {
+            let principal = if let Some(principal) = self
+                .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                    DirectoryClass::Principal(account_id),
+                )))
+                .await?
+            {
+                principal
+            } else {
+                continue;
+            };
+
+            // Rewrite addresses, keeping the old ones as aliases during the grace period
+            let mut emails = Vec::with_capacity(principal.emails.len() * 2);
+            let mut old_emails = Vec::new();
+            for email in principal.emails {
+                if let Some(local_part) = email.strip_suffix(&suffix) {
+                    let new_email = format!("{local_part}@{new_domain}");
+                    if !emails.contains(&new_email) {
+                        emails.push(new_email.clone());
+                    }
+                    renamed.push(RenamedAddress {
+                        account_id,
+                        old: email.clone(),
+                        new: new_email,
+                    });
+                    old_emails.push(email);
+                } else if !emails.contains(&email) {
+                    emails.push(email);
+                }
+            }
+            if grace_period > 0 {
+                emails.extend(old_emails.iter().cloned());
+            }
+
+            let mut changes = vec![PrincipalUpdate::set(
+                PrincipalField::Emails,
+                PrincipalValue::StringList(emails),
+            )];
+            if let Some(local_part) = principal.name.strip_suffix(&suffix) {
+                changes.push(PrincipalUpdate::set(
+                    PrincipalField::Name,
+                    PrincipalValue::String(format!("{local_part}@{new_domain}")),
+                ));
+            }
+            self.update_account(QueryBy::Id(account_id), changes)
+                .await?;
+
+            if grace_period > 0 {
+                let mut batch = BatchBuilder::new();
+                for email in old_emails {
+                    batch.set(
+                        ValueClass::Directory(DirectoryClass::TemporaryAlias(email.into_bytes())),
+                        temporary_alias(account_id, grace_period),
+                    );
+                }
+                self.write(batch.build()).await?;
+            }
+        }
+
+        // The old domain is removed once the grace period expires
+        if grace_period > 0 {
+            let mut batch = BatchBuilder::new();
+            batch.set(
+                ValueClass::Directory(DirectoryClass::TemporaryAlias(domain.into_bytes())),
+                temporary_alias(u32::MAX, grace_period),
+            );
+            self.write(batch.build()).await?;
+        } else {
+            self.delete_domain(&domain).await?;
+        }
+
+        Ok(renamed)
+    }
+
+    async fn purge_expired_aliases(&self) -> crate::Result<usize> {
+        let now = now();
+        let mut expired = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::TemporaryAlias(
+                    vec![],
+                ))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::TemporaryAlias(vec![
+                    u8::MAX;
+                    10
+                ]))),
+            )
+            .ascending(),
+            |key, value| {
+                if value.deserialize_be_u64(U32_LEN)? <= now {
+                    expired.push((
+                        String::from_utf8_lossy(key.get(1..).unwrap_or_default()).into_owned(),
+                        value.deserialize_be_u32(0)?,
+                    ));
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let num_expired = expired.len();
+        for (alias, account_id) in expired {
+            if alias.contains('@') {
+                match self
+                    .update_account(
+                        QueryBy::Id(account_id),
+                        vec![PrincipalUpdate::remove_item(
+                            PrincipalField::Emails,
+                            PrincipalValue::String(alias.clone()),
+                        )],
+                    )
+                    .await
+                {
+                    Ok(_) | Err(DirectoryError::Management(ManagementError::NotFound(_))) => (),
+                    Err(err) => return Err(err),
+                }
+            } else {
+                self.delete_domain(&alias).await?;
+            }
+
+            let mut batch = BatchBuilder::new();
+            batch.clear(ValueClass::Directory(DirectoryClass::TemporaryAlias(
+                alias.into_bytes(),
+            )));
+            self.write(batch.build()).await?;
+        }
+
+        Ok(num_expired)
+    }
+
+    async fn list_tombstones(&self) -> crate::Result<Vec<Tombstone>> {
+        let mut results = Vec::new();
+        self.iterate(
+            IterateParams::new(
+                ValueKey::from(ValueClass::Directory(DirectoryClass::Tombstone(0))),
+                ValueKey::from(ValueClass::Directory(DirectoryClass::Tombstone(u32::MAX))),
+            )
+            .ascending(),
+            |key, value| {
+                results.push(Tombstone::deserialize(key.deserialize_be_u32(1)?, value)?);
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(results)
+    }
+
+    async fn list_orphaned_accounts(&self) -> crate::Result<Vec<u32>> {
+        let tombstones = self
+            .list_tombstones()
+            .await?
+            .into_iter()
+            .map(|tombstone| tombstone.account_id)
+            .collect::<Vec<_>>();
+        let mut results = Vec::new();
+
+        for account_id in self.list_indexed_accounts().await? {
+            // Principal ids are owned by the directory, pending deletions are handled by the purge
+            if account_id != u32::MAX
+                && !tombstones.contains(&account_id)
+                && self
+                    .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                        DirectoryClass::Principal(account_id),
+                    )))
+                    .await?
+                    .is_none()
+            {
+                results.push(account_id);
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn advance_tombstone(&self, tombstone: Tombstone) -> crate::Result<Option<Tombstone>> {
+        let account_id = tombstone.account_id;
+
+        match tombstone.stage {
+            // Full-text index removal is handled by the caller
+            PurgeStage::Index => (),
+            PurgeStage::Blobs => {
+                self.blob_hash_unlink_account(account_id).await?;
+            }
+            PurgeStage::Acls => {
+                self.acl_revoke_all(account_id).await?;
+            }
+            PurgeStage::Data => {
+                self.purge_account(account_id).await?;
+            }
+            PurgeStage::Counters => {
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .clear(DirectoryClass::UsedQuota(account_id))
+                    .clear(DirectoryClass::UsedMessages(account_id));
+                self.write(batch.build()).await?;
+            }
+        }
+
+        // Persist the next stage or remove the tombstone
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(account_id);
+        let next = if let Some(stage) = tombstone.stage.next() {
+            let next = Tombstone { stage, ..tombstone };
+            batch.set(DirectoryClass::Tombstone(account_id), (&next).serialize());
+            Some(next)
+        } else {
+            batch.clear(DirectoryClass::Tombstone(account_id));
+            None
+        };
+        self.write(batch.build()).await?;
+
+        Ok(next)
+    }
+
     async fn update_account(
         &self,
         by: QueryBy<'_>,
@@ -364,6 +732,7 @@ impl ManageDirectory for Store {
                 &principal,
             );
         }
+        let previous_secrets = principal.inner.secrets.clone();
         for change in changes {
             match (change.action, change.field, change.value) {
                 (PrincipalAction::Set, PrincipalField::Name, PrincipalValue::String(new_name)) => {
@@ -409,6 +778,22 @@ impl ManageDirectory for Store {
                 ) => {
                     principal.inner.secrets = secrets;
                 }
+                (
+                    PrincipalAction::AddItem,
+                    PrincipalField::Secrets,
+                    PrincipalValue::String(secret),
+                ) => {
+                    if !principal.inner.secrets.contains(&secret) {
+                        principal.inner.secrets.push(secret);
+                    }
+                }
+                (
+                    PrincipalAction::RemoveItem,
+                    PrincipalField::Secrets,
+                    PrincipalValue::String(secret),
+                ) => {
+                    principal.inner.secrets.retain(|s| s != &secret);
+                }
                 (
                     PrincipalAction::Set,
                     PrincipalField::Description,
@@ -706,6 +1091,7 @@ impl ManageDirectory for Store {
         }
 
         if update_principal {
+            remove_stale_verifiers(&previous_secrets, &mut principal.inner.secrets);
             batch.set(
                 ValueClass::Directory(DirectoryClass::Principal(account_id)),
                 principal.inner.serialize(),
@@ -863,6 +1249,43 @@ impl ManageDirectory for Store {
         }
     }
 
+    async fn count_password_schemes(&self) -> crate::Result<BTreeMap<&'static str, u64>> {
+        let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![])));
+        let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::NameToId(vec![
+            u8::MAX;
+            10
+        ])));
+
+        let mut account_ids = Vec::new();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending(),
+            |_, value| {
+                let pt = PrincipalIdType::deserialize(value)?;
+                if matches!(pt.typ, Type::Individual | Type::Superuser) {
+                    account_ids.push(pt.account_id);
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        let mut schemes = BTreeMap::new();
+        for account_id in account_ids {
+            if let Some(scheme) = self
+                .get_value::<Principal<u32>>(ValueKey::from(ValueClass::Directory(
+                    DirectoryClass::Principal(account_id),
+                )))
+                .await?
+                .and_then(|principal| principal.password_scheme())
+            {
+                *schemes.entry(scheme).or_insert(0) += 1;
+            }
+        }
+
+        Ok(schemes)
+    }
+
     async fn list_domains(&self, filter: Option<&str>) -> crate::Result<Vec<String>> {
         let from_key = ValueKey::from(ValueClass::Directory(DirectoryClass::Domain(vec![])));
         let to_key = ValueKey::from(ValueClass::Directory(DirectoryClass::Domain(vec![
@@ -1016,3 +1439,10 @@ impl From<Principal<String>> for Principal<u32> {
         }
     }
 }
+
+fn temporary_alias(account_id: u32, grace_period: u64) -> Vec<u8> {
+    KeySerializer::new(U32_LEN + U64_LEN)
+        .write(account_id)
+        .write(now() + grace_period)
+        .finalize()
+}
@@ -26,7 +26,10 @@ pub mod manage;
 
 use std::{fmt::Display, slice::Iter};
 
-use store::{write::key::KeySerializer, Deserialize, Serialize, U32_LEN};
+use store::{
+    write::key::{DeserializeBigEndian, KeySerializer},
+    Deserialize, Serialize, U32_LEN, U64_LEN,
+};
 use utils::codec::leb128::Leb128Iterator;
 
 use crate::{Principal, Type};
@@ -108,6 +111,86 @@ impl PrincipalIdType {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenamedAddress {
+    pub account_id: u32,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tombstone {
+    pub account_id: u32,
+    pub deleted_at: u64,
+    pub stage: PurgeStage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PurgeStage {
+    Index = 0,
+    Blobs = 1,
+    Acls = 2,
+    Data = 3,
+    Counters = 4,
+}
+
+impl PurgeStage {
+    pub fn next(&self) -> Option<Self> {
+        match self {
+            PurgeStage::Index => Some(PurgeStage::Blobs),
+            PurgeStage::Blobs => Some(PurgeStage::Acls),
+            PurgeStage::Acls => Some(PurgeStage::Data),
+            PurgeStage::Data => Some(PurgeStage::Counters),
+            PurgeStage::Counters => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PurgeStage::Index => "index",
+            PurgeStage::Blobs => "blobs",
+            PurgeStage::Acls => "acls",
+            PurgeStage::Data => "data",
+            PurgeStage::Counters => "counters",
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PurgeStage::Index),
+            1 => Some(PurgeStage::Blobs),
+            2 => Some(PurgeStage::Acls),
+            3 => Some(PurgeStage::Data),
+            4 => Some(PurgeStage::Counters),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for &Tombstone {
+    fn serialize(self) -> Vec<u8> {
+        KeySerializer::new(U64_LEN + 1)
+            .write(self.stage as u8)
+            .write(self.deleted_at)
+            .finalize()
+    }
+}
+
+impl Tombstone {
+    pub fn deserialize(account_id: u32, bytes: &[u8]) -> store::Result<Self> {
+        Ok(Tombstone {
+            account_id,
+            stage: bytes
+                .first()
+                .and_then(|stage| PurgeStage::from_u8(*stage))
+                .ok_or_else(|| {
+                    store::Error::InternalError("Failed to deserialize tombstone stage".into())
+                })?,
+            deleted_at: bytes.deserialize_be_u64(1)?,
+        })
+    }
+}
+
 fn deserialize(bytes: &[u8]) -> Option<Principal<u32>> {
     let mut bytes = bytes.iter();
     if bytes.next()? != &1 {
@@ -202,6 +285,21 @@ impl PrincipalUpdate {
             value,
         }
     }
+
+    /// Returns the secrets being set or added by this update, if any.
+    pub fn new_secrets(&self) -> Option<&[String]> {
+        match (&self.action, &self.field, &self.value) {
+            (
+                PrincipalAction::Set,
+                PrincipalField::Secrets,
+                PrincipalValue::StringList(secrets),
+            ) => Some(secrets),
+            (PrincipalAction::AddItem, PrincipalField::Secrets, PrincipalValue::String(secret)) => {
+                Some(std::slice::from_ref(secret))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Display for PrincipalField {
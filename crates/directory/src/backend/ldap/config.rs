@@ -123,6 +123,7 @@ impl LdapDirectory {
             mappings,
             pool: build_pool(config, &prefix, manager)?,
             auth_bind,
+            inject_trace_id: config.property_or_static((&prefix, "trace-id.inject"), "false")?,
             data_store,
         })
     }
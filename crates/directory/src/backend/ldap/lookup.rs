@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use ldap3::{Ldap, LdapConnAsync, LdapError, Scope, SearchEntry};
+use ldap3::{controls::RawControl, Ldap, LdapConnAsync, LdapError, Scope, SearchEntry};
 use mail_send::Credentials;
 
 use crate::{backend::internal::manage::ManageDirectory, DirectoryError, Principal, QueryBy, Type};
@@ -85,7 +85,9 @@ impl LdapDirectory {
 
                     ldap3::drive!(conn);
 
-                    ldap.simple_bind(&auth_bind.build(username), secret).await?;
+                    self.traced(&mut ldap)
+                        .simple_bind(&auth_bind.build(username), secret)
+                        .await?;
 
                     match self
                         .find_principal(&mut ldap, &self.mappings.filter_name.build(username))
@@ -138,7 +140,8 @@ impl LdapDirectory {
         if return_member_of && !principal.member_of.is_empty() {
             for member_of in principal.member_of.iter_mut() {
                 if member_of.contains('=') {
-                    let (rs, _res) = conn
+                    let (rs, _res) = self
+                        .traced(&mut conn)
                         .search(
                             member_of,
                             Scope::Base,
@@ -175,9 +178,7 @@ impl LdapDirectory {
 
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
         let rs = self
-            .pool
-            .get()
-            .await?
+            .traced(&mut self.pool.get().await?)
             .search(
                 &self.mappings.base_dn,
                 Scope::Subtree,
@@ -205,9 +206,7 @@ impl LdapDirectory {
     }
 
     pub async fn rcpt(&self, address: &str) -> crate::Result<bool> {
-        self.pool
-            .get()
-            .await?
+        self.traced(&mut self.pool.get().await?)
             .streaming_search(
                 &self.mappings.base_dn,
                 Scope::Subtree,
@@ -223,9 +222,7 @@ impl LdapDirectory {
 
     pub async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
         let mut stream = self
-            .pool
-            .get()
-            .await?
+            .traced(&mut self.pool.get().await?)
             .streaming_search(
                 &self.mappings.base_dn,
                 Scope::Subtree,
@@ -253,9 +250,7 @@ impl LdapDirectory {
 
     pub async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
         let mut stream = self
-            .pool
-            .get()
-            .await?
+            .traced(&mut self.pool.get().await?)
             .streaming_search(
                 &self.mappings.base_dn,
                 Scope::Subtree,
@@ -282,9 +277,7 @@ impl LdapDirectory {
     }
 
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
-        self.pool
-            .get()
-            .await?
+        self.traced(&mut self.pool.get().await?)
             .streaming_search(
                 &self.mappings.base_dn,
                 Scope::Subtree,
@@ -305,22 +298,67 @@ impl LdapDirectory {
         conn: &mut Ldap,
         filter: &str,
     ) -> crate::Result<Option<Principal<String>>> {
-        conn.search(
-            &self.mappings.base_dn,
-            Scope::Subtree,
-            filter,
-            &self.mappings.attrs_principal,
-        )
-        .await?
-        .success()
-        .map(|(rs, _)| {
-            rs.into_iter().next().map(|entry| {
-                self.mappings
-                    .entry_to_principal(SearchEntry::construct(entry))
+        self.traced(conn)
+            .search(
+                &self.mappings.base_dn,
+                Scope::Subtree,
+                filter,
+                &self.mappings.attrs_principal,
+            )
+            .await?
+            .success()
+            .map(|(rs, _)| {
+                rs.into_iter().next().map(|entry| {
+                    self.mappings
+                        .entry_to_principal(SearchEntry::construct(entry))
+                })
             })
-        })
-        .map_err(Into::into)
+            .map_err(Into::into)
+    }
+}
+
+impl LdapDirectory {
+    fn traced<'x>(&self, conn: &'x mut Ldap) -> &'x mut Ldap {
+        if self.inject_trace_id {
+            if let Some(trace_id) = utils::trace_id::current_hex() {
+                return conn.with_controls(session_tracking_control(&trace_id));
+            }
+        }
+        conn
+    }
+}
+
+// Session Tracking control (draft-wahl-ldap-session), using the
+// RADIUS Acct-Session-Id format to carry the trace id.
+const SESSION_TRACKING_OID: &str = "1.3.6.1.4.1.21008.108.63.1";
+const SESSION_TRACKING_FORMAT_OID: &str = "1.3.6.1.4.1.21008.108.63.1.1";
+
+fn session_tracking_control(trace_id: &str) -> RawControl {
+    let mut fields = Vec::with_capacity(64);
+    for field in ["", "stalwart", SESSION_TRACKING_FORMAT_OID, trace_id] {
+        ber_encode(&mut fields, 0x04, field.as_bytes());
+    }
+    let mut value = Vec::with_capacity(fields.len() + 6);
+    ber_encode(&mut value, 0x30, &fields);
+
+    RawControl {
+        ctype: SESSION_TRACKING_OID.to_string(),
+        crit: false,
+        val: Some(value),
+    }
+}
+
+fn ber_encode(buf: &mut Vec<u8>, tag: u8, contents: &[u8]) {
+    buf.push(tag);
+    if contents.len() < 0x80 {
+        buf.push(contents.len() as u8);
+    } else {
+        let len = (contents.len() as u32).to_be_bytes();
+        let skip = len.iter().take_while(|b| **b == 0).count();
+        buf.push(0x80 | (len.len() - skip) as u8);
+        buf.extend_from_slice(&len[skip..]);
     }
+    buf.extend_from_slice(contents);
 }
 
 impl LdapMappings {
@@ -33,6 +33,7 @@ pub struct LdapDirectory {
     pool: Pool<LdapConnectionManager>,
     mappings: LdapMappings,
     auth_bind: Option<LdapFilter>,
+    inject_trace_id: bool,
     pub(crate) data_store: Store,
 }
 
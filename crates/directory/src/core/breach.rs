@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    sync::Arc,
+    time::Duration,
+};
+
+use sha1::{Digest, Sha1};
+use utils::config::Config;
+
+use crate::Directory;
+
+use super::secret::password_scheme;
+
+pub struct BreachCheck {
+    pub source: BreachSource,
+    pub on_change: BreachAction,
+    pub on_login: Option<BreachAction>,
+}
+
+pub enum BreachSource {
+    Local(BloomFilter),
+    Remote { url: String, timeout: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreachAction {
+    Warn,
+    ForceChange,
+}
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u64,
+}
+
+const BLOOM_BITS_PER_ENTRY: u64 = 10;
+const BLOOM_NUM_HASHES: u64 = 7;
+
+impl BreachCheck {
+    pub fn from_config(config: &Config) -> utils::config::Result<Option<Arc<Self>>> {
+        let source = match config.value("server.security.breach-check.source") {
+            Some("local") => BreachSource::Local(BloomFilter::from_file(
+                config.value_require("server.security.breach-check.file")?,
+            )?),
+            Some("remote") => BreachSource::Remote {
+                url: config
+                    .value("server.security.breach-check.url")
+                    .unwrap_or("https://api.pwnedpasswords.com/range/")
+                    .to_string(),
+                timeout: config.property_or_static("server.security.breach-check.timeout", "5s")?,
+            },
+            Some(other) => {
+                return Err(format!(
+                    "Invalid value {other:?} for property \"server.security.breach-check.source\"."
+                ))
+            }
+            None => return Ok(None),
+        };
+
+        Ok(Some(Arc::new(BreachCheck {
+            source,
+            on_change: BreachAction::parse(config, "server.security.breach-check.action.change")?
+                .unwrap_or(BreachAction::ForceChange),
+            on_login: BreachAction::parse(config, "server.security.breach-check.action.login")?,
+        })))
+    }
+
+    /// Returns whether a plain-text secret appears in the breach corpus. Only the
+    /// SHA-1 hash of the secret is used, remote lookups disclose just its first
+    /// five hexadecimal characters (k-anonymity range query).
+    pub async fn is_breached(&self, secret: &str) -> bool {
+        let hash = Sha1::digest(secret.as_bytes());
+
+        match &self.source {
+            BreachSource::Local(filter) => filter.contains(hash.as_slice()),
+            BreachSource::Remote { url, timeout } => {
+                let hash = hash
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect::<String>();
+                let (prefix, suffix) = hash.split_at(5);
+
+                match remote_range_query(url, prefix, *timeout).await {
+                    Ok(response) => response.lines().any(|line| {
+                        line.split_once(':').map_or(false, |(line_suffix, count)| {
+                            line_suffix.trim().eq_ignore_ascii_case(suffix)
+                                && count.trim().parse::<u64>().map_or(true, |count| count > 0)
+                        })
+                    }),
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "breach-check",
+                            event = "error",
+                            url = url,
+                            reason = %err,
+                            "Failed to query breached passwords service."
+                        );
+                        false
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn remote_range_query(url: &str, prefix: &str, timeout: Duration) -> reqwest::Result<String> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()?
+        .get(format!("{url}{prefix}"))
+        .header("Add-Padding", "true")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
+}
+
+impl BreachAction {
+    fn parse(config: &Config, key: &str) -> utils::config::Result<Option<Self>> {
+        match config.value(key) {
+            Some("warn") => Ok(Some(BreachAction::Warn)),
+            Some("force-change") => Ok(Some(BreachAction::ForceChange)),
+            Some(other) => Err(format!(
+                "Invalid value {other:?} for property {key:?}, expected \"warn\" or \"force-change\"."
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BloomFilter {
+    pub fn new(num_entries: u64) -> Self {
+        let num_bits = (num_entries.max(1) * BLOOM_BITS_PER_ENTRY).next_multiple_of(64);
+        BloomFilter {
+            bits: vec![0; (num_bits / 64) as usize],
+            num_bits,
+            num_hashes: BLOOM_NUM_HASHES,
+        }
+    }
+
+    /// Builds a filter from a file containing one hex-encoded SHA-1 hash per
+    /// line, optionally followed by `:count` as in the published corpus dumps.
+    pub fn from_file(path: &str) -> utils::config::Result<Self> {
+        let open = || {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|err| format!("Failed to open breach-check file {path:?}: {err}"))
+        };
+        let num_entries = open()?.lines().count() as u64;
+        let mut filter = BloomFilter::new(num_entries);
+
+        for line in open()?.lines() {
+            let line =
+                line.map_err(|err| format!("Failed to read breach-check file {path:?}: {err}"))?;
+            let hash = line.split_once(':').map_or(line.as_str(), |(hash, _)| hash);
+            if let Some(hash) = decode_hex(hash.trim()) {
+                filter.insert(&hash);
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn insert(&mut self, hash: &[u8]) {
+        for bit in self.bit_positions(hash) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.bit_positions(hash)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bit_positions<'x>(&'x self, hash: &'x [u8]) -> impl Iterator<Item = u64> + 'x {
+        // The input is already a cryptographic hash, derive the probes by double hashing
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() == 40 {
+        (0..40)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect()
+    } else {
+        None
+    }
+}
+
+/// Returns the clear-text password of secrets stored without hashing, either
+/// bare or with a `{PLAIN}` or `{CLEAR}` scheme prefix.
+pub fn plain_secret(secret: &str) -> Option<&str> {
+    if password_scheme(secret) == "plain" {
+        Some(
+            secret
+                .strip_prefix('{')
+                .and_then(|secret| secret.split_once('}'))
+                .map_or(secret, |(_, secret)| secret),
+        )
+    } else {
+        None
+    }
+}
+
+impl Directory {
+    /// Checks new plain-text secrets against the breach corpus, returning the
+    /// configured action when any of them has been exposed.
+    pub async fn check_breached_secrets(&self, secrets: &[String]) -> Option<BreachAction> {
+        let breach_check = self.breach_check.as_ref()?;
+        for secret in secrets.iter().filter_map(|secret| plain_secret(secret)) {
+            if breach_check.is_breached(secret).await {
+                return Some(breach_check.on_change);
+            }
+        }
+
+        None
+    }
+}
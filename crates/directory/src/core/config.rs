@@ -28,6 +28,7 @@ use deadpool::{
 use std::{sync::Arc, time::Duration};
 use store::{dispatch::blocked::BlockedIps, Store, Stores};
 use utils::{
+    circuit_breaker::CircuitBreaker,
     config::{
         utils::{AsKey, ParseValue},
         Config,
@@ -45,7 +46,7 @@ use crate::{
     AddressMapping, Directories, Directory, DirectoryInner,
 };
 
-use super::cache::CachedDirectory;
+use super::{breach::BreachCheck, cache::CachedDirectory};
 
 #[allow(async_fn_in_trait)]
 pub trait ConfigDirectory {
@@ -68,6 +69,7 @@ impl ConfigDirectory for Config {
         let blocked_ips = Arc::new(BlockedIps::new(
             stores.get_lookup_store(self, "storage.lookup")?,
         ));
+        let breach_check = BreachCheck::from_config(self)?;
 
         for id in self.sub_keys("directory", ".type") {
             if id.ends_with(".columns") || id.ends_with(".attributes") || id.contains(".principals")
@@ -143,7 +145,6 @@ impl ConfigDirectory for Config {
 
             // Build directory
             let directory = Arc::new(Directory {
-                store,
                 catch_all: AddressMapping::from_config(
                     self,
                     ("directory", id, "options.catch-all"),
@@ -154,6 +155,19 @@ impl ConfigDirectory for Config {
                 )?,
                 cache: CachedDirectory::try_from_config(self, ("directory", id))?,
                 blocked_ips: blocked_ips.clone(),
+                breach_check: breach_check.clone(),
+                circuit_breaker: if matches!(
+                    store,
+                    DirectoryInner::Ldap(_) | DirectoryInner::Imap(_) | DirectoryInner::Smtp(_)
+                ) {
+                    CircuitBreaker::parse(self, ("directory", id), format!("directory.{id}"))?
+                        .into()
+                } else {
+                    None
+                },
+                password_rehash: matches!(store, DirectoryInner::Internal(_))
+                    && self.property_or_static(("directory", id, "password.rehash"), "false")?,
+                store,
             });
 
             // Add directory
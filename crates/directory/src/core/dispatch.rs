@@ -21,16 +21,18 @@
  * for more details.
 */
 
-use std::net::IpAddr;
+use std::{future::Future, net::IpAddr};
 
 use mail_send::Credentials;
 use store::Store;
 
 use crate::{
-    backend::internal::lookup::DirectoryStore, AuthResult, Directory, DirectoryInner, Principal,
-    QueryBy,
+    backend::internal::lookup::DirectoryStore, AuthResult, Directory, DirectoryError,
+    DirectoryInner, Principal, QueryBy, Type,
 };
 
+use super::breach::BreachAction;
+
 impl Directory {
     pub async fn authenticate(
         &self,
@@ -38,17 +40,111 @@ impl Directory {
         remote_ip: IpAddr,
         return_member_of: bool,
     ) -> crate::Result<AuthResult<Principal<u32>>> {
-        if let Some(principal) = self
+        // Locked accounts are rejected regardless of the credentials supplied
+        let lockout_login = match credentials {
+            Credentials::Plain { username, .. } | Credentials::XOauth2 { username, .. } => {
+                Some(username.as_str())
+            }
+            Credentials::OAuthBearer { .. } => None,
+        };
+        if let Some(login) = lockout_login {
+            if self.is_account_locked(login, remote_ip).await {
+                return Ok(AuthResult::Failure);
+            }
+        }
+
+        if let Some(mut principal) = self
             .query(QueryBy::Credentials(credentials), return_member_of)
             .await?
         {
-            Ok(AuthResult::Success(principal))
-        } else if self.blocked_ips.has_fail2ban() {
-            let login = match credentials {
-                Credentials::Plain { username, .. }
-                | Credentials::XOauth2 { username, .. }
-                | Credentials::OAuthBearer { token: username } => username,
-            };
+            // Check whether the password has been exposed in a known breach
+            let mut is_breached = false;
+            if let (Some(breach_check), Credentials::Plain { username, secret }) =
+                (&self.breach_check, credentials)
+            {
+                if let Some(action) = breach_check.on_login {
+                    if breach_check.is_breached(secret).await {
+                        tracing::warn!(
+                            context = "directory",
+                            event = "breach-check",
+                            remote_ip = ?remote_ip,
+                            login = ?username,
+                            action = ?action,
+                            "Password found in breached passwords corpus",
+                        );
+
+                        // SCRAM logins cannot be checked against the corpus
+                        self.remove_scram_verifiers(&mut principal).await;
+                        is_breached = true;
+
+                        if action == BreachAction::ForceChange {
+                            return Ok(AuthResult::MustChangePassword(principal));
+                        }
+                    }
+                }
+            }
+
+            self.upgrade_password_hash(&mut principal, credentials)
+                .await;
+            if !is_breached {
+                self.capture_scram_verifier(&principal, credentials).await;
+            }
+
+            return Ok(AuthResult::Success(principal));
+        }
+
+        let login = match credentials {
+            Credentials::Plain { username, .. }
+            | Credentials::XOauth2 { username, .. }
+            | Credentials::OAuthBearer { token: username } => username,
+        };
+        self.register_auth_failure(login, lockout_login.is_some(), remote_ip)
+            .await
+    }
+
+    pub(crate) async fn is_account_locked(&self, login: &str, remote_ip: IpAddr) -> bool {
+        if self.blocked_ips.has_lockout() && self.blocked_ips.is_account_locked(login).await {
+            tracing::info!(
+                context = "directory",
+                event = "lockout",
+                remote_ip = ?remote_ip,
+                login = ?login,
+                "Rejected login attempt for locked account",
+            );
+
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) async fn register_auth_failure(
+        &self,
+        login: &str,
+        apply_lockout: bool,
+        remote_ip: IpAddr,
+    ) -> crate::Result<AuthResult<Principal<u32>>> {
+        if apply_lockout && self.blocked_ips.has_lockout() {
+            if let Some(duration) = self.blocked_ips.register_login_failure(login).await {
+                tracing::warn!(
+                    context = "directory",
+                    event = "lockout",
+                    remote_ip = ?remote_ip,
+                    login = ?login,
+                    duration = duration.as_secs(),
+                    "Account locked after too many failed login attempts",
+                );
+
+                // Notify the user on their secondary addresses
+                let addresses = match self.query(QueryBy::Name(login), false).await {
+                    Ok(Some(principal)) => principal.emails.into_iter().skip(1).collect(),
+                    _ => vec![],
+                };
+                self.blocked_ips.notify_lockout(login, addresses);
+            }
+        }
+
+        if self.blocked_ips.has_fail2ban() {
             if let Some(banned) = self
                 .blocked_ips
                 .is_fail2banned(remote_ip, login.to_string())
@@ -79,27 +175,60 @@ impl Directory {
         by: QueryBy<'_>,
         return_member_of: bool,
     ) -> crate::Result<Option<Principal<u32>>> {
-        match &self.store {
-            DirectoryInner::Internal(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Ldap(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Sql(store) => store.query(by, return_member_of).await,
-            DirectoryInner::Imap(store) => store.query(by).await,
-            DirectoryInner::Smtp(store) => store.query(by).await,
-            DirectoryInner::Memory(store) => store.query(by).await,
+        self.with_circuit_breaker(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Ldap(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Sql(store) => store.query(by, return_member_of).await,
+                DirectoryInner::Imap(store) => store.query(by).await,
+                DirectoryInner::Smtp(store) => store.query(by).await,
+                DirectoryInner::Memory(store) => store.query(by).await,
+            }
+        })
+        .await
+    }
+
+    /// Resolves the principal asserted by an external identity provider, first
+    /// by login name and then by email address. Only individual accounts are
+    /// returned and addresses shared by several principals are not resolved.
+    pub async fn query_by_claims(
+        &self,
+        name: Option<&str>,
+        email: Option<&str>,
+        return_member_of: bool,
+    ) -> crate::Result<Option<Principal<u32>>> {
+        let mut principal = None;
+        if let Some(name) = name {
+            principal = self.query(QueryBy::Name(name), return_member_of).await?;
         }
+        if let (None, Some(email)) = (&principal, email) {
+            if let [account_id] = self.email_to_ids(email).await?.as_slice() {
+                principal = self
+                    .query(QueryBy::Id(*account_id), return_member_of)
+                    .await?;
+            }
+        }
+
+        Ok(principal.filter(|principal| principal.typ == Type::Individual))
     }
 
     pub async fn email_to_ids(&self, email: &str) -> crate::Result<Vec<u32>> {
         let mut address = self.subaddressing.to_subaddress(email).await;
         for _ in 0..2 {
-            let result = match &self.store {
-                DirectoryInner::Internal(store) => store.email_to_ids(address.as_ref()).await,
-                DirectoryInner::Ldap(store) => store.email_to_ids(address.as_ref()).await,
-                DirectoryInner::Sql(store) => store.email_to_ids(address.as_ref()).await,
-                DirectoryInner::Imap(store) => store.email_to_ids(address.as_ref()).await,
-                DirectoryInner::Smtp(store) => store.email_to_ids(address.as_ref()).await,
-                DirectoryInner::Memory(store) => store.email_to_ids(address.as_ref()).await,
-            }?;
+            let result = self
+                .with_circuit_breaker(async {
+                    match &self.store {
+                        DirectoryInner::Internal(store) => {
+                            store.email_to_ids(address.as_ref()).await
+                        }
+                        DirectoryInner::Ldap(store) => store.email_to_ids(address.as_ref()).await,
+                        DirectoryInner::Sql(store) => store.email_to_ids(address.as_ref()).await,
+                        DirectoryInner::Imap(store) => store.email_to_ids(address.as_ref()).await,
+                        DirectoryInner::Smtp(store) => store.email_to_ids(address.as_ref()).await,
+                        DirectoryInner::Memory(store) => store.email_to_ids(address.as_ref()).await,
+                    }
+                })
+                .await?;
 
             if !result.is_empty() {
                 return Ok(result);
@@ -121,14 +250,18 @@ impl Directory {
             }
         }
 
-        let result = match &self.store {
-            DirectoryInner::Internal(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Ldap(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Sql(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Imap(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Smtp(store) => store.is_local_domain(domain).await,
-            DirectoryInner::Memory(store) => store.is_local_domain(domain).await,
-        }?;
+        let result = self
+            .with_circuit_breaker(async {
+                match &self.store {
+                    DirectoryInner::Internal(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Ldap(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Sql(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Imap(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Smtp(store) => store.is_local_domain(domain).await,
+                    DirectoryInner::Memory(store) => store.is_local_domain(domain).await,
+                }
+            })
+            .await?;
 
         // Update cache
         if let Some(cache) = &self.cache {
@@ -150,14 +283,18 @@ impl Directory {
         }
 
         for _ in 0..2 {
-            let result = match &self.store {
-                DirectoryInner::Internal(store) => store.rcpt(address.as_ref()).await,
-                DirectoryInner::Ldap(store) => store.rcpt(address.as_ref()).await,
-                DirectoryInner::Sql(store) => store.rcpt(address.as_ref()).await,
-                DirectoryInner::Imap(store) => store.rcpt(address.as_ref()).await,
-                DirectoryInner::Smtp(store) => store.rcpt(address.as_ref()).await,
-                DirectoryInner::Memory(store) => store.rcpt(address.as_ref()).await,
-            }?;
+            let result = self
+                .with_circuit_breaker(async {
+                    match &self.store {
+                        DirectoryInner::Internal(store) => store.rcpt(address.as_ref()).await,
+                        DirectoryInner::Ldap(store) => store.rcpt(address.as_ref()).await,
+                        DirectoryInner::Sql(store) => store.rcpt(address.as_ref()).await,
+                        DirectoryInner::Imap(store) => store.rcpt(address.as_ref()).await,
+                        DirectoryInner::Smtp(store) => store.rcpt(address.as_ref()).await,
+                        DirectoryInner::Memory(store) => store.rcpt(address.as_ref()).await,
+                    }
+                })
+                .await?;
 
             if result {
                 // Update cache
@@ -188,25 +325,51 @@ impl Directory {
 
     pub async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
         let address = self.subaddressing.to_subaddress(address).await;
-        match &self.store {
-            DirectoryInner::Internal(store) => store.vrfy(address.as_ref()).await,
-            DirectoryInner::Ldap(store) => store.vrfy(address.as_ref()).await,
-            DirectoryInner::Sql(store) => store.vrfy(address.as_ref()).await,
-            DirectoryInner::Imap(store) => store.vrfy(address.as_ref()).await,
-            DirectoryInner::Smtp(store) => store.vrfy(address.as_ref()).await,
-            DirectoryInner::Memory(store) => store.vrfy(address.as_ref()).await,
-        }
+        self.with_circuit_breaker(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.vrfy(address.as_ref()).await,
+                DirectoryInner::Ldap(store) => store.vrfy(address.as_ref()).await,
+                DirectoryInner::Sql(store) => store.vrfy(address.as_ref()).await,
+                DirectoryInner::Imap(store) => store.vrfy(address.as_ref()).await,
+                DirectoryInner::Smtp(store) => store.vrfy(address.as_ref()).await,
+                DirectoryInner::Memory(store) => store.vrfy(address.as_ref()).await,
+            }
+        })
+        .await
     }
 
     pub async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
         let address = self.subaddressing.to_subaddress(address).await;
-        match &self.store {
-            DirectoryInner::Internal(store) => store.expn(address.as_ref()).await,
-            DirectoryInner::Ldap(store) => store.expn(address.as_ref()).await,
-            DirectoryInner::Sql(store) => store.expn(address.as_ref()).await,
-            DirectoryInner::Imap(store) => store.expn(address.as_ref()).await,
-            DirectoryInner::Smtp(store) => store.expn(address.as_ref()).await,
-            DirectoryInner::Memory(store) => store.expn(address.as_ref()).await,
+        self.with_circuit_breaker(async {
+            match &self.store {
+                DirectoryInner::Internal(store) => store.expn(address.as_ref()).await,
+                DirectoryInner::Ldap(store) => store.expn(address.as_ref()).await,
+                DirectoryInner::Sql(store) => store.expn(address.as_ref()).await,
+                DirectoryInner::Imap(store) => store.expn(address.as_ref()).await,
+                DirectoryInner::Smtp(store) => store.expn(address.as_ref()).await,
+                DirectoryInner::Memory(store) => store.expn(address.as_ref()).await,
+            }
+        })
+        .await
+    }
+
+    async fn with_circuit_breaker<T>(
+        &self,
+        request: impl Future<Output = crate::Result<T>>,
+    ) -> crate::Result<T> {
+        match &self.circuit_breaker {
+            Some(breaker) if breaker.is_open() => Err(DirectoryError::unavailable(breaker.name())),
+            Some(breaker) => {
+                let result = request.await;
+                match &result {
+                    Ok(_) | Err(DirectoryError::Unsupported | DirectoryError::Management(_)) => {
+                        breaker.success()
+                    }
+                    Err(_) => breaker.failure(),
+                }
+                result
+            }
+            None => request.await,
         }
     }
 
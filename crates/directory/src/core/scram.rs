@@ -0,0 +1,464 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Display, net::IpAddr};
+
+use hmac::{Hmac, Mac};
+use mail_builder::encoders::base64::base64_encode;
+use mail_parser::decoders::base64::base64_decode;
+use mail_send::Credentials;
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use sha2::{Digest, Sha256};
+use utils::constant_time_eq;
+
+use crate::{
+    backend::internal::{manage::ManageDirectory, PrincipalField, PrincipalUpdate, PrincipalValue},
+    AuthResult, Directory, DirectoryInner, Principal, QueryBy,
+};
+
+use super::secret::{is_app_password, verify_secret_hash};
+
+pub const SCRAM_PREFIX: &str = "$scram-sha-256$";
+pub const SCRAM_ITERATIONS: u32 = 4096;
+
+/// Channel binding type of the -PLUS mechanisms (RFC 9266).
+pub const CHANNEL_BINDING_TLS_EXPORTER: &str = "tls-exporter";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// SCRAM-SHA-256 verifier (RFC 7677), stored as
+/// `$scram-sha-256$<iterations>$<salt>$<stored key>$<server key>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramVerifier {
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+/// State of a SCRAM exchange between protocol round trips.
+#[derive(Debug, Clone)]
+pub enum ScramState {
+    ClientFirst,
+    ClientFinal(Box<ScramServer>),
+    Verified(String),
+}
+
+/// Outcome of a SCRAM exchange step.
+#[derive(Debug)]
+pub enum ScramStep {
+    /// Challenge to send to the client, the exchange continues with the state.
+    Challenge(ScramState, String),
+    /// The exchange is over, to be completed with `Directory::authenticate_scram`.
+    Authenticate { username: String, is_verified: bool },
+    /// The client message is malformed.
+    Invalid,
+}
+
+/// Server side of a SCRAM-SHA-256(-PLUS) exchange. Only the `tls-exporter`
+/// channel binding type is supported.
+#[derive(Debug, Clone)]
+pub struct ScramServer {
+    username: String,
+    gs2_header: String,
+    channel_binding: Vec<u8>,
+    client_first_bare: String,
+    nonce: String,
+    server_first: String,
+    verifier: Option<ScramVerifier>,
+}
+
+impl ScramVerifier {
+    pub fn new(secret: &str) -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        thread_rng().fill_bytes(&mut salt);
+        Self::derive(secret, salt, SCRAM_ITERATIONS)
+    }
+
+    pub fn derive(secret: &str, salt: Vec<u8>, iterations: u32) -> Self {
+        let mut salted_password = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(secret.as_bytes(), &salt, iterations, &mut salted_password);
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+
+        ScramVerifier {
+            iterations,
+            salt,
+            stored_key: Sha256::digest(client_key).to_vec(),
+            server_key: hmac_sha256(&salted_password, b"Server Key"),
+        }
+    }
+
+    /// Returns a verifier that never matches, used for unknown accounts so
+    /// that their existence is not revealed by the exchange.
+    pub fn unknown(username: &str) -> Self {
+        ScramVerifier {
+            iterations: SCRAM_ITERATIONS,
+            salt: Sha256::digest(username.as_bytes())[..SALT_LEN].to_vec(),
+            stored_key: vec![],
+            server_key: vec![],
+        }
+    }
+
+    pub fn parse(secret: &str) -> Option<Self> {
+        let mut parts = secret.strip_prefix(SCRAM_PREFIX)?.split('$');
+        let verifier = ScramVerifier {
+            iterations: parts.next()?.parse().ok()?,
+            salt: base64_decode(parts.next()?.as_bytes())?,
+            stored_key: base64_decode(parts.next()?.as_bytes())?,
+            server_key: base64_decode(parts.next()?.as_bytes())?,
+        };
+
+        if parts.next().is_none()
+            && verifier.iterations > 0
+            && verifier.stored_key.len() == 32
+            && verifier.server_key.len() == 32
+        {
+            Some(verifier)
+        } else {
+            None
+        }
+    }
+
+    pub fn verify(&self, secret: &str) -> bool {
+        constant_time_eq(
+            &Self::derive(secret, self.salt.clone(), self.iterations).stored_key,
+            &self.stored_key,
+        )
+    }
+}
+
+impl Display for ScramVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{SCRAM_PREFIX}{}${}${}${}",
+            self.iterations,
+            encode(&self.salt),
+            encode(&self.stored_key),
+            encode(&self.server_key)
+        )
+    }
+}
+
+pub fn is_scram_verifier(secret: &str) -> bool {
+    secret.starts_with(SCRAM_PREFIX)
+}
+
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
+    pub fn scram_verifier(&self) -> Option<ScramVerifier> {
+        self.secrets
+            .iter()
+            .find_map(|secret| ScramVerifier::parse(secret))
+    }
+}
+
+impl ScramServer {
+    /// Parses the client-first message, `gs2-header client-first-bare`.
+    /// `channel_binding` holds the binding data of the connection when the
+    /// -PLUS mechanism is offered, in which case clients claiming that the
+    /// server does not support channel binding ("y") are rejected as a
+    /// downgrade (RFC 5802, section 6).
+    pub fn new(client_first: &str, is_plus: bool, channel_binding: Option<&[u8]>) -> Option<Self> {
+        let mut parts = client_first.splitn(3, ',');
+        let cb_flag = parts.next()?;
+        let authzid = parts.next()?;
+        let client_first_bare = parts.next()?;
+        if !(authzid.is_empty() || authzid.starts_with("a=")) {
+            return None;
+        }
+        let channel_binding = match (cb_flag, is_plus, channel_binding) {
+            ("n", false, _) | ("y", false, None) => Vec::new(),
+            (cb_flag, true, Some(channel_binding))
+                if cb_flag.strip_prefix("p=") == Some(CHANNEL_BINDING_TLS_EXPORTER) =>
+            {
+                channel_binding.to_vec()
+            }
+            _ => return None,
+        };
+
+        let mut username = None;
+        let mut nonce = None;
+        for attribute in client_first_bare.split(',') {
+            if let Some(value) = attribute.strip_prefix("n=") {
+                username = Some(value.replace("=2C", ",").replace("=3D", "="));
+            } else if let Some(value) = attribute.strip_prefix("r=") {
+                nonce = Some(value.to_string());
+            } else if attribute.starts_with("m=") {
+                return None;
+            }
+        }
+
+        Some(ScramServer {
+            username: username.filter(|u| !u.is_empty())?,
+            gs2_header: format!("{cb_flag},{authzid},"),
+            channel_binding,
+            client_first_bare: client_first_bare.to_string(),
+            nonce: nonce.filter(|n| !n.is_empty())?,
+            server_first: String::new(),
+            verifier: None,
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Builds the server-first message for the account's verifier.
+    pub fn server_first(&mut self, verifier: ScramVerifier) -> &str {
+        self.nonce.extend(
+            thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(NONCE_LEN)
+                .map(char::from),
+        );
+        self.server_first = format!(
+            "r={},s={},i={}",
+            self.nonce,
+            encode(&verifier.salt),
+            verifier.iterations
+        );
+        self.verifier = verifier.into();
+        &self.server_first
+    }
+
+    /// Verifies the client proof, returning the server-final message on success.
+    pub fn client_final(&self, client_final: &str) -> Option<String> {
+        let verifier = self.verifier.as_ref()?;
+        let (without_proof, proof) = client_final.rsplit_once(",p=")?;
+        let mut channel_binding = None;
+        let mut nonce = None;
+        for attribute in without_proof.split(',') {
+            if let Some(value) = attribute.strip_prefix("c=") {
+                channel_binding = Some(value);
+            } else if let Some(value) = attribute.strip_prefix("r=") {
+                nonce = Some(value);
+            }
+        }
+        let mut expected_binding = self.gs2_header.as_bytes().to_vec();
+        expected_binding.extend_from_slice(&self.channel_binding);
+        if channel_binding? != encode(&expected_binding) || nonce? != self.nonce {
+            return None;
+        }
+
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, self.server_first, without_proof
+        );
+        let client_signature = hmac_sha256(&verifier.stored_key, auth_message.as_bytes());
+        let proof = base64_decode(proof.as_bytes())?;
+        if proof.len() != client_signature.len() || verifier.stored_key.is_empty() {
+            return None;
+        }
+        let client_key = proof
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(a, b)| a ^ b)
+            .collect::<Vec<_>>();
+        if !constant_time_eq(&Sha256::digest(&client_key), &verifier.stored_key) {
+            return None;
+        }
+
+        Some(format!(
+            "v={}",
+            encode(&hmac_sha256(&verifier.server_key, auth_message.as_bytes()))
+        ))
+    }
+}
+
+impl Directory {
+    /// Returns the SCRAM verifier of an account, or a verifier that never
+    /// matches when the account does not exist or has no verifier yet.
+    pub async fn scram_verifier(&self, username: &str) -> crate::Result<ScramVerifier> {
+        Ok(self
+            .query(QueryBy::Name(username), false)
+            .await?
+            .and_then(|principal| principal.scram_verifier())
+            .unwrap_or_else(|| ScramVerifier::unknown(username)))
+    }
+
+    /// Advances a SCRAM exchange with the next (decoded) client message.
+    pub async fn scram_step(
+        &self,
+        state: ScramState,
+        response: &str,
+        is_plus: bool,
+        channel_binding: Option<&[u8]>,
+    ) -> crate::Result<ScramStep> {
+        Ok(match state {
+            ScramState::ClientFirst if response.is_empty() => {
+                ScramStep::Challenge(ScramState::ClientFirst, String::new())
+            }
+            ScramState::ClientFirst => {
+                if let Some(mut server) = ScramServer::new(response, is_plus, channel_binding) {
+                    let verifier = self.scram_verifier(server.username()).await?;
+                    let challenge = server.server_first(verifier).to_string();
+                    ScramStep::Challenge(ScramState::ClientFinal(Box::new(server)), challenge)
+                } else {
+                    ScramStep::Invalid
+                }
+            }
+            ScramState::ClientFinal(server) => {
+                if let Some(server_final) = server.client_final(response) {
+                    ScramStep::Challenge(
+                        ScramState::Verified(server.username().to_string()),
+                        server_final,
+                    )
+                } else {
+                    ScramStep::Authenticate {
+                        username: server.username().to_string(),
+                        is_verified: false,
+                    }
+                }
+            }
+            ScramState::Verified(username) if response.is_empty() => ScramStep::Authenticate {
+                username,
+                is_verified: true,
+            },
+            ScramState::Verified(_) => ScramStep::Invalid,
+        })
+    }
+
+    /// Completes a SCRAM exchange, applying the same lockout and fail2ban
+    /// policies as password logins. The exchange never reveals the password,
+    /// so breached passwords are instead kept from being used here by not
+    /// storing verifiers for them (see `remove_scram_verifiers`).
+    pub async fn authenticate_scram(
+        &self,
+        username: &str,
+        is_verified: bool,
+        remote_ip: IpAddr,
+        return_member_of: bool,
+    ) -> crate::Result<AuthResult<Principal<u32>>> {
+        if is_verified && !self.is_account_locked(username, remote_ip).await {
+            if let Some(principal) = self
+                .query(QueryBy::Name(username), return_member_of)
+                .await?
+            {
+                return Ok(AuthResult::Success(principal));
+            }
+        }
+
+        self.register_auth_failure(username, true, remote_ip).await
+    }
+
+    /// Stores a SCRAM verifier for accounts of the internal directory that
+    /// do not have one yet, so SCRAM becomes available after the next
+    /// successful password login.
+    pub(crate) async fn capture_scram_verifier(
+        &self,
+        principal: &Principal<u32>,
+        credentials: &Credentials<String>,
+    ) {
+        let (DirectoryInner::Internal(store), Credentials::Plain { secret, .. }) =
+            (&self.store, credentials)
+        else {
+            return;
+        };
+        if principal.secrets.iter().any(|s| is_scram_verifier(s)) {
+            return;
+        }
+        match principal.secrets.first() {
+            Some(password)
+                if !is_app_password(password) && verify_secret_hash(password, secret).await => {}
+            _ => return,
+        }
+
+        let mut secrets = principal.secrets.clone();
+        secrets.push(ScramVerifier::new(secret).to_string());
+        if let Err(err) = store
+            .update_account(
+                QueryBy::Id(principal.id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(secrets),
+                )],
+            )
+            .await
+        {
+            tracing::warn!(
+                context = "directory",
+                event = "error",
+                account = principal.name,
+                "Failed to store SCRAM verifier: {}",
+                err
+            );
+        }
+    }
+
+    /// Removes the SCRAM verifiers of an account of the internal directory
+    /// whose password was found in a breach, so that it can only log in
+    /// with mechanisms that check the password against the corpus.
+    pub(crate) async fn remove_scram_verifiers(&self, principal: &mut Principal<u32>) {
+        let DirectoryInner::Internal(store) = &self.store else {
+            return;
+        };
+        if !principal.secrets.iter().any(|s| is_scram_verifier(s)) {
+            return;
+        }
+
+        principal.secrets.retain(|s| !is_scram_verifier(s));
+        if let Err(err) = store
+            .update_account(
+                QueryBy::Id(principal.id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(principal.secrets.clone()),
+                )],
+            )
+            .await
+        {
+            tracing::warn!(
+                context = "directory",
+                event = "error",
+                account = principal.name,
+                "Failed to remove SCRAM verifier: {}",
+                err
+            );
+        }
+    }
+}
+
+/// SCRAM verifiers are derived from the password, so the ones stored before
+/// a password change are removed along with it. Verifiers supplied with the
+/// new password are kept, others are captured again on the next login.
+pub fn remove_stale_verifiers(previous: &[String], secrets: &mut Vec<String>) {
+    let is_password = |secret: &&String| !is_app_password(secret) && !is_scram_verifier(secret);
+    if previous
+        .iter()
+        .filter(is_password)
+        .ne(secrets.iter().filter(is_password))
+    {
+        secrets.retain(|secret| !is_scram_verifier(secret) || !previous.contains(secret));
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn encode(bytes: &[u8]) -> String {
+    String::from_utf8(base64_encode(bytes).unwrap_or_default()).unwrap_or_default()
+}
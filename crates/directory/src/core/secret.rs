@@ -24,9 +24,11 @@
 use argon2::Argon2;
 use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
-use password_hash::PasswordHash;
+use mail_send::Credentials;
+use password_hash::{PasswordHash, PasswordHasher, SaltString};
 use pbkdf2::Pbkdf2;
 use pwhash::{bcrypt, bsdi_crypt, md5_crypt, sha1_crypt, sha256_crypt, sha512_crypt, unix_crypt};
+use rand::{thread_rng, RngCore};
 use scrypt::Scrypt;
 use sha1::Digest;
 use sha1::Sha1;
@@ -34,17 +36,214 @@ use sha2::Sha256;
 use sha2::Sha512;
 use tokio::sync::oneshot;
 
-use crate::Principal;
+use crate::{
+    backend::internal::{manage::ManageDirectory, PrincipalField, PrincipalUpdate, PrincipalValue},
+    Directory, DirectoryInner, Principal, QueryBy,
+};
+
+use super::scram::{is_scram_verifier, ScramVerifier, SCRAM_PREFIX};
+
+const APP_PASSWORD_PREFIX: &str = "$app$";
 
 impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
     pub async fn verify_secret(&self, secret: &str) -> bool {
         for hashed_secret in &self.secrets {
+            let hashed_secret = app_password_parts(hashed_secret)
+                .map_or(hashed_secret.as_str(), |(_, hashed_secret)| hashed_secret);
             if verify_secret_hash(hashed_secret, secret).await {
                 return true;
             }
         }
         false
     }
+
+    /// Verifies a secret against the principal's password only, application
+    /// passwords are not accepted.
+    pub async fn verify_password(&self, secret: &str) -> bool {
+        match self
+            .secrets
+            .iter()
+            .find(|secret| !is_app_password(secret) && !is_scram_verifier(secret))
+        {
+            Some(hashed_secret) => verify_secret_hash(hashed_secret, secret).await,
+            None => false,
+        }
+    }
+
+    /// Returns the names of the application passwords of this principal.
+    pub fn app_password_names(&self) -> impl Iterator<Item = &str> {
+        self.secrets
+            .iter()
+            .filter_map(|secret| app_password_name(secret))
+    }
+
+    /// Returns the hashing scheme of the principal's password, if it has one.
+    pub fn password_scheme(&self) -> Option<&'static str> {
+        self.secrets
+            .first()
+            .filter(|secret| !is_app_password(secret) && !is_scram_verifier(secret))
+            .map(|secret| password_scheme(secret))
+    }
+}
+
+impl Directory {
+    /// Rehashes passwords of internal directory accounts stored with a legacy
+    /// scheme to Argon2id after a successful password login. LDAP, SQL and
+    /// the other backends are read-only, so their hashes are left untouched.
+    pub(crate) async fn upgrade_password_hash(
+        &self,
+        principal: &mut Principal<u32>,
+        credentials: &Credentials<String>,
+    ) {
+        let (true, DirectoryInner::Internal(store), Credentials::Plain { secret, .. }) =
+            (self.password_rehash, &self.store, credentials)
+        else {
+            return;
+        };
+        match principal.secrets.first() {
+            Some(password)
+                if principal.password_scheme().map_or(false, needs_rehash)
+                    && verify_secret_hash(password, secret).await => {}
+            _ => return,
+        }
+        let Some(hashed_secret) = hash_secret_argon2(secret).await else {
+            return;
+        };
+
+        // The SCRAM verifier is derived again, as the rehash replaces the password
+        let mut secrets = principal.secrets.clone();
+        let scheme = password_scheme(&std::mem::replace(&mut secrets[0], hashed_secret));
+        if secrets.iter().any(|secret| is_scram_verifier(secret)) {
+            secrets.retain(|secret| !is_scram_verifier(secret));
+            secrets.push(ScramVerifier::new(secret).to_string());
+        }
+        match store
+            .update_account(
+                QueryBy::Id(principal.id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(secrets.clone()),
+                )],
+            )
+            .await
+        {
+            Ok(_) => {
+                tracing::debug!(
+                    context = "directory",
+                    event = "rehash",
+                    account = principal.name,
+                    scheme = scheme,
+                    "Upgraded password hash to Argon2id"
+                );
+                principal.secrets = secrets;
+            }
+            Err(err) => {
+                tracing::warn!(
+                    context = "directory",
+                    event = "error",
+                    account = principal.name,
+                    "Failed to upgrade password hash: {}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Hashes a secret with Argon2id before storing it.
+pub fn hash_secret(secret: &str) -> String {
+    let mut salt = [0u8; 16];
+    thread_rng().fill_bytes(&mut salt);
+
+    SaltString::encode_b64(&salt)
+        .and_then(|salt| Argon2::default().hash_password(secret.as_bytes(), &salt))
+        .map(|hash| hash.to_string())
+        .unwrap_or_default()
+}
+
+/// Hashes a secret with Argon2id on a blocking thread, the scheme legacy
+/// hashes are upgraded to.
+pub async fn hash_secret_argon2(secret: &str) -> Option<String> {
+    let secret = secret.to_string();
+
+    tokio::task::spawn_blocking(move || hash_secret(&secret))
+        .await
+        .ok()
+        .filter(|hash| !hash.is_empty())
+}
+
+/// Returns the name of the scheme a password is hashed with.
+pub fn password_scheme(hashed_secret: &str) -> &'static str {
+    if let Some((algo, hashed_secret)) = hashed_secret
+        .strip_prefix('{')
+        .and_then(|hashed_secret| hashed_secret.split_once('}'))
+    {
+        match algo {
+            "ARGON2" | "ARGON2I" | "ARGON2ID" | "PBKDF2" => password_scheme(hashed_secret),
+            "CRYPT" | "crypt" if hashed_secret.starts_with('$') => password_scheme(hashed_secret),
+            "CRYPT" | "crypt" => "des-crypt",
+            "SHA" => "sha1",
+            "SSHA" => "ssha1",
+            "SHA256" => "sha256",
+            "SSHA256" => "ssha256",
+            "SHA512" => "sha512",
+            "SSHA512" => "ssha512",
+            "MD5" => "md5",
+            "PLAIN" | "plain" | "CLEAR" | "clear" => "plain",
+            _ => "unknown",
+        }
+    } else if hashed_secret.starts_with("$argon2id") {
+        "argon2id"
+    } else if hashed_secret.starts_with("$argon2") {
+        "argon2"
+    } else if hashed_secret.starts_with("$pbkdf2") {
+        "pbkdf2"
+    } else if hashed_secret.starts_with("$scrypt") {
+        "scrypt"
+    } else if hashed_secret.starts_with(SCRAM_PREFIX) {
+        "scram-sha-256"
+    } else if hashed_secret.starts_with("$2") {
+        "bcrypt"
+    } else if hashed_secret.starts_with("$6$") {
+        "sha512-crypt"
+    } else if hashed_secret.starts_with("$5$") {
+        "sha256-crypt"
+    } else if hashed_secret.starts_with("$sha1") {
+        "sha1-crypt"
+    } else if hashed_secret.starts_with("$1") {
+        "md5-crypt"
+    } else if hashed_secret.starts_with('_') {
+        "bsdi-crypt"
+    } else if hashed_secret.starts_with(['$', '{']) {
+        "unknown"
+    } else {
+        "plain"
+    }
+}
+
+/// Passwords hashed with any scheme other than Argon2id are rehashed on login.
+pub fn needs_rehash(scheme: &str) -> bool {
+    !matches!(scheme, "argon2id" | "unknown")
+}
+
+/// Application passwords are stored as `$app$<name><hashed secret>`.
+pub async fn app_password_secret(name: &str, secret: &str) -> Option<String> {
+    hash_secret_argon2(secret)
+        .await
+        .map(|hashed_secret| format!("{APP_PASSWORD_PREFIX}{name}{hashed_secret}"))
+}
+
+pub fn is_app_password(secret: &str) -> bool {
+    secret.starts_with(APP_PASSWORD_PREFIX)
+}
+
+pub fn app_password_name(secret: &str) -> Option<&str> {
+    app_password_parts(secret).map(|(name, _)| name)
+}
+
+fn app_password_parts(secret: &str) -> Option<(&str, &str)> {
+    let secret = secret.strip_prefix(APP_PASSWORD_PREFIX)?;
+    secret.find('$').map(|pos| secret.split_at(pos))
 }
 
 async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> bool {
@@ -109,8 +308,11 @@ async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> bool {
     }
 }
 
-async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
-    if hashed_secret.starts_with('$') {
+pub(crate) async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
+    if is_scram_verifier(hashed_secret) {
+        // Verifiers are only used by SCRAM exchanges, never for password logins
+        false
+    } else if hashed_secret.starts_with('$') {
         verify_hash_prefix(hashed_secret, secret).await
     } else if hashed_secret.starts_with('_') {
         // Enhanced DES-based hash
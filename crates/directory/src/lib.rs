@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use core::cache::CachedDirectory;
+use core::{breach::BreachCheck, cache::CachedDirectory};
 use std::{borrow::Cow, fmt::Debug, sync::Arc};
 
 use ahash::AHashMap;
@@ -37,7 +37,7 @@ use deadpool::managed::PoolError;
 use ldap3::LdapError;
 use mail_send::Credentials;
 use store::{dispatch::blocked::BlockedIps, Store};
-use utils::{config::if_block::IfBlock, expr::Variable};
+use utils::{circuit_breaker::CircuitBreaker, config::if_block::IfBlock, expr::Variable};
 
 pub mod backend;
 pub mod core;
@@ -48,6 +48,9 @@ pub struct Directory {
     pub subaddressing: AddressMapping,
     pub cache: Option<CachedDirectory>,
     pub blocked_ips: Arc<BlockedIps>,
+    pub breach_check: Option<Arc<BreachCheck>>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub password_rehash: bool,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -99,6 +102,7 @@ pub enum DirectoryError {
     Management(ManagementError),
     TimedOut,
     Unsupported,
+    Unavailable,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -128,6 +132,9 @@ pub enum QueryBy<'x> {
 
 pub enum AuthResult<T> {
     Success(T),
+    /// Valid credentials whose password must be changed before the account
+    /// can be used, only the password change itself is allowed.
+    MustChangePassword(T),
     Failure,
     Banned,
 }
@@ -287,6 +294,16 @@ impl DirectoryError {
         );
         DirectoryError::TimedOut
     }
+
+    pub fn unavailable(directory: &str) -> Self {
+        tracing::debug!(
+            context = "directory",
+            event = "error",
+            directory = directory,
+            "Directory is temporarily unavailable"
+        );
+        DirectoryError::Unavailable
+    }
 }
 
 impl AddressMapping {
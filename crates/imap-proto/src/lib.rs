@@ -89,6 +89,9 @@ pub enum Command {
 
     // RFC 2971
     Id,
+
+    // RFC 4978
+    Compress,
 }
 
 impl Command {
@@ -105,6 +108,57 @@ impl Command {
                 | Command::Thread(true)
         )
     }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Capability => "CAPABILITY",
+            Command::Noop => "NOOP",
+            Command::Logout => "LOGOUT",
+            Command::StartTls => "STARTTLS",
+            Command::Authenticate => "AUTHENTICATE",
+            Command::Login => "LOGIN",
+            Command::Enable => "ENABLE",
+            Command::Select => "SELECT",
+            Command::Examine => "EXAMINE",
+            Command::Create => "CREATE",
+            Command::Delete => "DELETE",
+            Command::Rename => "RENAME",
+            Command::Subscribe => "SUBSCRIBE",
+            Command::Unsubscribe => "UNSUBSCRIBE",
+            Command::List => "LIST",
+            Command::Namespace => "NAMESPACE",
+            Command::Status => "STATUS",
+            Command::Append => "APPEND",
+            Command::Idle => "IDLE",
+            Command::Close => "CLOSE",
+            Command::Unselect => "UNSELECT",
+            Command::Expunge(false) => "EXPUNGE",
+            Command::Expunge(true) => "UID EXPUNGE",
+            Command::Search(false) => "SEARCH",
+            Command::Search(true) => "UID SEARCH",
+            Command::Fetch(false) => "FETCH",
+            Command::Fetch(true) => "UID FETCH",
+            Command::Store(false) => "STORE",
+            Command::Store(true) => "UID STORE",
+            Command::Copy(false) => "COPY",
+            Command::Copy(true) => "UID COPY",
+            Command::Move(false) => "MOVE",
+            Command::Move(true) => "UID MOVE",
+            Command::Lsub => "LSUB",
+            Command::Check => "CHECK",
+            Command::Sort(false) => "SORT",
+            Command::Sort(true) => "UID SORT",
+            Command::Thread(false) => "THREAD",
+            Command::Thread(true) => "UID THREAD",
+            Command::SetAcl => "SETACL",
+            Command::DeleteAcl => "DELETEACL",
+            Command::GetAcl => "GETACL",
+            Command::ListRights => "LISTRIGHTS",
+            Command::MyRights => "MYRIGHTS",
+            Command::Unauthenticate => "UNAUTHENTICATE",
+            Command::Id => "ID",
+            Command::Compress => "COMPRESS",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -167,6 +221,9 @@ pub enum ResponseCode {
 
     // USEATTR
     UseAttr,
+
+    // COMPRESS
+    CompressionActive,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
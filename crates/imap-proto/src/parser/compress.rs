@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{
+    protocol::compress::{self, Algorithm},
+    receiver::Request,
+    Command,
+};
+
+impl Request<Command> {
+    pub fn parse_compress(self) -> crate::Result<compress::Arguments> {
+        match self.tokens.len() {
+            1 => {
+                let algorithm = self.tokens.into_iter().next().unwrap().unwrap_bytes();
+                if algorithm.eq_ignore_ascii_case(b"DEFLATE") {
+                    Ok(compress::Arguments {
+                        tag: self.tag,
+                        algorithm: Algorithm::Deflate,
+                    })
+                } else {
+                    Err(crate::StatusResponse::bad(format!(
+                        "Unsupported compression algorithm {:?}.",
+                        String::from_utf8_lossy(&algorithm)
+                    ))
+                    .with_tag(self.tag))
+                }
+            }
+            0 => Err(self.into_error("Missing compression algorithm.")),
+            _ => Err(self.into_error("Too many arguments.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        protocol::compress::{self, Algorithm},
+        receiver::Receiver,
+    };
+
+    #[test]
+    fn parse_compress() {
+        let mut receiver = Receiver::new();
+
+        for (command, arguments) in [
+            (
+                "a COMPRESS DEFLATE\r\n",
+                compress::Arguments {
+                    tag: "a".to_string(),
+                    algorithm: Algorithm::Deflate,
+                },
+            ),
+            (
+                "b compress deflate\r\n",
+                compress::Arguments {
+                    tag: "b".to_string(),
+                    algorithm: Algorithm::Deflate,
+                },
+            ),
+        ] {
+            assert_eq!(
+                receiver
+                    .parse(&mut command.as_bytes().iter())
+                    .unwrap()
+                    .parse_compress()
+                    .unwrap(),
+                arguments
+            );
+        }
+
+        for command in ["c COMPRESS\r\n", "d COMPRESS BZIP2\r\n"] {
+            assert!(receiver
+                .parse(&mut command.as_bytes().iter())
+                .unwrap()
+                .parse_compress()
+                .is_err());
+        }
+    }
+}
@@ -336,6 +336,33 @@ impl Request<Command> {
                         attributes.push_unique(Attribute::EmailId);
                     } else if value.eq_ignore_ascii_case(b"THREADID") {
                         attributes.push_unique(Attribute::ThreadId);
+                    } else if value.eq_ignore_ascii_case(b"ANNOTATION") {
+                        if tokens
+                            .next()
+                            .map_or(true, |token| !token.is_parenthesis_open())
+                        {
+                            return Err(
+                                (self.tag.as_str(), "Expected '(' after 'ANNOTATION'.").into()
+                            );
+                        }
+                        let entries = parse_annotation_list(&mut tokens)
+                            .map_err(|v| (self.tag.as_str(), v))?;
+                        let annotation_attributes = parse_annotation_list(&mut tokens)
+                            .map_err(|v| (self.tag.as_str(), v))?;
+                        if tokens
+                            .next()
+                            .map_or(true, |token| !token.is_parenthesis_close())
+                        {
+                            return Err((
+                                self.tag.as_str(),
+                                "Expected ')' after annotation attributes.",
+                            )
+                                .into());
+                        }
+                        attributes.push_unique(Attribute::Annotation {
+                            entries,
+                            attributes: annotation_attributes,
+                        });
                     } else {
                         return Err((
                             self.tag,
@@ -455,6 +482,42 @@ pub fn parse_partial(tokens: &mut Peekable<IntoIter<Token>>) -> super::Result<Op
     Ok(Some((start, end)))
 }
 
+fn parse_annotation_list(tokens: &mut Peekable<IntoIter<Token>>) -> super::Result<Vec<String>> {
+    let mut items = Vec::new();
+    match tokens.next() {
+        Some(Token::ParenthesisOpen) => loop {
+            match tokens.next() {
+                Some(Token::ParenthesisClose) if !items.is_empty() => break,
+                Some(Token::Argument(value)) => {
+                    items.push(parse_dotted_value(tokens, value)?);
+                }
+                _ => return Err("Invalid annotation list.".into()),
+            }
+        },
+        Some(Token::Argument(value)) => {
+            items.push(parse_dotted_value(tokens, value)?);
+        }
+        _ => return Err("Expected annotation entry or attribute.".into()),
+    }
+    Ok(items)
+}
+
+// Dots are tokenized in FETCH commands, join them back for names such as "value.shared"
+fn parse_dotted_value(
+    tokens: &mut Peekable<IntoIter<Token>>,
+    mut value: Vec<u8>,
+) -> super::Result<String> {
+    while tokens.peek().map_or(false, |token| token.is_dot()) {
+        tokens.next();
+        value.push(b'.');
+        if let Some(Token::Argument(part)) = tokens.peek() {
+            value.extend_from_slice(part);
+            tokens.next();
+        }
+    }
+    String::from_utf8(value).map_err(|_| Cow::from("Invalid UTF-8 in annotation name."))
+}
+
 /*
 
    fetch           = "FETCH" SP sequence-set SP (
@@ -772,6 +835,25 @@ mod tests {
                     include_vanished: false,
                 },
             ),
+            (
+                "A001 FETCH 1 (UID ANNOTATION (/vendor/stalwart/crm.id (value.shared value.priv)))\r\n",
+                fetch::Arguments {
+                    tag: "A001".to_string(),
+                    sequence_set: Sequence::number(1),
+                    attributes: vec![
+                        Attribute::Uid,
+                        Attribute::Annotation {
+                            entries: vec!["/vendor/stalwart/crm.id".to_string()],
+                            attributes: vec![
+                                "value.shared".to_string(),
+                                "value.priv".to_string(),
+                            ],
+                        },
+                    ],
+                    changed_since: None,
+                    include_vanished: false,
+                },
+            ),
             (
                 "s100 UID FETCH 1:* (FLAGS MODSEQ) (CHANGEDSINCE 12345 VANISHED)\r\n",
                 fetch::Arguments {
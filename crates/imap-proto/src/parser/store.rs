@@ -82,6 +82,19 @@ impl Request<Command> {
             .next()
             .ok_or((self.tag.as_str(), "Missing message data item name."))?
             .unwrap_bytes();
+        if operation.eq_ignore_ascii_case(b"ANNOTATION") {
+            return parse_annotations(tokens)
+                .map(|annotations| store::Arguments {
+                    tag: self.tag.clone(),
+                    sequence_set,
+                    operation: Operation::Annotate,
+                    is_silent: true,
+                    keywords: vec![],
+                    annotations,
+                    unchanged_since,
+                })
+                .map_err(|v| (self.tag.as_str(), v).into());
+        }
         let (is_silent, operation) = if operation.eq_ignore_ascii_case(b"FLAGS") {
             (false, Operation::Set)
         } else if operation.eq_ignore_ascii_case(b"FLAGS.SILENT") {
@@ -142,6 +155,7 @@ impl Request<Command> {
                 operation,
                 is_silent,
                 keywords,
+                annotations: vec![],
                 unchanged_since,
             })
         } else {
@@ -150,6 +164,66 @@ impl Request<Command> {
     }
 }
 
+fn parse_annotations(
+    mut tokens: impl Iterator<Item = Token>,
+) -> super::Result<Vec<store::Annotation>> {
+    if !tokens
+        .next()
+        .map_or(false, |token| token.is_parenthesis_open())
+    {
+        return Err("Expected '(' after 'ANNOTATION'.".into());
+    }
+
+    let mut annotations = Vec::new();
+    loop {
+        let entry = match tokens.next() {
+            Some(Token::Argument(entry)) => String::from_utf8(entry)
+                .map_err(|_| Cow::from("Invalid UTF-8 in annotation entry."))?,
+            Some(Token::ParenthesisClose) if !annotations.is_empty() => break,
+            _ => return Err("Expected annotation entry.".into()),
+        };
+        if !tokens
+            .next()
+            .map_or(false, |token| token.is_parenthesis_open())
+        {
+            return Err("Expected '(' after annotation entry.".into());
+        }
+        let mut value = None;
+        let mut has_value = false;
+        loop {
+            match tokens.next() {
+                Some(Token::Argument(attribute)) => {
+                    let attribute_value = match tokens.next() {
+                        Some(Token::Argument(value)) if value.eq_ignore_ascii_case(b"NIL") => None,
+                        Some(Token::Argument(value)) => Some(
+                            String::from_utf8(value)
+                                .map_err(|_| Cow::from("Invalid UTF-8 in annotation value."))?,
+                        ),
+                        _ => return Err("Missing annotation value.".into()),
+                    };
+                    if attribute.eq_ignore_ascii_case(b"value.shared")
+                        || attribute.eq_ignore_ascii_case(b"value.priv")
+                    {
+                        value = attribute_value;
+                        has_value = true;
+                    } else {
+                        return Err(format!(
+                            "Unsupported annotation attribute {:?}.",
+                            String::from_utf8_lossy(&attribute)
+                        )
+                        .into());
+                    }
+                }
+                Some(Token::ParenthesisClose) if has_value => break,
+                _ => return Err("Invalid annotation attributes.".into()),
+            }
+        }
+        annotations.push(store::Annotation { entry, value });
+    }
+
+    Ok(annotations)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -176,6 +250,7 @@ mod tests {
                     is_silent: false,
                     operation: Operation::Add,
                     keywords: vec![Flag::Deleted],
+                    annotations: vec![],
                     tag: "A003".to_string(),
                     unchanged_since: None,
                 },
@@ -190,6 +265,7 @@ mod tests {
                     is_silent: true,
                     operation: Operation::Clear,
                     keywords: vec![Flag::Phishing, Flag::Junk],
+                    annotations: vec![],
                     tag: "A004".to_string(),
                     unchanged_since: None,
                 },
@@ -207,10 +283,35 @@ mod tests {
                     is_silent: true,
                     operation: Operation::Add,
                     keywords: vec![Flag::Deleted],
+                    annotations: vec![],
                     tag: "d105".to_string(),
                     unchanged_since: Some(320162338),
                 },
             ),
+            (
+                concat!(
+                    "A005 STORE 1 ANNOTATION (/vendor/stalwart/crm (value.shared \"1234\") ",
+                    "/vendor/stalwart/ticket (value.shared NIL))\r\n"
+                ),
+                store::Arguments {
+                    sequence_set: Sequence::Number { value: 1 },
+                    is_silent: true,
+                    operation: Operation::Annotate,
+                    keywords: vec![],
+                    annotations: vec![
+                        store::Annotation {
+                            entry: "/vendor/stalwart/crm".to_string(),
+                            value: Some("1234".to_string()),
+                        },
+                        store::Annotation {
+                            entry: "/vendor/stalwart/ticket".to_string(),
+                            value: None,
+                        },
+                    ],
+                    tag: "A005".to_string(),
+                    unchanged_since: None,
+                },
+            ),
         ] {
             assert_eq!(
                 receiver
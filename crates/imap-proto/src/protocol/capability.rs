@@ -64,6 +64,8 @@ pub enum Capability {
     ObjectId,
     Preview,
     Utf8Accept,
+    Annotate, //ANNOTATE-EXPERIMENT-1
+    Compress, //COMPRESS=DEFLATE
     Auth(Mechanism),
 }
 
@@ -109,10 +111,16 @@ impl Capability {
             Capability::CreateSpecialUse => b"CREATE-SPECIAL-USE",
             Capability::Move => b"MOVE",
             Capability::Utf8Accept => b"UTF8=ACCEPT",
+            Capability::Annotate => b"ANNOTATE-EXPERIMENT-1",
+            Capability::Compress => b"COMPRESS=DEFLATE",
         });
     }
 
-    pub fn all_capabilities(is_authenticated: bool, is_tls: bool) -> Vec<Capability> {
+    pub fn all_capabilities(
+        is_authenticated: bool,
+        is_tls: bool,
+        is_compress_available: bool,
+    ) -> Vec<Capability> {
         let mut capabilties = vec![
             Capability::IMAP4rev2,
             Capability::IMAP4rev1,
@@ -150,16 +158,21 @@ impl Capability {
                 Capability::StatusSize,
                 Capability::ObjectId,
                 Capability::Preview,
+                Capability::Annotate,
             ]);
         } else {
             capabilties.extend([
                 Capability::Auth(Mechanism::OAuthBearer),
                 Capability::Auth(Mechanism::Plain),
+                Capability::Auth(Mechanism::ScramSha256),
             ]);
         }
         if !is_tls {
             capabilties.push(Capability::StartTLS);
         }
+        if is_compress_available {
+            capabilties.push(Capability::Compress);
+        }
 
         capabilties
     }
@@ -27,7 +27,7 @@ use mail_parser::DateTime;
 
 use super::{
     literal_string, quoted_or_literal_string, quoted_or_literal_string_or_nil,
-    quoted_rfc2822_or_nil, quoted_timestamp, Flag, ImapResponse, Sequence,
+    quoted_rfc2822_or_nil, quoted_string, quoted_timestamp, Flag, ImapResponse, Sequence,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -81,6 +81,10 @@ pub enum Attribute {
     ModSeq,
     EmailId,
     ThreadId,
+    Annotation {
+        entries: Vec<String>,
+        attributes: Vec<String>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -150,6 +154,9 @@ pub enum DataItem<'x> {
     ThreadId {
         thread_id: String,
     },
+    Annotation {
+        entries: Vec<(String, Vec<(String, Option<String>)>)>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -861,6 +868,26 @@ impl<'x> DataItem<'x> {
                 buf.extend_from_slice(thread_id.as_bytes());
                 buf.push(b')');
             }
+            DataItem::Annotation { entries } => {
+                buf.extend_from_slice(b"ANNOTATION (");
+                for (pos, (entry, attributes)) in entries.iter().enumerate() {
+                    if pos > 0 {
+                        buf.push(b' ');
+                    }
+                    quoted_string(buf, entry);
+                    buf.extend_from_slice(b" (");
+                    for (pos, (attribute, value)) in attributes.iter().enumerate() {
+                        if pos > 0 {
+                            buf.push(b' ');
+                        }
+                        buf.extend_from_slice(attribute.as_bytes());
+                        buf.push(b' ');
+                        quoted_or_literal_string_or_nil(buf, value.as_deref());
+                    }
+                    buf.push(b')');
+                }
+                buf.push(b')');
+            }
         }
     }
 }
@@ -25,18 +25,25 @@ use super::{quoted_string, ImapResponse};
 
 pub struct Response {
     pub shared_prefix: Option<String>,
+    pub unified_prefix: Option<String>,
 }
 
 impl ImapResponse for Response {
     fn serialize(self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(64);
-        if let Some(shared_prefix) = &self.shared_prefix {
-            buf.extend_from_slice(b"* NAMESPACE ((\"\" \"/\")) ((");
-            quoted_string(&mut buf, shared_prefix);
-            buf.extend_from_slice(b" \"/\")) NIL\r\n");
-        } else {
-            buf.extend_from_slice(b"* NAMESPACE ((\"\" \"/\")) NIL NIL\r\n");
+        buf.extend_from_slice(b"* NAMESPACE ((\"\" \"/\")) ");
+        for prefix in [&self.shared_prefix, &self.unified_prefix] {
+            if let Some(prefix) = prefix {
+                buf.extend_from_slice(b"((");
+                quoted_string(&mut buf, prefix);
+                buf.extend_from_slice(b" \"/\"))");
+            } else {
+                buf.extend_from_slice(b"NIL");
+            }
+            buf.push(b' ');
         }
+        buf.pop();
+        buf.extend_from_slice(b"\r\n");
         buf
     }
 }
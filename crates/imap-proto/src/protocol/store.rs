@@ -30,6 +30,7 @@ pub struct Arguments {
     pub operation: Operation,
     pub is_silent: bool,
     pub keywords: Vec<Flag>,
+    pub annotations: Vec<Annotation>,
     pub unchanged_since: Option<u64>,
 }
 
@@ -38,6 +39,17 @@ pub enum Operation {
     Set,
     Add,
     Clear,
+    Annotate,
+}
+
+/// Message annotations (RFC 5257) are mapped to the message's key-value
+/// annotations, using entry names under this prefix.
+pub const ANNOTATION_PREFIX: &str = "/vendor/stalwart/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub entry: String,
+    pub value: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,19 +21,26 @@
  * for more details.
 */
 
-use std::{iter::Peekable, sync::Arc, vec::IntoIter};
+use std::{
+    iter::Peekable,
+    sync::{atomic::Ordering, Arc},
+    vec::IntoIter,
+};
 
 use imap_proto::{
-    receiver::{self, Request},
+    receiver::{self, Request, Token},
     Command, ResponseCode, StatusResponse,
 };
 use jmap::auth::rate_limit::ConcurrencyLimiters;
-use utils::listener::{limiter::ConcurrencyLimiter, SessionStream};
+use utils::{
+    listener::{limiter::ConcurrencyLimiter, SessionStream},
+    metrics::{measure_command_with, slow::CommandDetails},
+};
 
-use super::{SelectedMailbox, Session, SessionData, State, IMAP};
+use super::{SelectedMailbox, Session, SessionData, State, StreamUpgrade, IMAP};
 
 impl<T: SessionStream> Session<T> {
-    pub async fn ingest(&mut self, bytes: &[u8]) -> crate::Result<bool> {
+    pub async fn ingest(&mut self, bytes: &[u8]) -> crate::Result<Option<StreamUpgrade>> {
         /*for line in String::from_utf8_lossy(bytes).split("\r\n") {
             let c = println!("{}", line);
         }*/
@@ -73,83 +80,181 @@ impl<T: SessionStream> Session<T> {
 
         let mut requests = requests.into_iter().peekable();
         while let Some(request) = requests.next() {
+            let command = request.command.name();
+            let details = CommandDetails::new(
+                self.state
+                    .is_authenticated()
+                    .then(|| self.state.session_data().account_id),
+                || match request.command {
+                    Command::Login | Command::Authenticate => String::new(),
+                    _ => sanitize_tokens(&request.tokens),
+                },
+            );
             match request.command {
                 Command::List | Command::Lsub => {
-                    self.handle_list(request).await?;
+                    measure_command_with("imap", command, details, self.handle_list(request))
+                        .await?;
                 }
                 Command::Select | Command::Examine => {
-                    self.handle_select(request).await?;
+                    measure_command_with("imap", command, details, self.handle_select(request))
+                        .await?;
                 }
                 Command::Create => {
-                    self.handle_create(group_requests(&mut requests, vec![request]))
-                        .await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_create(group_requests(&mut requests, vec![request])),
+                    )
+                    .await?;
                 }
                 Command::Delete => {
-                    self.handle_delete(group_requests(&mut requests, vec![request]))
-                        .await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_delete(group_requests(&mut requests, vec![request])),
+                    )
+                    .await?;
                 }
                 Command::Rename => {
-                    self.handle_rename(request).await?;
+                    measure_command_with("imap", command, details, self.handle_rename(request))
+                        .await?;
                 }
                 Command::Status => {
-                    self.handle_status(request).await?;
+                    measure_command_with("imap", command, details, self.handle_status(request))
+                        .await?;
                 }
                 Command::Append => {
-                    self.handle_append(request).await?;
+                    measure_command_with("imap", command, details, self.handle_append(request))
+                        .await?;
                 }
                 Command::Close => {
-                    self.handle_close(request).await?;
+                    measure_command_with("imap", command, details, self.handle_close(request))
+                        .await?;
                 }
                 Command::Unselect => {
-                    self.handle_unselect(request).await?;
+                    measure_command_with("imap", command, details, self.handle_unselect(request))
+                        .await?;
                 }
                 Command::Expunge(is_uid) => {
-                    self.handle_expunge(request, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_expunge(request, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Search(is_uid) => {
-                    self.handle_search(request, false, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_search(request, false, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Fetch(is_uid) => {
-                    self.handle_fetch(request, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_fetch(request, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Store(is_uid) => {
-                    self.handle_store(request, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_store(request, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Copy(is_uid) => {
-                    self.handle_copy_move(request, false, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_copy_move(request, false, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Move(is_uid) => {
-                    self.handle_copy_move(request, true, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_copy_move(request, true, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Sort(is_uid) => {
-                    self.handle_search(request, true, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_search(request, true, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Thread(is_uid) => {
-                    self.handle_thread(request, is_uid).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_thread(request, is_uid),
+                    )
+                    .await?;
                 }
                 Command::Idle => {
-                    self.handle_idle(request).await?;
+                    measure_command_with("imap", command, details, self.handle_idle(request))
+                        .await?;
                 }
                 Command::Subscribe => {
-                    self.handle_subscribe(request, true).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_subscribe(request, true),
+                    )
+                    .await?;
                 }
                 Command::Unsubscribe => {
-                    self.handle_subscribe(request, false).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_subscribe(request, false),
+                    )
+                    .await?;
                 }
                 Command::Namespace => {
-                    self.handle_namespace(request).await?;
+                    measure_command_with("imap", command, details, self.handle_namespace(request))
+                        .await?;
                 }
                 Command::Authenticate => {
-                    self.handle_authenticate(request).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_authenticate(request),
+                    )
+                    .await?;
                 }
                 Command::Login => {
-                    self.handle_login(request).await?;
+                    measure_command_with("imap", command, details, self.handle_login(request))
+                        .await?;
                 }
                 Command::Capability => {
-                    self.handle_capability(request).await?;
+                    measure_command_with("imap", command, details, self.handle_capability(request))
+                        .await?;
                 }
                 Command::Enable => {
-                    self.handle_enable(request).await?;
+                    measure_command_with("imap", command, details, self.handle_enable(request))
+                        .await?;
                 }
                 Command::StartTls => {
                     return self
@@ -159,37 +264,61 @@ impl<T: SessionStream> Session<T> {
                                 .into_bytes(),
                         )
                         .await
-                        .map(|_| true);
+                        .map(|_| Some(StreamUpgrade::Tls));
+                }
+                Command::Compress => {
+                    if self.handle_compress(request).await? {
+                        return Ok(Some(StreamUpgrade::Deflate));
+                    }
                 }
                 Command::Noop => {
-                    self.handle_noop(request).await?;
+                    measure_command_with("imap", command, details, self.handle_noop(request))
+                        .await?;
                 }
                 Command::Check => {
-                    self.handle_noop(request).await?;
+                    measure_command_with("imap", command, details, self.handle_noop(request))
+                        .await?;
                 }
                 Command::Logout => {
-                    self.handle_logout(request).await?;
+                    measure_command_with("imap", command, details, self.handle_logout(request))
+                        .await?;
                 }
                 Command::SetAcl => {
-                    self.handle_set_acl(request).await?;
+                    measure_command_with("imap", command, details, self.handle_set_acl(request))
+                        .await?;
                 }
                 Command::DeleteAcl => {
-                    self.handle_set_acl(request).await?;
+                    measure_command_with("imap", command, details, self.handle_set_acl(request))
+                        .await?;
                 }
                 Command::GetAcl => {
-                    self.handle_get_acl(request).await?;
+                    measure_command_with("imap", command, details, self.handle_get_acl(request))
+                        .await?;
                 }
                 Command::ListRights => {
-                    self.handle_list_rights(request).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_list_rights(request),
+                    )
+                    .await?;
                 }
                 Command::MyRights => {
-                    self.handle_my_rights(request).await?;
+                    measure_command_with("imap", command, details, self.handle_my_rights(request))
+                        .await?;
                 }
                 Command::Unauthenticate => {
-                    self.handle_unauthenticate(request).await?;
+                    measure_command_with(
+                        "imap",
+                        command,
+                        details,
+                        self.handle_unauthenticate(request),
+                    )
+                    .await?;
                 }
                 Command::Id => {
-                    self.handle_id(request).await?;
+                    measure_command_with("imap", command, details, self.handle_id(request)).await?;
                 }
             }
         }
@@ -199,8 +328,49 @@ impl<T: SessionStream> Session<T> {
                 .await?;
         }
 
-        Ok(false)
+        Ok(None)
+    }
+}
+
+/// Keeps IMAP keywords, flags and sequence sets while hiding any other
+/// argument, such as search terms or mailbox names.
+fn sanitize_tokens(tokens: &[Token]) -> String {
+    let mut result = String::new();
+    for token in tokens {
+        if result.len() >= 1024 {
+            result.push_str(" …");
+            break;
+        } else if !result.is_empty() {
+            result.push(' ');
+        }
+        result.push_str(match token {
+            Token::Argument(value) if is_keyword(value) => {
+                std::str::from_utf8(value).unwrap_or_default()
+            }
+            Token::Argument(_) => "\"…\"",
+            Token::ParenthesisOpen => "(",
+            Token::ParenthesisClose => ")",
+            Token::BracketOpen => "[",
+            Token::BracketClose => "]",
+            Token::Lt => "<",
+            Token::Gt => ">",
+            Token::Dot => ".",
+            Token::Nil => "NIL",
+        });
     }
+    result
+}
+
+fn is_keyword(value: &[u8]) -> bool {
+    value.len() <= 64
+        && match value.first() {
+            Some(b'\\' | b'$') => value[1..].iter().all(|ch| ch.is_ascii_alphanumeric()),
+            _ => value.iter().all(|&ch| {
+                ch.is_ascii_uppercase()
+                    || ch.is_ascii_digit()
+                    || matches!(ch, b'.' | b':' | b'*' | b',' | b'-')
+            }),
+        }
 }
 
 pub fn group_requests(
@@ -232,7 +402,7 @@ impl<T: SessionStream> Session<T> {
                 .lookup_store
                 .is_rate_allowed(
                     format!("ireq:{}", data.account_id).as_bytes(),
-                    &self.imap.rate_requests,
+                    &self.imap.rate_requests.load_full(),
                     true,
                 )
                 .await
@@ -254,7 +424,12 @@ impl<T: SessionStream> Session<T> {
         match &request.command {
             Command::Capability | Command::Noop | Command::Logout | Command::Id => Ok(request),
             Command::StartTls => {
-                if !self.is_tls {
+                if self.is_compressed {
+                    Err(
+                        StatusResponse::bad("STARTTLS is not allowed after COMPRESS.")
+                            .with_tag(request.tag),
+                    )
+                } else if !self.is_tls {
                     if self.instance.acceptor.is_tls() {
                         Ok(request)
                     } else {
@@ -264,6 +439,19 @@ impl<T: SessionStream> Session<T> {
                     Err(StatusResponse::no("Already in TLS mode.").with_tag(request.tag))
                 }
             }
+            Command::Compress => {
+                if !self.imap.enable_compress {
+                    Err(StatusResponse::no("Compression is not available.").with_tag(request.tag))
+                } else if self.is_compressed {
+                    Err(StatusResponse::no("DEFLATE is already active.")
+                        .with_tag(request.tag)
+                        .with_code(ResponseCode::CompressionActive))
+                } else if state.is_authenticated() {
+                    Ok(request)
+                } else {
+                    Err(StatusResponse::no("Not authenticated.").with_tag(request.tag))
+                }
+            }
             Command::Authenticate => {
                 if let State::NotAuthenticated { .. } = state {
                     Ok(request)
@@ -408,8 +596,12 @@ impl IMAP {
             .map(|limiter| limiter.clone())
             .unwrap_or_else(|| {
                 let limiter = Arc::new(ConcurrencyLimiters {
-                    concurrent_requests: ConcurrencyLimiter::new(self.rate_concurrent),
-                    concurrent_uploads: ConcurrencyLimiter::new(self.rate_concurrent),
+                    concurrent_requests: ConcurrencyLimiter::new(
+                        self.rate_concurrent.load(Ordering::Relaxed),
+                    ),
+                    concurrent_uploads: ConcurrencyLimiter::new(
+                        self.rate_concurrent.load(Ordering::Relaxed),
+                    ),
                 });
                 self.rate_limiter.insert(account_id, limiter.clone());
                 limiter
@@ -15,7 +15,10 @@ use parking_lot::Mutex;
 use store::query::log::{Change, Query};
 use utils::listener::{limiter::InFlight, SessionStream};
 
-use super::{Account, Mailbox, MailboxId, MailboxSync, Session, SessionData};
+use super::{
+    Account, Mailbox, MailboxId, MailboxSync, Session, SessionData, SEARCH_FOLDER_ID,
+    UNIFIED_FOLDER_ID,
+};
 
 impl<T: SessionStream> SessionData<T> {
     pub async fn new(
@@ -135,11 +138,6 @@ impl<T: SessionStream> SessionData<T> {
         let mut parent_id = 0;
         let mut path = Vec::new();
         let mut iter_stack = Vec::new();
-        let message_ids = self
-            .jmap
-            .get_document_ids(account_id, Collection::Email)
-            .await
-            .map_err(|_| {})?;
 
         if let Some(mailbox_prefix) = &mailbox_prefix {
             path.push(mailbox_prefix.to_string());
@@ -183,6 +181,11 @@ impl<T: SessionStream> SessionData<T> {
                         .iter()
                         .any(|(_, child_parent_id, _)| *child_parent_id == *mailbox_id + 1);
 
+                    let counters = self
+                        .jmap
+                        .mailbox_counters(account_id, *mailbox_id)
+                        .await
+                        .map_err(|_| {})?;
                     account.mailbox_state.insert(
                         *mailbox_id,
                         Mailbox {
@@ -202,27 +205,9 @@ impl<T: SessionStream> SessionData<T> {
                                     _ => None,
                                 },
                             ),
-                            total_messages: self
-                                .jmap
-                                .get_tag(
-                                    account_id,
-                                    Collection::Email,
-                                    Property::MailboxIds,
-                                    *mailbox_id,
-                                )
-                                .await
-                                .map_err(|_| {})?
-                                .map(|v| v.len() as u32)
-                                .unwrap_or(0)
-                                .into(),
-                            total_unseen: self
-                                .jmap
-                                .mailbox_unread_tags(account_id, *mailbox_id, &message_ids)
-                                .await
-                                .map_err(|_| {})?
-                                .map(|v| v.len() as u32)
-                                .unwrap_or(0)
-                                .into(),
+                            total_messages: (counters.total as u32).into(),
+                            total_unseen: (counters.unseen as u32).into(),
+                            size: (counters.size as u32).into(),
                             ..Default::default()
                         },
                     );
@@ -363,8 +348,9 @@ impl<T: SessionStream> SessionData<T> {
                     last_state.map(Query::Since).unwrap_or(Query::All),
                 )
                 .await?;
-            if !changelog.changes.is_empty() {
-                let mut has_changes = false;
+            if !changelog.changes.is_empty() || changelog.is_truncated {
+                // Mailboxes are refreshed in full when the change log was compacted
+                let mut has_changes = changelog.is_truncated;
                 let mut has_child_changes = false;
 
                 for change in changelog.changes {
@@ -527,6 +513,84 @@ impl<T: SessionStream> SessionData<T> {
         None
     }
 
+    /// Returns the saved searches of the primary account as search folder
+    /// names under the saved searches folder, along with their ids.
+    pub async fn search_folders(&self) -> crate::op::Result<Vec<(String, u32)>> {
+        let mut folders = Vec::new();
+        for search_id in self
+            .jmap
+            .get_document_ids(self.account_id, Collection::SavedSearch)
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(name) = self
+                .jmap
+                .get_property::<Object<Value>>(
+                    self.account_id,
+                    Collection::SavedSearch,
+                    search_id,
+                    &Property::Value,
+                )
+                .await?
+                .and_then(|mut search| search.remove(&Property::Name).try_unwrap_string())
+            {
+                folders.push((
+                    format!("{}/{}", self.imap.name_saved_searches, name),
+                    search_id,
+                ));
+            }
+        }
+        folders.sort_unstable();
+
+        Ok(folders)
+    }
+
+    pub async fn get_search_folder_by_name(
+        &self,
+        mailbox_name: &str,
+    ) -> crate::op::Result<Option<MailboxId>> {
+        if !mailbox_name
+            .strip_prefix(&self.imap.name_saved_searches)
+            .map_or(false, |name| name.starts_with('/'))
+        {
+            return Ok(None);
+        }
+
+        Ok(self
+            .search_folders()
+            .await?
+            .into_iter()
+            .find(|(name, _)| name == mailbox_name)
+            .map(|(_, search_id)| MailboxId {
+                account_id: self.account_id,
+                mailbox_id: SEARCH_FOLDER_ID | search_id,
+            }))
+    }
+
+    /// Returns the name of the unified inbox, which is only available when
+    /// unified views are enabled and other accounts are shared with the user.
+    pub fn unified_folder_name(&self) -> Option<String> {
+        if self.jmap.config.unified_max_accounts > 0 && self.mailboxes.lock().len() > 1 {
+            Some(format!("{}/INBOX", self.imap.name_unified))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_unified_folder_by_name(&self, mailbox_name: &str) -> Option<MailboxId> {
+        if self
+            .unified_folder_name()
+            .map_or(false, |name| name == mailbox_name)
+        {
+            Some(MailboxId {
+                account_id: self.account_id,
+                mailbox_id: UNIFIED_FOLDER_ID,
+            })
+        } else {
+            None
+        }
+    }
+
     pub async fn check_mailbox_acl(
         &self,
         account_id: u32,
@@ -41,12 +41,23 @@ use utils::listener::SessionStream;
 
 use crate::core::ImapId;
 
-use super::{Mailbox, MailboxId, MailboxState, NextMailboxState, SelectedMailbox, SessionData};
+use super::{
+    Mailbox, MailboxId, MailboxState, NextMailboxState, SelectedMailbox, SessionData,
+    UnifiedMailboxState,
+};
 
 pub(crate) const MAX_RETRIES: usize = 10;
 
 impl<T: SessionStream> SessionData<T> {
     pub async fn fetch_messages(&self, mailbox: &MailboxId) -> crate::op::Result<MailboxState> {
+        if let Some(search_id) = mailbox.search_folder_id() {
+            return self
+                .fetch_search_folder_messages(mailbox.account_id, search_id)
+                .await;
+        } else if mailbox.is_unified_folder() {
+            return self.fetch_unified_folder_messages().await;
+        }
+
         // Obtain message ids
         let message_ids = self
             .jmap
@@ -339,6 +350,90 @@ impl<T: SessionStream> SessionData<T> {
             uid_max: uid_next.saturating_sub(1),
             modseq,
             next_state: None,
+            unified: None,
+        })
+    }
+
+    async fn fetch_search_folder_messages(
+        &self,
+        account_id: u32,
+        search_id: u32,
+    ) -> crate::op::Result<MailboxState> {
+        // Search folders use the UIDs assigned when the saved search results
+        // are materialized, so no UIDs are stored in the messages themselves
+        let mut results = self
+            .jmap
+            .saved_search_refresh(account_id, search_id)
+            .await?
+            .ok_or_else(|| StatusResponse::no("Mailbox unavailable."))?;
+        results.items.sort_unstable_by_key(|item| item.uid);
+
+        let mut id_to_imap = AHashMap::with_capacity(results.items.len());
+        let mut uid_to_id = AHashMap::with_capacity(results.items.len());
+        for (seqnum, item) in results.items.into_iter().enumerate() {
+            id_to_imap.insert(
+                item.document_id,
+                ImapId {
+                    uid: item.uid,
+                    seqnum: seqnum as u32 + 1,
+                },
+            );
+            uid_to_id.insert(item.uid, item.document_id);
+        }
+
+        Ok(MailboxState {
+            uid_next: results.uid_next,
+            uid_validity: results.uid_validity,
+            total_messages: id_to_imap.len(),
+            id_to_imap,
+            uid_to_id,
+            uid_max: results.uid_next.saturating_sub(1),
+            modseq: results.change_id,
+            next_state: None,
+            unified: None,
+        })
+    }
+
+    async fn fetch_unified_folder_messages(&self) -> crate::op::Result<MailboxState> {
+        // Unified folders use the UIDs assigned in the unified inbox, which
+        // also serve as message ids as messages belong to different accounts
+        let access_token = self.get_access_token().await?;
+        let mut results = self
+            .jmap
+            .unified_inbox_refresh(&access_token)
+            .await?
+            .ok_or_else(|| StatusResponse::no("Mailbox unavailable."))?;
+        results.items.sort_unstable_by_key(|item| item.uid);
+
+        let mut id_to_imap = AHashMap::with_capacity(results.items.len());
+        let mut uid_to_id = AHashMap::with_capacity(results.items.len());
+        let mut ids = AHashMap::with_capacity(results.items.len());
+        for (seqnum, item) in results.items.into_iter().enumerate() {
+            id_to_imap.insert(
+                item.uid,
+                ImapId {
+                    uid: item.uid,
+                    seqnum: seqnum as u32 + 1,
+                },
+            );
+            uid_to_id.insert(item.uid, item.uid);
+            ids.insert(item.uid, (item.account_id, item.document_id));
+        }
+
+        Ok(MailboxState {
+            uid_next: results.uid_next,
+            uid_validity: results.uid_validity,
+            total_messages: id_to_imap.len(),
+            id_to_imap,
+            uid_to_id,
+            uid_max: results.uid_next.saturating_sub(1),
+            modseq: results.change_id.into(),
+            next_state: None,
+            unified: UnifiedMailboxState {
+                ids,
+                accounts: results.accounts,
+            }
+            .into(),
         })
     }
 
@@ -346,11 +441,21 @@ impl<T: SessionStream> SessionData<T> {
         &self,
         mailbox: &SelectedMailbox,
     ) -> crate::op::Result<Option<u64>> {
-        // Obtain current modseq
-        let modseq = self.get_modseq(mailbox.id.account_id).await?;
+        // Obtain current modseq, unified folders are refreshed to find out
+        // whether any of their accounts changed
+        let (modseq, new_state) = if mailbox.id.is_unified_folder() {
+            let new_state = self.fetch_messages(&mailbox.id).await?;
+            (new_state.modseq, Some(new_state))
+        } else {
+            (self.get_modseq(mailbox.id.account_id).await?, None)
+        };
         if mailbox.state.lock().modseq != modseq {
             // Synchronize messages
-            let new_state = self.fetch_messages(&mailbox.id).await?;
+            let new_state = if let Some(new_state) = new_state {
+                new_state
+            } else {
+                self.fetch_messages(&mailbox.id).await?
+            };
             let mut current_state = mailbox.state.lock();
 
             // Add missing uids
@@ -479,6 +584,17 @@ impl<T: SessionStream> SessionData<T> {
     }
 }
 
+impl MailboxState {
+    /// Returns the account and document ids of a message in this mailbox.
+    pub fn resolve_id(&self, account_id: u32, id: u32) -> Option<(u32, u32)> {
+        if let Some(unified) = &self.unified {
+            unified.ids.get(&id).copied()
+        } else {
+            Some((account_id, id))
+        }
+    }
+}
+
 impl SelectedMailbox {
     pub async fn sequence_to_ids(
         &self,
@@ -24,12 +24,17 @@
 use std::{
     collections::BTreeMap,
     net::IpAddr,
-    sync::{atomic::AtomicU32, Arc},
+    sync::{
+        atomic::{AtomicU32, AtomicU64},
+        Arc,
+    },
     time::Duration,
 };
 
 use ahash::AHashMap;
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
+use directory::core::scram::ScramState;
 use imap_proto::{
     protocol::{list::Attribute, ProtocolVersion},
     receiver::Receiver,
@@ -70,8 +75,11 @@ pub struct IMAP {
     pub max_request_size: usize,
     pub max_auth_failures: u32,
     pub name_shared: String,
+    pub name_saved_searches: String,
+    pub name_unified: String,
     pub allow_plain_auth: bool,
     pub enable_uidplus: bool,
+    pub enable_compress: bool,
 
     pub timeout_auth: Duration,
     pub timeout_unauth: Duration,
@@ -81,8 +89,8 @@ pub struct IMAP {
     pub greeting_tls: Vec<u8>,
 
     pub rate_limiter: DashMap<u32, Arc<ConcurrencyLimiters>>,
-    pub rate_requests: Rate,
-    pub rate_concurrent: u64,
+    pub rate_requests: ArcSwap<Rate>,
+    pub rate_concurrent: AtomicU64,
 }
 
 pub struct Session<T: SessionStream> {
@@ -93,15 +101,25 @@ pub struct Session<T: SessionStream> {
     pub version: ProtocolVersion,
     pub state: State<T>,
     pub is_tls: bool,
+    pub is_compressed: bool,
     pub is_condstore: bool,
     pub is_qresync: bool,
     pub stream_rx: ReadHalf<T>,
     pub stream_tx: Arc<tokio::sync::Mutex<WriteHalf<T>>>,
     pub in_flight: InFlight,
     pub remote_addr: IpAddr,
+    pub channel_binding: Option<Vec<u8>>,
+    pub scram: Option<ScramState>,
     pub span: tracing::Span,
 }
 
+/// Stream layers a client can negotiate once the session has started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamUpgrade {
+    Tls,
+    Deflate,
+}
+
 pub struct SessionData<T: SessionStream> {
     pub account_id: u32,
     pub jmap: Arc<JMAP>,
@@ -151,6 +169,28 @@ pub struct MailboxId {
     pub mailbox_id: u32,
 }
 
+/// Mailbox ids with this bit set refer to saved searches, which are
+/// exposed as read-only virtual mailboxes (search folders).
+pub const SEARCH_FOLDER_ID: u32 = 1 << 31;
+
+/// The unified inbox of the primary account, exposed as a read-only virtual
+/// mailbox that aggregates the inboxes of the accounts shared with it.
+pub const UNIFIED_FOLDER_ID: u32 = SEARCH_FOLDER_ID - 1;
+
+impl MailboxId {
+    pub fn search_folder_id(&self) -> Option<u32> {
+        if self.mailbox_id & SEARCH_FOLDER_ID != 0 {
+            Some(self.mailbox_id & !SEARCH_FOLDER_ID)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_unified_folder(&self) -> bool {
+        self.mailbox_id == UNIFIED_FOLDER_ID
+    }
+}
+
 #[derive(Debug)]
 pub struct MailboxState {
     pub uid_next: u32,
@@ -161,6 +201,15 @@ pub struct MailboxState {
     pub total_messages: usize,
     pub modseq: Option<u64>,
     pub next_state: Option<Box<NextMailboxState>>,
+    pub unified: Option<UnifiedMailboxState>,
+}
+
+/// Messages of a unified folder are identified by their UIDs, which map to
+/// the account and document ids of each message.
+#[derive(Debug, Default)]
+pub struct UnifiedMailboxState {
+    pub ids: AHashMap<u32, (u32, u32)>,
+    pub accounts: Vec<(u32, Option<u64>)>,
 }
 
 #[derive(Debug)]
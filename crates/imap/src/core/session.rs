@@ -21,14 +21,17 @@
  * for more details.
 */
 
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, future::Future, sync::Arc};
 
 use imap_proto::{protocol::ProtocolVersion, receiver::Receiver};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_rustls::server::TlsStream;
-use utils::listener::{stream::NullIo, SessionManager, SessionStream};
+use utils::listener::{
+    compress::DeflateStream, stream::NullIo, transcript::TranscriptStream, SessionManager,
+    SessionStream,
+};
 
-use super::{ImapSessionManager, Session, State};
+use super::{ImapSessionManager, Session, State, StreamUpgrade};
 
 impl SessionManager for ImapSessionManager {
     #[allow(clippy::manual_async_fn)]
@@ -38,10 +41,23 @@ impl SessionManager for ImapSessionManager {
     ) -> impl std::future::Future<Output = ()> + Send {
         async move {
             if let Ok(mut session) = Session::new(session, self).await {
-                if session.handle_conn().await && session.instance.acceptor.is_tls() {
-                    if let Ok(mut session) = session.into_tls().await {
-                        session.handle_conn().await;
+                match session.handle_conn().await {
+                    Some(StreamUpgrade::Tls) if session.instance.acceptor.is_tls() => {
+                        if let Ok(mut session) = session.into_tls().await {
+                            // Compression is layered on top of TLS
+                            if session.handle_conn().await == Some(StreamUpgrade::Deflate) {
+                                if let Ok(mut session) = session.into_deflate().await {
+                                    session.handle_conn().await;
+                                }
+                            }
+                        }
+                    }
+                    Some(StreamUpgrade::Deflate) => {
+                        if let Ok(mut session) = session.into_deflate().await {
+                            session.handle_conn().await;
+                        }
                     }
+                    _ => (),
                 }
             }
         }
@@ -58,7 +74,7 @@ impl SessionManager for ImapSessionManager {
 }
 
 impl<T: SessionStream> Session<T> {
-    pub async fn handle_conn(&mut self) -> bool {
+    pub async fn handle_conn(&mut self) -> Option<StreamUpgrade> {
         let mut buf = vec![0; 8192];
         let mut shutdown_rx = self.instance.shutdown_rx.clone();
 
@@ -75,9 +91,9 @@ impl<T: SessionStream> Session<T> {
                         Ok(Ok(bytes_read)) => {
                             if bytes_read > 0 {
                                 match self.ingest(&buf[..bytes_read]).await {
-                                    Ok(false) => (),
-                                    Ok(true) => {
-                                        return true;
+                                    Ok(None) => (),
+                                    Ok(Some(upgrade)) => {
+                                        return Some(upgrade);
                                     }
                                     Err(_) => {
                                         tracing::debug!(parent: &self.span, event = "disconnect", "Disconnecting client.");
@@ -108,7 +124,7 @@ impl<T: SessionStream> Session<T> {
             };
         }
 
-        false
+        None
     }
 
     pub async fn new(
@@ -128,6 +144,7 @@ impl<T: SessionStream> Session<T> {
         let _ = session.stream.flush().await;
 
         // Split stream into read and write halves
+        let channel_binding = session.stream.tls_exporter();
         let (stream_rx, stream_tx) = tokio::io::split(session.stream);
 
         Ok(Session {
@@ -135,6 +152,7 @@ impl<T: SessionStream> Session<T> {
             version: ProtocolVersion::Rev1,
             state: State::NotAuthenticated { auth_failures: 0 },
             is_tls,
+            is_compressed: false,
             is_condstore: false,
             is_qresync: false,
             imap: manager.imap,
@@ -143,12 +161,36 @@ impl<T: SessionStream> Session<T> {
             span: session.span,
             in_flight: session.in_flight,
             remote_addr: session.remote_ip,
+            channel_binding,
+            scram: None,
             stream_rx,
             stream_tx: Arc::new(tokio::sync::Mutex::new(stream_tx)),
         })
     }
 
-    pub async fn into_tls(self) -> Result<Session<TlsStream<T>>, ()> {
+    pub async fn into_tls(self) -> Result<Session<TranscriptStream<TlsStream<T>>>, ()> {
+        let instance = self.instance.clone();
+        let span = self.span.clone();
+        self.upgrade_stream(|stream| async move { instance.tls_accept(stream, &span).await })
+            .await
+    }
+
+    pub async fn into_deflate(self) -> Result<Session<TranscriptStream<DeflateStream<T>>>, ()> {
+        tracing::debug!(parent: &self.span, event = "compress", "DEFLATE compression enabled.");
+        self.upgrade_stream(|stream| async move { Ok(DeflateStream::wrap(stream)) })
+            .await
+            .map(|mut session| {
+                session.is_compressed = true;
+                session
+            })
+    }
+
+    async fn upgrade_stream<U, F, R>(self, upgrade: F) -> Result<Session<U>, ()>
+    where
+        U: SessionStream,
+        F: FnOnce(T) -> R,
+        R: Future<Output = Result<U, ()>>,
+    {
         // Drop references to write half from state
         let state = if let Some(state) =
             self.state
@@ -171,9 +213,11 @@ impl<T: SessionStream> Session<T> {
             return Err(());
         };
 
-        // Upgrade to TLS
-        let (stream_rx, stream_tx) =
-            tokio::io::split(self.instance.tls_accept(stream, &self.span).await?);
+        // Wrap stream
+        let stream = upgrade(stream).await?;
+        let is_tls = stream.is_tls();
+        let channel_binding = stream.tls_exporter();
+        let (stream_rx, stream_tx) = tokio::io::split(stream);
         let stream_tx = Arc::new(tokio::sync::Mutex::new(stream_tx));
 
         Ok(Session {
@@ -183,12 +227,15 @@ impl<T: SessionStream> Session<T> {
             receiver: self.receiver,
             version: self.version,
             state: state.try_replace_stream_tx(stream_tx.clone()).unwrap(),
-            is_tls: true,
+            is_tls,
+            is_compressed: self.is_compressed,
             is_condstore: self.is_condstore,
             is_qresync: self.is_qresync,
             span: self.span,
             in_flight: self.in_flight,
             remote_addr: self.remote_addr,
+            channel_binding,
+            scram: None,
             stream_rx,
             stream_tx,
         })
@@ -21,13 +21,17 @@
  * for more details.
 */
 
-use std::{collections::hash_map::RandomState, sync::Arc};
+use std::{
+    collections::hash_map::RandomState,
+    sync::{atomic::Ordering, Arc},
+};
 
 use crate::core::IMAP;
 
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
 use imap_proto::{protocol::capability::Capability, ResponseCode, StatusResponse};
-use utils::config::Config;
+use utils::config::{reload::ReloadableConfig, Config, Rate};
 
 pub mod core;
 pub mod op;
@@ -40,6 +44,8 @@ static SERVER_GREETING: &str = concat!(
 
 impl IMAP {
     pub async fn init(config: &Config) -> utils::config::Result<Arc<Self>> {
+        let enable_compress = config.property_or_static("imap.protocol.compress", "false")?;
+
         Ok(Arc::new(IMAP {
             max_request_size: config.property_or_static("imap.request.max-size", "52428800")?,
             max_auth_failures: config.property_or_static("imap.auth.max-failures", "3")?,
@@ -47,17 +53,25 @@ impl IMAP {
                 .value("imap.folders.name.shared")
                 .unwrap_or("Shared Folders")
                 .to_string(),
+            name_saved_searches: config
+                .value("imap.folders.name.saved-searches")
+                .unwrap_or("Saved Searches")
+                .to_string(),
+            name_unified: config
+                .value("imap.folders.name.unified")
+                .unwrap_or("Unified Folders")
+                .to_string(),
             timeout_auth: config.property_or_static("imap.timeout.authenticated", "30m")?,
             timeout_unauth: config.property_or_static("imap.timeout.anonymous", "1m")?,
             timeout_idle: config.property_or_static("imap.timeout.idle", "30m")?,
             greeting_plain: StatusResponse::ok(SERVER_GREETING)
                 .with_code(ResponseCode::Capability {
-                    capabilities: Capability::all_capabilities(false, false),
+                    capabilities: Capability::all_capabilities(false, false, enable_compress),
                 })
                 .into_bytes(),
             greeting_tls: StatusResponse::ok(SERVER_GREETING)
                 .with_code(ResponseCode::Capability {
-                    capabilities: Capability::all_capabilities(false, true),
+                    capabilities: Capability::all_capabilities(false, true, enable_compress),
                 })
                 .into_bytes(),
             rate_limiter: DashMap::with_capacity_and_hasher_and_shard_amount(
@@ -70,14 +84,34 @@ impl IMAP {
                     .unwrap_or(32)
                     .next_power_of_two() as usize,
             ),
-            rate_requests: config.property_or_static("imap.rate-limit.requests", "2000/1m")?,
-            rate_concurrent: config.property("imap.rate-limit.concurrent")?.unwrap_or(4),
+            rate_requests: ArcSwap::from_pointee(
+                config.property_or_static("imap.rate-limit.requests", "2000/1m")?,
+            ),
+            rate_concurrent: config
+                .property("imap.rate-limit.concurrent")?
+                .unwrap_or(4)
+                .into(),
             allow_plain_auth: config.property_or_static("imap.auth.allow-plain-text", "false")?,
             enable_uidplus: config.property_or_static("imap.protocol.uidplus", "false")?,
+            enable_compress,
         }))
     }
 }
 
+impl ReloadableConfig for IMAP {
+    fn reload(&self, config: &Config) -> utils::config::Result<()> {
+        let rate_requests =
+            config.property_or_static::<Rate>("imap.rate-limit.requests", "2000/1m")?;
+        let rate_concurrent = config.property("imap.rate-limit.concurrent")?.unwrap_or(4);
+
+        self.rate_requests.store(Arc::new(rate_requests));
+        self.rate_concurrent
+            .store(rate_concurrent, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
 pub struct ImapError;
 
 pub type Result<T> = std::result::Result<T, ()>;
@@ -60,7 +60,7 @@ impl<T: SessionStream> Session<T> {
                 let data = self.state.session_data();
                 let is_rev2 = self.version.is_rev2();
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     match data.get_acl_mailbox(&arguments, true).await {
                         Ok((_, values, _)) => {
                             let mut permissions = Vec::new();
@@ -142,7 +142,7 @@ impl<T: SessionStream> Session<T> {
                                 .await;
                         }
                     }
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -155,7 +155,7 @@ impl<T: SessionStream> Session<T> {
                 let data = self.state.session_data();
                 let is_rev2 = self.version.is_rev2();
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     match data.get_acl_mailbox(&arguments, false).await {
                         Ok((mailbox, values, access_token)) => {
                             data.write_bytes(
@@ -217,7 +217,7 @@ impl<T: SessionStream> Session<T> {
                                 .await;
                         }
                     }
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -230,7 +230,7 @@ impl<T: SessionStream> Session<T> {
             Ok(arguments) => {
                 let data = self.state.session_data();
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     // Validate mailbox
                     let (mailbox, values, _) = match data.get_acl_mailbox(&arguments, true).await {
                         Ok(result) => result,
@@ -407,7 +407,7 @@ impl<T: SessionStream> Session<T> {
                             .into_bytes(),
                     )
                     .await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -67,7 +67,7 @@ impl<T: SessionStream> Session<T> {
                     };
                 let is_qresync = self.is_qresync;
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     data.write_bytes(
                         match data
                             .append_messages(arguments, selected_mailbox, mailbox, is_qresync)
@@ -79,7 +79,7 @@ impl<T: SessionStream> Session<T> {
                         .into_bytes(),
                     )
                     .await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -134,6 +134,8 @@ impl<T: SessionStream> SessionData<T> {
                     received_at: message.received_at.map(|d| d as u64),
                     skip_duplicates: false,
                     encrypt: self.jmap.config.encrypt && self.jmap.config.encrypt_append,
+                    classify: false,
+                    trusted_sender: false,
                 })
                 .await
             {
@@ -23,14 +23,20 @@
 
 use std::sync::Arc;
 
-use directory::AuthResult;
+use directory::{
+    core::scram::{ScramState, ScramStep},
+    AuthResult,
+};
 use imap_proto::{
     protocol::{authenticate::Mechanism, capability::Capability},
     receiver::{self, Request},
     Command, ResponseCode, StatusResponse,
 };
+use jmap::auth::AccessToken;
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
+use smtp_proto::{AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_SCRAM_SHA_256, AUTH_SCRAM_SHA_256_PLUS};
 use utils::listener::SessionStream;
 
 use crate::core::{Session, SessionData, State};
@@ -43,15 +49,15 @@ impl<T: SessionStream> Session<T> {
                     if !args.params.is_empty() {
                         match base64_decode(args.params.pop().unwrap().as_bytes()) {
                             Some(challenge) => {
-                                let result = if args.mechanism == Mechanism::Plain {
-                                    decode_challenge_plain(&challenge)
+                                let (result, mechanism) = if args.mechanism == Mechanism::Plain {
+                                    (decode_challenge_plain(&challenge), AUTH_PLAIN)
                                 } else {
-                                    decode_challenge_oauth(&challenge)
+                                    (decode_challenge_oauth(&challenge), AUTH_OAUTHBEARER)
                                 };
 
                                 match result {
                                     Ok(credentials) => {
-                                        self.authenticate(credentials, args.tag).await
+                                        self.authenticate(credentials, mechanism, args.tag).await
                                     }
                                     Err(err) => {
                                         self.write_bytes(
@@ -81,6 +87,86 @@ impl<T: SessionStream> Session<T> {
                         self.write_bytes(b"+ \"\"\r\n".to_vec()).await
                     }
                 }
+                Mechanism::ScramSha256 | Mechanism::ScramSha256Plus => {
+                    let (is_plus, mechanism) = if args.mechanism == Mechanism::ScramSha256Plus {
+                        (true, AUTH_SCRAM_SHA_256_PLUS)
+                    } else {
+                        (false, AUTH_SCRAM_SHA_256)
+                    };
+                    let state = self.scram.take().unwrap_or(ScramState::ClientFirst);
+                    if matches!(state, ScramState::ClientFirst) {
+                        self.is_auth_allowed().await?;
+                    }
+
+                    let response = match args.params.pop() {
+                        Some(param) if !param.is_empty() => base64_decode(param.as_bytes())
+                            .and_then(|response| String::from_utf8(response).ok()),
+                        _ => Some(String::new()),
+                    };
+                    let step = match response {
+                        Some(response) => {
+                            self.jmap
+                                .directory
+                                .scram_step(
+                                    state,
+                                    &response,
+                                    is_plus,
+                                    self.channel_binding.as_deref(),
+                                )
+                                .await
+                        }
+                        None => Ok(ScramStep::Invalid),
+                    };
+
+                    match step {
+                        Ok(ScramStep::Challenge(state, challenge)) => {
+                            self.scram = Some(state);
+                            self.receiver.request = receiver::Request {
+                                tag: args.tag,
+                                command: Command::Authenticate,
+                                tokens: vec![receiver::Token::Argument(
+                                    args.mechanism.into_bytes(),
+                                )],
+                            };
+                            self.receiver.state = receiver::State::Argument { last_ch: b' ' };
+                            self.write_bytes(
+                                format!("+ {}\r\n", base64_encode(challenge.as_bytes()))
+                                    .into_bytes(),
+                            )
+                            .await
+                        }
+                        Ok(ScramStep::Authenticate {
+                            username,
+                            is_verified,
+                        }) => {
+                            let result = self
+                                .jmap
+                                .authenticate_scram(&username, is_verified, self.remote_addr)
+                                .await;
+                            self.finish_authentication(result, mechanism, args.tag)
+                                .await
+                        }
+                        Ok(ScramStep::Invalid) => {
+                            self.record_auth_usage(mechanism, false);
+                            self.write_bytes(
+                                StatusResponse::no("Invalid SCRAM exchange.")
+                                    .with_tag(args.tag)
+                                    .with_code(ResponseCode::Parse)
+                                    .into_bytes(),
+                            )
+                            .await
+                        }
+                        Err(_) => {
+                            self.write_bytes(
+                                StatusResponse::no("Temporary authentication failure.")
+                                    .with_tag(args.tag)
+                                    .with_code(ResponseCode::Unavailable)
+                                    .into_bytes(),
+                            )
+                            .await
+                        }
+                    }
+                }
                 _ => {
                     self.write_bytes(
                         StatusResponse::no("Authentication mechanism not supported.")
@@ -91,15 +177,52 @@ impl<T: SessionStream> Session<T> {
                     .await
                 }
             },
-            Err(response) => self.write_bytes(response.into_bytes()).await,
+            Err(response) => {
+                self.scram = None;
+                self.write_bytes(response.into_bytes()).await
+            }
         }
     }
 
     pub async fn authenticate(
         &mut self,
         credentials: Credentials<String>,
+        mechanism: u64,
         tag: String,
     ) -> crate::Result<()> {
+        self.is_auth_allowed().await?;
+
+        // Authenticate
+        let result = match credentials {
+            Credentials::Plain { username, secret } | Credentials::XOauth2 { username, secret } => {
+                self.jmap
+                    .authenticate_plain(&username, &secret, self.remote_addr)
+                    .await
+            }
+            Credentials::OAuthBearer { token } => {
+                match self.jmap.validate_bearer_token(&token).await {
+                    Ok(account_id) => self
+                        .jmap
+                        .get_access_token(account_id)
+                        .await
+                        .map_or(AuthResult::Failure, AuthResult::Success),
+                    Err(err) => {
+                        tracing::debug!(
+                            parent: &self.span,
+                            context = "authenticate",
+                            err = err,
+                            "Failed to validate access token."
+                        );
+                        AuthResult::Failure
+                    }
+                }
+            }
+        };
+
+        self.finish_authentication(result, mechanism, tag).await
+    }
+
+    async fn is_auth_allowed(&self) -> crate::Result<()> {
         // Throttle authentication requests
         if self
             .jmap
@@ -119,38 +242,35 @@ impl<T: SessionStream> Session<T> {
             return Err(());
         }
 
-        // Authenticate
-        let access_token = match credentials {
-            Credentials::Plain { username, secret } | Credentials::XOauth2 { username, secret } => {
-                match self
-                    .jmap
-                    .authenticate_plain(&username, &secret, self.remote_addr)
-                    .await
-                {
-                    AuthResult::Success(token) => Some(token),
-                    AuthResult::Failure => None,
-                    AuthResult::Banned => return Err(()),
-                }
+        Ok(())
+    }
+
+    async fn finish_authentication(
+        &mut self,
+        result: AuthResult<AccessToken>,
+        mechanism: u64,
+        tag: String,
+    ) -> crate::Result<()> {
+        let access_token = match result {
+            AuthResult::Success(token) => Some(token),
+            AuthResult::MustChangePassword(_) => {
+                self.record_auth_usage(mechanism, false);
+                self.write_bytes(
+                    StatusResponse::no("Password must be changed before use.")
+                        .with_tag(tag)
+                        .with_code(ResponseCode::Expired)
+                        .into_bytes(),
+                )
+                .await?;
+                return Ok(());
             }
-            Credentials::OAuthBearer { token } => {
-                match self
-                    .jmap
-                    .validate_access_token("access_token", &token)
-                    .await
-                {
-                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
-                    Err(err) => {
-                        tracing::debug!(
-                            parent: &self.span,
-                            context = "authenticate",
-                            err = err,
-                            "Failed to validate access token."
-                        );
-                        None
-                    }
-                }
+            AuthResult::Failure => None,
+            AuthResult::Banned => {
+                self.record_auth_usage(mechanism, false);
+                return Err(());
             }
         };
+        self.record_auth_usage(mechanism, access_token.is_some());
 
         if let Some(access_token) = access_token {
             // Enforce concurrency limits
@@ -171,7 +291,11 @@ impl<T: SessionStream> Session<T> {
                 self.write_bytes(
                     StatusResponse::ok("Authentication successful")
                         .with_code(ResponseCode::Capability {
-                            capabilities: Capability::all_capabilities(true, self.is_tls),
+                            capabilities: Capability::all_capabilities(
+                                true,
+                                self.is_tls,
+                                self.is_compress_available(),
+                            ),
                         })
                         .with_tag(tag)
                         .into_bytes(),
@@ -219,6 +343,20 @@ impl<T: SessionStream> Session<T> {
         }
     }
 
+    /// Counts the outcome of an authentication attempt in the mechanism
+    /// usage statistics shared with SMTP.
+    fn record_auth_usage(&self, mechanism: u64, is_success: bool) {
+        self.jmap
+            .smtp
+            .record_auth_usage(&self.instance.id, mechanism, |usage| {
+                if is_success {
+                    &usage.success
+                } else {
+                    &usage.failure
+                }
+            });
+    }
+
     pub async fn handle_unauthenticate(&mut self, request: Request<Command>) -> crate::OpResult {
         self.state = State::NotAuthenticated { auth_failures: 0 };
 
@@ -23,6 +23,7 @@
 
 use imap_proto::{
     protocol::{
+        authenticate::Mechanism,
         capability::{Capability, Response},
         ImapResponse,
     },
@@ -36,18 +37,21 @@ use crate::core::Session;
 
 impl<T: SessionStream> Session<T> {
     pub async fn handle_capability(&mut self, request: Request<Command>) -> crate::OpResult {
+        let mut capabilities = Capability::all_capabilities(
+            self.state.is_authenticated(),
+            self.is_tls,
+            self.is_compress_available(),
+        );
+
+        // Channel binding is only available over TLS 1.3
+        if !self.state.is_authenticated() && self.channel_binding.is_some() {
+            capabilities.push(Capability::Auth(Mechanism::ScramSha256Plus));
+        }
+
         self.write_bytes(
             StatusResponse::completed(Command::Capability)
                 .with_tag(request.tag)
-                .serialize(
-                    Response {
-                        capabilities: Capability::all_capabilities(
-                            self.state.is_authenticated(),
-                            self.is_tls,
-                        ),
-                    }
-                    .serialize(),
-                ),
+                .serialize(Response { capabilities }.serialize()),
         )
         .await
     }
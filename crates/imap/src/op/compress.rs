@@ -0,0 +1,50 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use imap_proto::{receiver::Request, Command, StatusResponse};
+
+use utils::listener::SessionStream;
+
+use crate::core::Session;
+
+impl<T: SessionStream> Session<T> {
+    pub fn is_compress_available(&self) -> bool {
+        self.imap.enable_compress && !self.is_compressed
+    }
+
+    /// Returns `true` if the client negotiated compression, in which case the
+    /// stream has to be wrapped before reading the next command.
+    pub async fn handle_compress(&mut self, request: Request<Command>) -> crate::Result<bool> {
+        match request.parse_compress() {
+            Ok(arguments) => self
+                .write_bytes(
+                    StatusResponse::ok("DEFLATE active")
+                        .with_tag(arguments.tag)
+                        .into_bytes(),
+                )
+                .await
+                .map(|_| true),
+            Err(response) => self.write_bytes(response.into_bytes()).await.map(|_| false),
+        }
+    }
+}
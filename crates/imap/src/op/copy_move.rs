@@ -28,12 +28,15 @@ use imap_proto::{
     StatusResponse,
 };
 
-use jmap::{email::set::TagManager, mailbox::UidMailbox};
+use jmap::{
+    email::set::TagManager,
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+};
 use jmap_proto::{
     error::{method::MethodError, set::SetErrorType},
     types::{
-        acl::Acl, collection::Collection, id::Id, property::Property, state::StateChange,
-        type_state::DataType,
+        acl::Acl, collection::Collection, id::Id, keyword::Keyword, property::Property,
+        state::StateChange, type_state::DataType,
     },
 };
 use store::write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, F_VALUE};
@@ -53,7 +56,7 @@ impl<T: SessionStream> Session<T> {
                 let (data, src_mailbox) = self.state.mailbox_state();
 
                 let is_qresync = self.is_qresync;
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     // Refresh mailboxes
                     if let Err(err) = data.synchronize_mailboxes(false).await {
                         return data
@@ -61,6 +64,31 @@ impl<T: SessionStream> Session<T> {
                             .await;
                     }
 
+                    // Search and unified folders do not own their messages.
+                    if src_mailbox.id.search_folder_id().is_some() {
+                        return data
+                            .write_bytes(
+                                StatusResponse::no(
+                                    "Messages cannot be copied from a saved search.",
+                                )
+                                .with_tag(arguments.tag)
+                                .with_code(ResponseCode::Cannot)
+                                .into_bytes(),
+                            )
+                            .await;
+                    } else if src_mailbox.id.is_unified_folder() {
+                        return data
+                            .write_bytes(
+                                StatusResponse::no(
+                                    "Messages cannot be copied from a unified folder.",
+                                )
+                                .with_tag(arguments.tag)
+                                .with_code(ResponseCode::Cannot)
+                                .into_bytes(),
+                            )
+                            .await;
+                    }
+
                     // Make sure the mailbox exists.
                     let dest_mailbox =
                         if let Some(mailbox) = data.get_mailbox_by_name(&arguments.mailbox_name) {
@@ -107,7 +135,7 @@ impl<T: SessionStream> Session<T> {
                     }
 
                     true
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -181,6 +209,17 @@ impl<T: SessionStream> SessionData<T> {
         let mut changelog = ChangeLogBuilder::new();
         let mut did_move = false;
         let mut copied_ids = Vec::with_capacity(ids.len());
+        let seen_ids = self
+            .jmap
+            .get_tag(
+                src_mailbox.id.account_id,
+                Collection::Email,
+                Property::Keywords,
+                Keyword::Seen,
+            )
+            .await
+            .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?
+            .unwrap_or_default();
         if src_mailbox.id.account_id == dest_mailbox.account_id {
             // Mailboxes are in the same account
             let account_id = src_mailbox.id.account_id;
@@ -217,11 +256,24 @@ impl<T: SessionStream> SessionData<T> {
                 }
 
                 // Write changes
+                let seen = seen_ids.contains(id);
+                let size = self
+                    .jmap
+                    .message_size(account_id, id)
+                    .await
+                    .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?;
                 let mut batch = BatchBuilder::new();
                 batch
                     .with_account_id(account_id)
                     .with_collection(Collection::Email)
-                    .update_document(id);
+                    .update_document(id)
+                    .update_mailbox_counters(
+                        mailboxes.previous(),
+                        seen,
+                        mailboxes.current(),
+                        seen,
+                        size,
+                    );
                 mailboxes.update_batch(&mut batch, Property::MailboxIds);
                 if changelog.change_id == u64::MAX {
                     changelog.change_id =
@@ -328,11 +380,23 @@ impl<T: SessionStream> SessionData<T> {
                         }
                     } else {
                         // Remove mailbox tag from message
+                        let size =
+                            self.jmap
+                                .message_size(src_account_id, id)
+                                .await
+                                .map_err(|_| {
+                                    StatusResponse::database_failure().with_tag(&arguments.tag)
+                                })?;
                         let mut batch = BatchBuilder::new();
                         batch
                             .with_account_id(src_account_id)
                             .with_collection(Collection::Email)
-                            .update_document(id);
+                            .update_document(id)
+                            .remove_from_mailbox_counters(
+                                [src_mailbox_id.mailbox_id],
+                                seen_ids.contains(id),
+                                size,
+                            );
                         mailboxes.update(src_mailbox_id, false);
                         mailboxes.update_batch(&mut batch, Property::MailboxIds);
                         if changelog.change_id == u64::MAX {
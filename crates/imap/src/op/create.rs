@@ -54,12 +54,12 @@ impl<T: SessionStream> Session<T> {
 
         if !arguments.is_empty() {
             let data = self.state.session_data();
-            tokio::spawn(async move {
+            tokio::spawn(utils::metrics::propagate(async move {
                 for argument in arguments {
                     data.write_bytes(data.create_folder(argument).await.into_bytes())
                         .await;
                 }
-            });
+            }));
         }
         Ok(())
     }
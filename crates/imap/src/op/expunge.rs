@@ -30,7 +30,10 @@ use imap_proto::{
     Command, ResponseCode, StatusResponse,
 };
 
-use jmap::{email::set::TagManager, mailbox::UidMailbox};
+use jmap::{
+    email::set::TagManager,
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+};
 use jmap_proto::{
     error::method::MethodError,
     types::{
@@ -202,11 +205,20 @@ impl<T: SessionStream> SessionData<T> {
                 keywords.update(Keyword::Deleted, false);
 
                 // Write changes
+                let seen = keywords.current().contains(&Keyword::Seen);
+                let size = self.jmap.message_size(account_id, id).await?;
                 let mut batch = BatchBuilder::new();
                 batch
                     .with_account_id(account_id)
                     .with_collection(Collection::Email)
-                    .update_document(id);
+                    .update_document(id)
+                    .update_mailbox_counters(
+                        mailboxes.previous(),
+                        seen,
+                        mailboxes.current(),
+                        seen,
+                        size,
+                    );
                 mailboxes.update_batch(&mut batch, Property::MailboxIds);
                 keywords.update_batch(&mut batch, Property::Keywords);
                 if changelog.change_id == u64::MAX {
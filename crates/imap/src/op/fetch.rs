@@ -32,17 +32,22 @@ use imap_proto::{
             self, Arguments, Attribute, BodyContents, BodyPart, BodyPartExtension, BodyPartFields,
             DataItem, Envelope, FetchItem, Section,
         },
+        store::ANNOTATION_PREFIX,
         Flag,
     },
     receiver::Request,
     Command, ResponseCode, StatusResponse,
 };
-use jmap::email::metadata::MessageMetadata;
+use jmap::{
+    email::{metadata::MessageMetadata, stats::BodyStats},
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+};
 use jmap_proto::{
     error::method::MethodError,
+    object::Object,
     types::{
         acl::Acl, collection::Collection, id::Id, keyword::Keyword, property::Property,
-        state::StateChange, type_state::DataType,
+        state::StateChange, type_state::DataType, value::Value,
     },
 };
 use mail_parser::{Address, GetHeader, HeaderName, Message, PartType};
@@ -77,7 +82,7 @@ impl<T: SessionStream> Session<T> {
                     false
                 };
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     data.write_bytes(
                         data.fetch(
                             arguments,
@@ -91,7 +96,7 @@ impl<T: SessionStream> Session<T> {
                         .into_bytes(),
                     )
                     .await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -143,8 +148,12 @@ impl<T: SessionStream> SessionData<T> {
             }
         };
 
-        // Convert state to modseq
-        if let Some(changed_since) = arguments.changed_since {
+        // Convert state to modseq, unified folders have no change log so
+        // all messages are reported as changed
+        if let Some(changed_since) = arguments
+            .changed_since
+            .filter(|_| !mailbox.id.is_unified_folder())
+        {
             // Obtain changes since the modseq.
             let changelog = match self
                 .jmap
@@ -159,9 +168,14 @@ impl<T: SessionStream> SessionData<T> {
                 Err(_) => return StatusResponse::database_failure().with_tag(arguments.tag),
             };
 
-            // Process changes
-            let mut changed_ids = AHashMap::new();
-            let mut has_vanished = false;
+            // Process changes, when the change log was compacted past the
+            // requested modseq all messages are reported as changed
+            let mut changed_ids = if !changelog.is_truncated {
+                AHashMap::new()
+            } else {
+                ids.clone()
+            };
+            let mut has_vanished = changelog.is_truncated;
 
             for change in changelog.changes {
                 match change {
@@ -219,14 +233,14 @@ impl<T: SessionStream> SessionData<T> {
         let mut set_seen_flags = false;
         let mut needs_thread_id = false;
         let mut needs_blobs = false;
+        let mut needs_stats = false;
 
         for attribute in &arguments.attributes {
             match attribute {
-                Attribute::Envelope
-                | Attribute::Rfc822Header
-                | Attribute::Body
-                | Attribute::BodyStructure
-                | Attribute::BinarySize { .. } => {
+                Attribute::Body | Attribute::BodyStructure => {
+                    needs_stats = true;
+                }
+                Attribute::Rfc822Header | Attribute::BinarySize { .. } => {
                     /*
                         Note that this did not result in \Seen being set, because
                         RFC822.HEADER response data occurs as a result of a FETCH
@@ -278,13 +292,19 @@ impl<T: SessionStream> SessionData<T> {
 
         let mut set_seen_ids = Vec::new();
 
-        // Process each message
-        let mut ids = ids
-            .into_iter()
-            .map(|(id, imap_id)| (imap_id.seqnum, imap_id.uid, id))
-            .collect::<Vec<_>>();
-        ids.sort_unstable_by_key(|(seqnum, _, _)| *seqnum);
-        for (seqnum, uid, id) in ids {
+        // Process each message, messages in unified folders belong to other accounts
+        let mut ids = {
+            let state = mailbox.state.lock();
+            ids.into_iter()
+                .filter_map(|(id, imap_id)| {
+                    state
+                        .resolve_id(account_id, id)
+                        .map(|(account_id, id)| (imap_id.seqnum, imap_id.uid, account_id, id))
+                })
+                .collect::<Vec<_>>()
+        };
+        ids.sort_unstable_by_key(|(seqnum, _, _, _)| *seqnum);
+        for (seqnum, uid, account_id, id) in ids {
             // Obtain attributes and keywords
             let (email, keywords) = if let (Ok(Some(email)), Ok(Some(keywords))) = (
                 self.jmap
@@ -315,10 +335,36 @@ impl<T: SessionStream> SessionData<T> {
                 continue;
             };
 
+            // Obtain the part statistics computed at ingest, messages stored
+            // before these were recorded fall back to reading the blob
+            let body_stats = if needs_stats && !needs_blobs {
+                match self
+                    .jmap
+                    .get_property::<Bincode<Vec<BodyStats>>>(
+                        account_id,
+                        Collection::Email,
+                        id,
+                        &Property::BodyStats,
+                    )
+                    .await
+                {
+                    Ok(body_stats) => body_stats.map(|body_stats| body_stats.inner),
+                    Err(_) => {
+                        return StatusResponse::database_failure().with_tag(arguments.tag);
+                    }
+                }
+            } else {
+                None
+            };
+
             // Fetch and parse blob
-            let raw_message = if needs_blobs {
+            let raw_message = if needs_blobs || (needs_stats && body_stats.is_none()) {
                 // Retrieve raw message if needed
-                match self.jmap.get_blob(&email.blob_hash, 0..u32::MAX).await {
+                match self
+                    .jmap
+                    .get_message_blob(&email.blob_hash, 0..u32::MAX)
+                    .await
+                {
                     Ok(Some(raw_message)) => raw_message.into(),
                     Ok(None) => {
                         tracing::warn!(event = "not-found",
@@ -432,12 +478,12 @@ impl<T: SessionStream> SessionData<T> {
                     }
                     Attribute::Body => {
                         items.push(DataItem::Body {
-                            part: message.body_structure(false),
+                            part: message.body_structure(false, body_stats.as_deref()),
                         });
                     }
                     Attribute::BodyStructure => {
                         items.push(DataItem::BodyStructure {
-                            part: message.body_structure(true),
+                            part: message.body_structure(true, body_stats.as_deref()),
                         });
                     }
                     Attribute::BodySection {
@@ -508,6 +554,18 @@ impl<T: SessionStream> SessionData<T> {
                             thread_id: Id::from_parts(account_id, thread_id).to_string(),
                         });
                     }
+                    Attribute::Annotation {
+                        entries,
+                        attributes,
+                    } => {
+                        if let Ok(annotations) =
+                            self.jmap.get_email_annotations(account_id, id).await
+                        {
+                            items.push(DataItem::Annotation {
+                                entries: annotation_entries(&annotations, entries, attributes),
+                            });
+                        }
+                    }
                 }
             }
 
@@ -544,12 +602,30 @@ impl<T: SessionStream> SessionData<T> {
                 }
             };
             for (id, mut keywords) in set_seen_ids {
+                let mailboxes = match self
+                    .jmap
+                    .get_property::<HashedValue<Vec<UidMailbox>>>(
+                        account_id,
+                        Collection::Email,
+                        id.document_id(),
+                        &Property::MailboxIds,
+                    )
+                    .await
+                {
+                    Ok(Some(mailboxes)) => mailboxes,
+                    Ok(None) => continue,
+                    Err(_) => {
+                        return StatusResponse::database_failure().with_tag(arguments.tag);
+                    }
+                };
                 keywords.inner.push(Keyword::Seen);
                 let mut batch = BatchBuilder::new();
                 batch
                     .with_account_id(account_id)
                     .with_collection(Collection::Email)
                     .update_document(id.document_id())
+                    .assert_value(Property::MailboxIds, &mailboxes)
+                    .update_mailbox_counters(&mailboxes.inner, false, &mailboxes.inner, true, 0)
                     .assert_value(Property::Keywords, &keywords)
                     .value(Property::Keywords, keywords.inner, F_VALUE)
                     .value(Property::Keywords, Keyword::Seen, F_BITMAP)
@@ -597,7 +673,7 @@ impl<T: SessionStream> SessionData<T> {
 
 #[allow(clippy::result_unit_err)]
 pub trait AsImapDataItem<'x> {
-    fn body_structure(&self, is_extended: bool) -> BodyPart;
+    fn body_structure(&self, is_extended: bool, stats: Option<&[BodyStats]>) -> BodyPart;
     fn body_section<'z: 'x>(
         &'z self,
         sections: &[Section],
@@ -609,30 +685,38 @@ pub trait AsImapDataItem<'x> {
         partial: Option<(u32, u32)>,
     ) -> Result<Option<BodyContents>, ()>;
     fn binary_size(&self, sections: &[u32]) -> Option<usize>;
-    fn as_body_part(&self, part_id: usize, is_extended: bool) -> BodyPart;
+    fn as_body_part(
+        &self,
+        part_id: usize,
+        is_extended: bool,
+        stats: Option<&BodyStats>,
+    ) -> BodyPart;
     fn envelope(&self) -> Envelope;
 }
 
 impl<'x> AsImapDataItem<'x> for Message<'x> {
-    fn body_structure(&self, is_extended: bool) -> BodyPart {
+    fn body_structure(&self, is_extended: bool, stats: Option<&[BodyStats]>) -> BodyPart {
         let mut stack = Vec::new();
         let mut parts = [0].iter();
         let mut message = self;
+        let mut stats = stats;
         let mut root_part = None;
 
         loop {
             while let Some(part_id) = parts.next() {
-                let mut part = message.as_body_part(*part_id, is_extended);
+                let part_stats = stats.and_then(|stats| stats.get(*part_id));
+                let mut part = message.as_body_part(*part_id, is_extended, part_stats);
 
                 match &message.parts[*part_id].body {
                     PartType::Message(nested_message) => {
                         part.set_envelope(nested_message.envelope());
                         if let Some(root_part) = root_part {
-                            stack.push((root_part, parts, message.into()));
+                            stack.push((root_part, parts, (message, stats).into()));
                         }
                         root_part = part.into();
                         parts = [0].iter();
                         message = nested_message;
+                        stats = part_stats.map(|stats| stats.nested.as_slice());
                         continue;
                     }
                     PartType::Multipart(subparts) => {
@@ -652,8 +736,9 @@ impl<'x> AsImapDataItem<'x> for Message<'x> {
                 }
             }
             if let Some((mut prev_root_part, prev_parts, prev_message)) = stack.pop() {
-                if let Some(prev_message) = prev_message {
+                if let Some((prev_message, prev_stats)) = prev_message {
                     message = prev_message;
+                    stats = prev_stats;
                 }
 
                 prev_root_part.add_part(root_part.unwrap());
@@ -667,7 +752,12 @@ impl<'x> AsImapDataItem<'x> for Message<'x> {
         root_part.unwrap()
     }
 
-    fn as_body_part(&self, part_id: usize, is_extended: bool) -> BodyPart {
+    fn as_body_part(
+        &self,
+        part_id: usize,
+        is_extended: bool,
+        stats: Option<&BodyStats>,
+    ) -> BodyPart {
         let part = &self.parts[part_id];
         let body = self.raw_message.get(part.offset_body..part.offset_end);
         let (is_multipart, is_text) = match &part.body {
@@ -714,7 +804,11 @@ impl<'x> AsImapDataItem<'x> for Message<'x> {
                 .header_value(&HeaderName::ContentTransferEncoding)
                 .and_then(|ct| ct.as_text().map(|ct| ct.into()));
 
-            fields.body_size_octets = body.as_ref().map(|b| b.len()).unwrap_or(0);
+            fields.body_size_octets = body
+                .as_ref()
+                .map(|b| b.len())
+                .or_else(|| stats.map(|_| part.offset_end.saturating_sub(part.offset_body)))
+                .unwrap_or(0);
 
             if is_text {
                 if fields.body_subtype.is_none() {
@@ -733,7 +827,8 @@ impl<'x> AsImapDataItem<'x> for Message<'x> {
             if !is_multipart {
                 body_md5 = body
                     .as_ref()
-                    .map(|b| format!("{:x}", md5::compute(b)).into());
+                    .map(|b| format!("{:x}", md5::compute(b)).into())
+                    .or_else(|| stats.map(|stats| stats.md5.clone().into()));
             }
 
             extension.body_disposition = part
@@ -793,6 +888,7 @@ impl<'x> AsImapDataItem<'x> for Message<'x> {
                         body_size_lines: body
                             .as_ref()
                             .map(|b| b.iter().filter(|&&ch| ch == b'\n').count())
+                            .or_else(|| stats.map(|stats| stats.lines))
                             .unwrap_or(0),
                         body_md5,
                         extension,
@@ -1150,3 +1246,63 @@ impl AsImapAddress for mail_parser::HeaderValue<'_> {
         addresses
     }
 }
+
+fn annotation_entries(
+    annotations: &Object<Value>,
+    entries: &[String],
+    attributes: &[String],
+) -> Vec<(String, Vec<(String, Option<String>)>)> {
+    let mut result = Vec::new();
+    for (key, value) in annotations.properties.iter() {
+        let (Property::_T(key), Value::Text(value)) = (key, value) else {
+            continue;
+        };
+        let entry = format!("{ANNOTATION_PREFIX}{key}");
+        if !entries
+            .iter()
+            .any(|pattern| matches_entry(pattern.as_bytes(), entry.as_bytes()))
+        {
+            continue;
+        }
+
+        // Annotations are shared, private values are always empty
+        let mut values = Vec::new();
+        for attribute in attributes {
+            let attribute = attribute.to_ascii_lowercase();
+            let (name, shared) = match attribute.split_once('.') {
+                Some((name, "shared")) => (name, Some(true)),
+                Some((name, "priv")) => (name, Some(false)),
+                Some((name, "*")) => (name, None),
+                None => (attribute.as_str(), None),
+                _ => continue,
+            };
+            let shared_value = match name {
+                "value" => value.clone(),
+                "size" => value.len().to_string(),
+                _ => continue,
+            };
+            if shared != Some(true) {
+                values.push((format!("{name}.priv"), None));
+            }
+            if shared != Some(false) {
+                values.push((format!("{name}.shared"), Some(shared_value)));
+            }
+        }
+        if !values.is_empty() {
+            result.push((entry, values));
+        }
+    }
+    result
+}
+
+// Matches RFC 5257 entry patterns, '*' matches any text and '%' stops at hierarchy separators
+fn matches_entry(pattern: &[u8], entry: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => entry.is_empty(),
+        Some((b'*', rest)) => (0..=entry.len()).any(|pos| matches_entry(rest, &entry[pos..])),
+        Some((b'%', rest)) => (0..=entry.len())
+            .take_while(|&pos| pos == 0 || entry[pos - 1] != b'/')
+            .any(|pos| matches_entry(rest, &entry[pos..])),
+        Some((ch, rest)) => entry.first() == Some(ch) && matches_entry(rest, &entry[1..]),
+    }
+}
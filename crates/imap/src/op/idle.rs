@@ -35,7 +35,10 @@ use imap_proto::{
     Command, ResponseCode, StatusResponse,
 };
 
-use jmap_proto::types::{collection::Collection, type_state::DataType};
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, type_state::DataType},
+};
 use store::query::log::Query;
 use tokio::io::AsyncReadExt;
 use utils::listener::SessionStream;
@@ -209,7 +212,16 @@ impl<T: SessionStream> SessionData<T> {
             // Synchronize emails
             if let Some(mailbox) = mailbox {
                 // Obtain changes since last sync
-                let modseq = mailbox.state.lock().modseq;
+                let (modseq, unified_accounts) = {
+                    let state = mailbox.state.lock();
+                    (
+                        state.modseq,
+                        state
+                            .unified
+                            .as_ref()
+                            .map(|unified| unified.accounts.clone()),
+                    )
+                };
                 match self.write_mailbox_changes(mailbox, is_qresync).await {
                     Ok(new_state) => {
                         if new_state == modseq {
@@ -223,32 +235,52 @@ impl<T: SessionStream> SessionData<T> {
                 }
 
                 // Obtain changed messages
-                let changed_ids = match self
-                    .jmap
-                    .changes_(
-                        mailbox.id.account_id,
-                        Collection::Email,
-                        modseq.map(Query::Since).unwrap_or(Query::All),
-                    )
-                    .await
-                {
-                    Ok(changelog) => {
-                        let state = mailbox.state.lock();
-                        changelog
-                            .changes
-                            .into_iter()
-                            .filter_map(|change| {
-                                state
-                                    .id_to_imap
-                                    .get(&((change.unwrap_id() & u32::MAX as u64) as u32))
-                                    .map(|id| id.uid)
-                            })
-                            .collect::<AHashSet<_>>()
+                let changed_ids = if let Some(accounts) = unified_accounts {
+                    match self.unified_changes(mailbox, accounts).await {
+                        Ok(changed_ids) => changed_ids,
+                        Err(_) => {
+                            self.write_bytes(StatusResponse::database_failure().into_bytes())
+                                .await;
+                            return;
+                        }
                     }
-                    Err(_) => {
-                        self.write_bytes(StatusResponse::database_failure().into_bytes())
-                            .await;
-                        return;
+                } else {
+                    match self
+                        .jmap
+                        .changes_(
+                            mailbox.id.account_id,
+                            Collection::Email,
+                            modseq.map(Query::Since).unwrap_or(Query::All),
+                        )
+                        .await
+                    {
+                        Ok(changelog) if changelog.is_truncated => {
+                            // The change log was compacted, report all messages
+                            let state = mailbox.state.lock();
+                            state
+                                .id_to_imap
+                                .values()
+                                .map(|id| id.uid)
+                                .collect::<AHashSet<_>>()
+                        }
+                        Ok(changelog) => {
+                            let state = mailbox.state.lock();
+                            changelog
+                                .changes
+                                .into_iter()
+                                .filter_map(|change| {
+                                    state
+                                        .id_to_imap
+                                        .get(&((change.unwrap_id() & u32::MAX as u64) as u32))
+                                        .map(|id| id.uid)
+                                })
+                                .collect::<AHashSet<_>>()
+                        }
+                        Err(_) => {
+                            self.write_bytes(StatusResponse::database_failure().into_bytes())
+                                .await;
+                            return;
+                        }
                     }
                 };
 
@@ -277,4 +309,50 @@ impl<T: SessionStream> SessionData<T> {
             }
         }
     }
+
+    /// Returns the UIDs of the messages in a unified folder that changed in
+    /// any of its accounts since the last synchronization.
+    async fn unified_changes(
+        &self,
+        mailbox: &SelectedMailbox,
+        accounts: Vec<(u32, Option<u64>)>,
+    ) -> Result<AHashSet<u32>, MethodError> {
+        let mut changed_ids = AHashSet::new();
+        let mut all_accounts = AHashSet::new();
+        for (account_id, change_id) in accounts {
+            let changelog = self
+                .jmap
+                .changes_(
+                    account_id,
+                    Collection::Email,
+                    change_id.map(Query::Since).unwrap_or(Query::All),
+                )
+                .await?;
+            if changelog.is_truncated {
+                // The change log was compacted, report all messages
+                all_accounts.insert(account_id);
+            } else {
+                changed_ids.extend(
+                    changelog
+                        .changes
+                        .into_iter()
+                        .map(|change| (account_id, (change.unwrap_id() & u32::MAX as u64) as u32)),
+                );
+            }
+        }
+
+        let state = mailbox.state.lock();
+        Ok(state
+            .unified
+            .as_ref()
+            .map(|unified| {
+                unified
+                    .ids
+                    .iter()
+                    .filter(|(_, id)| all_accounts.contains(&id.0) || changed_ids.contains(*id))
+                    .map(|(uid, _)| *uid)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
 }
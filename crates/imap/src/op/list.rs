@@ -49,9 +49,9 @@ impl<T: SessionStream> Session<T> {
                 if !arguments.is_separator_query() {
                     let data = self.state.session_data();
                     let version = self.version;
-                    tokio::spawn(async move {
+                    tokio::spawn(utils::metrics::propagate(async move {
                         data.list(arguments, is_lsub, version).await;
-                    });
+                    }));
                     Ok(())
                 } else {
                     self.write_bytes(
@@ -253,6 +253,68 @@ impl<T: SessionStream> SessionData<T> {
             }
         }
 
+        // Add unified and search folders, which are never subscribed and have no special use
+        if !filter_subscribed && !filter_special_use {
+            if let Some(unified_folder) = self.unified_folder_name() {
+                if matches_pattern(&patterns, &self.imap.name_unified) {
+                    list_items.push(ListItem {
+                        mailbox_name: self.imap.name_unified.clone(),
+                        attributes: if include_children {
+                            vec![Attribute::HasChildren, Attribute::NoSelect]
+                        } else {
+                            vec![Attribute::NoSelect]
+                        },
+                        tags: vec![],
+                    });
+                }
+                if matches_pattern(&patterns, &unified_folder) {
+                    list_items.push(ListItem {
+                        mailbox_name: unified_folder,
+                        attributes: if include_children {
+                            vec![Attribute::HasNoChildren]
+                        } else {
+                            vec![]
+                        },
+                        tags: vec![],
+                    });
+                }
+            }
+
+            let search_folders = match self.search_folders().await {
+                Ok(search_folders) => search_folders,
+                Err(err) => {
+                    self.write_bytes(err.with_tag(tag).into_bytes()).await;
+                    return;
+                }
+            };
+            if !search_folders.is_empty()
+                && matches_pattern(&patterns, &self.imap.name_saved_searches)
+            {
+                list_items.push(ListItem {
+                    mailbox_name: self.imap.name_saved_searches.clone(),
+                    attributes: if include_children {
+                        vec![Attribute::HasChildren, Attribute::NoSelect]
+                    } else {
+                        vec![Attribute::NoSelect]
+                    },
+                    tags: vec![],
+                });
+            }
+            for (mailbox_name, _) in search_folders {
+                if matches_pattern(&patterns, &mailbox_name) {
+                    list_items.push(ListItem {
+                        mailbox_name,
+                        attributes: if include_children {
+                            vec![Attribute::HasNoChildren]
+                        } else {
+                            vec![]
+                        },
+                        tags: vec![],
+                    });
+                }
+            }
+        }
+
         // Add status response
         let mut status_items = Vec::new();
         if let Some(include_status) = include_status {
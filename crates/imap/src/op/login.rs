@@ -24,6 +24,7 @@
 use imap_proto::{receiver::Request, Command};
 
 use mail_send::Credentials;
+use smtp_proto::AUTH_LOGIN;
 use utils::listener::SessionStream;
 
 use crate::core::Session;
@@ -37,6 +38,7 @@ impl<T: SessionStream> Session<T> {
                         username: args.username,
                         secret: args.password,
                     },
+                    AUTH_LOGIN,
                     args.tag,
                 )
                 .await
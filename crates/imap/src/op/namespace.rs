@@ -33,16 +33,20 @@ use crate::core::Session;
 
 impl<T: SessionStream> Session<T> {
     pub async fn handle_namespace(&mut self, request: Request<Command>) -> crate::OpResult {
+        let data = self.state.session_data();
         self.write_bytes(
             StatusResponse::completed(Command::Namespace)
                 .with_tag(request.tag)
                 .serialize(
                     Response {
-                        shared_prefix: if self.state.session_data().mailboxes.lock().len() > 1 {
+                        shared_prefix: if data.mailboxes.lock().len() > 1 {
                             self.imap.name_shared.clone().into()
                         } else {
                             None
                         },
+                        unified_prefix: data
+                            .unified_folder_name()
+                            .map(|_| self.imap.name_unified.clone()),
                     }
                     .serialize(),
                 ),
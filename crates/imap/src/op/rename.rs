@@ -45,10 +45,10 @@ impl<T: SessionStream> Session<T> {
         match request.parse_rename(self.version) {
             Ok(arguments) => {
                 let data = self.state.session_data();
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     data.write_bytes(data.rename_folder(arguments).await.into_bytes())
                         .await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -23,15 +23,17 @@
 
 use std::sync::Arc;
 
+use ahash::AHashMap;
 use imap_proto::{
     protocol::{
         search::{self, Arguments, Filter, Response, ResultOption},
         Sequence,
     },
     receiver::Request,
-    Command, StatusResponse,
+    Command, ResponseCode, StatusResponse,
 };
 
+use jmap::email::query::UNINDEXED_HEADER;
 use jmap_proto::types::{collection::Collection, id::Id, keyword::Keyword, property::Property};
 use mail_parser::HeaderName;
 use nlp::language::Language;
@@ -42,7 +44,7 @@ use store::{
     write::now,
 };
 use tokio::sync::watch;
-use utils::listener::SessionStream;
+use utils::{listener::SessionStream, metrics::advisor::index_advisor};
 
 use crate::core::{ImapId, MailboxState, SavedSearch, SelectedMailbox, Session, SessionData};
 
@@ -74,7 +76,7 @@ impl<T: SessionStream> Session<T> {
                         (None, None)
                     };
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     let tag = std::mem::take(&mut arguments.tag);
                     let bytes = match data
                         .search(
@@ -107,7 +109,7 @@ impl<T: SessionStream> Session<T> {
                         }
                     };
                     data.write_bytes(bytes).await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -124,6 +126,14 @@ impl<T: SessionStream> SessionData<T> {
         prev_saved_search: Option<Option<Arc<Vec<ImapId>>>>,
         is_uid: bool,
     ) -> Result<search::Response, StatusResponse> {
+        // Messages in unified folders belong to different accounts
+        if arguments.sort.is_some() && mailbox.id.is_unified_folder() {
+            return Err(
+                StatusResponse::no("Sorting is not supported in unified folders.")
+                    .with_code(ResponseCode::Cannot),
+            );
+        }
+
         // Run query
         let (result_set, include_highest_modseq) = self
             .query(arguments.filter, &mailbox, &prev_saved_search)
@@ -252,22 +262,95 @@ impl<T: SessionStream> SessionData<T> {
         mailbox: &SelectedMailbox,
         prev_saved_search: &Option<Option<Arc<Vec<ImapId>>>>,
     ) -> Result<(ResultSet, bool), StatusResponse> {
-        // Obtain message ids
+        if !mailbox.id.is_unified_folder() {
+            // Obtain message ids
+            let message_ids = if mailbox.id.search_folder_id().is_some() {
+                mailbox
+                    .state
+                    .lock()
+                    .id_to_imap
+                    .keys()
+                    .copied()
+                    .collect::<RoaringBitmap>()
+            } else {
+                self.jmap
+                    .get_tag(
+                        mailbox.id.account_id,
+                        Collection::Email,
+                        Property::MailboxIds,
+                        mailbox.id.mailbox_id,
+                    )
+                    .await?
+                    .unwrap_or_default()
+            };
+
+            return self
+                .query_account(
+                    imap_filter,
+                    mailbox,
+                    mailbox.id.account_id,
+                    message_ids,
+                    prev_saved_search,
+                )
+                .await;
+        }
+
+        // Unified folders are queried on each account, the matching messages
+        // are then mapped back to their ids in the folder
+        let mut accounts: AHashMap<u32, AHashMap<u32, u32>> = AHashMap::new();
+        if let Some(unified) = &mailbox.state.lock().unified {
+            for (&id, &(account_id, document_id)) in &unified.ids {
+                accounts
+                    .entry(account_id)
+                    .or_default()
+                    .insert(document_id, id);
+            }
+        }
+        let mut results = RoaringBitmap::new();
+        let mut include_highest_modseq = false;
+        for (account_id, ids) in accounts {
+            let (result_set, include_highest_modseq_) = self
+                .query_account(
+                    imap_filter.clone(),
+                    mailbox,
+                    account_id,
+                    ids.keys().copied().collect(),
+                    prev_saved_search,
+                )
+                .await?;
+            results.extend(
+                result_set
+                    .results
+                    .iter()
+                    .filter_map(|document_id| ids.get(&document_id).copied()),
+            );
+            include_highest_modseq |= include_highest_modseq_;
+        }
+
+        Ok((
+            ResultSet {
+                account_id: mailbox.id.account_id,
+                collection: Collection::Email.into(),
+                results,
+            },
+            include_highest_modseq,
+        ))
+    }
+
+    async fn query_account(
+        &self,
+        imap_filter: Vec<Filter>,
+        mailbox: &SelectedMailbox,
+        account_id: u32,
+        message_ids: RoaringBitmap,
+        prev_saved_search: &Option<Option<Arc<Vec<ImapId>>>>,
+    ) -> Result<(ResultSet, bool), StatusResponse> {
         let mut filters = Vec::with_capacity(imap_filter.len() + 1);
-        let message_ids = self
-            .jmap
-            .get_tag(
-                mailbox.id.account_id,
-                Collection::Email,
-                Property::MailboxIds,
-                mailbox.id.mailbox_id,
-            )
-            .await?
-            .unwrap_or_default();
         filters.push(query::Filter::is_in_set(message_ids.clone()));
 
         // Convert query
         let mut include_highest_modseq = false;
+        let default_language = self.jmap.account_search_language(account_id).await;
         for filter_group in imap_filter.into_filter_group() {
             match filter_group {
                 FilterGroup::Fts(conds) => {
@@ -285,7 +368,7 @@ impl<T: SessionStream> SessionData<T> {
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Body,
                                     text,
-                                    self.jmap.config.default_language,
+                                    default_language,
                                 ));
                             }
                             search::Filter::Cc(text) => {
@@ -305,6 +388,13 @@ impl<T: SessionStream> SessionData<T> {
                             search::Filter::Header(header, value) => {
                                 match HeaderName::parse(header) {
                                     Some(HeaderName::Other(header_name)) => {
+                                        index_advisor().record(
+                                            "imap",
+                                            "SEARCH",
+                                            format!("header:{}", header_name.to_lowercase()),
+                                            UNINDEXED_HEADER,
+                                            0,
+                                        );
                                         return Err(StatusResponse::no(format!(
                                             "Querying header '{header_name}' is not supported.",
                                         )));
@@ -343,7 +433,7 @@ impl<T: SessionStream> SessionData<T> {
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Header(HeaderName::Subject),
                                     text,
-                                    self.jmap.config.default_language,
+                                    default_language,
                                 ));
                             }
                             search::Filter::Text(text) => {
@@ -371,17 +461,17 @@ impl<T: SessionStream> SessionData<T> {
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Header(HeaderName::Subject),
                                     &text,
-                                    self.jmap.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Body,
                                     &text,
-                                    self.jmap.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Attachment,
                                     text,
-                                    self.jmap.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::End);
                             }
@@ -410,7 +500,7 @@ impl<T: SessionStream> SessionData<T> {
 
                     filters.push(query::Filter::is_in_set(
                         self.jmap
-                            .fts_filter(mailbox.id.account_id, Collection::Email, fts_filters)
+                            .fts_filter(account_id, Collection::Email, fts_filters)
                             .await?,
                     ));
                 }
@@ -423,16 +513,28 @@ impl<T: SessionStream> SessionData<T> {
                             if let Some(prev_saved_search) = prev_saved_search {
                                 let state = mailbox.state.lock();
                                 for imap_id in prev_saved_search.iter() {
-                                    if let Some(id) = state.uid_to_id.get(&imap_id.uid) {
-                                        set.insert(*id);
+                                    if let Some((_, id)) = state
+                                        .uid_to_id
+                                        .get(&imap_id.uid)
+                                        .and_then(|id| state.resolve_id(account_id, *id))
+                                        .filter(|(id_account_id, _)| *id_account_id == account_id)
+                                    {
+                                        set.insert(id);
                                     }
                                 }
                             } else {
                                 return Err(StatusResponse::no("No saved search found."));
                             }
                         } else {
-                            for id in mailbox.sequence_to_ids(&sequence, uid_filter).await?.keys() {
-                                set.insert(*id);
+                            let ids = mailbox.sequence_to_ids(&sequence, uid_filter).await?;
+                            let state = mailbox.state.lock();
+                            for id in ids.keys() {
+                                if let Some((_, id)) = state
+                                    .resolve_id(account_id, *id)
+                                    .filter(|(id_account_id, _)| *id_account_id == account_id)
+                                {
+                                    set.insert(id);
+                                }
                             }
                         }
                         filters.push(query::Filter::is_in_set(set));
@@ -600,23 +702,37 @@ impl<T: SessionStream> SessionData<T> {
                             now().saturating_sub(secs as u64),
                         ));
                     }
+                    search::Filter::ModSeq(_) if mailbox.id.is_unified_folder() => {
+                        // Unified folders have no change log, match all messages
+                        filters.push(query::Filter::is_in_set(message_ids.clone()));
+                        include_highest_modseq = true;
+                    }
                     search::Filter::ModSeq((modseq, _)) => {
-                        let mut set = RoaringBitmap::new();
-                        for change in self
+                        let changelog = self
                             .jmap
-                            .changes_(
-                                mailbox.id.account_id,
-                                Collection::Email,
-                                Query::from_modseq(modseq),
-                            )
-                            .await?
-                            .changes
-                        {
-                            let id = (change.unwrap_id() & u32::MAX as u64) as u32;
-                            if message_ids.contains(id) {
-                                set.insert(id);
+                            .changes_(account_id, Collection::Email, Query::from_modseq(modseq))
+                            .await?;
+                        let set = if !changelog.is_truncated {
+                            let mut set = RoaringBitmap::new();
+                            for change in changelog.changes {
+                                let id = (change.unwrap_id() & u32::MAX as u64) as u32;
+                                if message_ids.contains(id) {
+                                    set.insert(id);
+                                }
                             }
-                        }
+                            set
+                        } else {
+                            // The change log was compacted, match all messages
+                            index_advisor().record(
+                                "imap",
+                                "SEARCH",
+                                "modseq",
+                                "The change log no longer covers the requested MODSEQ, \
+                                 consider increasing jmap.maintenance.changes.retention.",
+                                message_ids.len(),
+                            );
+                            message_ids.clone()
+                        };
                         filters.push(query::Filter::is_in_set(set));
                         include_highest_modseq = true;
                     }
@@ -650,7 +766,7 @@ impl<T: SessionStream> SessionData<T> {
 
         // Run query
         self.jmap
-            .filter(mailbox.id.account_id, Collection::Email, filters)
+            .filter(account_id, Collection::Email, filters)
             .await
             .map(|res| (res, include_highest_modseq))
             .map_err(|err| err.into())
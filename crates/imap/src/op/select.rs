@@ -43,7 +43,7 @@ use super::ToModSeq;
 
 impl<T: SessionStream> Session<T> {
     pub async fn handle_select(&mut self, request: Request<Command>) -> crate::OpResult {
-        let is_select = request.command == Command::Select;
+        let mut is_select = request.command == Command::Select;
         let command = request.command;
         match request.parse_select(self.version) {
             Ok(arguments) => {
@@ -56,7 +56,33 @@ impl<T: SessionStream> Session<T> {
                         .await;
                 }
 
-                if let Some(mailbox) = data.get_mailbox_by_name(&arguments.mailbox_name) {
+                // Search and unified folders can only be opened read-only
+                let mailbox =
+                    if let Some(mailbox) = data.get_mailbox_by_name(&arguments.mailbox_name) {
+                        Some(mailbox)
+                    } else if let Some(mailbox) =
+                        data.get_unified_folder_by_name(&arguments.mailbox_name)
+                    {
+                        is_select = false;
+                        Some(mailbox)
+                    } else {
+                        match data
+                            .get_search_folder_by_name(&arguments.mailbox_name)
+                            .await
+                        {
+                            Ok(mailbox) => {
+                                is_select = false;
+                                mailbox
+                            }
+                            Err(err) => {
+                                return self
+                                    .write_bytes(err.with_tag(arguments.tag).into_bytes())
+                                    .await;
+                            }
+                        }
+                    };
+
+                if let Some(mailbox) = mailbox {
                     // Synchronize messages
                     match data.fetch_messages(&mailbox).await {
                         Ok(state) => {
@@ -23,12 +23,14 @@
 
 use std::sync::Arc;
 
+use ahash::AHashMap;
 use imap_proto::{
     parser::PushUnique,
     protocol::status::{Status, StatusItem, StatusItemType},
     receiver::Request,
     Command, ResponseCode, StatusResponse,
 };
+use jmap::mailbox::counters::MailboxCounters;
 use jmap_proto::{
     object::Object,
     types::{collection::Collection, id::Id, keyword::Keyword, property::Property, value::Value},
@@ -39,7 +41,7 @@ use store::{
 use store::{Deserialize, U32_LEN};
 use utils::listener::SessionStream;
 
-use crate::core::{Mailbox, Session, SessionData};
+use crate::core::{Mailbox, MailboxId, Session, SessionData};
 
 use super::ToModSeq;
 
@@ -49,7 +51,7 @@ impl<T: SessionStream> Session<T> {
             Ok(arguments) => {
                 let version = self.version;
                 let data = self.state.session_data();
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     // Refresh mailboxes
                     if let Err(err) = data.synchronize_mailboxes(false).await {
                         data.write_bytes(err.with_tag(arguments.tag).into_bytes())
@@ -74,7 +76,7 @@ impl<T: SessionStream> Session<T> {
                             data.write_bytes(response.into_bytes()).await;
                         }
                     }
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -91,9 +93,19 @@ impl<T: SessionStream> SessionData<T> {
         // Get mailbox id
         let mailbox = if let Some(mailbox) = self.get_mailbox_by_name(&mailbox_name) {
             mailbox
+        } else if let Some(mailbox) = self.get_unified_folder_by_name(&mailbox_name) {
+            return self
+                .virtual_folder_status(mailbox_name, mailbox, items)
+                .await;
+        } else if let Some(mailbox) = self.get_search_folder_by_name(&mailbox_name).await? {
+            return self
+                .virtual_folder_status(mailbox_name, mailbox, items)
+                .await;
         } else {
             // Some IMAP clients will try to get the status of a mailbox with the NoSelect flag
             return if mailbox_name == self.imap.name_shared
+                || mailbox_name == self.imap.name_saved_searches
+                || mailbox_name == self.imap.name_unified
                 || mailbox_name
                     .split_once('/')
                     .map_or(false, |(base_name, path)| {
@@ -218,24 +230,20 @@ impl<T: SessionStream> SessionData<T> {
         if !items_update.is_empty() {
             // Retrieve latest values
             let mut values_update = Vec::with_capacity(items_update.len());
-            let mailbox_message_ids = self
-                .jmap
-                .get_tag(
-                    mailbox.account_id,
-                    Collection::Email,
-                    Property::MailboxIds,
-                    mailbox.mailbox_id,
-                )
-                .await?
-                .map(Arc::new);
-            let message_ids = self
-                .jmap
-                .get_document_ids(mailbox.account_id, Collection::Email)
-                .await?;
+            let counters = if items_update
+                .iter()
+                .any(|item| matches!(item, Status::Messages | Status::Unseen | Status::Size))
+            {
+                self.jmap
+                    .mailbox_counters(mailbox.account_id, mailbox.mailbox_id)
+                    .await?
+            } else {
+                MailboxCounters::default()
+            };
 
             for item in items_update {
                 let result = match item {
-                    Status::Messages => mailbox_message_ids.as_ref().map(|v| v.len()).unwrap_or(0),
+                    Status::Messages => counters.total,
                     Status::UidNext => {
                         (self
                             .jmap
@@ -268,33 +276,17 @@ impl<T: SessionStream> SessionData<T> {
                                             "Failed to obtain uid validity");
                             StatusResponse::no("Mailbox unavailable.")
                         })?,
-                    Status::Unseen => {
-                        if let (Some(message_ids), Some(mailbox_message_ids)) =
-                            (&message_ids, &mailbox_message_ids)
-                        {
-                            if let Some(mut seen) = self
-                                .jmap
+                    Status::Unseen => counters.unseen,
+                    Status::Deleted => {
+                        if let (Some(mailbox_message_ids), Some(mut deleted)) = (
+                            self.jmap
                                 .get_tag(
                                     mailbox.account_id,
                                     Collection::Email,
-                                    Property::Keywords,
-                                    Keyword::Seen,
+                                    Property::MailboxIds,
+                                    mailbox.mailbox_id,
                                 )
-                                .await?
-                            {
-                                seen ^= message_ids;
-                                seen &= mailbox_message_ids.as_ref();
-                                seen.len()
-                            } else {
-                                mailbox_message_ids.len()
-                            }
-                        } else {
-                            0
-                        }
-                    }
-                    Status::Deleted => {
-                        if let (Some(mailbox_message_ids), Some(mut deleted)) = (
-                            &mailbox_message_ids,
+                                .await?,
                             self.jmap
                                 .get_tag(
                                     mailbox.account_id,
@@ -304,20 +296,13 @@ impl<T: SessionStream> SessionData<T> {
                                 )
                                 .await?,
                         ) {
-                            deleted &= mailbox_message_ids.as_ref();
+                            deleted &= mailbox_message_ids;
                             deleted.len()
                         } else {
                             0
                         }
                     }
-                    Status::Size => {
-                        if let Some(mailbox_message_ids) = &mailbox_message_ids {
-                            self.calculate_mailbox_size(mailbox.account_id, mailbox_message_ids)
-                                .await? as u64
-                        } else {
-                            0
-                        }
-                    }
+                    Status::Size => counters.size,
                     Status::Recent => {
                         self.fetch_messages(&mailbox).await?;
                         0
@@ -373,6 +358,86 @@ impl<T: SessionStream> SessionData<T> {
         })
     }
 
+    async fn virtual_folder_status(
+        &self,
+        mailbox_name: String,
+        mailbox: MailboxId,
+        items: &[Status],
+    ) -> super::Result<StatusItem> {
+        // Group the messages by account, as unified folders span several
+        let state = self.fetch_messages(&mailbox).await?;
+        let mut accounts: AHashMap<u32, RoaringBitmap> = AHashMap::new();
+        for id in state.id_to_imap.keys() {
+            if let Some((account_id, document_id)) = state.resolve_id(mailbox.account_id, *id) {
+                accounts.entry(account_id).or_default().insert(document_id);
+            }
+        }
+        let accounts = accounts
+            .into_iter()
+            .map(|(account_id, message_ids)| (account_id, Arc::new(message_ids)))
+            .collect::<Vec<_>>();
+        let mut items_response = Vec::with_capacity(items.len());
+
+        for item in items {
+            let result = match item {
+                Status::Messages => StatusItemType::Number(state.total_messages as u64),
+                Status::UidNext => StatusItemType::Number(state.uid_next as u64),
+                Status::UidValidity => StatusItemType::Number(state.uid_validity as u64),
+                Status::Unseen | Status::Deleted => {
+                    let mut total = 0;
+                    for (account_id, message_ids) in &accounts {
+                        let mut tagged = self
+                            .jmap
+                            .get_tag(
+                                *account_id,
+                                Collection::Email,
+                                Property::Keywords,
+                                if *item == Status::Unseen {
+                                    Keyword::Seen
+                                } else {
+                                    Keyword::Deleted
+                                },
+                            )
+                            .await?
+                            .unwrap_or_default();
+                        tagged &= message_ids.as_ref();
+                        total += if *item == Status::Unseen {
+                            message_ids.len() - tagged.len()
+                        } else {
+                            tagged.len()
+                        };
+                    }
+                    StatusItemType::Number(total)
+                }
+                Status::Size => {
+                    let mut total = 0;
+                    for (account_id, message_ids) in &accounts {
+                        total += self
+                            .calculate_mailbox_size(*account_id, message_ids)
+                            .await? as u64;
+                    }
+                    StatusItemType::Number(total)
+                }
+                Status::Recent => StatusItemType::Number(0),
+                Status::HighestModSeq => StatusItemType::Number(state.modseq.to_modseq()),
+                Status::MailboxId => StatusItemType::String(
+                    if let Some(search_id) = mailbox.search_folder_id() {
+                        Id::from(search_id)
+                    } else {
+                        Id::from_parts(mailbox.account_id, mailbox.mailbox_id)
+                    }
+                    .to_string(),
+                ),
+            };
+            items_response.push((*item, result));
+        }
+
+        Ok(StatusItem {
+            mailbox_name,
+            items: items_response,
+        })
+    }
+
     async fn calculate_mailbox_size(
         &self,
         account_id: u32,
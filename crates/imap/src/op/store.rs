@@ -27,13 +27,16 @@ use ahash::AHashSet;
 use imap_proto::{
     protocol::{
         fetch::{DataItem, FetchItem},
-        store::{Arguments, Operation, Response},
+        store::{Arguments, Operation, Response, ANNOTATION_PREFIX},
         Flag, ImapResponse,
     },
     receiver::Request,
     Command, ResponseCode, ResponseType, StatusResponse,
 };
-use jmap::{email::set::TagManager, mailbox::UidMailbox};
+use jmap::{
+    email::{annotations::update_annotations, set::TagManager},
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+};
 use jmap_proto::{
     error::method::MethodError,
     types::{
@@ -62,13 +65,13 @@ impl<T: SessionStream> Session<T> {
                 let (data, mailbox) = self.state.select_data();
                 let is_condstore = self.is_condstore || mailbox.is_condstore;
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     let bytes = match data.store(arguments, mailbox, is_uid, is_condstore).await {
                         Ok(response) => response,
                         Err(response) => response.into_bytes(),
                     };
                     data.write_bytes(bytes).await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -125,6 +128,19 @@ impl<T: SessionStream> SessionData<T> {
             .with_code(ResponseCode::NoPerm));
         }
 
+        // Only vendor annotations are supported
+        if arguments.annotations.iter().any(|annotation| {
+            annotation
+                .entry
+                .strip_prefix(ANNOTATION_PREFIX)
+                .map_or(true, |key| key.is_empty())
+        }) {
+            return Err(StatusResponse::no(format!(
+                "Only annotations under {ANNOTATION_PREFIX} are supported."
+            ))
+            .with_tag(arguments.tag));
+        }
+
         // Filter out unchanged since ids
         let mut response_code = None;
         let mut unchanged_failed = false;
@@ -147,6 +163,14 @@ impl<T: SessionStream> SessionData<T> {
                 .sequence_expand_missing(&arguments.sequence_set, is_uid)
                 .await;
 
+            // When the change log was compacted past the requested modseq,
+            // all messages are considered modified
+            if changelog.is_truncated {
+                for (_, imap_id) in ids.drain() {
+                    modified.push(if is_uid { imap_id.uid } else { imap_id.seqnum });
+                }
+            }
+
             // Add all IDs that changed in this mailbox
             for change in changelog.changes {
                 let (Change::Insert(id)
@@ -242,13 +266,42 @@ impl<T: SessionStream> SessionData<T> {
                             keywords.update(keyword.clone(), false);
                         }
                     }
+                    Operation::Annotate => (),
                 }
 
-                if keywords.has_changes() {
+                // Apply annotations
+                let annotations = if arguments.operation == Operation::Annotate {
+                    let mut annotations = self
+                        .jmap
+                        .get_email_annotations(account_id, id)
+                        .await
+                        .map_err(|_| {
+                            StatusResponse::database_failure()
+                                .with_tag(response.tag.as_ref().unwrap())
+                        })?;
+                    for annotation in &arguments.annotations {
+                        let key =
+                            Property::_T(annotation.entry[ANNOTATION_PREFIX.len()..].to_string());
+                        if let Some(value) = &annotation.value {
+                            annotations.set(key, value.clone());
+                        } else {
+                            annotations.remove(&key);
+                        }
+                    }
+                    if let Err(err) = self.jmap.validate_annotations(&annotations) {
+                        return Err(StatusResponse::no(err.description.unwrap_or_default())
+                            .with_tag(response.tag.as_ref().unwrap())
+                            .with_code(ResponseCode::Limit));
+                    }
+                    Some(annotations)
+                } else {
+                    None
+                };
+
+                if keywords.has_changes() || annotations.is_some() {
                     // Convert keywords to flags
-                    let seen_changed = keywords
-                        .changed_tags()
-                        .any(|keyword| keyword == &Keyword::Seen);
+                    let is_seen = keywords.current().contains(&Keyword::Seen);
+                    let seen_changed = keywords.previous().contains(&Keyword::Seen) != is_seen;
                     let flags = if !arguments.is_silent {
                         keywords
                             .current()
@@ -266,7 +319,41 @@ impl<T: SessionStream> SessionData<T> {
                         .with_account_id(account_id)
                         .with_collection(Collection::Email)
                         .update_document(id);
-                    keywords.update_batch(&mut batch, Property::Keywords);
+                    let mut seen_mailboxes = Vec::new();
+                    if seen_changed {
+                        // Update the unseen counters of the mailboxes containing this message
+                        if let Some(mailboxes) = self
+                            .jmap
+                            .get_property::<HashedValue<Vec<UidMailbox>>>(
+                                account_id,
+                                Collection::Email,
+                                id,
+                                Property::MailboxIds,
+                            )
+                            .await
+                            .map_err(|_| {
+                                StatusResponse::database_failure()
+                                    .with_tag(response.tag.as_ref().unwrap())
+                            })?
+                        {
+                            batch
+                                .assert_value(Property::MailboxIds, &mailboxes)
+                                .update_mailbox_counters(
+                                    &mailboxes.inner,
+                                    !is_seen,
+                                    &mailboxes.inner,
+                                    is_seen,
+                                    0,
+                                );
+                            seen_mailboxes = mailboxes.inner;
+                        }
+                    }
+                    if keywords.has_changes() {
+                        keywords.update_batch(&mut batch, Property::Keywords);
+                    }
+                    if let Some(annotations) = &annotations {
+                        update_annotations(&mut batch, annotations);
+                    }
                     if changelog.change_id == u64::MAX {
                         changelog.change_id =
                             self.jmap.assign_change_id(account_id).await.map_err(|_| {
@@ -278,25 +365,8 @@ impl<T: SessionStream> SessionData<T> {
                     match self.jmap.write_batch(batch).await {
                         Ok(_) => {
                             // Set all current mailboxes as changed if the Seen tag changed
-                            if seen_changed {
-                                if let Some(mailboxes) = self
-                                    .jmap
-                                    .get_property::<Vec<UidMailbox>>(
-                                        account_id,
-                                        Collection::Email,
-                                        id,
-                                        Property::MailboxIds,
-                                    )
-                                    .await
-                                    .map_err(|_| {
-                                        StatusResponse::database_failure()
-                                            .with_tag(response.tag.as_ref().unwrap())
-                                    })?
-                                {
-                                    for mailbox_id in mailboxes {
-                                        changed_mailboxes.insert(mailbox_id.mailbox_id);
-                                    }
-                                }
+                            for mailbox_id in seen_mailboxes {
+                                changed_mailboxes.insert(mailbox_id.mailbox_id);
                             }
                             changelog.log_update(Collection::Email, Id::from_parts(thread_id, id));
 
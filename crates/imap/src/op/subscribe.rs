@@ -45,14 +45,14 @@ impl<T: SessionStream> Session<T> {
         match request.parse_subscribe(self.version) {
             Ok(arguments) => {
                 let data = self.state.session_data();
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     data.write_bytes(
                         data.subscribe_folder(arguments.tag, arguments.mailbox_name, is_subscribe)
                             .await
                             .into_bytes(),
                     )
                     .await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -50,7 +50,7 @@ impl<T: SessionStream> Session<T> {
             Ok(mut arguments) => {
                 let (data, mailbox) = self.state.mailbox_state();
 
-                tokio::spawn(async move {
+                tokio::spawn(utils::metrics::propagate(async move {
                     let tag = std::mem::take(&mut arguments.tag);
                     let bytes = match data.thread(arguments, mailbox, is_uid).await {
                         Ok(response) => StatusResponse::completed(command)
@@ -59,7 +59,7 @@ impl<T: SessionStream> Session<T> {
                         Err(response) => response.with_tag(tag).into_bytes(),
                     };
                     data.write_bytes(bytes).await;
-                });
+                }));
                 Ok(())
             }
             Err(response) => self.write_bytes(response.into_bytes()).await,
@@ -82,18 +82,29 @@ impl<T: SessionStream> SessionData<T> {
             self.synchronize_messages(&mailbox).await?;
         }
 
-        // Obtain threadIds for matching messages
+        // Obtain threadIds for matching messages, which in unified folders
+        // belong to different accounts
+        let ids = {
+            let state = mailbox.state.lock();
+            result_set
+                .results
+                .iter()
+                .filter_map(|id| {
+                    state
+                        .resolve_id(mailbox.id.account_id, id)
+                        .map(|(account_id, document_id)| (id, account_id, document_id))
+                })
+                .collect::<Vec<_>>()
+        };
         let thread_ids = self
             .jmap
             .store
             .get_values::<u32>(
-                result_set
-                    .results
-                    .iter()
-                    .map(|document_id| ValueKey {
-                        account_id: mailbox.id.account_id,
+                ids.iter()
+                    .map(|(_, account_id, document_id)| ValueKey {
+                        account_id: *account_id,
                         collection: Collection::Email.into(),
-                        document_id,
+                        document_id: *document_id,
                         class: ValueClass::Property(Property::ThreadId.into()),
                     })
                     .collect(),
@@ -109,13 +120,16 @@ impl<T: SessionStream> SessionData<T> {
             })?;
 
         // Group messages by thread
-        let mut threads: AHashMap<u32, Vec<u32>> = AHashMap::new();
+        let mut threads: AHashMap<(u32, u32), Vec<u32>> = AHashMap::new();
         let state = mailbox.state.lock();
-        for (document_id, thread_id) in result_set.results.into_iter().zip(thread_ids) {
+        for ((id, account_id, _), thread_id) in ids.into_iter().zip(thread_ids) {
             if let (Some(thread_id), Some((imap_id, _))) =
-                (thread_id, state.map_result_id(document_id, is_uid))
+                (thread_id, state.map_result_id(id, is_uid))
             {
-                threads.entry(thread_id).or_default().push(imap_id);
+                threads
+                    .entry((account_id, thread_id))
+                    .or_default()
+                    .push(imap_id);
             }
         }
 
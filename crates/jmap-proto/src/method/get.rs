@@ -53,6 +53,9 @@ pub enum RequestArguments {
     VacationResponse,
     Principal,
     Quota,
+    Settings,
+    FilterRule,
+    SavedSearch,
     Blob(blob::GetArguments),
 }
 
@@ -89,6 +92,9 @@ impl JsonObjectParser for GetRequest<RequestArguments> {
                 MethodObject::Principal => RequestArguments::Principal,
                 MethodObject::Blob => RequestArguments::Blob(Default::default()),
                 MethodObject::Quota => RequestArguments::Quota,
+                MethodObject::Settings => RequestArguments::Settings,
+                MethodObject::FilterRule => RequestArguments::FilterRule,
+                MethodObject::SavedSearch => RequestArguments::SavedSearch,
                 _ => {
                     return Err(Error::Method(MethodError::UnknownMethod(format!(
                         "{}/get",
@@ -63,6 +63,10 @@ pub struct QueryResponse {
     #[serde(rename = "ids")]
     pub ids: Vec<Id>,
 
+    #[serde(rename = "accountIds")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_ids: Option<Vec<Id>>,
+
     #[serde(rename = "total")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub total: Option<usize>,
@@ -110,6 +114,7 @@ pub enum Filter {
     SentBefore(UTCDate),
     SentAfter(UTCDate),
     InThread(Id),
+    InSavedSearch(Id),
     ParentId(Option<Id>),
     Role(Option<String>),
     HasAnyRole(bool),
@@ -416,6 +421,9 @@ pub fn parse_filter(parser: &mut Parser) -> crate::parser::Result<Vec<Filter>> {
                         (0x6461_6572_6854_6e69, _) => {
                             Filter::InThread(parser.next_token::<Id>()?.unwrap_string("inThread")?)
                         }
+                        (0x0068_6372_6165_5364_6576_6153_6e69, _) => Filter::InSavedSearch(
+                            parser.next_token::<Id>()?.unwrap_string("inSavedSearch")?,
+                        ),
                         (0x6449_746e_6572_6170, _) => Filter::ParentId(
                             parser
                                 .next_token::<Id>()?
@@ -640,6 +648,7 @@ impl Display for Filter {
             Filter::SentBefore(_) => "sentBefore",
             Filter::SentAfter(_) => "sentAfter",
             Filter::InThread(_) => "inThread",
+            Filter::InSavedSearch(_) => "inSavedSearch",
             Filter::ParentId(_) => "parentId",
             Filter::Role(_) => "role",
             Filter::HasAnyRole(_) => "hasAnyRole",
@@ -71,6 +71,10 @@ pub enum RequestArguments {
     PushSubscription,
     SieveScript(sieve::SetArguments),
     VacationResponse,
+    Settings,
+    FilterRule,
+    SavedSearch,
+    Thread,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize)]
@@ -130,6 +134,10 @@ impl JsonObjectParser for SetRequest<RequestArguments> {
                 }
                 MethodObject::PushSubscription => RequestArguments::PushSubscription,
                 MethodObject::VacationResponse => RequestArguments::VacationResponse,
+                MethodObject::Settings => RequestArguments::Settings,
+                MethodObject::FilterRule => RequestArguments::FilterRule,
+                MethodObject::SavedSearch => RequestArguments::SavedSearch,
+                MethodObject::Thread => RequestArguments::Thread,
                 MethodObject::SieveScript => RequestArguments::SieveScript(Default::default()),
                 _ => {
                     return Err(Error::Method(MethodError::UnknownMethod(format!(
@@ -236,6 +244,9 @@ impl JsonObjectParser for Object<SetValue> {
                     | Property::Disposition
                     | Property::Language
                     | Property::Location
+                    | Property::Locale
+                    | Property::DateFormat
+                    | Property::QuietHours
                     | Property::Cid
                     | Property::Role
                     | Property::PartId => parser
@@ -260,17 +271,24 @@ impl JsonObjectParser for Object<SetValue> {
                     Property::HasAttachment
                     | Property::IsSubscribed
                     | Property::IsEnabled
-                    | Property::IsActive => parser
+                    | Property::IsActive
+                    | Property::BulkFiling => parser
                         .next_token::<String>()?
                         .unwrap_bool_or_null("")?
                         .map(|bool| SetValue::Value(Value::Bool(bool)))
                         .unwrap_or(SetValue::Value(Value::Null)),
-                    Property::Size | Property::SortOrder | Property::Quota => parser
+                    Property::Size
+                    | Property::SortOrder
+                    | Property::Quota
+                    | Property::MinInterval => parser
                         .next_token::<String>()?
                         .unwrap_uint_or_null("")?
                         .map(|uint| SetValue::Value(Value::UnsignedInt(uint)))
                         .unwrap_or(SetValue::Value(Value::Null)),
-                    Property::ParentId | Property::EmailId | Property::IdentityId => parser
+                    Property::ParentId
+                    | Property::EmailId
+                    | Property::IdentityId
+                    | Property::BulkMailboxId => parser
                         .next_token::<MaybeReference<Id, String>>()?
                         .unwrap_string_or_null("")?
                         .map(SetValue::from)
@@ -299,6 +317,23 @@ impl JsonObjectParser for Object<SetValue> {
                             SetValue::Patch(key.patch)
                         }
                     }
+                    Property::Annotations => {
+                        if key.patch.is_empty() {
+                            SetValue::Value(Value::parse::<String, String>(
+                                parser.next_token()?,
+                                parser,
+                            )?)
+                        } else {
+                            key.patch.push(
+                                parser
+                                    .next_token::<String>()?
+                                    .unwrap_string_or_null("")?
+                                    .map(Value::Text)
+                                    .unwrap_or(Value::Null),
+                            );
+                            SetValue::Patch(key.patch)
+                        }
+                    }
 
                     Property::Acl => match key.patch.len() {
                         0 => {
@@ -342,11 +377,16 @@ impl JsonObjectParser for Object<SetValue> {
                     | Property::SubParts
                     | Property::To
                     | Property::UndoStatus
-                    | Property::Types => SetValue::Value(Value::parse::<ObjectProperty, String>(
+                    | Property::Types
+                    | Property::Conditions
+                    | Property::Actions => SetValue::Value(Value::parse::<ObjectProperty, String>(
                         parser.next_token()?,
                         parser,
                     )?),
-                    Property::Parameters => SetValue::Value(Value::parse::<String, String>(
+                    Property::Parameters
+                    | Property::AllowedSenders
+                    | Property::BlockedSenders
+                    | Property::Filter => SetValue::Value(Value::parse::<String, String>(
                         parser.next_token()?,
                         parser,
                     )?),
@@ -39,6 +39,7 @@ pub struct GetArguments {
 #[derive(Debug, Clone, Default)]
 pub struct QueryArguments {
     pub collapse_threads: Option<bool>,
+    pub unified_inbox: Option<bool>,
 }
 
 impl RequestPropertyParser for GetArguments {
@@ -84,13 +85,20 @@ impl RequestPropertyParser for QueryArguments {
         parser: &mut Parser,
         property: RequestProperty,
     ) -> crate::parser::Result<bool> {
-        if property.hash[0] == 0x0073_6461_6572_6854_6573_7061_6c6c_6f63 {
-            self.collapse_threads = parser
-                .next_token::<Ignore>()?
-                .unwrap_bool_or_null("collapseThreads")?;
-            Ok(true)
-        } else {
-            Ok(false)
+        match property.hash[0] {
+            0x0073_6461_6572_6854_6573_7061_6c6c_6f63 => {
+                self.collapse_threads = parser
+                    .next_token::<Ignore>()?
+                    .unwrap_bool_or_null("collapseThreads")?;
+            }
+            0x786f_626e_4964_6569_6669_6e75 => {
+                self.unified_inbox = parser
+                    .next_token::<Ignore>()?
+                    .unwrap_bool_or_null("unifiedInbox")?;
+            }
+            _ => return Ok(false),
         }
+
+        Ok(true)
     }
 }
@@ -48,6 +48,18 @@ pub enum Capability {
     Blob = 1 << 8,
     #[serde(rename(serialize = "urn:ietf:params:jmap:quota"))]
     Quota = 1 << 9,
+    #[serde(rename(serialize = "urn:stalwart:jmap:settings"))]
+    Settings = 1 << 10,
+    #[serde(rename(serialize = "urn:stalwart:jmap:filters"))]
+    FilterRules = 1 << 11,
+    #[serde(rename(serialize = "urn:stalwart:jmap:annotations"))]
+    Annotations = 1 << 12,
+    #[serde(rename(serialize = "urn:stalwart:jmap:savedsearches"))]
+    SavedSearches = 1 << 13,
+    #[serde(rename(serialize = "urn:stalwart:jmap:unified"))]
+    Unified = 1 << 14,
+    #[serde(rename(serialize = "urn:stalwart:jmap:conversations"))]
+    Conversations = 1 << 15,
 }
 
 impl JsonObjectParser for Capability {
@@ -55,7 +67,7 @@ impl JsonObjectParser for Capability {
     where
         Self: Sized,
     {
-        for ch in b"urn:ietf:params:jmap:" {
+        for ch in b"urn:" {
             if parser
                 .next_unescaped()?
                 .ok_or_else(|| parser.error_capability())?
@@ -65,6 +77,43 @@ impl JsonObjectParser for Capability {
             }
         }
 
+        // Vendor extensions are published under the "urn:stalwart:jmap:" namespace
+        let is_vendor = match parser
+            .next_unescaped()?
+            .ok_or_else(|| parser.error_capability())?
+        {
+            b'i' => false,
+            b's' => true,
+            _ => return Err(parser.error_capability()),
+        };
+        let prefix: &[u8] = if is_vendor {
+            b"talwart:jmap:"
+        } else {
+            b"etf:params:jmap:"
+        };
+        for ch in prefix {
+            if parser
+                .next_unescaped()?
+                .ok_or_else(|| parser.error_capability())?
+                != *ch
+            {
+                return Err(parser.error_capability());
+            }
+        }
+
+        if is_vendor {
+            return match u128::parse(parser) {
+                Ok(0x7367_6e69_7474_6573) => Ok(Capability::Settings),
+                Ok(0x0073_7265_746c_6966) => Ok(Capability::FilterRules),
+                Ok(0x0073_6e6f_6974_6174_6f6e_6e61) => Ok(Capability::Annotations),
+                Ok(0x0073_6568_6372_6165_7364_6576_6173) => Ok(Capability::SavedSearches),
+                Ok(0x0064_6569_6669_6e75) => Ok(Capability::Unified),
+                Ok(0x0073_6e6f_6974_6173_7265_766e_6f63) => Ok(Capability::Conversations),
+                Ok(_) | Err(Error::Method(_)) => Err(parser.error_capability()),
+                Err(err @ Error::Request(_)) => Err(err),
+            };
+        }
+
         match u128::parse(parser) {
             Ok(key) => match key {
                 0x6572_6f63 => Ok(Capability::Core),
@@ -46,6 +46,9 @@ pub enum MethodObject {
     SieveScript,
     Principal,
     Quota,
+    Settings,
+    FilterRule,
+    SavedSearch,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -113,6 +116,9 @@ impl JsonObjectParser for MethodName {
                 0x0074_7069_7263_5365_7665_6953 => MethodObject::SieveScript,
                 0x006c_6170_6963_6e69_7250 => MethodObject::Principal,
                 0x0061_746f_7551 => MethodObject::Quota,
+                0x7367_6e69_7474_6553 => MethodObject::Settings,
+                0x656c_7552_7265_746c_6946 => MethodObject::FilterRule,
+                0x0068_6372_6165_5364_6576_6153 => MethodObject::SavedSearch,
                 0x6572_6f43 => MethodObject::Core,
                 _ => return Err(parser.error_value()),
             },
@@ -166,6 +172,7 @@ impl MethodName {
 
             (MethodFunction::Get, MethodObject::Thread) => "Thread/get",
             (MethodFunction::Changes, MethodObject::Thread) => "Thread/changes",
+            (MethodFunction::Set, MethodObject::Thread) => "Thread/set",
 
             (MethodFunction::Get, MethodObject::Email) => "Email/get",
             (MethodFunction::Changes, MethodObject::Email) => "Email/changes",
@@ -207,6 +214,15 @@ impl MethodName {
             (MethodFunction::Query, MethodObject::Quota) => "Quota/query",
             (MethodFunction::QueryChanges, MethodObject::Quota) => "Quota/queryChanges",
 
+            (MethodFunction::Get, MethodObject::Settings) => "Settings/get",
+            (MethodFunction::Set, MethodObject::Settings) => "Settings/set",
+
+            (MethodFunction::Get, MethodObject::FilterRule) => "FilterRule/get",
+            (MethodFunction::Set, MethodObject::FilterRule) => "FilterRule/set",
+
+            (MethodFunction::Get, MethodObject::SavedSearch) => "SavedSearch/get",
+            (MethodFunction::Set, MethodObject::SavedSearch) => "SavedSearch/set",
+
             (MethodFunction::Get, MethodObject::Blob) => "Blob/get",
             (MethodFunction::Copy, MethodObject::Blob) => "Blob/copy",
             (MethodFunction::Lookup, MethodObject::Blob) => "Blob/lookup",
@@ -234,6 +250,9 @@ impl Display for MethodObject {
             MethodObject::Thread => "Thread",
             MethodObject::Email => "Email",
             MethodObject::Quota => "Quota",
+            MethodObject::Settings => "Settings",
+            MethodObject::FilterRule => "FilterRule",
+            MethodObject::SavedSearch => "SavedSearch",
         })
     }
 }
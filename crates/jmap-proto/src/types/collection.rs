@@ -38,7 +38,12 @@ pub enum Collection {
     SieveScript = 5,
     PushSubscription = 6,
     Principal = 7,
-    None = 8,
+    FilterRule = 8,
+    SavedSearch = 9,
+    DavCollection = 10,
+    DavResource = 11,
+    Snapshot = 12,
+    None = 13,
 }
 
 impl From<u8> for Collection {
@@ -52,6 +57,11 @@ impl From<u8> for Collection {
             5 => Collection::SieveScript,
             6 => Collection::PushSubscription,
             7 => Collection::Principal,
+            8 => Collection::FilterRule,
+            9 => Collection::SavedSearch,
+            10 => Collection::DavCollection,
+            11 => Collection::DavResource,
+            12 => Collection::Snapshot,
             _ => Collection::None,
         }
     }
@@ -68,6 +78,11 @@ impl From<u64> for Collection {
             5 => Collection::SieveScript,
             6 => Collection::PushSubscription,
             7 => Collection::Principal,
+            8 => Collection::FilterRule,
+            9 => Collection::SavedSearch,
+            10 => Collection::DavCollection,
+            11 => Collection::DavResource,
+            12 => Collection::Snapshot,
             _ => Collection::None,
         }
     }
@@ -113,6 +128,11 @@ impl Display for Collection {
             Collection::EmailSubmission => write!(f, "emailSubmission"),
             Collection::SieveScript => write!(f, "sieveScript"),
             Collection::Principal => write!(f, "principal"),
+            Collection::FilterRule => write!(f, "filterRule"),
+            Collection::SavedSearch => write!(f, "savedSearch"),
+            Collection::DavCollection => write!(f, "davCollection"),
+            Collection::DavResource => write!(f, "davResource"),
+            Collection::Snapshot => write!(f, "snapshot"),
             Collection::None => write!(f, ""),
         }
     }
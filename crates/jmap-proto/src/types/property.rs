@@ -136,6 +136,23 @@ pub enum Property {
     WarnLimit,
     SoftLimit,
     Scope,
+    BulkFiling,
+    BulkMailboxId,
+    AllowedSenders,
+    BlockedSenders,
+    Conditions,
+    Actions,
+    Locale,
+    DateFormat,
+    MinInterval,
+    QuietHours,
+    Status,
+    FailureCount,
+    LastFailureAt,
+    Annotations,
+    Filter,
+    Signature,
+    BodyStats,
     Digest(DigestProperty),
     Data(DataProperty),
     _T(String),
@@ -321,7 +338,7 @@ impl JsonObjectParser for SetProperty {
                         }
                     }
                 }
-                Property::Aliases => match String::parse(parser) {
+                Property::Aliases | Property::Annotations => match String::parse(parser) {
                     Ok(text) if !text.is_empty() => {
                         patch.push(Value::Text(text));
                     }
@@ -350,13 +367,19 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
     Some(match first_char {
         b'a' => match hash {
             0x6c63 => Property::Acl,
+            0x736e_6f69_7463 => Property::Actions,
             0x7365_7361_696c => Property::Aliases,
+            0x736e_6f69_7461_746f_6e6e => Property::Annotations,
+            0x0073_7265_646e_6553_6465_776f_6c6c => Property::AllowedSenders,
             0x7374_6e65_6d68_6361_7474 => Property::Attachments,
             _ => return None,
         },
         b'b' => match hash {
             0x6363 => Property::Bcc,
             0x0064_4962_6f6c => Property::BlobId,
+            0x0073_7265_646e_6553_6465_6b63_6f6c => Property::BlockedSenders,
+            0x0067_6e69_6c69_466b_6c75 => Property::BulkFiling,
+            0x6449_786f_626c_6961_4d6b_6c75 => Property::BulkMailboxId,
             0x6572_7574_6375_7274_5379_646f => Property::BodyStructure,
             0x0073_6575_6c61_5679_646f => Property::BodyValues,
             _ => return None,
@@ -366,6 +389,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x63 => Property::Cc,
             0x7465_7372_6168 => Property::Charset,
             0x6469 => Property::Cid,
+            0x0073_6e6f_6974_6964_6e6f => Property::Conditions,
             _ => return None,
         },
         b'd' => match hash {
@@ -375,6 +399,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x6e6f_6974_6973_6f70_7369 => Property::Disposition,
             0x0073_6449_626f_6c42_6e73 => Property::DsnBlobIds,
             0x0061_7461 => Property::Data(DataProperty::Default),
+            0x0074_616d_726f_4665_7461 => Property::DateFormat,
             _ => return None,
         },
         b'e' => match hash {
@@ -388,6 +413,8 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
         b'f' => match hash {
             0x006d_6f72 => Property::From,
             0x0065_7461_446d_6f72 => Property::FromDate,
+            0x0074_6e75_6f43_6572_756c_6961 => Property::FailureCount,
+            0x0072_6574_6c69 => Property::Filter,
             _ => return None,
         },
         b'h' => match hash {
@@ -414,6 +441,8 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
         b'l' => match hash {
             0x0065_6761_7567_6e61 => Property::Language,
             0x006e_6f69_7461_636f => Property::Location,
+            0x0065_6c61_636f => Property::Locale,
+            0x7441_6572_756c_6961_4674_7361 => Property::LastFailureAt,
             _ => return None,
         },
         b'm' => match hash {
@@ -423,6 +452,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x7372_6562_6d65 => Property::Members,
             0x6449_6567_6173_7365 => Property::MessageId,
             0x0073_7468_6769_5279 => Property::MyRights,
+            0x6c61_7672_6574_6e49_6e69 => Property::MinInterval,
             _ => return None,
         },
         b'n' => match hash {
@@ -438,6 +468,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
         },
         b'q' => match hash {
             0x6174_6f75 => Property::Quota,
+            0x0073_7275_6f48_7465_6975 => Property::QuietHours,
             _ => return None,
         },
         b'r' => match hash {
@@ -456,6 +487,8 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x7265_6472_4f74_726f => Property::SortOrder,
             0x7463_656a_6275 => Property::Subject,
             0x7374_7261_5062_7573 => Property::SubParts,
+            0x0073_7574_6174 => Property::Status,
+            0x6572_7574_616e_6769 => Property::Signature,
             _ => return None,
         },
         b't' => match hash {
@@ -898,6 +931,23 @@ impl Display for Property {
             Property::Used => write!(f, "used"),
             Property::HardLimit => write!(f, "hardLimit"),
             Property::Scope => write!(f, "scope"),
+            Property::BulkFiling => write!(f, "bulkFiling"),
+            Property::BulkMailboxId => write!(f, "bulkMailboxId"),
+            Property::AllowedSenders => write!(f, "allowedSenders"),
+            Property::BlockedSenders => write!(f, "blockedSenders"),
+            Property::Conditions => write!(f, "conditions"),
+            Property::Actions => write!(f, "actions"),
+            Property::Locale => write!(f, "locale"),
+            Property::DateFormat => write!(f, "dateFormat"),
+            Property::MinInterval => write!(f, "minInterval"),
+            Property::QuietHours => write!(f, "quietHours"),
+            Property::Status => write!(f, "status"),
+            Property::FailureCount => write!(f, "failureCount"),
+            Property::LastFailureAt => write!(f, "lastFailureAt"),
+            Property::Annotations => write!(f, "annotations"),
+            Property::Filter => write!(f, "filter"),
+            Property::Signature => write!(f, "signature"),
+            Property::BodyStats => write!(f, "bodyStats"),
             Property::WarnLimit => write!(f, "warnLimit"),
             Property::SoftLimit => write!(f, "softLimit"),
             Property::_T(s) => write!(f, "{s}"),
@@ -1080,6 +1130,23 @@ impl From<&Property> for u8 {
             Property::WarnLimit => 101,
             Property::SoftLimit => 102,
             Property::Scope => 103,
+            Property::BulkFiling => 104,
+            Property::BulkMailboxId => 105,
+            Property::AllowedSenders => 106,
+            Property::BlockedSenders => 107,
+            Property::Conditions => 108,
+            Property::Actions => 109,
+            Property::Locale => 110,
+            Property::DateFormat => 111,
+            Property::MinInterval => 112,
+            Property::QuietHours => 113,
+            Property::Status => 114,
+            Property::FailureCount => 115,
+            Property::LastFailureAt => 116,
+            Property::Annotations => 117,
+            Property::Filter => 118,
+            Property::Signature => 119,
+            Property::BodyStats => 120,
             Property::Digest(_) | Property::Data(_) => unreachable!("invalid property"),
         }
     }
@@ -1222,6 +1289,23 @@ impl SerializeInto for Property {
             Property::WarnLimit => 101,
             Property::SoftLimit => 102,
             Property::Scope => 103,
+            Property::BulkFiling => 104,
+            Property::BulkMailboxId => 105,
+            Property::AllowedSenders => 106,
+            Property::BlockedSenders => 107,
+            Property::Conditions => 108,
+            Property::Actions => 109,
+            Property::Locale => 110,
+            Property::DateFormat => 111,
+            Property::MinInterval => 112,
+            Property::QuietHours => 113,
+            Property::Status => 114,
+            Property::FailureCount => 115,
+            Property::LastFailureAt => 116,
+            Property::Annotations => 117,
+            Property::Filter => 118,
+            Property::Signature => 119,
+            Property::BodyStats => 120,
             Property::Digest(_) | Property::Data(_) => {
                 unreachable!("Property::Digest and Property::Data are not serializable")
             }
@@ -1340,6 +1424,23 @@ impl DeserializeFrom for Property {
             101 => Some(Property::WarnLimit),
             102 => Some(Property::SoftLimit),
             103 => Some(Property::Scope),
+            104 => Some(Property::BulkFiling),
+            105 => Some(Property::BulkMailboxId),
+            106 => Some(Property::AllowedSenders),
+            107 => Some(Property::BlockedSenders),
+            108 => Some(Property::Conditions),
+            109 => Some(Property::Actions),
+            110 => Some(Property::Locale),
+            111 => Some(Property::DateFormat),
+            112 => Some(Property::MinInterval),
+            113 => Some(Property::QuietHours),
+            114 => Some(Property::Status),
+            115 => Some(Property::FailureCount),
+            116 => Some(Property::LastFailureAt),
+            117 => Some(Property::Annotations),
+            118 => Some(Property::Filter),
+            119 => Some(Property::Signature),
+            120 => Some(Property::BodyStats),
             _ => None,
         }
     }
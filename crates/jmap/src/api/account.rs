@@ -0,0 +1,521 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::{
+    backend::internal::{
+        lookup::DirectoryStore, manage::ManageDirectory, PrincipalField, PrincipalUpdate,
+        PrincipalValue,
+    },
+    core::{
+        scram::ScramVerifier,
+        secret::{app_password_name, app_password_secret, hash_secret_argon2, is_app_password},
+    },
+    DirectoryInner, Principal, QueryBy,
+};
+use hyper::{Method, StatusCode};
+use jmap_proto::{
+    error::{method::MethodError, request::RequestError},
+    method::{
+        get::{self, GetRequest},
+        set::{self, SetRequest, SetResponse},
+    },
+    object::Object,
+    types::{
+        date::UTCDate,
+        id::Id,
+        property::Property,
+        value::{SetValue, Value},
+    },
+};
+use serde_json::json;
+use store::{
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    write::log::ChangeLogBuilder,
+};
+use utils::map::vec_map::VecMap;
+
+use crate::{
+    auth::AccessToken,
+    identity::set::sanitize_email,
+    settings::{setting_key, FORWARD_KEEP_COPY, FORWARD_TO},
+    JMAP,
+};
+
+use super::{
+    admin::map_directory_error, http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse,
+};
+
+const MAX_APP_PASSWORDS: usize = 20;
+const MAX_FORWARD_ADDRESSES: usize = 10;
+
+#[derive(Debug, serde::Deserialize)]
+struct PasswordChange {
+    #[serde(rename = "currentPassword")]
+    current_password: String,
+    #[serde(rename = "newPassword")]
+    new_password: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AppPasswordCreate {
+    name: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Forwarding {
+    addresses: Vec<String>,
+    #[serde(rename = "keepCopy", default = "default_keep_copy")]
+    keep_copy: bool,
+}
+
+fn default_keep_copy() -> bool {
+    true
+}
+
+impl JMAP {
+    /// Self-service endpoints available to any authenticated user, acting on
+    /// the account of the access token.
+    pub async fn handle_account_request(
+        &self,
+        req: &HttpRequest,
+        body: Option<Vec<u8>>,
+        access_token: &AccessToken,
+    ) -> HttpResponse {
+        let mut path = req.uri().path().split('/');
+        path.next();
+        path.next();
+        let account_id = access_token.primary_id();
+        let request = (path.next().unwrap_or(""), path.next(), req.method());
+
+        // Accounts that must change their password can only do that
+        if access_token.must_change_password
+            && !matches!(request, ("password", None, &Method::POST))
+        {
+            return RequestError::blank(
+                StatusCode::FORBIDDEN.as_u16(),
+                "Password change required",
+                "The account password must be changed before it can be used.",
+            )
+            .into_http_response();
+        }
+
+        match request {
+            ("password", None, &Method::POST) => {
+                let request = match parse_body::<PasswordChange>(body) {
+                    Ok(request) if !request.new_password.is_empty() => request,
+                    Ok(_) => return invalid_parameters("The new password cannot be empty."),
+                    Err(response) => return response,
+                };
+                let principal = match self.managed_principal(account_id).await {
+                    Ok(principal) => principal,
+                    Err(response) => return response,
+                };
+
+                // Verify the current password
+                if !principal.verify_password(&request.current_password).await {
+                    return RequestError::blank(
+                        StatusCode::FORBIDDEN.as_u16(),
+                        "Invalid password",
+                        "The current password is incorrect.",
+                    )
+                    .into_http_response();
+                }
+                if let Some(response) = self
+                    .check_breached_secrets(&principal.name, &[request.new_password.clone()])
+                    .await
+                {
+                    return response;
+                }
+
+                // Replace the password and its SCRAM verifier, keeping application passwords
+                let Some(hashed_secret) = hash_secret_argon2(&request.new_password).await else {
+                    return RequestError::internal_server_error().into_http_response();
+                };
+                let mut secrets = vec![hashed_secret];
+                secrets.extend(
+                    principal
+                        .secrets
+                        .into_iter()
+                        .filter(|secret| is_app_password(secret)),
+                );
+                secrets.push(ScramVerifier::new(&request.new_password).to_string());
+                if let Err(response) = self.update_secrets(account_id, secrets).await {
+                    return response;
+                }
+
+                // Tokens are bound to the password hash, drop any cached sessions
+                self.clear_cached_sessions(account_id);
+
+                JsonResponse::new(json!({
+                    "data": [],
+                }))
+                .into_http_response()
+            }
+            ("app-passwords", None, &Method::GET) => {
+                let principal = match self.managed_principal(account_id).await {
+                    Ok(principal) => principal,
+                    Err(response) => return response,
+                };
+
+                JsonResponse::new(json!({
+                    "data": principal.app_password_names().collect::<Vec<_>>(),
+                }))
+                .into_http_response()
+            }
+            ("app-passwords", None, &Method::POST) => {
+                let request = match parse_body::<AppPasswordCreate>(body) {
+                    Ok(request)
+                        if !request.name.is_empty()
+                            && request.name.len() <= 64
+                            && !request.name.contains('$') =>
+                    {
+                        request
+                    }
+                    Ok(_) => return invalid_parameters("Invalid application password name."),
+                    Err(response) => return response,
+                };
+                let mut principal = match self.managed_principal(account_id).await {
+                    Ok(principal) => principal,
+                    Err(response) => return response,
+                };
+                let names = principal.app_password_names().collect::<Vec<_>>();
+                if names.contains(&request.name.as_str()) {
+                    return invalid_parameters("An application password with this name exists.");
+                } else if names.len() >= MAX_APP_PASSWORDS {
+                    return invalid_parameters("Too many application passwords.");
+                }
+
+                let password = thread_rng()
+                    .sample_iter(Alphanumeric)
+                    .take(24)
+                    .map(char::from)
+                    .collect::<String>();
+                let Some(secret) = app_password_secret(&request.name, &password).await else {
+                    return RequestError::internal_server_error().into_http_response();
+                };
+                principal.secrets.push(secret);
+                if let Err(response) = self.update_secrets(account_id, principal.secrets).await {
+                    return response;
+                }
+
+                JsonResponse::new(json!({
+                    "data": {
+                        "name": request.name,
+                        "password": password,
+                    },
+                }))
+                .into_http_response()
+            }
+            ("app-passwords", Some(name), &Method::DELETE) => {
+                let principal = match self.managed_principal(account_id).await {
+                    Ok(principal) => principal,
+                    Err(response) => return response,
+                };
+                let num_secrets = principal.secrets.len();
+                let secrets = principal
+                    .secrets
+                    .into_iter()
+                    .filter(|secret| app_password_name(secret) != Some(name))
+                    .collect::<Vec<_>>();
+                if secrets.len() == num_secrets {
+                    return RequestError::not_found().into_http_response();
+                }
+                if let Err(response) = self.update_secrets(account_id, secrets).await {
+                    return response;
+                }
+                self.clear_cached_sessions(account_id);
+
+                JsonResponse::new(json!({
+                    "data": [],
+                }))
+                .into_http_response()
+            }
+            ("sessions", None, &Method::DELETE) => match self.revoke_sessions(account_id).await {
+                Ok(_) => JsonResponse::new(json!({
+                    "data": [],
+                }))
+                .into_http_response(),
+                Err(_) => RequestError::internal_server_error().into_http_response(),
+            },
+            ("forwarding", None, &Method::GET) => match self.account_forwarding(account_id).await {
+                Ok((addresses, keep_copy)) => JsonResponse::new(json!({
+                    "data": Forwarding {
+                        addresses,
+                        keep_copy,
+                    },
+                }))
+                .into_http_response(),
+                Err(_) => RequestError::internal_server_error().into_http_response(),
+            },
+            ("forwarding", None, &Method::PUT) => {
+                let request = match parse_body::<Forwarding>(body) {
+                    Ok(request) if request.addresses.len() <= MAX_FORWARD_ADDRESSES => request,
+                    Ok(_) => return invalid_parameters("Too many forwarding addresses."),
+                    Err(response) => return response,
+                };
+                let mut addresses = Vec::with_capacity(request.addresses.len());
+                for address in &request.addresses {
+                    match sanitize_email(address) {
+                        Some(address) if !addresses.contains(&address) => {
+                            addresses.push(address);
+                        }
+                        Some(_) => (),
+                        None => {
+                            return invalid_parameters(format!("Invalid address {address:?}."));
+                        }
+                    }
+                }
+
+                match self
+                    .set_account_forwarding(account_id, addresses, request.keep_copy)
+                    .await
+                {
+                    Ok(_) => JsonResponse::new(json!({
+                        "data": [],
+                    }))
+                    .into_http_response(),
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            ("vacation", None, &Method::GET) => {
+                match self
+                    .vacation_response_get(GetRequest {
+                        account_id: Id::from(account_id),
+                        ids: None,
+                        properties: None,
+                        arguments: get::RequestArguments::VacationResponse,
+                    })
+                    .await
+                {
+                    Ok(response) => JsonResponse::new(json!({
+                        "data": response.list.into_iter().next(),
+                    }))
+                    .into_http_response(),
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            ("vacation", None, &Method::PUT) => {
+                let changes = match body
+                    .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+                    .and_then(|value| parse_vacation(value).ok())
+                {
+                    Some(changes) => changes,
+                    None => return invalid_parameters("Failed to parse vacation response."),
+                };
+
+                match self.set_vacation_response(account_id, changes).await {
+                    Ok(response) => {
+                        if let Some(err) = response
+                            .not_created
+                            .values()
+                            .chain(response.not_updated.values())
+                            .next()
+                        {
+                            invalid_parameters(
+                                err.description
+                                    .as_deref()
+                                    .unwrap_or("Invalid vacation response.")
+                                    .to_string(),
+                            )
+                        } else {
+                            JsonResponse::new(json!({
+                                "data": [],
+                            }))
+                            .into_http_response()
+                        }
+                    }
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            _ => RequestError::not_found().into_http_response(),
+        }
+    }
+
+    /// Returns the principal of an account whose secrets are managed by the
+    /// internal directory.
+    async fn managed_principal(&self, account_id: u32) -> Result<Principal<u32>, HttpResponse> {
+        if !matches!(self.directory.store, DirectoryInner::Internal(_)) {
+            return Err(RequestError::blank(
+                StatusCode::BAD_REQUEST.as_u16(),
+                "Unsupported",
+                "Credentials are managed by an external directory.",
+            )
+            .into_http_response());
+        }
+
+        match self.store.query(QueryBy::Id(account_id), false).await {
+            Ok(Some(principal)) => Ok(principal),
+            Ok(None) => Err(RequestError::not_found().into_http_response()),
+            Err(err) => Err(map_directory_error(err)),
+        }
+    }
+
+    async fn update_secrets(
+        &self,
+        account_id: u32,
+        secrets: Vec<String>,
+    ) -> Result<(), HttpResponse> {
+        self.store
+            .update_account(
+                QueryBy::Id(account_id),
+                vec![PrincipalUpdate::set(
+                    PrincipalField::Secrets,
+                    PrincipalValue::StringList(secrets),
+                )],
+            )
+            .await
+            .map(|_| ())
+            .map_err(map_directory_error)
+    }
+
+    async fn set_account_forwarding(
+        &self,
+        account_id: u32,
+        addresses: Vec<String>,
+        keep_copy: bool,
+    ) -> Result<(), MethodError> {
+        let mut settings = self
+            .get_account_settings(account_id)
+            .await?
+            .unwrap_or_default();
+        if !addresses.is_empty() {
+            settings.set(
+                setting_key(FORWARD_TO),
+                Value::List(addresses.into_iter().map(Value::Text).collect()),
+            );
+            settings.set(setting_key(FORWARD_KEEP_COPY), keep_copy);
+        } else {
+            settings.remove(&setting_key(FORWARD_TO));
+            settings.remove(&setting_key(FORWARD_KEEP_COPY));
+        }
+        self.set_account_settings(account_id, &settings).await?;
+
+        // Forwarding is applied by the filter rules Sieve script
+        let mut changes = ChangeLogBuilder::new();
+        self.filter_rules_compile(account_id, &mut changes).await?;
+        if !changes.is_empty() {
+            self.commit_changes(account_id, changes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_vacation_response(
+        &self,
+        account_id: u32,
+        changes: Object<SetValue>,
+    ) -> Result<SetResponse, MethodError> {
+        let (create, update) = if self
+            .get_vacation_sieve_script_id(account_id)
+            .await?
+            .is_some()
+        {
+            let mut update = VecMap::with_capacity(1);
+            update.append(Id::singleton(), changes);
+            (None, Some(update))
+        } else {
+            let mut create = VecMap::with_capacity(1);
+            create.append("vacation".to_string(), changes);
+            (Some(create), None)
+        };
+
+        self.vacation_response_set(SetRequest {
+            account_id: Id::from(account_id),
+            if_in_state: None,
+            create,
+            update,
+            destroy: None,
+            arguments: set::RequestArguments::VacationResponse,
+        })
+        .await
+    }
+}
+
+fn parse_vacation(value: serde_json::Value) -> Result<Object<SetValue>, ()> {
+    let fields = match value {
+        serde_json::Value::Object(fields) => fields,
+        _ => return Err(()),
+    };
+    let mut properties = VecMap::with_capacity(fields.len());
+
+    for (key, value) in fields {
+        let (property, value) = match (key.as_str(), value) {
+            ("isEnabled", serde_json::Value::Bool(value)) => {
+                (Property::IsEnabled, Value::Bool(value))
+            }
+            ("subject", serde_json::Value::String(value)) => {
+                (Property::Subject, Value::Text(value))
+            }
+            ("textBody", serde_json::Value::String(value)) => {
+                (Property::TextBody, Value::Text(value))
+            }
+            ("htmlBody", serde_json::Value::String(value)) => {
+                (Property::HtmlBody, Value::Text(value))
+            }
+            ("fromDate" | "toDate", serde_json::Value::String(value)) => (
+                if key == "fromDate" {
+                    Property::FromDate
+                } else {
+                    Property::ToDate
+                },
+                Value::Date(UTCDate::from_timestamp(
+                    mail_parser::DateTime::parse_rfc3339(&value)
+                        .ok_or(())?
+                        .to_timestamp(),
+                )),
+            ),
+            (
+                "isEnabled" | "subject" | "textBody" | "htmlBody" | "fromDate" | "toDate",
+                serde_json::Value::Null,
+            ) => (
+                match key.as_str() {
+                    "isEnabled" => Property::IsEnabled,
+                    "subject" => Property::Subject,
+                    "textBody" => Property::TextBody,
+                    "htmlBody" => Property::HtmlBody,
+                    "fromDate" => Property::FromDate,
+                    _ => Property::ToDate,
+                },
+                Value::Null,
+            ),
+            _ => return Err(()),
+        };
+        properties.append(property, SetValue::Value(value));
+    }
+
+    Ok(Object { properties })
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: Option<Vec<u8>>) -> Result<T, HttpResponse> {
+    body.and_then(|body| serde_json::from_slice::<T>(&body).ok())
+        .ok_or_else(|| invalid_parameters("Failed to deserialize request."))
+}
+
+fn invalid_parameters(details: impl Into<String>) -> HttpResponse {
+    RequestError::blank(
+        StatusCode::BAD_REQUEST.as_u16(),
+        "Invalid parameters",
+        details.into(),
+    )
+    .into_http_response()
+}
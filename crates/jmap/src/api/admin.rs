@@ -21,19 +21,44 @@
  * for more details.
 */
 
+use std::{collections::BTreeMap, sync::atomic::Ordering, time::Duration};
+
 use directory::{
     backend::internal::{lookup::DirectoryStore, manage::ManageDirectory, PrincipalUpdate},
+    core::breach::BreachAction,
     DirectoryError, ManagementError, Principal, QueryBy, Type,
 };
 use http_body_util::combinators::BoxBody;
 use hyper::{body::Bytes, Method, StatusCode};
-use jmap_proto::error::request::RequestError;
+use jmap_proto::{
+    error::{method::MethodError, request::RequestError},
+    types::property::Property,
+};
 use serde_json::json;
-use utils::config::ConfigKey;
+use store::dispatch::blob::BlobHealth;
+use utils::{
+    config::{utils::ParseValue, ConfigKey},
+    locale::format_utc_offset,
+    metrics::{advisor::index_advisor, latency_metrics, slow::slow_query_log},
+    BlobHash,
+};
 
-use crate::{services::housekeeper, JMAP};
+use crate::{
+    blob::DownloadResponse,
+    email::import::BulkImportRequest,
+    services::{
+        billing::{UsageRollup, SECONDS_PER_PERIOD},
+        housekeeper,
+        invalidate::Invalidation,
+        purge::ROLLUP_LOCK_KEY,
+    },
+    settings::{parse_locale_value, setting_key, settings_locale, SEARCH_LANGUAGE},
+    JMAP,
+};
 
-use super::{http::ToHttpResponse, HttpRequest, JsonResponse};
+use super::{http::ToHttpResponse, HttpRequest, JsonResponse, MetricsResponse};
+
+pub const DEFAULT_RENAME_GRACE_PERIOD: u64 = 30 * 86400;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct PrincipalResponse {
@@ -61,13 +86,30 @@ impl JMAP {
         let mut path = req.uri().path().split('/');
         path.next();
         path.next();
+        let (path_1, path_2) = (path.next().unwrap_or(""), path.next());
+
+        // Changes applied on this node are announced to the rest of the cluster
+        let invalidation = match (path_1, path_2, req.method()) {
+            ("principal" | "domain" | "rename", _, method) if method != Method::GET => {
+                Some(Invalidation::Directory)
+            }
+            ("reload", Some("config"), _) => Some(Invalidation::Config),
+            _ => None,
+        };
 
-        match (path.next().unwrap_or(""), path.next(), req.method()) {
+        let response = match (path_1, path_2, req.method()) {
             ("principal", None, &Method::POST) => {
                 // Create principal
                 if let Some(principal) =
                     body.and_then(|body| serde_json::from_slice::<Principal<String>>(&body).ok())
                 {
+                    if let Some(response) = self
+                        .check_breached_secrets(&principal.name, &principal.secrets)
+                        .await
+                    {
+                        return response;
+                    }
+
                     match self.store.create_account(principal).await {
                         Ok(account_id) => JsonResponse::new(json!({
                             "data": account_id,
@@ -134,6 +176,103 @@ impl JMAP {
                     Err(err) => map_directory_error(err),
                 }
             }
+            ("rename", Some(item @ ("account" | "domain")), &Method::POST) => {
+                // Rename an account's primary address or an entire domain
+                let mut name = None;
+                let mut new_name = None;
+                let mut grace_period = DEFAULT_RENAME_GRACE_PERIOD;
+
+                if let Some(query) = req.uri().query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "name" => {
+                                name = value.into_owned().into();
+                            }
+                            "to" => {
+                                new_name = value.into_owned().into();
+                            }
+                            "grace" => match Duration::parse_value("grace", &value) {
+                                Ok(duration) => {
+                                    grace_period = duration.as_secs();
+                                }
+                                Err(_) => {
+                                    return RequestError::blank(
+                                        StatusCode::BAD_REQUEST.as_u16(),
+                                        "Invalid parameters",
+                                        "Failed to parse grace period",
+                                    )
+                                    .into_http_response();
+                                }
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+
+                let (name, new_name) = if let (Some(name), Some(new_name)) = (name, new_name) {
+                    (name, new_name)
+                } else {
+                    return RequestError::blank(
+                        StatusCode::BAD_REQUEST.as_u16(),
+                        "Invalid parameters",
+                        "Missing 'name' or 'to' parameters",
+                    )
+                    .into_http_response();
+                };
+
+                let result = if item == "account" {
+                    self.store
+                        .rename_account(QueryBy::Name(&name), &new_name, grace_period)
+                        .await
+                } else {
+                    self.store
+                        .rename_domain(&name, &new_name, grace_period)
+                        .await
+                };
+
+                match result {
+                    Ok(renamed) => match self.apply_renamed_addresses(&renamed).await {
+                        Ok(_) => JsonResponse::new(json!({
+                            "data": renamed
+                                .into_iter()
+                                .map(|r| json!({
+                                    "accountId": r.account_id,
+                                    "old": r.old,
+                                    "new": r.new,
+                                }))
+                                .collect::<Vec<_>>(),
+                        }))
+                        .into_http_response(),
+                        Err(_) => RequestError::internal_server_error().into_http_response(),
+                    },
+                    Err(err) => map_directory_error(err),
+                }
+            }
+            ("lockout", Some(login), &Method::DELETE) => {
+                // Lift account lockout
+                match self.directory.blocked_ips.unlock_account(login).await {
+                    Ok(was_locked) => JsonResponse::new(json!({
+                        "data": was_locked,
+                    }))
+                    .into_http_response(),
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        "Unlock account failed",
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("password", Some("schemes"), &Method::GET) => {
+                // Number of accounts per password hashing scheme
+                match self.store.count_password_schemes().await {
+                    Ok(schemes) => JsonResponse::new(json!({
+                        "data": schemes,
+                    }))
+                    .into_http_response(),
+                    Err(err) => map_directory_error(err),
+                }
+            }
             ("principal", Some(name), method) => {
                 // Fetch, update or delete principal
                 let account_id = match self.store.get_account_id(name).await {
@@ -194,28 +333,20 @@ impl JMAP {
                         }
                     }
                     Method::DELETE => {
-                        // Remove FTS index
-                        if let Err(err) = self.fts_store.remove_all(account_id).await {
-                            tracing::warn!(
-                                context = "fts",
-                                event = "error",
-                                reason = ?err,
-                                "Failed to remove FTS index"
-                            );
-                            return RequestError::blank(
-                                StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                                "Failed to remove FTS index",
-                                "Contact the administrator if this problem persists",
-                            )
-                            .into_http_response();
-                        }
-
-                        // Delete account
+                        // Delete account, data is purged by the housekeeper
                         match self.store.delete_account(QueryBy::Id(account_id)).await {
-                            Ok(_) => JsonResponse::new(json!({
-                                "data": [],
-                            }))
-                            .into_http_response(),
+                            Ok(_) => {
+                                self.access_tokens.remove(&account_id);
+                                let _ = self
+                                    .housekeeper_tx
+                                    .send(housekeeper::Event::PurgeAccounts)
+                                    .await;
+
+                                JsonResponse::new(json!({
+                                    "data": [],
+                                }))
+                                .into_http_response()
+                            }
                             Err(err) => map_directory_error(err),
                         }
                     }
@@ -223,6 +354,15 @@ impl JMAP {
                         if let Some(changes) = body.and_then(|body| {
                             serde_json::from_slice::<Vec<PrincipalUpdate>>(&body).ok()
                         }) {
+                            for secrets in changes.iter().filter_map(|change| change.new_secrets())
+                            {
+                                if let Some(response) =
+                                    self.check_breached_secrets(name, secrets).await
+                                {
+                                    return response;
+                                }
+                            }
+
                             match self
                                 .store
                                 .update_account(QueryBy::Id(account_id), changes)
@@ -246,6 +386,121 @@ impl JMAP {
                     _ => RequestError::not_found().into_http_response(),
                 }
             }
+            ("settings", Some(name), method @ (&Method::GET | &Method::PATCH)) => {
+                // Fetch or update an account's locale preferences
+                let account_id = match self.store.get_account_id(name).await {
+                    Ok(Some(account_id)) => account_id,
+                    Ok(None) => {
+                        return RequestError::blank(
+                            StatusCode::NOT_FOUND.as_u16(),
+                            "Not found",
+                            "Account not found.",
+                        )
+                        .into_http_response();
+                    }
+                    Err(err) => {
+                        return map_directory_error(err);
+                    }
+                };
+                let mut settings = match self.get_account_settings(account_id).await {
+                    Ok(settings) => settings.unwrap_or_default(),
+                    Err(_) => return RequestError::internal_server_error().into_http_response(),
+                };
+
+                if *method == Method::PATCH {
+                    let changes = if let Some(changes) = body.and_then(|body| {
+                        serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&body)
+                            .ok()
+                    }) {
+                        changes
+                    } else {
+                        return RequestError::blank(
+                            StatusCode::BAD_REQUEST.as_u16(),
+                            "Invalid parameters",
+                            "Failed to deserialize modify request",
+                        )
+                        .into_http_response();
+                    };
+
+                    let timezone = settings.get(&Property::Timezone).clone();
+                    for (key, value) in changes {
+                        let property = match key.as_str() {
+                            "locale" => Property::Locale,
+                            "timezone" => Property::Timezone,
+                            "dateFormat" => Property::DateFormat,
+                            "searchLanguage" => setting_key(SEARCH_LANGUAGE),
+                            _ => {
+                                return RequestError::blank(
+                                    StatusCode::BAD_REQUEST.as_u16(),
+                                    "Invalid parameters",
+                                    format!("Unknown setting {key:?}."),
+                                )
+                                .into_http_response();
+                            }
+                        };
+                        match value {
+                            serde_json::Value::Null => {
+                                settings.remove(&property);
+                            }
+                            serde_json::Value::String(value) => {
+                                if let Some(value) = parse_locale_value(&property, &value) {
+                                    settings.set(property, value);
+                                } else {
+                                    return RequestError::blank(
+                                        StatusCode::BAD_REQUEST.as_u16(),
+                                        "Invalid parameters",
+                                        format!("Invalid value {value:?} for setting {key:?}."),
+                                    )
+                                    .into_http_response();
+                                }
+                            }
+                            _ => {
+                                return RequestError::blank(
+                                    StatusCode::BAD_REQUEST.as_u16(),
+                                    "Invalid parameters",
+                                    format!("Invalid value for setting {key:?}."),
+                                )
+                                .into_http_response();
+                            }
+                        }
+                    }
+
+                    if self
+                        .set_account_settings(account_id, &settings)
+                        .await
+                        .is_err()
+                    {
+                        return RequestError::internal_server_error().into_http_response();
+                    }
+                    if settings.get(&Property::Timezone) != &timezone {
+                        self.update_push_subscriptions(account_id).await;
+                    }
+                }
+
+                let locale = settings_locale(&settings);
+                JsonResponse::new(json!({
+                    "data": {
+                        "locale": settings.get(&Property::Locale).as_string().unwrap_or("en"),
+                        "timezone": format_utc_offset(locale.utc_offset),
+                        "dateFormat": locale.date_format.as_str(),
+                        "searchLanguage": settings.get(&setting_key(SEARCH_LANGUAGE)).as_string(),
+                    },
+                }))
+                .into_http_response()
+            }
+            ("push", Some("stats"), &Method::GET) => {
+                // Push delivery counters since startup
+                let stats = &self.push_stats;
+                JsonResponse::new(json!({
+                    "data": {
+                        "delivered": stats.delivered.load(Ordering::Relaxed),
+                        "failed": stats.failed.load(Ordering::Relaxed),
+                        "throttled": stats.throttled.load(Ordering::Relaxed),
+                        "expired": stats.expired.load(Ordering::Relaxed),
+                    },
+                }))
+                .into_http_response()
+            }
             ("domain", None, &Method::GET) => {
                 // List domains
                 let mut filter = None;
@@ -334,17 +589,647 @@ impl JMAP {
                     .into_http_response(),
                 }
             }
-            ("reload", Some("config"), &Method::GET) => {
-                let _ = self
-                    .housekeeper_tx
-                    .send(housekeeper::Event::ReloadConfig)
+            ("store", Some("repair"), &Method::GET) => {
+                // Recalculate the cached mailbox counters of one or all accounts
+                let account_ids = match path.next().filter(|name| !name.is_empty()) {
+                    Some(name) => match self.store.get_account_id(name).await {
+                        Ok(Some(account_id)) => vec![account_id],
+                        Ok(None) => {
+                            return RequestError::blank(
+                                StatusCode::NOT_FOUND.as_u16(),
+                                "Not found",
+                                "Account not found.",
+                            )
+                            .into_http_response();
+                        }
+                        Err(err) => {
+                            return map_directory_error(err);
+                        }
+                    },
+                    None => match self.store.list_indexed_accounts().await {
+                        Ok(account_ids) => account_ids,
+                        Err(err) => {
+                            return RequestError::blank(
+                                StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                                "Repair failed",
+                                err.to_string(),
+                            )
+                            .into_http_response();
+                        }
+                    },
+                };
+
+                let mut repaired = 0;
+                for account_id in account_ids {
+                    match self.mailbox_counters_repair(account_id).await {
+                        Ok(count) => {
+                            repaired += count;
+                        }
+                        Err(_) => {
+                            return RequestError::internal_server_error().into_http_response();
+                        }
+                    }
+                }
+
+                JsonResponse::new(json!({
+                    "data": {
+                        "mailboxes": repaired,
+                    },
+                }))
+                .into_http_response()
+            }
+            ("store", Some("reindex"), &Method::GET) => {
+                // Rebuild the full-text index of one or all accounts
+                let account_ids = match path.next().filter(|name| !name.is_empty()) {
+                    Some(name) => match self.store.get_account_id(name).await {
+                        Ok(Some(account_id)) => vec![account_id],
+                        Ok(None) => {
+                            return RequestError::blank(
+                                StatusCode::NOT_FOUND.as_u16(),
+                                "Not found",
+                                "Account not found.",
+                            )
+                            .into_http_response();
+                        }
+                        Err(err) => {
+                            return map_directory_error(err);
+                        }
+                    },
+                    None => match self.store.list_indexed_accounts().await {
+                        Ok(account_ids) => account_ids,
+                        Err(err) => {
+                            return RequestError::blank(
+                                StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                                "Reindex failed",
+                                err.to_string(),
+                            )
+                            .into_http_response();
+                        }
+                    },
+                };
+
+                let mut queued = 0;
+                for account_id in account_ids {
+                    match self.fts_reindex(account_id).await {
+                        Ok(count) => {
+                            queued += count;
+                        }
+                        Err(_) => {
+                            return RequestError::internal_server_error().into_http_response();
+                        }
+                    }
+                }
+
+                JsonResponse::new(json!({
+                    "data": {
+                        "messages": queued,
+                    },
+                }))
+                .into_http_response()
+            }
+            ("store", Some("backfill"), &Method::GET) => {
+                // Compute the part statistics of messages stored before they were recorded
+                let account_ids = match path.next().filter(|name| !name.is_empty()) {
+                    Some(name) => match self.store.get_account_id(name).await {
+                        Ok(Some(account_id)) => vec![account_id],
+                        Ok(None) => {
+                            return RequestError::blank(
+                                StatusCode::NOT_FOUND.as_u16(),
+                                "Not found",
+                                "Account not found.",
+                            )
+                            .into_http_response();
+                        }
+                        Err(err) => {
+                            return map_directory_error(err);
+                        }
+                    },
+                    None => match self.store.list_indexed_accounts().await {
+                        Ok(account_ids) => account_ids,
+                        Err(err) => {
+                            return RequestError::blank(
+                                StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                                "Backfill failed",
+                                err.to_string(),
+                            )
+                            .into_http_response();
+                        }
+                    },
+                };
+
+                let mut updated = 0;
+                for account_id in account_ids {
+                    match self.backfill_body_stats(account_id).await {
+                        Ok(count) => {
+                            updated += count;
+                        }
+                        Err(_) => {
+                            return RequestError::internal_server_error().into_http_response();
+                        }
+                    }
+                }
+
+                JsonResponse::new(json!({
+                    "data": {
+                        "messages": updated,
+                    },
+                }))
+                .into_http_response()
+            }
+            ("store", Some("scrub"), &Method::POST) => {
+                // Verify the next batch of blobs, repairing or quarantining damaged ones
+                if !self.claim_scrub_lock().await {
+                    return RequestError::blank(
+                        StatusCode::CONFLICT.as_u16(),
+                        "Blob verification in progress",
+                        "Another blob verification run is in progress, try again later.",
+                    )
+                    .into_http_response();
+                }
+                let result = self
+                    .store
+                    .scrub_blobs(
+                        &self.blob_store,
+                        self.blob_replica.as_ref(),
+                        self.config.scrub_batch_size,
+                        Duration::ZERO,
+                    )
                     .await;
+                self.release_scrub_lock().await;
+
+                match result {
+                    Ok(scrub) => JsonResponse::new(json!({
+                        "data": {
+                            "verified": scrub.verified,
+                            "repaired": scrub
+                                .repaired
+                                .iter()
+                                .map(blob_hash_hex)
+                                .collect::<Vec<_>>(),
+                            "quarantined": scrub
+                                .quarantined
+                                .iter()
+                                .map(|(hash, health)| json!({
+                                    "blobId": blob_hash_hex(hash),
+                                    "status": if *health == BlobHealth::Missing {
+                                        "missing"
+                                    } else {
+                                        "corrupt"
+                                    },
+                                }))
+                                .collect::<Vec<_>>(),
+                            "complete": scrub.complete,
+                        },
+                    }))
+                    .into_http_response(),
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        "Blob verification failed",
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("store", Some("quarantine"), &Method::GET) => {
+                match self.store.list_quarantined_blobs().await {
+                    Ok(blobs) => JsonResponse::new(json!({
+                        "data": blobs
+                            .iter()
+                            .map(|(hash, detected)| json!({
+                                "blobId": blob_hash_hex(hash),
+                                "detected": detected,
+                            }))
+                            .collect::<Vec<_>>(),
+                    }))
+                    .into_http_response(),
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        "Database error",
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("store", Some(action @ ("backup" | "restore")), &Method::POST) => {
+                // Export or import every subspace and blob to or from a file on the server
+                let path = req.uri().query().and_then(|query| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "path")
+                        .map(|(_, value)| value.into_owned())
+                });
+                let path = match path.filter(|path| !path.is_empty()) {
+                    Some(path) => path,
+                    None => {
+                        return RequestError::invalid_parameters().into_http_response();
+                    }
+                };
+
+                let result = if action == "backup" {
+                    match std::fs::File::create(&path) {
+                        Ok(file) => {
+                            self.store
+                                .export_all(&self.blob_store, &mut std::io::BufWriter::new(file))
+                                .await
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                } else {
+                    match std::fs::File::open(&path) {
+                        Ok(file) => {
+                            self.store
+                                .import_all(&self.blob_store, &mut std::io::BufReader::new(file))
+                                .await
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                };
+
+                match result {
+                    Ok(stats) => JsonResponse::new(json!({
+                        "data": {
+                            "values": stats.values,
+                            "counters": stats.counters,
+                            "indexes": stats.indexes,
+                            "bitmaps": stats.bitmaps,
+                            "logs": stats.logs,
+                            "blobs": stats.blobs,
+                        },
+                    }))
+                    .into_http_response(),
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        if action == "backup" {
+                            "Backup failed"
+                        } else {
+                            "Restore failed"
+                        },
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("import", Some(name), &Method::POST) => {
+                // Import messages keeping their received date, keywords and mailboxes
+                let request = if let Some(request) =
+                    body.and_then(|body| serde_json::from_slice::<BulkImportRequest>(&body).ok())
+                {
+                    request
+                } else {
+                    return RequestError::blank(
+                        StatusCode::BAD_REQUEST.as_u16(),
+                        "Invalid parameters",
+                        "Failed to deserialize import request",
+                    )
+                    .into_http_response();
+                };
+                let principal = match self.directory.query(QueryBy::Name(name), false).await {
+                    Ok(Some(principal)) => principal,
+                    Ok(None) => {
+                        return RequestError::blank(
+                            StatusCode::NOT_FOUND.as_u16(),
+                            "Not found",
+                            "Account not found.",
+                        )
+                        .into_http_response();
+                    }
+                    Err(err) => {
+                        return map_directory_error(err);
+                    }
+                };
+
+                match self
+                    .email_import_bulk(principal.id, principal.quota as i64, request)
+                    .await
+                {
+                    Ok(response) => JsonResponse::new(json!({
+                        "data": response,
+                    }))
+                    .into_http_response(),
+                    Err(MethodError::InvalidArguments(reason)) => RequestError::blank(
+                        StatusCode::BAD_REQUEST.as_u16(),
+                        "Invalid parameters",
+                        reason,
+                    )
+                    .into_http_response(),
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            ("snapshot", Some(name), method) => {
+                let principal = match self.directory.query(QueryBy::Name(name), false).await {
+                    Ok(Some(principal)) => principal,
+                    Ok(None) => {
+                        return RequestError::blank(
+                            StatusCode::NOT_FOUND.as_u16(),
+                            "Not found",
+                            "Account not found.",
+                        )
+                        .into_http_response();
+                    }
+                    Err(err) => {
+                        return map_directory_error(err);
+                    }
+                };
+                let snapshot_id = match path.next().filter(|id| !id.is_empty()) {
+                    Some(snapshot_id) => match snapshot_id.parse::<u32>() {
+                        Ok(snapshot_id) => Some(snapshot_id),
+                        Err(_) => return RequestError::not_found().into_http_response(),
+                    },
+                    None => None,
+                };
+
+                match (snapshot_id, method) {
+                    (None, &Method::GET) => {
+                        // List snapshots
+                        match self.snapshot_list(principal.id).await {
+                            Ok(mut snapshots) => {
+                                snapshots.sort_unstable_by_key(|(id, info)| (info.created_at, *id));
+                                JsonResponse::new(json!({
+                                    "data": snapshots
+                                        .into_iter()
+                                        .map(|(id, info)| json!({
+                                            "id": id,
+                                            "createdAt": mail_parser::DateTime::from_timestamp(
+                                                info.created_at as i64
+                                            )
+                                            .to_rfc3339(),
+                                            "mailboxes": info.mailboxes,
+                                            "messages": info.messages,
+                                        }))
+                                        .collect::<Vec<_>>(),
+                                }))
+                                .into_http_response()
+                            }
+                            Err(_) => RequestError::internal_server_error().into_http_response(),
+                        }
+                    }
+                    (None, &Method::POST) => {
+                        // Create snapshot
+                        match self.snapshot_create(principal.id).await {
+                            Ok(snapshot_id) => JsonResponse::new(json!({
+                                "data": snapshot_id,
+                            }))
+                            .into_http_response(),
+                            Err(_) => RequestError::internal_server_error().into_http_response(),
+                        }
+                    }
+                    (Some(snapshot_id), &Method::POST) => {
+                        // Restore account to the snapshot
+                        match self
+                            .snapshot_restore(principal.id, principal.quota as i64, snapshot_id)
+                            .await
+                        {
+                            Ok(Some(response)) => JsonResponse::new(json!({
+                                "data": response,
+                            }))
+                            .into_http_response(),
+                            Ok(None) => RequestError::not_found().into_http_response(),
+                            Err(_) => RequestError::internal_server_error().into_http_response(),
+                        }
+                    }
+                    (Some(snapshot_id), &Method::DELETE) => {
+                        // Delete snapshot, unreferenced blobs are purged later
+                        match self.snapshot_delete(principal.id, snapshot_id).await {
+                            Ok(true) => JsonResponse::new(json!({
+                                "data": [],
+                            }))
+                            .into_http_response(),
+                            Ok(false) => RequestError::not_found().into_http_response(),
+                            Err(_) => RequestError::internal_server_error().into_http_response(),
+                        }
+                    }
+                    _ => RequestError::not_found().into_http_response(),
+                }
+            }
+            ("cluster", Some("placement"), method) => {
+                let cluster = match &self.config.cluster {
+                    Some(cluster) => cluster,
+                    None => return RequestError::not_found().into_http_response(),
+                };
+                let account_id = match self
+                    .store
+                    .get_account_id(path.next().unwrap_or_default())
+                    .await
+                {
+                    Ok(Some(account_id)) => account_id,
+                    Ok(None) => {
+                        return RequestError::blank(
+                            StatusCode::NOT_FOUND.as_u16(),
+                            "Not found",
+                            "Account not found.",
+                        )
+                        .into_http_response();
+                    }
+                    Err(err) => {
+                        return map_directory_error(err);
+                    }
+                };
+
+                let result = match (path.next().filter(|id| !id.is_empty()), method) {
+                    (None, &Method::GET) => self.account_placement(account_id).await,
+                    (Some(node_id), &Method::POST) => {
+                        // Move the account to another node
+                        match node_id.parse::<u64>() {
+                            Ok(node_id) => match self.move_account(account_id, node_id).await {
+                                Ok(Some(placement)) => Ok(Some(placement)),
+                                Ok(None) => {
+                                    return RequestError::blank(
+                                        StatusCode::BAD_REQUEST.as_u16(),
+                                        "Invalid parameters",
+                                        format!("Node {node_id} is not part of the cluster."),
+                                    )
+                                    .into_http_response();
+                                }
+                                Err(err) => Err(err),
+                            },
+                            Err(_) => return RequestError::not_found().into_http_response(),
+                        }
+                    }
+                    (None, &Method::DELETE) => {
+                        // Return the account to its default node
+                        self.clear_account_placement(account_id).await.map(|_| None)
+                    }
+                    _ => return RequestError::not_found().into_http_response(),
+                };
+
+                match result {
+                    Ok(placement) => JsonResponse::new(json!({
+                        "data": {
+                            "node": cluster.placed_node(account_id, placement.as_ref()).id,
+                            "migratingFrom": placement
+                                .filter(|placement| cluster.is_migrating(placement))
+                                .and_then(|placement| placement.migration)
+                                .map(|migration| migration.from_node_id),
+                        },
+                    }))
+                    .into_http_response(),
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            ("cluster", Some("rebalance"), &Method::POST) => {
+                // Spread accounts evenly across the nodes of the cluster
+                if self.config.cluster.is_none() {
+                    return RequestError::not_found().into_http_response();
+                }
+                match self.cluster_rebalance().await {
+                    Ok(moved) => JsonResponse::new(json!({
+                        "data": {
+                            "moved": moved,
+                        },
+                    }))
+                    .into_http_response(),
+                    Err(_) => RequestError::internal_server_error().into_http_response(),
+                }
+            }
+            ("metrics", None, &Method::GET) => {
+                MetricsResponse::new(latency_metrics().to_prometheus()).into_http_response()
+            }
+            ("slow-queries", None, &Method::GET) => JsonResponse::new(json!({
+                "data": slow_query_log().entries(),
+            }))
+            .into_http_response(),
+            ("slow-queries", None, &Method::DELETE) => {
+                slow_query_log().clear();
+
+                JsonResponse::new(json!({
+                    "data": [],
+                }))
+                .into_http_response()
+            }
+            ("index-advisor", None, &Method::GET) => JsonResponse::new(json!({
+                "data": index_advisor().report(),
+            }))
+            .into_http_response(),
+            ("index-advisor", None, &Method::DELETE) => {
+                index_advisor().clear();
 
                 JsonResponse::new(json!({
                     "data": [],
                 }))
                 .into_http_response()
             }
+            ("billing", Some("rollup"), &Method::POST) => {
+                // Share the scheduled rollup's lock so counters are drained only once
+                if !self.claim_maintenance_lock(ROLLUP_LOCK_KEY).await {
+                    return RequestError::blank(
+                        StatusCode::CONFLICT.as_u16(),
+                        "Usage rollup in progress",
+                        "Another usage rollup is in progress, try again later.",
+                    )
+                    .into_http_response();
+                }
+                let result = self.billing_rollup().await;
+                self.release_maintenance_lock(ROLLUP_LOCK_KEY).await;
+
+                match result {
+                    Ok(rollups) => JsonResponse::new(json!({
+                        "data": rollups,
+                    }))
+                    .into_http_response(),
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        "Usage rollup failed",
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("billing", Some("export"), &Method::GET) => {
+                let mut from_period = 0;
+                let mut to_period = u32::MAX;
+                let mut domain = None;
+                let mut by_domain = true;
+                let mut as_csv = false;
+
+                if let Some(query) = req.uri().query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "from" | "to" => {
+                                if let Some(period) = parse_period(value.as_ref()) {
+                                    if key == "from" {
+                                        from_period = period;
+                                    } else {
+                                        to_period = period;
+                                    }
+                                } else {
+                                    return RequestError::blank(
+                                        StatusCode::BAD_REQUEST.as_u16(),
+                                        "Invalid parameters",
+                                        format!("Invalid date {value:?}."),
+                                    )
+                                    .into_http_response();
+                                }
+                            }
+                            "domain" => {
+                                domain = value.into_owned().into();
+                            }
+                            "group" => {
+                                by_domain = value != "account";
+                            }
+                            "format" => {
+                                as_csv = value == "csv";
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                match self
+                    .billing_export(from_period, to_period, domain.as_deref())
+                    .await
+                {
+                    Ok(rollups) => {
+                        let rollups = if by_domain {
+                            UsageRollup::group_by_domain(rollups)
+                        } else {
+                            rollups
+                        };
+
+                        if as_csv {
+                            DownloadResponse {
+                                filename: "usage.csv".to_string(),
+                                content_type: "text/csv; charset=utf-8".to_string(),
+                                blob: UsageRollup::to_csv(&rollups).into_bytes(),
+                            }
+                            .into_http_response()
+                        } else {
+                            JsonResponse::new(json!({
+                                "data": rollups,
+                            }))
+                            .into_http_response()
+                        }
+                    }
+                    Err(err) => RequestError::blank(
+                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        "Usage export failed",
+                        err.to_string(),
+                    )
+                    .into_http_response(),
+                }
+            }
+            ("reload", Some("config"), &Method::GET) => match self.reload_config().await {
+                Ok(result) if result.is_success() => JsonResponse::new(json!({
+                    "data": result.applied,
+                }))
+                .into_http_response(),
+                Ok(result) => JsonResponse::with_status(
+                    StatusCode::BAD_REQUEST,
+                    json!({
+                        "error": "Configuration reload failed",
+                        "data": result.applied,
+                        "failed": result
+                            .failed
+                            .into_iter()
+                            .collect::<BTreeMap<_, _>>(),
+                    }),
+                )
+                .into_http_response(),
+                Err(err) => RequestError::blank(
+                    StatusCode::BAD_REQUEST.as_u16(),
+                    "Configuration reload failed",
+                    err,
+                )
+                .into_http_response(),
+            },
             ("reload", Some("certificates"), &Method::GET) => {
                 let _ = self
                     .housekeeper_tx
@@ -423,17 +1308,57 @@ impl JMAP {
                     .into_http_response()
                 }
             }
-            (path_1 @ ("queue" | "report"), Some(path_2), &Method::GET) => {
+            (
+                path_1 @ ("queue" | "report" | "policy" | "dlp" | "monitor" | "quarantine"
+                | "shadow" | "transcript"),
+                Some(path_2),
+                &Method::GET,
+            ) => {
                 self.smtp
                     .handle_manage_request(req.uri(), req.method(), path_1, path_2)
                     .await
             }
             _ => RequestError::not_found().into_http_response(),
+        };
+
+        if let Some(invalidation) = invalidation.filter(|_| response.status().is_success()) {
+            self.broadcast_invalidation(invalidation).await;
+        }
+
+        response
+    }
+}
+
+impl JMAP {
+    pub(crate) async fn check_breached_secrets(
+        &self,
+        name: &str,
+        secrets: &[String],
+    ) -> Option<hyper::Response<BoxBody<Bytes, hyper::Error>>> {
+        match self.directory.check_breached_secrets(secrets).await? {
+            BreachAction::Warn => {
+                tracing::warn!(
+                    context = "breach-check",
+                    event = "password-change",
+                    account = name,
+                    "New password found in breached passwords corpus."
+                );
+                None
+            }
+            BreachAction::ForceChange => RequestError::blank(
+                StatusCode::BAD_REQUEST.as_u16(),
+                "Breached password",
+                "The password has appeared in a data breach, please choose a different one.",
+            )
+            .into_http_response()
+            .into(),
         }
     }
 }
 
-fn map_directory_error(err: DirectoryError) -> hyper::Response<BoxBody<Bytes, hyper::Error>> {
+pub(crate) fn map_directory_error(
+    err: DirectoryError,
+) -> hyper::Response<BoxBody<Bytes, hyper::Error>> {
     match err {
         DirectoryError::Management(err) => {
             let response = match err {
@@ -479,6 +1404,22 @@ fn map_directory_error(err: DirectoryError) -> hyper::Response<BoxBody<Bytes, hy
     }
 }
 
+fn blob_hash_hex(hash: &BlobHash) -> String {
+    hash.as_slice()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn parse_period(value: &str) -> Option<u32> {
+    if value.len() == 10 {
+        mail_parser::DateTime::parse_rfc3339(&format!("{value}T00:00:00Z"))
+    } else {
+        mail_parser::DateTime::parse_rfc3339(value)
+    }
+    .map(|dt| (dt.to_timestamp() as u64 / SECONDS_PER_PERIOD) as u32)
+}
+
 impl From<Principal<String>> for PrincipalResponse {
     fn from(principal: Principal<String>) -> Self {
         PrincipalResponse {
@@ -21,10 +21,27 @@
  * for more details.
 */
 
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
+use arc_swap::ArcSwap;
+use base64::{engine::general_purpose, Engine};
+use jmap_proto::types::keyword::Keyword;
 use nlp::language::Language;
-use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
+use store::{
+    fts::FtsLanguageConfig,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+};
+use utils::{
+    config::reload::{ReloadResult, ReloadableConfig},
+    metrics::{advisor::index_advisor, slow::slow_query_log},
+};
+
+use crate::{
+    auth::oidc::OidcProvider,
+    email::importance::ImportanceClassifier,
+    services::{cluster::ClusterHandoff, invalidate::CacheInvalidation},
+    RateLimits, JMAP,
+};
 
 use super::session::BaseCapabilities;
 
@@ -37,6 +54,7 @@ impl crate::Config {
                     .unwrap_or("en"),
             )
             .unwrap_or(Language::English),
+            fts_languages: FtsLanguageConfig::default(),
             query_max_results: settings
                 .property("jmap.protocol.query.max-results")?
                 .unwrap_or(5000),
@@ -67,6 +85,12 @@ impl crate::Config {
             upload_max_concurrent: settings
                 .property("jmap.protocol.upload.max-concurrent")?
                 .unwrap_or(4),
+            download_max_concurrent: settings
+                .property("jmap.protocol.download.max-concurrent")?
+                .unwrap_or(4),
+            download_max_concurrent_ip: settings
+                .property("jmap.protocol.download.max-concurrent-ip")?
+                .unwrap_or(8),
             upload_tmp_quota_size: settings
                 .property("jmap.protocol.upload.quota.size")?
                 .unwrap_or(50000000),
@@ -76,6 +100,15 @@ impl crate::Config {
             upload_tmp_ttl: settings
                 .property_or_static::<Duration>("jmap.protocol.upload.ttl", "1h")?
                 .as_secs(),
+            upload_resumable_max_size: settings
+                .property("jmap.protocol.upload.resumable.max-size")?
+                .unwrap_or(1000000000),
+            upload_resumable_chunk_size: settings
+                .property("jmap.protocol.upload.resumable.max-chunk-size")?
+                .unwrap_or(10000000),
+            upload_resumable_ttl: settings
+                .property_or_static::<Duration>("jmap.protocol.upload.resumable.ttl", "1d")?
+                .as_secs(),
             mailbox_max_depth: settings.property("jmap.mailbox.max-depth")?.unwrap_or(10),
             mailbox_name_max_len: settings
                 .property("jmap.mailbox.max-name-length")?
@@ -86,6 +119,9 @@ impl crate::Config {
             mail_max_size: settings
                 .property("jmap.email.max-size")?
                 .unwrap_or(75000000),
+            mail_max_messages: settings
+                .property("jmap.email.quota.max-messages")?
+                .unwrap_or(0),
             mail_parse_max_items: settings
                 .property("jmap.email.parse.max-items")?
                 .unwrap_or(10),
@@ -99,11 +135,21 @@ impl crate::Config {
             session_cache_ttl: settings
                 .property("jmap.session.cache.ttl")?
                 .unwrap_or(Duration::from_secs(3600)),
-            rate_authenticated: settings
-                .property_or_static("jmap.rate-limit.account", "1000/1m")?,
-            rate_authenticate_req: settings
-                .property_or_static("jmap.rate-limit.authentication", "10/1m")?,
-            rate_anonymous: settings.property_or_static("jmap.rate-limit.anonymous", "100/1m")?,
+            orphans_batch_size: settings
+                .property("jmap.maintenance.orphans.batch-size")?
+                .unwrap_or(500),
+            orphans_pause: settings
+                .property_or_static("jmap.maintenance.orphans.pause", "100ms")?,
+            changes_retention: settings
+                .property_or_static("jmap.maintenance.changes.retention", "30d")?,
+            scrub_batch_size: settings
+                .property("jmap.maintenance.scrub.batch-size")?
+                .unwrap_or(1000),
+            scrub_pause: settings.property_or_static("jmap.maintenance.scrub.pause", "50ms")?,
+            snapshots_max: settings.property_or_static("jmap.snapshot.max-snapshots", "10")?,
+            cluster: ClusterHandoff::parse(settings)?,
+            invalidation: CacheInvalidation::parse(settings)?,
+            rate_limits: ArcSwap::from_pointee(RateLimits::parse(settings)?),
             rate_use_forwarded: settings
                 .property("jmap.rate-limit.use-forwarded")?
                 .unwrap_or(false),
@@ -132,6 +178,7 @@ impl crate::Config {
                 .property_or_static::<Duration>("oauth.expiry.refresh-token-renew", "4d")?
                 .as_secs(),
             oauth_max_auth_attempts: settings.property_or_static("oauth.auth.max-attempts", "3")?,
+            oidc: OidcProvider::parse(settings)?,
             event_source_throttle: settings
                 .property_or_static("jmap.event-source.throttle", "1s")?,
             web_socket_throttle: settings.property_or_static("jmap.web-socket.throttle", "1s")?,
@@ -141,6 +188,8 @@ impl crate::Config {
             principal_allow_lookups: settings
                 .property("jmap.principal.allow-lookups")?
                 .unwrap_or(true),
+            principal_enumeration_score: settings
+                .property_or_static("jmap.principal.enumeration.score", "5.0")?,
             encrypt: settings.property_or_static("storage.encryption.enable", "true")?,
             encrypt_append: settings.property_or_static("storage.encryption.append", "false")?,
             spam_header: settings.value("storage.spam.header").and_then(|v| {
@@ -151,6 +200,58 @@ impl crate::Config {
                     )
                 })
             }),
+            importance: if settings.property_or_static("jmap.email.importance.enable", "false")? {
+                Some(ImportanceClassifier {
+                    keyword: Keyword::from(
+                        settings
+                            .value("jmap.email.importance.keyword")
+                            .unwrap_or("$important")
+                            .to_string(),
+                    ),
+                    min_score: settings
+                        .property_or_static("jmap.email.importance.min-score", "2")?,
+                    weight_thread: settings
+                        .property_or_static("jmap.email.importance.weight.thread", "2")?,
+                    weight_replied: settings
+                        .property_or_static("jmap.email.importance.weight.replied", "2")?,
+                    weight_history: settings
+                        .property_or_static("jmap.email.importance.weight.history", "1")?,
+                    weight_directory: settings
+                        .property_or_static("jmap.email.importance.weight.directory", "1")?,
+                })
+            } else {
+                None
+            },
+            bulk_mailbox: settings
+                .value("jmap.email.bulk.mailbox")
+                .unwrap_or("Newsletters")
+                .to_string(),
+            sender_list_max_entries: settings
+                .property_or_static("jmap.email.sender-list.max-entries", "1000")?,
+            filter_rules_max: settings.property_or_static("jmap.filter-rules.max-rules", "100")?,
+            annotations_max_entries: settings
+                .property_or_static("jmap.email.annotations.max-entries", "32")?,
+            annotations_max_size: settings
+                .property_or_static("jmap.email.annotations.max-size", "4096")?,
+            saved_searches_max: settings
+                .property_or_static("jmap.saved-searches.max-searches", "100")?,
+            unified_max_accounts: settings
+                .property_or_static("jmap.email.unified.max-accounts", "20")?,
+            signature_verify: settings.property_or_static("jmap.email.signature.verify", "true")?,
+            signature_header: settings
+                .property_or_static("jmap.email.signature.add-header", "true")?,
+            metrics_enable: settings
+                .property_or_static("global.metrics.prometheus.enable", "false")?,
+            metrics_auth: match (
+                settings.value("global.metrics.prometheus.auth.username"),
+                settings.value("global.metrics.prometheus.auth.secret"),
+            ) {
+                (Some(username), Some(secret)) => Some(format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(format!("{username}:{secret}"))
+                )),
+                _ => None,
+            },
             http_headers: settings
                 .values("jmap.http.headers")
                 .map(|(_, v)| {
@@ -178,7 +279,87 @@ impl crate::Config {
                 })
                 .collect::<Result<Vec<_>, String>>()?,
         };
+
+        // Per-language stemming and stop word settings
+        for code in settings.sub_keys("storage.fts.language", "") {
+            let language = Language::from_iso_639(code).ok_or_else(|| {
+                format!("Invalid language code {code:?} in property \"storage.fts.language\".")
+            })?;
+            if !settings
+                .property_or_static::<bool>(("storage.fts.language", code, "stemming"), "true")?
+            {
+                config.fts_languages.disable_stemming.insert(language);
+            }
+            if !settings.property_or_static::<bool>(
+                ("storage.fts.language", code, "remove-stop-words"),
+                "true",
+            )? {
+                config.fts_languages.keep_stop_words.insert(language);
+            }
+        }
         config.add_capabilites(settings);
         Ok(config)
     }
 }
+
+impl RateLimits {
+    pub fn parse(settings: &utils::config::Config) -> Result<Self, String> {
+        Ok(RateLimits {
+            authenticated: settings.property_or_static("jmap.rate-limit.account", "1000/1m")?,
+            authenticate_req: settings
+                .property_or_static("jmap.rate-limit.authentication", "10/1m")?,
+            anonymous: settings.property_or_static("jmap.rate-limit.anonymous", "100/1m")?,
+            download_account: settings.property("jmap.rate-limit.download.account")?,
+            download_ip: settings.property("jmap.rate-limit.download.ip")?,
+            principal_lookup: settings.property("jmap.rate-limit.principal-lookup")?,
+            api_cheap: settings.property("jmap.rate-limit.api.cheap")?,
+            api_expensive: settings.property("jmap.rate-limit.api.expensive")?,
+            management: settings.property("jmap.rate-limit.management")?,
+        })
+    }
+}
+
+impl ReloadableConfig for JMAP {
+    fn reload(&self, config: &utils::config::Config) -> utils::config::Result<()> {
+        self.config
+            .rate_limits
+            .store(Arc::new(RateLimits::parse(config)?));
+        slow_query_log().configure(config)?;
+        index_advisor().configure(config)?;
+
+        Ok(())
+    }
+}
+
+impl JMAP {
+    /// Reads the configuration file again, applies the settings stored in the
+    /// database on top of it and reloads every component that supports it.
+    /// An error is only returned when the configuration could not be read,
+    /// components that fail to reload are logged and listed in the result.
+    pub async fn reload_config(&self) -> utils::config::Result<ReloadResult> {
+        let config = self.config_reloader.build(
+            self.store
+                .config_list("")
+                .await
+                .map_err(|err| format!("Failed to read configuration: {err}"))?,
+        )?;
+
+        let jmap_result = ReloadableConfig::reload(self, &config);
+        let mut result = self.config_reloader.reload(&config);
+        match jmap_result {
+            Ok(_) => result.applied.insert(0, "jmap"),
+            Err(err) => result.failed.insert(0, ("jmap", err)),
+        }
+        for (target, err) in &result.failed {
+            tracing::error!(
+                context = "config",
+                event = "error",
+                target = target,
+                error = %err,
+                "Failed to reload configuration."
+            );
+        }
+
+        Ok(result)
+    }
+}
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{net::IpAddr, sync::Arc};
+use std::{net::IpAddr, ops::Range, sync::Arc};
 
 use http_body_util::{BodyExt, Full};
 use hyper::{
@@ -38,12 +38,16 @@ use jmap_proto::{
     response::Response,
     types::{blob::BlobId, id::Id},
 };
-
-use utils::listener::{ServerInstance, SessionData, SessionManager, SessionStream};
+use store::write::BILLING_BANDWIDTH_BYTES;
+use utils::{
+    constant_time_eq,
+    listener::{ServerInstance, SessionData, SessionManager, SessionStream},
+    metrics::server::server_metrics,
+};
 
 use crate::{
     auth::{oauth::OAuthMetadata, AccessToken},
-    blob::{DownloadResponse, UploadResponse},
+    blob::{DownloadResponse, ResumableUploadResponse, UploadResponse},
     services::state,
     websocket::upgrade::upgrade_websocket_connection,
     JMAP,
@@ -51,6 +55,7 @@ use crate::{
 
 use super::{
     session::Session, HtmlResponse, HttpRequest, HttpResponse, JmapSessionManager, JsonResponse,
+    MetricsResponse,
 };
 
 pub async fn parse_jmap_request(
@@ -80,48 +85,155 @@ pub async fn parse_jmap_request(
 
             match (path.next().unwrap_or(""), req.method()) {
                 ("", &Method::POST) => {
-                    return match fetch_body(&mut req, jmap.config.request_max_size, &access_token)
-                        .await
-                        .ok_or_else(|| RequestError::limit(RequestLimitError::SizeRequest))
-                        .and_then(|bytes| {
-                            Request::parse(
-                                &bytes,
-                                jmap.config.request_max_calls,
-                                jmap.config.request_max_size,
-                            )
-                        }) {
+                    let bytes =
+                        match fetch_body(&mut req, jmap.config.request_max_size, &access_token)
+                            .await
+                        {
+                            Some(bytes) => bytes,
+                            None => {
+                                return RequestError::limit(RequestLimitError::SizeRequest)
+                                    .into_http_response()
+                            }
+                        };
+
+                    // Accounts placed on another node are served by that node
+                    if let Some(response) =
+                        jmap.proxy_request(&req, &access_token, Some(&bytes)).await
+                    {
+                        return response;
+                    }
+
+                    return match Request::parse(
+                        &bytes,
+                        jmap.config.request_max_calls,
+                        jmap.config.request_max_size,
+                    ) {
                         Ok(request) => {
                             //let _ = println!("<- {}", String::from_utf8_lossy(&bytes));
 
-                            match jmap.handle_request(request, access_token, &instance).await {
-                                Ok(response) => response.into_http_response(),
-                                Err(err) => err.into_http_response(),
+                            // Limit API usage, keeping expensive methods in check
+                            let rate_limit =
+                                match jmap.is_api_request_allowed(&access_token, &request).await {
+                                    Ok(rate_limit) => rate_limit,
+                                    Err(err) => return err.into_http_response(),
+                                };
+                            let mut response = if rate_limit.map_or(false, |r| r.exceeded) {
+                                RequestError::too_many_requests().into_http_response()
+                            } else {
+                                match jmap.handle_request(request, access_token, &instance).await {
+                                    Ok(response) => response.into_http_response(),
+                                    Err(err) => err.into_http_response(),
+                                }
+                            };
+                            if let Some(rate_limit) = rate_limit {
+                                rate_limit.add_headers(&mut response);
                             }
+                            response
                         }
                         Err(err) => err.into_http_response(),
                     };
                 }
                 ("download", &Method::GET) => {
-                    if let (Some(_), Some(blob_id), Some(name)) = (
+                    if let (Some(_), Some((blob_id, etag)), Some(name)) = (
                         path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
-                        path.next().and_then(BlobId::from_base32),
+                        path.next().and_then(|p| {
+                            BlobId::from_base32(p).map(|blob_id| (blob_id, format!("\"{p}\"")))
+                        }),
                         path.next(),
                     ) {
+                        if let Some(response) = jmap.proxy_request(&req, &access_token, None).await
+                        {
+                            return response;
+                        }
+
+                        // Limit download rate and concurrent transfers
+                        let _in_flight =
+                            match jmap.is_download_allowed(&access_token, &remote_ip).await {
+                                Ok(in_flight) => in_flight,
+                                Err(err) => return err.into_http_response(),
+                            };
+
+                        // Blobs are immutable, the client already has the latest version
+                        if req
+                            .headers()
+                            .get(header::IF_NONE_MATCH)
+                            .and_then(|h| h.to_str().ok())
+                            .map_or(false, |value| {
+                                value.split(',').any(|tag| {
+                                    let tag = tag.trim();
+                                    tag == "*" || tag.trim_start_matches("W/") == etag
+                                })
+                            })
+                        {
+                            return hyper::Response::builder()
+                                .status(StatusCode::NOT_MODIFIED)
+                                .header(header::ETAG, etag)
+                                .body(
+                                    Full::new(Bytes::new())
+                                        .map_err(|never| match never {})
+                                        .boxed(),
+                                )
+                                .unwrap();
+                        }
+
                         return match jmap.blob_download(&blob_id, &access_token).await {
-                            Ok(Some(blob)) => DownloadResponse {
-                                filename: name.to_string(),
-                                content_type: req
-                                    .uri()
-                                    .query()
-                                    .and_then(|q| {
-                                        form_urlencoded::parse(q.as_bytes())
-                                            .find(|(k, _)| k == "accept")
-                                            .map(|(_, v)| v.into_owned())
+                            Ok(Some(blob)) => {
+                                // Only honour the range if the client's copy is current
+                                let range = req
+                                    .headers()
+                                    .get(header::RANGE)
+                                    .and_then(|h| h.to_str().ok())
+                                    .filter(|_| {
+                                        req.headers()
+                                            .get(header::IF_RANGE)
+                                            .and_then(|h| h.to_str().ok())
+                                            .map_or(true, |value| value.trim() == etag)
                                     })
-                                    .unwrap_or("application/octet-stream".to_string()),
-                                blob,
+                                    .and_then(|value| parse_range(value, blob.len()));
+                                let range = match range {
+                                    Some(Some(range)) => Some(range),
+                                    Some(None) => {
+                                        return hyper::Response::builder()
+                                            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                                            .header(
+                                                header::CONTENT_RANGE,
+                                                format!("bytes */{}", blob.len()),
+                                            )
+                                            .body(
+                                                Full::new(Bytes::new())
+                                                    .map_err(|never| match never {})
+                                                    .boxed(),
+                                            )
+                                            .unwrap();
+                                    }
+                                    None => None,
+                                };
+
+                                jmap.billing_incr(
+                                    blob_id.class.account_id(),
+                                    &[(
+                                        BILLING_BANDWIDTH_BYTES,
+                                        range.as_ref().map_or(blob.len(), |range| range.len())
+                                            as i64,
+                                    )],
+                                )
+                                .await;
+
+                                DownloadResponse {
+                                    filename: name.to_string(),
+                                    content_type: req
+                                        .uri()
+                                        .query()
+                                        .and_then(|q| {
+                                            form_urlencoded::parse(q.as_bytes())
+                                                .find(|(k, _)| k == "accept")
+                                                .map(|(_, v)| v.into_owned())
+                                        })
+                                        .unwrap_or("application/octet-stream".to_string()),
+                                    blob,
+                                }
+                                .into_ranged_response(etag, range)
                             }
-                            .into_http_response(),
                             Ok(None) => RequestError::not_found().into_http_response(),
                             Err(_) => RequestError::internal_server_error().into_http_response(),
                         };
@@ -130,37 +242,129 @@ pub async fn parse_jmap_request(
                 ("upload", &Method::POST) => {
                     if let Some(account_id) = path.next().and_then(|p| Id::from_bytes(p.as_bytes()))
                     {
-                        return match fetch_body(
-                            &mut req,
-                            jmap.config.upload_max_size,
-                            &access_token,
-                        )
-                        .await
-                        {
+                        // Resumable uploads announce their total length upfront
+                        let upload_length = match upload_header(&req, "upload-length") {
+                            Ok(upload_length) => upload_length,
+                            Err(err) => return err.into_http_response(),
+                        };
+                        let max_size = if upload_length.is_some() {
+                            jmap.config.upload_resumable_chunk_size
+                        } else {
+                            jmap.config.upload_max_size
+                        };
+
+                        return match fetch_body(&mut req, max_size, &access_token).await {
                             Some(bytes) => {
-                                match jmap
-                                    .blob_upload(
+                                if let Some(response) =
+                                    jmap.proxy_request(&req, &access_token, Some(&bytes)).await
+                                {
+                                    return response;
+                                }
+
+                                let content_type = req
+                                    .headers()
+                                    .get(CONTENT_TYPE)
+                                    .and_then(|h| h.to_str().ok())
+                                    .unwrap_or("application/octet-stream");
+                                if let Some(length) = upload_length {
+                                    match jmap
+                                        .resumable_upload_create(
+                                            account_id,
+                                            content_type,
+                                            length,
+                                            &bytes,
+                                            access_token,
+                                        )
+                                        .await
+                                    {
+                                        Ok(response) => response.into_http_response(),
+                                        Err(err) => err.into_http_response(),
+                                    }
+                                } else {
+                                    match jmap
+                                        .blob_upload(account_id, content_type, &bytes, access_token)
+                                        .await
+                                    {
+                                        Ok(response) => response.into_http_response(),
+                                        Err(err) => err.into_http_response(),
+                                    }
+                                }
+                            }
+                            None => RequestError::limit(RequestLimitError::SizeUpload)
+                                .into_http_response(),
+                        };
+                    }
+                }
+                ("upload", &Method::PATCH | &Method::HEAD | &Method::DELETE) => {
+                    if let (Some(account_id), Some(upload_id)) = (
+                        path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
+                        path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
+                    ) {
+                        let method = req.method().clone();
+                        let bytes = if method == Method::PATCH {
+                            match fetch_body(
+                                &mut req,
+                                jmap.config.upload_resumable_chunk_size,
+                                &access_token,
+                            )
+                            .await
+                            {
+                                Some(bytes) => bytes,
+                                None => {
+                                    return RequestError::limit(RequestLimitError::SizeUpload)
+                                        .into_http_response()
+                                }
+                            }
+                        } else {
+                            Vec::new()
+                        };
+                        if let Some(response) =
+                            jmap.proxy_request(&req, &access_token, Some(&bytes)).await
+                        {
+                            return response;
+                        }
+
+                        let result = match method {
+                            Method::PATCH => match upload_header(&req, "upload-offset") {
+                                Ok(Some(offset)) => {
+                                    jmap.resumable_upload_append(
                                         account_id,
-                                        req.headers()
-                                            .get(CONTENT_TYPE)
-                                            .and_then(|h| h.to_str().ok())
-                                            .unwrap_or("application/octet-stream"),
+                                        upload_id,
+                                        offset,
                                         &bytes,
                                         access_token,
                                     )
                                     .await
+                                }
+                                Ok(None) => Err(RequestError::invalid_parameters()),
+                                Err(err) => Err(err),
+                            },
+                            Method::HEAD => {
+                                jmap.resumable_upload_status(account_id, upload_id).await
+                            }
+                            _ => {
+                                return match jmap
+                                    .resumable_upload_cancel(account_id, upload_id)
+                                    .await
                                 {
                                     Ok(response) => response.into_http_response(),
                                     Err(err) => err.into_http_response(),
-                                }
+                                };
                             }
-                            None => RequestError::limit(RequestLimitError::SizeUpload)
-                                .into_http_response(),
+                        };
+
+                        return match result {
+                            Ok(response) => response.into_http_response(),
+                            Err(err) => err.into_http_response(),
                         };
                     }
                 }
                 ("eventsource", &Method::GET) => {
-                    return jmap.handle_event_source(req, access_token).await
+                    if let Some(response) = jmap.proxy_request(&req, &access_token, None).await {
+                        return response;
+                    }
+
+                    return jmap.handle_event_source(req, access_token).await;
                 }
                 ("ws", &Method::GET) => {
                     return upgrade_websocket_connection(jmap, req, access_token, instance.clone())
@@ -267,17 +471,78 @@ pub async fn parse_jmap_request(
                 _ => (),
             }
         }
+        "quarantine" if matches!(*req.method(), Method::GET | Method::POST) => {
+            let remote_addr = jmap.build_remote_addr(&req, remote_ip);
+
+            // Review links are signed, limit them as anonymous requests
+            return match jmap.is_anonymous_allowed(&remote_addr).await {
+                Ok(_) => {
+                    jmap.smtp
+                        .handle_quarantine_link(
+                            req.method(),
+                            req.uri(),
+                            path.next().unwrap_or_default(),
+                        )
+                        .await
+                }
+                Err(err) => err.into_http_response(),
+            };
+        }
+        "account" => {
+            // Self-service requests act on the authenticated account
+            return match jmap.authenticate_account_headers(&req, remote_ip).await {
+                Ok(Some((_, access_token))) => {
+                    let body = fetch_body(&mut req, 8192, &access_token).await;
+                    jmap.handle_account_request(&req, body, &access_token).await
+                }
+                Ok(None) => RequestError::unauthorized().into_http_response(),
+                Err(err) => err.into_http_response(),
+            };
+        }
         "api" => {
             // Make sure the user is a superuser
-            let body = match jmap.authenticate_headers(&req, remote_ip).await {
-                Ok(Some((_, access_token))) if access_token.is_super_user() => {
-                    fetch_body(&mut req, 8192, &access_token).await
-                }
+            let access_token = match jmap.authenticate_headers(&req, remote_ip).await {
+                Ok(Some((_, access_token))) if access_token.is_super_user() => access_token,
                 Ok(_) => return RequestError::unauthorized().into_http_response(),
                 Err(err) => return err.into_http_response(),
             };
+            let rate_limit = match jmap.is_management_request_allowed(&access_token).await {
+                Ok(rate_limit) => rate_limit,
+                Err(err) => return err.into_http_response(),
+            };
+            let mut response = if rate_limit.map_or(false, |r| r.exceeded) {
+                RequestError::too_many_requests().into_http_response()
+            } else {
+                let body = fetch_body(&mut req, 8192, &access_token).await;
+                jmap.handle_manage_request(&req, body).await
+            };
+            if let Some(rate_limit) = rate_limit {
+                rate_limit.add_headers(&mut response);
+            }
 
-            return jmap.handle_manage_request(&req, body).await;
+            return response;
+        }
+        "cluster" => {
+            // Messages handed off by other nodes of the cluster
+            return jmap.handle_cluster_request(&mut req).await;
+        }
+        "metrics" if jmap.config.metrics_enable && req.method() == Method::GET => {
+            // Scrapers authenticate with a dedicated credential, if configured
+            let is_authorized = jmap.config.metrics_auth.as_ref().map_or(true, |auth| {
+                req.headers()
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .map_or(false, |value| {
+                        constant_time_eq(value.as_bytes(), auth.as_bytes())
+                    })
+            });
+
+            return if is_authorized {
+                MetricsResponse::open_metrics(server_metrics().to_openmetrics())
+                    .into_http_response()
+            } else {
+                RequestError::unauthorized().into_http_response()
+            };
         }
         _ => (),
     }
@@ -324,10 +589,25 @@ async fn handle_request<T: SessionStream>(jmap: Arc<JMAP>, session: SessionData<
                         uri = req.uri().to_string(),
                     );
 
+                    // Continue SCRAM exchanges before routing the request
+                    let authentication_info =
+                        match jmap.handle_scram_headers(&req, session.remote_ip).await {
+                            Ok(authentication_info) => authentication_info,
+                            Err(response) => return Ok(response),
+                        };
+
                     // Parse JMAP request
                     let mut response =
                         parse_jmap_request(jmap.clone(), req, session.remote_ip, instance).await;
 
+                    if let Some(authentication_info) = authentication_info
+                        .and_then(|value| header::HeaderValue::from_str(&value).ok())
+                    {
+                        response
+                            .headers_mut()
+                            .insert("Authentication-Info", authentication_info);
+                    }
+
                     // Add custom headers
                     if !jmap.config.http_headers.is_empty() {
                         let headers = response.headers_mut();
@@ -372,6 +652,19 @@ pub async fn fetch_body(
     bytes.into()
 }
 
+fn upload_header(req: &HttpRequest, name: &str) -> Result<Option<usize>, RequestError> {
+    req.headers()
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .ok_or_else(RequestError::invalid_parameters)
+        })
+        .transpose()
+}
+
 pub trait ToHttpResponse {
     fn into_http_response(self) -> HttpResponse;
 }
@@ -416,6 +709,22 @@ impl HtmlResponse {
     }
 }
 
+impl MetricsResponse {
+    pub fn new(body: String) -> Self {
+        MetricsResponse {
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body,
+        }
+    }
+
+    pub fn open_metrics(body: String) -> Self {
+        MetricsResponse {
+            content_type: "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            body,
+        }
+    }
+}
+
 impl ToHttpResponse for Response {
     fn into_http_response(self) -> HttpResponse {
         //let c = println!("-> {}", serde_json::to_string_pretty(&self).unwrap());
@@ -455,12 +764,124 @@ impl ToHttpResponse for DownloadResponse {
     }
 }
 
+impl DownloadResponse {
+    pub fn into_ranged_response(self, etag: String, range: Option<Range<usize>>) -> HttpResponse {
+        let builder = hyper::Response::builder()
+            .header(header::CONTENT_TYPE, self.content_type)
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"",
+                    self.filename.replace('\"', "\\\"")
+                ),
+            )
+            .header(
+                header::CACHE_CONTROL,
+                "private, immutable, max-age=31536000",
+            )
+            .header(header::ETAG, etag)
+            .header(header::ACCEPT_RANGES, "bytes");
+
+        if let Some(range) = range {
+            let total = self.blob.len();
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, total),
+                )
+                .body(
+                    Full::new(Bytes::from(self.blob).slice(range))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap()
+        } else {
+            builder
+                .status(StatusCode::OK)
+                .body(
+                    Full::new(Bytes::from(self.blob))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap()
+        }
+    }
+}
+
+/// Parses a byte range header as defined in RFC 9110. Returns `None` if the
+/// header is malformed and should be ignored, or `Some(None)` if the range can
+/// not be satisfied. Multiple ranges are coalesced into a single one.
+pub fn parse_range(value: &str, len: usize) -> Option<Option<Range<usize>>> {
+    let mut result: Option<Range<usize>> = None;
+
+    for spec in value.trim().strip_prefix("bytes=")?.split(',') {
+        let range = match spec.trim().split_once('-')? {
+            ("", suffix) => {
+                let suffix = suffix.parse::<usize>().ok()?;
+                len.saturating_sub(suffix)..len
+            }
+            (start, "") => start.parse::<usize>().ok()?..len,
+            (start, end) => {
+                let start = start.parse::<usize>().ok()?;
+                let end = end.parse::<usize>().ok()?;
+                if end < start {
+                    return None;
+                }
+                start..std::cmp::min(end + 1, len)
+            }
+        };
+        if range.start < range.end {
+            result = Some(match result {
+                Some(prev) => {
+                    std::cmp::min(prev.start, range.start)..std::cmp::max(prev.end, range.end)
+                }
+                None => range,
+            });
+        }
+    }
+
+    Some(result)
+}
+
 impl ToHttpResponse for UploadResponse {
     fn into_http_response(self) -> HttpResponse {
         JsonResponse::new(self).into_http_response()
     }
 }
 
+impl ToHttpResponse for ResumableUploadResponse {
+    fn into_http_response(self) -> HttpResponse {
+        match self {
+            ResumableUploadResponse::InProgress(status) => {
+                let mut builder = hyper::Response::builder()
+                    .status(if status.created {
+                        StatusCode::CREATED
+                    } else {
+                        StatusCode::NO_CONTENT
+                    })
+                    .header("Upload-Offset", status.offset)
+                    .header("Upload-Length", status.length)
+                    .header(header::CACHE_CONTROL, "no-store");
+                if status.created {
+                    builder = builder.header(
+                        header::LOCATION,
+                        format!("/jmap/upload/{}/{}", status.account_id, status.upload_id),
+                    );
+                }
+                builder
+                    .body(
+                        Full::new(Bytes::new())
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .unwrap()
+            }
+            ResumableUploadResponse::Completed(response) => response.into_http_response(),
+        }
+    }
+}
+
 impl ToHttpResponse for RequestError {
     fn into_http_response(self) -> HttpResponse {
         hyper::Response::builder()
@@ -489,6 +910,20 @@ impl ToHttpResponse for HtmlResponse {
     }
 }
 
+impl ToHttpResponse for MetricsResponse {
+    fn into_http_response(self) -> HttpResponse {
+        hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, self.content_type)
+            .body(
+                Full::new(Bytes::from(self.body))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
 impl ToHttpResponse for () {
     fn into_http_response(self) -> HttpResponse {
         hyper::Response::builder()
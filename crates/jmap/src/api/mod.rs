@@ -30,6 +30,7 @@ use utils::map::vec_map::VecMap;
 
 use crate::JMAP;
 
+pub mod account;
 pub mod admin;
 pub mod config;
 pub mod event_source;
@@ -58,6 +59,11 @@ pub struct HtmlResponse {
     body: String,
 }
 
+pub struct MetricsResponse {
+    content_type: &'static str,
+    body: String,
+}
+
 pub type HttpRequest = hyper::Request<hyper::body::Incoming>;
 pub type HttpResponse =
     hyper::Response<http_body_util::combinators::BoxBody<hyper::body::Bytes, hyper::Error>>;
@@ -33,7 +33,13 @@ use jmap_proto::{
     response::{Response, ResponseMethod},
     types::collection::Collection,
 };
-use utils::listener::ServerInstance;
+use utils::{
+    listener::ServerInstance,
+    metrics::{
+        measure_command_with,
+        slow::{sanitize_arguments, CommandDetails},
+    },
+};
 
 use crate::{auth::AccessToken, JMAP};
 
@@ -62,9 +68,16 @@ impl JMAP {
                 let mut next_call = None;
 
                 // Add response
-                match self
-                    .handle_method_call(call.method, &access_token, &mut next_call, instance)
-                    .await
+                let details = CommandDetails::new(Some(access_token.primary_id()), || {
+                    sanitize_arguments(&format!("{:?}", call.method))
+                });
+                match measure_command_with(
+                    "jmap",
+                    call.name.as_str(),
+                    details,
+                    self.handle_method_call(call.method, &access_token, &mut next_call, instance),
+                )
+                .await
                 {
                     Ok(mut method_response) => {
                         match &mut method_response {
@@ -172,9 +185,24 @@ impl JMAP {
 
                     self.vacation_response_get(req).await?.into()
                 }
+                get::RequestArguments::Settings => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.settings_get(req).await?.into()
+                }
+                get::RequestArguments::FilterRule => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.filter_rule_get(req).await?.into()
+                }
+                get::RequestArguments::SavedSearch => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.saved_search_get(req).await?.into()
+                }
                 get::RequestArguments::Principal => {
                     if self.config.principal_allow_lookups || access_token.is_super_user() {
-                        self.principal_get(req).await?.into()
+                        self.principal_get(req, access_token).await?.into()
                     } else {
                         return Err(MethodError::Forbidden(
                             "Principal lookups are disabled".to_string(),
@@ -196,11 +224,19 @@ impl JMAP {
             },
             RequestMethod::Query(mut req) => match req.take_arguments() {
                 query::RequestArguments::Email(arguments) => {
-                    access_token.assert_has_access(req.account_id, Collection::Email)?;
+                    if arguments.unified_inbox.unwrap_or(false) {
+                        access_token.assert_is_member(req.account_id)?;
 
-                    self.email_query(req.with_arguments(arguments), access_token)
-                        .await?
-                        .into()
+                        self.email_query_unified(req.with_arguments(arguments), access_token)
+                            .await?
+                            .into()
+                    } else {
+                        access_token.assert_has_access(req.account_id, Collection::Email)?;
+
+                        self.email_query(req.with_arguments(arguments), access_token)
+                            .await?
+                            .into()
+                    }
                 }
                 query::RequestArguments::Mailbox(arguments) => {
                     access_token.assert_has_access(req.account_id, Collection::Mailbox)?;
@@ -221,7 +257,7 @@ impl JMAP {
                 }
                 query::RequestArguments::Principal => {
                     if self.config.principal_allow_lookups || access_token.is_super_user() {
-                        self.principal_query(req).await?.into()
+                        self.principal_query(req, access_token).await?.into()
                     } else {
                         return Err(MethodError::Forbidden(
                             "Principal lookups are disabled".to_string(),
@@ -274,6 +310,26 @@ impl JMAP {
 
                     self.vacation_response_set(req).await?.into()
                 }
+                set::RequestArguments::Settings => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.settings_set(req).await?.into()
+                }
+                set::RequestArguments::FilterRule => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.filter_rule_set(req).await?.into()
+                }
+                set::RequestArguments::SavedSearch => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.saved_search_set(req).await?.into()
+                }
+                set::RequestArguments::Thread => {
+                    access_token.assert_has_access(req.account_id, Collection::Email)?;
+
+                    self.thread_set(req, access_token).await?.into()
+                }
             },
             RequestMethod::Changes(req) => self.changes(req, access_token).await?.into(),
             RequestMethod::Copy(req) => {
@@ -33,7 +33,7 @@ use jmap_proto::{
 use store::ahash::AHashSet;
 use utils::{listener::ServerInstance, map::vec_map::VecMap, UnwrapFailure};
 
-use crate::{auth::AccessToken, JMAP};
+use crate::{auth::AccessToken, services::cluster::NODE_HINT_PARAM, JMAP};
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct Session {
@@ -191,6 +191,9 @@ impl JMAP {
     ) -> Result<Session, RequestError> {
         let mut session = Session::new(&instance.data, &self.config.capabilities);
         session.set_state(access_token.state());
+        if let Some(node_id) = self.account_affinity_node(access_token.primary_id()).await {
+            session.set_node_hint(node_id);
+        }
         session.set_primary_account(
             access_token.primary_id().into(),
             access_token.name.clone(),
@@ -307,6 +310,66 @@ impl crate::Config {
             Capability::Quota,
             Capabilities::Empty(EmptyCapabilities::default()),
         );
+
+        // Add account settings capabilities
+        self.capabilities.session.append(
+            Capability::Settings,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Settings,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add filter rules capabilities
+        self.capabilities.session.append(
+            Capability::FilterRules,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::FilterRules,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add message annotations capabilities
+        self.capabilities.session.append(
+            Capability::Annotations,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Annotations,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add saved searches capabilities
+        self.capabilities.session.append(
+            Capability::SavedSearches,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::SavedSearches,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add unified inbox capabilities
+        self.capabilities.session.append(
+            Capability::Unified,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Unified,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add conversation capabilities
+        self.capabilities.session.append(
+            Capability::Conversations,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Conversations,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
     }
 }
 
@@ -384,6 +447,18 @@ impl Session {
         self.state = state;
     }
 
+    pub fn set_node_hint(&mut self, node_id: u64) {
+        for url in [
+            &mut self.api_url,
+            &mut self.download_url,
+            &mut self.upload_url,
+            &mut self.event_source_url,
+        ] {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            url.push_str(&format!("{separator}{NODE_HINT_PARAM}={node_id}"));
+        }
+    }
+
     pub fn api_url(&self) -> &str {
         &self.api_url
     }
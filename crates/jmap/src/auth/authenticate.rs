@@ -21,16 +21,31 @@
  * for more details.
 */
 
-use std::{net::IpAddr, sync::Arc, time::Instant};
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use directory::{AuthResult, QueryBy};
-use hyper::header;
+use directory::{
+    core::scram::{ScramState, ScramStep},
+    AuthResult, Principal, QueryBy,
+};
+use hyper::header::{self, HeaderValue};
 use jmap_proto::error::request::RequestError;
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
+use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
 use utils::{listener::limiter::InFlight, map::ttl_dashmap::TtlMap};
 
-use crate::JMAP;
+use crate::{
+    api::{http::ToHttpResponse, HttpResponse},
+    JMAP,
+};
+
+// Seconds a client has to answer a SCRAM challenge
+const SCRAM_EXCHANGE_TTL: u64 = 60;
 
 use super::AccessToken;
 
@@ -39,6 +54,25 @@ impl JMAP {
         &self,
         req: &hyper::Request<hyper::body::Incoming>,
         remote_ip: IpAddr,
+    ) -> Result<Option<(InFlight, Arc<AccessToken>)>, RequestError> {
+        match self.authenticate_account_headers(req, remote_ip).await? {
+            Some((_, access_token)) if access_token.must_change_password => {
+                Err(RequestError::blank(
+                    403,
+                    "Password change required",
+                    "The account password must be changed before it can be used.",
+                ))
+            }
+            session => Ok(session),
+        }
+    }
+
+    /// Authenticates self-service account requests, which are also accepted
+    /// from accounts that must change their password.
+    pub async fn authenticate_account_headers(
+        &self,
+        req: &hyper::Request<hyper::body::Incoming>,
+        remote_ip: IpAddr,
     ) -> Result<Option<(InFlight, Arc<AccessToken>)>, RequestError> {
         if let Some((mechanism, token)) = req
             .headers()
@@ -63,12 +97,10 @@ impl JMAP {
                             })
                         })
                     {
-                        if let AuthResult::Success(access_token) =
-                            self.authenticate_plain(&account, &secret, addr).await
-                        {
-                            Some(access_token)
-                        } else {
-                            None
+                        match self.authenticate_plain(&account, &secret, addr).await {
+                            AuthResult::Success(access_token)
+                            | AuthResult::MustChangePassword(access_token) => Some(access_token),
+                            AuthResult::Failure | AuthResult::Banned => None,
                         }
                     } else {
                         tracing::debug!(
@@ -82,8 +114,8 @@ impl JMAP {
                     // Enforce anonymous rate limit for bearer auth requests
                     self.is_anonymous_allowed(&addr).await?;
 
-                    match self.validate_access_token("access_token", &token).await {
-                        Ok((account_id, _, _)) => self.get_access_token(account_id).await,
+                    match self.validate_bearer_token(&token).await {
+                        Ok(account_id) => self.get_access_token(account_id).await,
                         Err(err) => {
                             tracing::debug!(
                                 context = "authenticate_headers",
@@ -99,9 +131,13 @@ impl JMAP {
                     None
                 }
                 .map(|access_token| {
+                    // Restricted tokens are not cached, the next request after
+                    // the password change authenticates with the new password
                     let access_token = Arc::new(access_token);
-                    self.cache_session(token, &access_token);
-                    self.cache_access_token(access_token.clone());
+                    if !access_token.must_change_password {
+                        self.cache_session(token, &access_token);
+                        self.cache_access_token(access_token.clone());
+                    }
                     access_token
                 })
             };
@@ -121,6 +157,135 @@ impl JMAP {
         }
     }
 
+    /// Continues a SCRAM-SHA-256 exchange carried in the Authorization header
+    /// (RFC 7804). Intermediate steps are answered with a 401 challenge, once
+    /// the client proof is verified the session is cached under the final
+    /// credentials and the value of the Authentication-Info header returned.
+    pub async fn handle_scram_headers(
+        &self,
+        req: &hyper::Request<hyper::body::Incoming>,
+        remote_ip: IpAddr,
+    ) -> Result<Option<String>, HttpResponse> {
+        let token = match req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split_once(' '))
+        {
+            Some((mechanism, token)) if mechanism.eq_ignore_ascii_case("SCRAM-SHA-256") => {
+                token.trim().to_string()
+            }
+            _ => return Ok(None),
+        };
+        if self.sessions.get_with_ttl(&token).is_some() {
+            return Ok(None);
+        }
+
+        // Parse the sid and data attributes
+        let mut sid = None;
+        let mut data = None;
+        for attribute in token.split(',') {
+            if let Some((name, value)) = attribute.split_once('=') {
+                let value = value.trim().trim_matches('"');
+                match name.trim() {
+                    "sid" => sid = Some(value),
+                    "data" => data = Some(value),
+                    _ => (),
+                }
+            }
+        }
+
+        let addr = self.build_remote_addr(req, remote_ip);
+        let (sid, state) = if let Some(sid) = sid {
+            if let Some(state) = self.scram_sessions.get_with_ttl(sid) {
+                self.scram_sessions.remove(sid);
+                (sid.to_string(), state)
+            } else {
+                return Err(RequestError::unauthorized().into_http_response());
+            }
+        } else {
+            // Enforce rate limit for authentication requests
+            self.is_auth_allowed_soft(&addr)
+                .await
+                .map_err(|err| err.into_http_response())?;
+
+            (
+                thread_rng()
+                    .sample_iter(Alphanumeric)
+                    .take(24)
+                    .map(char::from)
+                    .collect::<String>(),
+                ScramState::ClientFirst,
+            )
+        };
+
+        let step = match data
+            .and_then(|data| base64_decode(data.as_bytes()))
+            .and_then(|data| String::from_utf8(data).ok())
+        {
+            Some(response) => {
+                self.directory
+                    .scram_step(state, &response, false, None)
+                    .await
+            }
+            None => Ok(ScramStep::Invalid),
+        };
+
+        match step {
+            Ok(ScramStep::Challenge(ScramState::Verified(username), server_final)) => {
+                // HTTP has no round trip for the server signature, it is
+                // returned with the response to the authenticated request
+                match self.authenticate_scram(&username, true, addr).await {
+                    AuthResult::Success(access_token) => {
+                        let access_token = Arc::new(access_token);
+                        self.cache_session(token, &access_token);
+                        self.cache_access_token(access_token);
+                        Ok(Some(format!(
+                            "sid={sid}, data={}",
+                            base64_encode(server_final.as_bytes())
+                        )))
+                    }
+                    AuthResult::MustChangePassword(_) => Err(RequestError::blank(
+                        403,
+                        "Password change required",
+                        "The account password must be changed before it can be used.",
+                    )
+                    .into_http_response()),
+                    AuthResult::Failure | AuthResult::Banned => {
+                        Err(RequestError::unauthorized().into_http_response())
+                    }
+                }
+            }
+            Ok(ScramStep::Challenge(state, challenge)) => {
+                let challenge = format!(
+                    "SCRAM-SHA-256 sid={sid}, data={}",
+                    base64_encode(challenge.as_bytes())
+                );
+                self.scram_sessions.insert_with_ttl(
+                    sid,
+                    state,
+                    Instant::now() + Duration::from_secs(SCRAM_EXCHANGE_TTL),
+                );
+                let mut response = RequestError::unauthorized().into_http_response();
+                if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+                    response
+                        .headers_mut()
+                        .insert(header::WWW_AUTHENTICATE, challenge);
+                }
+                Err(response)
+            }
+            Ok(ScramStep::Authenticate {
+                username,
+                is_verified,
+            }) => {
+                let _ = self.authenticate_scram(&username, is_verified, addr).await;
+                Err(RequestError::unauthorized().into_http_response())
+            }
+            Ok(ScramStep::Invalid) => Err(RequestError::unauthorized().into_http_response()),
+            Err(_) => Err(RequestError::internal_server_error().into_http_response()),
+        }
+    }
+
     pub fn cache_session(&self, session_id: String, access_token: &AccessToken) {
         self.sessions.insert_with_ttl(
             session_id,
@@ -177,7 +342,7 @@ impl JMAP {
         secret: &str,
         remote_ip: IpAddr,
     ) -> AuthResult<AccessToken> {
-        match self
+        let result = self
             .directory
             .authenticate(
                 &Credentials::Plain {
@@ -187,9 +352,35 @@ impl JMAP {
                 remote_ip,
                 true,
             )
-            .await
-        {
+            .await;
+        self.map_auth_result(result, remote_ip).await
+    }
+
+    /// Completes a SCRAM exchange, `is_verified` being the result of the
+    /// client proof verification.
+    pub async fn authenticate_scram(
+        &self,
+        username: &str,
+        is_verified: bool,
+        remote_ip: IpAddr,
+    ) -> AuthResult<AccessToken> {
+        let result = self
+            .directory
+            .authenticate_scram(username, is_verified, remote_ip, true)
+            .await;
+        self.map_auth_result(result, remote_ip).await
+    }
+
+    async fn map_auth_result(
+        &self,
+        result: directory::Result<AuthResult<Principal<u32>>>,
+        remote_ip: IpAddr,
+    ) -> AuthResult<AccessToken> {
+        match result {
             Ok(AuthResult::Success(principal)) => AuthResult::Success(AccessToken::new(principal)),
+            Ok(AuthResult::MustChangePassword(principal)) => AuthResult::MustChangePassword(
+                AccessToken::new(principal).with_password_change_required(),
+            ),
             Ok(AuthResult::Failure) => {
                 let _ = self.is_auth_allowed_hard(&remote_ip).await;
                 AuthResult::Failure
@@ -42,6 +42,7 @@ use utils::map::bitmap::Bitmap;
 pub mod acl;
 pub mod authenticate;
 pub mod oauth;
+pub mod oidc;
 pub mod rate_limit;
 
 #[derive(Debug, Clone, Default)]
@@ -53,6 +54,7 @@ pub struct AccessToken {
     pub description: Option<String>,
     pub quota: u32,
     pub is_superuser: bool,
+    pub must_change_password: bool,
 }
 
 impl AccessToken {
@@ -65,6 +67,15 @@ impl AccessToken {
             description: principal.description,
             quota: principal.quota,
             is_superuser: principal.typ == Type::Superuser,
+            must_change_password: false,
+        }
+    }
+
+    /// Restricts the token to changing the account password.
+    pub fn with_password_change_required(self) -> Self {
+        Self {
+            must_change_password: true,
+            ..self
         }
     }
 
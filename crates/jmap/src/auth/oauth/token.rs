@@ -180,16 +180,7 @@ impl JMAP {
         client_id: &str,
         with_refresh_token: bool,
     ) -> Result<TokenResponse, &'static str> {
-        let password_hash = self
-            .directory
-            .query(QueryBy::Id(account_id), false)
-            .await
-            .map_err(|_| "Temporary lookup error")?
-            .ok_or("Account no longer exists")?
-            .secrets
-            .into_iter()
-            .next()
-            .ok_or("Failed to obtain password hash")?;
+        let password_hash = self.token_secret(account_id).await?;
 
         Ok(TokenResponse::Granted {
             access_token: self.encode_access_token(
@@ -297,18 +288,8 @@ impl JMAP {
             return Err("Token expired.");
         }
 
-        // Optain password hash
-
-        let password_hash = self
-            .directory
-            .query(QueryBy::Id(account_id), false)
-            .await
-            .map_err(|_| "Temporary lookup error")?
-            .ok_or("Account no longer exists")?
-            .secrets
-            .into_iter()
-            .next()
-            .ok_or("Failed to obtain password hash")?;
+        // Obtain password hash
+        let password_hash = self.token_secret(account_id).await?;
 
         // Build context
         let key = self.config.oauth_key.clone();
@@ -341,4 +322,28 @@ impl JMAP {
         // Success
         Ok((account_id, client_id, expiry - now))
     }
+
+    /// Tokens are bound to the account's password hash and session generation,
+    /// changing either of them revokes all tokens issued until then.
+    async fn token_secret(&self, account_id: u32) -> Result<String, &'static str> {
+        let password_hash = self
+            .directory
+            .query(QueryBy::Id(account_id), false)
+            .await
+            .map_err(|_| "Temporary lookup error")?
+            .ok_or("Account no longer exists")?
+            .secrets
+            .into_iter()
+            .next()
+            .ok_or("Failed to obtain password hash")?;
+
+        match self
+            .session_generation(account_id)
+            .await
+            .map_err(|_| "Temporary lookup error")?
+        {
+            0 => Ok(password_hash),
+            generation => Ok(format!("{password_hash} {generation}")),
+        }
+    }
 }
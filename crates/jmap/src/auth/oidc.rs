@@ -0,0 +1,398 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+use base64::{engine::general_purpose, Engine};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use smtp::core::http::HttpClient;
+use utils::config::Config;
+
+use crate::JMAP;
+
+// Unknown key ids trigger a JWKS refresh at most once per minute
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+pub struct OidcProvider {
+    pub issuer: String,
+    pub audiences: Vec<String>,
+    pub jwks_url: String,
+    pub jwks_ttl: Duration,
+    pub timeout: Duration,
+    pub leeway: u64,
+    pub claim_name: Option<String>,
+    pub claim_email: Option<String>,
+    pub require_verified_email: bool,
+    jwks: ArcSwap<JwkCache>,
+}
+
+#[derive(Default)]
+struct JwkCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    use_: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+impl OidcProvider {
+    pub fn parse(settings: &Config) -> utils::config::Result<Option<Self>> {
+        let issuer = if let Some(issuer) = settings.value("oauth.oidc.issuer") {
+            issuer.trim_end_matches('/').to_string()
+        } else {
+            return Ok(None);
+        };
+
+        // Without an audience, tokens issued to any client of the provider
+        // would be accepted
+        let audiences = settings
+            .values("oauth.oidc.audience")
+            .map(|(_, audience)| audience.to_string())
+            .collect::<Vec<_>>();
+        if audiences.is_empty() {
+            return Err("Missing property \"oauth.oidc.audience\".".to_string());
+        }
+
+        Ok(Some(OidcProvider {
+            jwks_url: settings
+                .value("oauth.oidc.jwks.url")
+                .map(|url| url.to_string())
+                .unwrap_or_else(|| format!("{issuer}/.well-known/jwks.json")),
+            issuer,
+            audiences,
+            jwks_ttl: settings.property_or_static("oauth.oidc.jwks.cache-ttl", "1h")?,
+            timeout: settings.property_or_static("oauth.oidc.jwks.timeout", "5s")?,
+            leeway: settings
+                .property_or_static::<Duration>("oauth.oidc.leeway", "1m")?
+                .as_secs(),
+            claim_name: claim_name(settings, "oauth.oidc.claim.name", ""),
+            claim_email: claim_name(settings, "oauth.oidc.claim.email", "email"),
+            require_verified_email: settings
+                .property_or_static("oauth.oidc.require-verified-email", "true")?,
+            jwks: ArcSwap::from_pointee(JwkCache::default()),
+        }))
+    }
+
+    /// Verifies the signature and registered claims of a JWT, returning
+    /// the login name and email address it was issued for.
+    pub async fn validate(
+        &self,
+        token: &str,
+        http: &HttpClient,
+    ) -> Result<(Option<String>, Option<String>), &'static str> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(header), Some(payload), Some(signature), None) => {
+                    (header, payload, signature)
+                }
+                _ => return Err("Malformed token."),
+            };
+        let header = decode_part(header)
+            .and_then(|header| serde_json::from_slice::<JwtHeader>(&header).ok())
+            .ok_or("Failed to decode token header.")?;
+        let claims = decode_part(payload)
+            .and_then(|claims| serde_json::from_slice::<Value>(&claims).ok())
+            .filter(|claims| claims.is_object())
+            .ok_or("Failed to decode token claims.")?;
+        let signing_input = &token[..token.len() - signature.len() - 1];
+        let signature = decode_part(signature).ok_or("Failed to decode token signature.")?;
+
+        // Verify signature
+        let key = self
+            .verifying_key(&header.alg, header.kid.as_deref(), http)
+            .await
+            .ok_or("No matching key found.")?;
+        if !key.verify(&header.alg, signing_input.as_bytes(), &signature) {
+            return Err("Invalid token signature.");
+        }
+
+        // Validate registered claims
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if claims["iss"].as_str().map(|iss| iss.trim_end_matches('/')) != Some(&self.issuer) {
+            return Err("Invalid token issuer.");
+        }
+        let is_audience = match &claims["aud"] {
+            Value::String(aud) => self.audiences.contains(aud),
+            Value::Array(auds) => auds
+                .iter()
+                .filter_map(|aud| aud.as_str())
+                .any(|aud| self.audiences.iter().any(|a| a == aud)),
+            _ => false,
+        };
+        if !is_audience {
+            return Err("Invalid token audience.");
+        }
+        match claims["exp"].as_u64() {
+            Some(exp) if exp + self.leeway > now => (),
+            Some(_) => return Err("Token expired."),
+            None => return Err("Token does not expire."),
+        }
+        if claims["nbf"]
+            .as_u64()
+            .map_or(false, |nbf| nbf > now + self.leeway)
+        {
+            return Err("Token not yet valid.");
+        }
+
+        // Obtain the identity the token was issued for
+        let name = self
+            .claim_name
+            .as_ref()
+            .and_then(|claim| claims[claim].as_str())
+            .map(|name| name.to_lowercase());
+        let email = self
+            .claim_email
+            .as_ref()
+            .and_then(|claim| claims[claim].as_str())
+            .filter(|_| {
+                !self.require_verified_email
+                    || matches!(&claims["email_verified"], Value::Bool(true))
+                    || claims["email_verified"].as_str() == Some("true")
+            })
+            .map(|email| email.to_lowercase());
+        if name.is_none() && email.is_none() {
+            return Err("Token does not identify an account.");
+        }
+
+        Ok((name, email))
+    }
+
+    async fn verifying_key(&self, alg: &str, kid: Option<&str>, http: &HttpClient) -> Option<Jwk> {
+        let cache = self.jwks.load_full();
+        let is_expired = cache
+            .fetched_at
+            .map_or(true, |fetched_at| fetched_at.elapsed() >= self.jwks_ttl);
+        if !is_expired {
+            if let Some(key) = cache.find(alg, kid) {
+                return Some(key.clone());
+            }
+        }
+
+        // Refresh keys, which also picks up rotated keys
+        if is_expired
+            || cache
+                .fetched_at
+                .map_or(true, |fetched_at| fetched_at.elapsed() >= JWKS_MIN_REFRESH)
+        {
+            match self.fetch_jwks(http).await {
+                Ok(keys) => {
+                    let cache = Arc::new(JwkCache {
+                        keys,
+                        fetched_at: Some(Instant::now()),
+                    });
+                    self.jwks.store(cache.clone());
+                    return cache.find(alg, kid).cloned();
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "oidc",
+                        event = "error",
+                        url = self.jwks_url,
+                        reason = %err,
+                        "Failed to fetch JWKS."
+                    );
+                }
+            }
+        }
+
+        // Fall back to the keys last retrieved
+        cache.find(alg, kid).cloned()
+    }
+
+    async fn fetch_jwks(&self, http: &HttpClient) -> reqwest::Result<Vec<Jwk>> {
+        http.send(|client| client.get(&self.jwks_url).timeout(self.timeout))
+            .await?
+            .error_for_status()?
+            .json::<JwkSet>()
+            .await
+            .map(|jwks| jwks.keys)
+    }
+}
+
+impl JwkCache {
+    fn find(&self, alg: &str, kid: Option<&str>) -> Option<&Jwk> {
+        self.keys.iter().find(|key| {
+            key.use_.as_deref().map_or(true, |use_| use_ == "sig")
+                && key.alg.as_deref().map_or(true, |key_alg| key_alg == alg)
+                && (kid.is_none() || key.kid.as_deref() == kid)
+                && match alg {
+                    "RS256" | "RS384" | "RS512" => key.kty == "RSA",
+                    "ES256" => key.kty == "EC" && key.crv.as_deref() == Some("P-256"),
+                    _ => false,
+                }
+        })
+    }
+}
+
+impl Jwk {
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> bool {
+        match alg {
+            "RS256" | "RS384" | "RS512" => {
+                let key = match (
+                    self.n.as_deref().and_then(decode_part),
+                    self.e.as_deref().and_then(decode_part),
+                ) {
+                    (Some(n), Some(e)) => {
+                        match RsaPublicKey::new(
+                            BigUint::from_bytes_be(&n),
+                            BigUint::from_bytes_be(&e),
+                        ) {
+                            Ok(key) => key,
+                            Err(_) => return false,
+                        }
+                    }
+                    _ => return false,
+                };
+                match alg {
+                    "RS256" => key.verify(
+                        Pkcs1v15Sign::new::<Sha256>(),
+                        &Sha256::digest(message),
+                        signature,
+                    ),
+                    "RS384" => key.verify(
+                        Pkcs1v15Sign::new::<Sha384>(),
+                        &Sha384::digest(message),
+                        signature,
+                    ),
+                    _ => key.verify(
+                        Pkcs1v15Sign::new::<Sha512>(),
+                        &Sha512::digest(message),
+                        signature,
+                    ),
+                }
+                .is_ok()
+            }
+            "ES256" => {
+                let key = match (
+                    self.x.as_deref().and_then(decode_part),
+                    self.y.as_deref().and_then(decode_part),
+                ) {
+                    (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => {
+                        let mut point = Vec::with_capacity(65);
+                        point.push(0x04);
+                        point.extend_from_slice(&x);
+                        point.extend_from_slice(&y);
+                        match p256::ecdsa::VerifyingKey::from_sec1_bytes(&point) {
+                            Ok(key) => key,
+                            Err(_) => return false,
+                        }
+                    }
+                    _ => return false,
+                };
+                match p256::ecdsa::Signature::from_slice(signature) {
+                    Ok(signature) => key
+                        .verify_prehash(&Sha256::digest(message), &signature)
+                        .is_ok(),
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl JMAP {
+    /// Validates a bearer token issued either by this server or, when
+    /// configured, by an external OpenID Connect provider.
+    pub async fn validate_bearer_token(&self, token: &str) -> Result<u32, &'static str> {
+        match &self.config.oidc {
+            Some(oidc) if token.split('.').count() == 3 => {
+                let (name, email) = oidc.validate(token, &self.smtp.shared.http).await?;
+
+                // Addresses asserted by the provider must belong to a local domain
+                for address in [&name, &email].into_iter().flatten() {
+                    if let Some((_, domain)) = address.rsplit_once('@') {
+                        if !self
+                            .directory
+                            .is_local_domain(domain)
+                            .await
+                            .map_err(|_| "Directory lookup failed.")?
+                        {
+                            return Err("Token identifies an address outside the local domains.");
+                        }
+                    }
+                }
+
+                match self
+                    .directory
+                    .query_by_claims(name.as_deref(), email.as_deref(), false)
+                    .await
+                {
+                    Ok(Some(principal)) => Ok(principal.id),
+                    Ok(None) => Err("Account not found."),
+                    Err(_) => Err("Directory lookup failed."),
+                }
+            }
+            _ => self
+                .validate_access_token("access_token", token)
+                .await
+                .map(|(account_id, _, _)| account_id),
+        }
+    }
+}
+
+fn claim_name(settings: &Config, key: &str, default: &str) -> Option<String> {
+    match settings.value(key).unwrap_or(default) {
+        "" | "false" => None,
+        claim => Some(claim.to_string()),
+    }
+}
+
+fn decode_part(part: &str) -> Option<Vec<u8>> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .ok()
+}
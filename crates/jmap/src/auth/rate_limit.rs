@@ -23,16 +23,41 @@
 
 use std::{net::IpAddr, sync::Arc};
 
-use jmap_proto::error::request::{RequestError, RequestLimitError};
-use utils::listener::limiter::{ConcurrencyLimiter, InFlight};
+use directory::QueryBy;
+use hyper::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        request::{RequestError, RequestLimitError},
+    },
+    request::{
+        method::{MethodFunction, MethodName, MethodObject},
+        Request,
+    },
+};
+use utils::{
+    config::Rate,
+    listener::limiter::{ConcurrencyLimiter, InFlight},
+};
 
-use crate::JMAP;
+use crate::{api::HttpResponse, JMAP};
 
 use super::AccessToken;
 
 pub struct ConcurrencyLimiters {
     pub concurrent_requests: ConcurrencyLimiter,
     pub concurrent_uploads: ConcurrencyLimiter,
+    pub concurrent_downloads: ConcurrencyLimiter,
+}
+
+/// Usage of the most constrained API rate limit a request was counted
+/// against, reported to clients through the RateLimit headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiRateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset: u64,
+    pub exceeded: bool,
 }
 
 impl JMAP {
@@ -46,6 +71,9 @@ impl JMAP {
                         self.config.request_max_concurrent,
                     ),
                     concurrent_uploads: ConcurrencyLimiter::new(self.config.upload_max_concurrent),
+                    concurrent_downloads: ConcurrencyLimiter::new(
+                        self.config.download_max_concurrent,
+                    ),
                 });
                 self.concurrency_limiter.insert(account_id, limiter.clone());
                 limiter
@@ -62,7 +90,7 @@ impl JMAP {
             .lookup_store
             .is_rate_allowed(
                 format!("j:{}", access_token.primary_id).as_bytes(),
-                &self.config.rate_authenticated,
+                &self.config.rate_limits.load_full().authenticated,
                 false,
             )
             .await
@@ -83,12 +111,88 @@ impl JMAP {
         }
     }
 
+    /// Counts the method calls of a JMAP request against the account's API
+    /// rate limits, expensive methods such as queries have their own bucket.
+    pub async fn is_api_request_allowed(
+        &self,
+        access_token: &AccessToken,
+        request: &Request,
+    ) -> Result<Option<ApiRateLimit>, RequestError> {
+        let rate_limits = self.config.rate_limits.load_full();
+        let expensive = request
+            .method_calls
+            .iter()
+            .filter(|call| is_expensive_method(&call.name))
+            .count() as u64;
+        let cheap = request.method_calls.len() as u64 - expensive;
+
+        self.api_rate_usage([
+            (
+                format!("japi:c:{}", access_token.primary_id),
+                &rate_limits.api_cheap,
+                cheap,
+            ),
+            (
+                format!("japi:e:{}", access_token.primary_id),
+                &rate_limits.api_expensive,
+                expensive,
+            ),
+        ])
+        .await
+    }
+
+    pub async fn is_management_request_allowed(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<Option<ApiRateLimit>, RequestError> {
+        self.api_rate_usage([(
+            format!("jmgmt:{}", access_token.primary_id),
+            &self.config.rate_limits.load_full().management,
+            1,
+        )])
+        .await
+    }
+
+    async fn api_rate_usage<const N: usize>(
+        &self,
+        buckets: [(String, &Option<Rate>, u64); N],
+    ) -> Result<Option<ApiRateLimit>, RequestError> {
+        let mut result: Option<ApiRateLimit> = None;
+        for (key, rate, requests) in buckets {
+            let rate = match rate {
+                Some(rate) if requests > 0 => rate,
+                _ => continue,
+            };
+            let (total, reset) = self
+                .lookup_store
+                .rate_usage(key.as_bytes(), rate, requests)
+                .await
+                .map_err(|_| RequestError::internal_server_error())?;
+            let usage = ApiRateLimit {
+                limit: rate.requests,
+                remaining: rate.requests.saturating_sub(total),
+                reset,
+                exceeded: total > rate.requests,
+            };
+
+            // Report the bucket closest to being exhausted
+            if result.map_or(true, |result| {
+                (usage.exceeded, std::cmp::Reverse(usage.remaining))
+                    > (result.exceeded, std::cmp::Reverse(result.remaining))
+            }) {
+                result = Some(usage);
+            }
+        }
+
+        Ok(result)
+    }
+
     pub async fn is_anonymous_allowed(&self, addr: &IpAddr) -> Result<(), RequestError> {
         if self
             .lookup_store
             .is_rate_allowed(
                 format!("jreq:{}", addr).as_bytes(),
-                &self.config.rate_anonymous,
+                &self.config.rate_limits.load_full().anonymous,
                 false,
             )
             .await
@@ -115,12 +219,64 @@ impl JMAP {
         }
     }
 
+    pub async fn is_download_allowed(
+        &self,
+        access_token: &AccessToken,
+        remote_ip: &IpAddr,
+    ) -> Result<(InFlight, InFlight), RequestError> {
+        if access_token.is_super_user() {
+            return Ok((InFlight::default(), InFlight::default()));
+        }
+
+        // Enforce download rates
+        let rate_limits = self.config.rate_limits.load_full();
+        for (key, rate) in [
+            (
+                format!("jdl:{}", access_token.primary_id),
+                &rate_limits.download_account,
+            ),
+            (format!("jdlip:{}", remote_ip), &rate_limits.download_ip),
+        ] {
+            if let Some(rate) = rate {
+                if self
+                    .lookup_store
+                    .is_rate_allowed(key.as_bytes(), rate, false)
+                    .await
+                    .map_err(|_| RequestError::internal_server_error())?
+                    .is_some()
+                {
+                    return Err(RequestError::too_many_requests());
+                }
+            }
+        }
+
+        // Limit concurrent transfers per account and per IP address
+        let in_flight_account = self
+            .get_concurrency_limiter(access_token.primary_id())
+            .concurrent_downloads
+            .is_allowed()
+            .ok_or_else(RequestError::too_many_requests)?;
+        let in_flight_ip = self
+            .download_limiter
+            .entry(*remote_ip)
+            .or_insert_with(|| {
+                Arc::new(ConcurrencyLimiter::new(
+                    self.config.download_max_concurrent_ip,
+                ))
+            })
+            .clone()
+            .is_allowed()
+            .ok_or_else(RequestError::too_many_requests)?;
+
+        Ok((in_flight_account, in_flight_ip))
+    }
+
     pub async fn is_auth_allowed_soft(&self, addr: &IpAddr) -> Result<(), RequestError> {
         if self
             .lookup_store
             .is_rate_allowed(
                 format!("jauth:{}", addr).as_bytes(),
-                &self.config.rate_authenticate_req,
+                &self.config.rate_limits.load_full().authenticate_req,
                 true,
             )
             .await
@@ -138,7 +294,7 @@ impl JMAP {
             .lookup_store
             .is_rate_allowed(
                 format!("jauth:{}", addr).as_bytes(),
-                &self.config.rate_authenticate_req,
+                &self.config.rate_limits.load_full().authenticate_req,
                 false,
             )
             .await
@@ -150,10 +306,146 @@ impl JMAP {
             Err(RequestError::too_many_auth_attempts())
         }
     }
+
+    pub async fn is_principal_lookup_allowed(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<(), MethodError> {
+        if access_token.is_super_user() {
+            return Ok(());
+        }
+
+        if let Some(rate) = &self.config.rate_limits.load_full().principal_lookup {
+            if self
+                .lookup_store
+                .is_rate_allowed(
+                    format!("jplk:{}", access_token.primary_id).as_bytes(),
+                    rate,
+                    true,
+                )
+                .await
+                .map_err(|_| MethodError::ServerPartialFail)?
+                .is_some()
+            {
+                self.penalize_principal_lookups(access_token, rate).await;
+
+                return Err(MethodError::Forbidden(
+                    "Too many principal lookups, try again later.".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts lookups of principals that do not exist, which is what address
+    /// book harvesting looks like.
+    pub async fn register_principal_lookup_misses(
+        &self,
+        access_token: &AccessToken,
+        misses: usize,
+    ) -> Result<(), MethodError> {
+        if access_token.is_super_user() {
+            return Ok(());
+        }
+
+        if let Some(rate) = &self.config.rate_limits.load_full().principal_lookup {
+            for _ in 0..misses {
+                self.lookup_store
+                    .is_rate_allowed(
+                        format!("jplk:{}", access_token.primary_id).as_bytes(),
+                        rate,
+                        false,
+                    )
+                    .await
+                    .map_err(|_| MethodError::ServerPartialFail)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lowers the reputation of the addresses of an account that exceeded
+    /// the principal lookup rate, once per rate period.
+    async fn penalize_principal_lookups(&self, access_token: &AccessToken, rate: &Rate) {
+        let score = self.config.principal_enumeration_score;
+        let penalty_key = format!("jplp:{}", access_token.primary_id).into_bytes();
+        if score == 0.0
+            || self
+                .lookup_store
+                .key_exists(penalty_key.clone())
+                .await
+                .unwrap_or(true)
+        {
+            return;
+        }
+
+        tracing::info!(
+            context = "principal_lookup",
+            event = "rate-limited",
+            account_id = access_token.primary_id,
+            "Account exceeded the principal lookup rate, possible address book harvesting."
+        );
+
+        if let Err(err) = self
+            .lookup_store
+            .key_set(penalty_key, vec![], Some(rate.period.as_secs()))
+            .await
+        {
+            tracing::warn!(
+                context = "principal_lookup",
+                event = "error",
+                reason = ?err,
+                "Failed to store principal lookup penalty.");
+            return;
+        }
+
+        if let Ok(Some(principal)) = self
+            .directory
+            .query(QueryBy::Id(access_token.primary_id), false)
+            .await
+        {
+            for email in principal.emails {
+                self.smtp
+                    .update_reputation(format!("f:{}", email.to_lowercase()), score)
+                    .await;
+            }
+        }
+    }
+}
+
+impl ApiRateLimit {
+    pub fn add_headers(&self, response: &mut HttpResponse) {
+        let headers = response.headers_mut();
+        for (name, value) in [
+            ("ratelimit-limit", self.limit),
+            ("ratelimit-remaining", self.remaining),
+            ("ratelimit-reset", self.reset),
+        ] {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        }
+        if self.exceeded {
+            headers.insert(RETRY_AFTER, HeaderValue::from(self.reset));
+        }
+    }
+}
+
+fn is_expensive_method(name: &MethodName) -> bool {
+    matches!(
+        (name.obj, name.fnc),
+        (_, MethodFunction::Query)
+            | (_, MethodFunction::QueryChanges)
+            | (_, MethodFunction::Copy)
+            | (_, MethodFunction::Import)
+            | (_, MethodFunction::Parse)
+            | (MethodObject::SearchSnippet, MethodFunction::Get)
+    )
 }
 
 impl ConcurrencyLimiters {
     pub fn is_active(&self) -> bool {
-        self.concurrent_requests.is_active() || self.concurrent_uploads.is_active()
+        self.concurrent_requests.is_active()
+            || self.concurrent_uploads.is_active()
+            || self.concurrent_downloads.is_active()
     }
 }
@@ -26,6 +26,7 @@ use jmap_proto::types::{blob::BlobId, id::Id};
 pub mod copy;
 pub mod download;
 pub mod get;
+pub mod resumable;
 pub mod upload;
 
 #[derive(Debug, serde::Serialize)]
@@ -39,6 +40,19 @@ pub struct UploadResponse {
     size: usize,
 }
 
+pub enum ResumableUploadResponse {
+    InProgress(UploadStatus),
+    Completed(UploadResponse),
+}
+
+pub struct UploadStatus {
+    pub account_id: Id,
+    pub upload_id: Id,
+    pub offset: usize,
+    pub length: usize,
+    pub created: bool,
+}
+
 pub struct DownloadResponse {
     pub filename: String,
     pub content_type: String,
@@ -0,0 +1,460 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap_proto::{
+    error::request::{RequestError, RequestLimitError},
+    types::{blob::BlobId, id::Id},
+};
+use store::{
+    write::{
+        assert::{AssertValue, HashedValue},
+        key::DeserializeBigEndian,
+        now, BatchBuilder, Bincode, BlobOp, ValueClass,
+    },
+    BlobClass, Deserialize, IterateParams, Serialize, ValueKey, U32_LEN,
+};
+use utils::BlobHash;
+
+use crate::{auth::AccessToken, JMAP};
+
+use super::{ResumableUploadResponse, UploadResponse, UploadStatus};
+
+/// An upload sent over several requests. Each part is stored as a temporary
+/// blob reserved until the upload expires, so parts left behind by abandoned
+/// uploads are removed by the regular blob purge.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UploadSession {
+    pub content_type: String,
+    pub length: usize,
+    pub offset: usize,
+    pub parts: Vec<BlobHash>,
+    pub expires: u64,
+}
+
+impl JMAP {
+    pub async fn resumable_upload_create(
+        &self,
+        account_id: Id,
+        content_type: &str,
+        length: usize,
+        data: &[u8],
+        access_token: Arc<AccessToken>,
+    ) -> Result<ResumableUploadResponse, RequestError> {
+        if (length > self.config.upload_resumable_max_size
+            || data.len() > self.config.upload_resumable_chunk_size)
+            && !access_token.is_super_user()
+        {
+            return Err(RequestError::limit(RequestLimitError::SizeUpload));
+        } else if data.len() > length {
+            return Err(RequestError::invalid_parameters());
+        }
+
+        let session = UploadSession {
+            content_type: content_type.to_string(),
+            length,
+            offset: 0,
+            parts: Vec::new(),
+            expires: now() + self.config.upload_resumable_ttl,
+        };
+
+        self.resumable_upload_write(
+            account_id,
+            Id::from(rand::random::<u64>()),
+            session,
+            AssertValue::None,
+            data,
+            &access_token,
+        )
+        .await
+    }
+
+    pub async fn resumable_upload_append(
+        &self,
+        account_id: Id,
+        upload_id: Id,
+        offset: usize,
+        data: &[u8],
+        access_token: Arc<AccessToken>,
+    ) -> Result<ResumableUploadResponse, RequestError> {
+        let (session, assert_value) = self
+            .get_upload_session(account_id, upload_id)
+            .await?
+            .ok_or_else(RequestError::not_found)?;
+
+        if offset != session.offset {
+            return Err(RequestError::blank(
+                409,
+                "Conflict",
+                "The upload offset does not match the number of bytes received.",
+            ));
+        } else if offset + data.len() > session.length {
+            return Err(RequestError::invalid_parameters());
+        } else if data.len() > self.config.upload_resumable_chunk_size
+            && !access_token.is_super_user()
+        {
+            return Err(RequestError::limit(RequestLimitError::SizeUpload));
+        }
+
+        self.resumable_upload_write(
+            account_id,
+            upload_id,
+            session,
+            assert_value,
+            data,
+            &access_token,
+        )
+        .await
+    }
+
+    pub async fn resumable_upload_status(
+        &self,
+        account_id: Id,
+        upload_id: Id,
+    ) -> Result<ResumableUploadResponse, RequestError> {
+        self.get_upload_session(account_id, upload_id)
+            .await?
+            .map(|(session, _)| {
+                ResumableUploadResponse::InProgress(UploadStatus {
+                    account_id,
+                    upload_id,
+                    offset: session.offset,
+                    length: session.length,
+                    created: false,
+                })
+            })
+            .ok_or_else(RequestError::not_found)
+    }
+
+    pub async fn resumable_upload_cancel(
+        &self,
+        account_id: Id,
+        upload_id: Id,
+    ) -> Result<(), RequestError> {
+        let (session, assert_value) = self
+            .get_upload_session(account_id, upload_id)
+            .await?
+            .ok_or_else(RequestError::not_found)?;
+
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(account_id.document_id());
+        for hash in session.parts {
+            batch.clear(BlobOp::Reserve {
+                hash,
+                until: session.expires,
+            });
+        }
+        batch
+            .assert_value(ValueClass::UploadSession(upload_id.id()), assert_value)
+            .clear(ValueClass::UploadSession(upload_id.id()));
+        self.write_upload_session(batch).await
+    }
+
+    async fn resumable_upload_write(
+        &self,
+        account_id: Id,
+        upload_id: Id,
+        mut session: UploadSession,
+        assert_value: AssertValue,
+        data: &[u8],
+        access_token: &AccessToken,
+    ) -> Result<ResumableUploadResponse, RequestError> {
+        // Limit concurrent uploads
+        let _in_flight = self.is_upload_allowed(access_token)?;
+
+        // Store the received bytes as a new part
+        if !data.is_empty() {
+            self.assert_upload_quota(account_id, data.len(), access_token)
+                .await?;
+            let part = self
+                .put_blob_until(account_id.document_id(), data, true, session.expires)
+                .await
+                .map_err(|_| RequestError::internal_server_error())?;
+            session.parts.push(part.hash);
+            session.offset += data.len();
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id.document_id())
+            .assert_value(ValueClass::UploadSession(upload_id.id()), assert_value);
+
+        if session.offset < session.length {
+            let status = UploadStatus {
+                account_id,
+                upload_id,
+                offset: session.offset,
+                length: session.length,
+                created: assert_value.is_none(),
+            };
+            batch.set(
+                ValueClass::UploadSession(upload_id.id()),
+                Bincode::new(session).serialize(),
+            );
+            self.write_upload_session(batch).await?;
+
+            return Ok(ResumableUploadResponse::InProgress(status));
+        }
+
+        // All bytes were received, assemble the blob from its parts
+        let blob_id = self
+            .resumable_upload_complete(account_id.document_id(), &session)
+            .await?;
+        for hash in session.parts {
+            batch.clear(BlobOp::Reserve {
+                hash,
+                until: session.expires,
+            });
+        }
+        if !assert_value.is_none() {
+            batch.clear(ValueClass::UploadSession(upload_id.id()));
+        }
+        self.write_upload_session(batch).await?;
+
+        Ok(ResumableUploadResponse::Completed(UploadResponse {
+            account_id,
+            blob_id,
+            c_type: session.content_type,
+            size: session.length,
+        }))
+    }
+
+    async fn resumable_upload_complete(
+        &self,
+        account_id: u32,
+        session: &UploadSession,
+    ) -> Result<BlobId, RequestError> {
+        // Hash the parts in order without holding the whole blob in memory
+        let mut hasher = blake3::Hasher::new();
+        for part in &session.parts {
+            match self.get_blob(part, 0..u32::MAX).await {
+                Ok(Some(data)) => {
+                    hasher.update(&data);
+                }
+                Ok(None) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "resumable_upload",
+                        account_id = account_id,
+                        blob_id = ?part,
+                        "Upload part not found.");
+                    return Err(RequestError::internal_server_error());
+                }
+                Err(_) => return Err(RequestError::internal_server_error()),
+            }
+        }
+        let hash = BlobHash::try_from_hash_slice(hasher.finalize().as_bytes()).unwrap();
+
+        // Reserve the hash
+        let until = now() + self.config.upload_tmp_ttl;
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(account_id).set(
+            BlobOp::Reserve {
+                hash: hash.clone(),
+                until,
+            },
+            (session.length as u32).serialize(),
+        );
+        self.write_batch(batch)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        let exists = self.store.blob_exists(&hash).await.map_err(|err| {
+            tracing::error!(
+                event = "error",
+                context = "resumable_upload",
+                error = ?err,
+                "Failed to verify blob hash existence.");
+            RequestError::internal_server_error()
+        })?;
+        if !exists {
+            // Write the blob from its parts
+            let part_keys = session
+                .parts
+                .iter()
+                .map(|part| part.as_ref())
+                .collect::<Vec<_>>();
+            match self
+                .blob_store
+                .put_blob_from_parts(hash.as_ref(), &part_keys)
+                .await
+            {
+                Ok(true) => (),
+                Ok(false) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "resumable_upload",
+                        account_id = account_id,
+                        "Upload part not found."
+                    );
+                    return Err(RequestError::internal_server_error());
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "resumable_upload",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to store blob from upload parts.");
+                    return Err(RequestError::internal_server_error());
+                }
+            }
+
+            // Commit blob
+            let mut batch = BatchBuilder::new();
+            batch.set(BlobOp::Commit { hash: hash.clone() }, Vec::new());
+            self.write_batch(batch)
+                .await
+                .map_err(|_| RequestError::internal_server_error())?;
+        }
+
+        Ok(BlobId {
+            hash,
+            class: BlobClass::Reserved {
+                account_id,
+                expires: until,
+            },
+            section: None,
+        })
+    }
+
+    async fn get_upload_session(
+        &self,
+        account_id: Id,
+        upload_id: Id,
+    ) -> Result<Option<(UploadSession, AssertValue)>, RequestError> {
+        self.store
+            .get_value::<HashedValue<Bincode<UploadSession>>>(ValueKey {
+                account_id: account_id.document_id(),
+                collection: 0,
+                document_id: 0,
+                class: ValueClass::UploadSession(upload_id.id()),
+            })
+            .await
+            .map(|session| {
+                session
+                    .filter(|session| session.inner.inner.expires > now())
+                    .map(|session| (session.inner.inner, AssertValue::Hash(session.hash)))
+            })
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "resumable_upload",
+                    account_id = account_id.document_id(),
+                    error = ?err,
+                    "Failed to read upload session.");
+                RequestError::internal_server_error()
+            })
+    }
+
+    async fn write_upload_session(&self, batch: BatchBuilder) -> Result<(), RequestError> {
+        self.store
+            .write(batch.build())
+            .await
+            .map_err(|err| match err {
+                store::Error::AssertValueFailed => RequestError::blank(
+                    409,
+                    "Conflict",
+                    "The upload was modified by another request.",
+                ),
+                store::Error::InternalError(err) => {
+                    tracing::error!(
+                    event = "error",
+                    context = "resumable_upload",
+                    error = ?err,
+                    "Failed to write upload session.");
+                    RequestError::internal_server_error()
+                }
+            })
+    }
+
+    pub async fn purge_upload_sessions(&self) {
+        let now = now();
+        let mut expired = Vec::new();
+        let result = self
+            .store
+            .iterate(
+                IterateParams::new(
+                    ValueKey {
+                        account_id: 0,
+                        collection: 0,
+                        document_id: 0,
+                        class: ValueClass::UploadSession(0),
+                    },
+                    ValueKey {
+                        account_id: u32::MAX,
+                        collection: 0,
+                        document_id: 0,
+                        class: ValueClass::UploadSession(u64::MAX),
+                    },
+                )
+                .ascending(),
+                |key, value| {
+                    if Bincode::<UploadSession>::deserialize(value)?.inner.expires <= now {
+                        expired.push((
+                            key.deserialize_be_u32(1)?,
+                            key.deserialize_be_u64(1 + U32_LEN)?,
+                        ));
+                    }
+                    Ok(true)
+                },
+            )
+            .await;
+        if let Err(err) = result {
+            tracing::error!(
+                context = "resumable_upload",
+                event = "error",
+                error = ?err,
+                "Failed to obtain expired upload sessions."
+            );
+            return;
+        }
+
+        // Parts are reserved until the session expires and are removed by the blob purge
+        let num_expired = expired.len();
+        let mut batch = BatchBuilder::new();
+        for (account_id, upload_id) in expired {
+            batch
+                .with_account_id(account_id)
+                .clear(ValueClass::UploadSession(upload_id));
+        }
+        if !batch.is_empty() {
+            if let Err(err) = self.store.write(batch.build()).await {
+                tracing::error!(
+                    context = "resumable_upload",
+                    event = "error",
+                    error = ?err,
+                    "Failed to remove expired upload sessions."
+                );
+            } else {
+                tracing::info!(
+                    context = "resumable_upload",
+                    event = "purge",
+                    expired = num_expired,
+                    "Removed expired upload sessions."
+                );
+            }
+        }
+    }
+}
@@ -214,6 +214,26 @@ impl JMAP {
         }
 
         // Enforce quota
+        self.assert_upload_quota(account_id, data.len(), &access_token)
+            .await?;
+
+        Ok(UploadResponse {
+            account_id,
+            blob_id: self
+                .put_blob(account_id.document_id(), data, true)
+                .await
+                .map_err(|_| RequestError::internal_server_error())?,
+            c_type: content_type.to_string(),
+            size: data.len(),
+        })
+    }
+
+    pub(crate) async fn assert_upload_quota(
+        &self,
+        account_id: Id,
+        size: usize,
+        access_token: &AccessToken,
+    ) -> Result<(), RequestError> {
         let used = self
             .store
             .blob_quota(account_id.document_id())
@@ -228,7 +248,7 @@ impl JMAP {
             })?;
 
         if ((self.config.upload_tmp_quota_size > 0
-            && used.bytes + data.len() > self.config.upload_tmp_quota_size)
+            && used.bytes + size > self.config.upload_tmp_quota_size)
             || (self.config.upload_tmp_quota_amount > 0
                 && used.count + 1 > self.config.upload_tmp_quota_amount))
             && !access_token.is_super_user()
@@ -247,28 +267,35 @@ impl JMAP {
             return err;
         }
 
-        Ok(UploadResponse {
+        Ok(())
+    }
+
+    pub async fn put_blob(
+        &self,
+        account_id: u32,
+        data: &[u8],
+        set_quota: bool,
+    ) -> Result<BlobId, MethodError> {
+        self.put_blob_until(
             account_id,
-            blob_id: self
-                .put_blob(account_id.document_id(), data, true)
-                .await
-                .map_err(|_| RequestError::internal_server_error())?,
-            c_type: content_type.to_string(),
-            size: data.len(),
-        })
+            data,
+            set_quota,
+            now() + self.config.upload_tmp_ttl,
+        )
+        .await
     }
 
     #[allow(clippy::blocks_in_conditions)]
-    pub async fn put_blob(
+    pub async fn put_blob_until(
         &self,
         account_id: u32,
         data: &[u8],
         set_quota: bool,
+        until: u64,
     ) -> Result<BlobId, MethodError> {
         // First reserve the hash
         let hash = BlobHash::from(data);
         let mut batch = BatchBuilder::new();
-        let until = now() + self.config.upload_tmp_ttl;
 
         batch.with_account_id(account_id).set(
             BlobOp::Reserve {
@@ -130,6 +130,11 @@ impl JMAP {
             }
         };
 
+        // Changes older than the last compaction can no longer be calculated
+        if changelog.is_truncated {
+            return Err(MethodError::CannotCalculateChanges);
+        }
+
         if max_changes > 0 && changelog.changes.len() > max_changes {
             changelog
                 .changes
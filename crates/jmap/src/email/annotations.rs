@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    object::Object,
+    types::{collection::Collection, property::Property, value::Value},
+};
+use store::write::{BatchBuilder, F_CLEAR, F_VALUE};
+
+use crate::JMAP;
+
+pub const MAX_ANNOTATION_KEY_LEN: usize = 255;
+
+impl JMAP {
+    pub async fn get_email_annotations(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<Object<Value>, MethodError> {
+        self.get_property::<Object<Value>>(
+            account_id,
+            Collection::Email,
+            document_id,
+            Property::Annotations,
+        )
+        .await
+        .map(Option::unwrap_or_default)
+    }
+
+    pub fn validate_annotations(&self, annotations: &Object<Value>) -> Result<(), SetError> {
+        if annotations.properties.len() > self.config.annotations_max_entries {
+            return Err(
+                SetError::new(SetErrorType::OverQuota).with_description(format!(
+                    "Messages can have at most {} annotations.",
+                    self.config.annotations_max_entries
+                )),
+            );
+        }
+
+        let mut size = 0;
+        for (key, value) in annotations.properties.iter() {
+            match (key, value) {
+                (Property::_T(key), Value::Text(value))
+                    if !key.is_empty()
+                        && key.len() <= MAX_ANNOTATION_KEY_LEN
+                        && !key.chars().any(|ch| ch.is_control()) =>
+                {
+                    size += key.len() + value.len();
+                }
+                _ => {
+                    return Err(SetError::invalid_properties()
+                        .with_property(Property::Annotations)
+                        .with_description(format!(
+                            "Invalid annotation {key:?}, values must be strings."
+                        )));
+                }
+            }
+        }
+
+        if size > self.config.annotations_max_size {
+            Err(
+                SetError::new(SetErrorType::OverQuota).with_description(format!(
+                    "Annotations exceed the maximum size of {} bytes.",
+                    self.config.annotations_max_size
+                )),
+            )
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn update_annotations(batch: &mut BatchBuilder, annotations: &Object<Value>) {
+    if !annotations.properties.is_empty() {
+        batch.value(Property::Annotations, annotations, F_VALUE);
+    } else {
+        batch.value(Property::Annotations, (), F_VALUE | F_CLEAR);
+    }
+}
@@ -0,0 +1,98 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::Object,
+    types::{collection::Collection, property::Property, value::Value},
+};
+use mail_parser::Message;
+
+use crate::JMAP;
+
+/// Returns whether the message carries the markers of bulk or mailing list
+/// traffic, such as newsletters and marketing campaigns.
+pub fn is_bulk_message(message: &Message<'_>) -> bool {
+    let mut has_unsubscribe = false;
+    let mut has_one_click = false;
+
+    for header in message.headers() {
+        let name = header.name.as_str();
+        if name.eq_ignore_ascii_case("List-Id") {
+            return true;
+        } else if name.eq_ignore_ascii_case("Precedence") {
+            if let Some(value) = header.value.as_text() {
+                let value = value.trim();
+                if ["bulk", "list", "junk"]
+                    .iter()
+                    .any(|v| value.eq_ignore_ascii_case(v))
+                {
+                    return true;
+                }
+            }
+        } else if name.eq_ignore_ascii_case("List-Unsubscribe") {
+            has_unsubscribe = true;
+        } else if name.eq_ignore_ascii_case("List-Unsubscribe-Post") {
+            // RFC 8058 one-click unsubscription
+            has_one_click = header.value.as_text().map_or(false, |value| {
+                value
+                    .trim()
+                    .eq_ignore_ascii_case("List-Unsubscribe=One-Click")
+            });
+        }
+    }
+
+    has_unsubscribe && has_one_click
+}
+
+impl JMAP {
+    /// Returns the mailbox bulk mail should be filed into, or `None` if the
+    /// account has not enabled bulk filing or the message is not bulk mail.
+    pub async fn bulk_mailbox_id(
+        &self,
+        account_id: u32,
+        settings: &Object<Value>,
+        message: &Message<'_>,
+    ) -> Result<Option<u32>, MethodError> {
+        if settings.get(&Property::BulkFiling) != &Value::Bool(true) || !is_bulk_message(message) {
+            return Ok(None);
+        }
+
+        // Use the configured mailbox if it still exists
+        if let Value::Id(mailbox_id) = settings.get(&Property::BulkMailboxId) {
+            let mailbox_id = mailbox_id.document_id();
+            if self
+                .get_document_ids(account_id, Collection::Mailbox)
+                .await?
+                .map_or(false, |ids| ids.contains(mailbox_id))
+            {
+                return Ok(Some(mailbox_id));
+            }
+        }
+
+        Ok(self
+            .mailbox_create_path(account_id, &self.config.bulk_mailbox)
+            .await?
+            .map(|(mailbox_id, _)| mailbox_id))
+    }
+}
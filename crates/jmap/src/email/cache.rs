@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{ops::Range, sync::Arc};
+
+use jmap_proto::error::method::MethodError;
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+use utils::{config::Config, BlobHash};
+
+use crate::JMAP;
+
+/// Keeps the raw contents of recently accessed messages, keyed by blob hash,
+/// so that popular messages are not fetched from the blob store every time
+/// they are requested by IMAP FETCH or JMAP Email/get. Messages are only
+/// partially cached when just their headers were requested.
+pub struct MessageCache {
+    inner: Mutex<MessageCacheInner>,
+    max_size: usize,
+    max_item_size: usize,
+}
+
+struct MessageCacheInner {
+    entries: LruCache<BlobHash, CachedMessage, ahash::RandomState>,
+    size: usize,
+}
+
+#[derive(Clone)]
+struct CachedMessage {
+    contents: Arc<Vec<u8>>,
+    is_complete: bool,
+}
+
+impl MessageCache {
+    pub fn new(max_size: usize, max_item_size: usize) -> Self {
+        MessageCache {
+            inner: Mutex::new(MessageCacheInner {
+                entries: LruCache::with_hasher(usize::MAX, ahash::RandomState::new()),
+                size: 0,
+            }),
+            max_size,
+            max_item_size: std::cmp::min(max_item_size, max_size),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> utils::config::Result<Self> {
+        Ok(MessageCache::new(
+            config.property_or_static("jmap.email.cache.size", "33554432")?,
+            config.property_or_static("jmap.email.cache.max-message-size", "65536")?,
+        ))
+    }
+
+    /// Returns the requested range of a message if it can be served from the cache.
+    pub fn get(&self, blob_hash: &BlobHash, range: &Range<u32>) -> Option<Vec<u8>> {
+        if self.max_item_size == 0 {
+            return None;
+        }
+        let cached = self.inner.lock().entries.get_mut(blob_hash)?.clone();
+        let len = cached.contents.len();
+        if cached.is_complete || range.end as usize <= len {
+            let end = std::cmp::min(range.end as usize, len);
+            Some(
+                cached
+                    .contents
+                    .get(range.start as usize..end)
+                    .unwrap_or_default()
+                    .to_vec(),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Caches the bytes returned by the blob store for a range starting at
+    /// the beginning of the message.
+    pub fn insert(&self, blob_hash: &BlobHash, range: &Range<u32>, contents: &[u8]) {
+        if range.start != 0 || contents.len() > self.max_item_size {
+            return;
+        }
+        let is_complete = range.end == u32::MAX || contents.len() < range.end as usize;
+
+        let mut inner = self.inner.lock();
+        if let Some(cached) = inner.entries.get_mut(blob_hash) {
+            if cached.is_complete || cached.contents.len() >= contents.len() {
+                return;
+            }
+        }
+        if let Some(prev) = inner.entries.insert(
+            blob_hash.clone(),
+            CachedMessage {
+                contents: Arc::new(contents.to_vec()),
+                is_complete,
+            },
+        ) {
+            inner.size -= prev.contents.len();
+        }
+        inner.size += contents.len();
+        while inner.size > self.max_size {
+            if let Some((_, evicted)) = inner.entries.remove_lru() {
+                inner.size -= evicted.contents.len();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn remove(&self, blob_hash: &BlobHash) {
+        let mut inner = self.inner.lock();
+        if let Some(cached) = inner.entries.remove(blob_hash) {
+            inner.size -= cached.contents.len();
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+}
+
+impl JMAP {
+    /// Retrieves a range of a message, serving it from the message cache when possible.
+    pub async fn get_message_blob(
+        &self,
+        blob_hash: &BlobHash,
+        range: Range<u32>,
+    ) -> Result<Option<Vec<u8>>, MethodError> {
+        if let Some(contents) = self.message_cache.get(blob_hash, &range) {
+            return Ok(Some(contents));
+        }
+
+        let contents = self.get_blob(blob_hash, range.clone()).await?;
+        if let Some(contents) = &contents {
+            self.message_cache.insert(blob_hash, &range, contents);
+        }
+        Ok(contents)
+    }
+}
@@ -53,12 +53,19 @@ use store::{
 };
 use utils::map::vec_map::VecMap;
 
-use crate::{auth::AccessToken, mailbox::UidMailbox, services::housekeeper::Event, JMAP};
+use crate::{
+    auth::AccessToken,
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+    services::housekeeper::Event,
+    JMAP,
+};
 
 use super::{
     index::{EmailIndexBuilder, TrimTextValue, VisitValues, MAX_ID_LENGTH, MAX_SORT_FIELD_LENGTH},
     ingest::IngestedEmail,
     metadata::MessageMetadata,
+    signature::SignatureStatus,
+    stats::BodyStats,
 };
 
 impl JMAP {
@@ -309,9 +316,27 @@ impl JMAP {
             ))));
         };
 
+        let signature = self
+            .get_property::<Bincode<SignatureStatus>>(
+                from_account_id,
+                Collection::Email,
+                from_message_id,
+                Property::Signature,
+            )
+            .await?;
+        let body_stats = self
+            .get_property::<Bincode<Vec<BodyStats>>>(
+                from_account_id,
+                Collection::Email,
+                from_message_id,
+                Property::BodyStats,
+            )
+            .await?;
+
         // Check quota
-        if account_quota > 0
-            && metadata.size as i64 + self.get_used_quota(account_id).await? > account_quota
+        if self
+            .is_over_quota(account_id, account_quota, metadata.size as i64)
+            .await?
         {
             return Ok(Err(SetError::over_quota()));
         }
@@ -405,6 +430,11 @@ impl JMAP {
         batch
             .with_collection(Collection::Email)
             .create_document(message_id)
+            .add_to_mailbox_counters(
+                mailboxes.iter().copied(),
+                keywords.contains(&Keyword::Seen),
+                metadata.size as u32,
+            )
             .value(Property::ThreadId, thread_id, F_VALUE | F_BITMAP)
             .value(
                 Property::MailboxIds,
@@ -422,6 +452,12 @@ impl JMAP {
             )
             .custom(EmailIndexBuilder::set(metadata))
             .custom(changes);
+        if let Some(signature) = signature {
+            batch.value(Property::Signature, signature, F_VALUE);
+        }
+        if let Some(body_stats) = body_stats {
+            batch.value(Property::BodyStats, body_stats, F_VALUE);
+        }
 
         self.store.write(batch.build()).await.map_err(|err| {
             tracing::error!(
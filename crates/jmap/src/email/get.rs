@@ -45,6 +45,7 @@ use super::{
     body::{ToBodyPart, TruncateBody},
     headers::IntoForm,
     metadata::{MessageMetadata, MetadataPartType},
+    signature::SignatureStatus,
 };
 
 impl JMAP {
@@ -129,20 +130,26 @@ impl JMAP {
             not_found: vec![],
         };
 
-        // Check if we need to fetch the raw headers or body
+        // Check if we need to fetch the raw headers or body, body parts are built
+        // from the stored metadata unless their headers are requested
         let mut needs_headers = false;
         let mut needs_body = false;
+        let body_needs_headers = body_properties
+            .iter()
+            .any(|property| matches!(property, Property::Header(_) | Property::Headers));
         for property in &properties {
             match property {
                 Property::Header(_) | Property::Headers => {
                     needs_headers = true;
                 }
-                Property::BodyValues
-                | Property::TextBody
+                Property::BodyValues => {
+                    needs_body = true;
+                }
+                Property::TextBody
                 | Property::HtmlBody
                 | Property::Attachments
                 | Property::BodyStructure => {
-                    needs_body = true;
+                    needs_body = body_needs_headers;
                 }
                 _ => (),
             }
@@ -182,7 +189,10 @@ impl JMAP {
                     u32::MAX
                 };
 
-                if let Some(raw_message) = self.get_blob(&metadata.blob_hash, 0..offset).await? {
+                if let Some(raw_message) = self
+                    .get_message_blob(&metadata.blob_hash, 0..offset)
+                    .await?
+                {
                     raw_message
                 } else {
                     tracing::warn!(event = "not-found",
@@ -279,6 +289,29 @@ impl JMAP {
                             continue 'outer;
                         }
                     }
+                    Property::Annotations => {
+                        email.append(
+                            Property::Annotations,
+                            Value::Object(
+                                self.get_email_annotations(account_id, id.document_id())
+                                    .await?,
+                            ),
+                        );
+                    }
+                    Property::Signature => {
+                        email.append(
+                            Property::Signature,
+                            self.get_property::<Bincode<SignatureStatus>>(
+                                account_id,
+                                Collection::Email,
+                                id.document_id(),
+                                &Property::Signature,
+                            )
+                            .await?
+                            .map(|signature| signature.inner.into_value())
+                            .unwrap_or(Value::Null),
+                        );
+                    }
                     Property::Size => {
                         email.append(Property::Size, metadata.size);
                     }
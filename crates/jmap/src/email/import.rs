@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::{collections::HashMap, io::Cursor};
+
 use jmap_proto::{
     error::{
         method::MethodError,
@@ -29,17 +31,19 @@ use jmap_proto::{
     method::import::{ImportEmailRequest, ImportEmailResponse},
     types::{
         acl::Acl,
+        blob::BlobId,
         collection::Collection,
         id::Id,
+        keyword::Keyword,
         property::Property,
         state::{State, StateChange},
         type_state::DataType,
     },
 };
-use mail_parser::MessageParser;
+use mail_parser::{mailbox::mbox::MessageIterator, DateTime, MessageParser};
 use utils::map::vec_map::VecMap;
 
-use crate::{auth::AccessToken, IngestError, JMAP};
+use crate::{auth::AccessToken, mailbox::INBOX_ID, IngestError, JMAP};
 
 use super::ingest::IngestEmail;
 
@@ -142,6 +146,8 @@ impl JMAP {
                     received_at: email.received_at.map(|r| r.into()),
                     skip_duplicates: true,
                     encrypt: self.config.encrypt && self.config.encrypt_append,
+                    classify: false,
+                    trusted_sender: false,
                 })
                 .await
             {
@@ -182,3 +188,283 @@ impl JMAP {
         Ok(response)
     }
 }
+
+/// Request of the management import endpoint. Messages are either listed
+/// individually or read from an uploaded mbox archive, and are stored with
+/// the given dates, keywords and mailboxes without running any filters.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct BulkImportRequest {
+    #[serde(default)]
+    pub messages: Vec<BulkImportMessage>,
+    #[serde(rename = "archiveBlobId")]
+    pub archive_blob_id: Option<String>,
+    pub mailbox: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct BulkImportMessage {
+    pub message: Option<String>,
+    #[serde(rename = "blobId")]
+    pub blob_id: Option<String>,
+    #[serde(rename = "receivedAt")]
+    pub received_at: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub mailboxes: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BulkImportResponse {
+    pub imported: Vec<String>,
+    pub skipped: usize,
+    #[serde(rename = "notImported")]
+    pub not_imported: Vec<BulkImportError>,
+}
+
+/// Messages from the archive are numbered after the listed messages.
+#[derive(Debug, serde::Serialize)]
+pub struct BulkImportError {
+    pub index: usize,
+    pub reason: String,
+}
+
+struct PendingImport {
+    index: usize,
+    raw_message: Vec<u8>,
+    mailbox_ids: Vec<u32>,
+    keywords: Vec<Keyword>,
+    received_at: Option<u64>,
+}
+
+impl JMAP {
+    pub async fn email_import_bulk(
+        &self,
+        account_id: u32,
+        account_quota: i64,
+        request: BulkImportRequest,
+    ) -> Result<BulkImportResponse, MethodError> {
+        self.mailbox_get_or_create(account_id).await?;
+        let mut mailbox_paths = HashMap::new();
+        let mut response = BulkImportResponse::default();
+
+        // Messages from the archive and without mailboxes go to the default mailbox
+        let default_mailbox_id = if let Some(path) = &request.mailbox {
+            self.import_mailbox_id(account_id, path, &mut mailbox_paths)
+                .await?
+                .ok_or_else(|| {
+                    MethodError::InvalidArguments(format!("Invalid mailbox {path:?}."))
+                })?
+        } else {
+            INBOX_ID
+        };
+        let archive = if let Some(blob_id) = &request.archive_blob_id {
+            Some(
+                self.import_blob(account_id, blob_id)
+                    .await?
+                    .ok_or_else(|| {
+                        MethodError::InvalidArguments(format!("Archive {blob_id:?} not found."))
+                    })?,
+            )
+        } else {
+            None
+        };
+
+        let mut pending = Vec::with_capacity(request.messages.len());
+        let num_messages = request.messages.len();
+        'outer: for (index, message) in request.messages.into_iter().enumerate() {
+            // Obtain raw message
+            let raw_message = match (message.message, &message.blob_id) {
+                (Some(raw_message), None) => raw_message.into_bytes(),
+                (None, Some(blob_id)) => match self.import_blob(account_id, blob_id).await? {
+                    Some(raw_message) => raw_message,
+                    None => {
+                        response.not_imported.push(BulkImportError {
+                            index,
+                            reason: format!("BlobId {blob_id} not found."),
+                        });
+                        continue;
+                    }
+                },
+                _ => {
+                    response.not_imported.push(BulkImportError {
+                        index,
+                        reason: "Either message or blobId must be specified.".to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            // Parse received date
+            let received_at = match &message.received_at {
+                Some(received_at) => match DateTime::parse_rfc3339(received_at) {
+                    Some(received_at) => Some(received_at.to_timestamp() as u64),
+                    None => {
+                        response.not_imported.push(BulkImportError {
+                            index,
+                            reason: format!("Invalid receivedAt {received_at:?}."),
+                        });
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            // Resolve mailboxes, creating any missing ones
+            let mut mailbox_ids = Vec::with_capacity(message.mailboxes.len());
+            for path in &message.mailboxes {
+                if let Some(mailbox_id) = self
+                    .import_mailbox_id(account_id, path, &mut mailbox_paths)
+                    .await?
+                {
+                    mailbox_ids.push(mailbox_id);
+                } else {
+                    response.not_imported.push(BulkImportError {
+                        index,
+                        reason: format!("Invalid mailbox {path:?}."),
+                    });
+                    continue 'outer;
+                }
+            }
+            if mailbox_ids.is_empty() {
+                mailbox_ids.push(default_mailbox_id);
+            }
+
+            pending.push(PendingImport {
+                index,
+                raw_message,
+                mailbox_ids,
+                keywords: message.keywords.into_iter().map(Keyword::from).collect(),
+                received_at,
+            });
+        }
+
+        // Split archive, using the date of each "From " line as the received date
+        if let Some(archive) = archive {
+            let keywords = request
+                .keywords
+                .into_iter()
+                .map(Keyword::from)
+                .collect::<Vec<_>>();
+            for (index, message) in MessageIterator::new(Cursor::new(archive)).enumerate() {
+                let index = num_messages + index;
+                if let Ok(message) = message {
+                    pending.push(PendingImport {
+                        index,
+                        received_at: Some(message.internal_date()).filter(|date| *date > 0),
+                        raw_message: message.unwrap_contents(),
+                        mailbox_ids: vec![default_mailbox_id],
+                        keywords: keywords.clone(),
+                    });
+                } else {
+                    response.not_imported.push(BulkImportError {
+                        index,
+                        reason: "Failed to parse message from archive.".to_string(),
+                    });
+                    break;
+                }
+            }
+        }
+
+        // Import messages
+        let mut last_change_id = None;
+        for message in pending {
+            match self
+                .email_ingest(IngestEmail {
+                    raw_message: &message.raw_message,
+                    message: MessageParser::new().parse(&message.raw_message),
+                    account_id,
+                    account_quota,
+                    mailbox_ids: message.mailbox_ids,
+                    keywords: message.keywords,
+                    received_at: message.received_at,
+                    skip_duplicates: true,
+                    encrypt: self.config.encrypt && self.config.encrypt_append,
+                    classify: false,
+                    trusted_sender: false,
+                })
+                .await
+            {
+                Ok(email) if email.change_id != u64::MAX => {
+                    last_change_id = Some(email.change_id);
+                    response.imported.push(email.id.to_string());
+                }
+                Ok(_) => {
+                    response.skipped += 1;
+                }
+                Err(IngestError::Permanent { reason, .. }) => {
+                    response.not_imported.push(BulkImportError {
+                        index: message.index,
+                        reason,
+                    });
+                }
+                Err(IngestError::OverQuota) => {
+                    response.not_imported.push(BulkImportError {
+                        index: message.index,
+                        reason: "Account is over quota.".to_string(),
+                    });
+                }
+                Err(IngestError::Temporary) => {
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+
+        // Notify state change
+        if let Some(change_id) = last_change_id {
+            self.broadcast_state_change(
+                StateChange::new(account_id)
+                    .with_change(DataType::Email, change_id)
+                    .with_change(DataType::Mailbox, change_id)
+                    .with_change(DataType::Thread, change_id),
+            )
+            .await;
+        }
+
+        Ok(response)
+    }
+
+    async fn import_mailbox_id(
+        &self,
+        account_id: u32,
+        path: &str,
+        mailbox_paths: &mut HashMap<String, u32>,
+    ) -> Result<Option<u32>, MethodError> {
+        if let Some(mailbox_id) = mailbox_paths.get(path) {
+            return Ok(Some(*mailbox_id));
+        }
+
+        let mailbox_id = self
+            .mailbox_create_path(account_id, path)
+            .await?
+            .map(|(mailbox_id, _)| mailbox_id);
+        if let Some(mailbox_id) = mailbox_id {
+            mailbox_paths.insert(path.to_string(), mailbox_id);
+        }
+
+        Ok(mailbox_id)
+    }
+
+    /// Fetches a blob uploaded to the account being imported into.
+    async fn import_blob(
+        &self,
+        account_id: u32,
+        blob_id: &str,
+    ) -> Result<Option<Vec<u8>>, MethodError> {
+        match BlobId::from_base32(blob_id) {
+            Some(blob_id)
+                if blob_id.class.account_id() == account_id
+                    && self
+                        .store
+                        .blob_has_access(&blob_id.hash, &blob_id.class)
+                        .await
+                        .map_err(|_| MethodError::ServerPartialFail)? =>
+            {
+                self.get_blob(&blob_id.hash, 0..u32::MAX).await
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::QueryBy;
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, keyword::Keyword, property::Property},
+};
+use mail_parser::{HeaderName, Message};
+use nlp::language::Language;
+use store::{
+    fts::{Field, FtsFilter},
+    roaring::RoaringBitmap,
+};
+
+use crate::JMAP;
+
+pub struct ImportanceClassifier {
+    pub keyword: Keyword,
+    pub min_score: u32,
+    pub weight_thread: u32,
+    pub weight_replied: u32,
+    pub weight_history: u32,
+    pub weight_directory: u32,
+}
+
+impl JMAP {
+    /// Scores an incoming message based on the recipient's relationship with
+    /// the sender and returns whether it should be flagged as important.
+    pub async fn is_important(
+        &self,
+        account_id: u32,
+        message: &Message<'_>,
+        thread_id: Option<u32>,
+        classifier: &ImportanceClassifier,
+    ) -> bool {
+        match self
+            .importance_score(account_id, message, thread_id, classifier)
+            .await
+        {
+            Ok(score) => score >= classifier.min_score,
+            Err(err) => {
+                tracing::debug!(
+                    context = "email_importance",
+                    event = "error",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to classify message importance."
+                );
+                false
+            }
+        }
+    }
+
+    async fn importance_score(
+        &self,
+        account_id: u32,
+        message: &Message<'_>,
+        thread_id: Option<u32>,
+        classifier: &ImportanceClassifier,
+    ) -> Result<u32, MethodError> {
+        let sender = if let Some(sender) = message
+            .from()
+            .and_then(|addr| addr.first())
+            .and_then(|addr| addr.address())
+        {
+            sender.to_lowercase()
+        } else {
+            return Ok(0);
+        };
+
+        // Messages the user has sent or replied to
+        let sent_ids =
+            if let Some(mailbox_id) = self.mailbox_get_by_role(account_id, "sent").await? {
+                self.get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id,
+                )
+                .await?
+                .unwrap_or_default()
+            } else {
+                RoaringBitmap::new()
+            };
+        let answered_ids = self
+            .get_tag(
+                account_id,
+                Collection::Email,
+                Property::Keywords,
+                Keyword::Answered,
+            )
+            .await?
+            .unwrap_or_default();
+        let mut score = 0;
+
+        // Participation in a thread the user replied to
+        if let (Some(thread_id), true) = (thread_id, classifier.weight_thread > 0) {
+            if let Some(mut thread_ids) = self
+                .get_tag(account_id, Collection::Email, Property::ThreadId, thread_id)
+                .await?
+            {
+                thread_ids &= &(&sent_ids | &answered_ids);
+                if !thread_ids.is_empty() {
+                    score += classifier.weight_thread;
+                }
+            }
+        }
+
+        // The user has written to the sender before
+        if classifier.weight_replied > 0 && !sent_ids.is_empty() {
+            let mut written_ids = self
+                .fts_filter(
+                    account_id,
+                    Collection::Email,
+                    vec![FtsFilter::has_text(
+                        Field::Header(HeaderName::To),
+                        &sender,
+                        Language::None,
+                    )],
+                )
+                .await?;
+            written_ids &= &sent_ids;
+            if !written_ids.is_empty() {
+                score += classifier.weight_replied;
+            }
+        }
+
+        // The user has answered or flagged messages from the sender
+        if classifier.weight_history > 0 {
+            let mut history_ids = self
+                .fts_filter(
+                    account_id,
+                    Collection::Email,
+                    vec![FtsFilter::has_text(
+                        Field::Header(HeaderName::From),
+                        &sender,
+                        Language::None,
+                    )],
+                )
+                .await?;
+            if !history_ids.is_empty() {
+                history_ids &= &(&answered_ids
+                    | &self
+                        .get_tag(
+                            account_id,
+                            Collection::Email,
+                            Property::Keywords,
+                            Keyword::Flagged,
+                        )
+                        .await?
+                        .unwrap_or_default());
+                if !history_ids.is_empty() {
+                    score += classifier.weight_history;
+                }
+            }
+        }
+
+        // The sender is a member of one of the user's groups
+        if classifier.weight_directory > 0 && self.shares_group(account_id, &sender).await {
+            score += classifier.weight_directory;
+        }
+
+        Ok(score)
+    }
+
+    async fn shares_group(&self, account_id: u32, sender: &str) -> bool {
+        let member_of = match self.directory.query(QueryBy::Id(account_id), true).await {
+            Ok(Some(principal)) if !principal.member_of.is_empty() => principal.member_of,
+            _ => return false,
+        };
+
+        for sender_id in self
+            .directory
+            .email_to_ids(sender)
+            .await
+            .unwrap_or_default()
+        {
+            if sender_id == account_id || member_of.contains(&sender_id) {
+                continue;
+            }
+            if let Ok(Some(principal)) = self.directory.query(QueryBy::Id(sender_id), true).await {
+                if principal
+                    .member_of
+                    .iter()
+                    .any(|group_id| member_of.contains(group_id))
+                {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
@@ -41,7 +41,7 @@ use store::{
 };
 use utils::BlobHash;
 
-use crate::mailbox::UidMailbox;
+use crate::mailbox::{counters::UpdateMailboxCounters, UidMailbox};
 
 use super::metadata::MessageMetadata;
 
@@ -83,6 +83,13 @@ impl IndexMessage for BatchBuilder {
         mailbox_ids: Vec<UidMailbox>,
         received_at: u64,
     ) -> &mut Self {
+        // Update mailbox counters
+        self.add_to_mailbox_counters(
+            mailbox_ids.iter().map(|mailbox| mailbox.mailbox_id),
+            keywords.contains(&Keyword::Seen),
+            message.raw_message.len() as u32,
+        );
+
         // Index keywords
         self.value(Property::Keywords, keywords, F_VALUE | F_BITMAP);
 
@@ -95,7 +102,8 @@ impl IndexMessage for BatchBuilder {
             .add(
                 DirectoryClass::UsedQuota(account_id),
                 message.raw_message.len() as i64,
-            );
+            )
+            .add(DirectoryClass::UsedMessages(account_id), 1);
 
         // Index receivedAt
         self.value(Property::ReceivedAt, received_at, F_INDEX);
@@ -433,6 +441,10 @@ impl<'x> IntoOperations for EmailIndexBuilder<'x> {
                 } else {
                     -(metadata.size as i64)
                 },
+            )
+            .add(
+                DirectoryClass::UsedMessages(account_id),
+                if self.set { 1 } else { -1 },
             );
         batch.value(
             Property::ReceivedAt,
@@ -39,8 +39,8 @@ use store::{
     ahash::AHashSet,
     query::Filter,
     write::{
-        log::ChangeLogBuilder, now, BatchBuilder, BitmapClass, TagValue, ValueClass, F_BITMAP,
-        F_CLEAR, F_VALUE,
+        log::ChangeLogBuilder, now, BatchBuilder, Bincode, BitmapClass, TagValue, ValueClass,
+        F_BITMAP, F_CLEAR, F_VALUE,
     },
     BitmapKey, BlobClass, ValueKey,
 };
@@ -56,6 +56,8 @@ use crate::{
 use super::{
     crypto::{EncryptMessage, EncryptMessageError, EncryptionParams},
     index::{TrimTextValue, MAX_SORT_FIELD_LENGTH},
+    signature::{VerifySignature, SIGNATURE_HEADER},
+    stats::BodyStats,
 };
 
 #[derive(Default)]
@@ -76,6 +78,8 @@ pub struct IngestEmail<'x> {
     pub received_at: Option<u64>,
     pub skip_duplicates: bool,
     pub encrypt: bool,
+    pub classify: bool,
+    pub trusted_sender: bool,
 }
 
 const MAX_RETRIES: u32 = 10;
@@ -88,13 +92,10 @@ impl JMAP {
     ) -> Result<IngestedEmail, IngestError> {
         // Check quota
         let mut raw_message_len = params.raw_message.len() as i64;
-        if params.account_quota > 0
-            && raw_message_len
-                + self
-                    .get_used_quota(params.account_id)
-                    .await
-                    .map_err(|_| IngestError::Temporary)?
-                > params.account_quota
+        if self
+            .is_over_quota(params.account_id, params.account_quota, raw_message_len)
+            .await
+            .map_err(|_| IngestError::Temporary)?
         {
             return Err(IngestError::OverQuota);
         }
@@ -109,6 +110,7 @@ impl JMAP {
         // Check for Spam headers
         if let Some((header_name, header_value)) = &self.config.spam_header {
             if params.mailbox_ids == [INBOX_ID]
+                && !params.trusted_sender
                 && message.root_part().headers().iter().any(|header| {
                     &header.name == header_name
                         && header
@@ -205,6 +207,61 @@ impl JMAP {
             }
         };
 
+        // Flag messages the user is likely to care about
+        if let (Some(classifier), true) = (&self.config.importance, params.classify) {
+            if !params.keywords.contains(&classifier.keyword)
+                && self
+                    .is_important(params.account_id, &message, thread_id, classifier)
+                    .await
+            {
+                params.keywords.push(classifier.keyword.clone());
+            }
+        }
+
+        // Verify S/MIME and PGP signatures
+        let signature = if params.classify && self.config.signature_verify {
+            let signature = message.verify_signature();
+
+            // Replace any status header added before delivery
+            let root = message.root_part();
+            let is_status_header =
+                |name: &HeaderName| name.as_str().eq_ignore_ascii_case(SIGNATURE_HEADER);
+            if self.config.signature_header
+                && (signature.is_some()
+                    || root
+                        .headers()
+                        .iter()
+                        .any(|header| is_status_header(&header.name)))
+            {
+                let mut new_raw_message = Vec::with_capacity(raw_message.len() + 256);
+                if let Some(signature) = &signature {
+                    signature.write_header(&mut new_raw_message);
+                }
+                for header in root.headers() {
+                    if !is_status_header(&header.name) {
+                        new_raw_message.extend_from_slice(
+                            &raw_message[header.offset_field()..header.offset_end()],
+                        );
+                    }
+                }
+                new_raw_message.extend_from_slice(b"\r\n");
+                new_raw_message.extend_from_slice(&raw_message[root.raw_body_offset()..]);
+
+                raw_message = Cow::from(new_raw_message);
+                raw_message_len = raw_message.len() as i64;
+                message = MessageParser::default()
+                    .parse(raw_message.as_ref())
+                    .ok_or_else(|| IngestError::Permanent {
+                        code: [5, 5, 0],
+                        reason: "Failed to parse e-mail message.".to_string(),
+                    })?;
+            }
+
+            signature
+        } else {
+            None
+        };
+
         // Encrypt message
         if params.encrypt && !message.is_encrypted() {
             if let Some(encrypt_params) = self
@@ -330,6 +387,7 @@ impl JMAP {
         }
 
         // Build write batch
+        let body_stats = BodyStats::from_message(&message);
         batch
             .with_collection(Collection::Email)
             .create_document(document_id)
@@ -346,6 +404,7 @@ impl JMAP {
             )
             .value(Property::Cid, change_id, F_VALUE)
             .value(Property::ThreadId, thread_id, F_VALUE | F_BITMAP)
+            .value(Property::BodyStats, Bincode::new(body_stats), F_VALUE)
             .custom(changes)
             .set(
                 ValueClass::IndexEmail(
@@ -354,6 +413,9 @@ impl JMAP {
                 ),
                 blob_id.hash.clone(),
             );
+        if let Some(signature) = signature {
+            batch.value(Property::Signature, Bincode::new(signature), F_VALUE);
+        }
         self.store.write(batch.build()).await.map_err(|err| {
             tracing::error!(
                 event = "error",
@@ -107,7 +107,16 @@ impl<'x> MessageMetadataContents<'x> {
                             }
                         }
                         MetadataPartType::Multipart(parts) => PartType::Multipart(parts),
-                        _ => PartType::Binary(Cow::Borrowed(&[])),
+                        // Without the raw message only the structure of the parts is kept
+                        MetadataPartType::Text => PartType::Text("".into()),
+                        MetadataPartType::Html => PartType::Html("".into()),
+                        MetadataPartType::InlineBinary => {
+                            PartType::InlineBinary(Cow::Borrowed(&[]))
+                        }
+                        MetadataPartType::Binary => PartType::Binary(Cow::Borrowed(&[])),
+                        MetadataPartType::Message(contents) => {
+                            PartType::Message(contents.into_message(raw_message))
+                        }
                     },
                     headers: part.headers,
                     is_encoding_problem: part.is_encoding_problem,
@@ -21,16 +21,24 @@
  * for more details.
 */
 
+pub mod annotations;
 pub mod body;
+pub mod bulk;
+pub mod cache;
 pub mod copy;
 pub mod crypto;
 pub mod get;
 pub mod headers;
 pub mod import;
+pub mod importance;
 pub mod index;
 pub mod ingest;
 pub mod metadata;
 pub mod parse;
 pub mod query;
+pub mod sender_list;
 pub mod set;
+pub mod signature;
 pub mod snippet;
+pub mod stats;
+pub mod unified;
@@ -36,9 +36,15 @@ use store::{
     write::ValueClass,
     ValueKey,
 };
+use utils::metrics::advisor::index_advisor;
 
 use crate::{auth::AccessToken, JMAP};
 
+/// Suggestion reported by the index advisor for searches on headers that are not indexed.
+pub const UNINDEXED_HEADER: &str =
+    "Only the standard RFC 5322 headers are indexed, prefer searching the message text instead.";
+const UNINDEXED_THREAD_KEYWORD: &str = "Keywords are indexed per message, every message with the keyword is read to resolve its thread. Prefer hasKeyword with collapseThreads.";
+
 impl JMAP {
     pub async fn email_query(
         &self,
@@ -47,6 +53,7 @@ impl JMAP {
     ) -> Result<QueryResponse, MethodError> {
         let account_id = request.account_id.document_id();
         let mut filters = Vec::with_capacity(request.filter.len());
+        let default_language = self.account_search_language(account_id).await;
 
         for cond_group in std::mem::take(&mut request.filter).into_filter_group() {
             match cond_group {
@@ -79,17 +86,17 @@ impl JMAP {
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Header(HeaderName::Subject),
                                     &text,
-                                    self.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Body,
                                     &text,
-                                    self.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::has_text_detect(
                                     Field::Attachment,
                                     text,
-                                    self.config.default_language,
+                                    default_language,
                                 ));
                                 fts_filters.push(FtsFilter::End);
                             }
@@ -116,12 +123,12 @@ impl JMAP {
                             Filter::Subject(text) => fts_filters.push(FtsFilter::has_text_detect(
                                 Field::Header(HeaderName::Subject),
                                 text,
-                                self.config.default_language,
+                                default_language,
                             )),
                             Filter::Body(text) => fts_filters.push(FtsFilter::has_text_detect(
                                 Field::Body,
                                 text,
-                                self.config.default_language,
+                                default_language,
                             )),
                             Filter::Header(header) => {
                                 let mut header = header.into_iter();
@@ -133,6 +140,13 @@ impl JMAP {
 
                                 match HeaderName::parse(header_name) {
                                     Some(HeaderName::Other(header_name)) => {
+                                        index_advisor().record(
+                                            "jmap",
+                                            "Email/query",
+                                            format!("header:{}", header_name.to_lowercase()),
+                                            UNINDEXED_HEADER,
+                                            0,
+                                        );
                                         return Err(MethodError::InvalidArguments(format!(
                                             "Querying header '{header_name}' is not supported.",
                                         )));
@@ -178,95 +192,15 @@ impl JMAP {
                             .await?,
                     ));
                 }
+                FilterGroup::Store(Filter::InSavedSearch(id)) => {
+                    filters.push(query::Filter::is_in_set(
+                        self.saved_search_email_ids(account_id, id.document_id())
+                            .await?,
+                    ));
+                }
                 FilterGroup::Store(cond) => {
-                    match cond {
-                        Filter::InMailbox(mailbox) => filters.push(query::Filter::is_in_bitmap(
-                            Property::MailboxIds,
-                            mailbox.document_id(),
-                        )),
-                        Filter::InMailboxOtherThan(mailboxes) => {
-                            filters.push(query::Filter::Not);
-                            filters.push(query::Filter::Or);
-                            for mailbox in mailboxes {
-                                filters.push(query::Filter::is_in_bitmap(
-                                    Property::MailboxIds,
-                                    mailbox.document_id(),
-                                ));
-                            }
-                            filters.push(query::Filter::End);
-                            filters.push(query::Filter::End);
-                        }
-                        Filter::Before(date) => {
-                            filters.push(query::Filter::lt(Property::ReceivedAt, date))
-                        }
-                        Filter::After(date) => {
-                            filters.push(query::Filter::gt(Property::ReceivedAt, date))
-                        }
-                        Filter::MinSize(size) => {
-                            filters.push(query::Filter::ge(Property::Size, size))
-                        }
-                        Filter::MaxSize(size) => {
-                            filters.push(query::Filter::lt(Property::Size, size))
-                        }
-                        Filter::AllInThreadHaveKeyword(keyword) => {
-                            filters.push(query::Filter::is_in_set(
-                                self.thread_keywords(account_id, keyword, true).await?,
-                            ))
-                        }
-                        Filter::SomeInThreadHaveKeyword(keyword) => {
-                            filters.push(query::Filter::is_in_set(
-                                self.thread_keywords(account_id, keyword, false).await?,
-                            ))
-                        }
-                        Filter::NoneInThreadHaveKeyword(keyword) => {
-                            filters.push(query::Filter::Not);
-                            filters.push(query::Filter::is_in_set(
-                                self.thread_keywords(account_id, keyword, false).await?,
-                            ));
-                            filters.push(query::Filter::End);
-                        }
-                        Filter::HasKeyword(keyword) => {
-                            filters.push(query::Filter::is_in_bitmap(Property::Keywords, keyword))
-                        }
-                        Filter::NotKeyword(keyword) => {
-                            filters.push(query::Filter::Not);
-                            filters.push(query::Filter::is_in_bitmap(Property::Keywords, keyword));
-                            filters.push(query::Filter::End);
-                        }
-                        Filter::HasAttachment(has_attach) => {
-                            if !has_attach {
-                                filters.push(query::Filter::Not);
-                            }
-                            filters.push(query::Filter::is_in_bitmap(Property::HasAttachment, ()));
-                            if !has_attach {
-                                filters.push(query::Filter::End);
-                            }
-                        }
-
-                        // Non-standard
-                        Filter::Id(ids) => {
-                            let mut set = RoaringBitmap::new();
-                            for id in ids {
-                                set.insert(id.document_id());
-                            }
-                            filters.push(query::Filter::is_in_set(set));
-                        }
-                        Filter::SentBefore(date) => {
-                            filters.push(query::Filter::lt(Property::SentAt, date))
-                        }
-                        Filter::SentAfter(date) => {
-                            filters.push(query::Filter::gt(Property::SentAt, date))
-                        }
-                        Filter::InThread(id) => filters.push(query::Filter::is_in_bitmap(
-                            Property::ThreadId,
-                            id.document_id(),
-                        )),
-                        Filter::And | Filter::Or | Filter::Not | Filter::Close => {
-                            filters.push(cond.into());
-                        }
-
-                        other => return Err(MethodError::UnsupportedFilter(other.to_string())),
-                    }
+                    self.email_store_filter(account_id, cond, &mut filters)
+                        .await?;
                 }
             }
         }
@@ -323,6 +257,7 @@ impl JMAP {
                             account_id,
                             comparator.keyword.unwrap_or(Keyword::Seen),
                             true,
+                            "sort:allInThreadHaveKeyword",
                         )
                         .await?,
                         comparator.is_ascending,
@@ -332,6 +267,7 @@ impl JMAP {
                             account_id,
                             comparator.keyword.unwrap_or(Keyword::Seen),
                             false,
+                            "sort:someInThreadHaveKeyword",
                         )
                         .await?,
                         comparator.is_ascending,
@@ -365,16 +301,114 @@ impl JMAP {
         }
     }
 
+    pub(crate) async fn email_store_filter(
+        &self,
+        account_id: u32,
+        cond: Filter,
+        filters: &mut Vec<query::Filter>,
+    ) -> Result<(), MethodError> {
+        match cond {
+            Filter::InMailbox(mailbox) => filters.push(query::Filter::is_in_bitmap(
+                Property::MailboxIds,
+                mailbox.document_id(),
+            )),
+            Filter::InMailboxOtherThan(mailboxes) => {
+                filters.push(query::Filter::Not);
+                filters.push(query::Filter::Or);
+                for mailbox in mailboxes {
+                    filters.push(query::Filter::is_in_bitmap(
+                        Property::MailboxIds,
+                        mailbox.document_id(),
+                    ));
+                }
+                filters.push(query::Filter::End);
+                filters.push(query::Filter::End);
+            }
+            Filter::Before(date) => filters.push(query::Filter::lt(Property::ReceivedAt, date)),
+            Filter::After(date) => filters.push(query::Filter::gt(Property::ReceivedAt, date)),
+            Filter::MinSize(size) => filters.push(query::Filter::ge(Property::Size, size)),
+            Filter::MaxSize(size) => filters.push(query::Filter::lt(Property::Size, size)),
+            Filter::AllInThreadHaveKeyword(keyword) => filters.push(query::Filter::is_in_set(
+                self.thread_keywords(account_id, keyword, true, "filter:allInThreadHaveKeyword")
+                    .await?,
+            )),
+            Filter::SomeInThreadHaveKeyword(keyword) => filters.push(query::Filter::is_in_set(
+                self.thread_keywords(account_id, keyword, false, "filter:someInThreadHaveKeyword")
+                    .await?,
+            )),
+            Filter::NoneInThreadHaveKeyword(keyword) => {
+                filters.push(query::Filter::Not);
+                filters.push(query::Filter::is_in_set(
+                    self.thread_keywords(
+                        account_id,
+                        keyword,
+                        false,
+                        "filter:noneInThreadHaveKeyword",
+                    )
+                    .await?,
+                ));
+                filters.push(query::Filter::End);
+            }
+            Filter::HasKeyword(keyword) => {
+                filters.push(query::Filter::is_in_bitmap(Property::Keywords, keyword))
+            }
+            Filter::NotKeyword(keyword) => {
+                filters.push(query::Filter::Not);
+                filters.push(query::Filter::is_in_bitmap(Property::Keywords, keyword));
+                filters.push(query::Filter::End);
+            }
+            Filter::HasAttachment(has_attach) => {
+                if !has_attach {
+                    filters.push(query::Filter::Not);
+                }
+                filters.push(query::Filter::is_in_bitmap(Property::HasAttachment, ()));
+                if !has_attach {
+                    filters.push(query::Filter::End);
+                }
+            }
+
+            // Non-standard
+            Filter::Id(ids) => {
+                let mut set = RoaringBitmap::new();
+                for id in ids {
+                    set.insert(id.document_id());
+                }
+                filters.push(query::Filter::is_in_set(set));
+            }
+            Filter::SentBefore(date) => filters.push(query::Filter::lt(Property::SentAt, date)),
+            Filter::SentAfter(date) => filters.push(query::Filter::gt(Property::SentAt, date)),
+            Filter::InThread(id) => filters.push(query::Filter::is_in_bitmap(
+                Property::ThreadId,
+                id.document_id(),
+            )),
+            Filter::And | Filter::Or | Filter::Not | Filter::Close => {
+                filters.push(cond.into());
+            }
+
+            other => return Err(MethodError::UnsupportedFilter(other.to_string())),
+        }
+
+        Ok(())
+    }
+
     async fn thread_keywords(
         &self,
         account_id: u32,
         keyword: Keyword,
         match_all: bool,
+        criterion: &'static str,
     ) -> Result<RoaringBitmap, MethodError> {
         let keyword_doc_ids = self
             .get_tag(account_id, Collection::Email, Property::Keywords, keyword)
             .await?
             .unwrap_or_default();
+        index_advisor().record(
+            "jmap",
+            "Email/query",
+            criterion,
+            UNINDEXED_THREAD_KEYWORD,
+            keyword_doc_ids.len(),
+        );
 
         let mut not_matched_ids = RoaringBitmap::new();
         let mut matched_ids = RoaringBitmap::new();
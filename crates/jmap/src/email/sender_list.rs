@@ -0,0 +1,81 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    object::Object,
+    types::{property::Property, value::Value},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderList {
+    Allowed,
+    Blocked,
+}
+
+/// Looks up the sender addresses in the account's allowed and blocked sender
+/// lists. Blocked entries take precedence over allowed ones.
+pub fn match_sender_list(settings: &Object<Value>, senders: &[&str]) -> Option<SenderList> {
+    for (property, list) in [
+        (Property::BlockedSenders, SenderList::Blocked),
+        (Property::AllowedSenders, SenderList::Allowed),
+    ] {
+        if let Value::List(entries) = settings.get(&property) {
+            for entry in entries {
+                if let Value::Text(entry) = entry {
+                    if senders.iter().any(|sender| sender_matches(sender, entry)) {
+                        return Some(list);
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Entries are either full addresses or domains prefixed with '@'.
+fn sender_matches(sender: &str, entry: &str) -> bool {
+    if let Some(domain) = entry.strip_prefix('@') {
+        sender.rsplit_once('@').map_or(false, |(_, sender_domain)| {
+            sender_domain.eq_ignore_ascii_case(domain)
+        })
+    } else {
+        sender.eq_ignore_ascii_case(entry)
+    }
+}
+
+/// Normalizes and validates a sender list entry.
+pub fn parse_sender_entry(entry: &str) -> Option<String> {
+    let entry = entry.trim().to_lowercase();
+    let (local, domain) = entry.rsplit_once('@')?;
+    if !domain.is_empty()
+        && domain.contains('.')
+        && !domain.contains(char::is_whitespace)
+        && !local.contains(char::is_whitespace)
+        && entry.len() <= 255
+    {
+        Some(entry)
+    } else {
+        None
+    }
+}
@@ -29,6 +29,7 @@ use jmap_proto::{
         set::{SetError, SetErrorType},
     },
     method::set::{RequestArguments, SetRequest, SetResponse},
+    object::Object,
     response::references::EvalObjectReferences,
     types::{
         acl::Acl,
@@ -60,10 +61,14 @@ use store::{
 };
 
 use crate::{
-    auth::AccessToken, mailbox::UidMailbox, services::housekeeper::Event, IngestError, JMAP,
+    auth::AccessToken,
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+    services::housekeeper::Event,
+    IngestError, JMAP,
 };
 
 use super::{
+    annotations::update_annotations,
     headers::{BuildHeader, ValueToHeader},
     index::EmailIndexBuilder,
     ingest::IngestEmail,
@@ -742,6 +747,8 @@ impl JMAP {
                     received_at,
                     skip_duplicates: false,
                     encrypt: self.config.encrypt && self.config.encrypt_append,
+                    classify: false,
+                    trusted_sender: false,
                 })
                 .await
             {
@@ -797,6 +804,7 @@ impl JMAP {
             batch
                 .with_account_id(account_id)
                 .with_collection(Collection::Email);
+            let mut annotations: Option<Object<Value>> = None;
 
             for (property, value) in object.properties {
                 let value = match response.eval_object_references(value) {
@@ -842,6 +850,29 @@ impl JMAP {
                             );
                         }
                     }
+                    (Property::Annotations, MaybePatchValue::Value(Value::Object(value))) => {
+                        annotations = Some(value);
+                    }
+                    (Property::Annotations, MaybePatchValue::Value(Value::Null)) => {
+                        annotations = Some(Object::default());
+                    }
+                    (Property::Annotations, MaybePatchValue::Patch(patch)) => {
+                        if annotations.is_none() {
+                            annotations = self
+                                .get_email_annotations(account_id, document_id)
+                                .await?
+                                .into();
+                        }
+                        let annotations = annotations.as_mut().unwrap();
+                        let mut patch = patch.into_iter();
+                        if let Some(key) = patch.next().unwrap().try_unwrap_string() {
+                            if let Value::Text(value) = patch.next().unwrap() {
+                                annotations.set(Property::_T(key), value);
+                            } else {
+                                annotations.remove(&Property::_T(key));
+                            }
+                        }
+                    }
                     (property, _) => {
                         response.invalid_property_update(id, property);
                         continue 'update;
@@ -849,7 +880,7 @@ impl JMAP {
                 }
             }
 
-            if !mailboxes.has_changes() && !keywords.has_changes() {
+            if !mailboxes.has_changes() && !keywords.has_changes() && annotations.is_none() {
                 response.not_updated.append(
                     id,
                     SetError::invalid_properties()
@@ -863,6 +894,24 @@ impl JMAP {
             let mut changed_mailboxes = AHashSet::new();
             changes.log_update(Collection::Email, id);
 
+            // Update mailbox counters
+            let previous_seen = keywords.previous().contains(&Keyword::Seen);
+            let current_seen = keywords.current().contains(&Keyword::Seen);
+            if mailboxes.has_changes() || previous_seen != current_seen {
+                let size = if mailboxes.has_changes() {
+                    self.message_size(account_id, document_id).await?
+                } else {
+                    0
+                };
+                batch.update_mailbox_counters(
+                    mailboxes.previous(),
+                    previous_seen,
+                    mailboxes.current(),
+                    current_seen,
+                    size,
+                );
+            }
+
             // Process keywords
             if keywords.has_changes() {
                 // Verify permissions on shared accounts
@@ -895,6 +944,34 @@ impl JMAP {
                 batch.value(Property::Cid, changes.change_id, F_VALUE);
             }
 
+            // Process annotations
+            if let Some(annotations) = &annotations {
+                // Verify permissions on shared accounts
+                if matches!(&can_modify_message_ids, Some(ids) if !ids.contains(document_id)) {
+                    response.not_updated.append(
+                        id,
+                        SetError::forbidden()
+                            .with_description("You are not allowed to modify annotations."),
+                    );
+                    continue 'update;
+                }
+                if let Err(err) = self.validate_annotations(annotations) {
+                    response.not_updated.append(id, err);
+                    continue 'update;
+                }
+
+                // Update annotations property
+                update_annotations(&mut batch, annotations);
+
+                // Update last change id
+                if !keywords.has_changes() {
+                    if changes.change_id == u64::MAX {
+                        changes.change_id = self.assign_change_id(account_id).await?;
+                    }
+                    batch.value(Property::Cid, changes.change_id, F_VALUE);
+                }
+            }
+
             // Process mailboxes
             if mailboxes.has_changes() {
                 // Make sure the message is at least in one mailbox
@@ -1065,10 +1142,50 @@ impl JMAP {
         // Create batch
         let mut batch = BatchBuilder::new();
         let mut changes = ChangeLogBuilder::with_change_id(0);
+        batch.with_account_id(account_id);
+        if let Err(err) = self
+            .email_delete_batch(&mut batch, &mut changes, account_id, document_id, false)
+            .await?
+        {
+            return Ok(Err(err));
+        }
 
+        // Commit batch
+        match self.store.write(batch.build()).await {
+            Ok(_) => (),
+            Err(store::Error::AssertValueFailed) => {
+                return Ok(Err(SetError::forbidden().with_description(
+                    "Another process modified this message, please try again.",
+                )));
+            }
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "email_delete",
+                    error = ?err,
+                    "Failed to commit batch.");
+                return Err(MethodError::ServerPartialFail);
+            }
+        }
+
+        // Request FTS index
+        let _ = self.housekeeper_tx.send(Event::IndexStart).await;
+
+        Ok(Ok(changes))
+    }
+
+    /// Adds the removal of a message to a batch. When `whole_thread` is set the
+    /// caller is deleting every message in the thread and removes the thread itself.
+    pub(crate) async fn email_delete_batch(
+        &self,
+        batch: &mut BatchBuilder,
+        changes: &mut ChangeLogBuilder,
+        account_id: u32,
+        document_id: u32,
+        whole_thread: bool,
+    ) -> Result<Result<(), SetError>, MethodError> {
         // Delete document
         batch
-            .with_account_id(account_id)
             .with_collection(Collection::Email)
             .delete_document(document_id)
             .set(
@@ -1076,8 +1193,11 @@ impl JMAP {
                 vec![],
             );
 
-        // Remove last changeId
+        // Remove last changeId, annotations, signature status and part statistics
         batch.value(Property::Cid, (), F_VALUE | F_CLEAR);
+        batch.value(Property::Annotations, (), F_VALUE | F_CLEAR);
+        batch.value(Property::Signature, (), F_VALUE | F_CLEAR);
+        batch.value(Property::BodyStats, (), F_VALUE | F_CLEAR);
 
         // Remove mailboxes
         let mailboxes = if let Some(mailboxes) = self
@@ -1100,8 +1220,10 @@ impl JMAP {
             );
             return Ok(Err(SetError::not_found()));
         };
+        let mut mailbox_ids = Vec::with_capacity(mailboxes.inner.len());
         for mailbox_id in &mailboxes.inner {
             changes.log_child_update(Collection::Mailbox, mailbox_id.mailbox_id);
+            mailbox_ids.push(mailbox_id.mailbox_id);
         }
         batch.assert_value(Property::MailboxIds, &mailboxes).value(
             Property::MailboxIds,
@@ -1110,7 +1232,7 @@ impl JMAP {
         );
 
         // Remove keywords
-        if let Some(keywords) = self
+        let seen = if let Some(keywords) = self
             .get_property::<HashedValue<Vec<Keyword>>>(
                 account_id,
                 Collection::Email,
@@ -1119,11 +1241,13 @@ impl JMAP {
             )
             .await?
         {
+            let seen = keywords.inner.contains(&Keyword::Seen);
             batch.assert_value(Property::Keywords, &keywords).value(
                 Property::Keywords,
                 keywords.inner,
                 F_VALUE | F_BITMAP | F_CLEAR,
             );
+            seen
         } else {
             tracing::debug!(
                 event = "error",
@@ -1146,29 +1270,14 @@ impl JMAP {
             )
             .await?
         {
-            // Obtain all documentIds in thread
-            if let Some(thread_tags) = self
+            // Obtain all documentIds in thread, unless the caller deletes the whole thread
+            let thread_size = if whole_thread {
+                None
+            } else if let Some(thread_tags) = self
                 .get_tag(account_id, Collection::Email, Property::ThreadId, thread_id)
                 .await?
             {
-                if thread_tags.len() > 1 {
-                    // Thread has other document ids, remove this one
-                    changes.log_child_update(Collection::Thread, thread_id);
-                } else {
-                    // Thread is empty, delete it
-                    delete_thread_id = thread_id.into();
-                    changes.log_delete(Collection::Thread, thread_id);
-                }
-
-                // Remove threadId value and tag
-                batch.assert_value(Property::ThreadId, thread_id).value(
-                    Property::ThreadId,
-                    thread_id,
-                    F_VALUE | F_BITMAP | F_CLEAR,
-                );
-
-                // Log message deletion
-                changes.log_delete(Collection::Email, Id::from_parts(thread_id, document_id));
+                Some(thread_tags.len())
             } else {
                 tracing::debug!(
                     event = "error",
@@ -1179,7 +1288,29 @@ impl JMAP {
                     "Failed to fetch thread tags.",
                 );
                 return Ok(Err(SetError::not_found()));
+            };
+            match thread_size {
+                Some(size) if size > 1 => {
+                    // Thread has other document ids, remove this one
+                    changes.log_child_update(Collection::Thread, thread_id);
+                }
+                Some(_) => {
+                    // Thread is empty, delete it
+                    delete_thread_id = thread_id.into();
+                    changes.log_delete(Collection::Thread, thread_id);
+                }
+                None => (),
             }
+
+            // Remove threadId value and tag
+            batch.assert_value(Property::ThreadId, thread_id).value(
+                Property::ThreadId,
+                thread_id,
+                F_VALUE | F_BITMAP | F_CLEAR,
+            );
+
+            // Log message deletion
+            changes.log_delete(Collection::Email, Id::from_parts(thread_id, document_id));
         } else {
             tracing::debug!(
                 event = "error",
@@ -1201,7 +1332,10 @@ impl JMAP {
             )
             .await?
         {
-            batch.custom(EmailIndexBuilder::clear(metadata.inner));
+            self.message_cache.remove(&metadata.inner.blob_hash);
+            batch
+                .remove_from_mailbox_counters(mailbox_ids, seen, metadata.inner.size as u32)
+                .custom(EmailIndexBuilder::clear(metadata.inner));
         } else {
             tracing::debug!(
                 event = "error",
@@ -1220,28 +1354,7 @@ impl JMAP {
                 .delete_document(thread_id);
         }
 
-        // Commit batch
-        match self.store.write(batch.build()).await {
-            Ok(_) => (),
-            Err(store::Error::AssertValueFailed) => {
-                return Ok(Err(SetError::forbidden().with_description(
-                    "Another process modified this message, please try again.",
-                )));
-            }
-            Err(err) => {
-                tracing::error!(
-                    event = "error",
-                    context = "email_delete",
-                    error = ?err,
-                    "Failed to commit batch.");
-                return Err(MethodError::ServerPartialFail);
-            }
-        }
-
-        // Request FTS index
-        let _ = self.housekeeper_tx.send(Event::IndexStart).await;
-
-        Ok(Ok(changes))
+        Ok(Ok(()))
     }
 }
 
@@ -1249,6 +1362,7 @@ pub struct TagManager<
     T: PartialEq + Clone + ToBitmaps + SerializeInto + Serialize + DeserializeFrom + Sync + Send,
 > {
     current: HashedValue<Vec<T>>,
+    previous: Vec<T>,
     added: Vec<T>,
     removed: Vec<T>,
     last: LastTag,
@@ -1266,6 +1380,7 @@ impl<
 {
     pub fn new(current: HashedValue<Vec<T>>) -> Self {
         Self {
+            previous: current.inner.clone(),
             current,
             added: Vec::new(),
             removed: Vec::new(),
@@ -1322,6 +1437,10 @@ impl<
         &self.current.inner
     }
 
+    pub fn previous(&self) -> &[T] {
+        &self.previous
+    }
+
     pub fn changed_tags(&self) -> impl Iterator<Item = &T> {
         self.added.iter().chain(self.removed.iter())
     }
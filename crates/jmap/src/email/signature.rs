@@ -0,0 +1,625 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use jmap_proto::{
+    object::Object,
+    types::{property::Property, value::Value},
+};
+use mail_parser::{decoders::base64::base64_decode, Message, MimeHeaders, PartType};
+use openpgp::{
+    parse::{
+        stream::{
+            DetachedVerifierBuilder, GoodChecksum, MessageLayer, MessageStructure,
+            VerificationError, VerificationHelper, VerifierBuilder,
+        },
+        Parse,
+    },
+    Cert, KeyHandle,
+};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rasn::types::{ObjectIdentifier, OctetString};
+use rasn_cms::{
+    pkcs7_compat::EncapsulatedContentInfo, CertificateChoices, SignedData, SignerIdentifier,
+    CONTENT_SIGNED_DATA,
+};
+use rsa::{pkcs1::DecodeRsaPublicKey, Pkcs1v15Sign, RsaPublicKey};
+use sequoia_openpgp as openpgp;
+use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha384, Sha512};
+use x509_parser::{extensions::GeneralName, prelude::ParsedExtension};
+
+pub const SIGNATURE_HEADER: &str = "X-Signature-Status";
+
+const P: openpgp::policy::StandardPolicy<'static> = openpgp::policy::StandardPolicy::new();
+
+const OID_MESSAGE_DIGEST: &[u32] = &[1, 2, 840, 113549, 1, 9, 4];
+const OID_RSA_ENCRYPTION: &[u32] = &[1, 2, 840, 113549, 1, 1, 1];
+const OID_EC_PUBLIC_KEY: &[u32] = &[1, 2, 840, 10045, 2, 1];
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SignatureStatus {
+    pub method: SignatureMethod,
+    pub result: SignatureResult,
+    pub signer: Option<String>,
+    pub key_id: Option<String>,
+    pub from_match: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SignatureMethod {
+    SMime,
+    PgpMime,
+    PgpInline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SignatureResult {
+    /// The signature matches the content and the key it was made with.
+    Valid,
+    /// The content was modified after it was signed.
+    Invalid,
+    /// The signing key or certificate is expired, revoked or unusable.
+    BadKey,
+    /// The signing key was not included with the message.
+    NoKey,
+    /// The signature uses an algorithm that is not supported.
+    Unsupported,
+    /// The signature could not be parsed.
+    Error,
+}
+
+#[derive(Clone, Copy)]
+enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+pub trait VerifySignature {
+    fn verify_signature(&self) -> Option<SignatureStatus>;
+}
+
+impl VerifySignature for Message<'_> {
+    /// Detects S/MIME, PGP/MIME and inline PGP signatures and verifies them
+    /// using the certificates or keys included with the message. Certificate
+    /// chains are not validated, so a valid result only guarantees that the
+    /// content was not altered since it was signed by the reported signer.
+    fn verify_signature(&self) -> Option<SignatureStatus> {
+        let root = self.root_part();
+        let content_type = root.content_type()?;
+        let subtype = content_type.subtype().unwrap_or_default();
+
+        let mut status = if content_type.ctype().eq_ignore_ascii_case("multipart")
+            && subtype.eq_ignore_ascii_case("signed")
+        {
+            let protocol = content_type.attribute("protocol")?.to_ascii_lowercase();
+            let (signed_part, signature_part) = match &root.body {
+                PartType::Multipart(parts) if parts.len() == 2 => {
+                    (self.parts.get(parts[0])?, self.parts.get(parts[1])?)
+                }
+                _ => return None,
+            };
+            let content = self
+                .raw_message()
+                .get(signed_part.raw_header_offset()..signed_part.raw_end_offset())?;
+
+            match protocol.as_str() {
+                "application/pkcs7-signature" | "application/x-pkcs7-signature" => {
+                    verify_smime(Some(content), signature_part.contents())
+                }
+                "application/pgp-signature" => verify_pgp(
+                    SignatureMethod::PgpMime,
+                    Some(content),
+                    signature_part.contents(),
+                    autocrypt_certs(self),
+                ),
+                _ => return None,
+            }
+        } else if content_type.ctype().eq_ignore_ascii_case("application")
+            && (subtype.eq_ignore_ascii_case("pkcs7-mime")
+                || subtype.eq_ignore_ascii_case("x-pkcs7-mime"))
+            && content_type
+                .attribute("smime-type")
+                .map_or(false, |smime_type| {
+                    smime_type.eq_ignore_ascii_case("signed-data")
+                })
+        {
+            verify_smime(None, root.contents())
+        } else {
+            let text = self.text_bodies().find_map(|part| {
+                let text = part.text_contents()?;
+                let start = text.find("-----BEGIN PGP SIGNED MESSAGE-----")?;
+                let end = text[start..].find("-----END PGP SIGNATURE-----")?
+                    + start
+                    + "-----END PGP SIGNATURE-----".len();
+                Some(&text[start..end])
+            })?;
+            verify_pgp(
+                SignatureMethod::PgpInline,
+                None,
+                text.as_bytes(),
+                autocrypt_certs(self),
+            )
+        };
+
+        // Flag signatures made by someone other than the sender
+        if let (Some(signer), Some(from)) = (
+            &status.signer,
+            self.from()
+                .and_then(|from| from.first())
+                .and_then(|from| from.address()),
+        ) {
+            status.from_match = signer.eq_ignore_ascii_case(from);
+        }
+
+        Some(status)
+    }
+}
+
+fn verify_smime(detached: Option<&[u8]>, pkcs7: &[u8]) -> SignatureStatus {
+    let mut status = SignatureStatus::new(SignatureMethod::SMime, SignatureResult::Error);
+    let signed_data = match rasn::der::decode::<EncapsulatedContentInfo>(pkcs7)
+        .ok()
+        .filter(|info| info.content_type == ObjectIdentifier::from(CONTENT_SIGNED_DATA))
+        .and_then(|info| info.content)
+        .and_then(|content| rasn::der::decode::<SignedData>(content.as_bytes()).ok())
+    {
+        Some(signed_data) => signed_data,
+        None => return status,
+    };
+    let signer_info = match signed_data.signer_infos.iter().next() {
+        Some(signer_info) => signer_info,
+        None => return status,
+    };
+    let content = match (detached, &signed_data.encap_content_info.content) {
+        (Some(content), _) => content,
+        (None, Some(content)) => content.as_ref(),
+        (None, None) => return status,
+    };
+
+    // Find the signer's certificate
+    let cert = signed_data
+        .certificates
+        .iter()
+        .flatten()
+        .filter_map(|cert| match cert {
+            CertificateChoices::Certificate(cert) => Some(cert.as_ref()),
+            _ => None,
+        })
+        .find_map(|cert| {
+            let der = rasn::der::encode(cert).ok()?;
+            let is_signer = match &signer_info.sid {
+                SignerIdentifier::IssuerAndSerialNumber(sid) => {
+                    cert.tbs_certificate.issuer == sid.issuer
+                        && cert.tbs_certificate.serial_number == sid.serial_number
+                }
+                SignerIdentifier::SubjectKeyIdentifier(ski) => {
+                    x509_parser::parse_x509_certificate(&der)
+                        .ok()
+                        .map_or(false, |(_, x509)| {
+                            x509.extensions().iter().any(|ext| {
+                                matches!(ext.parsed_extension(),
+                                    ParsedExtension::SubjectKeyIdentifier(id) if id.0 == ski.as_ref())
+                            })
+                        })
+                }
+            };
+            if is_signer {
+                Some((cert, der))
+            } else {
+                None
+            }
+        });
+    let (cert, der) = match cert {
+        Some(cert) => cert,
+        None => {
+            status.result = SignatureResult::NoKey;
+            return status;
+        }
+    };
+    let x509 = match x509_parser::parse_x509_certificate(&der) {
+        Ok((_, x509)) => x509,
+        Err(_) => return status,
+    };
+    status.key_id = x509.raw_serial_as_string().into();
+    status.signer = x509
+        .subject()
+        .iter_email()
+        .filter_map(|email| email.as_str().ok())
+        .chain(
+            x509.subject_alternative_name()
+                .ok()
+                .flatten()
+                .into_iter()
+                .flat_map(|san| san.value.general_names.iter())
+                .filter_map(|name| match name {
+                    GeneralName::RFC822Name(email) => Some(*email),
+                    _ => None,
+                }),
+        )
+        .next()
+        .map(|email| email.to_lowercase());
+
+    // Verify the signature
+    let digest = match DigestAlgorithm::from_oid(&signer_info.digest_algorithm.algorithm) {
+        Some(digest) => digest,
+        None => {
+            status.result = SignatureResult::Unsupported;
+            return status;
+        }
+    };
+    let signed_bytes = if let Some(signed_attrs) = &signer_info.signed_attrs {
+        // Signed attributes include the digest of the content
+        let message_digest = signed_attrs
+            .iter()
+            .find(|attr| oid_is(&attr.r#type, OID_MESSAGE_DIGEST))
+            .and_then(|attr| attr.value.iter().next())
+            .and_then(|value| rasn::der::decode::<OctetString>(value.as_bytes()).ok());
+        let message_digest = match message_digest {
+            Some(message_digest) => message_digest,
+            None => return status,
+        };
+        if !signed_candidates(content)
+            .iter()
+            .any(|content| digest.digest(content) == message_digest.as_ref())
+        {
+            status.result = SignatureResult::Invalid;
+            return status;
+        }
+
+        match rasn::der::encode(signed_attrs) {
+            Ok(signed_attrs) => vec![signed_attrs],
+            Err(_) => return status,
+        }
+    } else {
+        signed_candidates(content)
+            .into_iter()
+            .map(|content| content.into_owned())
+            .collect()
+    };
+
+    let public_key = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .as_raw_slice();
+    let key_algorithm = &cert
+        .tbs_certificate
+        .subject_public_key_info
+        .algorithm
+        .algorithm;
+    let signature = signer_info.signature.as_ref();
+    let mut result = SignatureResult::Unsupported;
+    for signed_bytes in signed_bytes {
+        let hash = digest.digest(&signed_bytes);
+        result = if oid_is(key_algorithm, OID_RSA_ENCRYPTION) {
+            match RsaPublicKey::from_pkcs1_der(public_key) {
+                Ok(key) => {
+                    let mut digest_info = digest.digest_info_prefix().to_vec();
+                    digest_info.extend_from_slice(&hash);
+                    if key
+                        .verify(Pkcs1v15Sign::new_unprefixed(), &digest_info, signature)
+                        .is_ok()
+                    {
+                        SignatureResult::Valid
+                    } else {
+                        SignatureResult::Invalid
+                    }
+                }
+                Err(_) => SignatureResult::BadKey,
+            }
+        } else if oid_is(key_algorithm, OID_EC_PUBLIC_KEY) {
+            match (
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key),
+                p256::ecdsa::Signature::from_der(signature),
+            ) {
+                (Ok(key), Ok(signature)) => {
+                    if key.verify_prehash(&hash, &signature).is_ok() {
+                        SignatureResult::Valid
+                    } else {
+                        SignatureResult::Invalid
+                    }
+                }
+                _ => SignatureResult::Unsupported,
+            }
+        } else {
+            SignatureResult::Unsupported
+        };
+
+        if result != SignatureResult::Invalid {
+            break;
+        }
+    }
+
+    status.result = if result == SignatureResult::Valid && !x509.validity().is_valid() {
+        SignatureResult::BadKey
+    } else {
+        result
+    };
+    status
+}
+
+fn verify_pgp(
+    method: SignatureMethod,
+    detached: Option<&[u8]>,
+    signature: &[u8],
+    certs: Vec<Cert>,
+) -> SignatureStatus {
+    let helper = || PgpHelper {
+        certs: certs.clone(),
+        status: SignatureStatus::new(method, SignatureResult::Error),
+    };
+    let mut status = SignatureStatus::new(method, SignatureResult::Error);
+
+    if let Some(content) = detached {
+        for content in signed_candidates(content) {
+            match DetachedVerifierBuilder::from_bytes(signature)
+                .and_then(|builder| builder.with_policy(&P, None, helper()))
+            {
+                Ok(mut verifier) => {
+                    let _ = verifier.verify_bytes(&content);
+                    status = verifier.into_helper().status;
+                    if status.result != SignatureResult::Invalid {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    } else if let Ok(mut verifier) = VerifierBuilder::from_bytes(signature)
+        .and_then(|builder| builder.with_policy(&P, None, helper()))
+    {
+        let _ = std::io::copy(&mut verifier, &mut std::io::sink());
+        status = verifier.into_helper().status;
+    }
+
+    status
+}
+
+struct PgpHelper {
+    certs: Vec<Cert>,
+    status: SignatureStatus,
+}
+
+impl VerificationHelper for PgpHelper {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+        Ok(self.certs.clone())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> openpgp::Result<()> {
+        for layer in structure.into_iter() {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                for result in results {
+                    let (result, (signer, key_id)) = match result {
+                        Ok(GoodChecksum { ka, .. }) => {
+                            (SignatureResult::Valid, pgp_identity(ka.cert()))
+                        }
+                        Err(VerificationError::MissingKey { sig, .. }) => (
+                            SignatureResult::NoKey,
+                            (
+                                None,
+                                sig.get_issuers().first().map(|issuer| issuer.to_hex()),
+                            ),
+                        ),
+                        Err(VerificationError::BadKey { ka, .. }) => {
+                            (SignatureResult::BadKey, pgp_identity(ka.cert()))
+                        }
+                        Err(VerificationError::BadSignature { ka, .. }) => {
+                            (SignatureResult::Invalid, pgp_identity(ka.cert()))
+                        }
+                        Err(_) => (SignatureResult::Error, (None, None)),
+                    };
+
+                    // Report the first signature, or the first valid one
+                    if self.status.result == SignatureResult::Error
+                        || result == SignatureResult::Valid
+                    {
+                        self.status.result = result;
+                        self.status.signer = signer;
+                        self.status.key_id = key_id;
+                    }
+                    if result == SignatureResult::Valid {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn pgp_identity(cert: &Cert) -> (Option<String>, Option<String>) {
+    (
+        cert.userids()
+            .find_map(|uid| uid.userid().email_normalized().ok().flatten()),
+        Some(cert.fingerprint().to_hex()),
+    )
+}
+
+/// Returns the OpenPGP keys advertised by the sender in Autocrypt headers.
+fn autocrypt_certs(message: &Message<'_>) -> Vec<Cert> {
+    message
+        .root_part()
+        .headers()
+        .iter()
+        .filter(|header| header.name.as_str().eq_ignore_ascii_case("Autocrypt"))
+        .filter_map(|header| {
+            let value = header.value().as_text()?;
+            let keydata = value[value.find("keydata=")? + 8..]
+                .bytes()
+                .filter(|ch| !ch.is_ascii_whitespace() && *ch != b';')
+                .collect::<Vec<_>>();
+            Cert::from_bytes(&base64_decode(&keydata)?).ok()
+        })
+        .collect()
+}
+
+/// Returns the signed content along with its variants without the
+/// trailing line break and with bare line feeds converted to CRLF.
+fn signed_candidates(content: &[u8]) -> Vec<Cow<'_, [u8]>> {
+    let mut candidates = vec![Cow::Borrowed(content)];
+    if let Some(content) = content.strip_suffix(b"\r\n") {
+        candidates.push(Cow::Borrowed(content));
+    }
+    if content
+        .iter()
+        .enumerate()
+        .any(|(pos, ch)| *ch == b'\n' && (pos == 0 || content[pos - 1] != b'\r'))
+    {
+        let mut canonical = Vec::with_capacity(content.len() + 64);
+        for (pos, ch) in content.iter().enumerate() {
+            if *ch == b'\n' && (pos == 0 || content[pos - 1] != b'\r') {
+                canonical.push(b'\r');
+            }
+            canonical.push(*ch);
+        }
+        candidates.push(Cow::Owned(canonical));
+    }
+    candidates
+}
+
+fn oid_is(oid: &ObjectIdentifier, expected: &[u32]) -> bool {
+    ***oid == *expected
+}
+
+impl DigestAlgorithm {
+    fn from_oid(oid: &ObjectIdentifier) -> Option<Self> {
+        match &***oid {
+            [1, 3, 14, 3, 2, 26] => Some(DigestAlgorithm::Sha1),
+            [2, 16, 840, 1, 101, 3, 4, 2, 1] => Some(DigestAlgorithm::Sha256),
+            [2, 16, 840, 1, 101, 3, 4, 2, 2] => Some(DigestAlgorithm::Sha384),
+            [2, 16, 840, 1, 101, 3, 4, 2, 3] => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            DigestAlgorithm::Sha384 => Sha384::digest(data).to_vec(),
+            DigestAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn digest_info_prefix(&self) -> &'static [u8] {
+        match self {
+            DigestAlgorithm::Sha1 => &[
+                0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+                0x14,
+            ],
+            DigestAlgorithm::Sha256 => &[
+                0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x01, 0x05, 0x00, 0x04, 0x20,
+            ],
+            DigestAlgorithm::Sha384 => &[
+                0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x02, 0x05, 0x00, 0x04, 0x30,
+            ],
+            DigestAlgorithm::Sha512 => &[
+                0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x03, 0x05, 0x00, 0x04, 0x40,
+            ],
+        }
+    }
+}
+
+impl SignatureStatus {
+    fn new(method: SignatureMethod, result: SignatureResult) -> Self {
+        SignatureStatus {
+            method,
+            result,
+            signer: None,
+            key_id: None,
+            from_match: false,
+        }
+    }
+
+    pub fn write_header(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(SIGNATURE_HEADER.as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(self.method.as_str().as_bytes());
+        buf.extend_from_slice(b"; result=");
+        buf.extend_from_slice(self.result.as_str().as_bytes());
+        if let Some(signer) = &self.signer {
+            buf.extend_from_slice(b";\r\n\tsigner=\"");
+            buf.extend(
+                signer
+                    .bytes()
+                    .filter(|ch| !ch.is_ascii_control() && !matches!(ch, b'"' | b'\\')),
+            );
+            buf.push(b'"');
+        }
+        if let Some(key_id) = &self.key_id {
+            buf.extend_from_slice(b";\r\n\tkey-id=\"");
+            buf.extend_from_slice(key_id.as_bytes());
+            buf.push(b'"');
+        }
+        buf.extend_from_slice(b"; from-match=");
+        buf.extend_from_slice(if self.from_match { b"yes" } else { b"no" });
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::Object(
+            Object::with_capacity(5)
+                .with_property(Property::_T("method".to_string()), self.method.as_str())
+                .with_property(Property::_T("result".to_string()), self.result.as_str())
+                .with_property(
+                    Property::_T("signer".to_string()),
+                    self.signer.map(Value::Text).unwrap_or(Value::Null),
+                )
+                .with_property(
+                    Property::_T("keyId".to_string()),
+                    self.key_id.map(Value::Text).unwrap_or(Value::Null),
+                )
+                .with_property(Property::_T("fromMatch".to_string()), self.from_match),
+        )
+    }
+}
+
+impl SignatureMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureMethod::SMime => "smime",
+            SignatureMethod::PgpMime => "pgp-mime",
+            SignatureMethod::PgpInline => "pgp-inline",
+        }
+    }
+}
+
+impl SignatureResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureResult::Valid => "valid",
+            SignatureResult::Invalid => "invalid",
+            SignatureResult::BadKey => "badkey",
+            SignatureResult::NoKey => "nokey",
+            SignatureResult::Unsupported => "unsupported",
+            SignatureResult::Error => "error",
+        }
+    }
+}
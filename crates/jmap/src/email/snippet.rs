@@ -47,14 +47,16 @@ impl JMAP {
         let mut include_term = true;
         let mut terms = vec![];
         let mut is_exact = false;
-        let mut language = self.config.default_language;
+        let default_language = self
+            .account_search_language(request.account_id.document_id())
+            .await;
+        let mut language = default_language;
 
         for cond in request.filter {
             match cond {
                 Filter::Text(text) | Filter::Subject(text) | Filter::Body(text) => {
                     if include_term {
-                        let (text, language_) =
-                            Language::detect(text, self.config.default_language);
+                        let (text, language_) = Language::detect(text, default_language);
                         language = language_;
                         if (text.starts_with('"') && text.ends_with('"'))
                             || (text.starts_with('\'') && text.ends_with('\''))
@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, property::Property},
+};
+use mail_parser::{Message, MessageParser, PartType};
+use serde::{Deserialize, Serialize};
+use store::write::{BatchBuilder, Bincode, F_VALUE};
+
+use crate::JMAP;
+
+use super::metadata::MessageMetadata;
+
+const BACKFILL_BATCH_SIZE: usize = 100;
+
+/// Part details that can only be obtained from the raw message. They are
+/// stored at ingest so that the IMAP BODY and BODYSTRUCTURE of a message can
+/// be built from its metadata without fetching the blob.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BodyStats {
+    pub lines: usize,
+    pub md5: String,
+    pub nested: Vec<BodyStats>,
+}
+
+impl BodyStats {
+    pub fn from_message(message: &Message<'_>) -> Vec<BodyStats> {
+        message
+            .parts
+            .iter()
+            .map(|part| {
+                let body = message
+                    .raw_message
+                    .get(part.offset_body..part.offset_end)
+                    .unwrap_or_default();
+
+                match &part.body {
+                    PartType::Multipart(_) => BodyStats::default(),
+                    PartType::Message(nested) => BodyStats {
+                        lines: 0,
+                        md5: format!("{:x}", md5::compute(body)),
+                        nested: BodyStats::from_message(nested),
+                    },
+                    _ => BodyStats {
+                        lines: body.iter().filter(|&&ch| ch == b'\n').count(),
+                        md5: format!("{:x}", md5::compute(body)),
+                        nested: vec![],
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+impl JMAP {
+    /// Computes the part statistics of the messages stored before they were
+    /// recorded at ingest, returning the number of messages updated.
+    pub async fn backfill_body_stats(&self, account_id: u32) -> Result<u64, MethodError> {
+        let document_ids = self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .unwrap_or_default();
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email);
+        let mut count = 0;
+
+        for document_id in document_ids {
+            if self
+                .get_property::<Bincode<Vec<BodyStats>>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::BodyStats,
+                )
+                .await?
+                .is_some()
+            {
+                continue;
+            }
+
+            let metadata = if let Some(metadata) = self
+                .get_property::<Bincode<MessageMetadata>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::BodyStructure,
+                )
+                .await?
+            {
+                metadata.inner
+            } else {
+                continue;
+            };
+            let raw_message =
+                if let Some(raw_message) = self.get_blob(&metadata.blob_hash, 0..u32::MAX).await? {
+                    raw_message
+                } else {
+                    tracing::warn!(event = "not-found",
+                    account_id = account_id,
+                    collection = ?Collection::Email,
+                    document_id = document_id,
+                    blob_id = ?metadata.blob_hash,
+                    "Blob not found");
+                    continue;
+                };
+            let stats = if let Some(message) = MessageParser::new().parse(&raw_message) {
+                BodyStats::from_message(&message)
+            } else {
+                continue;
+            };
+
+            if batch.ops.len() >= BACKFILL_BATCH_SIZE {
+                self.write_batch(batch).await?;
+                batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::Email);
+            }
+            batch.update_document(document_id).value(
+                Property::BodyStats,
+                Bincode::new(stats),
+                F_VALUE,
+            );
+            count += 1;
+        }
+
+        if !batch.is_empty() {
+            self.write_batch(batch).await?;
+        }
+
+        Ok(count)
+    }
+}
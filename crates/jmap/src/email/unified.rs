@@ -0,0 +1,313 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::query::{Comparator, Filter, QueryRequest, QueryResponse, SortProperty},
+    object::email::QueryArguments,
+    types::{acl::Acl, collection::Collection, id::Id, property::Property, state::State},
+};
+use serde::{Deserialize, Serialize};
+use store::write::{
+    assert::{AssertValue, HashedValue},
+    BatchBuilder, Bincode, F_VALUE,
+};
+
+use crate::{auth::AccessToken, mailbox::INBOX_ID, JMAP};
+
+use super::metadata::MessageMetadata;
+
+const MAX_RETRIES: usize = 10;
+
+/// Messages of the unified inbox as exposed over IMAP, kept under the
+/// `emailIds` property of the primary account. Messages are numbered with
+/// their own UIDs as they first appear in any of the aggregated inboxes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UnifiedInboxResults {
+    pub change_id: u64,
+    pub accounts: Vec<(u32, Option<u64>)>,
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub items: Vec<UnifiedInboxItem>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UnifiedInboxItem {
+    pub account_id: u32,
+    pub document_id: u32,
+    pub uid: u32,
+}
+
+impl JMAP {
+    /// Queries the inboxes of the primary account and of every account shared
+    /// with it, returning the matching messages merged by their received date.
+    /// Per-mailbox ACLs are applied by the underlying query of each account.
+    pub async fn email_query_unified(
+        &self,
+        request: QueryRequest<QueryArguments>,
+        access_token: &AccessToken,
+    ) -> Result<QueryResponse, MethodError> {
+        if self.config.unified_max_accounts == 0 {
+            return Err(MethodError::InvalidArguments(
+                "Unified inbox queries are disabled.".to_string(),
+            ));
+        } else if request.anchor.is_some() {
+            return Err(MethodError::InvalidArguments(
+                "Anchors are not supported in unified inbox queries.".to_string(),
+            ));
+        }
+        let position = usize::try_from(request.position.unwrap_or(0)).map_err(|_| {
+            MethodError::InvalidArguments(
+                "Negative positions are not supported in unified inbox queries.".to_string(),
+            )
+        })?;
+        let is_ascending = match request.sort.as_deref() {
+            None | Some([]) => false,
+            Some([comparator]) if comparator.property == SortProperty::ReceivedAt => {
+                comparator.is_ascending
+            }
+            _ => {
+                return Err(MethodError::UnsupportedSort(
+                    "Unified inbox queries can only be sorted by receivedAt.".to_string(),
+                ))
+            }
+        };
+        let limit = std::cmp::min(
+            request.limit.unwrap_or(self.config.query_max_results),
+            self.config.query_max_results,
+        );
+
+        // Query each inbox for the first results up to the requested window
+        let primary_id = request.account_id.document_id();
+        let window = std::cmp::min(position + limit, self.config.query_max_results);
+        let mut total = 0;
+        let mut results = Vec::new();
+        for account_id in self.unified_account_ids(primary_id, access_token) {
+            let mut filter = Vec::with_capacity(request.filter.len() + 3);
+            filter.push(Filter::And);
+            filter.push(Filter::InMailbox(Id::from(INBOX_ID)));
+            filter.extend(request.filter.iter().cloned());
+            filter.push(Filter::Close);
+
+            let response = self
+                .email_query(
+                    QueryRequest {
+                        account_id: Id::from(account_id),
+                        filter,
+                        sort: vec![if is_ascending {
+                            Comparator::ascending(SortProperty::ReceivedAt)
+                        } else {
+                            Comparator::descending(SortProperty::ReceivedAt)
+                        }]
+                        .into(),
+                        position: None,
+                        anchor: None,
+                        anchor_offset: None,
+                        limit: window.into(),
+                        calculate_total: true.into(),
+                        arguments: QueryArguments {
+                            collapse_threads: request.arguments.collapse_threads,
+                            unified_inbox: None,
+                        },
+                    },
+                    access_token,
+                )
+                .await?;
+            total += response.total.unwrap_or_default();
+
+            for id in response.ids {
+                if let Some(metadata) = self
+                    .get_property::<Bincode<MessageMetadata>>(
+                        account_id,
+                        Collection::Email,
+                        id.document_id(),
+                        &Property::BodyStructure,
+                    )
+                    .await?
+                {
+                    results.push((metadata.inner.received_at, account_id, id));
+                }
+            }
+        }
+
+        // Merge the results, the sort is stable so each account keeps its own order
+        if is_ascending {
+            results.sort_by_key(|(received_at, _, _)| *received_at);
+        } else {
+            results.sort_by_key(|(received_at, _, _)| std::cmp::Reverse(*received_at));
+        }
+        let (account_ids, ids) = results
+            .into_iter()
+            .skip(position)
+            .take(limit)
+            .map(|(_, account_id, id)| (Id::from(account_id), id))
+            .unzip();
+
+        Ok(QueryResponse {
+            account_id: request.account_id,
+            query_state: self.get_state(primary_id, Collection::Email).await?,
+            can_calculate_changes: false,
+            position: position as i32,
+            ids,
+            account_ids: Some(account_ids),
+            total: if request.calculate_total.unwrap_or(false) {
+                Some(total)
+            } else {
+                None
+            },
+            limit: if total > limit { Some(limit) } else { None },
+        })
+    }
+
+    /// Returns the messages of the unified inbox of the primary account, or
+    /// `None` if unified views are disabled. The results are rebuilt every
+    /// time any of the aggregated accounts changes, which increments their
+    /// change id, while messages already in the view keep their UIDs.
+    pub async fn unified_inbox_refresh(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<Option<UnifiedInboxResults>, MethodError> {
+        if self.config.unified_max_accounts == 0 {
+            return Ok(None);
+        }
+
+        // Obtain the state of each aggregated account
+        let primary_id = access_token.primary_id();
+        let account_ids = self.unified_account_ids(primary_id, access_token);
+        let mut accounts = Vec::with_capacity(account_ids.len());
+        for account_id in account_ids {
+            accounts.push((
+                account_id,
+                match self.get_state(account_id, Collection::Email).await? {
+                    State::Exact(change_id) => Some(change_id),
+                    _ => None,
+                },
+            ));
+        }
+
+        for _ in 0..MAX_RETRIES {
+            let (assert_value, mut results) = match self
+                .get_property::<HashedValue<Bincode<UnifiedInboxResults>>>(
+                    primary_id,
+                    Collection::Principal,
+                    0,
+                    Property::EmailIds,
+                )
+                .await?
+            {
+                Some(results) if results.inner.inner.accounts == accounts => {
+                    return Ok(Some(results.inner.inner));
+                }
+                Some(results) => (AssertValue::Hash(results.hash), results.inner.inner),
+                None => (
+                    AssertValue::None,
+                    UnifiedInboxResults {
+                        uid_validity: rand::random::<u32>().max(1),
+                        uid_next: 1,
+                        ..Default::default()
+                    },
+                ),
+            };
+
+            // Obtain the messages in each inbox the user has access to
+            let mut messages = Vec::with_capacity(accounts.len());
+            for &(account_id, _) in &accounts {
+                let mut message_ids = self
+                    .get_tag(
+                        account_id,
+                        Collection::Email,
+                        Property::MailboxIds,
+                        INBOX_ID,
+                    )
+                    .await?
+                    .unwrap_or_default();
+                if access_token.is_shared(account_id) {
+                    message_ids &= self
+                        .shared_messages(access_token, account_id, Acl::ReadItems)
+                        .await?;
+                }
+                messages.push((account_id, message_ids));
+            }
+
+            // Existing messages keep their UIDs, new messages are appended
+            results.items.retain(|item| {
+                messages.iter_mut().any(|(account_id, message_ids)| {
+                    *account_id == item.account_id && message_ids.remove(item.document_id)
+                })
+            });
+            for (account_id, message_ids) in messages {
+                for document_id in message_ids {
+                    results.items.push(UnifiedInboxItem {
+                        account_id,
+                        document_id,
+                        uid: results.uid_next,
+                    });
+                    results.uid_next += 1;
+                }
+            }
+            results.change_id += 1;
+            results.accounts = accounts.clone();
+
+            // Store the updated results, retrying if another session
+            // refreshed them concurrently
+            let results = Bincode::new(results);
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(primary_id)
+                .with_collection(Collection::Principal)
+                .update_document(0)
+                .assert_value(Property::EmailIds, assert_value)
+                .value(Property::EmailIds, &results, F_VALUE);
+            match self.store.write(batch.build()).await {
+                Ok(_) => return Ok(Some(results.inner)),
+                Err(store::Error::AssertValueFailed) => continue,
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "unified_inbox",
+                        account_id = primary_id,
+                        error = ?err,
+                        "Failed to store unified inbox."
+                    );
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+
+        Err(MethodError::ServerUnavailable)
+    }
+
+    /// Returns the primary account followed by the accounts shared with it,
+    /// up to the maximum number of accounts in unified views.
+    fn unified_account_ids(&self, primary_id: u32, access_token: &AccessToken) -> Vec<u32> {
+        let mut account_ids = vec![primary_id];
+        for &account_id in access_token.shared_accounts(Collection::Email) {
+            if account_ids.len() == self.config.unified_max_accounts {
+                break;
+            } else if !account_ids.contains(&account_id) {
+                account_ids.push(account_id);
+            }
+        }
+        account_ids
+    }
+}
@@ -0,0 +1,328 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::{index::ObjectIndexBuilder, Object},
+    types::{blob::BlobId, collection::Collection, property::Property, value::Value},
+};
+use store::{
+    query::Filter,
+    write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, BlobOp, DirectoryClass},
+    BlobClass,
+};
+
+use crate::{
+    sieve::set::{ObjectBlobId, SCHEMA},
+    JMAP,
+};
+
+pub mod get;
+pub mod set;
+
+pub const FILTER_RULES_SCRIPT: &str = "filter-rules";
+
+pub(crate) const CONDITION_FIELD: &str = "field";
+pub(crate) const CONDITION_OPERATOR: &str = "operator";
+pub(crate) const ACTION_FILE_INTO: &str = "fileInto";
+pub(crate) const ACTION_MARK_READ: &str = "markRead";
+pub(crate) const ACTION_FORWARD_TO: &str = "forwardTo";
+
+impl JMAP {
+    /// Compiles the account's enabled filter rules into the reserved
+    /// "filter-rules" Sieve script and activates it, or removes the script
+    /// when no rules are enabled.
+    pub async fn filter_rules_compile(
+        &self,
+        account_id: u32,
+        changes: &mut ChangeLogBuilder,
+    ) -> Result<(), MethodError> {
+        // Obtain enabled rules in their sort order
+        let mut rules = Vec::new();
+        for document_id in self
+            .get_document_ids(account_id, Collection::FilterRule)
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(rule) = self
+                .get_property::<Object<Value>>(
+                    account_id,
+                    Collection::FilterRule,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                if rule.get(&Property::IsEnabled) != &Value::Bool(false) {
+                    let sort_order = match rule.get(&Property::SortOrder) {
+                        Value::UnsignedInt(sort_order) => *sort_order,
+                        _ => 0,
+                    };
+                    rules.push((sort_order, document_id, rule));
+                }
+            }
+        }
+        rules.sort_unstable_by_key(|(sort_order, document_id, _)| (*sort_order, *document_id));
+        let (forward_to, keep_copy) = self.account_forwarding(account_id).await?;
+
+        // Remove the script if there are no enabled rules or forwarding addresses
+        let document_id = self
+            .filter(
+                account_id,
+                Collection::SieveScript,
+                vec![Filter::eq(Property::Name, FILTER_RULES_SCRIPT)],
+            )
+            .await?
+            .results
+            .min();
+        if rules.is_empty() && forward_to.is_empty() {
+            if let Some(document_id) = document_id {
+                self.sieve_script_delete(account_id, document_id, false)
+                    .await?;
+                changes.log_delete(Collection::SieveScript, document_id);
+            }
+            return Ok(());
+        }
+
+        // Build and compile script
+        let mut script = build_script(
+            &forward_to,
+            keep_copy,
+            rules.iter().map(|(_, _, rule)| rule),
+        );
+        let script_size = script.len();
+        match self.sieve_compiler.compile(&script) {
+            Ok(compiled_script) => {
+                script.extend(bincode::serialize(&compiled_script).unwrap_or_default());
+            }
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "filter_rules_compile",
+                    account_id = account_id,
+                    "Filter rules Sieve script failed to compile: {}",
+                    err
+                );
+                return Err(MethodError::ServerPartialFail);
+            }
+        }
+
+        // Obtain current script
+        let current = if let Some(document_id) = document_id {
+            self.get_property::<HashedValue<Object<Value>>>(
+                account_id,
+                Collection::SieveScript,
+                document_id,
+                Property::Value,
+            )
+            .await?
+            .ok_or(MethodError::ServerPartialFail)?
+            .into()
+        } else {
+            None
+        };
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::SieveScript);
+        let document_id = if let Some(document_id) = document_id {
+            batch.update_document(document_id);
+            changes.log_update(Collection::SieveScript, document_id);
+            document_id
+        } else {
+            let document_id = self
+                .assign_document_id(account_id, Collection::SieveScript)
+                .await?;
+            batch.create_document(document_id);
+            changes.log_insert(Collection::SieveScript, document_id);
+            document_id
+        };
+
+        // Write script blob
+        let blob_id = BlobId::new(
+            self.put_blob(account_id, &script, false).await?.hash,
+            BlobClass::Linked {
+                account_id,
+                collection: Collection::SieveScript.into(),
+                document_id,
+            },
+        )
+        .with_section_size(script_size);
+        batch.set(
+            BlobOp::Link {
+                hash: blob_id.hash.clone(),
+            },
+            Vec::new(),
+        );
+
+        // Unlink previous blob and update quota
+        let mut quota = script_size as i64;
+        if let Some(current_blob_id) = current
+            .as_ref()
+            .and_then(|current: &HashedValue<Object<Value>>| current.inner.blob_id())
+        {
+            batch.clear(BlobOp::Link {
+                hash: current_blob_id.hash.clone(),
+            });
+            quota -= current_blob_id
+                .section
+                .as_ref()
+                .map_or(0, |s| s.size as i64);
+        }
+        if quota != 0 {
+            batch.add(DirectoryClass::UsedQuota(account_id), quota);
+        }
+
+        let obj = if let Some(current) = current {
+            ObjectIndexBuilder::new(SCHEMA)
+                .with_current(current)
+                .with_changes(
+                    Object::with_capacity(1)
+                        .with_property(Property::BlobId, Value::BlobId(blob_id)),
+                )
+        } else {
+            ObjectIndexBuilder::new(SCHEMA).with_changes(
+                Object::with_capacity(3)
+                    .with_property(Property::Name, FILTER_RULES_SCRIPT)
+                    .with_property(Property::IsActive, Value::Bool(false))
+                    .with_property(Property::BlobId, Value::BlobId(blob_id)),
+            )
+        };
+        batch.custom(obj);
+        self.write_batch(batch).await?;
+
+        // Activate script
+        for (document_id, _) in self
+            .sieve_activate_script(account_id, document_id.into())
+            .await?
+        {
+            changes.log_update(Collection::SieveScript, document_id);
+        }
+
+        Ok(())
+    }
+}
+
+fn build_script<'x>(
+    forward_to: &[String],
+    keep_copy: bool,
+    rules: impl Iterator<Item = &'x Object<Value>>,
+) -> Vec<u8> {
+    let mut script = Vec::with_capacity(1024);
+    script.extend_from_slice(
+        b"require [\"fileinto\", \"mailboxid\", \"imap4flags\", \"copy\"];\r\n\r\n",
+    );
+
+    // Account-wide forwarding applies before any rule
+    for address in forward_to {
+        script.extend_from_slice(b"redirect :copy ");
+        push_string(&mut script, address);
+        script.extend_from_slice(b";\r\n");
+    }
+    if !forward_to.is_empty() {
+        if !keep_copy {
+            script.extend_from_slice(b"discard;\r\nstop;\r\n");
+        }
+        script.extend_from_slice(b"\r\n");
+    }
+
+    for rule in rules {
+        // Conditions
+        script.extend_from_slice(b"if allof (");
+        if let Value::List(conditions) = rule.get(&Property::Conditions) {
+            for (pos, condition) in conditions.iter().enumerate() {
+                if let Value::Object(condition) = condition {
+                    if pos > 0 {
+                        script.extend_from_slice(b", ");
+                    }
+                    script.extend_from_slice(b"header :");
+                    script.extend_from_slice(
+                        match text_value(condition, &rule_key(CONDITION_OPERATOR)) {
+                            Some("is") => "is",
+                            _ => "contains",
+                        }
+                        .as_bytes(),
+                    );
+                    script.extend_from_slice(
+                        match text_value(condition, &rule_key(CONDITION_FIELD)) {
+                            Some("subject") => " \"subject\" ",
+                            Some("listId") => " \"list-id\" ",
+                            _ => " \"from\" ",
+                        }
+                        .as_bytes(),
+                    );
+                    push_string(
+                        &mut script,
+                        text_value(condition, &Property::Value).unwrap_or_default(),
+                    );
+                }
+            }
+        }
+        script.extend_from_slice(b") {\r\n");
+
+        // Actions
+        if let Value::Object(actions) = rule.get(&Property::Actions) {
+            if actions.get(&rule_key(ACTION_MARK_READ)) == &Value::Bool(true) {
+                script.extend_from_slice(b"    addflag \"\\\\Seen\";\r\n");
+            }
+            if let Some(address) = text_value(actions, &rule_key(ACTION_FORWARD_TO)) {
+                script.extend_from_slice(b"    redirect :copy ");
+                push_string(&mut script, address);
+                script.extend_from_slice(b";\r\n");
+            }
+            if let Some(mailbox_id) = text_value(actions, &rule_key(ACTION_FILE_INTO)) {
+                script.extend_from_slice(b"    fileinto :mailboxid ");
+                push_string(&mut script, mailbox_id);
+                script.extend_from_slice(b" \"INBOX\";\r\n");
+            }
+        }
+        script.extend_from_slice(b"}\r\n");
+    }
+
+    script
+}
+
+pub(crate) fn rule_key(name: &str) -> Property {
+    Property::_T(name.to_string())
+}
+
+fn text_value<'x>(obj: &'x Object<Value>, property: &Property) -> Option<&'x str> {
+    obj.get(property).as_string()
+}
+
+fn push_string(script: &mut Vec<u8>, value: &str) {
+    script.push(b'\"');
+    for &ch in value.as_bytes() {
+        match ch {
+            b'\\' | b'\"' => {
+                script.push(b'\\');
+            }
+            b'\r' | b'\n' => {
+                continue;
+            }
+            _ => (),
+        }
+        script.push(ch);
+    }
+    script.push(b'\"');
+}
@@ -0,0 +1,351 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    object::Object,
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        id::Id,
+        property::Property,
+        value::{MaybePatchValue, Value},
+    },
+};
+use store::write::{log::ChangeLogBuilder, BatchBuilder, F_CLEAR, F_VALUE};
+
+use crate::{identity::set::sanitize_email, JMAP};
+
+use super::{
+    rule_key, ACTION_FILE_INTO, ACTION_FORWARD_TO, ACTION_MARK_READ, CONDITION_FIELD,
+    CONDITION_OPERATOR,
+};
+
+const MAX_CONDITIONS: usize = 10;
+
+impl JMAP {
+    pub async fn filter_rule_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+    ) -> Result<SetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut rule_ids = self
+            .get_document_ids(account_id, Collection::FilterRule)
+            .await?
+            .unwrap_or_default();
+        let mut response = self
+            .prepare_set_response(&request, Collection::FilterRule)
+            .await?;
+        let will_destroy = request.unwrap_destroy();
+
+        // Process creates
+        let mut changes = ChangeLogBuilder::new();
+        'create: for (id, object) in request.unwrap_create() {
+            if rule_ids.len() as usize >= self.config.filter_rules_max {
+                response.not_created.append(
+                    id,
+                    SetError::forbidden().with_description(format!(
+                        "There are too many filter rules, maximum is {}.",
+                        self.config.filter_rules_max
+                    )),
+                );
+                continue 'create;
+            }
+
+            let mut rule = Object::with_capacity(object.properties.len());
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_rule_value(&property, value))
+                {
+                    Ok(Value::Null) => (),
+                    Ok(value) => {
+                        rule.set(property, value);
+                    }
+                    Err(err) => {
+                        response.not_created.append(id, err);
+                        continue 'create;
+                    }
+                }
+            }
+            if let Err(err) = self.validate_rule(account_id, &rule).await? {
+                response.not_created.append(id, err);
+                continue 'create;
+            }
+
+            // Insert record
+            let mut batch = BatchBuilder::new();
+            let document_id = self
+                .assign_document_id(account_id, Collection::FilterRule)
+                .await?;
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::FilterRule)
+                .create_document(document_id)
+                .value(Property::Value, rule, F_VALUE);
+            rule_ids.insert(document_id);
+            self.write_batch(batch).await?;
+            changes.log_insert(Collection::FilterRule, document_id);
+            response.created(id, document_id);
+        }
+
+        // Process updates
+        'update: for (id, object) in request.unwrap_update() {
+            // Make sure id won't be destroyed
+            if will_destroy.contains(&id) {
+                response.not_updated.append(id, SetError::will_destroy());
+                continue 'update;
+            }
+
+            // Obtain rule
+            let document_id = id.document_id();
+            let mut rule = if let Some(rule) = self
+                .get_property::<Object<Value>>(
+                    account_id,
+                    Collection::FilterRule,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                rule
+            } else {
+                response.not_updated.append(id, SetError::not_found());
+                continue 'update;
+            };
+
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_rule_value(&property, value))
+                {
+                    Ok(Value::Null) => {
+                        rule.remove(&property);
+                    }
+                    Ok(value) => {
+                        rule.set(property, value);
+                    }
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                };
+            }
+            if let Err(err) = self.validate_rule(account_id, &rule).await? {
+                response.not_updated.append(id, err);
+                continue 'update;
+            }
+
+            // Update record
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::FilterRule)
+                .update_document(document_id)
+                .value(Property::Value, rule, F_VALUE);
+            self.write_batch(batch).await?;
+            changes.log_update(Collection::FilterRule, document_id);
+            response.updated.append(id, None);
+        }
+
+        // Process deletions
+        for id in will_destroy {
+            let document_id = id.document_id();
+            if rule_ids.contains(document_id) {
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::FilterRule)
+                    .delete_document(document_id)
+                    .value(Property::Value, (), F_VALUE | F_CLEAR);
+                self.write_batch(batch).await?;
+                changes.log_delete(Collection::FilterRule, document_id);
+                response.destroyed.push(id);
+            } else {
+                response.not_destroyed.append(id, SetError::not_found());
+            }
+        }
+
+        // Recompile the account's Sieve script and write changes
+        if !changes.is_empty() {
+            self.filter_rules_compile(account_id, &mut changes).await?;
+            response.new_state = Some(self.commit_changes(account_id, changes).await?.into());
+        }
+
+        Ok(response)
+    }
+
+    async fn validate_rule(
+        &self,
+        account_id: u32,
+        rule: &Object<Value>,
+    ) -> Result<Result<(), SetError>, MethodError> {
+        if !matches!(rule.get(&Property::Conditions), Value::List(conditions) if !conditions.is_empty())
+        {
+            return Ok(Err(SetError::invalid_properties()
+                .with_property(Property::Conditions)
+                .with_description("At least one condition is required.")));
+        }
+        let actions = match rule.get(&Property::Actions) {
+            Value::Object(actions) if !actions.properties.is_empty() => actions,
+            _ => {
+                return Ok(Err(SetError::invalid_properties()
+                    .with_property(Property::Actions)
+                    .with_description("At least one action is required.")));
+            }
+        };
+
+        // Make sure the target mailbox exists
+        if let Value::Text(mailbox_id) = actions.get(&rule_key(ACTION_FILE_INTO)) {
+            let exists = match Id::from_bytes(mailbox_id.as_bytes()) {
+                Some(id) => self
+                    .get_document_ids(account_id, Collection::Mailbox)
+                    .await?
+                    .map_or(false, |ids| ids.contains(id.document_id())),
+                None => false,
+            };
+            if !exists {
+                return Ok(Err(SetError::invalid_properties()
+                    .with_property(Property::Actions)
+                    .with_description(format!(
+                        "Mailbox {} does not exist.",
+                        mailbox_id
+                    ))));
+            }
+        }
+
+        Ok(Ok(()))
+    }
+}
+
+fn validate_rule_value(property: &Property, value: MaybePatchValue) -> Result<Value, SetError> {
+    Ok(match (property, value) {
+        (Property::Name, MaybePatchValue::Value(Value::Text(value))) if value.len() < 255 => {
+            Value::Text(value)
+        }
+        (Property::IsEnabled, MaybePatchValue::Value(Value::Bool(value))) => Value::Bool(value),
+        (Property::SortOrder, MaybePatchValue::Value(Value::UnsignedInt(value))) => {
+            Value::UnsignedInt(value)
+        }
+        (Property::Conditions, MaybePatchValue::Value(Value::List(conditions)))
+            if conditions.len() <= MAX_CONDITIONS =>
+        {
+            let mut result = Vec::with_capacity(conditions.len());
+            for condition in conditions {
+                result.push(Value::Object(parse_condition(condition).ok_or_else(
+                    || {
+                        SetError::invalid_properties()
+                            .with_property(Property::Conditions)
+                            .with_description("Invalid condition.")
+                    },
+                )?));
+            }
+            Value::List(result)
+        }
+        (Property::Actions, MaybePatchValue::Value(Value::Object(actions))) => {
+            Value::Object(parse_actions(actions).ok_or_else(|| {
+                SetError::invalid_properties()
+                    .with_property(Property::Actions)
+                    .with_description("Invalid actions.")
+            })?)
+        }
+        (
+            Property::Name | Property::IsEnabled | Property::SortOrder,
+            MaybePatchValue::Value(Value::Null),
+        ) => Value::Null,
+
+        (property, _) => {
+            return Err(SetError::invalid_properties()
+                .with_property(property.clone())
+                .with_description("Field could not be set."));
+        }
+    })
+}
+
+fn parse_condition(condition: Value) -> Option<Object<Value>> {
+    let mut field = None;
+    let mut operator = "contains";
+    let mut value = None;
+
+    if let Value::Object(condition) = condition {
+        for (key, item) in condition.properties {
+            match (key, item) {
+                (Property::_T(key), Value::Text(item)) if key == CONDITION_FIELD => {
+                    field = match item.as_str() {
+                        "from" => "from",
+                        "subject" => "subject",
+                        "listId" => "listId",
+                        _ => return None,
+                    }
+                    .into();
+                }
+                (Property::_T(key), Value::Text(item)) if key == CONDITION_OPERATOR => {
+                    operator = match item.as_str() {
+                        "contains" => "contains",
+                        "is" => "is",
+                        _ => return None,
+                    };
+                }
+                (Property::Value, Value::Text(item)) if !item.is_empty() && item.len() < 255 => {
+                    value = item.into();
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    Some(
+        Object::with_capacity(3)
+            .with_property(rule_key(CONDITION_FIELD), field?)
+            .with_property(rule_key(CONDITION_OPERATOR), operator)
+            .with_property(Property::Value, value?),
+    )
+}
+
+fn parse_actions(actions: Object<Value>) -> Option<Object<Value>> {
+    let mut result = Object::with_capacity(3);
+
+    for (key, item) in actions.properties {
+        match (key, item) {
+            (Property::_T(key), Value::Text(item)) if key == ACTION_FILE_INTO => {
+                result.set(
+                    rule_key(ACTION_FILE_INTO),
+                    Id::from_bytes(item.as_bytes())?.to_string(),
+                );
+            }
+            (Property::_T(key), Value::Bool(item)) if key == ACTION_MARK_READ => {
+                if item {
+                    result.set(rule_key(ACTION_MARK_READ), true);
+                }
+            }
+            (Property::_T(key), Value::Text(item)) if key == ACTION_FORWARD_TO => {
+                result.set(rule_key(ACTION_FORWARD_TO), sanitize_email(&item)?);
+            }
+            (_, Value::Null) => (),
+            _ => return None,
+        }
+    }
+
+    Some(result)
+}
@@ -192,4 +192,27 @@ impl JMAP {
 
         Ok(identity_ids)
     }
+
+    pub async fn identity_allowed_emails(
+        &self,
+        account_id: u32,
+    ) -> Result<Vec<String>, MethodError> {
+        Ok(self
+            .directory
+            .query(QueryBy::Id(account_id), false)
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "identity_allowed_emails",
+                    error = ?err,
+                    "Failed to query directory.");
+                MethodError::ServerPartialFail
+            })?
+            .unwrap_or_default()
+            .emails
+            .iter()
+            .filter_map(|email| sanitize_email(email))
+            .collect())
+    }
 }
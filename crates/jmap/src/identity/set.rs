@@ -21,7 +21,6 @@
  * for more details.
 */
 
-use directory::QueryBy;
 use jmap_proto::{
     error::{method::MethodError, set::SetError},
     method::set::{RequestArguments, SetRequest, SetResponse},
@@ -74,12 +73,8 @@ impl JMAP {
             // Validate email address
             if let Value::Text(email) = identity.get(&Property::Email) {
                 if !self
-                    .directory
-                    .query(QueryBy::Id(account_id), false)
-                    .await
-                    .unwrap_or_default()
-                    .unwrap_or_default()
-                    .emails
+                    .identity_allowed_emails(account_id)
+                    .await?
                     .contains(email)
                 {
                     response.not_created.append(
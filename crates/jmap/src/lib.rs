@@ -21,13 +21,17 @@
  * for more details.
 */
 
-use std::{collections::hash_map::RandomState, fmt::Display, sync::Arc, time::Duration};
+use std::{
+    collections::hash_map::RandomState, fmt::Display, net::IpAddr, sync::Arc, time::Duration,
+};
 
 use ::sieve::{Compiler, Runtime};
 use api::session::BaseCapabilities;
-use auth::{oauth::OAuthCode, rate_limit::ConcurrencyLimiters, AccessToken};
+use arc_swap::ArcSwap;
+use auth::{oauth::OAuthCode, oidc::OidcProvider, rate_limit::ConcurrencyLimiters, AccessToken};
 use dashmap::DashMap;
-use directory::{Directories, Directory, QueryBy};
+use directory::{core::scram::ScramState, Directories, Directory, QueryBy};
+use email::{cache::MessageCache, importance::ImportanceClassifier};
 use jmap_proto::{
     error::method::MethodError,
     method::{
@@ -38,14 +42,17 @@ use jmap_proto::{
 };
 use mail_parser::HeaderName;
 use nlp::language::Language;
+use push::PushStats;
 use services::{
+    cluster::ClusterHandoff,
     delivery::spawn_delivery_manager,
     housekeeper::{self, init_housekeeper, spawn_housekeeper},
+    invalidate::{spawn_cache_invalidation, CacheInvalidation},
     state::{self, init_state_manager, spawn_state_manager},
 };
 use smtp::core::SMTP;
 use store::{
-    fts::FtsFilter,
+    fts::{FtsFilter, FtsLanguageConfig},
     query::{sort::Pagination, Comparator, Filter, ResultSet, SortedResultSet},
     roaring::RoaringBitmap,
     write::{BatchBuilder, BitmapClass, DirectoryClass, TagValue, ValueClass},
@@ -53,9 +60,11 @@ use store::{
 };
 use tokio::sync::mpsc;
 use utils::{
-    config::{Rate, Servers},
+    config::{reload::ConfigReloader, Rate, Servers},
     ipc::DeliveryEvent,
+    listener::limiter::ConcurrencyLimiter,
     map::ttl_dashmap::{TtlDashMap, TtlMap},
+    metrics::{advisor::index_advisor, slow::slow_query_log},
     snowflake::SnowflakeIdGenerator,
     UnwrapFailure,
 };
@@ -65,12 +74,15 @@ pub mod auth;
 pub mod blob;
 pub mod changes;
 pub mod email;
+pub mod filter_rule;
 pub mod identity;
 pub mod mailbox;
 pub mod principal;
 pub mod push;
 pub mod quota;
+pub mod saved_search;
 pub mod services;
+pub mod settings;
 pub mod sieve;
 pub mod submission;
 pub mod thread;
@@ -82,9 +94,11 @@ pub const LONG_SLUMBER: Duration = Duration::from_secs(60 * 60 * 24);
 pub struct JMAP {
     pub store: Store,
     pub blob_store: BlobStore,
+    pub blob_replica: Option<BlobStore>,
     pub fts_store: FtsStore,
     pub lookup_store: LookupStore,
     pub config: Config,
+    pub config_reloader: ConfigReloader,
     pub directory: Arc<Directory>,
 
     pub sessions: TtlDashMap<String, u32>,
@@ -92,11 +106,15 @@ pub struct JMAP {
     pub snowflake_id: SnowflakeIdGenerator,
 
     pub concurrency_limiter: DashMap<u32, Arc<ConcurrencyLimiters>>,
+    pub download_limiter: DashMap<IpAddr, Arc<ConcurrencyLimiter>>,
     pub oauth_codes: TtlDashMap<String, Arc<OAuthCode>>,
+    pub scram_sessions: TtlDashMap<String, ScramState>,
+    pub message_cache: MessageCache,
 
     pub state_tx: mpsc::Sender<state::Event>,
     pub housekeeper_tx: mpsc::Sender<housekeeper::Event>,
     pub smtp: Arc<SMTP>,
+    pub push_stats: PushStats,
 
     pub sieve_compiler: Compiler,
     pub sieve_runtime: Runtime<()>,
@@ -104,6 +122,7 @@ pub struct JMAP {
 
 pub struct Config {
     pub default_language: Language,
+    pub fts_languages: FtsLanguageConfig,
     pub query_max_results: usize,
     pub changes_max_results: usize,
     pub snippet_max_results: usize,
@@ -118,23 +137,37 @@ pub struct Config {
     pub upload_max_size: usize,
     pub upload_max_concurrent: u64,
 
+    pub download_max_concurrent: u64,
+    pub download_max_concurrent_ip: u64,
+
     pub upload_tmp_quota_size: usize,
     pub upload_tmp_quota_amount: usize,
     pub upload_tmp_ttl: u64,
 
+    pub upload_resumable_max_size: usize,
+    pub upload_resumable_chunk_size: usize,
+    pub upload_resumable_ttl: u64,
+
     pub mailbox_max_depth: usize,
     pub mailbox_name_max_len: usize,
     pub mail_attachments_max_size: usize,
     pub mail_parse_max_items: usize,
     pub mail_max_size: usize,
+    pub mail_max_messages: u64,
 
     pub sieve_max_script_name: usize,
     pub sieve_max_scripts: usize,
 
     pub session_cache_ttl: Duration,
-    pub rate_authenticated: Rate,
-    pub rate_authenticate_req: Rate,
-    pub rate_anonymous: Rate,
+    pub orphans_batch_size: usize,
+    pub orphans_pause: Duration,
+    pub changes_retention: Duration,
+    pub scrub_batch_size: usize,
+    pub scrub_pause: Duration,
+    pub snapshots_max: usize,
+    pub cluster: Option<ClusterHandoff>,
+    pub invalidation: Option<CacheInvalidation>,
+    pub rate_limits: ArcSwap<RateLimits>,
     pub rate_use_forwarded: bool,
 
     pub event_source_throttle: Duration,
@@ -151,8 +184,22 @@ pub struct Config {
     pub oauth_expiry_refresh_token: u64,
     pub oauth_expiry_refresh_token_renew: u64,
     pub oauth_max_auth_attempts: u32,
+    pub oidc: Option<OidcProvider>,
 
     pub spam_header: Option<(HeaderName<'static>, String)>,
+    pub importance: Option<ImportanceClassifier>,
+    pub bulk_mailbox: String,
+    pub sender_list_max_entries: usize,
+    pub filter_rules_max: usize,
+    pub annotations_max_entries: usize,
+    pub annotations_max_size: usize,
+    pub saved_searches_max: usize,
+    pub unified_max_accounts: usize,
+    pub signature_verify: bool,
+    pub signature_header: bool,
+
+    pub metrics_enable: bool,
+    pub metrics_auth: Option<String>,
 
     pub http_headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
 
@@ -160,10 +207,23 @@ pub struct Config {
     pub encrypt_append: bool,
 
     pub principal_allow_lookups: bool,
+    pub principal_enumeration_score: f64,
 
     pub capabilities: BaseCapabilities,
 }
 
+pub struct RateLimits {
+    pub authenticated: Rate,
+    pub authenticate_req: Rate,
+    pub anonymous: Rate,
+    pub download_account: Option<Rate>,
+    pub download_ip: Option<Rate>,
+    pub principal_lookup: Option<Rate>,
+    pub api_cheap: Option<Rate>,
+    pub api_expensive: Option<Rate>,
+    pub management: Option<Rate>,
+}
+
 #[derive(Debug)]
 pub enum IngestError {
     Temporary,
@@ -187,6 +247,22 @@ impl JMAP {
             .property::<u64>("global.shared-map.shard")?
             .unwrap_or(32)
             .next_power_of_two() as usize;
+        slow_query_log().configure(config)?;
+        index_advisor().configure(config)?;
+
+        // Settings stored in the database are read again on every reload,
+        // keep only the ones that were loaded from the configuration file
+        let store = stores.get_store(config, "storage.data")?;
+        let mut local_config = config.clone();
+        for key in store
+            .config_list("")
+            .await
+            .map_err(|err| format!("Failed to read configuration: {err}"))?
+            .keys
+            .into_keys()
+        {
+            local_config.keys.remove(&key);
+        }
 
         let jmap_server = Arc::new(JMAP {
             directory: directories
@@ -201,11 +277,19 @@ impl JMAP {
                 .property::<u64>("storage.cluster.node-id")?
                 .map(SnowflakeIdGenerator::with_node_id)
                 .unwrap_or_else(SnowflakeIdGenerator::new),
-            store: stores.get_store(config, "storage.data")?,
+            store,
             fts_store: stores.get_fts_store(config, "storage.fts")?,
             blob_store: stores.get_blob_store(config, "storage.blob")?,
+            blob_replica: if config.value("storage.blob-replica").is_some() {
+                stores
+                    .get_blob_store(config, "storage.blob-replica")?
+                    .into()
+            } else {
+                None
+            },
             lookup_store: stores.get_lookup_store(config, "storage.lookup")?,
             config: Config::new(config).failed("Invalid configuration file"),
+            config_reloader: ConfigReloader::new(local_config),
             sessions: TtlDashMap::with_capacity(
                 config.property("jmap.session.cache.size")?.unwrap_or(100),
                 shard_amount,
@@ -221,13 +305,26 @@ impl JMAP {
                 RandomState::default(),
                 shard_amount,
             ),
+            download_limiter: DashMap::with_capacity_and_hasher_and_shard_amount(
+                config
+                    .property("jmap.rate-limit.cache.size")?
+                    .unwrap_or(1024),
+                RandomState::default(),
+                shard_amount,
+            ),
             oauth_codes: TtlDashMap::with_capacity(
                 config.property("oauth.cache.size")?.unwrap_or(128),
                 shard_amount,
             ),
+            scram_sessions: TtlDashMap::with_capacity(
+                config.property("oauth.cache.size")?.unwrap_or(128),
+                shard_amount,
+            ),
+            message_cache: MessageCache::from_config(config)?,
             state_tx,
             housekeeper_tx,
             smtp,
+            push_stats: PushStats::default(),
             sieve_compiler: Compiler::new()
                 .with_max_script_size(
                     config
@@ -377,6 +474,14 @@ impl JMAP {
                 .with_env_variable("phase", "during"),
         });
 
+        // Register reloadable settings
+        jmap_server
+            .config_reloader
+            .register("smtp", jmap_server.smtp.clone());
+        jmap_server
+            .config_reloader
+            .register("security", jmap_server.directory.blocked_ips.clone());
+
         // Spawn delivery manager
         spawn_delivery_manager(jmap_server.clone(), delivery_rx);
 
@@ -386,6 +491,9 @@ impl JMAP {
         // Spawn housekeeper
         spawn_housekeeper(jmap_server.clone(), config, servers, housekeeper_rx);
 
+        // Spawn cluster cache invalidation
+        spawn_cache_invalidation(jmap_server.clone());
+
         Ok(jmap_server)
     }
 
@@ -598,6 +706,66 @@ impl JMAP {
             })
     }
 
+    pub async fn get_used_messages(&self, account_id: u32) -> Result<i64, MethodError> {
+        let used_messages = self
+            .store
+            .get_counter(DirectoryClass::UsedMessages(account_id))
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                event = "error",
+                context = "get_used_messages",
+                account_id = account_id,
+                error = ?err,
+                "Failed to obtain used message count quota for account.");
+                MethodError::ServerPartialFail
+            })?;
+        if used_messages > 0 {
+            return Ok(used_messages);
+        }
+
+        // Accounts with messages stored before message counting was introduced
+        // have no counter, backfill it from the number of stored messages
+        let num_messages = self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .map_or(0, |ids| ids.len() as i64);
+        if num_messages != used_messages {
+            let mut batch = BatchBuilder::new();
+            batch.add(
+                DirectoryClass::UsedMessages(account_id),
+                num_messages - used_messages,
+            );
+            self.write_batch(batch).await?;
+            tracing::debug!(
+                context = "quota",
+                event = "repair",
+                account_id = account_id,
+                messages = num_messages,
+                "Repaired used message count for account."
+            );
+        }
+
+        Ok(num_messages)
+    }
+
+    /// Returns `true` when storing a message of `size` bytes would exceed the
+    /// byte quota of the account or the configured maximum number of messages.
+    pub async fn is_over_quota(
+        &self,
+        account_id: u32,
+        account_quota: i64,
+        size: i64,
+    ) -> Result<bool, MethodError> {
+        if account_quota > 0 && size + self.get_used_quota(account_id).await? > account_quota {
+            Ok(true)
+        } else if self.config.mail_max_messages > 0 {
+            Ok(self.get_used_messages(account_id).await? >= self.config.mail_max_messages as i64)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub async fn filter(
         &self,
         account_id: u32,
@@ -668,6 +836,7 @@ impl JMAP {
                 can_calculate_changes: true,
                 position: 0,
                 ids: vec![],
+                account_ids: None,
                 total: if request.calculate_total.unwrap_or(false) {
                     Some(total)
                 } else {
@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, keyword::Keyword, property::Property},
+};
+use store::{
+    ahash::AHashMap,
+    roaring::RoaringBitmap,
+    write::{
+        key::DeserializeBigEndian, BatchBuilder, Bincode, ValueClass, MAILBOX_COUNTER_SIZE,
+        MAILBOX_COUNTER_TOTAL, MAILBOX_COUNTER_UNSEEN,
+    },
+    Deserialize, IndexKeyPrefix, IterateParams, ValueKey, U32_LEN,
+};
+
+use crate::{email::metadata::MessageMetadata, JMAP};
+
+use super::UidMailbox;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxCounters {
+    pub total: u64,
+    pub unseen: u64,
+    pub size: u64,
+}
+
+pub trait UpdateMailboxCounters {
+    fn add_to_mailbox_counters(
+        &mut self,
+        mailbox_ids: impl IntoIterator<Item = u32>,
+        seen: bool,
+        size: u32,
+    ) -> &mut Self;
+
+    fn remove_from_mailbox_counters(
+        &mut self,
+        mailbox_ids: impl IntoIterator<Item = u32>,
+        seen: bool,
+        size: u32,
+    ) -> &mut Self;
+
+    fn update_mailbox_counters(
+        &mut self,
+        previous_mailboxes: &[UidMailbox],
+        previous_seen: bool,
+        current_mailboxes: &[UidMailbox],
+        current_seen: bool,
+        size: u32,
+    ) -> &mut Self;
+
+    fn mailbox_counter_delta(
+        &mut self,
+        mailbox_id: u32,
+        total: i64,
+        unseen: i64,
+        size: i64,
+    ) -> &mut Self;
+
+    fn clear_mailbox_counters(&mut self, mailbox_id: u32) -> &mut Self;
+}
+
+impl UpdateMailboxCounters for BatchBuilder {
+    fn add_to_mailbox_counters(
+        &mut self,
+        mailbox_ids: impl IntoIterator<Item = u32>,
+        seen: bool,
+        size: u32,
+    ) -> &mut Self {
+        for mailbox_id in mailbox_ids {
+            self.mailbox_counter_delta(mailbox_id, 1, i64::from(!seen), size as i64);
+        }
+        self
+    }
+
+    fn remove_from_mailbox_counters(
+        &mut self,
+        mailbox_ids: impl IntoIterator<Item = u32>,
+        seen: bool,
+        size: u32,
+    ) -> &mut Self {
+        for mailbox_id in mailbox_ids {
+            self.mailbox_counter_delta(mailbox_id, -1, -i64::from(!seen), -(size as i64));
+        }
+        self
+    }
+
+    fn update_mailbox_counters(
+        &mut self,
+        previous_mailboxes: &[UidMailbox],
+        previous_seen: bool,
+        current_mailboxes: &[UidMailbox],
+        current_seen: bool,
+        size: u32,
+    ) -> &mut Self {
+        for mailbox in previous_mailboxes {
+            if !current_mailboxes.contains(mailbox) {
+                self.remove_from_mailbox_counters([mailbox.mailbox_id], previous_seen, size);
+            } else if previous_seen != current_seen {
+                self.mailbox_counter_delta(
+                    mailbox.mailbox_id,
+                    0,
+                    if current_seen { -1 } else { 1 },
+                    0,
+                );
+            }
+        }
+        for mailbox in current_mailboxes {
+            if !previous_mailboxes.contains(mailbox) {
+                self.add_to_mailbox_counters([mailbox.mailbox_id], current_seen, size);
+            }
+        }
+        self
+    }
+
+    fn mailbox_counter_delta(
+        &mut self,
+        mailbox_id: u32,
+        total: i64,
+        unseen: i64,
+        size: i64,
+    ) -> &mut Self {
+        for (counter, value) in [
+            (MAILBOX_COUNTER_TOTAL, total),
+            (MAILBOX_COUNTER_UNSEEN, unseen),
+            (MAILBOX_COUNTER_SIZE, size),
+        ] {
+            if value != 0 {
+                self.add(
+                    ValueClass::MailboxCounter {
+                        mailbox_id,
+                        counter,
+                    },
+                    value,
+                );
+            }
+        }
+        self
+    }
+
+    fn clear_mailbox_counters(&mut self, mailbox_id: u32) -> &mut Self {
+        for counter in [
+            MAILBOX_COUNTER_TOTAL,
+            MAILBOX_COUNTER_UNSEEN,
+            MAILBOX_COUNTER_SIZE,
+        ] {
+            self.clear(ValueClass::MailboxCounter {
+                mailbox_id,
+                counter,
+            });
+        }
+        self
+    }
+}
+
+impl JMAP {
+    pub async fn mailbox_counters(
+        &self,
+        account_id: u32,
+        mailbox_id: u32,
+    ) -> Result<MailboxCounters, MethodError> {
+        let mut values = self.mailbox_counter_values(account_id, mailbox_id).await?;
+        if values[0] <= 0 {
+            // Mailboxes populated before counters were maintained have none,
+            // initialize them from the mailbox contents on first access
+            let message_ids = self
+                .get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id,
+                )
+                .await?
+                .unwrap_or_default();
+            if !message_ids.is_empty() {
+                let seen = self.seen_message_ids(account_id).await?;
+                let sizes = self.message_sizes(account_id).await?;
+                let expected = expected_counters(&message_ids, &seen, &sizes);
+                let mut batch = BatchBuilder::new();
+                batch.with_account_id(account_id).mailbox_counter_delta(
+                    mailbox_id,
+                    expected[0] - values[0],
+                    expected[1] - values[1],
+                    expected[2] - values[2],
+                );
+                self.write_batch(batch).await?;
+                values = expected;
+            }
+        }
+        let [total, unseen, size] = values;
+
+        Ok(MailboxCounters {
+            total: total.max(0) as u64,
+            unseen: unseen.max(0) as u64,
+            size: size.max(0) as u64,
+        })
+    }
+
+    async fn mailbox_counter_values(
+        &self,
+        account_id: u32,
+        mailbox_id: u32,
+    ) -> Result<[i64; 3], MethodError> {
+        let mut values = [0i64; 3];
+        for (counter, value) in [
+            MAILBOX_COUNTER_TOTAL,
+            MAILBOX_COUNTER_UNSEEN,
+            MAILBOX_COUNTER_SIZE,
+        ]
+        .into_iter()
+        .zip(values.iter_mut())
+        {
+            *value = self
+                .store
+                .get_counter(ValueKey {
+                    account_id,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::MailboxCounter {
+                        mailbox_id,
+                        counter,
+                    },
+                })
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                        event = "error",
+                        context = "mailbox_counters",
+                        account_id = account_id,
+                        mailbox_id = mailbox_id,
+                        error = ?err,
+                        "Failed to obtain mailbox counters.");
+                    MethodError::ServerPartialFail
+                })?;
+        }
+
+        Ok(values)
+    }
+
+    pub async fn message_size(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<u32, MethodError> {
+        Ok(self
+            .get_property::<Bincode<MessageMetadata>>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::BodyStructure,
+            )
+            .await?
+            .map(|metadata| metadata.inner.size as u32)
+            .unwrap_or(0))
+    }
+
+    // Recalculates the counters of every mailbox in the account from the
+    // mailbox and keyword bitmaps, fixing any drift. Returns the number of
+    // mailboxes whose counters had to be corrected.
+    pub async fn mailbox_counters_repair(&self, account_id: u32) -> Result<usize, MethodError> {
+        let mailbox_ids = self
+            .get_document_ids(account_id, Collection::Mailbox)
+            .await?
+            .unwrap_or_default();
+        if mailbox_ids.is_empty() {
+            return Ok(0);
+        }
+        let seen = self.seen_message_ids(account_id).await?;
+        let sizes = self.message_sizes(account_id).await?;
+
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(account_id);
+        let mut repaired = 0;
+        for mailbox_id in mailbox_ids {
+            let message_ids = self
+                .get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id,
+                )
+                .await?
+                .unwrap_or_default();
+            let expected = expected_counters(&message_ids, &seen, &sizes);
+            let current = self.mailbox_counter_values(account_id, mailbox_id).await?;
+            if expected != current {
+                tracing::debug!(
+                    context = "mailbox_counters_repair",
+                    event = "repair",
+                    account_id = account_id,
+                    mailbox_id = mailbox_id,
+                    expected = ?expected,
+                    found = ?current,
+                    "Repairing mailbox counters."
+                );
+                batch.mailbox_counter_delta(
+                    mailbox_id,
+                    expected[0] - current[0],
+                    expected[1] - current[1],
+                    expected[2] - current[2],
+                );
+                repaired += 1;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.store.write(batch.build()).await.map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "mailbox_counters_repair",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to write mailbox counters.");
+                MethodError::ServerPartialFail
+            })?;
+        }
+
+        Ok(repaired)
+    }
+
+    async fn seen_message_ids(&self, account_id: u32) -> Result<RoaringBitmap, MethodError> {
+        self.get_tag(
+            account_id,
+            Collection::Email,
+            Property::Keywords,
+            Keyword::Seen,
+        )
+        .await
+        .map(|seen| seen.unwrap_or_default())
+    }
+
+    // Obtains the size of every message in the account from the index
+    async fn message_sizes(&self, account_id: u32) -> Result<AHashMap<u32, i64>, MethodError> {
+        let mut sizes = AHashMap::new();
+        self.store
+            .iterate(
+                IterateParams::new(
+                    IndexKeyPrefix {
+                        account_id,
+                        collection: Collection::Email.into(),
+                        field: Property::Size.into(),
+                    },
+                    IndexKeyPrefix {
+                        account_id,
+                        collection: Collection::Email.into(),
+                        field: u8::from(Property::Size) + 1,
+                    },
+                )
+                .ascending()
+                .no_values(),
+                |key, _| {
+                    let id_pos = key.len() - U32_LEN;
+                    let document_id = key.deserialize_be_u32(id_pos)?;
+                    let size = key
+                        .get(IndexKeyPrefix::len()..id_pos)
+                        .ok_or_else(|| {
+                            store::Error::InternalError("Invalid key length".to_string())
+                        })
+                        .and_then(u32::deserialize)?;
+                    sizes.insert(document_id, size as i64);
+                    Ok(true)
+                },
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "mailbox_counters_repair",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to obtain message sizes.");
+                MethodError::ServerPartialFail
+            })?;
+
+        Ok(sizes)
+    }
+}
+
+fn expected_counters(
+    message_ids: &RoaringBitmap,
+    seen: &RoaringBitmap,
+    sizes: &AHashMap<u32, i64>,
+) -> [i64; 3] {
+    [
+        message_ids.len() as i64,
+        (message_ids.len() - (message_ids & seen).len()) as i64,
+        message_ids
+            .iter()
+            .map(|document_id| sizes.get(&document_id).copied().unwrap_or(0))
+            .sum(),
+    ]
+}
@@ -34,6 +34,8 @@ use crate::{
     JMAP,
 };
 
+use super::counters::MailboxCounters;
+
 impl JMAP {
     pub async fn mailbox_get(
         &self,
@@ -82,6 +84,9 @@ impl JMAP {
                     | Property::MyRights
             )
         });
+        let fetch_counters = properties
+            .iter()
+            .any(|p| matches!(p, Property::TotalEmails | Property::UnreadEmails));
         let mut response = GetResponse {
             account_id: request.account_id.into(),
             state: self
@@ -120,6 +125,12 @@ impl JMAP {
                 Object::with_capacity(0)
             };
 
+            let counters = if fetch_counters {
+                self.mailbox_counters(account_id, document_id).await?
+            } else {
+                MailboxCounters::default()
+            };
+
             let mut mailbox = Object::with_capacity(properties.len());
 
             for property in &properties {
@@ -140,23 +151,8 @@ impl JMAP {
                             _ => Value::Null,
                         })
                         .unwrap_or_default(),
-                    Property::TotalEmails => Value::UnsignedInt(
-                        self.get_tag(
-                            account_id,
-                            Collection::Email,
-                            Property::MailboxIds,
-                            document_id,
-                        )
-                        .await?
-                        .map(|v| v.len())
-                        .unwrap_or(0),
-                    ),
-                    Property::UnreadEmails => Value::UnsignedInt(
-                        self.mailbox_unread_tags(account_id, document_id, &message_ids)
-                            .await?
-                            .map(|v| v.len())
-                            .unwrap_or(0),
-                    ),
+                    Property::TotalEmails => Value::UnsignedInt(counters.total),
+                    Property::UnreadEmails => Value::UnsignedInt(counters.unseen),
                     Property::TotalThreads => Value::UnsignedInt(
                         self.mailbox_count_threads(
                             account_id,
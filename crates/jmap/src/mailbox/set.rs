@@ -37,6 +37,7 @@ use jmap_proto::{
         acl::Acl,
         collection::Collection,
         id::Id,
+        keyword::Keyword,
         property::Property,
         state::StateChange,
         type_state::DataType,
@@ -58,6 +59,7 @@ use crate::{
     JMAP,
 };
 
+use super::counters::UpdateMailboxCounters;
 #[allow(unused_imports)]
 use super::{UidMailbox, INBOX_ID, JUNK_ID, TRASH_ID};
 
@@ -361,6 +363,15 @@ impl JMAP {
             if remove_emails {
                 // Flag removal for state change notification
                 did_remove_emails = true;
+                let seen_message_ids = self
+                    .get_tag(
+                        account_id,
+                        Collection::Email,
+                        Property::Keywords,
+                        Keyword::Seen,
+                    )
+                    .await?
+                    .unwrap_or_default();
 
                 // If the message is in multiple mailboxes, untag it from the current mailbox,
                 // otherwise delete it.
@@ -395,6 +406,7 @@ impl JMAP {
                                 .await?
                             {
                                 // Untag message from mailbox
+                                let size = self.message_size(account_id, message_id).await?;
                                 let mut batch = BatchBuilder::new();
                                 batch
                                     .with_account_id(account_id)
@@ -402,7 +414,12 @@ impl JMAP {
                                     .update_document(message_id)
                                     .assert_value(Property::MailboxIds, &mailbox_ids)
                                     .value(Property::MailboxIds, mailbox_ids.inner, F_VALUE)
-                                    .value(Property::MailboxIds, document_id, F_BITMAP | F_CLEAR);
+                                    .value(Property::MailboxIds, document_id, F_BITMAP | F_CLEAR)
+                                    .remove_from_mailbox_counters(
+                                        [document_id],
+                                        seen_message_ids.contains(message_id),
+                                        size,
+                                    );
                                 match self.store.write(batch.build()).await {
                                     Ok(_) => changes.log_update(
                                         Collection::Email,
@@ -495,6 +512,7 @@ impl JMAP {
                 .with_collection(Collection::Mailbox)
                 .delete_document(document_id)
                 .value(Property::EmailIds, (), F_VALUE | F_CLEAR)
+                .clear_mailbox_counters(document_id)
                 .custom(ObjectIndexBuilder::new(SCHEMA).with_current(mailbox));
 
             match self.store.write(batch.build()).await {
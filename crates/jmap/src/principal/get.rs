@@ -29,13 +29,16 @@ use jmap_proto::{
     types::{collection::Collection, property::Property, state::State, value::Value},
 };
 
-use crate::JMAP;
+use crate::{auth::AccessToken, JMAP};
 
 impl JMAP {
     pub async fn principal_get(
         &self,
         mut request: GetRequest<RequestArguments>,
+        access_token: &AccessToken,
     ) -> Result<GetResponse, MethodError> {
+        self.is_principal_lookup_allowed(access_token).await?;
+
         let ids = request.unwrap_ids(self.config.get_max_objects)?;
         let properties = request.unwrap_properties(&[
             Property::Id,
@@ -104,6 +107,9 @@ impl JMAP {
             response.list.push(result);
         }
 
+        self.register_principal_lookup_misses(access_token, response.not_found.len())
+            .await?;
+
         Ok(response)
     }
 }
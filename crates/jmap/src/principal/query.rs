@@ -29,13 +29,16 @@ use jmap_proto::{
 };
 use store::{query::ResultSet, roaring::RoaringBitmap};
 
-use crate::JMAP;
+use crate::{auth::AccessToken, JMAP};
 
 impl JMAP {
     pub async fn principal_query(
         &self,
         mut request: QueryRequest<RequestArguments>,
+        access_token: &AccessToken,
     ) -> Result<QueryResponse, MethodError> {
+        self.is_principal_lookup_allowed(access_token).await?;
+
         let account_id = request.account_id.document_id();
         let mut result_set = ResultSet {
             account_id,
@@ -43,6 +46,7 @@ impl JMAP {
             results: RoaringBitmap::new(),
         };
         let mut is_set = true;
+        let mut misses = 0;
 
         for cond in std::mem::take(&mut request.filter) {
             match cond {
@@ -61,6 +65,7 @@ impl JMAP {
                         }
                     } else {
                         result_set.results = RoaringBitmap::new();
+                        misses += 1;
                     }
                     is_set = false;
                 }
@@ -74,6 +79,9 @@ impl JMAP {
                     {
                         ids.insert(id);
                     }
+                    if ids.is_empty() {
+                        misses += 1;
+                    }
                     if is_set {
                         result_set.results = ids;
                         is_set = false;
@@ -86,6 +94,9 @@ impl JMAP {
             }
         }
 
+        self.register_principal_lookup_misses(access_token, misses)
+            .await?;
+
         if is_set {
             result_set.results = self
                 .get_document_ids(u32::MAX, Collection::Principal)
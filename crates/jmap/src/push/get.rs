@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use base64::{engine::general_purpose, Engine};
 use jmap_proto::{
     error::method::MethodError,
@@ -34,9 +36,9 @@ use store::{
 };
 use utils::map::bitmap::Bitmap;
 
-use crate::{auth::AccessToken, services::state, JMAP};
+use crate::{auth::AccessToken, services::state, settings::settings_locale, JMAP};
 
-use super::{EncryptionKeys, PushSubscription, UpdateSubscription};
+use super::{EncryptionKeys, PushPolicy, PushSubscription, QuietHours, UpdateSubscription};
 
 impl JMAP {
     pub async fn push_subscription_get(
@@ -51,6 +53,11 @@ impl JMAP {
             Property::VerificationCode,
             Property::Expires,
             Property::Types,
+            Property::MinInterval,
+            Property::QuietHours,
+            Property::Status,
+            Property::FailureCount,
+            Property::LastFailureAt,
         ]);
         let account_id = access_token.primary_id();
         let push_ids = self
@@ -94,6 +101,10 @@ impl JMAP {
                 response.not_found.push(id.into());
                 continue;
             };
+            let is_verified = matches!(
+                (push.get(&Property::VerificationCode), push.get(&Property::Value)),
+                (Value::Text(code), Value::Text(expected)) if code == expected
+            );
             let mut result = Object::with_capacity(properties.len());
             for property in &properties {
                 match property {
@@ -105,6 +116,22 @@ impl JMAP {
                             "The 'url' and 'keys' properties are not readable".to_string(),
                         ));
                     }
+                    Property::Status => {
+                        let status = match push.remove(&Property::Status) {
+                            Value::Null => Value::Text(
+                                if is_verified { "active" } else { "pending" }.to_string(),
+                            ),
+                            status => status,
+                        };
+                        result.append(Property::Status, status);
+                    }
+                    Property::FailureCount => {
+                        let failure_count = match push.remove(&Property::FailureCount) {
+                            Value::Null => Value::UnsignedInt(0),
+                            failure_count => failure_count,
+                        };
+                        result.append(Property::FailureCount, failure_count);
+                    }
                     property => {
                         result.append(property.clone(), push.remove(property));
                     }
@@ -129,6 +156,19 @@ impl JMAP {
 
         let current_time = now();
 
+        // Quiet hours are expressed in the account's time zone
+        let utc_offset = self
+            .store
+            .get_value::<Object<Value>>(ValueKey {
+                account_id,
+                collection: Collection::Principal.into(),
+                document_id: 0,
+                class: ValueClass::Property(Property::Value.into()),
+            })
+            .await?
+            .map(|settings| settings_locale(&settings).utc_offset)
+            .unwrap_or_default();
+
         for document_id in document_ids {
             let mut subscription = self
                 .store
@@ -231,6 +271,26 @@ impl JMAP {
                         Bitmap::all()
                     };
 
+                    let policy = PushPolicy {
+                        min_interval: Duration::from_secs(
+                            subscription
+                                .properties
+                                .get(&Property::MinInterval)
+                                .and_then(|p| p.as_uint())
+                                .unwrap_or_default(),
+                        ),
+                        quiet_hours: subscription
+                            .properties
+                            .get(&Property::QuietHours)
+                            .and_then(|p| p.as_string())
+                            .and_then(QuietHours::parse)
+                            .map(|(start, end)| QuietHours {
+                                start,
+                                end,
+                                utc_offset,
+                            }),
+                    };
+
                     // Add verified subscription
                     subscriptions.push(UpdateSubscription::Verified(PushSubscription {
                         id: document_id,
@@ -238,6 +298,7 @@ impl JMAP {
                         expires,
                         types,
                         keys,
+                        policy,
                     }));
                 } else {
                     // Add unverified subscription
@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    object::Object,
+    types::{collection::Collection, date::UTCDate, id::Id, property::Property, value::Value},
+};
+use store::write::{now, BatchBuilder, F_VALUE};
+
+use crate::JMAP;
+
+use super::PushHealth;
+
+impl JMAP {
+    pub async fn push_subscription_health(&self, id: Id, health: PushHealth) {
+        let account_id = id.prefix_id();
+        let document_id = id.document_id();
+        let mut push = match self
+            .get_property::<Object<Value>>(
+                account_id,
+                Collection::PushSubscription,
+                document_id,
+                Property::Value,
+            )
+            .await
+        {
+            Ok(Some(push)) => push,
+            _ => return,
+        };
+
+        let current_time = UTCDate::from_timestamp(now() as i64);
+        match health {
+            PushHealth::Active => {
+                push.remove(&Property::FailureCount);
+            }
+            PushHealth::Failing | PushHealth::Expired => {
+                let failure_count = push
+                    .get(&Property::FailureCount)
+                    .as_uint()
+                    .unwrap_or_default()
+                    + 1;
+                push.set(Property::FailureCount, Value::UnsignedInt(failure_count));
+                push.set(Property::LastFailureAt, Value::Date(current_time.clone()));
+
+                // Expired subscriptions are no longer loaded by the push manager
+                if health == PushHealth::Expired {
+                    push.set(Property::Expires, Value::Date(current_time));
+                }
+            }
+        }
+        push.set(Property::Status, Value::Text(health.as_str().to_string()));
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::PushSubscription)
+            .update_document(document_id)
+            .value(Property::Value, push, F_VALUE);
+        if self.write_batch(batch).await.is_ok() && health == PushHealth::Expired {
+            self.update_push_subscriptions(account_id).await;
+        }
+    }
+}
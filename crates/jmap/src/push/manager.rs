@@ -22,22 +22,31 @@
 */
 
 use base64::{engine::general_purpose, Engine};
-use jmap_proto::types::id::Id;
-use store::ahash::{AHashMap, AHashSet};
+use jmap_proto::types::{id::Id, state::StateChange};
+use store::{
+    ahash::{AHashMap, AHashSet},
+    write::now,
+};
 use tokio::sync::mpsc;
 use utils::{config::Config, UnwrapFailure};
 
-use crate::{api::StateChangeResponse, services::IPC_CHANNEL_BUFFER, LONG_SLUMBER};
+use crate::{api::StateChangeResponse, services::IPC_CHANNEL_BUFFER, JMAP, LONG_SLUMBER};
 
-use super::{ece::ece_encrypt, EncryptionKeys, Event, PushServer, PushUpdate};
+use super::{
+    ece::ece_encrypt, EncryptionKeys, Event, PushFailure, PushHealth, PushServer, PushUpdate,
+};
 
-use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER},
+    StatusCode,
+};
 use std::{
     collections::hash_map::Entry,
+    sync::{atomic::Ordering, Arc},
     time::{Duration, Instant},
 };
 
-pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
+pub fn spawn_push_manager(core: Arc<JMAP>, settings: &Config) -> mpsc::Sender<Event> {
     let (push_tx_, mut push_rx) = mpsc::channel::<Event>(IPC_CHANNEL_BUFFER);
     let push_tx = push_tx_.clone();
 
@@ -59,6 +68,21 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
     let push_throttle: Duration = settings
         .property_or_static("jmap.push.throttle", "1s")
         .failed("Invalid configuration");
+    let push_backoff_max: Duration = settings
+        .property_or_static("jmap.push.backoff.max", "1h")
+        .failed("Invalid configuration");
+    let push_gone_max: u32 = settings
+        .property_or_static("jmap.push.prune.max-gone", "3")
+        .failed("Invalid configuration");
+
+    // Health updates are written in order by a single task
+    let (health_tx, mut health_rx) = mpsc::channel::<(Id, PushHealth)>(IPC_CHANNEL_BUFFER);
+    let core_ = core.clone();
+    tokio::spawn(async move {
+        while let Some((id, health)) = health_rx.recv().await {
+            core_.push_subscription_health(id, health).await;
+        }
+    });
 
     tokio::spawn(async move {
         let mut subscriptions = AHashMap::default();
@@ -113,7 +137,8 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                                                 keys,
                                                 push_timeout,
                                             )
-                                            .await;
+                                            .await
+                                            .ok();
                                         });
 
                                         last_verify.insert(account_id, current_time);
@@ -128,8 +153,13 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                                         continue;
                                     }
                                 }
-                                PushUpdate::Register { id, url, keys } => {
-                                    if let Entry::Vacant(entry) = subscriptions.entry(id) {
+                                PushUpdate::Register {
+                                    id,
+                                    url,
+                                    keys,
+                                    policy,
+                                } => match subscriptions.entry(id) {
+                                    Entry::Vacant(entry) => {
                                         entry.insert(PushServer {
                                             url,
                                             keys,
@@ -138,9 +168,16 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                                                 - (push_throttle + Duration::from_millis(1)),
                                             state_changes: Vec::new(),
                                             in_flight: false,
+                                            policy,
+                                            backoff_until: None,
+                                            num_gone: 0,
+                                            is_failing: false,
                                         });
                                     }
-                                }
+                                    Entry::Occupied(mut entry) => {
+                                        entry.get_mut().policy = policy;
+                                    }
+                                },
                                 PushUpdate::Unregister { id } => {
                                     subscriptions.remove(&id);
                                 }
@@ -150,12 +187,14 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                     Event::Push { ids, state_change } => {
                         for id in ids {
                             if let Some(subscription) = subscriptions.get_mut(&id) {
-                                subscription.state_changes.push(state_change.clone());
+                                subscription.add_state_change(state_change.clone());
                                 let last_request = subscription.last_request.elapsed();
 
                                 if !subscription.in_flight
+                                    && !subscription.is_quiet()
+                                    && !subscription.is_backing_off()
                                     && ((subscription.num_attempts == 0
-                                        && last_request > push_throttle)
+                                        && last_request > subscription.throttle(push_throttle))
                                         || ((1..push_attempts_max)
                                             .contains(&subscription.num_attempts)
                                             && last_request > push_attempt_interval))
@@ -174,19 +213,75 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                         subscriptions.clear();
                     }
                     Event::DeliverySuccess { id } => {
+                        core.push_stats.delivered.fetch_add(1, Ordering::Relaxed);
                         if let Some(subscription) = subscriptions.get_mut(&id) {
                             subscription.num_attempts = 0;
                             subscription.in_flight = false;
+                            subscription.backoff_until = None;
+                            subscription.num_gone = 0;
                             retry_ids.remove(&id);
+
+                            if subscription.is_failing {
+                                subscription.is_failing = false;
+                                health_tx.send((id, PushHealth::Active)).await.ok();
+                            }
                         }
                     }
-                    Event::DeliveryFailure { id, state_changes } => {
+                    Event::DeliveryFailure {
+                        id,
+                        state_changes,
+                        failure,
+                    } => {
+                        core.push_stats.failed.fetch_add(1, Ordering::Relaxed);
                         if let Some(subscription) = subscriptions.get_mut(&id) {
                             subscription.last_request = Instant::now();
                             subscription.num_attempts += 1;
-                            subscription.state_changes.extend(state_changes);
                             subscription.in_flight = false;
-                            retry_ids.insert(id);
+                            subscription.is_failing = true;
+
+                            // Endpoints that no longer exist are expired after repeated failures
+                            let health = if failure == PushFailure::Gone {
+                                subscription.num_gone += 1;
+                                if subscription.num_gone >= push_gone_max {
+                                    PushHealth::Expired
+                                } else {
+                                    PushHealth::Failing
+                                }
+                            } else {
+                                subscription.num_gone = 0;
+                                PushHealth::Failing
+                            };
+
+                            if health == PushHealth::Expired {
+                                tracing::debug!(
+                                    concat!("Expiring push subscription: ", "Endpoint {} is gone."),
+                                    subscription.url
+                                );
+                                core.push_stats.expired.fetch_add(1, Ordering::Relaxed);
+                                subscriptions.remove(&id);
+                                retry_ids.remove(&id);
+                            } else {
+                                // Back off exponentially, or as long as requested by the endpoint
+                                let backoff = match failure {
+                                    PushFailure::Transient {
+                                        retry_after: Some(retry_after),
+                                    } => {
+                                        core.push_stats.throttled.fetch_add(1, Ordering::Relaxed);
+                                        retry_after
+                                    }
+                                    _ => push_attempt_interval.saturating_mul(
+                                        1 << (subscription.num_attempts.min(16) - 1),
+                                    ),
+                                };
+                                subscription.backoff_until =
+                                    Some(Instant::now() + backoff.min(push_backoff_max));
+                                for state_change in state_changes {
+                                    subscription.add_state_change(state_change);
+                                }
+                                retry_ids.insert(id);
+                            }
+
+                            health_tx.send((id, health)).await.ok();
                         }
                     }
                 },
@@ -207,8 +302,10 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
                             let last_request = subscription.last_request.elapsed();
 
                             if !subscription.in_flight
+                                && !subscription.is_quiet()
+                                && !subscription.is_backing_off()
                                 && ((subscription.num_attempts == 0
-                                    && last_request >= push_throttle)
+                                    && last_request >= subscription.throttle(push_throttle))
                                     || (subscription.num_attempts > 0
                                         && last_request >= push_attempt_interval))
                             {
@@ -255,6 +352,42 @@ pub fn spawn_push_manager(settings: &Config) -> mpsc::Sender<Event> {
 }
 
 impl PushServer {
+    // Changes received while throttled or during quiet hours are batched into a single push
+    fn add_state_change(&mut self, state_change: StateChange) {
+        if let Some(pending) = self
+            .state_changes
+            .iter_mut()
+            .find(|pending| pending.account_id == state_change.account_id)
+        {
+            for (type_state, change_id) in state_change.types {
+                if let Some((_, last_change_id)) =
+                    pending.types.iter_mut().find(|(ts, _)| ts == &type_state)
+                {
+                    *last_change_id = std::cmp::max(*last_change_id, change_id);
+                } else {
+                    pending.types.push((type_state, change_id));
+                }
+            }
+        } else {
+            self.state_changes.push(state_change);
+        }
+    }
+
+    fn throttle(&self, push_throttle: Duration) -> Duration {
+        std::cmp::max(self.policy.min_interval, push_throttle)
+    }
+
+    fn is_backing_off(&self) -> bool {
+        self.backoff_until
+            .map_or(false, |backoff_until| backoff_until > Instant::now())
+    }
+
+    fn is_quiet(&self) -> bool {
+        self.policy
+            .quiet_hours
+            .map_or(false, |quiet_hours| quiet_hours.is_quiet(now()))
+    }
+
     fn send(&mut self, id: Id, push_tx: mpsc::Sender<Event>, push_timeout: Duration) {
         let url = self.url.clone();
         let keys = self.keys.clone();
@@ -276,7 +409,7 @@ impl PushServer {
 
             push_tx
                 .send(
-                    if http_request(
+                    match http_request(
                         url,
                         serde_json::to_string(&response).unwrap(),
                         keys,
@@ -284,9 +417,12 @@ impl PushServer {
                     )
                     .await
                     {
-                        Event::DeliverySuccess { id }
-                    } else {
-                        Event::DeliveryFailure { id, state_changes }
+                        Ok(_) => Event::DeliverySuccess { id },
+                        Err(failure) => Event::DeliveryFailure {
+                            id,
+                            state_changes,
+                            failure,
+                        },
                     },
                 )
                 .await
@@ -300,7 +436,7 @@ async fn http_request(
     mut body: String,
     keys: Option<EncryptionKeys>,
     push_timeout: Duration,
-) -> bool {
+) -> Result<(), PushFailure> {
     let client_builder = reqwest::Client::builder().timeout(push_timeout);
 
     #[cfg(feature = "test_mode")]
@@ -324,16 +460,40 @@ async fn http_request(
             Err(err) => {
                 // Do not reattempt if encryption fails.
                 tracing::debug!("Failed to encrypt push subscription to {}: {}", url, err);
-                return true;
+                return Ok(());
             }
         }
     }
 
     match client.body(body).send().await {
-        Ok(response) => response.status().is_success(),
+        Ok(response) => {
+            let status = response.status();
+            if status.is_success() {
+                Ok(())
+            } else if matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE) {
+                tracing::debug!("HTTP post to {} failed with status {}", url, status);
+                Err(PushFailure::Gone)
+            } else {
+                tracing::debug!("HTTP post to {} failed with status {}", url, status);
+                Err(PushFailure::Transient {
+                    retry_after: if status == StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error()
+                    {
+                        response
+                            .headers()
+                            .get(RETRY_AFTER)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| value.trim().parse::<u64>().ok())
+                            .map(Duration::from_secs)
+                    } else {
+                        None
+                    },
+                })
+            }
+        }
         Err(err) => {
             tracing::debug!("HTTP post to {} failed with: {}", url, err);
-            false
+            Err(PushFailure::Transient { retry_after: None })
         }
     }
 }
@@ -23,10 +23,14 @@
 
 pub mod ece;
 pub mod get;
+pub mod health;
 pub mod manager;
 pub mod set;
 
-use std::time::Instant;
+use std::{
+    sync::atomic::AtomicU64,
+    time::{Duration, Instant},
+};
 
 use jmap_proto::types::{id::Id, state::StateChange, type_state::DataType};
 use utils::map::bitmap::Bitmap;
@@ -49,6 +53,20 @@ pub struct PushSubscription {
     pub expires: u64,
     pub types: Bitmap<DataType>,
     pub keys: Option<EncryptionKeys>,
+    pub policy: PushPolicy,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PushPolicy {
+    pub min_interval: Duration,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: u32,
+    pub end: u32,
+    pub utc_offset: i32,
 }
 
 #[derive(Debug, Clone)]
@@ -72,10 +90,34 @@ pub enum Event {
     DeliveryFailure {
         id: Id,
         state_changes: Vec<StateChange>,
+        failure: PushFailure,
     },
     Reset,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushFailure {
+    // Network errors, 429 and 5xx responses
+    Transient { retry_after: Option<Duration> },
+    // 404 and 410 responses
+    Gone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushHealth {
+    Active,
+    Failing,
+    Expired,
+}
+
+#[derive(Debug, Default)]
+pub struct PushStats {
+    pub delivered: AtomicU64,
+    pub failed: AtomicU64,
+    pub throttled: AtomicU64,
+    pub expired: AtomicU64,
+}
+
 #[derive(Debug)]
 pub enum PushUpdate {
     Verify {
@@ -89,6 +131,7 @@ pub enum PushUpdate {
         id: Id,
         url: String,
         keys: Option<EncryptionKeys>,
+        policy: PushPolicy,
     },
     Unregister {
         id: Id,
@@ -103,4 +146,50 @@ pub struct PushServer {
     last_request: Instant,
     state_changes: Vec<StateChange>,
     in_flight: bool,
+    policy: PushPolicy,
+    backoff_until: Option<Instant>,
+    num_gone: u32,
+    is_failing: bool,
+}
+
+impl PushHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PushHealth::Active => "active",
+            PushHealth::Failing => "failing",
+            PushHealth::Expired => "expired",
+        }
+    }
+}
+
+impl QuietHours {
+    // Parses a local time range such as "22:00-07:00" into minutes since midnight
+    pub fn parse(value: &str) -> Option<(u32, u32)> {
+        let (start, end) = value.split_once('-')?;
+        let start = parse_minutes(start.trim())?;
+        let end = parse_minutes(end.trim())?;
+        if start != end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_quiet(&self, timestamp: u64) -> bool {
+        let minute = ((timestamp as i64 + self.utc_offset as i64).rem_euclid(86400) / 60) as u32;
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+fn parse_minutes(value: &str) -> Option<u32> {
+    let (hours, minutes) = value
+        .split_once(':')
+        .filter(|(hours, minutes)| hours.len() == 2 && minutes.len() == 2)?;
+    let hours = hours.parse::<u32>().ok().filter(|h| *h < 24)?;
+    let minutes = minutes.parse::<u32>().ok().filter(|m| *m < 60)?;
+    Some(hours * 60 + minutes)
 }
@@ -42,8 +42,11 @@ use store::{
 
 use crate::{auth::AccessToken, JMAP};
 
+use super::QuietHours;
+
 const EXPIRES_MAX: i64 = 7 * 24 * 3600; // 7 days
 const VERIFICATION_CODE_LEN: usize = 32;
+const MIN_INTERVAL_MAX: u64 = 24 * 3600; // 1 day
 
 impl JMAP {
     pub async fn push_subscription_set(
@@ -183,6 +186,15 @@ impl JMAP {
                 };
             }
 
+            // Renewing an expired subscription resets its health
+            if matches!(push.get(&Property::Status), Value::Text(status) if status == "expired")
+                && matches!(push.get(&Property::Expires),
+                    Value::Date(expires) if expires.timestamp() > now() as i64)
+            {
+                push.remove(&Property::Status);
+                push.remove(&Property::FailureCount);
+            }
+
             // Update record
             let mut batch = BatchBuilder::new();
             batch
@@ -288,8 +300,22 @@ fn validate_push_value(
                     .with_description("Verification code does not match.".to_string()));
             }
         }
+        (Property::MinInterval, MaybePatchValue::Value(Value::UnsignedInt(value)))
+            if value <= MIN_INTERVAL_MAX =>
+        {
+            Value::UnsignedInt(value)
+        }
+        (Property::QuietHours, MaybePatchValue::Value(Value::Text(value)))
+            if QuietHours::parse(&value).is_some() =>
+        {
+            Value::Text(value)
+        }
         (
-            Property::Keys | Property::Types | Property::VerificationCode,
+            Property::Keys
+            | Property::Types
+            | Property::VerificationCode
+            | Property::MinInterval
+            | Property::QuietHours,
             MaybePatchValue::Value(Value::Null),
         ) => Value::Null,
         (property, _) => {
@@ -30,6 +30,8 @@ use jmap_proto::{
 
 use crate::{auth::AccessToken, JMAP};
 
+use super::QUOTA_OCTETS;
+
 impl JMAP {
     pub async fn quota_get(
         &self,
@@ -50,11 +52,7 @@ impl JMAP {
             Property::Types,
         ]);
         let account_id = request.account_id.document_id();
-        let quota_ids = if access_token.quota > 0 {
-            vec![0u32]
-        } else {
-            vec![]
-        };
+        let quota_ids = self.quota_ids(access_token);
         let ids = if let Some(ids) = ids {
             ids
         } else {
@@ -77,19 +75,27 @@ impl JMAP {
 
             let mut result = Object::with_capacity(properties.len());
             for property in &properties {
-                let value = match property {
-                    Property::Id => Value::Id(id),
-                    Property::ResourceType => "octets".to_string().into(),
-                    Property::Used => (self.get_used_quota(account_id).await? as u64).into(),
-                    Property::HardLimit => access_token.quota.into(),
-                    Property::Scope => "account".to_string().into(),
-                    Property::Name => access_token.name.clone().into(),
-                    Property::Description => access_token.description.clone().into(),
-                    Property::Types => vec![
+                let value = match (property, document_id) {
+                    (Property::Id, _) => Value::Id(id),
+                    (Property::ResourceType, QUOTA_OCTETS) => "octets".to_string().into(),
+                    (Property::ResourceType, _) => "count".to_string().into(),
+                    (Property::Used, QUOTA_OCTETS) => {
+                        (self.get_used_quota(account_id).await? as u64).into()
+                    }
+                    (Property::Used, _) => {
+                        (self.get_used_messages(account_id).await? as u64).into()
+                    }
+                    (Property::HardLimit, QUOTA_OCTETS) => access_token.quota.into(),
+                    (Property::HardLimit, _) => self.config.mail_max_messages.into(),
+                    (Property::Scope, _) => "account".to_string().into(),
+                    (Property::Name, _) => access_token.name.clone().into(),
+                    (Property::Description, _) => access_token.description.clone().into(),
+                    (Property::Types, QUOTA_OCTETS) => vec![
                         Value::Text(DataType::Email.to_string()),
                         Value::Text(DataType::SieveScript.to_string()),
                     ]
                     .into(),
+                    (Property::Types, _) => vec![Value::Text(DataType::Email.to_string())].into(),
 
                     _ => Value::Null,
                 };
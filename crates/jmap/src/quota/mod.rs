@@ -23,3 +23,21 @@
 
 pub mod get;
 pub mod query;
+
+use crate::{auth::AccessToken, JMAP};
+
+pub const QUOTA_OCTETS: u32 = 0;
+pub const QUOTA_MESSAGES: u32 = 1;
+
+impl JMAP {
+    pub fn quota_ids(&self, access_token: &AccessToken) -> Vec<u32> {
+        let mut ids = Vec::with_capacity(2);
+        if access_token.quota > 0 {
+            ids.push(QUOTA_OCTETS);
+        }
+        if self.config.mail_max_messages > 0 {
+            ids.push(QUOTA_MESSAGES);
+        }
+        ids
+    }
+}
@@ -35,17 +35,19 @@ impl JMAP {
         request: QueryRequest<RequestArguments>,
         access_token: &AccessToken,
     ) -> Result<QueryResponse, MethodError> {
+        let ids = self
+            .quota_ids(access_token)
+            .into_iter()
+            .map(|id| Id::new(id as u64))
+            .collect::<Vec<_>>();
         Ok(QueryResponse {
             account_id: request.account_id,
             query_state: State::Initial,
             can_calculate_changes: false,
             position: 0,
-            ids: if access_token.quota > 0 {
-                vec![Id::new(0)]
-            } else {
-                vec![]
-            },
-            total: Some(1),
+            total: Some(ids.len()),
+            ids,
+            account_ids: None,
             limit: None,
         })
 
@@ -0,0 +1,273 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::query::{parse_filter, Filter},
+    object::Object,
+    parser::{json::Parser, Ignore, Token},
+    types::{collection::Collection, property::Property, state::State, value::Value},
+};
+use serde::{Deserialize, Serialize};
+use store::{
+    query::{
+        self,
+        log::{Change, Query},
+    },
+    roaring::RoaringBitmap,
+    write::{BatchBuilder, Bincode, F_VALUE},
+};
+
+use crate::JMAP;
+
+pub mod get;
+pub mod set;
+
+/// Materialized results of a saved search, kept under the search's
+/// `emailIds` property. Messages are numbered with their own UIDs so the
+/// search can be exposed as a virtual IMAP mailbox.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedSearchResults {
+    pub change_id: Option<u64>,
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub items: Vec<SavedSearchItem>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SavedSearchItem {
+    pub document_id: u32,
+    pub uid: u32,
+}
+
+impl JMAP {
+    /// Returns the up-to-date results of a saved search, or `None` if it
+    /// does not exist. Results are recomputed from scratch only after the
+    /// search is created or its filter changes; otherwise only the messages
+    /// that appear in the Email change log since the last refresh are
+    /// evaluated against the filter.
+    pub async fn saved_search_refresh(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<Option<SavedSearchResults>, MethodError> {
+        let filter = if let Some(mut search) = self
+            .get_property::<Object<Value>>(
+                account_id,
+                Collection::SavedSearch,
+                document_id,
+                Property::Value,
+            )
+            .await?
+        {
+            parse_saved_filter(&search.remove(&Property::Filter)).unwrap_or_default()
+        } else {
+            return Ok(None);
+        };
+        let results = self
+            .get_property::<Bincode<SavedSearchResults>>(
+                account_id,
+                Collection::SavedSearch,
+                document_id,
+                Property::EmailIds,
+            )
+            .await?
+            .map(|results| results.inner);
+        let change_id = match self.get_state(account_id, Collection::Email).await? {
+            State::Exact(change_id) => Some(change_id),
+            _ => None,
+        };
+
+        // Cached results that predate a change log compaction are rebuilt
+        // with a new UID validity
+        let results = match results {
+            Some(results) if results.change_id == change_id => {
+                return Ok(Some(results));
+            }
+            Some(results) => {
+                let changelog = self
+                    .changes_(
+                        account_id,
+                        Collection::Email,
+                        results.change_id.map(Query::Since).unwrap_or(Query::All),
+                    )
+                    .await?;
+                (!changelog.is_truncated).then_some((results, changelog))
+            }
+            None => None,
+        };
+
+        let results = match results {
+            Some((mut results, changelog)) => {
+                // Collect the messages that changed since the last refresh
+                let mut changed = RoaringBitmap::new();
+                let mut deleted = RoaringBitmap::new();
+                for change in changelog.changes {
+                    match change {
+                        Change::Insert(id) | Change::Update(id) | Change::ChildUpdate(id) => {
+                            changed.insert(id as u32);
+                        }
+                        Change::Delete(id) => {
+                            deleted.insert(id as u32);
+                        }
+                    }
+                }
+                changed &= self
+                    .get_document_ids(account_id, Collection::Email)
+                    .await?
+                    .unwrap_or_default();
+
+                // Re-evaluate the filter against the changed messages only
+                let mut matched = if !changed.is_empty() {
+                    let mut filters = vec![query::Filter::is_in_set(changed.clone())];
+                    self.saved_search_filters(account_id, filter, &mut filters)
+                        .await?;
+                    self.filter(account_id, Collection::Email, filters)
+                        .await?
+                        .results
+                } else {
+                    RoaringBitmap::new()
+                };
+
+                // Existing messages keep their UIDs, new matches are appended
+                results.items.retain(|item| {
+                    !deleted.contains(item.document_id)
+                        && (!changed.contains(item.document_id) || matched.remove(item.document_id))
+                });
+                for document_id in matched {
+                    results.items.push(SavedSearchItem {
+                        document_id,
+                        uid: results.uid_next,
+                    });
+                    results.uid_next += 1;
+                }
+                results.change_id = change_id;
+                results
+            }
+            None => {
+                let mut filters = Vec::with_capacity(filter.len());
+                self.saved_search_filters(account_id, filter, &mut filters)
+                    .await?;
+                let matched = self
+                    .filter(account_id, Collection::Email, filters)
+                    .await?
+                    .results;
+                SavedSearchResults {
+                    change_id,
+                    uid_validity: rand::random::<u32>().max(1),
+                    uid_next: matched.len() as u32 + 1,
+                    items: matched
+                        .into_iter()
+                        .enumerate()
+                        .map(|(pos, document_id)| SavedSearchItem {
+                            document_id,
+                            uid: pos as u32 + 1,
+                        })
+                        .collect(),
+                }
+            }
+        };
+
+        // Store the updated results
+        let results = Bincode::new(results);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::SavedSearch)
+            .update_document(document_id)
+            .value(Property::EmailIds, &results, F_VALUE);
+        self.write_batch(batch).await?;
+
+        Ok(Some(results.inner))
+    }
+
+    pub async fn saved_search_email_ids(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<RoaringBitmap, MethodError> {
+        Ok(self
+            .saved_search_refresh(account_id, document_id)
+            .await?
+            .map(|results| {
+                results
+                    .items
+                    .into_iter()
+                    .map(|item| item.document_id)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn saved_search_filters(
+        &self,
+        account_id: u32,
+        filter: Vec<Filter>,
+        filters: &mut Vec<query::Filter>,
+    ) -> Result<(), MethodError> {
+        for cond in filter {
+            self.email_store_filter(account_id, cond, filters).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a stored saved search filter. Only conditions that depend solely
+/// on the message itself are accepted: full-text conditions are indexed
+/// asynchronously and thread conditions depend on other messages, so neither
+/// can be maintained incrementally.
+pub(crate) fn parse_saved_filter(filter: &Value) -> Option<Vec<Filter>> {
+    let json = serde_json::to_vec(filter).ok()?;
+    let mut parser = Parser::new(&json);
+    parser
+        .next_token::<Ignore>()
+        .ok()?
+        .assert(Token::DictStart)
+        .ok()?;
+    let filter = parse_filter(&mut parser).ok()?;
+
+    if filter.iter().all(|cond| {
+        matches!(
+            cond,
+            Filter::InMailbox(_)
+                | Filter::InMailboxOtherThan(_)
+                | Filter::Before(_)
+                | Filter::After(_)
+                | Filter::MinSize(_)
+                | Filter::MaxSize(_)
+                | Filter::HasKeyword(_)
+                | Filter::NotKeyword(_)
+                | Filter::HasAttachment(_)
+                | Filter::SentBefore(_)
+                | Filter::SentAfter(_)
+                | Filter::And
+                | Filter::Or
+                | Filter::Not
+                | Filter::Close
+        )
+    }) {
+        Some(filter)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    object::Object,
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        property::Property,
+        value::{MaybePatchValue, Value},
+    },
+};
+use store::write::{log::ChangeLogBuilder, BatchBuilder, F_CLEAR, F_VALUE};
+
+use crate::JMAP;
+
+use super::parse_saved_filter;
+
+impl JMAP {
+    pub async fn saved_search_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+    ) -> Result<SetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut search_ids = self
+            .get_document_ids(account_id, Collection::SavedSearch)
+            .await?
+            .unwrap_or_default();
+        let mut response = self
+            .prepare_set_response(&request, Collection::SavedSearch)
+            .await?;
+        let will_destroy = request.unwrap_destroy();
+
+        // Process creates
+        let mut changes = ChangeLogBuilder::new();
+        'create: for (id, object) in request.unwrap_create() {
+            if search_ids.len() as usize >= self.config.saved_searches_max {
+                response.not_created.append(
+                    id,
+                    SetError::forbidden().with_description(format!(
+                        "There are too many saved searches, maximum is {}.",
+                        self.config.saved_searches_max
+                    )),
+                );
+                continue 'create;
+            }
+
+            let mut search = Object::with_capacity(object.properties.len());
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_search_value(&property, value))
+                {
+                    Ok(value) => {
+                        search.set(property, value);
+                    }
+                    Err(err) => {
+                        response.not_created.append(id, err);
+                        continue 'create;
+                    }
+                }
+            }
+            if let Err(err) = validate_search(&search) {
+                response.not_created.append(id, err);
+                continue 'create;
+            }
+
+            // Insert record
+            let mut batch = BatchBuilder::new();
+            let document_id = self
+                .assign_document_id(account_id, Collection::SavedSearch)
+                .await?;
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::SavedSearch)
+                .create_document(document_id)
+                .value(Property::Value, search, F_VALUE);
+            search_ids.insert(document_id);
+            self.write_batch(batch).await?;
+            changes.log_insert(Collection::SavedSearch, document_id);
+            response.created(id, document_id);
+        }
+
+        // Process updates
+        'update: for (id, object) in request.unwrap_update() {
+            // Make sure id won't be destroyed
+            if will_destroy.contains(&id) {
+                response.not_updated.append(id, SetError::will_destroy());
+                continue 'update;
+            }
+
+            // Obtain saved search
+            let document_id = id.document_id();
+            let mut search = if let Some(search) = self
+                .get_property::<Object<Value>>(
+                    account_id,
+                    Collection::SavedSearch,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                search
+            } else {
+                response.not_updated.append(id, SetError::not_found());
+                continue 'update;
+            };
+
+            let mut filter_changed = false;
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_search_value(&property, value))
+                {
+                    Ok(value) => {
+                        filter_changed |= property == Property::Filter;
+                        search.set(property, value);
+                    }
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                };
+            }
+            if let Err(err) = validate_search(&search) {
+                response.not_updated.append(id, err);
+                continue 'update;
+            }
+
+            // Update record, a new filter invalidates the materialized results
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::SavedSearch)
+                .update_document(document_id)
+                .value(Property::Value, search, F_VALUE);
+            if filter_changed {
+                batch.value(Property::EmailIds, (), F_VALUE | F_CLEAR);
+            }
+            self.write_batch(batch).await?;
+            changes.log_update(Collection::SavedSearch, document_id);
+            response.updated.append(id, None);
+        }
+
+        // Process deletions
+        for id in will_destroy {
+            let document_id = id.document_id();
+            if search_ids.contains(document_id) {
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::SavedSearch)
+                    .delete_document(document_id)
+                    .value(Property::Value, (), F_VALUE | F_CLEAR)
+                    .value(Property::EmailIds, (), F_VALUE | F_CLEAR);
+                self.write_batch(batch).await?;
+                changes.log_delete(Collection::SavedSearch, document_id);
+                response.destroyed.push(id);
+            } else {
+                response.not_destroyed.append(id, SetError::not_found());
+            }
+        }
+
+        // Write changes
+        if !changes.is_empty() {
+            response.new_state = Some(self.commit_changes(account_id, changes).await?.into());
+        }
+
+        Ok(response)
+    }
+}
+
+fn validate_search(search: &Object<Value>) -> Result<(), SetError> {
+    if !matches!(search.get(&Property::Name), Value::Text(name) if !name.is_empty()) {
+        Err(SetError::invalid_properties()
+            .with_property(Property::Name)
+            .with_description("A name is required."))
+    } else if !matches!(search.get(&Property::Filter), Value::Object(_)) {
+        Err(SetError::invalid_properties()
+            .with_property(Property::Filter)
+            .with_description("A filter is required."))
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_search_value(property: &Property, value: MaybePatchValue) -> Result<Value, SetError> {
+    Ok(match (property, value) {
+        (Property::Name, MaybePatchValue::Value(Value::Text(value)))
+            if value.len() < 255 && !value.contains('/') =>
+        {
+            Value::Text(value)
+        }
+        (Property::Filter, MaybePatchValue::Value(value @ Value::Object(_))) => {
+            if parse_saved_filter(&value).is_some() {
+                value
+            } else {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::Filter)
+                    .with_description(
+                        "Invalid filter, saved searches only support conditions on mailboxes, keywords, dates, sizes and attachments.",
+                    ));
+            }
+        }
+        (property, _) => {
+            return Err(SetError::invalid_properties()
+                .with_property(property.clone())
+                .with_description("Field could not be set."));
+        }
+    })
+}
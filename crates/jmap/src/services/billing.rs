@@ -0,0 +1,319 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::QueryBy;
+use jmap_proto::types::collection::Collection;
+use store::{
+    ahash::{AHashMap, AHashSet},
+    write::{
+        key::DeserializeBigEndian, now, BatchBuilder, BillingClass, Bincode, DirectoryClass,
+        ValueClass, BILLING_BANDWIDTH_BYTES, BILLING_RECEIVED_BYTES, BILLING_RECEIVED_MESSAGES,
+        BILLING_SENT_BYTES, BILLING_SENT_MESSAGES,
+    },
+    BitmapKey, Deserialize, IterateParams, Serialize, ValueKey,
+};
+use utils::codec::leb128::Leb128Iterator;
+
+use crate::JMAP;
+
+pub const SECONDS_PER_PERIOD: u64 = 86400;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UsageRollup {
+    pub period: u32,
+    #[serde(rename = "accountId")]
+    pub account_id: u32,
+    pub name: String,
+    pub domain: String,
+    #[serde(rename = "storageBytes")]
+    pub storage_bytes: i64,
+    pub messages: u64,
+    #[serde(rename = "receivedMessages")]
+    pub received_messages: i64,
+    #[serde(rename = "receivedBytes")]
+    pub received_bytes: i64,
+    #[serde(rename = "sentMessages")]
+    pub sent_messages: i64,
+    #[serde(rename = "sentBytes")]
+    pub sent_bytes: i64,
+    #[serde(rename = "bandwidthBytes")]
+    pub bandwidth_bytes: i64,
+}
+
+impl JMAP {
+    pub async fn billing_incr(&self, account_id: u32, metrics: &[(u8, i64)]) {
+        let mut batch = BatchBuilder::new();
+        for (metric, value) in metrics {
+            if *value != 0 {
+                batch.add(
+                    BillingClass::Counter {
+                        account_id,
+                        metric: *metric,
+                    },
+                    *value,
+                );
+            }
+        }
+
+        if !batch.is_empty() {
+            if let Err(err) = self.store.write(batch.build()).await {
+                tracing::warn!(
+                    context = "billing",
+                    event = "error",
+                    account_id = account_id,
+                    reason = ?err,
+                    "Failed to update usage counters."
+                );
+            }
+        }
+    }
+
+    pub async fn billing_rollup(&self) -> store::Result<Vec<UsageRollup>> {
+        let period = (now() / SECONDS_PER_PERIOD) as u32;
+
+        // Obtain all accounts with stored data or pending usage counters
+        let mut account_ids = AHashSet::new();
+        self.store
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(DirectoryClass::UsedQuota(0)),
+                    ValueKey::from(DirectoryClass::UsedQuota(u32::MAX)),
+                )
+                .no_values(),
+                |key, _| {
+                    if let Some(account_id) = key.get(1..).and_then(|key| key.iter().next_leb128())
+                    {
+                        account_ids.insert(account_id);
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+        self.store
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(BillingClass::Counter {
+                        account_id: 0,
+                        metric: 0,
+                    }),
+                    ValueKey::from(BillingClass::Counter {
+                        account_id: u32::MAX,
+                        metric: u8::MAX,
+                    }),
+                )
+                .no_values(),
+                |key, _| {
+                    account_ids.insert(key.deserialize_be_u32(1)?);
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        let mut rollups = Vec::with_capacity(account_ids.len());
+        for account_id in account_ids {
+            // Merge with any rollup already written for this period
+            let mut rollup = self
+                .store
+                .get_value::<Bincode<UsageRollup>>(ValueKey::from(BillingClass::Rollup {
+                    period,
+                    account_id,
+                }))
+                .await?
+                .map(|r| r.inner)
+                .unwrap_or_else(|| UsageRollup {
+                    period,
+                    account_id,
+                    ..Default::default()
+                });
+
+            // Obtain account name and domain
+            match self.directory.query(QueryBy::Id(account_id), false).await {
+                Ok(Some(principal)) => {
+                    rollup.domain = principal
+                        .emails
+                        .first()
+                        .and_then(|email| email.rsplit_once('@'))
+                        .map(|(_, domain)| domain.to_lowercase())
+                        .unwrap_or_default();
+                    rollup.name = principal.name;
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    tracing::warn!(
+                        context = "billing",
+                        event = "error",
+                        account_id = account_id,
+                        reason = ?err,
+                        "Failed to obtain principal details."
+                    );
+                }
+            }
+
+            // Obtain storage usage
+            rollup.storage_bytes = self
+                .store
+                .get_counter(DirectoryClass::UsedQuota(account_id))
+                .await?;
+            rollup.messages = self
+                .store
+                .get_bitmap(BitmapKey::document_ids(account_id, Collection::Email))
+                .await?
+                .map_or(0, |ids| ids.len());
+
+            // Move usage counters into the rollup
+            let mut batch = BatchBuilder::new();
+            for metric in [
+                BILLING_RECEIVED_MESSAGES,
+                BILLING_RECEIVED_BYTES,
+                BILLING_SENT_MESSAGES,
+                BILLING_SENT_BYTES,
+                BILLING_BANDWIDTH_BYTES,
+            ] {
+                let class = BillingClass::Counter { account_id, metric };
+                let value = self.store.get_counter(class.clone()).await?;
+                if value != 0 {
+                    match metric {
+                        BILLING_RECEIVED_MESSAGES => rollup.received_messages += value,
+                        BILLING_RECEIVED_BYTES => rollup.received_bytes += value,
+                        BILLING_SENT_MESSAGES => rollup.sent_messages += value,
+                        BILLING_SENT_BYTES => rollup.sent_bytes += value,
+                        _ => rollup.bandwidth_bytes += value,
+                    }
+                    batch.add(class, -value);
+                }
+            }
+            batch.set(
+                BillingClass::Rollup { period, account_id },
+                Bincode::new(rollup.clone()).serialize(),
+            );
+            self.store.write(batch.build()).await?;
+            rollups.push(rollup);
+        }
+
+        tracing::debug!(
+            context = "billing",
+            event = "rollup",
+            period = period,
+            accounts = rollups.len(),
+            "Usage rollup completed."
+        );
+
+        Ok(rollups)
+    }
+
+    pub async fn billing_export(
+        &self,
+        from_period: u32,
+        to_period: u32,
+        domain: Option<&str>,
+    ) -> store::Result<Vec<UsageRollup>> {
+        let mut results = Vec::new();
+        self.store
+            .iterate(
+                IterateParams::new(
+                    ValueKey::from(BillingClass::Rollup {
+                        period: from_period,
+                        account_id: 0,
+                    }),
+                    ValueKey::from(BillingClass::Rollup {
+                        period: to_period,
+                        account_id: u32::MAX,
+                    }),
+                )
+                .ascending(),
+                |_, value| {
+                    let rollup = Bincode::<UsageRollup>::deserialize(value)?.inner;
+                    if domain.map_or(true, |domain| rollup.domain.eq_ignore_ascii_case(domain)) {
+                        results.push(rollup);
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        Ok(results)
+    }
+}
+
+impl UsageRollup {
+    pub fn group_by_domain(rollups: Vec<UsageRollup>) -> Vec<UsageRollup> {
+        let mut domains: AHashMap<(u32, String), UsageRollup> = AHashMap::new();
+        for rollup in rollups {
+            let entry = domains
+                .entry((rollup.period, rollup.domain.clone()))
+                .or_insert_with(|| UsageRollup {
+                    period: rollup.period,
+                    domain: rollup.domain.clone(),
+                    ..Default::default()
+                });
+            entry.storage_bytes += rollup.storage_bytes;
+            entry.messages += rollup.messages;
+            entry.received_messages += rollup.received_messages;
+            entry.received_bytes += rollup.received_bytes;
+            entry.sent_messages += rollup.sent_messages;
+            entry.sent_bytes += rollup.sent_bytes;
+            entry.bandwidth_bytes += rollup.bandwidth_bytes;
+        }
+
+        let mut results = domains.into_values().collect::<Vec<_>>();
+        results.sort_unstable_by(|a, b| a.period.cmp(&b.period).then(a.domain.cmp(&b.domain)));
+        results
+    }
+
+    pub fn to_csv(rollups: &[UsageRollup]) -> String {
+        let mut csv = String::from(concat!(
+            "period,accountId,name,domain,storageBytes,messages,receivedMessages,",
+            "receivedBytes,sentMessages,sentBytes,bandwidthBytes\r\n"
+        ));
+        for rollup in rollups {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{}\r\n",
+                mail_parser::DateTime::from_timestamp(
+                    rollup.period as i64 * SECONDS_PER_PERIOD as i64
+                )
+                .to_rfc3339()
+                .split_once('T')
+                .map_or("", |(date, _)| date),
+                rollup.account_id,
+                csv_escape(&rollup.name),
+                csv_escape(&rollup.domain),
+                rollup.storage_bytes,
+                rollup.messages,
+                rollup.received_messages,
+                rollup.received_bytes,
+                rollup.sent_messages,
+                rollup.sent_bytes,
+                rollup.bandwidth_bytes
+            ));
+        }
+        csv
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,639 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::{body::Frame, header, Method};
+use jmap_proto::error::{method::MethodError, request::RequestError};
+use store::{
+    write::{now, BatchBuilder, Bincode, ValueClass},
+    Serialize, ValueKey,
+};
+use utils::{config::Config, constant_time_eq, ipc::DeliveryResult};
+
+use crate::{
+    api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
+    auth::AccessToken,
+    JMAP,
+};
+
+/// Accounts are sharded across the nodes of a cluster by their id, unless
+/// an explicit placement has been stored for them. Messages accepted by a
+/// node for an account homed elsewhere are handed off to the owning node,
+/// which delivers them locally, and JMAP requests are proxied to it.
+pub struct ClusterHandoff {
+    pub node_id: u64,
+    pub nodes: Vec<ClusterNode>,
+    pub auth: String,
+    pub timeout: Duration,
+    pub client: reqwest::Client,
+    pub dual_read: Duration,
+}
+
+/// Placement of an account moved away from the node its id maps to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountPlacement {
+    pub node_id: u64,
+    pub migration: Option<Migration>,
+}
+
+/// While an account is being moved both the source and destination nodes
+/// serve its sessions, new messages are delivered to the destination only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Migration {
+    pub from_node_id: u64,
+    pub started: u64,
+}
+
+// Marks requests proxied by another node so they are never proxied twice
+const PROXY_HEADER: &str = "x-cluster-proxy";
+const HOP_HEADERS: &[&str] = &["host", "connection", "content-length", "transfer-encoding"];
+
+// Query parameter pinning the requests of a JMAP session to a node
+pub const NODE_HINT_PARAM: &str = "node";
+
+// Seconds handed off deliveries are remembered, so that retries of a queued
+// message are not delivered twice
+const HANDOFF_DEDUP_TTL: u64 = 7 * 86400;
+
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: u64,
+    pub url: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status")]
+#[serde(rename_all = "lowercase")]
+enum HandoffResponse {
+    Delivered,
+    Deferred { reason: String },
+    Rejected { code: [u8; 3], reason: String },
+}
+
+impl ClusterHandoff {
+    pub fn parse(settings: &Config) -> utils::config::Result<Option<Self>> {
+        if !settings.property_or_static::<bool>("storage.cluster.handoff.enable", "false")? {
+            return Ok(None);
+        }
+
+        let node_id = settings.property_require::<u64>("storage.cluster.node-id")?;
+        let mut nodes = Vec::new();
+        for id in settings.sub_keys("storage.cluster.node", ".url") {
+            nodes.push(ClusterNode {
+                id: id.parse().map_err(|_| {
+                    format!("Invalid node id {id:?} in property \"storage.cluster.node\".")
+                })?,
+                url: settings
+                    .value_require(("storage.cluster.node", id, "url"))?
+                    .trim_end_matches('/')
+                    .to_string(),
+            });
+        }
+        nodes.sort_unstable_by_key(|node| node.id);
+        if !nodes.iter().any(|node| node.id == node_id) {
+            return Err(format!(
+                "Local node {node_id} is not listed in property \"storage.cluster.node\"."
+            ));
+        }
+
+        let timeout = settings.property_or_static("storage.cluster.handoff.timeout", "30s")?;
+        Ok(Some(ClusterHandoff {
+            node_id,
+            nodes,
+            auth: format!(
+                "Bearer {}",
+                settings.value_require("storage.cluster.handoff.secret")?
+            ),
+            timeout,
+            client: reqwest::Client::builder()
+                .connect_timeout(timeout)
+                .danger_accept_invalid_certs(settings.property_or_static(
+                    "storage.cluster.handoff.tls.allow-invalid-certs",
+                    "false",
+                )?)
+                .build()
+                .map_err(|err| format!("Failed to build cluster HTTP client: {err}"))?,
+            dual_read: settings.property_or_static("storage.cluster.migration.dual-read", "10m")?,
+        }))
+    }
+
+    pub fn node(&self, node_id: u64) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == node_id)
+    }
+
+    /// Returns whether a request carries the cluster secret, which is
+    /// compared in constant time.
+    pub fn is_authorized(&self, value: Option<&header::HeaderValue>) -> bool {
+        value.map_or(false, |value| {
+            constant_time_eq(value.as_bytes(), self.auth.as_bytes())
+        })
+    }
+
+    fn default_node(&self, account_id: u32) -> &ClusterNode {
+        &self.nodes[account_id as usize % self.nodes.len()]
+    }
+
+    /// Returns the node an account is placed on, falling back to the default
+    /// placement if the stored node is no longer part of the cluster.
+    pub fn placed_node(
+        &self,
+        account_id: u32,
+        placement: Option<&AccountPlacement>,
+    ) -> &ClusterNode {
+        placement
+            .and_then(|placement| self.node(placement.node_id))
+            .unwrap_or_else(|| self.default_node(account_id))
+    }
+
+    pub fn is_migrating(&self, placement: &AccountPlacement) -> bool {
+        placement.migration.map_or(false, |migration| {
+            migration.started + self.dual_read.as_secs() > now()
+        })
+    }
+
+    /// Returns whether a node may serve an account's sessions, which is the
+    /// case for the node it is placed on and, while it is being moved, for
+    /// the node it is moved away from.
+    pub fn serves(
+        &self,
+        account_id: u32,
+        placement: Option<&AccountPlacement>,
+        node_id: u64,
+    ) -> bool {
+        self.placed_node(account_id, placement).id == node_id
+            || placement.map_or(false, |placement| {
+                self.is_migrating(placement)
+                    && placement
+                        .migration
+                        .map_or(false, |migration| migration.from_node_id == node_id)
+            })
+    }
+}
+
+impl JMAP {
+    /// Forwards a message to the node owning the account. Returns `None` if
+    /// the node could not be reached, in which case the caller delivers the
+    /// message itself through the shared store. The queue id lets the node
+    /// recognize retries of a delivery it already completed.
+    pub async fn handoff_message(
+        &self,
+        node: &ClusterNode,
+        queue_id: u64,
+        account_id: u32,
+        rcpt: &str,
+        sender: &str,
+        raw_message: &[u8],
+    ) -> Option<DeliveryResult> {
+        let cluster = self.config.cluster.as_ref()?;
+        let url = format!(
+            "{}/cluster/deliver?{}",
+            node.url,
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("id", &queue_id.to_string())
+                .append_pair("account", &account_id.to_string())
+                .append_pair("rcpt", rcpt)
+                .append_pair("sender", sender)
+                .finish()
+        );
+
+        let result = async {
+            cluster
+                .client
+                .post(&url)
+                .timeout(cluster.timeout)
+                .header(reqwest::header::AUTHORIZATION, &cluster.auth)
+                .body(raw_message.to_vec())
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<HandoffResponse>()
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(response) => {
+                tracing::debug!(
+                    context = "cluster",
+                    event = "handoff",
+                    account_id = account_id,
+                    node_id = node.id,
+                    result = ?response,
+                    "Handed off message to owning node."
+                );
+
+                Some(match response {
+                    HandoffResponse::Delivered => DeliveryResult::Success,
+                    HandoffResponse::Deferred { reason } => DeliveryResult::TemporaryFailure {
+                        reason: reason.into(),
+                    },
+                    HandoffResponse::Rejected { code, reason } => {
+                        DeliveryResult::PermanentFailure {
+                            code,
+                            reason: reason.into(),
+                        }
+                    }
+                })
+            }
+            Err(err) => {
+                tracing::warn!(
+                    context = "cluster",
+                    event = "error",
+                    account_id = account_id,
+                    node_id = node.id,
+                    reason = %err,
+                    "Message handoff failed, delivering through the shared store."
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn account_placement(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<AccountPlacement>, MethodError> {
+        self.store
+            .get_value::<Bincode<AccountPlacement>>(ValueKey {
+                account_id,
+                collection: 0,
+                document_id: 0,
+                class: ValueClass::AccountPlacement,
+            })
+            .await
+            .map(|placement| placement.map(|placement| placement.inner))
+            .map_err(|err| {
+                tracing::error!(
+                    context = "cluster",
+                    event = "error",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to read account placement."
+                );
+                MethodError::ServerPartialFail
+            })
+    }
+
+    /// Returns the node new messages for an account are delivered to, or
+    /// `None` if it is the local node.
+    pub async fn account_delivery_node(&self, account_id: u32) -> Option<&ClusterNode> {
+        let cluster = self.config.cluster.as_ref()?;
+        let placement = self.account_placement(account_id).await.ok()?;
+        let node = cluster.placed_node(account_id, placement.as_ref());
+        if node.id != cluster.node_id {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the node serving an account's sessions, or `None` if the local
+    /// node can serve them. Both nodes serve an account while it is moved.
+    pub async fn account_serving_node(&self, account_id: u32) -> Option<&ClusterNode> {
+        let cluster = self.config.cluster.as_ref()?;
+        let placement = self.account_placement(account_id).await.ok()?;
+        if cluster.serves(account_id, placement.as_ref(), cluster.node_id) {
+            None
+        } else {
+            Some(cluster.placed_node(account_id, placement.as_ref()))
+        }
+    }
+
+    /// Returns the node the sessions of an account are pinned to, so that
+    /// requests reaching any node through a load balancer end up on the
+    /// same one. The local node is preferred when it can serve the account.
+    pub async fn account_affinity_node(&self, account_id: u32) -> Option<u64> {
+        let cluster = self.config.cluster.as_ref()?;
+        Some(
+            self.account_serving_node(account_id)
+                .await
+                .map_or(cluster.node_id, |node| node.id),
+        )
+    }
+
+    /// Returns the node hinted by a request, provided it is still allowed to
+    /// serve the account. Stale hints are ignored once a move completes.
+    async fn hinted_node(&self, req: &HttpRequest, account_id: u32) -> Option<&ClusterNode> {
+        let cluster = self.config.cluster.as_ref()?;
+        let node_id = form_urlencoded::parse(req.uri().query()?.as_bytes())
+            .find(|(key, _)| key == NODE_HINT_PARAM)
+            .and_then(|(_, value)| value.parse::<u64>().ok())?;
+        let node = cluster.node(node_id)?;
+        let placement = self.account_placement(account_id).await.ok()?;
+        if cluster.serves(account_id, placement.as_ref(), node_id) {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    /// Moves an account to another node. Both nodes serve the account until
+    /// the dual-read period ends, after which only the destination does.
+    pub async fn move_account(
+        &self,
+        account_id: u32,
+        node_id: u64,
+    ) -> Result<Option<AccountPlacement>, MethodError> {
+        let cluster = match &self.config.cluster {
+            Some(cluster) if cluster.node(node_id).is_some() => cluster,
+            _ => return Ok(None),
+        };
+        let current = self.account_placement(account_id).await?;
+        let from_node_id = cluster.placed_node(account_id, current.as_ref()).id;
+        let placement = AccountPlacement {
+            node_id,
+            migration: if from_node_id != node_id {
+                Migration {
+                    from_node_id,
+                    started: now(),
+                }
+                .into()
+            } else {
+                current.and_then(|current| current.migration)
+            },
+        };
+
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(account_id).set(
+            ValueClass::AccountPlacement,
+            Bincode::new(placement).serialize(),
+        );
+        self.write_batch(batch).await?;
+
+        tracing::info!(
+            context = "cluster",
+            event = "move",
+            account_id = account_id,
+            from_node_id = from_node_id,
+            node_id = node_id,
+            "Moving account to node."
+        );
+
+        Ok(Some(placement))
+    }
+
+    /// Removes the stored placement of an account, which returns it to its
+    /// default node.
+    pub async fn clear_account_placement(&self, account_id: u32) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .clear(ValueClass::AccountPlacement);
+        self.write_batch(batch).await
+    }
+
+    /// Moves accounts from the busiest to the least busy nodes until every
+    /// node serves about the same number of accounts. Returns the number of
+    /// accounts moved.
+    pub async fn cluster_rebalance(&self) -> Result<u64, MethodError> {
+        let cluster = match &self.config.cluster {
+            Some(cluster) => cluster,
+            None => return Ok(0),
+        };
+        let account_ids = self.store.list_indexed_accounts().await.map_err(|err| {
+            tracing::error!(
+                context = "cluster",
+                event = "error",
+                error = ?err,
+                "Failed to list accounts."
+            );
+            MethodError::ServerPartialFail
+        })?;
+
+        // Group accounts by the node they are placed on
+        let mut placements = cluster
+            .nodes
+            .iter()
+            .map(|node| (node.id, Vec::new()))
+            .collect::<Vec<_>>();
+        for account_id in account_ids {
+            let placement = self.account_placement(account_id).await?;
+            let node_id = cluster.placed_node(account_id, placement.as_ref()).id;
+            if let Some((_, accounts)) = placements.iter_mut().find(|(id, _)| *id == node_id) {
+                accounts.push(account_id);
+            }
+        }
+
+        let mut moved = 0;
+        loop {
+            placements.sort_unstable_by_key(|(_, accounts)| accounts.len());
+            let (smallest, largest) = (
+                placements.first().map_or(0, |(_, accounts)| accounts.len()),
+                placements.last().map_or(0, |(_, accounts)| accounts.len()),
+            );
+            if largest <= smallest + 1 {
+                break;
+            }
+            let account_id = placements.last_mut().unwrap().1.pop().unwrap();
+            let node_id = placements[0].0;
+            self.move_account(account_id, node_id).await?;
+            placements[0].1.push(account_id);
+            moved += 1;
+        }
+
+        Ok(moved)
+    }
+
+    /// Forwards a request to the node serving the authenticated account, or
+    /// to the node hinted by the session it belongs to. Returns `None` if the
+    /// request is to be served locally, which is also the case if the node
+    /// could not be reached.
+    pub async fn proxy_request(
+        &self,
+        req: &HttpRequest,
+        access_token: &AccessToken,
+        body: Option<&[u8]>,
+    ) -> Option<HttpResponse> {
+        let cluster = self.config.cluster.as_ref()?;
+        if cluster.is_authorized(req.headers().get(PROXY_HEADER)) {
+            return None;
+        }
+        let node = match self.hinted_node(req, access_token.primary_id()).await {
+            Some(node) if node.id == cluster.node_id => return None,
+            Some(node) => node,
+            None => self.account_serving_node(access_token.primary_id()).await?,
+        };
+        let url = format!(
+            "{}{}",
+            node.url,
+            req.uri().path_and_query().map_or("/", |path| path.as_str())
+        );
+
+        // Event streams stay open for as long as the client is connected
+        let is_stream = req.uri().path().starts_with("/jmap/eventsource");
+        let result = async {
+            let mut request = cluster
+                .client
+                .request(
+                    reqwest::Method::from_bytes(req.method().as_str().as_bytes())
+                        .unwrap_or(reqwest::Method::GET),
+                    &url,
+                )
+                .header(PROXY_HEADER, &cluster.auth);
+            if !is_stream {
+                request = request.timeout(cluster.timeout);
+            }
+            for (name, value) in req.headers() {
+                if !HOP_HEADERS.contains(&name.as_str()) {
+                    request = request.header(name.as_str(), value.as_bytes());
+                }
+            }
+            if let Some(body) = body {
+                request = request.body(body.to_vec());
+            }
+            let mut response = request.send().await?;
+            let mut builder = hyper::Response::builder().status(response.status().as_u16());
+            for (name, value) in response.headers() {
+                if !HOP_HEADERS.contains(&name.as_str()) {
+                    builder = builder.header(name.as_str(), value.as_bytes());
+                }
+            }
+            if is_stream {
+                Ok(
+                    builder.body(BoxBody::new(StreamBody::new(async_stream::stream! {
+                        while let Ok(Some(chunk)) = response.chunk().await {
+                            yield Ok::<_, hyper::Error>(Frame::data(chunk));
+                        }
+                    }))),
+                )
+            } else {
+                response.bytes().await.map(|bytes| {
+                    builder.body(Full::new(bytes).map_err(|never| match never {}).boxed())
+                })
+            }
+        }
+        .await;
+
+        match result {
+            Ok(response) => response.ok(),
+            Err(err) => {
+                tracing::warn!(
+                    context = "cluster",
+                    event = "error",
+                    account_id = access_token.primary_id(),
+                    node_id = node.id,
+                    reason = %err,
+                    "Failed to proxy request, serving it locally."
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn handle_cluster_request(&self, req: &mut HttpRequest) -> HttpResponse {
+        let cluster = match &self.config.cluster {
+            Some(cluster) if req.uri().path() == "/cluster/deliver" => cluster,
+            _ => return RequestError::not_found().into_http_response(),
+        };
+        if req.method() != Method::POST {
+            return RequestError::not_found().into_http_response();
+        }
+        if !cluster.is_authorized(req.headers().get(header::AUTHORIZATION)) {
+            return RequestError::unauthorized().into_http_response();
+        }
+
+        // Parse envelope
+        let mut queue_id = None;
+        let mut account_id = None;
+        let mut rcpt = None;
+        let mut sender = None;
+        if let Some(query) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                match key.as_ref() {
+                    "id" => {
+                        queue_id = value.parse::<u64>().ok();
+                    }
+                    "account" => {
+                        account_id = value.parse::<u32>().ok();
+                    }
+                    "rcpt" => {
+                        rcpt = value.into_owned().into();
+                    }
+                    "sender" => {
+                        sender = value.into_owned().into();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let (account_id, rcpt, sender) = match (account_id, rcpt, sender) {
+            (Some(account_id), Some(rcpt), Some(sender)) => (account_id, rcpt, sender),
+            _ => {
+                return RequestError::invalid_parameters().into_http_response();
+            }
+        };
+        let raw_message = match req.body_mut().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(_) => {
+                return RequestError::invalid_parameters().into_http_response();
+            }
+        };
+
+        // Retries of a completed delivery are acknowledged without delivering again
+        let dedup_key = queue_id.map(|queue_id| format!("ch:{queue_id}:{account_id}").into_bytes());
+        if let Some(dedup_key) = &dedup_key {
+            if self
+                .lookup_store
+                .key_exists(dedup_key.clone())
+                .await
+                .unwrap_or(false)
+            {
+                return JsonResponse::new(HandoffResponse::Delivered).into_http_response();
+            }
+        }
+
+        // Deliver locally, messages are never handed off twice
+        let result = self
+            .deliver_to_account(account_id, &rcpt, &sender, &raw_message)
+            .await;
+        if let (DeliveryResult::Success, Some(dedup_key)) = (&result, dedup_key) {
+            if let Err(err) = self
+                .lookup_store
+                .key_set(dedup_key, vec![], Some(HANDOFF_DEDUP_TTL))
+                .await
+            {
+                tracing::warn!(
+                    context = "cluster",
+                    event = "error",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to record handed off delivery."
+                );
+            }
+        }
+
+        JsonResponse::new(match result {
+            DeliveryResult::Success => HandoffResponse::Delivered,
+            DeliveryResult::TemporaryFailure { reason } => HandoffResponse::Deferred {
+                reason: reason.into_owned(),
+            },
+            DeliveryResult::PermanentFailure { code, reason } => HandoffResponse::Rejected {
+                code,
+                reason: reason.into_owned(),
+            },
+        })
+        .into_http_response()
+    }
+}
@@ -24,7 +24,10 @@
 use std::sync::Arc;
 
 use tokio::sync::mpsc;
-use utils::ipc::DeliveryEvent;
+use utils::{
+    ipc::{DeliveryEvent, DeliveryResult},
+    metrics::server::server_metrics,
+};
 
 use crate::JMAP;
 
@@ -33,7 +36,19 @@ pub fn spawn_delivery_manager(core: Arc<JMAP>, mut delivery_rx: mpsc::Receiver<D
         while let Some(event) = delivery_rx.recv().await {
             match event {
                 DeliveryEvent::Ingest { message, result_tx } => {
-                    result_tx.send(core.deliver_message(message).await).ok();
+                    let result = core.deliver_message(message).await;
+                    let metrics = server_metrics();
+                    for status in &result {
+                        metrics.local_delivery(match status {
+                            DeliveryResult::Success => "delivered",
+                            DeliveryResult::TemporaryFailure { .. } => "deferred",
+                            DeliveryResult::PermanentFailure { .. } => "rejected",
+                        });
+                    }
+                    result_tx.send(result).ok();
+                }
+                DeliveryEvent::Locale { address, result_tx } => {
+                    result_tx.send(core.address_locale(&address).await).ok();
                 }
                 DeliveryEvent::Stop => break,
             }
@@ -23,8 +23,7 @@
 
 use std::sync::Arc;
 
-use store::dispatch::blocked::BLOCKED_IP_KEY;
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 use utils::{
     config::{cron::SimpleCron, Config, Servers},
     map::ttl_dashmap::TtlMap,
@@ -33,12 +32,17 @@ use utils::{
 
 use crate::JMAP;
 
-use super::IPC_CHANNEL_BUFFER;
+use super::{purge::ROLLUP_LOCK_KEY, IPC_CHANNEL_BUFFER};
 
 pub enum Event {
     PurgeSessions,
     ReloadCertificates,
     ReloadConfig,
+    PurgeAccounts,
+    PurgeOrphans,
+    CompactChanges,
+    SnapshotAccounts,
+    ScrubBlobs,
     IndexStart,
     IndexDone,
     #[cfg(feature = "test_mode")]
@@ -55,6 +59,21 @@ pub fn spawn_housekeeper(
     let purge_cache = settings
         .property_or_static::<SimpleCron>("jmap.session.purge.frequency", "15 * *")
         .failed("Initialize housekeeper");
+    let billing_rollup = settings
+        .property::<SimpleCron>("jmap.billing.rollup.frequency")
+        .failed("Initialize housekeeper");
+    let purge_orphans = settings
+        .property::<SimpleCron>("jmap.maintenance.orphans.frequency")
+        .failed("Initialize housekeeper");
+    let compact_changes = settings
+        .property::<SimpleCron>("jmap.maintenance.changes.frequency")
+        .failed("Initialize housekeeper");
+    let snapshot_accounts = settings
+        .property::<SimpleCron>("jmap.snapshot.frequency")
+        .failed("Initialize housekeeper");
+    let scrub_blobs = settings
+        .property::<SimpleCron>("jmap.maintenance.scrub.frequency")
+        .failed("Initialize housekeeper");
 
     let certificates = std::mem::take(&mut servers.certificates);
 
@@ -71,10 +90,28 @@ pub fn spawn_housekeeper(
         });
 
         loop {
-            let time_to_next = purge_cache.time_to_next();
+            let now = Instant::now();
+            let deadline = |cron: Option<SimpleCron>| cron.map(|cron| now + cron.time_to_next());
+            let purge_at = now + purge_cache.time_to_next();
+            let rollup_at = deadline(billing_rollup);
+            let orphans_at = deadline(purge_orphans);
+            let compact_at = deadline(compact_changes);
+            let snapshot_at = deadline(snapshot_accounts);
+            let scrub_at = deadline(scrub_blobs);
+            let next_at = [rollup_at, orphans_at, compact_at, snapshot_at, scrub_at]
+                .into_iter()
+                .flatten()
+                .fold(purge_at, Instant::min);
             let mut do_purge = false;
+            let mut do_rollup = false;
+            let mut do_purge_accounts = false;
+            let mut do_purge_aliases = false;
+            let mut do_purge_orphans = false;
+            let mut do_compact_changes = false;
+            let mut do_snapshot_accounts = false;
+            let mut do_scrub_blobs = false;
 
-            match tokio::time::timeout(time_to_next, rx.recv()).await {
+            match tokio::time::timeout_at(next_at, rx.recv()).await {
                 Ok(Some(event)) => match event {
                     Event::PurgeSessions => {
                         do_purge = true;
@@ -106,34 +143,33 @@ pub fn spawn_housekeeper(
                         });
                     }
                     Event::ReloadConfig => {
-                        // Future releases will support reloading the configuration
-                        // for now, we just reload the blocked IP addresses
                         let core = core.clone();
                         tokio::spawn(async move {
-                            match core.store.config_list(BLOCKED_IP_KEY).await {
-                                Ok(config) => {
-                                    if let Err(err) =
-                                        core.directory.blocked_ips.reload_blocked_ips(&config)
-                                    {
-                                        tracing::error!(
-                                            context = "store",
-                                            event = "error",
-                                            error = ?err,
-                                            "Failed to reload configuration."
-                                        );
-                                    }
-                                }
-                                Err(err) => {
-                                    tracing::error!(
-                                        context = "store",
-                                        event = "error",
-                                        error = ?err,
-                                        "Failed to reload configuration."
-                                    );
-                                }
+                            if let Err(err) = core.reload_config().await {
+                                tracing::error!(
+                                    context = "config",
+                                    event = "error",
+                                    error = %err,
+                                    "Failed to reload configuration."
+                                );
                             }
                         });
                     }
+                    Event::PurgeAccounts => {
+                        do_purge_accounts = true;
+                    }
+                    Event::PurgeOrphans => {
+                        do_purge_orphans = true;
+                    }
+                    Event::CompactChanges => {
+                        do_compact_changes = true;
+                    }
+                    Event::SnapshotAccounts => {
+                        do_snapshot_accounts = true;
+                    }
+                    Event::ScrubBlobs => {
+                        do_scrub_blobs = true;
+                    }
                     Event::IndexStart => {
                         if !index_busy {
                             index_busy = true;
@@ -170,10 +206,94 @@ pub fn spawn_housekeeper(
                     return;
                 }
                 Err(_) => {
-                    do_purge = true;
+                    // Run every job whose deadline has passed, including
+                    // jobs sharing the same schedule
+                    let now = Instant::now();
+                    let is_due = |at: Option<Instant>| at.map_or(false, |at| at <= now);
+                    if is_due(Some(purge_at)) {
+                        do_purge = true;
+                        do_purge_accounts = true;
+                        do_purge_aliases = true;
+                    }
+                    do_rollup = is_due(rollup_at);
+                    do_purge_orphans = is_due(orphans_at);
+                    do_compact_changes = is_due(compact_at);
+                    do_snapshot_accounts = is_due(snapshot_at);
+                    do_scrub_blobs = is_due(scrub_at);
                 }
             }
 
+            if do_purge_aliases {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    core.purge_expired_aliases().await;
+                    core.purge_upload_sessions().await;
+                });
+            }
+
+            if do_purge_accounts {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    core.purge_deleted_accounts().await;
+                });
+            }
+
+            if do_purge_orphans {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Purging orphaned index entries.");
+                    core.purge_orphaned_data().await;
+                });
+            }
+
+            if do_compact_changes {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Compacting change logs.");
+                    core.compact_change_logs().await;
+                });
+            }
+
+            if do_snapshot_accounts {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Creating account snapshots.");
+                    core.snapshot_all_accounts().await;
+                });
+            }
+
+            if do_scrub_blobs {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Verifying stored blobs.");
+                    core.scrub_all_blobs().await;
+                });
+            }
+
+            if do_rollup {
+                let core = core.clone();
+                tokio::spawn(async move {
+                    if !core.claim_maintenance_lock(ROLLUP_LOCK_KEY).await {
+                        tracing::info!(
+                            context = "billing",
+                            event = "skip",
+                            "Usage rollup is already in progress."
+                        );
+                        return;
+                    }
+                    tracing::info!("Generating usage rollups.");
+                    if let Err(err) = core.billing_rollup().await {
+                        tracing::error!(
+                            context = "billing",
+                            event = "error",
+                            error = ?err,
+                            "Failed to generate usage rollups."
+                        );
+                    }
+                    core.release_maintenance_lock(ROLLUP_LOCK_KEY).await;
+                });
+            }
+
             if do_purge {
                 let core = core.clone();
                 tokio::spawn(async move {
@@ -181,8 +301,11 @@ pub fn spawn_housekeeper(
                     core.sessions.cleanup();
                     core.access_tokens.cleanup();
                     core.oauth_codes.cleanup();
+                    core.scram_sessions.cleanup();
                     core.concurrency_limiter
                         .retain(|_, limiter| limiter.is_active());
+                    core.download_limiter
+                        .retain(|_, limiter| limiter.is_active());
                 });
             }
         }
@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use jmap_proto::types::{collection::Collection, property::Property};
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, property::Property},
+};
 use store::{
     fts::index::FtsDocument,
     write::{key::DeserializeBigEndian, BatchBuilder, Bincode, ValueClass},
@@ -35,6 +38,8 @@ use crate::{
 
 use super::housekeeper::Event;
 
+const REINDEX_BATCH_SIZE: usize = 1000;
+
 #[derive(Debug)]
 struct IndexEmail {
     account_id: u32,
@@ -112,13 +117,32 @@ impl JMAP {
                         };
                         let message = metadata.inner.contents.into_message(&raw_message);
 
+                        // Remove any terms from a previous index of the message
+                        if let Err(err) = self
+                            .fts_store
+                            .remove(key.account_id, Collection::Email.into(), key.document_id)
+                            .await
+                        {
+                            tracing::error!(
+                                context = "fts_index_queued",
+                                event = "error",
+                                account_id = key.account_id,
+                                document_id = key.document_id,
+                                reason = ?err,
+                                "Failed to remove document from FTS index"
+                            );
+                            continue;
+                        }
+
                         // Index message
-                        let document =
-                            FtsDocument::with_default_language(self.config.default_language)
-                                .with_account_id(key.account_id)
-                                .with_collection(Collection::Email)
-                                .with_document_id(key.document_id)
-                                .index_message(&message);
+                        let document = FtsDocument::with_default_language(
+                            self.account_search_language(key.account_id).await,
+                        )
+                        .with_language_config(&self.config.fts_languages)
+                        .with_account_id(key.account_id)
+                        .with_collection(Collection::Email)
+                        .with_document_id(key.document_id)
+                        .index_message(&message);
                         if let Err(err) = self.fts_store.index(document).await {
                             tracing::error!(
                                 context = "fts_index_queued",
@@ -216,6 +240,60 @@ impl JMAP {
     }
 }
 
+impl JMAP {
+    /// Queues all messages of an account for indexing, which replaces the
+    /// terms they were indexed with using the current language settings.
+    pub async fn fts_reindex(&self, account_id: u32) -> Result<u64, MethodError> {
+        let document_ids = self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .unwrap_or_default();
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email);
+        let mut count = 0;
+
+        for document_id in document_ids {
+            let metadata = if let Some(metadata) = self
+                .get_property::<Bincode<MessageMetadata>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::BodyStructure,
+                )
+                .await?
+            {
+                metadata
+            } else {
+                continue;
+            };
+
+            if batch.ops.len() >= REINDEX_BATCH_SIZE {
+                self.write_batch(batch).await?;
+                batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::Email);
+            }
+            batch.update_document(document_id).set(
+                ValueClass::IndexEmail(self.generate_snowflake_id()?),
+                metadata.inner.blob_hash.as_slice().to_vec(),
+            );
+            count += 1;
+        }
+
+        if !batch.is_empty() {
+            self.write_batch(batch).await?;
+        }
+        if count > 0 {
+            let _ = self.housekeeper_tx.send(Event::IndexStart).await;
+        }
+
+        Ok(count)
+    }
+}
+
 impl Deserialize for IndexEmail {
     fn deserialize(bytes: &[u8]) -> store::Result<Self> {
         let len = bytes.len();
@@ -24,10 +24,20 @@
 use directory::QueryBy;
 use jmap_proto::types::{state::StateChange, type_state::DataType};
 use mail_parser::MessageParser;
-use store::ahash::AHashMap;
+use store::{
+    ahash::AHashMap,
+    write::{BILLING_RECEIVED_BYTES, BILLING_RECEIVED_MESSAGES},
+};
 use utils::ipc::{DeliveryResult, IngestMessage};
 
-use crate::{email::ingest::IngestEmail, mailbox::INBOX_ID, IngestError, JMAP};
+use crate::{
+    email::{
+        ingest::IngestEmail,
+        sender_list::{match_sender_list, SenderList},
+    },
+    mailbox::{INBOX_ID, TRASH_ID},
+    IngestError, JMAP,
+};
 
 impl JMAP {
     pub async fn deliver_message(&self, message: IngestMessage) -> Vec<DeliveryResult> {
@@ -60,84 +70,28 @@ impl JMAP {
 
         // Deliver to each recipient
         for (uid, (status, rcpt)) in &mut deliver_names {
-            // Check if there is an active sieve script
-            let result = match self.sieve_script_get_active(*uid).await {
-                Ok(Some(active_script)) => {
-                    self.sieve_script_ingest(
-                        &raw_message,
-                        &message.sender_address,
-                        rcpt,
+            // Hand off messages for accounts homed on other nodes, falling back
+            // to delivering through the shared store if the node is unreachable
+            if let Some(node) = self.account_delivery_node(*uid).await {
+                if let Some(result) = self
+                    .handoff_message(
+                        node,
+                        message.queue_id,
                         *uid,
-                        active_script,
+                        rcpt,
+                        &message.sender_address,
+                        &raw_message,
                     )
                     .await
-                }
-                Ok(None) => {
-                    let account_quota = match self.directory.query(QueryBy::Id(*uid), false).await {
-                        Ok(Some(p)) => p.quota as i64,
-                        Ok(None) => 0,
-                        Err(_) => {
-                            *status = DeliveryResult::TemporaryFailure {
-                                reason: "Transient server failure.".into(),
-                            };
-                            continue;
-                        }
-                    };
-
-                    self.email_ingest(IngestEmail {
-                        raw_message: &raw_message,
-                        message: MessageParser::new().parse(&raw_message),
-                        account_id: *uid,
-                        account_quota,
-                        mailbox_ids: vec![INBOX_ID],
-                        keywords: vec![],
-                        received_at: None,
-                        skip_duplicates: true,
-                        encrypt: self.config.encrypt,
-                    })
-                    .await
-                }
-                Err(_) => {
-                    *status = DeliveryResult::TemporaryFailure {
-                        reason: "Transient server failure.".into(),
-                    };
+                {
+                    *status = result;
                     continue;
                 }
-            };
-
-            match result {
-                Ok(ingested_message) => {
-                    // Notify state change
-                    if ingested_message.change_id != u64::MAX {
-                        self.broadcast_state_change(
-                            StateChange::new(*uid)
-                                .with_change(DataType::EmailDelivery, ingested_message.change_id)
-                                .with_change(DataType::Email, ingested_message.change_id)
-                                .with_change(DataType::Mailbox, ingested_message.change_id)
-                                .with_change(DataType::Thread, ingested_message.change_id),
-                        )
-                        .await;
-                    }
-                }
-                Err(err) => match err {
-                    IngestError::OverQuota => {
-                        *status = DeliveryResult::TemporaryFailure {
-                            reason: "Mailbox over quota.".into(),
-                        }
-                    }
-                    IngestError::Temporary => {
-                        *status = DeliveryResult::TemporaryFailure {
-                            reason: "Transient server failure.".into(),
-                        }
-                    }
-                    IngestError::Permanent { code, reason } => {
-                        *status = DeliveryResult::PermanentFailure {
-                            code,
-                            reason: reason.into(),
-                        }
-                    }
-                },
             }
+
+            *status = self
+                .deliver_to_account(*uid, rcpt, &message.sender_address, &raw_message)
+                .await;
         }
 
         // Build result
@@ -184,4 +138,150 @@ impl JMAP {
             })
             .collect()
     }
+
+    /// Delivers a message to a single local account, running its sieve script
+    /// or filing it according to the account's sender lists.
+    pub async fn deliver_to_account(
+        &self,
+        account_id: u32,
+        rcpt: &str,
+        sender: &str,
+        raw_message: &[u8],
+    ) -> DeliveryResult {
+        let mut skip_push = false;
+
+        // Check the account's sender lists
+        let parsed_message = MessageParser::new().parse(raw_message);
+        let settings = match self.get_account_settings(account_id).await {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+        let sender_list = parsed_message.as_ref().and_then(|parsed_message| {
+            let mut senders = vec![sender];
+            if let Some(from) = parsed_message
+                .from()
+                .and_then(|addr| addr.first())
+                .and_then(|addr| addr.address())
+            {
+                senders.push(from);
+            }
+            match_sender_list(&settings, &senders)
+        });
+
+        // Check if there is an active sieve script
+        let result = match self.sieve_script_get_active(account_id).await {
+            Ok(Some(active_script)) if sender_list != Some(SenderList::Blocked) => {
+                self.sieve_script_ingest(raw_message, sender, rcpt, account_id, active_script)
+                    .await
+            }
+            Ok(_) => {
+                let account_quota = match self.directory.query(QueryBy::Id(account_id), false).await
+                {
+                    Ok(Some(p)) => p.quota as i64,
+                    Ok(None) => 0,
+                    Err(_) => {
+                        return DeliveryResult::TemporaryFailure {
+                            reason: "Transient server failure.".into(),
+                        };
+                    }
+                };
+
+                // File mail from blocked senders into the trash and bulk mail
+                // into the newsletters mailbox, if enabled
+                let mailbox_id = match &parsed_message {
+                    Some(_) if sender_list == Some(SenderList::Blocked) => {
+                        skip_push = true;
+                        TRASH_ID
+                    }
+                    Some(parsed_message) => {
+                        match self
+                            .bulk_mailbox_id(account_id, &settings, parsed_message)
+                            .await
+                        {
+                            Ok(Some(mailbox_id)) => {
+                                skip_push = true;
+                                mailbox_id
+                            }
+                            Ok(None) => INBOX_ID,
+                            Err(_) => {
+                                return DeliveryResult::TemporaryFailure {
+                                    reason: "Transient server failure.".into(),
+                                };
+                            }
+                        }
+                    }
+                    None => INBOX_ID,
+                };
+
+                self.email_ingest(IngestEmail {
+                    raw_message,
+                    message: parsed_message,
+                    account_id,
+                    account_quota,
+                    mailbox_ids: vec![mailbox_id],
+                    keywords: vec![],
+                    received_at: None,
+                    skip_duplicates: true,
+                    encrypt: self.config.encrypt,
+                    classify: true,
+                    trusted_sender: sender_list == Some(SenderList::Allowed),
+                })
+                .await
+            }
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+
+        match result {
+            Ok(ingested_message) => {
+                // Notify state change
+                if ingested_message.change_id != u64::MAX {
+                    self.billing_incr(
+                        account_id,
+                        &[
+                            (BILLING_RECEIVED_MESSAGES, 1),
+                            (BILLING_RECEIVED_BYTES, ingested_message.size as i64),
+                        ],
+                    )
+                    .await;
+
+                    // Bulk and blocked mail does not trigger new mail notifications
+                    let mut state_change = StateChange::new(account_id);
+                    if !skip_push {
+                        state_change = state_change
+                            .with_change(DataType::EmailDelivery, ingested_message.change_id);
+                    }
+                    self.broadcast_state_change(
+                        state_change
+                            .with_change(DataType::Email, ingested_message.change_id)
+                            .with_change(DataType::Mailbox, ingested_message.change_id)
+                            .with_change(DataType::Thread, ingested_message.change_id),
+                    )
+                    .await;
+                }
+
+                DeliveryResult::Success
+            }
+            Err(err) => match err {
+                IngestError::OverQuota => DeliveryResult::PermanentFailure {
+                    code: [5, 2, 2],
+                    reason: "Mailbox full.".into(),
+                },
+                IngestError::Temporary => DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                },
+                IngestError::Permanent { code, reason } => DeliveryResult::PermanentFailure {
+                    code,
+                    reason: reason.into(),
+                },
+            },
+        }
+    }
 }
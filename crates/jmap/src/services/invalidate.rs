@@ -0,0 +1,361 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use store::{dispatch::blocked::BLOCKED_IP_KEY, LookupStore};
+use tokio::sync::broadcast::error::RecvError;
+use utils::config::{Config, ConfigKey};
+
+use crate::JMAP;
+
+/// Changes to the configuration, the directory or the blocked IP list made on
+/// one node are announced to the rest of the cluster, which drop their cached
+/// copies right away instead of waiting for them to expire. Announcements are
+/// published on a Redis channel when Redis is used as the lookup store,
+/// otherwise every node polls a set of change counters in the data store.
+pub struct CacheInvalidation {
+    pub node_id: u64,
+    pub channel: String,
+    pub poll_interval: Duration,
+    // Increments of each counter made by this node, not yet seen by the poller
+    pending: [AtomicI64; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum Invalidation {
+    Config,
+    Directory,
+    BlockedIp { ip: IpAddr },
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct InvalidationMessage {
+    node: u64,
+    #[serde(flatten)]
+    invalidation: Invalidation,
+}
+
+pub const INVALIDATION_COUNTERS: [&str; 3] = [
+    "cluster.invalidate.config",
+    "cluster.invalidate.directory",
+    "cluster.invalidate.blocked-ips",
+];
+
+impl CacheInvalidation {
+    pub fn parse(settings: &Config) -> utils::config::Result<Option<Self>> {
+        if !settings.property_or_static::<bool>("storage.cluster.invalidation.enable", "false")? {
+            return Ok(None);
+        }
+
+        Ok(Some(CacheInvalidation {
+            node_id: settings.property_require("storage.cluster.node-id")?,
+            channel: settings
+                .value("storage.cluster.invalidation.channel")
+                .unwrap_or("stalwart-invalidate")
+                .to_string(),
+            poll_interval: settings
+                .property_or_static("storage.cluster.invalidation.poll-interval", "5s")?,
+            pending: Default::default(),
+        }))
+    }
+}
+
+impl Invalidation {
+    fn counter(&self) -> usize {
+        match self {
+            Invalidation::Config => 0,
+            Invalidation::Directory => 1,
+            Invalidation::BlockedIp { .. } => 2,
+        }
+    }
+}
+
+impl JMAP {
+    /// Announces a local change to the other nodes of the cluster.
+    pub async fn broadcast_invalidation(&self, invalidation: Invalidation) {
+        let Some(settings) = &self.config.invalidation else {
+            return;
+        };
+
+        let result = if self.lookup_store.supports_pubsub() {
+            self.lookup_store
+                .publish(
+                    &settings.channel,
+                    serde_json::to_vec(&InvalidationMessage {
+                        node: settings.node_id,
+                        invalidation,
+                    })
+                    .unwrap_or_default(),
+                )
+                .await
+        } else {
+            // Other nodes reload the banned addresses from the stored configuration,
+            // make sure the ban is persisted before announcing it
+            let result = if let Invalidation::BlockedIp { ip } = invalidation {
+                self.store
+                    .config_set(
+                        [ConfigKey {
+                            key: format!("{BLOCKED_IP_KEY}.{ip}"),
+                            value: String::new(),
+                        }]
+                        .into_iter(),
+                    )
+                    .await
+            } else {
+                Ok(())
+            };
+
+            let pending = &settings.pending[invalidation.counter()];
+            pending.fetch_add(1, Ordering::Relaxed);
+            let result = match result {
+                Ok(_) => LookupStore::from(self.store.clone())
+                    .counter_incr(
+                        INVALIDATION_COUNTERS[invalidation.counter()]
+                            .as_bytes()
+                            .to_vec(),
+                        1,
+                        None,
+                    )
+                    .await
+                    .map(|_| ()),
+                Err(err) => Err(err),
+            };
+            if result.is_err() {
+                pending.fetch_sub(1, Ordering::Relaxed);
+            }
+            result
+        };
+
+        if let Err(err) = result {
+            tracing::warn!(
+                context = "cluster",
+                event = "error",
+                invalidation = ?invalidation,
+                error = ?err,
+                "Failed to broadcast cache invalidation."
+            );
+        }
+    }
+
+    /// Drops the cached data affected by a change made on another node.
+    pub async fn invalidate_cache(&self, invalidation: Invalidation) {
+        tracing::debug!(
+            context = "cluster",
+            event = "invalidate",
+            invalidation = ?invalidation,
+            "Invalidating cache."
+        );
+
+        match invalidation {
+            Invalidation::Config => {
+                if let Err(err) = self.reload_config().await {
+                    tracing::error!(
+                        context = "config",
+                        event = "error",
+                        error = %err,
+                        "Failed to reload configuration."
+                    );
+                }
+            }
+            Invalidation::Directory => {
+                for directory in self.smtp.shared.directories.values() {
+                    if let Some(cache) = &directory.cache {
+                        cache.clear();
+                    }
+                }
+                self.access_tokens.clear();
+            }
+            Invalidation::BlockedIp { ip } => {
+                self.directory.blocked_ips.add_blocked_ip(ip);
+            }
+        }
+    }
+
+    async fn reload_blocked_ips(&self) {
+        let result = match self.store.config_list("").await {
+            Ok(stored) => self
+                .config_reloader
+                .build(stored)
+                .and_then(|config| self.directory.blocked_ips.reload_blocked_ips(&config)),
+            Err(err) => Err(format!("Failed to read configuration: {err}")),
+        };
+
+        if let Err(err) = result {
+            tracing::error!(
+                context = "config",
+                event = "error",
+                error = %err,
+                "Failed to reload blocked IP addresses."
+            );
+        }
+    }
+
+    async fn invalidation_counters(&self) -> Option<[i64; 3]> {
+        let store = LookupStore::from(self.store.clone());
+        let mut counters = [0; 3];
+        for (counter, key) in counters.iter_mut().zip(INVALIDATION_COUNTERS) {
+            match store.counter_get(key.as_bytes().to_vec()).await {
+                Ok(value) => *counter = value,
+                Err(err) => {
+                    tracing::warn!(
+                        context = "cluster",
+                        event = "error",
+                        error = ?err,
+                        "Failed to read cache invalidation counters."
+                    );
+                    return None;
+                }
+            }
+        }
+        Some(counters)
+    }
+}
+
+pub fn spawn_cache_invalidation(core: Arc<JMAP>) {
+    let Some(settings) = &core.config.invalidation else {
+        return;
+    };
+    let poll_interval = settings.poll_interval;
+
+    // Announce IP addresses banned on this node
+    let core_ = core.clone();
+    let mut bans = core.directory.blocked_ips.subscribe_bans();
+    tokio::spawn(async move {
+        loop {
+            match bans.recv().await {
+                Ok(ip) => {
+                    core_
+                        .broadcast_invalidation(Invalidation::BlockedIp { ip })
+                        .await;
+                }
+                Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    if core.lookup_store.supports_pubsub() {
+        tokio::spawn(async move {
+            let settings = core.config.invalidation.as_ref().unwrap();
+            let mut is_reconnect = false;
+
+            loop {
+                match core.lookup_store.subscribe(&settings.channel).await {
+                    Ok(mut messages) => {
+                        // Changes published while disconnected were missed
+                        if is_reconnect {
+                            core.invalidate_cache(Invalidation::Config).await;
+                            core.invalidate_cache(Invalidation::Directory).await;
+                        }
+                        is_reconnect = true;
+
+                        while let Some(message) = messages.recv().await {
+                            match serde_json::from_slice::<InvalidationMessage>(&message) {
+                                Ok(message) if message.node != settings.node_id => {
+                                    core.invalidate_cache(message.invalidation).await;
+                                }
+                                Ok(_) => (),
+                                Err(err) => {
+                                    tracing::debug!(
+                                        context = "cluster",
+                                        event = "error",
+                                        error = ?err,
+                                        "Failed to parse cache invalidation message."
+                                    );
+                                }
+                            }
+                        }
+
+                        tracing::warn!(
+                            context = "cluster",
+                            event = "disconnect",
+                            "Lost connection to the cache invalidation channel."
+                        );
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "cluster",
+                            event = "error",
+                            error = ?err,
+                            "Failed to subscribe to the cache invalidation channel."
+                        );
+                    }
+                }
+
+                tokio::time::sleep(poll_interval).await;
+            }
+        });
+    } else {
+        tokio::spawn(async move {
+            let settings = core.config.invalidation.as_ref().unwrap();
+            let mut last_counters = None;
+
+            loop {
+                if let Some(counters) = core.invalidation_counters().await {
+                    if let Some(last_counters) = last_counters {
+                        for (pos, (counter, last_counter)) in
+                            counters.into_iter().zip(last_counters).enumerate()
+                        {
+                            let changes = counter - last_counter;
+                            if changes <= 0 {
+                                continue;
+                            }
+
+                            // Skip the changes announced by this node
+                            let pending = &settings.pending[pos];
+                            let own = pending.load(Ordering::Relaxed).min(changes);
+                            pending.fetch_sub(own, Ordering::Relaxed);
+                            if changes == own {
+                                continue;
+                            }
+
+                            match pos {
+                                0 => core.invalidate_cache(Invalidation::Config).await,
+                                1 => core.invalidate_cache(Invalidation::Directory).await,
+                                _ => core.reload_blocked_ips().await,
+                            }
+                        }
+                    } else {
+                        // Changes made before startup are already applied
+                        for pending in &settings.pending {
+                            pending.store(0, Ordering::Relaxed);
+                        }
+                    }
+                    last_counters = Some(counters);
+                }
+
+                tokio::time::sleep(poll_interval).await;
+            }
+        });
+    }
+}
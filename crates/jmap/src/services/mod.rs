@@ -21,10 +21,16 @@
  * for more details.
 */
 
+pub mod billing;
+pub mod cluster;
 pub mod delivery;
 pub mod housekeeper;
 pub mod index;
 pub mod ingest;
+pub mod invalidate;
+pub mod purge;
+pub mod rename;
+pub mod snapshot;
 pub mod state;
 
 pub const IPC_CHANNEL_BUFFER: usize = 1024;
@@ -0,0 +1,371 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::{manage::ManageDirectory, PurgeStage};
+use jmap_proto::types::collection::Collection;
+
+use crate::JMAP;
+
+// Locks shared by scheduled and administrative maintenance runs, so that
+// only one of them advances the scrub cursor or drains usage counters
+const SCRUB_LOCK_KEY: &[u8] = b"scrub-lock";
+pub const ROLLUP_LOCK_KEY: &[u8] = b"rollup-lock";
+pub const MAINTENANCE_LOCK_TTL: u64 = 600;
+
+impl JMAP {
+    pub async fn purge_expired_aliases(&self) {
+        match self.store.purge_expired_aliases().await {
+            Ok(0) => (),
+            Ok(num_expired) => {
+                tracing::info!(
+                    context = "account",
+                    event = "purge",
+                    expired = num_expired,
+                    "Removed expired temporary aliases."
+                );
+            }
+            Err(err) => {
+                tracing::error!(
+                    context = "account",
+                    event = "error",
+                    error = ?err,
+                    "Failed to remove expired temporary aliases."
+                );
+            }
+        }
+    }
+
+    pub async fn purge_deleted_accounts(&self) {
+        let tombstones = match self.store.list_tombstones().await {
+            Ok(tombstones) => tombstones,
+            Err(err) => {
+                tracing::error!(
+                    context = "account",
+                    event = "error",
+                    error = ?err,
+                    "Failed to obtain deleted accounts."
+                );
+                return;
+            }
+        };
+
+        for tombstone in tombstones {
+            let account_id = tombstone.account_id;
+            let mut next = Some(tombstone);
+
+            while let Some(tombstone) = next {
+                // Remove full-text index before any other data
+                if tombstone.stage == PurgeStage::Index {
+                    if let Err(err) = self.fts_store.remove_all(account_id).await {
+                        tracing::warn!(
+                            context = "account",
+                            event = "error",
+                            account_id = account_id,
+                            stage = tombstone.stage.as_str(),
+                            reason = ?err,
+                            "Failed to remove FTS index."
+                        );
+                        break;
+                    }
+                }
+
+                match self.store.advance_tombstone(tombstone).await {
+                    Ok(result) => {
+                        tracing::debug!(
+                            context = "account",
+                            event = "purge",
+                            account_id = account_id,
+                            stage = tombstone.stage.as_str(),
+                            "Completed account purge stage."
+                        );
+                        next = result;
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "account",
+                            event = "error",
+                            account_id = account_id,
+                            stage = tombstone.stage.as_str(),
+                            reason = ?err,
+                            "Failed to purge account data, will retry later."
+                        );
+                        break;
+                    }
+                }
+
+                if next.is_none() {
+                    tracing::info!(
+                        context = "account",
+                        event = "purged",
+                        account_id = account_id,
+                        "Deleted account data purged."
+                    );
+                }
+            }
+        }
+    }
+
+    pub async fn purge_orphaned_data(&self) {
+        let account_ids = match self.store.list_orphaned_accounts().await {
+            Ok(account_ids) => account_ids,
+            Err(err) => {
+                tracing::error!(
+                    context = "maintenance",
+                    event = "error",
+                    error = ?err,
+                    "Failed to obtain orphaned accounts."
+                );
+                return;
+            }
+        };
+
+        let mut total_keys = 0;
+        let mut total_bytes = 0;
+        for account_id in account_ids {
+            match self
+                .store
+                .purge_account_indexes(
+                    account_id,
+                    self.config.orphans_batch_size,
+                    self.config.orphans_pause,
+                )
+                .await
+            {
+                Ok((keys, bytes)) => {
+                    tracing::debug!(
+                        context = "maintenance",
+                        event = "purge",
+                        account_id = account_id,
+                        keys = keys,
+                        bytes = bytes,
+                        "Removed orphaned index entries."
+                    );
+                    total_keys += keys;
+                    total_bytes += bytes;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "maintenance",
+                        event = "error",
+                        account_id = account_id,
+                        reason = ?err,
+                        "Failed to remove orphaned index entries, will retry later."
+                    );
+                }
+            }
+        }
+
+        if total_keys > 0 {
+            tracing::info!(
+                context = "maintenance",
+                event = "purge",
+                keys = total_keys,
+                bytes = total_bytes,
+                "Reclaimed space used by orphaned index entries."
+            );
+        }
+    }
+
+    pub async fn compact_change_logs(&self) {
+        let before_change_id = match self.snowflake_id.past_id(self.config.changes_retention) {
+            Some(before_change_id) => before_change_id,
+            None => return,
+        };
+        let account_ids = match self.store.list_indexed_accounts().await {
+            Ok(account_ids) => account_ids,
+            Err(err) => {
+                tracing::error!(
+                    context = "maintenance",
+                    event = "error",
+                    error = ?err,
+                    "Failed to obtain accounts."
+                );
+                return;
+            }
+        };
+
+        let mut total_entries = 0;
+        for account_id in account_ids {
+            for collection in 0..Collection::None as u8 {
+                match self
+                    .store
+                    .compact_changes(account_id, collection, before_change_id)
+                    .await
+                {
+                    Ok(0) => (),
+                    Ok(num_entries) => {
+                        tracing::debug!(
+                            context = "maintenance",
+                            event = "compact",
+                            account_id = account_id,
+                            collection = ?Collection::from(collection),
+                            entries = num_entries,
+                            "Compacted change log."
+                        );
+                        total_entries += num_entries;
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "maintenance",
+                            event = "error",
+                            account_id = account_id,
+                            collection = ?Collection::from(collection),
+                            reason = ?err,
+                            "Failed to compact change log, will retry later."
+                        );
+                    }
+                }
+            }
+        }
+
+        if total_entries > 0 {
+            tracing::info!(
+                context = "maintenance",
+                event = "compact",
+                entries = total_entries,
+                "Removed expired change log entries."
+            );
+        }
+    }
+
+    /// Claims a maintenance lock, returning `false` if another run holds it.
+    /// Lookup stores without support for claims do not block runs.
+    pub async fn claim_maintenance_lock(&self, key: &[u8]) -> bool {
+        match self
+            .lookup_store
+            .key_claim(key.to_vec(), MAINTENANCE_LOCK_TTL)
+            .await
+        {
+            Ok(claimed) => claimed,
+            Err(err) => {
+                tracing::warn!(
+                    context = "maintenance",
+                    event = "error",
+                    lock = String::from_utf8_lossy(key).as_ref(),
+                    reason = ?err,
+                    "Failed to claim maintenance lock."
+                );
+                true
+            }
+        }
+    }
+
+    pub async fn release_maintenance_lock(&self, key: &[u8]) {
+        if let Err(err) = self.lookup_store.key_delete(key.to_vec()).await {
+            tracing::warn!(
+                context = "maintenance",
+                event = "error",
+                lock = String::from_utf8_lossy(key).as_ref(),
+                reason = ?err,
+                "Failed to release maintenance lock."
+            );
+        }
+    }
+
+    pub async fn claim_scrub_lock(&self) -> bool {
+        self.claim_maintenance_lock(SCRUB_LOCK_KEY).await
+    }
+
+    pub async fn release_scrub_lock(&self) {
+        self.release_maintenance_lock(SCRUB_LOCK_KEY).await
+    }
+
+    pub async fn scrub_all_blobs(&self) {
+        if !self.claim_scrub_lock().await {
+            tracing::info!(
+                context = "maintenance",
+                event = "skip",
+                "Blob verification is already in progress."
+            );
+            return;
+        }
+
+        let mut verified = 0;
+        let mut repaired = 0;
+        let mut quarantined = 0;
+        loop {
+            match self
+                .store
+                .scrub_blobs(
+                    &self.blob_store,
+                    self.blob_replica.as_ref(),
+                    self.config.scrub_batch_size,
+                    self.config.scrub_pause,
+                )
+                .await
+            {
+                Ok(scrub) => {
+                    for hash in &scrub.repaired {
+                        tracing::warn!(
+                            context = "maintenance",
+                            event = "repair",
+                            blob_id = ?hash,
+                            "Repaired damaged blob from replica."
+                        );
+                    }
+                    for (hash, health) in &scrub.quarantined {
+                        tracing::error!(
+                            context = "maintenance",
+                            event = "quarantine",
+                            blob_id = ?hash,
+                            health = ?health,
+                            "Blob failed verification and was quarantined."
+                        );
+                    }
+                    verified += scrub.verified;
+                    repaired += scrub.repaired.len();
+                    quarantined += scrub.quarantined.len();
+                    if scrub.complete {
+                        break;
+                    }
+
+                    // Extend the lock for the next batch
+                    let _ = self
+                        .lookup_store
+                        .key_set(SCRUB_LOCK_KEY.to_vec(), vec![], Some(MAINTENANCE_LOCK_TTL))
+                        .await;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "maintenance",
+                        event = "error",
+                        reason = ?err,
+                        "Failed to verify blobs, will resume later."
+                    );
+                    self.release_scrub_lock().await;
+                    return;
+                }
+            }
+        }
+
+        tracing::info!(
+            context = "maintenance",
+            event = "scrub",
+            verified = verified,
+            repaired = repaired,
+            quarantined = quarantined,
+            "Completed blob verification pass."
+        );
+        self.release_scrub_lock().await;
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::RenamedAddress;
+use jmap_proto::{
+    error::method::MethodError,
+    object::Object,
+    types::{collection::Collection, property::Property, value::Value},
+};
+use store::write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, F_VALUE};
+
+use crate::JMAP;
+
+const MAX_RETRIES: usize = 3;
+
+impl JMAP {
+    pub async fn apply_renamed_addresses(
+        &self,
+        renamed: &[RenamedAddress],
+    ) -> Result<(), MethodError> {
+        for address in renamed {
+            // Update identities using the old address
+            let account_id = address.account_id;
+            let mut changes = ChangeLogBuilder::new();
+            for document_id in self
+                .get_document_ids(account_id, Collection::Identity)
+                .await?
+                .unwrap_or_default()
+            {
+                // Retry if the identity is modified concurrently
+                for _ in 0..MAX_RETRIES {
+                    let identity = if let Some(identity) = self
+                        .get_property::<HashedValue<Object<Value>>>(
+                            account_id,
+                            Collection::Identity,
+                            document_id,
+                            Property::Value,
+                        )
+                        .await?
+                    {
+                        identity
+                    } else {
+                        break;
+                    };
+                    if !identity
+                        .inner
+                        .get(&Property::Email)
+                        .as_string()
+                        .map_or(false, |email| email.eq_ignore_ascii_case(&address.old))
+                    {
+                        break;
+                    }
+
+                    let mut batch = BatchBuilder::new();
+                    batch
+                        .with_account_id(account_id)
+                        .with_collection(Collection::Identity)
+                        .update_document(document_id)
+                        .assert_value(Property::Value, &identity);
+                    let mut identity = identity.inner;
+                    identity.set(Property::Email, address.new.clone());
+                    batch.value(Property::Value, identity, F_VALUE);
+                    match self.store.write(batch.build()).await {
+                        Ok(_) => {
+                            changes.log_update(Collection::Identity, document_id);
+                            break;
+                        }
+                        Err(store::Error::AssertValueFailed) => {
+                            tracing::debug!(
+                                context = "account",
+                                event = "rename",
+                                account_id = account_id,
+                                document_id = document_id,
+                                "Identity modified concurrently, retrying."
+                            );
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                event = "error",
+                                context = "account",
+                                account_id = account_id,
+                                document_id = document_id,
+                                error = ?err,
+                                "Failed to update identity."
+                            );
+                            return Err(MethodError::ServerPartialFail);
+                        }
+                    }
+                }
+            }
+            if !changes.is_empty() {
+                self.commit_changes(account_id, changes).await?;
+            }
+
+            // Update queued messages
+            let num_messages = self
+                .smtp
+                .rename_queued_address(&address.old, &address.new)
+                .await;
+
+            // Invalidate cached access tokens
+            self.access_tokens.remove(&account_id);
+
+            tracing::info!(
+                context = "account",
+                event = "rename",
+                account_id = account_id,
+                old = address.old.as_str(),
+                new = address.new.as_str(),
+                queued_messages = num_messages,
+                "Renamed account address."
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,676 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::set::{RequestArguments, SetRequest},
+    object::Object,
+    types::{
+        collection::Collection,
+        id::Id,
+        keyword::Keyword,
+        property::Property,
+        state::StateChange,
+        type_state::DataType,
+        value::{SetValue, Value},
+    },
+};
+use mail_parser::MessageParser;
+use store::{
+    ahash::{AHashMap, AHashSet},
+    write::{now, BatchBuilder, Bincode, BlobOp, F_CLEAR, F_VALUE},
+    Deserialize, Serialize,
+};
+use utils::{map::vec_map::VecMap, BlobHash};
+
+use crate::{
+    email::{ingest::IngestEmail, metadata::MessageMetadata},
+    mailbox::{UidMailbox, INBOX_ID},
+    IngestError, JMAP,
+};
+
+// Maximum number of blob links written in a single batch
+const LINK_BATCH_SIZE: usize = 1000;
+
+/// Summary of an account snapshot, stored as the value of a document in
+/// the snapshot collection. The full mailbox tree and message list are kept
+/// in the manifest blob.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotInfo {
+    pub created_at: u64,
+    pub email_change_id: Option<u64>,
+    pub mailbox_change_id: Option<u64>,
+    pub manifest: BlobHash,
+    pub mailboxes: u32,
+    pub messages: u32,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct SnapshotManifest {
+    mailboxes: Vec<SnapshotMailbox>,
+    messages: Vec<SnapshotMessage>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SnapshotMailbox {
+    id: u32,
+    path: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SnapshotMessage {
+    id: u32,
+    blob_hash: BlobHash,
+    received_at: u64,
+    mailboxes: Vec<u32>,
+    keywords: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SnapshotRestoreResponse {
+    #[serde(rename = "mailboxesCreated")]
+    pub mailboxes_created: usize,
+    pub restored: usize,
+    pub updated: usize,
+    #[serde(rename = "notRestored")]
+    pub not_restored: Vec<SnapshotRestoreError>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SnapshotRestoreError {
+    pub id: String,
+    pub reason: String,
+}
+
+impl JMAP {
+    /// Records the mailbox tree, message list and flags of an account. Message
+    /// contents are not copied, instead their blobs are linked to the snapshot
+    /// so they are not purged once the messages are deleted.
+    pub async fn snapshot_create(&self, account_id: u32) -> Result<u32, MethodError> {
+        let email_change_id = self
+            .store
+            .get_last_change_id(account_id, Collection::Email)
+            .await
+            .map_err(|_| MethodError::ServerPartialFail)?;
+        let mailbox_change_id = self
+            .store
+            .get_last_change_id(account_id, Collection::Mailbox)
+            .await
+            .map_err(|_| MethodError::ServerPartialFail)?;
+
+        // Build mailbox tree
+        let mut mailbox_names = AHashMap::new();
+        for document_id in self
+            .get_document_ids(account_id, Collection::Mailbox)
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(mut mailbox) = self
+                .get_property::<Object<Value>>(
+                    account_id,
+                    Collection::Mailbox,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                let name = match mailbox.properties.remove(&Property::Name) {
+                    Some(Value::Text(name)) => name,
+                    _ => continue,
+                };
+                let parent_id = match mailbox.properties.remove(&Property::ParentId) {
+                    Some(Value::Id(parent_id)) if parent_id.document_id() > 0 => {
+                        Some(parent_id.document_id() - 1)
+                    }
+                    _ => None,
+                };
+                mailbox_names.insert(document_id, (name, parent_id));
+            }
+        }
+        let mut manifest = SnapshotManifest::default();
+        for (&document_id, (name, parent_id)) in &mailbox_names {
+            let mut path = vec![name.as_str()];
+            let mut parent_id = *parent_id;
+            while let Some((name, next_parent_id)) =
+                parent_id.and_then(|parent_id| mailbox_names.get(&parent_id))
+            {
+                if path.len() > self.config.mailbox_max_depth {
+                    break;
+                }
+                path.push(name.as_str());
+                parent_id = *next_parent_id;
+            }
+            path.reverse();
+            manifest.mailboxes.push(SnapshotMailbox {
+                id: document_id,
+                path: path.join("/"),
+            });
+        }
+
+        // Build message list
+        for document_id in self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .unwrap_or_default()
+        {
+            let (mailboxes, keywords, metadata) = match (
+                self.get_property::<Vec<UidMailbox>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::MailboxIds,
+                )
+                .await?,
+                self.get_property::<Vec<Keyword>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::Keywords,
+                )
+                .await?,
+                self.get_property::<Bincode<MessageMetadata>>(
+                    account_id,
+                    Collection::Email,
+                    document_id,
+                    Property::BodyStructure,
+                )
+                .await?,
+            ) {
+                (Some(mailboxes), Some(keywords), Some(metadata)) => {
+                    (mailboxes, keywords, metadata.inner)
+                }
+                _ => continue,
+            };
+            manifest.messages.push(SnapshotMessage {
+                id: document_id,
+                blob_hash: metadata.blob_hash,
+                received_at: metadata.received_at,
+                mailboxes: mailboxes.into_iter().map(|m| m.mailbox_id).collect(),
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            });
+        }
+
+        // Store manifest
+        let manifest = Bincode::new(manifest);
+        let manifest_hash = self
+            .put_blob(account_id, &(&manifest).serialize(), false)
+            .await?
+            .hash;
+        let manifest = manifest.inner;
+        let info = SnapshotInfo {
+            created_at: now(),
+            email_change_id,
+            mailbox_change_id,
+            manifest: manifest_hash.clone(),
+            mailboxes: manifest.mailboxes.len() as u32,
+            messages: manifest.messages.len() as u32,
+        };
+        let snapshot_id = self
+            .assign_document_id(account_id, Collection::Snapshot)
+            .await?;
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Snapshot)
+            .create_document(snapshot_id)
+            .value(Property::Value, Bincode::new(info), F_VALUE)
+            .set(
+                BlobOp::Link {
+                    hash: manifest_hash,
+                },
+                Vec::new(),
+            );
+        self.write_batch(batch).await?;
+
+        // Link message blobs
+        self.snapshot_link_blobs(account_id, snapshot_id, &manifest, true)
+            .await?;
+
+        // Remove the oldest snapshots
+        let mut snapshots = self.snapshot_list(account_id).await?;
+        if snapshots.len() > self.config.snapshots_max {
+            snapshots.sort_unstable_by_key(|(snapshot_id, info)| (info.created_at, *snapshot_id));
+            for (snapshot_id, _) in &snapshots[..snapshots.len() - self.config.snapshots_max.max(1)]
+            {
+                self.snapshot_delete(account_id, *snapshot_id).await?;
+            }
+        }
+
+        tracing::debug!(
+            context = "snapshot",
+            event = "create",
+            account_id = account_id,
+            snapshot_id = snapshot_id,
+            mailboxes = manifest.mailboxes.len(),
+            messages = manifest.messages.len(),
+            "Created account snapshot."
+        );
+
+        Ok(snapshot_id)
+    }
+
+    pub async fn snapshot_list(
+        &self,
+        account_id: u32,
+    ) -> Result<Vec<(u32, SnapshotInfo)>, MethodError> {
+        let mut snapshots = Vec::new();
+        for snapshot_id in self
+            .get_document_ids(account_id, Collection::Snapshot)
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(info) = self
+                .get_property::<Bincode<SnapshotInfo>>(
+                    account_id,
+                    Collection::Snapshot,
+                    snapshot_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                snapshots.push((snapshot_id, info.inner));
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    /// Deletes a snapshot, releasing the blobs of messages that no longer exist.
+    pub async fn snapshot_delete(
+        &self,
+        account_id: u32,
+        snapshot_id: u32,
+    ) -> Result<bool, MethodError> {
+        let info = if let Some(info) = self
+            .get_property::<Bincode<SnapshotInfo>>(
+                account_id,
+                Collection::Snapshot,
+                snapshot_id,
+                Property::Value,
+            )
+            .await?
+        {
+            info.inner
+        } else {
+            return Ok(false);
+        };
+
+        if let Some(manifest) = self.snapshot_manifest(&info).await? {
+            self.snapshot_link_blobs(account_id, snapshot_id, &manifest, false)
+                .await?;
+        }
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Snapshot)
+            .delete_document(snapshot_id)
+            .value(Property::Value, (), F_VALUE | F_CLEAR)
+            .clear(BlobOp::Link {
+                hash: info.manifest,
+            });
+        self.write_batch(batch).await?;
+
+        Ok(true)
+    }
+
+    /// Brings an account back to the state recorded in a snapshot. Missing
+    /// mailboxes are recreated by path, deleted messages are imported again
+    /// from their blobs and the mailboxes and keywords of the remaining ones
+    /// are reset. Messages received after the snapshot are left untouched.
+    pub async fn snapshot_restore(
+        &self,
+        account_id: u32,
+        account_quota: i64,
+        snapshot_id: u32,
+    ) -> Result<Option<SnapshotRestoreResponse>, MethodError> {
+        let info = if let Some(info) = self
+            .get_property::<Bincode<SnapshotInfo>>(
+                account_id,
+                Collection::Snapshot,
+                snapshot_id,
+                Property::Value,
+            )
+            .await?
+        {
+            info.inner
+        } else {
+            return Ok(None);
+        };
+        let mut response = SnapshotRestoreResponse::default();
+
+        // Nothing to do if the account did not change since the snapshot was taken
+        if self
+            .store
+            .get_last_change_id(account_id, Collection::Email)
+            .await
+            .map_err(|_| MethodError::ServerPartialFail)?
+            == info.email_change_id
+            && self
+                .store
+                .get_last_change_id(account_id, Collection::Mailbox)
+                .await
+                .map_err(|_| MethodError::ServerPartialFail)?
+                == info.mailbox_change_id
+        {
+            return Ok(Some(response));
+        }
+
+        let manifest = self
+            .snapshot_manifest(&info)
+            .await?
+            .ok_or(MethodError::ServerPartialFail)?;
+
+        // Map snapshot mailboxes to existing or recreated ones
+        self.mailbox_get_or_create(account_id).await?;
+        let mut last_change_id = None;
+        let mut mailbox_ids = AHashMap::with_capacity(manifest.mailboxes.len());
+        for mailbox in &manifest.mailboxes {
+            if let Some((mailbox_id, change_id)) =
+                self.mailbox_create_path(account_id, &mailbox.path).await?
+            {
+                if change_id.is_some() {
+                    response.mailboxes_created += 1;
+                    last_change_id = change_id;
+                }
+                mailbox_ids.insert(mailbox.id, mailbox_id);
+            }
+        }
+
+        let current_ids = self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .unwrap_or_default();
+        let mut updates = VecMap::new();
+        for message in manifest.messages {
+            let mut message_mailbox_ids = message
+                .mailboxes
+                .iter()
+                .filter_map(|mailbox_id| mailbox_ids.get(mailbox_id).copied())
+                .collect::<Vec<_>>();
+            if message_mailbox_ids.is_empty() {
+                message_mailbox_ids.push(INBOX_ID);
+            }
+            let keywords = message
+                .keywords
+                .into_iter()
+                .map(Keyword::from)
+                .collect::<Vec<_>>();
+
+            // Reset mailboxes and keywords of messages that still exist
+            if current_ids.contains(message.id)
+                && self
+                    .get_property::<Bincode<MessageMetadata>>(
+                        account_id,
+                        Collection::Email,
+                        message.id,
+                        Property::BodyStructure,
+                    )
+                    .await?
+                    .map_or(false, |metadata| {
+                        metadata.inner.blob_hash == message.blob_hash
+                    })
+            {
+                let current_mailbox_ids = self
+                    .get_property::<Vec<UidMailbox>>(
+                        account_id,
+                        Collection::Email,
+                        message.id,
+                        Property::MailboxIds,
+                    )
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|m| m.mailbox_id)
+                    .collect::<AHashSet<_>>();
+                let current_keywords = self
+                    .get_property::<Vec<Keyword>>(
+                        account_id,
+                        Collection::Email,
+                        message.id,
+                        Property::Keywords,
+                    )
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect::<AHashSet<_>>();
+                if current_mailbox_ids
+                    != message_mailbox_ids.iter().copied().collect::<AHashSet<_>>()
+                    || current_keywords != keywords.iter().cloned().collect::<AHashSet<_>>()
+                {
+                    let mut update = Object {
+                        properties: VecMap::with_capacity(2),
+                    };
+                    update.properties.append(
+                        Property::MailboxIds,
+                        SetValue::Value(Value::List(
+                            message_mailbox_ids
+                                .into_iter()
+                                .map(|mailbox_id| Value::Id(Id::from(mailbox_id)))
+                                .collect(),
+                        )),
+                    );
+                    update.properties.append(
+                        Property::Keywords,
+                        SetValue::Value(Value::List(
+                            keywords.into_iter().map(Value::Keyword).collect(),
+                        )),
+                    );
+                    updates.append(Id::from(message.id), update);
+                }
+                continue;
+            }
+
+            // Import deleted messages again
+            let raw_message =
+                if let Some(raw_message) = self.get_blob(&message.blob_hash, 0..u32::MAX).await? {
+                    raw_message
+                } else {
+                    response.not_restored.push(SnapshotRestoreError {
+                        id: Id::from(message.id).to_string(),
+                        reason: "Message contents no longer available.".to_string(),
+                    });
+                    continue;
+                };
+            match self
+                .email_ingest(IngestEmail {
+                    raw_message: &raw_message,
+                    message: MessageParser::new().parse(&raw_message),
+                    account_id,
+                    account_quota,
+                    mailbox_ids: message_mailbox_ids,
+                    keywords,
+                    received_at: Some(message.received_at),
+                    skip_duplicates: true,
+                    encrypt: false,
+                    classify: false,
+                    trusted_sender: false,
+                })
+                .await
+            {
+                Ok(email) => {
+                    if email.change_id != u64::MAX {
+                        last_change_id = Some(email.change_id);
+                        response.restored += 1;
+                    }
+                }
+                Err(IngestError::Permanent { reason, .. }) => {
+                    response.not_restored.push(SnapshotRestoreError {
+                        id: Id::from(message.id).to_string(),
+                        reason,
+                    });
+                }
+                Err(IngestError::OverQuota) => {
+                    response.not_restored.push(SnapshotRestoreError {
+                        id: Id::from(message.id).to_string(),
+                        reason: "Account is over quota.".to_string(),
+                    });
+                }
+                Err(IngestError::Temporary) => {
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+
+        // Apply updates as the account owner
+        if !updates.is_empty() {
+            let access_token = self
+                .get_access_token(account_id)
+                .await
+                .ok_or(MethodError::ServerPartialFail)?;
+            let mut set_response = self
+                .email_set(
+                    SetRequest {
+                        account_id: Id::from(account_id),
+                        if_in_state: None,
+                        create: None,
+                        update: Some(updates),
+                        destroy: None,
+                        arguments: RequestArguments::Email,
+                    },
+                    &access_token,
+                )
+                .await?;
+            response.updated = set_response.updated.len();
+            for (id, err) in set_response.not_updated {
+                response.not_restored.push(SnapshotRestoreError {
+                    id: id.to_string(),
+                    reason: err
+                        .description
+                        .map(|description| description.into_owned())
+                        .unwrap_or_else(|| format!("{:?}", err.type_)),
+                });
+            }
+            if let Some(state_change) = set_response.state_change.take() {
+                self.broadcast_state_change(state_change).await;
+            }
+        }
+
+        // Notify state change
+        if let Some(change_id) = last_change_id {
+            self.broadcast_state_change(
+                StateChange::new(account_id)
+                    .with_change(DataType::Email, change_id)
+                    .with_change(DataType::Mailbox, change_id)
+                    .with_change(DataType::Thread, change_id),
+            )
+            .await;
+        }
+
+        tracing::info!(
+            context = "snapshot",
+            event = "restore",
+            account_id = account_id,
+            snapshot_id = snapshot_id,
+            mailboxes_created = response.mailboxes_created,
+            restored = response.restored,
+            updated = response.updated,
+            failed = response.not_restored.len(),
+            "Restored account snapshot."
+        );
+
+        Ok(Some(response))
+    }
+
+    pub async fn snapshot_all_accounts(&self) {
+        let account_ids = match self.store.list_indexed_accounts().await {
+            Ok(account_ids) => account_ids,
+            Err(err) => {
+                tracing::error!(
+                    context = "snapshot",
+                    event = "error",
+                    error = ?err,
+                    "Failed to obtain accounts."
+                );
+                return;
+            }
+        };
+
+        for account_id in account_ids {
+            if let Err(err) = self.snapshot_create(account_id).await {
+                tracing::warn!(
+                    context = "snapshot",
+                    event = "error",
+                    account_id = account_id,
+                    reason = ?err,
+                    "Failed to create account snapshot."
+                );
+            }
+        }
+    }
+
+    async fn snapshot_manifest(
+        &self,
+        info: &SnapshotInfo,
+    ) -> Result<Option<SnapshotManifest>, MethodError> {
+        if let Some(manifest) = self.get_blob(&info.manifest, 0..u32::MAX).await? {
+            Bincode::<SnapshotManifest>::deserialize(&manifest)
+                .map(|manifest| Some(manifest.inner))
+                .map_err(|err| {
+                    tracing::error!(
+                        context = "snapshot",
+                        event = "error",
+                        error = ?err,
+                        "Failed to deserialize snapshot manifest."
+                    );
+                    MethodError::ServerPartialFail
+                })
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn snapshot_link_blobs(
+        &self,
+        account_id: u32,
+        snapshot_id: u32,
+        manifest: &SnapshotManifest,
+        link: bool,
+    ) -> Result<(), MethodError> {
+        let hashes = manifest
+            .messages
+            .iter()
+            .map(|message| &message.blob_hash)
+            .collect::<AHashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        for hashes in hashes.chunks(LINK_BATCH_SIZE) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Snapshot)
+                .update_document(snapshot_id);
+            for hash in hashes {
+                let hash = (*hash).clone();
+                if link {
+                    batch.set(BlobOp::Link { hash }, Vec::new());
+                } else {
+                    batch.clear(BlobOp::Link { hash });
+                }
+            }
+            self.write_batch(batch).await?;
+        }
+
+        Ok(())
+    }
+}
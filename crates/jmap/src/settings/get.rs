@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::get::{GetRequest, GetResponse, RequestArguments},
+    object::Object,
+    request::reference::MaybeReference,
+    types::{any_id::AnyId, collection::Collection, id::Id, property::Property, value::Value},
+};
+
+use utils::locale::DateFormat;
+
+use crate::JMAP;
+
+impl JMAP {
+    pub async fn settings_get(
+        &self,
+        mut request: GetRequest<RequestArguments>,
+    ) -> Result<GetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let properties = request.unwrap_properties(&[
+            Property::Id,
+            Property::BulkFiling,
+            Property::BulkMailboxId,
+            Property::AllowedSenders,
+            Property::BlockedSenders,
+            Property::Locale,
+            Property::Timezone,
+            Property::DateFormat,
+        ]);
+        let mut response = GetResponse {
+            account_id: request.account_id.into(),
+            state: self
+                .get_state(account_id, Collection::Principal)
+                .await?
+                .into(),
+            list: Vec::with_capacity(1),
+            not_found: vec![],
+        };
+
+        let do_get = if let Some(MaybeReference::Value(ids)) = request.ids {
+            let mut do_get = false;
+            for id in ids {
+                match id.try_unwrap() {
+                    Some(AnyId::Id(id)) if id.is_singleton() => {
+                        do_get = true;
+                    }
+                    Some(id) => {
+                        response.not_found.push(id);
+                    }
+                    _ => {}
+                }
+            }
+            do_get
+        } else {
+            true
+        };
+        if do_get {
+            // Settings always exist, missing values are returned with their defaults
+            let mut obj = self
+                .get_account_settings(account_id)
+                .await?
+                .unwrap_or_default();
+            let mut result = Object::with_capacity(properties.len());
+            for property in &properties {
+                match property {
+                    Property::Id => {
+                        result.append(Property::Id, Value::Id(Id::singleton()));
+                    }
+                    Property::BulkFiling => {
+                        result.append(
+                            Property::BulkFiling,
+                            match obj.remove(&Property::BulkFiling) {
+                                Value::Bool(value) => Value::Bool(value),
+                                _ => Value::Bool(false),
+                            },
+                        );
+                    }
+                    Property::BulkMailboxId => {
+                        result.append(
+                            Property::BulkMailboxId,
+                            obj.remove(&Property::BulkMailboxId),
+                        );
+                    }
+                    Property::AllowedSenders | Property::BlockedSenders => {
+                        result.append(
+                            property.clone(),
+                            match obj.remove(property) {
+                                Value::List(entries) => Value::List(entries),
+                                _ => Value::List(vec![]),
+                            },
+                        );
+                    }
+                    Property::Locale | Property::Timezone | Property::DateFormat => {
+                        result.append(
+                            property.clone(),
+                            match obj.remove(property) {
+                                Value::Text(value) => Value::Text(value),
+                                _ => match property {
+                                    Property::Locale => "en",
+                                    Property::Timezone => "UTC",
+                                    _ => DateFormat::default().as_str(),
+                                }
+                                .into(),
+                            },
+                        );
+                    }
+                    property => {
+                        result.append(property.clone(), Value::Null);
+                    }
+                }
+            }
+            response.list.push(result);
+        }
+
+        Ok(response)
+    }
+}
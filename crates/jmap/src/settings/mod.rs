@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::Object,
+    types::{collection::Collection, property::Property, value::Value},
+};
+use store::write::{BatchBuilder, F_CLEAR, F_VALUE};
+use utils::locale::{format_utc_offset, parse_utc_offset, AccountLocale, DateFormat, Language};
+
+use crate::JMAP;
+
+pub mod get;
+pub mod set;
+
+impl JMAP {
+    pub async fn get_account_settings(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<Object<Value>>, MethodError> {
+        self.get_property::<Object<Value>>(account_id, Collection::Principal, 0, Property::Value)
+            .await
+    }
+
+    pub async fn set_account_settings(
+        &self,
+        account_id: u32,
+        settings: &Object<Value>,
+    ) -> Result<(), MethodError> {
+        // Remove settings when all values are back to their defaults
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Principal)
+            .update_document(0);
+        if !settings.properties.is_empty() {
+            batch.value(Property::Value, settings, F_VALUE);
+        } else {
+            batch.value(Property::Value, (), F_VALUE | F_CLEAR);
+        }
+        self.write_batch(batch).await
+    }
+
+    pub async fn account_locale(&self, account_id: u32) -> Result<AccountLocale, MethodError> {
+        Ok(self
+            .get_account_settings(account_id)
+            .await?
+            .map(|settings| settings_locale(&settings))
+            .unwrap_or_default())
+    }
+
+    pub async fn address_locale(&self, address: &str) -> Option<AccountLocale> {
+        let account_id = self
+            .directory
+            .email_to_ids(address)
+            .await
+            .ok()?
+            .into_iter()
+            .next()?;
+        self.account_locale(account_id).await.ok()
+    }
+
+    /// Returns the language used to stem search terms and the messages of an
+    /// account when their language cannot be detected.
+    pub async fn account_search_language(&self, account_id: u32) -> nlp::language::Language {
+        self.get_account_settings(account_id)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|settings| {
+                settings
+                    .get(&setting_key(SEARCH_LANGUAGE))
+                    .as_string()
+                    .and_then(nlp::language::Language::from_iso_639)
+            })
+            .unwrap_or(self.config.default_language)
+    }
+
+    pub async fn session_generation(&self, account_id: u32) -> Result<u64, MethodError> {
+        Ok(self
+            .get_account_settings(account_id)
+            .await?
+            .and_then(|settings| settings.get(&setting_key(SESSION_GENERATION)).as_uint())
+            .unwrap_or(0))
+    }
+
+    /// Invalidates all sessions and OAuth tokens issued to an account.
+    pub async fn revoke_sessions(&self, account_id: u32) -> Result<(), MethodError> {
+        let mut settings = self
+            .get_account_settings(account_id)
+            .await?
+            .unwrap_or_default();
+        let generation = settings
+            .get(&setting_key(SESSION_GENERATION))
+            .as_uint()
+            .unwrap_or(0);
+        settings.set(setting_key(SESSION_GENERATION), generation + 1);
+        self.set_account_settings(account_id, &settings).await?;
+        self.clear_cached_sessions(account_id);
+
+        Ok(())
+    }
+
+    pub fn clear_cached_sessions(&self, account_id: u32) {
+        self.sessions.retain(|_, entry| entry.item != account_id);
+        self.access_tokens.remove(&account_id);
+    }
+
+    /// Returns the addresses all incoming messages are forwarded to and whether
+    /// a local copy is kept.
+    pub async fn account_forwarding(
+        &self,
+        account_id: u32,
+    ) -> Result<(Vec<String>, bool), MethodError> {
+        let settings = self
+            .get_account_settings(account_id)
+            .await?
+            .unwrap_or_default();
+        let addresses = match settings.get(&setting_key(FORWARD_TO)) {
+            Value::List(addresses) => addresses
+                .iter()
+                .filter_map(|address| address.as_string().map(|a| a.to_string()))
+                .collect(),
+            _ => vec![],
+        };
+        let keep_copy = settings
+            .get(&setting_key(FORWARD_KEEP_COPY))
+            .as_bool()
+            .unwrap_or(true);
+
+        Ok((addresses, keep_copy))
+    }
+}
+
+pub const SESSION_GENERATION: &str = "sessionGeneration";
+pub const FORWARD_TO: &str = "forwardTo";
+pub const FORWARD_KEEP_COPY: &str = "forwardKeepCopy";
+pub const SEARCH_LANGUAGE: &str = "searchLanguage";
+
+pub fn setting_key(name: &str) -> Property {
+    Property::_T(name.to_string())
+}
+
+pub fn settings_locale(settings: &Object<Value>) -> AccountLocale {
+    let mut locale = AccountLocale::default();
+    if let Some(language) = settings
+        .get(&Property::Locale)
+        .as_string()
+        .and_then(Language::parse)
+    {
+        locale.language = language;
+    }
+    if let Some(utc_offset) = settings
+        .get(&Property::Timezone)
+        .as_string()
+        .and_then(parse_utc_offset)
+    {
+        locale.utc_offset = utc_offset;
+    }
+    if let Some(date_format) = settings
+        .get(&Property::DateFormat)
+        .as_string()
+        .and_then(DateFormat::parse)
+    {
+        locale.date_format = date_format;
+    }
+    locale
+}
+
+pub fn parse_locale_value(property: &Property, value: &str) -> Option<Value> {
+    match property {
+        Property::Locale => Language::parse(value).map(|_| Value::Text(value.to_string())),
+        Property::Timezone => {
+            parse_utc_offset(value).map(|offset| format_utc_offset(offset).into())
+        }
+        Property::DateFormat => DateFormat::parse(value).map(|format| format.as_str().into()),
+        Property::_T(name) if name == SEARCH_LANGUAGE => {
+            nlp::language::Language::from_iso_639(value).map(|_| Value::Text(value.to_string()))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{email::sender_list::parse_sender_entry, JMAP};
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        id::Id,
+        property::Property,
+        value::{MaybePatchValue, Value},
+    },
+};
+
+use super::parse_locale_value;
+
+impl JMAP {
+    pub async fn settings_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+    ) -> Result<SetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut response = self
+            .prepare_set_response(&request, Collection::Principal)
+            .await?;
+
+        // Settings are a singleton that always exists
+        for (id, _) in request.unwrap_create() {
+            response.not_created.append(
+                id,
+                SetError::forbidden().with_description("Settings cannot be created."),
+            );
+        }
+        for id in request.unwrap_destroy() {
+            response.not_destroyed.append(
+                id,
+                SetError::forbidden().with_description("Settings cannot be destroyed."),
+            );
+        }
+
+        'next_update: for (id, obj) in request.unwrap_update() {
+            if !id.is_singleton() {
+                response.not_updated.append(
+                    id,
+                    SetError::new(SetErrorType::NotFound).with_description("ID not found."),
+                );
+                continue;
+            }
+
+            let mut settings = self
+                .get_account_settings(account_id)
+                .await?
+                .unwrap_or_default();
+            let timezone = settings.get(&Property::Timezone).clone();
+            for (property, value) in obj.properties {
+                let value = match response.eval_object_references(value) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'next_update;
+                    }
+                };
+                match (&property, value) {
+                    (Property::BulkFiling, MaybePatchValue::Value(Value::Bool(value))) => {
+                        settings.set(property, value);
+                    }
+                    (
+                        Property::AllowedSenders | Property::BlockedSenders,
+                        MaybePatchValue::Value(Value::List(entries)),
+                    ) => {
+                        if entries.len() > self.config.sender_list_max_entries {
+                            response.not_updated.append(
+                                id,
+                                SetError::invalid_properties()
+                                    .with_property(property)
+                                    .with_description(format!(
+                                        "Sender lists cannot contain more than {} entries.",
+                                        self.config.sender_list_max_entries
+                                    )),
+                            );
+                            continue 'next_update;
+                        }
+
+                        let mut list = Vec::with_capacity(entries.len());
+                        for entry in entries {
+                            if let Some(entry) = entry.as_string().and_then(parse_sender_entry) {
+                                if !list.contains(&Value::Text(entry.clone())) {
+                                    list.push(Value::Text(entry));
+                                }
+                            } else {
+                                response.not_updated.append(
+                                    id,
+                                    SetError::invalid_properties()
+                                        .with_property(property)
+                                        .with_description(
+                                            "Entries must be e-mail addresses or domains prefixed with '@'.",
+                                        ),
+                                );
+                                continue 'next_update;
+                            }
+                        }
+                        if !list.is_empty() {
+                            settings.set(property, Value::List(list));
+                        } else {
+                            settings.remove(&property);
+                        }
+                    }
+                    (
+                        Property::Locale | Property::Timezone | Property::DateFormat,
+                        MaybePatchValue::Value(Value::Text(value)),
+                    ) => {
+                        if let Some(value) = parse_locale_value(&property, &value) {
+                            settings.set(property, value);
+                        } else {
+                            response.not_updated.append(
+                                id,
+                                SetError::invalid_properties()
+                                    .with_property(property)
+                                    .with_description(format!("Invalid value {value:?}.")),
+                            );
+                            continue 'next_update;
+                        }
+                    }
+                    (
+                        Property::BulkFiling
+                        | Property::BulkMailboxId
+                        | Property::AllowedSenders
+                        | Property::BlockedSenders
+                        | Property::Locale
+                        | Property::Timezone
+                        | Property::DateFormat,
+                        MaybePatchValue::Value(Value::Null),
+                    ) => {
+                        settings.remove(&property);
+                    }
+                    (Property::BulkMailboxId, MaybePatchValue::Value(Value::Id(mailbox_id))) => {
+                        if self
+                            .get_document_ids(account_id, Collection::Mailbox)
+                            .await?
+                            .map_or(false, |ids| ids.contains(mailbox_id.document_id()))
+                        {
+                            settings.set(property, Value::Id(mailbox_id));
+                        } else {
+                            response.not_updated.append(
+                                id,
+                                SetError::invalid_properties()
+                                    .with_property(property)
+                                    .with_description(format!(
+                                        "Mailbox {} does not exist.",
+                                        mailbox_id
+                                    )),
+                            );
+                            continue 'next_update;
+                        }
+                    }
+                    _ => {
+                        response.not_updated.append(
+                            id,
+                            SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("Field could not be set."),
+                        );
+                        continue 'next_update;
+                    }
+                }
+            }
+
+            let refresh_push = settings.get(&Property::Timezone) != &timezone;
+            self.set_account_settings(account_id, &settings).await?;
+            response.updated.append(Id::singleton(), None);
+
+            // Quiet hours of push subscriptions depend on the account's time zone
+            if refresh_push {
+                self.update_push_subscriptions(account_id).await;
+            }
+        }
+
+        Ok(response)
+    }
+}
@@ -442,6 +442,8 @@ impl JMAP {
                         received_at: None,
                         skip_duplicates: true,
                         encrypt: self.config.encrypt,
+                        classify: true,
+                        trusted_sender: false,
                     })
                     .await
                 {
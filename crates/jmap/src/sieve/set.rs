@@ -53,7 +53,7 @@ use store::{
     BlobClass,
 };
 
-use crate::{auth::AccessToken, JMAP};
+use crate::{auth::AccessToken, filter_rule::FILTER_RULES_SCRIPT, JMAP};
 
 struct SetContext<'x> {
     account_id: u32,
@@ -431,6 +431,14 @@ impl JMAP {
             )));
         }
 
+        // Filter rules script cannot be modified
+        if matches!(update.as_ref().and_then(|(_, obj)| obj.inner.properties.get(&Property::Name)), Some(Value::Text ( value )) if value.eq_ignore_ascii_case(FILTER_RULES_SCRIPT))
+        {
+            return Ok(Err(SetError::forbidden().with_description(
+                "The 'filter-rules' script cannot be modified, use FilterRule/set instead.",
+            )));
+        }
+
         // Parse properties
         let mut changes = Object::with_capacity(changes_.properties.len());
         let mut blob_id = None;
@@ -447,12 +455,14 @@ impl JMAP {
                         return Ok(Err(SetError::invalid_properties()
                             .with_property(property)
                             .with_description("Script name is too long.")));
-                    } else if value.eq_ignore_ascii_case("vacation") {
+                    } else if value.eq_ignore_ascii_case("vacation")
+                        || value.eq_ignore_ascii_case(FILTER_RULES_SCRIPT)
+                    {
                         return Ok(Err(SetError::forbidden()
                             .with_property(property)
-                            .with_description(
-                                "The 'vacation' name is reserved, please use a different name.",
-                            )));
+                            .with_description(format!(
+                                "The '{value}' name is reserved, please use a different name.",
+                            ))));
                     } else if update
                         .as_ref()
                         .and_then(|(_, obj)| obj.inner.properties.get(&Property::Name))
@@ -137,6 +137,14 @@ impl JMAP {
                                                             .to_string()
                                                             .replace('\n', " ")
                                                     }
+                                                    queue::Status::Scheduled
+                                                        if (message.flags
+                                                            & queue::MAIL_QUARANTINED)
+                                                            != 0 =>
+                                                    {
+                                                        "250 2.1.5 Queued, pending approval"
+                                                            .to_string()
+                                                    }
                                                     queue::Status::Scheduled => {
                                                         "250 2.1.5 Queued".to_string()
                                                     }
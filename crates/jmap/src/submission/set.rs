@@ -23,6 +23,7 @@
 
 use std::{collections::HashMap, sync::Arc};
 
+use directory::QueryBy;
 use jmap_proto::{
     error::{
         method::MethodError,
@@ -48,9 +49,15 @@ use jmap_proto::{
     },
 };
 use mail_parser::{HeaderName, HeaderValue};
-use smtp::core::{Session, SessionData, State};
+use smtp::{
+    core::{Session, SessionData, State},
+    queue::MAIL_QUARANTINED,
+};
 use smtp_proto::{request::parser::Rfc5321Parser, MailFrom, RcptTo};
-use store::write::{assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, Bincode};
+use store::write::{
+    assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, Bincode, BILLING_SENT_BYTES,
+    BILLING_SENT_MESSAGES,
+};
 use utils::{
     listener::{stream::NullIo, ServerInstance},
     map::vec_map::VecMap,
@@ -174,8 +181,12 @@ impl JMAP {
                 Some(undo_status) if undo_status == "canceled" => {
                     if let Some(queue_message) = self.smtp.read_message(queue_id).await {
                         // Delete message from queue
-                        let message_due = queue_message.next_event().unwrap_or_default();
-                        queue_message.remove(&self.smtp, message_due).await;
+                        if (queue_message.flags & MAIL_QUARANTINED) != 0 {
+                            self.smtp.discard_quarantined(queue_id, "submitter").await;
+                        } else {
+                            let message_due = queue_message.next_event().unwrap_or_default();
+                            queue_message.remove(&self.smtp, message_due).await;
+                        }
 
                         // Update record
                         let mut batch = BatchBuilder::new();
@@ -459,6 +470,19 @@ impl JMAP {
                 .with_description("Identity not found.")));
         };
 
+        // Make sure the identity address is still permitted for this account
+        if !self
+            .identity_allowed_emails(account_id)
+            .await?
+            .iter()
+            .any(|email| email.eq_ignore_ascii_case(&identity_mail_from))
+        {
+            return Ok(Err(SetError::new(SetErrorType::ForbiddenFrom)
+                .with_description(
+                    "Identity e-mail address is not permitted for this account.",
+                )));
+        }
+
         // Make sure the envelope address matches the identity email address
         let mail_from = if let Some(mail_from) = mail_from {
             if !mail_from.address.eq_ignore_ascii_case(&identity_mail_from) {
@@ -576,9 +600,28 @@ impl JMAP {
                 .with_description("Blob for email not found.")));
         };
 
+        // Obtain account name, used when evaluating submission rules
+        let account_name = self
+            .directory
+            .query(QueryBy::Id(account_id), false)
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "email_submission_set",
+                    error = ?err,
+                    "Failed to query directory.");
+                MethodError::ServerPartialFail
+            })?
+            .map(|principal| principal.name);
+
         // Begin local SMTP session
+        let mut session_data = SessionData::default();
+        if let Some(account_name) = account_name {
+            session_data.authenticated_as = account_name;
+        }
         let mut session =
-            Session::<NullIo>::local(self.smtp.clone(), instance.clone(), SessionData::default());
+            Session::<NullIo>::local(self.smtp.clone(), instance.clone(), session_data);
 
         // MAIL FROM
         let _ = session.handle_mail_from(mail_from).await;
@@ -605,10 +648,19 @@ impl JMAP {
 
         // DATA
         if has_success {
+            let message_size = message.len();
             session.data.message = message;
             let response = session.queue_message().await;
             if let State::Accepted(queue_id) = session.state {
                 submission.append(Property::MessageId, queue_id);
+                self.billing_incr(
+                    account_id,
+                    &[
+                        (BILLING_SENT_MESSAGES, 1),
+                        (BILLING_SENT_BYTES, message_size as i64),
+                    ],
+                )
+                .await;
             } else {
                 return Ok(Err(SetError::new(SetErrorType::ForbiddenToSend)
                     .with_description(format!(
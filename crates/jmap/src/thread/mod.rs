@@ -22,3 +22,4 @@
 */
 
 pub mod get;
+pub mod set;
@@ -0,0 +1,515 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    response::references::EvalObjectReferences,
+    types::{
+        acl::Acl,
+        collection::Collection,
+        id::Id,
+        keyword::Keyword,
+        property::Property,
+        state::{State, StateChange},
+        type_state::DataType,
+        value::{MaybePatchValue, Value},
+    },
+};
+use store::{
+    ahash::AHashSet,
+    write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, F_VALUE},
+};
+
+use crate::{
+    auth::AccessToken,
+    email::set::TagManager,
+    mailbox::{counters::UpdateMailboxCounters, UidMailbox},
+    services::housekeeper::Event,
+    JMAP,
+};
+
+enum ThreadPatch {
+    MailboxIds(Vec<u32>),
+    Mailbox(u32, bool),
+    Keywords(Vec<Keyword>),
+    Keyword(Keyword, bool),
+}
+
+impl JMAP {
+    /// Applies keyword and mailbox changes to, or destroys, every message in a
+    /// thread. The changes to each thread are written in a single batch.
+    pub async fn thread_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+        access_token: &AccessToken,
+    ) -> Result<SetResponse, MethodError> {
+        // Thread changes are reported using the Email state
+        let account_id = request.account_id.document_id();
+        let mut response = self
+            .prepare_set_response(&request, Collection::Email)
+            .await?;
+
+        // Obtain mailboxIds and permissions on shared accounts
+        let mailbox_ids = self.mailbox_get_or_create(account_id).await?;
+        let (can_add_mailbox_ids, can_delete_mailbox_ids, can_modify_message_ids) = if access_token
+            .is_shared(account_id)
+        {
+            (
+                self.shared_documents(access_token, account_id, Collection::Mailbox, Acl::AddItems)
+                    .await?
+                    .into(),
+                self.shared_documents(
+                    access_token,
+                    account_id,
+                    Collection::Mailbox,
+                    Acl::RemoveItems,
+                )
+                .await?
+                .into(),
+                self.shared_messages(access_token, account_id, Acl::ModifyItems)
+                    .await?
+                    .into(),
+            )
+        } else {
+            (None, None, None)
+        };
+
+        let will_destroy = request.unwrap_destroy();
+
+        // Threads are created implicitly by adding messages
+        for (id, _) in request.unwrap_create() {
+            response.not_created.append(
+                id,
+                SetError::forbidden().with_description("Threads cannot be created."),
+            );
+        }
+
+        // Process updates
+        let mut changes = ChangeLogBuilder::new();
+        'update: for (id, object) in request.unwrap_update() {
+            // Make sure id won't be destroyed
+            if will_destroy.contains(&id) {
+                response.not_updated.append(id, SetError::will_destroy());
+                continue 'update;
+            }
+
+            // Parse changes
+            let mut patches = Vec::with_capacity(object.properties.len());
+            for (property, value) in object.properties {
+                let value = match response.eval_object_references(value) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                };
+                patches.push(match (property, value) {
+                    (Property::MailboxIds, MaybePatchValue::Value(Value::List(ids))) => {
+                        ThreadPatch::MailboxIds(
+                            ids.into_iter()
+                                .filter_map(|id| id.try_unwrap_id()?.document_id().into())
+                                .collect(),
+                        )
+                    }
+                    (Property::MailboxIds, MaybePatchValue::Patch(patch)) => {
+                        let mut patch = patch.into_iter();
+                        if let Some(id) = patch.next().unwrap().try_unwrap_id() {
+                            ThreadPatch::Mailbox(
+                                id.document_id(),
+                                patch.next().unwrap().try_unwrap_bool().unwrap_or_default(),
+                            )
+                        } else {
+                            continue;
+                        }
+                    }
+                    (Property::Keywords, MaybePatchValue::Value(Value::List(keywords))) => {
+                        ThreadPatch::Keywords(
+                            keywords
+                                .into_iter()
+                                .filter_map(|keyword| keyword.try_unwrap_keyword())
+                                .collect(),
+                        )
+                    }
+                    (Property::Keywords, MaybePatchValue::Patch(patch)) => {
+                        let mut patch = patch.into_iter();
+                        if let Some(keyword) = patch.next().unwrap().try_unwrap_keyword() {
+                            ThreadPatch::Keyword(
+                                keyword,
+                                patch.next().unwrap().try_unwrap_bool().unwrap_or_default(),
+                            )
+                        } else {
+                            continue;
+                        }
+                    }
+                    (property, _) => {
+                        response.invalid_property_update(id, property);
+                        continue 'update;
+                    }
+                });
+            }
+            if patches.is_empty() {
+                response.not_updated.append(
+                    id,
+                    SetError::invalid_properties()
+                        .with_description("No changes found in request.".to_string()),
+                );
+                continue 'update;
+            }
+
+            // Obtain the messages in the thread
+            let thread_id = id.document_id();
+            let document_ids = match self.thread_document_ids(account_id, thread_id).await? {
+                Ok(document_ids) => document_ids,
+                Err(err) => {
+                    response.not_updated.append(id, err);
+                    continue 'update;
+                }
+            };
+
+            // Prepare write batch
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Email);
+            let mut thread_changes = ChangeLogBuilder::with_change_id(0);
+            let mut changed_mailboxes = AHashSet::new();
+
+            for document_id in document_ids {
+                // Obtain current keywords and mailboxes
+                let (mut mailboxes, mut keywords) = if let (Some(mailboxes), Some(keywords)) = (
+                    self.get_property::<HashedValue<Vec<UidMailbox>>>(
+                        account_id,
+                        Collection::Email,
+                        document_id,
+                        Property::MailboxIds,
+                    )
+                    .await?,
+                    self.get_property::<HashedValue<Vec<Keyword>>>(
+                        account_id,
+                        Collection::Email,
+                        document_id,
+                        Property::Keywords,
+                    )
+                    .await?,
+                ) {
+                    (TagManager::new(mailboxes), TagManager::new(keywords))
+                } else {
+                    continue;
+                };
+
+                for patch in &patches {
+                    match patch {
+                        ThreadPatch::MailboxIds(ids) => {
+                            mailboxes.set(ids.iter().map(|id| UidMailbox::from(*id)).collect());
+                        }
+                        ThreadPatch::Mailbox(id, add) => {
+                            mailboxes.update(UidMailbox::from(*id), *add);
+                        }
+                        ThreadPatch::Keywords(values) => {
+                            keywords.set(values.clone());
+                        }
+                        ThreadPatch::Keyword(keyword, add) => {
+                            keywords.update(keyword.clone(), *add);
+                        }
+                    }
+                }
+
+                // Messages that already match the requested state are left untouched
+                if !mailboxes.has_changes() && !keywords.has_changes() {
+                    continue;
+                }
+                batch.update_document(document_id);
+                thread_changes
+                    .log_update(Collection::Email, Id::from_parts(thread_id, document_id));
+
+                // Update mailbox counters
+                let previous_seen = keywords.previous().contains(&Keyword::Seen);
+                let current_seen = keywords.current().contains(&Keyword::Seen);
+                if mailboxes.has_changes() || previous_seen != current_seen {
+                    let size = if mailboxes.has_changes() {
+                        self.message_size(account_id, document_id).await?
+                    } else {
+                        0
+                    };
+                    batch.update_mailbox_counters(
+                        mailboxes.previous(),
+                        previous_seen,
+                        mailboxes.current(),
+                        current_seen,
+                        size,
+                    );
+                }
+
+                // Process keywords
+                if keywords.has_changes() {
+                    // Verify permissions on shared accounts
+                    if matches!(&can_modify_message_ids, Some(ids) if !ids.contains(document_id)) {
+                        response.not_updated.append(
+                            id,
+                            SetError::forbidden()
+                                .with_description("You are not allowed to modify keywords."),
+                        );
+                        continue 'update;
+                    }
+
+                    // Set all current mailboxes as changed if the Seen tag changed
+                    if keywords
+                        .changed_tags()
+                        .any(|keyword| keyword == &Keyword::Seen)
+                    {
+                        for mailbox_id in mailboxes.current() {
+                            changed_mailboxes.insert(mailbox_id.mailbox_id);
+                        }
+                    }
+
+                    // Update keywords property
+                    keywords.update_batch(&mut batch, Property::Keywords);
+
+                    // Update last change id
+                    if changes.change_id == u64::MAX {
+                        changes.change_id = self.assign_change_id(account_id).await?;
+                    }
+                    batch.value(Property::Cid, changes.change_id, F_VALUE);
+                }
+
+                // Process mailboxes
+                if mailboxes.has_changes() {
+                    // Make sure the message is at least in one mailbox
+                    if !mailboxes.has_tags() {
+                        response.not_updated.append(
+                            id,
+                            SetError::invalid_properties()
+                                .with_property(Property::MailboxIds)
+                                .with_description("Message has to belong to at least one mailbox."),
+                        );
+                        continue 'update;
+                    }
+
+                    // Make sure all new mailboxIds are valid
+                    for mailbox_id in mailboxes.added() {
+                        if !mailbox_ids.contains(mailbox_id.mailbox_id) {
+                            response.not_updated.append(
+                                id,
+                                SetError::invalid_properties()
+                                    .with_property(Property::MailboxIds)
+                                    .with_description(format!(
+                                        "mailboxId {} does not exist.",
+                                        mailbox_id.mailbox_id
+                                    )),
+                            );
+                            continue 'update;
+                        } else if matches!(&can_add_mailbox_ids, Some(ids) if !ids.contains(mailbox_id.mailbox_id))
+                        {
+                            response.not_updated.append(
+                                id,
+                                SetError::forbidden().with_description(format!(
+                                    "You are not allowed to add messages to mailbox {}.",
+                                    mailbox_id.mailbox_id
+                                )),
+                            );
+                            continue 'update;
+                        }
+                        changed_mailboxes.insert(mailbox_id.mailbox_id);
+                    }
+
+                    // Add all removed mailboxes to change list
+                    for mailbox_id in mailboxes.removed() {
+                        if matches!(&can_delete_mailbox_ids, Some(ids) if !ids.contains(mailbox_id.mailbox_id))
+                        {
+                            response.not_updated.append(
+                                id,
+                                SetError::forbidden().with_description(format!(
+                                    "You are not allowed to delete messages from mailbox {}.",
+                                    mailbox_id.mailbox_id
+                                )),
+                            );
+                            continue 'update;
+                        }
+                        changed_mailboxes.insert(mailbox_id.mailbox_id);
+                    }
+
+                    // Update mailboxIds property
+                    mailboxes.update_batch(&mut batch, Property::MailboxIds);
+                }
+            }
+
+            // Log mailbox changes
+            for mailbox_id in changed_mailboxes {
+                thread_changes.log_child_update(Collection::Mailbox, mailbox_id);
+            }
+
+            // Write changes
+            if !batch.is_empty() {
+                match self.store.write(batch.build()).await {
+                    Ok(_) => {
+                        changes.merge(thread_changes);
+                    }
+                    Err(store::Error::AssertValueFailed) => {
+                        response.not_updated.append(
+                            id,
+                            SetError::forbidden().with_description(
+                                "Another process modified this thread, please try again.",
+                            ),
+                        );
+                        continue 'update;
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "thread_set",
+                            error = ?err,
+                            "Failed to write thread changes to database.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
+            }
+            response.updated.append(id, None);
+        }
+
+        // Process deletions
+        if !will_destroy.is_empty() {
+            let can_destroy_message_ids = if access_token.is_shared(account_id) {
+                self.shared_messages(access_token, account_id, Acl::RemoveItems)
+                    .await?
+                    .into()
+            } else {
+                None
+            };
+
+            'destroy: for destroy_id in will_destroy {
+                // Obtain the messages in the thread
+                let thread_id = destroy_id.document_id();
+                let document_ids = match self.thread_document_ids(account_id, thread_id).await? {
+                    Ok(document_ids) => document_ids,
+                    Err(err) => {
+                        response.not_destroyed.append(destroy_id, err);
+                        continue 'destroy;
+                    }
+                };
+                if matches!(&can_destroy_message_ids, Some(ids) if !document_ids.iter().all(|id| ids.contains(*id)))
+                {
+                    response.not_destroyed.append(
+                        destroy_id,
+                        SetError::forbidden()
+                            .with_description("You are not allowed to delete this thread."),
+                    );
+                    continue 'destroy;
+                }
+
+                // Delete all messages and the thread in a single batch
+                let mut batch = BatchBuilder::new();
+                let mut thread_changes = ChangeLogBuilder::with_change_id(0);
+                batch.with_account_id(account_id);
+                for document_id in document_ids {
+                    if let Err(err) = self
+                        .email_delete_batch(
+                            &mut batch,
+                            &mut thread_changes,
+                            account_id,
+                            document_id,
+                            true,
+                        )
+                        .await?
+                    {
+                        response.not_destroyed.append(destroy_id, err);
+                        continue 'destroy;
+                    }
+                }
+                batch
+                    .with_collection(Collection::Thread)
+                    .delete_document(thread_id);
+                thread_changes.log_delete(Collection::Thread, thread_id);
+
+                match self.store.write(batch.build()).await {
+                    Ok(_) => {
+                        changes.merge(thread_changes);
+                        response.destroyed.push(destroy_id);
+                    }
+                    Err(store::Error::AssertValueFailed) => {
+                        response.not_destroyed.append(
+                            destroy_id,
+                            SetError::forbidden().with_description(
+                                "Another process modified this thread, please try again.",
+                            ),
+                        );
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "thread_set",
+                            error = ?err,
+                            "Failed to delete thread.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
+            }
+
+            // Request FTS index
+            if !response.destroyed.is_empty() {
+                let _ = self.housekeeper_tx.send(Event::IndexStart).await;
+            }
+        }
+
+        // Update state
+        if !changes.is_empty() {
+            let new_state: State = self.commit_changes(account_id, changes).await?.into();
+            if let State::Exact(change_id) = &new_state {
+                response.state_change = StateChange::new(account_id)
+                    .with_change(DataType::Email, *change_id)
+                    .with_change(DataType::Mailbox, *change_id)
+                    .with_change(DataType::Thread, *change_id)
+                    .into();
+            }
+
+            response.new_state = new_state.into();
+        }
+
+        Ok(response)
+    }
+
+    async fn thread_document_ids(
+        &self,
+        account_id: u32,
+        thread_id: u32,
+    ) -> Result<Result<Vec<u32>, SetError>, MethodError> {
+        match self
+            .get_tag(account_id, Collection::Email, Property::ThreadId, thread_id)
+            .await?
+        {
+            Some(document_ids) if document_ids.len() as usize > self.config.set_max_objects => {
+                Ok(Err(SetError::new(SetErrorType::TooLarge).with_description(
+                    format!(
+                        "Thread has more than {} messages.",
+                        self.config.set_max_objects
+                    ),
+                )))
+            }
+            Some(document_ids) => Ok(Ok(document_ids.into_iter().collect())),
+            None => Ok(Err(SetError::not_found())),
+        }
+    }
+}
@@ -48,6 +48,7 @@ use store::{
     },
     BlobClass,
 };
+use utils::locale::{AccountLocale, LocaleText};
 
 use crate::{
     sieve::set::{ObjectBlobId, SCHEMA},
@@ -249,8 +250,9 @@ impl JMAP {
             // Create sieve script only if there are changes
             if build_script {
                 // Upload new blob
+                let locale = self.account_locale(account_id).await?;
                 let hash = self
-                    .put_blob(account_id, &self.build_script(&mut obj)?, false)
+                    .put_blob(account_id, &self.build_script(&mut obj, &locale)?, false)
                     .await?
                     .hash;
                 let blob_id = obj.changes_mut().unwrap().blob_id_mut().unwrap();
@@ -349,7 +351,11 @@ impl JMAP {
         Ok(response)
     }
 
-    fn build_script(&self, obj: &mut ObjectIndexBuilder) -> Result<Vec<u8>, MethodError> {
+    fn build_script(
+        &self,
+        obj: &mut ObjectIndexBuilder,
+        locale: &AccountLocale,
+    ) -> Result<Vec<u8>, MethodError> {
         // Build Sieve script
         let mut script = Vec::with_capacity(1024);
         script.extend_from_slice(b"require [\"vacation\", \"relational\", \"date\"];\r\n\r\n");
@@ -404,7 +410,7 @@ impl JMAP {
                 text_body = Cow::from(html_to_text(html_body.as_ref())).into();
             }
             (None, None) => {
-                text_body = Cow::from("I am away.").into();
+                text_body = Cow::from(locale.text(LocaleText::VacationBody)).into();
             }
             _ => (),
         }
@@ -95,18 +95,35 @@ impl JMAP {
                                         self.config.request_max_size,
                                     ) {
                                         Ok(WebSocketMessage::Request(request)) => {
+                                            // Apply the same API limits as HTTP requests
                                             match self
-                                                .handle_request(
-                                                    request.request,
-                                                    access_token.clone(),
-                                                    &instance,
-                                                )
+                                                .is_api_request_allowed(&access_token, &request.request)
                                                 .await
                                             {
-                                                Ok(response) => {
-                                                    WebSocketResponse::from_response(response, request.id)
-                                                        .to_json()
+                                                Ok(rate_limit) if rate_limit.map_or(false, |r| r.exceeded) => {
+                                                    WebSocketRequestError::from_error(
+                                                        RequestError::too_many_requests(),
+                                                        request.id,
+                                                    )
+                                                    .to_json()
                                                 }
+                                                Ok(_) => match self
+                                                    .handle_request(
+                                                        request.request,
+                                                        access_token.clone(),
+                                                        &instance,
+                                                    )
+                                                    .await
+                                                {
+                                                    Ok(response) => {
+                                                        WebSocketResponse::from_response(response, request.id)
+                                                            .to_json()
+                                                    }
+                                                    Err(err) => {
+                                                        WebSocketRequestError::from_error(err, request.id)
+                                                            .to_json()
+                                                    }
+                                                },
                                                 Err(err) => {
                                                     WebSocketRequestError::from_error(err, request.id)
                                                         .to_json()
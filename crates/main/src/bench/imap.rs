@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::{
+    account_name, build_message, BenchOptions, LineConnection, Stats, BENCH_DOMAIN, BENCH_SECRET,
+};
+
+const OP_APPEND: &str = "imap.append";
+const OP_FETCH: &str = "imap.fetch";
+
+pub async fn worker(
+    options: BenchOptions,
+    port: u16,
+    worker_id: usize,
+    deadline: Instant,
+) -> Stats {
+    let mut stats = Stats::default();
+    let mut rng = StdRng::from_entropy();
+    let account = account_name(worker_id % options.accounts);
+    let from = format!("{account}@{BENCH_DOMAIN}");
+    let mut conn = None;
+    let mut tag_id = 0;
+
+    while Instant::now() < deadline {
+        // (Re)connect when needed
+        if conn.is_none() {
+            match connect(port, &account).await {
+                Ok(session) => {
+                    conn = Some(session);
+                }
+                Err(_) => {
+                    stats.error(OP_APPEND);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            }
+        }
+        let session = conn.as_mut().unwrap();
+
+        // Append a message
+        let message = build_message(
+            &from,
+            &[from.clone()],
+            options.message_size(&mut rng),
+            &mut rng,
+        );
+        tag_id += 1;
+        let started = Instant::now();
+        match append(session, &format!("b{tag_id}"), &message).await {
+            Ok(_) => stats.record(OP_APPEND, started, message.len()),
+            Err(_) => {
+                stats.error(OP_APPEND);
+                conn = None;
+                continue;
+            }
+        }
+
+        // Fetch the most recent message
+        tag_id += 1;
+        let started = Instant::now();
+        match command(
+            session,
+            &format!("b{tag_id}"),
+            "UID FETCH * (UID RFC822.SIZE BODY.PEEK[])",
+        )
+        .await
+        {
+            Ok(bytes) => stats.record(OP_FETCH, started, bytes),
+            Err(_) => {
+                stats.error(OP_FETCH);
+                conn = None;
+            }
+        }
+    }
+
+    if let Some(mut session) = conn {
+        let _ = session.write(b"z LOGOUT\r\n").await;
+    }
+
+    stats
+}
+
+async fn connect(port: u16, account: &str) -> std::io::Result<LineConnection> {
+    let mut conn = LineConnection::connect(port).await?;
+    conn.expect("* OK").await?;
+    command(&mut conn, "a1", &format!("LOGIN {account} {BENCH_SECRET}")).await?;
+    command(&mut conn, "a2", "SELECT INBOX").await?;
+    Ok(conn)
+}
+
+async fn append(conn: &mut LineConnection, tag: &str, message: &[u8]) -> std::io::Result<usize> {
+    conn.write(format!("{tag} APPEND INBOX {{{}}}\r\n", message.len()).as_bytes())
+        .await?;
+    conn.expect("+").await?;
+    conn.write(message).await?;
+    conn.write(b"\r\n").await?;
+    read_tagged(conn, tag).await
+}
+
+async fn command(conn: &mut LineConnection, tag: &str, command: &str) -> std::io::Result<usize> {
+    conn.write(format!("{tag} {command}\r\n").as_bytes())
+        .await?;
+    read_tagged(conn, tag).await
+}
+
+async fn read_tagged(conn: &mut LineConnection, tag: &str) -> std::io::Result<usize> {
+    let mut bytes = 0;
+
+    loop {
+        let line = conn.read_line().await?;
+        bytes += line.len();
+
+        if let Some(status) = line
+            .strip_prefix(tag)
+            .and_then(|line| line.strip_prefix(' '))
+        {
+            return if status.starts_with("OK") {
+                Ok(bytes)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Command failed: {}", line.trim_end()),
+                ))
+            };
+        } else if let Some(size) = literal_size(&line) {
+            bytes += conn.read_exact(size).await?.len();
+        }
+    }
+}
+
+fn literal_size(line: &str) -> Option<usize> {
+    let line = line.trim_end().strip_suffix('}')?;
+    line[line.rfind('{')? + 1..]
+        .trim_end_matches('+')
+        .parse()
+        .ok()
+}
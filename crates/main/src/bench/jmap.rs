@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose, Engine};
+use serde_json::{json, Value};
+
+use super::{account_name, BenchOptions, LineConnection, Stats, BENCH_SECRET};
+
+const OP_SESSION: &str = "jmap.session";
+const OP_QUERY: &str = "jmap.query";
+
+struct HttpConnection {
+    conn: LineConnection,
+    authorization: String,
+}
+
+pub async fn worker(
+    options: BenchOptions,
+    port: u16,
+    worker_id: usize,
+    deadline: Instant,
+) -> Stats {
+    let mut stats = Stats::default();
+    let authorization = format!(
+        "Basic {}",
+        general_purpose::STANDARD.encode(format!(
+            "{}:{BENCH_SECRET}",
+            account_name(worker_id % options.accounts)
+        ))
+    );
+    let mut conn: Option<(HttpConnection, Value)> = None;
+
+    while Instant::now() < deadline {
+        // (Re)connect and fetch the session resource when needed
+        if conn.is_none() {
+            let started = Instant::now();
+            match connect(port, &authorization).await {
+                Ok((session, bytes)) => {
+                    stats.record(OP_SESSION, started, bytes);
+                    conn = Some(session);
+                }
+                Err(_) => {
+                    stats.error(OP_SESSION);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            }
+        }
+        let (session, request) = conn.as_mut().unwrap();
+
+        let started = Instant::now();
+        match session.request("POST", "/jmap", request).await {
+            Ok(response)
+                if serde_json::from_slice::<Value>(&response).map_or(false, |response| {
+                    response["methodResponses"]
+                        .as_array()
+                        .map_or(false, |responses| {
+                            responses.iter().all(|response| response[0] != "error")
+                        })
+                }) =>
+            {
+                stats.record(OP_QUERY, started, response.len())
+            }
+            _ => {
+                stats.error(OP_QUERY);
+                conn = None;
+            }
+        }
+    }
+
+    stats
+}
+
+async fn connect(
+    port: u16,
+    authorization: &str,
+) -> std::io::Result<((HttpConnection, Value), usize)> {
+    let mut conn = HttpConnection {
+        conn: LineConnection::connect(port).await?,
+        authorization: authorization.to_string(),
+    };
+    let response = conn
+        .request("GET", "/.well-known/jmap", &Value::Null)
+        .await?;
+    let account_id = serde_json::from_slice::<Value>(&response)
+        .ok()
+        .and_then(|session| {
+            session["primaryAccounts"]["urn:ietf:params:jmap:mail"]
+                .as_str()
+                .map(|id| id.to_string())
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid session resource.")
+        })?;
+
+    // Fetch mailboxes and the most recent messages
+    let request = json!({
+        "using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"],
+        "methodCalls": [
+            ["Mailbox/get", {
+                "accountId": account_id,
+                "properties": ["name", "totalEmails", "unreadEmails"]
+            }, "0"],
+            ["Email/query", {
+                "accountId": account_id,
+                "sort": [{"property": "receivedAt", "isAscending": false}],
+                "limit": 10
+            }, "1"],
+            ["Email/get", {
+                "accountId": account_id,
+                "#ids": {"resultOf": "1", "name": "Email/query", "path": "/ids"},
+                "properties": ["subject", "from", "preview", "size"]
+            }, "2"]
+        ]
+    });
+
+    Ok(((conn, request), response.len()))
+}
+
+impl HttpConnection {
+    async fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        body: &Value,
+    ) -> std::io::Result<Vec<u8>> {
+        let body = if !body.is_null() {
+            body.to_string()
+        } else {
+            String::new()
+        };
+        self.conn
+            .write(
+                format!(
+                    concat!(
+                        "{} {} HTTP/1.1\r\n",
+                        "Host: 127.0.0.1\r\n",
+                        "Authorization: {}\r\n",
+                        "Content-Type: application/json\r\n",
+                        "Content-Length: {}\r\n\r\n{}"
+                    ),
+                    method,
+                    path,
+                    self.authorization,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        // Parse status line and headers
+        let status = self
+            .conn
+            .read_line()
+            .await?
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .unwrap_or_default();
+        let mut content_length = 0;
+        loop {
+            let line = self.conn.read_line().await?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            } else if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or_default();
+                }
+            }
+        }
+
+        let body = self.conn.read_exact(content_length).await?;
+        if (200..300).contains(&status) {
+            Ok(body)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Request failed with status {status}."),
+            ))
+        }
+    }
+}
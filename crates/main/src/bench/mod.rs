@@ -0,0 +1,531 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+pub mod imap;
+pub mod jmap;
+pub mod smtp;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use utils::{
+    config::{utils::ParseValue, Config},
+    enable_tracing, failed, UnwrapFailure,
+};
+
+use crate::start_services;
+
+const BENCH_DOMAIN: &str = "bench.example.org";
+const BENCH_SECRET: &str = "bench";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Smtp,
+    Imap,
+    Jmap,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub protocols: Vec<Protocol>,
+    pub concurrency: usize,
+    pub duration: Duration,
+    pub sizes: Vec<usize>,
+    pub recipients: Vec<(usize, u32)>,
+    pub accounts: usize,
+    pub store: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ports {
+    pub smtp: u16,
+    pub imap: u16,
+    pub jmap: u16,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub ops: BTreeMap<&'static str, OpStats>,
+}
+
+#[derive(Debug, Default)]
+pub struct OpStats {
+    pub latencies: Vec<Duration>,
+    pub errors: usize,
+    pub bytes: usize,
+}
+
+pub struct LineConnection {
+    stream: BufReader<TcpStream>,
+}
+
+pub async fn run() -> std::io::Result<()> {
+    let options = BenchOptions::parse(std::env::args().skip(2));
+
+    // Create ephemeral store
+    let path = std::env::temp_dir().join(format!("stalwart-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&path).failed("Failed to create temporary directory");
+
+    // Build configuration
+    let ports = Ports {
+        smtp: free_port(),
+        imap: free_port(),
+        jmap: free_port(),
+    };
+    let config =
+        Config::new(&options.build_config(&path, &ports)).failed("Invalid benchmark configuration");
+    let _tracer = enable_tracing(
+        &config,
+        &format!(
+            "Starting Stalwart Mail Server v{} benchmark...",
+            env!("CARGO_PKG_VERSION"),
+        ),
+    )
+    .failed("Failed to enable tracing");
+
+    // Start services
+    let shutdown_tx = start_services(config).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    println!(
+        "Running benchmark with {} accounts, concurrency {} and duration {:?}.",
+        options.accounts, options.concurrency, options.duration
+    );
+
+    for protocol in &options.protocols {
+        let started = Instant::now();
+        let mut workers = Vec::with_capacity(options.concurrency);
+        for worker_id in 0..options.concurrency {
+            let options = options.clone();
+            let ports = ports.clone();
+            let protocol = *protocol;
+            workers.push(tokio::spawn(async move {
+                let deadline = Instant::now() + options.duration;
+                match protocol {
+                    Protocol::Smtp => smtp::worker(options, ports.smtp, worker_id, deadline).await,
+                    Protocol::Imap => imap::worker(options, ports.imap, worker_id, deadline).await,
+                    Protocol::Jmap => jmap::worker(options, ports.jmap, worker_id, deadline).await,
+                }
+            }));
+        }
+
+        let mut stats = Stats::default();
+        for worker in workers {
+            if let Ok(worker_stats) = worker.await {
+                stats.merge(worker_stats);
+            }
+        }
+        print!("{}", stats.report(started.elapsed()));
+    }
+
+    // Stop services
+    let _ = shutdown_tx.send(true);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let _ = std::fs::remove_dir_all(&path);
+
+    Ok(())
+}
+
+impl BenchOptions {
+    pub fn parse(args: impl Iterator<Item = String>) -> Self {
+        let mut options = BenchOptions {
+            protocols: vec![Protocol::Smtp, Protocol::Imap, Protocol::Jmap],
+            concurrency: 16,
+            duration: Duration::from_secs(30),
+            sizes: vec![2 * 1024, 20 * 1024, 200 * 1024],
+            recipients: vec![(1, 80), (5, 15), (25, 5)],
+            accounts: 100,
+            store: "rocksdb".to_string(),
+        };
+
+        for arg in args {
+            let (key, value) = arg
+                .strip_prefix("--")
+                .and_then(|arg| arg.split_once('='))
+                .unwrap_or_else(|| failed(&format!("Invalid command line argument: {arg}")));
+
+            match key {
+                "protocols" => {
+                    options.protocols = value
+                        .split(',')
+                        .map(|protocol| match protocol.trim() {
+                            "smtp" => Protocol::Smtp,
+                            "imap" => Protocol::Imap,
+                            "jmap" => Protocol::Jmap,
+                            protocol => failed(&format!("Invalid protocol: {protocol}")),
+                        })
+                        .collect();
+                }
+                "concurrency" => {
+                    options.concurrency = usize::parse_value(key, value).failed("Invalid argument");
+                }
+                "duration" => {
+                    options.duration = Duration::parse_value(key, value).failed("Invalid argument");
+                }
+                "sizes" => {
+                    options.sizes = value
+                        .split(',')
+                        .map(|size| parse_size(size.trim()).failed("Invalid argument"))
+                        .collect();
+                }
+                "recipients" => {
+                    options.recipients = value
+                        .split(',')
+                        .map(|item| {
+                            let item = item.trim();
+                            let (count, weight) = item.split_once(':').unwrap_or((item, "1"));
+                            (
+                                usize::parse_value(key, count).failed("Invalid argument"),
+                                u32::parse_value(key, weight).failed("Invalid argument"),
+                            )
+                        })
+                        .collect();
+                }
+                "accounts" => {
+                    options.accounts = usize::parse_value(key, value).failed("Invalid argument");
+                }
+                "store" => match value {
+                    "rocksdb" | "sqlite" => {
+                        options.store = value.to_string();
+                    }
+                    _ => failed(&format!("Unsupported ephemeral store: {value}")),
+                },
+                _ => failed(&format!("Invalid command line argument: {key}")),
+            }
+        }
+
+        if options.protocols.is_empty()
+            || options.concurrency == 0
+            || options.accounts == 0
+            || options.sizes.is_empty()
+            || options.recipients.is_empty()
+            || options.recipients.iter().all(|(_, weight)| *weight == 0)
+        {
+            failed("Invalid benchmark parameters.");
+        }
+
+        options
+    }
+
+    pub fn build_config(&self, path: &std::path::Path, ports: &Ports) -> String {
+        let path = path.display();
+        let mut config = format!(
+            r#"
+[server]
+hostname = "{BENCH_DOMAIN}"
+
+[server.listener.smtp]
+bind = ["127.0.0.1:{smtp}"]
+protocol = "smtp"
+
+[server.listener.imap]
+bind = ["127.0.0.1:{imap}"]
+protocol = "imap"
+
+[server.listener.jmap]
+bind = ["127.0.0.1:{jmap}"]
+url = "http://127.0.0.1:{jmap}"
+protocol = "jmap"
+
+[server.tls]
+enable = false
+
+[server.socket]
+reuse-addr = true
+
+[global.tracing]
+method = "stdout"
+level = "warn"
+
+[store."bench"]
+type = "{store}"
+path = "{path}/data.db"
+
+[storage]
+data = "bench"
+fts = "bench"
+blob = "bench"
+lookup = "bench"
+directory = "bench"
+
+[queue]
+path = "{path}"
+hash = 64
+
+[report]
+path = "{path}"
+hash = 64
+
+[resolver]
+type = "system"
+
+[queue.outbound]
+next-hop = [ {{ if = "is_local_domain('bench', rcpt_domain)", then = "'local'" }},
+             {{ else = false }} ]
+
+[session.rcpt]
+directory = "'bench'"
+max-recipients = {max_recipients}
+
+[auth.dkim]
+verify = "disable"
+
+[auth.arc]
+verify = "disable"
+
+[auth.spf.verify]
+ehlo = "disable"
+mail-from = "disable"
+
+[auth.dmarc]
+verify = "disable"
+
+[auth.iprev]
+verify = "disable"
+
+[imap.auth]
+allow-plain-text = true
+
+[imap.rate-limit]
+requests = "100000000/1s"
+concurrent = 100000
+
+[jmap.rate-limit]
+account = "100000000/1s"
+authentication = "100000000/1s"
+anonymous = "100000000/1s"
+
+[jmap.protocol.request]
+max-concurrent = 100000
+
+[directory."bench"]
+type = "memory"
+"#,
+            smtp = ports.smtp,
+            imap = ports.imap,
+            jmap = ports.jmap,
+            store = self.store,
+            max_recipients = self
+                .recipients
+                .iter()
+                .map(|(count, _)| *count)
+                .max()
+                .unwrap_or(1)
+                .max(100),
+        );
+
+        for account_id in 0..self.accounts {
+            let _ = write!(
+                config,
+                r#"
+[[directory."bench".principals]]
+name = "{name}"
+type = "individual"
+secret = "{BENCH_SECRET}"
+email = "{name}@{BENCH_DOMAIN}"
+"#,
+                name = account_name(account_id),
+            );
+        }
+
+        config
+    }
+
+    pub fn message_size(&self, rng: &mut StdRng) -> usize {
+        self.sizes[rng.gen_range(0..self.sizes.len())]
+    }
+
+    pub fn recipients(&self, rng: &mut StdRng) -> Vec<usize> {
+        let total = self
+            .recipients
+            .iter()
+            .map(|(_, weight)| *weight)
+            .sum::<u32>();
+        let mut pick = rng.gen_range(0..total);
+        let mut count = 1;
+        for (rcpt_count, weight) in &self.recipients {
+            if pick < *weight {
+                count = *rcpt_count;
+                break;
+            }
+            pick -= weight;
+        }
+
+        rand::seq::index::sample(rng, self.accounts, count.clamp(1, self.accounts)).into_vec()
+    }
+}
+
+impl Stats {
+    pub fn record(&mut self, op: &'static str, started: Instant, bytes: usize) {
+        let op = self.ops.entry(op).or_default();
+        op.latencies.push(started.elapsed());
+        op.bytes += bytes;
+    }
+
+    pub fn error(&mut self, op: &'static str) {
+        self.ops.entry(op).or_default().errors += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (name, other) in other.ops {
+            let op = self.ops.entry(name).or_default();
+            op.latencies.extend(other.latencies);
+            op.errors += other.errors;
+            op.bytes += other.bytes;
+        }
+    }
+
+    pub fn report(mut self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut report = String::new();
+
+        for (name, op) in &mut self.ops {
+            op.latencies.sort_unstable();
+            let _ = writeln!(
+                report,
+                "{name:<14} ops: {:>8}  errors: {:>6}  {:>10.1} ops/s  {:>8.2} MB/s  p50: {:>8.2?}  p90: {:>8.2?}  p99: {:>8.2?}  max: {:>8.2?}",
+                op.latencies.len(),
+                op.errors,
+                op.latencies.len() as f64 / secs,
+                op.bytes as f64 / secs / (1024.0 * 1024.0),
+                op.percentile(50.0),
+                op.percentile(90.0),
+                op.percentile(99.0),
+                op.latencies.last().copied().unwrap_or_default(),
+            );
+        }
+
+        report
+    }
+}
+
+impl OpStats {
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if !self.latencies.is_empty() {
+            let pos = ((percentile / 100.0) * (self.latencies.len() - 1) as f64).round() as usize;
+            self.latencies[pos.min(self.latencies.len() - 1)]
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+impl LineConnection {
+    pub async fn connect(port: u16) -> std::io::Result<Self> {
+        TcpStream::connect(("127.0.0.1", port))
+            .await
+            .map(|stream| LineConnection {
+                stream: BufReader::new(stream),
+            })
+    }
+
+    pub async fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.stream.get_mut().write_all(bytes).await
+    }
+
+    pub async fn read_line(&mut self) -> std::io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? > 0 {
+            Ok(line)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed.",
+            ))
+        }
+    }
+
+    pub async fn read_exact(&mut self, len: usize) -> std::io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len];
+        self.stream.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    pub async fn expect(&mut self, prefix: &str) -> std::io::Result<String> {
+        let line = self.read_line().await?;
+        if line.starts_with(prefix) {
+            Ok(line)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Expected {prefix:?}, got {:?}", line.trim_end()),
+            ))
+        }
+    }
+}
+
+pub fn account_name(account_id: usize) -> String {
+    format!("bench{account_id}")
+}
+
+pub fn build_message(from: &str, to: &[String], size: usize, rng: &mut StdRng) -> Vec<u8> {
+    let mut message = format!(
+        "From: <{from}>\r\nTo: {}\r\nSubject: Benchmark message {}\r\nMessage-ID: <{}@{BENCH_DOMAIN}>\r\n\r\n",
+        to.iter()
+            .map(|rcpt| format!("<{rcpt}>"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        rng.gen::<u32>(),
+        rng.gen::<u64>(),
+    )
+    .into_bytes();
+
+    // Fill the body with random printable lines
+    while message.len() < size {
+        let line_len = (size - message.len()).clamp(2, 78);
+        message.extend((0..line_len - 2).map(|_| rng.gen_range(b'a'..=b'z')));
+        message.extend_from_slice(b"\r\n");
+    }
+
+    message
+}
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (num, multiplier) = match value.chars().last() {
+        Some('k' | 'K') => (&value[..value.len() - 1], 1024),
+        Some('m' | 'M') => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    num.parse::<usize>()
+        .ok()
+        .filter(|num| *num > 0)
+        .map(|num| num * multiplier)
+        .ok_or_else(|| format!("Invalid size {value:?}."))
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .failed("Failed to find a free port")
+}
@@ -0,0 +1,121 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::{account_name, build_message, BenchOptions, LineConnection, Stats, BENCH_DOMAIN};
+
+const OP_MESSAGE: &str = "smtp.message";
+
+pub async fn worker(
+    options: BenchOptions,
+    port: u16,
+    worker_id: usize,
+    deadline: Instant,
+) -> Stats {
+    let mut stats = Stats::default();
+    let mut rng = StdRng::from_entropy();
+    let from = format!("sender{worker_id}@bench.example.net");
+    let mut conn = None;
+
+    while Instant::now() < deadline {
+        // (Re)connect when needed
+        if conn.is_none() {
+            match connect(port).await {
+                Ok(session) => {
+                    conn = Some(session);
+                }
+                Err(_) => {
+                    stats.error(OP_MESSAGE);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            }
+        }
+        let session = conn.as_mut().unwrap();
+
+        let rcpts = options
+            .recipients(&mut rng)
+            .into_iter()
+            .map(|account_id| format!("{}@{BENCH_DOMAIN}", account_name(account_id)))
+            .collect::<Vec<_>>();
+        let message = build_message(&from, &rcpts, options.message_size(&mut rng), &mut rng);
+
+        let started = Instant::now();
+        match send_message(session, &from, &rcpts, &message).await {
+            Ok(_) => stats.record(OP_MESSAGE, started, message.len()),
+            Err(_) => {
+                stats.error(OP_MESSAGE);
+                conn = None;
+            }
+        }
+    }
+
+    if let Some(mut session) = conn {
+        let _ = session.write(b"QUIT\r\n").await;
+    }
+
+    stats
+}
+
+async fn connect(port: u16) -> std::io::Result<LineConnection> {
+    let mut conn = LineConnection::connect(port).await?;
+    read_reply(&mut conn, "220").await?;
+    conn.write(format!("EHLO {BENCH_DOMAIN}\r\n").as_bytes())
+        .await?;
+    read_reply(&mut conn, "250").await?;
+    Ok(conn)
+}
+
+async fn send_message(
+    conn: &mut LineConnection,
+    from: &str,
+    rcpts: &[String],
+    message: &[u8],
+) -> std::io::Result<()> {
+    conn.write(format!("MAIL FROM:<{from}>\r\n").as_bytes())
+        .await?;
+    read_reply(conn, "250").await?;
+    for rcpt in rcpts {
+        conn.write(format!("RCPT TO:<{rcpt}>\r\n").as_bytes())
+            .await?;
+        read_reply(conn, "250").await?;
+    }
+    conn.write(b"DATA\r\n").await?;
+    read_reply(conn, "354").await?;
+    conn.write(message).await?;
+    conn.write(b"\r\n.\r\n").await?;
+    read_reply(conn, "250").await
+}
+
+async fn read_reply(conn: &mut LineConnection, code: &str) -> std::io::Result<()> {
+    // Skip multi-line continuations
+    loop {
+        let line = conn.expect(code).await?;
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
@@ -21,17 +21,20 @@
  * for more details.
 */
 
+mod bench;
+
 use std::time::Duration;
 
+use dav::DavSessionManager;
 use directory::core::config::ConfigDirectory;
 use imap::core::{ImapSessionManager, IMAP};
 use jmap::{api::JmapSessionManager, services::IPC_CHANNEL_BUFFER, JMAP};
 use managesieve::core::ManageSieveSessionManager;
 use smtp::core::{SmtpSessionManager, SMTP};
 use store::config::ConfigStore;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use utils::{
-    config::{Config, ServerProtocol},
+    config::{reload::ReloadableConfig, Config, ServerProtocol},
     enable_tracing, wait_for_shutdown, UnwrapFailure,
 };
 
@@ -44,7 +47,13 @@ static GLOBAL: Jemalloc = Jemalloc;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let mut config = Config::init();
+    // Run benchmarks
+    if std::env::args().nth(1).as_deref() == Some("--bench") {
+        return bench::run().await;
+    }
+
+    let config_path = Config::init_path();
+    let config = Config::from_file(&config_path).failed("Failed to load configuration");
 
     // Enable tracing
     let _tracer = enable_tracing(
@@ -56,6 +65,26 @@ async fn main() -> std::io::Result<()> {
     )
     .failed("Failed to enable tracing");
 
+    // Start services
+    let shutdown_tx = start_services(config, config_path).await;
+
+    // Wait for shutdown signal
+    wait_for_shutdown(&format!(
+        "Shutting down Stalwart Mail Server v{}...",
+        env!("CARGO_PKG_VERSION")
+    ))
+    .await;
+
+    // Stop services
+    let _ = shutdown_tx.send(true);
+
+    // Wait for services to finish
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    Ok(())
+}
+
+async fn start_services(mut config: Config, config_path: String) -> watch::Sender<bool> {
     // Bind ports and drop privileges
     let mut servers = config.parse_servers().failed("Invalid configuration");
     servers.bind(&config);
@@ -69,6 +98,9 @@ async fn main() -> std::io::Result<()> {
     // Update configuration
     config.update(data_store.config_list("").await.failed("Storage error"));
 
+    // Build the CPU worker pool
+    utils::worker::init_cpu_pool(&config).failed("Invalid configuration");
+
     // Parse directories
     let directory = config
         .parse_directory(&stores, data_store)
@@ -105,6 +137,8 @@ async fn main() -> std::io::Result<()> {
         .blocked_ips
         .reload(&config)
         .failed("Invalid configuration");
+    jmap.config_reloader.register("imap", imap.clone());
+    jmap.config_reloader.set_path(config_path);
 
     // Spawn servers
     let (shutdown_tx, shutdown_rx) = servers.spawn(|server, shutdown_rx| {
@@ -126,6 +160,7 @@ async fn main() -> std::io::Result<()> {
                 ManageSieveSessionManager::new(jmap.clone(), imap.clone()),
                 shutdown_rx,
             ),
+            ServerProtocol::Dav => server.spawn(DavSessionManager::new(jmap.clone()), shutdown_rx),
         };
     });
 
@@ -134,18 +169,5 @@ async fn main() -> std::io::Result<()> {
         scheduler.spawn(shutdown_rx.clone());
     }
 
-    // Wait for shutdown signal
-    wait_for_shutdown(&format!(
-        "Shutting down Stalwart Mail Server v{}...",
-        env!("CARGO_PKG_VERSION")
-    ))
-    .await;
-
-    // Stop services
-    let _ = shutdown_tx.send(true);
-
-    // Wait for services to finish
-    tokio::time::sleep(Duration::from_secs(1)).await;
-
-    Ok(())
+    shutdown_tx
 }
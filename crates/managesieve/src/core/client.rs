@@ -146,7 +146,7 @@ impl<T: SessionStream> Session<T> {
                         .lookup_store
                         .is_rate_allowed(
                             format!("ireq:{}", access_token.primary_id()).as_bytes(),
-                            &self.imap.rate_requests,
+                            &self.imap.rate_requests.load_full(),
                             true,
                         )
                         .await
@@ -26,6 +26,7 @@ pub mod session;
 
 use std::{borrow::Cow, net::IpAddr, sync::Arc};
 
+use directory::core::scram::ScramState;
 use imap::core::IMAP;
 use imap_proto::receiver::{CommandParser, Receiver};
 use jmap::{auth::AccessToken, JMAP};
@@ -42,6 +43,7 @@ pub struct Session<T: AsyncRead + AsyncWrite> {
     pub stream: T,
     pub span: tracing::Span,
     pub in_flight: InFlight,
+    pub scram: Option<ScramState>,
 }
 
 pub enum State {
@@ -23,7 +23,7 @@
 
 use imap_proto::receiver::{self, Receiver};
 use tokio_rustls::server::TlsStream;
-use utils::listener::{SessionManager, SessionStream};
+use utils::listener::{transcript::TranscriptStream, SessionManager, SessionStream};
 
 use crate::SERVER_GREETING;
 
@@ -48,6 +48,7 @@ impl SessionManager for ManageSieveSessionManager {
                 stream: session.stream,
                 in_flight: session.in_flight,
                 remote_addr: session.remote_ip,
+                scram: None,
             };
 
             if session
@@ -144,7 +145,7 @@ impl<T: SessionStream> Session<T> {
         false
     }
 
-    pub async fn into_tls(self) -> Result<Session<TlsStream<T>>, ()> {
+    pub async fn into_tls(self) -> Result<Session<TranscriptStream<TlsStream<T>>>, ()> {
         let span = self.span;
         Ok(Session {
             stream: self.instance.tls_accept(self.stream, &span).await?,
@@ -156,6 +157,7 @@ impl<T: SessionStream> Session<T> {
             imap: self.imap,
             receiver: self.receiver,
             remote_addr: self.remote_addr,
+            scram: None,
         })
     }
 }
@@ -23,14 +23,20 @@
 
 use std::sync::Arc;
 
-use directory::AuthResult;
+use directory::{
+    core::scram::{ScramState, ScramStep},
+    AuthResult,
+};
 use imap::op::authenticate::{decode_challenge_oauth, decode_challenge_plain};
 use imap_proto::{
     protocol::authenticate::Mechanism,
     receiver::{self, Request},
 };
+use jmap::auth::AccessToken;
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
+use smtp_proto::{AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_SCRAM_SHA_256, AUTH_SCRAM_SHA_256_PLUS};
 use utils::listener::SessionStream;
 
 use crate::core::{Command, Session, State, StatusResponse};
@@ -48,17 +54,24 @@ impl<T: SessionStream> Session<T> {
             .filter_map(|token| token.unwrap_string().ok())
             .collect();
 
-        let credentials = match mechanism {
+        let (result, mechanism) = match mechanism {
             Mechanism::Plain | Mechanism::OAuthBearer => {
                 if !params.is_empty() {
                     let challenge = base64_decode(params.pop().unwrap().as_bytes())
                         .ok_or_else(|| StatusResponse::no("Failed to decode challenge."))?;
-                    (if mechanism == Mechanism::Plain {
-                        decode_challenge_plain(&challenge)
+                    let (credentials, mechanism) = if mechanism == Mechanism::Plain {
+                        (
+                            decode_challenge_plain(&challenge).map_err(StatusResponse::no)?,
+                            AUTH_PLAIN,
+                        )
                     } else {
-                        decode_challenge_oauth(&challenge)
-                    }
-                    .map_err(StatusResponse::no))?
+                        (
+                            decode_challenge_oauth(&challenge).map_err(StatusResponse::no)?,
+                            AUTH_OAUTHBEARER,
+                        )
+                    };
+                    self.is_auth_allowed().await?;
+                    (self.authenticate_credentials(credentials).await, mechanism)
                 } else {
                     self.receiver.request = receiver::Request {
                         tag: String::new(),
@@ -69,65 +82,83 @@ impl<T: SessionStream> Session<T> {
                     return Ok(b"{0}\r\n".to_vec());
                 }
             }
-            _ => {
-                return Err(StatusResponse::no(
-                    "Authentication mechanism not supported.",
-                ))
-            }
-        };
+            Mechanism::ScramSha256 | Mechanism::ScramSha256Plus => {
+                let (is_plus, auth_mechanism) = if mechanism == Mechanism::ScramSha256Plus {
+                    (true, AUTH_SCRAM_SHA_256_PLUS)
+                } else {
+                    (false, AUTH_SCRAM_SHA_256)
+                };
+                let state = self.scram.take().unwrap_or(ScramState::ClientFirst);
+                if matches!(state, ScramState::ClientFirst) {
+                    self.is_auth_allowed().await?;
+                }
 
-        // Throttle authentication requests
-        if self
-            .jmap
-            .is_auth_allowed_soft(&self.remote_addr)
-            .await
-            .is_err()
-        {
-            tracing::debug!(parent: &self.span,
-                event = "disconnect",
-                "Too many authentication attempts, disconnecting.",
-            );
-            return Err(StatusResponse::bye(
-                "Too many authentication requests from this IP address.",
-            ));
-        }
+                let response = match params.pop() {
+                    Some(param) if !param.is_empty() => base64_decode(param.as_bytes())
+                        .and_then(|response| String::from_utf8(response).ok())
+                        .ok_or_else(|| StatusResponse::no("Failed to decode challenge."))?,
+                    _ => String::new(),
+                };
+                let channel_binding = self.stream.tls_exporter();
 
-        // Authenticate
-        let access_token = match credentials {
-            Credentials::Plain { username, secret } | Credentials::XOauth2 { username, secret } => {
                 match self
                     .jmap
-                    .authenticate_plain(&username, &secret, self.remote_addr)
+                    .directory
+                    .scram_step(state, &response, is_plus, channel_binding.as_deref())
                     .await
                 {
-                    AuthResult::Success(token) => Some(token),
-                    AuthResult::Failure => None,
-                    AuthResult::Banned => {
-                        return Err(StatusResponse::bye(
-                            "Too many authentication requests from this IP address.",
-                        ))
-                    }
-                }
-            }
-            Credentials::OAuthBearer { token } => {
-                match self
-                    .jmap
-                    .validate_access_token("access_token", &token)
-                    .await
-                {
-                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
-                    Err(err) => {
-                        tracing::debug!(
-                            parent: &self.span,
-                            context = "authenticate",
-                            err = err,
-                            "Failed to validate access token."
+                    Ok(ScramStep::Challenge(state, challenge)) => {
+                        self.scram = Some(state);
+                        self.receiver.request = receiver::Request {
+                            tag: String::new(),
+                            command: Command::Authenticate,
+                            tokens: vec![receiver::Token::Argument(mechanism.into_bytes())],
+                        };
+                        self.receiver.state = receiver::State::Argument { last_ch: b' ' };
+                        return Ok(
+                            format!("\"{}\"\r\n", base64_encode(challenge.as_bytes())).into_bytes()
                         );
-                        None
+                    }
+                    Ok(ScramStep::Authenticate {
+                        username,
+                        is_verified,
+                    }) => (
+                        self.jmap
+                            .authenticate_scram(&username, is_verified, self.remote_addr)
+                            .await,
+                        auth_mechanism,
+                    ),
+                    Ok(ScramStep::Invalid) => {
+                        self.record_auth_usage(auth_mechanism, false);
+                        return Err(StatusResponse::no("Invalid SCRAM exchange."));
+                    }
+                    Err(_) => {
+                        return Err(StatusResponse::no("Temporary authentication failure."));
                     }
                 }
             }
+            _ => {
+                return Err(StatusResponse::no(
+                    "Authentication mechanism not supported.",
+                ))
+            }
+        };
+
+        let access_token = match result {
+            AuthResult::Success(token) => Some(token),
+            AuthResult::MustChangePassword(_) => {
+                self.record_auth_usage(mechanism, false);
+                return Err(StatusResponse::no("Password must be changed before use."));
+            }
+            AuthResult::Failure => None,
+            AuthResult::Banned => {
+                self.record_auth_usage(mechanism, false);
+                return Err(StatusResponse::bye(
+                    "Too many authentication requests from this IP address.",
+                ));
+            }
         };
+        self.record_auth_usage(mechanism, access_token.is_some());
 
         if let Some(access_token) = access_token {
             // Enforce concurrency limits
@@ -177,6 +208,71 @@ impl<T: SessionStream> Session<T> {
         }
     }
 
+    async fn is_auth_allowed(&self) -> Result<(), StatusResponse> {
+        // Throttle authentication requests
+        if self
+            .jmap
+            .is_auth_allowed_soft(&self.remote_addr)
+            .await
+            .is_err()
+        {
+            tracing::debug!(parent: &self.span,
+                event = "disconnect",
+                "Too many authentication attempts, disconnecting.",
+            );
+            Err(StatusResponse::bye(
+                "Too many authentication requests from this IP address.",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn authenticate_credentials(
+        &self,
+        credentials: Credentials<String>,
+    ) -> AuthResult<AccessToken> {
+        match credentials {
+            Credentials::Plain { username, secret } | Credentials::XOauth2 { username, secret } => {
+                self.jmap
+                    .authenticate_plain(&username, &secret, self.remote_addr)
+                    .await
+            }
+            Credentials::OAuthBearer { token } => {
+                match self.jmap.validate_bearer_token(&token).await {
+                    Ok(account_id) => self
+                        .jmap
+                        .get_access_token(account_id)
+                        .await
+                        .map_or(AuthResult::Failure, AuthResult::Success),
+                    Err(err) => {
+                        tracing::debug!(
+                            parent: &self.span,
+                            context = "authenticate",
+                            err = err,
+                            "Failed to validate access token."
+                        );
+                        AuthResult::Failure
+                    }
+                }
+            }
+        }
+    }
+
+    /// Counts the outcome of an authentication attempt in the mechanism
+    /// usage statistics shared with SMTP.
+    fn record_auth_usage(&self, mechanism: u64, is_success: bool) {
+        self.jmap
+            .smtp
+            .record_auth_usage(&self.instance.id, mechanism, |usage| {
+                if is_success {
+                    &usage.success
+                } else {
+                    &usage.failure
+                }
+            });
+    }
+
     pub async fn handle_unauthenticate(&mut self) -> super::OpResult {
         self.state = State::NotAuthenticated { auth_failures: 0 };
 
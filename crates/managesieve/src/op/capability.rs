@@ -36,8 +36,12 @@ impl<T: SessionStream> Session<T> {
         if !self.stream.is_tls() {
             response.extend_from_slice(b"\"SASL\" \"\"\r\n");
             response.extend_from_slice(b"\"STARTTLS\"\r\n");
+        } else if self.stream.tls_exporter().is_some() {
+            response.extend_from_slice(
+                b"\"SASL\" \"PLAIN OAUTHBEARER SCRAM-SHA-256 SCRAM-SHA-256-PLUS\"\r\n",
+            );
         } else {
-            response.extend_from_slice(b"\"SASL\" \"PLAIN OAUTHBEARER\"\r\n");
+            response.extend_from_slice(b"\"SASL\" \"PLAIN OAUTHBEARER SCRAM-SHA-256\"\r\n");
         };
         if let Some(sieve) = self
             .jmap
@@ -22,7 +22,10 @@
 */
 
 use imap_proto::receiver::Request;
-use jmap::sieve::set::{ObjectBlobId, SCHEMA};
+use jmap::{
+    filter_rule::FILTER_RULES_SCRIPT,
+    sieve::set::{ObjectBlobId, SCHEMA},
+};
 use jmap_proto::{
     object::{index::ObjectIndexBuilder, Object},
     types::{blob::BlobId, collection::Collection, property::Property, value::Value},
@@ -218,10 +221,12 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             Err(StatusResponse::no("Script name cannot be empty."))
         } else if name.len() > self.jmap.config.sieve_max_script_name {
             Err(StatusResponse::no("Script name is too long."))
-        } else if name.eq_ignore_ascii_case("vacation") {
-            Err(StatusResponse::no(
-                "The 'vacation' name is reserved, please use a different name.",
-            ))
+        } else if name.eq_ignore_ascii_case("vacation")
+            || name.eq_ignore_ascii_case(FILTER_RULES_SCRIPT)
+        {
+            Err(StatusResponse::no(format!(
+                "The '{name}' name is reserved, please use a different name.",
+            )))
         } else {
             Ok(self
                 .jmap
@@ -31,13 +31,14 @@ pub mod shared;
 pub mod throttle;
 
 use std::{
-    net::SocketAddr,
+    net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::{atomic::AtomicU64, Arc},
     time::Duration,
 };
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use arc_swap::ArcSwap;
 use directory::Directories;
 use mail_auth::{
     common::crypto::{Ed25519Key, RsaKey, Sha256},
@@ -47,7 +48,9 @@ use mail_send::Credentials;
 use sieve::Sieve;
 use store::Stores;
 use utils::{
-    config::{if_block::IfBlock, utils::ConstantValue, Rate, Server, ServerProtocol},
+    config::{
+        if_block::IfBlock, ipmask::IpAddrMask, utils::ConstantValue, Rate, Server, ServerProtocol,
+    },
     expr::{Expression, Token},
 };
 
@@ -104,11 +107,21 @@ pub struct Extensions {
     pub future_release: IfBlock,
     pub deliver_by: IfBlock,
     pub mt_priority: IfBlock,
+
+    // Harvesting protection
+    pub enumeration: Enumeration,
+}
+
+pub struct Enumeration {
+    pub rate: IfBlock,
+    pub honey: IfBlock,
+    pub score: f64,
 }
 
 pub struct Auth {
     pub directory: IfBlock,
     pub mechanisms: IfBlock,
+    pub require_tls: IfBlock,
     pub require: IfBlock,
     pub allow_plain_text: IfBlock,
     pub must_match_sender: IfBlock,
@@ -116,6 +129,14 @@ pub struct Auth {
     pub errors_wait: IfBlock,
 }
 
+pub struct Responses {
+    pub user_unknown: IfBlock,
+    pub policy_reject: IfBlock,
+    pub rate_limited: IfBlock,
+    pub over_quota: IfBlock,
+    pub support_url: IfBlock,
+}
+
 pub struct Mail {
     pub script: IfBlock,
     pub rewrite: IfBlock,
@@ -133,6 +154,71 @@ pub struct Rcpt {
 
     // Limits
     pub max_recipients: IfBlock,
+
+    // Harvesting protection
+    pub cache_ttl: Duration,
+    pub tarpit_after: IfBlock,
+    pub tarpit_max_delay: IfBlock,
+    pub honeypot: Honeypot,
+
+    // Greylisting
+    pub greylist: Greylist,
+}
+
+pub struct Honeypot {
+    pub enable: IfBlock,
+    pub score: f64,
+    pub window: Duration,
+    pub penalize_after: u64,
+    pub ban_after: u64,
+}
+
+pub struct Greylist {
+    pub enable: IfBlock,
+    pub delay: Duration,
+    pub ttl: Duration,
+    pub whitelist_spf: bool,
+    pub whitelist_networks: Vec<IpAddrMask>,
+}
+
+pub struct Dnsbl {
+    pub enable: IfBlock,
+    pub action: IfBlock,
+    pub zones: Vec<DnsblZone>,
+    pub cache_ttl: Duration,
+}
+
+pub struct DnsblZone {
+    pub id: String,
+    pub zone: String,
+    pub kind: DnsblKind,
+    pub stage: DnsblStage,
+    pub weight: f64,
+    pub codes: Vec<Ipv4Addr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsblKind {
+    Ip,
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsblStage {
+    Connect,
+    Ehlo,
+    MailFrom,
+}
+
+/// Verdict for a client listed on one or more blocklists, obtained by
+/// evaluating `session.dnsbl.action` against the accumulated score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DnsblAction {
+    #[default]
+    Accept,
+    Tag,
+    Greylist,
+    Reject,
 }
 
 pub struct Data {
@@ -152,6 +238,102 @@ pub struct Data {
     pub add_auth_results: IfBlock,
     pub add_message_id: IfBlock,
     pub add_date: IfBlock,
+
+    // Footer
+    pub footer: IfBlock,
+
+    // Hold for approval
+    pub moderate: IfBlock,
+
+    // Data loss prevention
+    pub dlp: Dlp,
+
+    // Compliance journaling
+    pub journal: IfBlock,
+
+    // Compromised account detection
+    pub risk: SenderRisk,
+
+    // Rules deployed in monitor-only mode
+    pub monitor: Monitor,
+
+    // Spooling of large messages
+    pub spool: Spool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Spool {
+    pub threshold: usize,
+    pub path: PathBuf,
+}
+
+#[derive(Default)]
+pub struct SenderRisk {
+    pub enable: IfBlock,
+
+    // Recipient count spikes
+    pub recipients_min: u64,
+    pub recipients_factor: f64,
+    pub recipients_score: u32,
+
+    // Bulk sending after a login from a new country
+    pub country_score: u32,
+    pub country_bulk: u64,
+    pub country_window: Duration,
+
+    // Phishing kit URLs
+    pub url_patterns: Vec<regex::Regex>,
+    pub url_score: u32,
+
+    // Actions
+    pub throttle_threshold: u32,
+    pub suspend_threshold: u32,
+    pub suspend_duration: Duration,
+    pub notify_url: Option<String>,
+    pub notify_timeout: Duration,
+}
+
+#[derive(Default)]
+pub struct Monitor {
+    pub rules: AHashSet<String>,
+    pub notify_url: Option<String>,
+    pub notify_timeout: Duration,
+}
+
+#[derive(Default)]
+pub struct Dlp {
+    pub enable: IfBlock,
+    pub rules: Vec<DlpRule>,
+}
+
+pub struct DlpRule {
+    pub id: String,
+    pub patterns: Vec<DlpPattern>,
+    pub threshold: usize,
+    pub action: DlpAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum DlpPattern {
+    CreditCard,
+    Iban,
+    Regex { name: String, regex: regex::Regex },
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DlpAction {
+    Reject,
+    Quarantine,
+    Encrypt,
+}
+
+pub enum Journal {
+    Mailbox { address: String },
+    Relay { address: String, relay: String },
+    Blob { prefix: String },
 }
 
 pub struct Pipe {
@@ -175,13 +357,37 @@ pub struct Milter {
     pub protocol_version: milter::Version,
     pub flags_actions: Option<u32>,
     pub flags_protocol: Option<u32>,
+    pub actions: AHashMap<MilterStage, MilterAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MilterStage {
+    Connect,
+    Ehlo,
+    MailFrom,
+    RcptTo,
+    Data,
+    Headers,
+    Body,
+}
+
+/// Overrides the verdict a milter returns at a given stage, which allows
+/// trialling a filter by recording its verdicts in a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MilterAction {
+    #[default]
+    Milter,
+    Accept,
+    Reject,
+    TempFail,
+    AddHeader,
 }
 
 pub struct SessionConfig {
     pub timeout: IfBlock,
     pub duration: IfBlock,
     pub transfer_limit: IfBlock,
-    pub throttle: SessionThrottle,
+    pub throttle: ArcSwap<SessionThrottle>,
 
     pub connect: Connect,
     pub ehlo: Ehlo,
@@ -190,14 +396,23 @@ pub struct SessionConfig {
     pub rcpt: Rcpt,
     pub data: Data,
     pub extensions: Extensions,
+    pub responses: Responses,
+    pub dnsbl: Dnsbl,
 }
 
+#[derive(Default)]
 pub struct SessionThrottle {
     pub connect: Vec<Throttle>,
     pub mail_from: Vec<Throttle>,
     pub rcpt_to: Vec<Throttle>,
 }
 
+pub struct Footer {
+    pub text: String,
+    pub html: Option<String>,
+    pub once_per_thread: bool,
+}
+
 pub struct RelayHost {
     pub address: String,
     pub port: u16,
@@ -205,6 +420,14 @@ pub struct RelayHost {
     pub auth: Option<Credentials<String>>,
     pub tls_implicit: bool,
     pub tls_allow_invalid_certs: bool,
+    pub pipelining: bool,
+}
+
+pub struct PipeCommand {
+    pub command: String,
+    pub arguments: Vec<String>,
+    pub per_recipient: bool,
+    pub timeout: Duration,
 }
 
 pub struct QueueConfig {
@@ -227,8 +450,87 @@ pub struct QueueConfig {
     pub timeout: QueueOutboundTimeout,
 
     // Throttle and Quotas
-    pub throttle: QueueThrottle,
+    pub throttle: ArcSwap<QueueThrottle>,
     pub quota: QueueQuotas,
+
+    // Priority lanes
+    pub priority: IfBlock,
+    pub lanes: QueueLanes,
+
+    // Fair scheduling
+    pub fairness: QueueFairness,
+
+    // In-memory event index
+    pub index: QueueIndexConfig,
+
+    // Quarantine review
+    pub quarantine: Quarantine,
+
+    // Shadow delivery
+    pub shadow: Shadow,
+
+    // Source IP rotation
+    pub ip_rotation: IpRotation,
+
+    // Per-domain traffic shaping
+    pub shaping: TrafficShaping,
+
+    // Connection pooling
+    pub pool: QueuePool,
+}
+
+pub struct IpRotation {
+    pub duration: Option<Duration>,
+    pub threshold: u32,
+    pub patterns: Vec<String>,
+}
+
+pub struct TrafficShaping {
+    pub rate: IfBlock,
+    pub burst: IfBlock,
+    pub backoff: Option<Duration>,
+    pub backoff_max: Duration,
+}
+
+pub struct QueuePool {
+    pub enable: bool,
+    pub idle_timeout: Duration,
+    pub max_messages: usize,
+}
+
+pub struct Shadow {
+    pub relay: IfBlock,
+    pub until: Option<u64>,
+}
+
+pub struct Quarantine {
+    pub reviewers: Vec<String>,
+    pub approvers: Vec<String>,
+    pub digest_frequency: Duration,
+    pub from_name: String,
+    pub from_address: String,
+    pub sign: IfBlock,
+    pub link_url: String,
+    pub link_secret: Vec<u8>,
+    pub link_expiry: Duration,
+}
+
+pub struct QueueFairness {
+    pub bucket: IfBlock,
+    pub weight: IfBlock,
+    pub max_in_flight: u64,
+}
+
+pub struct QueueIndexConfig {
+    pub max_entries: usize,
+    pub rebuild: Duration,
+}
+
+pub struct QueueLanes {
+    pub weight_high: usize,
+    pub weight_normal: usize,
+    pub weight_low: usize,
+    pub max_dispatch: usize,
 }
 
 pub struct QueueOutboundSourceIp {
@@ -245,12 +547,15 @@ pub struct ReportConfig {
     pub dmarc: Report,
     pub dmarc_aggregate: AggregateReport,
     pub tls: AggregateReport,
+    pub dedup_window: Option<Duration>,
+    pub max_concurrent: usize,
 }
 
 pub struct ReportAnalysis {
     pub addresses: Vec<AddressMatch>,
     pub forward: bool,
     pub store: Option<PathBuf>,
+    pub retention: Duration,
     pub report_id: AtomicU64,
 }
 
@@ -264,6 +569,25 @@ pub struct Dsn {
     pub name: IfBlock,
     pub address: IfBlock,
     pub sign: IfBlock,
+
+    // Backscatter protection
+    pub require_auth: IfBlock,
+    pub suppress: IfBlock,
+    pub rate: Option<Rate>,
+    pub batv: Batv,
+}
+
+pub struct Batv {
+    pub keys: Vec<BatvKey>,
+    pub sign: IfBlock,
+    pub signing_key: IfBlock,
+    pub verify: IfBlock,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatvKey {
+    pub id: u8,
+    pub secret: Vec<u8>,
 }
 
 pub struct AggregateReport {
@@ -274,6 +598,7 @@ pub struct AggregateReport {
     pub send: IfBlock,
     pub sign: IfBlock,
     pub max_size: IfBlock,
+    pub delivery_window: Duration,
 }
 
 pub struct Report {
@@ -302,7 +627,7 @@ pub struct QueueOutboundTimeout {
     pub mta_sts: IfBlock,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct QueueThrottle {
     pub sender: Vec<Throttle>,
     pub rcpt: Vec<Throttle>,
@@ -456,6 +781,7 @@ impl std::fmt::Debug for RelayHost {
             .field("protocol", &self.protocol)
             .field("tls_implicit", &self.tls_implicit)
             .field("tls_allow_invalid_certs", &self.tls_allow_invalid_certs)
+            .field("pipelining", &self.pipelining)
             .finish()
     }
 }
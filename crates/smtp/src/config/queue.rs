@@ -23,22 +23,26 @@
 
 use std::time::Duration;
 
+use arc_swap::ArcSwap;
 use mail_auth::IpLookupStrategy;
+use mail_parser::DateTime;
 
 use crate::core::eval::*;
 
 use super::{
     map_expr_token,
     throttle::{ConfigThrottle, ParseTrottleKey},
-    Dsn, QueueConfig, QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueueQuota,
-    QueueQuotas, QueueThrottle, RequireOptional, THROTTLE_LOCAL_IP, THROTTLE_MX, THROTTLE_RCPT,
-    THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP, THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
+    Batv, BatvKey, Dsn, IpRotation, Quarantine, QueueConfig, QueueFairness, QueueIndexConfig,
+    QueueLanes, QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueuePool,
+    QueueQuota, QueueQuotas, QueueThrottle, RequireOptional, Shadow, TrafficShaping,
+    THROTTLE_LOCAL_IP, THROTTLE_MX, THROTTLE_RCPT, THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP,
+    THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
 };
 use utils::{
     config::{
         if_block::IfBlock,
         utils::{AsKey, ConstantValue, NoConstants, ParseValue},
-        Config,
+        Config, Rate,
     },
     expr::{Constant, Expression, ExpressionItem, Variable},
 };
@@ -48,6 +52,7 @@ pub trait ConfigQueue {
     fn parse_queue_throttle(&self) -> super::Result<QueueThrottle>;
     fn parse_queue_quota(&self) -> super::Result<QueueQuotas>;
     fn parse_queue_quota_item(&self, prefix: impl AsKey) -> super::Result<QueueQuota>;
+    fn parse_batv_keys(&self) -> super::Result<Vec<BatvKey>>;
 }
 
 impl ConfigQueue for Config {
@@ -71,6 +76,25 @@ impl ConfigQueue for Config {
             V_MX,
         ];
 
+        let session_keys = &[
+            V_SENDER,
+            V_SENDER_DOMAIN,
+            V_AUTHENTICATED_AS,
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+        ];
+        let rcpt_session_keys = &[
+            V_RECIPIENT,
+            V_RECIPIENT_DOMAIN,
+            V_SENDER,
+            V_SENDER_DOMAIN,
+            V_AUTHENTICATED_AS,
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+        ];
+
         let default_hostname = self.value_require("server.hostname")?;
 
         let config = QueueConfig {
@@ -148,8 +172,123 @@ impl ConfigQueue for Config {
                     })?
                     .unwrap_or_else(|| IfBlock::new(false)),
             },
-            throttle: self.parse_queue_throttle()?,
+            throttle: ArcSwap::from_pointee(self.parse_queue_throttle()?),
             quota: self.parse_queue_quota()?,
+            priority: self
+                .parse_if_block("queue.priority", |name| {
+                    map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                })?
+                .unwrap_or_default(),
+            lanes: QueueLanes {
+                weight_high: self
+                    .property_or_static::<usize>("queue.lanes.weight.high", "8")?
+                    .max(1),
+                weight_normal: self
+                    .property_or_static::<usize>("queue.lanes.weight.normal", "4")?
+                    .max(1),
+                weight_low: self
+                    .property_or_static::<usize>("queue.lanes.weight.low", "1")?
+                    .max(1),
+                max_dispatch: self.property_or_static("queue.lanes.max-dispatch", "100")?,
+            },
+            fairness: QueueFairness {
+                bucket: self
+                    .parse_if_block("queue.fairness.bucket", |name| {
+                        map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                weight: self
+                    .parse_if_block("queue.fairness.weight", |name| {
+                        map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(1)),
+                max_in_flight: self.property_or_static("queue.fairness.max-in-flight", "0")?,
+            },
+            index: QueueIndexConfig {
+                max_entries: self.property_or_static("queue.index.max-entries", "500000")?,
+                rebuild: self.property_or_static("queue.index.rebuild-interval", "15m")?,
+            },
+            quarantine: Quarantine {
+                reviewers: self
+                    .values("queue.quarantine.reviewers")
+                    .map(|(_, v)| v.trim().to_lowercase())
+                    .collect(),
+                approvers: self
+                    .values("queue.quarantine.approvers")
+                    .map(|(_, v)| v.trim().to_lowercase())
+                    .collect(),
+                digest_frequency: self
+                    .property_or_static("queue.quarantine.digest.frequency", "1d")?,
+                from_name: self
+                    .value("queue.quarantine.digest.from-name")
+                    .unwrap_or("Quarantine Review")
+                    .to_string(),
+                from_address: self
+                    .value("queue.quarantine.digest.from-address")
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| format!("MAILER-DAEMON@{default_hostname}")),
+                sign: self
+                    .parse_if_block("queue.quarantine.digest.sign", |name| {
+                        map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                link_url: self
+                    .value("queue.quarantine.link.url")
+                    .unwrap_or_default()
+                    .trim_end_matches('/')
+                    .to_string(),
+                link_secret: self
+                    .value("queue.quarantine.link.secret")
+                    .map(|v| v.as_bytes().to_vec())
+                    .unwrap_or_default(),
+                link_expiry: self.property_or_static("queue.quarantine.link.expiry", "7d")?,
+            },
+            shadow: Shadow {
+                relay: self
+                    .parse_if_block("queue.shadow.relay", |name| {
+                        map_expr_token::<NoConstants>(name, rcpt_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                until: self
+                    .value("queue.shadow.until")
+                    .map(|value| {
+                        DateTime::parse_rfc3339(value)
+                            .map(|dt| dt.to_timestamp() as u64)
+                            .ok_or_else(|| {
+                                format!(
+                                    "Invalid date {value:?} for property \"queue.shadow.until\"."
+                                )
+                            })
+                    })
+                    .transpose()?,
+            },
+            ip_rotation: IpRotation {
+                duration: self.property_or_static("queue.outbound.ip-rotation.duration", "6h")?,
+                threshold: self.property_or_static("queue.outbound.ip-rotation.threshold", "3")?,
+                patterns: self
+                    .values("queue.outbound.ip-rotation.patterns")
+                    .map(|(_, v)| v.to_lowercase())
+                    .collect(),
+            },
+            shaping: TrafficShaping {
+                rate: self
+                    .parse_if_block("queue.outbound.shaping.rate", |name| {
+                        map_expr_token::<Duration>(name, rcpt_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                burst: self
+                    .parse_if_block("queue.outbound.shaping.burst", |name| {
+                        map_expr_token::<NoConstants>(name, rcpt_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                backoff: self.property_or_static("queue.outbound.shaping.backoff", "1m")?,
+                backoff_max: self.property_or_static("queue.outbound.shaping.backoff-max", "1h")?,
+            },
+            pool: QueuePool {
+                enable: self.property_or_static("queue.outbound.pool.enable", "false")?,
+                idle_timeout: self.property_or_static("queue.outbound.pool.idle-timeout", "15s")?,
+                max_messages: self.property_or_static("queue.outbound.pool.max-messages", "100")?,
+            },
             timeout: QueueOutboundTimeout {
                 connect: self
                     .parse_if_block("queue.outbound.timeouts.connect", |name| {
@@ -208,6 +347,35 @@ impl ConfigQueue for Config {
                         map_expr_token::<NoConstants>(name, sender_envelope_keys)
                     })?
                     .unwrap_or_default(),
+                require_auth: self
+                    .parse_if_block("report.dsn.require-auth", |name| {
+                        map_expr_token::<NoConstants>(name, session_keys)
+                    })?
+                    .unwrap_or_default(),
+                suppress: self
+                    .parse_if_block("report.dsn.suppress", |name| {
+                        map_expr_token::<NoConstants>(name, sender_envelope_keys)
+                    })?
+                    .unwrap_or_default(),
+                rate: self.property::<Rate>("report.dsn.rate")?,
+                batv: Batv {
+                    keys: self.parse_batv_keys()?,
+                    sign: self
+                        .parse_if_block("report.dsn.batv.sign", |name| {
+                            map_expr_token::<NoConstants>(name, session_keys)
+                        })?
+                        .unwrap_or_default(),
+                    signing_key: self
+                        .parse_if_block("report.dsn.batv.signing-key", |name| {
+                            map_expr_token::<NoConstants>(name, session_keys)
+                        })?
+                        .unwrap_or_default(),
+                    verify: self
+                        .parse_if_block("report.dsn.batv.verify", |name| {
+                            map_expr_token::<NoConstants>(name, rcpt_session_keys)
+                        })?
+                        .unwrap_or_default(),
+                },
             },
         };
 
@@ -353,6 +521,24 @@ impl ConfigQueue for Config {
             Ok(quota)
         }
     }
+
+    fn parse_batv_keys(&self) -> super::Result<Vec<BatvKey>> {
+        let mut keys = Vec::new();
+        for id in self.sub_keys("report.dsn.batv.keys", "") {
+            let key = ("report.dsn.batv.keys", id);
+            keys.push(BatvKey {
+                id: id.parse::<u8>().ok().filter(|id| *id < 10).ok_or_else(|| {
+                    format!(
+                        "Invalid BATV key id {id:?} for property {:?}, expected a digit.",
+                        key.as_key()
+                    )
+                })?,
+                secret: self.value_require(key)?.as_bytes().to_vec(),
+            });
+        }
+
+        Ok(keys)
+    }
 }
 
 impl ParseValue for RequireOptional {
@@ -98,8 +98,11 @@ impl ConfigReport for Config {
                 addresses,
                 forward: self.property("report.analysis.forward")?.unwrap_or(false),
                 store: self.property("report.analysis.store")?,
+                retention: self.property_or_static("report.analysis.retention", "30d")?,
                 report_id: 0.into(),
             },
+            dedup_window: self.property_or_static("report.dedup-window", "10m")?,
+            max_concurrent: self.property_or_static("report.aggregate.max-concurrent", "4")?,
         })
     }
 
@@ -184,6 +187,8 @@ impl ConfigReport for Config {
                     map_expr_token::<NoConstants>(name, rcpt_envelope_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(25 * 1024 * 1024)),
+            delivery_window: self
+                .property_or_static(("report", id, "aggregate.delivery-window"), "30m")?,
         })
     }
 }
@@ -21,8 +21,13 @@
  * for more details.
 */
 
-use std::io::Read;
+use std::{
+    io::Read,
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
 
+use ahash::AHashMap;
 use mail_auth::{
     common::lru::{DnsCache, LruCache},
     flate2::read::GzDecoder,
@@ -30,15 +35,19 @@ use mail_auth::{
         config::{ResolverConfig, ResolverOpts},
         system_conf::read_system_conf,
     },
-    Resolver,
+    Resolver, MX,
 };
 
-use crate::{core::Resolvers, outbound::dane::DnssecResolver};
+use crate::{
+    core::{DnsOverrides, Resolvers},
+    outbound::dane::{DnssecResolver, Tlsa, TlsaEntry},
+};
 use utils::{config::Config, suffixlist::PublicSuffix};
 
 pub trait ConfigResolver {
     fn build_resolvers(&self) -> super::Result<Resolvers>;
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix>;
+    fn parse_dns_overrides(&self) -> super::Result<DnsOverrides>;
 }
 
 impl ConfigResolver for Config {
@@ -102,6 +111,7 @@ impl ConfigResolver for Config {
                     self.property("resolver.cache.mta-sts")?.unwrap_or(1024),
                 ),
             },
+            overrides: self.parse_dns_overrides()?,
         })
     }
 
@@ -193,4 +203,162 @@ impl ConfigResolver for Config {
 
         Ok(PublicSuffix::default())
     }
+
+    fn parse_dns_overrides(&self) -> super::Result<DnsOverrides> {
+        let mut overrides = DnsOverrides::default();
+        let mut mx: AHashMap<String, Vec<MX>> = AHashMap::new();
+        let mut ipv4: AHashMap<String, Vec<Ipv4Addr>> = AHashMap::new();
+        let mut ipv6: AHashMap<String, Vec<Ipv6Addr>> = AHashMap::new();
+        let mut tlsa: AHashMap<String, Tlsa> = AHashMap::new();
+
+        for (key, value) in self.values("resolver.override") {
+            let err =
+                |reason: &str| format!("Invalid DNS override {value:?} for {key:?}: {reason}");
+            let (name, rdata) = value
+                .trim()
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| err("missing record type"))?;
+            let (typ, rdata) = rdata
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| err("missing record data"))?;
+            let rdata = rdata.trim();
+            let mut name = name.to_lowercase();
+            if !name.ends_with('.') {
+                name.push('.');
+            }
+
+            match typ.to_ascii_uppercase().as_str() {
+                "MX" => {
+                    let (preference, exchange) = rdata
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| err("expected preference and exchange"))?;
+                    let preference = preference
+                        .parse::<u16>()
+                        .map_err(|_| err("invalid preference"))?;
+                    let exchange = exchange.trim().to_lowercase();
+                    let records = mx.entry(name).or_default();
+                    if let Some(record) = records.iter_mut().find(|r| r.preference == preference) {
+                        record.exchanges.push(exchange);
+                    } else {
+                        records.push(MX {
+                            exchanges: vec![exchange],
+                            preference,
+                        });
+                    }
+                }
+                "A" => {
+                    ipv4.entry(name)
+                        .or_default()
+                        .push(rdata.parse().map_err(|_| err("invalid IPv4 address"))?);
+                }
+                "AAAA" => {
+                    ipv6.entry(name)
+                        .or_default()
+                        .push(rdata.parse().map_err(|_| err("invalid IPv6 address"))?);
+                }
+                "TXT" => {
+                    if overrides
+                        .txt
+                        .insert(
+                            name,
+                            parse_txt(rdata).ok_or_else(|| err("invalid TXT data"))?,
+                        )
+                        .is_some()
+                    {
+                        return Err(err("only one TXT record per name is allowed"));
+                    }
+                }
+                "TLSA" => {
+                    let mut fields = rdata.split_whitespace();
+                    let is_end_entity = match fields.next() {
+                        Some("3") => true,
+                        Some("2") => false,
+                        _ => return Err(err("only DANE-TA(2) and DANE-EE(3) are supported")),
+                    };
+                    let is_spki = match fields.next() {
+                        Some("1") => true,
+                        Some("0") => false,
+                        _ => return Err(err("invalid selector")),
+                    };
+                    let is_sha256 = match fields.next() {
+                        Some("1") => true,
+                        Some("2") => false,
+                        _ => return Err(err("invalid matching type")),
+                    };
+                    let data = fields
+                        .collect::<String>()
+                        .as_bytes()
+                        .chunks(2)
+                        .map(|hex| {
+                            std::str::from_utf8(hex)
+                                .ok()
+                                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|data| !data.is_empty())
+                        .ok_or_else(|| err("invalid certificate association data"))?;
+
+                    let record = tlsa.entry(name).or_insert_with(|| Tlsa {
+                        entries: Vec::new(),
+                        has_end_entities: false,
+                        has_intermediates: false,
+                    });
+                    if is_end_entity {
+                        record.has_end_entities = true;
+                    } else {
+                        record.has_intermediates = true;
+                    }
+                    record.entries.push(TlsaEntry {
+                        is_end_entity,
+                        is_sha256,
+                        is_spki,
+                        data,
+                    });
+                }
+                _ => return Err(err("unsupported record type")),
+            }
+        }
+
+        overrides.mx = mx
+            .into_iter()
+            .map(|(name, mut records)| {
+                records.sort_unstable_by_key(|r| r.preference);
+                (name, Arc::new(records))
+            })
+            .collect();
+        overrides.ipv4 = ipv4.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+        overrides.ipv6 = ipv6.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+        overrides.tlsa = tlsa.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+
+        Ok(overrides)
+    }
+}
+
+fn parse_txt(rdata: &str) -> Option<Vec<u8>> {
+    if !rdata.starts_with('"') {
+        return Some(rdata.as_bytes().to_vec());
+    }
+
+    // Concatenate quoted character-strings
+    let mut txt = Vec::with_capacity(rdata.len());
+    let mut in_quotes = false;
+    let mut chars = rdata.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => {
+                let mut buf = [0u8; 4];
+                txt.extend_from_slice(chars.next()?.encode_utf8(&mut buf).as_bytes());
+            }
+            _ if in_quotes => {
+                let mut buf = [0u8; 4];
+                txt.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            }
+            _ if ch.is_whitespace() => (),
+            _ => return None,
+        }
+    }
+
+    (!in_quotes).then_some(txt)
 }
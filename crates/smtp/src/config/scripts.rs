@@ -184,6 +184,20 @@ impl ConfigSieve for Config {
             }
         }
 
+        // Parse ARC sealer
+        let seal = if let Some(id) = self.value("sieve.trusted.seal") {
+            if let Some(sealer) = ctx.sealers.get(id) {
+                sealer.clone().into()
+            } else {
+                return Err(format!(
+                    "No ARC sealer found with id {:?} for key {:?}.",
+                    id, "sieve.trusted.seal"
+                ));
+            }
+        } else {
+            None
+        };
+
         Ok(SieveCore {
             runtime,
             from_addr: self
@@ -198,7 +212,9 @@ impl ConfigSieve for Config {
                 .value("sieve.trusted.return-path")
                 .unwrap_or_default()
                 .to_string(),
+            hostname: hostname.to_string(),
             sign,
+            seal,
         })
     }
 }
@@ -21,8 +21,14 @@
  * for more details.
 */
 
-use std::{net::ToSocketAddrs, time::Duration};
+use std::{
+    net::{Ipv4Addr, ToSocketAddrs},
+    time::Duration,
+};
 
+use ahash::AHashMap;
+use arc_swap::ArcSwap;
+use regex::Regex;
 use smtp_proto::*;
 
 use crate::inbound::milter;
@@ -30,14 +36,17 @@ use crate::inbound::milter;
 use crate::core::eval::*;
 
 use super::{
-    map_expr_token, throttle::ConfigThrottle, Auth, Connect, Data, Ehlo, Extensions, Mail, Milter,
-    Pipe, Rcpt, SessionConfig, SessionThrottle, THROTTLE_AUTH_AS, THROTTLE_HELO_DOMAIN,
+    map_expr_token, throttle::ConfigThrottle, Auth, Connect, Data, Dlp, DlpAction, DlpPattern,
+    DlpRule, Dnsbl, DnsblAction, DnsblKind, DnsblStage, DnsblZone, Ehlo, Enumeration, Extensions,
+    Greylist, Honeypot, Mail, Milter, MilterAction, MilterStage, Monitor, Pipe, Rcpt, Responses,
+    SenderRisk, SessionConfig, SessionThrottle, Spool, THROTTLE_AUTH_AS, THROTTLE_HELO_DOMAIN,
     THROTTLE_LISTENER, THROTTLE_LOCAL_IP, THROTTLE_RCPT, THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP,
     THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
 };
 use utils::{
     config::{
         if_block::IfBlock,
+        ipmask::IpAddrMask,
         utils::{AsKey, ConstantValue, NoConstants, ParseValue},
         Config,
     },
@@ -54,8 +63,13 @@ pub trait ConfigSession {
     fn parse_session_mail(&self) -> super::Result<Mail>;
     fn parse_session_rcpt(&self) -> super::Result<Rcpt>;
     fn parse_session_data(&self) -> super::Result<Data>;
+    fn parse_session_responses(&self) -> super::Result<Responses>;
+    fn parse_session_dnsbl(&self) -> super::Result<Dnsbl>;
     fn parse_pipes(&self, available_keys: &[u32]) -> super::Result<Vec<Pipe>>;
     fn parse_milters(&self, available_keys: &[u32]) -> super::Result<Vec<Milter>>;
+    fn parse_dlp(&self, available_keys: &[u32]) -> super::Result<Dlp>;
+    fn parse_sender_risk(&self, available_keys: &[u32]) -> super::Result<SenderRisk>;
+    fn parse_monitor(&self) -> super::Result<Monitor>;
 }
 
 impl ConfigSession for Config {
@@ -78,7 +92,7 @@ impl ConfigSession for Config {
                     map_expr_token::<Duration>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 60))),
-            throttle: self.parse_session_throttle()?,
+            throttle: ArcSwap::from_pointee(self.parse_session_throttle()?),
             connect: self.parse_session_connect()?,
             ehlo: self.parse_session_ehlo()?,
             auth: self.parse_session_auth()?,
@@ -86,6 +100,8 @@ impl ConfigSession for Config {
             rcpt: self.parse_session_rcpt()?,
             data: self.parse_session_data()?,
             extensions: self.parse_extensions()?,
+            responses: self.parse_session_responses()?,
+            dnsbl: self.parse_session_dnsbl()?,
         })
     }
 
@@ -226,6 +242,19 @@ impl ConfigSession for Config {
                     map_expr_token::<MtPriority>(name, available_keys)
                 })?
                 .unwrap_or_default(),
+            enumeration: Enumeration {
+                rate: self
+                    .parse_if_block("session.extensions.enumeration.rate", |name| {
+                        map_expr_token::<Duration>(name, available_keys)
+                    })?
+                    .unwrap_or_default(),
+                honey: self
+                    .parse_if_block("session.extensions.enumeration.honey", |name| {
+                        map_expr_token::<NoConstants>(name, available_keys)
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(false)),
+                score: self.property_or_static("session.extensions.enumeration.score", "5.0")?,
+            },
         })
     }
 
@@ -265,6 +294,11 @@ impl ConfigSession for Config {
                     map_expr_token::<Mechanism>(name, available_keys)
                 })?
                 .unwrap_or_default(),
+            require_tls: self
+                .parse_if_block("session.auth.require-tls", |name| {
+                    map_expr_token::<Mechanism>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Mechanism(AUTH_PLAIN | AUTH_LOGIN))),
             require: self
                 .parse_if_block("session.auth.require", |name| {
                     map_expr_token::<NoConstants>(name, available_keys)
@@ -374,6 +408,44 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys_full)
                 })?
                 .unwrap_or_default(),
+            cache_ttl: self.property_or_static("session.rcpt.cache.ttl", "5m")?,
+            tarpit_after: self
+                .parse_if_block("session.rcpt.tarpit.after", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            tarpit_max_delay: self
+                .parse_if_block("session.rcpt.tarpit.max-delay", |name| {
+                    map_expr_token::<Duration>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(Duration::from_secs(60))),
+            honeypot: Honeypot {
+                enable: self
+                    .parse_if_block("session.rcpt.honeypot.enable", |name| {
+                        map_expr_token::<NoConstants>(name, available_keys_full)
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(false)),
+                score: self.property_or_static("session.rcpt.honeypot.score", "10.0")?,
+                window: self.property_or_static("session.rcpt.honeypot.window", "1d")?,
+                penalize_after: self
+                    .property_or_static("session.rcpt.honeypot.threshold.penalize", "2")?,
+                ban_after: self.property_or_static("session.rcpt.honeypot.threshold.ban", "0")?,
+            },
+            greylist: Greylist {
+                enable: self
+                    .parse_if_block("session.rcpt.greylist.enable", |name| {
+                        map_expr_token::<NoConstants>(name, available_keys_full)
+                    })?
+                    .unwrap_or_else(|| IfBlock::new(false)),
+                delay: self.property_or_static("session.rcpt.greylist.delay", "5m")?,
+                ttl: self.property_or_static("session.rcpt.greylist.ttl", "30d")?,
+                whitelist_spf: self
+                    .property_or_static("session.rcpt.greylist.whitelist.spf", "true")?,
+                whitelist_networks: self
+                    .properties::<IpAddrMask>("session.rcpt.greylist.whitelist.networks")
+                    .map(|result| result.map(|(_, network)| network))
+                    .collect::<super::Result<Vec<_>>>()?,
+            },
         })
     }
 
@@ -439,8 +511,140 @@ impl ConfigSession for Config {
                     map_expr_token::<NoConstants>(name, available_keys)
                 })?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            footer: self
+                .parse_if_block("session.data.add-footer", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            moderate: self
+                .parse_if_block("session.data.moderate", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
             pipe_commands: self.parse_pipes(available_keys)?,
             milters: self.parse_milters(available_keys)?,
+            dlp: self.parse_dlp(available_keys)?,
+            journal: self
+                .parse_if_block("session.data.journal", |name| {
+                    map_expr_token::<NoConstants>(
+                        name,
+                        &[
+                            V_SENDER,
+                            V_SENDER_DOMAIN,
+                            V_RECIPIENT,
+                            V_RECIPIENT_DOMAIN,
+                            V_PRIORITY,
+                        ],
+                    )
+                })?
+                .unwrap_or_default(),
+            risk: self.parse_sender_risk(available_keys)?,
+            monitor: self.parse_monitor()?,
+            spool: Spool {
+                threshold: self.property_or_static("session.data.spool.threshold", "10485760")?,
+                path: self
+                    .property("session.data.spool.path")?
+                    .unwrap_or_else(std::env::temp_dir),
+            },
+        })
+    }
+
+    fn parse_session_responses(&self) -> super::Result<Responses> {
+        let available_keys = &[
+            V_SENDER,
+            V_SENDER_DOMAIN,
+            V_RECIPIENT,
+            V_RECIPIENT_DOMAIN,
+            V_AUTHENTICATED_AS,
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+            V_HELO_DOMAIN,
+        ];
+        let parse = |key: &str| {
+            self.parse_if_block(("session.response", key), |name| {
+                map_expr_token::<NoConstants>(name, available_keys)
+            })
+            .map(|block| block.unwrap_or_default())
+        };
+
+        Ok(Responses {
+            user_unknown: parse("user-unknown")?,
+            policy_reject: parse("policy-reject")?,
+            rate_limited: parse("rate-limited")?,
+            over_quota: parse("over-quota")?,
+            support_url: parse("support-url")?,
+        })
+    }
+
+    fn parse_session_dnsbl(&self) -> super::Result<Dnsbl> {
+        let available_keys = &[
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+            V_HELO_DOMAIN,
+            V_AUTHENTICATED_AS,
+            V_SENDER,
+            V_SENDER_DOMAIN,
+        ];
+        let available_keys_score = &[
+            V_LISTENER,
+            V_REMOTE_IP,
+            V_LOCAL_IP,
+            V_HELO_DOMAIN,
+            V_AUTHENTICATED_AS,
+            V_SENDER,
+            V_SENDER_DOMAIN,
+            V_DNSBL_SCORE,
+        ];
+
+        let mut zones = Vec::new();
+        for id in self.sub_keys("session.dnsbl.zone", "") {
+            let kind =
+                self.property_or_static::<DnsblKind>(("session.dnsbl.zone", id, "type"), "ip")?;
+            let stage = self.property_or_static::<DnsblStage>(
+                ("session.dnsbl.zone", id, "stage"),
+                match kind {
+                    DnsblKind::Ip => "connect",
+                    DnsblKind::Domain => "mail-from",
+                },
+            )?;
+            if kind == DnsblKind::Domain && stage == DnsblStage::Connect {
+                return Err(format!(
+                    "Domain blocklist {:?} cannot be queried at the connect stage.",
+                    ("session.dnsbl.zone", id).as_key()
+                ));
+            }
+
+            zones.push(DnsblZone {
+                id: id.to_string(),
+                zone: self
+                    .value_require(("session.dnsbl.zone", id, "zone"))?
+                    .trim_end_matches('.')
+                    .to_lowercase(),
+                kind,
+                stage,
+                weight: self.property_or_static(("session.dnsbl.zone", id, "weight"), "1.0")?,
+                codes: self
+                    .properties::<Ipv4Addr>(("session.dnsbl.zone", id, "codes"))
+                    .map(|result| result.map(|(_, code)| code))
+                    .collect::<super::Result<Vec<_>>>()?,
+            });
+        }
+
+        Ok(Dnsbl {
+            enable: self
+                .parse_if_block("session.dnsbl.enable", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_else(|| IfBlock::new(!zones.is_empty())),
+            action: self
+                .parse_if_block("session.dnsbl.action", |name| {
+                    map_expr_token::<DnsblAction>(name, available_keys_score)
+                })?
+                .unwrap_or_else(|| IfBlock::new(DnsblAction::Reject)),
+            zones,
+            cache_ttl: self.property_or_static("session.dnsbl.cache.ttl", "1h")?,
         })
     }
 
@@ -524,10 +728,268 @@ impl ConfigSession for Config {
                     id,
                     "options.flags.protocol",
                 ))?,
+                actions: {
+                    let mut actions = AHashMap::new();
+                    for stage in MilterStage::ALL {
+                        if let Some(action) = self.property::<MilterAction>((
+                            "session.data.milter",
+                            id,
+                            "action",
+                            stage.as_str(),
+                        ))? {
+                            actions.insert(stage, action);
+                        }
+                    }
+                    actions
+                },
             })
         }
         Ok(milters)
     }
+
+    fn parse_dlp(&self, available_keys: &[u32]) -> super::Result<Dlp> {
+        // Parse dictionaries
+        let mut dictionaries = AHashMap::new();
+        for id in self.sub_keys("session.data.dlp.dictionary", "") {
+            let mut patterns = Vec::new();
+            for (key, value) in self.values(("session.data.dlp.dictionary", id)) {
+                patterns.push(DlpPattern::Regex {
+                    name: id.to_string(),
+                    regex: Regex::new(value).map_err(|err| {
+                        format!("Invalid regular expression {value:?} for property {key:?}: {err}")
+                    })?,
+                });
+            }
+            dictionaries.insert(id, patterns);
+        }
+
+        // Parse rules
+        let mut rules = Vec::new();
+        for id in self.sub_keys("session.data.dlp.rule", "") {
+            let mut patterns = Vec::new();
+            for (key, value) in self.values(("session.data.dlp.rule", id, "patterns")) {
+                match value {
+                    "credit-card" => patterns.push(DlpPattern::CreditCard),
+                    "iban" => patterns.push(DlpPattern::Iban),
+                    _ => {
+                        if let Some(dictionary) = value
+                            .strip_prefix("dictionary:")
+                            .and_then(|name| dictionaries.get(name))
+                        {
+                            patterns.extend(dictionary.iter().cloned());
+                        } else {
+                            return Err(format!(
+                                "Invalid DLP pattern {value:?} for property {key:?}."
+                            ));
+                        }
+                    }
+                }
+            }
+            if patterns.is_empty() {
+                return Err(format!(
+                    "No patterns defined for DLP rule {:?}.",
+                    ("session.data.dlp.rule", id).as_key()
+                ));
+            }
+
+            rules.push(DlpRule {
+                id: id.to_string(),
+                patterns,
+                threshold: self
+                    .property_or_static::<usize>(("session.data.dlp.rule", id, "threshold"), "1")?
+                    .max(1),
+                action: self.property_require(("session.data.dlp.rule", id, "action"))?,
+            });
+        }
+
+        Ok(Dlp {
+            enable: self
+                .parse_if_block("session.data.dlp.enable", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            rules,
+        })
+    }
+
+    fn parse_sender_risk(&self, available_keys: &[u32]) -> super::Result<SenderRisk> {
+        let mut url_patterns = Vec::new();
+        for (key, value) in self.values("session.data.risk.urls.patterns") {
+            url_patterns.push(Regex::new(value).map_err(|err| {
+                format!("Invalid regular expression {value:?} for property {key:?}: {err}")
+            })?);
+        }
+
+        Ok(SenderRisk {
+            enable: self
+                .parse_if_block("session.data.risk.enable", |name| {
+                    map_expr_token::<NoConstants>(name, available_keys)
+                })?
+                .unwrap_or_default(),
+            recipients_min: self.property_or_static("session.data.risk.recipients.min", "100")?,
+            recipients_factor: self
+                .property_or_static("session.data.risk.recipients.factor", "5.0")?,
+            recipients_score: self
+                .property_or_static("session.data.risk.recipients.score", "40")?,
+            country_score: self.property_or_static("session.data.risk.country.score", "30")?,
+            country_bulk: self.property_or_static("session.data.risk.country.bulk", "10")?,
+            country_window: self.property_or_static("session.data.risk.country.window", "1d")?,
+            url_patterns,
+            url_score: self.property_or_static("session.data.risk.urls.score", "30")?,
+            throttle_threshold: self
+                .property_or_static("session.data.risk.threshold.throttle", "50")?,
+            suspend_threshold: self
+                .property_or_static("session.data.risk.threshold.suspend", "80")?,
+            suspend_duration: self
+                .property_or_static("session.data.risk.suspend.duration", "1d")?,
+            notify_url: self
+                .value("session.data.risk.notify.url")
+                .map(|url| url.to_string()),
+            notify_timeout: self.property_or_static("session.data.risk.notify.timeout", "10s")?,
+        })
+    }
+
+    fn parse_monitor(&self) -> super::Result<Monitor> {
+        Ok(Monitor {
+            rules: self
+                .values("session.data.monitor.rules")
+                .map(|(_, rule)| rule.to_lowercase())
+                .collect(),
+            notify_url: self
+                .value("session.data.monitor.notify.url")
+                .map(|url| url.to_string()),
+            notify_timeout: self
+                .property_or_static("session.data.monitor.notify.timeout", "10s")?,
+        })
+    }
+}
+
+impl ParseValue for DlpAction {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "reject" | "block" => Ok(DlpAction::Reject),
+            "quarantine" => Ok(DlpAction::Quarantine),
+            "encrypt" | "require-tls" => Ok(DlpAction::Encrypt),
+            _ => Err(format!(
+                "Invalid DLP action {:?} for property {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ParseValue for DnsblKind {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "ip" | "dnsbl" => Ok(DnsblKind::Ip),
+            "domain" | "rhsbl" => Ok(DnsblKind::Domain),
+            _ => Err(format!(
+                "Invalid blocklist type {:?} for property {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ParseValue for DnsblStage {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "connect" => Ok(DnsblStage::Connect),
+            "ehlo" | "helo" => Ok(DnsblStage::Ehlo),
+            "mail-from" | "mail" => Ok(DnsblStage::MailFrom),
+            _ => Err(format!(
+                "Invalid blocklist stage {:?} for property {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl ParseValue for DnsblAction {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "accept" => Ok(DnsblAction::Accept),
+            "tag" => Ok(DnsblAction::Tag),
+            "greylist" => Ok(DnsblAction::Greylist),
+            "reject" => Ok(DnsblAction::Reject),
+            _ => Err(format!(
+                "Invalid blocklist action {:?} for property {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for DnsblAction {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(0) => Ok(DnsblAction::Accept),
+            Variable::Integer(1) => Ok(DnsblAction::Tag),
+            Variable::Integer(2) => Ok(DnsblAction::Greylist),
+            Variable::Integer(3) => Ok(DnsblAction::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<DnsblAction> for Constant {
+    fn from(value: DnsblAction) -> Self {
+        Constant::Integer(match value {
+            DnsblAction::Accept => 0,
+            DnsblAction::Tag => 1,
+            DnsblAction::Greylist => 2,
+            DnsblAction::Reject => 3,
+        })
+    }
+}
+
+impl ConstantValue for DnsblAction {}
+
+impl ParseValue for MilterAction {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "milter" | "default" => Ok(MilterAction::Milter),
+            "accept" => Ok(MilterAction::Accept),
+            "reject" => Ok(MilterAction::Reject),
+            "tempfail" => Ok(MilterAction::TempFail),
+            "add-header" => Ok(MilterAction::AddHeader),
+            _ => Err(format!(
+                "Invalid milter action {:?} for property {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
+impl MilterStage {
+    pub const ALL: [MilterStage; 7] = [
+        MilterStage::Connect,
+        MilterStage::Ehlo,
+        MilterStage::MailFrom,
+        MilterStage::RcptTo,
+        MilterStage::Data,
+        MilterStage::Headers,
+        MilterStage::Body,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MilterStage::Connect => "connect",
+            MilterStage::Ehlo => "ehlo",
+            MilterStage::MailFrom => "mail-from",
+            MilterStage::RcptTo => "rcpt-to",
+            MilterStage::Data => "data",
+            MilterStage::Headers => "headers",
+            MilterStage::Body => "body",
+        }
+    }
 }
 
 #[derive(Default)]
@@ -540,9 +1002,9 @@ impl ParseValue for Mechanism {
             "PLAIN" => AUTH_PLAIN,
             "XOAUTH2" => AUTH_XOAUTH2,
             "OAUTHBEARER" => AUTH_OAUTHBEARER,
-            /*"SCRAM-SHA-256-PLUS" => AUTH_SCRAM_SHA_256_PLUS,
             "SCRAM-SHA-256" => AUTH_SCRAM_SHA_256,
-            "SCRAM-SHA-1-PLUS" => AUTH_SCRAM_SHA_1_PLUS,
+            "SCRAM-SHA-256-PLUS" => AUTH_SCRAM_SHA_256_PLUS,
+            /*"SCRAM-SHA-1-PLUS" => AUTH_SCRAM_SHA_1_PLUS,
             "SCRAM-SHA-1" => AUTH_SCRAM_SHA_1,
             "XOAUTH" => AUTH_XOAUTH,
             "9798-M-DSA-SHA1" => AUTH_9798_M_DSA_SHA1,
@@ -589,6 +1051,18 @@ impl ParseValue for Mechanism {
     }
 }
 
+pub fn mechanism_name(mechanism: u64) -> &'static str {
+    match mechanism {
+        AUTH_LOGIN => "LOGIN",
+        AUTH_PLAIN => "PLAIN",
+        AUTH_XOAUTH2 => "XOAUTH2",
+        AUTH_OAUTHBEARER => "OAUTHBEARER",
+        AUTH_SCRAM_SHA_256 => "SCRAM-SHA-256",
+        AUTH_SCRAM_SHA_256_PLUS => "SCRAM-SHA-256-PLUS",
+        _ => "UNKNOWN",
+    }
+}
+
 impl<'x> TryFrom<Variable<'x>> for Mechanism {
     type Error = ();
 
@@ -21,17 +21,73 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use ahash::AHashMap;
 use mail_send::Credentials;
 use utils::config::Config;
 
-use crate::core::Shared;
+use crate::{
+    core::{
+        http::{HttpClient, HttpRetry},
+        Shared,
+    },
+    USER_AGENT,
+};
 
-use super::{ConfigContext, RelayHost};
+use super::{ConfigContext, Footer, Journal, PipeCommand, RelayHost};
 
 pub trait ConfigShared {
     fn parse_shared(&self, ctx: &ConfigContext) -> super::Result<Shared>;
     fn parse_host(&self, id: &str) -> super::Result<RelayHost>;
+    fn parse_footer(&self, id: &str) -> super::Result<Footer>;
+    fn parse_pipe(&self, id: &str) -> super::Result<PipeCommand>;
+    fn parse_journal(&self, id: &str) -> super::Result<Journal>;
+    fn parse_http_client(&self) -> super::Result<HttpClient> {
+        let timeout = self.property_or_static::<Duration>("http.client.timeout", "2m")?;
+        let connect_timeout =
+            self.property_or_static::<Duration>("http.client.connect-timeout", "30s")?;
+        let allow_invalid_certs =
+            self.property_or_static::<bool>("http.client.allow-invalid-certs", "false")?;
+        let proxy = self
+            .value("http.client.proxy")
+            .map(|proxy| {
+                reqwest::Proxy::all(proxy)
+                    .map_err(|err| format!("Invalid \"http.client.proxy\" URL: {err}"))
+            })
+            .transpose()?;
+        let build = |allow_invalid_certs: bool| {
+            let mut builder = reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(timeout)
+                .connect_timeout(connect_timeout)
+                .redirect(reqwest::redirect::Policy::none())
+                .danger_accept_invalid_certs(allow_invalid_certs);
+            if let Some(proxy) = proxy.clone() {
+                builder = builder.proxy(proxy);
+            }
+            builder
+                .build()
+                .map_err(|err| format!("Failed to build HTTP client: {err}"))
+        };
+        let client = build(allow_invalid_certs)?;
+
+        Ok(HttpClient {
+            client_verified: if allow_invalid_certs {
+                build(false)?
+            } else {
+                client.clone()
+            },
+            client,
+            retry: HttpRetry {
+                attempts: self
+                    .property_or_static::<u32>("http.client.retry.attempts", "3")?
+                    .max(1),
+                backoff: self.property_or_static("http.client.retry.backoff", "1s")?,
+                max_backoff: self.property_or_static("http.client.retry.max-backoff", "1m")?,
+            },
+        })
+    }
 }
 
 impl ConfigShared for Config {
@@ -42,6 +98,34 @@ impl ConfigShared for Config {
             relay_hosts.insert(id.to_string(), self.parse_host(id)?);
         }
 
+        let mut pipes = AHashMap::new();
+        for id in self.sub_keys("pipe", ".command") {
+            if relay_hosts.contains_key(id) {
+                return Err(format!(
+                    "Pipe {id:?} has the same name as an existing remote host."
+                ));
+            }
+            pipes.insert(id.to_string(), self.parse_pipe(id)?);
+        }
+
+        let mut footers = AHashMap::new();
+        for id in self.sub_keys("footer", ".text") {
+            footers.insert(id.to_string(), self.parse_footer(id)?);
+        }
+
+        let mut journals = AHashMap::new();
+        for id in self.sub_keys("journal", ".type") {
+            let journal = self.parse_journal(id)?;
+            if let Journal::Relay { relay, .. } = &journal {
+                if !relay_hosts.contains_key(relay) && !pipes.contains_key(relay) {
+                    return Err(format!(
+                        "Journal {id:?} references non-existent remote host {relay:?}."
+                    ));
+                }
+            }
+            journals.insert(id.to_string(), journal);
+        }
+
         Ok(Shared {
             scripts: ctx.scripts.clone(),
             signers: ctx.signers.clone(),
@@ -49,6 +133,11 @@ impl ConfigShared for Config {
             directories: ctx.directory.directories.clone(),
             lookup_stores: ctx.stores.lookup_stores.clone(),
             relay_hosts,
+            pipes,
+            footers,
+            journals,
+            http: self.parse_http_client()?,
+            transcripts: Default::default(),
             default_directory: ctx
                 .directory
                 .directories
@@ -105,6 +194,69 @@ impl ConfigShared for Config {
             tls_allow_invalid_certs: self
                 .property(("remote", id, "tls.allow-invalid-certs"))?
                 .unwrap_or(false),
+            pipelining: self
+                .property(("remote", id, "pipelining"))?
+                .unwrap_or(false),
         })
     }
+
+    fn parse_footer(&self, id: &str) -> super::Result<Footer> {
+        Ok(Footer {
+            text: self
+                .text_file_contents(("footer", id, "text"))?
+                .unwrap_or_default(),
+            html: self.text_file_contents(("footer", id, "html"))?,
+            once_per_thread: self
+                .property(("footer", id, "once-per-thread"))?
+                .unwrap_or(true),
+        })
+    }
+
+    fn parse_pipe(&self, id: &str) -> super::Result<PipeCommand> {
+        Ok(PipeCommand {
+            command: self.value_require(("pipe", id, "command"))?.to_string(),
+            arguments: self
+                .values(("pipe", id, "arguments"))
+                .map(|(_, v)| v.to_string())
+                .collect(),
+            per_recipient: self
+                .property(("pipe", id, "per-recipient"))?
+                .unwrap_or(true),
+            timeout: self
+                .property(("pipe", id, "timeout"))?
+                .unwrap_or_else(|| Duration::from_secs(30)),
+        })
+    }
+
+    fn parse_journal(&self, id: &str) -> super::Result<Journal> {
+        let address = || {
+            let address = self.value_require(("journal", id, "address"))?;
+            if address.contains('@') {
+                Ok(address.to_string())
+            } else {
+                Err(format!(
+                    "Invalid address {address:?} for property \"journal.{id}.address\"."
+                ))
+            }
+        };
+
+        match self.value_require(("journal", id, "type"))? {
+            "mailbox" => Ok(Journal::Mailbox {
+                address: address()?,
+            }),
+            "relay" => Ok(Journal::Relay {
+                address: address()?,
+                relay: self.value_require(("journal", id, "relay"))?.to_string(),
+            }),
+            "blob" => Ok(Journal::Blob {
+                prefix: self
+                    .value(("journal", id, "prefix"))
+                    .unwrap_or("journal/")
+                    .to_string(),
+            }),
+            other => Err(format!(
+                "Invalid journal type {other:?} for property \"journal.{id}.type\"."
+            )),
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use std::{borrow::Cow, cmp::Ordering, net::IpAddr, sync::Arc, vec::IntoIter};
 
 use directory::Directory;
-use mail_auth::IpLookupStrategy;
+use mail_auth::{common::headers::HeaderWriter, IpLookupStrategy};
 use sieve::Sieve;
 use smtp_proto::IntoString;
 use store::{Deserialize, LookupStore, Rows, Value};
@@ -10,7 +10,10 @@ use utils::{
     expr::{Expression, Variable},
 };
 
-use crate::config::{ArcSealer, DkimSigner, RelayHost};
+use crate::{
+    config::{ArcSealer, DkimSigner, Footer, RelayHost},
+    inbound::message_chunks,
+};
 
 use super::{ResolveVariable, SMTP};
 
@@ -25,6 +28,7 @@ pub const V_LISTENER: u32 = 7;
 pub const V_REMOTE_IP: u32 = 8;
 pub const V_LOCAL_IP: u32 = 9;
 pub const V_PRIORITY: u32 = 10;
+pub const V_DNSBL_SCORE: u32 = 11;
 
 pub const F_IS_LOCAL_DOMAIN: u32 = 0;
 pub const F_IS_LOCAL_ADDRESS: u32 = 1;
@@ -48,6 +52,7 @@ pub const VARIABLES_MAP: &[(&str, u32)] = &[
     ("remote_ip", V_REMOTE_IP),
     ("local_ip", V_LOCAL_IP),
     ("priority", V_PRIORITY),
+    ("dnsbl_score", V_DNSBL_SCORE),
 ];
 
 pub const FUNCTIONS_MAP: &[(&str, u32, u32)] = &[
@@ -345,6 +350,32 @@ impl SMTP {
             })
     }
 
+    /// Signs a message with each signer in turn, later signatures also cover
+    /// the headers added by earlier ones.
+    pub fn dkim_sign(
+        &self,
+        signers: &[String],
+        headers: &mut Vec<u8>,
+        message: &[u8],
+    ) -> Vec<mail_auth::Error> {
+        let mut errors = Vec::new();
+        for signer in signers {
+            if let Some(signer) = self.get_dkim_signer(signer) {
+                match signer.sign_chained(
+                    std::iter::once(headers.as_slice()).chain(message_chunks(message)),
+                ) {
+                    Ok(signature) => {
+                        signature.write_header(headers);
+                    }
+                    Err(err) => {
+                        errors.push(err);
+                    }
+                }
+            }
+        }
+        errors
+    }
+
     pub fn get_sieve_script(&self, name: &str) -> Option<&Arc<Sieve>> {
         self.shared.scripts.get(name).or_else(|| {
             tracing::warn!(
@@ -358,6 +389,19 @@ impl SMTP {
         })
     }
 
+    pub fn get_footer(&self, name: &str) -> Option<&Footer> {
+        self.shared.footers.get(name).or_else(|| {
+            tracing::warn!(
+                context = "get_footer",
+                event = "error",
+                name = name,
+                "Footer template not found."
+            );
+
+            None
+        })
+    }
+
     pub fn get_relay_host(&self, name: &str) -> Option<&RelayHost> {
         self.shared.relay_hosts.get(name).or_else(|| {
             tracing::warn!(
@@ -449,7 +493,7 @@ impl SMTP {
                 Err(_) => Variable::default(),
             }
         } else if record_type.eq_ignore_ascii_case("mx") {
-            match self.resolvers.dns.mx_lookup(entry.as_ref()).await {
+            match self.resolvers.mx_lookup(entry.as_ref()).await {
                 Ok(result) => result
                     .iter()
                     .flat_map(|mx| {
@@ -467,7 +511,7 @@ impl SMTP {
                 Err(_) => Variable::default(),
             }
         } else if record_type.eq_ignore_ascii_case("txt") {
-            match self.resolvers.dns.txt_raw_lookup(entry.as_ref()).await {
+            match self.resolvers.txt_raw_lookup(entry.as_ref()).await {
                 Ok(result) => Variable::from(String::from_utf8(result).unwrap_or_default()),
                 Err(_) => Variable::default(),
             }
@@ -485,7 +529,7 @@ impl SMTP {
                 Variable::default()
             }
         } else if record_type.eq_ignore_ascii_case("ipv4") {
-            match self.resolvers.dns.ipv4_lookup(entry.as_ref()).await {
+            match self.resolvers.ipv4_lookup(entry.as_ref()).await {
                 Ok(result) => result
                     .iter()
                     .map(|ip| Variable::from(ip.to_string()))
@@ -494,7 +538,7 @@ impl SMTP {
                 Err(_) => Variable::default(),
             }
         } else if record_type.eq_ignore_ascii_case("ipv6") {
-            match self.resolvers.dns.ipv6_lookup(entry.as_ref()).await {
+            match self.resolvers.ipv6_lookup(entry.as_ref()).await {
                 Ok(result) => result
                     .iter()
                     .map(|ip| Variable::from(ip.to_string()))
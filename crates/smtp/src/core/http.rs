@@ -0,0 +1,113 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+
+#[derive(Clone)]
+pub struct HttpClient {
+    pub client: reqwest::Client,
+    // Always validates certificates, used where RFC 8461 requires it (MTA-STS)
+    pub client_verified: reqwest::Client,
+    pub retry: HttpRetry,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl HttpClient {
+    // Sends a request built by the supplied closure, retrying on connection errors,
+    // timeouts, 429 and 5xx responses. Retry-After is honored when present.
+    pub async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        self.send_with(&self.client, request).await
+    }
+
+    pub async fn send_verified(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        self.send_with(&self.client_verified, request).await
+    }
+
+    async fn send_with(
+        &self,
+        client: &reqwest::Client,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let result = request(client).send().await;
+            if attempt >= self.retry.attempts {
+                return result;
+            }
+
+            let wait = match &result {
+                Ok(response)
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error() =>
+                {
+                    response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or_else(|| self.retry.backoff_for(attempt))
+                }
+                Err(err) if err.is_connect() || err.is_timeout() => self.retry.backoff_for(attempt),
+                _ => return result,
+            };
+
+            tracing::debug!(
+                context = "http",
+                event = "retry",
+                attempt = attempt,
+                wait_ms = wait.as_millis() as u64,
+                reason = match &result {
+                    Ok(response) => response.status().to_string(),
+                    Err(err) => err.to_string(),
+                },
+            );
+
+            tokio::time::sleep(wait.min(self.retry.max_backoff)).await;
+        }
+    }
+}
+
+impl HttpRetry {
+    pub fn backoff_for(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << (attempt.clamp(1, 16) - 1))
+            .min(self.max_backoff)
+    }
+}
@@ -21,7 +21,11 @@
  * for more details.
 */
 
-use std::{borrow::Cow, net::IpAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+};
 
 use directory::{AuthResult, Type};
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
@@ -43,15 +47,42 @@ use store::{
 
 use utils::listener::{limiter::InFlight, SessionData, SessionManager, SessionStream};
 
-use crate::queue::{self, HostResponse, QueueId, Status};
+use crate::{
+    config::session::mechanism_name,
+    inbound::{
+        dlp::IncidentStatus,
+        monitor::{MonitorAction, MonitorVerdict},
+    },
+    outbound::trace::TlsTrace,
+    queue::{
+        self,
+        quarantine::{LinkAction, QuarantineSource},
+        HostResponse, QueueId, Status, MAIL_QUARANTINED,
+    },
+};
 
-use super::{SmtpAdminSessionManager, SMTP};
+use super::{simulate::SimulationRequest, SmtpAdminSessionManager, SMTP};
 
 #[derive(Debug, serde::Serialize)]
 pub struct Response<T> {
     data: T,
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct TranscriptStatus {
+    pub enable: bool,
+    pub path: Option<String>,
+    pub listeners: Vec<String>,
+    pub remote_ips: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct TranscriptFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub struct Message {
     pub return_path: String,
@@ -83,6 +114,9 @@ pub struct Domain {
     #[serde(deserialize_with = "deserialize_datetime")]
     #[serde(serialize_with = "serialize_datetime")]
     pub expires: DateTime,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tls_trace: Vec<TlsTrace>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
@@ -107,6 +141,15 @@ pub struct Report {
     pub size: usize,
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct AuthMechanismUsage {
+    pub listener: String,
+    pub mechanism: String,
+    pub success: u64,
+    pub failure: u64,
+    pub rejected: u64,
+}
+
 impl SessionManager for SmtpAdminSessionManager {
     fn handle<T: SessionStream>(
         self,
@@ -178,10 +221,17 @@ async fn handle_request(
 
 impl SMTP {
     async fn parse_request(
-        &self,
+        self: &Arc<Self>,
         req: &hyper::Request<hyper::body::Incoming>,
         remote_addr: IpAddr,
     ) -> Result<hyper::Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        // Quarantine review links carry their own signature
+        if let Some(action) = req.uri().path().strip_prefix("/quarantine/") {
+            return Ok(self
+                .handle_quarantine_link(req.method(), req.uri(), action)
+                .await);
+        }
+
         // Authenticate request
         let mut is_authenticated = false;
         if let Some((mechanism, payload)) = req
@@ -216,6 +266,13 @@ impl SMTP {
                                 "Insufficient privileges."
                             );
                         }
+                        Ok(AuthResult::MustChangePassword(_)) => {
+                            tracing::debug!(
+                                context = "management",
+                                event = "auth-error",
+                                "Password must be changed before use."
+                            );
+                        }
                         Ok(AuthResult::Failure | AuthResult::Banned) => {
                             tracing::debug!(
                                 context = "management",
@@ -273,7 +330,7 @@ impl SMTP {
     }
 
     pub async fn handle_manage_request(
-        &self,
+        self: &Arc<Self>,
         uri: &Uri,
         method: &Method,
         path_1: &str,
@@ -285,6 +342,8 @@ impl SMTP {
                 let mut to = None;
                 let mut before = None;
                 let mut after = None;
+                let mut domain = None;
+                let mut status = None;
                 let mut error = None;
 
                 if let Some(query) = uri.query() {
@@ -314,6 +373,18 @@ impl SMTP {
                                     break;
                                 }
                             },
+                            "domain" => {
+                                domain = value.to_lowercase().into();
+                            }
+                            "status" => match value.parse_queue_status() {
+                                Ok(status_) => {
+                                    status = status_.into();
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
                             _ => {
                                 error = format!("Invalid parameter {key:?}.").into();
                                 break;
@@ -328,8 +399,12 @@ impl SMTP {
                         let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(0)));
                         let to_key =
                             ValueKey::from(ValueClass::Queue(QueueClass::Message(u64::MAX)));
-                        let has_filters =
-                            from.is_some() || to.is_some() || before.is_some() || after.is_some();
+                        let has_filters = from.is_some()
+                            || to.is_some()
+                            || before.is_some()
+                            || after.is_some()
+                            || domain.is_some()
+                            || status.is_some();
                         let _ =
                             self.shared
                                 .default_data_store
@@ -351,6 +426,12 @@ impl SMTP {
                                                 message.next_delivery_event() < *before
                                             }) && after.as_ref().map_or(true, |after| {
                                                 message.next_delivery_event() > *after
+                                            }) && message.domains.iter().any(|d| {
+                                                domain.as_ref().map_or(true, |domain| {
+                                                    is_domain_or_subdomain(&d.domain, domain)
+                                                }) && status.as_ref().map_or(true, |status| {
+                                                    status.matches(&d.status)
+                                                })
                                             }) {
                                                 result.push(key.deserialize_be_u64(1)?);
                                             }
@@ -413,6 +494,55 @@ impl SMTP {
                     Some(error) => error.into_bad_request(),
                 }
             }
+            (&Method::GET, "queue", "raw") => {
+                let queue_id = uri.query().and_then(|query| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "id")
+                        .map(|(_, value)| value.into_owned())
+                });
+
+                match queue_id.as_ref().map(|id| id.parse::<QueueId>()) {
+                    Some(Ok(queue_id)) => {
+                        let raw_message = if let Some(message) = self.read_message(queue_id).await {
+                            self.shared
+                                .default_blob_store
+                                .get_blob(message.blob_hash.as_slice(), 0..u32::MAX)
+                                .await
+                        } else {
+                            Ok(None)
+                        };
+
+                        match raw_message {
+                            Ok(Some(raw_message)) => {
+                                return hyper::Response::builder()
+                                    .status(StatusCode::OK)
+                                    .header(header::CONTENT_TYPE, "message/rfc822")
+                                    .body(
+                                        Full::new(Bytes::from(raw_message))
+                                            .map_err(|never| match never {})
+                                            .boxed(),
+                                    )
+                                    .unwrap();
+                            }
+                            Ok(None) => (
+                                StatusCode::NOT_FOUND,
+                                format!(
+                                    "{{\"error\": \"not-found\", \"details\": \"Message {queue_id} does not exist.\"}}",
+                                ),
+                            ),
+                            Err(err) => (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("Failed to fetch message: {err}"),
+                            ),
+                        }
+                    }
+                    Some(Err(_)) => {
+                        format!("Failed to parse id {:?}.", queue_id.unwrap_or_default())
+                            .into_bad_request()
+                    }
+                    None => "Missing parameter \"id\".".to_string().into_bad_request(),
+                }
+            }
             (&Method::GET, "queue", "retry") => {
                 let mut queue_ids = Vec::new();
                 let mut time = now();
@@ -458,7 +588,11 @@ impl SMTP {
                         for queue_id in queue_ids {
                             let mut found = false;
 
-                            if let Some(mut message) = self.read_message(queue_id).await {
+                            if let Some(mut message) = self
+                                .read_message(queue_id)
+                                .await
+                                .filter(|message| (message.flags & MAIL_QUARANTINED) == 0)
+                            {
                                 let prev_event = message.next_event().unwrap_or_default();
 
                                 for domain in &mut message.domains {
@@ -621,29 +755,130 @@ impl SMTP {
                     Some(error) => error.into_bad_request(),
                 }
             }
-            (&Method::GET, "report", "list") => {
-                let mut domain = None;
-                let mut type_ = None;
+            (&Method::GET, "queue", "replay") => {
+                let mut queue_id = None;
+                let mut script = None;
+                let mut redeliver = false;
                 let mut error = None;
 
                 if let Some(query) = uri.query() {
                     for (key, value) in form_urlencoded::parse(query.as_bytes()) {
                         match key.as_ref() {
-                            "type" => match value.as_ref() {
-                                "dmarc" => {
-                                    type_ = 0u8.into();
+                            "id" => match value.parse::<QueueId>() {
+                                Ok(id) => {
+                                    queue_id = id.into();
                                 }
-                                "tls" => {
-                                    type_ = 1u8.into();
+                                Err(_) => {
+                                    error = format!("Failed to parse id {value:?}.").into();
+                                    break;
                                 }
-                                _ => {
-                                    error = format!("Invalid report type {value:?}.").into();
+                            },
+                            "script" => {
+                                script = value.into_owned().into();
+                            }
+                            "redeliver" => {
+                                redeliver = value == "true" || value == "1";
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, queue_id) {
+                    (None, Some(queue_id)) => {
+                        match self.replay_message(queue_id, script, redeliver).await {
+                            Ok(result) => (
+                                StatusCode::OK,
+                                serde_json::to_string(&Response { data: result })
+                                    .unwrap_or_default(),
+                            ),
+                            Err(error) => error.into_bad_request(),
+                        }
+                    }
+                    (None, None) => "Missing parameter \"id\".".to_string().into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "dlp", "list") => {
+                let mut rule = None;
+                let mut status = None;
+                let mut sender = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "rule" => {
+                                rule = value.into_owned().into();
+                            }
+                            "from" => {
+                                sender = value.into_owned().into();
+                            }
+                            "status" => match IncidentStatus::parse(value.as_ref()) {
+                                Some(value) => {
+                                    status = value.into();
+                                }
+                                None => {
+                                    error = format!("Invalid status {value:?}.").into();
                                     break;
                                 }
                             },
-                            "domain" => {
-                                domain = value.into_owned().into();
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
                             }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_dlp_incidents(|incident| {
+                            rule.as_ref().map_or(true, |rule| &incident.rule == rule)
+                                && status.map_or(true, |status| incident.status == status)
+                                && sender
+                                    .as_ref()
+                                    .map_or(true, |from| incident.return_path.contains(from))
+                        })
+                        .await
+                    {
+                        Ok(incidents) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: incidents
+                                    .into_iter()
+                                    .map(|incident| incident.id)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list incidents: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "dlp", "status") => {
+                let mut incident_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    incident_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
                             _ => {
                                 error = format!("Invalid parameter {key:?}.").into();
                                 break;
@@ -654,51 +889,10 @@ impl SMTP {
 
                 match error {
                     None => {
-                        let mut result = Vec::new();
-                        let from_key = ValueKey::from(ValueClass::Queue(
-                            QueueClass::DmarcReportHeader(ReportEvent {
-                                due: 0,
-                                policy_hash: 0,
-                                seq_id: 0,
-                                domain: String::new(),
-                            }),
-                        ));
-                        let to_key = ValueKey::from(ValueClass::Queue(
-                            QueueClass::TlsReportHeader(ReportEvent {
-                                due: u64::MAX,
-                                policy_hash: 0,
-                                seq_id: 0,
-                                domain: String::new(),
-                            }),
-                        ));
-                        let _ = self
-                            .shared
-                            .default_data_store
-                            .iterate(
-                                IterateParams::new(from_key, to_key).ascending().no_values(),
-                                |key, _| {
-                                    if type_.map_or(true, |t| t == *key.last().unwrap()) {
-                                        let event = ReportEvent::deserialize(key)?;
-                                        if event.seq_id != 0
-                                            && domain.as_ref().map_or(true, |d| {
-                                                d.eq_ignore_ascii_case(&event.domain)
-                                            })
-                                        {
-                                            result.push(
-                                                if *key.last().unwrap() == 0 {
-                                                    QueueClass::DmarcReportHeader(event)
-                                                } else {
-                                                    QueueClass::TlsReportHeader(event)
-                                                }
-                                                .queue_id(),
-                                            );
-                                        }
-                                    }
-
-                                    Ok(true)
-                                },
-                            )
-                            .await;
+                        let mut result = Vec::with_capacity(incident_ids.len());
+                        for incident_id in incident_ids {
+                            result.push(self.read_dlp_incident(incident_id).await);
+                        }
 
                         (
                             StatusCode::OK,
@@ -708,16 +902,16 @@ impl SMTP {
                     Some(error) => error.into_bad_request(),
                 }
             }
-            (&Method::GET, "report", "status") => {
-                let mut report_ids = Vec::new();
+            (&Method::GET, "dlp", action @ ("release" | "discard")) => {
+                let mut incident_ids = Vec::new();
                 let mut error = None;
 
                 if let Some(query) = uri.query() {
                     for (key, value) in form_urlencoded::parse(query.as_bytes()) {
                         match key.as_ref() {
-                            "id" | "ids" => match value.parse_report_ids() {
+                            "id" | "ids" => match value.parse_queue_ids() {
                                 Ok(ids) => {
-                                    report_ids = ids;
+                                    incident_ids = ids;
                                 }
                                 Err(reason) => {
                                     error = reason.into();
@@ -732,38 +926,106 @@ impl SMTP {
                     }
                 }
 
-                let mut result = Vec::with_capacity(report_ids.len());
-                for report_id in report_ids {
-                    if let Ok(Some(_)) = self
-                        .shared
-                        .default_data_store
-                        .get_value::<()>(ValueKey::from(ValueClass::Queue(report_id.clone())))
-                        .await
-                    {
-                        result.push(Report::from(report_id).into());
-                    } else {
-                        result.push(None);
+                match error {
+                    None => {
+                        let mut result = Vec::with_capacity(incident_ids.len());
+                        for incident_id in incident_ids {
+                            let mut found = false;
+                            if let Some(queue_id) = self
+                                .read_dlp_incident(incident_id)
+                                .await
+                                .filter(|incident| incident.status == IncidentStatus::Quarantined)
+                                .and_then(|incident| incident.queue_id)
+                            {
+                                found = if action == "release" {
+                                    self.release_quarantined(queue_id, "admin").await
+                                } else {
+                                    self.discard_quarantined(queue_id, "admin").await
+                                };
+                            }
+                            result.push(found);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "monitor", action @ ("list" | "report")) => {
+                let mut rule = None;
+                let mut verdict = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "rule" => {
+                                rule = value.to_lowercase().into();
+                            }
+                            "verdict" => match MonitorAction::parse(value.as_ref()) {
+                                Some(value) => {
+                                    verdict = value.into();
+                                }
+                                None => {
+                                    error = format!("Invalid verdict {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
                     }
                 }
 
+                let filter = |entry: &MonitorVerdict| {
+                    rule.as_ref().map_or(true, |rule| &entry.rule == rule)
+                        && verdict.map_or(true, |verdict| entry.verdict == verdict)
+                };
                 match error {
-                    None => (
-                        StatusCode::OK,
-                        serde_json::to_string(&Response { data: result }).unwrap_or_default(),
-                    ),
+                    None if action == "list" => match self.list_monitor_verdicts(filter).await {
+                        Ok(verdicts) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: verdicts
+                                    .into_iter()
+                                    .map(|verdict| verdict.id)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list monitor verdicts: {err}"),
+                        ),
+                    },
+                    None => match self.monitor_report(filter).await {
+                        Ok(report) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: report }).unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to build monitor report: {err}"),
+                        ),
+                    },
                     Some(error) => error.into_bad_request(),
                 }
             }
-            (&Method::GET, "report", "cancel") => {
-                let mut report_ids = Vec::new();
+            (&Method::GET, "monitor", "status") => {
+                let mut verdict_ids = Vec::new();
                 let mut error = None;
 
                 if let Some(query) = uri.query() {
                     for (key, value) in form_urlencoded::parse(query.as_bytes()) {
                         match key.as_ref() {
-                            "id" | "ids" => match value.parse_report_ids() {
+                            "id" | "ids" => match value.parse_queue_ids() {
                                 Ok(ids) => {
-                                    report_ids = ids;
+                                    verdict_ids = ids;
                                 }
                                 Err(reason) => {
                                     error = reason.into();
@@ -780,20 +1042,9 @@ impl SMTP {
 
                 match error {
                     None => {
-                        let mut result = Vec::with_capacity(report_ids.len());
-
-                        for report_id in report_ids {
-                            match report_id {
-                                QueueClass::DmarcReportHeader(event) => {
-                                    self.delete_dmarc_report(event).await;
-                                }
-                                QueueClass::TlsReportHeader(event) => {
-                                    self.delete_tls_report(vec![event]).await;
-                                }
-                                _ => (),
-                            }
-
-                            result.push(true);
+                        let mut result = Vec::with_capacity(verdict_ids.len());
+                        for verdict_id in verdict_ids {
+                            result.push(self.read_monitor_verdict(verdict_id).await);
                         }
 
                         (
@@ -804,38 +1055,1226 @@ impl SMTP {
                     Some(error) => error.into_bad_request(),
                 }
             }
-            _ => (
-                StatusCode::NOT_FOUND,
-                format!(
-                    "{{\"error\": \"not-found\", \"details\": \"URL {} does not exist.\"}}",
-                    uri.path()
-                ),
-            ),
-        };
-
-        hyper::Response::builder()
-            .status(status)
-            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(
-                Full::new(Bytes::from(response))
-                    .map_err(|never| match never {})
-                    .boxed(),
-            )
-            .unwrap()
-    }
-}
-
-impl From<&queue::Message> for Message {
-    fn from(message: &queue::Message) -> Self {
-        let now = now();
+            (&Method::GET, "quarantine", "list") => {
+                let mut source = None;
+                let mut sender = None;
+                let mut error = None;
 
-        Message {
-            return_path: message.return_path.clone(),
-            created: DateTime::from_timestamp(message.created as i64),
-            size: message.size,
-            priority: message.priority,
-            env_id: message.env_id.clone(),
-            domains: message
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "from" => {
+                                sender = value.into_owned().into();
+                            }
+                            "source" => match value.as_ref() {
+                                "dlp" => {
+                                    source = QuarantineSource::Dlp.into();
+                                }
+                                "milter" => {
+                                    source = QuarantineSource::Milter.into();
+                                }
+                                "sieve" => {
+                                    source = QuarantineSource::Sieve.into();
+                                }
+                                "moderation" => {
+                                    source = QuarantineSource::Moderation.into();
+                                }
+                                _ => {
+                                    error = format!("Invalid source {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_quarantined(|entry| {
+                            source.map_or(true, |source| entry.source == source)
+                                && sender
+                                    .as_ref()
+                                    .map_or(true, |from| entry.return_path.contains(from))
+                        })
+                        .await
+                    {
+                        Ok(entries) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: entries
+                                    .into_iter()
+                                    .map(|entry| entry.queue_id)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list quarantined messages: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "quarantine", "status") => {
+                let mut queue_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    queue_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let mut result = Vec::with_capacity(queue_ids.len());
+                        for queue_id in queue_ids {
+                            result.push(self.read_quarantined(queue_id).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "quarantine", action @ ("release" | "discard")) => {
+                let mut queue_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    queue_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let mut result = Vec::with_capacity(queue_ids.len());
+                        for queue_id in queue_ids {
+                            result.push(if action == "release" {
+                                self.release_quarantined(queue_id, "admin").await
+                            } else {
+                                self.discard_quarantined(queue_id, "admin").await
+                            });
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "quarantine", "audit") => {
+                let mut queue_id = None;
+                let mut actor = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" => match value.parse() {
+                                Ok(id) => {
+                                    queue_id = Some(id);
+                                }
+                                Err(_) => {
+                                    error = format!("Invalid queue id {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "actor" => {
+                                actor = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_quarantine_audit(|entry| {
+                            queue_id.map_or(true, |id| entry.queue_id == id)
+                                && actor.as_ref().map_or(true, |actor| &entry.actor == actor)
+                        })
+                        .await
+                    {
+                        Ok(entries) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: entries }).unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to read audit log: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "shadow", "list") => {
+                let mut domain = None;
+                let mut diverged = None;
+                let mut completed = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "domain" => {
+                                domain = value.to_lowercase().into();
+                            }
+                            "diverged" | "completed" => match value.as_ref() {
+                                "true" | "false" => {
+                                    if key == "diverged" {
+                                        diverged = (value == "true").into();
+                                    } else {
+                                        completed = (value == "true").into();
+                                    }
+                                }
+                                _ => {
+                                    error = format!("Invalid value {value:?} for {key:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_shadow_entries(|entry| {
+                            domain
+                                .as_ref()
+                                .map_or(true, |domain| &entry.domain == domain)
+                                && diverged.map_or(true, |diverged| entry.diverged == diverged)
+                                && completed.map_or(true, |completed| entry.completed == completed)
+                        })
+                        .await
+                    {
+                        Ok(entries) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: entries
+                                    .into_iter()
+                                    .map(|entry| entry.queue_id)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list shadow deliveries: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "shadow", action @ ("status" | "delete")) => {
+                let mut queue_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    queue_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None if action == "status" => {
+                        let mut result = Vec::with_capacity(queue_ids.len());
+                        for queue_id in queue_ids {
+                            result.push(self.read_shadow_entry(queue_id).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    None => {
+                        let mut result = Vec::with_capacity(queue_ids.len());
+                        for queue_id in queue_ids {
+                            result.push(self.delete_shadow_entry(queue_id).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "dmarc", "list") => {
+                let mut domain = None;
+                let mut org_name = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "domain" => {
+                                domain = value.to_lowercase().into();
+                            }
+                            "org" => {
+                                org_name = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_incoming_dmarc_reports(|report| {
+                            domain
+                                .as_ref()
+                                .map_or(true, |domain| &report.domain == domain)
+                                && org_name
+                                    .as_ref()
+                                    .map_or(true, |org_name| report.org_name.contains(org_name))
+                        })
+                        .await
+                    {
+                        Ok(reports) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: reports
+                                    .into_iter()
+                                    .map(|report| report.id)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list DMARC reports: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "dmarc", action @ ("status" | "delete")) => {
+                let mut report_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    report_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None if action == "status" => {
+                        let mut result = Vec::with_capacity(report_ids.len());
+                        for report_id in report_ids {
+                            result.push(self.read_incoming_dmarc_report(report_id).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    None => {
+                        let mut result = Vec::with_capacity(report_ids.len());
+                        for report_id in report_ids {
+                            result.push(self.delete_incoming_dmarc_report(report_id).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "mta-sts", "list") => {
+                let mut has_error = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "error" => match value.as_ref() {
+                                "true" | "false" => {
+                                    has_error = (value == "true").into();
+                                }
+                                _ => {
+                                    error = format!("Invalid value {value:?} for {key:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => match self
+                        .list_mta_sts_entries(|entry| {
+                            has_error
+                                .map_or(true, |has_error| entry.last_error.is_some() == has_error)
+                        })
+                        .await
+                    {
+                        Ok(entries) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: entries
+                                    .into_iter()
+                                    .map(|entry| entry.domain)
+                                    .collect::<Vec<_>>(),
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to list MTA-STS policies: {err}"),
+                        ),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "mta-sts", action @ ("status" | "delete")) => {
+                let mut domains = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "domain" | "domains" => {
+                                domains.extend(
+                                    value
+                                        .split(',')
+                                        .map(|domain| domain.trim().to_lowercase())
+                                        .filter(|domain| !domain.is_empty()),
+                                );
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None if action == "status" => {
+                        let mut result = Vec::with_capacity(domains.len());
+                        for domain in domains {
+                            result.push(self.read_mta_sts_entry(&domain).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    None => {
+                        let mut result = Vec::with_capacity(domains.len());
+                        for domain in domains {
+                            result.push(self.delete_mta_sts_entry(&domain).await);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "health", "domain") => {
+                let mut domains = Vec::new();
+                let mut selectors = Vec::new();
+                let mut days = 7;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "domain" | "domains" => {
+                                domains.extend(
+                                    value
+                                        .split(',')
+                                        .map(|domain| domain.trim().to_lowercase())
+                                        .filter(|domain| !domain.is_empty()),
+                                );
+                            }
+                            "selector" | "selectors" => {
+                                selectors.extend(
+                                    value
+                                        .split(',')
+                                        .map(|selector| selector.trim().to_string())
+                                        .filter(|selector| !selector.is_empty()),
+                                );
+                            }
+                            "days" => match value.parse::<u64>() {
+                                Ok(value) if (1..=366).contains(&value) => {
+                                    days = value;
+                                }
+                                _ => {
+                                    error = format!("Invalid value {value:?} for {key:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+                if error.is_none() && domains.is_empty() {
+                    error = "Missing parameter \"domain\".".to_string().into();
+                }
+
+                match error {
+                    None => {
+                        let mut result = Vec::with_capacity(domains.len());
+                        let mut failure = None;
+                        for domain in domains {
+                            match self.domain_health(&domain, days, &selectors).await {
+                                Ok(health) => result.push(health),
+                                Err(err) => {
+                                    failure = err.into();
+                                    break;
+                                }
+                            }
+                        }
+
+                        match failure {
+                            None => (
+                                StatusCode::OK,
+                                serde_json::to_string(&Response { data: result })
+                                    .unwrap_or_default(),
+                            ),
+                            Some(err) => (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("Failed to obtain domain health: {err}"),
+                            ),
+                        }
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "auth", "usage") => {
+                let mut listener = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "listener" => {
+                                listener = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let mut result = self
+                            .session
+                            .auth_usage
+                            .iter()
+                            .filter(|entry| {
+                                listener
+                                    .as_ref()
+                                    .map_or(true, |listener| &entry.key().0 == listener)
+                            })
+                            .map(|entry| AuthMechanismUsage {
+                                listener: entry.key().0.clone(),
+                                mechanism: mechanism_name(entry.key().1).to_string(),
+                                success: entry.success.load(Ordering::Relaxed),
+                                failure: entry.failure.load(Ordering::Relaxed),
+                                rejected: entry.rejected.load(Ordering::Relaxed),
+                            })
+                            .collect::<Vec<_>>();
+                        result.sort_unstable_by(|a, b| {
+                            (&a.listener, &a.mechanism).cmp(&(&b.listener, &b.mechanism))
+                        });
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "risk", "unsuspend") => {
+                let mut account = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "account" => {
+                                account = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, account) {
+                    (None, Some(account)) => match self.unsuspend_sending(&account).await {
+                        Ok(was_suspended) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: was_suspended,
+                            })
+                            .unwrap_or_default(),
+                        ),
+                        Err(err) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to lift suspension: {err}"),
+                        ),
+                    },
+                    (Some(error), _) => error.into_bad_request(),
+                    (None, None) => "Missing parameter \"account\"."
+                        .to_string()
+                        .into_bad_request(),
+                }
+            }
+            (&Method::GET, "report", "list") => {
+                let mut domain = None;
+                let mut type_ = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "type" => match value.as_ref() {
+                                "dmarc" => {
+                                    type_ = 0u8.into();
+                                }
+                                "tls" => {
+                                    type_ = 1u8.into();
+                                }
+                                _ => {
+                                    error = format!("Invalid report type {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "domain" => {
+                                domain = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let mut result = Vec::new();
+                        let from_key = ValueKey::from(ValueClass::Queue(
+                            QueueClass::DmarcReportHeader(ReportEvent {
+                                due: 0,
+                                policy_hash: 0,
+                                seq_id: 0,
+                                domain: String::new(),
+                            }),
+                        ));
+                        let to_key = ValueKey::from(ValueClass::Queue(
+                            QueueClass::TlsReportHeader(ReportEvent {
+                                due: u64::MAX,
+                                policy_hash: 0,
+                                seq_id: 0,
+                                domain: String::new(),
+                            }),
+                        ));
+                        let _ = self
+                            .shared
+                            .default_data_store
+                            .iterate(
+                                IterateParams::new(from_key, to_key).ascending().no_values(),
+                                |key, _| {
+                                    if type_.map_or(true, |t| t == *key.last().unwrap()) {
+                                        let event = ReportEvent::deserialize(key)?;
+                                        if event.seq_id != 0
+                                            && domain.as_ref().map_or(true, |d| {
+                                                d.eq_ignore_ascii_case(&event.domain)
+                                            })
+                                        {
+                                            result.push(
+                                                if *key.last().unwrap() == 0 {
+                                                    QueueClass::DmarcReportHeader(event)
+                                                } else {
+                                                    QueueClass::TlsReportHeader(event)
+                                                }
+                                                .queue_id(),
+                                            );
+                                        }
+                                    }
+
+                                    Ok(true)
+                                },
+                            )
+                            .await;
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "report", "status") => {
+                let mut report_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_report_ids() {
+                                Ok(ids) => {
+                                    report_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                let mut result = Vec::with_capacity(report_ids.len());
+                for report_id in report_ids {
+                    if let Ok(Some(_)) = self
+                        .shared
+                        .default_data_store
+                        .get_value::<()>(ValueKey::from(ValueClass::Queue(report_id.clone())))
+                        .await
+                    {
+                        result.push(Report::from(report_id).into());
+                    } else {
+                        result.push(None);
+                    }
+                }
+
+                match error {
+                    None => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                    ),
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "report", "cancel") => {
+                let mut report_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_report_ids() {
+                                Ok(ids) => {
+                                    report_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let mut result = Vec::with_capacity(report_ids.len());
+
+                        for report_id in report_ids {
+                            match report_id {
+                                QueueClass::DmarcReportHeader(event) => {
+                                    self.delete_dmarc_report(event).await;
+                                }
+                                QueueClass::TlsReportHeader(event) => {
+                                    self.delete_tls_report(vec![event]).await;
+                                }
+                                _ => (),
+                            }
+
+                            result.push(true);
+                        }
+
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "policy", "simulate") => {
+                let mut request = SimulationRequest {
+                    remote_ip: IpAddr::from([127, 0, 0, 1]),
+                    local_ip: IpAddr::from([127, 0, 0, 1]),
+                    listener: String::new(),
+                    helo_domain: String::new(),
+                    authenticated_as: String::new(),
+                    mail_from: None,
+                    rcpt_to: Vec::new(),
+                };
+                let mut has_remote_ip = false;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "ip" | "remote_ip" => match value.parse() {
+                                Ok(ip) => {
+                                    request.remote_ip = ip;
+                                    has_remote_ip = true;
+                                }
+                                Err(_) => {
+                                    error = format!("Invalid IP address {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "local_ip" => match value.parse() {
+                                Ok(ip) => {
+                                    request.local_ip = ip;
+                                }
+                                Err(_) => {
+                                    error = format!("Invalid IP address {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "listener" => {
+                                request.listener = value.into_owned();
+                            }
+                            "helo" | "ehlo" => {
+                                request.helo_domain = value.into_owned();
+                            }
+                            "auth" => {
+                                request.authenticated_as = value.into_owned();
+                            }
+                            "from" => {
+                                request.mail_from = value.into_owned().into();
+                            }
+                            "to" => {
+                                request.rcpt_to.extend(
+                                    value
+                                        .split(',')
+                                        .filter(|rcpt| !rcpt.is_empty())
+                                        .map(|rcpt| rcpt.to_string()),
+                                );
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                if error.is_none() && !has_remote_ip {
+                    error = "Missing parameter \"ip\".".to_string().into();
+                }
+
+                match error {
+                    None => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: self.simulate_session(request).await,
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "transcript", "status") => {
+                let transcripts = &self.shared.transcripts;
+                let filter = transcripts.filter.read().clone();
+
+                (
+                    StatusCode::OK,
+                    serde_json::to_string(&Response {
+                        data: TranscriptStatus {
+                            enable: filter.enable,
+                            path: transcripts
+                                .path
+                                .as_ref()
+                                .map(|path| path.display().to_string()),
+                            listeners: filter.listeners,
+                            remote_ips: filter.remote_ips,
+                        },
+                    })
+                    .unwrap_or_default(),
+                )
+            }
+            (&Method::GET, "transcript", action @ ("enable" | "disable")) => {
+                let filter = self.shared.transcripts.filter.read().clone();
+                let mut listeners = Vec::new();
+                let mut remote_ips = Vec::new();
+                let mut has_filter = false;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "listener" => {
+                                listeners.push(value.into_owned());
+                                has_filter = true;
+                            }
+                            "ip" | "remote_ip" => {
+                                remote_ips.push(value.into_owned());
+                                has_filter = true;
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                // Keep the current filter when none is provided
+                if !has_filter {
+                    listeners = filter.listeners;
+                    remote_ips = filter.remote_ips;
+                }
+
+                match error {
+                    None => match self.shared.transcripts.set_filter(
+                        action == "enable",
+                        listeners,
+                        remote_ips,
+                    ) {
+                        Ok(_) => (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: true }).unwrap_or_default(),
+                        ),
+                        Err(error) => error.into_bad_request(),
+                    },
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "transcript", "list") => match self.shared.transcripts.list() {
+                Ok(files) => (
+                    StatusCode::OK,
+                    serde_json::to_string(&Response {
+                        data: files
+                            .into_iter()
+                            .map(|(name, size, modified)| TranscriptFile {
+                                name,
+                                size,
+                                modified,
+                            })
+                            .collect::<Vec<_>>(),
+                    })
+                    .unwrap_or_default(),
+                ),
+                Err(err) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to list transcripts: {err}"),
+                ),
+            },
+            (&Method::GET, "transcript", "get") => {
+                let name = uri.query().and_then(|query| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "name")
+                        .map(|(_, value)| value.into_owned())
+                });
+
+                match name
+                    .as_ref()
+                    .and_then(|name| self.shared.transcripts.read(name))
+                {
+                    Some(Ok(contents)) => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: String::from_utf8_lossy(&contents),
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    Some(Err(err)) => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read transcript: {err}"),
+                    ),
+                    None if name.is_some() => (
+                        StatusCode::NOT_FOUND,
+                        format!(
+                            "{{\"error\": \"not-found\", \"details\": \"Transcript {} does not exist.\"}}",
+                            name.unwrap_or_default()
+                        ),
+                    ),
+                    None => "Missing parameter \"name\".".to_string().into_bad_request(),
+                }
+            }
+            _ => (
+                StatusCode::NOT_FOUND,
+                format!(
+                    "{{\"error\": \"not-found\", \"details\": \"URL {} does not exist.\"}}",
+                    uri.path()
+                ),
+            ),
+        };
+
+        hyper::Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(
+                Full::new(Bytes::from(response))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+impl SMTP {
+    /// Handles the signed release and delete links included in quarantine
+    /// digests. These requests are not authenticated, the link signature
+    /// identifies the reviewer. Opening a link only asks for confirmation,
+    /// so that link scanners and prefetchers do not act on the message.
+    pub async fn handle_quarantine_link(
+        self: &Arc<Self>,
+        method: &Method,
+        uri: &Uri,
+        action: &str,
+    ) -> hyper::Response<BoxBody<Bytes, hyper::Error>> {
+        let mut queue_id = None;
+        let mut reviewer = None;
+        let mut expires = None;
+        let mut signature = None;
+
+        if let Some(query) = uri.query() {
+            for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                match key.as_ref() {
+                    "id" => {
+                        queue_id = value.parse::<QueueId>().ok();
+                    }
+                    "reviewer" => {
+                        reviewer = value.into_owned().into();
+                    }
+                    "expires" => {
+                        expires = value.parse::<u64>().ok();
+                    }
+                    "sig" => {
+                        signature = value.into_owned().into();
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let mut content_type = "text/plain; charset=utf-8";
+        let (status, response) = match (
+            LinkAction::parse(action),
+            queue_id,
+            reviewer,
+            expires,
+            signature,
+        ) {
+            (Some(action), Some(queue_id), Some(reviewer), Some(expires), Some(signature))
+                if self.queue.config.quarantine.verify_link(
+                    action,
+                    queue_id,
+                    &reviewer,
+                    expires,
+                    &signature,
+                    now(),
+                ) =>
+            {
+                // Reviewers may only act on the messages assigned to them
+                if self
+                    .read_quarantined(queue_id)
+                    .await
+                    .map_or(false, |entry| {
+                        !self
+                            .queue
+                            .config
+                            .quarantine
+                            .can_review(&reviewer, entry.source)
+                    })
+                {
+                    (
+                        StatusCode::FORBIDDEN,
+                        "Invalid or expired link.".to_string(),
+                    )
+                } else if method == Method::GET {
+                    content_type = "text/html; charset=utf-8";
+                    let verb = match action {
+                        LinkAction::Release => "Release",
+                        LinkAction::Discard => "Delete",
+                    };
+                    (
+                        StatusCode::OK,
+                        format!(
+                            concat!(
+                                "<!DOCTYPE html><html><head><title>{verb} message</title></head>",
+                                "<body><form method=\"post\" action=\"{target}\">",
+                                "<p>{verb} quarantined message {queue_id}?</p>",
+                                "<button type=\"submit\">{verb}</button></form></body></html>"
+                            ),
+                            verb = verb,
+                            target = html_escape(
+                                uri.path_and_query()
+                                    .map_or(uri.path(), |path| path.as_str())
+                            ),
+                            queue_id = queue_id,
+                        ),
+                    )
+                } else if method != Method::POST {
+                    (
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "Method not allowed.".to_string(),
+                    )
+                } else {
+                    let actor = format!("link:{reviewer}");
+                    let (success, verb) = match action {
+                        LinkAction::Release => {
+                            (self.release_quarantined(queue_id, &actor).await, "released")
+                        }
+                        LinkAction::Discard => {
+                            (self.discard_quarantined(queue_id, &actor).await, "deleted")
+                        }
+                    };
+                    if success {
+                        (
+                            StatusCode::OK,
+                            format!("Message {queue_id} has been {verb}."),
+                        )
+                    } else {
+                        (
+                            StatusCode::NOT_FOUND,
+                            format!("Message {queue_id} is no longer in quarantine."),
+                        )
+                    }
+                }
+            }
+            _ => (
+                StatusCode::FORBIDDEN,
+                "Invalid or expired link.".to_string(),
+            ),
+        };
+
+        hyper::Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(
+                Full::new(Bytes::from(response))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+impl From<&queue::Message> for Message {
+    fn from(message: &queue::Message) -> Self {
+        let now = now();
+
+        Message {
+            return_path: message.return_path.clone(),
+            created: DateTime::from_timestamp(message.created as i64),
+            size: message.size,
+            priority: message.priority,
+            env_id: message.env_id.clone(),
+            domains: message
                 .domains
                 .iter()
                 .enumerate()
@@ -852,6 +2291,7 @@ impl From<&queue::Message> for Message {
                         }
                     },
                     retry_num: domain.retry.inner,
+                    tls_trace: domain.tls_trace.clone(),
                     next_retry: if domain.retry.due > now {
                         DateTime::from_timestamp(domain.retry.due as i64).into()
                     } else {
@@ -934,6 +2374,26 @@ trait ParseValues {
     fn parse_timestamp(&self) -> Result<u64, String>;
     fn parse_queue_ids(&self) -> Result<Vec<QueueId>, String>;
     fn parse_report_ids(&self) -> Result<Vec<QueueClass>, String>;
+    fn parse_queue_status(&self) -> Result<QueueStatus, String>;
+}
+
+enum QueueStatus {
+    Scheduled,
+    TemporaryFailure,
+    PermanentFailure,
+    Completed,
+}
+
+impl QueueStatus {
+    fn matches(&self, status: &Status<(), queue::Error>) -> bool {
+        matches!(
+            (self, status),
+            (QueueStatus::Scheduled, Status::Scheduled)
+                | (QueueStatus::TemporaryFailure, Status::TemporaryFailure(_))
+                | (QueueStatus::PermanentFailure, Status::PermanentFailure(_))
+                | (QueueStatus::Completed, Status::Completed(_))
+        )
+    }
 }
 
 impl ParseValues for Cow<'_, str> {
@@ -1005,6 +2465,36 @@ impl ParseValues for Cow<'_, str> {
         }
         Ok(ids)
     }
+
+    fn parse_queue_status(&self) -> Result<QueueStatus, String> {
+        match self.as_ref() {
+            "scheduled" => Ok(QueueStatus::Scheduled),
+            "temporary-failure" => Ok(QueueStatus::TemporaryFailure),
+            "permanent-failure" => Ok(QueueStatus::PermanentFailure),
+            "completed" => Ok(QueueStatus::Completed),
+            _ => Err(format!("Invalid status {self:?}.")),
+        }
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn is_domain_or_subdomain(name: &str, domain: &str) -> bool {
+    name.strip_suffix(domain)
+        .map_or(false, |prefix| prefix.is_empty() || prefix.ends_with('.'))
 }
 
 trait BadRequest {
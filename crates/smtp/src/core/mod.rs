@@ -23,15 +23,16 @@
 
 use std::{
     hash::Hash,
-    net::IpAddr,
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{atomic::AtomicU64, Arc},
     time::{Duration, Instant},
 };
 
 use ahash::AHashMap;
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
 use directory::Directory;
-use mail_auth::{common::lru::LruCache, IprevOutput, Resolver, SpfOutput};
+use mail_auth::{common::lru::LruCache, IprevOutput, Resolver, SpfOutput, MX};
 use sieve::{runtime::Variable, Runtime, Sieve};
 use smtp_proto::{
     request::receiver::{
@@ -48,35 +49,44 @@ use tokio::{
 use tokio_rustls::TlsConnector;
 use tracing::Span;
 use utils::{
+    config::Rate,
     expr,
     ipc::DeliveryEvent,
     listener::{
         limiter::{ConcurrencyLimiter, InFlight},
         stream::NullIo,
+        transcript::TranscriptManager,
         ServerInstance, TcpAcceptor,
     },
     snowflake::SnowflakeIdGenerator,
+    worker::WorkerPool,
 };
 
 use crate::{
     config::{
-        scripts::SieveContext, ArcSealer, DkimSigner, MailAuthConfig, QueueConfig, RelayHost,
-        ReportConfig, SessionConfig, VerifyStrategy,
+        scripts::SieveContext, ArcSealer, DkimSigner, Footer, Journal, MailAuthConfig, PipeCommand,
+        QueueConfig, RelayHost, ReportConfig, SessionConfig, VerifyStrategy,
     },
-    inbound::auth::SaslToken,
+    inbound::{auth::SaslToken, dnsbl::DnsblHit, monitor::MonitorVerdict, spool::MessageSpool},
     outbound::{
         dane::{DnssecResolver, Tlsa},
         mta_sts,
+        pool::ConnectionPool,
+        reputation::IpReputation,
     },
-    queue::{self, DomainPart, QueueId},
+    queue::{self, index::QueueIndex, DomainPart, QueueId},
     reporting,
 };
 
 use self::throttle::{ThrottleKey, ThrottleKeyHasherBuilder};
 
 pub mod eval;
+pub mod http;
 pub mod management;
 pub mod params;
+pub mod reputation;
+pub mod resolver;
+pub mod simulate;
 pub mod throttle;
 pub mod worker;
 
@@ -103,7 +113,7 @@ impl SmtpAdminSessionManager {
 }
 
 pub struct SMTP {
-    pub worker_pool: rayon::ThreadPool,
+    pub worker_pool: WorkerPool,
     pub session: SessionCore,
     pub queue: QueueCore,
     pub resolvers: Resolvers,
@@ -122,6 +132,11 @@ pub struct Shared {
     pub directories: AHashMap<String, Arc<Directory>>,
     pub lookup_stores: AHashMap<String, LookupStore>,
     pub relay_hosts: AHashMap<String, RelayHost>,
+    pub pipes: AHashMap<String, PipeCommand>,
+    pub footers: AHashMap<String, Footer>,
+    pub journals: AHashMap<String, Journal>,
+    pub http: http::HttpClient,
+    pub transcripts: Arc<TranscriptManager>,
 
     // Default store and directory
     pub default_directory: Arc<Directory>,
@@ -135,13 +150,26 @@ pub struct SieveCore {
     pub from_addr: String,
     pub from_name: String,
     pub return_path: String,
+    pub hostname: String,
     pub sign: Vec<Arc<DkimSigner>>,
+    pub seal: Option<Arc<ArcSealer>>,
 }
 
 pub struct Resolvers {
     pub dns: Resolver,
     pub dnssec: DnssecResolver,
     pub cache: DnsCache,
+    pub overrides: DnsOverrides,
+}
+
+/// Static DNS answers that take precedence over the configured resolver.
+#[derive(Default)]
+pub struct DnsOverrides {
+    pub mx: AHashMap<String, Arc<Vec<MX>>>,
+    pub ipv4: AHashMap<String, Arc<Vec<Ipv4Addr>>>,
+    pub ipv6: AHashMap<String, Arc<Vec<Ipv6Addr>>>,
+    pub txt: AHashMap<String, Vec<u8>>,
+    pub tlsa: AHashMap<String, Arc<Tlsa>>,
 }
 
 pub struct DnsCache {
@@ -152,6 +180,15 @@ pub struct DnsCache {
 pub struct SessionCore {
     pub config: SessionConfig,
     pub throttle: DashMap<ThrottleKey, ConcurrencyLimiter, ThrottleKeyHasherBuilder>,
+    pub auth_usage: DashMap<(String, u64), AuthUsage>,
+    pub rcpt_cache: LruCache<String, ()>,
+}
+
+#[derive(Default)]
+pub struct AuthUsage {
+    pub success: AtomicU64,
+    pub failure: AtomicU64,
+    pub rejected: AtomicU64,
 }
 
 pub struct QueueCore {
@@ -160,10 +197,13 @@ pub struct QueueCore {
     pub tx: mpsc::Sender<queue::Event>,
     pub snowflake_id: SnowflakeIdGenerator,
     pub connectors: TlsConnectors,
+    pub reputation: IpReputation,
+    pub pool: ConnectionPool,
+    pub index: QueueIndex,
 }
 
 pub struct ReportCore {
-    pub config: ReportConfig,
+    pub config: ArcSwap<ReportConfig>,
     pub tx: mpsc::Sender<reporting::Event>,
 }
 
@@ -205,9 +245,14 @@ pub struct SessionData {
     pub mail_from: Option<SessionAddress>,
     pub rcpt_to: Vec<SessionAddress>,
     pub rcpt_errors: usize,
+    pub rcpt_unknown: usize,
+    pub rcpt_honeypot: usize,
+    pub enumeration_limited: bool,
     pub message: Vec<u8>,
+    pub spool: Option<MessageSpool>,
 
     pub authenticated_as: String,
+    pub authenticated_id: Option<u32>,
     pub authenticated_emails: Vec<String>,
     pub auth_errors: usize,
 
@@ -223,6 +268,8 @@ pub struct SessionData {
     pub spf_ehlo: Option<SpfOutput>,
     pub spf_mail_from: Option<SpfOutput>,
     pub dnsbl_error: Option<Vec<u8>>,
+    pub dnsbl_hits: Vec<DnsblHit>,
+    pub monitor_verdicts: Vec<MonitorVerdict>,
 }
 
 #[derive(Clone)]
@@ -248,16 +295,20 @@ pub struct SessionParameters {
     pub auth_require: bool,
     pub auth_errors_max: usize,
     pub auth_errors_wait: Duration,
-    pub auth_plain_text: bool,
+    pub auth_require_tls: u64,
     pub auth_match_sender: bool,
 
     // Rcpt parameters
     pub rcpt_errors_max: usize,
     pub rcpt_errors_wait: Duration,
+    pub rcpt_tarpit_after: usize,
+    pub rcpt_tarpit_max_delay: Duration,
     pub rcpt_max: usize,
     pub rcpt_dsn: bool,
     pub can_expn: bool,
     pub can_vrfy: bool,
+    pub enumeration_rate: Option<Rate>,
+    pub enumeration_honey: bool,
     pub max_message_size: usize,
 
     // Mail authentication parameters
@@ -278,11 +329,16 @@ impl SessionData {
             mail_from: None,
             rcpt_to: Vec::new(),
             authenticated_as: String::new(),
+            authenticated_id: None,
             authenticated_emails: Vec::new(),
             priority: 0,
             valid_until: Instant::now(),
             rcpt_errors: 0,
+            rcpt_unknown: 0,
+            rcpt_honeypot: 0,
+            enumeration_limited: false,
             message: Vec::with_capacity(0),
+            spool: None,
             auth_errors: 0,
             messages_sent: 0,
             bytes_left: 0,
@@ -292,6 +348,8 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            dnsbl_hits: Vec::new(),
+            monitor_verdicts: Vec::new(),
         }
     }
 }
@@ -388,6 +446,7 @@ static ref SIEVE: Arc<ServerInstance> = Arc::new(utils::listener::ServerInstance
     limiter: utils::listener::limiter::ConcurrencyLimiter::new(0),
     shutdown_rx: tokio::sync::watch::channel(false).1,
     proxy_networks: vec![],
+    transcripts: Default::default(),
 });
 }
 
@@ -427,9 +486,11 @@ impl Session<NullIo> {
                 auth_require: Default::default(),
                 auth_errors_max: Default::default(),
                 auth_errors_wait: Default::default(),
-                auth_plain_text: false,
+                auth_require_tls: 0,
                 rcpt_errors_max: Default::default(),
                 rcpt_errors_wait: Default::default(),
+                rcpt_tarpit_after: Default::default(),
+                rcpt_tarpit_max_delay: Default::default(),
                 rcpt_max: Default::default(),
                 rcpt_dsn: Default::default(),
                 max_message_size: Default::default(),
@@ -439,6 +500,8 @@ impl Session<NullIo> {
                 spf_mail_from: crate::config::VerifyStrategy::Disable,
                 can_expn: false,
                 can_vrfy: false,
+                enumeration_rate: None,
+                enumeration_honey: false,
             },
             in_flight: vec![],
         }
@@ -489,8 +552,13 @@ impl SessionData {
             mail_from,
             rcpt_to,
             rcpt_errors: 0,
+            rcpt_unknown: 0,
+            rcpt_honeypot: 0,
+            enumeration_limited: false,
             message,
+            spool: None,
             authenticated_as: "local".into(),
+            authenticated_id: None,
             authenticated_emails: vec![],
             auth_errors: 0,
             priority: 0,
@@ -503,6 +571,8 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            dnsbl_hits: Vec::new(),
+            monitor_verdicts: Vec::new(),
         }
     }
 }
@@ -23,9 +23,10 @@
 
 use std::time::Duration;
 
+use smtp_proto::{AUTH_LOGIN, AUTH_PLAIN};
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::config::VerifyStrategy;
+use crate::config::{session::Mechanism, VerifyStrategy};
 
 use super::Session;
 
@@ -88,11 +89,20 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&ac.errors_wait, self)
             .await
             .unwrap_or_else(|| Duration::from_secs(30));
-        self.params.auth_plain_text = self
+        self.params.auth_require_tls = if !self
             .core
             .eval_if(&ac.allow_plain_text, self)
             .await
-            .unwrap_or(false);
+            .unwrap_or(false)
+        {
+            self.core
+                .eval_if::<Mechanism, _>(&ac.require_tls, self)
+                .await
+                .map(u64::from)
+                .unwrap_or(AUTH_PLAIN | AUTH_LOGIN)
+        } else {
+            0
+        };
         self.params.auth_match_sender = self
             .core
             .eval_if(&ac.must_match_sender, self)
@@ -103,6 +113,12 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         let ec = &self.core.session.config.extensions;
         self.params.can_expn = self.core.eval_if(&ec.expn, self).await.unwrap_or(false);
         self.params.can_vrfy = self.core.eval_if(&ec.vrfy, self).await.unwrap_or(false);
+        self.params.enumeration_rate = self.core.eval_if(&ec.enumeration.rate, self).await;
+        self.params.enumeration_honey = self
+            .core
+            .eval_if(&ec.enumeration.honey, self)
+            .await
+            .unwrap_or(false);
     }
 
     pub async fn eval_post_auth_params(&mut self) {
@@ -110,6 +126,12 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         let ec = &self.core.session.config.extensions;
         self.params.can_expn = self.core.eval_if(&ec.expn, self).await.unwrap_or(false);
         self.params.can_vrfy = self.core.eval_if(&ec.vrfy, self).await.unwrap_or(false);
+        self.params.enumeration_rate = self.core.eval_if(&ec.enumeration.rate, self).await;
+        self.params.enumeration_honey = self
+            .core
+            .eval_if(&ec.enumeration.honey, self)
+            .await
+            .unwrap_or(false);
     }
 
     pub async fn eval_rcpt_params(&mut self) {
@@ -120,6 +142,13 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             .eval_if(&rc.errors_wait, self)
             .await
             .unwrap_or_else(|| Duration::from_secs(30));
+        self.params.rcpt_tarpit_after =
+            self.core.eval_if(&rc.tarpit_after, self).await.unwrap_or(0);
+        self.params.rcpt_tarpit_max_delay = self
+            .core
+            .eval_if(&rc.tarpit_max_delay, self)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(60));
         self.params.rcpt_max = self
             .core
             .eval_if(&rc.max_recipients, self)
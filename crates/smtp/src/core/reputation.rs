@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use sieve::runtime::Variable;
+
+use crate::scripts::plugins::lookup::VariableWrapper;
+
+use super::SMTP;
+
+const REPUTATION_EXPIRY: Duration = Duration::from_secs(30 * 86400);
+
+impl SMTP {
+    /// Updates a reputation token using the same format and formula as the
+    /// spam filter's reputation script.
+    pub async fn update_reputation(&self, token: String, score: f64) {
+        let key = token.into_bytes();
+        let store = &self.shared.default_lookup_store;
+        let (score, count) = match store.key_get::<VariableWrapper>(key.clone()).await {
+            Ok(Some(value)) => match value.into_inner() {
+                Variable::Array(items) if items.len() == 2 => {
+                    let token_score = match &items[0] {
+                        Variable::Float(score) => *score,
+                        Variable::Integer(score) => *score as f64,
+                        _ => 0.0,
+                    };
+                    let token_count = items[1].to_integer();
+                    (
+                        (token_count + 1) as f64 * (score + 0.98 * token_score)
+                            / (0.98 * token_count as f64 + 1.0),
+                        token_count + 1,
+                    )
+                }
+                _ => (score, 1),
+            },
+            Ok(None) => (score, 1),
+            Err(err) => {
+                tracing::warn!(
+                    context = "reputation",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to obtain reputation.");
+                return;
+            }
+        };
+
+        let value = Variable::from(vec![Variable::Float(score), Variable::Integer(count)]);
+        if let Err(err) = store
+            .key_set(
+                key,
+                bincode::serialize(&value).unwrap_or_default(),
+                Some(REPUTATION_EXPIRY.as_secs()),
+            )
+            .await
+        {
+            tracing::warn!(
+                context = "reputation",
+                event = "error",
+                reason = ?err,
+                "Failed to update reputation.");
+        }
+    }
+}
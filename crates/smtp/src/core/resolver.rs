@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use mail_auth::{
+    common::{
+        parse::TxtRecordParser,
+        resolver::{IntoFqdn, UnwrapTxtRecord},
+    },
+    Txt, MX,
+};
+
+use super::Resolvers;
+
+impl Resolvers {
+    pub async fn mx_lookup<'x>(&self, key: impl IntoFqdn<'x>) -> mail_auth::Result<Arc<Vec<MX>>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.overrides.mx.get(key.to_lowercase().as_str()) {
+            Ok(value.clone())
+        } else {
+            self.dns.mx_lookup(key.as_ref()).await
+        }
+    }
+
+    pub async fn ipv4_lookup<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+    ) -> mail_auth::Result<Arc<Vec<Ipv4Addr>>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.overrides.ipv4.get(key.to_lowercase().as_str()) {
+            Ok(value.clone())
+        } else {
+            self.dns.ipv4_lookup(key.as_ref()).await
+        }
+    }
+
+    pub async fn ipv6_lookup<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+    ) -> mail_auth::Result<Arc<Vec<Ipv6Addr>>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.overrides.ipv6.get(key.to_lowercase().as_str()) {
+            Ok(value.clone())
+        } else {
+            self.dns.ipv6_lookup(key.as_ref()).await
+        }
+    }
+
+    pub async fn txt_lookup<'x, T: TxtRecordParser + Into<Txt> + UnwrapTxtRecord>(
+        &self,
+        key: impl IntoFqdn<'x>,
+    ) -> mail_auth::Result<Arc<T>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.overrides.txt.get(key.to_lowercase().as_str()) {
+            T::parse(value).map(Arc::new)
+        } else {
+            self.dns.txt_lookup::<T>(key.as_ref()).await
+        }
+    }
+
+    pub async fn txt_raw_lookup<'x>(&self, key: impl IntoFqdn<'x>) -> mail_auth::Result<Vec<u8>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.overrides.txt.get(key.to_lowercase().as_str()) {
+            Ok(value.clone())
+        } else {
+            self.dns.txt_raw_lookup(key.as_ref()).await
+        }
+    }
+}
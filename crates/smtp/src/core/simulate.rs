@@ -0,0 +1,820 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Duration};
+
+use directory::QueryBy;
+use mail_auth::spf::verify::HasLabels;
+use store::write::now;
+use utils::config::if_block::IfBlock;
+
+use crate::{
+    config::{
+        Throttle, VerifyStrategy, THROTTLE_AUTH_AS, THROTTLE_HELO_DOMAIN, THROTTLE_LISTENER,
+        THROTTLE_LOCAL_IP, THROTTLE_MX, THROTTLE_RCPT, THROTTLE_RCPT_DOMAIN, THROTTLE_REMOTE_IP,
+        THROTTLE_SENDER, THROTTLE_SENDER_DOMAIN,
+    },
+    inbound::response::ResponseKind,
+    queue::{
+        dsn::{batv_verify, BatvResult},
+        DomainPart,
+    },
+};
+
+use super::{eval::*, ResolveVariable, SessionAddress, SessionData, SMTP};
+
+#[derive(Debug, Clone)]
+pub struct SimulationRequest {
+    pub remote_ip: IpAddr,
+    pub local_ip: IpAddr,
+    pub listener: String,
+    pub helo_domain: String,
+    pub authenticated_as: String,
+    pub mail_from: Option<String>,
+    pub rcpt_to: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Simulation {
+    pub accepted: bool,
+    pub stages: Vec<SimulatedStage>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SimulatedStage {
+    pub stage: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub address: Option<String>,
+    pub rules: Vec<SimulatedRule>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub throttles: Vec<SimulatedThrottle>,
+    pub response: String,
+    pub accepted: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct SimulatedRule {
+    pub property: String,
+    pub value: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct SimulatedThrottle {
+    pub keys: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub concurrency: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub rate: Option<String>,
+    pub allowed: bool,
+}
+
+struct SimulatedSession {
+    listener: String,
+    data: SessionData,
+}
+
+impl SMTP {
+    /// Predicts how an SMTP session with the given envelope would be handled
+    /// at each stage without accepting any mail. Sieve scripts, milters and
+    /// SPF/iprev lookups are reported but not executed, and throttles and
+    /// greylisting triplets are checked without consuming or recording them.
+    /// Honeypot hits and unknown recipients are reported but not recorded, and
+    /// tarpit delays are reported instead of being applied. Custom responses
+    /// use the Sieve hostname and keep their tracking ID placeholder.
+    /// As no SPF checks are run, the greylisting SPF whitelist never applies.
+    pub async fn simulate_session(&self, request: SimulationRequest) -> Simulation {
+        let config = &self.session.config;
+        let throttle = config.throttle.load_full();
+        let mut simulation = Simulation::default();
+        let mut session = SimulatedSession {
+            listener: request.listener,
+            data: SessionData::new(request.local_ip, request.remote_ip, 0),
+        };
+
+        // Connect stage
+        let mut stage = SimulatedStage::new("connect");
+        if self
+            .shared
+            .default_directory
+            .blocked_ips
+            .is_blocked(&request.remote_ip)
+        {
+            stage.rule("blocked_ip", true);
+            simulation.push(stage, "Connection refused, IP address is blocked.", false);
+            return simulation;
+        }
+        stage.rule(&config.timeout.key, {
+            let timeout = self
+                .eval_if(&config.timeout, &session)
+                .await
+                .unwrap_or_else(|| Duration::from_secs(5 * 60));
+            format!("{timeout:?}")
+        });
+        stage.rule(
+            &config.transfer_limit.key,
+            self.eval_if(&config.transfer_limit, &session)
+                .await
+                .unwrap_or(250 * 1024 * 1024usize),
+        );
+        self.simulate_script(&config.connect.script, &session, &mut stage)
+            .await;
+        if !self
+            .simulate_throttles(&throttle.connect, &session, &mut stage)
+            .await
+        {
+            simulation.push(stage, "Connection dropped, throttle limit exceeded.", false);
+            return simulation;
+        }
+        simulation.push(stage, "220 Service ready.", true);
+
+        // EHLO stage
+        let mut stage = SimulatedStage::new("ehlo");
+        let ehlo_require = self
+            .eval_if(&config.ehlo.require, &session)
+            .await
+            .unwrap_or(true);
+        let ehlo_reject_non_fqdn = self
+            .eval_if(&config.ehlo.reject_non_fqdn, &session)
+            .await
+            .unwrap_or(true);
+        let spf_ehlo = self
+            .eval_if(&self.mail_auth.spf.verify_ehlo, &session)
+            .await
+            .unwrap_or(VerifyStrategy::Relaxed);
+        let spf_mail_from = self
+            .eval_if(&self.mail_auth.spf.verify_mail_from, &session)
+            .await
+            .unwrap_or(VerifyStrategy::Relaxed);
+        stage.rule(&config.ehlo.require.key, ehlo_require);
+        stage.rule(&config.ehlo.reject_non_fqdn.key, ehlo_reject_non_fqdn);
+        stage.rule(
+            &self.mail_auth.spf.verify_ehlo.key,
+            format!("{spf_ehlo:?}").to_lowercase(),
+        );
+        if request.helo_domain.is_empty() {
+            if ehlo_require || spf_ehlo.verify() || spf_mail_from.verify() {
+                simulation.push(stage, "503 5.5.1 Polite people say EHLO first.", false);
+                return simulation;
+            }
+            simulation.push(stage, "EHLO skipped.", true);
+        } else {
+            if ehlo_reject_non_fqdn && !request.helo_domain.as_str().has_labels() {
+                simulation.push(stage, "550 5.5.0 Invalid EHLO domain.", false);
+                return simulation;
+            }
+            session.data.helo_domain = request.helo_domain;
+            self.simulate_script(&config.ehlo.script, &session, &mut stage)
+                .await;
+            simulation.push(stage, "250 OK", true);
+        }
+
+        // Authentication
+        if !request.authenticated_as.is_empty() {
+            let mut stage = SimulatedStage::new("auth");
+            match self
+                .shared
+                .default_directory
+                .query(QueryBy::Name(&request.authenticated_as), false)
+                .await
+            {
+                Ok(Some(principal)) => {
+                    session.data.authenticated_emails = principal.emails;
+                    session.data.authenticated_as = principal.name;
+                    simulation.push(stage, "235 2.7.0 Authentication succeeded.", true);
+                }
+                Ok(None) => {
+                    simulation.push(
+                        stage,
+                        "535 5.7.8 Authentication credentials invalid.",
+                        false,
+                    );
+                    return simulation;
+                }
+                Err(_) => {
+                    simulation.push(stage, "454 4.7.0 Temporary authentication failure.", false);
+                    return simulation;
+                }
+            }
+        }
+
+        // MAIL FROM stage
+        let mail_from = if let Some(mail_from) = request.mail_from {
+            mail_from
+        } else {
+            return simulation;
+        };
+        let mut stage = SimulatedStage::new("mail");
+        stage.address = mail_from.clone().into();
+        let auth_require = self
+            .eval_if(&config.auth.require, &session)
+            .await
+            .unwrap_or(false);
+        let auth_match_sender = self
+            .eval_if(&config.auth.must_match_sender, &session)
+            .await
+            .unwrap_or(true);
+        stage.rule(&config.auth.require.key, auth_require);
+        stage.rule(&config.auth.must_match_sender.key, auth_match_sender);
+        stage.rule(
+            &self.mail_auth.spf.verify_mail_from.key,
+            format!("{spf_mail_from:?}").to_lowercase(),
+        );
+        stage.rule(&self.mail_auth.iprev.verify.key, {
+            let iprev = self
+                .eval_if(&self.mail_auth.iprev.verify, &session)
+                .await
+                .unwrap_or(VerifyStrategy::Relaxed);
+            format!("{iprev:?}").to_lowercase()
+        });
+        if auth_require && session.data.authenticated_as.is_empty() {
+            simulation.push(stage, "503 5.5.1 You must authenticate first.", false);
+            return simulation;
+        }
+        let address_lcase = mail_from.to_lowercase();
+        if !session.data.authenticated_as.is_empty()
+            && auth_match_sender
+            && (session.data.authenticated_as != address_lcase
+                && !session.data.authenticated_emails.contains(&address_lcase))
+        {
+            simulation.push(
+                stage,
+                "501 5.5.4 You are not allowed to send from this address.",
+                false,
+            );
+            return simulation;
+        }
+        session.data.mail_from = SessionAddress::simulated(mail_from).into();
+        self.simulate_script(&config.mail.script, &session, &mut stage)
+            .await;
+        if let Some(new_address) = self
+            .eval_if::<String, _>(&config.mail.rewrite, &session)
+            .await
+        {
+            stage.rule(&config.mail.rewrite.key, &new_address);
+            if new_address.contains('@') || new_address.is_empty() {
+                session.data.mail_from = SessionAddress::simulated(new_address).into();
+            }
+        }
+        if !self
+            .simulate_throttles(&throttle.mail_from, &session, &mut stage)
+            .await
+        {
+            let response = self
+                .simulate_response(
+                    ResponseKind::RateLimited,
+                    "451 4.4.5 Rate limit exceeded, try again later.",
+                    &session,
+                )
+                .await;
+            simulation.push(stage, response, false);
+            return simulation;
+        }
+        simulation.push(stage, "250 2.1.0 OK", true);
+
+        // RCPT TO stage
+        let rcpt_max = self
+            .eval_if(&config.rcpt.max_recipients, &session)
+            .await
+            .unwrap_or(100usize);
+        let rcpt_errors_max = self
+            .eval_if(&config.rcpt.errors_max, &session)
+            .await
+            .unwrap_or(10usize);
+        let rcpt_errors_wait = self
+            .eval_if(&config.rcpt.errors_wait, &session)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(30));
+        let rcpt_tarpit_after = self
+            .eval_if(&config.rcpt.tarpit_after, &session)
+            .await
+            .unwrap_or(0usize);
+        let rcpt_tarpit_max_delay = self
+            .eval_if(&config.rcpt.tarpit_max_delay, &session)
+            .await
+            .unwrap_or_else(|| Duration::from_secs(60));
+        let mut rcpt_errors = 0;
+        let mut rcpt_unknown = 0;
+        for rcpt in request.rcpt_to {
+            let mut stage = SimulatedStage::new("rcpt");
+            stage.address = rcpt.clone().into();
+            stage.rule(&config.rcpt.max_recipients.key, rcpt_max);
+            if session.data.rcpt_to.len() >= rcpt_max {
+                simulation.push(stage, "451 4.5.3 Too many recipients.", false);
+                continue;
+            }
+
+            // Verify bounce address tag
+            let is_bounce = session
+                .data
+                .mail_from
+                .as_ref()
+                .map_or(false, |mail_from| mail_from.address.is_empty());
+            let batv = &self.queue.config.dsn.batv;
+            let mut rcpt = rcpt;
+            let mut has_batv = false;
+            if !batv.keys.is_empty() {
+                match batv_verify(&batv.keys, &rcpt) {
+                    BatvResult::Valid(original) => {
+                        stage.rule("batv", "valid");
+                        rcpt = original;
+                        has_batv = true;
+                    }
+                    BatvResult::Invalid => {
+                        stage.rule("batv", "invalid");
+                        if is_bounce {
+                            if simulation.push_rcpt_error(
+                                stage,
+                                "550 5.7.1 Invalid bounce address tag.",
+                                &mut rcpt_errors,
+                                rcpt_errors_max,
+                            ) {
+                                return simulation;
+                            }
+                            continue;
+                        }
+                    }
+                    BatvResult::Unsigned => {
+                        stage.rule("batv", "unsigned");
+                    }
+                }
+            }
+
+            session.data.rcpt_to.push(SessionAddress::simulated(rcpt));
+            self.simulate_script(&config.rcpt.script, &session, &mut stage)
+                .await;
+            if let Some(new_address) = self
+                .eval_if::<String, _>(&config.rcpt.rewrite, &session)
+                .await
+            {
+                stage.rule(&config.rcpt.rewrite.key, &new_address);
+                if new_address.contains('@') {
+                    *session.data.rcpt_to.last_mut().unwrap() =
+                        SessionAddress::simulated(new_address);
+                }
+            }
+
+            // Honeypot addresses are accepted and discarded
+            let honeypot = self
+                .eval_if(&config.rcpt.honeypot.enable, &session)
+                .await
+                .unwrap_or(false);
+            stage.rule(&config.rcpt.honeypot.enable.key, honeypot);
+            if honeypot {
+                session.data.rcpt_to.pop();
+                simulation.push(stage, "250 2.1.5 OK", true);
+                continue;
+            }
+
+            // Verify address
+            let rcpt = session.data.rcpt_to.last().unwrap();
+            let mut response_kind = None;
+            let relay = self
+                .eval_if(&config.rcpt.relay, &session)
+                .await
+                .unwrap_or(false);
+            stage.rule(&config.rcpt.relay.key, relay);
+            let result = if let Some(directory_name) = self
+                .eval_if::<String, _>(&config.rcpt.directory, &session)
+                .await
+            {
+                stage.rule(&config.rcpt.directory.key, &directory_name);
+                match self.get_directory(&directory_name) {
+                    Some(directory) => match directory.is_local_domain(&rcpt.domain).await {
+                        Ok(true) => {
+                            stage.rule("is_local_domain", true);
+                            let is_local_address =
+                                if self.session.rcpt_cache.get(&rcpt.address_lcase).is_some() {
+                                    stage.rule("rcpt_cache", "unknown");
+                                    Ok(false)
+                                } else {
+                                    directory.rcpt(&rcpt.address_lcase).await
+                                };
+                            match is_local_address {
+                                Ok(true) => {
+                                    if is_bounce
+                                        && !has_batv
+                                        && !batv.keys.is_empty()
+                                        && self
+                                            .eval_if(&batv.verify, &session)
+                                            .await
+                                            .unwrap_or(false)
+                                    {
+                                        stage.rule(&batv.verify.key, true);
+                                        Err("550 5.7.1 Bounce address tag missing.")
+                                    } else {
+                                        Ok(())
+                                    }
+                                }
+                                Ok(false) => {
+                                    response_kind = ResponseKind::UserUnknown.into();
+                                    Err("550 5.1.2 Mailbox does not exist.")
+                                }
+                                Err(_) => Err("451 4.4.3 Unable to verify address at this time."),
+                            }
+                        }
+                        Ok(false) => {
+                            stage.rule("is_local_domain", false);
+                            if relay {
+                                Ok(())
+                            } else {
+                                response_kind = ResponseKind::PolicyReject.into();
+                                Err("550 5.1.2 Relay not allowed.")
+                            }
+                        }
+                        Err(_) => Err("451 4.4.3 Unable to verify address at this time."),
+                    },
+                    None if relay => Ok(()),
+                    None => {
+                        response_kind = ResponseKind::PolicyReject.into();
+                        Err("550 5.1.2 Relay not allowed.")
+                    }
+                }
+            } else if relay {
+                Ok(())
+            } else {
+                response_kind = ResponseKind::PolicyReject.into();
+                Err("550 5.1.2 Relay not allowed.")
+            };
+
+            match result {
+                Ok(_) => {
+                    if self.simulate_greylist(&session, &mut stage).await {
+                        session.data.rcpt_to.pop();
+                        simulation.push(
+                            stage,
+                            "451 4.7.1 Greylisted, please try again later.",
+                            false,
+                        );
+                    } else if self
+                        .simulate_throttles(&throttle.rcpt_to, &session, &mut stage)
+                        .await
+                    {
+                        simulation.push(stage, "250 2.1.5 OK", true);
+                    } else {
+                        let response = self
+                            .simulate_response(
+                                ResponseKind::RateLimited,
+                                "451 4.4.5 Rate limit exceeded, try again later.",
+                                &session,
+                            )
+                            .await;
+                        session.data.rcpt_to.pop();
+                        simulation.push(stage, response, false);
+                    }
+                }
+                Err(response) => {
+                    let is_permanent = response.starts_with('5');
+                    let response = if let Some(kind) = response_kind {
+                        self.simulate_response(kind, response, &session).await
+                    } else {
+                        response.to_string()
+                    };
+                    session.data.rcpt_to.pop();
+                    if response_kind == Some(ResponseKind::UserUnknown) {
+                        // Responses are delayed to slow down directory harvesting
+                        rcpt_unknown += 1;
+                        stage.rule(&config.rcpt.tarpit_after.key, rcpt_tarpit_after);
+                        if rcpt_tarpit_after > 0 && rcpt_unknown > rcpt_tarpit_after {
+                            let delay = rcpt_errors_wait
+                                .saturating_mul(1 << (rcpt_unknown - rcpt_tarpit_after).min(16))
+                                .min(rcpt_tarpit_max_delay);
+                            stage.rule("tarpit_delay", format!("{delay:?}"));
+                        }
+                    }
+                    if is_permanent {
+                        if simulation.push_rcpt_error(
+                            stage,
+                            &response,
+                            &mut rcpt_errors,
+                            rcpt_errors_max,
+                        ) {
+                            return simulation;
+                        }
+                    } else {
+                        simulation.push(stage, response, false);
+                    }
+                }
+            }
+        }
+
+        // DATA stage
+        if session.data.rcpt_to.is_empty() {
+            return simulation;
+        }
+        let data = &config.data;
+        let mut stage = SimulatedStage::new("data");
+        stage.rule(
+            &data.max_message_size.key,
+            self.eval_if(&data.max_message_size, &session)
+                .await
+                .unwrap_or(25 * 1024 * 1024usize),
+        );
+        stage.rule(
+            &data.max_messages.key,
+            self.eval_if(&data.max_messages, &session)
+                .await
+                .unwrap_or(10usize),
+        );
+        for milter in &data.milters {
+            if self
+                .eval_if(&milter.enable, &session)
+                .await
+                .unwrap_or(false)
+            {
+                stage.rule(
+                    &milter.enable.key,
+                    format!("{}:{}", milter.hostname, milter.port),
+                );
+            }
+        }
+        for pipe in &data.pipe_commands {
+            if let Some(command) = self.eval_if::<String, _>(&pipe.command, &session).await {
+                stage.rule(&pipe.command.key, command);
+            }
+        }
+        self.simulate_script(&data.script, &session, &mut stage)
+            .await;
+        simulation.push(stage, "354 Start mail input; end with <CRLF>.<CRLF>", true);
+        simulation.accepted = true;
+
+        simulation
+    }
+
+    async fn simulate_script(
+        &self,
+        if_block: &IfBlock,
+        session: &SimulatedSession,
+        stage: &mut SimulatedStage,
+    ) {
+        if let Some(name) = self.eval_if::<String, _>(if_block, session).await {
+            if self.get_sieve_script(&name).is_some() {
+                stage.rule(&if_block.key, name);
+            } else {
+                stage.rule(&if_block.key, format!("{name} (not found)"));
+            }
+        }
+    }
+
+    async fn simulate_response(
+        &self,
+        kind: ResponseKind,
+        default: &'static str,
+        session: &SimulatedSession,
+    ) -> String {
+        let response = self
+            .build_custom_response(
+                kind,
+                default.as_bytes(),
+                &self.sieve.hostname,
+                session,
+                None,
+            )
+            .await;
+        String::from_utf8_lossy(&response).trim_end().to_string()
+    }
+
+    async fn simulate_greylist(
+        &self,
+        session: &SimulatedSession,
+        stage: &mut SimulatedStage,
+    ) -> bool {
+        let config = &self.session.config.rcpt.greylist;
+        let enable = self.eval_if(&config.enable, session).await.unwrap_or(false);
+        stage.rule(&config.enable.key, enable);
+        if !enable {
+            return false;
+        }
+
+        let key = if let Some(key) = session.data.greylist_key(config) {
+            key
+        } else {
+            stage.rule("greylist", "whitelisted");
+            return false;
+        };
+        let status = match self.shared.default_lookup_store.key_get::<i64>(key).await {
+            Ok(Some(first_seen)) => {
+                if now().saturating_sub(first_seen as u64) < config.delay.as_secs() {
+                    "retry-too-soon"
+                } else {
+                    "passed"
+                }
+            }
+            Ok(None) => "first-seen",
+            Err(_) => "unavailable",
+        };
+        stage.rule("greylist", status);
+
+        matches!(status, "retry-too-soon" | "first-seen")
+    }
+
+    async fn simulate_throttles(
+        &self,
+        throttles: &[Throttle],
+        session: &SimulatedSession,
+        stage: &mut SimulatedStage,
+    ) -> bool {
+        let mut is_allowed = true;
+
+        for t in throttles {
+            if t.expr.is_empty()
+                || self
+                    .eval_expr(&t.expr, session, "throttle")
+                    .await
+                    .unwrap_or(false)
+            {
+                if (t.keys & THROTTLE_RCPT_DOMAIN) != 0 {
+                    let d = session
+                        .data
+                        .rcpt_to
+                        .last()
+                        .map(|r| r.domain.as_str())
+                        .unwrap_or_default();
+
+                    if session
+                        .data
+                        .rcpt_to
+                        .iter()
+                        .filter(|p| p.domain == d)
+                        .count()
+                        > 1
+                    {
+                        continue;
+                    }
+                }
+
+                // Check limits without consuming them
+                let key = t.new_key(session);
+                let mut allowed = t.concurrency.is_none()
+                    || self
+                        .session
+                        .throttle
+                        .get(&key)
+                        .map_or(true, |limiter| limiter.check_is_allowed());
+                if let (true, Some(rate)) = (allowed, &t.rate) {
+                    allowed = self
+                        .shared
+                        .default_lookup_store
+                        .is_rate_allowed(key.as_ref(), rate, true)
+                        .await
+                        .unwrap_or_default()
+                        .is_none();
+                }
+
+                stage.throttles.push(SimulatedThrottle {
+                    keys: [
+                        (THROTTLE_RCPT, "rcpt"),
+                        (THROTTLE_RCPT_DOMAIN, "rcpt_domain"),
+                        (THROTTLE_SENDER, "sender"),
+                        (THROTTLE_SENDER_DOMAIN, "sender_domain"),
+                        (THROTTLE_HELO_DOMAIN, "helo_domain"),
+                        (THROTTLE_AUTH_AS, "authenticated_as"),
+                        (THROTTLE_LISTENER, "listener"),
+                        (THROTTLE_MX, "mx"),
+                        (THROTTLE_REMOTE_IP, "remote_ip"),
+                        (THROTTLE_LOCAL_IP, "local_ip"),
+                    ]
+                    .into_iter()
+                    .filter(|(key, _)| (t.keys & key) != 0)
+                    .map(|(_, name)| name.to_string())
+                    .collect(),
+                    concurrency: t.concurrency,
+                    rate: t
+                        .rate
+                        .as_ref()
+                        .map(|rate| format!("{}/{}s", rate.requests, rate.period.as_secs())),
+                    allowed,
+                });
+                is_allowed &= allowed;
+            }
+        }
+
+        is_allowed
+    }
+}
+
+impl Simulation {
+    fn push(&mut self, mut stage: SimulatedStage, response: impl Into<String>, accepted: bool) {
+        stage.response = response.into();
+        stage.accepted = accepted;
+        self.stages.push(stage);
+    }
+
+    /// Records a permanent RCPT error, returning true when the session would
+    /// be dropped after too many errors.
+    fn push_rcpt_error(
+        &mut self,
+        stage: SimulatedStage,
+        response: &str,
+        rcpt_errors: &mut usize,
+        rcpt_errors_max: usize,
+    ) -> bool {
+        self.push(stage, response, false);
+        *rcpt_errors += 1;
+        if *rcpt_errors >= rcpt_errors_max {
+            self.push(
+                SimulatedStage::new("rcpt"),
+                "421 4.3.0 Too many errors, disconnecting.",
+                false,
+            );
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SimulatedStage {
+    fn new(stage: &str) -> Self {
+        SimulatedStage {
+            stage: stage.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn rule(&mut self, property: &str, value: impl ToString) {
+        self.rules.push(SimulatedRule {
+            property: property.to_string(),
+            value: value.to_string(),
+        });
+    }
+}
+
+impl SessionAddress {
+    fn simulated(address: String) -> Self {
+        let address_lcase = address.to_lowercase();
+        SessionAddress {
+            domain: address_lcase.domain_part().to_string(),
+            address_lcase,
+            address,
+            flags: 0,
+            dsn_info: None,
+        }
+    }
+}
+
+impl ResolveVariable for SimulatedSession {
+    fn resolve_variable(&self, variable: u32) -> utils::expr::Variable<'_> {
+        match variable {
+            V_RECIPIENT => self
+                .data
+                .rcpt_to
+                .last()
+                .map(|r| r.address_lcase.as_str())
+                .unwrap_or_default()
+                .into(),
+            V_RECIPIENT_DOMAIN => self
+                .data
+                .rcpt_to
+                .last()
+                .map(|r| r.domain.as_str())
+                .unwrap_or_default()
+                .into(),
+            V_SENDER => self
+                .data
+                .mail_from
+                .as_ref()
+                .map(|m| m.address_lcase.as_str())
+                .unwrap_or_default()
+                .into(),
+            V_SENDER_DOMAIN => self
+                .data
+                .mail_from
+                .as_ref()
+                .map(|m| m.domain.as_str())
+                .unwrap_or_default()
+                .into(),
+            V_HELO_DOMAIN => self.data.helo_domain.as_str().into(),
+            V_AUTHENTICATED_AS => self.data.authenticated_as.as_str().into(),
+            V_LISTENER => self.listener.as_str().into(),
+            V_REMOTE_IP => self.data.remote_ip_str.as_str().into(),
+            V_LOCAL_IP => self.data.local_ip_str.as_str().into(),
+            V_PRIORITY => self.data.priority.to_string().into(),
+            V_DNSBL_SCORE => self.data.dnsbl_score().into(),
+            _ => utils::expr::Variable::default(),
+        }
+    }
+}
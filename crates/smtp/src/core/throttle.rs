@@ -206,12 +206,13 @@ impl Throttle {
 
 impl<T: AsyncRead + AsyncWrite> Session<T> {
     pub async fn is_allowed(&mut self) -> bool {
+        let throttle = self.core.session.config.throttle.load_full();
         let throttles = if !self.data.rcpt_to.is_empty() {
-            &self.core.session.config.throttle.rcpt_to
+            &throttle.rcpt_to
         } else if self.data.mail_from.is_some() {
-            &self.core.session.config.throttle.mail_from
+            &throttle.mail_from
         } else {
-            &self.core.session.config.throttle.connect
+            &throttle.connect
         };
 
         for t in throttles {
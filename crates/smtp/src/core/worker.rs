@@ -23,8 +23,6 @@
 
 use std::sync::{atomic::Ordering, Arc};
 
-use tokio::sync::oneshot;
-
 use super::SMTP;
 
 impl SMTP {
@@ -33,23 +31,7 @@ impl SMTP {
         U: FnOnce() -> V + Send + 'static,
         V: Sync + Send + 'static,
     {
-        let (tx, rx) = oneshot::channel();
-
-        self.worker_pool.spawn(move || {
-            tx.send(f()).ok();
-        });
-
-        match rx.await {
-            Ok(result) => Some(result),
-            Err(err) => {
-                tracing::warn!(
-                    context = "worker-pool",
-                    event = "error",
-                    reason = %err,
-                );
-                None
-            }
-        }
+        self.worker_pool.run(f).await
     }
 
     fn cleanup(&self) {
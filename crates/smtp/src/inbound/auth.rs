@@ -21,21 +21,37 @@
  * for more details.
 */
 
-use directory::AuthResult;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use directory::{
+    core::scram::{ScramState, ScramStep},
+    AuthResult, Principal,
+};
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
-use smtp_proto::{IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2};
+use smtp_proto::{
+    IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_SCRAM_SHA_256,
+    AUTH_SCRAM_SHA_256_PLUS, AUTH_XOAUTH2,
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::core::Session;
+use crate::{
+    config::session::mechanism_name,
+    core::{AuthUsage, Session, SMTP},
+};
 
 pub struct SaslToken {
     mechanism: u64,
     credentials: Credentials<String>,
+    scram: Option<ScramState>,
+    channel_binding: Option<Vec<u8>>,
 }
 
 impl SaslToken {
-    pub fn from_mechanism(mechanism: u64) -> Option<SaslToken> {
+    /// Creates the token of a SASL exchange, `channel_binding` holds the
+    /// binding data of the connection when the -PLUS mechanisms are offered.
+    pub fn from_mechanism(mechanism: u64, channel_binding: Option<Vec<u8>>) -> Option<SaslToken> {
         match mechanism {
             AUTH_PLAIN | AUTH_LOGIN => SaslToken {
                 mechanism,
@@ -43,6 +59,8 @@ impl SaslToken {
                     username: String::new(),
                     secret: String::new(),
                 },
+                scram: None,
+                channel_binding: None,
             }
             .into(),
             AUTH_OAUTHBEARER => SaslToken {
@@ -50,6 +68,8 @@ impl SaslToken {
                 credentials: Credentials::OAuthBearer {
                     token: String::new(),
                 },
+                scram: None,
+                channel_binding: None,
             }
             .into(),
             AUTH_XOAUTH2 => SaslToken {
@@ -58,6 +78,18 @@ impl SaslToken {
                     username: String::new(),
                     secret: String::new(),
                 },
+                scram: None,
+                channel_binding: None,
+            }
+            .into(),
+            AUTH_SCRAM_SHA_256 | AUTH_SCRAM_SHA_256_PLUS => SaslToken {
+                mechanism,
+                credentials: Credentials::Plain {
+                    username: String::new(),
+                    secret: String::new(),
+                },
+                scram: Some(ScramState::ClientFirst),
+                channel_binding,
             }
             .into(),
             _ => None,
@@ -71,6 +103,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         token: &mut SaslToken,
         response: &[u8],
     ) -> Result<bool, ()> {
+        if let Some(state) = token.scram.take() {
+            return self.handle_scram_response(token, state, response).await;
+        }
+
         if response.is_empty() {
             match (token.mechanism, &token.credentials) {
                 (AUTH_PLAIN | AUTH_XOAUTH2 | AUTH_OAUTHBEARER, _) => {
@@ -107,7 +143,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                             *username = s_username;
                             *secret = s_secret;
                             return self
-                                .authenticate(std::mem::take(&mut token.credentials))
+                                .authenticate(
+                                    token.mechanism,
+                                    std::mem::take(&mut token.credentials),
+                                )
                                 .await;
                         }
                         _ => (),
@@ -120,7 +159,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                         Ok(true)
                     } else {
                         *secret = response.into_string();
-                        self.authenticate(std::mem::take(&mut token.credentials))
+                        self.authenticate(token.mechanism, std::mem::take(&mut token.credentials))
                             .await
                     };
                 }
@@ -129,7 +168,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                     if response.contains("auth=") {
                         *token_ = response;
                         return self
-                            .authenticate(std::mem::take(&mut token.credentials))
+                            .authenticate(token.mechanism, std::mem::take(&mut token.credentials))
                             .await;
                     }
                 }
@@ -160,7 +199,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                             *username = s_username;
                             *secret = s_secret;
                             return self
-                                .authenticate(std::mem::take(&mut token.credentials))
+                                .authenticate(
+                                    token.mechanism,
+                                    std::mem::take(&mut token.credentials),
+                                )
                                 .await;
                         }
                         _ => (),
@@ -174,61 +216,23 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await
     }
 
-    pub async fn authenticate(&mut self, credentials: Credentials<String>) -> Result<bool, ()> {
+    pub async fn authenticate(
+        &mut self,
+        mechanism: u64,
+        credentials: Credentials<String>,
+    ) -> Result<bool, ()> {
         if let Some(lookup) = &self.params.auth_directory {
             let authenticated_as = match &credentials {
                 Credentials::Plain { username, .. }
                 | Credentials::XOauth2 { username, .. }
                 | Credentials::OAuthBearer { token: username } => username.to_string(),
             };
-
-            match lookup
+            let result = lookup
                 .authenticate(&credentials, self.data.remote_ip, false)
-                .await
-            {
-                Ok(AuthResult::Success(principal)) => {
-                    tracing::debug!(
-                        parent: &self.span,
-                        context = "auth",
-                        event = "authenticate",
-                        result = "success"
-                    );
-
-                    self.data.authenticated_as = authenticated_as.to_lowercase();
-                    self.data.authenticated_emails = principal
-                        .emails
-                        .into_iter()
-                        .map(|e| e.trim().to_lowercase())
-                        .collect();
-                    self.eval_post_auth_params().await;
-                    self.write(b"235 2.7.0 Authentication succeeded.\r\n")
-                        .await?;
-                    return Ok(false);
-                }
-                Ok(AuthResult::Failure) => {
-                    tracing::debug!(
-                        parent: &self.span,
-                        context = "auth",
-                        event = "authenticate",
-                        result = "failed"
-                    );
+                .await;
 
-                    return self
-                        .auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
-                        .await;
-                }
-                Ok(AuthResult::Banned) => {
-                    tracing::debug!(
-                        parent: &self.span,
-                        context = "auth",
-                        event = "authenticate",
-                        result = "banned"
-                    );
-
-                    return Err(());
-                }
-                _ => (),
-            }
+            self.handle_auth_result(mechanism, authenticated_as, result)
+                .await
         } else {
             tracing::warn!(
                 parent: &self.span,
@@ -236,11 +240,163 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 event = "error",
                 "No lookup list configured for authentication."
             );
+            self.write(b"454 4.7.0 Temporary authentication failure\r\n")
+                .await?;
+
+            Ok(false)
+        }
+    }
+
+    async fn handle_scram_response(
+        &mut self,
+        token: &mut SaslToken,
+        state: ScramState,
+        response: &[u8],
+    ) -> Result<bool, ()> {
+        let response = if !response.is_empty() {
+            match base64_decode(response).and_then(|r| String::from_utf8(r).ok()) {
+                Some(response) => response,
+                None => return self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await,
+            }
+        } else {
+            String::new()
+        };
+
+        let step = match &self.params.auth_directory {
+            Some(lookup) => {
+                lookup
+                    .scram_step(
+                        state,
+                        &response,
+                        token.mechanism == AUTH_SCRAM_SHA_256_PLUS,
+                        token.channel_binding.as_deref(),
+                    )
+                    .await
+            }
+            None => Ok(ScramStep::Invalid),
+        };
+
+        match step {
+            Ok(ScramStep::Challenge(state, challenge)) => {
+                token.scram = Some(state);
+                self.write(b"334 ").await?;
+                self.write(&base64_encode(challenge.as_bytes()).unwrap_or_default())
+                    .await?;
+                self.write(b"\r\n").await?;
+                Ok(true)
+            }
+            Ok(ScramStep::Authenticate {
+                username,
+                is_verified,
+            }) => {
+                self.authenticate_scram(token.mechanism, &username, is_verified)
+                    .await
+            }
+            Ok(ScramStep::Invalid) => self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await,
+            Err(_) => {
+                self.write(b"454 4.7.0 Temporary authentication failure\r\n")
+                    .await?;
+                Ok(false)
+            }
         }
-        self.write(b"454 4.7.0 Temporary authentication failure\r\n")
-            .await?;
+    }
+
+    async fn authenticate_scram(
+        &mut self,
+        mechanism: u64,
+        username: &str,
+        is_verified: bool,
+    ) -> Result<bool, ()> {
+        let result = match &self.params.auth_directory {
+            Some(lookup) => {
+                lookup
+                    .authenticate_scram(username, is_verified, self.data.remote_ip, false)
+                    .await
+            }
+            None => Ok(AuthResult::Failure),
+        };
 
-        Ok(false)
+        self.handle_auth_result(mechanism, username.to_string(), result)
+            .await
+    }
+
+    async fn handle_auth_result(
+        &mut self,
+        mechanism: u64,
+        authenticated_as: String,
+        result: directory::Result<AuthResult<Principal<u32>>>,
+    ) -> Result<bool, ()> {
+        match result {
+            Ok(AuthResult::Success(principal)) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "authenticate",
+                    result = "success",
+                    mechanism = mechanism_name(mechanism)
+                );
+                self.core
+                    .record_auth_usage(&self.instance.id, mechanism, |usage| &usage.success);
+
+                self.data.authenticated_as = authenticated_as.to_lowercase();
+                self.data.authenticated_id = principal.id.into();
+                self.data.authenticated_emails = principal
+                    .emails
+                    .into_iter()
+                    .map(|e| e.trim().to_lowercase())
+                    .collect();
+                self.eval_post_auth_params().await;
+                self.write(b"235 2.7.0 Authentication succeeded.\r\n")
+                    .await?;
+                Ok(false)
+            }
+            Ok(AuthResult::MustChangePassword(_)) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "authenticate",
+                    result = "password-change-required",
+                    mechanism = mechanism_name(mechanism)
+                );
+                self.core
+                    .record_auth_usage(&self.instance.id, mechanism, |usage| &usage.failure);
+
+                self.auth_error(b"535 5.7.8 Password must be changed before use.\r\n")
+                    .await
+            }
+            Ok(AuthResult::Failure) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "authenticate",
+                    result = "failed",
+                    mechanism = mechanism_name(mechanism)
+                );
+                self.core
+                    .record_auth_usage(&self.instance.id, mechanism, |usage| &usage.failure);
+
+                self.auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                    .await
+            }
+            Ok(AuthResult::Banned) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "authenticate",
+                    result = "banned",
+                    mechanism = mechanism_name(mechanism)
+                );
+                self.core
+                    .record_auth_usage(&self.instance.id, mechanism, |usage| &usage.failure);
+
+                Err(())
+            }
+            Err(_) => {
+                self.write(b"454 4.7.0 Temporary authentication failure\r\n")
+                    .await?;
+                Ok(false)
+            }
+        }
     }
 
     pub async fn auth_error(&mut self, response: &[u8]) -> Result<bool, ()> {
@@ -262,3 +418,22 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         }
     }
 }
+
+impl SMTP {
+    /// Counts an authentication attempt using a mechanism on a listener.
+    pub fn record_auth_usage(
+        &self,
+        listener: &str,
+        mechanism: u64,
+        counter: impl Fn(&AuthUsage) -> &AtomicU64,
+    ) {
+        counter(
+            &self
+                .session
+                .auth_usage
+                .entry((listener.to_string(), mechanism))
+                .or_default(),
+        )
+        .fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -24,37 +24,84 @@
 use std::{
     borrow::Cow,
     process::Stdio,
-    sync::Arc,
     time::{Duration, SystemTime},
 };
 
+use bytes::Bytes;
 use mail_auth::{
     common::{headers::HeaderWriter, verify::VerifySignature},
     dmarc, AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, ReceivedSpf,
+    SpfResult,
 };
 use mail_builder::headers::{date::Date, message_id::generate_message_id_header};
+use mail_parser::MessageParser;
 use sieve::runtime::Variable;
 use smtp_proto::{
-    MAIL_BY_RETURN, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    MAIL_BY_RETURN, MAIL_REQUIRETLS, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER,
+    RCPT_NOTIFY_SUCCESS,
 };
-use store::write::now;
+use store::write::{now, BatchBuilder, BillingClass, BILLING_SENT_BYTES, BILLING_SENT_MESSAGES};
 use tokio::{io::AsyncWriteExt, process::Command};
-use utils::{config::Rate, listener::SessionStream};
+use utils::{config::Rate, listener::SessionStream, worker::cpu_pool};
 
 use crate::{
-    config::VerifyStrategy,
+    config::{DlpAction, DnsblAction, VerifyStrategy},
     core::{Session, SessionAddress, State},
-    queue::{self, Message, SimpleEnvelope},
+    inbound::response::ResponseKind,
+    queue::{
+        self,
+        dsn::batv_sign,
+        quarantine::{QuarantineEntry, QuarantineSource},
+        Message, SimpleEnvelope, MAIL_DSN_SUPPRESSED, MAIL_QUARANTINED,
+    },
     reporting::analysis::AnalyzeReport,
-    scripts::{ScriptModification, ScriptResult},
+    scripts::{ScriptModification, ScriptResult, SessionAuthResults},
 };
 
-use super::AuthResult;
+use super::{
+    dlp::DlpIncident,
+    milter::Modification,
+    monitor::{Disposition, MonitorAction},
+    AuthResult,
+};
 
 impl<T: SessionStream> Session<T> {
     pub async fn queue_message(&mut self) -> Cow<'static, [u8]> {
+        let response = self.process_message().await;
+        self.record_monitor_verdicts(response.as_ref()).await;
+        response
+    }
+
+    async fn process_message(&mut self) -> Cow<'static, [u8]> {
+        // Discard messages addressed only to honeypot recipients
+        if self.data.rcpt_to.is_empty() {
+            tracing::info!(parent: &self.span,
+                    context = "data",
+                    event = "discard",
+                    reason = "honeypot",
+                    size = self.data.message_size());
+
+            self.data.message = Vec::with_capacity(0);
+            self.data.spool = None;
+            self.data.messages_sent += 1;
+            return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
+        }
+
         // Authenticate message
-        let raw_message = Arc::new(std::mem::take(&mut self.data.message));
+        let raw_message = match self.take_message().await {
+            Ok(raw_message) => raw_message,
+            Err(err) => {
+                tracing::error!(parent: &self.span,
+                    context = "spool",
+                    event = "error",
+                    "Failed to read spooled message: {}", err);
+
+                return (&b"451 4.3.0 Failed to spool message.\r\n"[..]).into();
+            }
+        };
+
+        // DKIM body hashes are computed by the parser over the whole body, which
+        // for spooled messages is the memory map rather than a heap copy.
         let auth_message = if let Some(auth_message) = AuthenticatedMessage::parse(&raw_message) {
             auth_message
         } else {
@@ -69,7 +116,7 @@ impl<T: SessionStream> Session<T> {
         // Loop detection
         let dc = &self.core.session.config.data;
         let ac = &self.core.mail_auth;
-        let rc = &self.core.report.config;
+        let rc = self.core.report.config.load_full();
         if auth_message.received_headers_count()
             > self
                 .core
@@ -100,10 +147,20 @@ impl<T: SessionStream> Session<T> {
             .unwrap_or(VerifyStrategy::Relaxed);
         let dkim_output = if dkim.verify() || dmarc.verify() {
             let dkim_output = self.core.resolvers.dns.verify_dkim(&auth_message).await;
-            let rejected = dkim.is_strict()
+            let mut rejected = dkim.is_strict()
                 && !dkim_output
                     .iter()
                     .any(|d| matches!(d.result(), DkimResult::Pass));
+            if rejected {
+                if let Some(verdict) = self.monitor_verdict(
+                    "dkim",
+                    MonitorAction::Reject,
+                    "No passing DKIM signatures found.",
+                ) {
+                    self.data.monitor_verdicts.push(verdict);
+                    rejected = false;
+                }
+            }
 
             // Send reports for failed signatures
             if let Some(rate) = self.core.eval_if::<Rate, _>(&rc.dkim.send, self).await {
@@ -159,10 +216,18 @@ impl<T: SessionStream> Session<T> {
             .and_then(|name| self.core.get_arc_sealer(&name));
         let arc_output = if arc.verify() || arc_sealer.is_some() {
             let arc_output = self.core.resolvers.dns.verify_arc(&auth_message).await;
+            let mut rejected = arc.is_strict()
+                && !matches!(arc_output.result(), DkimResult::Pass | DkimResult::None);
+            if rejected {
+                if let Some(verdict) =
+                    self.monitor_verdict("arc", MonitorAction::Reject, "ARC validation failed.")
+                {
+                    self.data.monitor_verdicts.push(verdict);
+                    rejected = false;
+                }
+            }
 
-            if arc.is_strict()
-                && !matches!(arc_output.result(), DkimResult::Pass | DkimResult::None)
-            {
+            if rejected {
                 tracing::info!(parent: &self.span,
                     context = "arc",
                     event = "auth-failed",
@@ -215,7 +280,7 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Verify DMARC
-        let (dmarc_result, dmarc_policy) = match &self.data.spf_mail_from {
+        let (dmarc_result, dmarc_policy, dmarc_output) = match &self.data.spf_mail_from {
             Some(spf_output) if dmarc.verify() => {
                 let dmarc_output = self
                     .core
@@ -233,7 +298,7 @@ impl<T: SessionStream> Session<T> {
                     )
                     .await;
 
-                let rejected = dmarc.is_strict()
+                let mut rejected = dmarc.is_strict()
                     && dmarc_output.policy() == dmarc::Policy::Reject
                     && !(matches!(dmarc_output.spf_result(), DmarcResult::Pass)
                         || matches!(dmarc_output.dkim_result(), DmarcResult::Pass));
@@ -272,6 +337,15 @@ impl<T: SessionStream> Session<T> {
                     from = auth_message.from(),
                     dkim_result = %dmarc_output.dkim_result(),
                     spf_result = %dmarc_output.spf_result());
+
+                    if let Some(verdict) = self.monitor_verdict(
+                        "dmarc",
+                        MonitorAction::Reject,
+                        "Email rejected per DMARC policy.",
+                    ) {
+                        self.data.monitor_verdicts.push(verdict);
+                        rejected = false;
+                    }
                 }
 
                 // Send DMARC report
@@ -280,7 +354,7 @@ impl<T: SessionStream> Session<T> {
                         &auth_message,
                         &auth_results,
                         rejected,
-                        dmarc_output,
+                        dmarc_output.clone(),
                         &dkim_output,
                         &arc_output,
                     )
@@ -291,13 +365,21 @@ impl<T: SessionStream> Session<T> {
                     return if is_temp_fail {
                         (&b"451 4.7.1 Email temporarily rejected per DMARC policy.\r\n"[..]).into()
                     } else {
-                        (&b"550 5.7.1 Email rejected per DMARC policy.\r\n"[..]).into()
+                        self.custom_response(
+                            ResponseKind::PolicyReject,
+                            b"550 5.7.1 Email rejected per DMARC policy.\r\n",
+                        )
+                        .await
                     };
                 }
 
-                (dmarc_result.into(), dmarc_policy.into())
+                (
+                    dmarc_result.into(),
+                    dmarc_policy.into(),
+                    dmarc_output.into(),
+                )
             }
-            _ => (None, None),
+            _ => (None, None, None),
         };
 
         // Analyze reports
@@ -310,9 +392,24 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Run Milter filters
+        let mut quarantine = None;
         let mut edited_message = match self.run_milters(&auth_message).await {
             Ok(modifications) => {
                 if !modifications.is_empty() {
+                    quarantine = modifications.iter().find_map(|m| match m {
+                        Modification::Quarantine { reason } => {
+                            (QuarantineSource::Milter, reason.clone()).into()
+                        }
+                        _ => None,
+                    });
+                    if let Some((_, reason)) = &quarantine {
+                        if let Some(verdict) =
+                            self.monitor_verdict("milter", MonitorAction::Quarantine, reason)
+                        {
+                            self.data.monitor_verdicts.push(verdict);
+                            quarantine = None;
+                        }
+                    }
                     tracing::debug!(
                     parent: &self.span,
                     context = "milter",
@@ -329,12 +426,28 @@ impl<T: SessionStream> Session<T> {
 
                     self.data
                         .apply_milter_modifications(modifications, &auth_message)
-                        .map(Arc::new)
+                        .map(Bytes::from)
                 } else {
                     None
                 }
             }
-            Err(response) => return response,
+            Err(response) => {
+                let verdict = if response.starts_with(b"5") {
+                    self.monitor_verdict(
+                        "milter",
+                        MonitorAction::Reject,
+                        String::from_utf8_lossy(&response).trim_end(),
+                    )
+                } else {
+                    None
+                };
+                if let Some(verdict) = verdict {
+                    self.data.monitor_verdicts.push(verdict);
+                    None
+                } else {
+                    return response;
+                }
+            }
         };
 
         // Pipe message
@@ -377,7 +490,7 @@ impl<T: SessionStream> Session<T> {
                                                 && !output.stdout.is_empty()
                                                 && output.stdout[..] != piped_message[..]
                                             {
-                                                edited_message = Arc::new(output.stdout).into();
+                                                edited_message = Bytes::from(output.stdout).into();
                                             }
 
                                             tracing::debug!(parent: &self.span,
@@ -487,7 +600,20 @@ impl<T: SessionStream> Session<T> {
                         .as_ref()
                         .map(|a| a.as_str())
                         .unwrap_or_default(),
-                );
+                )
+                .with_auth_results(SessionAuthResults {
+                    remote_ip: self.data.remote_ip,
+                    helo_domain: self.data.helo_domain.clone(),
+                    mail_from: self
+                        .data
+                        .mail_from
+                        .as_ref()
+                        .map(|mail_from| mail_from.address.clone())
+                        .unwrap_or_default(),
+                    spf_ehlo: self.data.spf_ehlo.clone(),
+                    spf_mail_from: self.data.spf_mail_from.clone(),
+                    dmarc: dmarc_output,
+                });
 
             let modifications = match self.run_script(script.clone(), params).await {
                 ScriptResult::Accept { modifications } => modifications,
@@ -495,7 +621,7 @@ impl<T: SessionStream> Session<T> {
                     message,
                     modifications,
                 } => {
-                    edited_message = Arc::new(message).into();
+                    edited_message = Bytes::from(message).into();
                     modifications
                 }
                 ScriptResult::Reject(message) => {
@@ -504,7 +630,14 @@ impl<T: SessionStream> Session<T> {
                         event = "reject",
                         reason = message);
 
-                    return message.into_bytes().into();
+                    if let Some(verdict) =
+                        self.monitor_verdict("sieve", MonitorAction::Reject, message.trim_end())
+                    {
+                        self.data.monitor_verdicts.push(verdict);
+                        vec![]
+                    } else {
+                        return message.into_bytes().into();
+                    }
                 }
                 ScriptResult::Discard => {
                     return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
@@ -525,15 +658,189 @@ impl<T: SessionStream> Session<T> {
                     ScriptModification::SetEnvelope { name, value } => {
                         self.data.apply_envelope_modification(name, value);
                     }
+                    ScriptModification::Quarantine { reason } => {
+                        if let Some(verdict) =
+                            self.monitor_verdict("sieve", MonitorAction::Quarantine, &reason)
+                        {
+                            self.data.monitor_verdicts.push(verdict);
+                        } else {
+                            quarantine = (QuarantineSource::Sieve, reason).into();
+                        }
+                    }
+                }
+            }
+        }
+
+        // Data loss prevention
+        let mut dlp_incident = None;
+        if !dc.dlp.rules.is_empty()
+            && self
+                .core
+                .eval_if(&dc.dlp.enable, self)
+                .await
+                .unwrap_or(false)
+        {
+            if let Some((rule, matches)) =
+                dc.dlp.scan(edited_message.as_ref().unwrap_or(&raw_message))
+            {
+                tracing::info!(parent: &self.span,
+                    context = "dlp",
+                    event = "match",
+                    rule = rule.id,
+                    action = ?rule.action,
+                    "Message matched data loss prevention rule.");
+
+                // Rules in monitor-only mode record their verdict without being enforced
+                let monitored = match rule.action {
+                    DlpAction::Reject => self.monitor_verdict(
+                        &format!("dlp:{}", rule.id),
+                        MonitorAction::Reject,
+                        "Message rejected by data loss prevention policy.",
+                    ),
+                    DlpAction::Quarantine => self.monitor_verdict(
+                        &format!("dlp:{}", rule.id),
+                        MonitorAction::Quarantine,
+                        &format!("Matched data loss prevention rule {:?}", rule.id),
+                    ),
+                    DlpAction::Encrypt => None,
+                };
+                if let Some(verdict) = monitored {
+                    self.data.monitor_verdicts.push(verdict);
+                } else {
+                    let mut incident = DlpIncident::new(
+                        self.core.queue.snowflake_id.generate().unwrap_or_else(now),
+                        rule,
+                        matches,
+                        self.data.remote_ip,
+                    );
+                    incident.return_path = self
+                        .data
+                        .mail_from
+                        .as_ref()
+                        .map(|mail_from| mail_from.address.clone())
+                        .unwrap_or_default();
+                    incident.recipients = self
+                        .data
+                        .rcpt_to
+                        .iter()
+                        .map(|rcpt| rcpt.address.clone())
+                        .collect();
+                    incident.authenticated_as = self.data.authenticated_as.clone();
+
+                    if rule.action == DlpAction::Reject {
+                        self.core.save_dlp_incident(incident).await;
+                        return (b"550 5.7.1 Message rejected by data loss prevention policy.\r\n"
+                            [..])
+                            .into();
+                    }
+                    if rule.action == DlpAction::Quarantine {
+                        quarantine = (
+                            QuarantineSource::Dlp,
+                            format!("Matched data loss prevention rule {:?}", rule.id),
+                        )
+                            .into();
+                    }
+                    dlp_incident = incident.into();
                 }
             }
         }
 
+        // Compromised account detection
+        if !self.data.authenticated_as.is_empty()
+            && self
+                .core
+                .eval_if(&dc.risk.enable, self)
+                .await
+                .unwrap_or(false)
+        {
+            if let Some(response) = self
+                .check_sender_risk(edited_message.as_ref().unwrap_or(&raw_message))
+                .await
+            {
+                return response;
+            }
+        }
+
+        // Hold messages submitted by moderated accounts until approved
+        if quarantine.is_none() && self.core.eval_if(&dc.moderate, self).await.unwrap_or(false) {
+            let reason = format!("Submitted by {:?} for approval", self.data.authenticated_as);
+            if let Some(verdict) =
+                self.monitor_verdict("moderation", MonitorAction::Quarantine, &reason)
+            {
+                self.data.monitor_verdicts.push(verdict);
+            } else {
+                quarantine = (QuarantineSource::Moderation, reason).into();
+            }
+        }
+
+        // Add footer
+        if let Some(footer) = self
+            .core
+            .eval_if::<String, _>(&dc.footer, self)
+            .await
+            .and_then(|name| self.core.get_footer(&name))
+        {
+            if let Some(message) = footer.apply(edited_message.as_ref().unwrap_or(&raw_message)) {
+                tracing::debug!(parent: &self.span,
+                    context = "footer",
+                    event = "add",
+                    "Added footer to message.");
+
+                edited_message = Bytes::from(message).into();
+            }
+        }
+
         // Build message
         let mail_from = self.data.mail_from.clone().unwrap();
         let rcpt_to = std::mem::take(&mut self.data.rcpt_to);
         let mut message = self.build_message(mail_from, rcpt_to).await;
 
+        // Require TLS for messages matching a DLP encryption rule
+        if dlp_incident
+            .as_ref()
+            .map_or(false, |incident| incident.action == DlpAction::Encrypt)
+        {
+            message.flags |= MAIL_REQUIRETLS;
+        }
+
+        // Hold quarantined messages until they are reviewed
+        let quarantine = quarantine.map(|(source, reason)| {
+            message.flags |= MAIL_QUARANTINED;
+            QuarantineEntry {
+                queue_id: message.id,
+                created: message.created,
+                source,
+                reason,
+                return_path: message.return_path.clone(),
+                recipients: message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| rcpt.address.clone())
+                    .collect(),
+                subject: MessageParser::new()
+                    .parse_headers(edited_message.as_ref().unwrap_or(&raw_message).as_ref())
+                    .and_then(|message| message.subject().map(|s| s.to_string()))
+                    .unwrap_or_default(),
+                size: 0,
+                notified: false,
+            }
+        });
+
+        // Suppress bounces for messages failing authentication
+        if self.data.authenticated_as.is_empty()
+            && (matches!(dmarc_result, Some(DmarcResult::Fail(_)))
+                || self.data.spf_mail_from.as_ref().map_or(false, |spf| {
+                    matches!(spf.result(), SpfResult::Fail | SpfResult::SoftFail)
+                }))
+            && self
+                .core
+                .eval_if(&self.core.queue.config.dsn.require_auth, self)
+                .await
+                .unwrap_or(false)
+        {
+            message.flags |= MAIL_DSN_SUPPRESSED;
+        }
+
         // Add Received header
         if self
             .core
@@ -554,6 +861,11 @@ impl<T: SessionStream> Session<T> {
             auth_results.write_header(&mut headers);
         }
 
+        // Add DNS blocklist results
+        if self.dnsbl_action().await == DnsblAction::Tag {
+            self.write_dnsbl_header(&mut headers);
+        }
+
         // Add Received-SPF header
         if let Some(spf_output) = &self.data.spf_mail_from {
             if self
@@ -573,9 +885,11 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
-        // ARC Seal
+        // ARC Seal, as long as there are DKIM or SPF results to preserve for the next hop
         if let (Some(arc_sealer), Some(arc_output)) = (arc_sealer, &arc_output) {
-            if !dkim_output.is_empty() && arc_output.can_be_sealed() {
+            if (!dkim_output.is_empty() || self.data.spf_mail_from.is_some())
+                && arc_output.can_be_sealed()
+            {
                 match arc_sealer.seal(&auth_message, &auth_results, arc_output) {
                     Ok(set) => {
                         set.write_header(&mut headers);
@@ -624,27 +938,38 @@ impl<T: SessionStream> Session<T> {
             headers.extend_from_slice(b">\r\n");
         }
 
-        // DKIM sign
+        // DKIM sign on the CPU pool, falling back to signing inline when it is saturated
         let raw_message = edited_message.unwrap_or(raw_message);
-        for signer in self
+        let signers = self
             .core
             .eval_if::<Vec<String>, _>(&ac.dkim.sign, self)
             .await
-            .unwrap_or_default()
-        {
-            if let Some(signer) = self.core.get_dkim_signer(&signer) {
-                match signer.sign_chained(&[headers.as_ref(), &raw_message]) {
-                    Ok(signature) => {
-                        signature.write_header(&mut headers);
-                    }
-                    Err(err) => {
-                        tracing::info!(parent: &self.span,
-                        context = "dkim",
-                        event = "sign-failed",
-                        return_path = message.return_path,
-                        "Failed to sign message: {}", err);
-                    }
+            .unwrap_or_default();
+        if !signers.is_empty() {
+            let core = self.core.clone();
+            let pool_signers = signers.clone();
+            let mut pool_headers = headers.clone();
+            let pool_message = raw_message.clone();
+            let errors = match cpu_pool()
+                .run(move || {
+                    let errors = core.dkim_sign(&pool_signers, &mut pool_headers, &pool_message);
+                    (pool_headers, errors)
+                })
+                .await
+            {
+                Some((signed_headers, errors)) => {
+                    headers = signed_headers;
+                    errors
                 }
+                None => self.core.dkim_sign(&signers, &mut headers, &raw_message),
+            };
+
+            for err in errors {
+                tracing::info!(parent: &self.span,
+                context = "dkim",
+                event = "sign-failed",
+                return_path = message.return_path,
+                "Failed to sign message: {}", err);
             }
         }
 
@@ -653,13 +978,117 @@ impl<T: SessionStream> Session<T> {
 
         // Verify queue quota
         if self.core.has_quota(&mut message).await {
+            // Sign return path
+            let batv = &self.core.queue.config.dsn.batv;
+            if !batv.keys.is_empty() && self.core.eval_if(&batv.sign, self).await.unwrap_or(false) {
+                // Use the configured signing key, defaulting to the newest one
+                let key_id = self.core.eval_if::<u64, _>(&batv.signing_key, self).await;
+                if let Some(key) = batv
+                    .keys
+                    .iter()
+                    .filter(|key| key_id.map_or(true, |id| id == key.id as u64))
+                    .max_by_key(|key| key.id)
+                {
+                    message.return_path = batv_sign(key, &message.return_path);
+                    message.return_path_lcase = message.return_path.to_lowercase();
+                }
+            }
+
             let queue_id = message.id;
+            let message_size = message.size;
+            let journals = self.core.journals_for(&message).await;
+            let return_path = if !journals.is_empty() {
+                message.return_path.clone()
+            } else {
+                String::new()
+            };
             if message
                 .queue(Some(&headers), &raw_message, &self.core, &self.span)
                 .await
             {
                 self.state = State::Accepted(queue_id);
                 self.data.messages_sent += 1;
+
+                // Record the disposition of the message for rules in monitor-only mode
+                for verdict in &mut self.data.monitor_verdicts {
+                    verdict.queue_id = queue_id.into();
+                    verdict.disposition = if quarantine.is_some() {
+                        Disposition::Quarantined
+                    } else {
+                        Disposition::Delivered
+                    }
+                    .into();
+                }
+
+                // Record DLP incident
+                if let Some(mut incident) = dlp_incident {
+                    incident.queue_id = queue_id.into();
+                    self.core.save_dlp_incident(incident).await;
+                }
+
+                // Copy message to the compliance journals
+                if !journals.is_empty() {
+                    self.core
+                        .journal_message(
+                            &journals,
+                            queue_id,
+                            &return_path,
+                            &headers,
+                            &raw_message,
+                            &self.span,
+                        )
+                        .await;
+                }
+
+                // Hold message for review
+                if let Some(mut entry) = quarantine {
+                    tracing::info!(parent: &self.span,
+                        context = "quarantine",
+                        event = "hold",
+                        id = queue_id,
+                        source = entry.source.as_str(),
+                        reason = entry.reason,
+                        "Message quarantined for review.");
+
+                    entry.size = message_size;
+                    self.core.quarantine_message(entry).await;
+                }
+
+                // Update usage counters
+                if let Some(account_id) = self.data.authenticated_id {
+                    let mut batch = BatchBuilder::new();
+                    batch
+                        .add(
+                            BillingClass::Counter {
+                                account_id,
+                                metric: BILLING_SENT_MESSAGES,
+                            },
+                            1,
+                        )
+                        .add(
+                            BillingClass::Counter {
+                                account_id,
+                                metric: BILLING_SENT_BYTES,
+                            },
+                            message_size as i64,
+                        );
+                    if let Err(err) = self
+                        .core
+                        .shared
+                        .default_data_store
+                        .write(batch.build())
+                        .await
+                    {
+                        tracing::warn!(
+                            parent: &self.span,
+                            context = "billing",
+                            event = "error",
+                            reason = ?err,
+                            "Failed to update usage counters."
+                        );
+                    }
+                }
+
                 (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
             } else {
                 (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into()
@@ -672,7 +1101,11 @@ impl<T: SessionStream> Session<T> {
                 from = message.return_path,
                 "Queue quota exceeded, rejecting message."
             );
-            (b"452 4.3.1 Mail system full, try again later.\r\n"[..]).into()
+            self.custom_response(
+                ResponseKind::OverQuota,
+                b"452 4.3.1 Mail system full, try again later.\r\n",
+            )
+            .await
         }
     }
 
@@ -779,6 +1212,7 @@ impl<T: SessionStream> Session<T> {
                     status: queue::Status::Scheduled,
                     domain: rcpt.domain,
                     disable_tls: false,
+                    tls_trace: vec![],
                 });
             }
 
@@ -805,7 +1239,7 @@ impl<T: SessionStream> Session<T> {
     }
 
     pub async fn can_send_data(&mut self) -> Result<bool, ()> {
-        if !self.data.rcpt_to.is_empty() {
+        if !self.data.rcpt_to.is_empty() || self.data.rcpt_honeypot > 0 {
             if self.data.messages_sent
                 < self
                     .core
@@ -0,0 +1,372 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::IpAddr;
+
+use mail_parser::{decoders::html::html_to_text, Message, MessageParser, PartType};
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize, IterateParams, Serialize, ValueKey,
+};
+
+use crate::{
+    config::{Dlp, DlpAction, DlpPattern, DlpRule},
+    core::SMTP,
+    queue::QueueId,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DlpIncident {
+    pub id: u64,
+    pub created: u64,
+    pub rule: String,
+    pub action: DlpAction,
+    pub status: IncidentStatus,
+    pub matches: Vec<DlpMatch>,
+    pub return_path: String,
+    pub recipients: Vec<String>,
+    pub authenticated_as: String,
+    pub remote_ip: IpAddr,
+    pub queue_id: Option<QueueId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DlpMatch {
+    pub pattern: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncidentStatus {
+    Rejected,
+    Quarantined,
+    Released,
+    Discarded,
+    Encrypted,
+}
+
+const MAX_NESTING: usize = 3;
+
+impl Dlp {
+    pub fn scan(&self, raw_message: &[u8]) -> Option<(&DlpRule, Vec<DlpMatch>)> {
+        let message = MessageParser::new().parse(raw_message)?;
+        let mut contents = Vec::new();
+        extract_text(&message, &mut contents, 0);
+
+        // Actions are declared from most to least severe
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let matches = rule
+                    .patterns
+                    .iter()
+                    .filter_map(|pattern| {
+                        let count = contents
+                            .iter()
+                            .map(|text| pattern.count_matches(text))
+                            .sum::<usize>();
+                        if count > 0 {
+                            Some(DlpMatch {
+                                pattern: pattern.name().to_string(),
+                                count,
+                            })
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if matches.iter().map(|m| m.count).sum::<usize>() >= rule.threshold {
+                    Some((rule, matches))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(rule, _)| rule.action)
+    }
+}
+
+impl DlpPattern {
+    pub fn name(&self) -> &str {
+        match self {
+            DlpPattern::CreditCard => "credit-card",
+            DlpPattern::Iban => "iban",
+            DlpPattern::Regex { name, .. } => name,
+        }
+    }
+
+    pub fn count_matches(&self, text: &str) -> usize {
+        match self {
+            DlpPattern::CreditCard => count_credit_cards(text),
+            DlpPattern::Iban => count_ibans(text),
+            DlpPattern::Regex { regex, .. } => regex.find_iter(text).count(),
+        }
+    }
+}
+
+impl SMTP {
+    pub async fn save_dlp_incident(&self, incident: DlpIncident) -> bool {
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::DlpIncident(incident.id)),
+            Bincode::new(incident).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "dlp",
+                event = "error",
+                "Failed to write incident: {}",
+                err
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn read_dlp_incident(&self, id: u64) -> Option<DlpIncident> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<DlpIncident>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::DlpIncident(id),
+            )))
+            .await
+        {
+            Ok(Some(incident)) => Some(incident.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "dlp",
+                    event = "error",
+                    "Failed to read incident from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_dlp_incidents(
+        &self,
+        filter: impl Fn(&DlpIncident) -> bool + Sync + Send,
+    ) -> store::Result<Vec<DlpIncident>> {
+        let mut incidents = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::DlpIncident(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::DlpIncident(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let incident = Bincode::<DlpIncident>::deserialize(value)?.inner;
+                    if filter(&incident) {
+                        incidents.push(incident);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| incidents)
+    }
+
+    pub async fn update_dlp_incident(&self, queue_id: QueueId, status: IncidentStatus) {
+        match self
+            .list_dlp_incidents(|incident| incident.queue_id == Some(queue_id))
+            .await
+        {
+            Ok(incidents) => {
+                for mut incident in incidents {
+                    incident.status = status;
+                    self.save_dlp_incident(incident).await;
+                }
+            }
+            Err(err) => {
+                tracing::error!(
+                    context = "dlp",
+                    event = "error",
+                    "Failed to read incidents from store: {}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+impl IncidentStatus {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rejected" => Some(IncidentStatus::Rejected),
+            "quarantined" => Some(IncidentStatus::Quarantined),
+            "released" => Some(IncidentStatus::Released),
+            "discarded" => Some(IncidentStatus::Discarded),
+            "encrypted" => Some(IncidentStatus::Encrypted),
+            _ => None,
+        }
+    }
+}
+
+impl DlpIncident {
+    pub fn new(id: u64, rule: &DlpRule, matches: Vec<DlpMatch>, remote_ip: IpAddr) -> Self {
+        DlpIncident {
+            id,
+            created: now(),
+            rule: rule.id.clone(),
+            action: rule.action,
+            status: match rule.action {
+                DlpAction::Reject => IncidentStatus::Rejected,
+                DlpAction::Quarantine => IncidentStatus::Quarantined,
+                DlpAction::Encrypt => IncidentStatus::Encrypted,
+            },
+            matches,
+            return_path: String::new(),
+            recipients: Vec::new(),
+            authenticated_as: String::new(),
+            remote_ip,
+            queue_id: None,
+        }
+    }
+}
+
+fn extract_text(message: &Message<'_>, contents: &mut Vec<String>, depth: usize) {
+    for part in &message.parts {
+        match &part.body {
+            PartType::Text(text) => contents.push(text.to_string()),
+            PartType::Html(html) => contents.push(html_to_text(html)),
+            PartType::Binary(bytes) | PartType::InlineBinary(bytes) => {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    contents.push(text.to_string());
+                }
+            }
+            PartType::Message(nested) if depth < MAX_NESTING => {
+                extract_text(nested, contents, depth + 1);
+            }
+            _ => (),
+        }
+    }
+}
+
+fn count_credit_cards(text: &str) -> usize {
+    let mut count = 0;
+    let mut digits = Vec::with_capacity(19);
+    let mut last_is_separator = false;
+
+    for ch in text.chars().chain([' ']) {
+        match ch {
+            '0'..='9' => {
+                digits.push(ch as u8 - b'0');
+                last_is_separator = false;
+            }
+            ' ' | '-' if !digits.is_empty() && !last_is_separator => {
+                last_is_separator = true;
+            }
+            _ => {
+                if is_credit_card(&digits) {
+                    count += 1;
+                }
+                digits.clear();
+                last_is_separator = false;
+            }
+        }
+    }
+
+    count
+}
+
+fn is_credit_card(digits: &[u8]) -> bool {
+    if !(13..=19).contains(&digits.len()) || digits.iter().all(|d| *d == digits[0]) {
+        return false;
+    }
+
+    // Luhn check
+    let mut sum = 0;
+    for (pos, &digit) in digits.iter().rev().enumerate() {
+        let mut digit = digit as u32;
+        if pos % 2 == 1 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+    sum % 10 == 0
+}
+
+fn count_ibans(text: &str) -> usize {
+    let text = text.as_bytes();
+    let mut count = 0;
+    let mut pos = 0;
+
+    while pos + 4 <= text.len() {
+        if text[pos].is_ascii_uppercase()
+            && text[pos + 1].is_ascii_uppercase()
+            && text[pos + 2].is_ascii_digit()
+            && text[pos + 3].is_ascii_digit()
+            && (pos == 0 || !text[pos - 1].is_ascii_alphanumeric())
+        {
+            // Collect up to 34 alphanumeric characters, allowing single space separators
+            let mut iban = Vec::with_capacity(34);
+            let mut offsets = Vec::with_capacity(34);
+            let mut end = pos;
+            while end < text.len() && iban.len() < 34 {
+                let ch = text[end];
+                if ch.is_ascii_alphanumeric() {
+                    iban.push(ch.to_ascii_uppercase());
+                    offsets.push(end + 1);
+                } else if ch != b' '
+                    || !text
+                        .get(end + 1)
+                        .map_or(false, |ch| ch.is_ascii_alphanumeric())
+                {
+                    break;
+                }
+                end += 1;
+            }
+
+            // Try the longest valid candidate
+            if let Some(len) = (15..=iban.len()).rev().find(|len| is_iban(&iban[..*len])) {
+                count += 1;
+                pos = offsets[len - 1];
+                continue;
+            }
+        }
+        pos += 1;
+    }
+
+    count
+}
+
+fn is_iban(iban: &[u8]) -> bool {
+    let mut remainder = 0u32;
+    for &ch in iban[4..].iter().chain(iban[..4].iter()) {
+        remainder = match ch {
+            b'0'..=b'9' => (remainder * 10 + (ch - b'0') as u32) % 97,
+            b'A'..=b'Z' => (remainder * 100 + (ch - b'A' + 10) as u32) % 97,
+            _ => return false,
+        };
+    }
+    remainder == 1
+}
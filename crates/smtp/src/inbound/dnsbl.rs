@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, net::Ipv4Addr};
+
+use futures::future::join_all;
+use mail_auth::common::resolver::ToReverseName;
+use utils::listener::SessionStream;
+
+use crate::{
+    config::{DnsblAction, DnsblKind, DnsblStage, DnsblZone},
+    core::{Session, SessionData},
+};
+
+use super::response::ResponseKind;
+
+#[derive(Debug, Clone)]
+pub struct DnsblHit {
+    pub zone: String,
+    pub stage: DnsblStage,
+    pub weight: f64,
+    pub code: Ipv4Addr,
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Queries the blocklists configured for a stage concurrently and returns
+    /// the response to send when the accumulated score requires a rejection.
+    pub async fn check_dnsbl(&mut self, stage: DnsblStage) -> Option<Cow<'static, [u8]>> {
+        // Results from a previous EHLO or transaction no longer apply
+        self.data.dnsbl_hits.retain(|hit| hit.stage != stage);
+
+        let core = self.core.clone();
+        let config = &core.session.config.dnsbl;
+        if !config.zones.iter().any(|zone| zone.stage == stage)
+            || !core.eval_if(&config.enable, self).await.unwrap_or(false)
+        {
+            return None;
+        }
+
+        // IP zones are queried for the remote IP, domain zones for the EHLO or sender domain
+        let remote_ip = self.data.remote_ip.to_reverse_name();
+        let domain = match stage {
+            DnsblStage::Connect => None,
+            DnsblStage::Ehlo => Some(self.data.helo_domain.to_lowercase()),
+            DnsblStage::MailFrom => self
+                .data
+                .mail_from
+                .as_ref()
+                .map(|mail_from| mail_from.domain.clone()),
+        }
+        .filter(|domain| domain.contains('.') && !domain.starts_with('['));
+        let session = &*self;
+        let results = join_all(
+            config
+                .zones
+                .iter()
+                .filter(|zone| zone.stage == stage)
+                .filter_map(|zone| {
+                    let name = match zone.kind {
+                        DnsblKind::Ip => format!("{remote_ip}.{}", zone.zone),
+                        DnsblKind::Domain => format!("{}.{}", domain.as_ref()?, zone.zone),
+                    };
+                    Some(async move { (zone, session.dnsbl_lookup(zone, name).await) })
+                }),
+        )
+        .await;
+
+        for (zone, code) in results {
+            if let Some(code) = code {
+                tracing::debug!(parent: &self.span,
+                    context = "dnsbl",
+                    event = "listed",
+                    zone = zone.zone,
+                    code = %code,
+                    weight = zone.weight);
+
+                self.data.dnsbl_hits.push(DnsblHit {
+                    zone: zone.zone.clone(),
+                    stage,
+                    weight: zone.weight,
+                    code,
+                });
+            }
+        }
+
+        if self.dnsbl_action().await == DnsblAction::Reject {
+            tracing::info!(parent: &self.span,
+                context = "dnsbl",
+                event = "reject",
+                remote_ip = %self.data.remote_ip,
+                score = self.data.dnsbl_score());
+
+            Some(
+                self.custom_response(
+                    ResponseKind::PolicyReject,
+                    if stage == DnsblStage::Connect {
+                        &b"554 5.7.1 Client host listed on a DNS blocklist.\r\n"[..]
+                    } else {
+                        &b"550 5.7.1 Rejected, listed on a DNS blocklist.\r\n"[..]
+                    },
+                )
+                .await,
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Evaluates the configured action against the score accumulated so far.
+    pub async fn dnsbl_action(&self) -> DnsblAction {
+        if self.data.dnsbl_hits.is_empty() {
+            return DnsblAction::Accept;
+        }
+
+        self.core
+            .eval_if(&self.core.session.config.dnsbl.action, self)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn dnsbl_lookup(&self, zone: &DnsblZone, name: String) -> Option<Ipv4Addr> {
+        let store = &self.core.shared.default_lookup_store;
+        let key = format!("bl:{name}").into_bytes();
+
+        // Cached results store the listing code, or zero when not listed
+        match store.key_get::<i64>(key.clone()).await {
+            Ok(Some(code)) => return (code != 0).then(|| Ipv4Addr::from(code as u32)),
+            Ok(None) => (),
+            Err(err) => {
+                tracing::debug!(parent: &self.span,
+                    context = "dnsbl",
+                    event = "error",
+                    zone = zone.zone,
+                    "Failed to read cached blocklist result: {}", err);
+            }
+        }
+
+        let code = match self.core.resolvers.dns.ipv4_lookup(name.as_str()).await {
+            Ok(codes) => codes.iter().find(|code| zone.is_listing(code)).copied(),
+            Err(mail_auth::Error::DnsRecordNotFound(_)) => None,
+            Err(err) => {
+                // Do not cache transient failures
+                tracing::debug!(parent: &self.span,
+                    context = "dnsbl",
+                    event = "error",
+                    zone = zone.zone,
+                    "Failed to query blocklist: {}", err);
+                return None;
+            }
+        };
+
+        if let Err(err) = store
+            .key_set(
+                key,
+                i64::from(code.map_or(0, u32::from)).to_be_bytes().to_vec(),
+                Some(self.core.session.config.dnsbl.cache_ttl.as_secs()),
+            )
+            .await
+        {
+            tracing::debug!(parent: &self.span,
+                context = "dnsbl",
+                event = "error",
+                zone = zone.zone,
+                "Failed to cache blocklist result: {}", err);
+        }
+
+        code
+    }
+
+    pub fn write_dnsbl_header(&self, headers: &mut Vec<u8>) {
+        headers.extend_from_slice(format!("X-DNSBL: score={}", self.data.dnsbl_score()).as_bytes());
+        for (pos, hit) in self.data.dnsbl_hits.iter().enumerate() {
+            headers.extend_from_slice(if pos == 0 {
+                &b"; listed="[..]
+            } else {
+                &b",\r\n\t"[..]
+            });
+            headers.extend_from_slice(format!("{} ({})", hit.zone, hit.code).as_bytes());
+        }
+        headers.extend_from_slice(b"\r\n");
+    }
+}
+
+impl SessionData {
+    pub fn dnsbl_score(&self) -> f64 {
+        self.dnsbl_hits.iter().map(|hit| hit.weight).sum()
+    }
+}
+
+impl DnsblZone {
+    fn is_listing(&self, code: &Ipv4Addr) -> bool {
+        if !self.codes.is_empty() {
+            self.codes.contains(code)
+        } else {
+            // Answers outside 127.0.0.0/8 or within 127.255.255.0/24 signal query errors
+            let octets = code.octets();
+            octets[0] == 127 && !(octets[1] == 255 && octets[2] == 255)
+        }
+    }
+}
@@ -23,7 +23,11 @@
 
 use std::time::{Duration, SystemTime};
 
-use crate::{config::session::Mechanism, core::Session, scripts::ScriptResult};
+use crate::{
+    config::{session::Mechanism, DnsblStage},
+    core::Session,
+    scripts::ScriptResult,
+};
 use mail_auth::spf::verify::HasLabels;
 use smtp_proto::*;
 use utils::listener::SessionStream;
@@ -103,6 +107,14 @@ impl<T: SessionStream> Session<T> {
                 }
             }
 
+            // DNS blocklists
+            if let Some(response) = self.check_dnsbl(DnsblStage::Ehlo).await {
+                self.data.mail_from = None;
+                self.data.helo_domain = prev_helo_domain;
+                self.data.spf_ehlo = None;
+                return self.write(&response).await;
+            }
+
             tracing::debug!(parent: &self.span,
                 context = "ehlo",
                 event = "ehlo",
@@ -180,8 +192,12 @@ impl<T: SessionStream> Session<T> {
                 .unwrap_or_default()
                 .into();
             if response.auth_mechanisms != 0 {
-                if !self.stream.is_tls() && !self.params.auth_plain_text {
-                    response.auth_mechanisms &= !(AUTH_PLAIN | AUTH_LOGIN);
+                if !self.stream.is_tls() {
+                    response.auth_mechanisms &= !self.params.auth_require_tls;
+                }
+                if self.stream.tls_exporter().is_none() {
+                    // Channel binding is only available on TLS 1.3 sessions
+                    response.auth_mechanisms &= !AUTH_SCRAM_SHA_256_PLUS;
                 }
                 if response.auth_mechanisms != 0 {
                     response.capabilities |= EXT_AUTH;
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_builder::{
+    headers::content_type::ContentType,
+    mime::{BodyPart, MimePart},
+};
+use mail_parser::PartType;
+
+use crate::config::Footer;
+
+use super::mime::MimeRewrite;
+
+impl Footer {
+    pub fn apply(&self, raw_message: &[u8]) -> Option<Vec<u8>> {
+        let mut rewrite = MimeRewrite::parse(raw_message)?;
+
+        // Do not alter signed or encrypted messages
+        if rewrite.is_signed_or_encrypted() {
+            return None;
+        }
+
+        // Collect the inline text parts
+        let message = rewrite.message();
+        let mut parts = message
+            .text_body
+            .iter()
+            .chain(message.html_body.iter())
+            .filter_map(|part_id| {
+                let part = message.parts.get(*part_id)?;
+                match &part.body {
+                    PartType::Text(text) => {
+                        Some((*part_id, part.offset_header, text.as_ref(), false))
+                    }
+                    PartType::Html(html) => {
+                        Some((*part_id, part.offset_header, html.as_ref(), true))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        parts.sort_by_key(|(_, offset, _, is_html)| (*offset, *is_html));
+        parts.dedup_by_key(|(_, offset, _, _)| *offset);
+        if parts.is_empty() {
+            return None;
+        }
+
+        // Add the footer only once per thread
+        if self.once_per_thread
+            && (!message.in_reply_to().is_empty() || !message.references().is_empty())
+        {
+            if let Some(marker) = self.text.lines().map(str::trim).find(|l| !l.is_empty()) {
+                if parts.iter().any(|(_, _, text, _)| text.contains(marker)) {
+                    return None;
+                }
+            }
+        }
+
+        // Write the parts with the footer added
+        let parts = parts
+            .into_iter()
+            .map(|(part_id, _, text, is_html)| {
+                if is_html {
+                    (part_id, "text/html", self.append_html(text))
+                } else {
+                    (part_id, "text/plain", self.append_text(text))
+                }
+            })
+            .collect::<Vec<_>>();
+        for (part_id, content_type, body) in parts {
+            rewrite.replace_part(
+                part_id,
+                MimePart::new(ContentType::new(content_type), BodyPart::Text(body.into())),
+            );
+        }
+
+        if rewrite.has_changes() {
+            Some(rewrite.build())
+        } else {
+            None
+        }
+    }
+
+    fn append_text(&self, text: &str) -> String {
+        let mut body = String::with_capacity(text.len() + self.text.len() + 4);
+        body.push_str(text);
+        if !text.is_empty() && !text.ends_with('\n') {
+            body.push_str("\r\n");
+        }
+        body.push_str("\r\n");
+        body.push_str(&self.text);
+        body
+    }
+
+    fn append_html(&self, html: &str) -> String {
+        let footer = self.html.clone().unwrap_or_else(|| {
+            let mut footer = String::with_capacity(self.text.len() + 32);
+            footer.push_str("<p>");
+            for (pos, line) in self.text.lines().enumerate() {
+                if pos > 0 {
+                    footer.push_str("<br>");
+                }
+                for ch in line.chars() {
+                    match ch {
+                        '<' => footer.push_str("&lt;"),
+                        '>' => footer.push_str("&gt;"),
+                        '&' => footer.push_str("&amp;"),
+                        '"' => footer.push_str("&quot;"),
+                        _ => footer.push(ch),
+                    }
+                }
+            }
+            footer.push_str("</p>");
+            footer
+        });
+
+        // Insert the footer before the closing body tag
+        let pos = html
+            .to_ascii_lowercase()
+            .rfind("</body>")
+            .unwrap_or(html.len());
+        let mut body = String::with_capacity(html.len() + footer.len());
+        body.push_str(&html[..pos]);
+        body.push_str(&footer);
+        body.push_str(&html[pos..]);
+        body
+    }
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_auth::SpfResult;
+use store::write::now;
+use utils::listener::SessionStream;
+
+use crate::{
+    config::Greylist,
+    core::{Session, SessionData},
+};
+
+impl<T: SessionStream> Session<T> {
+    /// Returns true when the (remote IP, sender, recipient) triplet of the
+    /// last recipient was first seen less than the configured delay ago.
+    pub async fn is_greylisted(&self) -> bool {
+        let config = &self.core.session.config.rcpt.greylist;
+        let key = if let Some(key) = self.data.greylist_key(config) {
+            key
+        } else {
+            return false;
+        };
+        let store = &self.core.shared.default_lookup_store;
+        let now = now();
+
+        match store.key_get::<i64>(key.clone()).await {
+            Ok(Some(first_seen)) => {
+                if now.saturating_sub(first_seen as u64) < config.delay.as_secs() {
+                    tracing::debug!(parent: &self.span,
+                        context = "greylist",
+                        event = "retry-too-soon",
+                        remote_ip = %self.data.remote_ip,
+                        "Triplet retried before the greylisting delay.");
+                    return true;
+                }
+
+                // Keep the triplet for as long as the sender keeps using it
+                if let Err(err) = store
+                    .key_set(
+                        key,
+                        first_seen.to_be_bytes().to_vec(),
+                        Some(config.ttl.as_secs()),
+                    )
+                    .await
+                {
+                    tracing::warn!(parent: &self.span,
+                        context = "greylist",
+                        event = "error",
+                        reason = ?err,
+                        "Failed to refresh greylisting triplet.");
+                }
+
+                false
+            }
+            Ok(None) => {
+                tracing::debug!(parent: &self.span,
+                    context = "greylist",
+                    event = "greylisted",
+                    remote_ip = %self.data.remote_ip,
+                    "First delivery attempt for triplet.");
+
+                if let Err(err) = store
+                    .key_set(
+                        key,
+                        (now as i64).to_be_bytes().to_vec(),
+                        Some(config.ttl.as_secs()),
+                    )
+                    .await
+                {
+                    tracing::warn!(parent: &self.span,
+                        context = "greylist",
+                        event = "error",
+                        reason = ?err,
+                        "Failed to store greylisting triplet.");
+
+                    // Retries would be deferred forever if the triplet cannot be stored
+                    return false;
+                }
+
+                true
+            }
+            Err(err) => {
+                tracing::warn!(parent: &self.span,
+                    context = "greylist",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to obtain greylisting triplet.");
+                false
+            }
+        }
+    }
+}
+
+impl SessionData {
+    /// Returns the lookup key of the triplet of the last recipient, or `None`
+    /// when the client is exempt from greylisting.
+    pub fn greylist_key(&self, config: &Greylist) -> Option<Vec<u8>> {
+        if config
+            .whitelist_networks
+            .iter()
+            .any(|network| network.matches(&self.remote_ip))
+            || (config.whitelist_spf
+                && self
+                    .spf_mail_from
+                    .as_ref()
+                    .map_or(false, |spf| spf.result() == SpfResult::Pass))
+        {
+            return None;
+        }
+
+        format!(
+            "gt:{}:{}:{}",
+            self.remote_ip,
+            self.mail_from
+                .as_ref()
+                .map_or("", |mail_from| mail_from.address_lcase.as_str()),
+            self.rcpt_to.last()?.address_lcase
+        )
+        .into_bytes()
+        .into()
+    }
+}
@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_auth::SpfResult;
+use utils::listener::SessionStream;
+
+use crate::{
+    core::{Session, SessionAddress},
+    scripts::functions::email::domain_sld,
+};
+
+impl<T: SessionStream> Session<T> {
+    pub async fn handle_honeypot_rcpt(&mut self, rcpt: SessionAddress) {
+        tracing::info!(parent: &self.span,
+            context = "honeypot",
+            event = "hit",
+            address = &rcpt.address_lcase,
+            "Recipient is a honeypot address, message will be discarded.");
+        self.data.rcpt_honeypot += 1;
+
+        // Only distinct honeypot addresses are counted, so that a sender
+        // repeating the same typo does not reach the thresholds
+        let hits = match self.register_honeypot_hit(&rcpt).await {
+            Ok(Some(hits)) => hits as u64,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!(parent: &self.span,
+                    context = "honeypot",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to record honeypot hit.");
+                return;
+            }
+        };
+
+        let config = &self.core.session.config.rcpt.honeypot;
+        if hits >= config.penalize_after {
+            let score = config.score;
+            self.core
+                .update_reputation(format!("i:{}", self.data.remote_ip), score)
+                .await;
+
+            // Forged sender domains are penalized under a separate token,
+            // as done by the spam filter for messages failing DMARC
+            if let Some(domain) = self
+                .data
+                .mail_from
+                .as_ref()
+                .map(|mail_from| mail_from.domain.as_str())
+                .filter(|domain| !domain.is_empty())
+            {
+                let domain = domain_sld(domain, &self.core.sieve.runtime.context().psl);
+                let token = if self
+                    .data
+                    .spf_mail_from
+                    .as_ref()
+                    .map_or(false, |spf| spf.result() == SpfResult::Pass)
+                {
+                    format!("d:{domain}")
+                } else {
+                    format!("d:_{domain}")
+                };
+                self.core.update_reputation(token, score).await;
+            }
+        }
+
+        let config = &self.core.session.config.rcpt.honeypot;
+        let blocked_ips = &self.core.shared.default_directory.blocked_ips;
+        if config.ban_after > 0
+            && hits >= config.ban_after
+            && !blocked_ips.is_blocked(&self.data.remote_ip)
+        {
+            tracing::info!(parent: &self.span,
+                context = "honeypot",
+                event = "ban",
+                remote_ip = ?self.data.remote_ip,
+                hits = hits,
+                "IP address blocked after too many honeypot hits.");
+
+            let banned = blocked_ips.block_ip(self.data.remote_ip);
+            if let Err(err) = self
+                .core
+                .shared
+                .default_data_store
+                .config_set([banned].into_iter())
+                .await
+            {
+                tracing::warn!(parent: &self.span,
+                    context = "honeypot",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to persist blocked IP address.");
+            }
+        }
+    }
+
+    /// Records a honeypot hit from the remote IP, returning the number of
+    /// distinct honeypot addresses it has hit within the configured window,
+    /// or `None` if this address was already counted.
+    async fn register_honeypot_hit(&self, rcpt: &SessionAddress) -> store::Result<Option<i64>> {
+        let store = &self.core.shared.default_lookup_store;
+        let window = self.core.session.config.rcpt.honeypot.window.as_secs();
+        let hit_key = format!("t:{}:{}", self.data.remote_ip, rcpt.address_lcase).into_bytes();
+
+        if !store.key_exists(hit_key.clone()).await? {
+            store.key_set(hit_key, vec![], Some(window)).await?;
+            store
+                .counter_incr(
+                    format!("t:{}", self.data.remote_ip).into_bytes(),
+                    1,
+                    Some(window),
+                )
+                .await
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
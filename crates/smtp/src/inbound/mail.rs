@@ -28,7 +28,9 @@ use smtp_proto::{MailFrom, MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQU
 use utils::{config::Rate, listener::SessionStream};
 
 use crate::{
+    config::DnsblStage,
     core::{Session, SessionAddress},
+    inbound::response::ResponseKind,
     queue::DomainPart,
     scripts::{ScriptModification, ScriptResult},
 };
@@ -179,6 +181,12 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // DNS blocklists
+        if let Some(response) = self.check_dnsbl(DnsblStage::MailFrom).await {
+            self.data.mail_from = None;
+            return self.write(&response).await;
+        }
+
         // Validate parameters
         let config = &self.core.session.config.extensions;
         let config_data = &self.core.session.config.data;
@@ -360,9 +368,14 @@ impl<T: SessionStream> Session<T> {
             self.eval_rcpt_params().await;
             self.write(b"250 2.1.0 OK\r\n").await
         } else {
+            let response = self
+                .custom_response(
+                    ResponseKind::RateLimited,
+                    b"451 4.4.5 Rate limit exceeded, try again later.\r\n",
+                )
+                .await;
             self.data.mail_from = None;
-            self.write(b"451 4.4.5 Rate limit exceeded, try again later.\r\n")
-                .await
+            self.write(&response).await
         }
     }
 
@@ -392,7 +405,7 @@ impl<T: SessionStream> Session<T> {
         if let (Some(recipient), Some(rate)) = (
             spf_output.report_address(),
             self.core
-                .eval_if::<Rate, _>(&self.core.report.config.spf.send, self)
+                .eval_if::<Rate, _>(&self.core.report.config.load_full().spf.send, self)
                 .await,
         ) {
             self.send_spf_report(recipient, &rate, !result, spf_output)
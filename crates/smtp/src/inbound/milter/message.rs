@@ -29,7 +29,7 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use utils::listener::SessionStream;
 
 use crate::{
-    config::Milter,
+    config::{Milter, MilterAction, MilterStage},
     core::{Session, SessionAddress, SessionData},
     inbound::milter::MilterClient,
     queue::DomainPart,
@@ -39,7 +39,7 @@ use crate::{
 use super::{Action, Error, Macros, Modification};
 
 enum Rejection {
-    Action(Action),
+    Action(Action, MilterStage),
     Error(Error),
 }
 
@@ -75,16 +75,41 @@ impl<T: SessionStream> Session<T> {
                         modifications = new_modifications;
                     }
                 }
-                Err(Rejection::Action(action)) => {
+                Err(Rejection::Action(action, stage)) => {
                     tracing::info!(
                         parent: &self.span,
                         milter.host = &milter.hostname,
                         milter.port = &milter.port,
                         context = "milter",
                         event = "reject",
+                        stage = stage.as_str(),
                         action = ?action,
                         "Milter rejected message.");
 
+                    // Apply any configured override of the milter's verdict
+                    let action = match (
+                        milter.actions.get(&stage).copied().unwrap_or_default(),
+                        action,
+                    ) {
+                        (_, action @ (Action::Shutdown | Action::ConnectionFailure))
+                        | (MilterAction::Milter, action) => action,
+                        (MilterAction::Accept, _) => continue,
+                        (MilterAction::AddHeader, action) => {
+                            modifications.push(Modification::AddHeader {
+                                name: "X-Milter-Action".to_string(),
+                                value: format!(
+                                    "{}; stage={}; action={}",
+                                    milter.hostname,
+                                    stage.as_str(),
+                                    action.as_str()
+                                ),
+                            });
+                            continue;
+                        }
+                        (MilterAction::Reject, _) => Action::Reject,
+                        (MilterAction::TempFail, _) => Action::TempFail,
+                    };
+
                     return Err(match action {
                         Action::Discard => {
                             (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
@@ -184,7 +209,7 @@ impl<T: SessionStream> Session<T> {
                     .with_client_ptr(client_ptr.map(|p| p.as_str()).unwrap_or("unknown")),
             )
             .await?
-            .assert_continue()?;
+            .assert_continue(MilterStage::Connect)?;
 
         // EHLO/HELO
         let (tls_version, tls_ciper) = self.stream.tls_version_and_cipher();
@@ -196,7 +221,7 @@ impl<T: SessionStream> Session<T> {
                     .with_tls_version(tls_version.as_ref()),
             )
             .await?
-            .assert_continue()?;
+            .assert_continue(MilterStage::Ehlo)?;
 
         // Mail from
         let addr = &self.data.mail_from.as_ref().unwrap().address_lcase;
@@ -209,7 +234,7 @@ impl<T: SessionStream> Session<T> {
                     .with_sasl_login_name(&self.data.authenticated_as),
             )
             .await?
-            .assert_continue()?;
+            .assert_continue(MilterStage::MailFrom)?;
 
         // Rcpt to
         for rcpt in &self.data.rcpt_to {
@@ -220,11 +245,11 @@ impl<T: SessionStream> Session<T> {
                     Macros::new().with_rcpt_address(&rcpt.address_lcase),
                 )
                 .await?
-                .assert_continue()?;
+                .assert_continue(MilterStage::RcptTo)?;
         }
 
         // Data
-        client.data().await?.assert_continue()?;
+        client.data().await?.assert_continue(MilterStage::Data)?;
 
         // Headers
         client
@@ -235,11 +260,11 @@ impl<T: SessionStream> Session<T> {
                 )
             }))
             .await?
-            .assert_continue()?;
+            .assert_continue(MilterStage::Headers)?;
 
         // Message body
         let (action, modifications) = client.body(message.raw_message()).await?;
-        action.assert_continue()?;
+        action.assert_continue(MilterStage::Body)?;
 
         // Quit
         let _ = client.quit().await;
@@ -480,10 +505,23 @@ impl SessionData {
 }
 
 impl Action {
-    fn assert_continue(self) -> Result<(), Rejection> {
+    fn assert_continue(self, stage: MilterStage) -> Result<(), Rejection> {
         match self {
             Action::Continue | Action::Accept => Ok(()),
-            action => Err(Rejection::Action(action)),
+            action => Err(Rejection::Action(action, stage)),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Accept => "accept",
+            Action::Continue => "continue",
+            Action::Discard => "discard",
+            Action::Reject => "reject",
+            Action::TempFail => "tempfail",
+            Action::ReplyCode { .. } => "reply-code",
+            Action::Shutdown => "shutdown",
+            Action::ConnectionFailure => "connection-failure",
         }
     }
 }
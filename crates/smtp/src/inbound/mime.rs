@@ -0,0 +1,342 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_builder::{
+    headers::content_type::ContentType,
+    mime::{BodyPart, MimePart},
+};
+use mail_parser::{Message, MessageParser, MimeHeaders, PartType};
+
+/// Applies edits to the MIME structure of a message while copying every
+/// byte that is not affected by an edit verbatim. The output only depends
+/// on the input message and the requested edits.
+pub struct MimeRewrite<'x> {
+    raw_message: &'x [u8],
+    message: Message<'x>,
+    edits: Vec<Edit>,
+    attachments: Vec<Vec<u8>>,
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    bytes: Vec<u8>,
+}
+
+impl<'x> MimeRewrite<'x> {
+    pub fn parse(raw_message: &'x [u8]) -> Option<Self> {
+        Some(MimeRewrite {
+            message: MessageParser::new().parse(raw_message)?,
+            raw_message,
+            edits: Vec::new(),
+            attachments: Vec::new(),
+        })
+    }
+
+    pub fn message(&self) -> &Message<'x> {
+        &self.message
+    }
+
+    /// Signed or encrypted messages cannot be modified without breaking them.
+    pub fn is_signed_or_encrypted(&self) -> bool {
+        self.message.parts.iter().any(|part| {
+            part.content_type().map_or(false, |ct| {
+                let subtype = ct.subtype().unwrap_or_default();
+                match ct.ctype() {
+                    "multipart" => {
+                        subtype.eq_ignore_ascii_case("signed")
+                            || subtype.eq_ignore_ascii_case("encrypted")
+                    }
+                    "application" => {
+                        subtype.eq_ignore_ascii_case("pkcs7-mime")
+                            || subtype.eq_ignore_ascii_case("x-pkcs7-mime")
+                            || subtype.eq_ignore_ascii_case("pgp-encrypted")
+                    }
+                    _ => false,
+                }
+            }) || matches!(&part.body, PartType::Text(text) if text.contains("-----BEGIN PGP "))
+        })
+    }
+
+    /// Replaces the contents of a part. Headers not describing the content
+    /// are preserved unless the new part defines them.
+    pub fn replace_part(&mut self, part_id: usize, part: MimePart<'_>) -> bool {
+        let Some(old_part) = self.message.parts.get(part_id) else {
+            return false;
+        };
+        let Some(headers) = self
+            .raw_message
+            .get(old_part.offset_header..old_part.offset_body)
+        else {
+            return false;
+        };
+
+        let mut bytes = Vec::with_capacity(old_part.offset_end - old_part.offset_header);
+        let has_mime_version = copy_headers(headers, &mut bytes, |name| {
+            name.eq_ignore_ascii_case("Content-Type")
+                || name.eq_ignore_ascii_case("Content-Transfer-Encoding")
+                || part
+                    .headers
+                    .iter()
+                    .any(|(header, _)| header.eq_ignore_ascii_case(name))
+        });
+        if part_id == 0 && !has_mime_version {
+            bytes.extend_from_slice(b"MIME-Version: 1.0\r\n");
+        }
+        if part.write_part(&mut bytes).is_err() {
+            return false;
+        }
+        if !bytes.ends_with(b"\n")
+            && !matches!(
+                self.raw_message.get(old_part.offset_end),
+                Some(b'\r' | b'\n') | None
+            )
+        {
+            bytes.extend_from_slice(b"\r\n");
+        }
+
+        self.edits.push(Edit {
+            start: old_part.offset_header,
+            end: old_part.offset_end,
+            bytes,
+        });
+        true
+    }
+
+    /// Decodes a part and encodes it again using the encoding that best
+    /// fits its contents. Text parts are converted to UTF-8.
+    pub fn reencode_part(&mut self, part_id: usize) -> bool {
+        let Some(old_part) = self.message.parts.get(part_id) else {
+            return false;
+        };
+        let (body, is_text) = match &old_part.body {
+            PartType::Text(text) | PartType::Html(text) => {
+                (BodyPart::Text(text.to_string().into()), true)
+            }
+            PartType::Binary(bytes) | PartType::InlineBinary(bytes) => {
+                (BodyPart::Binary(bytes.to_vec().into()), false)
+            }
+            PartType::Message(_) | PartType::Multipart(_) => return false,
+        };
+
+        let mut content_type = ContentType::new(
+            old_part
+                .content_type()
+                .map(|ct| match ct.subtype() {
+                    Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                    None => ct.ctype().to_string(),
+                })
+                .unwrap_or_else(|| "text/plain".to_string()),
+        );
+        if is_text {
+            content_type = content_type.attribute("charset", "utf-8");
+        }
+        if let Some(attributes) = old_part.content_type().and_then(|ct| ct.attributes()) {
+            for (name, value) in attributes {
+                if !name.eq_ignore_ascii_case("charset") {
+                    content_type = content_type.attribute(name.to_string(), value.to_string());
+                }
+            }
+        }
+
+        self.replace_part(part_id, MimePart::new(content_type, body))
+    }
+
+    /// Removes a part from its enclosing multipart.
+    pub fn remove_part(&mut self, part_id: usize) -> bool {
+        let Some(part) = self.message.parts.get(part_id).filter(|_| part_id > 0) else {
+            return false;
+        };
+
+        // Remove the boundary line that precedes the part
+        let mut pos = part.offset_header;
+        if pos > 0 && self.raw_message[pos - 1] == b'\n' {
+            pos -= 1;
+        }
+        if pos > 0 && self.raw_message[pos - 1] == b'\r' {
+            pos -= 1;
+        }
+        let start = self.raw_message[..pos]
+            .iter()
+            .rposition(|&ch| ch == b'\n')
+            .map_or(0, |pos| pos + 1);
+        if !self.raw_message[start..].starts_with(b"--") {
+            return false;
+        }
+
+        // Remove the line break that belongs to the next boundary
+        let mut end = part.offset_end;
+        if self.raw_message.get(end) == Some(&b'\r') {
+            end += 1;
+        }
+        if self.raw_message.get(end) == Some(&b'\n') {
+            end += 1;
+        }
+
+        self.edits.push(Edit {
+            start,
+            end,
+            bytes: Vec::new(),
+        });
+        true
+    }
+
+    /// Adds a part to the message, converting it to multipart/mixed when needed.
+    pub fn add_part(&mut self, part: MimePart<'_>) -> bool {
+        let mut bytes = Vec::new();
+        if part.write_part(&mut bytes).is_err() {
+            return false;
+        }
+        if bytes.ends_with(b"\r\n") {
+            bytes.truncate(bytes.len() - 2);
+        }
+        self.attachments.push(bytes);
+        true
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.edits.is_empty() || !self.attachments.is_empty()
+    }
+
+    /// Builds the rewritten message. Edits to parts that are contained in a
+    /// part that was already removed or replaced are ignored.
+    pub fn build(mut self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.raw_message.len() + 1024);
+        let mut last_offset = 0;
+
+        // Stable sort, insertions at the same offset keep their order
+        self.edits.sort_by_key(|edit| (edit.start, edit.end));
+        for edit in &self.edits {
+            if edit.start < last_offset {
+                continue;
+            }
+            output.extend_from_slice(&self.raw_message[last_offset..edit.start]);
+            output.extend_from_slice(&edit.bytes);
+            last_offset = edit.end;
+        }
+        output.extend_from_slice(&self.raw_message[last_offset..]);
+
+        if self.attachments.is_empty() {
+            output
+        } else {
+            add_attachments(output, &self.attachments)
+        }
+    }
+}
+
+fn add_attachments(raw_message: Vec<u8>, attachments: &[Vec<u8>]) -> Vec<u8> {
+    let Some(message) = MessageParser::new().parse(&raw_message) else {
+        return raw_message;
+    };
+    let root = &message.parts[0];
+
+    // Append the parts to an existing multipart/mixed
+    if let (Some(boundary), PartType::Multipart(sub_parts)) = (
+        root.content_type()
+            .filter(|ct| {
+                ct.ctype().eq_ignore_ascii_case("multipart")
+                    && ct
+                        .subtype()
+                        .map_or(false, |st| st.eq_ignore_ascii_case("mixed"))
+            })
+            .and_then(|ct| ct.attribute("boundary")),
+        &root.body,
+    ) {
+        if let Some(last_part) = sub_parts.last().and_then(|id| message.parts.get(*id)) {
+            let mut output = Vec::with_capacity(raw_message.len() + 1024);
+            output.extend_from_slice(&raw_message[..last_part.offset_end]);
+            for attachment in attachments {
+                write_boundary(&mut output, boundary, false);
+                output.extend_from_slice(attachment);
+            }
+            output.extend_from_slice(&raw_message[last_part.offset_end..]);
+            return output;
+        }
+    }
+
+    // Wrap the message in a new multipart/mixed, the boundary is derived
+    // from the contents to keep the output deterministic
+    let boundary = format!("mixed_{}", &blake3::hash(&raw_message).to_hex()[..32]);
+    let headers = &raw_message[root.offset_header..root.offset_body];
+    let mut output = Vec::with_capacity(raw_message.len() + 1024);
+    output.extend_from_slice(&raw_message[..root.offset_header]);
+    if !copy_headers(headers, &mut output, is_content_header) {
+        output.extend_from_slice(b"MIME-Version: 1.0\r\n");
+    }
+    output.extend_from_slice(b"Content-Type: multipart/mixed;\r\n\tboundary=\"");
+    output.extend_from_slice(boundary.as_bytes());
+    output.extend_from_slice(b"\"\r\n\r\n");
+    write_boundary(&mut output, &boundary, true);
+    copy_headers(headers, &mut output, |name| !is_content_header(name));
+    output.extend_from_slice(b"\r\n");
+    let body = raw_message
+        .get(root.offset_body..root.offset_end)
+        .unwrap_or_default();
+    output.extend_from_slice(body.strip_suffix(b"\r\n").unwrap_or(body));
+    for attachment in attachments {
+        write_boundary(&mut output, &boundary, false);
+        output.extend_from_slice(attachment);
+    }
+    output.extend_from_slice(b"\r\n--");
+    output.extend_from_slice(boundary.as_bytes());
+    output.extend_from_slice(b"--\r\n");
+    output
+}
+
+fn write_boundary(output: &mut Vec<u8>, boundary: &str, is_first: bool) {
+    if !is_first {
+        output.extend_from_slice(b"\r\n");
+    }
+    output.extend_from_slice(b"--");
+    output.extend_from_slice(boundary.as_bytes());
+    output.extend_from_slice(b"\r\n");
+}
+
+fn is_content_header(name: &str) -> bool {
+    name.len() > 8 && name[..8].eq_ignore_ascii_case("Content-")
+}
+
+/// Copies the raw header lines except those matching `skip`, returns whether
+/// a MIME-Version header was found.
+fn copy_headers(headers: &[u8], output: &mut Vec<u8>, skip: impl Fn(&str) -> bool) -> bool {
+    let mut skip_header = false;
+    let mut has_mime_version = false;
+    for line in headers.split_inclusive(|&ch| ch == b'\n') {
+        match line.first() {
+            Some(b' ' | b'\t') => (),
+            Some(b'\r' | b'\n') | None => break,
+            _ => {
+                let name = String::from_utf8_lossy(
+                    line.split(|&ch| ch == b':').next().unwrap_or_default(),
+                );
+                let name = name.trim();
+                skip_header = skip(name);
+                has_mime_version |= name.eq_ignore_ascii_case("MIME-Version");
+            }
+        }
+        if !skip_header {
+            output.extend_from_slice(line);
+        }
+    }
+    has_mime_version
+}
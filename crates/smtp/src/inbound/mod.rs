@@ -30,12 +30,22 @@ use crate::config::{ArcSealer, DkimSigner};
 
 pub mod auth;
 pub mod data;
+pub mod dlp;
+pub mod dnsbl;
 pub mod ehlo;
+pub mod footer;
+pub mod greylist;
+pub mod honeypot;
 pub mod mail;
 pub mod milter;
+pub mod mime;
+pub mod monitor;
 pub mod rcpt;
+pub mod response;
+pub mod risk;
 pub mod session;
 pub mod spawn;
+pub mod spool;
 pub mod vrfy;
 
 impl ArcSealer {
@@ -52,21 +62,46 @@ impl ArcSealer {
     }
 }
 
+/// Size of the body slices fed to the DKIM hasher, large messages are hashed
+/// one slice at a time instead of as a single buffer.
+pub const DKIM_CHUNK_SIZE: usize = 64 * 1024;
+
 impl DkimSigner {
     pub fn sign(&self, message: &[u8]) -> mail_auth::Result<Signature> {
-        match self {
-            DkimSigner::RsaSha256(signer) => signer.sign(message),
-            DkimSigner::Ed25519Sha256(signer) => signer.sign(message),
-        }
+        self.sign_chained(message_chunks(message))
     }
-    pub fn sign_chained(&self, message: &[&[u8]]) -> mail_auth::Result<Signature> {
+
+    pub fn sign_chained<'x>(
+        &self,
+        message: impl IntoIterator<Item = &'x [u8]>,
+    ) -> mail_auth::Result<Signature> {
         match self {
-            DkimSigner::RsaSha256(signer) => signer.sign_chained(message.iter().copied()),
-            DkimSigner::Ed25519Sha256(signer) => signer.sign_chained(message.iter().copied()),
+            DkimSigner::RsaSha256(signer) => signer.sign_chained(message),
+            DkimSigner::Ed25519Sha256(signer) => signer.sign_chained(message),
         }
     }
 }
 
+/// Splits a message into its header section followed by the body in
+/// `DKIM_CHUNK_SIZE` slices. Headers are kept whole as they are parsed before
+/// hashing, the body canonicalization carries its state across slices.
+pub fn message_chunks(message: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let body_offset = message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+        .or_else(|| {
+            message
+                .windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|pos| pos + 2)
+        })
+        .unwrap_or(message.len());
+    let (headers, body) = message.split_at(body_offset);
+
+    std::iter::once(headers).chain(body.chunks(DKIM_CHUNK_SIZE))
+}
+
 pub trait AuthResult {
     fn as_str(&self) -> &'static str;
 }
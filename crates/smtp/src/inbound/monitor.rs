@@ -0,0 +1,323 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize, IterateParams, Serialize, ValueKey,
+};
+use utils::{listener::SessionStream, metrics::server::server_metrics};
+
+use crate::{
+    core::{Session, SMTP},
+    queue::QueueId,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MonitorVerdict {
+    pub id: u64,
+    pub created: u64,
+    pub rule: String,
+    pub verdict: MonitorAction,
+    pub disposition: Option<Disposition>,
+    pub reason: String,
+    pub return_path: String,
+    pub recipients: Vec<String>,
+    pub remote_ip: IpAddr,
+    pub queue_id: Option<QueueId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MonitorAction {
+    Reject,
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    Delivered,
+    Quarantined,
+    Rejected,
+    Deferred,
+    Discarded,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MonitorSummary {
+    pub total: u64,
+    pub reject: u64,
+    pub quarantine: u64,
+    pub delivered: u64,
+    pub quarantined: u64,
+    pub rejected: u64,
+    pub deferred: u64,
+    pub discarded: u64,
+    pub matched: u64,
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Returns the verdict to record instead of enforcing it when the rule
+    /// is deployed in monitor-only mode, or `None` if it has to be enforced.
+    pub fn monitor_verdict(
+        &self,
+        rule: &str,
+        verdict: MonitorAction,
+        reason: &str,
+    ) -> Option<MonitorVerdict> {
+        if !self.core.session.config.data.monitor.rules.contains(rule) {
+            return None;
+        }
+
+        tracing::info!(parent: &self.span,
+            context = "monitor",
+            event = "verdict",
+            rule = rule,
+            verdict = verdict.as_str(),
+            reason = reason,
+            "Rule in monitor-only mode would have {} the message.",
+            verdict.as_past());
+
+        server_metrics().monitor_verdict(rule, verdict.as_str());
+
+        let verdict = MonitorVerdict {
+            id: self.core.queue.snowflake_id.generate().unwrap_or_else(now),
+            created: now(),
+            rule: rule.to_string(),
+            verdict,
+            disposition: None,
+            reason: reason.to_string(),
+            return_path: self
+                .data
+                .mail_from
+                .as_ref()
+                .map(|mail_from| mail_from.address.clone())
+                .unwrap_or_default(),
+            recipients: self
+                .data
+                .rcpt_to
+                .iter()
+                .map(|rcpt| rcpt.address.clone())
+                .collect(),
+            remote_ip: self.data.remote_ip,
+            queue_id: None,
+        };
+        self.notify_monitor_verdict(&verdict);
+
+        Some(verdict)
+    }
+
+    /// Stores the verdicts collected while processing a message along with
+    /// the disposition that was actually applied to it.
+    pub async fn record_monitor_verdicts(&mut self, response: &[u8]) {
+        if self.data.monitor_verdicts.is_empty() {
+            return;
+        }
+
+        let disposition = match response.first() {
+            Some(b'2') => Disposition::Discarded,
+            Some(b'4') => Disposition::Deferred,
+            _ => Disposition::Rejected,
+        };
+        for mut verdict in std::mem::take(&mut self.data.monitor_verdicts) {
+            if verdict.disposition.is_none() {
+                verdict.disposition = disposition.into();
+            }
+            self.core.save_monitor_verdict(verdict).await;
+        }
+    }
+
+    fn notify_monitor_verdict(&self, verdict: &MonitorVerdict) {
+        let config = &self.core.session.config.data.monitor;
+        let url = if let Some(url) = &config.notify_url {
+            url.clone()
+        } else {
+            return;
+        };
+        let timeout = config.notify_timeout;
+        let rule = verdict.rule.clone();
+        let action = verdict.verdict.as_str();
+        let reason = verdict.reason.clone();
+        let return_path = verdict.return_path.clone();
+        let remote_ip = verdict.remote_ip.to_string();
+
+        tokio::spawn(async move {
+            let result = match reqwest::Client::builder().timeout(timeout).build() {
+                Ok(client) => client
+                    .post(&url)
+                    .form(&[
+                        ("event", "monitor-verdict"),
+                        ("rule", rule.as_str()),
+                        ("verdict", action),
+                        ("reason", reason.as_str()),
+                        ("return-path", return_path.as_str()),
+                        ("remote-ip", remote_ip.as_str()),
+                    ])
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                tracing::warn!(
+                    context = "monitor",
+                    event = "error",
+                    url = url,
+                    rule = rule,
+                    reason = %err,
+                    "Failed to send monitor verdict notification."
+                );
+            }
+        });
+    }
+}
+
+impl SMTP {
+    pub async fn save_monitor_verdict(&self, verdict: MonitorVerdict) -> bool {
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::MonitorVerdict(verdict.id)),
+            Bincode::new(verdict).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "monitor",
+                event = "error",
+                "Failed to write verdict: {}",
+                err
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn read_monitor_verdict(&self, id: u64) -> Option<MonitorVerdict> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<MonitorVerdict>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MonitorVerdict(id),
+            )))
+            .await
+        {
+            Ok(Some(verdict)) => Some(verdict.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "monitor",
+                    event = "error",
+                    "Failed to read verdict from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_monitor_verdicts(
+        &self,
+        filter: impl Fn(&MonitorVerdict) -> bool + Sync + Send,
+    ) -> store::Result<Vec<MonitorVerdict>> {
+        let mut verdicts = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::MonitorVerdict(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::MonitorVerdict(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let verdict = Bincode::<MonitorVerdict>::deserialize(value)?.inner;
+                    if filter(&verdict) {
+                        verdicts.push(verdict);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| verdicts)
+    }
+
+    /// Compares the verdicts of each monitored rule against the disposition
+    /// that was actually applied to the messages.
+    pub async fn monitor_report(
+        &self,
+        filter: impl Fn(&MonitorVerdict) -> bool + Sync + Send,
+    ) -> store::Result<BTreeMap<String, MonitorSummary>> {
+        let mut report = BTreeMap::<String, MonitorSummary>::new();
+        for verdict in self.list_monitor_verdicts(filter).await? {
+            let summary = report.entry(verdict.rule).or_default();
+            summary.total += 1;
+            match verdict.verdict {
+                MonitorAction::Reject => summary.reject += 1,
+                MonitorAction::Quarantine => summary.quarantine += 1,
+            }
+            match verdict.disposition {
+                Some(Disposition::Delivered) => summary.delivered += 1,
+                Some(Disposition::Quarantined) => summary.quarantined += 1,
+                Some(Disposition::Rejected) => summary.rejected += 1,
+                Some(Disposition::Deferred) => summary.deferred += 1,
+                Some(Disposition::Discarded) => summary.discarded += 1,
+                None => (),
+            }
+            if matches!(
+                (verdict.verdict, verdict.disposition),
+                (MonitorAction::Reject, Some(Disposition::Rejected))
+                    | (MonitorAction::Quarantine, Some(Disposition::Quarantined))
+            ) {
+                summary.matched += 1;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl MonitorAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MonitorAction::Reject => "reject",
+            MonitorAction::Quarantine => "quarantine",
+        }
+    }
+
+    fn as_past(&self) -> &'static str {
+        match self {
+            MonitorAction::Reject => "rejected",
+            MonitorAction::Quarantine => "quarantined",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(MonitorAction::Reject),
+            "quarantine" => Some(MonitorAction::Quarantine),
+            _ => None,
+        }
+    }
+}
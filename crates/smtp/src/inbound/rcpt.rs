@@ -21,14 +21,21 @@
  * for more details.
 */
 
+use std::time::Instant;
+
 use smtp_proto::{
     RcptTo, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use utils::listener::SessionStream;
 
 use crate::{
+    config::DnsblAction,
     core::{Session, SessionAddress},
-    queue::DomainPart,
+    inbound::response::ResponseKind,
+    queue::{
+        dsn::{batv_verify, BatvResult},
+        DomainPart,
+    },
     scripts::{ScriptModification, ScriptResult},
 };
 
@@ -61,12 +68,41 @@ impl<T: SessionStream> Session<T> {
                 .await;
         }
 
+        // Verify bounce address tag
+        let is_bounce = self
+            .data
+            .mail_from
+            .as_ref()
+            .map_or(false, |mail_from| mail_from.address.is_empty());
+        let mut has_batv = false;
+        let mut address = to.address;
+        if !self.core.queue.config.dsn.batv.keys.is_empty() {
+            match batv_verify(&self.core.queue.config.dsn.batv.keys, &address) {
+                BatvResult::Valid(original) => {
+                    address = original;
+                    has_batv = true;
+                }
+                BatvResult::Invalid if is_bounce => {
+                    tracing::debug!(parent: &self.span,
+                        context = "rcpt",
+                        event = "error",
+                        address = &address,
+                        "Invalid bounce address tag.");
+
+                    return self
+                        .rcpt_error(b"550 5.7.1 Invalid bounce address tag.\r\n")
+                        .await;
+                }
+                _ => (),
+            }
+        }
+
         // Build RCPT
-        let address_lcase = to.address.to_lowercase();
+        let address_lcase = address.to_lowercase();
         let rcpt = SessionAddress {
             domain: address_lcase.domain_part().to_string(),
             address_lcase,
-            address: to.address,
+            address,
             flags: to.flags,
             dsn_info: to.orcpt,
         };
@@ -142,6 +178,18 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Accept and discard messages sent to honeypot addresses
+        if self
+            .core
+            .eval_if(&self.core.session.config.rcpt.honeypot.enable, self)
+            .await
+            .unwrap_or(false)
+        {
+            let rcpt = self.data.rcpt_to.pop().unwrap();
+            self.handle_honeypot_rcpt(rcpt).await;
+            return self.write(b"250 2.1.5 OK\r\n").await;
+        }
+
         // Verify address
         let rcpt = self.data.rcpt_to.last().unwrap();
         if let Some(directory) = self
@@ -152,7 +200,18 @@ impl<T: SessionStream> Session<T> {
         {
             if let Ok(is_local_domain) = directory.is_local_domain(&rcpt.domain).await {
                 if is_local_domain {
-                    if let Ok(is_local_address) = directory.rcpt(&rcpt.address_lcase).await {
+                    let is_local_address = if self
+                        .core
+                        .session
+                        .rcpt_cache
+                        .get(&rcpt.address_lcase)
+                        .is_some()
+                    {
+                        Ok(false)
+                    } else {
+                        directory.rcpt(&rcpt.address_lcase).await
+                    };
+                    if let Ok(is_local_address) = is_local_address {
                         if !is_local_address {
                             tracing::debug!(parent: &self.span,
                                             context = "rcpt", 
@@ -160,9 +219,42 @@ impl<T: SessionStream> Session<T> {
                                             address = &rcpt.address_lcase,
                                             "Mailbox does not exist.");
 
+                            let cache_ttl = self.core.session.config.rcpt.cache_ttl;
+                            if !cache_ttl.is_zero() {
+                                self.core.session.rcpt_cache.insert(
+                                    rcpt.address_lcase.clone(),
+                                    (),
+                                    Instant::now() + cache_ttl,
+                                );
+                            }
+
+                            self.rcpt_tarpit().await;
+                            let response = self
+                                .custom_response(
+                                    ResponseKind::UserUnknown,
+                                    b"550 5.1.2 Mailbox does not exist.\r\n",
+                                )
+                                .await;
+                            self.data.rcpt_to.pop();
+                            return self.rcpt_error(&response).await;
+                        } else if is_bounce
+                            && !has_batv
+                            && !self.core.queue.config.dsn.batv.keys.is_empty()
+                            && self
+                                .core
+                                .eval_if(&self.core.queue.config.dsn.batv.verify, self)
+                                .await
+                                .unwrap_or(false)
+                        {
+                            tracing::debug!(parent: &self.span,
+                                            context = "rcpt",
+                                            event = "error",
+                                            address = &rcpt.address_lcase,
+                                            "Bounce address tag missing.");
+
                             self.data.rcpt_to.pop();
                             return self
-                                .rcpt_error(b"550 5.1.2 Mailbox does not exist.\r\n")
+                                .rcpt_error(b"550 5.7.1 Bounce address tag missing.\r\n")
                                 .await;
                         }
                     } else {
@@ -189,8 +281,14 @@ impl<T: SessionStream> Session<T> {
                         address = &rcpt.address_lcase,
                         "Relay not allowed.");
 
+                    let response = self
+                        .custom_response(
+                            ResponseKind::PolicyReject,
+                            b"550 5.1.2 Relay not allowed.\r\n",
+                        )
+                        .await;
                     self.data.rcpt_to.pop();
-                    return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
+                    return self.rcpt_error(&response).await;
                 }
             } else {
                 tracing::debug!(parent: &self.span,
@@ -216,8 +314,29 @@ impl<T: SessionStream> Session<T> {
                 address = &rcpt.address_lcase,
                 "Relay not allowed.");
 
+            let response = self
+                .custom_response(
+                    ResponseKind::PolicyReject,
+                    b"550 5.1.2 Relay not allowed.\r\n",
+                )
+                .await;
             self.data.rcpt_to.pop();
-            return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
+            return self.rcpt_error(&response).await;
+        }
+
+        // Temporarily reject first-time senders, or clients listed on DNS blocklists
+        if (self
+            .core
+            .eval_if(&self.core.session.config.rcpt.greylist.enable, self)
+            .await
+            .unwrap_or(false)
+            || self.dnsbl_action().await == DnsblAction::Greylist)
+            && self.is_greylisted().await
+        {
+            self.data.rcpt_to.pop();
+            return self
+                .write(b"451 4.7.1 Greylisted, please try again later.\r\n")
+                .await;
         }
 
         if self.is_allowed().await {
@@ -226,15 +345,41 @@ impl<T: SessionStream> Session<T> {
                     event = "success",
                     address = &self.data.rcpt_to.last().unwrap().address);
         } else {
-            self.data.rcpt_to.pop();
-            return self
-                .write(b"451 4.4.5 Rate limit exceeded, try again later.\r\n")
+            let response = self
+                .custom_response(
+                    ResponseKind::RateLimited,
+                    b"451 4.4.5 Rate limit exceeded, try again later.\r\n",
+                )
                 .await;
+            self.data.rcpt_to.pop();
+            return self.write(&response).await;
         }
 
         self.write(b"250 2.1.5 OK\r\n").await
     }
 
+    async fn rcpt_tarpit(&mut self) {
+        self.data.rcpt_unknown += 1;
+        let after = self.params.rcpt_tarpit_after;
+        if after > 0 && self.data.rcpt_unknown > after {
+            // Double the delay for every unknown recipient past the threshold
+            let delay = self
+                .params
+                .rcpt_errors_wait
+                .saturating_mul(1 << (self.data.rcpt_unknown - after).min(16))
+                .min(self.params.rcpt_tarpit_max_delay);
+            tracing::debug!(
+                parent: &self.span,
+                context = "rcpt",
+                event = "tarpit",
+                unknown_recipients = self.data.rcpt_unknown,
+                delay = delay.as_millis() as u64,
+                "Delaying response to possible directory harvesting."
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
     async fn rcpt_error(&mut self, response: &[u8]) -> Result<(), ()> {
         tokio::time::sleep(self.params.rcpt_errors_wait).await;
         self.data.rcpt_errors += 1;
@@ -0,0 +1,120 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use store::write::now;
+use utils::listener::SessionStream;
+
+use crate::core::{ResolveVariable, Session, SMTP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    UserUnknown,
+    PolicyReject,
+    RateLimited,
+    OverQuota,
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Returns the response for a common rejection. When the operator has
+    /// configured a text for it, the text replaces the default one while the
+    /// reply and enhanced status codes are preserved.
+    pub async fn custom_response(
+        &self,
+        kind: ResponseKind,
+        default: &'static [u8],
+    ) -> Cow<'static, [u8]> {
+        self.core
+            .build_custom_response(
+                kind,
+                default,
+                &self.instance.hostname,
+                self,
+                Some(&self.span),
+            )
+            .await
+    }
+}
+
+impl SMTP {
+    /// Builds the response for a common rejection. Tracking IDs are only
+    /// generated when a span to log them under is provided, otherwise the
+    /// placeholder is left in the text.
+    pub async fn build_custom_response<V: ResolveVariable>(
+        &self,
+        kind: ResponseKind,
+        default: &'static [u8],
+        hostname: &str,
+        resolver: &V,
+        span: Option<&tracing::Span>,
+    ) -> Cow<'static, [u8]> {
+        let config = &self.session.config.responses;
+        let template = match kind {
+            ResponseKind::UserUnknown => &config.user_unknown,
+            ResponseKind::PolicyReject => &config.policy_reject,
+            ResponseKind::RateLimited => &config.rate_limited,
+            ResponseKind::OverQuota => &config.over_quota,
+        };
+        let text = match self.eval_if::<String, _>(template, resolver).await {
+            Some(text) if !text.trim().is_empty() => text,
+            _ => return default.into(),
+        };
+
+        // Expand template variables
+        let mut text = text.replace("{hostname}", hostname);
+        if text.contains("{support-url}") {
+            let support_url = self
+                .eval_if::<String, _>(&config.support_url, resolver)
+                .await
+                .unwrap_or_default();
+            text = text.replace("{support-url}", &support_url);
+        }
+        if let (true, Some(span)) = (text.contains("{tracking-id}"), span) {
+            let tracking_id = format!(
+                "{:x}",
+                self.queue.snowflake_id.generate().unwrap_or_else(now)
+            );
+            tracing::info!(
+                parent: span,
+                context = "response",
+                event = "reject",
+                kind = ?kind,
+                tracking_id = tracking_id,
+                response = std::str::from_utf8(default).unwrap_or_default().trim_end(),
+            );
+            text = text.replace("{tracking-id}", &tracking_id);
+        }
+
+        let mut codes = std::str::from_utf8(default)
+            .unwrap_or_default()
+            .splitn(3, ' ');
+        let code = codes.next().unwrap_or_default();
+        let status = codes.next().unwrap_or_default();
+        let text = text.replace(['\r', '\n'], " ");
+
+        format!("{code} {status} {}\r\n", text.trim())
+            .into_bytes()
+            .into()
+    }
+}
@@ -0,0 +1,378 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, time::Duration};
+
+use mail_auth::common::resolver::ToReverseName;
+use mail_parser::{MessageParser, PartType};
+use store::write::now;
+use utils::listener::SessionStream;
+
+use crate::{
+    config::SenderRisk,
+    core::{Session, SMTP},
+};
+
+const KNOWN_COUNTRY_EXPIRY: Duration = Duration::from_secs(90 * 86400);
+
+#[derive(Debug, Default)]
+pub struct RiskAssessment {
+    pub score: u32,
+    pub signals: Vec<&'static str>,
+}
+
+impl RiskAssessment {
+    fn add(&mut self, signal: &'static str, score: u32) {
+        self.signals.push(signal);
+        self.score += score;
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Checks whether the authenticated account shows signs of being
+    /// compromised, returning the SMTP response if the message has to be
+    /// refused.
+    pub async fn check_sender_risk(&self, raw_message: &[u8]) -> Option<Cow<'static, [u8]>> {
+        let config = &self.core.session.config.data.risk;
+        let account = &self.data.authenticated_as;
+
+        if self.core.is_sending_suspended(account).await {
+            tracing::info!(parent: &self.span,
+                context = "risk",
+                event = "reject",
+                account = account,
+                "Account is suspended from sending.");
+            return Some(
+                (&b"550 5.7.1 Account suspended from sending, contact your administrator.\r\n"[..])
+                    .into(),
+            );
+        }
+
+        let risk = self.assess_sender_risk(raw_message).await;
+        let action = if risk.score >= config.suspend_threshold {
+            "suspend"
+        } else if risk.score >= config.throttle_threshold {
+            "throttle"
+        } else {
+            if risk.score > 0 {
+                tracing::debug!(parent: &self.span,
+                    context = "risk",
+                    event = "assess",
+                    account = account,
+                    score = risk.score,
+                    signals = ?risk.signals,
+                    "Sender risk below thresholds.");
+            }
+            return None;
+        };
+
+        tracing::warn!(parent: &self.span,
+            context = "risk",
+            event = action,
+            account = account,
+            score = risk.score,
+            signals = ?risk.signals,
+            "Possibly compromised account detected.");
+
+        self.core
+            .notify_sender_risk(account, &self.data.remote_ip_str, &risk, action);
+
+        if action == "suspend" {
+            self.core
+                .suspend_sending(account, config.suspend_duration)
+                .await;
+            Some(
+                (&b"550 5.7.1 Account suspended from sending, contact your administrator.\r\n"[..])
+                    .into(),
+            )
+        } else {
+            Some((&b"451 4.7.1 Unusual sending activity detected, try again later.\r\n"[..]).into())
+        }
+    }
+
+    pub async fn assess_sender_risk(&self, raw_message: &[u8]) -> RiskAssessment {
+        let config = &self.core.session.config.data.risk;
+        let account = &self.data.authenticated_as;
+        let store = &self.core.shared.default_lookup_store;
+        let mut risk = RiskAssessment::default();
+
+        // Recipient count spike compared to the previous hour
+        let hour = now() / 3600;
+        let sent = store
+            .counter_incr(
+                format!("rr:{account}:{hour}").into_bytes(),
+                self.data.rcpt_to.len() as i64,
+                Some(7200),
+            )
+            .await
+            .unwrap_or(0) as u64;
+        let sent_before = store
+            .counter_get(format!("rr:{account}:{}", hour - 1).into_bytes())
+            .await
+            .unwrap_or(0) as u64;
+        if sent >= config.recipients_min
+            && sent as f64 > config.recipients_factor * sent_before as f64
+        {
+            risk.add("recipient-spike", config.recipients_score);
+        }
+
+        // Bulk sending after a login from a new country
+        if sent >= config.country_bulk && self.is_new_country().await {
+            risk.add("new-country", config.country_score);
+        }
+
+        // URLs matching known phishing kits
+        if config.count_phishing_urls(raw_message) > 0 {
+            risk.add("phishing-url", config.url_score);
+        }
+
+        risk
+    }
+
+    /// Returns whether the session's country was first seen for this
+    /// account within the configured window. Accounts without any
+    /// history only have their country recorded.
+    async fn is_new_country(&self) -> bool {
+        let country = if let Some(country) = self.lookup_country().await {
+            country
+        } else {
+            return false;
+        };
+        let account = &self.data.authenticated_as;
+        let store = &self.core.shared.default_lookup_store;
+        let new_key = format!("rn:{account}:{country}").into_bytes();
+
+        if store
+            .key_exists(format!("rc:{account}:{country}").into_bytes())
+            .await
+            .unwrap_or(true)
+        {
+            return store.key_exists(new_key).await.unwrap_or(false);
+        }
+
+        let has_history = store
+            .key_exists(format!("rc:{account}").into_bytes())
+            .await
+            .unwrap_or(false);
+        for key in [format!("rc:{account}:{country}"), format!("rc:{account}")] {
+            let _ = store
+                .key_set(
+                    key.into_bytes(),
+                    vec![],
+                    KNOWN_COUNTRY_EXPIRY.as_secs().into(),
+                )
+                .await;
+        }
+        if has_history {
+            let _ = store
+                .key_set(
+                    new_key,
+                    vec![],
+                    self.core
+                        .session
+                        .config
+                        .data
+                        .risk
+                        .country_window
+                        .as_secs()
+                        .into(),
+                )
+                .await;
+        }
+
+        has_history
+    }
+
+    async fn lookup_country(&self) -> Option<String> {
+        let ip = self.data.remote_ip;
+        if ip.is_loopback() || ip.is_unspecified() {
+            return None;
+        }
+        let zone = if ip.is_ipv4() {
+            "origin.asn.cymru.com"
+        } else {
+            "origin6.asn.cymru.com"
+        };
+
+        // Response format is "ASN | prefix | country | registry | date"
+        let txt = self
+            .core
+            .resolvers
+            .dns
+            .txt_raw_lookup(format!("{}.{zone}", ip.to_reverse_name()))
+            .await
+            .ok()?;
+        let country = std::str::from_utf8(&txt)
+            .ok()?
+            .split('|')
+            .nth(2)?
+            .trim()
+            .to_ascii_uppercase();
+
+        if country.len() == 2 {
+            Some(country)
+        } else {
+            None
+        }
+    }
+}
+
+impl SMTP {
+    pub async fn is_sending_suspended(&self, account: &str) -> bool {
+        self.shared
+            .default_lookup_store
+            .key_exists(format!("rs:{account}").into_bytes())
+            .await
+            .unwrap_or(false)
+    }
+
+    pub async fn suspend_sending(&self, account: &str, duration: Duration) {
+        if let Err(err) = self
+            .shared
+            .default_lookup_store
+            .key_set(
+                format!("rs:{account}").into_bytes(),
+                vec![],
+                duration.as_secs().into(),
+            )
+            .await
+        {
+            tracing::error!(
+                context = "risk",
+                event = "error",
+                account = account,
+                reason = ?err,
+                "Failed to suspend account."
+            );
+        }
+    }
+
+    /// Lifts a sending suspension, returning whether the account was suspended.
+    pub async fn unsuspend_sending(&self, account: &str) -> store::Result<bool> {
+        let key = format!("rs:{account}").into_bytes();
+        let was_suspended = self
+            .shared
+            .default_lookup_store
+            .key_exists(key.clone())
+            .await?;
+        self.shared.default_lookup_store.key_delete(key).await?;
+
+        Ok(was_suspended)
+    }
+
+    fn notify_sender_risk(
+        &self,
+        account: &str,
+        remote_ip: &str,
+        risk: &RiskAssessment,
+        action: &'static str,
+    ) {
+        let config = &self.session.config.data.risk;
+        let url = if let Some(url) = &config.notify_url {
+            url.clone()
+        } else {
+            return;
+        };
+        let timeout = config.notify_timeout;
+        let account = account.to_string();
+        let remote_ip = remote_ip.to_string();
+        let score = risk.score.to_string();
+        let signals = risk.signals.join(",");
+
+        tokio::spawn(async move {
+            let result = match reqwest::Client::builder().timeout(timeout).build() {
+                Ok(client) => client
+                    .post(&url)
+                    .form(&[
+                        ("event", "account-risk"),
+                        ("account", account.as_str()),
+                        ("remote-ip", remote_ip.as_str()),
+                        ("score", score.as_str()),
+                        ("signals", signals.as_str()),
+                        ("action", action),
+                    ])
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                tracing::warn!(
+                    context = "risk",
+                    event = "error",
+                    url = url,
+                    account = account,
+                    reason = %err,
+                    "Failed to send sender risk notification."
+                );
+            }
+        });
+    }
+}
+
+impl SenderRisk {
+    pub fn count_phishing_urls(&self, raw_message: &[u8]) -> usize {
+        if self.url_patterns.is_empty() {
+            return 0;
+        }
+        let message = if let Some(message) = MessageParser::new().parse(raw_message) {
+            message
+        } else {
+            return 0;
+        };
+
+        message
+            .parts
+            .iter()
+            .filter_map(|part| match &part.body {
+                PartType::Text(text) | PartType::Html(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .flat_map(extract_urls)
+            .filter(|url| {
+                self.url_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(url))
+            })
+            .count()
+    }
+}
+
+fn extract_urls(text: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = text[pos..].find("http").map(|start| start + pos) {
+        let url = &text[start..];
+        let end = url
+            .find(|ch: char| ch.is_whitespace() || matches!(ch, '"' | '\'' | '<' | '>'))
+            .unwrap_or(url.len());
+        let url = &url[..end];
+        if url.starts_with("https://") || url.starts_with("http://") {
+            urls.push(url);
+        }
+        pos = start + end.max(4);
+    }
+    urls
+}
@@ -29,10 +29,13 @@ use smtp_proto::{
     *,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use utils::{config::ServerProtocol, listener::SessionStream};
+use utils::{config::ServerProtocol, listener::SessionStream, metrics::measure_command};
 
 use crate::{
-    config::session::Mechanism,
+    config::{
+        session::{mechanism_name, Mechanism},
+        DnsblStage,
+    },
     core::{eval::*, ResolveVariable, Session, State},
 };
 
@@ -49,14 +52,16 @@ impl<T: SessionStream> Session<T> {
                     match receiver.ingest(&mut iter, bytes) {
                         Ok(request) => match request {
                             Request::Rcpt { to } => {
-                                self.handle_rcpt_to(to).await?;
+                                measure_command("smtp", "RCPT", self.handle_rcpt_to(to)).await?;
                             }
                             Request::Mail { from } => {
-                                self.handle_mail_from(from).await?;
+                                measure_command("smtp", "MAIL", self.handle_mail_from(from))
+                                    .await?;
                             }
                             Request::Ehlo { host } => {
                                 if self.instance.protocol == ServerProtocol::Smtp {
-                                    self.handle_ehlo(host, true).await?;
+                                    measure_command("smtp", "EHLO", self.handle_ehlo(host, true))
+                                        .await?;
                                 } else {
                                     self.write(b"500 5.5.1 Invalid command.\r\n").await?;
                                 }
@@ -74,13 +79,19 @@ impl<T: SessionStream> Session<T> {
                                 chunk_size,
                                 is_last,
                             } => {
-                                state = if chunk_size + self.data.message.len()
+                                state = if chunk_size + self.data.message_size()
                                     < self.params.max_message_size
                                 {
+                                    // Avoid preallocating chunks that will be spooled to disk
+                                    let chunk_capacity =
+                                        match self.core.session.config.data.spool.threshold {
+                                            0 => chunk_size,
+                                            threshold => chunk_size.min(threshold),
+                                        };
                                     if self.data.message.is_empty() {
-                                        self.data.message = Vec::with_capacity(chunk_size);
+                                        self.data.message = Vec::with_capacity(chunk_capacity);
                                     } else {
-                                        self.data.message.reserve(chunk_size);
+                                        self.data.message.reserve(chunk_capacity);
                                     }
                                     State::Bdat(BdatReceiver::new(chunk_size, is_last))
                                 } else {
@@ -93,7 +104,7 @@ impl<T: SessionStream> Session<T> {
                                 mechanism,
                                 initial_response,
                             } => {
-                                let auth: u64 = self
+                                let mut auth: u64 = self
                                     .core
                                     .eval_if::<Mechanism, _>(
                                         &self.core.session.config.auth.mechanisms,
@@ -102,29 +113,53 @@ impl<T: SessionStream> Session<T> {
                                     .await
                                     .unwrap_or_default()
                                     .into();
+                                let channel_binding = self.stream.tls_exporter();
+                                if channel_binding.is_none() {
+                                    auth &= !AUTH_SCRAM_SHA_256_PLUS;
+                                }
                                 if auth == 0 || self.params.auth_directory.is_none() {
                                     self.write(b"503 5.5.1 AUTH not allowed.\r\n").await?;
                                 } else if !self.data.authenticated_as.is_empty() {
                                     self.write(b"503 5.5.1 Already authenticated.\r\n").await?;
-                                } else if mechanism & (AUTH_LOGIN | AUTH_PLAIN) != 0
+                                } else if mechanism & self.params.auth_require_tls != 0
                                     && !self.stream.is_tls()
-                                    && !self.params.auth_plain_text
                                 {
+                                    tracing::debug!(
+                                        parent: &self.span,
+                                        context = "auth",
+                                        event = "reject",
+                                        mechanism = mechanism_name(mechanism),
+                                        reason = "tls-required",
+                                    );
+                                    self.core.record_auth_usage(
+                                        &self.instance.id,
+                                        mechanism,
+                                        |usage| &usage.rejected,
+                                    );
                                     self.write(b"503 5.5.1 Clear text authentication without TLS is forbidden.\r\n").await?;
-                                } else if let Some(mut token) =
-                                    SaslToken::from_mechanism(mechanism & auth)
-                                {
-                                    if self
-                                        .handle_sasl_response(
+                                } else if let Some(mut token) = SaslToken::from_mechanism(
+                                    mechanism & auth,
+                                    channel_binding.filter(|_| auth & AUTH_SCRAM_SHA_256_PLUS != 0),
+                                ) {
+                                    if measure_command(
+                                        "smtp",
+                                        "AUTH",
+                                        self.handle_sasl_response(
                                             &mut token,
                                             initial_response.as_bytes(),
-                                        )
-                                        .await?
+                                        ),
+                                    )
+                                    .await?
                                     {
                                         state = State::Sasl(LineReceiver::new(token));
                                         continue 'outer;
                                     }
                                 } else {
+                                    self.core.record_auth_usage(
+                                        &self.instance.id,
+                                        mechanism,
+                                        |usage| &usage.rejected,
+                                    );
                                     self.write(
                                         b"554 5.7.8 Authentication mechanism not supported.\r\n",
                                     )
@@ -135,10 +170,10 @@ impl<T: SessionStream> Session<T> {
                                 self.write(b"250 2.0.0 OK\r\n").await?;
                             }
                             Request::Vrfy { value } => {
-                                self.handle_vrfy(value).await?;
+                                measure_command("smtp", "VRFY", self.handle_vrfy(value)).await?;
                             }
                             Request::Expn { value } => {
-                                self.handle_expn(value).await?;
+                                measure_command("smtp", "EXPN", self.handle_expn(value)).await?;
                             }
                             Request::StartTls => {
                                 if !self.stream.is_tls() {
@@ -173,14 +208,16 @@ impl<T: SessionStream> Session<T> {
                             }
                             Request::Helo { host } => {
                                 if self.instance.protocol == ServerProtocol::Smtp {
-                                    self.handle_ehlo(host, false).await?;
+                                    measure_command("smtp", "HELO", self.handle_ehlo(host, false))
+                                        .await?;
                                 } else {
                                     self.write(b"500 5.5.1 Invalid command.\r\n").await?;
                                 }
                             }
                             Request::Lhlo { host } => {
                                 if self.instance.protocol == ServerProtocol::Lmtp {
-                                    self.handle_ehlo(host, true).await?;
+                                    measure_command("smtp", "LHLO", self.handle_ehlo(host, true))
+                                        .await?;
                                 } else {
                                     self.write(b"502 5.5.1 Invalid command.\r\n").await?;
                                 }
@@ -234,10 +271,13 @@ impl<T: SessionStream> Session<T> {
                     }
                 },
                 State::Data(receiver) => {
-                    if self.data.message.len() + bytes.len() < self.params.max_message_size {
-                        if receiver.ingest(&mut iter, &mut self.data.message) {
-                            let num_rcpts = self.data.rcpt_to.len();
-                            let message = self.queue_message().await;
+                    if self.data.message_size() + bytes.len() < self.params.max_message_size {
+                        let is_complete = receiver.ingest(&mut iter, &mut self.data.message);
+                        self.spool_message().await;
+                        if is_complete {
+                            let num_rcpts = self.data.rcpt_to.len() + self.data.rcpt_honeypot;
+                            let message =
+                                measure_command("smtp", "DATA", self.queue_message()).await;
                             if !message.is_empty() {
                                 if self.instance.protocol == ServerProtocol::Smtp {
                                     self.write(message.as_ref()).await?;
@@ -260,11 +300,14 @@ impl<T: SessionStream> Session<T> {
                     }
                 }
                 State::Bdat(receiver) => {
-                    if receiver.ingest(&mut iter, &mut self.data.message) {
+                    let is_complete = receiver.ingest(&mut iter, &mut self.data.message);
+                    self.spool_message().await;
+                    if is_complete {
                         if self.can_send_data().await? {
                             if receiver.is_last {
-                                let num_rcpts = self.data.rcpt_to.len();
-                                let message = self.queue_message().await;
+                                let num_rcpts = self.data.rcpt_to.len() + self.data.rcpt_honeypot;
+                                let message =
+                                    measure_command("smtp", "BDAT", self.queue_message()).await;
                                 if !message.is_empty() {
                                     if self.instance.protocol == ServerProtocol::Smtp {
                                         self.write(message.as_ref()).await?;
@@ -283,6 +326,7 @@ impl<T: SessionStream> Session<T> {
                             }
                         } else {
                             self.data.message = Vec::with_capacity(0);
+                            self.data.spool = None;
                         }
                         state = State::default();
                     } else {
@@ -292,9 +336,12 @@ impl<T: SessionStream> Session<T> {
                 State::Sasl(receiver) => {
                     if receiver.ingest(&mut iter) {
                         if receiver.buf.len() < MAX_LINE_LENGTH {
-                            if self
-                                .handle_sasl_response(&mut receiver.state, &receiver.buf)
-                                .await?
+                            if measure_command(
+                                "smtp",
+                                "AUTH",
+                                self.handle_sasl_response(&mut receiver.state, &receiver.buf),
+                            )
+                            .await?
                             {
                                 receiver.buf.clear();
                                 continue 'outer;
@@ -320,6 +367,7 @@ impl<T: SessionStream> Session<T> {
                         );
 
                         self.data.message = Vec::with_capacity(0);
+                        self.data.spool = None;
                         self.write(b"552 5.3.4 Message too big for system.\r\n")
                             .await?;
                         state = State::default();
@@ -348,8 +396,13 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
     pub fn reset(&mut self) {
         self.data.mail_from = None;
         self.data.spf_mail_from = None;
+        self.data
+            .dnsbl_hits
+            .retain(|hit| hit.stage != DnsblStage::MailFrom);
         self.data.rcpt_to.clear();
+        self.data.rcpt_honeypot = 0;
         self.data.message = Vec::with_capacity(0);
+        self.data.spool = None;
         self.data.priority = 0;
         self.data.delivery_by = 0;
         self.data.future_release = 0;
@@ -439,6 +492,7 @@ impl<T: AsyncRead + AsyncWrite> ResolveVariable for Session<T> {
             V_REMOTE_IP => self.data.remote_ip_str.as_str().into(),
             V_LOCAL_IP => self.data.local_ip_str.as_str().into(),
             V_PRIORITY => self.data.priority.to_string().into(),
+            V_DNSBL_SCORE => self.data.dnsbl_score().into(),
             _ => utils::expr::Variable::default(),
         }
     }
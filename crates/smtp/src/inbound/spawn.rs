@@ -24,9 +24,10 @@
 use std::{net::IpAddr, time::Instant};
 
 use tokio_rustls::server::TlsStream;
-use utils::listener::{SessionManager, SessionStream};
+use utils::listener::{transcript::TranscriptStream, SessionManager, SessionStream};
 
 use crate::{
+    config::DnsblStage,
     core::{Session, SessionData, SessionParameters, SmtpSessionManager, State},
     queue, reporting,
     scripts::ScriptResult,
@@ -111,6 +112,12 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // DNS blocklists
+        if let Some(response) = self.check_dnsbl(DnsblStage::Connect).await {
+            let _ = self.write(&response).await;
+            return false;
+        }
+
         let instance = self.instance.clone();
         if self.write(instance.data.as_bytes()).await.is_err() {
             return false;
@@ -211,7 +218,7 @@ impl<T: SessionStream> Session<T> {
         false
     }
 
-    pub async fn into_tls(self) -> Result<Session<TlsStream<T>>, ()> {
+    pub async fn into_tls(self) -> Result<Session<TranscriptStream<TlsStream<T>>>, ()> {
         let span = self.span;
         Ok(Session {
             stream: self.instance.tls_accept(self.stream, &span).await?,
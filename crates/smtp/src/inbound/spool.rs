@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use memmap2::Mmap;
+use tokio::{fs::File, io::AsyncWriteExt};
+use utils::listener::SessionStream;
+
+use crate::core::{Session, SessionData};
+
+/// Holds the part of a message being received that exceeded the spool
+/// threshold. The file is removed once the spool is dropped, a memory map
+/// created from it remains valid until the last reference is released.
+pub struct MessageSpool {
+    file: File,
+    path: PathBuf,
+    len: usize,
+    failed: bool,
+}
+
+impl MessageSpool {
+    pub async fn create(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(format!("smtp-{:016x}.spool", rand::random::<u64>()));
+        Ok(MessageSpool {
+            file: File::create(&path).await?,
+            path,
+            len: 0,
+            failed: false,
+        })
+    }
+
+    pub async fn append(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if self.failed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "A previous write to the spool file failed",
+            ));
+        }
+        match self.file.write_all(bytes).await {
+            Ok(_) => {
+                self.len += bytes.len();
+                Ok(())
+            }
+            Err(err) => {
+                self.failed = true;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the remaining bytes and maps the spooled message into memory.
+    pub async fn into_bytes(mut self, tail: &[u8]) -> std::io::Result<Bytes> {
+        if !tail.is_empty() || self.failed {
+            self.append(tail).await?;
+        }
+        self.file.flush().await?;
+        let file = self.file.try_clone().await?.into_std().await;
+
+        // SAFETY: the spool file is private to this session and is never
+        // modified after this point.
+        let map = unsafe { Mmap::map(&file)? };
+
+        // DKIM body hashes and the blob writer read the message front to
+        // back, let the kernel read ahead and evict pages already hashed.
+        #[cfg(unix)]
+        let _ = map.advise(memmap2::Advice::Sequential);
+
+        Ok(Bytes::from_owner(map))
+    }
+}
+
+impl Drop for MessageSpool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl SessionData {
+    pub fn message_size(&self) -> usize {
+        self.message.len() + self.spool.as_ref().map_or(0, |spool| spool.len())
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Moves the received bytes to disk once they exceed the spool threshold.
+    pub async fn spool_message(&mut self) {
+        let config = &self.core.session.config.data.spool;
+        if config.threshold == 0 || self.data.message.len() < config.threshold {
+            return;
+        }
+
+        if self.data.spool.is_none() {
+            match MessageSpool::create(&config.path).await {
+                Ok(spool) => {
+                    self.data.spool = spool.into();
+                }
+                Err(err) => {
+                    tracing::warn!(parent: &self.span,
+                        context = "spool",
+                        event = "error",
+                        path = %config.path.display(),
+                        "Failed to create spool file, keeping message in memory: {}", err);
+                    return;
+                }
+            }
+        }
+
+        if let Some(spool) = &mut self.data.spool {
+            // Once a write fails the message is rejected when it is queued
+            if !spool.failed {
+                if let Err(err) = spool.append(&self.data.message).await {
+                    tracing::warn!(parent: &self.span,
+                        context = "spool",
+                        event = "error",
+                        "Failed to write to spool file: {}", err);
+                }
+            }
+            self.data.message.clear();
+        }
+    }
+
+    /// Takes the received message, either from memory or mapped from its
+    /// spool file, without copying it.
+    pub async fn take_message(&mut self) -> std::io::Result<Bytes> {
+        let message = std::mem::take(&mut self.data.message);
+        match self.data.spool.take() {
+            Some(spool) => spool.into_bytes(&message).await,
+            None => Ok(Bytes::from(message)),
+        }
+    }
+}
@@ -22,13 +22,19 @@
 */
 
 use directory::DirectoryError;
-use tokio::io::{AsyncRead, AsyncWrite};
+use utils::listener::SessionStream;
 
 use crate::core::Session;
 use std::fmt::Write;
 
-impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
+use super::response::ResponseKind;
+
+impl<T: SessionStream> Session<T> {
     pub async fn handle_vrfy(&mut self, address: String) -> Result<(), ()> {
+        if !self.is_enumeration_allowed("vrfy", &address).await {
+            return self.enumeration_limited(&address).await;
+        }
+
         match self
             .core
             .eval_if::<String, _>(&self.core.session.config.rcpt.directory, self)
@@ -80,12 +86,20 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                     event = "forbidden",
                     address = &address);
 
-                self.write(b"252 2.5.1 VRFY is disabled.\r\n").await
+                if self.params.enumeration_honey {
+                    self.write_honey_response(&address).await
+                } else {
+                    self.write(b"252 2.5.1 VRFY is disabled.\r\n").await
+                }
             }
         }
     }
 
     pub async fn handle_expn(&mut self, address: String) -> Result<(), ()> {
+        if !self.is_enumeration_allowed("expn", &address).await {
+            return self.enumeration_limited(&address).await;
+        }
+
         match self
             .core
             .eval_if::<String, _>(&self.core.session.config.rcpt.directory, self)
@@ -135,8 +149,73 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                     event = "forbidden",
                     address = &address);
 
-                self.write(b"252 2.5.1 EXPN is disabled.\r\n").await
+                if self.params.enumeration_honey {
+                    self.write_honey_response(&address).await
+                } else {
+                    self.write(b"252 2.5.1 EXPN is disabled.\r\n").await
+                }
+            }
+        }
+    }
+
+    /// Enforces the VRFY/EXPN rate of the remote IP, lowering its reputation
+    /// the first time it is exceeded during the session.
+    async fn is_enumeration_allowed(&mut self, context: &str, address: &str) -> bool {
+        let rate = if let Some(rate) = &self.params.enumeration_rate {
+            rate
+        } else {
+            return true;
+        };
+        let key = format!("e:{}:{}", self.instance.id, self.data.remote_ip);
+        if self
+            .core
+            .shared
+            .default_lookup_store
+            .is_rate_allowed(key.as_bytes(), rate, false)
+            .await
+            .unwrap_or_default()
+            .is_none()
+        {
+            return true;
+        }
+
+        tracing::debug!(parent: &self.span,
+            context = context,
+            event = "rate-limited",
+            address = address,
+            "Too many address verification requests.");
+
+        if !self.data.enumeration_limited {
+            self.data.enumeration_limited = true;
+            let score = self.core.session.config.extensions.enumeration.score;
+            if score != 0.0 {
+                self.core
+                    .update_reputation(format!("i:{}", self.data.remote_ip), score)
+                    .await;
             }
         }
+
+        false
+    }
+
+    async fn enumeration_limited(&mut self, address: &str) -> Result<(), ()> {
+        if self.params.enumeration_honey {
+            self.write_honey_response(address).await
+        } else {
+            let response = self
+                .custom_response(
+                    ResponseKind::RateLimited,
+                    b"451 4.4.5 Rate limit exceeded, try again later.\r\n",
+                )
+                .await;
+            self.write(&response).await
+        }
+    }
+
+    /// Replies as if the address existed, so that harvested lists are of no
+    /// use to the client.
+    async fn write_honey_response(&mut self, address: &str) -> Result<(), ()> {
+        self.write(format!("250 <{}>\r\n", address.trim_matches(['<', '>'])).as_bytes())
+            .await
     }
 }
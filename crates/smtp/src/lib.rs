@@ -27,22 +27,25 @@ use crate::{
         throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, SessionCore, TlsConnectors, SMTP,
     },
 };
-use std::sync::Arc;
+use std::sync::{atomic::Ordering, Arc};
 
+use arc_swap::ArcSwap;
 use config::{
     auth::ConfigAuth, queue::ConfigQueue, report::ConfigReport, resolver::ConfigResolver,
     scripts::ConfigSieve, session::ConfigSession, shared::ConfigShared, ConfigContext,
 };
 use dashmap::DashMap;
 use directory::Directories;
+use mail_auth::common::lru::LruCache;
 use mail_send::smtp::tls::build_tls_connector;
 use queue::manager::SpawnQueue;
 use reporting::scheduler::SpawnReport;
 use store::Stores;
 use tokio::sync::mpsc;
 use utils::{
-    config::{Config, ServerProtocol, Servers},
+    config::{reload::ReloadableConfig, Config, ServerProtocol, Servers},
     snowflake::SnowflakeIdGenerator,
+    worker::WorkerPool,
     UnwrapFailure,
 };
 
@@ -78,6 +81,11 @@ impl SMTP {
         let mail_auth_config = config.parse_mail_auth()?;
         let report_config = config.parse_reports()?;
         let mut shared = config.parse_shared(&config_ctx)?;
+        shared.transcripts = servers.transcripts.clone();
+        shared
+            .default_directory
+            .blocked_ips
+            .set_http_client(shared.http.client.clone());
 
         // Add local delivery host
         #[cfg(feature = "local_delivery")]
@@ -90,6 +98,7 @@ impl SMTP {
                     protocol: ServerProtocol::Jmap,
                     tls_implicit: Default::default(),
                     tls_allow_invalid_certs: Default::default(),
+                    pipelining: false,
                     auth: None,
                 },
             );
@@ -99,15 +108,15 @@ impl SMTP {
         let (queue_tx, queue_rx) = mpsc::channel(1024);
         let (report_tx, report_rx) = mpsc::channel(1024);
         let core = Arc::new(SMTP {
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(
-                    config
-                        .property::<usize>("global.thread-pool")?
-                        .filter(|v| *v > 0)
-                        .unwrap_or_else(num_cpus::get),
-                )
-                .build()
-                .unwrap(),
+            worker_pool: WorkerPool::parse(
+                config,
+                "global.worker-pool.smtp",
+                "smtp",
+                config
+                    .property::<usize>("global.thread-pool")?
+                    .filter(|v| *v > 0)
+                    .unwrap_or_else(num_cpus::get),
+            )?,
             resolvers: config.build_resolvers().failed("Failed to build resolvers"),
             session: SessionCore {
                 config: session_config,
@@ -119,6 +128,10 @@ impl SMTP {
                         .unwrap_or(32)
                         .next_power_of_two() as usize,
                 ),
+                auth_usage: DashMap::new(),
+                rcpt_cache: LruCache::with_capacity(
+                    config.property("session.rcpt.cache.size")?.unwrap_or(1024),
+                ),
             },
             queue: QueueCore {
                 config: queue_config,
@@ -139,10 +152,13 @@ impl SMTP {
                     pki_verify: build_tls_connector(false),
                     dummy_verify: build_tls_connector(true),
                 },
+                reputation: Default::default(),
+                pool: Default::default(),
+                index: Default::default(),
             },
             report: ReportCore {
                 tx: report_tx,
-                config: report_config,
+                config: ArcSwap::from_pointee(report_config),
             },
             mail_auth: mail_auth_config,
             sieve: sieve_config,
@@ -160,3 +176,31 @@ impl SMTP {
         Ok(core)
     }
 }
+
+impl ReloadableConfig for SMTP {
+    fn reload(&self, config: &Config) -> utils::config::Result<()> {
+        let session_throttle = config.parse_session_throttle()?;
+        let queue_throttle = config.parse_queue_throttle()?;
+        let report_config = config.parse_reports()?;
+
+        // Keep numbering stored reports where the previous configuration left off
+        report_config.analysis.report_id.store(
+            self.report
+                .config
+                .load()
+                .analysis
+                .report_id
+                .load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+
+        self.session
+            .config
+            .throttle
+            .store(Arc::new(session_throttle));
+        self.queue.config.throttle.store(Arc::new(queue_throttle));
+        self.report.config.store(Arc::new(report_config));
+
+        Ok(())
+    }
+}
@@ -56,6 +56,9 @@ impl Resolvers {
         key: impl IntoFqdn<'x>,
     ) -> mail_auth::Result<Option<Arc<Tlsa>>> {
         let key = key.into_fqdn();
+        if let Some(value) = self.overrides.tlsa.get(key.to_lowercase().as_str()) {
+            return Ok(Some(value.clone()));
+        }
         if let Some(value) = self.cache.tlsa.get(key.as_ref()) {
             return Ok(Some(value));
         }
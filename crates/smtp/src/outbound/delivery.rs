@@ -34,7 +34,7 @@ use mail_auth::{
 use mail_send::SmtpClient;
 use smtp_proto::MAIL_REQUIRETLS;
 use store::write::{now, BatchBuilder, QueueClass, QueueEvent, ValueClass};
-use utils::config::ServerProtocol;
+use utils::{config::ServerProtocol, metrics::server::server_metrics};
 
 use crate::{
     config::{AggregateFrequency, RequireOptional, TlsStrategy},
@@ -46,11 +46,14 @@ use crate::{
 use super::{
     lookup::ToNextHop,
     mta_sts,
+    pool::{PoolKey, PooledClient, TlsMode},
     session::{read_greeting, say_helo, try_start_tls, SessionParams, StartTlsResult},
+    trace::{TlsSource, TlsTrace, WithTlsTrace, MAX_TLS_TRACES},
     NextHop,
 };
 use crate::queue::{
-    throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope, Status,
+    throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope, Status, MAIL_JOURNAL,
+    MAIL_SHADOW,
 };
 
 impl DeliveryAttempt {
@@ -74,6 +77,7 @@ impl DeliveryAttempt {
                     queue_id: self.event.queue_id,
                 })));
                 let _ = core.shared.default_data_store.write(batch.build()).await;
+                core.queue.index.clear(self.event.due, self.event.queue_id);
                 return;
             };
 
@@ -119,7 +123,7 @@ impl DeliveryAttempt {
             }
 
             // Throttle sender
-            for throttle in &core.queue.config.throttle.sender {
+            for throttle in &core.queue.config.throttle.load_full().sender {
                 if let Err(err) = core
                     .is_allowed(throttle, &message, &mut self.in_flight, &span)
                     .await
@@ -128,10 +132,12 @@ impl DeliveryAttempt {
                         throttle::Error::Concurrency { limiter } => {
                             // Save changes to disk
                             let next_due = message.next_event_after(now());
+                            let priority = message.priority;
                             message.save_changes(&core, None, None).await;
 
                             Event::OnHold(OnHold {
                                 next_due,
+                                priority,
                                 limiters: vec![limiter],
                                 message: self.event,
                             })
@@ -163,6 +169,17 @@ impl DeliveryAttempt {
 
             let mut domains = std::mem::take(&mut message.domains);
             let mut recipients = std::mem::take(&mut message.recipients);
+            let mut attempted = Vec::new();
+            let was_pending = recipients
+                .iter()
+                .map(|rcpt| {
+                    matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_))
+                        && matches!(
+                            domains[rcpt.domain_idx].status,
+                            Status::Scheduled | Status::TemporaryFailure(_)
+                        )
+                })
+                .collect::<Vec<_>>();
             'next_domain: for (domain_idx, domain) in domains.iter_mut().enumerate() {
                 // Only process domains due for delivery
                 if !matches!(&domain.status, Status::Scheduled | Status::TemporaryFailure(_)
@@ -171,6 +188,8 @@ impl DeliveryAttempt {
                     continue;
                 }
 
+                attempted.push(domain_idx);
+
                 // Create new span for domain
                 let span = tracing::info_span!(
                     parent: &span,
@@ -190,7 +209,7 @@ impl DeliveryAttempt {
 
                 // Throttle recipient domain
                 let mut in_flight = Vec::new();
-                for throttle in &queue_config.throttle.rcpt {
+                for throttle in &queue_config.throttle.load_full().rcpt {
                     if let Err(err) = core
                         .is_allowed(throttle, &envelope, &mut in_flight, &span)
                         .await
@@ -200,40 +219,99 @@ impl DeliveryAttempt {
                     }
                 }
 
-                // Obtain next hop
-                let (mut remote_hosts, is_smtp) = match core
-                    .eval_if::<String, _>(&queue_config.next_hop, &envelope)
+                // Shape traffic towards the recipient domain
+                if let Err(err) = core.take_shaping_token(&envelope, &span).await {
+                    domain.set_throttle_error(err, &mut on_hold);
+                    continue 'next_domain;
+                }
+
+                // Obtain next hop, shadow copies are only sent to the shadow destination
+                // and journal copies to the relay of their journal
+                let next_hop = if let Some(relay) = ((message.flags & MAIL_JOURNAL) != 0)
+                    .then(|| core.journal_relay(&domain.domain))
+                    .flatten()
+                {
+                    Some(relay)
+                } else if (message.flags & MAIL_SHADOW) == 0 {
+                    core.eval_if::<String, _>(&queue_config.next_hop, &envelope)
+                        .await
+                } else if let Some(relay) = core
+                    .eval_if::<String, _>(&queue_config.shadow.relay, &envelope)
                     .await
-                    .and_then(|name| core.get_relay_host(&name))
                 {
-                    #[cfg(feature = "local_delivery")]
-                    Some(next_hop) if next_hop.protocol == ServerProtocol::Jmap => {
-                        // Deliver message locally
-                        let delivery_result = message
-                            .deliver_local(
-                                recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
-                                &core.delivery_tx,
-                                &span,
-                            )
-                            .await;
-
-                        // Update status for the current domain and continue with the next one
-                        domain.set_status(
-                            delivery_result,
-                            &core
-                                .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                                .await
-                                .unwrap_or_else(|| vec![Duration::from_secs(60)]),
-                        );
-                        continue 'next_domain;
-                    }
-                    Some(next_hop) => (
-                        vec![NextHop::Relay(next_hop)],
-                        next_hop.protocol == ServerProtocol::Smtp,
-                    ),
-                    None => (Vec::with_capacity(0), true),
+                    Some(relay)
+                } else {
+                    tracing::info!(
+                        parent: &span,
+                        context = "shadow",
+                        event = "disabled",
+                        "Shadow delivery is no longer enabled for this domain."
+                    );
+                    domain.set_status(
+                        Status::PermanentFailure(Error::Io(
+                            "Shadow delivery is no longer enabled.".to_string(),
+                        )),
+                        &[],
+                    );
+                    continue 'next_domain;
                 };
 
+                // Deliver message using an external command
+                if let Some((pipe_id, pipe)) = next_hop
+                    .as_ref()
+                    .and_then(|name| core.shared.pipes.get_key_value(name))
+                {
+                    let delivery_result = message
+                        .deliver_pipe(
+                            pipe_id,
+                            pipe,
+                            recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
+                            &core,
+                            &span,
+                        )
+                        .await;
+
+                    // Update status for the current domain and continue with the next one
+                    domain.set_status(
+                        delivery_result,
+                        &core
+                            .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
+                            .await
+                            .unwrap_or_else(|| vec![Duration::from_secs(60)]),
+                    );
+                    continue 'next_domain;
+                }
+
+                let (mut remote_hosts, is_smtp) =
+                    match next_hop.and_then(|name| core.get_relay_host(&name)) {
+                        #[cfg(feature = "local_delivery")]
+                        Some(next_hop) if next_hop.protocol == ServerProtocol::Jmap => {
+                            // Deliver message locally
+                            let delivery_result = message
+                                .deliver_local(
+                                    recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
+                                    &core.delivery_tx,
+                                    &span,
+                                )
+                                .await;
+
+                            // Update status for the current domain and continue with the next one
+                            domain.set_status(
+                                delivery_result,
+                                &core
+                                    .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
+                                    .await
+                                    .unwrap_or_else(|| vec![Duration::from_secs(60)]),
+                            );
+                            continue 'next_domain;
+                        }
+                        Some(next_hop) => (
+                            vec![NextHop::Relay(next_hop)],
+                            next_hop.protocol == ServerProtocol::Smtp,
+                        ),
+                        None => (Vec::with_capacity(0), true),
+                    };
+
                 // Prepare TLS strategy
                 let mut disable_tls = false;
                 let mut tls_strategy = TlsStrategy {
@@ -247,10 +325,11 @@ impl DeliveryAttempt {
                     .eval_if(&queue_config.tls.invalid_certs, &envelope)
                     .await
                     .unwrap_or(false);
+                let mut tls_trace = TlsTrace::new();
 
                 // Obtain TLS reporting
                 let tls_report = match core
-                    .eval_if(&core.report.config.tls.send, &envelope)
+                    .eval_if(&core.report.config.load_full().tls.send, &envelope)
                     .await
                     .unwrap_or(AggregateFrequency::Never)
                 {
@@ -261,7 +340,6 @@ impl DeliveryAttempt {
                     {
                         match core
                             .resolvers
-                            .dns
                             .txt_lookup::<TlsRpt>(format!("_smtp._tls.{}.", envelope.domain))
                             .await
                         {
@@ -305,10 +383,23 @@ impl DeliveryAttempt {
                                 event = "policy-fetched",
                                 policy = ?mta_sts_policy,
                             );
+                            tls_trace.add(
+                                TlsSource::MtaSts,
+                                None,
+                                mta_sts_policy.describe(),
+                                format!("Policy {:?} found", mta_sts_policy.id),
+                            );
 
                             mta_sts_policy.into()
                         }
                         Err(err) => {
+                            tls_trace.add(
+                                TlsSource::MtaSts,
+                                None,
+                                tls_strategy.mta_sts.as_str(),
+                                err.to_string(),
+                            );
+
                             // Report MTA-STS error
                             if let Some(tls_report) = &tls_report {
                                 match &err {
@@ -319,6 +410,7 @@ impl DeliveryAttempt {
                                                 domain: envelope.domain.to_string(),
                                                 failure: FailureDetails::new(ResultType::Other)
                                                 .with_failure_reason_code("MTA-STS is required and no policy was found.")
+                                                .with_tls_trace(&tls_trace)
                                                     .into(),
                                                 tls_record: tls_report.record.clone(),
                                                 interval: tls_report.interval,
@@ -333,6 +425,7 @@ impl DeliveryAttempt {
                                             domain: envelope.domain.to_string(),
                                             failure: FailureDetails::new(&err)
                                                 .with_failure_reason_code(err.to_string())
+                                                .with_tls_trace(&tls_trace)
                                                 .into(),
                                             tls_record: tls_report.record.clone(),
                                             interval: tls_report.interval,
@@ -350,6 +443,7 @@ impl DeliveryAttempt {
                                     "Failed to retrieve MTA-STS policy: {}",
                                     err
                                 );
+                                domain.add_tls_trace(tls_trace);
                                 domain.set_status(
                                     err,
                                     &core
@@ -379,7 +473,7 @@ impl DeliveryAttempt {
                 let mx_list;
                 if is_smtp && remote_hosts.is_empty() {
                     // Lookup MX
-                    mx_list = match core.resolvers.dns.mx_lookup(&domain.domain).await {
+                    mx_list = match core.resolvers.mx_lookup(&domain.domain).await {
                         Ok(mx) => mx,
                         Err(err) => {
                             tracing::info!(
@@ -437,6 +531,13 @@ impl DeliveryAttempt {
                     envelope.mx = remote_host.hostname();
                     if let Some(mta_sts_policy) = &mta_sts_policy {
                         if !mta_sts_policy.verify(envelope.mx) {
+                            tls_trace.add(
+                                TlsSource::MtaSts,
+                                Some(envelope.mx),
+                                mta_sts_policy.describe(),
+                                "MX not authorized by policy",
+                            );
+
                             // Report MTA-STS failed verification
                             if let Some(tls_report) = &tls_report {
                                 core.schedule_report(TlsEvent {
@@ -445,6 +546,7 @@ impl DeliveryAttempt {
                                     failure: FailureDetails::new(ResultType::ValidationFailure)
                                         .with_receiving_mx_hostname(envelope.mx)
                                         .with_failure_reason_code("MX not authorized by policy.")
+                                        .with_tls_trace(&tls_trace)
                                         .into(),
                                     tls_record: tls_report.record.clone(),
                                     interval: tls_report.interval,
@@ -498,6 +600,21 @@ impl DeliveryAttempt {
                         .eval_if(&queue_config.tls.start, &envelope)
                         .await
                         .unwrap_or(RequireOptional::Optional);
+                    tls_trace.add(
+                        TlsSource::Config,
+                        Some(envelope.mx),
+                        format!(
+                            "starttls {}, dane {}, mta-sts {}",
+                            tls_strategy.tls.as_str(),
+                            tls_strategy.dane.as_str(),
+                            tls_strategy.mta_sts.as_str()
+                        ),
+                        if allow_invalid_certs || remote_host.allow_invalid_certs() {
+                            "Invalid certificates allowed"
+                        } else {
+                            "Certificates verified"
+                        },
+                    );
 
                     // Lookup DANE policy
                     let dane_policy = if tls_strategy.try_dane() && is_smtp {
@@ -515,6 +632,12 @@ impl DeliveryAttempt {
                                         mx = envelope.mx,
                                         record = ?tlsa,
                                     );
+                                    tls_trace.add(
+                                        TlsSource::Dane,
+                                        Some(envelope.mx),
+                                        tls_strategy.dane.as_str(),
+                                        "TLSA records found",
+                                    );
 
                                     tlsa.into()
                                 } else {
@@ -525,6 +648,12 @@ impl DeliveryAttempt {
                                         mx = envelope.mx,
                                         "No valid TLSA records were found.",
                                     );
+                                    tls_trace.add(
+                                        TlsSource::Dane,
+                                        Some(envelope.mx),
+                                        tls_strategy.dane.as_str(),
+                                        "No valid TLSA records were found",
+                                    );
 
                                     // Report invalid TLSA record
                                     if let Some(tls_report) = &tls_report {
@@ -534,6 +663,7 @@ impl DeliveryAttempt {
                                             failure: FailureDetails::new(ResultType::TlsaInvalid)
                                                 .with_receiving_mx_hostname(envelope.mx)
                                                 .with_failure_reason_code("Invalid TLSA record.")
+                                                .with_tls_trace(&tls_trace)
                                                 .into(),
                                             tls_record: tls_report.record.clone(),
                                             interval: tls_report.interval,
@@ -555,6 +685,13 @@ impl DeliveryAttempt {
                                 }
                             }
                             Ok(None) => {
+                                tls_trace.add(
+                                    TlsSource::Dane,
+                                    Some(envelope.mx),
+                                    tls_strategy.dane.as_str(),
+                                    "No TLSA DNSSEC records found",
+                                );
+
                                 if tls_strategy.is_dane_required() {
                                     // Report DANE required
                                     if let Some(tls_report) = &tls_report {
@@ -566,6 +703,7 @@ impl DeliveryAttempt {
                                                 .with_failure_reason_code(
                                                     "No TLSA DNSSEC records found.",
                                                 )
+                                                .with_tls_trace(&tls_trace)
                                                 .into(),
                                             tls_record: tls_report.record.clone(),
                                             interval: tls_report.interval,
@@ -591,6 +729,13 @@ impl DeliveryAttempt {
                                 None
                             }
                             Err(err) => {
+                                tls_trace.add(
+                                    TlsSource::Dane,
+                                    Some(envelope.mx),
+                                    tls_strategy.dane.as_str(),
+                                    format!("TLSA lookup failed: {err}"),
+                                );
+
                                 if tls_strategy.is_dane_required() {
                                     tracing::info!(
                                         parent: &span,
@@ -614,6 +759,7 @@ impl DeliveryAttempt {
                                                     .with_failure_reason_code(
                                                         "No TLSA records found for MX.",
                                                     )
+                                                    .with_tls_trace(&tls_trace)
                                                     .into(),
                                                     tls_record: tls_report.record.clone(),
                                                     interval: tls_report.interval,
@@ -652,7 +798,7 @@ impl DeliveryAttempt {
                         // Throttle remote host
                         let mut in_flight_host = Vec::new();
                         envelope.remote_ip = remote_ip;
-                        for throttle in &queue_config.throttle.host {
+                        for throttle in &queue_config.throttle.load_full().host {
                             if let Err(err) = core
                                 .is_allowed(throttle, &envelope, &mut in_flight_host, &span)
                                 .await
@@ -662,6 +808,164 @@ impl DeliveryAttempt {
                             }
                         }
 
+                        // Prepare TLS connector
+                        let is_strict_tls = tls_strategy.is_tls_required()
+                            || (message.flags & MAIL_REQUIRETLS) != 0
+                            || mta_sts_policy.is_some()
+                            || dane_policy.is_some();
+                        let tls_requires = if is_strict_tls { "require" } else { "optional" };
+                        let verify_certs =
+                            !(allow_invalid_certs || remote_host.allow_invalid_certs());
+                        let tls_connector = if !verify_certs {
+                            &core.queue.connectors.dummy_verify
+                        } else {
+                            &core.queue.connectors.pki_verify
+                        };
+
+                        // Obtain session parameters
+                        let local_hostname = core
+                            .eval_if::<String, _>(&queue_config.hostname, &envelope)
+                            .await
+                            .unwrap_or_else(|| "localhost".to_string());
+                        let params = SessionParams {
+                            span: &span,
+                            core: &core,
+                            credentials: remote_host.credentials(),
+                            is_smtp: remote_host.is_smtp(),
+                            pipelining: remote_host.pipelining(),
+                            hostname: envelope.mx,
+                            local_hostname: &local_hostname,
+                            timeout_ehlo: core
+                                .eval_if(&queue_config.timeout.ehlo, &envelope)
+                                .await
+                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            timeout_mail: core
+                                .eval_if(&queue_config.timeout.mail, &envelope)
+                                .await
+                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            timeout_rcpt: core
+                                .eval_if(&queue_config.timeout.rcpt, &envelope)
+                                .await
+                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            timeout_data: core
+                                .eval_if(&queue_config.timeout.data, &envelope)
+                                .await
+                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            pool_key: queue_config.pool.enable.then(|| PoolKey {
+                                mx: envelope.mx.to_string(),
+                                remote_addr: SocketAddr::new(remote_ip, remote_host.port()),
+                                source_ip,
+                                tls: if remote_host.implicit_tls() {
+                                    TlsMode::Implicit { verify_certs }
+                                } else if tls_strategy.try_start_tls() && !domain.disable_tls {
+                                    TlsMode::StartTls {
+                                        verify_certs,
+                                        dane: dane_policy.is_some(),
+                                    }
+                                } else {
+                                    TlsMode::Plain
+                                },
+                            }),
+                        };
+
+                        // Reuse an idle session to this host, if available
+                        if let Some(pool_key) = &params.pool_key {
+                            let mut session =
+                                core.queue.pool.acquire(pool_key, &queue_config.pool).await;
+                            if session.is_none()
+                                && !is_strict_tls
+                                && matches!(pool_key.tls, TlsMode::StartTls { .. })
+                            {
+                                // Hosts not offering STARTTLS are only reachable in plain-text
+                                let pool_key = PoolKey {
+                                    tls: TlsMode::Plain,
+                                    ..pool_key.clone()
+                                };
+                                session =
+                                    core.queue.pool.acquire(&pool_key, &queue_config.pool).await;
+                            }
+
+                            if let Some(session) = session {
+                                tracing::debug!(
+                                    parent: &span,
+                                    context = "connect",
+                                    event = "reuse",
+                                    mx = envelope.mx,
+                                    source_ip = %source_ip.unwrap_or(no_ip),
+                                    remote_ip = %remote_ip,
+                                    remote_port = remote_host.port(),
+                                    messages = session.messages,
+                                );
+
+                                let delivery_result = match session.client {
+                                    PooledClient::Plain(smtp_client) => {
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            tls_requires,
+                                            "Plain-text: reused session",
+                                        );
+                                        message
+                                            .deliver_session(
+                                                smtp_client,
+                                                session.capabilities,
+                                                session.messages,
+                                                recipients
+                                                    .iter_mut()
+                                                    .filter(|r| r.domain_idx == domain_idx),
+                                                params.with_tls_mode(TlsMode::Plain),
+                                            )
+                                            .await
+                                    }
+                                    PooledClient::Tls(smtp_client) => {
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            tls_requires,
+                                            "TLS negotiated: reused session",
+                                        );
+                                        message
+                                            .deliver_session(
+                                                smtp_client,
+                                                session.capabilities,
+                                                session.messages,
+                                                recipients
+                                                    .iter_mut()
+                                                    .filter(|r| r.domain_idx == domain_idx),
+                                                params,
+                                            )
+                                            .await
+                                    }
+                                };
+
+                                // Track reputation blocks for the source IP
+                                core.update_ip_reputation(
+                                    &delivery_result,
+                                    recipients.iter().filter(|r| r.domain_idx == domain_idx),
+                                    envelope.local_ip,
+                                    envelope.mx,
+                                    &span,
+                                );
+                                core.update_shaping_backoff(
+                                    &delivery_result,
+                                    recipients.iter().filter(|r| r.domain_idx == domain_idx),
+                                    &envelope,
+                                    &span,
+                                )
+                                .await;
+
+                                domain.add_tls_trace(tls_trace);
+                                domain.set_status(
+                                    delivery_result,
+                                    &core
+                                        .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
+                                        .await
+                                        .unwrap_or_else(|| vec![Duration::from_secs(60)]),
+                                );
+                                continue 'next_domain;
+                            }
+                        }
+
                         // Connect
                         let conn_timeout = core
                             .eval_if(&queue_config.timeout.connect, &envelope)
@@ -707,48 +1011,6 @@ impl DeliveryAttempt {
                             }
                         };
 
-                        // Obtain session parameters
-                        let local_hostname = core
-                            .eval_if::<String, _>(&queue_config.hostname, &envelope)
-                            .await
-                            .unwrap_or_else(|| "localhost".to_string());
-                        let params = SessionParams {
-                            span: &span,
-                            core: &core,
-                            credentials: remote_host.credentials(),
-                            is_smtp: remote_host.is_smtp(),
-                            hostname: envelope.mx,
-                            local_hostname: &local_hostname,
-                            timeout_ehlo: core
-                                .eval_if(&queue_config.timeout.ehlo, &envelope)
-                                .await
-                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
-                            timeout_mail: core
-                                .eval_if(&queue_config.timeout.mail, &envelope)
-                                .await
-                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
-                            timeout_rcpt: core
-                                .eval_if(&queue_config.timeout.rcpt, &envelope)
-                                .await
-                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
-                            timeout_data: core
-                                .eval_if(&queue_config.timeout.data, &envelope)
-                                .await
-                                .unwrap_or_else(|| Duration::from_secs(5 * 60)),
-                        };
-
-                        // Prepare TLS connector
-                        let is_strict_tls = tls_strategy.is_tls_required()
-                            || (message.flags & MAIL_REQUIRETLS) != 0
-                            || mta_sts_policy.is_some()
-                            || dane_policy.is_some();
-                        let tls_connector =
-                            if allow_invalid_certs || remote_host.allow_invalid_certs() {
-                                &core.queue.connectors.dummy_verify
-                            } else {
-                                &core.queue.connectors.pki_verify
-                            };
-
                         let delivery_result = if !remote_host.implicit_tls() {
                             // Read greeting
                             smtp_client.timeout = core
@@ -765,6 +1027,20 @@ impl DeliveryAttempt {
                                     status = %status,
                                 );
 
+                                core.update_ip_reputation(
+                                    &status,
+                                    std::iter::empty(),
+                                    envelope.local_ip,
+                                    envelope.mx,
+                                    &span,
+                                );
+                                core.update_shaping_backoff(
+                                    &status,
+                                    std::iter::empty(),
+                                    &envelope,
+                                    &span,
+                                )
+                                .await;
                                 last_status = status;
                                 continue 'next_host;
                             }
@@ -781,6 +1057,20 @@ impl DeliveryAttempt {
                                         status = %status,
                                     );
 
+                                    core.update_ip_reputation(
+                                        &status,
+                                        std::iter::empty(),
+                                        envelope.local_ip,
+                                        envelope.mx,
+                                        &span,
+                                    );
+                                    core.update_shaping_backoff(
+                                        &status,
+                                        std::iter::empty(),
+                                        &envelope,
+                                        &span,
+                                    )
+                                    .await;
                                     last_status = status;
                                     continue 'next_host;
                                 }
@@ -809,6 +1099,12 @@ impl DeliveryAttempt {
                                             protocol = ?smtp_client.tls_connection().protocol_version(),
                                             cipher = ?smtp_client.tls_connection().negotiated_cipher_suite(),
                                         );
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            tls_requires,
+                                            "TLS negotiated",
+                                        );
 
                                         // Verify DANE
                                         if let Some(dane_policy) = &dane_policy {
@@ -817,6 +1113,13 @@ impl DeliveryAttempt {
                                                 envelope.mx,
                                                 smtp_client.tls_connection().peer_certificates(),
                                             ) {
+                                                tls_trace.add(
+                                                    TlsSource::Dane,
+                                                    Some(envelope.mx),
+                                                    tls_strategy.dane.as_str(),
+                                                    "No matching certificates found",
+                                                );
+
                                                 // Report DANE verification failure
                                                 if let Some(tls_report) = &tls_report {
                                                     core.schedule_report(TlsEvent {
@@ -830,6 +1133,7 @@ impl DeliveryAttempt {
                                                         .with_failure_reason_code(
                                                             "No matching certificates found.",
                                                         )
+                                                        .with_tls_trace(&tls_trace)
                                                         .into(),
                                                         tls_record: tls_report.record.clone(),
                                                         interval: tls_report.interval,
@@ -884,6 +1188,16 @@ impl DeliveryAttempt {
                                             mx = envelope.mx,
                                             reason = reason,
                                         );
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            tls_requires,
+                                            if is_strict_tls {
+                                                format!("Failed: {reason}")
+                                            } else {
+                                                format!("Downgraded to plain-text: {reason}")
+                                            },
+                                        );
 
                                         if let Some(tls_report) = &tls_report {
                                             core.schedule_report(TlsEvent {
@@ -895,6 +1209,7 @@ impl DeliveryAttempt {
                                                 .with_receiving_mx_hostname(envelope.mx)
                                                 .with_receiving_ip(remote_ip)
                                                 .with_failure_reason_code(reason)
+                                                .with_tls_trace(&tls_trace)
                                                 .into(),
                                                 tls_record: tls_report.record.clone(),
                                                 interval: tls_report.interval,
@@ -914,7 +1229,7 @@ impl DeliveryAttempt {
                                                     recipients
                                                         .iter_mut()
                                                         .filter(|r| r.domain_idx == domain_idx),
-                                                    params,
+                                                    params.with_tls_mode(TlsMode::Plain),
                                                 )
                                                 .await
                                         }
@@ -927,6 +1242,18 @@ impl DeliveryAttempt {
                                             mx = envelope.mx,
                                             error = %error,
                                         );
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            tls_requires,
+                                            if is_strict_tls {
+                                                format!("Failed: {error}")
+                                            } else {
+                                                format!(
+                                                    "Failed, plain-text on next attempt: {error}"
+                                                )
+                                            },
+                                        );
 
                                         // Report TLS failure
                                         if let (Some(tls_report), mail_send::Error::Tls(error)) =
@@ -941,6 +1268,7 @@ impl DeliveryAttempt {
                                                 .with_receiving_mx_hostname(envelope.mx)
                                                 .with_receiving_ip(remote_ip)
                                                 .with_failure_reason_code(error.to_string())
+                                                .with_tls_trace(&tls_trace)
                                                 .into(),
                                                 tls_record: tls_report.record.clone(),
                                                 interval: tls_report.interval,
@@ -967,6 +1295,16 @@ impl DeliveryAttempt {
                                     mx = envelope.mx,
                                     reason = if domain.disable_tls {"TLS is disabled for this host"} else {"TLS is unavailable for this host, falling back to plain-text."},
                                 );
+                                tls_trace.add(
+                                    TlsSource::StartTls,
+                                    Some(envelope.mx),
+                                    tls_strategy.tls.as_str(),
+                                    if domain.disable_tls {
+                                        "Plain-text: TLS disabled after a previous failure"
+                                    } else {
+                                        "Plain-text: TLS disabled by configuration"
+                                    },
+                                );
 
                                 message
                                     .deliver(
@@ -986,7 +1324,15 @@ impl DeliveryAttempt {
                                 .unwrap_or_else(|| Duration::from_secs(3 * 60));
                             let mut smtp_client =
                                 match smtp_client.into_tls(tls_connector, envelope.mx).await {
-                                    Ok(smtp_client) => smtp_client,
+                                    Ok(smtp_client) => {
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            "implicit",
+                                            "TLS negotiated",
+                                        );
+                                        smtp_client
+                                    }
                                     Err(error) => {
                                         tracing::info!(
                                             parent: &span,
@@ -995,6 +1341,12 @@ impl DeliveryAttempt {
                                             mx = envelope.mx,
                                             error = %error,
                                         );
+                                        tls_trace.add(
+                                            TlsSource::StartTls,
+                                            Some(envelope.mx),
+                                            "implicit",
+                                            format!("Failed: {error}"),
+                                        );
 
                                         last_status = Status::from_tls_error(envelope.mx, error);
                                         continue 'next_host;
@@ -1016,6 +1368,20 @@ impl DeliveryAttempt {
                                     status = %status,
                                 );
 
+                                core.update_ip_reputation(
+                                    &status,
+                                    std::iter::empty(),
+                                    envelope.local_ip,
+                                    envelope.mx,
+                                    &span,
+                                );
+                                core.update_shaping_backoff(
+                                    &status,
+                                    std::iter::empty(),
+                                    &envelope,
+                                    &span,
+                                )
+                                .await;
                                 last_status = status;
                                 continue 'next_host;
                             }
@@ -1030,7 +1396,24 @@ impl DeliveryAttempt {
                                 .await
                         };
 
+                        // Track reputation blocks for the source IP
+                        core.update_ip_reputation(
+                            &delivery_result,
+                            recipients.iter().filter(|r| r.domain_idx == domain_idx),
+                            envelope.local_ip,
+                            envelope.mx,
+                            &span,
+                        );
+                        core.update_shaping_backoff(
+                            &delivery_result,
+                            recipients.iter().filter(|r| r.domain_idx == domain_idx),
+                            &envelope,
+                            &span,
+                        )
+                        .await;
+
                         // Update status for the current domain and continue with the next one
+                        domain.add_tls_trace(tls_trace);
                         domain.set_status(
                             delivery_result,
                             &core
@@ -1044,6 +1427,7 @@ impl DeliveryAttempt {
 
                 // Update status
                 domain.disable_tls = disable_tls;
+                domain.add_tls_trace(tls_trace);
                 domain.set_status(
                     last_status,
                     &core
@@ -1055,6 +1439,34 @@ impl DeliveryAttempt {
             message.domains = domains;
             message.recipients = recipients;
 
+            // Update delivery metrics
+            let (mut delivered, mut deferred, mut bounced) = (0, 0, 0);
+            for (rcpt, was_pending) in message.recipients.iter().zip(&was_pending) {
+                if *was_pending && attempted.contains(&rcpt.domain_idx) {
+                    match (&rcpt.status, &message.domains[rcpt.domain_idx].status) {
+                        (Status::Completed(_), _) | (_, Status::Completed(_)) => delivered += 1,
+                        (Status::PermanentFailure(_), _) | (_, Status::PermanentFailure(_)) => {
+                            bounced += 1
+                        }
+                        (Status::TemporaryFailure(_), _) | (_, Status::TemporaryFailure(_)) => {
+                            deferred += 1
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            let metrics = server_metrics();
+            metrics.delivery_attempts("delivered", delivered);
+            metrics.delivery_attempts("deferred", deferred);
+            metrics.delivery_attempts("bounced", bounced);
+
+            // Update sender domain health counters
+            core.record_delivery_health(&message, &was_pending).await;
+
+            // Mirror finished deliveries to the shadow destination
+            core.process_shadow_delivery(&message, &attempted, &span)
+                .await;
+
             // Send Delivery Status Notifications
             core.send_dsn(&mut message, &span).await;
 
@@ -1063,6 +1475,7 @@ impl DeliveryAttempt {
             let result = if !on_hold.is_empty() {
                 // Save changes to disk
                 let next_due = message.next_event_after(now());
+                let priority = message.priority;
                 message.save_changes(&core, None, None).await;
 
                 tracing::info!(
@@ -1075,6 +1488,7 @@ impl DeliveryAttempt {
 
                 Event::OnHold(OnHold {
                     next_due,
+                    priority,
                     limiters: on_hold,
                     message: self.event,
                 })
@@ -1183,6 +1597,15 @@ impl Domain {
         }
     }
 
+    pub fn add_tls_trace(&mut self, trace: TlsTrace) {
+        if !trace.is_empty() {
+            if self.tls_trace.len() >= MAX_TLS_TRACES {
+                self.tls_trace.remove(0);
+            }
+            self.tls_trace.push(trace);
+        }
+    }
+
     pub fn retry(&mut self, schedule: &[Duration]) {
         self.retry.due = now()
             + schedule[std::cmp::min(self.retry.inner as usize, schedule.len() - 1)].as_secs();
@@ -61,6 +61,7 @@ impl Message {
         let delivery_result = match delivery_tx
             .send(DeliveryEvent::Ingest {
                 message: IngestMessage {
+                    queue_id: self.id,
                     sender_address: self.return_path_lcase.clone(),
                     recipients: recipient_addresses,
                     message_blob: self.blob_hash.clone(),
@@ -153,7 +154,10 @@ impl Message {
                             details: format!("RCPT TO:<{}>", rcpt.address),
                         },
                         response: Response {
-                            code: 550,
+                            code: match code {
+                                [5, 2, 2] | [5, 3, 4] => 552,
+                                _ => 550,
+                            },
                             esc: code,
                             message: reason.into_owned(),
                         },
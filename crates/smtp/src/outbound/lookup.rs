@@ -34,7 +34,7 @@ use crate::{
     queue::{Error, ErrorDetails, Status},
 };
 
-use super::NextHop;
+use super::{reputation::provider_name, NextHop};
 
 pub struct IpLookupResult {
     pub source_ipv4: Option<IpAddr>,
@@ -56,7 +56,7 @@ impl SMTP {
             IpLookupStrategy::Ipv6thenIpv4 => (true, true, false),
         };
         let ipv4_addrs = if has_ipv4 {
-            match self.resolvers.dns.ipv4_lookup(key).await {
+            match self.resolvers.ipv4_lookup(key).await {
                 Ok(addrs) => addrs,
                 Err(_) if has_ipv6 => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
@@ -66,7 +66,7 @@ impl SMTP {
         };
 
         if has_ipv6 {
-            let ipv6_addrs = match self.resolvers.dns.ipv6_lookup(key).await {
+            let ipv6_addrs = match self.resolvers.ipv6_lookup(key).await {
                 Ok(addrs) => addrs,
                 Err(_) if !ipv4_addrs.is_empty() => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
@@ -135,38 +135,21 @@ impl SMTP {
             };
 
             // Obtain source IPv4 address
+            let provider = provider_name(remote_host.hostname());
             let source_ips = self
                 .eval_if::<Vec<Ipv4Addr>, _>(&self.queue.config.source_ip.ipv4, envelope)
                 .await
                 .unwrap_or_default();
-            match source_ips.len().cmp(&1) {
-                std::cmp::Ordering::Equal => {
-                    result.source_ipv4 = IpAddr::from(*source_ips.first().unwrap()).into();
-                }
-                std::cmp::Ordering::Greater => {
-                    result.source_ipv4 =
-                        IpAddr::from(source_ips[rand::thread_rng().gen_range(0..source_ips.len())])
-                            .into();
-                }
-                std::cmp::Ordering::Less => (),
-            }
+            result.source_ipv4 =
+                self.select_source_ip(source_ips.into_iter().map(IpAddr::from).collect(), provider);
 
             // Obtain source IPv6 address
             let source_ips = self
                 .eval_if::<Vec<Ipv6Addr>, _>(&self.queue.config.source_ip.ipv6, envelope)
                 .await
                 .unwrap_or_default();
-            match source_ips.len().cmp(&1) {
-                std::cmp::Ordering::Equal => {
-                    result.source_ipv6 = IpAddr::from(*source_ips.first().unwrap()).into();
-                }
-                std::cmp::Ordering::Greater => {
-                    result.source_ipv6 =
-                        IpAddr::from(source_ips[rand::thread_rng().gen_range(0..source_ips.len())])
-                            .into();
-                }
-                std::cmp::Ordering::Less => (),
-            }
+            result.source_ipv6 =
+                self.select_source_ip(source_ips.into_iter().map(IpAddr::from).collect(), provider);
 
             Ok(result)
         } else {
@@ -176,6 +159,28 @@ impl SMTP {
             ))))
         }
     }
+
+    fn select_source_ip(&self, mut source_ips: Vec<IpAddr>, provider: &str) -> Option<IpAddr> {
+        // Rotate away from source IPs blocked by the provider, as long as alternatives remain
+        if source_ips.len() > 1 {
+            let available = source_ips
+                .iter()
+                .filter(|ip| !self.queue.reputation.is_blocked(**ip, provider))
+                .copied()
+                .collect::<Vec<_>>();
+            if !available.is_empty() {
+                source_ips = available;
+            }
+        }
+
+        match source_ips.len().cmp(&1) {
+            std::cmp::Ordering::Equal => source_ips.first().copied(),
+            std::cmp::Ordering::Greater => {
+                source_ips[rand::thread_rng().gen_range(0..source_ips.len())].into()
+            }
+            std::cmp::Ordering::Less => None,
+        }
+    }
 }
 
 pub trait ToNextHop {
@@ -38,7 +38,12 @@ pub mod delivery;
 pub mod local;
 pub mod lookup;
 pub mod mta_sts;
+pub mod pipe;
+pub mod pool;
+pub mod reputation;
 pub mod session;
+pub mod shaping;
+pub mod trace;
 
 impl Status<(), Error> {
     pub fn from_smtp_error(hostname: &str, command: &str, err: mail_send::Error) -> Self {
@@ -295,6 +300,14 @@ impl<'x> NextHop<'x> {
         }
     }
 
+    #[inline(always)]
+    fn pipelining(&self) -> bool {
+        match self {
+            NextHop::MX(_) => false,
+            NextHop::Relay(host) => host.pipelining,
+        }
+    }
+
     #[inline(always)]
     fn is_smtp(&self) -> bool {
         match self {
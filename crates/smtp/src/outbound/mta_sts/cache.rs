@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize as _, IterateParams, Serialize as _, ValueKey,
+};
+
+use crate::core::SMTP;
+
+use super::Policy;
+
+// RFC 8461 section 3.2: max_age has a maximum value of 31557600 seconds
+pub const MAX_POLICY_AGE: u64 = 31557600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyEntry {
+    pub domain: String,
+    pub policy: Option<Policy>,
+    pub fetched_at: u64,
+    pub expires_at: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+}
+
+impl PolicyEntry {
+    pub fn valid_policy(&self, now: u64) -> Option<&Policy> {
+        self.policy.as_ref().filter(|_| self.expires_at > now)
+    }
+}
+
+impl SMTP {
+    // Inserts a policy in the in-memory cache, expiring at the given UNIX timestamp.
+    pub(super) fn cache_mta_sts_policy(
+        &self,
+        domain: &str,
+        policy: Policy,
+        expires_at: u64,
+    ) -> Arc<Policy> {
+        self.resolvers.cache.mta_sts.insert(
+            domain.to_string(),
+            Arc::new(policy),
+            Instant::now() + Duration::from_secs(expires_at.saturating_sub(now())),
+        )
+    }
+
+    pub(super) async fn store_mta_sts_policy(&self, domain: &str, policy: &Policy) -> u64 {
+        let fetched_at = now();
+        let expires_at = fetched_at + policy.max_age.min(MAX_POLICY_AGE);
+        self.save_mta_sts_entry(PolicyEntry {
+            domain: domain.to_string(),
+            policy: policy.clone().into(),
+            fetched_at,
+            expires_at,
+            last_error: None,
+            last_error_at: None,
+        })
+        .await;
+        expires_at
+    }
+
+    pub(super) async fn store_mta_sts_error(
+        &self,
+        domain: &str,
+        entry: Option<PolicyEntry>,
+        error: String,
+    ) {
+        let mut entry = entry.unwrap_or_else(|| PolicyEntry {
+            domain: domain.to_string(),
+            policy: None,
+            fetched_at: 0,
+            expires_at: 0,
+            last_error: None,
+            last_error_at: None,
+        });
+        entry.last_error = error.into();
+        entry.last_error_at = now().into();
+        self.save_mta_sts_entry(entry).await;
+    }
+
+    async fn save_mta_sts_entry(&self, entry: PolicyEntry) {
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::MtaStsPolicy(entry.domain.as_bytes().to_vec())),
+            Bincode::new(entry).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "mta-sts",
+                event = "error",
+                "Failed to write MTA-STS policy to store: {}",
+                err
+            );
+        }
+    }
+
+    pub async fn read_mta_sts_entry(&self, domain: &str) -> Option<PolicyEntry> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<PolicyEntry>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MtaStsPolicy(domain.as_bytes().to_vec()),
+            )))
+            .await
+        {
+            Ok(Some(entry)) => Some(entry.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "mta-sts",
+                    event = "error",
+                    "Failed to read MTA-STS policy from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_mta_sts_entries(
+        &self,
+        filter: impl Fn(&PolicyEntry) -> bool + Sync + Send,
+    ) -> store::Result<Vec<PolicyEntry>> {
+        let mut entries = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::MtaStsPolicy(vec![])));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::MtaStsPolicy(vec![
+            u8::MAX;
+            10
+        ])));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let entry = Bincode::<PolicyEntry>::deserialize(value)?.inner;
+                    if filter(&entry) {
+                        entries.push(entry);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| entries)
+    }
+
+    pub async fn delete_mta_sts_entry(&self, domain: &str) -> bool {
+        if self.read_mta_sts_entry(domain).await.is_none() {
+            return false;
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.clear(ValueClass::Queue(QueueClass::MtaStsPolicy(
+            domain.as_bytes().to_vec(),
+        )));
+        self.shared
+            .default_data_store
+            .write(batch.build())
+            .await
+            .is_ok()
+    }
+}
@@ -21,17 +21,15 @@
  * for more details.
 */
 
-use std::{
-    fmt::Display,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{fmt::Display, sync::Arc, time::Duration};
 
 #[cfg(feature = "test_mode")]
 pub static STS_TEST_POLICY: parking_lot::Mutex<Vec<u8>> = parking_lot::Mutex::new(Vec::new());
 
 use mail_auth::{common::lru::DnsCache, mta_sts::MtaSts, report::tlsrpt::ResultType};
 
+use store::write::now;
+
 use crate::core::SMTP;
 
 use super::{Error, Policy};
@@ -46,14 +44,13 @@ impl SMTP {
         // Lookup MTA-STS TXT record
         let record = match self
             .resolvers
-            .dns
             .txt_lookup::<MtaSts>(format!("_mta-sts.{domain}."))
             .await
         {
             Ok(record) => record,
             Err(err) => {
                 // Return the cached policy in case of failure
-                return if let Some(value) = self.resolvers.cache.mta_sts.get(domain) {
+                return if let Some(value) = self.cached_mta_sts_policy(domain).await {
                     Ok(value)
                 } else {
                     Err(err.into())
@@ -68,15 +65,56 @@ impl SMTP {
             }
         }
 
+        // Check if the policy has been persisted by a previous run
+        let entry = self.read_mta_sts_entry(domain).await;
+        if let Some(entry) = &entry {
+            if let Some(policy) = entry
+                .valid_policy(now())
+                .filter(|policy| policy.id == record.id)
+            {
+                return Ok(self.cache_mta_sts_policy(domain, policy.clone(), entry.expires_at));
+            }
+        }
+
         // Fetch policy
+        match self.fetch_mta_sts_policy(domain, &record, timeout).await {
+            Ok(policy) => {
+                let expires_at = self.store_mta_sts_policy(domain, &policy).await;
+                Ok(self.cache_mta_sts_policy(domain, policy, expires_at))
+            }
+            Err(err) => {
+                // Keep using the last known policy until it expires (RFC 8461 section 10.2)
+                let cached = if let Some(value) = self.resolvers.cache.mta_sts.get(domain) {
+                    Some(value)
+                } else {
+                    entry.as_ref().and_then(|entry| {
+                        entry.valid_policy(now()).map(|policy| {
+                            self.cache_mta_sts_policy(domain, policy.clone(), entry.expires_at)
+                        })
+                    })
+                };
+                self.store_mta_sts_error(domain, entry, err.to_string())
+                    .await;
+                cached.ok_or(err)
+            }
+        }
+    }
+
+    async fn fetch_mta_sts_policy(
+        &self,
+        domain: &str,
+        record: &MtaSts,
+        timeout: Duration,
+    ) -> Result<Policy, Error> {
         #[cfg(not(feature = "test_mode"))]
-        let bytes = reqwest::Client::builder()
-            .user_agent(crate::USER_AGENT)
-            .timeout(timeout)
-            .redirect(reqwest::redirect::Policy::none())
-            .build()?
-            .get(&format!("https://mta-sts.{domain}/.well-known/mta-sts.txt"))
-            .send()
+        let bytes = self
+            .shared
+            .http
+            .send_verified(|client| {
+                client
+                    .get(&format!("https://mta-sts.{domain}/.well-known/mta-sts.txt"))
+                    .timeout(timeout)
+            })
             .await?
             .bytes()
             .await?;
@@ -84,22 +122,22 @@ impl SMTP {
         let bytes = STS_TEST_POLICY.lock().clone();
 
         // Parse policy
-        let policy = Policy::parse(
+        Policy::parse(
             std::str::from_utf8(&bytes).map_err(|err| Error::InvalidPolicy(err.to_string()))?,
             record.id.clone(),
-        )?;
-        let valid_until = Instant::now()
-            + Duration::from_secs(if (3600..31557600).contains(&policy.max_age) {
-                policy.max_age
-            } else {
-                86400
-            });
-
-        Ok(self
-            .resolvers
-            .cache
-            .mta_sts
-            .insert(domain.to_string(), Arc::new(policy), valid_until))
+        )
+        .map_err(Error::InvalidPolicy)
+    }
+
+    async fn cached_mta_sts_policy(&self, domain: &str) -> Option<Arc<Policy>> {
+        if let Some(value) = self.resolvers.cache.mta_sts.get(domain) {
+            Some(value)
+        } else {
+            let entry = self.read_mta_sts_entry(domain).await?;
+            entry
+                .valid_policy(now())
+                .map(|policy| self.cache_mta_sts_policy(domain, policy.clone(), entry.expires_at))
+        }
     }
 
     #[cfg(feature = "test_mode")]
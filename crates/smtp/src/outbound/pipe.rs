@@ -0,0 +1,353 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::process::Stdio;
+
+use smtp_proto::Response;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+    config::PipeCommand,
+    core::SMTP,
+    queue::{Error, ErrorDetails, HostResponse, Message, Recipient, Status, RCPT_STATUS_CHANGED},
+};
+
+// Exit codes from sysexits.h
+const EX_DATAERR: i32 = 65;
+const EX_NOUSER: i32 = 67;
+const EX_NOHOST: i32 = 68;
+const EX_UNAVAILABLE: i32 = 69;
+const EX_TEMPFAIL: i32 = 75;
+const EX_NOPERM: i32 = 77;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipeResult {
+    Success,
+    TemporaryFailure(String),
+    PermanentFailure([u8; 3], String),
+}
+
+impl Message {
+    pub async fn deliver_pipe(
+        &self,
+        pipe_id: &str,
+        pipe: &PipeCommand,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        core: &SMTP,
+        span: &tracing::Span,
+    ) -> Status<(), Error> {
+        // Prepare recipients list
+        let mut total_rcpt = 0;
+        let mut total_completed = 0;
+        let mut pending_recipients = Vec::new();
+        for rcpt in recipients {
+            total_rcpt += 1;
+            if matches!(
+                &rcpt.status,
+                Status::Completed(_) | Status::PermanentFailure(_)
+            ) {
+                total_completed += 1;
+                continue;
+            }
+            pending_recipients.push(rcpt);
+        }
+
+        // Fetch message
+        let raw_message = match core
+            .shared
+            .default_blob_store
+            .get_blob(self.blob_hash.as_slice(), 0..u32::MAX)
+            .await
+        {
+            Ok(Some(raw_message)) => raw_message,
+            Ok(None) => {
+                tracing::error!(parent: span,
+                    context = "queue",
+                    event = "error",
+                    "BlobHash {:?} does not exist.",
+                    self.blob_hash,
+                );
+                return Status::TemporaryFailure(Error::Io("Queue system error.".to_string()));
+            }
+            Err(err) => {
+                tracing::error!(parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to fetch blobId {:?}: {}",
+                    self.blob_hash,
+                    err);
+                return Status::TemporaryFailure(Error::Io("Queue system error.".to_string()));
+            }
+        };
+
+        // Run the command once per recipient or once for all recipients
+        let groups = if pipe.per_recipient {
+            pending_recipients
+                .into_iter()
+                .map(|rcpt| vec![rcpt])
+                .collect::<Vec<_>>()
+        } else {
+            vec![pending_recipients]
+        };
+        for group in groups {
+            let result = pipe
+                .run(
+                    &raw_message,
+                    &self.return_path,
+                    group.iter().map(|rcpt| rcpt.address.as_str()),
+                    self.id,
+                )
+                .await;
+
+            for rcpt in group {
+                rcpt.flags |= RCPT_STATUS_CHANGED;
+                match &result {
+                    PipeResult::Success => {
+                        tracing::info!(
+                            parent: span,
+                            context = "deliver_pipe",
+                            event = "delivered",
+                            pipe = pipe_id,
+                            rcpt = rcpt.address,
+                        );
+
+                        rcpt.status = Status::Completed(HostResponse {
+                            hostname: pipe_id.to_string(),
+                            response: Response {
+                                code: 250,
+                                esc: [2, 0, 0],
+                                message: "Delivered".to_string(),
+                            },
+                        });
+                        total_completed += 1;
+                    }
+                    PipeResult::TemporaryFailure(reason) => {
+                        tracing::info!(
+                            parent: span,
+                            context = "deliver_pipe",
+                            event = "deferred",
+                            pipe = pipe_id,
+                            rcpt = rcpt.address,
+                            reason = reason,
+                        );
+
+                        rcpt.status = Status::TemporaryFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: pipe_id.to_string(),
+                                details: format!("RCPT TO:<{}>", rcpt.address),
+                            },
+                            response: Response {
+                                code: 451,
+                                esc: [4, 3, 0],
+                                message: reason.clone(),
+                            },
+                        });
+                    }
+                    PipeResult::PermanentFailure(esc, reason) => {
+                        tracing::info!(
+                            parent: span,
+                            context = "deliver_pipe",
+                            event = "rejected",
+                            pipe = pipe_id,
+                            rcpt = rcpt.address,
+                            reason = reason,
+                        );
+
+                        rcpt.status = Status::PermanentFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: pipe_id.to_string(),
+                                details: format!("RCPT TO:<{}>", rcpt.address),
+                            },
+                            response: Response {
+                                code: 550,
+                                esc: *esc,
+                                message: reason.clone(),
+                            },
+                        });
+                        total_completed += 1;
+                    }
+                }
+            }
+        }
+
+        if total_completed == total_rcpt {
+            Status::Completed(())
+        } else {
+            Status::Scheduled
+        }
+    }
+}
+
+impl PipeCommand {
+    pub async fn run<'x>(
+        &self,
+        raw_message: &[u8],
+        sender: &str,
+        recipients: impl Iterator<Item = &'x str>,
+        queue_id: u64,
+    ) -> PipeResult {
+        // Addresses starting with a dash would be taken as options by the command
+        let recipients = recipients.collect::<Vec<_>>();
+        let uses_sender = self
+            .arguments
+            .iter()
+            .any(|argument| argument.contains("{sender}"));
+        if let Some(address) = std::iter::once(sender)
+            .filter(|_| uses_sender)
+            .chain(recipients.iter().copied())
+            .find(|address| address.starts_with('-'))
+        {
+            return PipeResult::PermanentFailure(
+                [5, 1, 3],
+                format!("Address {address:?} cannot be passed to a command"),
+            );
+        }
+
+        // Build arguments, arguments containing {rcpt} are repeated for each recipient
+        let queue_id = queue_id.to_string();
+        let mut arguments = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            if argument.contains("{rcpt") {
+                for rcpt in &recipients {
+                    let (user, domain) = rcpt.rsplit_once('@').unwrap_or((rcpt, ""));
+                    arguments.push(expand_argument(
+                        argument,
+                        &[
+                            ("sender", sender),
+                            ("queue_id", &queue_id),
+                            ("rcpt", rcpt),
+                            ("rcpt_user", user),
+                            ("rcpt_domain", domain),
+                        ],
+                    ));
+                }
+            } else {
+                arguments.push(expand_argument(
+                    argument,
+                    &[("sender", sender), ("queue_id", &queue_id)],
+                ));
+            }
+        }
+
+        let mut child = match Command::new(&self.command)
+            .args(&arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                return PipeResult::TemporaryFailure(format!(
+                    "Failed to execute {:?}: {}",
+                    self.command, err
+                ))
+            }
+        };
+
+        // Write the message while the output is drained, as commands writing
+        // more than a pipe buffer would otherwise never read their input
+        let stdin = child.stdin.take();
+        let result = tokio::time::timeout(self.timeout, async move {
+            let write_stdin = async move {
+                if let Some(mut stdin) = stdin {
+                    // Commands might exit without reading the whole message
+                    let _ = stdin.write_all(raw_message).await;
+                    let _ = stdin.shutdown().await;
+                }
+            };
+            tokio::join!(write_stdin, child.wait_with_output()).1
+        })
+        .await;
+
+        match result {
+            Ok(Ok(output)) => {
+                let reason = String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .map(|line| line.trim())
+                    .find(|line| !line.is_empty())
+                    .map(|line| line.chars().take(256).collect::<String>());
+                let reason = |default: &str| reason.clone().unwrap_or_else(|| default.to_string());
+
+                match output.status.code() {
+                    Some(0) => PipeResult::Success,
+                    Some(EX_NOUSER) => {
+                        PipeResult::PermanentFailure([5, 1, 1], reason("Mailbox does not exist"))
+                    }
+                    Some(EX_NOHOST) => {
+                        PipeResult::PermanentFailure([5, 1, 2], reason("Domain does not exist"))
+                    }
+                    Some(EX_NOPERM) => {
+                        PipeResult::PermanentFailure([5, 7, 1], reason("Permission denied"))
+                    }
+                    Some(EX_DATAERR) => {
+                        PipeResult::PermanentFailure([5, 6, 0], reason("Invalid message"))
+                    }
+                    Some(EX_UNAVAILABLE) => {
+                        PipeResult::PermanentFailure([5, 3, 0], reason("Service unavailable"))
+                    }
+                    Some(EX_TEMPFAIL) => PipeResult::TemporaryFailure(reason("Temporary failure")),
+                    Some(code) => PipeResult::TemporaryFailure(reason(&format!(
+                        "Command exited with status {code}"
+                    ))),
+                    None => PipeResult::TemporaryFailure(reason("Command terminated by signal")),
+                }
+            }
+            Ok(Err(err)) => {
+                PipeResult::TemporaryFailure(format!("Failed to run {:?}: {}", self.command, err))
+            }
+            Err(_) => {
+                PipeResult::TemporaryFailure(format!("Timeout while running {:?}", self.command))
+            }
+        }
+    }
+}
+
+/// Replaces `{name}` placeholders in a single pass, so that placeholders
+/// within substituted values are left as they are.
+fn expand_argument(argument: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(argument.len());
+    let mut rest = argument;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        }) {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
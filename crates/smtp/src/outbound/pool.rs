@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use mail_send::{smtp::AssertReply, SmtpClient};
+use parking_lot::Mutex;
+use smtp_proto::EhloResponse;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+
+use crate::config::QueuePool;
+
+use super::session::quit;
+
+/// Outbound SMTP sessions left open after a delivery, so that messages queued
+/// for the same host shortly afterwards are sent over them instead of opening
+/// a new connection for each message.
+#[derive(Default)]
+pub struct ConnectionPool {
+    sessions: Mutex<AHashMap<PoolKey, Vec<PooledSession>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub mx: String,
+    pub remote_addr: SocketAddr,
+    pub source_ip: Option<IpAddr>,
+    pub tls: TlsMode,
+}
+
+/// How the session was secured. Sessions are only reused by deliveries that
+/// would have negotiated them the same way, including how the certificate
+/// presented by the remote host was verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TlsMode {
+    Plain,
+    StartTls { verify_certs: bool, dane: bool },
+    Implicit { verify_certs: bool },
+}
+
+pub struct PooledSession {
+    pub client: PooledClient,
+    pub capabilities: EhloResponse<String>,
+    pub messages: usize,
+    idle_since: Instant,
+}
+
+pub enum PooledClient {
+    Plain(SmtpClient<TcpStream>),
+    Tls(SmtpClient<TlsStream<TcpStream>>),
+}
+
+pub trait IntoPooledClient {
+    fn into_pooled(self) -> PooledClient;
+}
+
+impl ConnectionPool {
+    /// Returns an idle session to the host that is still alive.
+    pub async fn acquire(&self, key: &PoolKey, config: &QueuePool) -> Option<PooledSession> {
+        loop {
+            let mut session = {
+                let mut sessions = self.sessions.lock();
+                let idle = sessions.get_mut(key)?;
+                let session = idle.pop();
+                if idle.is_empty() {
+                    sessions.remove(key);
+                }
+                session?
+            };
+
+            if session.idle_since.elapsed() < config.idle_timeout && session.client.reset().await {
+                return Some(session);
+            }
+            session.client.quit().await;
+        }
+    }
+
+    /// Returns a session to the pool, or closes it once it reached the
+    /// maximum number of messages allowed per connection.
+    pub async fn release(
+        &self,
+        key: PoolKey,
+        client: PooledClient,
+        capabilities: EhloResponse<String>,
+        messages: usize,
+        config: &QueuePool,
+    ) {
+        if messages < config.max_messages {
+            self.sessions
+                .lock()
+                .entry(key)
+                .or_default()
+                .push(PooledSession {
+                    client,
+                    capabilities,
+                    messages,
+                    idle_since: Instant::now(),
+                });
+        } else {
+            client.quit().await;
+        }
+    }
+
+    /// Closes the sessions that have been idle for longer than the timeout.
+    pub async fn purge(&self, idle_timeout: Duration) {
+        let mut expired = Vec::new();
+        self.sessions.lock().retain(|_, sessions| {
+            let mut idx = 0;
+            while idx < sessions.len() {
+                if sessions[idx].idle_since.elapsed() >= idle_timeout {
+                    expired.push(sessions.swap_remove(idx));
+                } else {
+                    idx += 1;
+                }
+            }
+            !sessions.is_empty()
+        });
+
+        for session in expired {
+            session.client.quit().await;
+        }
+    }
+
+    pub fn idle_sessions(&self) -> usize {
+        self.sessions
+            .lock()
+            .values()
+            .map(|sessions| sessions.len())
+            .sum()
+    }
+}
+
+impl PooledClient {
+    async fn reset(&mut self) -> bool {
+        match self {
+            PooledClient::Plain(client) => reset(client).await,
+            PooledClient::Tls(client) => reset(client).await,
+        }
+    }
+
+    async fn quit(self) {
+        match self {
+            PooledClient::Plain(client) => quit(client).await,
+            PooledClient::Tls(client) => quit(client).await,
+        }
+    }
+}
+
+async fn reset<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
+    client: &mut SmtpClient<T>,
+) -> bool {
+    tokio::time::timeout(Duration::from_secs(10), client.cmd(b"RSET\r\n"))
+        .await
+        .map_or(false, |result| {
+            result.and_then(|r| r.assert_positive_completion()).is_ok()
+        })
+}
+
+impl IntoPooledClient for SmtpClient<TcpStream> {
+    fn into_pooled(self) -> PooledClient {
+        PooledClient::Plain(self)
+    }
+}
+
+impl IntoPooledClient for SmtpClient<TlsStream<TcpStream>> {
+    fn into_pooled(self) -> PooledClient {
+        PooledClient::Tls(self)
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Instant};
+
+use dashmap::DashMap;
+use smtp_proto::Response;
+
+use crate::{
+    config::IpRotation,
+    core::SMTP,
+    queue::{Error, HostResponse, Recipient, Status},
+};
+
+// Response fragments that indicate the source IP has a reputation problem
+const REPUTATION_PATTERNS: &[&str] = &[
+    "spamhaus",
+    "spamcop",
+    "barracuda",
+    "sorbs",
+    "dnsbl",
+    " rbl",
+    "blacklist",
+    "black list",
+    "blocklist",
+    "block list",
+    "blocked using",
+    "listed at",
+    "listed on",
+    "listed in",
+    "poor reputation",
+    "ip reputation",
+    "bad reputation",
+];
+
+/// Tracks source IPs that received reputation blocks from a provider, so that
+/// traffic towards that provider can be moved to alternate source IPs.
+#[derive(Default)]
+pub struct IpReputation {
+    entries: DashMap<(IpAddr, String), BlockEntry>,
+}
+
+struct BlockEntry {
+    hits: u32,
+    first_hit: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl IpReputation {
+    pub fn is_blocked(&self, source_ip: IpAddr, provider: &str) -> bool {
+        self.entries
+            .get(&(source_ip, provider.to_string()))
+            .and_then(|entry| entry.blocked_until)
+            .map_or(false, |until| until > Instant::now())
+    }
+
+    /// Registers a reputation block and returns true when the source IP has
+    /// just reached the threshold and is now blocked for the provider.
+    pub fn add_block(&self, source_ip: IpAddr, provider: &str, config: &IpRotation) -> bool {
+        let duration = if let Some(duration) = config.duration {
+            duration
+        } else {
+            return false;
+        };
+        let now = Instant::now();
+        let mut entry = self
+            .entries
+            .entry((source_ip, provider.to_string()))
+            .or_insert_with(|| BlockEntry {
+                hits: 0,
+                first_hit: now,
+                blocked_until: None,
+            });
+
+        // Start a new window once the previous block or observation period expired
+        if entry.blocked_until.map_or_else(
+            || now.duration_since(entry.first_hit) > duration,
+            |until| until <= now,
+        ) {
+            entry.hits = 0;
+            entry.first_hit = now;
+            entry.blocked_until = None;
+        }
+
+        if entry.blocked_until.is_none() {
+            entry.hits += 1;
+            if entry.hits >= config.threshold {
+                entry.blocked_until = Some(now + duration);
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+impl IpRotation {
+    pub fn is_reputation_block(&self, response: &Response<String>) -> bool {
+        if !matches!(response.code, 421 | 450 | 451 | 550 | 551 | 553 | 554) {
+            return false;
+        }
+        let message = response.message.to_lowercase();
+        REPUTATION_PATTERNS
+            .iter()
+            .copied()
+            .chain(self.patterns.iter().map(|p| p.as_str()))
+            .any(|pattern| message.contains(pattern))
+    }
+
+    /// Returns the first reputation block response found in the delivery status
+    /// of a domain or any of its recipients.
+    pub fn find_reputation_block<'x>(
+        &self,
+        status: &'x Status<(), Error>,
+        mut recipients: impl Iterator<Item = &'x Recipient>,
+    ) -> Option<&'x Response<String>> {
+        match status {
+            Status::TemporaryFailure(Error::UnexpectedResponse(HostResponse {
+                response, ..
+            }))
+            | Status::PermanentFailure(Error::UnexpectedResponse(HostResponse {
+                response, ..
+            })) if self.is_reputation_block(response) => Some(response),
+            _ => recipients.find_map(|rcpt| match &rcpt.status {
+                Status::TemporaryFailure(HostResponse { response, .. })
+                | Status::PermanentFailure(HostResponse { response, .. })
+                    if self.is_reputation_block(response) =>
+                {
+                    Some(response)
+                }
+                _ => None,
+            }),
+        }
+    }
+}
+
+impl SMTP {
+    pub fn update_ip_reputation<'x>(
+        &self,
+        status: &'x Status<(), Error>,
+        recipients: impl Iterator<Item = &'x Recipient>,
+        source_ip: IpAddr,
+        mx: &str,
+        span: &tracing::Span,
+    ) {
+        let config = &self.queue.config.ip_rotation;
+        if config.duration.is_none() || source_ip.is_unspecified() {
+            return;
+        }
+
+        if let Some(response) = config.find_reputation_block(status, recipients) {
+            let provider = provider_name(mx);
+            tracing::debug!(
+                parent: span,
+                context = "ip-rotation",
+                event = "reputation-block",
+                source_ip = %source_ip,
+                provider = provider,
+                mx = mx,
+                response = %response.message,
+            );
+
+            if self.queue.reputation.add_block(source_ip, provider, config) {
+                tracing::warn!(
+                    parent: span,
+                    context = "ip-rotation",
+                    event = "alert",
+                    source_ip = %source_ip,
+                    provider = provider,
+                    mx = mx,
+                    response = %response.message,
+                    "Source IP blocked by provider, rotating traffic to alternate source IPs."
+                );
+            }
+        }
+    }
+}
+
+/// Providers are identified by the parent domain of their MX host, so that
+/// all MX hosts of a large provider share the same reputation state.
+pub fn provider_name(mx: &str) -> &str {
+    let mx = mx.trim_end_matches('.');
+    match mx.split_once('.') {
+        Some((_, parent)) if parent.contains('.') => parent,
+        _ => mx,
+    }
+}
@@ -23,9 +23,9 @@
 
 use mail_send::{smtp::AssertReply, Credentials, SmtpClient};
 use smtp_proto::{
-    EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_REQUIRE_TLS, EXT_SIZE,
-    EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS, MAIL_SMTPUTF8,
-    RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_PIPELINING, EXT_REQUIRE_TLS,
+    EXT_SIZE, EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS,
+    MAIL_SMTPUTF8, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
 use std::time::Duration;
@@ -41,6 +41,8 @@ use crate::{
     queue::{ErrorDetails, HostResponse, RCPT_STATUS_CHANGED},
 };
 
+use super::pool::{IntoPooledClient, PoolKey, TlsMode};
+
 use crate::queue::{Error, Message, Recipient, Status};
 
 pub struct SessionParams<'x> {
@@ -49,11 +51,13 @@ pub struct SessionParams<'x> {
     pub hostname: &'x str,
     pub credentials: Option<&'x Credentials<String>>,
     pub is_smtp: bool,
+    pub pipelining: bool,
     pub local_hostname: &'x str,
     pub timeout_ehlo: Duration,
     pub timeout_mail: Duration,
     pub timeout_rcpt: Duration,
     pub timeout_data: Duration,
+    pub pool_key: Option<PoolKey>,
 }
 
 impl Message {
@@ -62,7 +66,10 @@ impl Message {
         mut smtp_client: SmtpClient<T>,
         recipients: impl Iterator<Item = &mut Recipient>,
         params: SessionParams<'_>,
-    ) -> Status<(), Error> {
+    ) -> Status<(), Error>
+    where
+        SmtpClient<T>: IntoPooledClient,
+    {
         // Obtain capabilities
         let capabilities = match say_helo(&mut smtp_client, &params).await {
             Ok(capabilities) => capabilities,
@@ -111,14 +118,58 @@ impl Message {
             };*/
         }
 
-        // MAIL FROM
+        self.deliver_session(smtp_client, capabilities, 0, recipients, params)
+            .await
+    }
+
+    /// Runs a mail transaction on an established session. Once done, the session
+    /// is either returned to the connection pool or closed.
+    pub async fn deliver_session<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut smtp_client: SmtpClient<T>,
+        capabilities: EhloResponse<String>,
+        messages: usize,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        params: SessionParams<'_>,
+    ) -> Status<(), Error>
+    where
+        SmtpClient<T>: IntoPooledClient,
+    {
+        // Build RCPT TO commands for pending recipients
+        let mut total_rcpt = 0;
+        let mut total_completed = 0;
+        let mut pending_rcpts = Vec::new();
+        for rcpt in recipients {
+            total_rcpt += 1;
+            if matches!(
+                &rcpt.status,
+                Status::Completed(_) | Status::PermanentFailure(_)
+            ) {
+                total_completed += 1;
+                continue;
+            }
+
+            let cmd = self.build_rcpt_to(rcpt, &capabilities);
+            pending_rcpts.push((rcpt, cmd));
+        }
+
+        // MAIL FROM, pipelined with all RCPT TO commands if the server supports it
         smtp_client.timeout = params.timeout_mail;
         let cmd = self.build_mail_from(&capabilities);
-        if let Err(err) = smtp_client
-            .cmd(cmd.as_bytes())
-            .await
-            .and_then(|r| r.assert_positive_completion())
-        {
+        let is_pipelining = params.pipelining && capabilities.has_capability(EXT_PIPELINING);
+        let result = if is_pipelining {
+            let mut cmds = cmd.clone();
+            for (_, rcpt_cmd) in &pending_rcpts {
+                cmds.push_str(rcpt_cmd);
+            }
+            match write_chunks(&mut smtp_client, &[cmds.as_bytes()]).await {
+                Ok(_) => read_response(&mut smtp_client).await,
+                Err(err) => Err(err),
+            }
+        } else {
+            smtp_client.cmd(cmd.as_bytes()).await
+        };
+        if let Err(err) = result.and_then(|r| r.assert_positive_completion()) {
             tracing::info!(
                 parent: params.span,
                 context = "sender",
@@ -131,22 +182,15 @@ impl Message {
         }
 
         // RCPT TO
-        let mut total_rcpt = 0;
-        let mut total_completed = 0;
         let mut accepted_rcpts = Vec::new();
         smtp_client.timeout = params.timeout_rcpt;
-        for rcpt in recipients {
-            total_rcpt += 1;
-            if matches!(
-                &rcpt.status,
-                Status::Completed(_) | Status::PermanentFailure(_)
-            ) {
-                total_completed += 1;
-                continue;
-            }
-
-            let cmd = self.build_rcpt_to(rcpt, &capabilities);
-            match smtp_client.cmd(cmd.as_bytes()).await {
+        for (rcpt, cmd) in pending_rcpts {
+            let result = if is_pipelining {
+                read_response(&mut smtp_client).await
+            } else {
+                smtp_client.cmd(cmd.as_bytes()).await
+            };
+            match result {
                 Ok(response) => match response.severity() {
                     Severity::PositiveCompletion => {
                         accepted_rcpts.push((
@@ -346,7 +390,22 @@ impl Message {
             }
         }
 
-        quit(smtp_client).await;
+        if let Some(pool_key) = params.pool_key {
+            params
+                .core
+                .queue
+                .pool
+                .release(
+                    pool_key,
+                    smtp_client.into_pooled(),
+                    capabilities,
+                    messages + 1,
+                    &params.core.queue.config.pool,
+                )
+                .await;
+        } else {
+            quit(smtp_client).await;
+        }
         if total_completed == total_rcpt {
             Status::Completed(())
         } else {
@@ -421,6 +480,16 @@ impl Message {
     }
 }
 
+impl SessionParams<'_> {
+    /// Sets how the session was secured before it is returned to the pool.
+    pub fn with_tls_mode(mut self, tls: TlsMode) -> Self {
+        if let Some(pool_key) = &mut self.pool_key {
+            pool_key.tls = tls;
+        }
+        self
+    }
+}
+
 impl Recipient {
     #[inline(always)]
     pub fn has_flag(&self, flag: u64) -> bool {
@@ -496,6 +565,14 @@ pub async fn read_smtp_data_respone<T: AsyncRead + AsyncWrite + Unpin>(
         })
 }
 
+async fn read_response<T: AsyncRead + AsyncWrite + Unpin>(
+    smtp_client: &mut SmtpClient<T>,
+) -> Result<Response<String>, mail_send::Error> {
+    tokio::time::timeout(smtp_client.timeout, smtp_client.read())
+        .await
+        .map_err(|_| mail_send::Error::Timeout)?
+}
+
 pub async fn read_lmtp_data_respone<T: AsyncRead + AsyncWrite + Unpin>(
     smtp_client: &mut SmtpClient<T>,
     hostname: &str,
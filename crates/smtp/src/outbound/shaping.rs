@@ -0,0 +1,238 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use smtp_proto::Response;
+use store::LookupStore;
+use utils::config::Rate;
+
+use crate::{
+    core::SMTP,
+    queue::{throttle, Error, HostResponse, QueueEnvelope, Recipient, Status},
+};
+
+impl SMTP {
+    /// Takes a token from the bucket of the destination domain. Buckets are
+    /// tracked as a theoretical arrival time (GCRA) stored in the lookup store,
+    /// so all cluster nodes share the same view of the traffic sent.
+    pub async fn take_shaping_token(
+        &self,
+        envelope: &QueueEnvelope<'_>,
+        span: &tracing::Span,
+    ) -> Result<(), throttle::Error> {
+        let config = &self.queue.config.shaping;
+        let rate = if let Some(rate) = self.eval_if::<Rate, _>(&config.rate, envelope).await {
+            rate
+        } else {
+            return Ok(());
+        };
+        let interval = (rate.period.as_millis() as i64 / rate.requests as i64).max(1);
+        let burst = self
+            .eval_if::<u64, _>(&config.burst, envelope)
+            .await
+            .filter(|burst| *burst > 0)
+            .unwrap_or(rate.requests) as i64;
+        let store = &self.shared.default_lookup_store;
+        let key = format!("ts:{}", envelope.domain).into_bytes();
+        let expires = Some(
+            (Duration::from_millis((burst * interval) as u64) + config.backoff_max).as_secs() + 1,
+        );
+
+        let result: store::Result<Option<i64>> = async {
+            let now = now_millis();
+            let mut tat = incr_and_get(store, &key, interval, expires).await?;
+            if tat - interval < now {
+                // The bucket was refilled while idle, catch up with the current time
+                tat = incr_and_get(store, &key, now - (tat - interval), expires).await?;
+            }
+            if tat - now > burst * interval {
+                // Give the token back, the message has to wait
+                store.counter_incr(key.clone(), -interval, None).await?;
+                Ok(Some(tat - burst * interval))
+            } else {
+                Ok(None)
+            }
+        }
+        .await;
+
+        match result {
+            Ok(None) => Ok(()),
+            Ok(Some(retry_at)) => {
+                tracing::info!(
+                    parent: span,
+                    context = "shaping",
+                    event = "rate-limit-exceeded",
+                    domain = envelope.domain,
+                    max_requests = rate.requests,
+                    max_interval = rate.period.as_secs(),
+                    burst = burst,
+                    "Outbound traffic shaping limit reached for domain."
+                );
+                Err(throttle::Error::Rate {
+                    retry_at: (retry_at as u64 + 999) / 1000,
+                })
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: span,
+                    context = "shaping",
+                    event = "error",
+                    domain = envelope.domain,
+                    reason = %err,
+                    "Failed to access traffic shaping bucket."
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Delays further deliveries to a shaped domain when the remote host asked
+    /// to slow down, doubling the delay on each consecutive deferral.
+    pub async fn update_shaping_backoff<'x>(
+        &self,
+        status: &'x Status<(), Error>,
+        recipients: impl Iterator<Item = &'x Recipient>,
+        envelope: &QueueEnvelope<'_>,
+        span: &tracing::Span,
+    ) {
+        let config = &self.queue.config.shaping;
+        let backoff = if let Some(backoff) = config.backoff {
+            backoff
+        } else {
+            return;
+        };
+        let response = if let Some(response) = find_deferral(status, recipients) {
+            response
+        } else {
+            return;
+        };
+        if self
+            .eval_if::<Rate, _>(&config.rate, envelope)
+            .await
+            .is_none()
+        {
+            return;
+        }
+
+        let store = &self.shared.default_lookup_store;
+        let key = format!("ts:{}", envelope.domain).into_bytes();
+        let expires = Some(config.backoff_max.as_secs() + 1);
+
+        let result: store::Result<(u32, Duration)> = async {
+            let deferrals = incr_and_get(
+                store,
+                format!("tb:{}", envelope.domain).as_bytes(),
+                1,
+                expires,
+            )
+            .await?
+            .clamp(1, 32) as u32;
+            let delay = backoff
+                .checked_mul(1 << (deferrals - 1))
+                .unwrap_or(config.backoff_max)
+                .min(config.backoff_max);
+            let now = now_millis();
+            let tat = store.counter_get(key.clone()).await?;
+            store
+                .counter_incr(
+                    key.clone(),
+                    tat.max(now) + delay.as_millis() as i64 - tat,
+                    expires,
+                )
+                .await?;
+            Ok((deferrals, delay))
+        }
+        .await;
+
+        match result {
+            Ok((deferrals, delay)) => {
+                tracing::info!(
+                    parent: span,
+                    context = "shaping",
+                    event = "backoff",
+                    domain = envelope.domain,
+                    mx = envelope.mx,
+                    deferrals = deferrals,
+                    delay = delay.as_secs(),
+                    response = %response.message,
+                    "Remote host deferred delivery, backing off."
+                );
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: span,
+                    context = "shaping",
+                    event = "error",
+                    domain = envelope.domain,
+                    reason = %err,
+                    "Failed to update traffic shaping backoff."
+                );
+            }
+        }
+    }
+}
+
+/// Returns the first 421 or 450 response found in the delivery status of a
+/// domain or any of its recipients.
+fn find_deferral<'x>(
+    status: &'x Status<(), Error>,
+    mut recipients: impl Iterator<Item = &'x Recipient>,
+) -> Option<&'x Response<String>> {
+    match status {
+        Status::TemporaryFailure(Error::UnexpectedResponse(HostResponse { response, .. }))
+            if matches!(response.code, 421 | 450) =>
+        {
+            Some(response)
+        }
+        _ => recipients.find_map(|rcpt| match &rcpt.status {
+            Status::TemporaryFailure(HostResponse { response, .. })
+                if matches!(response.code, 421 | 450) =>
+            {
+                Some(response)
+            }
+            _ => None,
+        }),
+    }
+}
+
+async fn incr_and_get(
+    store: &LookupStore,
+    key: &[u8],
+    value: i64,
+    expires: Option<u64>,
+) -> store::Result<i64> {
+    // Counters kept in a data store do not return their updated value
+    let total = store.counter_incr(key.to_vec(), value, expires).await?;
+    if total != 0 {
+        Ok(total)
+    } else {
+        store.counter_get(key.to_vec()).await
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
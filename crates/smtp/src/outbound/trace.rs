@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use mail_auth::report::tlsrpt::FailureDetails;
+use serde::{Deserialize, Serialize};
+use store::write::now;
+
+use crate::config::RequireOptional;
+
+use super::mta_sts::{Mode, MxPattern, Policy};
+
+// Number of delivery attempts for which a TLS trace is kept per domain
+pub const MAX_TLS_TRACES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TlsSource {
+    Config,
+    MtaSts,
+    Dane,
+    StartTls,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsTraceEntry {
+    pub source: TlsSource,
+    pub mx: Option<String>,
+    pub requires: String,
+    pub outcome: String,
+}
+
+/// Records which TLS policy sources were evaluated during a delivery
+/// attempt, what each of them required and what the outcome was.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsTrace {
+    pub attempted_at: u64,
+    pub entries: Vec<TlsTraceEntry>,
+}
+
+impl TlsTrace {
+    pub fn new() -> Self {
+        TlsTrace {
+            attempted_at: now(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        source: TlsSource,
+        mx: Option<&str>,
+        requires: impl Into<String>,
+        outcome: impl Into<String>,
+    ) {
+        self.entries.push(TlsTraceEntry {
+            source,
+            mx: mx.map(|mx| mx.to_string()),
+            requires: requires.into(),
+            outcome: outcome.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Compact single-line form used in TLS-RPT failure details
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        for entry in &self.entries {
+            if !summary.is_empty() {
+                summary.push_str("; ");
+            }
+            let _ = write!(summary, "{}", entry.source.as_str());
+            if let Some(mx) = &entry.mx {
+                let _ = write!(summary, "[{mx}]");
+            }
+            let _ = write!(summary, ": {} -> {}", entry.requires, entry.outcome);
+        }
+        summary
+    }
+}
+
+impl TlsSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsSource::Config => "config",
+            TlsSource::MtaSts => "mta-sts",
+            TlsSource::Dane => "dane",
+            TlsSource::StartTls => "starttls",
+        }
+    }
+}
+
+impl RequireOptional {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequireOptional::Optional => "optional",
+            RequireOptional::Require => "require",
+            RequireOptional::Disable => "disable",
+        }
+    }
+}
+
+impl Policy {
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "mode {}, mx ",
+            match self.mode {
+                Mode::Enforce => "enforce",
+                Mode::Testing => "testing",
+                Mode::None => "none",
+            }
+        );
+        for (pos, mx) in self.mx.iter().enumerate() {
+            if pos > 0 {
+                description.push(',');
+            }
+            match mx {
+                MxPattern::Equals(mx) => description.push_str(mx),
+                MxPattern::StartsWith(mx) => {
+                    let _ = write!(description, "*.{mx}");
+                }
+            }
+        }
+        description
+    }
+}
+
+pub trait WithTlsTrace {
+    fn with_tls_trace(self, trace: &TlsTrace) -> Self;
+}
+
+impl WithTlsTrace for FailureDetails {
+    fn with_tls_trace(mut self, trace: &TlsTrace) -> Self {
+        if !trace.is_empty() {
+            self.additional_information = trace.summary().into();
+        }
+        self
+    }
+}
@@ -32,18 +32,40 @@ use smtp_proto::{
 use std::fmt::Write;
 use std::time::Duration;
 use store::write::now;
+#[cfg(feature = "local_delivery")]
+use tokio::sync::oneshot;
+#[cfg(feature = "local_delivery")]
+use utils::ipc::DeliveryEvent;
+use utils::locale::{AccountLocale, LocaleText};
+use utils::metrics::server::server_metrics;
 
-use crate::core::SMTP;
+use crate::{config::BatvKey, core::SMTP};
 
 use super::{
     Domain, Error, ErrorDetails, HostResponse, Message, Recipient, SimpleEnvelope, Status,
-    RCPT_DSN_SENT, RCPT_STATUS_CHANGED,
+    MAIL_DSN_SUPPRESSED, RCPT_DSN_SENT, RCPT_STATUS_CHANGED,
 };
 
+pub const BATV_MAX_AGE: u64 = 7;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BatvResult {
+    Unsigned,
+    Valid(String),
+    Invalid,
+}
+
 impl SMTP {
     pub async fn send_dsn(&self, message: &mut Message, span: &tracing::Span) {
         if !message.return_path.is_empty() {
+            let dedup_key = message.dsn_dedup_key();
             if let Some(dsn) = message.build_dsn(self, span).await {
+                if !self.is_dsn_allowed(message, span).await
+                    || !self.is_report_unique("dsn", &[&dedup_key], span).await
+                {
+                    return;
+                }
+
                 let mut dsn_message = self.queue.new_message("", "", "");
                 dsn_message
                     .add_recipient_parts(
@@ -58,17 +80,196 @@ impl SMTP {
                 let signature = self
                     .sign_message(message, &self.queue.config.dsn.sign, &dsn, span)
                     .await;
-                dsn_message
+                if dsn_message
                     .queue(signature.as_deref(), &dsn, self, span)
-                    .await;
+                    .await
+                {
+                    server_metrics().dsn_sent();
+                }
             }
         } else {
             message.handle_double_bounce(span);
         }
     }
+
+    #[cfg(feature = "local_delivery")]
+    async fn sender_locale(&self, address: &str) -> AccountLocale {
+        let (result_tx, result_rx) = oneshot::channel();
+        if self
+            .delivery_tx
+            .send(DeliveryEvent::Locale {
+                address: address.to_string(),
+                result_tx,
+            })
+            .await
+            .is_ok()
+        {
+            if let Ok(Some(locale)) = result_rx.await {
+                return locale;
+            }
+        }
+        AccountLocale::default()
+    }
+
+    #[cfg(not(feature = "local_delivery"))]
+    async fn sender_locale(&self, _address: &str) -> AccountLocale {
+        AccountLocale::default()
+    }
+
+    async fn is_dsn_allowed(&self, message: &Message, span: &tracing::Span) -> bool {
+        let config = &self.queue.config.dsn;
+        let reason = if (message.flags & MAIL_DSN_SUPPRESSED) != 0 {
+            "unauthenticated"
+        } else if self
+            .eval_if(&config.suppress, message)
+            .await
+            .unwrap_or(false)
+        {
+            "suppressed"
+        } else if let Some(rate) = &config.rate {
+            // Rate limit on the untagged address so that rotating BATV tags
+            // cannot be used to bypass the limit
+            let return_path = batv_strip(&config.batv.keys, &message.return_path_lcase);
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(b"dsn");
+            hasher.update(
+                return_path
+                    .as_deref()
+                    .unwrap_or(&message.return_path_lcase)
+                    .as_bytes(),
+            );
+            hasher.update(&rate.period.as_secs().to_ne_bytes()[..]);
+            hasher.update(&rate.requests.to_ne_bytes()[..]);
+
+            if self
+                .shared
+                .default_lookup_store
+                .is_rate_allowed(hasher.finalize().as_bytes(), rate, false)
+                .await
+                .unwrap_or_default()
+                .is_none()
+            {
+                return true;
+            }
+            "rate-limited"
+        } else {
+            return true;
+        };
+
+        tracing::info!(
+            parent: span,
+            context = "queue",
+            event = "dsn-suppressed",
+            reason = reason,
+            return_path = message.return_path,
+            "Delivery Status Notification not sent."
+        );
+
+        false
+    }
+}
+
+pub fn batv_sign(key: &BatvKey, address: &str) -> String {
+    if address.is_empty() || is_batv_address(address) {
+        address.to_string()
+    } else {
+        format!(
+            "prvs={}={}",
+            batv_tag(key, (now() / 86400) % 1000, address),
+            address
+        )
+    }
+}
+
+pub fn batv_verify(keys: &[BatvKey], address: &str) -> BatvResult {
+    if !is_batv_address(address) {
+        return BatvResult::Unsigned;
+    }
+    let (tag, original) = if let Some(parts) = address[5..].split_once('=') {
+        parts
+    } else {
+        return BatvResult::Invalid;
+    };
+
+    // Obtain the key used to sign the address
+    let key = if let Some(key) = tag.get(..1).and_then(|id| {
+        let id = id.parse::<u8>().ok()?;
+        keys.iter().find(|key| key.id == id)
+    }) {
+        key
+    } else {
+        return BatvResult::Invalid;
+    };
+    let day = if let Some(day) = tag.get(1..4).and_then(|day| day.parse::<u64>().ok()) {
+        day
+    } else {
+        return BatvResult::Invalid;
+    };
+    let today = (now() / 86400) % 1000;
+    if (today + 1000 - day) % 1000 <= BATV_MAX_AGE
+        && tag.eq_ignore_ascii_case(&batv_tag(key, day, original))
+    {
+        BatvResult::Valid(original.to_string())
+    } else {
+        BatvResult::Invalid
+    }
+}
+
+pub fn batv_strip(keys: &[BatvKey], address: &str) -> Option<String> {
+    match batv_verify(keys, address) {
+        BatvResult::Valid(original) => Some(original),
+        _ => None,
+    }
+}
+
+fn is_batv_address(address: &str) -> bool {
+    address
+        .get(..5)
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case("prvs="))
+}
+
+fn batv_tag(key: &BatvKey, day: u64, address: &str) -> String {
+    let mut hasher = blake3::Hasher::new_keyed(blake3::hash(&key.secret).as_bytes());
+    hasher.update(&day.to_be_bytes());
+    hasher.update(address.to_lowercase().as_bytes());
+    let hash = hasher.finalize();
+    let hash = hash.as_bytes();
+
+    format!(
+        "{}{day:03}{:02x}{:02x}{:02x}",
+        key.id, hash[0], hash[1], hash[2]
+    )
 }
 
 impl Message {
+    // Identifies a notification by the message contents and the recipient
+    // statuses it reports, which are the same on every node handling it
+    fn dsn_dedup_key(&self) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.return_path_lcase.as_bytes());
+        hasher.update(self.blob_hash.as_slice());
+        for rcpt in &self.recipients {
+            if rcpt.has_flag(RCPT_DSN_SENT | RCPT_NOTIFY_NEVER) {
+                continue;
+            }
+            let status: &[u8] = match &rcpt.status {
+                Status::Completed(_) => b"completed",
+                Status::TemporaryFailure(_) => b"temp_fail",
+                Status::PermanentFailure(_) => b"perm_fail",
+                Status::Scheduled => match &self.domains[rcpt.domain_idx].status {
+                    Status::TemporaryFailure(_) => b"temp_fail",
+                    Status::PermanentFailure(_) => b"perm_fail",
+                    Status::Scheduled | Status::Completed(_) => b"scheduled",
+                },
+            };
+            hasher.update(rcpt.address_lcase.as_bytes());
+            hasher.update(&[0]);
+            hasher.update(status);
+            hasher.update(&[0]);
+        }
+        hasher.finalize().as_bytes().to_vec()
+    }
+
     pub async fn build_dsn(&mut self, core: &SMTP, span: &tracing::Span) -> Option<Vec<u8>> {
         let config = &core.queue.config;
         let now = now();
@@ -77,6 +278,7 @@ impl Message {
         let mut txt_delay = String::new();
         let mut txt_failed = String::new();
         let mut dsn = String::new();
+        let mut retry_until = 0;
 
         for rcpt in &mut self.recipients {
             if rcpt.has_flag(RCPT_DSN_SENT | RCPT_NOTIFY_NEVER) {
@@ -100,6 +302,7 @@ impl Message {
                     rcpt.status.write_dsn(&mut dsn);
                     domain.write_dsn_will_retry_until(&mut dsn);
                     response.write_dsn_text(&rcpt.address, &mut txt_delay);
+                    retry_until = retry_until.max(domain.expires);
                 }
                 Status::PermanentFailure(response) => {
                     rcpt.flags |= RCPT_DSN_SENT | RCPT_STATUS_CHANGED;
@@ -129,6 +332,7 @@ impl Message {
                             domain.status.write_dsn(&mut dsn);
                             domain.write_dsn_will_retry_until(&mut dsn);
                             err.write_dsn_text(&rcpt.address, &domain.domain, &mut txt_delay);
+                            retry_until = retry_until.max(domain.expires);
                         }
                         Status::Scheduled
                             if domain.notify.due <= now && rcpt.has_flag(RCPT_NOTIFY_DELAY) =>
@@ -142,6 +346,7 @@ impl Message {
                                 &domain.domain,
                                 &mut txt_delay,
                             );
+                            retry_until = retry_until.max(domain.expires);
                         }
                         Status::Completed(_) => {
                             #[cfg(feature = "test_mode")]
@@ -166,35 +371,34 @@ impl Message {
         let has_delay = !txt_delay.is_empty();
         let has_failure = !txt_failed.is_empty();
 
+        // Use the sender's language and date preferences when it is a local account
+        let locale = core.sender_locale(&self.return_path_lcase).await;
         let mut txt = String::with_capacity(txt_len + 128);
-        let (subject, is_mixed) = if has_success && !has_delay && !has_failure {
-            txt.push_str(
-                "Your message has been successfully delivered to the following recipients:\r\n\r\n",
-            );
-            ("Successfully delivered message", false)
+        let (intro, subject, is_mixed) = if has_success && !has_delay && !has_failure {
+            (
+                LocaleText::DsnDelivered,
+                LocaleText::DsnSubjectDelivered,
+                false,
+            )
         } else if has_delay && !has_success && !has_failure {
-            txt.push_str("There was a temporary problem delivering your message to the following recipients:\r\n\r\n");
-            ("Warning: Delay in message delivery", false)
+            (LocaleText::DsnDelayed, LocaleText::DsnSubjectDelayed, false)
         } else if has_failure && !has_success && !has_delay {
-            txt.push_str(
-                "Your message could not be delivered to the following recipients:\r\n\r\n",
-            );
-            ("Failed to deliver message", false)
+            (LocaleText::DsnFailed, LocaleText::DsnSubjectFailed, false)
         } else if has_success {
-            txt.push_str("Your message has been partially delivered:\r\n\r\n");
-            ("Partially delivered message", true)
+            (LocaleText::DsnPartial, LocaleText::DsnSubjectPartial, true)
         } else {
-            txt.push_str("Your message could not be delivered to some recipients:\r\n\r\n");
-            (
-                "Warning: Temporary and permanent failures during message delivery",
-                true,
-            )
+            (LocaleText::DsnMixed, LocaleText::DsnSubjectMixed, true)
         };
+        let subject = locale.text(subject);
+        txt.push_str(locale.text(intro));
+        txt.push_str("\r\n\r\n");
 
         if has_success {
             if is_mixed {
-                txt.push_str(
-                    "    ----- Delivery to the following addresses was successful -----\r\n",
+                let _ = write!(
+                    txt,
+                    "    {}\r\n",
+                    locale.text(LocaleText::DsnSectionDelivered)
                 );
             }
 
@@ -204,17 +408,27 @@ impl Message {
 
         if has_delay {
             if is_mixed {
-                txt.push_str(
-                    "    ----- There was a temporary problem delivering to these addresses -----\r\n",
+                let _ = write!(
+                    txt,
+                    "    {}\r\n",
+                    locale.text(LocaleText::DsnSectionDelayed)
                 );
             }
             txt.push_str(&txt_delay);
+            if retry_until > now {
+                let _ = write!(
+                    txt,
+                    "{} {}.\r\n",
+                    locale.text(LocaleText::DsnRetryUntil),
+                    locale.format_date(retry_until as i64)
+                );
+            }
             txt.push_str("\r\n");
         }
 
         if has_failure {
             if is_mixed {
-                txt.push_str("    ----- Delivery to the following addresses failed -----\r\n");
+                let _ = write!(txt, "    {}\r\n", locale.text(LocaleText::DsnSectionFailed));
             }
             txt.push_str(&txt_failed);
             txt.push_str("\r\n");
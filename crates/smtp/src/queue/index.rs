@@ -0,0 +1,263 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::BTreeMap, time::Instant};
+
+use parking_lot::Mutex;
+use store::{
+    write::{key::DeserializeBigEndian, now, QueueClass, QueueEvent, ValueClass},
+    Deserialize, IterateParams, ValueKey, U64_LEN,
+};
+
+use crate::core::SMTP;
+
+use super::{spool::QueueEventLock, QueueId};
+
+/// In-memory copy of the queue event schedule, used to avoid range scanning
+/// the data store on every scheduler run.
+#[derive(Default)]
+pub struct QueueIndex {
+    inner: Mutex<IndexState>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    events: BTreeMap<(u64, QueueId), u64>,
+    status: IndexStatus,
+    pending: Vec<IndexChange>,
+    loaded_at: Option<Instant>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexStatus {
+    #[default]
+    Unloaded,
+    Loading,
+    Ready,
+    Overflow,
+}
+
+enum IndexChange {
+    Set {
+        due: u64,
+        queue_id: QueueId,
+        lock_expiry: u64,
+    },
+    Clear {
+        due: u64,
+        queue_id: QueueId,
+    },
+}
+
+impl QueueIndex {
+    pub fn set(&self, due: u64, queue_id: QueueId, lock_expiry: u64) {
+        let mut state = self.inner.lock();
+        match state.status {
+            IndexStatus::Ready => {
+                state.events.insert((due, queue_id), lock_expiry);
+            }
+            IndexStatus::Loading => {
+                state.pending.push(IndexChange::Set {
+                    due,
+                    queue_id,
+                    lock_expiry,
+                });
+            }
+            IndexStatus::Unloaded | IndexStatus::Overflow => (),
+        }
+    }
+
+    pub fn clear(&self, due: u64, queue_id: QueueId) {
+        let mut state = self.inner.lock();
+        match state.status {
+            IndexStatus::Ready => {
+                state.events.remove(&(due, queue_id));
+            }
+            IndexStatus::Loading => {
+                state.pending.push(IndexChange::Clear { due, queue_id });
+            }
+            IndexStatus::Unloaded | IndexStatus::Overflow => (),
+        }
+    }
+
+    /// Returns the due and next unlocked events, or `None` when the index is
+    /// not available and the data store has to be scanned instead.
+    pub fn next_events(&self, max_entries: usize) -> Option<Vec<QueueEventLock>> {
+        let mut state = self.inner.lock();
+        if state.status != IndexStatus::Ready {
+            return None;
+        } else if state.events.len() > max_entries {
+            tracing::info!(
+                context = "queue",
+                event = "index-overflow",
+                entries = state.events.len(),
+                max_entries = max_entries,
+                "Queue index exceeded its maximum size, falling back to store scans."
+            );
+            state.events.clear();
+            state.status = IndexStatus::Overflow;
+            return None;
+        }
+
+        let now = now();
+        let mut events = Vec::new();
+        for (&(due, queue_id), &lock_expiry) in &state.events {
+            if lock_expiry < now {
+                events.push(QueueEventLock {
+                    due,
+                    queue_id,
+                    lock_expiry,
+                });
+            }
+            if due > now {
+                break;
+            }
+        }
+
+        Some(events)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().events.is_empty()
+    }
+
+    fn begin_rebuild(&self, interval: std::time::Duration) -> bool {
+        let mut state = self.inner.lock();
+        let needs_rebuild = match state.status {
+            IndexStatus::Unloaded => true,
+            IndexStatus::Loading => false,
+            IndexStatus::Ready | IndexStatus::Overflow => state
+                .loaded_at
+                .map_or(true, |loaded_at| loaded_at.elapsed() >= interval),
+        };
+        if needs_rebuild {
+            state.status = IndexStatus::Loading;
+            state.pending.clear();
+        }
+        needs_rebuild
+    }
+
+    fn finish_rebuild(&self, events: Option<BTreeMap<(u64, QueueId), u64>>) {
+        let mut state = self.inner.lock();
+        state.loaded_at = Some(Instant::now());
+        if let Some(events) = events {
+            state.events = events;
+            for change in std::mem::take(&mut state.pending) {
+                match change {
+                    IndexChange::Set {
+                        due,
+                        queue_id,
+                        lock_expiry,
+                    } => {
+                        state.events.insert((due, queue_id), lock_expiry);
+                    }
+                    IndexChange::Clear { due, queue_id } => {
+                        state.events.remove(&(due, queue_id));
+                    }
+                }
+            }
+            state.status = IndexStatus::Ready;
+        } else {
+            state.events.clear();
+            state.pending.clear();
+            state.status = IndexStatus::Overflow;
+        }
+    }
+}
+
+impl SMTP {
+    pub async fn rebuild_queue_index(&self) {
+        let config = &self.queue.config.index;
+        if config.max_entries == 0 || !self.queue.index.begin_rebuild(config.rebuild) {
+            return;
+        }
+
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+            due: 0,
+            queue_id: 0,
+        })));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+            due: u64::MAX,
+            queue_id: u64::MAX,
+        })));
+
+        let mut events = BTreeMap::new();
+        let mut is_overflow = false;
+        let result = self
+            .shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |key, value| {
+                    if events.len() < config.max_entries {
+                        events.insert(
+                            (
+                                key.deserialize_be_u64(1)?,
+                                key.deserialize_be_u64(U64_LEN + 1)?,
+                            ),
+                            u64::deserialize(value)?,
+                        );
+                        Ok(true)
+                    } else {
+                        is_overflow = true;
+                        Ok(false)
+                    }
+                },
+            )
+            .await;
+
+        match result {
+            Ok(_) if !is_overflow => {
+                tracing::debug!(
+                    context = "queue",
+                    event = "index-rebuild",
+                    entries = events.len(),
+                    "Queue index rebuilt."
+                );
+                self.queue.index.finish_rebuild(Some(events));
+            }
+            Ok(_) => {
+                tracing::info!(
+                    context = "queue",
+                    event = "index-overflow",
+                    max_entries = config.max_entries,
+                    "Queue exceeds the maximum index size, falling back to store scans."
+                );
+                self.queue.index.finish_rebuild(None);
+            }
+            Err(err) => {
+                tracing::error!(
+                    context = "queue",
+                    event = "error",
+                    "Failed to rebuild queue index: {}",
+                    err
+                );
+                self.queue.index.finish_rebuild(None);
+            }
+        }
+    }
+}
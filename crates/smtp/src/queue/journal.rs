@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{config::Journal, core::SMTP};
+
+use super::{DomainPart, Message, QueueId, SimpleEnvelope, MAIL_DSN_SUPPRESSED, MAIL_JOURNAL};
+
+impl SMTP {
+    /// Evaluates the journaling rules for every recipient of a message and
+    /// returns the ids of the journals it has to be copied to.
+    pub async fn journals_for(&self, message: &Message) -> Vec<String> {
+        let mut journals = Vec::new();
+        for rcpt in &message.recipients {
+            if let Some(id) = self
+                .eval_if::<String, _>(
+                    &self.session.config.data.journal,
+                    &SimpleEnvelope::new_rcpt(
+                        message,
+                        &message.domains[rcpt.domain_idx].domain,
+                        &rcpt.address_lcase,
+                    ),
+                )
+                .await
+            {
+                if !journals.contains(&id) {
+                    journals.push(id);
+                }
+            }
+        }
+        journals
+    }
+
+    /// Copies an accepted message to the journals. Copies addressed to a
+    /// mailbox or relay are queued with bounces suppressed, blob copies are
+    /// written under the journal prefix using the id of the original message.
+    pub async fn journal_message(
+        &self,
+        journals: &[String],
+        queue_id: QueueId,
+        return_path: &str,
+        raw_headers: &[u8],
+        raw_message: &[u8],
+        span: &tracing::Span,
+    ) {
+        for id in journals {
+            let address = match self.shared.journals.get(id) {
+                Some(Journal::Mailbox { address } | Journal::Relay { address, .. }) => address,
+                Some(Journal::Blob { prefix }) => {
+                    let key = format!("{prefix}{queue_id}.eml");
+                    match self
+                        .shared
+                        .default_blob_store
+                        .put_blob_parts(key.as_bytes(), &[raw_headers, raw_message])
+                        .await
+                    {
+                        Ok(_) => {
+                            tracing::debug!(parent: span,
+                                context = "journal",
+                                event = "archive",
+                                journal = id,
+                                key = key,
+                                "Archived message to blob store.");
+                        }
+                        Err(err) => {
+                            tracing::error!(parent: span,
+                                context = "journal",
+                                event = "error",
+                                journal = id,
+                                "Failed to archive message to blob store: {}",
+                                err);
+                        }
+                    }
+                    continue;
+                }
+                None => {
+                    tracing::warn!(parent: span,
+                        context = "journal",
+                        event = "not-found",
+                        journal = id,
+                        "Journal does not exist.");
+                    continue;
+                }
+            };
+
+            let return_path_lcase = return_path.to_lowercase();
+            let return_path_domain = return_path_lcase.domain_part().to_string();
+            let mut copy =
+                self.queue
+                    .new_message(return_path, return_path_lcase, return_path_domain);
+            copy.flags = MAIL_JOURNAL | MAIL_DSN_SUPPRESSED;
+            copy.size = raw_headers.len() + raw_message.len();
+            copy.add_recipient(address.as_str(), self).await;
+
+            tracing::debug!(parent: span,
+                context = "journal",
+                event = "queue",
+                journal = id,
+                journal_id = copy.id,
+                "Queued journal copy of message.");
+
+            copy.queue(Some(raw_headers), raw_message, self, span).await;
+        }
+    }
+
+    /// Returns the relay journal copies addressed to a domain are sent to.
+    pub fn journal_relay(&self, domain: &str) -> Option<String> {
+        self.shared
+            .journals
+            .values()
+            .find_map(|journal| match journal {
+                Journal::Relay { address, relay }
+                    if address.domain_part().eq_ignore_ascii_case(domain) =>
+                {
+                    Some(relay.clone())
+                }
+                _ => None,
+            })
+    }
+}
@@ -22,29 +22,78 @@
 */
 
 use std::{
+    collections::VecDeque,
     sync::{atomic::Ordering, Arc},
     time::Duration,
 };
 
+use ahash::AHashMap;
 use store::write::now;
 use tokio::sync::mpsc;
+use utils::listener::limiter::{ConcurrencyLimiter, InFlight};
 
 use crate::core::SMTP;
 
-use super::{spool::QueueEventLock, DeliveryAttempt, Event, Message, OnHold, Status};
+use super::{spool::QueueEventLock, DeliveryAttempt, Event, Message, OnHold, QueueId, Status};
 
 pub(crate) const SHORT_WAIT: Duration = Duration::from_millis(1);
 pub(crate) const LONG_WAIT: Duration = Duration::from_secs(86400 * 365);
+pub(crate) const CAPPED_WAIT: Duration = Duration::from_millis(100);
 
 pub struct Queue {
     pub core: Arc<SMTP>,
     pub on_hold: Vec<OnHold<QueueEventLock>>,
     pub next_wake_up: Duration,
+    pub dispatch: AHashMap<QueueId, Dispatch>,
+    pub in_flight: AHashMap<String, ConcurrencyLimiter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dispatch {
+    pub lane: Lane,
+    pub bucket: String,
+    pub weight: usize,
+}
+
+/// Weighted round-robin queue across sender buckets.
+pub struct FairQueue<T> {
+    buckets: Vec<Bucket<T>>,
+    next: usize,
+    served: usize,
+}
+
+struct Bucket<T> {
+    key: String,
+    weight: usize,
+    items: VecDeque<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lane {
+    High = 0,
+    Normal = 1,
+    Low = 2,
 }
 
 impl SpawnQueue for mpsc::Receiver<Event> {
     fn spawn(mut self, core: Arc<SMTP>) {
+        // Close pooled sessions once they have been idle for too long
+        if core.queue.config.pool.enable {
+            let idle_timeout = core.queue.config.pool.idle_timeout;
+            let core = Arc::downgrade(&core);
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(idle_timeout).await;
+                    match core.upgrade() {
+                        Some(core) => core.queue.pool.purge(idle_timeout).await,
+                        None => break,
+                    }
+                }
+            });
+        }
+
         tokio::spawn(async move {
+            core.load_queue_metrics().await;
             let mut queue = Queue::new(core);
 
             loop {
@@ -73,6 +122,8 @@ impl Queue {
             core,
             on_hold: Vec::with_capacity(128),
             next_wake_up: SHORT_WAIT,
+            dispatch: AHashMap::new(),
+            in_flight: AHashMap::new(),
         }
     }
 
@@ -84,23 +135,124 @@ impl Queue {
                 .await;
         }
 
-        // Deliver scheduled messages
+        // Sort scheduled messages into priority lanes and sender buckets
         let now = now();
+        let mut lanes = [FairQueue::new(), FairQueue::new(), FairQueue::new()];
         self.next_wake_up = LONG_WAIT;
         for queue_event in self.core.next_event().await {
             if queue_event.due <= now {
-                DeliveryAttempt::new(queue_event)
-                    .try_deliver(self.core.clone())
-                    .await;
+                let dispatch = self.dispatch(queue_event.queue_id).await;
+                lanes[dispatch.lane as usize].push(&dispatch.bucket, dispatch.weight, queue_event);
             } else {
                 self.next_wake_up = Duration::from_secs(queue_event.due - now);
             }
         }
+
+        // Deliver scheduled messages using weighted round-robin across lanes and buckets
+        let config = &self.core.queue.config.lanes;
+        let weights = [config.weight_high, config.weight_normal, config.weight_low];
+        let max_dispatch = config.max_dispatch;
+        let max_in_flight = self.core.queue.config.fairness.max_in_flight;
+        let mut dispatched = 0;
+        let mut is_capped = false;
+        'dispatch: while lanes.iter().any(|lane| !lane.is_empty()) {
+            let mut round_dispatched = 0;
+            for (lane, weight) in lanes.iter_mut().zip(weights) {
+                for _ in 0..weight {
+                    if max_dispatch > 0 && dispatched >= max_dispatch {
+                        break 'dispatch;
+                    }
+                    if let Some((queue_event, in_flight)) =
+                        lane.pop(|bucket| self.acquire_bucket(bucket, max_in_flight))
+                    {
+                        self.dispatch.remove(&queue_event.queue_id);
+                        let mut attempt = DeliveryAttempt::new(queue_event);
+                        attempt.in_flight.extend(in_flight);
+                        attempt.try_deliver(self.core.clone()).await;
+                        dispatched += 1;
+                        round_dispatched += 1;
+                    } else {
+                        break;
+                    }
+                }
+            }
+
+            // Remaining messages belong to buckets that reached their in-flight limit
+            if round_dispatched == 0 {
+                is_capped = true;
+                break;
+            }
+        }
+
+        // Keep dispatch information only for messages pending dispatch
+        if lanes.iter().any(|lane| !lane.is_empty()) {
+            self.next_wake_up = if is_capped {
+                std::cmp::min(self.next_wake_up, CAPPED_WAIT)
+            } else {
+                SHORT_WAIT
+            };
+            self.dispatch.retain(|queue_id, _| {
+                lanes
+                    .iter()
+                    .any(|lane| lane.iter().any(|e| e.queue_id == *queue_id))
+            });
+        } else {
+            self.dispatch.clear();
+        }
+        self.in_flight.retain(|_, limiter| limiter.is_active());
+    }
+
+    async fn dispatch(&mut self, queue_id: QueueId) -> Dispatch {
+        if let Some(dispatch) = self.dispatch.get(&queue_id) {
+            dispatch.clone()
+        } else {
+            let dispatch = if let Some(message) = self.core.read_message(queue_id).await {
+                let config = &self.core.queue.config.fairness;
+                Dispatch {
+                    lane: Lane::from(message.priority),
+                    bucket: self
+                        .core
+                        .eval_if::<String, _>(&config.bucket, &message)
+                        .await
+                        .unwrap_or_default(),
+                    weight: self
+                        .core
+                        .eval_if::<usize, _>(&config.weight, &message)
+                        .await
+                        .unwrap_or(1)
+                        .max(1),
+                }
+            } else {
+                Dispatch {
+                    lane: Lane::Normal,
+                    bucket: String::new(),
+                    weight: 1,
+                }
+            };
+            self.dispatch.insert(queue_id, dispatch.clone());
+            dispatch
+        }
+    }
+
+    fn acquire_bucket(&mut self, bucket: &str, max_in_flight: u64) -> Option<Option<InFlight>> {
+        if max_in_flight > 0 {
+            if let Some(limiter) = self.in_flight.get(bucket) {
+                limiter.is_allowed().map(Some)
+            } else {
+                let limiter = ConcurrencyLimiter::new(max_in_flight);
+                let in_flight = limiter.is_allowed();
+                self.in_flight.insert(bucket.to_string(), limiter);
+                in_flight.map(Some)
+            }
+        } else {
+            Some(None)
+        }
     }
 
     pub fn on_hold(&mut self, message: OnHold<QueueEventLock>) {
         self.on_hold.push(OnHold {
             next_due: message.next_due,
+            priority: message.priority,
             limiters: message.limiters,
             message: message.message,
         });
@@ -108,15 +260,87 @@ impl Queue {
 
     pub fn next_on_hold(&mut self) -> Option<QueueEventLock> {
         let now = now();
-        self.on_hold
-            .iter()
-            .position(|o| {
-                o.limiters
+        let mut next: Option<(usize, i16)> = None;
+        for (pos, o) in self.on_hold.iter().enumerate() {
+            if next.map_or(true, |(_, priority)| o.priority > priority)
+                && (o
+                    .limiters
                     .iter()
                     .any(|l| l.concurrent.load(Ordering::Relaxed) < l.max_concurrent)
-                    || o.next_due.map_or(false, |due| due <= now)
-            })
-            .map(|pos| self.on_hold.remove(pos).message)
+                    || o.next_due.map_or(false, |due| due <= now))
+            {
+                next = Some((pos, o.priority));
+            }
+        }
+        next.map(|(pos, _)| self.on_hold.remove(pos).message)
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub fn new() -> Self {
+        FairQueue {
+            buckets: Vec::new(),
+            next: 0,
+            served: 0,
+        }
+    }
+
+    pub fn push(&mut self, bucket: &str, weight: usize, item: T) {
+        if let Some(bucket) = self.buckets.iter_mut().find(|b| b.key == bucket) {
+            bucket.items.push_back(item);
+        } else {
+            self.buckets.push(Bucket {
+                key: bucket.to_string(),
+                weight: weight.max(1),
+                items: VecDeque::from([item]),
+            });
+        }
+    }
+
+    /// Returns the next item in weighted round-robin order, skipping buckets
+    /// for which `acquire` does not grant a dispatch slot.
+    pub fn pop<G>(&mut self, mut acquire: impl FnMut(&str) -> Option<G>) -> Option<(T, G)> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+
+        for _ in 0..=self.buckets.len() {
+            let bucket = &mut self.buckets[self.next];
+            if self.served < bucket.weight && !bucket.items.is_empty() {
+                if let Some(guard) = acquire(&bucket.key) {
+                    self.served += 1;
+                    return bucket.items.pop_front().map(|item| (item, guard));
+                }
+            }
+            self.next = (self.next + 1) % self.buckets.len();
+            self.served = 0;
+        }
+
+        None
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(|b| b.items.is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.buckets.iter().flat_map(|b| b.items.iter())
+    }
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<i16> for Lane {
+    fn from(priority: i16) -> Self {
+        match priority {
+            1.. => Lane::High,
+            0 => Lane::Normal,
+            _ => Lane::Low,
+        }
     }
 }
 
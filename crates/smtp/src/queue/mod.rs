@@ -35,13 +35,21 @@ use utils::{
     BlobHash,
 };
 
-use crate::core::{eval::*, ResolveVariable};
+use crate::{
+    core::{eval::*, ResolveVariable},
+    outbound::trace::TlsTrace,
+};
 
 use self::spool::QueueEventLock;
 
 pub mod dsn;
+pub mod index;
+pub mod journal;
 pub mod manager;
+pub mod quarantine;
 pub mod quota;
+pub mod replay;
+pub mod shadow;
 pub mod spool;
 pub mod throttle;
 
@@ -57,6 +65,7 @@ pub enum Event {
 #[derive(Debug)]
 pub struct OnHold<T> {
     pub next_due: Option<u64>,
+    pub priority: i16,
     pub limiters: Vec<ConcurrencyLimiter>,
     pub message: T,
 }
@@ -101,6 +110,7 @@ pub struct Domain {
     pub expires: u64,
     pub status: Status<(), Error>,
     pub disable_tls: bool,
+    pub tls_trace: Vec<TlsTrace>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -115,6 +125,10 @@ pub struct Recipient {
 
 pub const RCPT_DSN_SENT: u64 = 1 << 32;
 pub const RCPT_STATUS_CHANGED: u64 = 2 << 32;
+pub const MAIL_DSN_SUPPRESSED: u64 = 1 << 32;
+pub const MAIL_QUARANTINED: u64 = 2 << 32;
+pub const MAIL_SHADOW: u64 = 4 << 32;
+pub const MAIL_JOURNAL: u64 = 8 << 32;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Status<T, E> {
@@ -0,0 +1,573 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_builder::{
+    headers::{content_type::ContentType, HeaderType},
+    mime::{make_boundary, BodyPart, MimePart},
+    MessageBuilder,
+};
+use serde::{Deserialize, Serialize};
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, QueueEvent, ValueClass},
+    Deserialize as _, IterateParams, Serialize as _, ValueKey,
+};
+use utils::constant_time_eq;
+
+use crate::{config::Quarantine, core::SMTP, inbound::dlp::IncidentStatus};
+
+use super::{Event, QueueId, Status, MAIL_QUARANTINED};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    pub queue_id: QueueId,
+    pub created: u64,
+    pub source: QuarantineSource,
+    pub reason: String,
+    pub return_path: String,
+    pub recipients: Vec<String>,
+    pub subject: String,
+    pub size: usize,
+    pub notified: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuarantineSource {
+    Dlp,
+    Milter,
+    Sieve,
+    Moderation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub queue_id: QueueId,
+    pub action: AuditAction,
+    pub actor: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub details: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Quarantined,
+    Notified,
+    Released,
+    Discarded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkAction {
+    Release,
+    Discard,
+}
+
+impl SMTP {
+    /// Records a message that was placed on hold so that it can be
+    /// reviewed. The message itself must already be in the queue with the
+    /// quarantine flag set.
+    pub async fn quarantine_message(&self, entry: QuarantineEntry) -> bool {
+        let mut batch = BatchBuilder::new();
+        self.add_audit_entry(
+            &mut batch,
+            entry.queue_id,
+            AuditAction::Quarantined,
+            "system",
+            format!("{}: {}", entry.source.as_str(), entry.reason),
+        );
+        batch.set(
+            ValueClass::Queue(QueueClass::QuarantineEntry(entry.queue_id)),
+            Bincode::new(entry).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "quarantine",
+                event = "error",
+                "Failed to write quarantine entry: {}",
+                err
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn read_quarantined(&self, queue_id: QueueId) -> Option<QuarantineEntry> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<QuarantineEntry>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::QuarantineEntry(queue_id),
+            )))
+            .await
+        {
+            Ok(Some(entry)) => Some(entry.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "quarantine",
+                    event = "error",
+                    "Failed to read quarantine entry from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_quarantined(
+        &self,
+        filter: impl Fn(&QuarantineEntry) -> bool + Sync + Send,
+    ) -> store::Result<Vec<QuarantineEntry>> {
+        let mut entries = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::QuarantineEntry(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::QuarantineEntry(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let entry = Bincode::<QuarantineEntry>::deserialize(value)?.inner;
+                    if filter(&entry) {
+                        entries.push(entry);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| entries)
+    }
+
+    pub async fn list_quarantine_audit(
+        &self,
+        filter: impl Fn(&AuditEntry) -> bool + Sync + Send,
+    ) -> store::Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::QuarantineAudit(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::QuarantineAudit(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let entry = Bincode::<AuditEntry>::deserialize(value)?.inner;
+                    if filter(&entry) {
+                        entries.push(entry);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| entries)
+    }
+
+    /// Schedules a quarantined message for delivery. Retry, notification and
+    /// expiration times are shifted by the time the message spent on hold.
+    pub async fn release_quarantined(&self, queue_id: QueueId, actor: &str) -> bool {
+        let mut message = match self.read_message(queue_id).await {
+            Some(message) if (message.flags & MAIL_QUARANTINED) != 0 => message,
+            _ => return false,
+        };
+
+        let now = now();
+        let held_for = now.saturating_sub(message.created);
+        message.flags &= !MAIL_QUARANTINED;
+        for domain in &mut message.domains {
+            if matches!(
+                domain.status,
+                Status::Scheduled | Status::TemporaryFailure(_)
+            ) {
+                domain.retry.due = now;
+                domain.notify.due += held_for;
+                domain.expires += held_for;
+            }
+        }
+
+        // Write the queue event and remove the quarantine entry
+        let due = message.next_event().unwrap_or(now);
+        let mut batch = BatchBuilder::new();
+        batch
+            .set(
+                ValueClass::Queue(QueueClass::MessageEvent(QueueEvent { due, queue_id })),
+                0u64.serialize(),
+            )
+            .clear(ValueClass::Queue(QueueClass::QuarantineEntry(queue_id)));
+        self.add_audit_entry(
+            &mut batch,
+            queue_id,
+            AuditAction::Released,
+            actor,
+            String::new(),
+        );
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "queue",
+                event = "error",
+                "Failed to write queue event: {}",
+                err
+            );
+            return false;
+        }
+        self.queue.index.set(due, queue_id, 0);
+
+        if message.save_changes(self, None, None).await {
+            tracing::info!(
+                context = "queue",
+                event = "released",
+                id = queue_id,
+                actor = actor,
+                "Quarantined message released for delivery."
+            );
+            self.update_dlp_incident(queue_id, IncidentStatus::Released)
+                .await;
+            let _ = self.queue.tx.send(Event::Reload).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Deletes a quarantined message without delivering it.
+    pub async fn discard_quarantined(&self, queue_id: QueueId, actor: &str) -> bool {
+        match self.read_message(queue_id).await {
+            Some(message) if (message.flags & MAIL_QUARANTINED) != 0 => {
+                let prev_event = message.next_delivery_event();
+                if message.remove(self, prev_event).await {
+                    let mut batch = BatchBuilder::new();
+                    batch.clear(ValueClass::Queue(QueueClass::QuarantineEntry(queue_id)));
+                    self.add_audit_entry(
+                        &mut batch,
+                        queue_id,
+                        AuditAction::Discarded,
+                        actor,
+                        String::new(),
+                    );
+                    if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+                        tracing::error!(
+                            context = "quarantine",
+                            event = "error",
+                            "Failed to write audit entry: {}",
+                            err
+                        );
+                    }
+
+                    tracing::info!(
+                        context = "queue",
+                        event = "discarded",
+                        id = queue_id,
+                        actor = actor,
+                        "Quarantined message discarded."
+                    );
+                    self.update_dlp_incident(queue_id, IncidentStatus::Discarded)
+                        .await;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Sends each reviewer a summary of the messages quarantined since the
+    /// last digest.
+    pub async fn send_quarantine_digest(&self) {
+        let config = &self.queue.config.quarantine;
+        if config.reviewers.is_empty() && config.approvers.is_empty() {
+            return;
+        }
+
+        let entries = match self
+            .list_quarantined(|entry| {
+                !config.reviewers_for(entry.source).is_empty() && !entry.notified
+            })
+            .await
+        {
+            Ok(entries) if !entries.is_empty() => entries,
+            Ok(_) => return,
+            Err(err) => {
+                tracing::error!(
+                    context = "quarantine",
+                    event = "error",
+                    "Failed to read quarantine entries from store: {}",
+                    err
+                );
+                return;
+            }
+        };
+
+        let span = tracing::info_span!(
+            "quarantine-digest",
+            reviewers = config.reviewers.len(),
+            approvers = config.approvers.len(),
+            messages = entries.len(),
+        );
+        let now = now();
+        let mut notified = Vec::new();
+        for reviewer in config.reviewers.iter().chain(config.approvers.iter()) {
+            if notified.contains(&reviewer) {
+                continue;
+            }
+            notified.push(reviewer);
+
+            let assigned = entries
+                .iter()
+                .filter(|entry| config.can_review(reviewer, entry.source))
+                .collect::<Vec<_>>();
+            if !assigned.is_empty() {
+                let digest = config.build_digest(reviewer, &assigned, now);
+                self.send_report(
+                    &config.from_address,
+                    [reviewer.as_str()].into_iter(),
+                    digest,
+                    &config.sign,
+                    &span,
+                    true,
+                )
+                .await;
+            }
+        }
+
+        // Mark entries as notified
+        let mut batch = BatchBuilder::new();
+        for mut entry in entries {
+            entry.notified = true;
+            self.add_audit_entry(
+                &mut batch,
+                entry.queue_id,
+                AuditAction::Notified,
+                "system",
+                config.reviewers_for(entry.source).join(", "),
+            );
+            batch.set(
+                ValueClass::Queue(QueueClass::QuarantineEntry(entry.queue_id)),
+                Bincode::new(entry).serialize(),
+            );
+        }
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                parent: &span,
+                context = "quarantine",
+                event = "error",
+                "Failed to update quarantine entries: {}",
+                err
+            );
+        }
+    }
+
+    pub(super) fn add_audit_entry(
+        &self,
+        batch: &mut BatchBuilder,
+        queue_id: QueueId,
+        action: AuditAction,
+        actor: &str,
+        details: String,
+    ) {
+        let timestamp = now();
+        let id = self.queue.snowflake_id.generate().unwrap_or(timestamp);
+        batch.set(
+            ValueClass::Queue(QueueClass::QuarantineAudit(id)),
+            Bincode::new(AuditEntry {
+                id,
+                timestamp,
+                queue_id,
+                action,
+                actor: actor.to_string(),
+                details,
+            })
+            .serialize(),
+        );
+    }
+}
+
+impl Quarantine {
+    /// Moderated messages are reviewed by the approvers when configured,
+    /// everything else goes to the quarantine reviewers.
+    pub fn reviewers_for(&self, source: QuarantineSource) -> &[String] {
+        if source == QuarantineSource::Moderation && !self.approvers.is_empty() {
+            &self.approvers
+        } else {
+            &self.reviewers
+        }
+    }
+
+    pub fn can_review(&self, address: &str, source: QuarantineSource) -> bool {
+        self.reviewers_for(source).iter().any(|r| r == address)
+    }
+
+    /// Builds a signed release or delete link for a reviewer.
+    pub fn build_link(
+        &self,
+        action: LinkAction,
+        queue_id: QueueId,
+        reviewer: &str,
+        now: u64,
+    ) -> Option<String> {
+        if self.link_url.is_empty() || self.link_secret.is_empty() {
+            return None;
+        }
+
+        let expires = now + self.link_expiry.as_secs();
+        Some(format!(
+            "{}/quarantine/{}?id={}&reviewer={}&expires={}&sig={}",
+            self.link_url,
+            action.as_str(),
+            queue_id,
+            form_urlencoded::byte_serialize(reviewer.as_bytes()).collect::<String>(),
+            expires,
+            self.sign_link(action, queue_id, reviewer, expires)
+        ))
+    }
+
+    pub fn verify_link(
+        &self,
+        action: LinkAction,
+        queue_id: QueueId,
+        reviewer: &str,
+        expires: u64,
+        signature: &str,
+        now: u64,
+    ) -> bool {
+        if self.link_secret.is_empty()
+            || expires < now
+            || !self
+                .reviewers
+                .iter()
+                .chain(self.approvers.iter())
+                .any(|r| r == reviewer)
+        {
+            return false;
+        }
+
+        // Compare in constant time
+        constant_time_eq(
+            self.sign_link(action, queue_id, reviewer, expires)
+                .as_bytes(),
+            signature.as_bytes(),
+        )
+    }
+
+    fn sign_link(
+        &self,
+        action: LinkAction,
+        queue_id: QueueId,
+        reviewer: &str,
+        expires: u64,
+    ) -> String {
+        let mut hasher = blake3::Hasher::new_keyed(blake3::hash(&self.link_secret).as_bytes());
+        hasher.update(action.as_str().as_bytes());
+        hasher.update(&queue_id.to_be_bytes());
+        hasher.update(&expires.to_be_bytes());
+        hasher.update(reviewer.as_bytes());
+        hasher.finalize().to_hex().to_string()
+    }
+
+    pub fn build_digest(&self, reviewer: &str, entries: &[&QuarantineEntry], now: u64) -> Vec<u8> {
+        let mut txt = format!(
+            "The following {} message(s) have been placed in quarantine and are waiting for review.\r\n",
+            entries.len()
+        );
+
+        for entry in entries {
+            txt.push_str("\r\n----------------------------------------\r\n");
+            txt.push_str(&format!("Queue ID: {}\r\n", entry.queue_id));
+            txt.push_str(&format!(
+                "Received: {}\r\n",
+                mail_parser::DateTime::from_timestamp(entry.created as i64).to_rfc822()
+            ));
+            txt.push_str(&format!("From: <{}>\r\n", entry.return_path));
+            txt.push_str(&format!("To: {}\r\n", entry.recipients.join(", ")));
+            if !entry.subject.is_empty() {
+                txt.push_str(&format!("Subject: {}\r\n", entry.subject));
+            }
+            txt.push_str(&format!("Size: {} bytes\r\n", entry.size));
+            txt.push_str(&format!(
+                "Reason: {} ({})\r\n",
+                entry.reason,
+                entry.source.as_str()
+            ));
+            if let (Some(release), Some(discard)) = (
+                self.build_link(LinkAction::Release, entry.queue_id, reviewer, now),
+                self.build_link(LinkAction::Discard, entry.queue_id, reviewer, now),
+            ) {
+                txt.push_str(&format!("\r\nRelease: {release}\r\n"));
+                txt.push_str(&format!("Delete: {discard}\r\n"));
+            }
+        }
+
+        let from_domain = self
+            .from_address
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or("localhost");
+
+        MessageBuilder::new()
+            .from((self.from_name.as_str(), self.from_address.as_str()))
+            .header("To", HeaderType::Text(reviewer.into()))
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .message_id(format!("<{}@{}>", make_boundary("."), from_domain))
+            .subject(format!(
+                "Quarantine digest: {} message(s) held for review",
+                entries.len()
+            ))
+            .body(MimePart::new(
+                ContentType::new("text/plain"),
+                BodyPart::Text(txt.into()),
+            ))
+            .write_to_vec()
+            .unwrap_or_default()
+    }
+}
+
+impl QuarantineSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineSource::Dlp => "dlp",
+            QuarantineSource::Milter => "milter",
+            QuarantineSource::Sieve => "sieve",
+            QuarantineSource::Moderation => "moderation",
+        }
+    }
+}
+
+impl LinkAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "release" => Some(LinkAction::Release),
+            "discard" | "delete" => Some(LinkAction::Discard),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkAction::Release => "release",
+            LinkAction::Discard => "discard",
+        }
+    }
+}
@@ -0,0 +1,289 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use sieve::{runtime::Variable, Envelope};
+use store::write::{BatchBuilder, QueueClass, ValueClass};
+use tokio::runtime::Handle;
+
+use crate::{
+    core::{SessionAddress, SessionData, SMTP},
+    inbound::dlp::IncidentStatus,
+    scripts::{ScriptModification, ScriptParameters, ScriptResult},
+};
+
+use super::{quarantine::AuditAction, QueueId, Status, MAIL_QUARANTINED};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct Replay {
+    pub script: String,
+    pub verdict: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub modifications: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub redelivered_id: Option<QueueId>,
+}
+
+impl SMTP {
+    /// Runs a queued message through the current DATA stage Sieve script (or the
+    /// named script) and reports the resulting verdict. When `redeliver` is set
+    /// and the script accepts the message, a new copy containing the script's
+    /// header and envelope changes is queued for the pending recipients and
+    /// replaces the original. Redelivering a quarantined message releases it.
+    /// Only messages still in the queue can be replayed: journal copies archived
+    /// to the blob store are stored without their envelope.
+    pub async fn replay_message(
+        self: &Arc<Self>,
+        queue_id: QueueId,
+        script_name: Option<String>,
+        redeliver: bool,
+    ) -> Result<Option<Replay>, String> {
+        let message = if let Some(message) = self.read_message(queue_id).await {
+            message
+        } else {
+            return Ok(None);
+        };
+
+        // Obtain script
+        let script_name = if let Some(script_name) = script_name {
+            script_name
+        } else if let Some(script_name) = self
+            .eval_if::<String, _>(&self.session.config.data.script, &message)
+            .await
+        {
+            script_name
+        } else {
+            return Err("No DATA stage script is configured.".to_string());
+        };
+        let script = self
+            .get_sieve_script(&script_name)
+            .cloned()
+            .ok_or_else(|| format!("Script {script_name:?} does not exist."))?;
+
+        // Fetch message
+        let raw_message = match self
+            .shared
+            .default_blob_store
+            .get_blob(message.blob_hash.as_slice(), 0..u32::MAX)
+            .await
+        {
+            Ok(Some(raw_message)) => Bytes::from(raw_message),
+            Ok(None) => return Err("Message blob not found.".to_string()),
+            Err(err) => {
+                tracing::error!(
+                    context = "replay",
+                    event = "error",
+                    id = queue_id,
+                    "Failed to fetch message blob: {}",
+                    err
+                );
+                return Err("Failed to fetch message blob.".to_string());
+            }
+        };
+
+        // Run script
+        let params = ScriptParameters::new()
+            .with_message(raw_message.clone())
+            .with_envelope(Envelope::From, message.return_path_lcase.clone())
+            .with_envelope(
+                Envelope::To,
+                message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| Variable::from(rcpt.address_lcase.clone()))
+                    .collect::<Vec<_>>(),
+            )
+            .set_variable("stage", "data")
+            .set_variable("replay", Variable::Integer(1));
+        let core = self.clone();
+        let handle = Handle::current();
+        let span = tracing::info_span!("replay", id = queue_id);
+        let result = self
+            .spawn_worker(move || core.run_script_blocking(script, params, handle, span))
+            .await
+            .ok_or_else(|| "Failed to run script.".to_string())?;
+
+        let mut replay = Replay {
+            script: script_name,
+            verdict: String::new(),
+            reason: None,
+            modifications: Vec::new(),
+            redelivered_id: None,
+        };
+        let (modifications, replaced_message) = match result {
+            ScriptResult::Accept { modifications } => {
+                replay.verdict = "accept".to_string();
+                (modifications, None)
+            }
+            ScriptResult::Replace {
+                message,
+                modifications,
+            } => {
+                replay.verdict = "replace".to_string();
+                (modifications, Some(message))
+            }
+            ScriptResult::Reject(reason) => {
+                replay.verdict = "reject".to_string();
+                replay.reason = reason.trim_end().to_string().into();
+                return Ok(Some(replay));
+            }
+            ScriptResult::Discard => {
+                replay.verdict = "discard".to_string();
+                return Ok(Some(replay));
+            }
+        };
+
+        // Build the envelope of the pending recipients
+        let mut envelope = SessionData::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            0,
+        );
+        envelope.mail_from = SessionAddress {
+            address: message.return_path.clone(),
+            address_lcase: message.return_path_lcase.clone(),
+            domain: message.return_path_domain.clone(),
+            flags: message.flags & !MAIL_QUARANTINED,
+            dsn_info: message.env_id.clone(),
+        }
+        .into();
+        envelope.rcpt_to = message
+            .recipients
+            .iter()
+            .filter(|rcpt| matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_)))
+            .map(|rcpt| SessionAddress {
+                address: rcpt.address.clone(),
+                address_lcase: rcpt.address_lcase.clone(),
+                domain: message.domains[rcpt.domain_idx].domain.clone(),
+                flags: rcpt.flags,
+                dsn_info: rcpt.orcpt.clone(),
+            })
+            .collect();
+
+        let mut headers = Vec::new();
+        for modification in modifications {
+            match modification {
+                ScriptModification::AddHeader { name, value } => {
+                    replay
+                        .modifications
+                        .push(format!("add-header {name}: {value}"));
+                    headers.extend_from_slice(name.as_bytes());
+                    headers.extend_from_slice(b": ");
+                    headers.extend_from_slice(value.as_bytes());
+                    if !value.ends_with('\n') {
+                        headers.extend_from_slice(b"\r\n");
+                    }
+                }
+                ScriptModification::SetEnvelope { name, value } => {
+                    replay
+                        .modifications
+                        .push(format!("set-envelope {name:?}: {value}"));
+                    envelope.apply_envelope_modification(name, value);
+                }
+                ScriptModification::Quarantine { reason } => {
+                    replay.modifications.push(format!("quarantine: {reason}"));
+                }
+            }
+        }
+
+        // Redeliver message to pending recipients
+        if redeliver {
+            let mail_from = envelope.mail_from.take().unwrap();
+            let mut new_message = self.queue.new_message(
+                mail_from.address,
+                mail_from.address_lcase,
+                mail_from.domain,
+            );
+            new_message.flags = mail_from.flags;
+            new_message.env_id = mail_from.dsn_info;
+            new_message.priority = message.priority;
+            for rcpt in envelope.rcpt_to {
+                new_message
+                    .add_recipient_parts(rcpt.address, rcpt.address_lcase, rcpt.domain, self)
+                    .await;
+                let new_rcpt = new_message.recipients.last_mut().unwrap();
+                new_rcpt.flags = rcpt.flags;
+                new_rcpt.orcpt = rcpt.dsn_info;
+            }
+
+            if !new_message.recipients.is_empty() {
+                let new_id = new_message.id;
+                let is_quarantined = (message.flags & MAIL_QUARANTINED) != 0;
+                let prev_event = if is_quarantined {
+                    message.next_delivery_event()
+                } else {
+                    message.next_event().unwrap_or_default()
+                };
+                if new_message
+                    .queue(
+                        (!headers.is_empty()).then_some(headers.as_slice()),
+                        replaced_message.as_deref().unwrap_or(raw_message.as_ref()),
+                        self,
+                        &tracing::Span::current(),
+                    )
+                    .await
+                {
+                    message.remove(self, prev_event).await;
+                    replay.redelivered_id = new_id.into();
+
+                    // Replaying a quarantined message releases it
+                    if is_quarantined {
+                        let mut batch = BatchBuilder::new();
+                        batch.clear(ValueClass::Queue(QueueClass::QuarantineEntry(queue_id)));
+                        self.add_audit_entry(
+                            &mut batch,
+                            queue_id,
+                            AuditAction::Released,
+                            "replay",
+                            format!("Redelivered as {new_id}."),
+                        );
+                        if let Err(err) = self.shared.default_data_store.write(batch.build()).await
+                        {
+                            tracing::error!(
+                                context = "replay",
+                                event = "error",
+                                id = queue_id,
+                                "Failed to release quarantined message: {}",
+                                err
+                            );
+                        }
+                        self.update_dlp_incident(queue_id, IncidentStatus::Released)
+                            .await;
+                    }
+                } else {
+                    return Err("Failed to queue message.".to_string());
+                }
+            }
+        }
+
+        Ok(Some(replay))
+    }
+}
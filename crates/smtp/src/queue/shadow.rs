@@ -0,0 +1,373 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use serde::{Deserialize, Serialize};
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize as _, IterateParams, Serialize as _, ValueKey,
+};
+
+use crate::core::SMTP;
+
+use super::{
+    Domain, Message, QueueEnvelope, QueueId, Recipient, Status, MAIL_DSN_SUPPRESSED, MAIL_SHADOW,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowEntry {
+    pub queue_id: QueueId,
+    pub primary_id: QueueId,
+    pub created: u64,
+    pub domain: String,
+    pub relay: String,
+    pub return_path: String,
+    pub recipients: Vec<ShadowRecipient>,
+    pub completed: bool,
+    pub diverged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowRecipient {
+    pub address: String,
+    pub primary: ShadowOutcome,
+    pub primary_response: String,
+    pub shadow: ShadowOutcome,
+    pub shadow_response: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShadowOutcome {
+    Pending,
+    Delivered,
+    Rejected,
+}
+
+impl SMTP {
+    /// Mirrors the domains that reached a final delivery status to the
+    /// configured shadow destination. When the message is itself a shadow
+    /// copy, the outcome is compared against the primary delivery instead.
+    pub async fn process_shadow_delivery(
+        &self,
+        message: &Message,
+        attempted: &[usize],
+        span: &tracing::Span,
+    ) {
+        let is_shadow = (message.flags & MAIL_SHADOW) != 0;
+        if !is_shadow
+            && self
+                .queue
+                .config
+                .shadow
+                .until
+                .map_or(false, |until| until <= now())
+        {
+            return;
+        }
+
+        for &domain_idx in attempted {
+            let domain = &message.domains[domain_idx];
+            if !matches!(
+                &domain.status,
+                Status::Completed(_) | Status::PermanentFailure(_)
+            ) {
+                continue;
+            }
+
+            if is_shadow {
+                self.complete_shadow_copy(message, domain_idx, span).await;
+            } else if let Some(relay) = self
+                .eval_if::<String, _>(
+                    &self.queue.config.shadow.relay,
+                    &QueueEnvelope {
+                        message,
+                        domain: &domain.domain,
+                        mx: "",
+                        remote_ip: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                        local_ip: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                    },
+                )
+                .await
+            {
+                self.queue_shadow_copy(message, domain_idx, relay, span)
+                    .await;
+            }
+        }
+    }
+
+    async fn queue_shadow_copy(
+        &self,
+        message: &Message,
+        domain_idx: usize,
+        relay: String,
+        span: &tracing::Span,
+    ) {
+        let raw_message = match self
+            .shared
+            .default_blob_store
+            .get_blob(message.blob_hash.as_slice(), 0..u32::MAX)
+            .await
+        {
+            Ok(Some(raw_message)) => raw_message,
+            Ok(None) => {
+                tracing::error!(parent: span,
+                    context = "shadow",
+                    event = "error",
+                    "BlobHash {:?} does not exist.",
+                    message.blob_hash,
+                );
+                return;
+            }
+            Err(err) => {
+                tracing::error!(parent: span,
+                    context = "shadow",
+                    event = "error",
+                    "Failed to fetch blobId {:?}: {}",
+                    message.blob_hash,
+                    err);
+                return;
+            }
+        };
+
+        // Build a copy of the message containing only the recipients of this domain
+        let domain = &message.domains[domain_idx];
+        let mut shadow = self.queue.new_message(
+            message.return_path.as_str(),
+            message.return_path_lcase.as_str(),
+            message.return_path_domain.as_str(),
+        );
+        shadow.flags = message.flags | MAIL_SHADOW | MAIL_DSN_SUPPRESSED;
+        shadow.env_id = message.env_id.clone();
+        shadow.size = message.size;
+        let mut recipients = Vec::new();
+        for rcpt in message
+            .recipients
+            .iter()
+            .filter(|rcpt| rcpt.domain_idx == domain_idx)
+        {
+            shadow
+                .add_recipient_parts(
+                    rcpt.address.as_str(),
+                    rcpt.address_lcase.as_str(),
+                    domain.domain.as_str(),
+                    self,
+                )
+                .await;
+            let (primary, primary_response) = rcpt.shadow_outcome(domain);
+            recipients.push(ShadowRecipient {
+                address: rcpt.address.clone(),
+                primary,
+                primary_response,
+                shadow: ShadowOutcome::Pending,
+                shadow_response: String::new(),
+            });
+        }
+
+        // Record the primary delivery results before queueing the copy
+        let entry = ShadowEntry {
+            queue_id: shadow.id,
+            primary_id: message.id,
+            created: now(),
+            domain: domain.domain.clone(),
+            relay,
+            return_path: message.return_path.clone(),
+            recipients,
+            completed: false,
+            diverged: false,
+        };
+        if !self.save_shadow_entry(entry).await {
+            return;
+        }
+
+        tracing::info!(parent: span,
+            context = "shadow",
+            event = "queue",
+            domain = domain.domain,
+            shadow_id = shadow.id,
+            "Queued shadow copy of message.");
+
+        shadow.queue(None, &raw_message, self, span).await;
+    }
+
+    async fn complete_shadow_copy(
+        &self,
+        message: &Message,
+        domain_idx: usize,
+        span: &tracing::Span,
+    ) {
+        let mut entry = if let Some(entry) = self.read_shadow_entry(message.id).await {
+            entry
+        } else {
+            return;
+        };
+
+        let domain = &message.domains[domain_idx];
+        for rcpt in message
+            .recipients
+            .iter()
+            .filter(|rcpt| rcpt.domain_idx == domain_idx)
+        {
+            if let Some(shadow_rcpt) = entry
+                .recipients
+                .iter_mut()
+                .find(|shadow_rcpt| shadow_rcpt.address == rcpt.address)
+            {
+                let (shadow, shadow_response) = rcpt.shadow_outcome(domain);
+                shadow_rcpt.shadow = shadow;
+                shadow_rcpt.shadow_response = shadow_response;
+            }
+        }
+        entry.completed = entry
+            .recipients
+            .iter()
+            .all(|rcpt| rcpt.shadow != ShadowOutcome::Pending);
+        entry.diverged = entry
+            .recipients
+            .iter()
+            .any(|rcpt| rcpt.shadow != ShadowOutcome::Pending && rcpt.shadow != rcpt.primary);
+
+        if entry.diverged {
+            tracing::warn!(parent: span,
+                context = "shadow",
+                event = "diverged",
+                domain = entry.domain,
+                relay = entry.relay,
+                primary_id = entry.primary_id,
+                recipients = ?entry
+                    .recipients
+                    .iter()
+                    .filter(|rcpt| rcpt.shadow != rcpt.primary)
+                    .map(|rcpt| rcpt.address.as_str())
+                    .collect::<Vec<_>>(),
+                "Shadow delivery result differs from primary delivery.");
+        } else {
+            tracing::debug!(parent: span,
+                context = "shadow",
+                event = "matched",
+                domain = entry.domain,
+                relay = entry.relay,
+                primary_id = entry.primary_id,
+                "Shadow delivery result matches primary delivery.");
+        }
+
+        self.save_shadow_entry(entry).await;
+    }
+
+    async fn save_shadow_entry(&self, entry: ShadowEntry) -> bool {
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::ShadowEntry(entry.queue_id)),
+            Bincode::new(entry).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "shadow",
+                event = "error",
+                "Failed to write shadow delivery entry: {}",
+                err
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn read_shadow_entry(&self, queue_id: QueueId) -> Option<ShadowEntry> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<ShadowEntry>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::ShadowEntry(queue_id),
+            )))
+            .await
+        {
+            Ok(Some(entry)) => Some(entry.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "shadow",
+                    event = "error",
+                    "Failed to read shadow delivery entry from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_shadow_entries(
+        &self,
+        filter: impl Fn(&ShadowEntry) -> bool + Sync + Send,
+    ) -> store::Result<Vec<ShadowEntry>> {
+        let mut entries = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::ShadowEntry(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::ShadowEntry(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let entry = Bincode::<ShadowEntry>::deserialize(value)?.inner;
+                    if filter(&entry) {
+                        entries.push(entry);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| entries)
+    }
+
+    pub async fn delete_shadow_entry(&self, queue_id: QueueId) -> bool {
+        if self.read_shadow_entry(queue_id).await.is_none() {
+            return false;
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.clear(ValueClass::Queue(QueueClass::ShadowEntry(queue_id)));
+        self.shared
+            .default_data_store
+            .write(batch.build())
+            .await
+            .is_ok()
+    }
+}
+
+impl Recipient {
+    fn shadow_outcome(&self, domain: &Domain) -> (ShadowOutcome, String) {
+        match (&self.status, &domain.status) {
+            (Status::Completed(response), _) => {
+                (ShadowOutcome::Delivered, response.response.to_string())
+            }
+            (Status::PermanentFailure(response), _) => {
+                (ShadowOutcome::Rejected, response.response.to_string())
+            }
+            (_, Status::Completed(_)) => (ShadowOutcome::Delivered, String::new()),
+            (_, Status::PermanentFailure(err)) => (ShadowOutcome::Rejected, err.to_string()),
+            _ => (ShadowOutcome::Pending, String::new()),
+        }
+    }
+}
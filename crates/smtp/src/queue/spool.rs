@@ -22,17 +22,20 @@
 */
 
 use crate::queue::DomainPart;
-use std::borrow::Cow;
+use ahash::AHashSet;
 use std::time::{Duration, SystemTime};
 use store::write::key::DeserializeBigEndian;
-use store::write::{now, BatchBuilder, Bincode, BlobOp, QueueClass, QueueEvent, ValueClass};
+use store::write::{
+    assert::HashedValue, now, BatchBuilder, Bincode, BlobOp, QueueClass, QueueEvent, ValueClass,
+};
 use store::{Deserialize, IterateParams, Serialize, ValueKey, U64_LEN};
-use utils::BlobHash;
+use utils::{metrics::server::server_metrics, BlobHash};
 
 use crate::core::{QueueCore, SMTP};
 
 use super::{
     Domain, Event, Message, QueueId, QuotaKey, Recipient, Schedule, SimpleEnvelope, Status,
+    MAIL_QUARANTINED,
 };
 
 pub const LOCK_EXPIRY: u64 = 300;
@@ -76,6 +79,15 @@ impl QueueCore {
 
 impl SMTP {
     pub async fn next_event(&self) -> Vec<QueueEventLock> {
+        // Use the in-memory index when available
+        let max_entries = self.queue.config.index.max_entries;
+        if max_entries > 0 {
+            self.rebuild_queue_index().await;
+            if let Some(events) = self.queue.index.next_events(max_entries) {
+                return events;
+            }
+        }
+
         let from_key = ValueKey::from(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
             due: 0,
             queue_id: 0,
@@ -146,7 +158,12 @@ impl SMTP {
             event.lock_expiry.serialize(),
         );
         match self.shared.default_data_store.write(batch.build()).await {
-            Ok(_) => Some(event),
+            Ok(_) => {
+                self.queue
+                    .index
+                    .set(event.due, event.queue_id, event.lock_expiry);
+                Some(event)
+            }
             Err(store::Error::AssertValueFailed) => {
                 tracing::debug!(
                     context = "queue",
@@ -191,9 +208,250 @@ impl SMTP {
             }
         }
     }
+
+    /// Adds the messages already in the queue at startup to the queue depth metric.
+    pub async fn load_queue_metrics(&self) {
+        let mut count = 0;
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(u64::MAX)));
+        match self
+            .shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending().no_values(),
+                |_, _| {
+                    count += 1;
+                    Ok(true)
+                },
+            )
+            .await
+        {
+            Ok(_) => server_metrics().messages_loaded(count),
+            Err(err) => {
+                tracing::error!(
+                    context = "queue",
+                    event = "error",
+                    "Failed to count queued messages: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    pub async fn rename_queued_address(&self, address: &str, new_address: &str) -> usize {
+        let address = address.to_lowercase();
+        let new_address = new_address.to_lowercase();
+        let new_domain = new_address.domain_part().to_string();
+
+        // Obtain queued messages sent by or addressed to the old address
+        let mut queue_ids = AHashSet::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(u64::MAX)));
+        if let Err(err) = self
+            .shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let message = Bincode::<Message>::deserialize(value)?.inner;
+                    if message.has_address(&address) {
+                        queue_ids.insert(message.id);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+        {
+            tracing::error!(
+                context = "queue",
+                event = "error",
+                "Failed to iterate queued messages: {}",
+                err
+            );
+            return 0;
+        }
+        if queue_ids.is_empty() {
+            return 0;
+        }
+
+        // Obtain the queue events of these messages
+        let mut events = Vec::with_capacity(queue_ids.len());
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+            due: 0,
+            queue_id: 0,
+        })));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+            due: u64::MAX,
+            queue_id: u64::MAX,
+        })));
+        if let Err(err) = self
+            .shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |key, value| {
+                    let queue_id = key.deserialize_be_u64(U64_LEN + 1)?;
+                    if queue_ids.contains(&queue_id) {
+                        events.push(QueueEventLock {
+                            due: key.deserialize_be_u64(1)?,
+                            queue_id,
+                            lock_expiry: u64::deserialize(value)?,
+                        });
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+        {
+            tracing::error!(
+                context = "queue",
+                event = "error",
+                "Failed to iterate queue events: {}",
+                err
+            );
+            return 0;
+        }
+
+        let mut num_renamed = 0;
+        for event in events {
+            // Lock the message so it is not modified by a delivery attempt
+            let event = if let Some(event) = self.try_lock_event(event).await {
+                event
+            } else {
+                tracing::debug!(
+                    context = "queue",
+                    event = "rename",
+                    id = event.queue_id,
+                    "Skipping address rename: Message locked by another process."
+                );
+                continue;
+            };
+
+            let message = match self
+                .shared
+                .default_data_store
+                .get_value::<HashedValue<Bincode<Message>>>(ValueKey::from(ValueClass::Queue(
+                    QueueClass::Message(event.queue_id),
+                )))
+                .await
+            {
+                Ok(Some(message)) if message.inner.inner.has_address(&address) => message,
+                Ok(_) => {
+                    self.unlock_event(event).await;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "queue",
+                        event = "error",
+                        "Failed to read message from store: {}",
+                        err
+                    );
+                    self.unlock_event(event).await;
+                    continue;
+                }
+            };
+
+            let mut batch = BatchBuilder::new();
+            batch.assert_value(
+                ValueClass::Queue(QueueClass::Message(event.queue_id)),
+                &message,
+            );
+            let mut message = message.inner.inner;
+            if message.return_path_lcase == address {
+                message.return_path = new_address.clone();
+                message.return_path_lcase = new_address.clone();
+                message.return_path_domain = new_domain.clone();
+            }
+            for idx in 0..message.recipients.len() {
+                let rcpt = &message.recipients[idx];
+                if rcpt.address_lcase != address
+                    || !matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_))
+                {
+                    continue;
+                }
+                let domain_idx = if message.domains[rcpt.domain_idx].domain == new_domain {
+                    rcpt.domain_idx
+                } else if let Some(idx) =
+                    message.domains.iter().position(|d| d.domain == new_domain)
+                {
+                    idx
+                } else {
+                    let mut domain = message.domains[rcpt.domain_idx].clone();
+                    domain.domain = new_domain.clone();
+                    message.domains.push(domain);
+                    message.domains.len() - 1
+                };
+                let rcpt = &mut message.recipients[idx];
+                rcpt.address = new_address.clone();
+                rcpt.address_lcase = new_address.clone();
+                rcpt.domain_idx = domain_idx;
+            }
+
+            // Write the message and release the lock
+            batch
+                .set(
+                    ValueClass::Queue(QueueClass::Message(message.id)),
+                    Bincode::new(message).serialize(),
+                )
+                .set(
+                    ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+                        due: event.due,
+                        queue_id: event.queue_id,
+                    })),
+                    0u64.serialize(),
+                );
+            match self.shared.default_data_store.write(batch.build()).await {
+                Ok(_) => {
+                    self.queue.index.set(event.due, event.queue_id, 0);
+                    num_renamed += 1;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "queue",
+                        event = "error",
+                        "Failed to update queued message: {}",
+                        err
+                    );
+                    self.unlock_event(event).await;
+                }
+            }
+        }
+
+        num_renamed
+    }
+
+    async fn unlock_event(&self, event: QueueEventLock) {
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
+                due: event.due,
+                queue_id: event.queue_id,
+            })),
+            0u64.serialize(),
+        );
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "queue",
+                event = "error",
+                "Failed to unlock queue event: {}",
+                err
+            );
+        } else {
+            self.queue.index.set(event.due, event.queue_id, 0);
+        }
+    }
 }
 
 impl Message {
+    fn has_address(&self, address: &str) -> bool {
+        self.return_path_lcase == address
+            || self.recipients.iter().any(|r| {
+                r.address_lcase == address
+                    && matches!(r.status, Status::Scheduled | Status::TemporaryFailure(_))
+            })
+    }
+
     pub async fn queue(
         mut self,
         raw_headers: Option<&[u8]>,
@@ -203,18 +461,23 @@ impl Message {
     ) -> bool {
         // Write blob
         let message = if let Some(raw_headers) = raw_headers {
-            let mut message = Vec::with_capacity(raw_headers.len() + raw_message.len());
-            message.extend_from_slice(raw_headers);
-            message.extend_from_slice(raw_message);
-            Cow::Owned(message)
+            vec![raw_headers, raw_message]
         } else {
-            raw_message.into()
+            vec![raw_message]
         };
-        self.blob_hash = BlobHash::from(message.as_ref());
+        self.blob_hash = BlobHash::from_parts(&message);
+
+        // Assign priority lane
+        if let Some(priority) = core
+            .eval_if::<i64, _>(&core.queue.config.priority, &self)
+            .await
+        {
+            self.priority = priority.clamp(-9, 9) as i16;
+        }
 
         // Generate id
         if self.size == 0 {
-            self.size = message.len();
+            self.size = message.iter().map(|part| part.len()).sum();
         }
 
         // Reserve and write blob
@@ -239,7 +502,7 @@ impl Message {
         if let Err(err) = core
             .shared
             .default_blob_store
-            .put_blob(self.blob_hash.as_slice(), message.as_ref())
+            .put_blob_parts(self.blob_hash.as_slice(), &message)
             .await
         {
             tracing::error!(
@@ -284,14 +547,20 @@ impl Message {
                 }
             }
         }
-        batch
-            .set(
+        let event = if (self.flags & MAIL_QUARANTINED) == 0 {
+            let due = self.next_event().unwrap_or_default();
+            batch.set(
                 ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
-                    due: self.next_event().unwrap_or_default(),
+                    due,
                     queue_id: self.id,
                 })),
                 0u64.serialize(),
-            )
+            );
+            Some((due, self.id))
+        } else {
+            None
+        };
+        batch
             .set(
                 BlobOp::Commit {
                     hash: self.blob_hash.clone(),
@@ -312,7 +581,10 @@ impl Message {
                 err
             );
             return false;
+        } else if let Some((due, queue_id)) = event {
+            core.queue.index.set(due, queue_id, 0);
         }
+        server_metrics().message_queued();
 
         // Queue the message
         if core.queue.tx.send(Event::Reload).await.is_err() {
@@ -354,6 +626,7 @@ impl Message {
                     expires: now() + expires.as_secs(),
                     status: Status::Scheduled,
                     disable_tls: false,
+                    tls_trace: vec![],
                 });
                 idx
             };
@@ -413,6 +686,7 @@ impl Message {
             0u32.serialize(),
         );
 
+        let queue_id = self.id;
         batch.set(
             ValueClass::Queue(QueueClass::Message(self.id)),
             Bincode::new(self).serialize(),
@@ -427,6 +701,10 @@ impl Message {
             );
             false
         } else {
+            if let (Some(prev_event), Some(next_event)) = (prev_event, next_event) {
+                core.queue.index.clear(prev_event, queue_id);
+                core.queue.index.set(next_event, queue_id, 0);
+            }
             true
         }
     }
@@ -477,6 +755,8 @@ impl Message {
             );
             false
         } else {
+            core.queue.index.clear(prev_event, self.id);
+            server_metrics().message_removed();
             true
         }
     }
@@ -30,12 +30,14 @@ use std::{
 };
 
 use ahash::AHashMap;
+use bytes::Bytes;
 use mail_auth::{
     flate2::read::GzDecoder,
     report::{tlsrpt::TlsReport, ActionDisposition, DmarcResult, Feedback, Report},
     zip,
 };
 use mail_parser::{DateTime, MessageParser, MimeHeaders, PartType};
+use utils::metrics::server::server_metrics;
 
 use crate::core::SMTP;
 
@@ -58,12 +60,13 @@ struct ReportData<'x> {
 }
 
 pub trait AnalyzeReport {
-    fn analyze_report(&self, message: Arc<Vec<u8>>);
+    fn analyze_report(&self, message: Bytes);
 }
 
 impl AnalyzeReport for Arc<SMTP> {
-    fn analyze_report(&self, message: Arc<Vec<u8>>) {
+    fn analyze_report(&self, message: Bytes) {
         let core = self.clone();
+        let handle = tokio::runtime::Handle::current();
         self.worker_pool.spawn(move || {
             let message = if let Some(message) = MessageParser::default().parse(message.as_ref()) {
                 message
@@ -217,6 +220,11 @@ impl AnalyzeReport for Arc<SMTP> {
                     Format::Dmarc => match Report::parse_xml(&data) {
                         Ok(report) => {
                             report.log();
+                            server_metrics().report_received("dmarc");
+                            handle.block_on(async {
+                                core.record_dmarc_report_health(&report).await;
+                                core.save_incoming_dmarc_report(from, &report).await;
+                            });
                         }
                         Err(err) => {
                             tracing::debug!(
@@ -231,6 +239,8 @@ impl AnalyzeReport for Arc<SMTP> {
                     Format::Tls => match TlsReport::parse_json(&data) {
                         Ok(report) => {
                             report.log();
+                            server_metrics().report_received("tls");
+                            handle.block_on(core.record_tls_report_health(&report));
                         }
                         Err(err) => {
                             tracing::debug!(
@@ -245,6 +255,8 @@ impl AnalyzeReport for Arc<SMTP> {
                     Format::Arf => match Feedback::parse_arf(&data) {
                         Some(report) => {
                             report.log();
+                            server_metrics().report_received("arf");
+                            handle.block_on(core.record_feedback_health(&report));
                         }
                         None => {
                             tracing::debug!(
@@ -258,7 +270,8 @@ impl AnalyzeReport for Arc<SMTP> {
                 }
 
                 // Save report
-                if let Some(report_path) = &core.report.config.analysis.store {
+                let report_config = core.report.config.load_full();
+                if let Some(report_path) = &report_config.analysis.store {
                     let (report_format, extension) = match report.format {
                         Format::Dmarc => ("dmarc", "xml"),
                         Format::Tls => ("tlsrpt", "json"),
@@ -272,9 +285,7 @@ impl AnalyzeReport for Arc<SMTP> {
                     let now = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .map_or(0, |d| d.as_secs());
-                    let id = core
-                        .report
-                        .config
+                    let id = report_config
                         .analysis
                         .report_id
                         .fetch_add(1, Ordering::Relaxed);
@@ -25,7 +25,7 @@ use mail_auth::{
     common::verify::VerifySignature, AuthenticatedMessage, AuthenticationResults, DkimOutput,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
-use utils::config::Rate;
+use utils::{config::Rate, metrics::server::server_metrics};
 
 use crate::core::Session;
 
@@ -45,6 +45,19 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
             return;
         };
 
+        // Skip reports already generated by another node
+        if !self
+            .core
+            .is_report_unique(
+                "dkim",
+                &[rcpt.as_bytes(), message.raw_headers()],
+                &self.span,
+            )
+            .await
+        {
+            return;
+        }
+
         // Throttle recipient
         if !self.throttle_rcpt(rcpt, rate, "dkim").await {
             tracing::debug!(
@@ -57,7 +70,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
             return;
         }
 
-        let config = &self.core.report.config.dkim;
+        let report_config = self.core.report.config.load_full();
+        let config = &report_config.dkim;
         let from_addr = self
             .core
             .eval_if(&config.address, self)
@@ -103,7 +117,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         );
 
         // Send report
-        self.core
+        if self
+            .core
             .send_report(
                 &from_addr,
                 [rcpt].into_iter(),
@@ -112,6 +127,9 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 &self.span,
                 true,
             )
-            .await;
+            .await
+        {
+            server_metrics().report_sent("arf");
+        }
     }
 }
@@ -21,8 +21,6 @@
  * for more details.
 */
 
-use std::collections::hash_map::Entry;
-
 use ahash::AHashMap;
 use mail_auth::{
     common::verify::VerifySignature,
@@ -32,11 +30,12 @@ use mail_auth::{
     SpfResult,
 };
 use store::{
+    dispatch::store::DELETE_CHUNK_SIZE,
     write::{now, BatchBuilder, Bincode, QueueClass, ReportEvent, ValueClass},
     Deserialize, IterateParams, Serialize, ValueKey,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
-use utils::config::Rate;
+use utils::{config::Rate, metrics::server::server_metrics};
 
 use crate::{
     config::AggregateFrequency,
@@ -44,7 +43,7 @@ use crate::{
     queue::{DomainPart, RecipientDomain},
 };
 
-use super::{scheduler::ToHash, DmarcEvent, ReportLock, SerializedSize};
+use super::{part_report_id, scheduler::ToHash, split_by_size, DmarcEvent, ReportLock};
 
 #[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct DmarcFormat {
@@ -65,7 +64,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         arc_output: &Option<ArcOutput<'_>>,
     ) {
         let dmarc_record = dmarc_output.dmarc_record_cloned().unwrap();
-        let config = &self.core.report.config.dmarc;
+        let report_config = self.core.report.config.load_full();
+        let config = &report_config.dmarc;
 
         // Send failure report
         if let (Some(failure_rate), Some(report_options)) = (
@@ -118,8 +118,17 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 }
             };
 
-            // Throttle recipient
-            if !rcpts.is_empty() {
+            // Throttle recipient and skip reports already generated by another node
+            if !rcpts.is_empty()
+                && self
+                    .core
+                    .is_report_unique(
+                        "dmarc",
+                        &[rcpts.join(",").as_bytes(), message.raw_headers()],
+                        &self.span,
+                    )
+                    .await
+            {
                 let mut report = Vec::with_capacity(128);
                 let from_addr = self
                     .core
@@ -232,7 +241,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 );
 
                 // Send report
-                self.core
+                if self
+                    .core
                     .send_report(
                         &from_addr,
                         rcpts.into_iter(),
@@ -241,7 +251,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                         &self.span,
                         true,
                     )
-                    .await;
+                    .await
+                {
+                    server_metrics().report_sent("arf");
+                }
             } else {
                 tracing::debug!(
                     parent: &self.span,
@@ -256,7 +269,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         // Send agregate reports
         let interval = self
             .core
-            .eval_if(&self.core.report.config.dmarc_aggregate.send, self)
+            .eval_if(
+                &self.core.report.config.load_full().dmarc_aggregate.send,
+                self,
+            )
             .await
             .unwrap_or(AggregateFrequency::Never);
 
@@ -338,6 +354,7 @@ impl SMTP {
         };
 
         // Verify external reporting addresses
+        let mut rua_max_size = usize::MAX;
         let rua = match self
             .resolvers
             .dns
@@ -348,7 +365,12 @@ impl SMTP {
                 if !rcpts.is_empty() {
                     rcpts
                         .into_iter()
-                        .map(|u| u.uri().to_string())
+                        .map(|u| {
+                            if u.max_size > 0 {
+                                rua_max_size = rua_max_size.min(u.max_size);
+                            }
+                            u.uri().to_string()
+                        })
                         .collect::<Vec<_>>()
                 } else {
                     tracing::info!(
@@ -375,16 +397,16 @@ impl SMTP {
             }
         };
 
-        let mut serialized_size = serde_json::Serializer::new(SerializedSize::new(
-            self.eval_if(
-                &self.report.config.dmarc_aggregate.max_size,
+        let report_config = self.report.config.load_full();
+        let config = &report_config.dmarc_aggregate;
+        let max_size = self
+            .eval_if(
+                &config.max_size,
                 &RecipientDomain::new(event.domain.as_str()),
             )
             .await
-            .unwrap_or(25 * 1024 * 1024),
-        ));
-        let _ = serde::Serialize::serialize(&dmarc, &mut serialized_size);
-        let config = &self.report.config.dmarc_aggregate;
+            .unwrap_or(25 * 1024 * 1024)
+            .min(rua_max_size);
 
         // Group duplicates
         let from_key = ValueKey::from(ValueClass::Queue(QueueClass::DmarcReportEvent(
@@ -407,23 +429,12 @@ impl SMTP {
         if let Err(err) = self
             .shared
             .default_data_store
-            .iterate(
-                IterateParams::new(from_key, to_key).ascending(),
-                |_, v| match record_map.entry(Bincode::<Record>::deserialize(v)?.inner) {
-                    Entry::Occupied(mut e) => {
-                        *e.get_mut() += 1;
-                        Ok(true)
-                    }
-                    Entry::Vacant(e) => {
-                        if serde::Serialize::serialize(e.key(), &mut serialized_size).is_ok() {
-                            e.insert(1u32);
-                            Ok(true)
-                        } else {
-                            Ok(false)
-                        }
-                    }
-                },
-            )
+            .iterate(IterateParams::new(from_key, to_key).ascending(), |_, v| {
+                *record_map
+                    .entry(Bincode::<Record>::deserialize(v)?.inner)
+                    .or_insert(0u32) += 1;
+                Ok(true)
+            })
             .await
         {
             tracing::warn!(
@@ -434,12 +445,20 @@ impl SMTP {
             );
         }
 
+        // Split records into as many reports as needed to stay within the size limit
+        let parts = split_by_size(
+            &dmarc,
+            record_map
+                .into_iter()
+                .map(|(record, count)| record.with_count(count)),
+            max_size,
+        );
+
         // Create report
         let mut report = Report::new()
             .with_policy_published(dmarc.policy)
             .with_date_range_begin(event.seq_id)
             .with_date_range_end(event.due)
-            .with_report_id(format!("{}_{}", event.policy_hash, event.seq_id))
             .with_email(
                 self.eval_if(
                     &config.address,
@@ -466,9 +485,6 @@ impl SMTP {
         {
             report = report.with_extra_contact_info(contact_info);
         }
-        for (record, count) in record_map {
-            report.add_record(record.with_count(count));
-        }
         let from_addr = self
             .eval_if(
                 &config.address,
@@ -476,29 +492,45 @@ impl SMTP {
             )
             .await
             .unwrap_or_else(|| "MAILER-DAEMON@localhost".to_string());
-        let mut message = Vec::with_capacity(2048);
-        let _ = report.write_rfc5322(
-            &self
-                .eval_if(
-                    &self.report.config.submitter,
-                    &RecipientDomain::new(event.domain.as_str()),
-                )
-                .await
-                .unwrap_or_else(|| "localhost".to_string()),
-            (
-                self.eval_if(&config.name, &RecipientDomain::new(event.domain.as_str()))
-                    .await
-                    .unwrap_or_else(|| "Mail Delivery Subsystem".to_string())
-                    .as_str(),
-                from_addr.as_str(),
-            ),
-            rua.iter().map(|a| a.as_str()),
-            &mut message,
-        );
+        let submitter = self
+            .eval_if(
+                &report_config.submitter,
+                &RecipientDomain::new(event.domain.as_str()),
+            )
+            .await
+            .unwrap_or_else(|| "localhost".to_string());
+        let from_name = self
+            .eval_if(&config.name, &RecipientDomain::new(event.domain.as_str()))
+            .await
+            .unwrap_or_else(|| "Mail Delivery Subsystem".to_string());
+
+        let num_parts = parts.len();
+        for (part_num, records) in parts.into_iter().enumerate() {
+            let mut report = report.clone().with_report_id(part_report_id(
+                format!("{}_{}", event.policy_hash, event.seq_id),
+                part_num,
+                num_parts,
+            ));
+            for record in records {
+                report.add_record(record);
+            }
 
-        // Send report
-        self.send_report(&from_addr, rua.iter(), message, &config.sign, &span, false)
-            .await;
+            let mut message = Vec::with_capacity(2048);
+            let _ = report.write_rfc5322(
+                &submitter,
+                (from_name.as_str(), from_addr.as_str()),
+                rua.iter().map(|a| a.as_str()),
+                &mut message,
+            );
+
+            // Send report
+            if self
+                .send_report(&from_addr, rua.iter(), message, &config.sign, &span, false)
+                .await
+            {
+                server_metrics().report_sent("dmarc");
+            }
+        }
 
         self.delete_dmarc_report(event).await;
     }
@@ -520,9 +552,19 @@ impl SMTP {
         if let Err(err) = self
             .shared
             .default_data_store
-            .delete_range(
+            .delete_range_chunked(
                 ValueKey::from(ValueClass::Queue(QueueClass::DmarcReportEvent(from_key))),
                 ValueKey::from(ValueClass::Queue(QueueClass::DmarcReportEvent(to_key))),
+                DELETE_CHUNK_SIZE,
+                self.shared.default_data_store.delete_range_concurrency(),
+                |deleted| {
+                    tracing::trace!(
+                        context = "report",
+                        event = "purge",
+                        deleted = deleted,
+                        "Deleted report events."
+                    );
+                },
             )
             .await
         {
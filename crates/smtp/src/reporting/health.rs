@@ -0,0 +1,376 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_auth::{
+    dkim::DomainKey,
+    dmarc::Dmarc,
+    mta_sts::{MtaSts, TlsRpt},
+    report::{tlsrpt::TlsReport, DmarcResult, Feedback, FeedbackType, Report},
+    spf::Spf,
+};
+use serde::Serialize;
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize, IterateParams, ValueKey,
+};
+
+use crate::{
+    core::SMTP,
+    queue::{Message, Status},
+};
+
+pub const HEALTH_DMARC_PASS: u8 = 0;
+pub const HEALTH_DMARC_FAIL: u8 = 1;
+pub const HEALTH_SENT: u8 = 2;
+pub const HEALTH_BOUNCED: u8 = 3;
+pub const HEALTH_COMPLAINTS: u8 = 4;
+pub const HEALTH_TLS_SUCCESS: u8 = 5;
+pub const HEALTH_TLS_FAILURE: u8 = 6;
+
+#[derive(Debug, Serialize)]
+pub struct DomainHealth {
+    pub domain: String,
+    pub days: u64,
+    pub dmarc: DmarcHealth,
+    pub outbound: OutboundHealth,
+    pub tls: TlsHealth,
+    pub queue: QueueBacklog,
+    pub dns: DnsHealth,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DmarcHealth {
+    pub pass: u64,
+    pub fail: u64,
+    pub pass_rate: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct OutboundHealth {
+    pub sent: u64,
+    pub bounced: u64,
+    pub complaints: u64,
+    pub bounce_rate: Option<f64>,
+    pub complaint_rate: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TlsHealth {
+    pub success: u64,
+    pub failure: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct QueueBacklog {
+    pub messages: u64,
+    pub recipients: u64,
+    pub oldest: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DnsHealth {
+    pub mx: DnsCheck,
+    pub spf: DnsCheck,
+    pub dmarc: DnsCheck,
+    pub dkim: Vec<DkimCheck>,
+    pub mta_sts: DnsCheck,
+    pub tls_rpt: DnsCheck,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DkimCheck {
+    pub selector: String,
+    #[serde(flatten)]
+    pub check: DnsCheck,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", content = "details", rename_all = "lowercase")]
+pub enum DnsCheck {
+    Valid,
+    Missing,
+    Invalid(String),
+    Error(String),
+}
+
+impl SMTP {
+    /// Adds the given metrics to today's health counters of a local domain.
+    pub async fn record_domain_health(&self, domain: &str, metrics: &[(u8, u64)]) {
+        if metrics.iter().all(|(_, value)| *value == 0) {
+            return;
+        }
+        let domain = domain.to_lowercase();
+        match self.shared.default_directory.is_local_domain(&domain).await {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(err) => {
+                tracing::debug!(
+                    context = "health",
+                    event = "error",
+                    domain = domain,
+                    "Failed to check whether domain is local: {}",
+                    err
+                );
+                return;
+            }
+        }
+
+        let day = now() / 86400;
+        let mut batch = BatchBuilder::new();
+        for (metric, value) in metrics {
+            if *value > 0 {
+                batch.add(
+                    ValueClass::Queue(QueueClass::DomainHealth(health_key(&domain, day, *metric))),
+                    *value as i64,
+                );
+            }
+        }
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::warn!(
+                context = "health",
+                event = "error",
+                domain = domain,
+                "Failed to update domain health counters: {}",
+                err
+            );
+        }
+    }
+
+    /// Counts final delivery outcomes of the recipients that were pending before this attempt.
+    pub async fn record_delivery_health(&self, message: &Message, was_pending: &[bool]) {
+        let mut sent = 0;
+        let mut bounced = 0;
+        for (rcpt, was_pending) in message.recipients.iter().zip(was_pending) {
+            if *was_pending {
+                match (&rcpt.status, &message.domains[rcpt.domain_idx].status) {
+                    (Status::Completed(_), _) | (_, Status::Completed(_)) => sent += 1,
+                    (Status::PermanentFailure(_), _) | (_, Status::PermanentFailure(_)) => {
+                        bounced += 1
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if !message.return_path_domain.is_empty() {
+            self.record_domain_health(
+                &message.return_path_domain,
+                &[(HEALTH_SENT, sent), (HEALTH_BOUNCED, bounced)],
+            )
+            .await;
+        }
+    }
+
+    pub async fn record_dmarc_report_health(&self, report: &Report) {
+        let mut pass = 0;
+        let mut fail = 0;
+        for record in report.records() {
+            if matches!(record.dmarc_dkim_result(), DmarcResult::Pass)
+                || matches!(record.dmarc_spf_result(), DmarcResult::Pass)
+            {
+                pass += record.count() as u64;
+            } else {
+                fail += record.count() as u64;
+            }
+        }
+
+        self.record_domain_health(
+            report.domain(),
+            &[(HEALTH_DMARC_PASS, pass), (HEALTH_DMARC_FAIL, fail)],
+        )
+        .await;
+    }
+
+    pub async fn record_tls_report_health(&self, report: &TlsReport) {
+        for policy in &report.policies {
+            self.record_domain_health(
+                &policy.policy.policy_domain,
+                &[
+                    (HEALTH_TLS_SUCCESS, policy.summary.total_success as u64),
+                    (HEALTH_TLS_FAILURE, policy.summary.total_failure as u64),
+                ],
+            )
+            .await;
+        }
+    }
+
+    pub async fn record_feedback_health(&self, feedback: &Feedback<'_>) {
+        if matches!(feedback.feedback_type(), FeedbackType::Abuse) {
+            for domain in feedback.reported_domain() {
+                self.record_domain_health(domain, &[(HEALTH_COMPLAINTS, 1)])
+                    .await;
+            }
+        }
+    }
+
+    pub async fn domain_health(
+        &self,
+        domain: &str,
+        days: u64,
+        selectors: &[String],
+    ) -> store::Result<DomainHealth> {
+        // Add up daily counters
+        let today = now() / 86400;
+        let mut totals = [0u64; 7];
+        for day in (today + 1).saturating_sub(days)..=today {
+            for (metric, total) in totals.iter_mut().enumerate() {
+                *total += self
+                    .shared
+                    .default_data_store
+                    .get_counter(ValueKey::from(ValueClass::Queue(QueueClass::DomainHealth(
+                        health_key(domain, day, metric as u8),
+                    ))))
+                    .await?
+                    .max(0) as u64;
+            }
+        }
+        let rate = |value: u64, total: u64| {
+            if total > 0 {
+                Some(value as f64 / total as f64)
+            } else {
+                None
+            }
+        };
+        let sent = totals[HEALTH_SENT as usize];
+        let bounced = totals[HEALTH_BOUNCED as usize];
+        let complaints = totals[HEALTH_COMPLAINTS as usize];
+        let dmarc_pass = totals[HEALTH_DMARC_PASS as usize];
+        let dmarc_fail = totals[HEALTH_DMARC_FAIL as usize];
+
+        Ok(DomainHealth {
+            domain: domain.to_string(),
+            days,
+            dmarc: DmarcHealth {
+                pass: dmarc_pass,
+                fail: dmarc_fail,
+                pass_rate: rate(dmarc_pass, dmarc_pass + dmarc_fail),
+            },
+            outbound: OutboundHealth {
+                sent,
+                bounced,
+                complaints,
+                bounce_rate: rate(bounced, sent + bounced),
+                complaint_rate: rate(complaints, sent),
+            },
+            tls: TlsHealth {
+                success: totals[HEALTH_TLS_SUCCESS as usize],
+                failure: totals[HEALTH_TLS_FAILURE as usize],
+            },
+            queue: self.queue_backlog(domain).await?,
+            dns: self.check_domain_dns(domain, selectors).await,
+        })
+    }
+
+    async fn queue_backlog(&self, domain: &str) -> store::Result<QueueBacklog> {
+        let mut backlog = QueueBacklog::default();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::Message(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let message = Bincode::<Message>::deserialize(value)?.inner;
+                    if message.return_path_domain == domain {
+                        backlog.messages += 1;
+                        backlog.recipients += message
+                            .recipients
+                            .iter()
+                            .filter(|rcpt| {
+                                matches!(
+                                    rcpt.status,
+                                    Status::Scheduled | Status::TemporaryFailure(_)
+                                )
+                            })
+                            .count() as u64;
+                        backlog.oldest = Some(
+                            backlog
+                                .oldest
+                                .map_or(message.created, |oldest| oldest.min(message.created)),
+                        );
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| backlog)
+    }
+
+    async fn check_domain_dns(&self, domain: &str, selectors: &[String]) -> DnsHealth {
+        let dns = &self.resolvers;
+        let mut dkim = Vec::with_capacity(selectors.len());
+        for selector in selectors {
+            dkim.push(DkimCheck {
+                selector: selector.to_string(),
+                check: dns
+                    .txt_lookup::<DomainKey>(format!("{selector}._domainkey.{domain}."))
+                    .await
+                    .into(),
+            });
+        }
+
+        DnsHealth {
+            mx: match dns.mx_lookup(domain).await {
+                Ok(mx) if mx.iter().any(|mx| !mx.exchanges.is_empty()) => DnsCheck::Valid,
+                Ok(_) | Err(mail_auth::Error::DnsRecordNotFound(_)) => DnsCheck::Missing,
+                Err(err) => DnsCheck::Error(err.to_string()),
+            },
+            spf: dns.txt_lookup::<Spf>(format!("{domain}.")).await.into(),
+            dmarc: dns
+                .txt_lookup::<Dmarc>(format!("_dmarc.{domain}."))
+                .await
+                .into(),
+            dkim,
+            mta_sts: dns
+                .txt_lookup::<MtaSts>(format!("_mta-sts.{domain}."))
+                .await
+                .into(),
+            tls_rpt: dns
+                .txt_lookup::<TlsRpt>(format!("_smtp._tls.{domain}."))
+                .await
+                .into(),
+        }
+    }
+}
+
+impl<T> From<mail_auth::Result<T>> for DnsCheck {
+    fn from(result: mail_auth::Result<T>) -> Self {
+        match result {
+            Ok(_) => DnsCheck::Valid,
+            Err(mail_auth::Error::DnsRecordNotFound(_)) => DnsCheck::Missing,
+            Err(err @ (mail_auth::Error::InvalidRecordType | mail_auth::Error::ParseError)) => {
+                DnsCheck::Invalid(err.to_string())
+            }
+            Err(err) => DnsCheck::Error(err.to_string()),
+        }
+    }
+}
+
+fn health_key(domain: &str, day: u64, metric: u8) -> Vec<u8> {
+    let mut key = Vec::with_capacity(domain.len() + 6);
+    key.extend_from_slice(domain.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&(day as u32).to_be_bytes());
+    key.push(metric);
+    key
+}
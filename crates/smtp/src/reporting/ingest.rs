@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::IpAddr;
+
+use mail_auth::report::{ActionDisposition, DmarcResult, Report};
+use store::{
+    write::{now, BatchBuilder, Bincode, QueueClass, ValueClass},
+    Deserialize, IterateParams, Serialize, ValueKey,
+};
+
+use crate::core::SMTP;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IncomingDmarcReport {
+    pub id: u64,
+    pub received: u64,
+    pub from: String,
+    pub org_name: String,
+    pub report_id: String,
+    pub email: String,
+    pub domain: String,
+    pub date_range_begin: u64,
+    pub date_range_end: u64,
+    pub total: u64,
+    pub dmarc_pass: u64,
+    pub dkim_pass: u64,
+    pub spf_pass: u64,
+    pub quarantined: u64,
+    pub rejected: u64,
+    pub sources: Vec<DmarcSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DmarcSource {
+    pub source_ip: Option<IpAddr>,
+    pub header_from: String,
+    pub count: u64,
+    pub dkim_pass: u64,
+    pub spf_pass: u64,
+}
+
+impl IncomingDmarcReport {
+    pub fn new(id: u64, from: &str, report: &Report) -> Self {
+        let mut summary = IncomingDmarcReport {
+            id,
+            received: now(),
+            from: from.to_string(),
+            org_name: report.org_name().to_string(),
+            report_id: report.report_id().to_string(),
+            email: report.email().to_string(),
+            domain: report.domain().to_lowercase(),
+            date_range_begin: report.date_range_begin(),
+            date_range_end: report.date_range_end(),
+            total: 0,
+            dmarc_pass: 0,
+            dkim_pass: 0,
+            spf_pass: 0,
+            quarantined: 0,
+            rejected: 0,
+            sources: Vec::new(),
+        };
+
+        for record in report.records() {
+            let count = record.count() as u64;
+            let dkim_pass = matches!(record.dmarc_dkim_result(), DmarcResult::Pass);
+            let spf_pass = matches!(record.dmarc_spf_result(), DmarcResult::Pass);
+
+            summary.total += count;
+            if dkim_pass || spf_pass {
+                summary.dmarc_pass += count;
+            }
+            if dkim_pass {
+                summary.dkim_pass += count;
+            }
+            if spf_pass {
+                summary.spf_pass += count;
+            }
+            match record.action_disposition() {
+                ActionDisposition::Quarantine => summary.quarantined += count,
+                ActionDisposition::Reject => summary.rejected += count,
+                _ => (),
+            }
+
+            // Aggregate by source address and header from
+            let source_ip = record.source_ip();
+            let header_from = record.header_from().to_lowercase();
+            let source = if let Some(source) = summary
+                .sources
+                .iter_mut()
+                .find(|s| s.source_ip == source_ip && s.header_from == header_from)
+            {
+                source
+            } else {
+                summary.sources.push(DmarcSource {
+                    source_ip,
+                    header_from,
+                    count: 0,
+                    dkim_pass: 0,
+                    spf_pass: 0,
+                });
+                summary.sources.last_mut().unwrap()
+            };
+            source.count += count;
+            if dkim_pass {
+                source.dkim_pass += count;
+            }
+            if spf_pass {
+                source.spf_pass += count;
+            }
+        }
+
+        summary
+    }
+}
+
+impl SMTP {
+    /// Stores a summary of an incoming DMARC aggregate report. Reports already
+    /// received from the same organization with the same ID are skipped.
+    pub async fn save_incoming_dmarc_report(&self, from: &str, report: &Report) -> bool {
+        let retention = self.report.config.load().analysis.retention;
+        let mut hasher = blake3::Hasher::new();
+        for part in [report.org_name(), report.report_id()] {
+            hasher.update(&(part.len() as u64).to_be_bytes()[..]);
+            hasher.update(part.as_bytes());
+        }
+        let mut key = b"dri:".to_vec();
+        key.extend_from_slice(hasher.finalize().as_bytes());
+        match self
+            .shared
+            .default_lookup_store
+            .key_claim(key, retention.as_secs().max(1))
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => {
+                tracing::debug!(
+                    context = "report",
+                    event = "duplicate",
+                    org_name = report.org_name(),
+                    report_id = report.report_id(),
+                    "Duplicate DMARC report not stored."
+                );
+                return false;
+            }
+            Err(err) => {
+                tracing::warn!(
+                    context = "report",
+                    event = "error",
+                    "Failed to claim DMARC report ID: {}",
+                    err
+                );
+            }
+        }
+
+        let id = self.queue.snowflake_id.generate().unwrap_or_else(now);
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            ValueClass::Queue(QueueClass::IncomingDmarcReport(id)),
+            Bincode::new(IncomingDmarcReport::new(id, from, report)).serialize(),
+        );
+
+        if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+            tracing::error!(
+                context = "report",
+                event = "error",
+                "Failed to write incoming DMARC report: {}",
+                err
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    pub async fn read_incoming_dmarc_report(&self, id: u64) -> Option<IncomingDmarcReport> {
+        match self
+            .shared
+            .default_data_store
+            .get_value::<Bincode<IncomingDmarcReport>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::IncomingDmarcReport(id),
+            )))
+            .await
+        {
+            Ok(Some(report)) => Some(report.inner),
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(
+                    context = "report",
+                    event = "error",
+                    "Failed to read incoming DMARC report from store: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn list_incoming_dmarc_reports(
+        &self,
+        filter: impl Fn(&IncomingDmarcReport) -> bool + Sync + Send,
+    ) -> store::Result<Vec<IncomingDmarcReport>> {
+        let mut reports = Vec::new();
+        let from_key = ValueKey::from(ValueClass::Queue(QueueClass::IncomingDmarcReport(0)));
+        let to_key = ValueKey::from(ValueClass::Queue(QueueClass::IncomingDmarcReport(u64::MAX)));
+        self.shared
+            .default_data_store
+            .iterate(
+                IterateParams::new(from_key, to_key).ascending(),
+                |_, value| {
+                    let report = Bincode::<IncomingDmarcReport>::deserialize(value)?.inner;
+                    if filter(&report) {
+                        reports.push(report);
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .map(|_| reports)
+    }
+
+    /// Deletes the stored DMARC report summaries older than the retention period.
+    pub async fn purge_incoming_dmarc_reports(&self) {
+        let expires = now().saturating_sub(self.report.config.load().analysis.retention.as_secs());
+        match self
+            .list_incoming_dmarc_reports(|report| report.received < expires)
+            .await
+        {
+            Ok(reports) if !reports.is_empty() => {
+                let mut batch = BatchBuilder::new();
+                for report in &reports {
+                    batch.clear(ValueClass::Queue(QueueClass::IncomingDmarcReport(
+                        report.id,
+                    )));
+                }
+                if let Err(err) = self.shared.default_data_store.write(batch.build()).await {
+                    tracing::error!(
+                        context = "report",
+                        event = "error",
+                        "Failed to purge incoming DMARC reports: {}",
+                        err
+                    );
+                } else {
+                    tracing::debug!(
+                        context = "report",
+                        event = "purge",
+                        count = reports.len(),
+                        "Purged expired incoming DMARC reports."
+                    );
+                }
+            }
+            Ok(_) => (),
+            Err(err) => {
+                tracing::error!(
+                    context = "report",
+                    event = "error",
+                    "Failed to list incoming DMARC reports: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    pub async fn delete_incoming_dmarc_report(&self, id: u64) -> bool {
+        if self.read_incoming_dmarc_report(id).await.is_none() {
+            return false;
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch.clear(ValueClass::Queue(QueueClass::IncomingDmarcReport(id)));
+        self.shared
+            .default_data_store
+            .write(batch.build())
+            .await
+            .is_ok()
+    }
+}
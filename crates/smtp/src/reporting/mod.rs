@@ -48,6 +48,8 @@ use crate::{
 pub mod analysis;
 pub mod dkim;
 pub mod dmarc;
+pub mod health;
+pub mod ingest;
 pub mod scheduler;
 pub mod spf;
 pub mod tls;
@@ -103,7 +105,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
     }
 
     pub fn is_report(&self) -> bool {
-        for addr_match in &self.core.report.config.analysis.addresses {
+        for addr_match in &self.core.report.config.load().analysis.addresses {
             for addr in &self.data.rcpt_to {
                 match addr_match {
                     AddressMatch::StartsWith(prefix) if addr.address_lcase.starts_with(prefix) => {
@@ -131,7 +133,7 @@ impl SMTP {
         sign_config: &IfBlock,
         span: &tracing::Span,
         deliver_now: bool,
-    ) {
+    ) -> bool {
         // Build message
         let from_addr_lcase = from_addr.to_lowercase();
         let from_addr_domain = from_addr_lcase.domain_part().to_string();
@@ -165,7 +167,63 @@ impl SMTP {
         // Queue message
         message
             .queue(signature.as_deref(), &report, self, span)
-            .await;
+            .await
+    }
+
+    pub async fn is_report_unique(
+        &self,
+        report: &str,
+        parts: &[&[u8]],
+        span: &tracing::Span,
+    ) -> bool {
+        let window = if let Some(window) = self.report.config.load().dedup_window {
+            window
+        } else {
+            return true;
+        };
+
+        // Claim the report key in the shared store so that other nodes
+        // processing the same event within the window do not send a copy
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"dedup");
+        hasher.update(report.as_bytes());
+        for part in parts {
+            hasher.update(&(part.len() as u64).to_be_bytes()[..]);
+            hasher.update(part);
+        }
+
+        match self
+            .shared
+            .default_lookup_store
+            .key_claim(
+                hasher.finalize().as_bytes().to_vec(),
+                window.as_secs().max(1),
+            )
+            .await
+        {
+            Ok(true) => true,
+            Ok(false) => {
+                tracing::debug!(
+                    parent: span,
+                    context = "report",
+                    report = report,
+                    event = "duplicate",
+                    "Duplicate report not sent."
+                );
+                false
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: span,
+                    context = "report",
+                    report = report,
+                    event = "error",
+                    reason = %err,
+                    "Failed to check for duplicate report."
+                );
+                true
+            }
+        }
     }
 
     pub async fn schedule_report(&self, report: impl Into<Event>) {
@@ -325,6 +383,43 @@ impl io::Write for SerializedSize {
     }
 }
 
+// Splits records into consecutive parts so that each report, including its
+// header, stays within the maximum size. A record that does not fit on its
+// own is sent in a part of its own.
+pub fn split_by_size<T: serde::Serialize>(
+    header: &impl serde::Serialize,
+    records: impl IntoIterator<Item = T>,
+    max_size: usize,
+) -> Vec<Vec<T>> {
+    let mut parts = Vec::new();
+    let mut part = Vec::new();
+    let mut serialized_size = serde_json::Serializer::new(SerializedSize::new(max_size));
+    let _ = serde::Serialize::serialize(header, &mut serialized_size);
+
+    for record in records {
+        if serde::Serialize::serialize(&record, &mut serialized_size).is_err() && !part.is_empty() {
+            parts.push(std::mem::take(&mut part));
+            serialized_size = serde_json::Serializer::new(SerializedSize::new(max_size));
+            let _ = serde::Serialize::serialize(header, &mut serialized_size);
+            let _ = serde::Serialize::serialize(&record, &mut serialized_size);
+        }
+        part.push(record);
+    }
+    if !part.is_empty() || parts.is_empty() {
+        parts.push(part);
+    }
+
+    parts
+}
+
+pub fn part_report_id(report_id: String, part_num: usize, num_parts: usize) -> String {
+    if num_parts > 1 {
+        format!("{report_id}_{}", part_num + 1)
+    } else {
+        report_id
+    }
+}
+
 pub trait ReportLock {
     fn tls_lock(event: &ReportEvent) -> Self;
     fn dmarc_lock(event: &ReportEvent) -> Self;
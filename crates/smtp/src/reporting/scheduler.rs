@@ -21,9 +21,10 @@
  * for more details.
 */
 
-use ahash::{AHashMap, RandomState};
+use ahash::{AHashMap, AHashSet, RandomState};
 use mail_auth::dmarc::Dmarc;
 
+use parking_lot::Mutex;
 use std::{
     sync::Arc,
     time::{Duration, Instant, SystemTime},
@@ -32,7 +33,7 @@ use store::{
     write::{now, BatchBuilder, QueueClass, ReportEvent, ValueClass},
     Deserialize, IterateParams, Serialize, ValueKey,
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Semaphore};
 
 use crate::{
     core::{worker::SpawnCleanup, SMTP},
@@ -45,53 +46,94 @@ impl SpawnReport for mpsc::Receiver<Event> {
     fn spawn(mut self, core: Arc<SMTP>) {
         tokio::spawn(async move {
             let mut last_cleanup = Instant::now();
+            let mut last_digest = Instant::now();
             let mut next_wake_up;
+            let in_flight = Arc::new(Mutex::new(AHashSet::new()));
+            let build_limit = Arc::new(Semaphore::new(
+                core.report.config.load().max_concurrent.max(1),
+            ));
 
             loop {
                 // Read events
                 let now = now();
                 let events = core.next_report_event().await;
                 next_wake_up = events
-                    .last()
-                    .and_then(|e| match e {
+                    .iter()
+                    .filter_map(|e| match e {
                         QueueClass::DmarcReportHeader(e) | QueueClass::TlsReportHeader(e)
                             if e.due > now =>
                         {
-                            Duration::from_secs(e.due - now).into()
+                            Some(e.due)
                         }
-                        _ => None,
+                        e => core.report_ready_at(e),
                     })
+                    .filter(|due| *due > now)
+                    .min()
+                    .map(|due| Duration::from_secs(due - now))
                     .unwrap_or(LONG_WAIT);
 
-                let core_ = core.clone();
-                tokio::spawn(async move {
-                    let mut tls_reports = AHashMap::new();
-                    for report_event in events {
-                        match report_event {
-                            QueueClass::DmarcReportHeader(event) if event.due <= now => {
-                                if core_.try_lock_report(QueueClass::dmarc_lock(&event)).await {
-                                    core_.generate_dmarc_report(event).await;
-                                }
-                            }
-                            QueueClass::TlsReportHeader(event) if event.due <= now => {
-                                tls_reports
-                                    .entry(event.domain.clone())
-                                    .or_insert_with(Vec::new)
-                                    .push(event);
+                // Send quarantine digests
+                let quarantine = &core.queue.config.quarantine;
+                if !quarantine.reviewers.is_empty() || !quarantine.approvers.is_empty() {
+                    let elapsed = last_digest.elapsed();
+                    if elapsed >= quarantine.digest_frequency {
+                        last_digest = Instant::now();
+                        let core_ = core.clone();
+                        tokio::spawn(async move {
+                            core_.send_quarantine_digest().await;
+                        });
+                    } else {
+                        next_wake_up = next_wake_up.min(quarantine.digest_frequency - elapsed);
+                    }
+                }
+
+                // Build reports whose delivery window has been reached
+                let mut tls_reports = AHashMap::new();
+                for report_event in events {
+                    let is_ready = core
+                        .report_ready_at(&report_event)
+                        .map_or(false, |ready_at| ready_at <= now);
+                    match report_event {
+                        QueueClass::DmarcReportHeader(event) if is_ready => {
+                            let lock = QueueClass::dmarc_lock(&event);
+                            if in_flight.lock().insert(lock.clone()) {
+                                let core_ = core.clone();
+                                let in_flight = in_flight.clone();
+                                let build_limit = build_limit.clone();
+                                tokio::spawn(async move {
+                                    let _permit = build_limit.acquire_owned().await;
+                                    if core_.try_lock_report(lock.clone()).await {
+                                        core_.generate_dmarc_report(event).await;
+                                    }
+                                    in_flight.lock().remove(&lock);
+                                });
                             }
-                            _ => (),
                         }
+                        QueueClass::TlsReportHeader(event) if is_ready => {
+                            tls_reports
+                                .entry(event.domain.clone())
+                                .or_insert_with(Vec::new)
+                                .push(event);
+                        }
+                        _ => (),
                     }
+                }
 
-                    for (domain_name, tls_report) in tls_reports {
-                        if core_
-                            .try_lock_report(QueueClass::tls_lock(tls_report.first().unwrap()))
-                            .await
-                        {
-                            core_.generate_tls_report(domain_name, tls_report).await;
-                        }
+                for (domain_name, tls_report) in tls_reports {
+                    let lock = QueueClass::tls_lock(tls_report.first().unwrap());
+                    if in_flight.lock().insert(lock.clone()) {
+                        let core_ = core.clone();
+                        let in_flight = in_flight.clone();
+                        let build_limit = build_limit.clone();
+                        tokio::spawn(async move {
+                            let _permit = build_limit.acquire_owned().await;
+                            if core_.try_lock_report(lock.clone()).await {
+                                core_.generate_tls_report(domain_name, tls_report).await;
+                            }
+                            in_flight.lock().remove(&lock);
+                        });
                     }
-                });
+                }
 
                 match tokio::time::timeout(next_wake_up, self.recv()).await {
                     Ok(Some(event)) => match event {
@@ -109,6 +151,11 @@ impl SpawnReport for mpsc::Receiver<Event> {
                         if last_cleanup.elapsed().as_secs() >= 86400 {
                             last_cleanup = Instant::now();
                             core.spawn_cleanup();
+
+                            let core_ = core.clone();
+                            tokio::spawn(async move {
+                                core_.purge_incoming_dmarc_reports().await;
+                            });
                         }
                     }
                 }
@@ -172,6 +219,23 @@ impl SMTP {
         events
     }
 
+    pub fn report_ready_at(&self, event: &QueueClass) -> Option<u64> {
+        match event {
+            QueueClass::DmarcReportHeader(event) => Some(
+                event.due
+                    + delivery_jitter(
+                        &event.domain,
+                        self.report.config.load().dmarc_aggregate.delivery_window,
+                    ),
+            ),
+            QueueClass::TlsReportHeader(event) => Some(
+                event.due
+                    + delivery_jitter(&event.domain, self.report.config.load().tls.delivery_window),
+            ),
+            _ => None,
+        }
+    }
+
     pub async fn try_lock_report(&self, lock: QueueClass) -> bool {
         let now = now();
         match self
@@ -259,6 +323,19 @@ impl ToHash for super::PolicyType {
     }
 }
 
+// Spreads the generation of each domain's reports over the delivery window
+// following the end of the reporting period. The offset is derived from the
+// domain name so that every node and restart agrees on it.
+pub fn delivery_jitter(domain: &str, window: Duration) -> u64 {
+    let window = window.as_secs();
+    if window > 0 {
+        let hash = blake3::hash(domain.as_bytes());
+        u64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap()) % window
+    } else {
+        0
+    }
+}
+
 pub trait ToTimestamp {
     fn to_timestamp(&self) -> u64;
 }
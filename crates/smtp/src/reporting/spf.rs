@@ -23,7 +23,7 @@
 
 use mail_auth::{report::AuthFailureType, AuthenticationResults, SpfOutput};
 use tokio::io::{AsyncRead, AsyncWrite};
-use utils::config::Rate;
+use utils::{config::Rate, metrics::server::server_metrics};
 
 use crate::core::Session;
 
@@ -35,6 +35,28 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         rejected: bool,
         output: &SpfOutput,
     ) {
+        // Skip reports already generated by another node
+        if !self
+            .core
+            .is_report_unique(
+                "spf",
+                &[
+                    rcpt.as_bytes(),
+                    self.data.remote_ip.to_string().as_bytes(),
+                    self.data
+                        .mail_from
+                        .as_ref()
+                        .map_or(b"".as_slice(), |mail_from| mail_from.address.as_bytes()),
+                    self.data.helo_domain.as_bytes(),
+                    output.domain().as_bytes(),
+                ],
+                &self.span,
+            )
+            .await
+        {
+            return;
+        }
+
         // Throttle recipient
         if !self.throttle_rcpt(rcpt, rate, "spf").await {
             tracing::debug!(
@@ -48,7 +70,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         }
 
         // Generate report
-        let config = &self.core.report.config.spf;
+        let report_config = self.core.report.config.load_full();
+        let config = &report_config.spf;
         let from_addr = self
             .core
             .eval_if(&config.address, self)
@@ -103,7 +126,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         );
 
         // Send report
-        self.core
+        if self
+            .core
             .send_report(
                 &from_addr,
                 [rcpt].into_iter(),
@@ -112,6 +136,9 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 &self.span,
                 true,
             )
-            .await;
+            .await
+        {
+            server_metrics().report_sent("arf");
+        }
     }
 }
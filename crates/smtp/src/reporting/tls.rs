@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{collections::hash_map::Entry, sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use ahash::AHashMap;
 use mail_auth::{
@@ -36,19 +36,20 @@ use mail_parser::DateTime;
 use reqwest::header::CONTENT_TYPE;
 use std::fmt::Write;
 use store::{
+    dispatch::store::DELETE_CHUNK_SIZE,
     write::{now, BatchBuilder, Bincode, QueueClass, ReportEvent, ValueClass},
     Deserialize, IterateParams, Serialize, ValueKey,
 };
+use utils::metrics::server::server_metrics;
 
 use crate::{
     config::AggregateFrequency,
     core::SMTP,
     outbound::mta_sts::{Mode, MxPattern},
     queue::RecipientDomain,
-    USER_AGENT,
 };
 
-use super::{scheduler::ToHash, ReportLock, SerializedSize, TlsEvent};
+use super::{part_report_id, scheduler::ToHash, ReportLock, SerializedSize, TlsEvent};
 
 #[derive(Debug, Clone)]
 pub struct TlsRptOptions {
@@ -66,6 +67,67 @@ pub struct TlsFormat {
 #[cfg(feature = "test_mode")]
 pub static TLS_HTTP_REPORT: parking_lot::Mutex<Vec<u8>> = parking_lot::Mutex::new(Vec::new());
 
+// Splits the policies of a report so that each part stays within the maximum
+// size. Failure details of a policy may be spread over several parts, in which
+// case each part carries the policy along with the failures it reports.
+fn split_policies(header: &TlsReport, policies: Vec<Policy>, max_size: usize) -> Vec<Vec<Policy>> {
+    let mut parts = Vec::new();
+    let mut part = Vec::new();
+    let mut serialized_size = serde_json::Serializer::new(SerializedSize::new(max_size));
+    let _ = serde::Serialize::serialize(header, &mut serialized_size);
+
+    for policy in policies {
+        let mut current = Policy {
+            policy: policy.policy,
+            summary: Summary {
+                total_success: policy.summary.total_success,
+                total_failure: 0,
+            },
+            failure_details: Vec::new(),
+        };
+        if serde::Serialize::serialize(&current.policy, &mut serialized_size).is_err()
+            && !part.is_empty()
+        {
+            parts.push(std::mem::take(&mut part));
+            serialized_size = serde_json::Serializer::new(SerializedSize::new(max_size));
+            let _ = serde::Serialize::serialize(header, &mut serialized_size);
+            let _ = serde::Serialize::serialize(&current.policy, &mut serialized_size);
+        }
+
+        for failure in policy.failure_details {
+            if serde::Serialize::serialize(&failure, &mut serialized_size).is_err()
+                && (!part.is_empty() || !current.failure_details.is_empty())
+            {
+                let policy = current.policy.clone();
+                part.push(std::mem::replace(
+                    &mut current,
+                    Policy {
+                        policy,
+                        summary: Summary {
+                            total_success: 0,
+                            total_failure: 0,
+                        },
+                        failure_details: Vec::new(),
+                    },
+                ));
+                parts.push(std::mem::take(&mut part));
+                serialized_size = serde_json::Serializer::new(SerializedSize::new(max_size));
+                let _ = serde::Serialize::serialize(header, &mut serialized_size);
+                let _ = serde::Serialize::serialize(&current.policy, &mut serialized_size);
+                let _ = serde::Serialize::serialize(&failure, &mut serialized_size);
+            }
+            current.summary.total_failure += failure.failed_session_count;
+            current.failure_details.push(failure);
+        }
+        part.push(current);
+    }
+    if !part.is_empty() || parts.is_empty() {
+        parts.push(part);
+    }
+
+    parts
+}
+
 impl SMTP {
     pub async fn generate_tls_report(&self, domain_name: String, events: Vec<ReportEvent>) {
         let (event_from, event_to, policy) = events
@@ -81,7 +143,8 @@ impl SMTP {
         );
 
         // Deserialize report
-        let config = &self.report.config.tls;
+        let report_config = self.report.config.load_full();
+        let config = &report_config.tls;
         let mut report = TlsReport {
             organization_name: self
                 .eval_if(
@@ -105,15 +168,14 @@ impl SMTP {
             policies: Vec::with_capacity(events.len()),
         };
         let mut rua = Vec::new();
-        let mut serialized_size = serde_json::Serializer::new(SerializedSize::new(
-            self.eval_if(
-                &self.report.config.tls.max_size,
+        let mut policies = Vec::with_capacity(events.len());
+        let max_size = self
+            .eval_if(
+                &config.max_size,
                 &RecipientDomain::new(domain_name.as_str()),
             )
             .await
-            .unwrap_or(25 * 1024 * 1024),
-        ));
-        let _ = serde::Serialize::serialize(&report, &mut serialized_size);
+            .unwrap_or(25 * 1024 * 1024);
 
         for event in &events {
             // Deserialize report
@@ -144,7 +206,6 @@ impl SMTP {
                     continue;
                 }
             };
-            let _ = serde::Serialize::serialize(&tls, &mut serialized_size);
 
             // Group duplicates
             let mut total_success = 0;
@@ -172,24 +233,9 @@ impl SMTP {
                     if let Some(failure_details) =
                         Bincode::<Option<FailureDetails>>::deserialize(v)?.inner
                     {
-                        match record_map.entry(failure_details) {
-                            Entry::Occupied(mut e) => {
-                                total_failure += 1;
-                                *e.get_mut() += 1;
-                                Ok(true)
-                            }
-                            Entry::Vacant(e) => {
-                                if serde::Serialize::serialize(e.key(), &mut serialized_size)
-                                    .is_ok()
-                                {
-                                    total_failure += 1;
-                                    e.insert(1u32);
-                                    Ok(true)
-                                } else {
-                                    Ok(false)
-                                }
-                            }
-                        }
+                        total_failure += 1;
+                        *record_map.entry(failure_details).or_insert(0u32) += 1;
+                        Ok(true)
                     } else {
                         total_success += 1;
                         Ok(true)
@@ -205,7 +251,7 @@ impl SMTP {
                 );
             }
 
-            report.policies.push(Policy {
+            policies.push(Policy {
                 policy: tls.policy,
                 summary: Summary {
                     total_success,
@@ -223,7 +269,7 @@ impl SMTP {
             rua = tls.rua;
         }
 
-        if report.policies.is_empty() {
+        if policies.is_empty() {
             // This should not happen
             tracing::warn!(
                 parent: &span,
@@ -234,46 +280,52 @@ impl SMTP {
             return;
         }
 
-        // Compress and serialize report
-        let json = report.to_json();
-        let mut e = GzEncoder::new(Vec::with_capacity(json.len()), Compression::default());
-        let json = match std::io::Write::write_all(&mut e, json.as_bytes()).and_then(|_| e.finish())
-        {
-            Ok(report) => report,
-            Err(err) => {
-                tracing::error!(
-                    parent: &span,
-                    event = "error",
-                    "Failed to compress report: {}",
-                    err
-                );
-                self.delete_tls_report(events).await;
-                return;
-            }
-        };
+        // Split policies into as many reports as needed to stay within the size limit
+        let parts = split_policies(&report, policies, max_size);
+        let num_parts = parts.len();
+        let report_id = std::mem::take(&mut report.report_id);
+
+        'parts: for (part_num, policies) in parts.into_iter().enumerate() {
+            report.report_id = part_report_id(report_id.clone(), part_num, num_parts);
+            report.policies = policies;
+
+            // Compress and serialize report
+            let json = report.to_json();
+            let mut e = GzEncoder::new(Vec::with_capacity(json.len()), Compression::default());
+            let json =
+                match std::io::Write::write_all(&mut e, json.as_bytes()).and_then(|_| e.finish()) {
+                    Ok(report) => report,
+                    Err(err) => {
+                        tracing::error!(
+                            parent: &span,
+                            event = "error",
+                            "Failed to compress report: {}",
+                            err
+                        );
+                        continue;
+                    }
+                };
 
-        // Try delivering report over HTTP
-        let mut rcpts = Vec::with_capacity(rua.len());
-        for uri in &rua {
-            match uri {
-                ReportUri::Http(uri) => {
-                    if let Ok(client) = reqwest::Client::builder()
-                        .user_agent(USER_AGENT)
-                        .timeout(Duration::from_secs(2 * 60))
-                        .build()
-                    {
+            // Try delivering report over HTTP
+            let mut rcpts = Vec::with_capacity(rua.len());
+            for uri in &rua {
+                match uri {
+                    ReportUri::Http(uri) => {
                         #[cfg(feature = "test_mode")]
                         if uri == "https://127.0.0.1/tls" {
                             TLS_HTTP_REPORT.lock().extend_from_slice(&json);
-                            self.delete_tls_report(events).await;
-                            return;
+                            continue 'parts;
                         }
 
-                        match client
-                            .post(uri)
-                            .header(CONTENT_TYPE, "application/tlsrpt+gzip")
-                            .body(json.to_vec())
-                            .send()
+                        match self
+                            .shared
+                            .http
+                            .send(|client| {
+                                client
+                                    .post(uri)
+                                    .header(CONTENT_TYPE, "application/tlsrpt+gzip")
+                                    .body(json.to_vec())
+                            })
                             .await
                         {
                             Ok(response) => {
@@ -284,8 +336,7 @@ impl SMTP {
                                         event = "success",
                                         url = uri,
                                     );
-                                    self.delete_tls_report(events).await;
-                                    return;
+                                    continue 'parts;
                                 } else {
                                     tracing::debug!(
                                         parent: &span,
@@ -307,58 +358,63 @@ impl SMTP {
                             }
                         }
                     }
-                }
-                ReportUri::Mail(mailto) => {
-                    rcpts.push(mailto.as_str());
+                    ReportUri::Mail(mailto) => {
+                        rcpts.push(mailto.as_str());
+                    }
                 }
             }
-        }
 
-        // Deliver report over SMTP
-        if !rcpts.is_empty() {
-            let from_addr = self
-                .eval_if(&config.address, &RecipientDomain::new(domain_name.as_str()))
-                .await
-                .unwrap_or_else(|| "MAILER-DAEMON@localhost".to_string());
-            let mut message = Vec::with_capacity(2048);
-            let _ = report.write_rfc5322_from_bytes(
-                &domain_name,
-                &self
-                    .eval_if(
-                        &self.report.config.submitter,
-                        &RecipientDomain::new(domain_name.as_str()),
-                    )
+            // Deliver report over SMTP
+            if !rcpts.is_empty() {
+                let from_addr = self
+                    .eval_if(&config.address, &RecipientDomain::new(domain_name.as_str()))
                     .await
-                    .unwrap_or_else(|| "localhost".to_string()),
-                (
-                    self.eval_if(&config.name, &RecipientDomain::new(domain_name.as_str()))
+                    .unwrap_or_else(|| "MAILER-DAEMON@localhost".to_string());
+                let mut message = Vec::with_capacity(2048);
+                let _ = report.write_rfc5322_from_bytes(
+                    &domain_name,
+                    &self
+                        .eval_if(
+                            &report_config.submitter,
+                            &RecipientDomain::new(domain_name.as_str()),
+                        )
                         .await
-                        .unwrap_or_else(|| "Mail Delivery Subsystem".to_string())
-                        .as_str(),
-                    from_addr.as_str(),
-                ),
-                rcpts.iter().copied(),
-                &json,
-                &mut message,
-            );
+                        .unwrap_or_else(|| "localhost".to_string()),
+                    (
+                        self.eval_if(&config.name, &RecipientDomain::new(domain_name.as_str()))
+                            .await
+                            .unwrap_or_else(|| "Mail Delivery Subsystem".to_string())
+                            .as_str(),
+                        from_addr.as_str(),
+                    ),
+                    rcpts.iter().copied(),
+                    &json,
+                    &mut message,
+                );
 
-            // Send report
-            self.send_report(
-                &from_addr,
-                rcpts.iter(),
-                message,
-                &config.sign,
-                &span,
-                false,
-            )
-            .await;
-        } else {
-            tracing::info!(
-                parent: &span,
-                event = "delivery-failed",
-                "No valid recipients found to deliver report to."
-            );
+                // Send report
+                if self
+                    .send_report(
+                        &from_addr,
+                        rcpts.iter(),
+                        message,
+                        &config.sign,
+                        &span,
+                        false,
+                    )
+                    .await
+                {
+                    server_metrics().report_sent("tls");
+                }
+            } else {
+                tracing::info!(
+                    parent: &span,
+                    event = "delivery-failed",
+                    "No valid recipients found to deliver report to."
+                );
+            }
         }
+
         self.delete_tls_report(events).await;
     }
 
@@ -499,9 +555,19 @@ impl SMTP {
             if let Err(err) = self
                 .shared
                 .default_data_store
-                .delete_range(
+                .delete_range_chunked(
                     ValueKey::from(ValueClass::Queue(QueueClass::TlsReportEvent(from_key))),
                     ValueKey::from(ValueClass::Queue(QueueClass::TlsReportEvent(to_key))),
+                    DELETE_CHUNK_SIZE,
+                    self.shared.default_data_store.delete_range_concurrency(),
+                    |deleted| {
+                        tracing::trace!(
+                            context = "report",
+                            event = "purge",
+                            deleted = deleted,
+                            "Deleted report events."
+                        );
+                    },
                 )
                 .await
             {
@@ -23,7 +23,7 @@
 
 use std::sync::Arc;
 
-use mail_auth::common::headers::HeaderWriter;
+use mail_auth::{common::headers::HeaderWriter, AuthenticatedMessage, AuthenticationResults};
 use sieve::{
     compiler::grammar::actions::action_redirect::{ByMode, ByTime, Notify, NotifyItem, Ret},
     Event, Input, MatchAs, Recipient, Sieve,
@@ -35,9 +35,11 @@ use smtp_proto::{
 use store::{backend::memory::MemoryStore, LookupStore};
 use tokio::runtime::Handle;
 
-use crate::{core::SMTP, queue::DomainPart};
+use crate::{config::ArcSealer, core::SMTP, queue::DomainPart};
 
-use super::{plugins::PluginContext, ScriptModification, ScriptParameters, ScriptResult};
+use super::{
+    plugins::PluginContext, ScriptModification, ScriptParameters, ScriptResult, SessionAuthResults,
+};
 
 impl SMTP {
     pub fn run_script_blocking(
@@ -274,25 +276,36 @@ impl SMTP {
                             instance.message().raw_message().into()
                         };
                         if let Some(raw_message) = raw_message {
-                            let headers = if !self.sieve.sign.is_empty() {
-                                let mut headers = Vec::new();
-                                for dkim in &self.sieve.sign {
-                                    match dkim.sign(raw_message) {
-                                        Ok(signature) => {
-                                            signature.write_header(&mut headers);
-                                        }
-                                        Err(err) => {
-                                            tracing::warn!(parent: &span,
+                            let headers =
+                                if !self.sieve.sign.is_empty() || self.sieve.seal.is_some() {
+                                    let mut headers = Vec::new();
+                                    if let Some(sealer) = &self.sieve.seal {
+                                        self.seal_forwarded_message(
+                                            sealer,
+                                            raw_message,
+                                            params.auth_results.as_ref(),
+                                            &handle,
+                                            &span,
+                                            &mut headers,
+                                        );
+                                    }
+                                    for dkim in &self.sieve.sign {
+                                        match dkim.sign(raw_message) {
+                                            Ok(signature) => {
+                                                signature.write_header(&mut headers);
+                                            }
+                                            Err(err) => {
+                                                tracing::warn!(parent: &span,
                                                 context = "dkim",
                                                 event = "sign-failed",
                                                 reason = %err);
+                                            }
                                         }
                                     }
-                                }
-                                Some(headers)
-                            } else {
-                                None
-                            };
+                                    Some(headers)
+                                } else {
+                                    None
+                                };
 
                             handle.block_on(message.queue(
                                 headers.as_deref(),
@@ -397,4 +410,73 @@ impl SMTP {
             ScriptResult::Discard
         }
     }
+
+    fn seal_forwarded_message(
+        &self,
+        sealer: &ArcSealer,
+        raw_message: &[u8],
+        session: Option<&SessionAuthResults>,
+        handle: &Handle,
+        span: &tracing::Span,
+        headers: &mut Vec<u8>,
+    ) {
+        let auth_message = if let Some(auth_message) = AuthenticatedMessage::parse(raw_message) {
+            auth_message
+        } else {
+            tracing::debug!(parent: span,
+                context = "arc",
+                event = "seal-failed",
+                reason = "Failed to parse message.");
+            return;
+        };
+
+        // Verify the existing chain and preserve the DKIM, SPF and DMARC results for the next hop
+        let arc_output = handle.block_on(self.resolvers.dns.verify_arc(&auth_message));
+        if !arc_output.can_be_sealed() {
+            tracing::debug!(parent: span,
+                context = "arc",
+                event = "seal-skipped",
+                result = %arc_output.result(),
+                "ARC chain cannot be sealed.");
+            return;
+        }
+        let dkim_output = handle.block_on(self.resolvers.dns.verify_dkim(&auth_message));
+        let mut auth_results = AuthenticationResults::new(&self.sieve.hostname);
+        if !dkim_output.is_empty() {
+            auth_results = auth_results.with_dkim_results(&dkim_output, auth_message.from());
+        }
+        if let Some(session) = session {
+            if let Some(spf_ehlo) = &session.spf_ehlo {
+                auth_results = auth_results.with_spf_ehlo_result(
+                    spf_ehlo,
+                    session.remote_ip,
+                    &session.helo_domain,
+                );
+            }
+            if let Some(spf_mail_from) = &session.spf_mail_from {
+                auth_results = auth_results.with_spf_mailfrom_result(
+                    spf_mail_from,
+                    session.remote_ip,
+                    &session.mail_from,
+                    &session.helo_domain,
+                );
+            }
+            if let Some(dmarc_output) = &session.dmarc {
+                auth_results = auth_results.with_dmarc_result(dmarc_output);
+            }
+        }
+
+        match sealer.seal(&auth_message, &auth_results, &arc_output) {
+            Ok(set) => {
+                set.write_header(headers);
+            }
+            Err(err) => {
+                tracing::warn!(parent: span,
+                    context = "arc",
+                    event = "seal-failed",
+                    from = auth_message.from(),
+                    reason = %err);
+            }
+        }
+    }
 }
@@ -23,6 +23,8 @@
 
 use sieve::{runtime::Variable, Context};
 
+use utils::suffixlist::PublicSuffix;
+
 use crate::config::scripts::SieveContext;
 
 use super::ApplyString;
@@ -165,3 +167,24 @@ pub fn fn_domain_part<'x>(ctx: &'x Context<'x, SieveContext>, v: Vec<Variable>)
         }
     })
 }
+
+/// Returns the registrable domain of a host name, using the same rules as
+/// `domain_part(domain, 'sld')` in Sieve scripts.
+pub fn domain_sld(domain: &str, psl: &PublicSuffix) -> String {
+    let d = domain.trim().to_lowercase();
+    let mut seen_dot = false;
+    for (pos, ch) in d.as_bytes().iter().enumerate().rev() {
+        if *ch == b'.' {
+            if seen_dot {
+                let maybe_domain = &d[pos + 1..];
+                if !psl.contains(maybe_domain) {
+                    return maybe_domain.to_string();
+                }
+            } else {
+                seen_dot = true;
+            }
+        }
+    }
+
+    d
+}
@@ -21,9 +21,11 @@
  * for more details.
 */
 
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, net::IpAddr, sync::Arc};
 
 use ahash::AHashMap;
+use bytes::Bytes;
+use mail_auth::{DmarcOutput, SpfOutput};
 use sieve::{runtime::Variable, Envelope};
 
 pub mod envelope;
@@ -55,12 +57,28 @@ pub enum ScriptModification {
         name: Arc<String>,
         value: Arc<String>,
     },
+    Quarantine {
+        reason: String,
+    },
+}
+
+/// SPF and DMARC results of the session running a script, preserved in the
+/// ARC seals of the messages it forwards.
+#[derive(Debug, Clone)]
+pub struct SessionAuthResults {
+    pub remote_ip: IpAddr,
+    pub helo_domain: String,
+    pub mail_from: String,
+    pub spf_ehlo: Option<SpfOutput>,
+    pub spf_mail_from: Option<SpfOutput>,
+    pub dmarc: Option<DmarcOutput>,
 }
 
 pub struct ScriptParameters {
-    message: Option<Arc<Vec<u8>>>,
+    message: Option<Bytes>,
     variables: AHashMap<Cow<'static, str>, Variable>,
     envelope: Vec<(Envelope, Variable)>,
+    auth_results: Option<SessionAuthResults>,
     #[cfg(feature = "test_mode")]
     expected_variables: Option<AHashMap<String, Variable>>,
 }
@@ -71,12 +89,13 @@ impl ScriptParameters {
             variables: AHashMap::with_capacity(10),
             envelope: Vec::with_capacity(6),
             message: None,
+            auth_results: None,
             #[cfg(feature = "test_mode")]
             expected_variables: None,
         }
     }
 
-    pub fn with_message(self, message: Arc<Vec<u8>>) -> Self {
+    pub fn with_message(self, message: Bytes) -> Self {
         Self {
             message: message.into(),
             ..self
@@ -92,6 +111,18 @@ impl ScriptParameters {
         self
     }
 
+    pub fn with_envelope(mut self, name: Envelope, value: impl Into<Variable>) -> Self {
+        self.envelope.push((name, value.into()));
+        self
+    }
+
+    pub fn with_auth_results(self, auth_results: SessionAuthResults) -> Self {
+        Self {
+            auth_results: auth_results.into(),
+            ..self
+        }
+    }
+
     #[cfg(feature = "test_mode")]
     pub fn with_expected_variables(
         mut self,
@@ -58,7 +58,7 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
     } else if record_type.eq_ignore_ascii_case("mx") {
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.mx_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.mx_lookup(entry.as_ref()))
         {
             Ok(result) => result
                 .iter()
@@ -81,7 +81,7 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
 
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.txt_raw_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.txt_raw_lookup(entry.as_ref()))
         {
             Ok(result) => Variable::from(String::from_utf8(result).unwrap_or_default()),
             Err(err) => err.short_error().into(),
@@ -110,7 +110,7 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
 
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.ipv4_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.ipv4_lookup(entry.as_ref()))
         {
             Ok(result) => result
                 .iter()
@@ -122,7 +122,7 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
     } else if record_type.eq_ignore_ascii_case("ipv6") {
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.ipv6_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.ipv6_lookup(entry.as_ref()))
         {
             Ok(result) => result
                 .iter()
@@ -153,7 +153,7 @@ pub fn exec_exists(ctx: PluginContext<'_>) -> Variable {
     } else if record_type.eq_ignore_ascii_case("mx") {
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.mx_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.mx_lookup(entry.as_ref()))
         {
             Ok(result) => i64::from(result.iter().any(|mx| !mx.exchanges.is_empty())),
             Err(Error::DnsRecordNotFound(_)) => 0,
@@ -179,7 +179,7 @@ pub fn exec_exists(ctx: PluginContext<'_>) -> Variable {
 
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.ipv4_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.ipv4_lookup(entry.as_ref()))
         {
             Ok(result) => i64::from(!result.is_empty()),
             Err(Error::DnsRecordNotFound(_)) => 0,
@@ -188,7 +188,7 @@ pub fn exec_exists(ctx: PluginContext<'_>) -> Variable {
     } else if record_type.eq_ignore_ascii_case("ipv6") {
         match ctx
             .handle
-            .block_on(ctx.core.resolvers.dns.ipv6_lookup(entry.as_ref()))
+            .block_on(ctx.core.resolvers.ipv6_lookup(entry.as_ref()))
         {
             Ok(result) => i64::from(!result.is_empty()),
             Err(Error::DnsRecordNotFound(_)) => 0,
@@ -132,6 +132,7 @@ impl FdbStore {
                 } else {
                     options::StreamingMode::Iterator
                 },
+                limit: (params.limit > 0).then_some(params.limit),
                 reverse: !params.ascending,
                 ..Default::default()
             },
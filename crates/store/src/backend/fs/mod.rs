@@ -90,15 +90,22 @@ impl FsStore {
     }
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        self.put_blob_parts(key, &[data]).await
+    }
+
+    pub(crate) async fn put_blob_parts(&self, key: &[u8], parts: &[&[u8]]) -> crate::Result<()> {
         let blob_path = self.build_path(key);
+        let blob_size = parts.iter().map(|part| part.len()).sum::<usize>();
 
         if fs::metadata(&blob_path)
             .await
-            .map_or(true, |m| m.len() as usize != data.len())
+            .map_or(true, |m| m.len() as usize != blob_size)
         {
             fs::create_dir_all(blob_path.parent().unwrap()).await?;
             let mut blob_file = File::create(&blob_path).await?;
-            blob_file.write_all(data).await?;
+            for part in parts {
+                blob_file.write_all(part).await?;
+            }
             blob_file.flush().await?;
         }
 
@@ -49,6 +49,14 @@ pub static ID_ASSIGNMENT_EXPIRY: std::sync::atomic::AtomicU64 =
 #[cfg(not(feature = "test_mode"))]
 pub const ID_ASSIGNMENT_EXPIRY: u64 = 60 * 60; // seconds
 
+/// Prefixes a SQL query with a comment containing the current trace id, so
+/// that the database's slow-query log can be correlated with the session
+/// that issued it.
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub(crate) fn trace_comment(query: &str) -> Option<String> {
+    utils::trace_id::current_hex().map(|trace_id| format!("/* trace_id={trace_id} */ {query}"))
+}
+
 impl From<std::io::Error> for crate::Error {
     fn from(err: std::io::Error) -> Self {
         Self::InternalError(format!("IO error: {}", err))
@@ -34,7 +34,9 @@ impl MysqlStore {
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
         let mut conn = self.conn_pool.get_conn().await?;
-        let s = conn.prep("SELECT v FROM t WHERE k = ?").await?;
+        let s = conn
+            .prep(self.traced("SELECT v FROM t WHERE k = ?").as_ref())
+            .await?;
         conn.exec_first::<Vec<u8>, _, _>(&s, (key,))
             .await
             .map(|bytes| {
@@ -58,7 +60,12 @@ impl MysqlStore {
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
         let mut conn = self.conn_pool.get_conn().await?;
         let s = conn
-            .prep("INSERT INTO t (k, v) VALUES (?, ?) ON DUPLICATE KEY UPDATE v = VALUES(v)")
+            .prep(
+                self.traced(
+                    "INSERT INTO t (k, v) VALUES (?, ?) ON DUPLICATE KEY UPDATE v = VALUES(v)",
+                )
+                .as_ref(),
+            )
             .await?;
         conn.exec_drop(&s, (key, data))
             .await
@@ -68,7 +75,9 @@ impl MysqlStore {
 
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let mut conn = self.conn_pool.get_conn().await?;
-        let s = conn.prep("DELETE FROM t WHERE k = ?").await?;
+        let s = conn
+            .prep(self.traced("DELETE FROM t WHERE k = ?").as_ref())
+            .await?;
         conn.exec_iter(&s, (key,))
             .await
             .map_err(|e| crate::Error::InternalError(format!("Failed to delete blob: {}", e)))
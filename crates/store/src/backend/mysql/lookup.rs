@@ -34,7 +34,7 @@ impl MysqlStore {
         params: Vec<Value<'_>>,
     ) -> crate::Result<T> {
         let mut conn = self.conn_pool.get_conn().await?;
-        let s = conn.prep(query).await?;
+        let s = conn.prep(self.traced(query).as_ref()).await?;
         let params = Params::Positional(params.into_iter().map(Into::into).collect());
 
         match T::query_type() {
@@ -24,7 +24,7 @@
 use std::time::Duration;
 
 use mysql_async::{prelude::Queryable, OptsBuilder, Pool, PoolConstraints, PoolOpts, SslOpts};
-use utils::config::utils::AsKey;
+use utils::{circuit_breaker::CircuitBreaker, config::utils::AsKey};
 
 use crate::{
     SUBSPACE_BITMAPS, SUBSPACE_BLOBS, SUBSPACE_COUNTERS, SUBSPACE_INDEXES, SUBSPACE_LOGS,
@@ -77,6 +77,8 @@ impl MysqlStore {
 
         let db = Self {
             conn_pool: Pool::new(opts),
+            circuit_breaker: CircuitBreaker::parse(config, prefix.as_str(), prefix.as_str())?,
+            inject_trace_id: config.property_or_static((&prefix, "trace-id.inject"), "false")?,
         };
 
         db.create_tables().await?;
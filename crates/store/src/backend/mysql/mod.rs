@@ -21,7 +21,12 @@
  * for more details.
 */
 
+use std::borrow::Cow;
+
 use mysql_async::Pool;
+use utils::circuit_breaker::CircuitBreaker;
+
+use super::trace_comment;
 
 pub mod blob;
 pub mod lookup;
@@ -31,6 +36,18 @@ pub mod write;
 
 pub struct MysqlStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) circuit_breaker: CircuitBreaker,
+    pub(crate) inject_trace_id: bool,
+}
+
+impl MysqlStore {
+    /// Prefixes a query with the current trace id when enabled.
+    pub(crate) fn traced<'x>(&self, query: &'x str) -> Cow<'x, str> {
+        match self.inject_trace_id.then(|| trace_comment(query)).flatten() {
+            Some(query) => Cow::Owned(query),
+            None => Cow::Borrowed(query),
+        }
+    }
 }
 
 impl From<mysql_async::Error> for crate::Error {
@@ -39,10 +39,13 @@ impl MysqlStore {
     {
         let mut conn = self.conn_pool.get_conn().await?;
         let s = conn
-            .prep(&format!(
-                "SELECT v FROM {} WHERE k = ?",
-                char::from(key.subspace())
-            ))
+            .prep(
+                self.traced(&format!(
+                    "SELECT v FROM {} WHERE k = ?",
+                    char::from(key.subspace())
+                ))
+                .as_ref(),
+            )
             .await?;
         let key = key.serialize(0);
         conn.exec_first::<Vec<u8>, _, _>(&s, (key,))
@@ -68,7 +71,12 @@ impl MysqlStore {
         let mut conn = self.conn_pool.get_conn().await?;
 
         let mut bm = RoaringBitmap::new();
-        let s = conn.prep("SELECT k FROM b WHERE k >= ? AND k <= ?").await?;
+        let s = conn
+            .prep(
+                self.traced("SELECT k FROM b WHERE k >= ? AND k <= ?")
+                    .as_ref(),
+            )
+            .await?;
         let mut rows = conn.exec_stream::<Vec<u8>, _, _>(&s, (begin, end)).await?;
 
         while let Some(key) = rows.try_next().await? {
@@ -90,26 +98,35 @@ impl MysqlStore {
         let end = params.end.serialize(0);
         let keys = if params.values { "k, v" } else { "k" };
 
-        let s = conn
-            .prep(&match (params.first, params.ascending) {
-                (true, true) => {
-                    format!(
-                        "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC LIMIT 1"
-                    )
-                }
-                (true, false) => {
-                    format!(
+        let query = match (params.first, params.ascending) {
+            (true, true) => {
+                format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC LIMIT 1")
+            }
+            (true, false) => {
+                format!(
                     "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC LIMIT 1"
                 )
-                }
-                (false, true) => {
-                    format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC")
-                }
-                (false, false) => {
-                    format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC")
-                }
-            })
-            .await?;
+            }
+            (false, true) if params.limit > 0 => {
+                format!(
+                    "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC LIMIT {}",
+                    params.limit
+                )
+            }
+            (false, false) if params.limit > 0 => {
+                format!(
+                    "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC LIMIT {}",
+                    params.limit
+                )
+            }
+            (false, true) => {
+                format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC")
+            }
+            (false, false) => {
+                format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC")
+            }
+        };
+        let s = conn.prep(self.traced(&query).as_ref()).await?;
         let mut rows = conn.exec_stream::<Row, _, _>(&s, (begin, end)).await?;
 
         if params.values {
@@ -146,7 +163,9 @@ impl MysqlStore {
     ) -> crate::Result<i64> {
         let key = key.into().serialize(0);
         let mut conn = self.conn_pool.get_conn().await?;
-        let s = conn.prep("SELECT v FROM c WHERE k = ?").await?;
+        let s = conn
+            .prep(self.traced("SELECT v FROM c WHERE k = ?").as_ref())
+            .await?;
         match conn.exec_first::<i64, _, _>(&s, (key,)).await {
             Ok(Some(num)) => Ok(num),
             Ok(None) => Ok(0),
@@ -109,14 +109,19 @@ impl MysqlStore {
 
                     if *by >= 0 {
                         let s = trx
-                            .prep(concat!(
-                                "INSERT INTO c (k, v) VALUES (?, ?) ",
-                                "ON DUPLICATE KEY UPDATE v = v + VALUES(v)"
-                            ))
+                            .prep(
+                                self.traced(concat!(
+                                    "INSERT INTO c (k, v) VALUES (?, ?) ",
+                                    "ON DUPLICATE KEY UPDATE v = v + VALUES(v)"
+                                ))
+                                .as_ref(),
+                            )
                             .await?;
                         trx.exec_drop(&s, (key, by)).await?;
                     } else {
-                        let s = trx.prep("UPDATE c SET v = v + ? WHERE k = ?").await?;
+                        let s = trx
+                            .prep(self.traced("UPDATE c SET v = v + ? WHERE k = ?").as_ref())
+                            .await?;
                         trx.exec_drop(&s, (by, key)).await?;
                     }
                 }
@@ -134,16 +139,34 @@ impl MysqlStore {
                         let exists = asserted_values.get(&key);
                         let s = if let Some(exists) = exists {
                             if *exists {
-                                trx.prep(&format!("UPDATE {} SET v = :v WHERE k = :k", table))
-                                    .await?
+                                trx.prep(
+                                    self.traced(&format!(
+                                        "UPDATE {} SET v = :v WHERE k = :k",
+                                        table
+                                    ))
+                                    .as_ref(),
+                                )
+                                .await?
                             } else {
-                                trx.prep(&format!("INSERT INTO {} (k, v) VALUES (:k, :v)", table))
-                                    .await?
+                                trx.prep(
+                                    self.traced(&format!(
+                                        "INSERT INTO {} (k, v) VALUES (:k, :v)",
+                                        table
+                                    ))
+                                    .as_ref(),
+                                )
+                                .await?
                             }
                         } else {
-                            trx
-                            .prep(
-                                &format!("INSERT INTO {} (k, v) VALUES (:k, :v) ON DUPLICATE KEY UPDATE v = VALUES(v)", table),
+                            trx.prep(
+                                self.traced(&format!(
+                                    concat!(
+                                        "INSERT INTO {} (k, v) VALUES (:k, :v) ",
+                                        "ON DUPLICATE KEY UPDATE v = VALUES(v)"
+                                    ),
+                                    table
+                                ))
+                                .as_ref(),
                             )
                             .await?
                         };
@@ -163,7 +186,9 @@ impl MysqlStore {
 
                         if matches!(class, ValueClass::ReservedId) {
                             // Make sure the reserved id is not already in use
-                            let s = trx.prep("SELECT 1 FROM b WHERE k = ?").await?;
+                            let s = trx
+                                .prep(self.traced("SELECT 1 FROM b WHERE k = ?").as_ref())
+                                .await?;
                             let key = BitmapKey {
                                 account_id,
                                 collection,
@@ -178,7 +203,10 @@ impl MysqlStore {
                         }
                     } else {
                         let s = trx
-                            .prep(&format!("DELETE FROM {} WHERE k = ?", table))
+                            .prep(
+                                self.traced(&format!("DELETE FROM {} WHERE k = ?", table))
+                                    .as_ref(),
+                            )
                             .await?;
                         trx.exec_drop(&s, (key,)).await?;
                     }
@@ -194,9 +222,11 @@ impl MysqlStore {
                     .serialize(0);
 
                     let s = if *set {
-                        trx.prep("INSERT IGNORE INTO i (k) VALUES (?)").await?
+                        trx.prep(self.traced("INSERT IGNORE INTO i (k) VALUES (?)").as_ref())
+                            .await?
                     } else {
-                        trx.prep("DELETE FROM i WHERE k = ?").await?
+                        trx.prep(self.traced("DELETE FROM i WHERE k = ?").as_ref())
+                            .await?
                     };
                     trx.exec_drop(&s, (key,)).await?;
                 }
@@ -211,12 +241,15 @@ impl MysqlStore {
 
                     let s = if *set {
                         if matches!(class, BitmapClass::DocumentIds) {
-                            trx.prep("INSERT INTO b (k) VALUES (?)").await?
+                            trx.prep(self.traced("INSERT INTO b (k) VALUES (?)").as_ref())
+                                .await?
                         } else {
-                            trx.prep("INSERT IGNORE INTO b (k) VALUES (?)").await?
+                            trx.prep(self.traced("INSERT IGNORE INTO b (k) VALUES (?)").as_ref())
+                                .await?
                         }
                     } else {
-                        trx.prep("DELETE FROM b WHERE k = ?").await?
+                        trx.prep(self.traced("DELETE FROM b WHERE k = ?").as_ref())
+                            .await?
                     };
                     trx.exec_drop(&s, (key,)).await?;
                 }
@@ -233,7 +266,13 @@ impl MysqlStore {
                     .serialize(0);
 
                     let s = trx
-                        .prep("INSERT INTO l (k, v) VALUES (?, ?) ON DUPLICATE KEY UPDATE v = VALUES(v)")
+                        .prep(
+                            self.traced(concat!(
+                                "INSERT INTO l (k, v) VALUES (?, ?) ",
+                                "ON DUPLICATE KEY UPDATE v = VALUES(v)"
+                            ))
+                            .as_ref(),
+                        )
                         .await?;
                     trx.exec_drop(&s, (key, set)).await?;
                 }
@@ -251,7 +290,10 @@ impl MysqlStore {
                     let key = key.serialize(0);
 
                     let s = trx
-                        .prep(&format!("SELECT v FROM {} WHERE k = ? FOR UPDATE", table))
+                        .prep(
+                            self.traced(&format!("SELECT v FROM {} WHERE k = ? FOR UPDATE", table))
+                                .as_ref(),
+                        )
                         .await?;
                     let (exists, matches) = trx
                         .exec_first::<Vec<u8>, _, _>(&s, (&key,))
@@ -274,10 +316,13 @@ impl MysqlStore {
         let mut conn = self.conn_pool.get_conn().await?;
 
         let s = conn
-            .prep(&format!(
-                "DELETE FROM {} WHERE v = 0",
-                char::from(SUBSPACE_COUNTERS),
-            ))
+            .prep(
+                self.traced(&format!(
+                    "DELETE FROM {} WHERE v = 0",
+                    char::from(SUBSPACE_COUNTERS),
+                ))
+                .as_ref(),
+            )
             .await?;
         conn.exec_drop(&s, ()).await.map_err(Into::into)
     }
@@ -286,10 +331,13 @@ impl MysqlStore {
         let mut conn = self.conn_pool.get_conn().await?;
 
         let s = conn
-            .prep(&format!(
-                "DELETE FROM {} WHERE k >= ? AND k < ?",
-                char::from(from.subspace()),
-            ))
+            .prep(
+                self.traced(&format!(
+                    "DELETE FROM {} WHERE k >= ? AND k < ?",
+                    char::from(from.subspace()),
+                ))
+                .as_ref(),
+            )
             .await?;
         conn.exec_drop(&s, (&from.serialize(0), &to.serialize(0)))
             .await
@@ -32,7 +32,7 @@ impl PostgresStore {
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
         let conn = self.conn_pool.get().await?;
-        let s = conn.prepare_cached("SELECT v FROM t WHERE k = $1").await?;
+        let s = self.prepare(&*conn, "SELECT v FROM t WHERE k = $1").await?;
         conn.query_opt(&s, &[&key])
             .await
             .and_then(|row| {
@@ -58,8 +58,9 @@ impl PostgresStore {
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
         let conn = self.conn_pool.get().await?;
-        let s = conn
-            .prepare_cached(
+        let s = self
+            .prepare(
+                &*conn,
                 "INSERT INTO t (k, v) VALUES ($1, $2) ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v",
             )
             .await?;
@@ -71,7 +72,7 @@ impl PostgresStore {
 
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
         let conn = self.conn_pool.get().await?;
-        let s = conn.prepare_cached("DELETE FROM t WHERE k = $1").await?;
+        let s = self.prepare(&*conn, "DELETE FROM t WHERE k = $1").await?;
         conn.execute(&s, &[&key])
             .await
             .map_err(|e| crate::Error::InternalError(format!("Failed to delete blob: {}", e)))
@@ -38,7 +38,7 @@ impl PostgresStore {
         params_: Vec<crate::Value<'_>>,
     ) -> crate::Result<T> {
         let conn = self.conn_pool.get().await?;
-        let s = conn.prepare_cached(query).await?;
+        let s = self.prepare(&*conn, query).await?;
         let params = params_
             .iter()
             .map(|v| v as &(dyn tokio_postgres::types::ToSql + Sync))
@@ -32,7 +32,7 @@ use deadpool_postgres::{
     Config, CreatePoolError, ManagerConfig, PoolConfig, RecyclingMethod, Runtime,
 };
 use tokio_postgres::NoTls;
-use utils::{config::utils::AsKey, rustls_client_config};
+use utils::{circuit_breaker::CircuitBreaker, config::utils::AsKey, rustls_client_config};
 
 impl PostgresStore {
     pub async fn open(config: &utils::config::Config, prefix: impl AsKey) -> crate::Result<Self> {
@@ -64,6 +64,8 @@ impl PostgresStore {
             } else {
                 cfg.create_pool(Some(Runtime::Tokio1), NoTls)?
             },
+            circuit_breaker: CircuitBreaker::parse(config, prefix.as_str(), prefix.as_str())?,
+            inject_trace_id: config.property_or_static((&prefix, "trace-id.inject"), "false")?,
         };
 
         db.create_tables().await?;
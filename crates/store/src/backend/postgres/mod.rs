@@ -21,7 +21,11 @@
  * for more details.
 */
 
-use deadpool_postgres::{Pool, PoolError};
+use deadpool_postgres::{GenericClient, Pool, PoolError};
+use tokio_postgres::Statement;
+use utils::circuit_breaker::CircuitBreaker;
+
+use super::trace_comment;
 
 pub mod blob;
 pub mod lookup;
@@ -32,6 +36,24 @@ pub mod write;
 
 pub struct PostgresStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) circuit_breaker: CircuitBreaker,
+    pub(crate) inject_trace_id: bool,
+}
+
+impl PostgresStore {
+    /// Prepares a statement, prefixing it with the current trace id when
+    /// enabled. Traced statements are not cached as their text is unique
+    /// to each session.
+    pub(crate) async fn prepare(
+        &self,
+        client: &impl GenericClient,
+        query: &str,
+    ) -> Result<Statement, tokio_postgres::Error> {
+        match self.inject_trace_id.then(|| trace_comment(query)).flatten() {
+            Some(query) => client.prepare(&query).await,
+            None => client.prepare_cached(query).await,
+        }
+    }
 }
 
 impl From<PoolError> for crate::Error {
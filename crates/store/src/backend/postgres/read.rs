@@ -37,11 +37,11 @@ impl PostgresStore {
         U: Deserialize + 'static,
     {
         let conn = self.conn_pool.get().await?;
-        let s = conn
-            .prepare_cached(&format!(
-                "SELECT v FROM {} WHERE k = $1",
-                char::from(key.subspace())
-            ))
+        let s = self
+            .prepare(
+                &*conn,
+                &format!("SELECT v FROM {} WHERE k = $1", char::from(key.subspace())),
+            )
             .await?;
         let key = key.serialize(0);
         conn.query_opt(&s, &[&key])
@@ -67,8 +67,8 @@ impl PostgresStore {
         let conn = self.conn_pool.get().await?;
 
         let mut bm = RoaringBitmap::new();
-        let s = conn
-            .prepare_cached("SELECT k FROM b WHERE k >= $1 AND k <= $2")
+        let s = self
+            .prepare(&*conn, "SELECT k FROM b WHERE k >= $1 AND k <= $2")
             .await?;
         let rows = conn.query_raw(&s, &[&begin, &end]).await?;
 
@@ -94,8 +94,7 @@ impl PostgresStore {
         let end = params.end.serialize(0);
         let keys = if params.values { "k, v" } else { "k" };
 
-        let s = conn
-            .prepare_cached(&match (params.first, params.ascending) {
+        let s = self.prepare(&*conn, &match (params.first, params.ascending) {
                 (true, true) => {
                     format!(
                         "SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k ASC LIMIT 1"
@@ -103,8 +102,20 @@ impl PostgresStore {
                 }
                 (true, false) => {
                     format!(
-                    "SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k DESC LIMIT 1"
-                )
+                        "SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k DESC LIMIT 1"
+                    )
+                }
+                (false, true) if params.limit > 0 => {
+                    format!(
+                        "SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k ASC LIMIT {}",
+                        params.limit
+                    )
+                }
+                (false, false) if params.limit > 0 => {
+                    format!(
+                        "SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k DESC LIMIT {}",
+                        params.limit
+                    )
                 }
                 (false, true) => {
                     format!("SELECT {keys} FROM {table} WHERE k >= $1 AND k <= $2 ORDER BY k ASC")
@@ -144,7 +155,7 @@ impl PostgresStore {
     ) -> crate::Result<i64> {
         let key = key.into().serialize(0);
         let conn = self.conn_pool.get().await?;
-        let s = conn.prepare_cached("SELECT v FROM c WHERE k = $1").await?;
+        let s = self.prepare(&*conn, "SELECT v FROM c WHERE k = $1").await?;
         match conn.query_opt(&s, &[&key]).await {
             Ok(Some(row)) => row.try_get(0).map_err(Into::into),
             Ok(None) => Ok(0),
@@ -114,16 +114,19 @@ impl PostgresStore {
                     .serialize(0);
 
                     if *by >= 0 {
-                        let s = trx
-                            .prepare_cached(concat!(
-                                "INSERT INTO c (k, v) VALUES ($1, $2) ",
-                                "ON CONFLICT(k) DO UPDATE SET v = c.v + EXCLUDED.v"
-                            ))
+                        let s = self
+                            .prepare(
+                                &trx,
+                                concat!(
+                                    "INSERT INTO c (k, v) VALUES ($1, $2) ",
+                                    "ON CONFLICT(k) DO UPDATE SET v = c.v + EXCLUDED.v"
+                                ),
+                            )
                             .await?;
                         trx.execute(&s, &[&key, &by]).await?;
                     } else {
-                        let s = trx
-                            .prepare_cached("UPDATE c SET v = v + $1 WHERE k = $2")
+                        let s = self
+                            .prepare(&trx, "UPDATE c SET v = v + $1 WHERE k = $2")
                             .await?;
                         trx.execute(&s, &[&by, &key]).await?;
                     }
@@ -141,26 +144,29 @@ impl PostgresStore {
                     if let ValueOp::Set(value) = op {
                         let s = if let Some(exists) = asserted_values.get(&key) {
                             if *exists {
-                                trx.prepare_cached(&format!(
-                                    "UPDATE {} SET v = $2 WHERE k = $1",
-                                    table
-                                ))
+                                self.prepare(
+                                    &trx,
+                                    &format!("UPDATE {} SET v = $2 WHERE k = $1", table),
+                                )
                                 .await?
                             } else {
-                                trx.prepare_cached(&format!(
-                                    "INSERT INTO {} (k, v) VALUES ($1, $2)",
-                                    table
-                                ))
+                                self.prepare(
+                                    &trx,
+                                    &format!("INSERT INTO {} (k, v) VALUES ($1, $2)", table),
+                                )
                                 .await?
                             }
                         } else {
-                            trx.prepare_cached(&format!(
-                                concat!(
-                                    "INSERT INTO {} (k, v) VALUES ($1, $2) ",
-                                    "ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v"
+                            self.prepare(
+                                &trx,
+                                &format!(
+                                    concat!(
+                                        "INSERT INTO {} (k, v) VALUES ($1, $2) ",
+                                        "ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v"
+                                    ),
+                                    table
                                 ),
-                                table
-                            ))
+                            )
                             .await?
                         };
 
@@ -170,7 +176,7 @@ impl PostgresStore {
 
                         if matches!(class, ValueClass::ReservedId) {
                             // Make sure the reserved id is not already in use
-                            let s = trx.prepare_cached("SELECT 1 FROM b WHERE k = $1").await?;
+                            let s = self.prepare(&trx, "SELECT 1 FROM b WHERE k = $1").await?;
                             let key = BitmapKey {
                                 account_id,
                                 collection,
@@ -183,8 +189,8 @@ impl PostgresStore {
                             }
                         }
                     } else {
-                        let s = trx
-                            .prepare_cached(&format!("DELETE FROM {} WHERE k = $1", table))
+                        let s = self
+                            .prepare(&trx, &format!("DELETE FROM {} WHERE k = $1", table))
                             .await?;
                         trx.execute(&s, &[&key]).await?;
                     }
@@ -200,12 +206,13 @@ impl PostgresStore {
                     .serialize(0);
 
                     let s = if *set {
-                        trx.prepare_cached(
+                        self.prepare(
+                            &trx,
                             "INSERT INTO i (k) VALUES ($1) ON CONFLICT (k) DO NOTHING",
                         )
                         .await?
                     } else {
-                        trx.prepare_cached("DELETE FROM i WHERE k = $1").await?
+                        self.prepare(&trx, "DELETE FROM i WHERE k = $1").await?
                     };
                     trx.execute(&s, &[&key]).await?;
                 }
@@ -220,15 +227,16 @@ impl PostgresStore {
 
                     let s = if *set {
                         if matches!(class, BitmapClass::DocumentIds) {
-                            trx.prepare_cached("INSERT INTO b (k) VALUES ($1)").await?
+                            self.prepare(&trx, "INSERT INTO b (k) VALUES ($1)").await?
                         } else {
-                            trx.prepare_cached(
+                            self.prepare(
+                                &trx,
                                 "INSERT INTO b (k) VALUES ($1) ON CONFLICT (k) DO NOTHING",
                             )
                             .await?
                         }
                     } else {
-                        trx.prepare_cached("DELETE FROM b WHERE k = $1").await?
+                        self.prepare(&trx, "DELETE FROM b WHERE k = $1").await?
                     };
                     trx.execute(&s, &[&key]).await?;
                 }
@@ -244,11 +252,14 @@ impl PostgresStore {
                     }
                     .serialize(0);
 
-                    let s = trx
-                        .prepare_cached(concat!(
-                            "INSERT INTO l (k, v) VALUES ($1, $2) ",
-                            "ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v"
-                        ))
+                    let s = self
+                        .prepare(
+                            &trx,
+                            concat!(
+                                "INSERT INTO l (k, v) VALUES ($1, $2) ",
+                                "ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v"
+                            ),
+                        )
                         .await?;
                     trx.execute(&s, &[&key, set]).await?;
                 }
@@ -265,8 +276,11 @@ impl PostgresStore {
                     let table = char::from(key.subspace());
                     let key = key.serialize(0);
 
-                    let s = trx
-                        .prepare_cached(&format!("SELECT v FROM {} WHERE k = $1 FOR UPDATE", table))
+                    let s = self
+                        .prepare(
+                            &trx,
+                            &format!("SELECT v FROM {} WHERE k = $1 FOR UPDATE", table),
+                        )
                         .await?;
                     let (exists, matches) = trx
                         .query_opt(&s, &[&key])
@@ -290,11 +304,11 @@ impl PostgresStore {
     pub(crate) async fn purge_bitmaps(&self) -> crate::Result<()> {
         let conn = self.conn_pool.get().await?;
 
-        let s = conn
-            .prepare_cached(&format!(
-                "DELETE FROM {} WHERE v = 0",
-                char::from(SUBSPACE_COUNTERS),
-            ))
+        let s = self
+            .prepare(
+                &*conn,
+                &format!("DELETE FROM {} WHERE v = 0", char::from(SUBSPACE_COUNTERS),),
+            )
             .await?;
         conn.execute(&s, &[]).await.map(|_| ()).map_err(Into::into)
     }
@@ -302,11 +316,14 @@ impl PostgresStore {
     pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
         let conn = self.conn_pool.get().await?;
 
-        let s = conn
-            .prepare_cached(&format!(
-                "DELETE FROM {} WHERE k >= $1 AND k < $2",
-                char::from(from.subspace()),
-            ))
+        let s = self
+            .prepare(
+                &*conn,
+                &format!(
+                    "DELETE FROM {} WHERE k >= $1 AND k < $2",
+                    char::from(from.subspace()),
+                ),
+            )
             .await?;
         conn.execute(&s, &[&from.serialize(0), &to.serialize(0)])
             .await
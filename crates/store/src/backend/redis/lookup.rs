@@ -64,6 +64,19 @@ impl RedisStore {
         }
     }
 
+    pub async fn key_claim(&self, key: Vec<u8>, expires: u64) -> crate::Result<bool> {
+        match &self.pool {
+            RedisPool::Single(pool) => {
+                self.key_claim_(pool.get().await?.as_mut(), key, expires)
+                    .await
+            }
+            RedisPool::Cluster(pool) => {
+                self.key_claim_(pool.get().await?.as_mut(), key, expires)
+                    .await
+            }
+        }
+    }
+
     pub async fn key_delete(&self, key: Vec<u8>) -> crate::Result<()> {
         match &self.pool {
             RedisPool::Single(pool) => self.key_delete_(pool.get().await?.as_mut(), key).await,
@@ -140,6 +153,24 @@ impl RedisStore {
         }
     }
 
+    async fn key_claim_(
+        &self,
+        conn: &mut impl AsyncCommands,
+        key: Vec<u8>,
+        expires: u64,
+    ) -> crate::Result<bool> {
+        redis::cmd("SET")
+            .arg(key)
+            .arg(1u8)
+            .arg("NX")
+            .arg("EX")
+            .arg(expires)
+            .query_async::<_, Option<String>>(conn)
+            .await
+            .map(|result| result.is_some())
+            .map_err(Into::into)
+    }
+
     async fn key_incr_(
         &self,
         conn: &mut impl AsyncCommands,
@@ -31,13 +31,20 @@ use redis::{
     cluster::{ClusterClient, ClusterClientBuilder},
     Client, RedisError,
 };
-use utils::config::{utils::AsKey, Config};
+use utils::{
+    circuit_breaker::CircuitBreaker,
+    config::{utils::AsKey, Config},
+};
 
 pub mod lookup;
 pub mod pool;
+pub mod pubsub;
 
 pub struct RedisStore {
     pool: RedisPool,
+    subscriber: Client,
+    timeout: Duration,
+    pub(crate) circuit_breaker: CircuitBreaker,
 }
 
 struct RedisConnectionManager {
@@ -59,16 +66,22 @@ impl RedisStore {
     pub async fn open(config: &Config, prefix: impl AsKey) -> crate::Result<Self> {
         let prefix = prefix.as_key();
 
+        let timeout = config.property_or_static((&prefix, "timeout"), "10s")?;
+
         let db = if let Some(url) = config.value((&prefix, "url")) {
+            let client = Client::open(url)?;
             Self {
                 pool: RedisPool::Single(build_pool(
                     config,
                     &prefix,
                     RedisConnectionManager {
-                        client: Client::open(url)?,
-                        timeout: config.property_or_static((&prefix, "timeout"), "10s")?,
+                        client: client.clone(),
+                        timeout,
                     },
                 )?),
+                subscriber: client,
+                timeout,
+                circuit_breaker: CircuitBreaker::parse(config, prefix.as_str(), prefix.as_str())?,
             }
         } else {
             let addresses = config
@@ -80,6 +93,8 @@ impl RedisStore {
                     "No Redis cluster URLs specified for {prefix:?}"
                 )));
             }
+            // Messages published on any node are broadcast to the whole cluster
+            let subscriber = Client::open(addresses[0].as_str())?;
             let mut builder = ClusterClientBuilder::new(addresses.into_iter());
             if let Some(value) = config.property((&prefix, "username"))? {
                 builder = builder.username(value);
@@ -105,9 +120,12 @@ impl RedisStore {
                     &prefix,
                     RedisClusterConnectionManager {
                         client: builder.build()?,
-                        timeout: config.property_or_static((&prefix, "timeout"), "10s")?,
+                        timeout,
                     },
                 )?),
+                subscriber,
+                timeout,
+                circuit_breaker: CircuitBreaker::parse(config, prefix.as_str(), prefix.as_str())?,
             }
         };
 
@@ -115,6 +133,15 @@ impl RedisStore {
     }
 }
 
+impl RedisStore {
+    pub(crate) fn unavailable(&self) -> crate::Error {
+        crate::Error::InternalError(format!(
+            "Redis store {:?} is temporarily unavailable",
+            self.circuit_breaker.name()
+        ))
+    }
+}
+
 fn build_pool<M: Manager>(
     config: &Config,
     prefix: &str,
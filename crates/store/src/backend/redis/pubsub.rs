@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use futures::StreamExt;
+use redis::aio::ConnectionLike;
+use tokio::sync::mpsc;
+
+use super::{RedisPool, RedisStore};
+
+impl RedisStore {
+    pub async fn publish(&self, channel: &str, message: Vec<u8>) -> crate::Result<()> {
+        match &self.pool {
+            RedisPool::Single(pool) => {
+                self.publish_(pool.get().await?.as_mut(), channel, message)
+                    .await
+            }
+            RedisPool::Cluster(pool) => {
+                self.publish_(pool.get().await?.as_mut(), channel, message)
+                    .await
+            }
+        }
+    }
+
+    /// Subscribes to a channel on a dedicated connection, forwarding the
+    /// payload of every message received. The returned receiver is closed
+    /// once the connection is lost.
+    pub async fn subscribe(&self, channel: &str) -> crate::Result<mpsc::Receiver<Vec<u8>>> {
+        let mut pubsub = match tokio::time::timeout(
+            self.timeout,
+            self.subscriber.get_tokio_connection(),
+        )
+        .await
+        {
+            Ok(conn) => conn?.into_pubsub(),
+            Err(_) => {
+                return Err(crate::Error::InternalError(
+                    "Redis connection timeout".into(),
+                ))
+            }
+        };
+        pubsub.subscribe(channel).await?;
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(async move {
+            let mut messages = pubsub.on_message();
+            while let Some(message) = messages.next().await {
+                if tx.send(message.get_payload_bytes().to_vec()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    async fn publish_(
+        &self,
+        conn: &mut impl ConnectionLike,
+        channel: &str,
+        message: Vec<u8>,
+    ) -> crate::Result<()> {
+        redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(message)
+            .query_async::<_, ()>(conn)
+            .await
+            .map_err(Into::into)
+    }
+}
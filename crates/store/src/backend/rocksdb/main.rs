@@ -29,9 +29,9 @@ use rocksdb::{
     Options,
 };
 
-use tokio::sync::oneshot;
 use utils::{
     config::{utils::AsKey, Config},
+    worker::WorkerPool,
     UnwrapFailure,
 };
 
@@ -99,38 +99,30 @@ impl RocksDbStore {
             db: OptimisticTransactionDB::open_cf_descriptors(&db_opts, idx_path, cfs)
                 .map_err(|e| Error::InternalError(e.into_string()))?
                 .into(),
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(
-                    config
-                        .property::<usize>((&prefix, "pool.workers"))?
-                        .filter(|v| *v > 0)
-                        .unwrap_or_else(|| num_cpus::get() * 4),
-                )
-                .build()
-                .map_err(|err| {
-                    crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
-                })?,
+            worker_pool: WorkerPool::new(
+                prefix.as_str(),
+                config
+                    .property::<usize>((&prefix, "pool.workers"))?
+                    .filter(|v| *v > 0)
+                    .unwrap_or_else(|| num_cpus::get() * 4),
+                config
+                    .property::<usize>((&prefix, "pool.queue-size"))?
+                    .unwrap_or(0),
+            )
+            .map_err(crate::Error::InternalError)?,
         })
     }
 
-    pub async fn spawn_worker<U, V>(&self, mut f: U) -> crate::Result<V>
+    pub async fn spawn_worker<U, V>(&self, f: U) -> crate::Result<V>
     where
         U: FnMut() -> crate::Result<V> + Send,
         V: Sync + Send + 'static,
     {
-        let (tx, rx) = oneshot::channel();
-
-        self.worker_pool.scope(|s| {
-            s.spawn(|_| {
-                tx.send(f()).ok();
-            });
-        });
-
-        match rx.await {
+        match self.worker_pool.install(f) {
             Ok(result) => result,
-            Err(err) => Err(crate::Error::InternalError(format!(
-                "Worker thread failed: {}",
-                err
+            Err(_) => Err(crate::Error::InternalError(format!(
+                "Worker pool {} is saturated",
+                self.worker_pool.name()
             ))),
         }
     }
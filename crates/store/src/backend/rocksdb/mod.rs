@@ -51,5 +51,5 @@ impl From<rocksdb::Error> for crate::Error {
 
 pub struct RocksDbStore {
     db: Arc<OptimisticTransactionDB<MultiThreaded>>,
-    worker_pool: rayon::ThreadPool,
+    worker_pool: utils::worker::WorkerPool,
 }
@@ -102,12 +102,15 @@ impl RocksDbStore {
                 IteratorMode::From(&end, Direction::Reverse)
             };
 
+            let mut count = 0;
             for row in db.iterator_cf(&cf, it_mode) {
                 let (key, value) = row?;
+                count += 1;
                 if key.as_ref() < begin.as_slice()
                     || key.as_ref() > end.as_slice()
                     || !cb(&key, &value)?
                     || params.first
+                    || count == params.limit
                 {
                     break;
                 }
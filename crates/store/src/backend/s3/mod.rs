@@ -21,11 +21,13 @@
  * for more details.
 */
 
-use std::{ops::Range, time::Duration};
+use std::{future::Future, ops::Range, time::Duration};
 
 use s3::{
     creds::{error::CredentialsError, Credentials},
     error::S3Error,
+    request::ResponseData,
+    serde_types::Part,
     Bucket, Region,
 };
 use utils::{
@@ -33,8 +35,24 @@ use utils::{
     config::{utils::AsKey, Config},
 };
 
+// S3 rejects multipart parts smaller than 5 MiB (except the last one)
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+// and uploads with more than 10,000 parts
+const MAX_PARTS: usize = 10_000;
+const CONTENT_TYPE: &str = "application/octet-stream";
+
 pub struct S3Store {
     bucket: Bucket,
+    prefix: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    multipart_threshold: usize,
+    multipart_part_size: usize,
+}
+
+enum S3Failure {
+    Transient(crate::Error),
+    Permanent(crate::Error),
 }
 
 impl S3Store {
@@ -67,6 +85,18 @@ impl S3Store {
             )?
             .with_path_style()
             .with_request_timeout(timeout),
+            prefix: config
+                .value((&prefix, "key-prefix"))
+                .map(|key_prefix| key_prefix.trim_matches('/'))
+                .filter(|key_prefix| !key_prefix.is_empty())
+                .map(|key_prefix| format!("{key_prefix}/")),
+            max_retries: config.property_or_static((&prefix, "retry.max-attempts"), "3")?,
+            retry_backoff: config.property_or_static((&prefix, "retry.backoff"), "200ms")?,
+            multipart_threshold: config
+                .property_or_static((&prefix, "multipart.threshold"), "16777216")?,
+            multipart_part_size: config
+                .property_or_static::<usize>((&prefix, "multipart.part-size"), "8388608")?
+                .max(MIN_PART_SIZE),
         })
     }
 
@@ -75,57 +105,221 @@ impl S3Store {
         key: &[u8],
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
-        let path = Base32Writer::from_bytes(key).finalize();
-        let response = if range.start != 0 || range.end != u32::MAX {
-            self.bucket
-                .get_object_range(
-                    path,
-                    range.start as u64,
-                    Some(range.end.saturating_sub(1) as u64),
-                )
+        let path = self.build_path(key);
+        let path = path.as_str();
+        self.with_retry(|| async move {
+            let response = if range.start != 0 || range.end != u32::MAX {
+                self.bucket
+                    .get_object_range(
+                        path,
+                        range.start as u64,
+                        Some(range.end.saturating_sub(1) as u64),
+                    )
+                    .await
+            } else {
+                self.bucket.get_object(path).await
+            }?;
+            match response.status_code() {
+                200..=299 => Ok(Some(response.to_vec())),
+                404 => Ok(None),
+                _ => Err(S3Failure::from(response)),
+            }
+        })
+        .await
+    }
+
+    pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        let path = self.build_path(key);
+        let path = path.as_str();
+        if data.len() >= self.multipart_threshold {
+            return self.put_blob_multipart(path, data).await;
+        }
+
+        self.with_retry(|| async move {
+            let response = self.bucket.put_object(path, data).await?;
+            if (200..300).contains(&response.status_code()) {
+                Ok(())
+            } else {
+                Err(S3Failure::from(response))
+            }
+        })
+        .await
+    }
+
+    async fn put_blob_multipart(&self, path: &str, data: &[u8]) -> crate::Result<()> {
+        let upload = self
+            .with_retry(|| async move {
+                self.bucket
+                    .initiate_multipart_upload(path, CONTENT_TYPE)
+                    .await
+                    .map_err(S3Failure::from)
+            })
+            .await?;
+        let upload_id = upload.upload_id.as_str();
+
+        // Upload parts, aborting the upload on failure so S3 does not keep orphaned parts
+        let chunks = multipart_chunks(data, self.multipart_part_size);
+        let mut parts = Vec::with_capacity(chunks.len());
+        for (part_number, chunk) in chunks {
+            match self
+                .with_retry(|| async move {
+                    let part = self
+                        .bucket
+                        .put_multipart_chunk(
+                            chunk.to_vec(),
+                            path,
+                            part_number,
+                            upload_id,
+                            CONTENT_TYPE,
+                        )
+                        .await?;
+
+                    // A part without an ETag was not stored and cannot be completed
+                    if part.part_number == part_number && !part.etag.trim().is_empty() {
+                        Ok(part)
+                    } else {
+                        Err(S3Failure::Permanent(crate::Error::InternalError(format!(
+                            "S3 multipart upload of part {part_number} returned no ETag"
+                        ))))
+                    }
+                })
                 .await
-        } else {
-            self.bucket.get_object(path).await
-        };
-        match response {
-            Ok(response) if (200..300).contains(&response.status_code()) => {
-                Ok(Some(response.to_vec()))
+            {
+                Ok(part) => parts.push(part),
+                Err(err) => {
+                    self.abort_multipart(path, upload_id).await;
+                    return Err(err);
+                }
             }
-            Ok(response) if response.status_code() == 404 => Ok(None),
-            Ok(response) => Err(crate::Error::InternalError(format!(
-                "S3 error code {}: {}",
-                response.status_code(),
-                String::from_utf8_lossy(response.as_slice())
-            ))),
-            Err(err) => Err(err.into()),
         }
+
+        let parts = &parts;
+        let result = self
+            .with_retry(|| async move {
+                let response = self
+                    .bucket
+                    .complete_multipart_upload(path, upload_id, parts.clone())
+                    .await?;
+                if (200..300).contains(&response.status_code()) {
+                    Ok(())
+                } else {
+                    Err(S3Failure::from(response))
+                }
+            })
+            .await;
+        if result.is_err() {
+            self.abort_multipart(path, upload_id).await;
+        }
+        result
     }
 
-    pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self
-            .bucket
-            .put_object(Base32Writer::from_bytes(key).finalize(), data)
-            .await
-        {
-            Ok(response) if (200..300).contains(&response.status_code()) => Ok(()),
-            Ok(response) => Err(crate::Error::InternalError(format!(
-                "S3 error code {}: {}",
-                response.status_code(),
-                String::from_utf8_lossy(response.as_slice())
-            ))),
-            Err(e) => Err(e.into()),
+    async fn abort_multipart(&self, path: &str, upload_id: &str) {
+        if let Err(err) = self.bucket.abort_upload(path, upload_id).await {
+            tracing::debug!(
+                context = "s3",
+                event = "error",
+                path = path,
+                reason = %err,
+                "Failed to abort multipart upload."
+            );
         }
     }
 
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        self.bucket
-            .delete_object(Base32Writer::from_bytes(key).finalize())
-            .await
-            .map(|response| (200..300).contains(&response.status_code()))
-            .map_err(|e| e.into())
+        let path = self.build_path(key);
+        let path = path.as_str();
+        self.with_retry(|| async move {
+            let response = self.bucket.delete_object(path).await?;
+            match response.status_code() {
+                200..=299 => Ok(true),
+                404 => Ok(false),
+                _ => Err(S3Failure::from(response)),
+            }
+        })
+        .await
+    }
+
+    fn build_path(&self, key: &[u8]) -> String {
+        let key = Base32Writer::from_bytes(key).finalize();
+        if let Some(prefix) = &self.prefix {
+            format!("{prefix}{key}")
+        } else {
+            key
+        }
+    }
+
+    async fn with_retry<T, F, Fut>(&self, op: F) -> crate::Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, S3Failure>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(result) => return Ok(result),
+                Err(S3Failure::Transient(err)) if attempt < self.max_retries => {
+                    let backoff = self.retry_backoff * 2u32.saturating_pow(attempt);
+                    tracing::debug!(
+                        context = "s3",
+                        event = "retry",
+                        attempt = attempt + 1,
+                        backoff_ms = backoff.as_millis() as u64,
+                        reason = %err,
+                        "S3 request failed, retrying."
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(S3Failure::Transient(err) | S3Failure::Permanent(err)) => return Err(err),
+            }
+        }
+    }
+}
+
+impl From<ResponseData> for S3Failure {
+    fn from(response: ResponseData) -> Self {
+        let status_code = response.status_code();
+        let err = crate::Error::InternalError(format!(
+            "S3 error code {}: {}",
+            status_code,
+            String::from_utf8_lossy(response.as_slice())
+        ));
+
+        // Throttling and server errors are worth retrying
+        if status_code == 429 || status_code >= 500 {
+            S3Failure::Transient(err)
+        } else {
+            S3Failure::Permanent(err)
+        }
     }
 }
 
+impl From<S3Error> for S3Failure {
+    fn from(err: S3Error) -> Self {
+        match err {
+            // Requests rejected by S3 fail again when retried
+            S3Error::Http(status_code, _) if status_code != 429 && status_code < 500 => {
+                S3Failure::Permanent(err.into())
+            }
+            _ => S3Failure::Transient(err.into()),
+        }
+    }
+}
+
+/// Splits a blob into numbered multipart upload parts.
+fn multipart_chunks(data: &[u8], part_size: usize) -> Vec<(u32, &[u8])> {
+    data.chunks(multipart_part_size(data.len(), part_size))
+        .enumerate()
+        .map(|(part_number, chunk)| (part_number as u32 + 1, chunk))
+        .collect()
+}
+
+/// Parts are grown past the configured size when needed to stay within the
+/// S3 part count limit.
+fn multipart_part_size(len: usize, part_size: usize) -> usize {
+    part_size.max(MIN_PART_SIZE).max(len.div_ceil(MAX_PARTS))
+}
+
 impl From<S3Error> for crate::Error {
     fn from(err: S3Error) -> Self {
         Self::InternalError(format!("S3 error: {}", err))
@@ -137,3 +331,59 @@ impl From<CredentialsError> for crate::Error {
         Self::InternalError(format!("S3 Credentials error: {}", err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_multipart_chunks() {
+        // Only the last part may be smaller than the part size
+        let data = vec![0u8; MIN_PART_SIZE * 2 + 10];
+        let chunks = multipart_chunks(&data, MIN_PART_SIZE);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|(part_number, chunk)| (*part_number, chunk.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, MIN_PART_SIZE), (2, MIN_PART_SIZE), (3, 10)]
+        );
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|(_, chunk)| *chunk)
+                .collect::<Vec<_>>()
+                .concat(),
+            data
+        );
+
+        // Exact multiples do not produce an empty trailing part
+        let data = vec![0u8; MIN_PART_SIZE * 2];
+        assert_eq!(multipart_chunks(&data, MIN_PART_SIZE).len(), 2);
+
+        // Part sizes below the S3 minimum are raised
+        let data = vec![0u8; MIN_PART_SIZE + 1];
+        assert_eq!(
+            multipart_chunks(&data, 1024)
+                .iter()
+                .map(|(_, chunk)| chunk.len())
+                .collect::<Vec<_>>(),
+            vec![MIN_PART_SIZE, 1]
+        );
+
+        // Parts grow to keep the upload within the part count limit
+        for len in [
+            MIN_PART_SIZE * MAX_PARTS,
+            MIN_PART_SIZE * MAX_PARTS + 1,
+            MIN_PART_SIZE * (MAX_PARTS + 7) + 3,
+        ] {
+            let part_size = multipart_part_size(len, MIN_PART_SIZE);
+            assert!(part_size >= MIN_PART_SIZE);
+            assert!(len.div_ceil(part_size) <= MAX_PARTS, "{len}");
+        }
+        assert_eq!(
+            multipart_part_size(MIN_PART_SIZE * MAX_PARTS, MIN_PART_SIZE),
+            MIN_PART_SIZE
+        );
+    }
+}
@@ -22,9 +22,9 @@
 */
 
 use r2d2::Pool;
-use tokio::sync::oneshot;
 use utils::{
     config::{utils::AsKey, Config},
+    worker::WorkerPool,
     UnwrapFailure,
 };
 
@@ -60,17 +60,17 @@ impl SqliteStore {
                         ))
                     }),
                 )?,
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(
-                    config
-                        .property::<usize>((&prefix, "pool.workers"))?
-                        .filter(|v| *v > 0)
-                        .unwrap_or_else(num_cpus::get),
-                )
-                .build()
-                .map_err(|err| {
-                    crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
-                })?,
+            worker_pool: WorkerPool::new(
+                prefix.as_str(),
+                config
+                    .property::<usize>((&prefix, "pool.workers"))?
+                    .filter(|v| *v > 0)
+                    .unwrap_or_else(num_cpus::get),
+                config
+                    .property::<usize>((&prefix, "pool.queue-size"))?
+                    .unwrap_or(0),
+            )
+            .map_err(crate::Error::InternalError)?,
         };
         db.create_tables()?;
         Ok(db)
@@ -82,12 +82,8 @@ impl SqliteStore {
             conn_pool: Pool::builder()
                 .max_size(1)
                 .build(SqliteConnectionManager::memory())?,
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(num_cpus::get())
-                .build()
-                .map_err(|err| {
-                    crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
-                })?,
+            worker_pool: WorkerPool::new("store.memory", num_cpus::get(), 0)
+                .map_err(crate::Error::InternalError)?,
         };
         db.create_tables()?;
         Ok(db)
@@ -135,24 +131,16 @@ impl SqliteStore {
         Ok(())
     }
 
-    pub async fn spawn_worker<U, V>(&self, mut f: U) -> crate::Result<V>
+    pub async fn spawn_worker<U, V>(&self, f: U) -> crate::Result<V>
     where
         U: FnMut() -> crate::Result<V> + Send,
         V: Sync + Send + 'static,
     {
-        let (tx, rx) = oneshot::channel();
-
-        self.worker_pool.scope(|s| {
-            s.spawn(|_| {
-                tx.send(f()).ok();
-            });
-        });
-
-        match rx.await {
+        match self.worker_pool.install(f) {
             Ok(result) => result,
-            Err(err) => Err(crate::Error::InternalError(format!(
-                "Worker thread failed: {}",
-                err
+            Err(_) => Err(crate::Error::InternalError(format!(
+                "Worker pool {} is saturated",
+                self.worker_pool.name()
             ))),
         }
     }
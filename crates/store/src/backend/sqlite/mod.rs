@@ -52,5 +52,5 @@ impl From<rusqlite::types::FromSqlError> for crate::Error {
 
 pub struct SqliteStore {
     pub(crate) conn_pool: Pool<SqliteConnectionManager>,
-    pub(crate) worker_pool: rayon::ThreadPool,
+    pub(crate) worker_pool: utils::worker::WorkerPool,
 }
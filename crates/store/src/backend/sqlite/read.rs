@@ -104,6 +104,18 @@ impl SqliteStore {
                         "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC LIMIT 1"
                     )
                 }
+                (false, true) if params.limit > 0 => {
+                    format!(
+                        "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC LIMIT {}",
+                        params.limit
+                    )
+                }
+                (false, false) if params.limit > 0 => {
+                    format!(
+                        "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k DESC LIMIT {}",
+                        params.limit
+                    )
+                }
                 (false, true) => {
                     format!("SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC")
                 }
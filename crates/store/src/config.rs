@@ -28,7 +28,7 @@ use utils::config::{cron::SimpleCron, Config};
 use crate::{
     backend::{fs::FsStore, memory::MemoryStore},
     write::purge::{PurgeSchedule, PurgeStore},
-    LookupStore, QueryStore, Store, Stores,
+    BlobChunking, BlobStore, CompressionAlgo, LookupStore, QueryStore, Store, Stores,
 };
 
 #[cfg(feature = "s3")]
@@ -83,6 +83,7 @@ impl ConfigStore for Config {
                 .to_ascii_lowercase();
             let prefix = ("store", id);
             let store_id = id.to_string();
+            let compression = parse_compression(self, id)?;
 
             let lookup_store: Store = match protocol.as_str() {
                 #[cfg(feature = "rocks")]
@@ -92,9 +93,10 @@ impl ConfigStore for Config {
                     config
                         .fts_stores
                         .insert(store_id.clone(), db.clone().into());
-                    config
-                        .blob_stores
-                        .insert(store_id.clone(), db.clone().into());
+                    config.blob_stores.insert(
+                        store_id.clone(),
+                        BlobStore::from(db.clone()).with_compression(compression),
+                    );
                     config.lookup_stores.insert(store_id, db.into());
                     continue;
                 }
@@ -105,9 +107,10 @@ impl ConfigStore for Config {
                     config
                         .fts_stores
                         .insert(store_id.clone(), db.clone().into());
-                    config
-                        .blob_stores
-                        .insert(store_id.clone(), db.clone().into());
+                    config.blob_stores.insert(
+                        store_id.clone(),
+                        BlobStore::from(db.clone()).with_compression(compression),
+                    );
                     config.lookup_stores.insert(store_id, db.into());
                     continue;
                 }
@@ -118,9 +121,10 @@ impl ConfigStore for Config {
                     config
                         .fts_stores
                         .insert(store_id.clone(), db.clone().into());
-                    config
-                        .blob_stores
-                        .insert(store_id.clone(), db.clone().into());
+                    config.blob_stores.insert(
+                        store_id.clone(),
+                        BlobStore::from(db.clone()).with_compression(compression),
+                    );
                     db
                 }
                 #[cfg(feature = "mysql")]
@@ -130,9 +134,10 @@ impl ConfigStore for Config {
                     config
                         .fts_stores
                         .insert(store_id.clone(), db.clone().into());
-                    config
-                        .blob_stores
-                        .insert(store_id.clone(), db.clone().into());
+                    config.blob_stores.insert(
+                        store_id.clone(),
+                        BlobStore::from(db.clone()).with_compression(compression),
+                    );
                     db
                 }
                 #[cfg(feature = "sqlite")]
@@ -142,22 +147,27 @@ impl ConfigStore for Config {
                     config
                         .fts_stores
                         .insert(store_id.clone(), db.clone().into());
-                    config
-                        .blob_stores
-                        .insert(store_id.clone(), db.clone().into());
+                    config.blob_stores.insert(
+                        store_id.clone(),
+                        BlobStore::from(db.clone()).with_compression(compression),
+                    );
                     db
                 }
                 "fs" => {
-                    config
-                        .blob_stores
-                        .insert(store_id, FsStore::open(self, prefix).await?.into());
+                    config.blob_stores.insert(
+                        store_id,
+                        BlobStore::from(FsStore::open(self, prefix).await?)
+                            .with_compression(compression),
+                    );
                     continue;
                 }
                 #[cfg(feature = "s3")]
                 "s3" => {
-                    config
-                        .blob_stores
-                        .insert(store_id, S3Store::open(self, prefix).await?.into());
+                    config.blob_stores.insert(
+                        store_id,
+                        BlobStore::from(S3Store::open(self, prefix).await?)
+                            .with_compression(compression),
+                    );
                     continue;
                 }
                 #[cfg(feature = "elastic")]
@@ -209,6 +219,34 @@ impl ConfigStore for Config {
             }
         }
 
+        // Enable content-defined chunking on blob stores
+        for (id, blob_store) in config.blob_stores.iter_mut() {
+            if !self
+                .property_or_static::<bool>(("store", id.as_str(), "chunking.enable"), "false")?
+            {
+                continue;
+            }
+            let store_id = self
+                .value(("store", id.as_str(), "chunking.store"))
+                .unwrap_or(id.as_str());
+            let store = config.stores.get(store_id).cloned().ok_or_else(|| {
+                format!(
+                    "Unable to find data store {store_id:?} defined in key \"store.{id}.chunking.store\"."
+                )
+            })?;
+            *blob_store = blob_store.clone().with_chunking(Some(BlobChunking {
+                store,
+                threshold: self
+                    .property_or_static(("store", id.as_str(), "chunking.threshold"), "1048576")?,
+                min_size: self
+                    .property_or_static(("store", id.as_str(), "chunking.min-size"), "16384")?,
+                avg_size: self
+                    .property_or_static(("store", id.as_str(), "chunking.avg-size"), "65536")?,
+                max_size: self
+                    .property_or_static(("store", id.as_str(), "chunking.max-size"), "262144")?,
+            }));
+        }
+
         Ok(config)
     }
 
@@ -275,3 +313,21 @@ impl From<crate::Error> for String {
         }
     }
 }
+
+fn parse_compression(config: &Config, id: &str) -> utils::config::Result<CompressionAlgo> {
+    match config
+        .value(("store", id, "compression.algorithm"))
+        .unwrap_or("none")
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "zstd" => Ok(CompressionAlgo::Zstd {
+            level: config.property_or_static(("store", id, "compression.level"), "3")?,
+            min_size: config.property_or_static(("store", id, "compression.min-size"), "4096")?,
+        }),
+        "none" => Ok(CompressionAlgo::None),
+        algo => Err(format!(
+            "Invalid compression algorithm {algo:?} for property \"store.{id}.compression.algorithm\"."
+        )),
+    }
+}
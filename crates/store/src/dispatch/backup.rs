@@ -0,0 +1,460 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::io::{Read, Write};
+
+use roaring::RoaringBitmap;
+use utils::{codec::leb128::Leb128Iterator, BLOB_HASH_LEN};
+
+use crate::{
+    write::{
+        key::{
+            DeserializeBigEndian, BM_DOCUMENT_IDS, BM_TAG, BM_TEXT, TAG_ID, TAG_STATIC, TAG_TEXT,
+        },
+        AnyClass, AnyKey, Batch, BitmapClass, BitmapHash, Operation, TagValue, ValueClass, ValueOp,
+    },
+    BitmapKey, BlobStore, IterateParams, Store, ValueKey, SUBSPACE_BITMAPS, SUBSPACE_COUNTERS,
+    SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_VALUES, U32_LEN,
+};
+
+const BACKUP_MAGIC: &[u8] = b"STWBKP";
+const BACKUP_VERSION: u8 = 1;
+const BACKUP_PAGE_SIZE: usize = 1000;
+
+const FAMILY_VALUE: u8 = 0;
+const FAMILY_COUNTER: u8 = 1;
+const FAMILY_INDEX: u8 = 2;
+const FAMILY_BITMAP: u8 = 3;
+const FAMILY_LOG: u8 = 4;
+const FAMILY_BLOB: u8 = 5;
+const FAMILY_END: u8 = u8::MAX;
+
+// Value key prefixes that are rebuilt on import or are not portable
+const VALUE_BLOB_RESERVE: u8 = 6;
+const VALUE_BLOB_LINK: u8 = 7;
+const VALUE_BLOB_CHUNK_REF: u8 = 11;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BackupStats {
+    pub values: usize,
+    pub counters: usize,
+    pub indexes: usize,
+    pub bitmaps: usize,
+    pub logs: usize,
+    pub blobs: usize,
+}
+
+type BackupPage = (Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>);
+
+impl Store {
+    /// Writes the contents of every subspace and the blobs they link to into a
+    /// backend independent backup stream. Keys are read page by page, so the
+    /// store remains online while it is being exported.
+    pub async fn export_all(
+        &self,
+        blob_store: &BlobStore,
+        writer: &mut impl Write,
+    ) -> crate::Result<BackupStats> {
+        let mut stats = BackupStats::default();
+        writer.write_all(BACKUP_MAGIC)?;
+        writer.write_all(&[BACKUP_VERSION])?;
+
+        // Export values, collecting the hashes of linked blobs
+        let mut blob_hashes: Vec<Vec<u8>> = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next_cursor) = self.backup_page(SUBSPACE_VALUES, true, cursor).await?;
+            for (key, value) in page {
+                match key.first() {
+                    Some(&VALUE_BLOB_RESERVE | &VALUE_BLOB_CHUNK_REF) => continue,
+                    Some(&VALUE_BLOB_LINK) => {
+                        if let Some(hash) = key.get(1..BLOB_HASH_LEN + 1) {
+                            if blob_hashes.last().map_or(true, |last| last != hash) {
+                                blob_hashes.push(hash.to_vec());
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+                write_record(writer, FAMILY_VALUE, &key, &value)?;
+                stats.values += 1;
+            }
+            if next_cursor.is_none() {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        // Export counters
+        let mut cursor = None;
+        loop {
+            let (page, next_cursor) = self.backup_page(SUBSPACE_COUNTERS, false, cursor).await?;
+            for (key, _) in page {
+                let value = self
+                    .get_counter(ValueKey::from(ValueClass::Any(AnyClass {
+                        subspace: SUBSPACE_COUNTERS,
+                        key: key.clone(),
+                    })))
+                    .await?;
+                if value != 0 {
+                    write_record(writer, FAMILY_COUNTER, &key, &value.to_be_bytes())?;
+                    stats.counters += 1;
+                }
+            }
+            if next_cursor.is_none() {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        // Export indexes and logs
+        for (subspace, family) in [
+            (SUBSPACE_INDEXES, FAMILY_INDEX),
+            (SUBSPACE_LOGS, FAMILY_LOG),
+        ] {
+            let mut cursor = None;
+            loop {
+                let (page, next_cursor) = self
+                    .backup_page(subspace, family == FAMILY_LOG, cursor)
+                    .await?;
+                for (key, value) in page {
+                    write_record(writer, family, &key, &value)?;
+                    if family == FAMILY_LOG {
+                        stats.logs += 1;
+                    } else {
+                        stats.indexes += 1;
+                    }
+                }
+                if next_cursor.is_none() {
+                    break;
+                }
+                cursor = next_cursor;
+            }
+        }
+
+        // Export bitmaps as a list of document ids, as each backend stores them differently
+        let block_len = self.bitmap_block_len();
+        let mut last_key: Vec<u8> = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next_cursor) = self.backup_page(SUBSPACE_BITMAPS, false, cursor).await?;
+            for (key, _) in page {
+                let key = &key[..key.len().saturating_sub(block_len)];
+                if key == last_key.as_slice() {
+                    continue;
+                }
+                last_key = key.to_vec();
+
+                let bitmap_key = deserialize_bitmap_key(key)?;
+                if let Some(bitmap) = self.get_bitmap(bitmap_key).await? {
+                    let mut value = Vec::with_capacity(bitmap.serialized_size());
+                    bitmap.serialize_into(&mut value)?;
+                    write_record(writer, FAMILY_BITMAP, key, &value)?;
+                    stats.bitmaps += 1;
+                }
+            }
+            if next_cursor.is_none() {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        // Export blobs
+        for hash in blob_hashes {
+            if let Some(data) = blob_store.get_blob(&hash, 0..u32::MAX).await? {
+                write_record(writer, FAMILY_BLOB, &hash, &data)?;
+                stats.blobs += 1;
+            }
+        }
+
+        writer.write_all(&[FAMILY_END])?;
+        writer.flush()?;
+
+        Ok(stats)
+    }
+
+    /// Imports a backup stream written by `export_all`, which may originate
+    /// from a different backend. Existing keys are overwritten.
+    pub async fn import_all(
+        &self,
+        blob_store: &BlobStore,
+        reader: &mut impl Read,
+    ) -> crate::Result<BackupStats> {
+        let mut stats = BackupStats::default();
+        let mut header = [0u8; BACKUP_MAGIC.len() + 1];
+        reader.read_exact(&mut header)?;
+        if &header[..BACKUP_MAGIC.len()] != BACKUP_MAGIC {
+            return Err(crate::Error::InternalError(
+                "Invalid backup file header".to_string(),
+            ));
+        } else if header[BACKUP_MAGIC.len()] != BACKUP_VERSION {
+            return Err(crate::Error::InternalError(format!(
+                "Unsupported backup version {}",
+                header[BACKUP_MAGIC.len()]
+            )));
+        }
+
+        let mut ops = Vec::with_capacity(BACKUP_PAGE_SIZE);
+        loop {
+            let mut family = [0u8; 1];
+            reader.read_exact(&mut family)?;
+            if family[0] == FAMILY_END {
+                break;
+            }
+            let key = read_bytes(reader)?;
+            let value = read_bytes(reader)?;
+
+            match family[0] {
+                FAMILY_VALUE => {
+                    ops.push(Operation::Value {
+                        class: ValueClass::Any(AnyClass {
+                            subspace: SUBSPACE_VALUES,
+                            key,
+                        }),
+                        op: ValueOp::Set(value),
+                    });
+                    stats.values += 1;
+                }
+                FAMILY_COUNTER => {
+                    let value = value.as_slice().deserialize_be_u64(0)? as i64;
+                    ops.push(Operation::Value {
+                        class: ValueClass::Any(AnyClass {
+                            subspace: SUBSPACE_COUNTERS,
+                            key,
+                        }),
+                        op: ValueOp::Add(value),
+                    });
+                    stats.counters += 1;
+                }
+                FAMILY_INDEX => {
+                    let account_id = key.as_slice().deserialize_be_u32(0)?;
+                    let document_id = key
+                        .as_slice()
+                        .deserialize_be_u32(key.len().saturating_sub(U32_LEN))?;
+                    let (collection, field) = match key.get(U32_LEN..U32_LEN + 2) {
+                        Some(&[collection, field]) if key.len() >= (U32_LEN * 2) + 2 => {
+                            (collection, field)
+                        }
+                        _ => return Err(invalid_key("index", &key)),
+                    };
+                    ops.extend([
+                        Operation::AccountId { account_id },
+                        Operation::Collection { collection },
+                        Operation::DocumentId { document_id },
+                        Operation::Index {
+                            field,
+                            key: key[U32_LEN + 2..key.len() - U32_LEN].to_vec(),
+                            set: true,
+                        },
+                    ]);
+                    stats.indexes += 1;
+                }
+                FAMILY_LOG => {
+                    let account_id = key.as_slice().deserialize_be_u32(0)?;
+                    let change_id = key.as_slice().deserialize_be_u64(U32_LEN + 1)?;
+                    ops.extend([
+                        Operation::AccountId { account_id },
+                        Operation::Log {
+                            change_id,
+                            collection: key[U32_LEN],
+                            set: value,
+                        },
+                    ]);
+                    stats.logs += 1;
+                }
+                FAMILY_BITMAP => {
+                    let bitmap_key = deserialize_bitmap_key(&key)?;
+                    let bitmap = RoaringBitmap::deserialize_from(value.as_slice())?;
+                    ops.extend([
+                        Operation::AccountId {
+                            account_id: bitmap_key.account_id,
+                        },
+                        Operation::Collection {
+                            collection: bitmap_key.collection,
+                        },
+                    ]);
+                    for document_id in bitmap {
+                        ops.extend([
+                            Operation::DocumentId { document_id },
+                            Operation::Bitmap {
+                                class: bitmap_key.class.clone(),
+                                set: true,
+                            },
+                        ]);
+                        if ops.len() >= BACKUP_PAGE_SIZE {
+                            self.write(Batch {
+                                ops: std::mem::take(&mut ops),
+                            })
+                            .await?;
+                            ops.extend([
+                                Operation::AccountId {
+                                    account_id: bitmap_key.account_id,
+                                },
+                                Operation::Collection {
+                                    collection: bitmap_key.collection,
+                                },
+                            ]);
+                        }
+                    }
+                    stats.bitmaps += 1;
+                }
+                FAMILY_BLOB => {
+                    blob_store.put_blob(&key, &value).await?;
+                    stats.blobs += 1;
+                }
+                family => {
+                    return Err(crate::Error::InternalError(format!(
+                        "Unknown backup record family {family}"
+                    )));
+                }
+            }
+
+            if ops.len() >= BACKUP_PAGE_SIZE {
+                self.write(Batch {
+                    ops: std::mem::take(&mut ops),
+                })
+                .await?;
+            }
+        }
+
+        if !ops.is_empty() {
+            self.write(Batch { ops }).await?;
+        }
+
+        Ok(stats)
+    }
+
+    async fn backup_page(
+        &self,
+        subspace: u8,
+        with_values: bool,
+        cursor: Option<Vec<u8>>,
+    ) -> crate::Result<BackupPage> {
+        let mut page = Vec::with_capacity(BACKUP_PAGE_SIZE);
+        let next_cursor = self
+            .iterate_paged(
+                IterateParams::new(
+                    AnyKey {
+                        subspace,
+                        key: vec![0u8],
+                    },
+                    AnyKey {
+                        subspace,
+                        key: vec![u8::MAX; 10],
+                    },
+                )
+                .set_values(with_values),
+                cursor.as_deref(),
+                BACKUP_PAGE_SIZE,
+                |key, value| {
+                    page.push((key.to_vec(), value.to_vec()));
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        Ok((page, next_cursor))
+    }
+
+    // Length of the block number appended to bitmap keys by each backend
+    fn bitmap_block_len(&self) -> usize {
+        match self {
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(_) => 0,
+            #[allow(unreachable_patterns)]
+            _ => U32_LEN,
+        }
+    }
+}
+
+fn deserialize_bitmap_key(key: &[u8]) -> crate::Result<BitmapKey<BitmapClass>> {
+    let account_id = key.deserialize_be_u32(0)?;
+    let (collection, family) = match key.get(U32_LEN..U32_LEN + 2) {
+        Some(&[collection, family]) => (collection, family),
+        _ => return Err(invalid_key("bitmap", key)),
+    };
+    let field = key.get(U32_LEN + 2).copied();
+    let bytes = key.get(U32_LEN + 3..).unwrap_or_default();
+
+    let class = match (family, field) {
+        (BM_DOCUMENT_IDS, _) => BitmapClass::DocumentIds,
+        (family, Some(field)) if family == BM_TAG | TAG_ID => BitmapClass::Tag {
+            field,
+            value: TagValue::Id(
+                bytes
+                    .iter()
+                    .next_leb128()
+                    .ok_or_else(|| invalid_key("bitmap", key))?,
+            ),
+        },
+        (family, Some(field)) if family == BM_TAG | TAG_TEXT => BitmapClass::Tag {
+            field,
+            value: TagValue::Text(bytes.to_vec()),
+        },
+        (family, Some(field)) if family == BM_TAG | TAG_STATIC => BitmapClass::Tag {
+            field,
+            value: TagValue::Static(*bytes.first().ok_or_else(|| invalid_key("bitmap", key))?),
+        },
+        (family, Some(field)) if family & BM_TEXT != 0 => BitmapClass::Text {
+            field,
+            token: BitmapHash {
+                hash: bytes.try_into().map_err(|_| invalid_key("bitmap", key))?,
+                len: family & !BM_TEXT,
+            },
+        },
+        _ => return Err(invalid_key("bitmap", key)),
+    };
+
+    Ok(BitmapKey {
+        account_id,
+        collection,
+        class,
+        block_num: 0,
+    })
+}
+
+fn write_record(
+    writer: &mut impl Write,
+    family: u8,
+    key: &[u8],
+    value: &[u8],
+) -> crate::Result<()> {
+    writer.write_all(&[family])?;
+    writer.write_all(&(key.len() as u32).to_be_bytes())?;
+    writer.write_all(key)?;
+    writer.write_all(&(value.len() as u32).to_be_bytes())?;
+    writer.write_all(value)?;
+    Ok(())
+}
+
+fn read_bytes(reader: &mut impl Read) -> crate::Result<Vec<u8>> {
+    let mut len = [0u8; U32_LEN];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0u8; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid_key(family: &str, key: &[u8]) -> crate::Error {
+    crate::Error::InternalError(format!("Invalid {family} key in backup: {key:?}"))
+}
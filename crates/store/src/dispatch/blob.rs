@@ -21,13 +21,607 @@
  * for more details.
 */
 
-use std::ops::Range;
+use std::{
+    io::{Read, Write},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::{BlobStore, Store};
+use utils::{metrics::record_operation, worker::cpu_pool, BlobHash, BLOB_HASH_LEN};
+
+use crate::{
+    write::{now, BatchBuilder, BlobOp, ValueClass},
+    BlobBackend, BlobChunking, BlobStore, CompressionAlgo, Serialize, Store, ValueKey, U32_LEN,
+};
+
+// Stored blobs starting with a flag byte followed by the zstd magic number
+// are encoded, any other blob is read as-is.
+const FLAG_RAW: u8 = 0;
+const FLAG_ZSTD: u8 = 1;
+const FLAG_CHUNKED: u8 = 2;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const HEADER_LEN: usize = 1 + ZSTD_MAGIC.len();
+const ESCAPE_HEADER: [u8; HEADER_LEN] = [FLAG_RAW, 0x28, 0xb5, 0x2f, 0xfd];
+const CHUNK_ENTRY_LEN: usize = BLOB_HASH_LEN + U32_LEN;
+
+// Chunk reference counts are updated with conditional writes. A chunk that
+// is no longer referenced is marked as being deleted, along with the time of
+// the mark, until it is removed from the blob store and can't be referenced
+// again in the meantime. Marks left behind by an interrupted deletion expire,
+// after which the chunk is treated as absent and stored again.
+const CHUNK_DELETING: u64 = 1 << 63;
+const CHUNK_DELETING_EXPIRY: u64 = 300;
+const CHUNK_MAX_RETRIES: usize = 100;
+const CHUNK_RETRY_WAIT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobHealth {
+    Healthy,
+    Missing,
+    Corrupt,
+}
 
 impl BlobStore {
     pub async fn get_blob(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        if range.start == 0 && range.end == u32::MAX {
+            return match self.backend.get_blob(key, range.clone()).await? {
+                Some(data) if blob_flag(&data) == Some(FLAG_CHUNKED) => {
+                    self.get_chunks(&data, range).await.map(Some)
+                }
+                Some(data) => decode_blob(data).map(Some),
+                None => Ok(None),
+            };
+        }
+
+        // Obtain the blob header before reading a range, blobs written while
+        // compression or chunking were enabled remain encoded once disabled
+        let header = if let Some(header) = self.backend.get_blob(key, 0..HEADER_LEN as u32).await? {
+            header
+        } else {
+            return Ok(None);
+        };
+        match blob_flag(&header) {
+            Some(FLAG_ZSTD) => {
+                if let Some(data) = self.backend.get_blob(key, 0..u32::MAX).await? {
+                    decode_range(&data[1..], range).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+            Some(FLAG_CHUNKED) => {
+                if let Some(data) = self.backend.get_blob(key, 0..u32::MAX).await? {
+                    self.get_chunks(&data, range).await.map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+            Some(_) => {
+                self.backend
+                    .get_blob(
+                        key,
+                        range.start.saturating_add(HEADER_LEN as u32)
+                            ..range.end.saturating_add(HEADER_LEN as u32),
+                    )
+                    .await
+            }
+            None => self.backend.get_blob(key, range).await,
+        }
+    }
+
+    pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        match &self.chunking {
+            Some(chunking) if data.len() >= chunking.threshold => {
+                self.put_chunks(chunking, key, data).await
+            }
+            _ => self.put_encoded(key, &[data]).await,
+        }
+    }
+
+    /// Writes a blob made of several consecutive parts, only joining them
+    /// in memory when the blob has to be chunked.
+    pub async fn put_blob_parts(&self, key: &[u8], parts: &[&[u8]]) -> crate::Result<()> {
+        match (&self.chunking, parts) {
+            (_, [data]) => self.put_blob(key, data).await,
+            (Some(chunking), _) if blob_len(parts) >= chunking.threshold => {
+                self.put_chunks(chunking, key, &parts.concat()).await
+            }
+            _ => self.put_encoded(key, parts).await,
+        }
+    }
+
+    /// Writes a blob out of other stored blobs, reading them back one at a
+    /// time so that, when chunking is enabled, the whole blob is never held
+    /// in memory. Returns `false` if any of the parts is missing.
+    pub async fn put_blob_from_parts(
+        &self,
+        key: &[u8],
+        part_keys: &[&[u8]],
+    ) -> crate::Result<bool> {
+        if let Some(chunking) = &self.chunking {
+            let mut map = Vec::with_capacity(HEADER_LEN);
+            map.push(FLAG_CHUNKED);
+            map.extend_from_slice(&ZSTD_MAGIC);
+            for part_key in part_keys {
+                if let Some(part) = self.get_blob(part_key, 0..u32::MAX).await? {
+                    self.append_chunks(chunking, &mut map, &part).await?;
+                } else {
+                    self.release_chunks(chunking, &map).await?;
+                    return Ok(false);
+                }
+            }
+
+            self.backend.put_blob(key, &map).await?;
+        } else {
+            let mut parts = Vec::with_capacity(part_keys.len());
+            for part_key in part_keys {
+                if let Some(part) = self.get_blob(part_key, 0..u32::MAX).await? {
+                    parts.push(part);
+                } else {
+                    return Ok(false);
+                }
+            }
+
+            self.put_blob_parts(
+                key,
+                &parts.iter().map(|part| part.as_slice()).collect::<Vec<_>>(),
+            )
+            .await?;
+        }
+
+        Ok(true)
+    }
+
+    pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        // Release the chunks referenced by a chunk map
+        if let Some(chunking) = &self.chunking {
+            if matches!(
+                self.backend.get_blob(key, 0..HEADER_LEN as u32).await?,
+                Some(header) if blob_flag(&header) == Some(FLAG_CHUNKED)
+            ) {
+                if let Some(map) = self.backend.get_blob(key, 0..u32::MAX).await? {
+                    self.release_chunks(chunking, &map).await?;
+                }
+            }
+        }
+
+        self.backend.delete_blob(key).await
+    }
+
+    /// Reads a blob back and checks its contents against the hash it is
+    /// stored under. Backend errors are returned rather than reported as
+    /// corruption, so an unavailable backend never flags a healthy blob.
+    pub async fn verify_blob(&self, hash: &BlobHash) -> crate::Result<BlobHealth> {
+        let data = if let Some(data) = self.backend.get_blob(hash.as_ref(), 0..u32::MAX).await? {
+            data
+        } else {
+            return Ok(BlobHealth::Missing);
+        };
+
+        let mut hasher = blake3::Hasher::new();
+        if blob_flag(&data) == Some(FLAG_CHUNKED) {
+            let entries = if let Ok(entries) = chunk_entries(&data) {
+                entries
+            } else {
+                return Ok(BlobHealth::Corrupt);
+            };
+            for (entry_hash, len) in entries {
+                match self
+                    .backend
+                    .get_blob(entry_hash.as_ref(), 0..u32::MAX)
+                    .await?
+                    .map(decode_blob)
+                {
+                    Some(Ok(chunk))
+                        if chunk.len() == len as usize && chunk_hash(&chunk) == entry_hash =>
+                    {
+                        hasher.update(&chunk);
+                    }
+                    _ => return Ok(BlobHealth::Corrupt),
+                }
+            }
+        } else if let Ok(data) = decode_blob(data) {
+            hasher.update(&data);
+        } else {
+            return Ok(BlobHealth::Corrupt);
+        }
+
+        if hasher.finalize().as_bytes() == hash.as_slice() {
+            Ok(BlobHealth::Healthy)
+        } else {
+            Ok(BlobHealth::Corrupt)
+        }
+    }
+
+    /// Rewrites a damaged blob from a healthy copy of its contents. Chunks
+    /// are only rewritten when missing or corrupt, and an intact chunk map
+    /// is kept so that the chunk reference counts remain accurate.
+    pub async fn repair_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        match &self.chunking {
+            Some(chunking) if data.len() >= chunking.threshold => {
+                let mut map = Vec::with_capacity(HEADER_LEN);
+                map.push(FLAG_CHUNKED);
+                map.extend_from_slice(&ZSTD_MAGIC);
+                for range in chunk_boundaries(
+                    data,
+                    chunking.min_size,
+                    chunking.avg_size,
+                    chunking.max_size,
+                ) {
+                    let chunk = &data[range];
+                    let hash = chunk_hash(chunk);
+                    if !matches!(
+                        self.backend
+                            .get_blob(hash.as_ref(), 0..u32::MAX)
+                            .await?
+                            .map(decode_blob),
+                        Some(Ok(stored)) if stored == chunk
+                    ) {
+                        self.put_encoded(hash.as_ref(), &[chunk]).await?;
+                    }
+                    map.extend_from_slice(hash.as_ref());
+                    map.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+                }
+
+                // The chunks referenced by a damaged map cannot be released,
+                // write a new map holding its own references
+                if self.backend.get_blob(key, 0..u32::MAX).await?.as_deref() != Some(map.as_slice())
+                {
+                    self.put_chunks(chunking, key, data).await?;
+                }
+
+                Ok(())
+            }
+            _ => self.put_encoded(key, &[data]).await,
+        }
+    }
+
+    pub fn with_compression(mut self, compression: CompressionAlgo) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_chunking(mut self, chunking: Option<BlobChunking>) -> Self {
+        self.chunking = chunking.map(Arc::new);
+        self
+    }
+
+    async fn put_encoded(&self, key: &[u8], parts: &[&[u8]]) -> crate::Result<()> {
+        let len = blob_len(parts);
+        match self.compression {
+            CompressionAlgo::Zstd { level, min_size } if len >= min_size => {
+                let compress = || {
+                    let mut compressed = Vec::with_capacity(len / 2);
+                    compressed.push(FLAG_ZSTD);
+                    let mut encoder = zstd::stream::write::Encoder::new(compressed, level)
+                        .map_err(compression_error)?;
+                    for part in parts {
+                        encoder.write_all(part).map_err(compression_error)?;
+                    }
+                    encoder.finish().map_err(compression_error)
+                };
+                let compressed = cpu_pool().install(compress).unwrap_or_else(|f| f())?;
+                if compressed.len() < len {
+                    return self.backend.put_blob(key, &compressed).await;
+                }
+            }
+            _ => (),
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        for part in parts {
+            header.extend_from_slice(&part[..part.len().min(HEADER_LEN - header.len())]);
+            if header.len() == HEADER_LEN {
+                break;
+            }
+        }
+
+        if blob_flag(&header).is_some() {
+            // Escape raw blobs that look like encoded blobs
+            let mut escaped = Vec::with_capacity(parts.len() + 1);
+            escaped.push(&ESCAPE_HEADER[..]);
+            escaped.extend_from_slice(parts);
+            self.backend.put_blob_parts(key, &escaped).await
+        } else {
+            self.backend.put_blob_parts(key, parts).await
+        }
+    }
+
+    async fn put_chunks(
+        &self,
+        chunking: &BlobChunking,
+        key: &[u8],
+        data: &[u8],
+    ) -> crate::Result<()> {
+        let mut map = Vec::with_capacity(HEADER_LEN);
+        map.push(FLAG_CHUNKED);
+        map.extend_from_slice(&ZSTD_MAGIC);
+        self.append_chunks(chunking, &mut map, data).await?;
+
+        self.backend.put_blob(key, &map).await
+    }
+
+    async fn append_chunks(
+        &self,
+        chunking: &BlobChunking,
+        map: &mut Vec<u8>,
+        data: &[u8],
+    ) -> crate::Result<()> {
+        let chunks = chunk_boundaries(
+            data,
+            chunking.min_size,
+            chunking.avg_size,
+            chunking.max_size,
+        );
+        map.reserve(chunks.len() * CHUNK_ENTRY_LEN);
+
+        for range in chunks {
+            let chunk = &data[range];
+            let hash = chunk_hash(chunk);
+            self.acquire_chunk(chunking, &hash, chunk).await?;
+
+            map.extend_from_slice(hash.as_ref());
+            map.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Increments the reference count of a chunk, writing the chunk first
+    /// when it is not stored.
+    async fn acquire_chunk(
+        &self,
+        chunking: &BlobChunking,
+        hash: &BlobHash,
+        chunk: &[u8],
+    ) -> crate::Result<()> {
+        let class = ValueClass::Blob(BlobOp::ChunkRef { hash: hash.clone() });
+        for _ in 0..CHUNK_MAX_RETRIES {
+            let mut batch = BatchBuilder::new();
+            match chunking
+                .store
+                .get_value::<u64>(ValueKey::from(class.clone()))
+                .await?
+            {
+                Some(marker) if marker & CHUNK_DELETING != 0 => {
+                    if (marker & !CHUNK_DELETING) + CHUNK_DELETING_EXPIRY > now() {
+                        tokio::time::sleep(CHUNK_RETRY_WAIT).await;
+                        continue;
+                    }
+
+                    // The deletion was interrupted, store the chunk again
+                    self.put_encoded(hash.as_ref(), &[chunk]).await?;
+                    batch
+                        .assert_value(class.clone(), marker)
+                        .set(class.clone(), 1u64.serialize());
+                }
+                Some(refs) => {
+                    batch
+                        .assert_value(class.clone(), refs)
+                        .set(class.clone(), (refs + 1).serialize());
+                }
+                None => {
+                    self.put_encoded(hash.as_ref(), &[chunk]).await?;
+                    batch
+                        .assert_value(class.clone(), ())
+                        .set(class.clone(), 1u64.serialize());
+                }
+            }
+
+            match chunking.store.write(batch.build()).await {
+                Ok(_) => return Ok(()),
+                Err(crate::Error::AssertValueFailed) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(crate::Error::AssertValueFailed)
+    }
+
+    async fn get_chunks(&self, map: &[u8], range: Range<u32>) -> crate::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut offset = 0u32;
+        for (hash, len) in chunk_entries(map)? {
+            let chunk_range = offset..offset.saturating_add(len);
+            offset = chunk_range.end;
+            if chunk_range.end <= range.start {
+                continue;
+            } else if chunk_range.start >= range.end {
+                break;
+            }
+
+            let chunk = self
+                .backend
+                .get_blob(hash.as_ref(), 0..u32::MAX)
+                .await?
+                .ok_or_else(|| {
+                    crate::Error::InternalError(format!("Blob chunk {hash:?} not found"))
+                })
+                .and_then(decode_blob)?;
+            let from = range.start.saturating_sub(chunk_range.start) as usize;
+            let to = std::cmp::min(range.end - chunk_range.start, len) as usize;
+            data.extend_from_slice(chunk.get(from..to).unwrap_or_default());
+        }
+
+        Ok(data)
+    }
+
+    async fn release_chunks(&self, chunking: &BlobChunking, map: &[u8]) -> crate::Result<()> {
+        for (hash, _) in chunk_entries(map)? {
+            self.release_chunk(chunking, &hash).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Decrements the reference count of a chunk, deleting the chunk once
+    /// it is no longer referenced.
+    async fn release_chunk(&self, chunking: &BlobChunking, hash: &BlobHash) -> crate::Result<()> {
+        let class = ValueClass::Blob(BlobOp::ChunkRef { hash: hash.clone() });
+        for _ in 0..CHUNK_MAX_RETRIES {
+            let refs = match chunking
+                .store
+                .get_value::<u64>(ValueKey::from(class.clone()))
+                .await?
+            {
+                Some(marker) if marker & CHUNK_DELETING != 0 => return Ok(()),
+                Some(refs) => refs,
+                None => return Ok(()),
+            };
+
+            let marker = CHUNK_DELETING | now();
+            let mut batch = BatchBuilder::new();
+            batch.assert_value(class.clone(), refs).set(
+                class.clone(),
+                if refs > 1 { refs - 1 } else { marker }.serialize(),
+            );
+            match chunking.store.write(batch.build()).await {
+                Ok(_) if refs > 1 => return Ok(()),
+                Ok(_) => {
+                    self.backend.delete_blob(hash.as_ref()).await?;
+
+                    let mut batch = BatchBuilder::new();
+                    batch.assert_value(class.clone(), marker).clear(class);
+                    return chunking.store.write(batch.build()).await;
+                }
+                Err(crate::Error::AssertValueFailed) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(crate::Error::AssertValueFailed)
+    }
+}
+
+fn blob_len(parts: &[&[u8]]) -> usize {
+    parts.iter().map(|part| part.len()).sum()
+}
+
+fn blob_flag(data: &[u8]) -> Option<u8> {
+    match data.get(0..HEADER_LEN) {
+        Some([flag @ (FLAG_RAW | FLAG_ZSTD | FLAG_CHUNKED), magic @ ..]) if magic == ZSTD_MAGIC => {
+            Some(*flag)
+        }
+        _ => None,
+    }
+}
+
+fn decode_blob(data: Vec<u8>) -> crate::Result<Vec<u8>> {
+    match blob_flag(&data) {
+        Some(FLAG_ZSTD) => zstd::stream::decode_all(&data[1..]).map_err(compression_error),
+        Some(FLAG_RAW) => Ok(data[HEADER_LEN..].to_vec()),
+        _ => Ok(data),
+    }
+}
+
+fn decode_range(data: &[u8], range: Range<u32>) -> crate::Result<Vec<u8>> {
+    let mut decoder = zstd::stream::read::Decoder::new(data).map_err(compression_error)?;
+    std::io::copy(
+        &mut (&mut decoder).take(range.start as u64),
+        &mut std::io::sink(),
+    )
+    .map_err(compression_error)?;
+    let mut bytes = Vec::new();
+    decoder
+        .take(range.end.saturating_sub(range.start) as u64)
+        .read_to_end(&mut bytes)
+        .map_err(compression_error)?;
+    Ok(bytes)
+}
+
+fn compression_error(err: std::io::Error) -> crate::Error {
+    crate::Error::InternalError(format!("Blob compression error: {err}"))
+}
+
+// Chunks are stored under a hash derived from their contents, kept apart
+// from regular blob hashes so that deleting a blob never removes a chunk.
+pub fn chunk_hash(chunk: &[u8]) -> BlobHash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"chunk");
+    hasher.update(chunk);
+    BlobHash::try_from_hash_slice(hasher.finalize().as_bytes()).unwrap()
+}
+
+fn chunk_entries(map: &[u8]) -> crate::Result<Vec<(BlobHash, u32)>> {
+    let entries = map.get(HEADER_LEN..).unwrap_or_default();
+    if entries.len() % CHUNK_ENTRY_LEN != 0 {
+        return Err(crate::Error::InternalError(
+            "Invalid blob chunk map".to_string(),
+        ));
+    }
+
+    Ok(entries
+        .chunks_exact(CHUNK_ENTRY_LEN)
+        .map(|entry| {
+            (
+                BlobHash::try_from_hash_slice(&entry[..BLOB_HASH_LEN]).unwrap(),
+                u32::from_be_bytes(entry[BLOB_HASH_LEN..].try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+/// Splits data into content-defined chunks using a gear rolling hash, so that
+/// identical content produces identical chunks regardless of its offset.
+pub fn chunk_boundaries(
+    data: &[u8],
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+) -> Vec<Range<usize>> {
+    let mask = (avg_size.max(2).next_power_of_two() - 1) as u64;
+    let mut chunks = Vec::with_capacity(data.len() / avg_size.max(1) + 1);
+    let mut start = 0;
+
+    while start < data.len() {
+        let end = std::cmp::min(start + max_size, data.len());
+        let mut pos = std::cmp::min(start + min_size, end);
+        let mut hash = 0u64;
+        while pos < end {
+            hash = (hash << 1).wrapping_add(GEAR[data[pos] as usize]);
+            pos += 1;
+            if hash & mask == 0 {
+                break;
+            }
+        }
+        chunks.push(start..pos);
+        start = pos;
+    }
+
+    chunks
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed = 0x9e3779b97f4a7c15u64;
+    let mut i = 0;
+    while i < 256 {
+        // SplitMix64
+        seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+impl BlobBackend {
+    pub fn backend_name(&self) -> &'static str {
         match self {
+            Self::Store(store) => store.backend_name(),
+            Self::Fs(_) => "fs",
+            #[cfg(feature = "s3")]
+            Self::S3(_) => "s3",
+        }
+    }
+
+    async fn get_blob(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        let result = match self {
             Self::Store(store) => match store {
                 #[cfg(feature = "sqlite")]
                 Store::SQLite(store) => store.get_blob(key, range).await,
@@ -43,11 +637,15 @@ impl BlobStore {
             Self::Fs(store) => store.get_blob(key, range).await,
             #[cfg(feature = "s3")]
             Self::S3(store) => store.get_blob(key, range).await,
-        }
+        };
+        record_operation(self.backend_name(), "get_blob", started.elapsed());
+
+        result
     }
 
-    pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self {
+    async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        let started = Instant::now();
+        let result = match self {
             Self::Store(store) => match store {
                 #[cfg(feature = "sqlite")]
                 Store::SQLite(store) => store.put_blob(key, data).await,
@@ -63,11 +661,29 @@ impl BlobStore {
             Self::Fs(store) => store.put_blob(key, data).await,
             #[cfg(feature = "s3")]
             Self::S3(store) => store.put_blob(key, data).await,
+        };
+        record_operation(self.backend_name(), "put_blob", started.elapsed());
+
+        result
+    }
+
+    async fn put_blob_parts(&self, key: &[u8], parts: &[&[u8]]) -> crate::Result<()> {
+        match (self, parts) {
+            (_, [data]) => self.put_blob(key, data).await,
+            (Self::Fs(store), _) => {
+                let started = Instant::now();
+                let result = store.put_blob_parts(key, parts).await;
+                record_operation(self.backend_name(), "put_blob", started.elapsed());
+
+                result
+            }
+            _ => self.put_blob(key, &parts.concat()).await,
         }
     }
 
-    pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        match self {
+    async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
+        let started = Instant::now();
+        let result = match self {
             Self::Store(store) => match store {
                 #[cfg(feature = "sqlite")]
                 Store::SQLite(store) => store.delete_blob(key).await,
@@ -83,6 +699,9 @@ impl BlobStore {
             Self::Fs(store) => store.delete_blob(key).await,
             #[cfg(feature = "s3")]
             Self::S3(store) => store.delete_blob(key).await,
-        }
+        };
+        record_operation(self.backend_name(), "delete_blob", started.elapsed());
+
+        result
     }
 }
@@ -28,14 +28,18 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use ahash::AHashSet;
 use arc_swap::{ArcSwap, ArcSwapOption};
 use parking_lot::RwLock;
-use utils::config::{ipmask::IpAddrMask, utils::ParseKey, Config, ConfigKey, Rate};
+use tokio::sync::broadcast;
+use utils::config::{
+    ipmask::IpAddrMask, reload::ReloadableConfig, utils::ParseKey, Config, ConfigKey, Rate,
+};
 
-use crate::LookupStore;
+use crate::{write::now, LookupStore};
 
 pub struct BlockedIps {
     ip_addresses: RwLock<AHashSet<IpAddr>>,
@@ -43,10 +47,27 @@ pub struct BlockedIps {
     has_networks: AtomicBool,
     store: LookupStore,
     limiter_rate: ArcSwapOption<Rate>,
+    lockout: ArcSwapOption<AccountLockout>,
+    http_client: ArcSwap<reqwest::Client>,
+    bans: broadcast::Sender<IpAddr>,
+}
+
+#[derive(Debug)]
+pub struct AccountLockout {
+    pub rate: Rate,
+    pub duration: Duration,
+    pub notify_url: Option<String>,
+    pub notify_timeout: Duration,
 }
 
 pub const BLOCKED_IP_KEY: &str = "server.security.blocked-networks";
 
+// Logins are matched case-insensitively, so that variants of the same login
+// share their failure counter and lock.
+fn lockout_key(prefix: char, login: &str) -> String {
+    format!("{prefix}:{}", login.trim().to_lowercase())
+}
+
 impl BlockedIps {
     pub fn new(store: LookupStore) -> Self {
         Self {
@@ -54,19 +75,13 @@ impl BlockedIps {
             ip_networks: ArcSwap::new(Arc::new(Vec::new())),
             has_networks: AtomicBool::new(false),
             limiter_rate: ArcSwapOption::empty(),
+            lockout: ArcSwapOption::empty(),
+            http_client: ArcSwap::from_pointee(reqwest::Client::new()),
+            bans: broadcast::channel(64).0,
             store,
         }
     }
 
-    pub fn reload(&self, config: &Config) -> utils::config::Result<()> {
-        self.limiter_rate.store(
-            config
-                .property::<Rate>("server.security.fail2ban")?
-                .map(Arc::new),
-        );
-        self.reload_blocked_ips(config)
-    }
-
     pub fn reload_blocked_ips(&self, config: &Config) -> utils::config::Result<()> {
         let mut ip_addresses = AHashSet::new();
         let mut ip_networks = Vec::new();
@@ -102,21 +117,157 @@ impl BlockedIps {
                     .map(|v| v.is_none())
                     .unwrap_or(false);
             if !is_allowed {
-                self.ip_addresses.write().insert(ip);
-                return Some(ConfigKey {
-                    key: format!("{}.{}", BLOCKED_IP_KEY, ip),
-                    value: String::new(),
-                });
+                return Some(self.block_ip(ip));
             }
         }
 
         None
     }
 
+    /// Blocks an IP address, returning the configuration key that
+    /// persists the block.
+    pub fn block_ip(&self, ip: IpAddr) -> ConfigKey {
+        self.add_blocked_ip(ip);
+        let _ = self.bans.send(ip);
+        ConfigKey {
+            key: format!("{}.{}", BLOCKED_IP_KEY, ip),
+            value: String::new(),
+        }
+    }
+
+    /// Blocks an IP address banned by another node, without announcing it.
+    pub fn add_blocked_ip(&self, ip: IpAddr) {
+        self.ip_addresses.write().insert(ip);
+    }
+
+    /// Returns a receiver for the IP addresses banned locally from now on.
+    pub fn subscribe_bans(&self) -> broadcast::Receiver<IpAddr> {
+        self.bans.subscribe()
+    }
+
     pub fn has_fail2ban(&self) -> bool {
         self.limiter_rate.load().is_some()
     }
 
+    pub fn has_lockout(&self) -> bool {
+        self.lockout.load().is_some()
+    }
+
+    pub async fn is_account_locked(&self, login: &str) -> bool {
+        self.lockout.load().is_some()
+            && self
+                .store
+                .key_exists(lockout_key('l', login).into_bytes())
+                .await
+                .unwrap_or(false)
+    }
+
+    /// Records a failed login attempt for an account, regardless of the
+    /// remote address. Returns the lockout duration when the account has
+    /// exceeded the allowed number of failures and is now locked.
+    pub async fn register_login_failure(&self, login: &str) -> Option<Duration> {
+        let lockout = self.lockout.load_full()?;
+        let is_allowed = self
+            .store
+            .is_rate_allowed(lockout_key('f', login).as_bytes(), &lockout.rate, false)
+            .await
+            .map(|v| v.is_none())
+            .unwrap_or(true);
+        if !is_allowed {
+            match self
+                .store
+                .key_set(
+                    lockout_key('l', login).into_bytes(),
+                    vec![],
+                    lockout.duration.as_secs().into(),
+                )
+                .await
+            {
+                Ok(_) => Some(lockout.duration),
+                Err(err) => {
+                    tracing::error!(
+                        context = "lockout",
+                        event = "error",
+                        login = login,
+                        reason = ?err,
+                        "Failed to lock account."
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Lifts an account lockout and resets its failed login counter.
+    /// Returns whether the account was locked.
+    pub async fn unlock_account(&self, login: &str) -> crate::Result<bool> {
+        let lock_key = lockout_key('l', login).into_bytes();
+        let was_locked = self.store.key_exists(lock_key.clone()).await?;
+        self.store.key_delete(lock_key).await?;
+
+        if let Some(lockout) = self.lockout.load().as_ref() {
+            self.store
+                .reset_rate(lockout_key('f', login).as_bytes(), &lockout.rate)
+                .await?;
+        }
+
+        Ok(was_locked)
+    }
+
+    /// Sets the HTTP client used to send lockout notifications, so that the
+    /// server wide proxy and TLS settings apply.
+    pub fn set_http_client(&self, client: reqwest::Client) {
+        self.http_client.store(Arc::new(client));
+    }
+
+    /// Notifies the configured webhook that an account has been locked,
+    /// including the secondary addresses the user can be reached at.
+    pub fn notify_lockout(&self, login: &str, addresses: Vec<String>) {
+        let lockout = if let Some(lockout) = self.lockout.load_full() {
+            lockout
+        } else {
+            return;
+        };
+        let url = if let Some(url) = &lockout.notify_url {
+            url.clone()
+        } else {
+            return;
+        };
+        let login = login.to_string();
+        let client = self.http_client.load_full();
+
+        tokio::spawn(async move {
+            let locked_until = (now() + lockout.duration.as_secs()).to_string();
+            let addresses = addresses.join(",");
+            let result = client
+                .post(&url)
+                .timeout(lockout.notify_timeout)
+                .form(&[
+                    ("event", "account-locked"),
+                    ("login", login.as_str()),
+                    ("locked-until", locked_until.as_str()),
+                    ("addresses", addresses.as_str()),
+                ])
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ());
+
+            if let Err(err) = result {
+                tracing::warn!(
+                    context = "lockout",
+                    event = "error",
+                    login = login.as_str(),
+                    url = url.as_str(),
+                    reason = %err,
+                    "Failed to send account lockout notification."
+                );
+            }
+        });
+    }
+
     pub fn is_blocked(&self, ip: &IpAddr) -> bool {
         self.ip_addresses.read().contains(ip)
             || (self.has_networks.load(Ordering::Relaxed)
@@ -128,12 +279,39 @@ impl BlockedIps {
     }
 }
 
+impl ReloadableConfig for BlockedIps {
+    fn reload(&self, config: &Config) -> utils::config::Result<()> {
+        let limiter_rate = config
+            .property::<Rate>("server.security.fail2ban")?
+            .map(Arc::new);
+        let lockout = if let Some(rate) = config.property::<Rate>("server.security.lockout.rate")? {
+            Some(Arc::new(AccountLockout {
+                rate,
+                duration: config.property_or_static("server.security.lockout.duration", "1h")?,
+                notify_url: config
+                    .value("server.security.lockout.notify.url")
+                    .map(|url| url.to_string()),
+                notify_timeout: config
+                    .property_or_static("server.security.lockout.notify.timeout", "10s")?,
+            }))
+        } else {
+            None
+        };
+        self.reload_blocked_ips(config)?;
+        self.limiter_rate.store(limiter_rate);
+        self.lockout.store(lockout);
+
+        Ok(())
+    }
+}
+
 impl Debug for BlockedIps {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BlockedIps")
             .field("ip_addresses", &self.ip_addresses)
             .field("ip_networks", &self.ip_networks)
             .field("limiter_rate", &self.limiter_rate)
+            .field("lockout", &self.lockout)
             .finish()
     }
 }
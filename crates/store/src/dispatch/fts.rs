@@ -31,7 +31,7 @@ use crate::{
 };
 
 impl FtsStore {
-    pub async fn index<T: Into<u8> + Display + Clone + std::fmt::Debug>(
+    pub async fn index<T: Into<u8> + Display + Clone + std::fmt::Debug + Send>(
         &self,
         document: FtsDocument<'_, T>,
     ) -> crate::Result<()> {
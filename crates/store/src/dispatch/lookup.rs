@@ -27,6 +27,7 @@ use crate::{backend::memory::MemoryStore, write::LookupClass, Row};
 #[allow(unused_imports)]
 use crate::{
     write::{
+        assert::{AssertValue, HashedValue},
         key::{DeserializeBigEndian, KeySerializer},
         now, BatchBuilder, Operation, ValueClass, ValueOp,
     },
@@ -79,7 +80,12 @@ impl LookupStore {
                 store.write(batch.build()).await
             }
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_set(key, value, expires).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_set(key, value, expires), || store.unavailable())
+                    .await
+            }
             LookupStore::Query(lookup) => lookup
                 .store
                 .query::<usize>(
@@ -94,6 +100,56 @@ impl LookupStore {
         }
     }
 
+    /// Atomically sets a key if it does not exist or has expired, returning
+    /// `true` when the key was claimed by this call.
+    pub async fn key_claim(&self, key: Vec<u8>, expires: u64) -> crate::Result<bool> {
+        match self {
+            LookupStore::Store(store) => {
+                let class = ValueClass::Lookup(LookupClass::Key(key));
+                let assert_value = match store
+                    .get_value::<HashedValue<LookupValue<()>>>(ValueKey::from(class.clone()))
+                    .await?
+                {
+                    Some(HashedValue {
+                        inner: LookupValue::Value(_),
+                        ..
+                    }) => return Ok(false),
+                    Some(HashedValue { hash, .. }) => AssertValue::Hash(hash),
+                    None => AssertValue::None,
+                };
+
+                let mut batch = BatchBuilder::new();
+                batch.ops.push(Operation::AssertValue {
+                    class: class.clone(),
+                    assert_value,
+                });
+                batch.ops.push(Operation::Value {
+                    class,
+                    op: ValueOp::Set(
+                        KeySerializer::new(U64_LEN)
+                            .write(now() + expires)
+                            .finalize(),
+                    ),
+                });
+                match store.write(batch.build()).await {
+                    Ok(_) => Ok(true),
+                    Err(crate::Error::AssertValueFailed) => Ok(false),
+                    Err(err) => Err(err),
+                }
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_claim(key, expires), || store.unavailable())
+                    .await
+            }
+            LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
+                "This store does not support key_claim".into(),
+            )),
+        }
+    }
+
     pub async fn counter_incr(
         &self,
         key: Vec<u8>,
@@ -125,7 +181,12 @@ impl LookupStore {
                 Ok(0)
             }
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_incr(key, value, expires).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_incr(key, value, expires), || store.unavailable())
+                    .await
+            }
             LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
                 "This store does not support counter_incr".into(),
             )),
@@ -143,7 +204,12 @@ impl LookupStore {
                 store.write(batch.build()).await
             }
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_delete(key).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_delete(key), || store.unavailable())
+                    .await
+            }
             LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
                 "This store does not support key_set".into(),
             )),
@@ -161,7 +227,12 @@ impl LookupStore {
                 store.write(batch.build()).await
             }
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_delete(key).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_delete(key), || store.unavailable())
+                    .await
+            }
             LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
                 "This store does not support key_set".into(),
             )),
@@ -180,7 +251,12 @@ impl LookupStore {
                 .await
                 .map(|value| value.and_then(|v| v.into())),
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_get(key).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_get(key), || store.unavailable())
+                    .await
+            }
             LookupStore::Memory(store) => {
                 let key = String::from_utf8(key).unwrap_or_default();
                 match store.as_ref() {
@@ -218,7 +294,12 @@ impl LookupStore {
                     .await
             }
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.counter_get(key).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.counter_get(key), || store.unavailable())
+                    .await
+            }
             LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
                 "This store does not support counter_get".into(),
             )),
@@ -234,7 +315,12 @@ impl LookupStore {
                 .await
                 .map(|value| matches!(value, Some(LookupValue::Value(())))),
             #[cfg(feature = "redis")]
-            LookupStore::Redis(store) => store.key_exists(key).await,
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.key_exists(key), || store.unavailable())
+                    .await
+            }
             LookupStore::Memory(store) => {
                 let key = String::from_utf8(key).unwrap_or_default();
                 match store.as_ref() {
@@ -269,16 +355,7 @@ impl LookupStore {
         bucket.extend_from_slice(range_start.to_be_bytes().as_slice());
 
         let requests = if !soft_check {
-            let requests = self.counter_incr(bucket, 1, expires_in.into()).await?;
-            if requests > 0 {
-                requests
-            } else {
-                // Increment and get not supported by store, fetch counter
-                let mut bucket = Vec::with_capacity(key.len() + U64_LEN);
-                bucket.extend_from_slice(key);
-                bucket.extend_from_slice(range_start.to_be_bytes().as_slice());
-                self.counter_get(bucket).await?
-            }
+            self.rate_usage(key, rate, 1).await?.0 as i64
         } else {
             self.counter_get(bucket).await? + 1
         };
@@ -290,6 +367,45 @@ impl LookupStore {
         }
     }
 
+    /// Adds a number of requests to a rate limit bucket. Returns the requests
+    /// made during the current period and the seconds left until it ends.
+    pub async fn rate_usage(
+        &self,
+        key: &[u8],
+        rate: &Rate,
+        requests: u64,
+    ) -> crate::Result<(u64, u64)> {
+        let now = now();
+        let range_start = now / rate.period.as_secs();
+        let range_end = (range_start * rate.period.as_secs()) + rate.period.as_secs();
+        let expires_in = range_end - now;
+
+        let mut bucket = Vec::with_capacity(key.len() + U64_LEN);
+        bucket.extend_from_slice(key);
+        bucket.extend_from_slice(range_start.to_be_bytes().as_slice());
+
+        let total = self
+            .counter_incr(bucket.clone(), requests as i64, expires_in.into())
+            .await?;
+        let total = if total > 0 {
+            total
+        } else {
+            // Increment and get not supported by store, fetch counter
+            self.counter_get(bucket).await?
+        };
+
+        Ok((total.max(0) as u64, expires_in))
+    }
+
+    /// Clears the requests made to a rate limit bucket during the current period.
+    pub async fn reset_rate(&self, key: &[u8], rate: &Rate) -> crate::Result<()> {
+        let mut bucket = Vec::with_capacity(key.len() + U64_LEN);
+        bucket.extend_from_slice(key);
+        bucket.extend_from_slice((now() / rate.period.as_secs()).to_be_bytes().as_slice());
+
+        self.counter_delete(bucket).await
+    }
+
     pub async fn purge_expired(&self) -> crate::Result<()> {
         match self {
             LookupStore::Store(store) => {
@@ -371,6 +487,44 @@ impl LookupStore {
 
         Ok(())
     }
+
+    pub fn supports_pubsub(&self) -> bool {
+        match self {
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(_) => true,
+            _ => false,
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub async fn publish(&self, channel: &str, message: Vec<u8>) -> crate::Result<()> {
+        match self {
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => {
+                store
+                    .circuit_breaker
+                    .call(store.publish(channel, message), || store.unavailable())
+                    .await
+            }
+            _ => Err(crate::Error::InternalError(
+                "This store does not support publish".into(),
+            )),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub async fn subscribe(
+        &self,
+        channel: &str,
+    ) -> crate::Result<tokio::sync::mpsc::Receiver<Vec<u8>>> {
+        match self {
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.subscribe(channel).await,
+            _ => Err(crate::Error::InternalError(
+                "This store does not support subscribe".into(),
+            )),
+        }
+    }
 }
 
 enum LookupValue<T> {
@@ -21,6 +21,7 @@
  * for more details.
 */
 
+pub mod backup;
 pub mod blob;
 pub mod blocked;
 pub mod config;
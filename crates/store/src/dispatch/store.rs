@@ -21,16 +21,25 @@
  * for more details.
 */
 
-use std::ops::{BitAndAssign, Range};
+use std::{
+    ops::{BitAndAssign, Range},
+    time::{Duration, Instant},
+};
 
 use roaring::RoaringBitmap;
+use utils::{circuit_breaker::CircuitBreaker, metrics::record_operation};
 
 use crate::{
-    write::{key::KeySerializer, AnyKey, Batch, BitmapClass, ValueClass},
+    write::{
+        key::{DeserializeBigEndian, KeySerializer},
+        AnyKey, Batch, BitmapClass, ValueClass,
+    },
     BitmapKey, Deserialize, IterateParams, Key, Store, ValueKey, SUBSPACE_BITMAPS,
     SUBSPACE_INDEXES, SUBSPACE_LOGS, U32_LEN,
 };
 
+pub const DELETE_CHUNK_SIZE: usize = 1000;
+
 #[cfg(feature = "test_mode")]
 lazy_static::lazy_static! {
 pub static ref BITMAPS: std::sync::Arc<parking_lot::Mutex<std::collections::HashMap<Vec<u8>, std::collections::HashSet<u32>>>> =
@@ -42,7 +51,9 @@ impl Store {
     where
         U: Deserialize + 'static,
     {
-        match self {
+        self.assert_available()?;
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.get_value(key).await,
             #[cfg(feature = "foundation")]
@@ -53,7 +64,10 @@ impl Store {
             Self::MySQL(store) => store.get_value(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_value(key).await,
-        }
+        };
+        record_operation(self.backend_name(), "get_value", started.elapsed());
+
+        self.track_availability(result)
     }
 
     pub async fn get_values<U>(&self, key: Vec<impl Key>) -> crate::Result<Vec<Option<U>>>
@@ -73,7 +87,9 @@ impl Store {
         &self,
         key: BitmapKey<BitmapClass>,
     ) -> crate::Result<Option<RoaringBitmap>> {
-        match self {
+        self.assert_available()?;
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.get_bitmap(key).await,
             #[cfg(feature = "foundation")]
@@ -84,7 +100,10 @@ impl Store {
             Self::MySQL(store) => store.get_bitmap(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_bitmap(key).await,
-        }
+        };
+        record_operation(self.backend_name(), "get_bitmap", started.elapsed());
+
+        self.track_availability(result)
     }
 
     pub async fn get_bitmaps_intersection(
@@ -114,7 +133,9 @@ impl Store {
         params: IterateParams<T>,
         cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
     ) -> crate::Result<()> {
-        match self {
+        self.assert_available()?;
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.iterate(params, cb).await,
             #[cfg(feature = "foundation")]
@@ -125,14 +146,79 @@ impl Store {
             Self::MySQL(store) => store.iterate(params, cb).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.iterate(params, cb).await,
+        };
+        record_operation(self.backend_name(), "iterate", started.elapsed());
+
+        self.track_availability(result)
+    }
+
+    /// Iterates over a single page of at most `page_size` keys, resuming after
+    /// `cursor` when provided. Returns the cursor of the next page, or `None`
+    /// once the range has been exhausted or the callback requested to stop.
+    pub async fn iterate_paged<T: Key>(
+        &self,
+        params: IterateParams<T>,
+        cursor: Option<&[u8]>,
+        page_size: usize,
+        mut cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        let subspace = params.begin.subspace();
+        let mut begin = params.begin.serialize(0);
+        let mut end = params.end.serialize(0);
+
+        // Resume from the cursor, which is returned again and skipped below
+        if let Some(cursor) = cursor {
+            if params.ascending {
+                if cursor > begin.as_slice() {
+                    begin = cursor.to_vec();
+                }
+            } else if cursor < end.as_slice() {
+                end = cursor.to_vec();
+            }
         }
+
+        let mut count = 0;
+        let mut next_cursor = None;
+        self.iterate(
+            IterateParams {
+                begin: AnyKey {
+                    subspace,
+                    key: begin,
+                },
+                end: AnyKey { subspace, key: end },
+                first: false,
+                ascending: params.ascending,
+                values: params.values,
+                limit: page_size + usize::from(cursor.is_some()),
+            },
+            |key, value| {
+                if cursor.map_or(false, |cursor| cursor == key) {
+                    return Ok(true);
+                }
+                count += 1;
+                if !cb(key, value)? {
+                    return Ok(false);
+                }
+                if count < page_size {
+                    Ok(true)
+                } else {
+                    next_cursor = key.to_vec().into();
+                    Ok(false)
+                }
+            },
+        )
+        .await?;
+
+        Ok(next_cursor)
     }
 
     pub async fn get_counter(
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
     ) -> crate::Result<i64> {
-        match self {
+        self.assert_available()?;
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.get_counter(key).await,
             #[cfg(feature = "foundation")]
@@ -143,7 +229,10 @@ impl Store {
             Self::MySQL(store) => store.get_counter(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_counter(key).await,
-        }
+        };
+        record_operation(self.backend_name(), "get_counter", started.elapsed());
+
+        self.track_availability(result)
     }
 
     pub async fn write(&self, batch: Batch) -> crate::Result<()> {
@@ -227,7 +316,9 @@ impl Store {
             return Ok(());
         }
 
-        match self {
+        self.assert_available()?;
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.write(batch).await,
             #[cfg(feature = "foundation")]
@@ -238,7 +329,10 @@ impl Store {
             Self::MySQL(store) => store.write(batch).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.write(batch).await,
-        }
+        };
+        record_operation(self.backend_name(), "write", started.elapsed());
+
+        self.track_availability(result)
     }
 
     pub async fn purge_bitmaps(&self) -> crate::Result<()> {
@@ -271,9 +365,164 @@ impl Store {
         }
     }
 
+    /// Deletes a key range in chunks of at most `chunk_size` keys, each one in its
+    /// own transaction, running up to `concurrency` chunk deletions at a time.
+    /// The progress callback receives the total number of keys deleted so far.
+    pub async fn delete_range_chunked(
+        &self,
+        from: impl Key,
+        to: impl Key,
+        chunk_size: usize,
+        concurrency: usize,
+        mut progress: impl FnMut(usize) + Send,
+    ) -> crate::Result<usize> {
+        let subspace = from.subspace();
+        let begin = from.serialize(0);
+        let end = to.serialize(0);
+        let chunk_size = chunk_size.max(1);
+        let concurrency = concurrency.max(1);
+        let mut cursor: Option<Vec<u8>> = None;
+        let mut total_deleted = 0;
+
+        loop {
+            // Obtain the boundaries of the next chunks
+            let mut chunks = Vec::with_capacity(concurrency);
+            let mut is_done = false;
+            while chunks.len() < concurrency {
+                let mut first_key = None;
+                let mut last_key = Vec::new();
+                let mut num_keys = 0;
+                cursor = self
+                    .iterate_paged(
+                        IterateParams::new(
+                            AnyKey {
+                                subspace,
+                                key: begin.as_slice(),
+                            },
+                            AnyKey {
+                                subspace,
+                                key: end.as_slice(),
+                            },
+                        )
+                        .no_values(),
+                        cursor.as_deref(),
+                        chunk_size,
+                        |key, _| {
+                            if key < end.as_slice() {
+                                if first_key.is_none() {
+                                    first_key = key.to_vec().into();
+                                }
+                                last_key = key.to_vec();
+                                num_keys += 1;
+                            }
+                            Ok(true)
+                        },
+                    )
+                    .await?;
+
+                if let Some(first_key) = first_key {
+                    // Upper bound is exclusive, delete up to the key following the last one
+                    last_key.push(0);
+                    if last_key.as_slice() > end.as_slice() {
+                        last_key = end.clone();
+                    }
+                    chunks.push((first_key, last_key, num_keys));
+                }
+                if cursor.is_none() {
+                    is_done = true;
+                    break;
+                }
+            }
+
+            // Delete chunks
+            let num_keys = chunks
+                .iter()
+                .map(|(_, _, num_keys)| num_keys)
+                .sum::<usize>();
+            futures::future::try_join_all(chunks.into_iter().map(|(from, to, _)| {
+                self.delete_range(
+                    AnyKey {
+                        subspace,
+                        key: from,
+                    },
+                    AnyKey { subspace, key: to },
+                )
+            }))
+            .await?;
+            if num_keys > 0 {
+                total_deleted += num_keys;
+                progress(total_deleted);
+            }
+
+            if is_done {
+                break;
+            }
+        }
+
+        Ok(total_deleted)
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(_) => "sqlite",
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(_) => "foundationdb",
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(_) => "postgresql",
+            #[cfg(feature = "mysql")]
+            Self::MySQL(_) => "mysql",
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(_) => "rocksdb",
+        }
+    }
+
+    /// Returns the circuit breaker of backends reached over the network.
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => Some(&store.circuit_breaker),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => Some(&store.circuit_breaker),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn assert_available(&self) -> crate::Result<()> {
+        match self.circuit_breaker() {
+            Some(breaker) if breaker.is_open() => Err(crate::Error::InternalError(format!(
+                "Store {:?} is temporarily unavailable",
+                breaker.name()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn track_availability<T>(&self, result: crate::Result<T>) -> crate::Result<T> {
+        if let Some(breaker) = self.circuit_breaker() {
+            // Failed assertions are a successful roundtrip to the backend
+            match &result {
+                Ok(_) | Err(crate::Error::AssertValueFailed) => breaker.success(),
+                Err(_) => breaker.failure(),
+            }
+        }
+        result
+    }
+
+    pub fn delete_range_concurrency(&self) -> usize {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(_) => 1,
+            #[allow(unreachable_patterns)]
+            _ => 4,
+        }
+    }
+
     pub async fn purge_account(&self, account_id: u32) -> crate::Result<()> {
+        let concurrency = self.delete_range_concurrency();
         for subspace in [SUBSPACE_BITMAPS, SUBSPACE_LOGS, SUBSPACE_INDEXES] {
-            self.delete_range(
+            self.delete_range_chunked(
                 AnyKey {
                     subspace,
                     key: KeySerializer::new(U32_LEN).write(account_id).finalize(),
@@ -282,6 +531,9 @@ impl Store {
                     subspace,
                     key: KeySerializer::new(U32_LEN).write(account_id + 1).finalize(),
                 },
+                DELETE_CHUNK_SIZE,
+                concurrency,
+                |_| (),
             )
             .await?;
         }
@@ -291,8 +543,21 @@ impl Store {
             (ValueClass::ReservedId, ValueClass::ReservedId),
             (ValueClass::Property(0), ValueClass::Property(0)),
             (ValueClass::TermIndex, ValueClass::TermIndex),
+            (
+                ValueClass::MailboxCounter {
+                    mailbox_id: 0,
+                    counter: 0,
+                },
+                ValueClass::MailboxCounter {
+                    mailbox_id: 0,
+                    counter: 0,
+                },
+            ),
+            (ValueClass::ChangesCompacted, ValueClass::ChangesCompacted),
+            (ValueClass::AccountPlacement, ValueClass::AccountPlacement),
+            (ValueClass::UploadSession(0), ValueClass::UploadSession(0)),
         ] {
-            self.delete_range(
+            self.delete_range_chunked(
                 ValueKey {
                     account_id,
                     collection: 0,
@@ -305,6 +570,9 @@ impl Store {
                     document_id: 0,
                     class: to_class,
                 },
+                DELETE_CHUNK_SIZE,
+                concurrency,
+                |_| (),
             )
             .await?;
         }
@@ -312,8 +580,141 @@ impl Store {
         Ok(())
     }
 
+    /// Returns the distinct account ids owning keys in the bitmap and index subspaces.
+    pub async fn list_indexed_accounts(&self) -> crate::Result<Vec<u32>> {
+        let mut account_ids = Vec::new();
+        for subspace in [SUBSPACE_BITMAPS, SUBSPACE_INDEXES] {
+            let mut next_account_id = 0u32;
+            loop {
+                let mut account_id = None;
+                self.iterate(
+                    IterateParams::new(
+                        AnyKey {
+                            subspace,
+                            key: KeySerializer::new(U32_LEN)
+                                .write(next_account_id)
+                                .finalize(),
+                        },
+                        AnyKey {
+                            subspace,
+                            key: vec![u8::MAX; U32_LEN + 1],
+                        },
+                    )
+                    .no_values()
+                    .only_first(),
+                    |key, _| {
+                        account_id = key.deserialize_be_u32(0)?.into();
+                        Ok(false)
+                    },
+                )
+                .await?;
+
+                match account_id {
+                    Some(account_id) => {
+                        if !account_ids.contains(&account_id) {
+                            account_ids.push(account_id);
+                        }
+                        if account_id == u32::MAX {
+                            break;
+                        }
+                        next_account_id = account_id + 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        account_ids.sort_unstable();
+        Ok(account_ids)
+    }
+
+    /// Deletes the bitmap and index keys of an account in batches of at most
+    /// `batch_size` keys, pausing between batches. Returns the number of keys
+    /// and bytes reclaimed.
+    pub async fn purge_account_indexes(
+        &self,
+        account_id: u32,
+        batch_size: usize,
+        pause: Duration,
+    ) -> crate::Result<(usize, usize)> {
+        let batch_size = batch_size.max(1);
+        let mut total_keys = 0;
+        let mut total_bytes = 0;
+
+        for subspace in [SUBSPACE_BITMAPS, SUBSPACE_INDEXES] {
+            let begin = KeySerializer::new(U32_LEN).write(account_id).finalize();
+            let end = KeySerializer::new(U32_LEN)
+                .write(account_id.wrapping_add(1))
+                .finalize();
+            let end = if account_id != u32::MAX {
+                end
+            } else {
+                vec![u8::MAX; U32_LEN + 1]
+            };
+
+            loop {
+                let mut first_key = None;
+                let mut last_key = Vec::new();
+                let mut num_keys = 0;
+                self.iterate(
+                    IterateParams::new(
+                        AnyKey {
+                            subspace,
+                            key: begin.as_slice(),
+                        },
+                        AnyKey {
+                            subspace,
+                            key: end.as_slice(),
+                        },
+                    )
+                    .with_limit(batch_size),
+                    |key, value| {
+                        if key >= end.as_slice() {
+                            return Ok(false);
+                        }
+                        if first_key.is_none() {
+                            first_key = key.to_vec().into();
+                        }
+                        last_key = key.to_vec();
+                        num_keys += 1;
+                        total_bytes += key.len() + value.len();
+                        Ok(num_keys < batch_size)
+                    },
+                )
+                .await?;
+
+                if let Some(first_key) = first_key {
+                    last_key.push(0);
+                    self.delete_range(
+                        AnyKey {
+                            subspace,
+                            key: first_key,
+                        },
+                        AnyKey {
+                            subspace,
+                            key: last_key,
+                        },
+                    )
+                    .await?;
+                    total_keys += num_keys;
+                    if num_keys < batch_size {
+                        break;
+                    }
+                    if !pause.is_zero() {
+                        tokio::time::sleep(pause).await;
+                    }
+                } else {
+                    break;
+                }
+            }
+        }
+
+        Ok((total_keys, total_bytes))
+    }
+
     pub async fn get_blob(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
-        match self {
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.get_blob(key, range).await,
             #[cfg(feature = "foundation")]
@@ -324,11 +725,15 @@ impl Store {
             Self::MySQL(store) => store.get_blob(key, range).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.get_blob(key, range).await,
-        }
+        };
+        record_operation(self.backend_name(), "get_blob", started.elapsed());
+
+        result
     }
 
     pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self {
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.put_blob(key, data).await,
             #[cfg(feature = "foundation")]
@@ -339,11 +744,15 @@ impl Store {
             Self::MySQL(store) => store.put_blob(key, data).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.put_blob(key, data).await,
-        }
+        };
+        record_operation(self.backend_name(), "put_blob", started.elapsed());
+
+        result
     }
 
     pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        match self {
+        let started = Instant::now();
+        let result = match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.delete_blob(key).await,
             #[cfg(feature = "foundation")]
@@ -354,7 +763,10 @@ impl Store {
             Self::MySQL(store) => store.delete_blob(key).await,
             #[cfg(feature = "rocks")]
             Self::RocksDb(store) => store.delete_blob(key).await,
-        }
+        };
+        record_operation(self.backend_name(), "delete_blob", started.elapsed());
+
+        result
     }
 
     #[cfg(feature = "test_mode")]
@@ -464,6 +876,53 @@ impl Store {
         self.purge_blobs(blob_store).await.unwrap();
         self.purge_bitmaps().await.unwrap();
 
+        // Compaction markers are removed along with the logs
+        self.delete_range(
+            ValueKey {
+                account_id: 0,
+                collection: 0,
+                document_id: 0,
+                class: ValueClass::ChangesCompacted,
+            },
+            ValueKey {
+                account_id: u32::MAX,
+                collection: u8::MAX,
+                document_id: 0,
+                class: ValueClass::ChangesCompacted,
+            },
+        )
+        .await
+        .unwrap();
+
+        // Usage counters and rollups are accumulated by every delivery
+        use crate::write::BillingClass;
+        for (from, to) in [
+            (
+                BillingClass::Counter {
+                    account_id: 0,
+                    metric: 0,
+                },
+                BillingClass::Counter {
+                    account_id: u32::MAX,
+                    metric: u8::MAX,
+                },
+            ),
+            (
+                BillingClass::Rollup {
+                    period: 0,
+                    account_id: 0,
+                },
+                BillingClass::Rollup {
+                    period: u32::MAX,
+                    account_id: u32::MAX,
+                },
+            ),
+        ] {
+            self.delete_range(ValueKey::from(from), ValueKey::from(to))
+                .await
+                .unwrap();
+        }
+
         let store = self.clone();
         let mut failed = false;
 
@@ -32,7 +32,7 @@ use nlp::{
     },
     tokenizers::word::WordTokenizer,
 };
-use utils::codec::leb128::Leb128Reader;
+use utils::{codec::leb128::Leb128Reader, worker::cpu_pool};
 
 use crate::{
     backend::MAX_TOKEN_LENGTH,
@@ -43,7 +43,7 @@ use crate::{
     Deserialize, Error, Store, ValueKey, U64_LEN,
 };
 
-use super::Field;
+use super::{Field, FtsLanguageConfig};
 pub const TERM_INDEX_VERSION: u8 = 1;
 
 #[derive(Debug)]
@@ -64,6 +64,7 @@ pub(crate) enum Type {
 pub struct FtsDocument<'x, T: Into<u8> + Display + Clone + std::fmt::Debug> {
     pub(crate) parts: Vec<Text<'x, T>>,
     pub(crate) default_language: Language,
+    pub(crate) language_config: Option<&'x FtsLanguageConfig>,
     pub(crate) account_id: u32,
     pub(crate) collection: u8,
     pub(crate) document_id: u32,
@@ -74,12 +75,18 @@ impl<'x, T: Into<u8> + Display + Clone + std::fmt::Debug> FtsDocument<'x, T> {
         FtsDocument {
             parts: vec![],
             default_language,
+            language_config: None,
             account_id: 0,
             document_id: 0,
             collection: 0,
         }
     }
 
+    pub fn with_language_config(mut self, language_config: &'x FtsLanguageConfig) -> Self {
+        self.language_config = Some(language_config);
+        self
+    }
+
     pub fn with_account_id(mut self, account_id: u32) -> Self {
         self.account_id = account_id;
         self
@@ -132,77 +139,15 @@ impl<T: Into<u8> + Display + Clone + std::fmt::Debug> From<Field<T>> for u8 {
 }
 
 impl Store {
-    pub async fn fts_index<T: Into<u8> + Display + Clone + std::fmt::Debug>(
+    pub async fn fts_index<T: Into<u8> + Display + Clone + std::fmt::Debug + Send>(
         &self,
         document: FtsDocument<'_, T>,
     ) -> crate::Result<()> {
-        let mut detect = LanguageDetector::new();
-        let mut tokens: AHashMap<BitmapHash, AHashSet<u8>> = AHashMap::new();
-        let mut parts = Vec::new();
-
-        for text in document.parts {
-            match text.typ {
-                Type::Text(language) => {
-                    let language = if language == Language::Unknown {
-                        detect.detect(&text.text, MIN_LANGUAGE_SCORE)
-                    } else {
-                        language
-                    };
-                    parts.push((text.field, language, text.text));
-                }
-                Type::Tokenize => {
-                    let field = u8::from(text.field);
-                    for token in WordTokenizer::new(text.text.as_ref(), MAX_TOKEN_LENGTH) {
-                        tokens
-                            .entry(BitmapHash::new(token.word.as_ref()))
-                            .or_default()
-                            .insert(TokenType::word(field));
-                    }
-                }
-                Type::Keyword => {
-                    let field = u8::from(text.field);
-                    tokens
-                        .entry(BitmapHash::new(text.text.as_ref()))
-                        .or_default()
-                        .insert(TokenType::word(field));
-                }
-            }
-        }
-
-        let default_language = detect
-            .most_frequent_language()
-            .unwrap_or(document.default_language);
-        let mut bigrams = BTreeSet::new();
-
-        for (field, language, text) in parts.into_iter() {
-            let language = if language != Language::Unknown {
-                language
-            } else {
-                default_language
-            };
-            let field: u8 = field.into();
-
-            let mut last_token = Cow::Borrowed("");
-            for token in Stemmer::new(&text, language, MAX_TOKEN_LENGTH) {
-                if !last_token.is_empty() {
-                    bigrams.insert(BitmapHash::new(&format!("{} {}", last_token, token.word)).hash);
-                }
-
-                tokens
-                    .entry(BitmapHash::new(token.word.as_ref()))
-                    .or_default()
-                    .insert(TokenType::word(field));
-
-                if let Some(stemmed_word) = token.stemmed_word {
-                    tokens
-                        .entry(BitmapHash::new(stemmed_word.as_ref()))
-                        .or_default()
-                        .insert(TokenType::stemmed(field));
-                }
-
-                last_token = token.word;
-            }
-        }
+        let parts = document.parts;
+        let default_language = document.default_language;
+        let language_config = document.language_config;
+        let tokenize = move || tokenize_document(parts, default_language, language_config);
+        let (tokens, bigrams) = cpu_pool().install(tokenize).unwrap_or_else(|f| f());
 
         if tokens.is_empty() {
             return Ok(());
@@ -341,6 +286,88 @@ impl Store {
     }
 }
 
+fn tokenize_document<T: Into<u8> + Display + Clone + std::fmt::Debug>(
+    parts: Vec<Text<'_, T>>,
+    default_language: Language,
+    language_config: Option<&FtsLanguageConfig>,
+) -> (AHashMap<BitmapHash, AHashSet<u8>>, BTreeSet<[u8; 8]>) {
+    let mut detect = LanguageDetector::new();
+    let mut tokens: AHashMap<BitmapHash, AHashSet<u8>> = AHashMap::new();
+    let mut texts = Vec::new();
+
+    for text in parts {
+        match text.typ {
+            Type::Text(language) => {
+                let language = if language == Language::Unknown {
+                    detect.detect(&text.text, MIN_LANGUAGE_SCORE)
+                } else {
+                    language
+                };
+                texts.push((text.field, language, text.text));
+            }
+            Type::Tokenize => {
+                let field = u8::from(text.field);
+                for token in WordTokenizer::new(text.text.as_ref(), MAX_TOKEN_LENGTH) {
+                    tokens
+                        .entry(BitmapHash::new(token.word.as_ref()))
+                        .or_default()
+                        .insert(TokenType::word(field));
+                }
+            }
+            Type::Keyword => {
+                let field = u8::from(text.field);
+                tokens
+                    .entry(BitmapHash::new(text.text.as_ref()))
+                    .or_default()
+                    .insert(TokenType::word(field));
+            }
+        }
+    }
+
+    let default_language = detect.most_frequent_language().unwrap_or(default_language);
+    let mut bigrams = BTreeSet::new();
+
+    for (field, language, text) in texts.into_iter() {
+        let language = if language != Language::Unknown {
+            language
+        } else {
+            default_language
+        };
+        let field: u8 = field.into();
+        let is_stemmed = language_config.map_or(true, |config| config.is_stemmed(language));
+
+        let mut last_token = Cow::Borrowed("");
+        for token in Stemmer::new(&text, language, MAX_TOKEN_LENGTH) {
+            // Stop words are only kept in bigrams so they can be matched by phrase searches
+            if !last_token.is_empty() {
+                bigrams.insert(BitmapHash::new(&format!("{} {}", last_token, token.word)).hash);
+            }
+            if language_config.map_or(false, |config| {
+                config.is_stop_word(language, token.word.as_ref())
+            }) {
+                last_token = token.word;
+                continue;
+            }
+
+            tokens
+                .entry(BitmapHash::new(token.word.as_ref()))
+                .or_default()
+                .insert(TokenType::word(field));
+
+            if let Some(stemmed_word) = token.stemmed_word.filter(|_| is_stemmed) {
+                tokens
+                    .entry(BitmapHash::new(stemmed_word.as_ref()))
+                    .or_default()
+                    .insert(TokenType::stemmed(field));
+            }
+
+            last_token = token.word;
+        }
+    }
+
+    (tokens, bigrams)
+}
+
 struct TermIndex {
     ops: Vec<Operation>,
 }
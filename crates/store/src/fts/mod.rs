@@ -23,7 +23,8 @@
 
 use std::fmt::Display;
 
-use nlp::language::Language;
+use ahash::AHashSet;
+use nlp::language::{stopwords::STOP_WORDS, Language};
 
 pub mod index;
 pub mod query;
@@ -36,6 +37,32 @@ pub enum Field<T: Into<u8> + Display + Clone + std::fmt::Debug> {
     Keyword,
 }
 
+/// Per-language tokenization settings. Stemming is applied and stop words
+/// are left out of the index for every language not listed here.
+#[derive(Debug, Clone, Default)]
+pub struct FtsLanguageConfig {
+    pub disable_stemming: AHashSet<Language>,
+    pub keep_stop_words: AHashSet<Language>,
+}
+
+impl FtsLanguageConfig {
+    pub fn is_stemmed(&self, language: Language) -> bool {
+        !self.disable_stemming.contains(&language)
+    }
+
+    pub fn is_stop_word(&self, language: Language, word: &str) -> bool {
+        !self.keep_stop_words.contains(&language) && is_stop_word(language, word)
+    }
+}
+
+pub fn is_stop_word(language: Language, word: &str) -> bool {
+    STOP_WORDS
+        .get(language as usize)
+        .copied()
+        .flatten()
+        .map_or(false, |stop_words| stop_words.contains(word))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum FtsFilter<T: Into<u8> + Display + Clone + std::fmt::Debug> {
     Exact {
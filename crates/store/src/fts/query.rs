@@ -34,7 +34,7 @@ use utils::codec::leb128::Leb128Reader;
 
 use crate::{
     backend::MAX_TOKEN_LENGTH,
-    fts::FtsFilter,
+    fts::{is_stop_word, FtsFilter},
     write::{BitmapClass, BitmapHash, ValueClass},
     BitmapKey, Deserialize, Error, Store, ValueKey,
 };
@@ -76,13 +76,22 @@ impl Store {
                     let mut keys = Vec::new();
                     let mut bigrams = AHashSet::new();
                     let mut last_token = Cow::Borrowed("");
-                    for token in language.tokenize_text(text.as_ref(), MAX_TOKEN_LENGTH) {
-                        keys.push(BitmapKey {
-                            account_id,
-                            collection,
-                            class: BitmapClass::word(token.word.as_ref(), field),
-                            block_num: 0,
-                        });
+                    let tokens = language
+                        .tokenize_text(text.as_ref(), MAX_TOKEN_LENGTH)
+                        .collect::<Vec<_>>();
+                    let skip_stop_words = tokens
+                        .iter()
+                        .any(|token| !is_stop_word(language, token.word.as_ref()));
+                    for token in tokens {
+                        // Stop words might not be indexed, match them using bigrams instead
+                        if !skip_stop_words || !is_stop_word(language, token.word.as_ref()) {
+                            keys.push(BitmapKey {
+                                account_id,
+                                collection,
+                                class: BitmapClass::word(token.word.as_ref(), field),
+                                block_num: 0,
+                            });
+                        }
 
                         if !last_token.is_empty() {
                             bigrams.insert(
@@ -93,39 +102,38 @@ impl Store {
                         last_token = token.word;
                     }
 
-                    match keys.len().cmp(&1) {
-                        std::cmp::Ordering::Less => None,
-                        std::cmp::Ordering::Equal => self.get_bitmaps_intersection(keys).await?,
-                        std::cmp::Ordering::Greater => {
-                            if let Some(document_ids) = self.get_bitmaps_intersection(keys).await? {
-                                let mut results = RoaringBitmap::new();
-                                for document_id in document_ids {
-                                    if let Some(bigram_index) = self
-                                        .get_value::<BigramIndex>(ValueKey {
-                                            account_id,
-                                            collection,
-                                            document_id,
-                                            class: ValueClass::TermIndex,
-                                        })
-                                        .await?
-                                    {
-                                        if bigrams.iter().all(|bigram| {
-                                            bigram_index.grams.binary_search(bigram).is_ok()
-                                        }) {
-                                            results.insert(document_id);
-                                        }
-                                    }
-                                }
-
-                                if !results.is_empty() {
-                                    Some(results)
-                                } else {
-                                    None
+                    if keys.is_empty() {
+                        None
+                    } else if bigrams.is_empty() {
+                        self.get_bitmaps_intersection(keys).await?
+                    } else if let Some(document_ids) = self.get_bitmaps_intersection(keys).await? {
+                        let mut results = RoaringBitmap::new();
+                        for document_id in document_ids {
+                            if let Some(bigram_index) = self
+                                .get_value::<BigramIndex>(ValueKey {
+                                    account_id,
+                                    collection,
+                                    document_id,
+                                    class: ValueClass::TermIndex,
+                                })
+                                .await?
+                            {
+                                if bigrams
+                                    .iter()
+                                    .all(|bigram| bigram_index.grams.binary_search(bigram).is_ok())
+                                {
+                                    results.insert(document_id);
                                 }
-                            } else {
-                                None
                             }
                         }
+
+                        if !results.is_empty() {
+                            Some(results)
+                        } else {
+                            None
+                        }
+                    } else {
+                        None
                     }
                 }
                 FtsFilter::Contains {
@@ -136,7 +144,16 @@ impl Store {
                     let mut result = RoaringBitmap::new();
                     let field: u8 = field.clone().into();
 
-                    for token in Stemmer::new(text.as_ref(), language, MAX_TOKEN_LENGTH) {
+                    let tokens =
+                        Stemmer::new(text.as_ref(), language, MAX_TOKEN_LENGTH).collect::<Vec<_>>();
+                    let skip_stop_words = tokens
+                        .iter()
+                        .any(|token| !is_stop_word(language, token.word.as_ref()));
+                    for token in tokens {
+                        if skip_stop_words && is_stop_word(language, token.word.as_ref()) {
+                            continue;
+                        }
+
                         let token1 = BitmapKey {
                             account_id,
                             collection,
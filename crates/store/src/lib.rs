@@ -182,6 +182,7 @@ pub struct IterateParams<T: Key> {
     first: bool,
     ascending: bool,
     values: bool,
+    limit: usize,
 }
 
 #[derive(Clone, Default)]
@@ -207,13 +208,38 @@ pub enum Store {
 }
 
 #[derive(Clone)]
-pub enum BlobStore {
+pub struct BlobStore {
+    pub backend: BlobBackend,
+    pub compression: CompressionAlgo,
+    pub chunking: Option<Arc<BlobChunking>>,
+}
+
+pub struct BlobChunking {
+    pub store: Store,
+    pub threshold: usize,
+    pub min_size: usize,
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+#[derive(Clone)]
+pub enum BlobBackend {
     Store(Store),
     Fs(Arc<FsStore>),
     #[cfg(feature = "s3")]
     S3(Arc<S3Store>),
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionAlgo {
+    #[default]
+    None,
+    Zstd {
+        level: i32,
+        min_size: usize,
+    },
+}
+
 #[derive(Clone)]
 pub enum FtsStore {
     Store(Store),
@@ -272,14 +298,22 @@ impl From<RocksDbStore> for Store {
 
 impl From<FsStore> for BlobStore {
     fn from(store: FsStore) -> Self {
-        Self::Fs(Arc::new(store))
+        BlobStore {
+            backend: BlobBackend::Fs(Arc::new(store)),
+            compression: CompressionAlgo::None,
+            chunking: None,
+        }
     }
 }
 
 #[cfg(feature = "s3")]
 impl From<S3Store> for BlobStore {
     fn from(store: S3Store) -> Self {
-        Self::S3(Arc::new(store))
+        BlobStore {
+            backend: BlobBackend::S3(Arc::new(store)),
+            compression: CompressionAlgo::None,
+            chunking: None,
+        }
     }
 }
 
@@ -305,7 +339,11 @@ impl From<Store> for FtsStore {
 
 impl From<Store> for BlobStore {
     fn from(store: Store) -> Self {
-        Self::Store(store)
+        BlobStore {
+            backend: BlobBackend::Store(store),
+            compression: CompressionAlgo::None,
+            chunking: None,
+        }
     }
 }
 
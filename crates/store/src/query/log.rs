@@ -23,7 +23,10 @@
 
 use utils::codec::leb128::Leb128Iterator;
 
-use crate::{write::key::DeserializeBigEndian, Error, IterateParams, LogKey, Store, U64_LEN};
+use crate::{
+    write::{key::DeserializeBigEndian, BatchBuilder, ValueClass},
+    Error, IterateParams, LogKey, Serialize, Store, ValueKey, U64_LEN,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Change {
@@ -38,6 +41,9 @@ pub struct Changes {
     pub changes: Vec<Change>,
     pub from_change_id: u64,
     pub to_change_id: u64,
+    /// Set when entries in the requested range were removed by a compaction,
+    /// meaning that the returned changes are incomplete.
+    pub is_truncated: bool,
 }
 
 #[derive(Debug)]
@@ -54,6 +60,7 @@ impl Default for Changes {
             changes: Vec::with_capacity(10),
             from_change_id: 0,
             to_change_id: 0,
+            is_truncated: false,
         }
     }
 }
@@ -108,6 +115,11 @@ impl Store {
         )
         .await?;
 
+        // Entries older than the compaction point are no longer available
+        if let Some(compacted_id) = self.get_compacted_change_id(account_id, collection).await? {
+            changelog.is_truncated = from_change_id < compacted_id;
+        }
+
         if changelog.changes.is_empty() {
             changelog.from_change_id = from_change_id;
             changelog.to_change_id = if to_change_id != u64::MAX {
@@ -154,6 +166,97 @@ impl Store {
 
         Ok(last_change_id)
     }
+
+    /// Returns the id of the oldest change log entry kept after the last
+    /// compaction of a collection, if it was ever compacted.
+    pub async fn get_compacted_change_id(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+    ) -> crate::Result<Option<u64>> {
+        self.get_value::<u64>(ValueKey {
+            account_id,
+            collection: collection.into(),
+            document_id: 0,
+            class: ValueClass::ChangesCompacted,
+        })
+        .await
+    }
+
+    /// Removes the change log entries of a collection older than `before_change_id`.
+    /// The most recent entry is always kept so the collection state does not change.
+    /// Returns the number of entries removed.
+    pub async fn compact_changes(
+        &self,
+        account_id: u32,
+        collection: impl Into<u8> + Sync + Send,
+        before_change_id: u64,
+    ) -> crate::Result<usize> {
+        let collection = collection.into();
+        let last_change_id = match self.get_last_change_id(account_id, collection).await? {
+            Some(last_change_id) => last_change_id,
+            None => return Ok(0),
+        };
+        let compacted_id = before_change_id.min(last_change_id);
+        if self
+            .get_compacted_change_id(account_id, collection)
+            .await?
+            .map_or(false, |prev_compacted_id| prev_compacted_id >= compacted_id)
+        {
+            return Ok(0);
+        }
+
+        // Count the entries to remove
+        let from_key = LogKey {
+            account_id,
+            collection,
+            change_id: 0,
+        };
+        let to_key = LogKey {
+            account_id,
+            collection,
+            change_id: compacted_id,
+        };
+        let mut num_entries = 0;
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending().no_values(),
+            |key, _| {
+                if key.deserialize_be_u64(key.len() - U64_LEN)? < compacted_id {
+                    num_entries += 1;
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+        if num_entries == 0 {
+            return Ok(0);
+        }
+
+        // Record the compaction point before removing any entries, this way
+        // clients holding older states are told to resynchronize
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(collection)
+            .set(ValueClass::ChangesCompacted, compacted_id.serialize());
+        self.write(batch.build()).await?;
+
+        self.delete_range(
+            LogKey {
+                account_id,
+                collection,
+                change_id: 0,
+            },
+            LogKey {
+                account_id,
+                collection,
+                change_id: compacted_id,
+            },
+        )
+        .await?;
+
+        Ok(num_entries)
+    }
 }
 
 impl Changes {
@@ -252,6 +252,7 @@ impl<T: Key> IterateParams<T> {
             first: false,
             ascending: true,
             values: true,
+            limit: 0,
         }
     }
 
@@ -284,4 +285,9 @@ impl<T: Key> IterateParams<T> {
         self.values = false;
         self
     }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
 }
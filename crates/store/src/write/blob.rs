@@ -21,12 +21,14 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use ahash::AHashSet;
 use utils::{BlobHash, BLOB_HASH_LEN};
 
 use crate::{
-    write::BatchBuilder, BlobClass, BlobStore, Deserialize, IterateParams, Store, ValueKey,
-    U32_LEN, U64_LEN,
+    dispatch::blob::BlobHealth, write::BatchBuilder, BlobClass, BlobStore, Deserialize,
+    IterateParams, Serialize, Store, ValueKey, U32_LEN, U64_LEN,
 };
 
 use super::{key::DeserializeBigEndian, now, BlobOp, Operation, ValueClass, ValueOp};
@@ -37,6 +39,21 @@ pub struct BlobQuota {
     pub count: usize,
 }
 
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BlobScrub {
+    pub verified: usize,
+    pub repaired: Vec<BlobHash>,
+    pub quarantined: Vec<(BlobHash, BlobHealth)>,
+    pub complete: bool,
+}
+
+impl Deserialize for BlobHash {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        BlobHash::try_from_hash_slice(bytes)
+            .map_err(|_| crate::Error::InternalError("Failed to deserialize blob hash".to_string()))
+    }
+}
+
 impl Store {
     pub async fn blob_exists(
         &self,
@@ -255,6 +272,9 @@ impl Store {
                 batch.with_account_id(key.account_id);
                 last_account_id = key.account_id;
             }
+            if let ValueClass::Blob(BlobOp::Commit { hash }) = &key.class {
+                batch.clear(BlobOp::Quarantine { hash: hash.clone() });
+            }
             batch.ops.push(Operation::Value {
                 class: key.class,
                 op: ValueOp::Clear,
@@ -267,6 +287,138 @@ impl Store {
         Ok(())
     }
 
+    /// Verifies the contents of up to `batch_size` committed blobs, resuming
+    /// from where the previous call left off. Missing or corrupt blobs are
+    /// restored from `replica` when it holds a healthy copy, otherwise they
+    /// are quarantined until they are repaired or deleted.
+    pub async fn scrub_blobs(
+        &self,
+        blob_store: &BlobStore,
+        replica: Option<&BlobStore>,
+        batch_size: usize,
+        pause: Duration,
+    ) -> crate::Result<BlobScrub> {
+        let batch_size = batch_size.max(1);
+        let cursor = self
+            .get_value::<BlobHash>(ValueKey::from(ValueClass::Blob(BlobOp::ScrubCursor)))
+            .await?;
+
+        // Obtain the next batch of committed blobs
+        let from_key = ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::Link {
+                hash: cursor.clone().unwrap_or_default(),
+            }),
+        };
+        let to_key = ValueKey {
+            account_id: u32::MAX,
+            collection: u8::MAX,
+            document_id: u32::MAX,
+            class: ValueClass::Blob(BlobOp::Link {
+                hash: BlobHash::new_max(),
+            }),
+        };
+        let mut hashes = Vec::with_capacity(batch_size);
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending().no_values(),
+            |key, _| {
+                let hash = BlobHash::try_from_hash_slice(
+                    key.get(1..1 + BLOB_HASH_LEN).ok_or_else(|| {
+                        crate::Error::InternalError(format!(
+                            "Invalid key {key:?} in blob hash tables"
+                        ))
+                    })?,
+                )
+                .unwrap();
+                if key.deserialize_be_u32(key.len() - U32_LEN)? == u32::MAX
+                    && cursor.as_ref() != Some(&hash)
+                {
+                    hashes.push(hash);
+                }
+
+                Ok(hashes.len() < batch_size)
+            },
+        )
+        .await?;
+
+        let mut scrub = BlobScrub {
+            complete: hashes.len() < batch_size,
+            ..Default::default()
+        };
+        let mut batch = BatchBuilder::new();
+        if let Some(last_hash) = hashes.last().filter(|_| !scrub.complete) {
+            batch.set(BlobOp::ScrubCursor, last_hash.as_slice().to_vec());
+        } else {
+            batch.clear(BlobOp::ScrubCursor);
+        }
+
+        for hash in hashes {
+            let health = blob_store.verify_blob(&hash).await?;
+            if health == BlobHealth::Healthy {
+                scrub.verified += 1;
+            } else {
+                // Restore the blob from the replica if it holds a healthy copy
+                let copy = if let Some(replica) = replica {
+                    replica
+                        .get_blob(hash.as_ref(), 0..u32::MAX)
+                        .await
+                        .ok()
+                        .flatten()
+                        .filter(|data| BlobHash::from(data.as_slice()) == hash)
+                } else {
+                    None
+                };
+
+                let quarantine_key =
+                    ValueKey::from(ValueClass::Blob(BlobOp::Quarantine { hash: hash.clone() }));
+                if let Some(data) = copy {
+                    blob_store.repair_blob(hash.as_ref(), &data).await?;
+                    batch.clear(BlobOp::Quarantine { hash: hash.clone() });
+                    scrub.repaired.push(hash);
+                } else {
+                    if self.get_value::<u64>(quarantine_key).await?.is_none() {
+                        batch.set(BlobOp::Quarantine { hash: hash.clone() }, now().serialize());
+                    }
+                    scrub.quarantined.push((hash, health));
+                }
+            }
+
+            if !pause.is_zero() {
+                tokio::time::sleep(pause).await;
+            }
+        }
+        self.write(batch.build()).await?;
+
+        Ok(scrub)
+    }
+
+    /// Returns the blobs that failed verification and could not be repaired,
+    /// along with the time they were first found to be damaged.
+    pub async fn list_quarantined_blobs(&self) -> crate::Result<Vec<(BlobHash, u64)>> {
+        let from_key = ValueKey::from(ValueClass::Blob(BlobOp::Quarantine {
+            hash: BlobHash::default(),
+        }));
+        let to_key = ValueKey::from(ValueClass::Blob(BlobOp::Quarantine {
+            hash: BlobHash::new_max(),
+        }));
+        let mut blobs = Vec::new();
+        self.iterate(
+            IterateParams::new(from_key, to_key).ascending(),
+            |key, value| {
+                blobs.push((
+                    BlobHash::deserialize(key.get(1..).unwrap_or_default())?,
+                    u64::deserialize(value)?,
+                ));
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(blobs)
+    }
+
     pub async fn blob_hash_unlink_account(&self, account_id: u32) -> crate::Result<()> {
         // Validate linked blobs
         let from_key = ValueKey {
@@ -31,10 +31,18 @@ use crate::{
 };
 
 use super::{
-    AnyKey, BitmapClass, BlobOp, DirectoryClass, LookupClass, QueueClass, ReportEvent, TagValue,
-    ValueClass,
+    AnyKey, BillingClass, BitmapClass, BlobOp, DirectoryClass, LookupClass, QueueClass,
+    ReportEvent, TagValue, ValueClass,
 };
 
+pub(crate) const BM_DOCUMENT_IDS: u8 = 0;
+pub(crate) const BM_TAG: u8 = 1 << 6;
+pub(crate) const BM_TEXT: u8 = 1 << 7;
+
+pub(crate) const TAG_ID: u8 = 0;
+pub(crate) const TAG_TEXT: u8 = 1 << 0;
+pub(crate) const TAG_STATIC: u8 = 1 << 1;
+
 pub struct KeySerializer {
     pub buf: Vec<u8>,
 }
@@ -220,11 +228,19 @@ impl Key for LogKey {
 
 impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
     fn subspace(&self) -> u8 {
-        if !matches!(
+        if let ValueClass::Any(any) = self.class.as_ref() {
+            any.subspace
+        } else if !matches!(
             self.class.as_ref(),
-            ValueClass::Directory(DirectoryClass::UsedQuota(_))
+            ValueClass::Directory(DirectoryClass::UsedQuota(_) | DirectoryClass::UsedMessages(_))
                 | ValueClass::Lookup(LookupClass::Counter(_))
-                | ValueClass::Queue(QueueClass::QuotaCount(_) | QueueClass::QuotaSize(_))
+                | ValueClass::Queue(
+                    QueueClass::QuotaCount(_)
+                        | QueueClass::QuotaSize(_)
+                        | QueueClass::DomainHealth(_)
+                )
+                | ValueClass::Billing(BillingClass::Counter { .. })
+                | ValueClass::MailboxCounter { .. }
         ) {
             SUBSPACE_VALUES
         } else {
@@ -285,6 +301,9 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(self.account_id)
                     .write(self.collection)
                     .write(self.document_id),
+                BlobOp::ChunkRef { hash } => serializer.write(11u8).write::<&[u8]>(hash.as_ref()),
+                BlobOp::Quarantine { hash } => serializer.write(16u8).write::<&[u8]>(hash.as_ref()),
+                BlobOp::ScrubCursor => serializer.write(17u8),
             },
             ValueClass::Config(key) => serializer.write(8u8).write(key.as_slice()),
             ValueClass::Lookup(lookup) => match lookup {
@@ -298,6 +317,7 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                 DirectoryClass::Principal(uid) => serializer.write(22u8).write_leb128(*uid),
                 DirectoryClass::Domain(name) => serializer.write(23u8).write(name.as_slice()),
                 DirectoryClass::UsedQuota(uid) => serializer.write(24u8).write_leb128(*uid),
+                DirectoryClass::UsedMessages(uid) => serializer.write(29u8).write_leb128(*uid),
                 DirectoryClass::MemberOf {
                     principal_id,
                     member_of,
@@ -312,6 +332,10 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(26u8)
                     .write(*principal_id)
                     .write(*has_member),
+                DirectoryClass::Tombstone(uid) => serializer.write(27u8).write(*uid),
+                DirectoryClass::TemporaryAlias(name) => {
+                    serializer.write(28u8).write(name.as_slice())
+                }
             },
             ValueClass::Queue(queue) => match queue {
                 QueueClass::Message(queue_id) => serializer.write(50u8).write(*queue_id),
@@ -347,7 +371,41 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(event.seq_id),
                 QueueClass::QuotaCount(key) => serializer.write(55u8).write(key.as_slice()),
                 QueueClass::QuotaSize(key) => serializer.write(56u8).write(key.as_slice()),
+                QueueClass::DlpIncident(id) => serializer.write(57u8).write(*id),
+                QueueClass::QuarantineEntry(queue_id) => serializer.write(58u8).write(*queue_id),
+                QueueClass::QuarantineAudit(id) => serializer.write(59u8).write(*id),
+                QueueClass::ShadowEntry(queue_id) => serializer.write(60u8).write(*queue_id),
+                QueueClass::MtaStsPolicy(domain) => serializer.write(63u8).write(domain.as_slice()),
+                QueueClass::DomainHealth(key) => serializer.write(62u8).write(key.as_slice()),
+                QueueClass::IncomingDmarcReport(id) => serializer.write(64u8).write(*id),
+                QueueClass::MonitorVerdict(id) => serializer.write(65u8).write(*id),
+            },
+            ValueClass::Billing(billing) => match billing {
+                BillingClass::Counter { account_id, metric } => {
+                    serializer.write(60u8).write(*account_id).write(*metric)
+                }
+                BillingClass::Rollup { period, account_id } => {
+                    serializer.write(61u8).write(*period).write(*account_id)
+                }
             },
+            ValueClass::MailboxCounter {
+                mailbox_id,
+                counter,
+            } => serializer
+                .write(12u8)
+                .write(self.account_id)
+                .write(*mailbox_id)
+                .write(*counter),
+            ValueClass::ChangesCompacted => serializer
+                .write(13u8)
+                .write(self.account_id)
+                .write(self.collection),
+            ValueClass::AccountPlacement => serializer.write(14u8).write(self.account_id),
+            ValueClass::UploadSession(upload_id) => serializer
+                .write(15u8)
+                .write(self.account_id)
+                .write(*upload_id),
+            ValueClass::Any(any) => serializer.write(any.key.as_slice()),
         }
         .finalize()
     }
@@ -383,14 +441,6 @@ impl<T: AsRef<BitmapClass> + Sync + Send> Key for BitmapKey<T> {
     }
 
     fn serialize(&self, flags: u32) -> Vec<u8> {
-        const BM_DOCUMENT_IDS: u8 = 0;
-        const BM_TAG: u8 = 1 << 6;
-        const BM_TEXT: u8 = 1 << 7;
-
-        const TAG_ID: u8 = 0;
-        const TAG_TEXT: u8 = 1 << 0;
-        const TAG_STATIC: u8 = 1 << 1;
-
         let serializer = match self.class.as_ref() {
             BitmapClass::DocumentIds => if (flags & WITH_SUBSPACE) != 0 {
                 KeySerializer::new(U32_LEN + 3).write(SUBSPACE_BITMAPS)
@@ -483,17 +533,29 @@ impl ValueClass {
             ValueClass::Directory(d) => match d {
                 DirectoryClass::NameToId(v)
                 | DirectoryClass::EmailToId(v)
-                | DirectoryClass::Domain(v) => v.len(),
-                DirectoryClass::Principal(_) | DirectoryClass::UsedQuota(_) => U32_LEN,
+                | DirectoryClass::Domain(v)
+                | DirectoryClass::TemporaryAlias(v) => v.len(),
+                DirectoryClass::Principal(_)
+                | DirectoryClass::UsedQuota(_)
+                | DirectoryClass::UsedMessages(_)
+                | DirectoryClass::Tombstone(_) => U32_LEN,
                 DirectoryClass::Members { .. } | DirectoryClass::MemberOf { .. } => U32_LEN * 2,
             },
             ValueClass::Blob(op) => match op {
                 BlobOp::Reserve { .. } => BLOB_HASH_LEN + U64_LEN + U32_LEN + 1,
                 BlobOp::Commit { .. } | BlobOp::Link { .. } => BLOB_HASH_LEN + U32_LEN * 2 + 2,
+                BlobOp::ChunkRef { .. } | BlobOp::Quarantine { .. } => BLOB_HASH_LEN + 1,
+                BlobOp::ScrubCursor => 1,
             },
             ValueClass::IndexEmail { .. } => U64_LEN * 2,
             ValueClass::Queue(q) => match q {
-                QueueClass::Message(_) => U64_LEN,
+                QueueClass::Message(_)
+                | QueueClass::DlpIncident(_)
+                | QueueClass::QuarantineEntry(_)
+                | QueueClass::QuarantineAudit(_)
+                | QueueClass::ShadowEntry(_)
+                | QueueClass::IncomingDmarcReport(_)
+                | QueueClass::MonitorVerdict(_) => U64_LEN,
                 QueueClass::MessageEvent(_) => U64_LEN * 2,
                 QueueClass::DmarcReportEvent(event) | QueueClass::TlsReportEvent(event) => {
                     event.domain.len() + U64_LEN * 3
@@ -501,8 +563,20 @@ impl ValueClass {
                 QueueClass::DmarcReportHeader(event) | QueueClass::TlsReportHeader(event) => {
                     event.domain.len() + (U64_LEN * 3) + 1
                 }
-                QueueClass::QuotaCount(v) | QueueClass::QuotaSize(v) => v.len(),
+                QueueClass::QuotaCount(v)
+                | QueueClass::QuotaSize(v)
+                | QueueClass::MtaStsPolicy(v)
+                | QueueClass::DomainHealth(v) => v.len(),
             },
+            ValueClass::Billing(billing) => match billing {
+                BillingClass::Counter { .. } => U32_LEN + 1,
+                BillingClass::Rollup { .. } => U32_LEN * 2,
+            },
+            ValueClass::MailboxCounter { .. } => U32_LEN * 2 + 1,
+            ValueClass::ChangesCompacted => U32_LEN + 1,
+            ValueClass::AccountPlacement => U32_LEN,
+            ValueClass::UploadSession(_) => U32_LEN + U64_LEN,
+            ValueClass::Any(any) => any.key.len(),
         }
     }
 }
@@ -535,6 +609,23 @@ impl From<DirectoryClass> for ValueClass {
     }
 }
 
+impl From<BillingClass> for ValueKey<ValueClass> {
+    fn from(value: BillingClass) -> Self {
+        ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Billing(value),
+        }
+    }
+}
+
+impl From<BillingClass> for ValueClass {
+    fn from(value: BillingClass) -> Self {
+        ValueClass::Billing(value)
+    }
+}
+
 impl From<BlobOp> for ValueClass {
     fn from(value: BlobOp) -> Self {
         ValueClass::Blob(value)
@@ -140,6 +140,18 @@ pub enum ValueClass {
     IndexEmail(u64),
     Config(Vec<u8>),
     Queue(QueueClass),
+    Billing(BillingClass),
+    MailboxCounter { mailbox_id: u32, counter: u8 },
+    ChangesCompacted,
+    AccountPlacement,
+    UploadSession(u64),
+    Any(AnyClass),
+}
+
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub struct AnyClass {
+    pub subspace: u8,
+    pub key: Vec<u8>,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -158,6 +170,9 @@ pub enum DirectoryClass {
     Domain(Vec<u8>),
     Principal(u32),
     UsedQuota(u32),
+    UsedMessages(u32),
+    Tombstone(u32),
+    TemporaryAlias(Vec<u8>),
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -170,6 +185,30 @@ pub enum QueueClass {
     TlsReportEvent(ReportEvent),
     QuotaCount(Vec<u8>),
     QuotaSize(Vec<u8>),
+    DlpIncident(u64),
+    QuarantineEntry(u64),
+    QuarantineAudit(u64),
+    ShadowEntry(u64),
+    MtaStsPolicy(Vec<u8>),
+    DomainHealth(Vec<u8>),
+    IncomingDmarcReport(u64),
+    MonitorVerdict(u64),
+}
+
+pub const MAILBOX_COUNTER_TOTAL: u8 = 0;
+pub const MAILBOX_COUNTER_UNSEEN: u8 = 1;
+pub const MAILBOX_COUNTER_SIZE: u8 = 2;
+
+pub const BILLING_RECEIVED_MESSAGES: u8 = 0;
+pub const BILLING_RECEIVED_BYTES: u8 = 1;
+pub const BILLING_SENT_MESSAGES: u8 = 2;
+pub const BILLING_SENT_BYTES: u8 = 3;
+pub const BILLING_BANDWIDTH_BYTES: u8 = 4;
+
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub enum BillingClass {
+    Counter { account_id: u32, metric: u8 },
+    Rollup { period: u32, account_id: u32 },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -199,6 +238,9 @@ pub enum BlobOp {
     Reserve { hash: BlobHash, until: u64 },
     Commit { hash: BlobHash },
     Link { hash: BlobHash },
+    ChunkRef { hash: BlobHash },
+    Quarantine { hash: BlobHash },
+    ScrubCursor,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
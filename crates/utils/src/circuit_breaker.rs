@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::config::{utils::AsKey, Config};
+
+/// Tracks consecutive failures of a remote backend and short-circuits
+/// requests while it is considered down, so that callers fail fast instead
+/// of waiting for connection timeouts. Once the cooldown elapses requests
+/// are let through again and the first one to succeed closes the breaker.
+pub struct CircuitBreaker {
+    name: String,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Default)]
+struct CircuitState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: impl Into<String>, threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            name: name.into(),
+            threshold,
+            cooldown,
+            state: Mutex::new(CircuitState::default()),
+        }
+    }
+
+    pub fn parse(
+        config: &Config,
+        prefix: impl AsKey,
+        name: impl Into<String>,
+    ) -> crate::config::Result<Self> {
+        let prefix = prefix.as_key();
+        Ok(CircuitBreaker::new(
+            name,
+            config.property_or_static((&prefix, "circuit-breaker.threshold"), "5")?,
+            config.property_or_static((&prefix, "circuit-breaker.cooldown"), "30s")?,
+        ))
+    }
+
+    /// Returns `true` while the backend is considered down.
+    pub fn is_open(&self) -> bool {
+        self.threshold > 0
+            && self
+                .state
+                .lock()
+                .open_until
+                .map_or(false, |open_until| open_until > Instant::now())
+    }
+
+    pub fn success(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock();
+        if state.failures >= self.threshold {
+            tracing::info!(
+                context = "circuit-breaker",
+                event = "recovered",
+                backend = self.name.as_str(),
+                "Backend is available again."
+            );
+        }
+        state.failures = 0;
+        state.open_until = None;
+    }
+
+    pub fn failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.threshold {
+            // Open the breaker, or reopen it if the request probing the backend failed
+            if state.failures == self.threshold || state.open_until.is_some() {
+                tracing::warn!(
+                    context = "circuit-breaker",
+                    event = "open",
+                    backend = self.name.as_str(),
+                    failures = state.failures,
+                    cooldown = ?self.cooldown,
+                    "Backend is unavailable, failing requests until the cooldown elapses."
+                );
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Runs a request unless the breaker is open, in which case the error
+    /// returned by `unavailable` is returned without contacting the backend.
+    pub async fn call<T, E>(
+        &self,
+        request: impl Future<Output = Result<T, E>>,
+        unavailable: impl FnOnce() -> E,
+    ) -> Result<T, E> {
+        if !self.is_open() {
+            self.track(request.await)
+        } else {
+            Err(unavailable())
+        }
+    }
+
+    /// Records the outcome of a request and passes the result through.
+    pub fn track<T, E>(&self, result: Result<T, E>) -> Result<T, E> {
+        if result.is_ok() {
+            self.success();
+        } else {
+            self.failure();
+        }
+        result
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::CircuitBreaker;
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = CircuitBreaker::new("test", 3, Duration::from_millis(100));
+        breaker.failure();
+        breaker.failure();
+        assert!(!breaker.is_open());
+        breaker.failure();
+        assert!(breaker.is_open());
+
+        // Probe after the cooldown, a failure reopens the breaker right away
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!breaker.is_open());
+        breaker.failure();
+        assert!(breaker.is_open());
+
+        // A successful probe closes it
+        std::thread::sleep(Duration::from_millis(150));
+        breaker.success();
+        assert!(!breaker.is_open());
+        breaker.failure();
+        assert!(!breaker.is_open());
+
+        // Disabled breakers never open
+        let breaker = CircuitBreaker::new("test", 0, Duration::from_millis(100));
+        for _ in 0..10 {
+            breaker.failure();
+        }
+        assert!(!breaker.is_open());
+    }
+}
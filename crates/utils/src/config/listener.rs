@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use ahash::AHashMap;
 use rustls::{
@@ -44,6 +44,7 @@ use crate::{
     acme::{directory::ACME_TLS_ALPN_NAME, AcmeManager},
     listener::{
         tls::{Certificate, CertificateResolver},
+        transcript::TranscriptManager,
         TcpAcceptor,
     },
     UnwrapFailure,
@@ -102,6 +103,12 @@ impl Config {
             }
         }
 
+        // Share the transcript recorder across listeners
+        servers.transcripts = Arc::new(self.parse_transcripts()?);
+        for server in &mut servers.inner {
+            server.transcripts = servers.transcripts.clone();
+        }
+
         if !servers.inner.is_empty() {
             Ok(servers)
         } else {
@@ -109,6 +116,31 @@ impl Config {
         }
     }
 
+    pub fn parse_transcripts(&self) -> super::Result<TranscriptManager> {
+        let transcripts = TranscriptManager {
+            path: self.value("server.transcript.path").map(PathBuf::from),
+            max_size: self.property_or_static("server.transcript.max-size", "10485760")?,
+            max_age: self.property_or_static("server.transcript.retention.max-age", "7d")?,
+            max_files: self.property_or_static("server.transcript.retention.max-files", "1000")?,
+            ..Default::default()
+        };
+        if let Some(path) = &transcripts.path {
+            std::fs::create_dir_all(path)
+                .map_err(|err| format!("Failed to create transcripts directory {path:?}: {err}"))?;
+        }
+        transcripts.set_filter(
+            self.property_or_static("server.transcript.enable", "false")?,
+            self.values("server.transcript.listeners")
+                .map(|(_, id)| id.to_string())
+                .collect(),
+            self.values("server.transcript.remote-ips")
+                .map(|(_, ip)| ip.to_string())
+                .collect(),
+        )?;
+
+        Ok(transcripts)
+    }
+
     fn parse_server(
         &self,
         id: &str,
@@ -359,7 +391,10 @@ impl Config {
                     .value_or_default(("server.listener", id, "url"), "server.url")
                     .failed(&format!("No 'url' directive found for listener {id:?}"))
                     .to_string(),
-                ServerProtocol::Imap | ServerProtocol::Http | ServerProtocol::ManageSieve => self
+                ServerProtocol::Imap
+                | ServerProtocol::Http
+                | ServerProtocol::ManageSieve
+                | ServerProtocol::Dav => self
                     .value_or_default(("server.listener", id, "url"), "server.url")
                     .unwrap_or_default()
                     .to_string(),
@@ -375,6 +410,7 @@ impl Config {
             acceptor,
             tls_implicit,
             proxy_networks,
+            transcripts: Default::default(),
         })
     }
 }
@@ -393,6 +429,11 @@ impl ParseValue for ServerProtocol {
             Ok(Self::Http)
         } else if value.eq_ignore_ascii_case("managesieve") {
             Ok(Self::ManageSieve)
+        } else if value.eq_ignore_ascii_case("dav")
+            | value.eq_ignore_ascii_case("caldav")
+            | value.eq_ignore_ascii_case("carddav")
+        {
+            Ok(Self::Dav)
         } else {
             Err(format!(
                 "Invalid server protocol type {:?} for property {:?}.",
@@ -26,6 +26,7 @@ pub mod if_block;
 pub mod ipmask;
 pub mod listener;
 pub mod parser;
+pub mod reload;
 pub mod tls;
 pub mod utils;
 
@@ -37,7 +38,7 @@ use tokio::net::TcpSocket;
 use crate::{
     acme::AcmeManager,
     failed,
-    listener::{tls::Certificate, TcpAcceptor},
+    listener::{tls::Certificate, transcript::TranscriptManager, TcpAcceptor},
     UnwrapFailure,
 };
 
@@ -66,6 +67,7 @@ pub struct Server {
     pub acceptor: TcpAcceptor,
     pub tls_implicit: bool,
     pub max_connections: u64,
+    pub transcripts: Arc<TranscriptManager>,
 }
 
 #[derive(Default)]
@@ -73,6 +75,7 @@ pub struct Servers {
     pub inner: Vec<Server>,
     pub certificates: Vec<Arc<Certificate>>,
     pub acme_managers: Vec<Arc<AcmeManager>>,
+    pub transcripts: Arc<TranscriptManager>,
 }
 
 #[derive(Debug)]
@@ -96,6 +99,7 @@ pub enum ServerProtocol {
     Imap,
     Http,
     ManageSieve,
+    Dav,
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
@@ -113,6 +117,7 @@ impl Display for ServerProtocol {
             ServerProtocol::Imap => write!(f, "imap"),
             ServerProtocol::Http => write!(f, "http"),
             ServerProtocol::ManageSieve => write!(f, "managesieve"),
+            ServerProtocol::Dav => write!(f, "dav"),
         }
     }
 }
@@ -120,7 +125,8 @@ impl Display for ServerProtocol {
 pub type Result<T> = std::result::Result<T, String>;
 
 impl Config {
-    pub fn init() -> Self {
+    /// Returns the path of the configuration file passed on the command line.
+    pub fn init_path() -> String {
         let mut config_path = None;
         let mut found_param = false;
 
@@ -142,16 +148,20 @@ impl Config {
             }
         }
 
+        config_path.failed("Missing parameter --config=<path-to-config>.")
+    }
+
+    /// Reads a configuration file together with its included files and
+    /// expands its macros.
+    pub fn from_file(path: &str) -> Result<Self> {
         // Read main configuration file
         let mut config = Config::default();
         config
             .parse(
-                &std::fs::read_to_string(
-                    config_path.failed("Missing parameter --config=<path-to-config>."),
-                )
-                .failed("Could not read configuration file"),
+                &std::fs::read_to_string(path)
+                    .map_err(|err| format!("Could not read configuration file: {err}"))?,
             )
-            .failed("Invalid configuration file");
+            .map_err(|err| format!("Invalid configuration file: {err}"))?;
 
         // Extract macros and includes
         let mut keys = BTreeMap::new();
@@ -171,20 +181,20 @@ impl Config {
         // Include files
         config.keys = keys;
         for mut include in includes {
-            include.replace_macros("include.files", &macros);
+            include.replace_macros("include.files", &macros)?;
             config
-                .parse(&std::fs::read_to_string(&include).failed(&format!(
-                    "Could not read included configuration file {include:?}"
-                )))
-                .failed(&format!("Invalid included configuration file {include:?}"));
+                .parse(&std::fs::read_to_string(&include).map_err(|err| {
+                    format!("Could not read included configuration file {include:?}: {err}")
+                })?)
+                .map_err(|err| format!("Invalid included configuration file {include:?}: {err}"))?;
         }
 
         // Replace macros
         for (key, value) in &mut config.keys {
-            value.replace_macros(key, &macros);
+            value.replace_macros(key, &macros)?;
         }
 
-        config
+        Ok(config)
     }
 
     pub fn update(&mut self, config: Self) {
@@ -193,11 +203,11 @@ impl Config {
 }
 
 trait ReplaceMacros: Sized {
-    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>);
+    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) -> Result<()>;
 }
 
 impl ReplaceMacros for String {
-    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) {
+    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) -> Result<()> {
         if self.contains("%{") {
             let mut result = String::with_capacity(self.len());
             let mut value = self.as_str();
@@ -212,12 +222,10 @@ impl ReplaceMacros for String {
                             result.push_str(macro_value);
                             value = rest;
                         } else {
-                            failed(&format!("Unknown macro {macro_name:?} for key {key:?}"));
+                            return Err(format!("Unknown macro {macro_name:?} for key {key:?}"));
                         }
                     } else {
-                        failed(&format!(
-                            "Unterminated macro name {value:?} for key {key:?}"
-                        ));
+                        return Err(format!("Unterminated macro name {value:?} for key {key:?}"));
                     }
                 } else {
                     result.push_str(value);
@@ -227,5 +235,7 @@ impl ReplaceMacros for String {
 
             *self = result;
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,105 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use super::Config;
+
+/// Settings that can be swapped on a running server without a restart.
+pub trait ReloadableConfig: Send + Sync {
+    /// Parses the reloadable settings from `config` and replaces the active
+    /// ones. Nothing is replaced when any of the settings fail to parse.
+    fn reload(&self, config: &Config) -> super::Result<()>;
+}
+
+/// Keeps the local configuration the server was started with together with
+/// the components that can be reloaded from it.
+pub struct ConfigReloader {
+    local: RwLock<Config>,
+    path: RwLock<Option<String>>,
+    targets: RwLock<Vec<(&'static str, Arc<dyn ReloadableConfig>)>>,
+}
+
+/// Outcome of a reload. Components are reloaded independently, so some of
+/// them may have been applied even when others failed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadResult {
+    pub applied: Vec<&'static str>,
+    pub failed: Vec<(&'static str, String)>,
+}
+
+impl ConfigReloader {
+    pub fn new(local: Config) -> Self {
+        Self {
+            local: RwLock::new(local),
+            path: RwLock::new(None),
+            targets: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Sets the configuration file the local configuration was read from, so
+    /// that it is read again on every reload.
+    pub fn set_path(&self, path: impl Into<String>) {
+        *self.path.write() = Some(path.into());
+    }
+
+    pub fn register(&self, name: &'static str, target: Arc<dyn ReloadableConfig>) {
+        self.targets.write().push((name, target));
+    }
+
+    /// Reads the configuration file again, if known, and overlays the settings
+    /// stored in the database on top of it. The previous local configuration
+    /// is kept when the file cannot be read.
+    pub fn build(&self, stored: Config) -> super::Result<Config> {
+        let path = self.path.read().clone();
+        if let Some(path) = path {
+            *self.local.write() = Config::from_file(&path)?;
+        }
+        let mut config = self.local.read().clone();
+        config.update(stored);
+        Ok(config)
+    }
+
+    /// Reloads all registered components, a failure in one of them does not
+    /// prevent the others from being reloaded.
+    pub fn reload(&self, config: &Config) -> ReloadResult {
+        let mut result = ReloadResult::default();
+
+        for (name, target) in self.targets.read().iter() {
+            match target.reload(config) {
+                Ok(_) => result.applied.push(*name),
+                Err(err) => result.failed.push((*name, err)),
+            }
+        }
+
+        result
+    }
+}
+
+impl ReloadResult {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
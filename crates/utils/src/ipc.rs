@@ -25,7 +25,7 @@ use std::borrow::Cow;
 
 use tokio::sync::oneshot;
 
-use crate::BlobHash;
+use crate::{locale::AccountLocale, BlobHash};
 
 #[derive(Debug)]
 pub enum DeliveryEvent {
@@ -33,11 +33,16 @@ pub enum DeliveryEvent {
         message: IngestMessage,
         result_tx: oneshot::Sender<Vec<DeliveryResult>>,
     },
+    Locale {
+        address: String,
+        result_tx: oneshot::Sender<Option<AccountLocale>>,
+    },
     Stop,
 }
 
 #[derive(Debug)]
 pub struct IngestMessage {
+    pub queue_id: u64,
     pub sender_address: String,
     pub recipients: Vec<String>,
     pub message_blob: BlobHash,
@@ -26,14 +26,19 @@ use std::{collections::HashMap, sync::Arc};
 use config::Config;
 
 pub mod acme;
+pub mod circuit_breaker;
 pub mod codec;
 pub mod config;
 pub mod expr;
 pub mod ipc;
 pub mod listener;
+pub mod locale;
 pub mod map;
+pub mod metrics;
 pub mod snowflake;
 pub mod suffixlist;
+pub mod trace_id;
+pub mod worker;
 
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::WithExportConfig;
@@ -67,6 +72,14 @@ impl BlobHash {
     pub fn as_slice(&self) -> &[u8] {
         self.0.as_ref()
     }
+
+    pub fn from_parts(parts: &[&[u8]]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        BlobHash(hasher.finalize().into())
+    }
 }
 
 impl From<&[u8]> for BlobHash {
@@ -298,6 +311,11 @@ pub async fn wait_for_shutdown(message: &str) {
     tracing::info!(message);
 }
 
+/// Compares two secrets in constant time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 pub fn rustls_client_config(allow_invalid_certs: bool) -> ClientConfig {
     let config = ClientConfig::builder();
 
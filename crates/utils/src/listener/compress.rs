@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    borrow::Cow,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{
+    transcript::{Transcript, TranscriptStream},
+    SessionStream,
+};
+
+const BUF_SIZE: usize = 8192;
+
+/// Raw DEFLATE (RFC 1951) compression layered on top of a session stream,
+/// as negotiated by the IMAP COMPRESS (RFC 4978) extension. Output is sync
+/// flushed on every flush so that responses are never held back.
+pub struct DeflateStream<T> {
+    inner: T,
+    compress: Compress,
+    decompress: Decompress,
+    read_buf: Box<[u8]>,
+    read_pos: usize,
+    read_len: usize,
+    write_buf: Vec<u8>,
+    write_pos: usize,
+}
+
+impl<T: SessionStream> DeflateStream<T> {
+    /// Wraps a stream, recording the uncompressed data from now on.
+    pub fn wrap(mut inner: T) -> TranscriptStream<Self> {
+        let mut transcript = inner.take_transcript();
+        if let Some(transcript) = &mut transcript {
+            transcript.note("DEFLATE compression enabled");
+        }
+        TranscriptStream::new(DeflateStream::new(inner), transcript)
+    }
+}
+
+impl<T> DeflateStream<T> {
+    pub fn new(inner: T) -> Self {
+        DeflateStream {
+            inner,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            read_buf: vec![0; BUF_SIZE].into_boxed_slice(),
+            read_pos: 0,
+            read_len: 0,
+            write_buf: Vec::with_capacity(BUF_SIZE),
+            write_pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn deflate(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        loop {
+            self.write_buf.reserve(input.len().max(BUF_SIZE));
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut self.write_buf, flush)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            input = &input[(self.compress.total_in() - total_in) as usize..];
+
+            // Deflate is done once it stops filling the output buffer
+            if input.is_empty() && self.write_buf.len() < self.write_buf.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> DeflateStream<T> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.write_pos..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                Poll::Ready(Ok(written)) => {
+                    self.write_pos += written;
+                }
+                Poll::Ready(Err(err)) => {
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => {
+                    return Poll::Pending;
+                }
+            }
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for DeflateStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.read_pos < this.read_len {
+                let (total_in, total_out) =
+                    (this.decompress.total_in(), this.decompress.total_out());
+                this.decompress
+                    .decompress(
+                        &this.read_buf[this.read_pos..this.read_len],
+                        buf.initialize_unfilled(),
+                        FlushDecompress::Sync,
+                    )
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                this.read_pos += (this.decompress.total_in() - total_in) as usize;
+                let produced = (this.decompress.total_out() - total_out) as usize;
+                if produced > 0 || buf.remaining() == 0 {
+                    buf.advance(produced);
+                    return Poll::Ready(Ok(()));
+                } else if this.read_pos < this.read_len {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid DEFLATE stream",
+                    )));
+                }
+            }
+
+            // Fetch more compressed data
+            let mut read_buf = ReadBuf::new(&mut this.read_buf);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(())) => {
+                    let read = read_buf.filled().len();
+                    if read == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                    this.read_pos = 0;
+                    this.read_len = read;
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for DeflateStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {
+                this.deflate(buf, FlushCompress::None)?;
+                Poll::Ready(Ok(buf.len()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        if this.write_pos == 0 {
+            this.deflate(&[], FlushCompress::Sync)?;
+        }
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            result => result,
+        }
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        match self.as_mut().poll_flush(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.get_mut().inner).poll_shutdown(cx),
+            result => result,
+        }
+    }
+}
+
+impl<T: SessionStream> SessionStream for DeflateStream<T> {
+    fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+
+    fn tls_version_and_cipher(&self) -> (Cow<'static, str>, Cow<'static, str>) {
+        self.inner.tls_version_and_cipher()
+    }
+
+    fn tls_exporter(&self) -> Option<Vec<u8>> {
+        self.inner.tls_exporter()
+    }
+
+    fn take_transcript(&mut self) -> Option<Transcript> {
+        self.inner.take_transcript()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::DeflateStream;
+
+    #[tokio::test]
+    async fn deflate_roundtrip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = DeflateStream::new(client);
+        let mut server = DeflateStream::new(server);
+
+        for message in [
+            b"a NOOP\r\n".to_vec(),
+            b"b FETCH 1:* (FLAGS)\r\n".repeat(500),
+            b"c LOGOUT\r\n".to_vec(),
+        ] {
+            let writer = async {
+                client.write_all(&message).await.unwrap();
+                client.flush().await.unwrap();
+            };
+            let reader = async {
+                let mut received = vec![0u8; message.len()];
+                server.read_exact(&mut received).await.unwrap();
+                received
+            };
+            let (_, received) = tokio::join!(writer, reader);
+            assert_eq!(received, message);
+        }
+
+        // Sync flushes make every response readable without closing the stream
+        server.write_all(b"* OK done\r\n").await.unwrap();
+        server.flush().await.unwrap();
+        let mut received = [0u8; 11];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"* OK done\r\n");
+    }
+}
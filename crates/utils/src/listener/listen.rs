@@ -41,11 +41,13 @@ use crate::{
     config::{Config, Listener, Server, ServerProtocol, Servers},
     failed,
     listener::SessionData,
+    metrics::server::server_metrics,
     UnwrapFailure,
 };
 
 use super::{
-    limiter::ConcurrencyLimiter, ServerInstance, SessionManager, SessionStream, TcpAcceptorResult,
+    limiter::ConcurrencyLimiter, transcript::TranscriptStream, ServerInstance, SessionManager,
+    SessionStream, TcpAcceptorResult,
 };
 
 impl Server {
@@ -63,9 +65,11 @@ impl Server {
             hostname: self.hostname,
             acceptor: self.acceptor,
             proxy_networks: self.proxy_networks,
+            transcripts: self.transcripts,
             limiter: ConcurrencyLimiter::new(self.max_connections),
             shutdown_rx,
         });
+        server_metrics().register_listener(&instance.id, instance.limiter.concurrent.clone());
         let is_tls = self.tls_implicit;
         let has_proxies = !instance.proxy_networks.is_empty();
 
@@ -193,6 +197,7 @@ impl BuildSession for Arc<ServerInstance> {
 
         // Check if blocked
         if manager.is_ip_blocked(&remote_ip) {
+            server_metrics().connection_rejected(&self.id);
             tracing::debug!(
                 context = "listener",
                 event = "blocked",
@@ -205,6 +210,7 @@ impl BuildSession for Arc<ServerInstance> {
             None
         } else if let Some(in_flight) = self.limiter.is_allowed() {
             // Enforce concurrency
+            server_metrics().connection_accepted(&self.id);
             SessionData {
                 stream,
                 in_flight,
@@ -214,6 +220,7 @@ impl BuildSession for Arc<ServerInstance> {
                     protocol = ?self.protocol,
                     remote.ip = remote_ip.to_string(),
                     remote.port = remote_port,
+                    trace_id = tracing::field::Empty,
                 ),
                 local_ip,
                 remote_ip,
@@ -222,6 +229,7 @@ impl BuildSession for Arc<ServerInstance> {
             }
             .into()
         } else {
+            server_metrics().connection_rejected(&self.id);
             tracing::info!(
                 context = "throttle",
                 event = "too-many-requests",
@@ -332,12 +340,18 @@ impl Listener {
 impl ServerInstance {
     pub async fn tls_accept<T: SessionStream>(
         &self,
-        stream: T,
+        mut stream: T,
         span: &Span,
-    ) -> Result<TlsStream<T>, ()> {
+    ) -> Result<TranscriptStream<TlsStream<T>>, ()> {
+        // Record the decrypted stream from now on
+        let mut transcript = stream.take_transcript();
+
         match self.acceptor.accept(stream).await {
             TcpAcceptorResult::Tls(accept) => match accept.await {
                 Ok(stream) => {
+                    if let Some(transcript) = &mut transcript {
+                        transcript.note("TLS handshake completed");
+                    }
                     tracing::info!(
                         parent: span,
                         context = "tls",
@@ -345,9 +359,12 @@ impl ServerInstance {
                         version = ?stream.get_ref().1.protocol_version().unwrap_or(rustls::ProtocolVersion::TLSv1_3),
                         cipher = ?stream.get_ref().1.negotiated_cipher_suite().unwrap_or(TLS13_AES_128_GCM_SHA256),
                     );
-                    Ok(stream)
+                    Ok(TranscriptStream::new(stream, transcript))
                 }
                 Err(err) => {
+                    if let Some(transcript) = &mut transcript {
+                        transcript.note(&format!("TLS handshake failed: {err}"));
+                    }
                     tracing::debug!(
                         parent: span,
                         context = "tls",
@@ -35,12 +35,17 @@ use tokio::{
 };
 use tokio_rustls::{Accept, TlsAcceptor};
 
-use self::limiter::{ConcurrencyLimiter, InFlight};
+use self::{
+    limiter::{ConcurrencyLimiter, InFlight},
+    transcript::{Transcript, TranscriptManager, TranscriptStream},
+};
 
+pub mod compress;
 pub mod limiter;
 pub mod listen;
 pub mod stream;
 pub mod tls;
+pub mod transcript;
 
 pub struct ServerInstance {
     pub id: String,
@@ -51,6 +56,7 @@ pub struct ServerInstance {
     pub acceptor: TcpAcceptor,
     pub limiter: ConcurrencyLimiter,
     pub proxy_networks: Vec<IpAddrMask>,
+    pub transcripts: Arc<TranscriptManager>,
     pub shutdown_rx: watch::Receiver<bool>,
 }
 
@@ -89,19 +95,47 @@ pub struct SessionData<T: SessionStream> {
 pub trait SessionStream: AsyncRead + AsyncWrite + Unpin + 'static + Sync + Send {
     fn is_tls(&self) -> bool;
     fn tls_version_and_cipher(&self) -> (Cow<'static, str>, Cow<'static, str>);
+
+    /// Returns the `tls-exporter` channel binding data (RFC 9266), which is
+    /// only available on TLS 1.3 sessions.
+    fn tls_exporter(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Detaches the transcript recorder, used when upgrading to TLS so that
+    /// the decrypted stream is recorded instead.
+    fn take_transcript(&mut self) -> Option<Transcript> {
+        None
+    }
 }
 
 pub trait SessionManager: Sync + Send + 'static + Clone {
-    fn spawn<T: SessionStream>(&self, mut session: SessionData<T>, is_tls: bool) {
+    fn spawn<T: SessionStream>(&self, session: SessionData<T>, is_tls: bool) {
         let manager = self.clone();
+        let trace_id = crate::trace_id::generate();
+        session
+            .span
+            .record("trace_id", format!("{trace_id:016x}").as_str());
+
+        tokio::spawn(crate::trace_id::scope(trace_id, async move {
+            let transcript = session.instance.transcripts.start(
+                &session.instance.id,
+                session.remote_ip,
+                session.remote_port,
+            );
 
-        tokio::spawn(async move {
             if is_tls {
                 match session.instance.acceptor.accept(session.stream).await {
                     TcpAcceptorResult::Tls(accept) => match accept.await {
                         Ok(stream) => {
                             let session = SessionData {
-                                stream,
+                                stream: TranscriptStream::new(
+                                    stream,
+                                    transcript.map(|mut transcript| {
+                                        transcript.note("TLS handshake completed");
+                                        transcript
+                                    }),
+                                ),
                                 local_ip: session.local_ip,
                                 remote_ip: session.remote_ip,
                                 remote_port: session.remote_port,
@@ -112,6 +146,9 @@ pub trait SessionManager: Sync + Send + 'static + Clone {
                             manager.handle(session).await;
                         }
                         Err(err) => {
+                            if let Some(mut transcript) = transcript {
+                                transcript.note(&format!("TLS handshake failed: {err}"));
+                            }
                             tracing::debug!(
                                 context = "tls",
                                 event = "error",
@@ -124,15 +161,32 @@ pub trait SessionManager: Sync + Send + 'static + Clone {
                         }
                     },
                     TcpAcceptorResult::Plain(stream) => {
-                        session.stream = stream;
+                        let session = SessionData {
+                            stream: TranscriptStream::new(stream, transcript),
+                            local_ip: session.local_ip,
+                            remote_ip: session.remote_ip,
+                            remote_port: session.remote_port,
+                            span: session.span,
+                            in_flight: session.in_flight,
+                            instance: session.instance,
+                        };
                         manager.handle(session).await;
                     }
                     TcpAcceptorResult::Close => (),
                 }
             } else {
+                let session = SessionData {
+                    stream: TranscriptStream::new(session.stream, transcript),
+                    local_ip: session.local_ip,
+                    remote_ip: session.remote_ip,
+                    remote_port: session.remote_port,
+                    span: session.span,
+                    in_flight: session.in_flight,
+                    instance: session.instance,
+                };
                 manager.handle(session).await;
             }
-        });
+        }));
     }
 
     fn handle<T: SessionStream>(
@@ -30,7 +30,7 @@ use tokio::{
 };
 use tokio_rustls::server::TlsStream;
 
-use super::SessionStream;
+use super::{transcript::Transcript, SessionStream};
 
 impl SessionStream for TcpStream {
     fn is_tls(&self) -> bool {
@@ -79,6 +79,21 @@ impl<T: SessionStream> SessionStream for TlsStream<T> {
             .into(),
         )
     }
+
+    fn tls_exporter(&self) -> Option<Vec<u8>> {
+        let (_, conn) = self.get_ref();
+
+        if conn.protocol_version() == Some(rustls::ProtocolVersion::TLSv1_3) {
+            conn.export_keying_material(vec![0u8; 32], b"EXPORTER-Channel-Binding", None)
+                .ok()
+        } else {
+            None
+        }
+    }
+
+    fn take_transcript(&mut self) -> Option<Transcript> {
+        self.get_mut().0.take_transcript()
+    }
 }
 
 impl SessionStream for ProxiedStream<TcpStream> {
@@ -0,0 +1,487 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::RwLock;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::config::{ipmask::IpAddrMask, utils::ParseValue};
+
+use super::SessionStream;
+
+const MAX_LINE_LEN: usize = 4096;
+const PURGE_INTERVAL: u64 = 60;
+
+#[derive(Debug, Default)]
+pub struct TranscriptManager {
+    pub path: Option<PathBuf>,
+    pub max_size: usize,
+    pub max_age: Duration,
+    pub max_files: usize,
+    pub filter: RwLock<TranscriptFilter>,
+    pub last_purge: AtomicU64,
+    pub sequence: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TranscriptFilter {
+    pub enable: bool,
+    pub listeners: Vec<String>,
+    pub remote_ips: Vec<String>,
+    pub masks: Vec<IpAddrMask>,
+}
+
+pub struct Transcript {
+    file: BufWriter<File>,
+    written: usize,
+    max_size: usize,
+    client_buf: Vec<u8>,
+    server_buf: Vec<u8>,
+    auth: AuthState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthState {
+    None,
+    Started,
+    Challenge,
+    Literal,
+}
+
+pub struct TranscriptStream<T> {
+    inner: T,
+    transcript: Option<Transcript>,
+}
+
+impl TranscriptManager {
+    pub fn start(
+        &self,
+        listener_id: &str,
+        remote_ip: IpAddr,
+        remote_port: u16,
+    ) -> Option<Transcript> {
+        let path = self.path.as_ref()?;
+
+        // Check whether this session should be recorded
+        {
+            let filter = self.filter.read();
+            if !filter.enable
+                || (!filter.listeners.is_empty()
+                    && !filter.listeners.iter().any(|id| id == listener_id))
+                || (!filter.masks.is_empty()
+                    && !filter.masks.iter().any(|mask| mask.matches(&remote_ip)))
+            {
+                return None;
+            }
+        }
+
+        // Enforce retention limits
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let last_purge = self.last_purge.load(Ordering::Relaxed);
+        if last_purge + PURGE_INTERVAL <= now
+            && self
+                .last_purge
+                .compare_exchange(last_purge, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let path = path.clone();
+            let max_age = self.max_age;
+            let max_files = self.max_files;
+            tokio::task::spawn_blocking(move || purge_transcripts(&path, max_age, max_files));
+        }
+
+        let file_name = format!(
+            "{}-{}-{}-{}-{}.txt",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            self.sequence.fetch_add(1, Ordering::Relaxed),
+            listener_id,
+            remote_ip.to_string().replace(':', "_"),
+            remote_port
+        );
+        match File::create(path.join(&file_name)) {
+            Ok(file) => {
+                let mut transcript = Transcript {
+                    file: BufWriter::new(file),
+                    written: 0,
+                    max_size: self.max_size,
+                    client_buf: Vec::new(),
+                    server_buf: Vec::new(),
+                    auth: AuthState::None,
+                };
+                transcript.note(&format!(
+                    "Session started on {} by {}:{} at {}",
+                    listener_id,
+                    remote_ip,
+                    remote_port,
+                    chrono::Utc::now().to_rfc3339()
+                ));
+                Some(transcript)
+            }
+            Err(err) => {
+                tracing::warn!(
+                    context = "transcript",
+                    event = "error",
+                    path = ?path,
+                    "Failed to create transcript file {file_name:?}: {err}"
+                );
+                None
+            }
+        }
+    }
+
+    pub fn set_filter(
+        &self,
+        enable: bool,
+        listeners: Vec<String>,
+        remote_ips: Vec<String>,
+    ) -> Result<(), String> {
+        if enable && self.path.is_none() {
+            return Err("Transcript path is not configured.".to_string());
+        }
+
+        let masks = remote_ips
+            .iter()
+            .map(|ip| IpAddrMask::parse_value("server.transcript.remote-ips", ip))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        *self.filter.write() = TranscriptFilter {
+            enable,
+            listeners,
+            remote_ips,
+            masks,
+        };
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> std::io::Result<Vec<(String, u64, u64)>> {
+        let mut files = Vec::new();
+        if let Some(path) = &self.path {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_file() {
+                    files.push((
+                        entry.file_name().to_string_lossy().into_owned(),
+                        metadata.len(),
+                        metadata
+                            .modified()
+                            .ok()
+                            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                            .map_or(0, |d| d.as_secs()),
+                    ));
+                }
+            }
+        }
+        files.sort_unstable();
+
+        Ok(files)
+    }
+
+    pub fn read(&self, name: &str) -> Option<std::io::Result<Vec<u8>>> {
+        // Do not allow path traversal
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return None;
+        }
+
+        let path = self.path.as_ref()?.join(name);
+        if path.is_file() {
+            Some(std::fs::read(path))
+        } else {
+            None
+        }
+    }
+}
+
+fn purge_transcripts(path: &Path, max_age: Duration, max_files: usize) {
+    let mut files = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                if metadata.is_file() {
+                    Some((metadata.modified().ok()?, entry.path()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            tracing::warn!(
+                context = "transcript",
+                event = "error",
+                path = ?path,
+                "Failed to read transcripts directory: {err}"
+            );
+            return;
+        }
+    };
+
+    // Remove expired transcripts, then the oldest ones over the limit
+    files.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+    let now = SystemTime::now();
+    for (pos, (modified, path)) in files.into_iter().enumerate() {
+        if pos >= max_files
+            || now
+                .duration_since(modified)
+                .map_or(false, |age| age > max_age)
+        {
+            if let Err(err) = std::fs::remove_file(&path) {
+                tracing::debug!(
+                    context = "transcript",
+                    event = "error",
+                    path = ?path,
+                    "Failed to remove transcript: {err}"
+                );
+            }
+        }
+    }
+}
+
+impl Transcript {
+    pub fn client(&mut self, bytes: &[u8]) {
+        self.client_buf.extend_from_slice(bytes);
+        while let Some(line) = next_line(&mut self.client_buf) {
+            let line = self.redact(String::from_utf8_lossy(&line));
+            self.write_line("C: ", &line);
+        }
+    }
+
+    pub fn server(&mut self, bytes: &[u8]) {
+        self.server_buf.extend_from_slice(bytes);
+        while let Some(line) = next_line(&mut self.server_buf) {
+            let line = String::from_utf8_lossy(&line);
+            if self.auth != AuthState::None {
+                self.auth = if is_challenge(&line) {
+                    AuthState::Challenge
+                } else {
+                    AuthState::None
+                };
+            }
+            self.write_line("S: ", &line);
+        }
+    }
+
+    pub fn note(&mut self, note: &str) {
+        self.write_line("# ", note);
+        let _ = self.file.flush();
+    }
+
+    fn write_line(&mut self, prefix: &str, line: &str) {
+        if self.written < self.max_size {
+            self.written += prefix.len() + line.len() + 1;
+            if self.written < self.max_size {
+                let _ = writeln!(self.file, "{prefix}{line}");
+            } else {
+                let _ = writeln!(self.file, "# Transcript size limit reached.");
+                let _ = self.file.flush();
+            }
+        }
+    }
+
+    fn redact<'x>(&mut self, line: Cow<'x, str>) -> Cow<'x, str> {
+        // Client response to an authentication challenge
+        if self.auth == AuthState::Challenge {
+            self.auth = AuthState::Started;
+            return "[redacted]".into();
+        }
+
+        // Credentials sent as a literal, which might be followed by another one
+        if self.auth == AuthState::Literal {
+            if !ends_with_literal(&line) {
+                self.auth = AuthState::Started;
+            }
+            return "[redacted]".into();
+        }
+
+        // HTTP credentials
+        if let Some((name, _)) = line.split_once(':') {
+            if ["authorization", "proxy-authorization", "cookie"]
+                .iter()
+                .any(|header| name.eq_ignore_ascii_case(header))
+            {
+                return format!("{name}: [redacted]").into();
+            }
+        }
+
+        // SMTP, IMAP and ManageSieve credentials, commands might be tagged
+        for (pos, token) in line.split(' ').take(2).enumerate() {
+            if ["AUTH", "AUTHENTICATE", "LOGIN"]
+                .iter()
+                .any(|command| token.eq_ignore_ascii_case(command))
+            {
+                self.auth = if ends_with_literal(&line) {
+                    AuthState::Literal
+                } else {
+                    AuthState::Started
+                };
+                let tokens = line.split(' ').collect::<Vec<_>>();
+                return if tokens.len() > pos + 2 {
+                    format!("{} [redacted]", tokens[..pos + 2].join(" ")).into()
+                } else {
+                    line
+                };
+            }
+        }
+
+        line
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        for (prefix, buf) in [
+            ("C: ", std::mem::take(&mut self.client_buf)),
+            ("S: ", std::mem::take(&mut self.server_buf)),
+        ] {
+            if !buf.is_empty() {
+                let line = String::from_utf8_lossy(&buf);
+                let line = if prefix == "C: " {
+                    self.redact(line)
+                } else {
+                    line
+                };
+                self.write_line(prefix, &line);
+            }
+        }
+        self.note("Session closed");
+    }
+}
+
+fn next_line(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut line = if let Some(pos) = buf.iter().position(|&ch| ch == b'\n') {
+        buf.drain(..=pos).collect::<Vec<_>>()
+    } else if buf.len() >= MAX_LINE_LEN {
+        buf.drain(..MAX_LINE_LEN).collect::<Vec<_>>()
+    } else {
+        return None;
+    };
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    Some(line)
+}
+
+fn ends_with_literal(line: &str) -> bool {
+    line.strip_suffix('}')
+        .and_then(|line| line.rsplit_once('{'))
+        .map_or(false, |(_, size)| {
+            let size = size.strip_suffix(['+', '-']).unwrap_or(size);
+            !size.is_empty() && size.bytes().all(|ch| ch.is_ascii_digit())
+        })
+}
+
+fn is_challenge(line: &str) -> bool {
+    line == "334"
+        || line.starts_with("334 ")
+        || line.starts_with('+')
+        || line.starts_with('"')
+        || (line.starts_with('{') && line.ends_with('}'))
+}
+
+impl<T> TranscriptStream<T> {
+    pub fn new(inner: T, transcript: Option<Transcript>) -> Self {
+        TranscriptStream { inner, transcript }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TranscriptStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let offset = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(transcript)) = (&result, &mut this.transcript) {
+            transcript.client(&buf.filled()[offset..]);
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TranscriptStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(written)), Some(transcript)) = (&result, &mut this.transcript) {
+            transcript.server(&buf[..*written]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: SessionStream> SessionStream for TranscriptStream<T> {
+    fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+
+    fn tls_version_and_cipher(&self) -> (Cow<'static, str>, Cow<'static, str>) {
+        self.inner.tls_version_and_cipher()
+    }
+
+    fn tls_exporter(&self) -> Option<Vec<u8>> {
+        self.inner.tls_exporter()
+    }
+
+    fn take_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.take()
+    }
+}
@@ -0,0 +1,292 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use chrono::NaiveDateTime;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Spanish,
+    French,
+    German,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateFormat {
+    #[default]
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountLocale {
+    pub language: Language,
+    pub utc_offset: i32,
+    pub date_format: DateFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocaleText {
+    DsnSubjectDelivered,
+    DsnSubjectDelayed,
+    DsnSubjectFailed,
+    DsnSubjectPartial,
+    DsnSubjectMixed,
+    DsnDelivered,
+    DsnDelayed,
+    DsnFailed,
+    DsnPartial,
+    DsnMixed,
+    DsnSectionDelivered,
+    DsnSectionDelayed,
+    DsnSectionFailed,
+    DsnRetryUntil,
+    VacationBody,
+}
+
+impl Language {
+    pub fn parse(locale: &str) -> Option<Self> {
+        let (language, region) = locale
+            .split_once(['-', '_'])
+            .map_or((locale, None), |(language, region)| {
+                (language, Some(region))
+            });
+        if region.map_or(false, |region| {
+            !(2..=8).contains(&region.len()) || !region.chars().all(|ch| ch.is_ascii_alphanumeric())
+        }) {
+            return None;
+        }
+
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::English),
+            "es" => Some(Language::Spanish),
+            "fr" => Some(Language::French),
+            "de" => Some(Language::German),
+            _ => None,
+        }
+    }
+}
+
+impl DateFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "YYYY-MM-DD" => Some(DateFormat::YearMonthDay),
+            "DD/MM/YYYY" => Some(DateFormat::DayMonthYear),
+            "MM/DD/YYYY" => Some(DateFormat::MonthDayYear),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateFormat::YearMonthDay => "YYYY-MM-DD",
+            DateFormat::DayMonthYear => "DD/MM/YYYY",
+            DateFormat::MonthDayYear => "MM/DD/YYYY",
+        }
+    }
+}
+
+// Parses "UTC" or a fixed offset such as "+02:00", returning the offset in seconds
+pub fn parse_utc_offset(value: &str) -> Option<i32> {
+    if value.eq_ignore_ascii_case("utc") || value == "Z" {
+        return Some(0);
+    }
+    let (sign, value) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = value.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours = hours.parse::<i32>().ok().filter(|h| *h <= 14)?;
+    let minutes = minutes.parse::<i32>().ok().filter(|m| *m < 60)?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+pub fn format_utc_offset(offset: i32) -> String {
+    if offset == 0 {
+        "UTC".to_string()
+    } else {
+        format!(
+            "{}{:02}:{:02}",
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 3600,
+            (offset.abs() % 3600) / 60
+        )
+    }
+}
+
+impl AccountLocale {
+    pub fn format_date(&self, timestamp: i64) -> String {
+        let date = NaiveDateTime::from_timestamp_opt(timestamp + self.utc_offset as i64, 0)
+            .unwrap_or_default();
+        format!(
+            "{} {}",
+            date.format(match self.date_format {
+                DateFormat::YearMonthDay => "%Y-%m-%d %H:%M",
+                DateFormat::DayMonthYear => "%d/%m/%Y %H:%M",
+                DateFormat::MonthDayYear => "%m/%d/%Y %H:%M",
+            }),
+            format_utc_offset(self.utc_offset)
+        )
+    }
+
+    pub fn text(&self, text: LocaleText) -> &'static str {
+        match self.language {
+            Language::English => match text {
+                LocaleText::DsnSubjectDelivered => "Successfully delivered message",
+                LocaleText::DsnSubjectDelayed => "Warning: Delay in message delivery",
+                LocaleText::DsnSubjectFailed => "Failed to deliver message",
+                LocaleText::DsnSubjectPartial => "Partially delivered message",
+                LocaleText::DsnSubjectMixed => {
+                    "Warning: Temporary and permanent failures during message delivery"
+                }
+                LocaleText::DsnDelivered => {
+                    "Your message has been successfully delivered to the following recipients:"
+                }
+                LocaleText::DsnDelayed => {
+                    "There was a temporary problem delivering your message to the following recipients:"
+                }
+                LocaleText::DsnFailed => {
+                    "Your message could not be delivered to the following recipients:"
+                }
+                LocaleText::DsnPartial => "Your message has been partially delivered:",
+                LocaleText::DsnMixed => "Your message could not be delivered to some recipients:",
+                LocaleText::DsnSectionDelivered => {
+                    "----- Delivery to the following addresses was successful -----"
+                }
+                LocaleText::DsnSectionDelayed => {
+                    "----- There was a temporary problem delivering to these addresses -----"
+                }
+                LocaleText::DsnSectionFailed => {
+                    "----- Delivery to the following addresses failed -----"
+                }
+                LocaleText::DsnRetryUntil => "Delivery will be retried until",
+                LocaleText::VacationBody => "I am away.",
+            },
+            Language::Spanish => match text {
+                LocaleText::DsnSubjectDelivered => "Mensaje entregado correctamente",
+                LocaleText::DsnSubjectDelayed => "Aviso: Retraso en la entrega del mensaje",
+                LocaleText::DsnSubjectFailed => "No se pudo entregar el mensaje",
+                LocaleText::DsnSubjectPartial => "Mensaje entregado parcialmente",
+                LocaleText::DsnSubjectMixed => {
+                    "Aviso: Errores temporales y permanentes durante la entrega del mensaje"
+                }
+                LocaleText::DsnDelivered => {
+                    "Su mensaje ha sido entregado correctamente a los siguientes destinatarios:"
+                }
+                LocaleText::DsnDelayed => {
+                    "Hubo un problema temporal al entregar su mensaje a los siguientes destinatarios:"
+                }
+                LocaleText::DsnFailed => {
+                    "Su mensaje no pudo ser entregado a los siguientes destinatarios:"
+                }
+                LocaleText::DsnPartial => "Su mensaje ha sido entregado parcialmente:",
+                LocaleText::DsnMixed => {
+                    "Su mensaje no pudo ser entregado a algunos destinatarios:"
+                }
+                LocaleText::DsnSectionDelivered => {
+                    "----- La entrega a las siguientes direcciones fue correcta -----"
+                }
+                LocaleText::DsnSectionDelayed => {
+                    "----- Hubo un problema temporal al entregar a estas direcciones -----"
+                }
+                LocaleText::DsnSectionFailed => {
+                    "----- La entrega a las siguientes direcciones ha fallado -----"
+                }
+                LocaleText::DsnRetryUntil => "La entrega se reintentará hasta el",
+                LocaleText::VacationBody => "Estoy ausente.",
+            },
+            Language::French => match text {
+                LocaleText::DsnSubjectDelivered => "Message distribué avec succès",
+                LocaleText::DsnSubjectDelayed => "Avertissement : Retard de distribution du message",
+                LocaleText::DsnSubjectFailed => "Échec de la distribution du message",
+                LocaleText::DsnSubjectPartial => "Message partiellement distribué",
+                LocaleText::DsnSubjectMixed => {
+                    "Avertissement : Échecs temporaires et permanents lors de la distribution du message"
+                }
+                LocaleText::DsnDelivered => {
+                    "Votre message a été distribué avec succès aux destinataires suivants :"
+                }
+                LocaleText::DsnDelayed => {
+                    "Un problème temporaire a empêché la distribution de votre message aux destinataires suivants :"
+                }
+                LocaleText::DsnFailed => {
+                    "Votre message n'a pas pu être distribué aux destinataires suivants :"
+                }
+                LocaleText::DsnPartial => "Votre message a été partiellement distribué :",
+                LocaleText::DsnMixed => {
+                    "Votre message n'a pas pu être distribué à certains destinataires :"
+                }
+                LocaleText::DsnSectionDelivered => {
+                    "----- La distribution aux adresses suivantes a réussi -----"
+                }
+                LocaleText::DsnSectionDelayed => {
+                    "----- Un problème temporaire a empêché la distribution à ces adresses -----"
+                }
+                LocaleText::DsnSectionFailed => {
+                    "----- La distribution aux adresses suivantes a échoué -----"
+                }
+                LocaleText::DsnRetryUntil => "La distribution sera retentée jusqu'au",
+                LocaleText::VacationBody => "Je suis absent.",
+            },
+            Language::German => match text {
+                LocaleText::DsnSubjectDelivered => "Nachricht erfolgreich zugestellt",
+                LocaleText::DsnSubjectDelayed => "Warnung: Verzögerung bei der Zustellung",
+                LocaleText::DsnSubjectFailed => "Nachricht konnte nicht zugestellt werden",
+                LocaleText::DsnSubjectPartial => "Nachricht teilweise zugestellt",
+                LocaleText::DsnSubjectMixed => {
+                    "Warnung: Vorübergehende und dauerhafte Fehler bei der Zustellung"
+                }
+                LocaleText::DsnDelivered => {
+                    "Ihre Nachricht wurde erfolgreich an folgende Empfänger zugestellt:"
+                }
+                LocaleText::DsnDelayed => {
+                    "Bei der Zustellung Ihrer Nachricht an folgende Empfänger ist ein vorübergehendes Problem aufgetreten:"
+                }
+                LocaleText::DsnFailed => {
+                    "Ihre Nachricht konnte nicht an folgende Empfänger zugestellt werden:"
+                }
+                LocaleText::DsnPartial => "Ihre Nachricht wurde teilweise zugestellt:",
+                LocaleText::DsnMixed => {
+                    "Ihre Nachricht konnte nicht an alle Empfänger zugestellt werden:"
+                }
+                LocaleText::DsnSectionDelivered => {
+                    "----- Die Zustellung an folgende Adressen war erfolgreich -----"
+                }
+                LocaleText::DsnSectionDelayed => {
+                    "----- Bei der Zustellung an diese Adressen ist ein vorübergehendes Problem aufgetreten -----"
+                }
+                LocaleText::DsnSectionFailed => {
+                    "----- Die Zustellung an folgende Adressen ist fehlgeschlagen -----"
+                }
+                LocaleText::DsnRetryUntil => "Die Zustellung wird wiederholt bis",
+                LocaleText::VacationBody => "Ich bin nicht im Büro.",
+            },
+        }
+    }
+}
@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ahash::AHashMap;
+use parking_lot::Mutex;
+
+use crate::config::Config;
+
+/// Aggregates the query criteria that could not be answered from an index,
+/// either because they had to be evaluated message by message or because
+/// the property they refer to is not indexed at all.
+pub struct IndexAdvisor {
+    enabled: AtomicBool,
+    patterns: Mutex<AHashMap<PatternKey, UnindexedQuery>>,
+}
+
+type PatternKey = (&'static str, &'static str, String);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UnindexedQuery {
+    pub protocol: &'static str,
+    pub command: &'static str,
+    pub criterion: String,
+    pub suggestion: &'static str,
+    pub count: u64,
+    pub documents: u64,
+    #[serde(rename = "maxDocuments")]
+    pub max_documents: u64,
+    #[serde(rename = "firstSeen")]
+    pub first_seen: u64,
+    #[serde(rename = "lastSeen")]
+    pub last_seen: u64,
+}
+
+static INDEX_ADVISOR: OnceLock<IndexAdvisor> = OnceLock::new();
+
+pub fn index_advisor() -> &'static IndexAdvisor {
+    INDEX_ADVISOR.get_or_init(|| IndexAdvisor {
+        enabled: AtomicBool::new(true),
+        patterns: Mutex::new(AHashMap::new()),
+    })
+}
+
+impl IndexAdvisor {
+    pub fn configure(&self, config: &Config) -> crate::config::Result<()> {
+        self.set_enabled(config.property_or_static("global.metrics.index-advisor.enable", "true")?);
+        Ok(())
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Records a criterion that was not served from an index, along with the
+    /// number of documents that had to be examined to evaluate it.
+    pub fn record(
+        &self,
+        protocol: &'static str,
+        command: &'static str,
+        criterion: impl Into<String>,
+        suggestion: &'static str,
+        documents: u64,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let criterion = criterion.into();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        tracing::debug!(
+            context = "index-advisor",
+            event = "unindexed",
+            protocol = protocol,
+            command = command,
+            criterion = criterion,
+            documents = documents,
+            "Query criterion was not served from an index."
+        );
+
+        self.patterns
+            .lock()
+            .entry((protocol, command, criterion.clone()))
+            .and_modify(|pattern| {
+                pattern.count += 1;
+                pattern.documents += documents;
+                pattern.max_documents = pattern.max_documents.max(documents);
+                pattern.last_seen = now;
+            })
+            .or_insert_with(|| UnindexedQuery {
+                protocol,
+                command,
+                criterion,
+                suggestion,
+                count: 1,
+                documents,
+                max_documents: documents,
+                first_seen: now,
+                last_seen: now,
+            });
+    }
+
+    /// Returns the aggregated criteria, the ones that examined the most
+    /// documents first.
+    pub fn report(&self) -> Vec<UnindexedQuery> {
+        let mut report = self.patterns.lock().values().cloned().collect::<Vec<_>>();
+        report.sort_unstable_by(|a, b| {
+            b.documents
+                .cmp(&a.documents)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.criterion.cmp(&b.criterion))
+                .then_with(|| a.protocol.cmp(b.protocol))
+                .then_with(|| a.command.cmp(b.command))
+        });
+        report
+    }
+
+    pub fn clear(&self) {
+        self.patterns.lock().clear();
+    }
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Write,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+
+use self::slow::{slow_query_log, CommandDetails, OperationTiming};
+
+pub mod advisor;
+pub mod server;
+pub mod slow;
+
+// Bucket upper bounds in microseconds
+const BUCKETS: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+type CommandKey = (&'static str, &'static str);
+type OperationKey = (&'static str, &'static str, &'static str, &'static str);
+
+#[derive(Default)]
+pub struct LatencyMetrics {
+    commands: DashMap<CommandKey, Histogram>,
+    operations: DashMap<OperationKey, Histogram>,
+}
+
+/// Tracks a running command. The command's duration is recorded once the
+/// last reference is dropped, which includes any tasks it was propagated to.
+pub struct CommandScope {
+    protocol: &'static str,
+    command: &'static str,
+    started: Instant,
+    details: Option<CommandDetails>,
+    operations: Mutex<Vec<OperationTiming>>,
+}
+
+tokio::task_local! {
+    static CURRENT_COMMAND: Arc<CommandScope>;
+}
+
+static METRICS: OnceLock<LatencyMetrics> = OnceLock::new();
+
+pub fn latency_metrics() -> &'static LatencyMetrics {
+    METRICS.get_or_init(LatencyMetrics::default)
+}
+
+/// Runs a protocol command, recording its duration and attributing the
+/// store operations it performs to it.
+pub async fn measure_command<F: Future>(
+    protocol: &'static str,
+    command: &'static str,
+    future: F,
+) -> F::Output {
+    measure_command_with(protocol, command, None, future).await
+}
+
+/// Same as [`measure_command`], also adding the command to the slow query
+/// log along with the given details if it exceeds the configured threshold.
+pub async fn measure_command_with<F: Future>(
+    protocol: &'static str,
+    command: &'static str,
+    details: Option<CommandDetails>,
+    future: F,
+) -> F::Output {
+    CURRENT_COMMAND
+        .scope(
+            Arc::new(CommandScope {
+                protocol,
+                command,
+                started: Instant::now(),
+                details,
+                operations: Mutex::new(Vec::new()),
+            }),
+            future,
+        )
+        .await
+}
+
+/// Keeps attributing store operations to the current command when the
+/// future is spawned as a separate task, along with the session's trace id.
+pub fn propagate<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let scope = CURRENT_COMMAND.try_with(|scope| scope.clone()).ok();
+    crate::trace_id::propagate(async move {
+        if let Some(scope) = scope {
+            CURRENT_COMMAND.scope(scope, future).await
+        } else {
+            future.await
+        }
+    })
+}
+
+/// Records the duration of a store operation under the current command.
+pub fn record_operation(backend: &'static str, operation: &'static str, elapsed: Duration) {
+    let (protocol, command) = CURRENT_COMMAND
+        .try_with(|scope| {
+            if scope.details.is_some() {
+                scope.add_operation(backend, operation, elapsed);
+            }
+            (scope.protocol, scope.command)
+        })
+        .unwrap_or(("internal", "none"));
+    latency_metrics()
+        .operations
+        .entry((protocol, command, backend, operation))
+        .or_default()
+        .observe(elapsed);
+}
+
+impl CommandScope {
+    fn add_operation(&self, backend: &'static str, operation: &'static str, elapsed: Duration) {
+        let elapsed = elapsed.as_micros() as u64;
+        let mut operations = self.operations.lock();
+        if let Some(timing) = operations
+            .iter_mut()
+            .find(|timing| timing.backend == backend && timing.operation == operation)
+        {
+            timing.count += 1;
+            timing.elapsed += elapsed;
+        } else {
+            operations.push(OperationTiming {
+                backend,
+                operation,
+                count: 1,
+                elapsed,
+            });
+        }
+    }
+}
+
+impl Drop for CommandScope {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        latency_metrics()
+            .commands
+            .entry((self.protocol, self.command))
+            .or_default()
+            .observe(elapsed);
+        if let Some(details) = self.details.take() {
+            slow_query_log().record(
+                self.protocol,
+                self.command,
+                details,
+                std::mem::take(self.operations.get_mut()),
+                elapsed,
+            );
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        if let Some(bucket) = BUCKETS.iter().position(|bound| micros <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"{}\"}} {cumulative}",
+                *bound as f64 / 1_000_000.0
+            );
+        }
+        let count = self.count();
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(
+            out,
+            "{name}_sum{{{labels}}} {}",
+            self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+impl LatencyMetrics {
+    pub fn command(&self, protocol: &str, command: &str) -> u64 {
+        self.commands
+            .iter()
+            .find(|entry| entry.key().0 == protocol && entry.key().1 == command)
+            .map_or(0, |entry| entry.count())
+    }
+
+    pub fn operation(&self, protocol: &str, command: &str, operation: &str) -> u64 {
+        self.operations
+            .iter()
+            .filter(|entry| {
+                let (p, c, _, o) = entry.key();
+                *p == protocol && *c == command && *o == operation
+            })
+            .map(|entry| entry.count())
+            .sum()
+    }
+
+    /// Renders all histograms in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let mut commands = self.commands.iter().collect::<Vec<_>>();
+        commands.sort_unstable_by_key(|entry| *entry.key());
+        out.push_str("# HELP command_duration_seconds Duration of protocol commands.\n");
+        out.push_str("# TYPE command_duration_seconds histogram\n");
+        for entry in commands {
+            let (protocol, command) = entry.key();
+            entry.write_prometheus(
+                &mut out,
+                "command_duration_seconds",
+                &format!("protocol=\"{protocol}\",command=\"{command}\""),
+            );
+        }
+
+        let mut operations = self.operations.iter().collect::<Vec<_>>();
+        operations.sort_unstable_by_key(|entry| *entry.key());
+        out.push_str(
+            "# HELP store_operation_duration_seconds Duration of store operations by command.\n",
+        );
+        out.push_str("# TYPE store_operation_duration_seconds histogram\n");
+        for entry in operations {
+            let (protocol, command, backend, operation) = entry.key();
+            entry.write_prometheus(
+                &mut out,
+                "store_operation_duration_seconds",
+                &format!(
+                    "protocol=\"{protocol}\",command=\"{command}\",backend=\"{backend}\",operation=\"{operation}\""
+                ),
+            );
+        }
+
+        crate::worker::write_prometheus(&mut out);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        advisor::index_advisor,
+        latency_metrics, measure_command, measure_command_with, propagate, record_operation,
+        slow::{sanitize_arguments, slow_query_log, CommandDetails, OperationTiming},
+    };
+
+    #[tokio::test]
+    async fn command_metrics() {
+        measure_command("test", "FETCH", async {
+            record_operation("sqlite", "get_bitmap", Duration::from_millis(2));
+            tokio::spawn(propagate(async {
+                record_operation("sqlite", "get_value", Duration::from_micros(50));
+            }))
+            .await
+            .unwrap();
+        })
+        .await;
+        record_operation("sqlite", "write", Duration::from_millis(1));
+
+        let metrics = latency_metrics();
+        assert_eq!(metrics.command("test", "FETCH"), 1);
+        assert_eq!(metrics.operation("test", "FETCH", "get_bitmap"), 1);
+        assert_eq!(metrics.operation("test", "FETCH", "get_value"), 1);
+        assert_eq!(metrics.operation("internal", "none", "write"), 1);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains(
+            "store_operation_duration_seconds_bucket{protocol=\"test\",command=\"FETCH\",backend=\"sqlite\",operation=\"get_bitmap\",le=\"0.0025\"} 1"
+        ));
+        assert!(
+            text.contains("command_duration_seconds_count{protocol=\"test\",command=\"FETCH\"} 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_query_log_entries() {
+        let log = slow_query_log();
+        log.set_threshold(Some(Duration::from_millis(10)), 2);
+
+        for (command, delay) in [("FAST", 0), ("SLOW1", 20), ("SLOW2", 20), ("SLOW3", 20)] {
+            measure_command_with(
+                "slow-test",
+                command,
+                CommandDetails::new(Some(1), || {
+                    sanitize_arguments(r#"Filter { text: "secret \"words\"", limit: 10 }"#)
+                }),
+                async {
+                    record_operation("sqlite", "get_bitmap", Duration::from_millis(2));
+                    record_operation("sqlite", "get_bitmap", Duration::from_millis(3));
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                },
+            )
+            .await;
+        }
+
+        let entries = log.entries();
+        log.set_threshold(None, 2);
+        log.clear();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>(),
+            ["SLOW3", "SLOW2"]
+        );
+        assert_eq!(entries[0].account_id, Some(1));
+        assert_eq!(entries[0].arguments, r#"Filter { text: "…", limit: 10 }"#);
+        assert_eq!(
+            entries[0].operations,
+            [OperationTiming {
+                backend: "sqlite",
+                operation: "get_bitmap",
+                count: 2,
+                elapsed: 5000,
+            }]
+        );
+        assert!(CommandDetails::new(None, String::new).is_none());
+    }
+
+    #[test]
+    fn index_advisor_report() {
+        let advisor = index_advisor();
+        for (criterion, documents) in [("modseq", 100), ("header:x-spam", 0), ("modseq", 300)] {
+            advisor.record("advisor-test", "SEARCH", criterion, "", documents);
+        }
+        advisor.record("advisor-test", "Email/query", "header:x-spam", "", 0);
+
+        let report = advisor
+            .report()
+            .into_iter()
+            .filter(|pattern| pattern.protocol == "advisor-test")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            report
+                .iter()
+                .map(|pattern| (
+                    pattern.command,
+                    pattern.criterion.as_str(),
+                    pattern.count,
+                    pattern.documents,
+                    pattern.max_documents
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("SEARCH", "modseq", 2, 400, 300),
+                ("Email/query", "header:x-spam", 1, 0, 0),
+                ("SEARCH", "header:x-spam", 1, 0, 0),
+            ]
+        );
+
+        advisor.set_enabled(false);
+        advisor.record("advisor-test", "SEARCH", "modseq", "", 100);
+        advisor.set_enabled(true);
+        assert_eq!(
+            advisor
+                .report()
+                .into_iter()
+                .find(|pattern| pattern.protocol == "advisor-test" && pattern.criterion == "modseq")
+                .unwrap()
+                .count,
+            2
+        );
+    }
+}
@@ -0,0 +1,378 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use dashmap::DashMap;
+
+/// Server-wide counters and gauges for the delivery queue, reporting,
+/// listeners and local delivery.
+#[derive(Default)]
+pub struct ServerMetrics {
+    queue_messages: AtomicI64,
+    queue_messages_queued: AtomicU64,
+    delivery_attempts: DashMap<&'static str, AtomicU64>,
+    dsn_messages: AtomicU64,
+    reports: DashMap<(&'static str, &'static str), AtomicU64>,
+    local_deliveries: DashMap<&'static str, AtomicU64>,
+    monitor_verdicts: DashMap<(String, &'static str), AtomicU64>,
+    listeners: DashMap<String, ListenerMetrics>,
+}
+
+struct ListenerMetrics {
+    active: Arc<AtomicU64>,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+static METRICS: OnceLock<ServerMetrics> = OnceLock::new();
+
+pub fn server_metrics() -> &'static ServerMetrics {
+    METRICS.get_or_init(ServerMetrics::default)
+}
+
+impl ServerMetrics {
+    pub fn message_queued(&self) {
+        self.queue_messages.fetch_add(1, Ordering::Relaxed);
+        self.queue_messages_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_removed(&self) {
+        self.queue_messages.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Adds messages found in the queue at startup to the queue depth.
+    pub fn messages_loaded(&self, count: u64) {
+        self.queue_messages
+            .fetch_add(count as i64, Ordering::Relaxed);
+    }
+
+    pub fn delivery_attempts(&self, result: &'static str, count: u64) {
+        if count > 0 {
+            increment(&self.delivery_attempts, result, count);
+        }
+    }
+
+    pub fn dsn_sent(&self) {
+        self.dsn_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report_sent(&self, report: &'static str) {
+        increment(&self.reports, (report, "sent"), 1);
+    }
+
+    pub fn report_received(&self, report: &'static str) {
+        increment(&self.reports, (report, "received"), 1);
+    }
+
+    pub fn local_delivery(&self, result: &'static str) {
+        increment(&self.local_deliveries, result, 1);
+    }
+
+    /// Counts a verdict of a rule deployed in monitor-only mode.
+    pub fn monitor_verdict(&self, rule: &str, verdict: &'static str) {
+        increment(&self.monitor_verdicts, (rule.to_string(), verdict), 1);
+    }
+
+    /// Registers a listener's active connection counter.
+    pub fn register_listener(&self, id: &str, active: Arc<AtomicU64>) {
+        self.listeners.insert(
+            id.to_string(),
+            ListenerMetrics {
+                active,
+                accepted: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+            },
+        );
+    }
+
+    pub fn connection_accepted(&self, id: &str) {
+        if let Some(listener) = self.listeners.get(id) {
+            listener.accepted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn connection_rejected(&self, id: &str) {
+        if let Some(listener) = self.listeners.get(id) {
+            listener.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn queue_depth(&self) -> u64 {
+        self.queue_messages.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    pub fn delivery_attempt_count(&self, result: &str) -> u64 {
+        self.delivery_attempts
+            .get(result)
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    pub fn report_count(&self, report: &str, direction: &str) -> u64 {
+        self.reports
+            .iter()
+            .filter(|entry| entry.key().0 == report && entry.key().1 == direction)
+            .map(|entry| entry.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn monitor_verdict_count(&self, rule: &str, verdict: &str) -> u64 {
+        self.monitor_verdicts
+            .iter()
+            .filter(|entry| entry.key().0 == rule && entry.key().1 == verdict)
+            .map(|entry| entry.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Renders all metrics in the OpenMetrics text format.
+    pub fn to_openmetrics(&self) -> String {
+        let mut out = String::new();
+
+        write_family(
+            &mut out,
+            "smtp_queue_messages",
+            "gauge",
+            "Messages currently in the delivery queue.",
+        );
+        let _ = writeln!(out, "smtp_queue_messages {}", self.queue_depth());
+
+        write_family(
+            &mut out,
+            "smtp_queue_messages_queued",
+            "counter",
+            "Messages added to the delivery queue.",
+        );
+        let _ = writeln!(
+            out,
+            "smtp_queue_messages_queued_total {}",
+            self.queue_messages_queued.load(Ordering::Relaxed)
+        );
+
+        write_family(
+            &mut out,
+            "smtp_delivery_attempts",
+            "counter",
+            "Outbound delivery attempts per recipient by result.",
+        );
+        for (result, count) in sorted(&self.delivery_attempts) {
+            let _ = writeln!(
+                out,
+                "smtp_delivery_attempts_total{{result=\"{result}\"}} {count}"
+            );
+        }
+
+        write_family(
+            &mut out,
+            "smtp_dsn_messages",
+            "counter",
+            "Delivery status notifications sent.",
+        );
+        let _ = writeln!(
+            out,
+            "smtp_dsn_messages_total {}",
+            self.dsn_messages.load(Ordering::Relaxed)
+        );
+
+        write_family(
+            &mut out,
+            "smtp_reports",
+            "counter",
+            "DMARC, TLS and authentication failure reports sent and received.",
+        );
+        for ((report, direction), count) in sorted(&self.reports) {
+            let _ = writeln!(
+                out,
+                "smtp_reports_total{{report=\"{report}\",direction=\"{direction}\"}} {count}"
+            );
+        }
+
+        let mut monitor_verdicts = self
+            .monitor_verdicts
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        monitor_verdicts.sort_unstable();
+        write_family(
+            &mut out,
+            "smtp_monitor_verdicts",
+            "counter",
+            "Verdicts of rules deployed in monitor-only mode that were not enforced.",
+        );
+        for ((rule, verdict), count) in monitor_verdicts {
+            let _ = writeln!(
+                out,
+                "smtp_monitor_verdicts_total{{rule=\"{rule}\",verdict=\"{verdict}\"}} {count}"
+            );
+        }
+
+        let mut listeners = self
+            .listeners
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key().clone(),
+                    entry.active.load(Ordering::Relaxed),
+                    entry.accepted.load(Ordering::Relaxed),
+                    entry.rejected.load(Ordering::Relaxed),
+                )
+            })
+            .collect::<Vec<_>>();
+        listeners.sort_unstable();
+        write_family(
+            &mut out,
+            "listener_connections",
+            "gauge",
+            "Open connections per listener.",
+        );
+        for (id, active, _, _) in &listeners {
+            let _ = writeln!(out, "listener_connections{{listener=\"{id}\"}} {active}");
+        }
+        write_family(
+            &mut out,
+            "listener_connections_accepted",
+            "counter",
+            "Connections accepted per listener.",
+        );
+        for (id, _, accepted, _) in &listeners {
+            let _ = writeln!(
+                out,
+                "listener_connections_accepted_total{{listener=\"{id}\"}} {accepted}"
+            );
+        }
+        write_family(
+            &mut out,
+            "listener_connections_rejected",
+            "counter",
+            "Connections rejected per listener because the remote IP was blocked or the listener was at capacity.",
+        );
+        for (id, _, _, rejected) in &listeners {
+            let _ = writeln!(
+                out,
+                "listener_connections_rejected_total{{listener=\"{id}\"}} {rejected}"
+            );
+        }
+
+        write_family(
+            &mut out,
+            "jmap_local_deliveries",
+            "counter",
+            "Messages delivered to local mailboxes per recipient by result.",
+        );
+        for (result, count) in sorted(&self.local_deliveries) {
+            let _ = writeln!(
+                out,
+                "jmap_local_deliveries_total{{result=\"{result}\"}} {count}"
+            );
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn increment<K: Eq + std::hash::Hash>(map: &DashMap<K, AtomicU64>, key: K, count: u64) {
+    if let Some(counter) = map.get(&key) {
+        counter.fetch_add(count, Ordering::Relaxed);
+    } else {
+        map.entry(key)
+            .or_default()
+            .fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+fn sorted<K: Eq + std::hash::Hash + Ord + Copy>(map: &DashMap<K, AtomicU64>) -> Vec<(K, u64)> {
+    let mut entries = map
+        .iter()
+        .map(|entry| (*entry.key(), entry.load(Ordering::Relaxed)))
+        .collect::<Vec<_>>();
+    entries.sort_unstable();
+    entries
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use super::ServerMetrics;
+
+    #[test]
+    fn openmetrics_output() {
+        let metrics = ServerMetrics::default();
+        metrics.message_queued();
+        metrics.message_queued();
+        metrics.message_removed();
+        metrics.delivery_attempts("delivered", 2);
+        metrics.delivery_attempts("bounced", 1);
+        metrics.delivery_attempts("deferred", 0);
+        metrics.report_sent("dmarc");
+        metrics.report_received("tls");
+        metrics.monitor_verdict("dlp:ssn", "reject");
+        metrics.monitor_verdict("dlp:ssn", "reject");
+
+        let active = Arc::new(AtomicU64::new(0));
+        metrics.register_listener("smtp", active.clone());
+        active.fetch_add(3, Ordering::Relaxed);
+        metrics.connection_accepted("smtp");
+        metrics.connection_rejected("smtp");
+        metrics.connection_accepted("unknown");
+
+        assert_eq!(metrics.queue_depth(), 1);
+        assert_eq!(metrics.delivery_attempt_count("delivered"), 2);
+        assert_eq!(metrics.delivery_attempt_count("deferred"), 0);
+        assert_eq!(metrics.report_count("dmarc", "sent"), 1);
+        assert_eq!(metrics.report_count("dmarc", "received"), 0);
+        assert_eq!(metrics.monitor_verdict_count("dlp:ssn", "reject"), 2);
+        assert_eq!(metrics.monitor_verdict_count("dlp:ssn", "quarantine"), 0);
+
+        let text = metrics.to_openmetrics();
+        for line in [
+            "smtp_queue_messages 1",
+            "smtp_queue_messages_queued_total 2",
+            "smtp_delivery_attempts_total{result=\"bounced\"} 1",
+            "smtp_delivery_attempts_total{result=\"delivered\"} 2",
+            "smtp_reports_total{report=\"tls\",direction=\"received\"} 1",
+            "smtp_monitor_verdicts_total{rule=\"dlp:ssn\",verdict=\"reject\"} 2",
+            "listener_connections{listener=\"smtp\"} 3",
+            "listener_connections_accepted_total{listener=\"smtp\"} 1",
+            "listener_connections_rejected_total{listener=\"smtp\"} 1",
+        ] {
+            assert!(text.contains(line), "missing {line:?} in {text}");
+        }
+        assert!(!text.contains("deferred"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+
+use crate::config::Config;
+
+const MAX_ARGUMENTS_LEN: usize = 1024;
+
+/// Keeps the most recent commands that took longer than the configured
+/// threshold, along with the time they spent on each store operation.
+#[derive(Default)]
+pub struct SlowQueryLog {
+    threshold: AtomicU64,
+    max_entries: AtomicUsize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowQuery>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SlowQuery {
+    pub id: u64,
+    pub timestamp: u64,
+    pub protocol: &'static str,
+    pub command: &'static str,
+    #[serde(rename = "accountId")]
+    pub account_id: Option<u32>,
+    pub arguments: String,
+    pub elapsed: u64,
+    pub operations: Vec<OperationTiming>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct OperationTiming {
+    pub backend: &'static str,
+    pub operation: &'static str,
+    pub count: u64,
+    pub elapsed: u64,
+}
+
+/// Account and arguments of a command, reported if the command turns out
+/// to be slow.
+#[derive(Debug, Clone)]
+pub struct CommandDetails {
+    pub account_id: Option<u32>,
+    pub arguments: String,
+}
+
+static SLOW_QUERIES: OnceLock<SlowQueryLog> = OnceLock::new();
+
+pub fn slow_query_log() -> &'static SlowQueryLog {
+    SLOW_QUERIES.get_or_init(SlowQueryLog::default)
+}
+
+impl SlowQueryLog {
+    pub fn configure(&self, config: &Config) -> crate::config::Result<()> {
+        self.set_threshold(
+            config.property::<Duration>("global.metrics.slow-query.threshold")?,
+            config
+                .property("global.metrics.slow-query.max-entries")?
+                .unwrap_or(100),
+        );
+        Ok(())
+    }
+
+    pub fn set_threshold(&self, threshold: Option<Duration>, max_entries: usize) {
+        self.threshold.store(
+            threshold.map_or(0, |threshold| (threshold.as_micros() as u64).max(1)),
+            Ordering::Relaxed,
+        );
+        self.max_entries.store(max_entries, Ordering::Relaxed);
+        let mut entries = self.entries.lock();
+        while entries.len() > max_entries {
+            entries.pop_front();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold.load(Ordering::Relaxed) > 0
+    }
+
+    pub(crate) fn record(
+        &self,
+        protocol: &'static str,
+        command: &'static str,
+        details: CommandDetails,
+        operations: Vec<OperationTiming>,
+        elapsed: Duration,
+    ) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        let elapsed = elapsed.as_micros() as u64;
+        if threshold == 0 || elapsed < threshold {
+            return;
+        }
+
+        let query = SlowQuery {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            protocol,
+            command,
+            account_id: details.account_id,
+            arguments: details.arguments,
+            elapsed,
+            operations,
+        };
+        tracing::info!(
+            context = "slow-query",
+            event = "slow",
+            protocol = protocol,
+            command = command,
+            account_id = query.account_id,
+            arguments = query.arguments,
+            elapsed_ms = elapsed / 1000,
+            "Command exceeded the slow query threshold."
+        );
+
+        let max_entries = self.max_entries.load(Ordering::Relaxed);
+        let mut entries = self.entries.lock();
+        if entries.len() >= max_entries {
+            entries.pop_front();
+        }
+        if max_entries > 0 {
+            entries.push_back(query);
+        }
+    }
+
+    /// Returns the logged commands, most recent first.
+    pub fn entries(&self) -> Vec<SlowQuery> {
+        self.entries.lock().iter().rev().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+}
+
+impl CommandDetails {
+    /// Builds the details of a command only when slow queries are logged.
+    pub fn new(account_id: Option<u32>, arguments: impl FnOnce() -> String) -> Option<Self> {
+        if slow_query_log().is_enabled() {
+            Some(CommandDetails {
+                account_id,
+                arguments: arguments(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Replaces quoted strings with an ellipsis and truncates the result, which
+/// keeps the shape of a query without exposing the values it was run with.
+pub fn sanitize_arguments(arguments: &str) -> String {
+    let mut result = String::with_capacity(std::cmp::min(arguments.len(), MAX_ARGUMENTS_LEN));
+    let mut chars = arguments.chars();
+    while let Some(ch) = chars.next() {
+        if result.len() >= MAX_ARGUMENTS_LEN {
+            result.push('…');
+            break;
+        }
+        result.push(ch);
+        if ch == '"' {
+            let mut is_escaped = false;
+            for ch in chars.by_ref() {
+                match ch {
+                    '\\' if !is_escaped => is_escaped = true,
+                    '"' if !is_escaped => break,
+                    _ => is_escaped = false,
+                }
+            }
+            result.push_str("…\"");
+        }
+    }
+    result
+}
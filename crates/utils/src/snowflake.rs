@@ -60,6 +60,13 @@ impl SnowflakeIdGenerator {
             | (sequence & SEQUENCE_MASK))
             .into()
     }
+
+    /// Returns the lowest id that could have been generated `period` ago.
+    pub fn past_id(&self, period: Duration) -> Option<u64> {
+        let elapsed = self.epoch.elapsed().ok()?.checked_sub(period)?.as_millis() as u64;
+
+        (elapsed << (SEQUENCE_LEN + NODE_ID_LEN)).into()
+    }
 }
 
 impl Default for SnowflakeIdGenerator {
@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::future::Future;
+
+use rand::Rng;
+
+tokio::task_local! {
+    static TRACE_ID: u64;
+}
+
+/// Generates a new random trace id.
+pub fn generate() -> u64 {
+    rand::thread_rng().gen_range(1..u64::MAX)
+}
+
+/// Runs a future with the given trace id, making it available to the store
+/// and directory calls it performs.
+pub async fn scope<F: Future>(trace_id: u64, future: F) -> F::Output {
+    TRACE_ID.scope(trace_id, future).await
+}
+
+/// Returns the trace id of the current session or request, if any.
+pub fn current() -> Option<u64> {
+    TRACE_ID.try_with(|trace_id| *trace_id).ok()
+}
+
+/// Returns the current trace id formatted as a fixed-width hex string.
+pub fn current_hex() -> Option<String> {
+    current().map(|trace_id| format!("{trace_id:016x}"))
+}
+
+/// Keeps the current trace id when the future is spawned as a separate task.
+pub fn propagate<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let trace_id = current();
+    async move {
+        if let Some(trace_id) = trace_id {
+            TRACE_ID.scope(trace_id, future).await
+        } else {
+            future.await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn propagate_trace_id() {
+        assert_eq!(super::current(), None);
+
+        super::scope(0xabcd, async {
+            assert_eq!(super::current_hex().unwrap(), "000000000000abcd");
+            let handle = tokio::spawn(super::propagate(async { super::current() }));
+            assert_eq!(handle.await.unwrap(), Some(0xabcd));
+            let handle = tokio::spawn(async { super::current() });
+            assert_eq!(handle.await.unwrap(), None);
+        })
+        .await;
+    }
+}
@@ -0,0 +1,346 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use crate::{config::Config, metrics::Histogram};
+
+/// A dedicated rayon pool with a bounded number of queued jobs. Once the
+/// queue is full new jobs are rejected rather than delaying every other
+/// caller sharing the pool.
+pub struct WorkerPool {
+    pool: rayon::ThreadPool,
+    stats: Arc<WorkerPoolStats>,
+}
+
+#[derive(Default)]
+pub struct WorkerPoolStats {
+    name: String,
+    threads: usize,
+    max_queued: usize,
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicU64,
+    rejected: AtomicU64,
+    wait: Histogram,
+}
+
+static POOLS: Mutex<Vec<Arc<WorkerPoolStats>>> = Mutex::new(Vec::new());
+static CPU_POOL: OnceLock<WorkerPool> = OnceLock::new();
+
+/// Returns the pool used for CPU bound work such as DKIM signing,
+/// compression and full-text tokenization.
+pub fn cpu_pool() -> &'static WorkerPool {
+    CPU_POOL.get_or_init(|| WorkerPool::new("cpu", 0, 0).unwrap_or_else(|err| panic!("{}", err)))
+}
+
+/// Builds the CPU pool from the `global.worker-pool.cpu` settings, this
+/// has to be called before any CPU bound work is scheduled.
+pub fn init_cpu_pool(config: &Config) -> crate::config::Result<()> {
+    let pool = WorkerPool::parse(config, "global.worker-pool.cpu", "cpu", 0)?;
+    if CPU_POOL.set(pool).is_err() {
+        tracing::debug!(
+            context = "worker-pool",
+            event = "init",
+            "CPU worker pool was already initialized."
+        );
+    }
+    Ok(())
+}
+
+impl WorkerPool {
+    pub fn new(name: impl Into<String>, threads: usize, max_queued: usize) -> Result<Self, String> {
+        let name = name.into();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name({
+                let name = name.clone();
+                move |idx| format!("{name}-{idx}")
+            })
+            .build()
+            .map_err(|err| format!("Failed to build worker pool {name:?}: {err}"))?;
+        let stats = Arc::new(WorkerPoolStats {
+            threads: pool.current_num_threads(),
+            name,
+            max_queued,
+            ..Default::default()
+        });
+        POOLS.lock().push(stats.clone());
+
+        Ok(WorkerPool { pool, stats })
+    }
+
+    /// Parses `<prefix>.threads` and `<prefix>.queue-size`, a value of zero
+    /// uses one thread per CPU or an unbounded queue respectively.
+    pub fn parse(
+        config: &Config,
+        prefix: &str,
+        name: impl Into<String>,
+        default_threads: usize,
+    ) -> crate::config::Result<Self> {
+        WorkerPool::new(
+            name,
+            config
+                .property::<usize>((prefix, "threads"))?
+                .unwrap_or(default_threads),
+            config
+                .property::<usize>((prefix, "queue-size"))?
+                .unwrap_or(0),
+        )
+    }
+
+    /// Queues a job, returns `false` if the pool is saturated.
+    pub fn spawn<U>(&self, f: U) -> bool
+    where
+        U: FnOnce() + Send + 'static,
+    {
+        if !self.stats.enqueue() {
+            return false;
+        }
+
+        let stats = self.stats.clone();
+        let queued_at = Instant::now();
+        self.pool.spawn(move || {
+            stats.start(queued_at);
+            f();
+            stats.finish();
+        });
+        true
+    }
+
+    /// Runs a job on the pool and waits for its result without blocking
+    /// the async runtime, returns `None` if the pool is saturated.
+    pub async fn run<U, V>(&self, f: U) -> Option<V>
+    where
+        U: FnOnce() -> V + Send + 'static,
+        V: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        if !self.spawn(move || {
+            tx.send(f()).ok();
+        }) {
+            return None;
+        }
+
+        match rx.await {
+            Ok(result) => Some(result),
+            Err(err) => {
+                tracing::warn!(
+                    context = "worker-pool",
+                    event = "error",
+                    pool = %self.stats.name,
+                    reason = %err,
+                );
+                None
+            }
+        }
+    }
+
+    /// Runs a job that borrows from the caller on the pool, blocking the
+    /// current thread until it completes. If the pool is saturated the job
+    /// is handed back so the caller can decide how to proceed.
+    pub fn install<U, V>(&self, f: U) -> Result<V, U>
+    where
+        U: FnOnce() -> V + Send,
+        V: Send,
+    {
+        if !self.stats.enqueue() {
+            return Err(f);
+        }
+
+        let queued_at = Instant::now();
+        Ok(self.pool.install(|| {
+            self.stats.start(queued_at);
+            let result = f();
+            self.stats.finish();
+            result
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.stats.name
+    }
+
+    pub fn stats(&self) -> &WorkerPoolStats {
+        &self.stats
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        POOLS
+            .lock()
+            .retain(|stats| !Arc::ptr_eq(stats, &self.stats));
+    }
+}
+
+impl WorkerPoolStats {
+    fn enqueue(&self) -> bool {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.max_queued == 0 || queued < self.max_queued {
+            true
+        } else {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                context = "worker-pool",
+                event = "saturated",
+                pool = %self.name,
+                queued = queued,
+                "Worker pool queue is full, rejecting job."
+            );
+            false
+        }
+    }
+
+    fn start(&self, queued_at: Instant) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.wait.observe(queued_at.elapsed());
+    }
+
+    fn finish(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// Renders the state of all worker pools in the Prometheus text exposition format.
+pub fn write_prometheus(out: &mut String) {
+    let mut pools = POOLS.lock().clone();
+    pools.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    for (name, typ, help) in [
+        (
+            "worker_pool_threads",
+            "gauge",
+            "Number of threads in the worker pool.",
+        ),
+        (
+            "worker_pool_queue_limit",
+            "gauge",
+            "Maximum number of queued jobs, zero if unbounded.",
+        ),
+        (
+            "worker_pool_queued",
+            "gauge",
+            "Jobs waiting for a worker thread.",
+        ),
+        ("worker_pool_active", "gauge", "Jobs currently running."),
+        ("worker_pool_completed_total", "counter", "Jobs completed."),
+        (
+            "worker_pool_rejected_total",
+            "counter",
+            "Jobs rejected because the queue was full.",
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {typ}");
+        for stats in &pools {
+            let value = match name {
+                "worker_pool_threads" => stats.threads as u64,
+                "worker_pool_queue_limit" => stats.max_queued as u64,
+                "worker_pool_queued" => stats.queued() as u64,
+                "worker_pool_active" => stats.active() as u64,
+                "worker_pool_completed_total" => stats.completed(),
+                _ => stats.rejected(),
+            };
+            let _ = writeln!(out, "{name}{{pool=\"{}\"}} {value}", stats.name);
+        }
+    }
+
+    let name = "worker_pool_wait_seconds";
+    let _ = writeln!(out, "# HELP {name} Time jobs spent queued before running.");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for stats in &pools {
+        stats
+            .wait
+            .write_prometheus(out, name, &format!("pool=\"{}\"", stats.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use super::WorkerPool;
+
+    #[tokio::test]
+    async fn worker_pool_queue_limit() {
+        let pool = WorkerPool::new("test", 1, 1).unwrap();
+
+        // Block the only worker thread
+        let barrier = Arc::new(Barrier::new(2));
+        let worker_barrier = barrier.clone();
+        assert!(pool.spawn(move || {
+            worker_barrier.wait();
+        }));
+        while pool.stats().active() == 0 {
+            std::thread::yield_now();
+        }
+
+        // One job can be queued, the next one is rejected
+        assert!(pool.spawn(|| {}));
+        assert!(!pool.spawn(|| {}));
+        assert_eq!(pool.stats().rejected(), 1);
+
+        barrier.wait();
+        while pool.stats().queued() > 0 || pool.stats().active() > 0 {
+            std::thread::yield_now();
+        }
+        assert_eq!(pool.run(|| 1 + 1).await, Some(2));
+        assert_eq!(pool.install(|| 2 + 2).ok(), Some(4));
+        assert_eq!(pool.stats().completed(), 4);
+
+        let mut out = String::new();
+        super::write_prometheus(&mut out);
+        assert!(out.contains("worker_pool_rejected_total{pool=\"test\"} 1"));
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_send::smtp::tls::build_tls_connector;
+use rustls_pki_types::ServerName;
+use tokio::net::TcpStream;
+
+use super::{load_scripts, StreamConversation};
+
+pub async fn test() {
+    println!("Running JMAP conformance scripts...");
+
+    let connector = build_tls_connector(true);
+    for script in load_scripts("jmap") {
+        let stream = connector
+            .connect(
+                ServerName::try_from("localhost").unwrap().to_owned(),
+                TcpStream::connect("127.0.0.1:8899").await.unwrap(),
+            )
+            .await
+            .unwrap();
+        script.run(&mut StreamConversation::new(stream)).await;
+    }
+}
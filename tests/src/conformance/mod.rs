@@ -0,0 +1,362 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+//! Protocol conformance tests.
+//!
+//! Each `.script` file under `resources/conformance/<protocol>` describes a
+//! conversation with the server, one step per line:
+//!
+//! - `C: <text>` sends `<text>` followed by CRLF.
+//! - `R: <text>` sends `<text>` as is, no line ending is appended.
+//! - `L: <count> <text>` sends `<text>` repeated `<count>` times.
+//! - `S: <prefix>` expects the next line from the server to start with `<prefix>`.
+//! - `S*: <text>` skips server lines until one contains `<text>`.
+//! - `N:` expects no response from the server.
+//! - `D:` expects the server to close the connection, discarding any
+//!   pending output.
+//!
+//! Lines starting with `#` are comments. The escapes `\r`, `\n`, `\t`, `\s`
+//! (space), `\\` and `\xHH` are supported in both sent text and prefixes.
+//!
+//! Run with `cargo test --features conformance conformance`. The IMAP and
+//! JMAP scripts run against a live server and require the `STORE`
+//! environment variable.
+
+pub mod imap;
+pub mod jmap;
+pub mod smtp;
+
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
+};
+
+use crate::jmap::init_jmap_tests;
+
+pub struct Script {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+pub struct Step {
+    pub line: usize,
+    pub action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Send(Vec<u8>),
+    Expect(String),
+    ExpectSkip(String),
+    ExpectIdle,
+    ExpectClose,
+}
+
+#[derive(Debug)]
+pub enum Reply {
+    Line(String),
+    Idle,
+    Closed,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Conversation {
+    async fn send(&mut self, bytes: &[u8]);
+    async fn read_line(&mut self) -> Reply;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn server_conformance() {
+    let handle = init_jmap_tests(
+        &std::env::var("STORE")
+            .expect("Missing store type. Try running `STORE=<store_type> cargo test`"),
+        true,
+    )
+    .await;
+
+    imap::test().await;
+    jmap::test().await;
+
+    handle.temp_dir.delete();
+}
+
+pub fn load_scripts(protocol: &str) -> Vec<Script> {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources");
+    path.push("conformance");
+    path.push(protocol);
+
+    let mut files = std::fs::read_dir(&path)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "script"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|path| {
+            let name = format!(
+                "{}/{}",
+                protocol,
+                path.file_name().unwrap().to_string_lossy()
+            );
+            let contents = std::fs::read_to_string(&path).unwrap();
+            Script::parse(name, &contents).unwrap_or_else(|err| panic!("{err}"))
+        })
+        .collect()
+}
+
+impl Script {
+    pub fn parse(name: String, contents: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+
+        for (line_num, line) in contents.lines().enumerate() {
+            let line_num = line_num + 1;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (directive, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("{name}:{line_num}: Missing directive."))?;
+            let value = value.strip_prefix(' ').unwrap_or(value);
+
+            let action = match directive {
+                "C" => {
+                    let mut bytes =
+                        unescape(value).map_err(|err| format!("{name}:{line_num}: {err}"))?;
+                    bytes.extend_from_slice(b"\r\n");
+                    Action::Send(bytes)
+                }
+                "R" => Action::Send(
+                    unescape(value).map_err(|err| format!("{name}:{line_num}: {err}"))?,
+                ),
+                "L" => {
+                    let (count, text) = value
+                        .split_once(' ')
+                        .and_then(|(count, text)| {
+                            count.parse::<usize>().ok().map(|count| (count, text))
+                        })
+                        .ok_or_else(|| format!("{name}:{line_num}: Expected '<count> <text>'."))?;
+                    Action::Send(
+                        unescape(text)
+                            .map_err(|err| format!("{name}:{line_num}: {err}"))?
+                            .repeat(count),
+                    )
+                }
+                "S" | "S*" => {
+                    let prefix = String::from_utf8(
+                        unescape(value).map_err(|err| format!("{name}:{line_num}: {err}"))?,
+                    )
+                    .map_err(|_| format!("{name}:{line_num}: Prefix is not valid UTF-8."))?;
+                    if directive == "S" {
+                        Action::Expect(prefix)
+                    } else {
+                        Action::ExpectSkip(prefix)
+                    }
+                }
+                "N" => Action::ExpectIdle,
+                "D" => Action::ExpectClose,
+                _ => {
+                    return Err(format!(
+                        "{name}:{line_num}: Unknown directive {directive:?}."
+                    ))
+                }
+            };
+
+            steps.push(Step {
+                line: line_num,
+                action,
+            });
+        }
+
+        if !steps.is_empty() {
+            Ok(Script { name, steps })
+        } else {
+            Err(format!("{name}: Script is empty."))
+        }
+    }
+
+    pub async fn run(&self, conn: &mut impl Conversation) {
+        for step in &self.steps {
+            match &step.action {
+                Action::Send(bytes) => {
+                    conn.send(bytes).await;
+                }
+                Action::Expect(prefix) => match conn.read_line().await {
+                    Reply::Line(line) if line.starts_with(prefix.as_str()) => {}
+                    reply => self.fail(step, format!("Expected {prefix:?}, got {reply:?}")),
+                },
+                Action::ExpectSkip(prefix) => loop {
+                    match conn.read_line().await {
+                        Reply::Line(line) if line.contains(prefix.as_str()) => break,
+                        Reply::Line(_) => (),
+                        reply => self.fail(step, format!("Expected {prefix:?}, got {reply:?}")),
+                    }
+                },
+                Action::ExpectIdle => match conn.read_line().await {
+                    Reply::Idle => {}
+                    reply => self.fail(step, format!("Expected no response, got {reply:?}")),
+                },
+                Action::ExpectClose => loop {
+                    match conn.read_line().await {
+                        Reply::Closed => break,
+                        Reply::Line(_) => (),
+                        Reply::Idle => self.fail(step, "Expected disconnection, got no response"),
+                    }
+                },
+            }
+        }
+    }
+
+    fn fail(&self, step: &Step, reason: impl Display) -> ! {
+        panic!("{}:{}: {}", self.name, step.line, reason);
+    }
+}
+
+/// Conversation over a live connection.
+pub struct StreamConversation<T: AsyncRead + AsyncWrite> {
+    reader: BufReader<ReadHalf<T>>,
+    writer: WriteHalf<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite> StreamConversation<T> {
+    pub fn new(stream: T) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        StreamConversation {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Conversation for StreamConversation<T> {
+    async fn send(&mut self, bytes: &[u8]) {
+        // Write errors are reported by the next expectation
+        if self.writer.write_all(bytes).await.is_ok() {
+            let _ = self.writer.flush().await;
+        }
+    }
+
+    async fn read_line(&mut self) -> Reply {
+        let mut line = Vec::new();
+        match tokio::time::timeout(
+            Duration::from_millis(1500),
+            self.reader.read_until(b'\n', &mut line),
+        )
+        .await
+        {
+            Ok(Ok(0)) | Ok(Err(_)) => Reply::Closed,
+            Ok(Ok(_)) => Reply::Line(
+                String::from_utf8_lossy(&line)
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+            ),
+            Err(_) if line.is_empty() => Reply::Idle,
+            Err(_) => Reply::Line(String::from_utf8_lossy(&line).into_owned()),
+        }
+    }
+}
+
+fn unescape(value: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+
+    while let Some(ch) = bytes.next() {
+        if ch != b'\\' {
+            result.push(ch);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'r') => result.push(b'\r'),
+            Some(b'n') => result.push(b'\n'),
+            Some(b't') => result.push(b'\t'),
+            Some(b's') => result.push(b' '),
+            Some(b'\\') => result.push(b'\\'),
+            Some(b'x') => {
+                let hex = [
+                    bytes.next().unwrap_or_default(),
+                    bytes.next().unwrap_or_default(),
+                ];
+                result.push(
+                    std::str::from_utf8(&hex)
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| "Invalid \\x escape.".to_string())?,
+                );
+            }
+            ch => {
+                return Err(format!(
+                    "Invalid escape sequence {:?}.",
+                    ch.map(char::from).unwrap_or_default()
+                ))
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn parse_script() {
+    let script = Script::parse(
+        "test".to_string(),
+        concat!(
+            "# Comment\n",
+            "C: EHLO mx.foobar.org\n",
+            "S*: 250\\s\n",
+            "\n",
+            "R: NOOP\\n\n",
+            "L: 3 AB\n",
+            "S: 250\n",
+            "N:\n",
+            "D:\n",
+        ),
+    )
+    .unwrap();
+
+    assert_eq!(
+        script
+            .steps
+            .into_iter()
+            .map(|step| (step.line, step.action))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, Action::Send(b"EHLO mx.foobar.org\r\n".to_vec())),
+            (3, Action::ExpectSkip("250 ".to_string())),
+            (5, Action::Send(b"NOOP\n".to_vec())),
+            (6, Action::Send(b"ABABAB".to_vec())),
+            (7, Action::Expect("250".to_string())),
+            (8, Action::ExpectIdle),
+            (9, Action::ExpectClose),
+        ]
+    );
+
+    for invalid in ["X: foo", "C: \\q", "L: foo", "R: \\xZZ", "# Only a comment"] {
+        assert!(
+            Script::parse("test".to_string(), invalid).is_err(),
+            "{invalid}"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::VecDeque, sync::Arc};
+
+use smtp::core::{Session, SMTP};
+use utils::config::if_block::IfBlock;
+
+use crate::smtp::{
+    session::{DummyIo, TestSession},
+    TestSMTP,
+};
+
+use super::{load_scripts, Conversation, Reply};
+
+/// Conversation with an in-memory SMTP session.
+struct SessionConversation {
+    session: Session<DummyIo>,
+    lines: VecDeque<String>,
+    closed: bool,
+}
+
+#[tokio::test]
+async fn smtp_conformance() {
+    let mut core = SMTP::test();
+    let _qr = core.init_test_queue("smtp_conformance_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let core = Arc::new(core);
+
+    for script in load_scripts("smtp") {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+
+        script
+            .run(&mut SessionConversation {
+                session,
+                lines: VecDeque::new(),
+                closed: false,
+            })
+            .await;
+    }
+}
+
+impl Conversation for SessionConversation {
+    async fn send(&mut self, bytes: &[u8]) {
+        if !self.closed && !matches!(self.session.ingest(bytes).await, Ok(true)) {
+            self.closed = true;
+        }
+        let output = std::mem::take(&mut self.session.stream.tx_buf);
+        self.lines.extend(
+            String::from_utf8_lossy(&output)
+                .split_terminator("\r\n")
+                .map(|line| line.to_string()),
+        );
+    }
+
+    async fn read_line(&mut self) -> Reply {
+        if let Some(line) = self.lines.pop_front() {
+            Reply::Line(line)
+        } else if self.closed {
+            Reply::Closed
+        } else {
+            Reply::Idle
+        }
+    }
+}
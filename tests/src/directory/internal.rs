@@ -21,22 +21,34 @@
  * for more details.
 */
 
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose, Engine};
 use directory::{
     backend::internal::{
         lookup::DirectoryStore, manage::ManageDirectory, PrincipalField, PrincipalUpdate,
-        PrincipalValue,
+        PrincipalValue, PurgeStage, RenamedAddress,
+    },
+    core::{
+        breach::{plain_secret, BreachAction, BreachCheck},
+        scram::{is_scram_verifier, ScramServer, ScramVerifier},
+        secret::{app_password_secret, hash_secret, needs_rehash, password_scheme},
     },
-    DirectoryError, ManagementError, Principal, QueryBy, Type,
+    AddressMapping, AuthResult, Directory, DirectoryError, DirectoryInner, ManagementError,
+    Principal, QueryBy, Type,
 };
+use hmac::{Hmac, Mac};
 use jmap_proto::types::collection::Collection;
 use mail_send::Credentials;
+use sha2::{Digest, Sha256};
 use store::{
+    dispatch::blocked::BlockedIps,
     roaring::RoaringBitmap,
-    write::{BatchBuilder, BitmapClass, ValueClass},
+    write::{BatchBuilder, BitmapClass, ValueClass, F_INDEX},
     BitmapKey, ValueKey,
 };
 
-use crate::directory::DirectoryTest;
+use crate::{directory::DirectoryTest, store::TempDir};
 
 #[tokio::test]
 async fn internal_directory() {
@@ -569,6 +581,27 @@ async fn internal_directory() {
             store.list_accounts(None, None).await.unwrap(),
             vec!["jane", "list", "sales", "support"]
         );
+
+        // Account data is kept until the staged purge completes
+        let tombstones = store.list_tombstones().await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].account_id, 0);
+        assert_eq!(tombstones[0].stage, PurgeStage::Index);
+        assert!(store
+            .get_bitmap(BitmapKey {
+                account_id: 0,
+                collection: Collection::Email.into(),
+                class: BitmapClass::DocumentIds,
+                block_num: 0
+            })
+            .await
+            .unwrap()
+            .is_some());
+        let mut next = tombstones.into_iter().next();
+        while let Some(tombstone) = next {
+            next = store.advance_tombstone(tombstone).await.unwrap();
+        }
+        assert_eq!(store.list_tombstones().await.unwrap(), vec![]);
         assert_eq!(
             store
                 .get_bitmap(BitmapKey {
@@ -625,5 +658,687 @@ async fn internal_directory() {
                 .unwrap(),
             Some("hello".to_string())
         );
+
+        // Indexes left behind by accounts missing from the directory are orphaned
+        assert_eq!(
+            store.list_orphaned_accounts().await.unwrap(),
+            Vec::<u32>::new()
+        );
+        let orphan_id = 1000;
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(orphan_id)
+                    .with_collection(Collection::Email)
+                    .create_document(0)
+                    .value(0u8, 1234u64, F_INDEX)
+                    .build_batch(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.list_orphaned_accounts().await.unwrap(),
+            vec![orphan_id]
+        );
+        let (keys, bytes) = store
+            .purge_account_indexes(orphan_id, 1, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(keys >= 2, "{keys}");
+        assert!(bytes > 0);
+        assert_eq!(
+            store.list_orphaned_accounts().await.unwrap(),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            store
+                .get_bitmap(BitmapKey {
+                    account_id: orphan_id,
+                    collection: Collection::Email.into(),
+                    class: BitmapClass::DocumentIds,
+                    block_num: 0
+                })
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(store
+            .get_bitmap(BitmapKey {
+                account_id: 1,
+                collection: Collection::Email.into(),
+                class: BitmapClass::DocumentIds,
+                block_num: 0
+            })
+            .await
+            .unwrap()
+            .is_some());
+
+        // Rename domain
+        let renamed = store
+            .rename_domain("example.org", "example.net", 0)
+            .await
+            .unwrap();
+        assert_eq!(renamed.len(), 2);
+        assert!(store.is_local_domain("example.net").await.unwrap());
+        assert!(!store.is_local_domain("example.org").await.unwrap());
+        assert!(store.rcpt("jane@example.net").await.unwrap());
+        assert!(store.rcpt("list@example.net").await.unwrap());
+        assert!(!store.rcpt("jane@example.org").await.unwrap());
+
+        // Rename account, the old address is kept during the grace period
+        assert_eq!(
+            store
+                .rename_account(QueryBy::Name("jane"), "Jane.Doe@example.net", 1)
+                .await
+                .unwrap(),
+            vec![RenamedAddress {
+                account_id: 1,
+                old: "jane@example.net".to_string(),
+                new: "jane.doe@example.net".to_string(),
+            }]
+        );
+        assert_eq!(
+            store
+                .query(QueryBy::Id(1), false)
+                .await
+                .unwrap()
+                .unwrap()
+                .emails,
+            vec!["jane.doe@example.net", "jane@example.net"]
+        );
+        assert!(store.rcpt("jane.doe@example.net").await.unwrap());
+        assert!(store.rcpt("jane@example.net").await.unwrap());
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        assert_eq!(store.purge_expired_aliases().await.unwrap(), 1);
+        assert!(store.rcpt("jane.doe@example.net").await.unwrap());
+        assert!(!store.rcpt("jane@example.net").await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn account_lockout() {
+    let config = DirectoryTest::new(None).await;
+    let store = config.stores.stores.get("rocksdb").unwrap().clone();
+    let directory = config.directories.directories.get("rocksdb").unwrap();
+    store.destroy().await;
+    directory
+        .blocked_ips
+        .reload(
+            &utils::config::Config::new(
+                r#"[server.security.lockout]
+rate = "3/1h"
+duration = "1h"
+"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+    store
+        .create_account(Principal {
+            name: "jane".to_string(),
+            secrets: vec!["secret".to_string()],
+            emails: vec![
+                "jane@example.org".to_string(),
+                "jane@backup.org".to_string(),
+            ],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // Failed attempts are counted per account across all remote addresses
+    // and regardless of the case of the login
+    let right = Credentials::new("jane".to_string(), "secret".to_string());
+    for (ip, login) in [
+        ("10.0.0.1", "jane"),
+        ("10.0.0.2", "Jane"),
+        ("10.0.0.3", " JANE "),
+        ("10.0.0.4", "jane"),
+    ] {
+        let wrong = Credentials::new(login.to_string(), "wrong".to_string());
+        assert!(matches!(
+            directory
+                .authenticate(&wrong, ip.parse().unwrap(), false)
+                .await
+                .unwrap(),
+            AuthResult::Failure
+        ));
+    }
+    assert!(directory.blocked_ips.is_account_locked("jane").await);
+    assert!(directory.blocked_ips.is_account_locked("JANE").await);
+
+    // Valid credentials are rejected while the account is locked
+    assert!(matches!(
+        directory
+            .authenticate(&right, "10.0.0.5".parse().unwrap(), false)
+            .await
+            .unwrap(),
+        AuthResult::Failure
+    ));
+
+    // Lift the lockout, which also resets the failure counter
+    assert!(directory.blocked_ips.unlock_account("Jane").await.unwrap());
+    assert!(!directory.blocked_ips.unlock_account("jane").await.unwrap());
+    assert!(matches!(
+        directory
+            .authenticate(&right, "10.0.0.5".parse().unwrap(), false)
+            .await
+            .unwrap(),
+        AuthResult::Success(_)
+    ));
+    assert_eq!(
+        directory.blocked_ips.register_login_failure("jane").await,
+        None
+    );
+    assert!(!directory.blocked_ips.is_account_locked("jane").await);
+}
+
+#[tokio::test]
+async fn breach_check() {
+    let temp_dir = TempDir::new("breach_check_tests", true);
+    let corpus = temp_dir.path.join("corpus.txt");
+    std::fs::write(
+        &corpus,
+        concat!(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n",
+            "7C4A8D09CA3762AF61E59520943DC26494F8941B:37359195\n",
+        ),
+    )
+    .unwrap();
+
+    let breach_check = BreachCheck::from_config(
+        &utils::config::Config::new(&format!(
+            "[server.security.breach-check]\nsource = \"local\"\nfile = {:?}\naction.login = \"warn\"\n",
+            corpus.to_string_lossy()
+        ))
+        .unwrap(),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(breach_check.on_change, BreachAction::ForceChange);
+    assert_eq!(breach_check.on_login, Some(BreachAction::Warn));
+    assert!(breach_check.is_breached("password").await);
+    assert!(breach_check.is_breached("123456").await);
+    assert!(
+        !breach_check
+            .is_breached("correct horse battery staple")
+            .await
+    );
+
+    // Only plain-text secrets can be checked
+    assert_eq!(plain_secret("password"), Some("password"));
+    assert_eq!(plain_secret("{PLAIN}password"), Some("password"));
+    assert_eq!(plain_secret("{CLEAR}password"), Some("password"));
+    assert_eq!(plain_secret("$6$rounds=5000$salt$hash"), None);
+    assert_eq!(plain_secret("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="), None);
+    assert_eq!(plain_secret(&hash_secret("password")), None);
+
+    // Logins with a breached password are restricted to changing it
+    let config = DirectoryTest::new(None).await;
+    let store = config.stores.stores.get("rocksdb").unwrap().clone();
+    store.destroy().await;
+    let directory = Directory {
+        store: DirectoryInner::Internal(store.clone()),
+        catch_all: AddressMapping::Disable,
+        subaddressing: AddressMapping::Disable,
+        cache: None,
+        blocked_ips: Arc::new(BlockedIps::new(store.clone().into())),
+        breach_check: BreachCheck::from_config(
+            &utils::config::Config::new(&format!(
+                "[server.security.breach-check]\nsource = \"local\"\nfile = {:?}\naction.login = \"force-change\"\n",
+                corpus.to_string_lossy()
+            ))
+            .unwrap(),
+        )
+        .unwrap(),
+        circuit_breaker: None,
+        password_rehash: false,
+    };
+    for (name, secrets) in [
+        (
+            "jane",
+            vec![
+                "password".to_string(),
+                ScramVerifier::new("password").to_string(),
+            ],
+        ),
+        ("john", vec!["{PLAIN}abc123-unique".to_string()]),
+    ] {
+        store
+            .create_account(Principal {
+                name: name.to_string(),
+                secrets,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("jane".to_string(), "password".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::MustChangePassword(_)
+    ));
+
+    // SCRAM verifiers of breached passwords are removed
+    assert_eq!(
+        store
+            .query(QueryBy::Name("jane"), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .secrets,
+        vec!["password".to_string()]
+    );
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("jane".to_string(), "wrong".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::Failure
+    ));
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("john".to_string(), "abc123-unique".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::Success(_)
+    ));
+
+    temp_dir.delete();
+}
+
+#[tokio::test]
+async fn scram_verifiers() {
+    let config = DirectoryTest::new(None).await;
+    let store = config.stores.stores.get("rocksdb").unwrap().clone();
+    let directory = config.directories.directories.get("rocksdb").unwrap();
+    store.destroy().await;
+    store
+        .create_account(Principal {
+            name: "jane".to_string(),
+            secrets: vec!["secret".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // Verifiers round-trip and can verify plain-text passwords
+    let verifier = ScramVerifier::new("secret");
+    assert_eq!(
+        ScramVerifier::parse(&verifier.to_string()),
+        Some(verifier.clone())
+    );
+    assert!(verifier.verify("secret"));
+    assert!(!verifier.verify("wrong"));
+    assert_eq!(
+        ScramVerifier::parse(&format!(
+            "{}$AAAA",
+            verifier.to_string().rsplit_once('$').unwrap().0
+        )),
+        None
+    );
+
+    // Without a verifier the exchange fails
+    let (server, proof) = scram_exchange(directory, "jane", "secret").await;
+    assert!(server.client_final(&proof).is_none());
+
+    // A verifier is captured on the next successful password login
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("jane".to_string(), "secret".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::Success(_)
+    ));
+    let principal = store
+        .query(QueryBy::Name("jane"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.secrets.len(), 2);
+    assert_eq!(principal.secrets[0], "secret");
+    assert!(is_scram_verifier(&principal.secrets[1]));
+
+    // Verifiers are never accepted for password logins
+    let principal = Principal {
+        secrets: vec![principal.secrets[1].clone()],
+        ..principal
+    };
+    assert!(!principal.verify_secret("secret").await);
+    assert!(!principal.verify_secret(&principal.secrets[0]).await);
+
+    // SCRAM succeeds with the right password only
+    let (server, proof) = scram_exchange(directory, "jane", "wrong").await;
+    assert!(server.client_final(&proof).is_none());
+    let (server, proof) = scram_exchange(directory, "jane", "secret").await;
+    assert!(server.client_final(&proof).unwrap().starts_with("v="));
+    assert!(matches!(
+        directory
+            .authenticate_scram("jane", true, "10.0.0.1".parse().unwrap(), false)
+            .await
+            .unwrap(),
+        AuthResult::Success(_)
+    ));
+
+    // SCRAM-SHA-256-PLUS binds the exchange to the TLS session
+    let channel_binding = b"tls-exporter-data".as_slice();
+    for (client_binding, is_valid) in [
+        (channel_binding, true),
+        (b"other-session-data".as_slice(), false),
+    ] {
+        let (server, proof) = scram_exchange_with(
+            directory,
+            "jane",
+            "secret",
+            ("p=tls-exporter,,", client_binding),
+            true,
+            Some(channel_binding),
+        )
+        .await;
+        assert_eq!(server.client_final(&proof).is_some(), is_valid);
+    }
+
+    // Unsupported channel binding types and downgrades are rejected
+    for (client_first, is_plus, channel_binding) in [
+        (
+            "p=tls-server-end-point,,n=jane,r=abc",
+            true,
+            Some(channel_binding),
+        ),
+        ("p=tls-exporter,,n=jane,r=abc", true, None),
+        ("p=tls-exporter,,n=jane,r=abc", false, Some(channel_binding)),
+        ("n,,n=jane,r=abc", true, Some(channel_binding)),
+        ("y,,n=jane,r=abc", false, Some(channel_binding)),
+    ] {
+        assert!(
+            ScramServer::new(client_first, is_plus, channel_binding).is_none(),
+            "{client_first}"
+        );
+    }
+    assert!(ScramServer::new("y,,n=jane,r=abc", false, None).is_some());
+    assert!(ScramServer::new("n,,n=jane,r=abc", false, Some(channel_binding)).is_some());
+
+    // Adding a new password drops the verifiers derived from the old one
+    store
+        .update_account(
+            QueryBy::Name("jane"),
+            vec![PrincipalUpdate::add_item(
+                PrincipalField::Secrets,
+                PrincipalValue::String("new-secret".to_string()),
+            )],
+        )
+        .await
+        .unwrap();
+    let principal = store
+        .query(QueryBy::Name("jane"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.secrets, vec!["secret", "new-secret"]);
+    let (server, proof) = scram_exchange(directory, "jane", "secret").await;
+    assert!(server.client_final(&proof).is_none());
+
+    // Verifiers supplied along with the new password are kept
+    let verifier = ScramVerifier::new("other-secret").to_string();
+    store
+        .update_account(
+            QueryBy::Name("jane"),
+            vec![PrincipalUpdate::set(
+                PrincipalField::Secrets,
+                PrincipalValue::StringList(vec!["other-secret".to_string(), verifier.clone()]),
+            )],
+        )
+        .await
+        .unwrap();
+    let principal = store
+        .query(QueryBy::Name("jane"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        principal.secrets,
+        vec!["other-secret".to_string(), verifier]
+    );
+    let (server, proof) = scram_exchange(directory, "jane", "other-secret").await;
+    assert!(server.client_final(&proof).unwrap().starts_with("v="));
+}
+
+#[tokio::test]
+async fn app_passwords() {
+    let principal = Principal::<u32> {
+        name: "john".to_string(),
+        secrets: vec![
+            hash_secret("password"),
+            app_password_secret("phone", "app-secret").await.unwrap(),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        principal.app_password_names().collect::<Vec<_>>(),
+        ["phone"]
+    );
+
+    // Application passwords log in, but cannot stand in for the password
+    assert!(principal.verify_secret("app-secret").await);
+    assert!(!principal.verify_password("app-secret").await);
+    assert!(principal.verify_password("password").await);
+    assert!(!principal.verify_password("wrong").await);
+}
+
+#[tokio::test]
+async fn password_rehash() {
+    let config = DirectoryTest::new(None).await;
+    let store = config.stores.stores.get("rocksdb").unwrap().clone();
+    store.destroy().await;
+    let directory = Directory {
+        store: DirectoryInner::Internal(store.clone()),
+        catch_all: AddressMapping::Disable,
+        subaddressing: AddressMapping::Disable,
+        cache: None,
+        blocked_ips: Arc::new(BlockedIps::new(store.clone().into())),
+        breach_check: None,
+        circuit_breaker: None,
+        password_rehash: true,
+    };
+
+    // Schemes are identified with and without a RFC 2307 prefix
+    for (secret, scheme) in [
+        ("$6$rounds=5000$salt$hash", "sha512-crypt"),
+        ("$1$salt$hash", "md5-crypt"),
+        ("{CRYPT}$1$salt$hash", "md5-crypt"),
+        ("{CRYPT}aaXYZ", "des-crypt"),
+        ("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA", "argon2id"),
+        (
+            "{ARGON2ID}$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "argon2id",
+        ),
+        ("$argon2i$v=19$m=4096,t=3,p=1$c2FsdA$aGFzaA", "argon2"),
+        ("{SSHA512}aGFzaA==", "ssha512"),
+        ("{FOO}bar", "unknown"),
+        ("secret", "plain"),
+    ] {
+        assert_eq!(password_scheme(secret), scheme, "{secret}");
+    }
+    assert_eq!(password_scheme(&hash_secret("secret")), "argon2id");
+    assert!(needs_rehash("md5-crypt"));
+    assert!(needs_rehash("plain"));
+    assert!(!needs_rehash("argon2id"));
+
+    for (name, secret) in [
+        ("jane", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="),
+        ("john", "secret"),
+    ] {
+        store
+            .create_account(Principal {
+                name: name.to_string(),
+                secrets: vec![secret.to_string()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
     }
+    store
+        .create_account(Principal {
+            name: "sales".to_string(),
+            typ: Type::Group,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        store.count_password_schemes().await.unwrap(),
+        BTreeMap::from([("plain", 1), ("sha1", 1)])
+    );
+
+    // Failed logins leave the stored hash untouched
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("jane".to_string(), "wrong".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::Failure
+    ));
+    assert_eq!(
+        store.count_password_schemes().await.unwrap(),
+        BTreeMap::from([("plain", 1), ("sha1", 1)])
+    );
+
+    // Successful logins rehash the password to Argon2id
+    assert!(matches!(
+        directory
+            .authenticate(
+                &Credentials::new("jane".to_string(), "password".to_string()),
+                "10.0.0.1".parse().unwrap(),
+                false
+            )
+            .await
+            .unwrap(),
+        AuthResult::Success(_)
+    ));
+    let principal = store
+        .query(QueryBy::Name("jane"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.secrets.len(), 2);
+    assert!(principal.secrets[0].starts_with("$argon2id$"));
+    assert!(is_scram_verifier(&principal.secrets[1]));
+    assert_eq!(principal.password_scheme(), Some("argon2id"));
+    assert!(principal.verify_secret("password").await);
+    assert!(!principal.verify_secret("wrong").await);
+    assert_eq!(
+        store.count_password_schemes().await.unwrap(),
+        BTreeMap::from([("argon2id", 1), ("plain", 1)])
+    );
+
+    // Argon2id hashes are not rehashed again
+    let hashed_secret = principal.secrets[0].clone();
+    directory
+        .authenticate(
+            &Credentials::new("jane".to_string(), "password".to_string()),
+            "10.0.0.1".parse().unwrap(),
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .query(QueryBy::Name("jane"), false)
+            .await
+            .unwrap()
+            .unwrap()
+            .secrets[0],
+        hashed_secret
+    );
+}
+
+async fn scram_exchange(
+    directory: &directory::Directory,
+    username: &str,
+    password: &str,
+) -> (ScramServer, String) {
+    scram_exchange_with(directory, username, password, ("n,,", b""), false, None).await
+}
+
+async fn scram_exchange_with(
+    directory: &directory::Directory,
+    username: &str,
+    password: &str,
+    (gs2_header, client_binding): (&str, &[u8]),
+    is_plus: bool,
+    channel_binding: Option<&[u8]>,
+) -> (ScramServer, String) {
+    let client_first_bare = format!("n={username},r=rOprNGfwEbeRWgbNEkqO");
+    let mut server = ScramServer::new(
+        &format!("{gs2_header}{client_first_bare}"),
+        is_plus,
+        channel_binding,
+    )
+    .unwrap();
+    let verifier = directory.scram_verifier(username).await.unwrap();
+    let server_first = server.server_first(verifier).to_string();
+
+    // Compute the client proof
+    let mut nonce = "";
+    let mut salt = vec![];
+    let mut iterations = 0;
+    for attribute in server_first.split(',') {
+        if let Some(value) = attribute.strip_prefix("r=") {
+            nonce = value;
+        } else if let Some(value) = attribute.strip_prefix("s=") {
+            salt = general_purpose::STANDARD.decode(value).unwrap();
+        } else if let Some(value) = attribute.strip_prefix("i=") {
+            iterations = value.parse().unwrap();
+        }
+    }
+    let mut salted_password = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, iterations, &mut salted_password);
+    let client_key = hmac_sha256(&salted_password, b"Client Key");
+    let stored_key = Sha256::digest(&client_key);
+    let mut binding = gs2_header.as_bytes().to_vec();
+    binding.extend_from_slice(client_binding);
+    let without_proof = format!("c={},r={nonce}", general_purpose::STANDARD.encode(&binding));
+    let auth_message = format!("{client_first_bare},{server_first},{without_proof}");
+    let signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+    let proof = client_key
+        .iter()
+        .zip(signature.iter())
+        .map(|(a, b)| a ^ b)
+        .collect::<Vec<_>>();
+
+    (
+        server,
+        format!(
+            "{without_proof},p={}",
+            general_purpose::STANDARD.encode(proof)
+        ),
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
 }
@@ -137,7 +137,9 @@ pub async fn test(mut imap_john: &mut ImapConnection, _imap_check: &mut ImapConn
     imap_john
         .assert_read(Type::Tagged, ResponseType::Ok)
         .await
-        .assert_equals("* NAMESPACE ((\"\" \"/\")) ((\"Shared Folders\" \"/\")) NIL");
+        .assert_equals(
+            "* NAMESPACE ((\"\" \"/\")) ((\"Shared Folders\" \"/\")) ((\"Unified Folders\" \"/\"))",
+        );
 
     // List John's right on Jane's Inbox
     imap_john
@@ -262,6 +264,46 @@ pub async fn test(mut imap_john: &mut ImapConnection, _imap_check: &mut ImapConn
         .await
         .assert_contains("copy test");
 
+    // The copied message shows up twice in John's unified inbox
+    imap_john.send("LIST \"\" \"Unified Folders/*\"").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_equals("* LIST () \"/\" \"Unified Folders/INBOX\"");
+    imap_john.send("EXAMINE \"Unified Folders/INBOX\"").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_contains("[READ-ONLY]");
+    imap_john.send("FETCH 1:* (PREVIEW)").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_count("copy test", 2);
+    imap_john.send("UID SEARCH BODY \"copy test\"").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_contains("* SEARCH ");
+    imap_john
+        .send("STATUS \"Unified Folders/INBOX\" (MESSAGES UIDVALIDITY)")
+        .await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_contains("UIDVALIDITY");
+
+    // Unified folders are read-only
+    imap_john.send("STORE 1 +FLAGS (\\Deleted)").await;
+    imap_john.assert_read(Type::Tagged, ResponseType::No).await;
+    imap_john.send("COPY 1 INBOX").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::No)
+        .await
+        .assert_response_code("CANNOT");
+    imap_john.send("UNSELECT").await;
+    imap_john.assert_read(Type::Tagged, ResponseType::Ok).await;
+
     // Bill now moves the message to his own Inbox
     imap_bill.send(&format!("UID MOVE {} INBOX", uid)).await;
     let uid_moved = imap_bill
@@ -28,6 +28,7 @@ use imap_proto::{
     protocol::fetch::{BodyContents, DataItem, Section},
     ResponseCode, StatusResponse,
 };
+use jmap::email::{metadata::MessageMetadataContents, stats::BodyStats};
 use mail_parser::MessageParser;
 
 use super::resources_dir;
@@ -48,8 +49,22 @@ fn body_structure() {
         for is_extended in [false, true] {
             let mut buf_ = Vec::new();
             message
-                .body_structure(is_extended)
+                .body_structure(is_extended, None)
                 .serialize(&mut buf_, is_extended);
+
+            // Body structures built from the stored metadata and the part
+            // statistics computed at ingest must match the ones built from the raw message
+            let mut buf_stats = Vec::new();
+            MessageMetadataContents::from(MessageParser::new().parse(&raw_message).unwrap())
+                .into_message(&[])
+                .body_structure(is_extended, Some(&BodyStats::from_message(&message)))
+                .serialize(&mut buf_stats, is_extended);
+            assert_eq!(
+                String::from_utf8_lossy(&buf_),
+                String::from_utf8_lossy(&buf_stats),
+                "{}",
+                file_name.display()
+            );
             if is_extended {
                 buf.extend_from_slice(b"BODYSTRUCTURE ");
             } else {
@@ -0,0 +1,104 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use utils::listener::compress::DeflateStream;
+
+pub async fn test() {
+    println!("Running COMPRESS tests...");
+
+    let mut stream = BufReader::new(TcpStream::connect("127.0.0.1:9991").await.unwrap());
+    assert_contains(&mut stream, "* OK", "COMPRESS=DEFLATE").await;
+
+    // Compression requires an authenticated session
+    send(&mut stream, "a COMPRESS DEFLATE").await;
+    assert_contains(&mut stream, "a NO", "").await;
+    send(
+        &mut stream,
+        "b AUTHENTICATE PLAIN {32+}\r\nAGpkb2VAZXhhbXBsZS5jb20Ac2VjcmV0",
+    )
+    .await;
+    assert_contains(&mut stream, "b OK", "COMPRESS=DEFLATE").await;
+
+    // Unsupported algorithms are rejected
+    send(&mut stream, "c COMPRESS BZIP2").await;
+    assert_contains(&mut stream, "c BAD", "").await;
+
+    // Enable compression, all further traffic is deflated
+    send(&mut stream, "d COMPRESS DEFLATE").await;
+    assert_contains(&mut stream, "d OK", "DEFLATE active").await;
+    let mut stream = BufReader::new(DeflateStream::new(stream.into_inner()));
+
+    send(&mut stream, "e CAPABILITY").await;
+    let capabilities = assert_contains(&mut stream, "e OK", "").await;
+    assert!(!capabilities.contains("COMPRESS=DEFLATE"), "{capabilities}");
+    send(&mut stream, "f LIST \"\" \"*\"").await;
+    assert_contains(&mut stream, "f OK", "INBOX").await;
+
+    // Compression cannot be negotiated twice or followed by STARTTLS
+    send(&mut stream, "g COMPRESS DEFLATE").await;
+    assert_contains(&mut stream, "g NO", "[COMPRESSIONACTIVE]").await;
+    send(&mut stream, "h STARTTLS").await;
+    assert_contains(&mut stream, "h BAD", "").await;
+
+    send(&mut stream, "i LOGOUT").await;
+    assert_contains(&mut stream, "i OK", "* BYE").await;
+}
+
+async fn send<T: AsyncWrite + Unpin>(stream: &mut BufReader<T>, command: &str) {
+    let stream = stream.get_mut();
+    stream.write_all(command.as_bytes()).await.unwrap();
+    stream.write_all(b"\r\n").await.unwrap();
+    stream.flush().await.unwrap();
+}
+
+async fn assert_contains<T: AsyncBufRead + Unpin>(
+    stream: &mut T,
+    last_line: &str,
+    expected: &str,
+) -> String {
+    let mut response = String::new();
+    loop {
+        let mut line = String::new();
+        assert!(
+            tokio::time::timeout(
+                std::time::Duration::from_millis(1500),
+                stream.read_line(&mut line)
+            )
+            .await
+            .unwrap()
+            .unwrap()
+                > 0,
+            "Connection closed, response: {response:?}"
+        );
+        response.push_str(&line);
+        if line.starts_with(last_line) {
+            break;
+        }
+    }
+    assert!(response.contains(expected), "{response:?}");
+    response
+}
@@ -25,6 +25,7 @@ pub mod acl;
 pub mod append;
 pub mod basic;
 pub mod body_structure;
+pub mod compress;
 pub mod condstore;
 pub mod copy_move;
 pub mod fetch;
@@ -173,6 +174,7 @@ private-key = "file://{PK}"
 
 [imap.protocol]
 uidplus = true
+compress = true
 
 [storage]
 data = "{STORE}"
@@ -419,6 +421,7 @@ pub async fn imap_tests() {
     idle::test(&mut imap, &mut imap_check).await;
     condstore::test(&mut imap, &mut imap_check).await;
     acl::test(&mut imap, &mut imap_check).await;
+    compress::test().await;
 
     // Logout
     for imap in [&mut imap, &mut imap_check] {
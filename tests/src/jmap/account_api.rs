@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running account API tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+
+    // Forwarding is empty by default
+    let (status, response) = account_request(Method::GET, "forwarding", None).await;
+    assert_eq!(status, 200, "{}", response);
+    assert!(response.contains("\"addresses\":[]"), "{}", response);
+    assert!(response.contains("\"keepCopy\":true"), "{}", response);
+
+    // Invalid addresses are rejected
+    let (status, response) = account_request(
+        Method::PUT,
+        "forwarding",
+        Some(r#"{"addresses": ["not an address"], "keepCopy": true}"#),
+    )
+    .await;
+    assert_eq!(status, 400, "{}", response);
+
+    // Enabling forwarding compiles a filter script
+    let (status, response) = account_request(
+        Method::PUT,
+        "forwarding",
+        Some(r#"{"addresses": ["Bill@Example.com", "bill@example.com"], "keepCopy": false}"#),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", response);
+    let (_, response) = account_request(Method::GET, "forwarding", None).await;
+    assert!(
+        response.contains("\"addresses\":[\"bill@example.com\"]"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\"keepCopy\":false"), "{}", response);
+    assert!(server
+        .sieve_script_get_active(account_id.document_id())
+        .await
+        .unwrap()
+        .is_some());
+
+    // Disabling forwarding removes the script
+    let (status, response) = account_request(
+        Method::PUT,
+        "forwarding",
+        Some(r#"{"addresses": [], "keepCopy": true}"#),
+    )
+    .await;
+    assert_eq!(status, 200, "{}", response);
+    assert!(server
+        .sieve_script_get_active(account_id.document_id())
+        .await
+        .unwrap()
+        .is_none());
+
+    // Revoking sessions bumps the session generation
+    let generation = server
+        .session_generation(account_id.document_id())
+        .await
+        .unwrap();
+    let (status, response) = account_request(Method::DELETE, "sessions", None).await;
+    assert_eq!(status, 200, "{}", response);
+    assert_eq!(
+        server
+            .session_generation(account_id.document_id())
+            .await
+            .unwrap(),
+        generation + 1
+    );
+
+    // Unknown endpoints return not found
+    let (status, _) = account_request(Method::GET, "unknown", None).await;
+    assert_eq!(status, 404);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn account_request(method: Method, path: &str, body: Option<&str>) -> (u16, String) {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap()
+        .request(method, format!("https://127.0.0.1:8899/account/{path}"))
+        .basic_auth("jdoe@example.com", Some("12345"));
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+
+    (status, response.text().await.unwrap())
+}
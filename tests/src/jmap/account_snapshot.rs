@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_client::{
+    email::{self, query::Filter},
+    mailbox::Role,
+};
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running account snapshot tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let client = &mut params.client;
+
+    // Populate account
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    let projects_id = client
+        .mailbox_create("Projects", None::<&str>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let mut email_ids = Vec::new();
+    for (num, mailbox_id, keyword) in [
+        (1, &inbox_id, "$seen"),
+        (2, &projects_id, "$flagged"),
+        (3, &inbox_id, "$answered"),
+    ] {
+        email_ids.push(
+            client
+                .email_import(
+                    format!(
+                        concat!(
+                            "From: bill@example.com\r\n",
+                            "Message-ID: <snapshot-{}@example.com>\r\n",
+                            "Subject: Message {}\r\n",
+                            "\r\n",
+                            "Test message {}."
+                        ),
+                        num, num, num
+                    )
+                    .into_bytes(),
+                    [mailbox_id],
+                    Some(vec![keyword]),
+                    Some(1275386400 + num),
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+
+    // Take snapshot
+    let (status, response) = manage_request(Method::POST, "snapshot/jdoe@example.com", None).await;
+    assert_eq!(status, 200, "{response}");
+    let snapshot_id = serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"]
+        .as_u64()
+        .unwrap();
+    let (status, response) = manage_request(Method::GET, "snapshot/jdoe@example.com", None).await;
+    assert_eq!(status, 200, "{response}");
+    let snapshots = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+    let snapshots = snapshots["data"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 1, "{snapshots:?}");
+    assert_eq!(snapshots[0]["id"], snapshot_id);
+    assert_eq!(snapshots[0]["messages"], 3);
+    assert_eq!(snapshots[0]["mailboxes"], 6);
+
+    // Restoring an unchanged account is a no-op
+    let response = restore_snapshot(snapshot_id).await;
+    assert_eq!(response["restored"], 0, "{response}");
+    assert_eq!(response["updated"], 0, "{response}");
+
+    // Simulate a rogue client wiping out the account
+    client.mailbox_destroy(&projects_id, true).await.unwrap();
+    client.email_destroy(&email_ids[2]).await.unwrap();
+    client
+        .email_set_keyword(&email_ids[0], "$seen", false)
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .email_query(None::<Filter>, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        1
+    );
+
+    // Restore account
+    let response = restore_snapshot(snapshot_id).await;
+    assert_eq!(response["mailboxesCreated"], 1, "{response}");
+    assert_eq!(response["restored"], 2, "{response}");
+    assert_eq!(response["updated"], 1, "{response}");
+    assert_eq!(response["notRestored"].as_array().unwrap().len(), 0);
+
+    let mut restored = Vec::new();
+    for email_id in client
+        .email_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids()
+    {
+        let email = client
+            .email_get(
+                &email_id,
+                [
+                    email::Property::Subject,
+                    email::Property::ReceivedAt,
+                    email::Property::Keywords,
+                    email::Property::MailboxIds,
+                ]
+                .into(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        restored.push((
+            email.subject().unwrap().to_string(),
+            email.received_at().unwrap(),
+            email.keywords().to_vec(),
+            email.mailbox_ids().len(),
+            email.mailbox_ids().contains(&inbox_id.as_str()),
+        ));
+    }
+    restored.sort_unstable();
+    assert_eq!(
+        restored,
+        [
+            ("Message 1".to_string(), 1275386401, vec!["$seen"], 1, true),
+            (
+                "Message 2".to_string(),
+                1275386402,
+                vec!["$flagged"],
+                1,
+                false
+            ),
+            (
+                "Message 3".to_string(),
+                1275386403,
+                vec!["$answered"],
+                1,
+                true
+            ),
+        ]
+    );
+
+    // Restoring again does not create duplicates
+    let response = restore_snapshot(snapshot_id).await;
+    assert_eq!(response["mailboxesCreated"], 0, "{response}");
+    assert_eq!(response["restored"], 0, "{response}");
+    assert_eq!(response["updated"], 0, "{response}");
+
+    // Delete snapshot
+    let (status, _) = manage_request(
+        Method::DELETE,
+        &format!("snapshot/jdoe@example.com/{snapshot_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, _) = manage_request(
+        Method::DELETE,
+        &format!("snapshot/jdoe@example.com/{snapshot_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, 404);
+    let (_, response) = manage_request(Method::GET, "snapshot/jdoe@example.com", None).await;
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"],
+        serde_json::json!([])
+    );
+
+    // Unknown accounts are rejected
+    let (status, _) = manage_request(Method::POST, "snapshot/nobody@example.com", None).await;
+    assert_eq!(status, 404);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn restore_snapshot(snapshot_id: u64) -> serde_json::Value {
+    let (status, response) = manage_request(
+        Method::POST,
+        &format!("snapshot/jdoe@example.com/{snapshot_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, 200, "{response}");
+    serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone()
+}
@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use directory::backend::internal::manage::ManageDirectory;
+use futures::StreamExt;
+use jmap::RateLimits;
+use jmap_proto::types::id::Id;
+use reqwest::{header::HeaderMap, Method};
+use utils::config::Rate;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes, test_account_login};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running API rate limit tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    )
+    .to_string();
+    params.client.set_default_account_id(&account_id);
+
+    // Requests are not limited nor annotated unless configured
+    let (status, headers) = api_request(ECHO, ("jdoe@example.com", "12345"), "jmap/").await;
+    assert_eq!(status, 200);
+    assert!(headers.get("ratelimit-limit").is_none());
+
+    // Enable API rate limits
+    let rate = |requests| {
+        Some(Rate {
+            requests,
+            period: Duration::from_secs(3600),
+        })
+    };
+    let rate_limits = server.config.rate_limits.load_full();
+    server.config.rate_limits.store(Arc::new(RateLimits {
+        authenticated: rate_limits.authenticated.clone(),
+        authenticate_req: rate_limits.authenticate_req.clone(),
+        anonymous: rate_limits.anonymous.clone(),
+        download_account: rate_limits.download_account.clone(),
+        download_ip: rate_limits.download_ip.clone(),
+        principal_lookup: rate_limits.principal_lookup.clone(),
+        api_cheap: rate(5),
+        api_expensive: rate(1),
+        management: rate(2),
+    }));
+
+    // Cheap methods are counted against their own bucket
+    let (status, headers) = api_request(ECHO, ("jdoe@example.com", "12345"), "jmap/").await;
+    assert_eq!(status, 200);
+    assert_rate_limit(&headers, 5, 4);
+    assert!(headers.get("retry-after").is_none());
+
+    // Expensive methods are limited separately, the most constrained bucket is reported
+    let query = format!(
+        concat!(
+            r#"{{"using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"], "#,
+            r#""methodCalls": [["Email/query", {{"accountId": "{}"}}, "c0"], "#,
+            r#"["Email/get", {{"accountId": "{}", "ids": []}}, "c1"]]}}"#
+        ),
+        account_id, account_id
+    );
+    let (status, headers) = api_request(&query, ("jdoe@example.com", "12345"), "jmap/").await;
+    assert_eq!(status, 200);
+    assert_rate_limit(&headers, 1, 0);
+    let (status, headers) = api_request(&query, ("jdoe@example.com", "12345"), "jmap/").await;
+    assert_eq!(status, 429);
+    assert_rate_limit(&headers, 1, 0);
+    assert!(headers.get("retry-after").is_some());
+
+    // A client stuck querying does not prevent cheap requests
+    for remaining in [1, 0] {
+        let (status, headers) = api_request(ECHO, ("jdoe@example.com", "12345"), "jmap/").await;
+        assert_eq!(status, 200);
+        assert_rate_limit(&headers, 5, remaining);
+    }
+    let (status, _) = api_request(ECHO, ("jdoe@example.com", "12345"), "jmap/").await;
+    assert_eq!(status, 429);
+
+    // Requests sent over WebSockets count against the same limits
+    let client = test_account_login("jdoe@example.com", "12345").await;
+    let mut ws_stream = client.connect_ws().await.unwrap();
+    let mut request = client.build();
+    request.get_mailbox();
+    request.send_ws().await.unwrap();
+    match tokio::time::timeout(Duration::from_millis(1000), ws_stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+    {
+        Err(jmap_client::Error::Problem(problem)) => assert_eq!(problem.status(), Some(429)),
+        other => panic!("Expected rate limit error, got {other:?}"),
+    }
+
+    // Management requests have their own limit
+    for remaining in [1, 0] {
+        let (status, headers) = api_request("", ("admin", "secret"), "api/principal").await;
+        assert_eq!(status, 200);
+        assert_rate_limit(&headers, 2, remaining);
+    }
+    let (status, headers) = api_request("", ("admin", "secret"), "api/principal").await;
+    assert_eq!(status, 429);
+    assert!(headers.get("retry-after").is_some());
+
+    // Restore rate limits
+    server.config.rate_limits.store(rate_limits);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+const ECHO: &str = concat!(
+    r#"{"using": ["urn:ietf:params:jmap:core"], "#,
+    r#""methodCalls": [["Core/echo", {"hello": "world"}, "c0"]]}"#
+);
+
+fn assert_rate_limit(headers: &HeaderMap, limit: u64, remaining: u64) {
+    for (name, value) in [
+        ("ratelimit-limit", limit),
+        ("ratelimit-remaining", remaining),
+    ] {
+        assert_eq!(
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok()),
+            Some(value),
+            "{name}: {headers:?}"
+        );
+    }
+    assert!(headers.get("ratelimit-reset").is_some(), "{headers:?}");
+}
+
+async fn api_request(body: &str, credentials: (&str, &str), path: &str) -> (u16, HeaderMap) {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap();
+    let url = format!("https://127.0.0.1:8899/{path}");
+    let request = if body.is_empty() {
+        client.request(Method::GET, url)
+    } else {
+        client.request(Method::POST, url).body(body.to_string())
+    };
+    let response = request
+        .basic_auth(credentials.0, Some(credentials.1))
+        .send()
+        .await
+        .unwrap();
+
+    (response.status().as_u16(), response.headers().clone())
+}
@@ -30,13 +30,14 @@ use jmap_client::{
     client::{Client, Credentials},
     core::set::{SetError, SetErrorType},
     mailbox::{self},
+    principal,
 };
 use jmap_proto::types::id::Id;
 use store::{dispatch::blocked::BLOCKED_IP_KEY, write::now};
 
 use crate::{
     imap::{ImapConnection, Type},
-    jmap::{assert_is_empty, mailbox::destroy_all_mailboxes},
+    jmap::{assert_is_empty, auth_acl::assert_forbidden, mailbox::destroy_all_mailboxes},
 };
 
 use super::JMAPTest;
@@ -224,6 +225,39 @@ pub async fn test(params: &mut JMAPTest) {
     client.identity_destroy(&iid1).await.unwrap();
     client.identity_destroy(&iid2).await.unwrap();
 
+    // Lookups of principals that do not exist should be rate limited,
+    // and the addresses of the account penalized
+    for email in [
+        "unknown1@example.com",
+        "unknown2@example.com",
+        "unknown3@example.com",
+    ] {
+        assert!(client
+            .principal_query(
+                principal::query::Filter::email(email).into(),
+                None::<Vec<_>>
+            )
+            .await
+            .unwrap()
+            .ids()
+            .is_empty());
+    }
+    assert_forbidden(
+        client
+            .principal_query(
+                principal::query::Filter::email("jdoe@example.com").into(),
+                None::<Vec<_>>,
+            )
+            .await,
+    );
+    assert!(server
+        .smtp
+        .shared
+        .default_lookup_store
+        .key_exists(b"f:jdoe@example.com".to_vec())
+        .await
+        .unwrap());
+
     // Concurrent requests check
     let client = Arc::new(client);
     for _ in 0..8 {
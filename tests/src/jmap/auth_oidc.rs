@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose, Engine};
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::auth::oidc::OidcProvider;
+use jmap_client::client::{Client, Credentials};
+use jmap_proto::types::id::Id;
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::watch,
+};
+use utils::config::Config;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+const ISSUER: &str = "https://idp.example.org";
+const AUDIENCE: &str = "stalwart-test";
+const KEY_ID: &str = "test-key";
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running OpenID Connect tests...");
+
+    // An audience is required
+    assert!(OidcProvider::parse(
+        &Config::new("[oauth.oidc]\nissuer = \"https://idp.example.org\"\n").unwrap()
+    )
+    .is_err());
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let john_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    )
+    .to_string();
+
+    // Publish the identity provider's signing key
+    let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+    let shutdown = spawn_mock_jwks_server(&signing_key);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Tokens identifying the account by the configured login claim are accepted
+    let token = sign_jwt(
+        &signing_key,
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "JDoe@example.com",
+            "iat": now,
+            "exp": now + 300,
+        }),
+    );
+    let client = Client::new()
+        .credentials(Credentials::bearer(&token))
+        .accept_invalid_certs(true)
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap();
+    assert_eq!(client.default_account_id(), john_id);
+
+    // Tokens identifying the account by a verified email address are accepted
+    let token = sign_jwt(
+        &signing_key,
+        json!({
+            "iss": ISSUER,
+            "aud": [AUDIENCE, "other-client"],
+            "sub": "00u8f2a9",
+            "email": "jdoe@example.com",
+            "email_verified": true,
+            "exp": now + 300,
+        }),
+    );
+    let client = Client::new()
+        .credentials(Credentials::bearer(&token))
+        .accept_invalid_certs(true)
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap();
+    assert_eq!(client.default_account_id(), john_id);
+
+    // The subject is not mapped to a login name
+    assert_unauthorized(&sign_jwt(
+        &signing_key,
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "sub": "jdoe@example.com",
+            "exp": now + 300,
+        }),
+    ))
+    .await;
+
+    // Addresses outside the local domains are not trusted
+    assert_unauthorized(&sign_jwt(
+        &signing_key,
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "email": "jdoe@example.net",
+            "email_verified": true,
+            "exp": now + 300,
+        }),
+    ))
+    .await;
+
+    // Unverified email addresses are not trusted
+    assert_unauthorized(&sign_jwt(
+        &signing_key,
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "sub": "00u8f2a9",
+            "email": "jdoe@example.com",
+            "email_verified": false,
+            "exp": now + 300,
+        }),
+    ))
+    .await;
+
+    // Tokens with an invalid issuer, audience or lifetime are rejected
+    for claims in [
+        json!({
+            "iss": "https://evil.example.org",
+            "aud": AUDIENCE,
+            "preferred_username": "jdoe@example.com",
+            "exp": now + 300,
+        }),
+        json!({
+            "iss": ISSUER,
+            "aud": "other-client",
+            "preferred_username": "jdoe@example.com",
+            "exp": now + 300,
+        }),
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "jdoe@example.com",
+            "exp": now - 3600,
+        }),
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "jdoe@example.com",
+            "nbf": now + 3600,
+            "exp": now + 7200,
+        }),
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "jdoe@example.com",
+        }),
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "nobody@example.com",
+            "exp": now + 300,
+        }),
+    ] {
+        assert_unauthorized(&sign_jwt(&signing_key, claims)).await;
+    }
+
+    // Tokens signed by a different key are rejected
+    let claims = json!({
+        "iss": ISSUER,
+        "aud": AUDIENCE,
+        "preferred_username": "jdoe@example.com",
+        "exp": now + 300,
+    });
+    assert_unauthorized(&sign_jwt(
+        &SigningKey::from_slice(&[9u8; 32]).unwrap(),
+        claims.clone(),
+    ))
+    .await;
+
+    // Tampered tokens are rejected
+    let token = sign_jwt(&signing_key, claims);
+    let mut parts = token.split('.').collect::<Vec<_>>();
+    let tampered_claims = general_purpose::URL_SAFE_NO_PAD.encode(
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "preferred_username": "admin",
+            "exp": now + 300,
+        })
+        .to_string(),
+    );
+    parts[1] = &tampered_claims;
+    assert_unauthorized(&parts.join(".")).await;
+
+    // Remove test data
+    shutdown.send(false).ok();
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+fn sign_jwt(signing_key: &SigningKey, claims: serde_json::Value) -> String {
+    let signing_input = format!(
+        "{}.{}",
+        general_purpose::URL_SAFE_NO_PAD.encode(
+            json!({
+                "alg": "ES256",
+                "typ": "JWT",
+                "kid": KEY_ID,
+            })
+            .to_string()
+        ),
+        general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature: Signature = signing_key.sign(signing_input.as_bytes());
+    format!(
+        "{signing_input}.{}",
+        general_purpose::URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+async fn assert_unauthorized(token: &str) {
+    match Client::new()
+        .credentials(Credentials::bearer(token))
+        .accept_invalid_certs(true)
+        .connect("https://127.0.0.1:8899")
+        .await
+    {
+        Ok(_) => panic!("Expected unauthorized access."),
+        Err(err) => {
+            let err = err.to_string();
+            assert!(err.contains("Unauthorized"), "{}", err);
+        }
+    }
+}
+
+fn spawn_mock_jwks_server(signing_key: &SigningKey) -> watch::Sender<bool> {
+    let (tx, mut rx) = watch::channel(true);
+    let point = signing_key.verifying_key().to_encoded_point(false);
+    let jwks = json!({
+        "keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": KEY_ID,
+            "alg": "ES256",
+            "use": "sig",
+            "x": general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            "y": general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        }]
+    })
+    .to_string();
+
+    tokio::spawn(async move {
+        let listener = TcpListener::bind("127.0.0.1:9914")
+            .await
+            .unwrap_or_else(|e| {
+                panic!("Failed to bind mock JWKS server to 127.0.0.1:9914: {e}");
+            });
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((mut stream, _)) => {
+                            let jwks = jwks.clone();
+                            tokio::spawn(async move {
+                                let mut buf = vec![0u8; 4096];
+                                let _ = stream.read(&mut buf).await;
+                                let response = format!(
+                                    concat!(
+                                        "HTTP/1.1 200 OK\r\n",
+                                        "Content-Type: application/json\r\n",
+                                        "Content-Length: {}\r\n",
+                                        "Connection: close\r\n\r\n{}"
+                                    ),
+                                    jwks.len(),
+                                    jwks
+                                );
+                                let _ = stream.write_all(response.as_bytes()).await;
+                                let _ = stream.flush().await;
+                            });
+                        }
+                        Err(err) => {
+                            panic!("Something went wrong: {err}" );
+                        }
+                    }
+                },
+                _ = rx.changed() => {
+                    break;
+                }
+            };
+        }
+    });
+
+    tx
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::services::purge::ROLLUP_LOCK_KEY;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+use store::write::{
+    BillingClass, BILLING_BANDWIDTH_BYTES, BILLING_RECEIVED_BYTES, BILLING_RECEIVED_MESSAGES,
+    BILLING_SENT_BYTES, BILLING_SENT_MESSAGES,
+};
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running billing rollup tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+
+    // Usage counters are moved into the rollup
+    server
+        .billing_incr(
+            account_id,
+            &[
+                (BILLING_RECEIVED_MESSAGES, 2),
+                (BILLING_RECEIVED_BYTES, 1000),
+                (BILLING_SENT_MESSAGES, 1),
+                (BILLING_SENT_BYTES, 500),
+            ],
+        )
+        .await;
+    let rollup = billing_rollup(account_id).await;
+    assert_eq!(rollup["domain"], "example.com", "{rollup}");
+    assert_eq!(rollup["receivedMessages"], 2, "{rollup}");
+    assert_eq!(rollup["receivedBytes"], 1000, "{rollup}");
+    assert_eq!(rollup["sentMessages"], 1, "{rollup}");
+    assert_eq!(rollup["sentBytes"], 500, "{rollup}");
+    assert_eq!(rollup["bandwidthBytes"], 0, "{rollup}");
+    for metric in [
+        BILLING_RECEIVED_MESSAGES,
+        BILLING_RECEIVED_BYTES,
+        BILLING_SENT_MESSAGES,
+        BILLING_SENT_BYTES,
+        BILLING_BANDWIDTH_BYTES,
+    ] {
+        assert_eq!(
+            server
+                .store
+                .get_counter(BillingClass::Counter { account_id, metric })
+                .await
+                .unwrap(),
+            0,
+            "metric {metric}"
+        );
+    }
+
+    // Later rollups in the same period are merged, without counting usage twice
+    server
+        .billing_incr(
+            account_id,
+            &[
+                (BILLING_RECEIVED_MESSAGES, 1),
+                (BILLING_RECEIVED_BYTES, 100),
+            ],
+        )
+        .await;
+    let rollup = billing_rollup(account_id).await;
+    assert_eq!(rollup["receivedMessages"], 3, "{rollup}");
+    assert_eq!(rollup["receivedBytes"], 1100, "{rollup}");
+    assert_eq!(rollup["sentMessages"], 1, "{rollup}");
+    let rollup = billing_rollup(account_id).await;
+    assert_eq!(rollup["receivedMessages"], 3, "{rollup}");
+    assert_eq!(rollup["receivedBytes"], 1100, "{rollup}");
+
+    // A rollup in progress blocks concurrent runs
+    server
+        .billing_incr(account_id, &[(BILLING_SENT_MESSAGES, 1)])
+        .await;
+    assert!(server
+        .lookup_store
+        .key_claim(ROLLUP_LOCK_KEY.to_vec(), 60)
+        .await
+        .unwrap());
+    let (status, response) = manage_request(Method::POST, "billing/rollup", None).await;
+    assert_eq!(status, 409, "{response}");
+    assert_eq!(
+        server
+            .store
+            .get_counter(BillingClass::Counter {
+                account_id,
+                metric: BILLING_SENT_MESSAGES
+            })
+            .await
+            .unwrap(),
+        1
+    );
+    server
+        .lookup_store
+        .key_delete(ROLLUP_LOCK_KEY.to_vec())
+        .await
+        .unwrap();
+    let rollup = billing_rollup(account_id).await;
+    assert_eq!(rollup["sentMessages"], 2, "{rollup}");
+    assert!(!server
+        .lookup_store
+        .key_exists(ROLLUP_LOCK_KEY.to_vec())
+        .await
+        .unwrap());
+
+    // Export rollups by account and by domain
+    let (status, response) =
+        manage_request(Method::GET, "billing/export?group=account", None).await;
+    assert_eq!(status, 200, "{response}");
+    let export = serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone();
+    let rollup = export
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|rollup| rollup["accountId"] == account_id)
+        .unwrap_or_else(|| panic!("Missing account rollup: {export}"));
+    assert_eq!(rollup["receivedMessages"], 3, "{rollup}");
+    assert_eq!(rollup["sentMessages"], 2, "{rollup}");
+    let (status, response) =
+        manage_request(Method::GET, "billing/export?domain=example.com", None).await;
+    assert_eq!(status, 200, "{response}");
+    let export = serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone();
+    assert_eq!(export.as_array().unwrap().len(), 1, "{export}");
+    assert_eq!(export[0]["domain"], "example.com", "{export}");
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn billing_rollup(account_id: u32) -> serde_json::Value {
+    let (status, response) = manage_request(Method::POST, "billing/rollup", None).await;
+    assert_eq!(status, 200, "{response}");
+    let rollups = serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone();
+    rollups
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|rollup| rollup["accountId"] == account_id)
+        .unwrap_or_else(|| panic!("Missing account rollup: {rollups}"))
+        .clone()
+}
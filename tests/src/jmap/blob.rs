@@ -118,6 +118,34 @@ pub async fn test(params: &mut JMAPTest) {
     .unwrap()
     .to_string();
 
+    // Download with range and ETag validation
+    let url = format!(
+        "https://127.0.0.1:8899/jmap/download/{account_id}/{blob_id}/fox.txt?accept=text/plain"
+    );
+    let response = download(&url, &[]).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{blob_id}\""));
+    assert_eq!(
+        response.text().await.unwrap(),
+        "The quick brown fox jumped over the lazy dog."
+    );
+    let response = download(&url, &[("range", "bytes=4-8")]).await;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.headers()["content-range"], "bytes 4-8/45");
+    assert_eq!(response.text().await.unwrap(), "quick");
+    let response = download(&url, &[("range", "bytes=-4")]).await;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.text().await.unwrap(), "dog.");
+    let response = download(&url, &[("range", "bytes=100-")]).await;
+    assert_eq!(response.status(), 416);
+    assert_eq!(response.headers()["content-range"], "bytes */45");
+    let response = download(&url, &[("range", "bytes=4-8"), ("if-range", "\"other\"")]).await;
+    assert_eq!(response.status(), 200);
+    let response = download(&url, &[("if-none-match", &etag)]).await;
+    assert_eq!(response.status(), 304);
+
     let response = jmap_json_request(
         r#"[
             [
@@ -498,3 +526,16 @@ pub async fn test(params: &mut JMAPTest) {
     destroy_all_mailboxes(params).await;
     assert_is_empty(server).await;
 }
+
+async fn download(url: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(url)
+        .basic_auth("jdoe@example.com", Some("12345"));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap()
+}
@@ -0,0 +1,219 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running resumable upload tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let upload_url = format!("https://127.0.0.1:8899/jmap/upload/{account_id}/");
+    let data = (0..50000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect::<Vec<_>>();
+
+    // Create an upload session
+    let response = upload_request(
+        Method::POST,
+        &upload_url,
+        &[
+            ("upload-length", "50000"),
+            ("content-type", "application/pdf"),
+        ],
+        vec![],
+    )
+    .await;
+    assert_eq!(response.status(), 201);
+    assert_eq!(header(&response, "upload-offset"), "0");
+    let session_url = format!("https://127.0.0.1:8899{}", header(&response, "location"));
+    assert!(session_url.starts_with(&upload_url), "{session_url}");
+    let response = upload_request(Method::HEAD, &session_url, &[], vec![]).await;
+    assert_eq!(header(&response, "upload-offset"), "0");
+    assert_eq!(header(&response, "upload-length"), "50000");
+
+    // Chunks must be sent in order and within the configured size
+    let response = upload_request(
+        Method::PATCH,
+        &session_url,
+        &[("upload-offset", "100")],
+        data[..100].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 409);
+    let response = upload_request(
+        Method::PATCH,
+        &session_url,
+        &[("upload-offset", "0")],
+        data[..25000].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+    let response = upload_request(Method::PATCH, &session_url, &[], data[..100].to_vec()).await;
+    assert_eq!(response.status(), 400);
+
+    // Upload the blob in several chunks
+    for (offset, expected_offset) in [(0, "20000"), (20000, "40000")] {
+        let response = upload_request(
+            Method::PATCH,
+            &session_url,
+            &[("upload-offset", &offset.to_string())],
+            data[offset..offset + 20000].to_vec(),
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(header(&response, "upload-offset"), expected_offset);
+    }
+    let response = upload_request(Method::HEAD, &session_url, &[], vec![]).await;
+    assert_eq!(header(&response, "upload-offset"), "40000");
+
+    // Sending the last chunk returns the assembled blob
+    let response = upload_request(
+        Method::PATCH,
+        &session_url,
+        &[("upload-offset", "40000")],
+        data[40000..].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    let response = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(response["size"], 50000, "{response}");
+    assert_eq!(response["type"], "application/pdf", "{response}");
+    let blob_id = response["blobId"].as_str().unwrap().to_string();
+    assert_eq!(
+        upload_request(
+            Method::GET,
+            &format!("https://127.0.0.1:8899/jmap/download/{account_id}/{blob_id}/file.pdf"),
+            &[],
+            vec![],
+        )
+        .await
+        .bytes()
+        .await
+        .unwrap()
+        .as_ref(),
+        data.as_slice()
+    );
+
+    // Completed sessions are removed
+    let response = upload_request(Method::HEAD, &session_url, &[], vec![]).await;
+    assert_eq!(response.status(), 404);
+
+    // Uploads exceeding the maximum size are rejected
+    let response = upload_request(
+        Method::POST,
+        &upload_url,
+        &[("upload-length", "100001")],
+        vec![],
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+
+    // Small blobs can be sent along with the session creation request
+    let response = upload_request(
+        Method::POST,
+        &upload_url,
+        &[("upload-length", "10")],
+        data[..10].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    let response = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(response["size"], 10, "{response}");
+    assert_eq!(response["type"], "application/octet-stream", "{response}");
+
+    // Cancel an upload
+    let response = upload_request(
+        Method::POST,
+        &upload_url,
+        &[("upload-length", "50000")],
+        data[..20000].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 201);
+    assert_eq!(header(&response, "upload-offset"), "20000");
+    let session_url = format!("https://127.0.0.1:8899{}", header(&response, "location"));
+    let response = upload_request(Method::DELETE, &session_url, &[], vec![]).await;
+    assert_eq!(response.status(), 204);
+    for method in [Method::HEAD, Method::DELETE] {
+        let response = upload_request(method, &session_url, &[], vec![]).await;
+        assert_eq!(response.status(), 404);
+    }
+    let response = upload_request(
+        Method::PATCH,
+        &session_url,
+        &[("upload-offset", "20000")],
+        data[20000..40000].to_vec(),
+    )
+    .await;
+    assert_eq!(response.status(), 404);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn upload_request(
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .request(method, url)
+        .basic_auth("jdoe@example.com", Some("12345"))
+        .body(body);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap()
+}
+
+fn header<'x>(response: &'x reqwest::Response, name: &str) -> &'x str {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::services::cluster::{AccountPlacement, ClusterHandoff, ClusterNode, Migration};
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+use store::write::now;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running cluster session affinity tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+    assert_eq!(server.account_affinity_node(account_id).await, Some(1));
+
+    // Session URLs are pinned to the node serving the account
+    let (status, response) = session_request(Method::GET, "/.well-known/jmap", None).await;
+    assert_eq!(status, 200, "{response}");
+    let session = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+    assert_eq!(
+        session["apiUrl"].as_str().unwrap(),
+        "https://127.0.0.1:8899/jmap/?node=1"
+    );
+    assert_eq!(
+        session["uploadUrl"].as_str().unwrap(),
+        "https://127.0.0.1:8899/jmap/upload/{accountId}/?node=1"
+    );
+    assert!(session["downloadUrl"]
+        .as_str()
+        .unwrap()
+        .ends_with("?accept={type}&node=1"));
+    assert!(session["eventSourceUrl"]
+        .as_str()
+        .unwrap()
+        .ends_with("&ping={ping}&node=1"));
+
+    // Hints naming the local node or nodes outside the cluster are served locally
+    for node in ["1", "9", "invalid"] {
+        let (status, response) = session_request(
+            Method::POST,
+            &format!("/jmap/?node={node}"),
+            Some(concat!(
+                r#"{"using": ["urn:ietf:params:jmap:core"], "#,
+                r#""methodCalls": [["Core/echo", {"hello": "world"}, "c0"]]}"#
+            )),
+        )
+        .await;
+        assert_eq!(status, 200, "{response}");
+        assert!(response.contains("\"hello\":\"world\""), "{response}");
+    }
+
+    // Both nodes serve an account while it is moved, hints pointing to the
+    // node it was moved away from become stale once the move completes
+    let cluster = ClusterHandoff {
+        node_id: 1,
+        nodes: (1..=3)
+            .map(|id| ClusterNode {
+                id,
+                url: format!("https://node{id}.example.org"),
+            })
+            .collect(),
+        auth: "Bearer secret".to_string(),
+        timeout: Duration::from_secs(30),
+        client: reqwest::Client::new(),
+        dual_read: Duration::from_secs(600),
+    };
+    assert!(cluster.serves(4, None, 2));
+    assert!(!cluster.serves(4, None, 1));
+    let moving = AccountPlacement {
+        node_id: 3,
+        migration: Some(Migration {
+            from_node_id: 2,
+            started: now(),
+        }),
+    };
+    assert!(cluster.serves(4, Some(&moving), 3));
+    assert!(cluster.serves(4, Some(&moving), 2));
+    assert!(!cluster.serves(4, Some(&moving), 1));
+    let moved = AccountPlacement {
+        node_id: 3,
+        migration: Some(Migration {
+            from_node_id: 2,
+            started: now() - 601,
+        }),
+    };
+    assert!(cluster.serves(4, Some(&moved), 3));
+    assert!(!cluster.serves(4, Some(&moved), 2));
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn session_request(method: Method, path: &str, body: Option<&str>) -> (u16, String) {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap()
+        .request(method, format!("https://127.0.0.1:8899{path}"))
+        .basic_auth("jdoe@example.com", Some("12345"));
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+
+    (status, response.text().await.unwrap())
+}
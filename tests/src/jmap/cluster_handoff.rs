@@ -0,0 +1,192 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::services::cluster::ClusterNode;
+use jmap_client::email::query::Filter;
+use jmap_proto::types::id::Id;
+use reqwest::{header, Method};
+use utils::ipc::DeliveryResult;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+const TEST_MESSAGE: &str = concat!(
+    "From: bill@example.com\r\n",
+    "To: jdoe@example.com\r\n",
+    "Message-ID: <handoff@example.com>\r\n",
+    "Subject: Handoff test\r\n",
+    "\r\n",
+    "Delivered by the owning node.\r\n"
+);
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running cluster handoff tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+
+    // All accounts are homed on the only node in the cluster
+    let cluster = server.config.cluster.as_ref().unwrap();
+    assert_eq!(cluster.placed_node(account_id, None).id, cluster.node_id);
+
+    // Requests without valid credentials or envelope are rejected
+    let query = format!("account={account_id}&rcpt=jdoe%40example.com&sender=bill%40example.com");
+    for (method, query, auth, expected_status) in [
+        (Method::POST, query.as_str(), None, 401),
+        (
+            Method::POST,
+            query.as_str(),
+            Some("Bearer wrong-secret"),
+            401,
+        ),
+        (
+            Method::GET,
+            query.as_str(),
+            Some("Bearer cluster-secret"),
+            404,
+        ),
+        (
+            Method::POST,
+            "rcpt=jdoe%40example.com&sender=bill%40example.com",
+            Some("Bearer cluster-secret"),
+            400,
+        ),
+    ] {
+        assert_eq!(
+            cluster_request(method, query, auth).await,
+            expected_status,
+            "{query} {auth:?}"
+        );
+    }
+
+    // Messages handed off by another node are delivered locally
+    let peer = ClusterNode {
+        id: 2,
+        url: "https://127.0.0.1:8899".to_string(),
+    };
+    assert!(matches!(
+        server
+            .handoff_message(
+                &peer,
+                1,
+                account_id,
+                "jdoe@example.com",
+                "bill@example.com",
+                TEST_MESSAGE.as_bytes()
+            )
+            .await,
+        Some(DeliveryResult::Success)
+    ));
+
+    // Retrying a completed handoff does not deliver the message twice
+    assert!(matches!(
+        server
+            .handoff_message(
+                &peer,
+                1,
+                account_id,
+                "jdoe@example.com",
+                "bill@example.com",
+                TEST_MESSAGE.as_bytes()
+            )
+            .await,
+        Some(DeliveryResult::Success)
+    ));
+    let email_ids = params
+        .client
+        .email_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids();
+    assert_eq!(email_ids.len(), 1);
+    assert_eq!(
+        params
+            .client
+            .email_get(&email_ids[0], None::<Vec<_>>)
+            .await
+            .unwrap()
+            .unwrap()
+            .subject()
+            .unwrap(),
+        "Handoff test"
+    );
+
+    // Unreachable nodes make the sender fall back to the shared store
+    let unreachable = ClusterNode {
+        id: 3,
+        url: "https://127.0.0.1:1".to_string(),
+    };
+    assert!(server
+        .handoff_message(
+            &unreachable,
+            2,
+            account_id,
+            "jdoe@example.com",
+            "bill@example.com",
+            TEST_MESSAGE.as_bytes()
+        )
+        .await
+        .is_none());
+
+    // Remove test data
+    server
+        .lookup_store
+        .key_delete(format!("ch:1:{account_id}").into_bytes())
+        .await
+        .unwrap();
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn cluster_request(method: Method, query: &str, auth: Option<&str>) -> u16 {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap()
+        .request(
+            method,
+            format!("https://127.0.0.1:8899/cluster/deliver?{query}"),
+        )
+        .body(TEST_MESSAGE);
+    if let Some(auth) = auth {
+        request = request.header(header::AUTHORIZATION, auth);
+    }
+    request.send().await.unwrap().status().as_u16()
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use directory::Principal;
+use jmap::{
+    auth::AccessToken,
+    services::invalidate::{Invalidation, INVALIDATION_COUNTERS},
+};
+use store::LookupStore;
+use utils::{config::ConfigKey, map::ttl_dashmap::TtlMap};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running cluster cache invalidation tests...");
+    let server = params.server.clone();
+    let counters = LookupStore::from(server.store.clone());
+    let remote_ip: IpAddr = "10.0.0.200".parse().unwrap();
+    let local_ip: IpAddr = "10.0.0.201".parse().unwrap();
+
+    // Announcements made by this node are not applied twice
+    server
+        .store
+        .config_set(
+            [ConfigKey {
+                key: "jmap.rate-limit.anonymous".to_string(),
+                value: "4321/1m".to_string(),
+            }]
+            .into_iter(),
+        )
+        .await
+        .unwrap();
+    server.broadcast_invalidation(Invalidation::Config).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+
+    // Configuration changes announced by other nodes are reloaded
+    announce(&counters, Invalidation::Config).await;
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 4321);
+    server
+        .store
+        .config_clear("jmap.rate-limit.anonymous")
+        .await
+        .unwrap();
+    announce(&counters, Invalidation::Config).await;
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+
+    // Directory changes announced by other nodes drop the cached access tokens
+    let account_id = u32::MAX - 1;
+    server.cache_access_token(Arc::new(AccessToken::new(Principal {
+        id: account_id,
+        name: "remote@example.com".to_string(),
+        ..Default::default()
+    })));
+    assert!(server.access_tokens.get_with_ttl(&account_id).is_some());
+    announce(&counters, Invalidation::Directory).await;
+    assert!(server.access_tokens.get_with_ttl(&account_id).is_none());
+
+    // Addresses banned by other nodes are blocked
+    server
+        .store
+        .config_set(
+            [ConfigKey {
+                key: format!("server.security.blocked-networks.{remote_ip}"),
+                value: String::new(),
+            }]
+            .into_iter(),
+        )
+        .await
+        .unwrap();
+    assert!(!server.directory.blocked_ips.is_blocked(&remote_ip));
+    announce(&counters, Invalidation::BlockedIp { ip: remote_ip }).await;
+    assert!(server.directory.blocked_ips.is_blocked(&remote_ip));
+
+    // Addresses banned by this node are persisted before being announced
+    server.directory.blocked_ips.block_ip(local_ip);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(server
+        .store
+        .config_get(format!("server.security.blocked-networks.{local_ip}"))
+        .await
+        .unwrap()
+        .is_some());
+
+    // Remove test data
+    for ip in [remote_ip, local_ip] {
+        server
+            .store
+            .config_clear(format!("server.security.blocked-networks.{ip}"))
+            .await
+            .unwrap();
+    }
+    announce(&counters, Invalidation::BlockedIp { ip: remote_ip }).await;
+    assert!(!server.directory.blocked_ips.is_blocked(&remote_ip));
+    assert!(!server.directory.blocked_ips.is_blocked(&local_ip));
+}
+
+// Simulates an announcement made by another node
+async fn announce(counters: &LookupStore, invalidation: Invalidation) {
+    let counter = match invalidation {
+        Invalidation::Config => 0,
+        Invalidation::Directory => 1,
+        Invalidation::BlockedIp { .. } => 2,
+    };
+    counters
+        .counter_incr(INVALIDATION_COUNTERS[counter].as_bytes().to_vec(), 1, None)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
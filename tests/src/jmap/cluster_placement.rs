@@ -0,0 +1,140 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::services::cluster::{AccountPlacement, ClusterHandoff, ClusterNode, Migration};
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+use store::write::now;
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running cluster placement tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+
+    // Accounts without a stored placement live on their default node
+    let response = placement_request(Method::GET, "jdoe@example.com", 200).await;
+    assert_eq!(response["node"], 1, "{response}");
+    assert!(response["migratingFrom"].is_null(), "{response}");
+    assert!(server.account_delivery_node(account_id).await.is_none());
+    assert!(server.account_serving_node(account_id).await.is_none());
+
+    // Accounts can only be moved to nodes that are part of the cluster
+    placement_request(Method::POST, "jdoe@example.com/2", 400).await;
+    let response = placement_request(Method::POST, "jdoe@example.com/1", 200).await;
+    assert_eq!(response["node"], 1, "{response}");
+    assert!(response["migratingFrom"].is_null(), "{response}");
+    assert_eq!(
+        server.account_placement(account_id).await.unwrap(),
+        Some(AccountPlacement {
+            node_id: 1,
+            migration: None
+        })
+    );
+
+    // A single node cluster is always balanced
+    let (status, response) = manage_request(Method::POST, "cluster/rebalance", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"]["moved"],
+        0
+    );
+
+    // Removing the placement returns the account to its default node
+    let response = placement_request(Method::DELETE, "jdoe@example.com", 200).await;
+    assert_eq!(response["node"], 1, "{response}");
+    assert_eq!(server.account_placement(account_id).await.unwrap(), None);
+
+    // Unknown accounts are rejected
+    placement_request(Method::GET, "nobody@example.com", 404).await;
+
+    // Stored placements override the default node, both nodes serve an
+    // account during the dual-read period of a move
+    let cluster = ClusterHandoff {
+        node_id: 1,
+        nodes: (1..=3)
+            .map(|id| ClusterNode {
+                id,
+                url: format!("https://node{id}.example.org"),
+            })
+            .collect(),
+        auth: "Bearer secret".to_string(),
+        timeout: Duration::from_secs(30),
+        client: reqwest::Client::new(),
+        dual_read: Duration::from_secs(600),
+    };
+    assert_eq!(cluster.placed_node(4, None).id, 2);
+    assert_eq!(cluster.placed_node(3, None).id, cluster.node_id);
+    let moving = AccountPlacement {
+        node_id: 3,
+        migration: Some(Migration {
+            from_node_id: 2,
+            started: now(),
+        }),
+    };
+    assert_eq!(cluster.placed_node(4, Some(&moving)).id, 3);
+    assert!(cluster.is_migrating(&moving));
+    let moved = AccountPlacement {
+        node_id: 3,
+        migration: Some(Migration {
+            from_node_id: 2,
+            started: now() - 601,
+        }),
+    };
+    assert!(!cluster.is_migrating(&moved));
+    let removed_node = AccountPlacement {
+        node_id: 7,
+        migration: None,
+    };
+    assert_eq!(cluster.placed_node(4, Some(&removed_node)).id, 2);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn placement_request(method: Method, path: &str, expected_status: u16) -> serde_json::Value {
+    let (status, response) =
+        manage_request(method, &format!("cluster/placement/{path}"), None).await;
+    assert_eq!(status, expected_status, "{response}");
+    serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone()
+}
@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Duration};
+
+use reqwest::Method;
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running configuration reload tests...");
+    let server = params.server.clone();
+    let blocked_ip: IpAddr = "10.0.0.100".parse().unwrap();
+
+    // Make sure the defaults are in place
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+    assert!(server
+        .smtp
+        .session
+        .config
+        .throttle
+        .load()
+        .connect
+        .is_empty());
+    assert!(!server.directory.blocked_ips.is_blocked(&blocked_ip));
+
+    // Changes stored in the database are not applied until reloaded
+    let (status, _) = manage_request(
+        Method::POST,
+        "config",
+        Some(
+            r#"[["jmap.rate-limit.anonymous", "1234/1m"],
+                ["session.throttle.0.key", "remote_ip"],
+                ["session.throttle.0.rate", "1000/1s"],
+                ["server.security.blocked-networks.10.0.0.100", ""]]"#,
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+
+    // Reload configuration
+    let (status, response) = manage_request(Method::GET, "reload/config", None).await;
+    assert_eq!(status, 200, "{response}");
+    let reloaded = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+    for component in ["jmap", "smtp", "security", "imap"] {
+        assert!(
+            reloaded["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|name| name == component),
+            "{component} not reloaded: {response}"
+        );
+    }
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 1234);
+    assert_eq!(server.smtp.session.config.throttle.load().connect.len(), 1);
+    assert!(server.directory.blocked_ips.is_blocked(&blocked_ip));
+
+    // Invalid settings are reported and leave the active ones untouched
+    let (status, _) = manage_request(
+        Method::POST,
+        "config",
+        Some(r#"[["jmap.rate-limit.anonymous", "fast"]]"#),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, response) = manage_request(Method::GET, "reload/config", None).await;
+    assert_eq!(status, 400, "{response}");
+    assert!(response.contains("jmap.rate-limit.anonymous"), "{response}");
+    let reloaded = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+    assert!(reloaded["failed"]["jmap"].is_string(), "{response}");
+    for component in ["smtp", "security", "imap"] {
+        assert!(
+            reloaded["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|name| name == component),
+            "{component} not reloaded: {response}"
+        );
+    }
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 1234);
+
+    // Removing the settings restores the local configuration
+    for key in [
+        "jmap.rate-limit.anonymous",
+        "session.throttle.",
+        "server.security.blocked-networks.10.0.0.100",
+    ] {
+        let (status, _) = manage_request(Method::DELETE, &format!("config/{key}"), None).await;
+        assert_eq!(status, 200);
+    }
+    let (status, response) = manage_request(Method::GET, "reload/config", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+    assert!(server
+        .smtp
+        .session
+        .config
+        .throttle
+        .load()
+        .connect
+        .is_empty());
+    assert!(!server.directory.blocked_ips.is_blocked(&blocked_ip));
+
+    // Changes to the configuration file are applied on reload
+    let config_path = params.temp_dir.path.join("config.toml");
+    let config_file = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(
+        &config_path,
+        config_file.replace(r#"anonymous = "100/1m""#, r#"anonymous = "4321/1m""#),
+    )
+    .unwrap();
+    let (status, response) = manage_request(Method::GET, "reload/config", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 4321);
+
+    // Restore the configuration file
+    std::fs::write(&config_path, config_file).unwrap();
+    let (status, response) = manage_request(Method::GET, "reload/config", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(server.config.rate_limits.load().anonymous.requests, 100);
+}
+
+pub async fn manage_request(method: Method, path: &str, body: Option<&str>) -> (u16, String) {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap()
+        .request(method, format!("https://127.0.0.1:8899/api/{path}"))
+        .basic_auth("admin", Some("secret"));
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+
+    (status, response.text().await.unwrap())
+}
@@ -0,0 +1,417 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use base64::{engine::general_purpose, Engine};
+use directory::backend::internal::manage::ManageDirectory;
+use reqwest::{header, Method, StatusCode};
+
+use crate::jmap::assert_is_empty;
+
+use super::JMAPTest;
+
+const VCARD: &str = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:jane\r\nFN:Jane Doe\r\nEND:VCARD\r\n";
+const VEVENT: &str = concat!(
+    "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\n",
+    "BEGIN:VEVENT\r\nUID:meeting\r\nDTSTART:20240101T100000Z\r\n",
+    "SUMMARY:Planning & review\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+);
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running CalDAV/CardDAV tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+
+    // Service discovery does not require authentication
+    let response = dav_request(Method::OPTIONS, "/dav/", None, &[], "").await;
+    assert_eq!(response.0, StatusCode::OK);
+    assert!(response
+        .1
+        .get("dav")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains("calendar-access"));
+    let response = dav_request(Method::GET, "/.well-known/caldav", None, &[], "").await;
+    assert_eq!(response.0, StatusCode::MOVED_PERMANENTLY);
+    let response = dav_request(Method::GET, "/.well-known/carddav", None, &[], "").await;
+    assert_eq!(response.0, StatusCode::MOVED_PERMANENTLY);
+    let response = propfind("/dav/", Some(("jdoe@example.com", "wrong")), "0").await;
+    assert_eq!(response.0, StatusCode::UNAUTHORIZED);
+
+    // Discover the principal and home sets
+    let response = propfind("/dav/", AUTH, "0").await;
+    assert_eq!(response.0, StatusCode::MULTI_STATUS);
+    assert!(
+        response.2.contains("/dav/principal/jdoe@example.com/"),
+        "{}",
+        response.2
+    );
+    let response = propfind("/dav/principal/jdoe@example.com/", AUTH, "0").await;
+    assert!(
+        response.2.contains("/dav/cal/jdoe@example.com/"),
+        "{}",
+        response.2
+    );
+    assert!(
+        response.2.contains("/dav/card/jdoe@example.com/"),
+        "{}",
+        response.2
+    );
+
+    // A default calendar and address book are provisioned
+    let response = propfind("/dav/cal/jdoe@example.com/", AUTH, "1").await;
+    assert!(
+        response.2.contains("/dav/cal/jdoe@example.com/default/"),
+        "{}",
+        response.2
+    );
+    assert!(response.2.contains("<C:calendar/>"), "{}", response.2);
+    let response = propfind("/dav/card/jdoe@example.com/", AUTH, "1").await;
+    assert!(
+        response.2.contains("/dav/card/jdoe@example.com/default/"),
+        "{}",
+        response.2
+    );
+    assert!(response.2.contains("<CR:addressbook/>"), "{}", response.2);
+    let ctag = getctag("/dav/cal/jdoe@example.com/default/").await;
+
+    // Create a contact and an event
+    let card_url = "/dav/card/jdoe@example.com/default/jane.vcf";
+    let response = dav_request(Method::PUT, card_url, AUTH, &[], VCARD).await;
+    assert_eq!(response.0, StatusCode::CREATED);
+    let card_etag = response
+        .1
+        .get("etag")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let event_url = "/dav/cal/jdoe@example.com/default/meeting.ics";
+    let response = dav_request(
+        Method::PUT,
+        event_url,
+        AUTH,
+        &[("if-none-match", "*")],
+        VEVENT,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::CREATED);
+    assert_ne!(getctag("/dav/cal/jdoe@example.com/default/").await, ctag);
+
+    // Invalid objects and missing collections are rejected
+    let response = dav_request(Method::PUT, card_url, AUTH, &[], VEVENT).await;
+    assert_eq!(response.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = dav_request(
+        Method::PUT,
+        "/dav/card/jdoe@example.com/missing/jane.vcf",
+        AUTH,
+        &[],
+        VCARD,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::CONFLICT);
+
+    // Fetch resources
+    let response = dav_request(Method::GET, card_url, AUTH, &[], "").await;
+    assert_eq!(response.0, StatusCode::OK);
+    assert_eq!(response.1.get("etag").unwrap().to_str().unwrap(), card_etag);
+    assert_eq!(response.2, VCARD);
+    let response = propfind("/dav/card/jdoe@example.com/default/", AUTH, "1").await;
+    assert!(response.2.contains(card_url), "{}", response.2);
+    assert!(
+        response.2.contains(&card_etag.replace('"', "&quot;")),
+        "{}",
+        response.2
+    );
+    let response = dav_request(
+        Method::from_bytes(b"REPORT").unwrap(),
+        "/dav/cal/jdoe@example.com/default/",
+        AUTH,
+        &[("depth", "1")],
+        &format!(
+            concat!(
+                "<?xml version=\"1.0\"?><C:calendar-multiget xmlns:D=\"DAV:\" ",
+                "xmlns:C=\"urn:ietf:params:xml:ns:caldav\"><D:prop><D:getetag/>",
+                "<C:calendar-data/></D:prop><D:href>{}</D:href>",
+                "<D:href>/dav/cal/jdoe@example.com/default/missing.ics</D:href>",
+                "</C:calendar-multiget>"
+            ),
+            event_url
+        ),
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::MULTI_STATUS);
+    assert!(
+        response.2.contains("Planning &amp; review"),
+        "{}",
+        response.2
+    );
+    assert!(response.2.contains("404 Not Found"), "{}", response.2);
+
+    // Conditional updates
+    let response = dav_request(
+        Method::PUT,
+        card_url,
+        AUTH,
+        &[("if-match", "\"0000\"")],
+        VCARD,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::PRECONDITION_FAILED);
+    let response = dav_request(
+        Method::PUT,
+        card_url,
+        AUTH,
+        &[("if-none-match", "*")],
+        VCARD,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::PRECONDITION_FAILED);
+    let updated_card = VCARD.replace("Jane Doe", "Jane Smith");
+    let response = dav_request(
+        Method::PUT,
+        card_url,
+        AUTH,
+        &[("if-match", &card_etag)],
+        &updated_card,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::NO_CONTENT);
+    assert_ne!(response.1.get("etag").unwrap().to_str().unwrap(), card_etag);
+    let response = dav_request(Method::GET, card_url, AUTH, &[], "").await;
+    assert_eq!(response.2, updated_card);
+
+    // Create and rename a calendar
+    let work_url = "/dav/cal/jdoe@example.com/work/";
+    let response = dav_request(
+        Method::from_bytes(b"MKCALENDAR").unwrap(),
+        work_url,
+        AUTH,
+        &[],
+        concat!(
+            "<?xml version=\"1.0\"?><C:mkcalendar xmlns:D=\"DAV:\" ",
+            "xmlns:C=\"urn:ietf:params:xml:ns:caldav\"><D:set><D:prop>",
+            "<D:displayname>Work</D:displayname></D:prop></D:set></C:mkcalendar>"
+        ),
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::CREATED);
+    let response = dav_request(
+        Method::from_bytes(b"MKCALENDAR").unwrap(),
+        work_url,
+        AUTH,
+        &[],
+        "",
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::METHOD_NOT_ALLOWED);
+    let response = dav_request(
+        Method::from_bytes(b"PROPPATCH").unwrap(),
+        work_url,
+        AUTH,
+        &[],
+        concat!(
+            "<?xml version=\"1.0\"?><D:propertyupdate xmlns:D=\"DAV:\"><D:set><D:prop>",
+            "<D:displayname>Office</D:displayname></D:prop></D:set></D:propertyupdate>"
+        ),
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::MULTI_STATUS);
+    assert!(response.2.contains("200 OK"), "{}", response.2);
+    let response = propfind(work_url, AUTH, "0").await;
+    assert!(
+        response.2.contains("<D:displayname>Office</D:displayname>"),
+        "{}",
+        response.2
+    );
+
+    // Other accounts cannot be accessed
+    params
+        .directory
+        .create_test_user_with_email("jane@example.com", "abcde", "Jane Smith")
+        .await;
+    params
+        .directory
+        .set_test_quota("jane@example.com", 100)
+        .await;
+    let response = propfind(
+        "/dav/cal/jdoe@example.com/",
+        Some(("jane@example.com", "abcde")),
+        "1",
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::FORBIDDEN);
+
+    // Resources exceeding the quota are rejected
+    let response = dav_request(
+        Method::PUT,
+        "/dav/card/jane@example.com/default/jane.vcf",
+        Some(("jane@example.com", "abcde")),
+        &[],
+        VCARD,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::CREATED);
+    let response = dav_request(
+        Method::PUT,
+        "/dav/cal/jane@example.com/default/meeting.ics",
+        Some(("jane@example.com", "abcde")),
+        &[],
+        VEVENT,
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::INSUFFICIENT_STORAGE);
+    let response = dav_request(
+        Method::GET,
+        "/dav/cal/jane@example.com/default/meeting.ics",
+        Some(("jane@example.com", "abcde")),
+        &[],
+        "",
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::NOT_FOUND);
+    let response = dav_request(
+        Method::DELETE,
+        "/dav/card/jane@example.com/default/jane.vcf",
+        Some(("jane@example.com", "abcde")),
+        &[],
+        "",
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::NO_CONTENT);
+
+    // Delete resources and collections
+    let response = dav_request(
+        Method::DELETE,
+        card_url,
+        AUTH,
+        &[("if-match", &card_etag)],
+        "",
+    )
+    .await;
+    assert_eq!(response.0, StatusCode::PRECONDITION_FAILED);
+    let response = dav_request(Method::DELETE, card_url, AUTH, &[], "").await;
+    assert_eq!(response.0, StatusCode::NO_CONTENT);
+    let response = dav_request(Method::GET, card_url, AUTH, &[], "").await;
+    assert_eq!(response.0, StatusCode::NOT_FOUND);
+    for url in [
+        work_url,
+        "/dav/cal/jdoe@example.com/default/",
+        "/dav/card/jdoe@example.com/default/",
+    ] {
+        let response = dav_request(Method::DELETE, url, AUTH, &[], "").await;
+        assert_eq!(response.0, StatusCode::NO_CONTENT, "{url}");
+    }
+
+    // Remove test data
+    server
+        .store
+        .destroy_account("jane@example.com")
+        .await
+        .unwrap();
+    assert_is_empty(server).await;
+}
+
+const AUTH: Option<(&str, &str)> = Some(("jdoe@example.com", "12345"));
+
+async fn propfind(
+    url: &str,
+    auth: Option<(&str, &str)>,
+    depth: &str,
+) -> (StatusCode, header::HeaderMap, String) {
+    dav_request(
+        Method::from_bytes(b"PROPFIND").unwrap(),
+        url,
+        auth,
+        &[("depth", depth)],
+        "",
+    )
+    .await
+}
+
+async fn getctag(url: &str) -> String {
+    let response = dav_request(
+        Method::from_bytes(b"PROPFIND").unwrap(),
+        url,
+        AUTH,
+        &[("depth", "0")],
+        concat!(
+            "<?xml version=\"1.0\"?><D:propfind xmlns:D=\"DAV:\" ",
+            "xmlns:CS=\"http://calendarserver.org/ns/\"><D:prop><CS:getctag/></D:prop>",
+            "</D:propfind>"
+        ),
+    )
+    .await;
+    response
+        .2
+        .split_once("<CS:getctag>")
+        .and_then(|(_, ctag)| ctag.split_once("</CS:getctag>"))
+        .map(|(ctag, _)| ctag.to_string())
+        .unwrap_or_else(|| panic!("Missing getctag: {}", response.2))
+}
+
+async fn dav_request(
+    method: Method,
+    url: &str,
+    auth: Option<(&str, &str)>,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (StatusCode, header::HeaderMap, String) {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap()
+        .request(method, format!("https://127.0.0.1:8898{url}"))
+        .body(body.to_string());
+    if let Some((username, secret)) = auth {
+        request = request.header(
+            header::AUTHORIZATION,
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode(format!("{}:{}", username, secret))
+            ),
+        );
+    }
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    (status, headers, response.text().await.unwrap())
+}
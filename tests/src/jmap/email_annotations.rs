@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_proto::types::id::Id;
+
+use crate::jmap::{assert_is_empty, jmap_raw_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Email annotations tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let email_id = params
+        .client
+        .email_import(
+            concat!(
+                "From: bill@example.com\r\n",
+                "To: jdoe@example.com\r\n",
+                "Subject: TPS Report\r\n",
+                "\r\n",
+                "I'm going to need those TPS reports ASAP."
+            )
+            .as_bytes()
+            .to_vec(),
+            [&Id::from(INBOX_ID).to_string()],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+
+    // Messages have no annotations by default
+    let response = annotations_request(
+        r#"[[ "Email/get", {
+            "accountId": "$$",
+            "ids": ["%%"],
+            "properties": ["annotations"]
+          }, "0" ]]"#,
+        account_id,
+        &email_id,
+    )
+    .await;
+    assert!(response.contains("\"annotations\":{}"), "{}", response);
+    let state = response
+        .split_once("\"state\":\"")
+        .and_then(|(_, state)| state.split_once('"'))
+        .map(|(state, _)| state.to_string())
+        .unwrap();
+
+    // Set annotations and patch them
+    let response = annotations_request(
+        r#"[[ "Email/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {
+                    "annotations": {"crmId": "A-1234", "ticket": "42"}
+                }
+            }
+          }, "0" ],
+          [ "Email/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {
+                    "annotations/ticket": null,
+                    "annotations/owner": "jdoe"
+                }
+            }
+          }, "1" ],
+          [ "Email/get", {
+            "accountId": "$$",
+            "ids": ["%%"],
+            "properties": ["annotations", "keywords"]
+          }, "2" ]]"#,
+        account_id,
+        &email_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"annotations\":{\"crmId\":\"A-1234\",\"owner\":\"jdoe\"}"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\"keywords\":{}"), "{}", response);
+
+    // Annotations are included in the change log
+    let response = annotations_request(
+        &r#"[[ "Email/changes", {
+            "accountId": "$$",
+            "sinceState": "@@"
+          }, "0" ]]"#
+            .replace("@@", &state),
+        account_id,
+        &email_id,
+    )
+    .await;
+    assert!(
+        response.contains(&format!("\"updated\":[\"{email_id}\"]")),
+        "{}",
+        response
+    );
+
+    // Limits are enforced
+    let too_many = (0..40)
+        .map(|n| format!("\"key{n}\": \"value\""))
+        .collect::<Vec<_>>()
+        .join(",");
+    let too_large = format!("\"large\": \"{}\"", "a".repeat(5000));
+    for annotations in [too_many, too_large] {
+        let response = annotations_request(
+            &r#"[[ "Email/set", {
+                "accountId": "$$",
+                "update": {
+                    "%%": {
+                        "annotations": {@@}
+                    }
+                }
+              }, "0" ]]"#
+                .replace("@@", &annotations),
+            account_id,
+            &email_id,
+        )
+        .await;
+        assert!(response.contains("\"overQuota\""), "{}", response);
+    }
+
+    // Only string values are accepted
+    let response = annotations_request(
+        r#"[[ "Email/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {
+                    "annotations": {"count": 1}
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+        &email_id,
+    )
+    .await;
+    assert!(response.contains("\"invalidProperties\""), "{}", response);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn annotations_request(request: &str, account_id: Id, email_id: &str) -> String {
+    jmap_raw_request(
+        request
+            .replace("$$", &account_id.to_string())
+            .replace("%%", email_id),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
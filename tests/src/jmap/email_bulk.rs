@@ -0,0 +1,340 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+use utils::locale::{DateFormat, Language};
+
+use crate::jmap::{
+    assert_is_empty, delivery::SmtpConnection, jmap_raw_request, mailbox::destroy_all_mailboxes,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running bulk mail filing tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+
+    // Bulk filing is disabled by default
+    let response = settings_request(
+        r#"[[ "Settings/get", {
+            "accountId": "$$",
+            "ids": null
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"bulkFiling\":false"), "{}", response);
+    assert!(response.contains("\"bulkMailboxId\":null"), "{}", response);
+
+    // Newsletters are delivered to the inbox when filing is disabled
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "news@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: news@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: Weekly News <weekly.example.com>\r\n",
+            "Subject: This week's news\r\n",
+            "\r\n",
+            "Nothing happened this week."
+        ),
+    )
+    .await;
+    assert_eq!(mailbox_ids(params, account_id, INBOX_ID).await, vec![0]);
+
+    // Enable bulk filing
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "bulkFiling": true
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+
+    // One-click unsubscribe and Precedence headers are detected as bulk mail
+    lmtp.ingest(
+        "deals@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: deals@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Unsubscribe: <https://example.com/unsubscribe/jdoe>\r\n",
+            "List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n",
+            "Subject: Limited time offer\r\n",
+            "\r\n",
+            "Buy now!"
+        ),
+    )
+    .await;
+    lmtp.ingest(
+        "digest@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: digest@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Precedence: bulk\r\n",
+            "Subject: Daily digest\r\n",
+            "\r\n",
+            "Your daily digest."
+        ),
+    )
+    .await;
+    let newsletters_id = server
+        .mailbox_get_by_name(account_id.document_id(), "Newsletters")
+        .await
+        .unwrap()
+        .expect("Newsletters mailbox was not created");
+    assert_eq!(
+        mailbox_ids(params, account_id, newsletters_id).await,
+        vec![1, 2]
+    );
+
+    // Regular mail is still delivered to the inbox
+    lmtp.ingest(
+        "jane@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: jane@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Unsubscribe: <mailto:unsubscribe@example.com>\r\n",
+            "Subject: Lunch\r\n",
+            "\r\n",
+            "Anyone up for lunch?"
+        ),
+    )
+    .await;
+    assert_eq!(mailbox_ids(params, account_id, INBOX_ID).await, vec![0, 3]);
+
+    // File bulk mail into a user selected mailbox
+    let mailbox_id = params
+        .client
+        .mailbox_create(
+            "Promotions",
+            None::<String>,
+            jmap_client::mailbox::Role::None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    let response = settings_request(
+        &r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "bulkMailboxId": "%%"
+                }
+            }
+          }, "0" ]]"#
+            .replace("%%", &mailbox_id),
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+    lmtp.ingest(
+        "news@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: news@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: <weekly.example.com>\r\n",
+            "Subject: Next week's news\r\n",
+            "\r\n",
+            "Nothing will happen next week."
+        ),
+    )
+    .await;
+    let mailbox_id = Id::from_bytes(mailbox_id.as_bytes()).unwrap().document_id();
+    assert_eq!(mailbox_ids(params, account_id, mailbox_id).await, vec![4]);
+
+    // Unknown mailboxes are rejected
+    let response = settings_request(
+        &r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "bulkMailboxId": "%%"
+                }
+            }
+          }, "0" ]]"#
+            .replace("%%", &Id::from(999u64).to_string()),
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"notUpdated\""), "{}", response);
+
+    // Locale preferences default to English, UTC and ISO dates
+    let response = settings_request(
+        r#"[[ "Settings/get", {
+            "accountId": "$$",
+            "ids": null,
+            "properties": ["locale", "timezone", "dateFormat"]
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    for expected in [
+        "\"locale\":\"en\"",
+        "\"timezone\":\"UTC\"",
+        "\"dateFormat\":\"YYYY-MM-DD\"",
+    ] {
+        assert!(response.contains(expected), "{}", response);
+    }
+
+    // Unsupported languages and named time zones are rejected
+    for (property, value) in [
+        ("locale", "xx-YY"),
+        ("timezone", "Europe/Madrid"),
+        ("dateFormat", "DD.MM.YY"),
+    ] {
+        let response = settings_request(
+            &r#"[[ "Settings/set", {
+                "accountId": "$$",
+                "update": {
+                    "singleton": {
+                        "%%": "@@"
+                    }
+                }
+              }, "0" ]]"#
+                .replace("%%", property)
+                .replace("@@", value),
+            account_id,
+        )
+        .await;
+        assert!(response.contains("\"notUpdated\""), "{}", response);
+    }
+
+    // Update locale preferences
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "locale": "es-MX",
+                    "timezone": "-06:00",
+                    "dateFormat": "DD/MM/YYYY"
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+    let locale = server
+        .account_locale(account_id.document_id())
+        .await
+        .unwrap();
+    assert_eq!(locale.language, Language::Spanish);
+    assert_eq!(locale.utc_offset, -6 * 3600);
+    assert_eq!(locale.date_format, DateFormat::DayMonthYear);
+    assert_eq!(locale.format_date(86400 * 365), "31/12/1970 18:00 -06:00");
+    assert_eq!(
+        server.address_locale("jdoe@example.com").await,
+        Some(locale)
+    );
+
+    // Reset settings
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "bulkFiling": null,
+                    "bulkMailboxId": null,
+                    "locale": null,
+                    "timezone": null,
+                    "dateFormat": null
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn settings_request(request: &str, account_id: Id) -> String {
+    jmap_raw_request(
+        request.replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
+
+async fn mailbox_ids(params: &JMAPTest, account_id: Id, mailbox_id: u32) -> Vec<u32> {
+    params
+        .server
+        .get_tag(
+            account_id.document_id(),
+            Collection::Email,
+            Property::MailboxIds,
+            mailbox_id,
+        )
+        .await
+        .unwrap()
+        .map(|ids| ids.into_iter().collect())
+        .unwrap_or_default()
+}
@@ -21,6 +21,7 @@
  * for more details.
 */
 
+use jmap_client::core::error::{MethodError, MethodErrorType};
 use jmap_proto::{
     parser::{json::Parser, JsonObjectParser},
     types::{collection::Collection, id::Id, state::State},
@@ -322,6 +323,62 @@ pub async fn test(params: &mut JMAPTest) {
     assert_eq!(created, vec![2, 3, 11, 12]);
     assert_eq!(changes.updated(), Vec::<String>::new());
     assert_eq!(changes.destroyed(), Vec::<String>::new());
+
+    // Compacting the change log keeps the current state
+    let changes_before = params
+        .client
+        .email_changes(states[4].to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .store
+            .compact_changes(1, Collection::Email, 3)
+            .await
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        server
+            .store
+            .compact_changes(1, Collection::Email, 2)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        server
+            .store
+            .get_last_change_id(1, Collection::Email)
+            .await
+            .unwrap(),
+        Some(7)
+    );
+
+    // Clients holding states older than the compaction have to resynchronize
+    for state in [&State::Initial, &states[1], &states[3]] {
+        assert!(
+            matches!(
+                params.client.email_changes(state.to_string(), None).await,
+                Err(jmap_client::Error::Method(MethodError {
+                    p_type: MethodErrorType::CannotCalculateChanges
+                }))
+            ),
+            "state: {state:?}"
+        );
+    }
+
+    // Newer states are not affected
+    let changes = params
+        .client
+        .email_changes(states[4].to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(changes.created(), changes_before.created());
+    assert_eq!(changes.updated(), changes_before.updated());
+    assert_eq!(changes.destroyed(), changes_before.destroyed());
+    assert_eq!(changes.new_state(), changes_before.new_state());
+
     assert_is_empty(server).await;
 }
 
@@ -43,6 +43,7 @@ pub async fn test(params: &mut JMAPTest) {
 
     let mailbox_id = Id::from(INBOX_ID).to_string();
     params.client.set_default_account_id(Id::from(1u64));
+    server.message_cache.clear();
 
     for file_name in fs::read_dir(&test_dir).unwrap() {
         let mut file_name = file_name.as_ref().unwrap().path();
@@ -188,7 +189,10 @@ pub async fn test(params: &mut JMAPTest) {
         }
     }
 
+    // Fetched messages are cached until they are deleted
+    assert!(server.message_cache.size() > 0);
     destroy_all_mailboxes(params).await;
+    assert_eq!(server.message_cache.size(), 0);
     assert_is_empty(server).await;
 }
 
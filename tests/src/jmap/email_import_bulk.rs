@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_client::email;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running bulk import tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+
+    // Import messages keeping their original date, flags and folders
+    let (status, response) = manage_request(
+        Method::POST,
+        "import/jdoe@example.com",
+        Some(
+            r#"{
+                "messages": [
+                    {
+                        "message": "From: bill@example.com\r\nMessage-ID: <old-1@example.com>\r\nSubject: Old news\r\n\r\nFrom the archives.",
+                        "receivedAt": "2010-06-01T10:00:00Z",
+                        "keywords": ["$seen", "$flagged"],
+                        "mailboxes": ["Archive/2010"]
+                    },
+                    {
+                        "message": "From: jane@example.com\r\nMessage-ID: <old-2@example.com>\r\nSubject: Unread\r\n\r\nStill unread."
+                    },
+                    {
+                        "message": "From: bill@example.com\r\nMessage-ID: <old-1@example.com>\r\nSubject: Old news\r\n\r\nFrom the archives.",
+                        "receivedAt": "2010-06-01T10:00:00Z"
+                    },
+                    {
+                        "message": "From: jane@example.com\r\nSubject: Bad date\r\n\r\nTest",
+                        "receivedAt": "yesterday"
+                    }
+                ]
+            }"#,
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "{response}");
+    let response = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+    let data = &response["data"];
+    let imported = data["imported"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| id.as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(imported.len(), 2, "{data}");
+    assert_eq!(data["skipped"], 1, "{data}");
+    assert_eq!(data["notImported"][0]["index"], 3, "{data}");
+
+    // Imported messages keep their received date, keywords and mailboxes
+    let email = params
+        .client
+        .email_get(
+            &imported[0],
+            [
+                email::Property::ReceivedAt,
+                email::Property::Keywords,
+                email::Property::MailboxIds,
+            ]
+            .into(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email.received_at(), Some(1275386400));
+    let mut keywords = email.keywords().to_vec();
+    keywords.sort_unstable();
+    assert_eq!(keywords, ["$flagged", "$seen"]);
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    assert_eq!(email.mailbox_ids().len(), 1);
+    assert_ne!(email.mailbox_ids()[0], inbox_id);
+
+    // Messages without mailboxes are filed into the inbox
+    let email = params
+        .client
+        .email_get(
+            &imported[1],
+            [email::Property::Keywords, email::Property::MailboxIds].into(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(email.keywords().is_empty());
+    assert_eq!(email.mailbox_ids(), [inbox_id.as_str()]);
+
+    // Unknown accounts are rejected
+    let (status, _) = manage_request(
+        Method::POST,
+        "import/nobody@example.com",
+        Some(r#"{"messages": []}"#),
+    )
+    .await;
+    assert_eq!(status, 404);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_client::email::query::Filter;
+use jmap_proto::types::{collection::Collection, id::Id, keyword::Keyword, property::Property};
+
+use crate::jmap::{assert_is_empty, delivery::SmtpConnection, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running importance classification tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+
+    // First message from an unknown sender is not important
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Message-ID: <tps-report@example.com>\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP."
+        ),
+    )
+    .await;
+    let email_ids = params
+        .client
+        .email_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids();
+    assert_eq!(email_ids.len(), 1);
+    assert_eq!(important_ids(params, account_id).await, Vec::<u32>::new());
+
+    // Replies in a thread the user answered are important
+    params
+        .client
+        .email_set_keyword(&email_ids[0], "$answered", true)
+        .await
+        .unwrap();
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Message-ID: <tps-report-2@example.com>\r\n",
+            "In-Reply-To: <tps-report@example.com>\r\n",
+            "References: <tps-report@example.com>\r\n",
+            "Subject: Re: TPS Report\r\n",
+            "\r\n",
+            "Did you get the memo?"
+        ),
+    )
+    .await;
+    assert_eq!(important_ids(params, account_id).await, vec![1]);
+
+    // Unrelated messages are not flagged
+    lmtp.ingest(
+        "jane@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: jane@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Message-ID: <lunch@example.com>\r\n",
+            "Subject: Lunch\r\n",
+            "\r\n",
+            "Anyone up for lunch?"
+        ),
+    )
+    .await;
+    assert_eq!(important_ids(params, account_id).await, vec![1]);
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn important_ids(params: &JMAPTest, account_id: Id) -> Vec<u32> {
+    params
+        .server
+        .get_tag(
+            account_id.document_id(),
+            Collection::Email,
+            Property::Keywords,
+            Keyword::Important,
+        )
+        .await
+        .unwrap()
+        .map(|ids| ids.into_iter().collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_client::{client::Client, email::query::Filter};
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{
+    assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes, wait_for_index,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Email search language tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let client = &mut params.client;
+
+    // Import a German and an English message
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    let mut email_ids = Vec::new();
+    for (num, text) in [
+        (
+            1,
+            concat!(
+                "Gestern Abend sind wir mit den Hunden durch den Wald gelaufen. ",
+                "Die Kinder haben im Garten gespielt und wir haben zusammen ",
+                "gegessen, bevor es dunkel wurde und alle nach Hause gingen."
+            ),
+        ),
+        (
+            2,
+            concat!(
+                "Yesterday evening the children were playing in the garden ",
+                "while the neighbours painted their fence. Afterwards we all ",
+                "had dinner together before it got dark outside."
+            ),
+        ),
+    ] {
+        email_ids.push(
+            client
+                .email_import(
+                    format!(
+                        concat!(
+                            "From: bill@example.com\r\n",
+                            "Message-ID: <language-{}@example.com>\r\n",
+                            "Subject: Message {}\r\n",
+                            "\r\n",
+                            "{}"
+                        ),
+                        num, num, text
+                    )
+                    .into_bytes(),
+                    [&inbox_id],
+                    None::<Vec<&str>>,
+                    None,
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+    wait_for_index(&server).await;
+
+    // Words are stemmed using the detected language
+    assert_query(client, "Hund", &[&email_ids[0]]).await;
+    assert_query(client, "garden", &[&email_ids[1]]).await;
+
+    // Stop words are not indexed but can still be matched inside phrases
+    assert_query(client, "the", &[]).await;
+    assert_query(client, "\"in the garden\"", &[&email_ids[1]]).await;
+    assert_query(client, "\"in the fence\"", &[]).await;
+
+    // Update the account's search language
+    let (status, response) = manage_request(
+        Method::PATCH,
+        "settings/jdoe@example.com",
+        Some(r#"{"searchLanguage": "klingon"}"#),
+    )
+    .await;
+    assert_eq!(status, 400, "{response}");
+    let (status, response) = manage_request(
+        Method::PATCH,
+        "settings/jdoe@example.com",
+        Some(r#"{"searchLanguage": "de"}"#),
+    )
+    .await;
+    assert_eq!(status, 200, "{response}");
+    let (status, response) = manage_request(Method::GET, "settings/jdoe@example.com", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"]["searchLanguage"],
+        "de"
+    );
+
+    // Reindex the account
+    let (status, response) =
+        manage_request(Method::GET, "store/reindex/jdoe@example.com", None).await;
+    assert_eq!(status, 200, "{response}");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"]["messages"],
+        2
+    );
+    wait_for_index(&server).await;
+
+    // Reindexing does not leave stale or duplicate entries behind
+    assert_query(client, "Hund", &[&email_ids[0]]).await;
+    assert_query(client, "\"in the garden\"", &[&email_ids[1]]).await;
+    assert_query(client, "the", &[]).await;
+
+    // Unknown accounts are rejected
+    let (status, _) = manage_request(Method::GET, "store/reindex/nobody@example.com", None).await;
+    assert_eq!(status, 404);
+
+    // Remove test data
+    let (status, response) = manage_request(
+        Method::PATCH,
+        "settings/jdoe@example.com",
+        Some(r#"{"searchLanguage": null}"#),
+    )
+    .await;
+    assert_eq!(status, 200, "{response}");
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn assert_query(client: &mut Client, text: &str, expected: &[&str]) {
+    let mut ids = client
+        .email_query(Filter::body(text).into(), None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids();
+    ids.sort_unstable();
+    let mut expected = expected.to_vec();
+    expected.sort_unstable();
+    assert_eq!(ids, expected, "query: {text}");
+}
@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::{INBOX_ID, JUNK_ID, TRASH_ID};
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+
+use crate::jmap::{
+    assert_is_empty, delivery::SmtpConnection, jmap_raw_request, mailbox::destroy_all_mailboxes,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running sender list tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+
+    // Invalid entries are rejected
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "blockedSenders": ["not an address"]
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"notUpdated\""), "{}", response);
+
+    // Allow a contact and block a domain
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "allowedSenders": ["Friend@Example.com"],
+                    "blockedSenders": ["@spammer.org"]
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+    let response = settings_request(
+        r#"[[ "Settings/get", {
+            "accountId": "$$",
+            "ids": ["singleton"]
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"allowedSenders\":[\"friend@example.com\"]"),
+        "{}",
+        response
+    );
+    assert!(
+        response.contains("\"blockedSenders\":[\"@spammer.org\"]"),
+        "{}",
+        response
+    );
+
+    // Mail from allowed senders bypasses spam filing
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "friend@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: friend@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "X-Spam-Status: Yes\r\n",
+            "Subject: Party tonight\r\n",
+            "\r\n",
+            "See you there!"
+        ),
+    )
+    .await;
+    lmtp.ingest(
+        "stranger@example.net",
+        &["jdoe@example.com"],
+        concat!(
+            "From: stranger@example.net\r\n",
+            "To: jdoe@example.com\r\n",
+            "X-Spam-Status: Yes\r\n",
+            "Subject: Party tonight\r\n",
+            "\r\n",
+            "See you there!"
+        ),
+    )
+    .await;
+    assert_eq!(mailbox_ids(params, account_id, INBOX_ID).await, vec![0]);
+    assert_eq!(mailbox_ids(params, account_id, JUNK_ID).await, vec![1]);
+
+    // Mail from blocked senders is filed into the trash
+    lmtp.ingest(
+        "bounces@mail.example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: Deals <deals@spammer.org>\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: You won!\r\n",
+            "\r\n",
+            "Claim your prize now."
+        ),
+    )
+    .await;
+    assert_eq!(mailbox_ids(params, account_id, TRASH_ID).await, vec![2]);
+    assert_eq!(mailbox_ids(params, account_id, INBOX_ID).await, vec![0]);
+
+    // Reset settings
+    let response = settings_request(
+        r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {
+                    "allowedSenders": null,
+                    "blockedSenders": []
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(
+        response.contains("\"updated\":{\"singleton\":null}"),
+        "{}",
+        response
+    );
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn settings_request(request: &str, account_id: Id) -> String {
+    jmap_raw_request(
+        request.replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
+
+async fn mailbox_ids(params: &JMAPTest, account_id: Id, mailbox_id: u32) -> Vec<u32> {
+    params
+        .server
+        .get_tag(
+            account_id.document_id(),
+            Collection::Email,
+            Property::MailboxIds,
+            mailbox_id,
+        )
+        .await
+        .unwrap()
+        .map(|ids| ids.into_iter().collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::path::PathBuf;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_proto::types::id::Id;
+
+use crate::jmap::{
+    assert_is_empty, delivery::SmtpConnection, jmap_json_request, mailbox::destroy_all_mailboxes,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Email signature tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+
+    // Deliver signed messages, along with an unsigned message that
+    // carries a forged status header
+    let mut lmtp = SmtpConnection::connect().await;
+    let mut test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    test_dir.push("resources");
+    test_dir.push("jmap");
+    test_dir.push("email_signature");
+    for test_name in [
+        "smime",
+        "smime_tampered",
+        "pgp_mime",
+        "pgp_mime_nokey",
+        "pgp_inline",
+    ] {
+        let message = std::fs::read_to_string(test_dir.join(format!("{test_name}.eml"))).unwrap();
+        lmtp.ingest("sender@example.org", &["jdoe@example.com"], &message)
+            .await;
+    }
+    lmtp.ingest(
+        "sender@example.org",
+        &["jdoe@example.com"],
+        concat!(
+            "From: Eve <eve@example.org>\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Unsigned message\r\n",
+            "Message-ID: <forged@example.org>\r\n",
+            "X-Signature-Status: smime; result=valid; signer=\"ceo@example.com\"\r\n",
+            "\r\n",
+            "Please wire the funds today.\r\n"
+        ),
+    )
+    .await;
+
+    // Fetch signature status and headers
+    let response = jmap_json_request(
+        r##"[[ "Email/query", {
+            "accountId": "$$"
+          }, "0" ],
+          [ "Email/get", {
+            "accountId": "$$",
+            "#ids": {
+                "resultOf": "0",
+                "name": "Email/query",
+                "path": "/ids"
+            },
+            "properties": ["messageId", "signature", "header:X-Signature-Status:asText:all"]
+          }, "1" ]]"##
+            .replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await;
+    let emails = response["methodResponses"][1][1]["list"]
+        .as_array()
+        .unwrap_or_else(|| panic!("Unexpected response: {response}"));
+    assert_eq!(emails.len(), 6, "{response}");
+    let find_email = |message_id: &str| {
+        emails
+            .iter()
+            .find(|email| email["messageId"][0] == message_id)
+            .unwrap_or_else(|| panic!("Message {message_id} not found: {response}"))
+    };
+
+    for (message_id, method, result, signer, from_match) in [
+        (
+            "smime@example.org",
+            "smime",
+            "valid",
+            "alice@example.org",
+            true,
+        ),
+        (
+            "smime-tampered@example.org",
+            "smime",
+            "invalid",
+            "alice@example.org",
+            true,
+        ),
+        (
+            "pgp-mime@example.org",
+            "pgp-mime",
+            "valid",
+            "bob@example.org",
+            true,
+        ),
+        (
+            "pgp-inline@example.org",
+            "pgp-inline",
+            "valid",
+            "bob@example.org",
+            false,
+        ),
+    ] {
+        let email = find_email(message_id);
+        let signature = &email["signature"];
+        assert_eq!(signature["method"], method, "{email}");
+        assert_eq!(signature["result"], result, "{email}");
+        assert_eq!(signature["signer"], signer, "{email}");
+        assert_eq!(signature["fromMatch"], from_match, "{email}");
+        assert!(signature["keyId"].is_string(), "{email}");
+
+        let headers = email["header:X-Signature-Status:asText:all"]
+            .as_array()
+            .unwrap();
+        assert_eq!(headers.len(), 1, "{email}");
+        let header = headers[0].as_str().unwrap();
+        assert!(
+            header.starts_with(&format!("{method}; result={result};")),
+            "{header}"
+        );
+        assert!(header.contains(&format!("signer=\"{signer}\"")), "{header}");
+    }
+
+    // Signatures made with keys that are not available cannot be verified
+    let email = find_email("pgp-mime-nokey@example.org");
+    assert_eq!(email["signature"]["method"], "pgp-mime", "{email}");
+    assert_eq!(email["signature"]["result"], "nokey", "{email}");
+    assert!(email["signature"]["signer"].is_null(), "{email}");
+    assert!(email["signature"]["keyId"].is_string(), "{email}");
+
+    // Forged status headers are removed from unsigned messages
+    let email = find_email("forged@example.org");
+    assert!(email["signature"].is_null(), "{email}");
+    assert_eq!(
+        email["header:X-Signature-Status:asText:all"],
+        serde_json::json!([]),
+        "{email}"
+    );
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
@@ -44,7 +44,8 @@ use tokio::{
 };
 
 use crate::jmap::{
-    assert_is_empty, email_set::assert_email_properties, mailbox::destroy_all_mailboxes,
+    assert_is_empty, email_set::assert_email_properties, jmap_raw_request,
+    mailbox::destroy_all_mailboxes,
 };
 
 use super::JMAPTest;
@@ -139,6 +140,44 @@ pub async fn test(params: &mut JMAPTest) {
         .unwrap()
         .take_id();
 
+    // Identities can use any of the account's addresses, regardless of case
+    let alias_identity_id = client
+        .identity_create("John Doe (alias)", "John.Doe@Example.com")
+        .await
+        .unwrap()
+        .take_id();
+
+    // Update the identity's signatures and reply-to addresses
+    let response = jmap_raw_request(
+        r#"[[ "Identity/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {
+                    "textSignature": "-- John",
+                    "replyTo": [{"name": null, "email": "jdoe@example.com"}]
+                }
+            }
+          }, "0" ],
+          [ "Identity/get", {
+            "accountId": "$$",
+            "ids": ["%%"],
+            "properties": ["email", "textSignature", "replyTo"]
+          }, "1" ]]"#
+            .replace("$$", &account_id)
+            .replace("%%", &alias_identity_id),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await;
+    assert!(response.contains("\"updated\":{"), "{}", response);
+    for expected in [
+        "\"email\":\"john.doe@example.com\"",
+        "\"textSignature\":\"-- John\"",
+        "\"email\":\"jdoe@example.com\"",
+    ] {
+        assert!(response.contains(expected), "{}", response);
+    }
+
     // Create test mailboxes
     let mailbox_id = client
         .mailbox_create("JMAP EmailSubmission", None::<String>, Role::None)
@@ -485,11 +524,37 @@ pub async fn test(params: &mut JMAPTest) {
         .await
         .unwrap()
         .is_none());
+    // Submissions using an identity whose address is no longer
+    // assigned to the account should fail
+    params
+        .directory
+        .remove_test_alias("jdoe@example.com", "john.doe@example.com")
+        .await;
+    let email_id = client
+        .email_import(
+            email_body.as_bytes().to_vec(),
+            [&mailbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    assert!(matches!(
+        client
+            .email_submission_create(&email_id, &alias_identity_id)
+            .await,
+        Err(Error::Set(SetError {
+            type_: SetErrorType::ForbiddenFrom,
+            ..
+        }))
+    ));
     smtp_settings.lock().do_stop = true;
 
     // Destroy the created mailbox, identity and all submissions
     for identity_id in [
         identity_id,
+        alias_identity_id,
         Id::from(0u64).to_string(),
         Id::from(1u64).to_string(),
     ] {
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::{INBOX_ID, TRASH_ID};
+use jmap_client::principal::ACL;
+use jmap_proto::types::id::Id;
+
+use crate::jmap::{
+    assert_is_empty, jmap_raw_request, mailbox::destroy_all_mailboxes, test_account_login,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Unified Inbox tests...");
+    let server = params.server.clone();
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    let trash_id = Id::from(TRASH_ID).to_string();
+
+    // Create test accounts
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    params
+        .directory
+        .create_test_user_with_email("jane.smith@example.com", "abcde", "Jane Smith")
+        .await;
+    let john_id: Id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap()
+        .into();
+    let jane_id: Id = server
+        .store
+        .get_or_create_account_id("jane.smith@example.com")
+        .await
+        .unwrap()
+        .into();
+    let mut john_client = test_account_login("jdoe@example.com", "12345").await;
+    let mut jane_client = test_account_login("jane.smith@example.com", "abcde").await;
+
+    // Import messages with interleaved received dates
+    let mut email_ids = Vec::new();
+    for (client, account_id, mailbox_id, received_at) in [
+        (&mut john_client, &john_id, &inbox_id, 1000),
+        (&mut jane_client, &jane_id, &inbox_id, 2000),
+        (&mut john_client, &john_id, &inbox_id, 3000),
+        (&mut jane_client, &jane_id, &trash_id, 4000),
+    ] {
+        email_ids.push(
+            client
+                .set_default_account_id(account_id.to_string())
+                .email_import(
+                    format!("From: bill@example.com\r\nSubject: Received at {received_at}\r\n\r\nHello.")
+                        .into_bytes(),
+                    [mailbox_id],
+                    None::<Vec<&str>>,
+                    Some(received_at),
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+
+    // Without delegated access only John's inbox is included
+    let response = unified_query(john_id, r#""calculateTotal": true"#).await;
+    assert_unified_results(
+        &response,
+        &[(&john_id, &email_ids[2]), (&john_id, &email_ids[0])],
+    );
+    assert!(response.contains("\"total\":2"), "{}", response);
+
+    // Jane shares her inbox, her trash remains private
+    jane_client
+        .mailbox_update_acl(&inbox_id, "jdoe@example.com", [ACL::ReadItems])
+        .await
+        .unwrap();
+    let response = unified_query(john_id, r#""calculateTotal": true"#).await;
+    assert_unified_results(
+        &response,
+        &[
+            (&john_id, &email_ids[2]),
+            (&jane_id, &email_ids[1]),
+            (&john_id, &email_ids[0]),
+        ],
+    );
+    assert!(response.contains("\"total\":3"), "{}", response);
+
+    // Results can be sorted, filtered and paginated
+    let response = unified_query(
+        john_id,
+        r#""sort": [{"property": "receivedAt", "isAscending": true}], "position": 1, "limit": 1"#,
+    )
+    .await;
+    assert_unified_results(&response, &[(&jane_id, &email_ids[1])]);
+    let response = unified_query(john_id, r#""filter": {"before": "1970-01-01T00:40:00Z"}"#).await;
+    assert_unified_results(
+        &response,
+        &[(&jane_id, &email_ids[1]), (&john_id, &email_ids[0])],
+    );
+
+    // Only receivedAt sorting is supported
+    let response = unified_query(john_id, r#""sort": [{"property": "subject"}]"#).await;
+    assert!(response.contains("unsupportedSort"), "{}", response);
+
+    // Unified queries can only be issued for the user's own account
+    let response = unified_query(jane_id, "").await;
+    assert!(response.contains("forbidden"), "{}", response);
+
+    // Destroy test account data
+    for id in [john_id, jane_id] {
+        params.client.set_default_account_id(&id.to_string());
+        destroy_all_mailboxes(params).await;
+    }
+    assert_is_empty(server).await;
+}
+
+fn assert_unified_results(response: &str, expected: &[(&Id, &String)]) {
+    let ids = expected
+        .iter()
+        .map(|(_, id)| format!("\"{id}\""))
+        .collect::<Vec<_>>()
+        .join(",");
+    let account_ids = expected
+        .iter()
+        .map(|(account_id, _)| format!("\"{account_id}\""))
+        .collect::<Vec<_>>()
+        .join(",");
+    assert!(
+        response.contains(&format!("\"ids\":[{ids}],\"accountIds\":[{account_ids}]")),
+        "{}",
+        response
+    );
+}
+
+async fn unified_query(account_id: Id, arguments: &str) -> String {
+    let arguments = if arguments.is_empty() {
+        String::new()
+    } else {
+        format!(", {arguments}")
+    };
+    jmap_raw_request(
+        format!(
+            r#"[[ "Email/query", {{
+                "accountId": "{account_id}",
+                "unifiedInbox": true{arguments}
+              }}, "0" ]]"#
+        ),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
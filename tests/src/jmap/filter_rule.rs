@@ -0,0 +1,283 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::{filter_rule::FILTER_RULES_SCRIPT, mailbox::INBOX_ID};
+use jmap_client::mailbox::Role;
+use jmap_proto::types::{collection::Collection, id::Id, keyword::Keyword, property::Property};
+use std::time::Instant;
+
+use crate::jmap::{
+    assert_is_empty,
+    delivery::SmtpConnection,
+    email_submission::{
+        assert_message_delivery, expect_nothing, spawn_mock_smtp_server, MockMessage,
+    },
+    jmap_raw_request,
+    mailbox::destroy_all_mailboxes,
+};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Filter Rule tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let mailbox_id = params
+        .client
+        .mailbox_create("Reports", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+
+    // Start mock SMTP server
+    let (mut smtp_rx, _smtp_settings) = spawn_mock_smtp_server();
+    server.smtp.resolvers.dns.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + std::time::Duration::from_secs(10),
+    );
+
+    // Rules without actions or with unknown mailboxes are rejected
+    let response = rules_request(
+        r#"[[ "FilterRule/set", {
+            "accountId": "$$",
+            "create": {
+                "a": {
+                    "name": "No actions",
+                    "conditions": [{"field": "from", "value": "bill@example.com"}],
+                    "actions": {}
+                },
+                "b": {
+                    "name": "Unknown mailbox",
+                    "conditions": [{"field": "from", "value": "bill@example.com"}],
+                    "actions": {"fileInto": "zzzzzz"}
+                },
+                "c": {
+                    "name": "Invalid field",
+                    "conditions": [{"field": "body", "value": "hello"}],
+                    "actions": {"markRead": true}
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"notCreated\":{"), "{}", response);
+    for id in ["\"a\":{", "\"b\":{", "\"c\":{"] {
+        assert!(response.contains(id), "{}", response);
+    }
+    assert!(!response.contains("\"created\""), "{}", response);
+    assert!(server
+        .sieve_script_get_active(account_id.document_id())
+        .await
+        .unwrap()
+        .is_none());
+
+    // Create rules
+    let response = rules_request(
+        &r#"[[ "FilterRule/set", {
+            "accountId": "$$",
+            "create": {
+                "reports": {
+                    "name": "TPS reports",
+                    "conditions": [
+                        {"field": "from", "value": "bill@example.com"},
+                        {"field": "subject", "operator": "contains", "value": "TPS"}
+                    ],
+                    "actions": {"fileInto": "%%", "markRead": true}
+                },
+                "forward": {
+                    "name": "Forward lists",
+                    "sortOrder": 1,
+                    "conditions": [{"field": "listId", "value": "tps.example.com"}],
+                    "actions": {"forwardTo": "jane_smith@remote.org"}
+                }
+            }
+          }, "0" ]]"#
+            .replace("%%", &mailbox_id),
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"reports\":{\"id\""), "{}", response);
+    assert!(response.contains("\"forward\":{\"id\""), "{}", response);
+    let active_script = server
+        .sieve_script_get_active(account_id.document_id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(active_script.script_name, FILTER_RULES_SCRIPT);
+
+    // The generated script cannot be modified using SieveScript/set
+    let response = rules_request(
+        &r#"[[ "SieveScript/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {"name": "my-rules"}
+            }
+          }, "0" ]]"#
+            .replace("%%", &Id::from(active_script.document_id).to_string()),
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"forbidden\""), "{}", response);
+
+    // Matching messages are filed and marked as read
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP."
+        ),
+    )
+    .await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Lunch\r\n",
+            "\r\n",
+            "Anyone up for lunch?"
+        ),
+    )
+    .await;
+    let reports_id = Id::from_bytes(mailbox_id.as_bytes()).unwrap().document_id();
+    assert_eq!(
+        tagged_ids(params, account_id, Property::MailboxIds, reports_id).await,
+        vec![0]
+    );
+    assert_eq!(
+        tagged_ids(params, account_id, Property::MailboxIds, INBOX_ID).await,
+        vec![1]
+    );
+    assert_eq!(
+        tagged_ids(params, account_id, Property::Keywords, Keyword::Seen).await,
+        vec![0]
+    );
+    expect_nothing(&mut smtp_rx).await;
+
+    // List messages are forwarded and kept
+    lmtp.ingest(
+        "lists@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: lists@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: TPS Reports <tps.example.com>\r\n",
+            "Subject: New cover sheets\r\n",
+            "\r\n",
+            "Did you get the memo?"
+        ),
+    )
+    .await;
+    lmtp.quit().await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<jane_smith@remote.org>"],
+            "@New cover sheets",
+        ),
+    )
+    .await;
+    assert_eq!(
+        tagged_ids(params, account_id, Property::MailboxIds, INBOX_ID).await,
+        vec![1, 2]
+    );
+
+    // Removing all rules removes the script
+    let response = rules_request(
+        r#"[[ "FilterRule/get", {
+            "accountId": "$$",
+            "ids": null,
+            "properties": ["id"]
+          }, "0" ]]"#,
+        account_id,
+    )
+    .await;
+    let rule_ids = super::find_values(&response, "\"id\"");
+    assert_eq!(rule_ids.len(), 2, "{}", response);
+    let response = rules_request(
+        &r#"[[ "FilterRule/set", {
+            "accountId": "$$",
+            "destroy": %%
+          }, "0" ]]"#
+            .replace("%%", &serde_json::to_string(&rule_ids).unwrap()),
+        account_id,
+    )
+    .await;
+    assert!(response.contains("\"destroyed\""), "{}", response);
+    assert!(server
+        .sieve_script_get_active(account_id.document_id())
+        .await
+        .unwrap()
+        .is_none());
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn rules_request(request: &str, account_id: Id) -> String {
+    jmap_raw_request(
+        request.replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
+
+async fn tagged_ids(
+    params: &JMAPTest,
+    account_id: Id,
+    property: Property,
+    value: impl Into<store::write::TagValue>,
+) -> Vec<u32> {
+    params
+        .server
+        .get_tag(account_id.document_id(), Collection::Email, property, value)
+        .await
+        .unwrap()
+        .map(|ids| ids.into_iter().collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_client::email::query::Filter;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running index advisor tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+    let (status, _) = manage_request(Method::DELETE, "index-advisor", None).await;
+    assert_eq!(status, 200);
+
+    // Indexed criteria are not reported
+    params
+        .client
+        .email_query(Filter::has_keyword("$seen").into(), None::<Vec<_>>)
+        .await
+        .unwrap();
+    assert_eq!(index_report().await, serde_json::json!([]));
+
+    // Report unindexed headers and thread keyword filters
+    for _ in 0..2 {
+        assert!(params
+            .client
+            .email_query(
+                Filter::header("X-Custom-Flag".to_string(), Some("yes")).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .is_err());
+    }
+    params
+        .client
+        .email_query(
+            Filter::all_in_thread_have_keyword("$seen").into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap();
+
+    let report = index_report().await;
+    for (criterion, count) in [
+        ("header:x-custom-flag", 2),
+        ("filter:allInThreadHaveKeyword", 1),
+    ] {
+        let entry = report
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["criterion"] == criterion)
+            .unwrap_or_else(|| panic!("Missing {criterion} entry: {report}"));
+        assert_eq!(entry["protocol"], "jmap", "{entry}");
+        assert_eq!(entry["command"], "Email/query", "{entry}");
+        assert_eq!(entry["count"], count, "{entry}");
+        assert!(!entry["suggestion"].as_str().unwrap().is_empty(), "{entry}");
+    }
+
+    // Clear the report
+    let (status, _) = manage_request(Method::DELETE, "index-advisor", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(index_report().await, serde_json::json!([]));
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn index_report() -> serde_json::Value {
+    let (status, response) = manage_request(Method::GET, "index-advisor", None).await;
+    assert_eq!(status, 200, "{response}");
+    serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone()
+}
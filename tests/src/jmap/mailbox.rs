@@ -21,6 +21,7 @@
  * for more details.
 */
 
+use jmap::mailbox::counters::{MailboxCounters, UpdateMailboxCounters};
 use jmap_client::{
     client::Client,
     core::{
@@ -32,7 +33,7 @@ use jmap_client::{
 };
 use jmap_proto::types::{id::Id, state::State};
 use serde::{Deserialize, Serialize};
-use store::ahash::AHashMap;
+use store::{ahash::AHashMap, write::BatchBuilder};
 
 use crate::jmap::assert_is_empty;
 
@@ -470,6 +471,46 @@ pub async fn test(params: &mut JMAPTest) {
         )
     );
 
+    // Cached counters follow the message and need no repair
+    let trash_id = Id::from_bytes(id_map["trash"].as_bytes())
+        .unwrap()
+        .document_id();
+    let inbox_id = Id::from_bytes(id_map["inbox"].as_bytes())
+        .unwrap()
+        .document_id();
+    let trash_counters = server.mailbox_counters(0, trash_id).await.unwrap();
+    assert_eq!(trash_counters.total, 1);
+    assert_eq!(trash_counters.unseen, 0);
+    assert!(trash_counters.size > 0);
+    assert_eq!(
+        server.mailbox_counters(0, inbox_id).await.unwrap(),
+        MailboxCounters::default()
+    );
+    assert_eq!(server.mailbox_counters_repair(0).await.unwrap(), 0);
+
+    // Counters that drifted are recalculated by the repair path
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(0)
+        .mailbox_counter_delta(trash_id, 2, 1, 100);
+    server.store.write(batch.build()).await.unwrap();
+    assert_eq!(server.mailbox_counters(0, trash_id).await.unwrap().total, 3);
+    assert_eq!(server.mailbox_counters_repair(0).await.unwrap(), 1);
+    assert_eq!(
+        server.mailbox_counters(0, trash_id).await.unwrap(),
+        trash_counters
+    );
+
+    // Missing counters are initialized on first access
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(0).clear_mailbox_counters(trash_id);
+    server.store.write(batch.build()).await.unwrap();
+    assert_eq!(
+        server.mailbox_counters(0, trash_id).await.unwrap(),
+        trash_counters
+    );
+    assert_eq!(server.mailbox_counters_repair(0).await.unwrap(), 0);
+
     // Deleting folders with children is not allowed
     let mut request = client.build();
     request.set_mailbox().destroy([&id_map["1"]]);
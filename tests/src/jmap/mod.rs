@@ -24,6 +24,7 @@
 use std::{sync::Arc, time::Duration};
 
 use base64::{engine::general_purpose, Engine};
+use dav::DavSessionManager;
 use directory::core::config::ConfigDirectory;
 use imap::core::{ImapSessionManager, IMAP};
 use jmap::{
@@ -37,33 +38,61 @@ use reqwest::header;
 use smtp::core::{SmtpSessionManager, SMTP};
 use store::config::ConfigStore;
 use tokio::sync::{mpsc, watch};
-use utils::{config::ServerProtocol, UnwrapFailure};
+use utils::{
+    config::{reload::ReloadableConfig, ServerProtocol},
+    UnwrapFailure,
+};
 
 use crate::{add_test_certs, directory::DirectoryStore, store::TempDir};
 
+pub mod account_api;
+pub mod account_snapshot;
+pub mod api_rate_limit;
 pub mod auth_acl;
 pub mod auth_limits;
 pub mod auth_oauth;
+pub mod auth_oidc;
+pub mod billing;
 pub mod blob;
+pub mod blob_resumable;
+pub mod cluster_affinity;
+pub mod cluster_handoff;
+pub mod cluster_invalidation;
+pub mod cluster_placement;
+pub mod config_reload;
 pub mod crypto;
+pub mod dav;
 pub mod delivery;
+pub mod email_annotations;
+pub mod email_bulk;
 pub mod email_changes;
 pub mod email_copy;
 pub mod email_get;
+pub mod email_import_bulk;
+pub mod email_importance;
 pub mod email_parse;
 pub mod email_query;
 pub mod email_query_changes;
+pub mod email_search_language;
 pub mod email_search_snippet;
+pub mod email_sender_list;
 pub mod email_set;
+pub mod email_signature;
 pub mod email_submission;
+pub mod email_unified;
 pub mod event_source;
+pub mod filter_rule;
+pub mod index_advisor;
 pub mod mailbox;
 pub mod push_subscription;
 pub mod quota;
+pub mod saved_search;
 pub mod sieve_script;
+pub mod slow_query;
 pub mod stress_test;
 pub mod thread_get;
 pub mod thread_merge;
+pub mod thread_set;
 pub mod vacation_response;
 pub mod websocket;
 
@@ -78,6 +107,12 @@ protocol = "jmap"
 max-connections = 81920
 tls.implicit = true
 
+[server.listener.dav]
+bind = ["127.0.0.1:8898"]
+protocol = "dav"
+max-connections = 81920
+tls.implicit = true
+
 [server.listener.imap]
 bind = ["127.0.0.1:9991"]
 protocol = "imap"
@@ -188,9 +223,27 @@ blob = "{STORE}"
 lookup = "{STORE}"
 directory = "auth"
 
+[storage.cluster]
+node-id = 1
+
+[storage.cluster.handoff]
+enable = true
+secret = "cluster-secret"
+tls.allow-invalid-certs = true
+
+[storage.cluster.node."1"]
+url = "https://127.0.0.1:8899"
+
+[storage.cluster.invalidation]
+enable = true
+poll-interval = "100ms"
+
 [storage.spam]
 header = "X-Spam-Status: Yes"
 
+[jmap.email.importance]
+enable = true
+
 [jmap.protocol.get]
 max-objects = 100000
 
@@ -209,10 +262,16 @@ ttl = "1m"
 files = 3
 size = 50000
 
+[jmap.protocol.upload.resumable]
+max-size = 100000
+max-chunk-size = 20000
+ttl = "1m"
+
 [jmap.rate-limit]
 account = "1000/1m"
 authentication = "100/2s"
 anonymous = "100/1m"
+principal-lookup = "3/1h"
 
 [jmap.event-source]
 throttle = "500ms"
@@ -270,6 +329,16 @@ user-code = "1s"
 token = "1s"
 refresh-token = "3s"
 refresh-token-renew = "2s"
+
+[oauth.oidc]
+issuer = "https://idp.example.org"
+audience = "stalwart-test"
+
+[oauth.oidc.jwks]
+url = "http://127.0.0.1:9914/jwks.json"
+
+[oauth.oidc.claim]
+name = "preferred_username"
 "#;
 
 #[tokio::test(flavor = "multi_thread")]
@@ -307,20 +376,45 @@ pub async fn jmap_tests() {
     email_copy::test(&mut params).await;
     thread_get::test(&mut params).await;
     thread_merge::test(&mut params).await;
+    thread_set::test(&mut params).await;
     mailbox::test(&mut params).await;
     delivery::test(&mut params).await;
+    email_importance::test(&mut params).await;
+    email_bulk::test(&mut params).await;
+    email_import_bulk::test(&mut params).await;
+    account_snapshot::test(&mut params).await;
+    cluster_handoff::test(&mut params).await;
+    cluster_placement::test(&mut params).await;
+    cluster_affinity::test(&mut params).await;
+    email_search_language::test(&mut params).await;
+    email_annotations::test(&mut params).await;
+    email_sender_list::test(&mut params).await;
+    email_unified::test(&mut params).await;
+    email_signature::test(&mut params).await;
     auth_acl::test(&mut params).await;
     auth_limits::test(&mut params).await;
+    api_rate_limit::test(&mut params).await;
+    slow_query::test(&mut params).await;
+    index_advisor::test(&mut params).await;
+    billing::test(&mut params).await;
+    config_reload::test(&mut params).await;
+    cluster_invalidation::test(&mut params).await;
     auth_oauth::test(&mut params).await;
+    auth_oidc::test(&mut params).await;
     event_source::test(&mut params).await;
     push_subscription::test(&mut params).await;
     sieve_script::test(&mut params).await;
     vacation_response::test(&mut params).await;
+    account_api::test(&mut params).await;
+    filter_rule::test(&mut params).await;
+    saved_search::test(&mut params).await;
     email_submission::test(&mut params).await;
     websocket::test(&mut params).await;
     quota::test(&mut params).await;
     crypto::test(&mut params).await;
     blob::test(&mut params).await;
+    blob_resumable::test(&mut params).await;
+    dav::test(&mut params).await;
 
     if delete {
         params.temp_dir.delete();
@@ -360,7 +454,7 @@ pub struct JMAPTest {
     server: Arc<JMAP>,
     client: Client,
     directory: DirectoryStore,
-    temp_dir: TempDir,
+    pub(crate) temp_dir: TempDir,
     shutdown_tx: watch::Sender<bool>,
 }
 
@@ -391,15 +485,19 @@ pub async fn assert_is_empty(server: Arc<JMAP>) {
         .await;
 }
 
-async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
+pub(crate) async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
     // Load and parse config
     let temp_dir = TempDir::new("jmap_tests", delete_if_exists);
-    let config = utils::config::Config::new(
-        &add_test_certs(SERVER)
+    let config_path = temp_dir.path.join("config.toml");
+    std::fs::write(
+        &config_path,
+        add_test_certs(SERVER)
             .replace("{STORE}", store_id)
             .replace("{TMP}", &temp_dir.path.display().to_string()),
     )
     .unwrap();
+    let config_path = config_path.display().to_string();
+    let config = utils::config::Config::from_file(&config_path).unwrap();
     let mut servers = config.parse_servers().unwrap();
     let stores = config.parse_stores().await.failed("Invalid configuration");
     let directory = config
@@ -427,6 +525,8 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
         .await
         .failed("Invalid configuration file");
     jmap.directory.blocked_ips.reload(&config).unwrap();
+    jmap.config_reloader.register("imap", imap.clone());
+    jmap.config_reloader.set_path(config_path);
 
     let (shutdown_tx, _) = servers.spawn(|server, shutdown_rx| {
         match &server.protocol {
@@ -440,6 +540,7 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
                 ImapSessionManager::new(jmap.clone(), imap.clone()),
                 shutdown_rx,
             ),
+            ServerProtocol::Dav => server.spawn(DavSessionManager::new(jmap.clone()), shutdown_rx),
             _ => unreachable!(),
         };
     });
@@ -489,7 +590,7 @@ pub async fn jmap_raw_request(body: impl AsRef<str>, username: &str, secret: &st
     );
 
     const BODY_TEMPLATE: &str = r#"{
-        "using": [ "urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail", "urn:ietf:params:jmap:quota" ],
+        "using": [ "urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail", "urn:ietf:params:jmap:quota", "urn:stalwart:jmap:settings", "urn:stalwart:jmap:filters", "urn:stalwart:jmap:annotations", "urn:stalwart:jmap:savedsearches", "urn:stalwart:jmap:unified", "urn:stalwart:jmap:conversations" ],
         "methodCalls": $$
       }"#;
 
@@ -44,14 +44,14 @@ use jmap::{
 };
 use jmap_client::{mailbox::Role, push_subscription::Keys};
 use jmap_proto::types::{id::Id, type_state::DataType};
-use store::ahash::AHashSet;
+use store::{ahash::AHashSet, write::now};
 
 use tokio::sync::mpsc;
 use utils::listener::SessionData;
 
 use crate::{
     add_test_certs,
-    jmap::{assert_is_empty, mailbox::destroy_all_mailboxes, test_account_login},
+    jmap::{assert_is_empty, jmap_raw_request, mailbox::destroy_all_mailboxes, test_account_login},
 };
 
 use super::JMAPTest;
@@ -110,6 +110,7 @@ pub async fn test(params: &mut JMAPTest) {
         auth_secret: auth_secret.to_vec(),
         tx: event_tx,
         fail_requests: false.into(),
+        gone_requests: false.into(),
     });
 
     // Start mock push server
@@ -203,6 +204,12 @@ pub async fn test(params: &mut JMAPTest) {
     push_server.fail_requests.store(false, Ordering::Relaxed);
     assert_state(&mut event_rx, &account_id, &[DataType::Mailbox]).await;
 
+    // The subscription is reported as active again after a successful delivery
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = push_status(&push_id).await;
+    assert!(response.contains("\"status\":\"active\""), "{}", response);
+    assert!(response.contains("\"failureCount\":0"), "{}", response);
+
     // Make a mailbox change and expect state change
     client
         .mailbox_rename(&mailbox_id, "My Mailbox")
@@ -221,6 +228,95 @@ pub async fn test(params: &mut JMAPTest) {
     assert_state(&mut event_rx, &account_id, &[DataType::Mailbox]).await;
     expect_nothing(&mut event_rx).await;
 
+    // Invalid quiet hours are rejected
+    let response = push_request(
+        &r#"[[ "PushSubscription/set", {
+            "update": {
+                "%%": {"quietHours": "25:00-07:00"}
+            }
+          }, "0" ]]"#
+            .replace("%%", &push_id),
+    )
+    .await;
+    assert!(response.contains("\"notUpdated\""), "{}", response);
+
+    // Pushes are withheld during quiet hours, which use the account's time zone
+    let response = push_request(
+        &r#"[[ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {"timezone": "+05:00"}
+            }
+          }, "0" ]]"#
+            .replace("$$", &account_id.to_string()),
+    )
+    .await;
+    assert!(response.contains("\"updated\""), "{}", response);
+    let local_minute = ((now() + 5 * 3600) % 86400 / 60) as u32;
+    let hhmm = |minute: u32| format!("{:02}:{:02}", (minute / 60) % 24, minute % 60);
+    let response = push_request(
+        &r#"[[ "PushSubscription/set", {
+            "update": {
+                "%%": {"quietHours": "@@"}
+            }
+          }, "0" ]]"#
+            .replace("%%", &push_id)
+            .replace(
+                "@@",
+                &format!(
+                    "{}-{}",
+                    hhmm(local_minute + 1440 - 60),
+                    hhmm(local_minute + 60)
+                ),
+            ),
+    )
+    .await;
+    assert!(response.contains("\"updated\""), "{}", response);
+    client
+        .mailbox_update_sort_order(&mailbox_id, 200)
+        .await
+        .unwrap();
+    expect_nothing(&mut event_rx).await;
+
+    // Withheld changes are delivered once quiet hours are removed
+    let response = push_request(
+        &r#"[[ "PushSubscription/set", {
+            "update": {
+                "%%": {"quietHours": null}
+            }
+          }, "0" ],
+          [ "Settings/set", {
+            "accountId": "$$",
+            "update": {
+                "singleton": {"timezone": null}
+            }
+          }, "1" ]]"#
+            .replace("%%", &push_id)
+            .replace("$$", &account_id.to_string()),
+    )
+    .await;
+    assert!(response.contains("\"updated\""), "{}", response);
+    assert_state(&mut event_rx, &account_id, &[DataType::Mailbox]).await;
+    expect_nothing(&mut event_rx).await;
+
+    // Subscriptions are expired after repeated 410 responses
+    push_server.gone_requests.store(true, Ordering::Relaxed);
+    client
+        .mailbox_update_sort_order(&mailbox_id, 300)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(4000)).await;
+    push_server.gone_requests.store(false, Ordering::Relaxed);
+    let response = push_status(&push_id).await;
+    assert!(response.contains("\"status\":\"expired\""), "{}", response);
+    assert!(response.contains("\"failureCount\":3"), "{}", response);
+    assert!(!response.contains("\"lastFailureAt\":null"), "{}", response);
+    client
+        .mailbox_update_sort_order(&mailbox_id, 301)
+        .await
+        .unwrap();
+    expect_nothing(&mut event_rx).await;
+
     // Destroy mailbox
     client.push_subscription_destroy(&push_id).await.unwrap();
     client.mailbox_destroy(&mailbox_id, true).await.unwrap();
@@ -245,6 +341,7 @@ pub struct PushServer {
     auth_secret: Vec<u8>,
     tx: mpsc::Sender<PushMessage>,
     fail_requests: AtomicBool,
+    gone_requests: AtomicBool,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -316,6 +413,12 @@ impl utils::listener::SessionManager for SessionManager {
                                     "too many requests".to_string(),
                                 )
                                 .into_http_response());
+                            } else if push.gone_requests.load(Ordering::Relaxed) {
+                                return Ok(HtmlResponse::with_status(
+                                    StatusCode::GONE,
+                                    "gone".to_string(),
+                                )
+                                .into_http_response());
                             }
                             let is_encrypted = req
                                 .headers()
@@ -362,6 +465,21 @@ impl utils::listener::SessionManager for SessionManager {
     }
 }
 
+async fn push_request(request: &str) -> String {
+    jmap_raw_request(request, "jdoe@example.com", "12345").await
+}
+
+async fn push_status(push_id: &str) -> String {
+    push_request(
+        &r#"[[ "PushSubscription/get", {
+            "ids": ["%%"],
+            "properties": ["status", "failureCount", "lastFailureAt"]
+          }, "0" ]]"#
+            .replace("%%", push_id),
+    )
+    .await
+}
+
 async fn expect_push(event_rx: &mut mpsc::Receiver<PushMessage>) -> PushMessage {
     match tokio::time::timeout(Duration::from_millis(1500), event_rx.recv()).await {
         Ok(Some(push)) => {
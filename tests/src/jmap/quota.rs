@@ -32,6 +32,7 @@ use jmap_client::{
     email::EmailBodyPart,
 };
 use jmap_proto::types::{collection::Collection, id::Id};
+use store::write::{BatchBuilder, DirectoryClass};
 
 use super::JMAPTest;
 
@@ -195,6 +196,14 @@ pub async fn test(params: &mut JMAPTest) {
         0
     );
 
+    assert_eq!(
+        server
+            .get_used_messages(account_id.document_id())
+            .await
+            .unwrap(),
+        0
+    );
+
     // Test Email/set quota
     let mut message_ids = Vec::new();
     for i in 0..2 {
@@ -334,6 +343,35 @@ pub async fn test(params: &mut JMAPTest) {
             .len(),
         1,
     );
+    assert_eq!(
+        server
+            .get_used_messages(account_id.document_id())
+            .await
+            .unwrap(),
+        1
+    );
+
+    // Missing message counters are rebuilt from the stored messages
+    let mut batch = BatchBuilder::new();
+    batch.clear(DirectoryClass::UsedMessages(account_id.document_id()));
+    server.store.write(batch.build()).await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            server
+                .get_used_messages(account_id.document_id())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+    assert_eq!(
+        server
+            .store
+            .get_counter(DirectoryClass::UsedMessages(account_id.document_id()))
+            .await
+            .unwrap(),
+        1
+    );
     DISABLE_UPLOAD_QUOTA.store(true, std::sync::atomic::Ordering::Relaxed);
 
     // Remove test data
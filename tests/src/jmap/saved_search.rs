@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_proto::types::id::Id;
+
+use crate::jmap::{assert_is_empty, jmap_raw_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Saved Search tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let mut email_ids = Vec::new();
+    for (subject, keywords) in [
+        ("First", vec!["$flagged"]),
+        ("Second", vec![]),
+        ("Third", vec!["$flagged"]),
+    ] {
+        email_ids.push(
+            params
+                .client
+                .email_import(
+                    format!("From: bill@example.com\r\nSubject: {subject}\r\n\r\nHello.")
+                        .into_bytes(),
+                    [&Id::from(INBOX_ID).to_string()],
+                    Some(keywords),
+                    None,
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+
+    // Full-text conditions and searches without a name are rejected
+    let response = search_request(
+        r#"[[ "SavedSearch/set", {
+            "accountId": "$$",
+            "create": {
+                "a": {
+                    "name": "Reports",
+                    "filter": {"text": "report"}
+                },
+                "b": {
+                    "filter": {"hasKeyword": "$flagged"}
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+        "",
+    )
+    .await;
+    assert!(response.contains("\"notCreated\":{"), "{}", response);
+    assert!(!response.contains("\"created\""), "{}", response);
+
+    // Create a saved search
+    let response = search_request(
+        r#"[[ "SavedSearch/set", {
+            "accountId": "$$",
+            "create": {
+                "flagged": {
+                    "name": "Flagged",
+                    "filter": {"hasKeyword": "$flagged"}
+                }
+            }
+          }, "0" ]]"#,
+        account_id,
+        "",
+    )
+    .await;
+    let search_id = response
+        .split_once("\"flagged\":{\"id\":\"")
+        .and_then(|(_, id)| id.split_once('"'))
+        .map(|(id, _)| id.to_string())
+        .unwrap_or_else(|| panic!("{}", response));
+    assert_search_results(account_id, &search_id, &email_ids, &[0, 2]).await;
+
+    // Results are updated as messages change
+    params
+        .client
+        .email_set_keyword(&email_ids[0], "$flagged", false)
+        .await
+        .unwrap();
+    params
+        .client
+        .email_set_keyword(&email_ids[1], "$flagged", true)
+        .await
+        .unwrap();
+    assert_search_results(account_id, &search_id, &email_ids, &[1, 2]).await;
+    params.client.email_destroy(&email_ids[2]).await.unwrap();
+    assert_search_results(account_id, &search_id, &email_ids, &[1]).await;
+
+    // Changing the filter recomputes the results
+    let response = search_request(
+        r#"[[ "SavedSearch/set", {
+            "accountId": "$$",
+            "update": {
+                "%%": {
+                    "filter": {
+                        "operator": "NOT",
+                        "conditions": [{"hasKeyword": "$flagged"}]
+                    }
+                }
+            }
+          }, "0" ],
+          [ "SavedSearch/get", {
+            "accountId": "$$",
+            "ids": ["%%"]
+          }, "1" ]]"#,
+        account_id,
+        &search_id,
+    )
+    .await;
+    assert!(response.contains("\"name\":\"Flagged\""), "{}", response);
+    assert!(response.contains("\"operator\":\"NOT\""), "{}", response);
+    assert_search_results(account_id, &search_id, &email_ids, &[0]).await;
+
+    // Remove test data
+    let response = search_request(
+        r#"[[ "SavedSearch/set", {
+            "accountId": "$$",
+            "destroy": ["%%"]
+          }, "0" ]]"#,
+        account_id,
+        &search_id,
+    )
+    .await;
+    assert!(response.contains("\"destroyed\":["), "{}", response);
+    assert_search_results(account_id, &search_id, &email_ids, &[]).await;
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn assert_search_results(
+    account_id: Id,
+    search_id: &str,
+    email_ids: &[String],
+    expected: &[usize],
+) {
+    let response = search_request(
+        r#"[[ "Email/query", {
+            "accountId": "$$",
+            "filter": {"inSavedSearch": "%%"}
+          }, "0" ]]"#,
+        account_id,
+        search_id,
+    )
+    .await;
+    for (pos, email_id) in email_ids.iter().enumerate() {
+        assert_eq!(
+            response.contains(&format!("\"{email_id}\"")),
+            expected.contains(&pos),
+            "{email_id}: {response}"
+        );
+    }
+}
+
+async fn search_request(request: &str, account_id: Id, search_id: &str) -> String {
+    jmap_raw_request(
+        request
+            .replace("$$", &account_id.to_string())
+            .replace("%%", search_id),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
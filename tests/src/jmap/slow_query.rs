@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap_client::email::query::Filter;
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+use utils::metrics::slow::slow_query_log;
+
+use crate::jmap::{assert_is_empty, config_reload::manage_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running slow query log tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("jdoe@example.com")
+        .await
+        .unwrap();
+    params
+        .client
+        .set_default_account_id(Id::from(account_id).to_string());
+
+    // Nothing is logged unless a threshold is configured
+    params
+        .client
+        .email_query(Filter::text("confidential").into(), None::<Vec<_>>)
+        .await
+        .unwrap();
+    assert_eq!(slow_queries().await, serde_json::json!([]));
+
+    // Log every method call
+    slow_query_log().set_threshold(Some(Duration::from_micros(1)), 10);
+    params
+        .client
+        .email_query(Filter::text("confidential").into(), None::<Vec<_>>)
+        .await
+        .unwrap();
+    slow_query_log().set_threshold(None, 10);
+
+    let entries = slow_queries().await;
+    let entry = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["protocol"] == "jmap" && entry["command"] == "Email/query")
+        .unwrap_or_else(|| panic!("Missing Email/query entry: {entries}"));
+    assert_eq!(entry["accountId"], account_id, "{entry}");
+    assert!(entry["elapsed"].as_u64().unwrap() > 0, "{entry}");
+    assert!(
+        !entry["operations"].as_array().unwrap().is_empty(),
+        "{entry}"
+    );
+    let arguments = entry["arguments"].as_str().unwrap();
+    assert!(arguments.contains("Text"), "{arguments}");
+    assert!(!arguments.contains("confidential"), "{arguments}");
+
+    // Clear the log
+    let (status, _) = manage_request(Method::DELETE, "slow-queries", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(slow_queries().await, serde_json::json!([]));
+
+    // Remove test data
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn slow_queries() -> serde_json::Value {
+    let (status, response) = manage_request(Method::GET, "slow-queries", None).await;
+    assert_eq!(status, 200, "{response}");
+    serde_json::from_str::<serde_json::Value>(&response).unwrap()["data"].clone()
+}
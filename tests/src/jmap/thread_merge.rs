@@ -260,6 +260,8 @@ async fn test_multi_thread(params: &mut JMAPTest) {
                         received_at: None,
                         skip_duplicates: true,
                         encrypt: false,
+                        classify: false,
+                        trusted_sender: false,
                     })
                     .await
                 {
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::mailbox::INBOX_ID;
+use jmap_client::mailbox::Role;
+use jmap_proto::types::id::Id;
+
+use crate::jmap::{assert_is_empty, jmap_raw_request, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Thread/set tests...");
+
+    // Create test account
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("jdoe@example.com", "12345", "John Doe")
+        .await;
+    let account_id = Id::from(
+        server
+            .store
+            .get_or_create_account_id("jdoe@example.com")
+            .await
+            .unwrap(),
+    );
+    params.client.set_default_account_id(account_id.to_string());
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    let archive_id = params
+        .client
+        .mailbox_create("Archive", None::<String>, Role::Archive)
+        .await
+        .unwrap()
+        .take_id();
+
+    // Import a thread with three messages
+    let mut email_ids = Vec::new();
+    let mut thread_id = String::new();
+    for num in 0..3 {
+        let mut email = params
+            .client
+            .email_import(
+                format!("Subject: Conversation\r\nReferences: <thread-set>\r\n\r\n{num}")
+                    .into_bytes(),
+                [&inbox_id],
+                None::<Vec<String>>,
+                None,
+            )
+            .await
+            .unwrap();
+        thread_id = email.thread_id().unwrap().to_string();
+        email_ids.push(email.take_id());
+    }
+
+    // Changes that cannot be applied to every message are rejected as a whole
+    let response = thread_request(&format!(
+        r#"[[ "Thread/set", {{
+                "accountId": "{account_id}",
+                "update": {{
+                    "{thread_id}": {{
+                        "keywords/$seen": true,
+                        "mailboxIds/{}": true
+                    }}
+                }}
+            }}, "0" ]]"#,
+        Id::new(9999)
+    ))
+    .await;
+    assert!(response.contains("\"notUpdated\":{"), "{}", response);
+    for email_id in &email_ids {
+        let email = params
+            .client
+            .email_get(email_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(email.keywords().is_empty());
+    }
+
+    // Mark as read and archive the entire thread
+    let response = thread_request(&format!(
+        r#"[[ "Thread/set", {{
+                "accountId": "{account_id}",
+                "update": {{
+                    "{thread_id}": {{
+                        "keywords/$seen": true,
+                        "mailboxIds/{inbox_id}": false,
+                        "mailboxIds/{archive_id}": true
+                    }}
+                }}
+            }}, "0" ]]"#
+    ))
+    .await;
+    assert!(
+        response.contains(&format!("\"updated\":{{\"{thread_id}\":null}}")),
+        "{}",
+        response
+    );
+    for email_id in &email_ids {
+        let email = params
+            .client
+            .email_get(email_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(email.keywords(), &["$seen"]);
+        assert_eq!(email.mailbox_ids(), &[archive_id.as_str()]);
+    }
+
+    // Threads cannot be created
+    let response = thread_request(&format!(
+        r#"[[ "Thread/set", {{
+                "accountId": "{account_id}",
+                "create": {{ "a": {{}} }}
+            }}, "0" ]]"#
+    ))
+    .await;
+    assert!(response.contains("\"notCreated\":{\"a\""), "{}", response);
+
+    // Delete the entire thread
+    let response = thread_request(&format!(
+        r#"[[ "Thread/set", {{
+                "accountId": "{account_id}",
+                "destroy": ["{thread_id}"]
+            }}, "0" ]]"#
+    ))
+    .await;
+    assert!(
+        response.contains(&format!("\"destroyed\":[\"{thread_id}\"]")),
+        "{}",
+        response
+    );
+    for email_id in &email_ids {
+        assert!(params
+            .client
+            .email_get(email_id, None::<Vec<_>>)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    assert!(params
+        .client
+        .thread_get(&thread_id)
+        .await
+        .unwrap()
+        .is_none());
+
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn thread_request(request: &str) -> String {
+    jmap_raw_request(request, "jdoe@example.com", "12345").await
+}
@@ -30,6 +30,8 @@ use jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+#[cfg(all(test, feature = "conformance"))]
+pub mod conformance;
 #[cfg(test)]
 pub mod directory;
 #[cfg(test)]
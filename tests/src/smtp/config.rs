@@ -37,13 +37,13 @@ use utils::{
 
 use smtp::{
     config::{
-        map_expr_token, throttle::ConfigThrottle, ConfigContext, Throttle, THROTTLE_AUTH_AS,
-        THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
+        map_expr_token, resolver::ConfigResolver, throttle::ConfigThrottle, ConfigContext,
+        Throttle, THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
     },
-    core::{eval::*, ResolveVariable},
+    core::{eval::*, ResolveVariable, SMTP},
 };
 
-use super::add_test_certs;
+use super::{add_test_certs, TestConfig};
 
 struct TestEnvelope {
     pub local_ip: IpAddr,
@@ -332,6 +332,85 @@ fn parse_throttle() {
     );
 }
 
+#[tokio::test]
+async fn dns_overrides() {
+    let config = Config::new(concat!(
+        "[resolver]\n",
+        "override = [\"example.org MX 20 mx2.example.org.\", ",
+        "\"Example.org. MX 10 mx1.example.org.\", ",
+        "\"mx1.example.org A 192.0.2.1\", ",
+        "\"mx1.example.org A 192.0.2.2\", ",
+        "\"mx1.example.org AAAA 2001:db8::1\", ",
+        "\"example.org TXT \\\"v=spf1 \\\" \\\"-all\\\"\", ",
+        "\"_25._tcp.mx1.example.org TLSA 3 1 1 0a0B0c\"]\n",
+    ))
+    .unwrap();
+    let mut core = SMTP::test();
+    core.resolvers.overrides = config.parse_dns_overrides().unwrap();
+
+    let mx = core.resolvers.mx_lookup("example.org").await.unwrap();
+    assert_eq!(
+        mx.iter()
+            .map(|mx| (mx.preference, mx.exchanges.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (10, vec!["mx1.example.org.".to_string()]),
+            (20, vec!["mx2.example.org.".to_string()])
+        ]
+    );
+    assert_eq!(
+        core.resolvers
+            .ipv4_lookup("mx1.example.org")
+            .await
+            .unwrap()
+            .as_ref(),
+        &[
+            "192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap(),
+            "192.0.2.2".parse().unwrap()
+        ]
+    );
+    assert_eq!(
+        core.resolvers
+            .ipv6_lookup("mx1.example.org.")
+            .await
+            .unwrap()
+            .as_ref(),
+        &["2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap()]
+    );
+    assert_eq!(
+        core.resolvers.txt_raw_lookup("example.org").await.unwrap(),
+        b"v=spf1 -all".to_vec()
+    );
+    assert!(core
+        .resolvers
+        .txt_lookup::<mail_auth::spf::Spf>("example.org")
+        .await
+        .is_ok());
+    let tlsa = core
+        .resolvers
+        .tlsa_lookup("_25._tcp.mx1.example.org")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(tlsa.has_end_entities && !tlsa.has_intermediates);
+    assert_eq!(tlsa.entries[0].data, vec![0x0a, 0x0b, 0x0c]);
+
+    for invalid in [
+        "example.org",
+        "example.org MX mx.example.org",
+        "example.org A 2001:db8::1",
+        "example.org TLSA 1 1 1 abcd",
+        "example.org TLSA 3 1 1 xyz",
+        "example.org CNAME mx.example.org",
+    ] {
+        let config = Config::new(&format!("[resolver]\noverride = [\"{invalid}\"]\n")).unwrap();
+        assert!(
+            config.parse_dns_overrides().is_err(),
+            "{invalid:?} should not parse"
+        );
+    }
+}
+
 #[test]
 fn parse_servers() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -364,6 +443,7 @@ fn parse_servers() {
             tls_implicit: false,
             max_connections: 8192,
             proxy_networks: vec![],
+            transcripts: Default::default(),
         },
         Server {
             id: "smtps".to_string(),
@@ -393,6 +473,7 @@ fn parse_servers() {
             tls_implicit: true,
             max_connections: 1024,
             proxy_networks: vec![],
+            transcripts: Default::default(),
         },
         Server {
             id: "submission".to_string(),
@@ -412,6 +493,7 @@ fn parse_servers() {
             tls_implicit: true,
             max_connections: 8192,
             proxy_networks: vec![],
+            transcripts: Default::default(),
         },
     ];
 
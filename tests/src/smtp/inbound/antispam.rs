@@ -9,6 +9,7 @@ use std::{
 
 use crate::smtp::session::TestSession;
 use ahash::AHashMap;
+use bytes::Bytes;
 use mail_auth::{dmarc::Policy, DkimResult, DmarcResult, IprevResult, SpfResult, MX};
 use sieve::runtime::Variable;
 use smtp::{
@@ -443,7 +444,7 @@ async fn antispam() {
             let mut params = session
                 .build_script_parameters("data")
                 .with_expected_variables(expected_variables)
-                .with_message(Arc::new(message.into_bytes()));
+                .with_message(Bytes::from(message.into_bytes()));
             for (name, value) in variables {
                 params = params.set_variable(name, value);
             }
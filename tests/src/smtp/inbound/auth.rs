@@ -21,7 +21,10 @@
  * for more details.
 */
 
+use std::sync::atomic::Ordering;
+
 use directory::core::config::ConfigDirectory;
+use smtp_proto::AUTH_PLAIN;
 use store::Store;
 use utils::config::{if_block::IfBlock, Config};
 
@@ -101,6 +104,11 @@ async fn auth() {
         .assert_not_contains(" PLAIN")
         .assert_not_contains(" LOGIN");
 
+    // Plain text mechanisms should be rejected before STARTTLS
+    session
+        .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "503 5.5.1")
+        .await;
+
     // EHLO should advertise AUTH for 10.0.0.1
     session.stream.tls = true;
     session
@@ -133,6 +141,24 @@ async fn auth() {
         .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "235 2.7.0")
         .await;
 
+    // Mechanism usage should be recorded
+    let usage = session
+        .core
+        .session
+        .auth_usage
+        .iter()
+        .find(|entry| entry.key().1 == AUTH_PLAIN)
+        .unwrap();
+    assert_eq!(
+        (
+            usage.success.load(Ordering::Relaxed),
+            usage.failure.load(Ordering::Relaxed),
+            usage.rejected.load(Ordering::Relaxed)
+        ),
+        (1, 2, 1)
+    );
+    drop(usage);
+
     // Users should be able to send emails only from their own email addresses
     session.mail_from("bill@foobar.org", "501 5.5.4").await;
     session.mail_from("john@example.org", "250").await;
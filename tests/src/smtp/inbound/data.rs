@@ -24,6 +24,7 @@
 use std::sync::Arc;
 
 use directory::core::config::ConfigDirectory;
+use mail_parser::MessageParser;
 use store::Store;
 use utils::config::{if_block::IfBlock, Config};
 
@@ -32,7 +33,10 @@ use crate::smtp::{
     session::{load_test_message, TestSession, VerifyResponse},
     ParseTestConfig, TestConfig, TestSMTP,
 };
-use smtp::core::{Session, SMTP};
+use smtp::{
+    config::Footer,
+    core::{Session, SMTP},
+};
 
 const DIRECTORY: &str = r#"
 [storage]
@@ -249,3 +253,88 @@ async fn data() {
         .assert_is_empty(core.shared.default_blob_store.clone())
         .await;
 }
+
+#[test]
+fn footer() {
+    let footer = Footer {
+        text: "This message is confidential.".to_string(),
+        html: None,
+        once_per_thread: true,
+    };
+
+    // Plain text messages
+    let message = footer
+        .apply(b"From: john@foobar.org\r\nSubject: test\r\n\r\nHello world\r\n")
+        .unwrap();
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(parsed.subject(), Some("test"));
+    assert!(parsed
+        .body_text(0)
+        .unwrap()
+        .starts_with("Hello world\r\n\r\nThis message is confidential."));
+    assert!(parsed
+        .body_html(0)
+        .unwrap()
+        .contains("This message is confidential."));
+
+    // Alternative text and HTML parts
+    let message = footer
+        .apply(
+            concat!(
+                "From: john@foobar.org\r\n",
+                "Content-Type: multipart/alternative; boundary=\"b\"\r\n\r\n",
+                "--b\r\nContent-Type: text/plain\r\n\r\nHello world\r\n",
+                "--b\r\nContent-Type: text/html\r\n\r\n",
+                "<html><body><p>Hello world</p></body></html>\r\n",
+                "--b--\r\n"
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(parsed.parts.len(), 3);
+    assert!(parsed
+        .body_text(0)
+        .unwrap()
+        .ends_with("This message is confidential."));
+    assert!(parsed
+        .body_html(0)
+        .unwrap()
+        .contains("<p>This message is confidential.</p></body>"));
+
+    // Signed messages are not altered
+    assert!(footer
+        .apply(
+            concat!(
+                "From: john@foobar.org\r\n",
+                "Content-Type: multipart/signed; boundary=\"b\"\r\n\r\n",
+                "--b\r\nContent-Type: text/plain\r\n\r\nHello world\r\n",
+                "--b\r\nContent-Type: application/pgp-signature\r\n\r\nsig\r\n",
+                "--b--\r\n"
+            )
+            .as_bytes(),
+        )
+        .is_none());
+
+    // The footer is added only once per thread
+    assert!(footer
+        .apply(
+            concat!(
+                "From: john@foobar.org\r\n",
+                "In-Reply-To: <1234@foobar.org>\r\n\r\n",
+                "Reply\r\n\r\n> Hello world\r\n> This message is confidential.\r\n"
+            )
+            .as_bytes(),
+        )
+        .is_none());
+    assert!(footer
+        .apply(
+            concat!(
+                "From: john@foobar.org\r\n",
+                "In-Reply-To: <1234@foobar.org>\r\n\r\n",
+                "Reply\r\n"
+            )
+            .as_bytes(),
+        )
+        .is_some());
+}
@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use smtp_proto::MAIL_REQUIRETLS;
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{session::ConfigSession, DlpAction},
+    core::{eval::V_SENDER, Session, SMTP},
+    inbound::dlp::IncidentStatus,
+    queue::MAIL_QUARANTINED,
+};
+
+const CONFIG: &str = r#"
+[session.data.dlp.dictionary]
+confidential = ["(?i)project\\s+falcon"]
+
+[session.data.dlp.rule."cards"]
+patterns = ["credit-card"]
+action = "reject"
+
+[session.data.dlp.rule."finance"]
+patterns = ["iban", "dictionary:confidential"]
+threshold = 2
+action = "quarantine"
+
+[session.data.dlp.rule."internal"]
+patterns = ["dictionary:confidential"]
+action = "encrypt"
+"#;
+
+const CARD: &str = "4111 1111 1111 1111";
+const INVALID_CARD: &str = "4111 1111 1111 1112";
+const IBAN: &str = "GB82 WEST 1234 5698 7654 32";
+const KEYWORD: &str = "Project Falcon";
+
+#[tokio::test]
+async fn dlp() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_dlp_test");
+    let mut dlp = Config::new(CONFIG).unwrap().parse_dlp(&[V_SENDER]).unwrap();
+    dlp.enable = IfBlock::new(true);
+
+    // Test pattern matching
+    for (text, expected_rule) in [
+        (format!("Card number {CARD}."), Some("cards")),
+        (format!("Card number {INVALID_CARD}."), None),
+        (
+            format!("Card number {}.", CARD.replace(' ', "-")),
+            Some("cards"),
+        ),
+        (format!("Account {IBAN} for {KEYWORD}."), Some("finance")),
+        (format!("Account {}.", IBAN.replace(' ', "")), None),
+        (format!("About {KEYWORD}."), Some("internal")),
+        (format!("{CARD} and {KEYWORD} {IBAN}"), Some("cards")),
+        ("Nothing to see here.".to_string(), None),
+    ] {
+        let message = format!("From: john@doe.org\r\nSubject: test\r\n\r\n{text}\r\n");
+        assert_eq!(
+            dlp.scan(message.as_bytes())
+                .map(|(rule, _)| rule.id.as_str()),
+            expected_rule,
+            "{text}"
+        );
+    }
+
+    // Attachments and HTML parts are also scanned
+    let message = concat!(
+        "From: john@doe.org\r\n",
+        "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n",
+        "--b\r\nContent-Type: text/html\r\n\r\n<p>Hello</p>\r\n",
+        "--b\r\nContent-Type: text/csv\r\nContent-Disposition: attachment\r\n",
+        "Content-Transfer-Encoding: base64\r\n\r\n",
+        "bmFtZSxjYXJkCmpvaG4sNDExMSAxMTExIDExMTEgMTExMQo=\r\n",
+        "--b--\r\n"
+    );
+    let (rule, matches) = dlp.scan(message.as_bytes()).unwrap();
+    assert_eq!(rule.action, DlpAction::Reject);
+    assert_eq!(matches[0].count, 1);
+
+    // Enable DLP
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.dlp = dlp;
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages containing credit card numbers are rejected
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: card\r\n\r\nMy card is {CARD}.\r\n"),
+            "550 5.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+    let incidents = core.list_dlp_incidents(|_| true).await.unwrap();
+    assert_eq!(incidents.len(), 1);
+    assert_eq!(incidents[0].rule, "cards");
+    assert_eq!(incidents[0].status, IncidentStatus::Rejected);
+    assert_eq!(incidents[0].return_path, "john@doe.org");
+    assert_eq!(incidents[0].recipients, vec!["bill@foobar.org".to_string()]);
+    assert_eq!(incidents[0].queue_id, None);
+
+    // Messages containing IBANs and keywords are quarantined
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: iban\r\n\r\n{IBAN} for {KEYWORD}.\r\n"),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_QUARANTINED, 0);
+    assert!(qr.read_queued_events().await.is_empty());
+    let incident = core
+        .list_dlp_incidents(|incident| incident.status == IncidentStatus::Quarantined)
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(incident.rule, "finance");
+    assert_eq!(incident.queue_id, Some(message.id));
+    assert_eq!(incident.matches.len(), 2);
+
+    // Release the quarantined message
+    assert!(core.release_quarantined(message.id, "admin").await);
+    assert!(!core.release_quarantined(message.id, "admin").await);
+    qr.read_event().await.assert_reload();
+    assert_eq!(qr.last_queued_message().await.flags & MAIL_QUARANTINED, 0);
+    assert_eq!(qr.read_queued_events().await.len(), 1);
+    assert_eq!(
+        core.read_dlp_incident(incident.id).await.unwrap().status,
+        IncidentStatus::Released
+    );
+    qr.clear_queue(&core).await;
+
+    // Messages containing keywords require TLS
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: keyword\r\n\r\nAbout {KEYWORD}.\r\n"),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_REQUIRETLS, 0);
+    assert_eq!(message.flags & MAIL_QUARANTINED, 0);
+    assert_eq!(
+        core.list_dlp_incidents(|incident| incident.status == IncidentStatus::Encrypted)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    qr.clear_queue(&core).await;
+
+    // Quarantined messages can be discarded
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: iban\r\n\r\n{IBAN} for {KEYWORD}.\r\n"),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert!(core.discard_quarantined(message.id, "admin").await);
+    qr.assert_queue_is_empty().await;
+    assert_eq!(
+        core.list_dlp_incidents(|incident| incident.status == IncidentStatus::Discarded)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
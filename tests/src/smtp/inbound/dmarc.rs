@@ -43,7 +43,7 @@ use crate::smtp::{
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, ConfigContext, VerifyStrategy},
+    config::{AggregateFrequency, ConfigContext, ReportConfig, VerifyStrategy},
     core::{Session, SMTP},
 };
 
@@ -151,11 +151,11 @@ async fn dmarc() {
     config.data.add_return_path = IfBlock::new(true);
     config.data.add_received_spf = IfBlock::new(true);
 
-    let config = &mut core.report.config;
-    config.dkim.send = "\"[1, 1s]\"".parse_if();
-    config.dmarc.send = config.dkim.send.clone();
-    config.spf.send = config.dkim.send.clone();
-    config.dmarc_aggregate.send = IfBlock::new(AggregateFrequency::Daily);
+    let mut report_config = ReportConfig::test();
+    report_config.dkim.send = "\"[1, 1s]\"".parse_if();
+    report_config.dmarc.send = report_config.dkim.send.clone();
+    report_config.spf.send = report_config.dkim.send.clone();
+    report_config.dmarc_aggregate.send = IfBlock::new(AggregateFrequency::Daily);
 
     let config = &mut core.mail_auth;
     config.spf.verify_ehlo = r#"[{if = "remote_ip = '10.0.0.2'", then = 'strict'},
@@ -168,10 +168,10 @@ async fn dmarc() {
     { else = 'strict' }]"#
         .parse_if_constant::<VerifyStrategy>();
 
-    let config = &mut core.report.config;
-    config.spf.sign = "\"['rsa']\"".parse_if();
-    config.dmarc.sign = "\"['rsa']\"".parse_if();
-    config.dkim.sign = "\"['rsa']\"".parse_if();
+    report_config.spf.sign = "\"['rsa']\"".parse_if();
+    report_config.dmarc.sign = "\"['rsa']\"".parse_if();
+    report_config.dkim.sign = "\"['rsa']\"".parse_if();
+    core.report.config.store(Arc::new(report_config));
 
     // SPF must pass
     let core = Arc::new(core);
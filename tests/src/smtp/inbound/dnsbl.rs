@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    inbound::TestMessage,
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::session::ConfigSession,
+    core::{Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[session.dnsbl]
+action = [ { if = "dnsbl_score >= 5", then = "reject" },
+           { if = "dnsbl_score >= 2", then = "greylist" },
+           { if = "dnsbl_score > 0", then = "tag" },
+           { else = "accept" } ]
+
+[session.dnsbl.zone."zen"]
+zone = "zen.example.org"
+weight = 5.0
+
+[session.dnsbl.zone."ips"]
+zone = "ips.example.org."
+stage = "ehlo"
+codes = ["127.0.0.3"]
+
+[session.dnsbl.zone."dbl"]
+zone = "dbl.example.org"
+type = "domain"
+weight = 2.0
+
+[session.dnsbl.zone."hbl"]
+zone = "hbl.example.org"
+type = "domain"
+stage = "ehlo"
+weight = 5.0
+"#;
+
+const INVALID_CONFIG: &str = r#"
+[session.dnsbl.zone."dbl"]
+zone = "dbl.example.org"
+type = "domain"
+stage = "connect"
+"#;
+
+const MESSAGE: &str = "From: john@foobar.org\r\nSubject: hi\r\n\r\nHello.\r\n";
+
+#[tokio::test]
+async fn dnsbl() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_dnsbl_test");
+    core.session.config.dnsbl = Config::new(CONFIG).unwrap().parse_session_dnsbl().unwrap();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    // Domain blocklists cannot be queried before EHLO
+    assert!(Config::new(INVALID_CONFIG)
+        .unwrap()
+        .parse_session_dnsbl()
+        .is_err());
+
+    // Add mock DNS entries
+    for (name, codes) in [
+        ("2.0.0.10.zen.example.org", vec!["127.0.0.2"]),
+        ("1.0.0.10.zen.example.org", vec!["127.255.255.254"]),
+        ("3.0.0.10.zen.example.org", vec![]),
+        ("1.0.0.10.ips.example.org", vec!["127.0.0.2"]),
+        ("3.0.0.10.ips.example.org", vec!["127.0.0.3"]),
+        ("mx.spammer.org.hbl.example.org", vec!["127.0.1.2"]),
+        ("mx.foobar.org.hbl.example.org", vec![]),
+        ("spammer.org.dbl.example.org", vec!["127.0.1.2"]),
+        ("foobar.org.dbl.example.org", vec![]),
+    ] {
+        core.resolvers.dns.ipv4_add(
+            name,
+            codes
+                .into_iter()
+                .map(|code| code.parse().unwrap())
+                .collect(),
+            Instant::now() + Duration::from_secs(10),
+        );
+    }
+    let core = Arc::new(core);
+
+    // Listed IPs are rejected on connect and the result is cached
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(!session.init_conn().await);
+    session.response().assert_code("554 5.7.1");
+    assert_eq!(
+        core.shared
+            .default_lookup_store
+            .key_get::<i64>(b"bl:2.0.0.10.zen.example.org".to_vec())
+            .await
+            .unwrap(),
+        Some(0x7f000002)
+    );
+
+    // Error codes and codes not configured for a zone are ignored
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response().assert_code("220");
+
+    // EHLO domains are checked against domain blocklists
+    session.cmd("EHLO mx.spammer.org", "550 5.7.1").await;
+    session.ehlo("mx.foobar.org").await;
+    assert!(session.data.dnsbl_hits.is_empty());
+
+    // Listed sender domains are greylisted
+    session.mail_from("john@spammer.org", "250").await;
+    assert_eq!(session.data.dnsbl_score(), 2.0);
+    session.rcpt_to("bill@foobar.org", "451 4.7.1").await;
+    session.rset().await;
+    session
+        .send_message("john@foobar.org", &["bill@foobar.org"], MESSAGE, "250")
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_not_contains("X-DNSBL");
+
+    // Low scores tag the message
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response().assert_code("220");
+    session.ehlo("mx.foobar.org").await;
+    session
+        .send_message("john@foobar.org", &["bill@foobar.org"], MESSAGE, "250")
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-DNSBL: score=1; listed=ips.example.org (127.0.0.3)");
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    inbound::TestMessage,
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{shared::ConfigShared, Journal},
+    core::{Session, SMTP},
+    queue::{MAIL_DSN_SUPPRESSED, MAIL_JOURNAL},
+};
+
+const CONFIG: &str = r#"
+[journal."compliance"]
+type = "mailbox"
+address = "archive@example.org"
+
+[journal."external"]
+type = "relay"
+address = "journal@archive.example.net"
+relay = "archive-relay"
+
+[journal."cold-storage"]
+type = "blob"
+prefix = "archive/"
+
+[journal."invalid-type"]
+type = "tape"
+
+[journal."invalid-address"]
+type = "mailbox"
+address = "archive"
+"#;
+
+const MESSAGE: &str = "From: john@doe.org\r\nSubject: journal\r\n\r\nKeep a copy of this.\r\n";
+
+#[tokio::test]
+async fn journal() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_journal_test");
+
+    // Parse journals
+    let config = Config::new(CONFIG).unwrap();
+    for id in ["compliance", "external", "cold-storage"] {
+        core.shared
+            .journals
+            .insert(id.to_string(), config.parse_journal(id).unwrap());
+    }
+    assert!(matches!(
+        core.shared.journals.get("external"),
+        Some(Journal::Relay { address, relay })
+            if address == "journal@archive.example.net" && relay == "archive-relay"
+    ));
+    for id in ["invalid-type", "invalid-address"] {
+        assert!(config.parse_journal(id).is_err(), "{id}");
+    }
+
+    // Journal copies addressed to a relay journal are routed to its relay
+    assert_eq!(
+        core.journal_relay("archive.example.net").as_deref(),
+        Some("archive-relay")
+    );
+    assert_eq!(core.journal_relay("example.org"), None);
+
+    // Enable journaling
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(100);
+    core.session.config.data.journal =
+        r#"[{if = "rcpt_domain = 'foobar.org'", then = "'compliance'"},
+    {if = "sender_domain = 'bar.org'", then = "'cold-storage'"},
+    {else = false}]"#
+            .parse_if();
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages to journaled domains are copied to the archive mailbox
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org", "jane@foobar.org", "mike@test.net"],
+            MESSAGE,
+            "250",
+        )
+        .await;
+    qr.read_event().await.assert_reload();
+    qr.read_event().await.assert_reload();
+    let messages = qr.read_queued_messages().await;
+    assert_eq!(messages.len(), 2);
+    let (message, copy) = (&messages[0], &messages[1]);
+    assert_eq!(message.flags & MAIL_JOURNAL, 0);
+    assert_eq!(message.recipients.len(), 3);
+    assert_eq!(
+        copy.flags & (MAIL_JOURNAL | MAIL_DSN_SUPPRESSED),
+        MAIL_JOURNAL | MAIL_DSN_SUPPRESSED
+    );
+    assert_eq!(copy.return_path, "john@doe.org");
+    assert_eq!(
+        copy.recipients
+            .iter()
+            .map(|rcpt| rcpt.address.as_str())
+            .collect::<Vec<_>>(),
+        vec!["archive@example.org"]
+    );
+    assert_eq!(
+        copy.read_message(&qr).await,
+        message.read_message(&qr).await
+    );
+    qr.assert_no_events();
+    qr.clear_queue(&core).await;
+
+    // Messages from journaled domains are archived to the blob store
+    session
+        .send_message("jane@bar.org", &["mike@test.net"], MESSAGE, "250")
+        .await;
+    let message = qr.expect_message().await;
+    qr.assert_no_events();
+    assert_eq!(qr.read_queued_messages().await.len(), 1);
+    assert_eq!(
+        String::from_utf8(
+            qr.blob_store
+                .get_blob(
+                    format!("archive/{}.eml", message.id).as_bytes(),
+                    0..u32::MAX
+                )
+                .await
+                .unwrap()
+                .expect("Journal blob not found")
+        )
+        .unwrap(),
+        message.read_message(&qr).await
+    );
+    qr.clear_queue(&core).await;
+
+    // Other messages are not journaled
+    session
+        .send_message("john@doe.org", &["mike@test.net"], MESSAGE, "250")
+        .await;
+    qr.expect_message().await;
+    qr.assert_no_events();
+    assert_eq!(qr.read_queued_messages().await.len(), 1);
+}
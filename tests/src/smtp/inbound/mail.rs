@@ -35,7 +35,7 @@ use crate::smtp::{
     ParseTestConfig, TestConfig,
 };
 use smtp::{
-    config::VerifyStrategy,
+    config::{SessionThrottle, VerifyStrategy},
     core::{Session, SMTP},
 };
 
@@ -93,12 +93,15 @@ async fn mail() {
     {else = 1024}]"#
         .parse_if();
 
-    config.throttle.mail_from = r#"[[throttle]]
+    config.throttle.store(Arc::new(SessionThrottle {
+        mail_from: r#"[[throttle]]
     match = "remote_ip = '10.0.0.1'"
     key = 'sender'
     rate = '2/1s'
     "#
-    .parse_throttle();
+        .parse_throttle(),
+        ..Default::default()
+    }));
 
     // Be rude and do not say EHLO
     let core = Arc::new(core);
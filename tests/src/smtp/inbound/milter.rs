@@ -192,6 +192,71 @@ async fn milter_session() {
         .await
         .assert_contains("X-Spam: Yes")
         .assert_contains("123456");
+
+    // Verdicts can be recorded in a header rather than enforced
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_milter_action_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.milters = r#"[[session.data.milter]]
+    hostname = "127.0.0.1"
+    port = 9332
+    enable = true
+    action.body = "add-header"
+
+    [[session.data.milter]]
+    hostname = "127.0.0.1"
+    port = 9332
+    enable = true
+    action.body = "tempfail"
+    "#
+    .parse_milters();
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // The first milter records the rejection, the second one turns it into a temporary failure
+    session
+        .send_message(
+            "reject@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "451 4.3.5",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Verdicts are recorded for accepted messages
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_milter_action_test");
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.milters = r#"[[session.data.milter]]
+    hostname = "127.0.0.1"
+    port = 9332
+    enable = true
+    action.body = "add-header"
+    "#
+    .parse_milters();
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    session
+        .send_message(
+            "discard@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-Milter-Action: 127.0.0.1; stage=body; action=discard")
+        .assert_contains("Are you hungry yet?");
 }
 
 #[test]
@@ -394,6 +459,7 @@ async fn milter_client_test() {
             protocol_version: Version::V6,
             flags_actions: None,
             flags_protocol: None,
+            actions: Default::default(),
         },
         tracing::span!(tracing::Level::TRACE, "hi"),
     )
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_builder::{
+    headers::content_type::ContentType,
+    mime::{BodyPart, MimePart},
+};
+use mail_parser::{MessageParser, MimeHeaders};
+use smtp::inbound::mime::MimeRewrite;
+
+const MIXED: &str = concat!(
+    "From: john@foobar.org\r\n",
+    "Subject: report\r\n",
+    "X-Custom: value\r\n",
+    "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n",
+    "--b\r\nContent-Type: text/plain; charset=iso-8859-1\r\n",
+    "Content-Transfer-Encoding: quoted-printable\r\n",
+    "X-Part: keep\r\n\r\n",
+    "Caf=E9\r\n",
+    "--b\r\nContent-Type: application/octet-stream\r\n",
+    "Content-Disposition: attachment; filename=\"a.bin\"\r\n\r\n",
+    "AAAA\r\n",
+    "--b--\r\n"
+);
+
+#[test]
+fn mime_rewrite() {
+    // Remove a part
+    let mut rewrite = MimeRewrite::parse(MIXED.as_bytes()).unwrap();
+    assert!(rewrite.remove_part(2));
+    assert!(!rewrite.remove_part(0));
+    let message = rewrite.build();
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(parsed.parts.len(), 2);
+    assert_eq!(parsed.attachment_count(), 0);
+    assert_eq!(parsed.body_text(0).unwrap(), "Café");
+
+    // Replace a part, preserving unknown headers
+    let mut rewrite = MimeRewrite::parse(MIXED.as_bytes()).unwrap();
+    assert!(rewrite.replace_part(
+        1,
+        MimePart::new(
+            ContentType::new("text/plain"),
+            BodyPart::Text("Replaced".into())
+        )
+    ));
+    let message = rewrite.build();
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(parsed.body_text(0).unwrap().trim_end(), "Replaced");
+    let message = String::from_utf8(message.clone()).unwrap();
+    assert!(message.contains("X-Custom: value\r\n"));
+    assert!(message.contains("X-Part: keep\r\n"));
+    assert!(!message.contains("quoted-printable"));
+    assert_eq!(
+        parsed.attachment(0).unwrap().attachment_name(),
+        Some("a.bin")
+    );
+
+    // Re-encode a part as UTF-8
+    let mut rewrite = MimeRewrite::parse(MIXED.as_bytes()).unwrap();
+    assert!(rewrite.reencode_part(1));
+    let message = rewrite.build();
+    let parsed = MessageParser::new().parse(&message).unwrap();
+    assert_eq!(parsed.body_text(0).unwrap().trim_end(), "Café");
+    assert_eq!(
+        parsed.parts[1].content_type().unwrap().attribute("charset"),
+        Some("utf-8")
+    );
+
+    // Add a part to a multipart/mixed message and to a plain text message
+    for (raw_message, expected_parts) in [
+        (MIXED, 4),
+        (
+            "From: john@foobar.org\r\nSubject: test\r\n\r\nHello world\r\n",
+            3,
+        ),
+    ] {
+        let build = || {
+            let mut rewrite = MimeRewrite::parse(raw_message.as_bytes()).unwrap();
+            assert!(rewrite.add_part(
+                MimePart::new(
+                    ContentType::new("text/csv"),
+                    BodyPart::Text("a,b,c\r\n".into())
+                )
+                .attachment("data.csv")
+            ));
+            rewrite.build()
+        };
+        let message = build();
+
+        // Output is deterministic
+        assert_eq!(message, build());
+
+        let parsed = MessageParser::new().parse(&message).unwrap();
+        assert_eq!(
+            parsed.parts.len(),
+            expected_parts,
+            "{}",
+            String::from_utf8_lossy(&message)
+        );
+        assert!(parsed.subject().is_some());
+        assert!(parsed.body_text(0).is_some());
+        assert_eq!(
+            parsed.attachments().last().unwrap().attachment_name(),
+            Some("data.csv")
+        );
+    }
+}
@@ -41,16 +41,23 @@ pub mod antispam;
 pub mod auth;
 pub mod basic;
 pub mod data;
+pub mod dlp;
 pub mod dmarc;
+pub mod dnsbl;
 pub mod ehlo;
+pub mod journal;
 pub mod limits;
 pub mod mail;
 pub mod milter;
+pub mod monitor;
+pub mod mime;
 pub mod rcpt;
 pub mod rewrite;
+pub mod risk;
 pub mod scripts;
 pub mod sign;
 pub mod throttle;
+pub mod transcript;
 pub mod vrfy;
 
 impl QueueReceiver {
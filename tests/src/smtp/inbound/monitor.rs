@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::session::ConfigSession,
+    core::{eval::V_SENDER, Session, SMTP},
+    inbound::monitor::{Disposition, MonitorAction},
+    queue::MAIL_QUARANTINED,
+};
+
+const CONFIG: &str = r#"
+[session.data.dlp.dictionary]
+confidential = ["(?i)project\\s+falcon"]
+
+[session.data.dlp.rule."cards"]
+patterns = ["credit-card"]
+action = "reject"
+
+[session.data.dlp.rule."internal"]
+patterns = ["dictionary:confidential"]
+action = "quarantine"
+
+[session.data.monitor]
+rules = ["dlp:cards"]
+"#;
+
+const CARD: &str = "4111 1111 1111 1111";
+const KEYWORD: &str = "Project Falcon";
+
+#[tokio::test]
+async fn monitor() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_monitor_test");
+    let config = Config::new(CONFIG).unwrap();
+    let mut dlp = config.parse_dlp(&[V_SENDER]).unwrap();
+    dlp.enable = IfBlock::new(true);
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.dlp = dlp;
+    core.session.config.data.monitor = config.parse_monitor().unwrap();
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Monitored rules record their verdict but the message is delivered
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: card\r\n\r\nMy card is {CARD}.\r\n"),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_eq!(message.flags & MAIL_QUARANTINED, 0);
+    assert!(core.list_dlp_incidents(|_| true).await.unwrap().is_empty());
+    let verdicts = core.list_monitor_verdicts(|_| true).await.unwrap();
+    assert_eq!(verdicts.len(), 1);
+    assert_eq!(verdicts[0].rule, "dlp:cards");
+    assert_eq!(verdicts[0].verdict, MonitorAction::Reject);
+    assert_eq!(verdicts[0].disposition, Some(Disposition::Delivered));
+    assert_eq!(verdicts[0].return_path, "john@doe.org");
+    assert_eq!(verdicts[0].recipients, vec!["bill@foobar.org".to_string()]);
+    assert_eq!(verdicts[0].queue_id, Some(message.id));
+    assert_eq!(
+        core.read_monitor_verdict(verdicts[0].id).await.as_ref(),
+        Some(&verdicts[0])
+    );
+    qr.clear_queue(&core).await;
+
+    // Rules that are not monitored are still enforced
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: keyword\r\n\r\nAbout {KEYWORD}.\r\n"),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_QUARANTINED, 0);
+    assert_eq!(core.list_monitor_verdicts(|_| true).await.unwrap().len(), 1);
+    assert_eq!(core.list_dlp_incidents(|_| true).await.unwrap().len(), 1);
+    qr.clear_queue(&core).await;
+
+    // The report compares monitor verdicts against the actual dispositions
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: card\r\n\r\nCard {CARD}.\r\n"),
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+    let report = core.monitor_report(|_| true).await.unwrap();
+    let summary = report.get("dlp:cards").unwrap();
+    assert_eq!(summary.total, 2);
+    assert_eq!(summary.reject, 2);
+    assert_eq!(summary.delivered, 2);
+    assert_eq!(summary.matched, 0);
+    assert_eq!(report.len(), 1);
+}
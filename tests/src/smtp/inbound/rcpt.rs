@@ -21,19 +21,27 @@
  * for more details.
 */
 
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use directory::core::config::ConfigDirectory;
+use sieve::runtime::Variable;
 use smtp_proto::{RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
 use store::Store;
-use utils::config::{if_block::IfBlock, Config};
+use utils::config::{if_block::IfBlock, ipmask::IpAddrMask, utils::ParseValue, Config};
 
 use crate::smtp::{
     inbound::dummy_stores,
-    session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    session::{DummyIo, TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::SessionThrottle,
+    core::{Session, State, SMTP},
+    scripts::plugins::lookup::VariableWrapper,
 };
-use smtp::core::{Session, State, SMTP};
 
 const DIRECTORY: &str = r#"
 [storage]
@@ -96,12 +104,23 @@ async fn rcpt() {
     config.errors_wait = r#"[{if = "remote_ip = '10.0.0.1'", then = '5ms'},
     {else = '1s'}]"#
         .parse_if();
-    core.session.config.throttle.rcpt_to = r#"[[throttle]]
+    let config = &mut core.session.config.responses;
+    config.user_unknown = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'Unknown user, see {support-url} (ref {tracking-id})'"},
+    {else = false}]"#
+        .parse_if();
+    config.support_url = "'https://help.foobar.org'".parse_if();
+    core.session
+        .config
+        .throttle
+        .store(Arc::new(SessionThrottle {
+            rcpt_to: r#"[[throttle]]
     match = "remote_ip = '10.0.0.1'"
     key = 'sender'
     rate = '2/1s'
     "#
-    .parse_throttle();
+            .parse_throttle(),
+            ..Default::default()
+        }));
 
     // RCPT without MAIL FROM
     let mut session = Session::test(core);
@@ -121,8 +140,11 @@ async fn rcpt() {
         .unwrap();
     session.response().assert_code("501 5.5.4");
 
-    // Send to non-existing user
-    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+    // Send to non-existing user, using the custom response text
+    session
+        .cmd("RCPT TO:<tom@foobar.org>", "550 5.1.2")
+        .await
+        .assert_contains("550 5.1.2 Unknown user, see https://help.foobar.org (ref ");
 
     // Exceeding max number of errors
     session
@@ -174,3 +196,150 @@ async fn rcpt() {
     assert!((rcpt.flags & (RCPT_NOTIFY_DELAY | RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE)) != 0);
     assert_eq!(rcpt.dsn_info.as_ref().unwrap(), "Jane.Doe@Foobar.org");
 }
+
+#[tokio::test]
+async fn rcpt_harvesting() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_rcpt_harvesting");
+
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+    config.errors_max = IfBlock::new(100);
+    config.errors_wait = IfBlock::new(Duration::from_millis(10));
+    config.cache_ttl = Duration::from_secs(3600);
+    config.tarpit_after = IfBlock::new(2);
+    config.tarpit_max_delay = IfBlock::new(Duration::from_millis(30));
+    config.honeypot.enable = "rcpt_domain = 'spamtrap.org'".parse_if();
+    config.honeypot.penalize_after = 2;
+    config.honeypot.ban_after = 3;
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("john@example.net", "250").await;
+
+    // Unknown recipients are cached
+    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+    assert!(session
+        .core
+        .session
+        .rcpt_cache
+        .get(&"tom@foobar.org".to_string())
+        .is_some());
+    assert!(session
+        .core
+        .session
+        .rcpt_cache
+        .get(&"jane@foobar.org".to_string())
+        .is_none());
+
+    // Responses are delayed once the tarpit threshold is exceeded
+    session.rcpt_to("sam@foobar.org", "550 5.1.2").await;
+    let time = Instant::now();
+    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+    session.rcpt_to("bob@foobar.org", "550 5.1.2").await;
+    assert!(time.elapsed() >= Duration::from_millis(70));
+    assert_eq!(session.data.rcpt_unknown, 4);
+
+    // Honeypot recipients are accepted and the message discarded
+    session.rcpt_to("trap1@spamtrap.org", "250").await;
+    assert!(session.data.rcpt_to.is_empty());
+    assert_eq!(session.data.rcpt_honeypot, 1);
+    session.data("test:no_msgid", "250").await;
+    qr.assert_no_events();
+
+    // A single honeypot address, even if repeated, does not penalize the sender
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("trap1@spamtrap.org", "250").await;
+    assert_eq!(reputation(&session, "i:127.0.0.1").await, None);
+
+    // Hitting a second honeypot address penalizes the IP and the (unverified) domain
+    session.rcpt_to("trap2@spamtrap.org", "250").await;
+    let expected = Some(Variable::from(vec![
+        Variable::Float(10.0),
+        Variable::Integer(1),
+    ]));
+    assert_eq!(reputation(&session, "i:127.0.0.1").await, expected);
+    assert_eq!(reputation(&session, "d:_example.net").await, expected);
+    assert_eq!(reputation(&session, "d:example.net").await, None);
+    assert!(!session
+        .core
+        .shared
+        .default_directory
+        .blocked_ips
+        .is_blocked(&session.data.remote_ip));
+
+    // The IP is banned once the ban threshold is reached
+    session.rcpt_to("trap3@spamtrap.org", "250").await;
+    assert!(session
+        .core
+        .shared
+        .default_directory
+        .blocked_ips
+        .is_blocked(&session.data.remote_ip));
+}
+
+#[tokio::test]
+async fn rcpt_greylist() {
+    let mut core = SMTP::test();
+    core.init_test_queue("smtp_rcpt_greylist");
+
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+    config.greylist.enable = "is_empty(authenticated_as)".parse_if();
+    config.greylist.delay = Duration::from_secs(2);
+    config.greylist.whitelist_networks =
+        vec![IpAddrMask::parse_value("test", "10.0.0.0/8").unwrap()];
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "192.168.1.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx1.foobar.org").await;
+    session.mail_from("bill@example.net", "250").await;
+
+    // First delivery attempts are deferred, as well as retries before the delay
+    session.rcpt_to("jane@foobar.org", "451 4.7.1").await;
+    session.rcpt_to("jane@foobar.org", "451 4.7.1").await;
+    assert!(session.data.rcpt_to.is_empty());
+
+    // Unknown recipients are rejected before greylisting
+    session.rcpt_to("tom@foobar.org", "550 5.1.2").await;
+
+    // Each triplet is greylisted separately
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session.rcpt_to("john@foobar.org", "451 4.7.1").await;
+    session.rset().await;
+    session.mail_from("mike@example.net", "250").await;
+    session.rcpt_to("jane@foobar.org", "451 4.7.1").await;
+
+    // Whitelisted networks are not greylisted
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.rcpt_to("john@foobar.org", "250").await;
+}
+
+pub async fn reputation(session: &Session<DummyIo>, token: &str) -> Option<Variable> {
+    session
+        .core
+        .shared
+        .default_lookup_store
+        .key_get::<VariableWrapper>(token.as_bytes().to_vec())
+        .await
+        .unwrap()
+        .map(|value| value.into_inner())
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{session::TestSession, TestConfig, TestSMTP};
+use smtp::{
+    config::session::ConfigSession,
+    core::{eval::V_SENDER, Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[session.data.risk]
+recipients.min = 3
+recipients.factor = 2.0
+recipients.score = 40
+urls.patterns = ["(?i)/wp-(admin|includes)/.+\\.php", "(?i)secure-?login.+verify"]
+urls.score = 30
+threshold.throttle = 30
+threshold.suspend = 60
+"#;
+
+const PHISHING: &str = "https://shop.example.com/wp-includes/js/login.php?id=1";
+
+#[tokio::test]
+async fn sender_risk() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_sender_risk_test");
+    let mut risk = Config::new(CONFIG)
+        .unwrap()
+        .parse_sender_risk(&[V_SENDER])
+        .unwrap();
+    risk.enable = IfBlock::new(true);
+
+    // Test URL pattern matching
+    for (text, expected) in [
+        (format!("Please log in at {PHISHING}"), 1),
+        (
+            format!("<a href=\"{PHISHING}\">Log in</a> or <a href='http://secure-login.example.net/verify'>here</a>"),
+            2,
+        ),
+        ("Visit https://www.example.com/about for details.".to_string(), 0),
+        ("No links here, not even http or https.".to_string(), 0),
+    ] {
+        let message = format!(
+            "From: john@doe.org\r\nContent-Type: text/html\r\nSubject: test\r\n\r\n{text}\r\n"
+        );
+        assert_eq!(risk.count_phishing_urls(message.as_bytes()), expected, "{text}");
+    }
+
+    // Enable sender risk assessment
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.risk = risk;
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.authenticated_as = "john".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Regular messages are accepted
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nSubject: hi\r\n\r\nHello.\r\n",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Phishing URLs throttle the account
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: hi\r\n\r\nLog in at {PHISHING}\r\n"),
+            "451 4.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // A recipient spike combined with phishing URLs suspends the account
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org", "jane@foobar.org", "mike@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: hi\r\n\r\nLog in at {PHISHING}\r\n"),
+            "550 5.7.1",
+        )
+        .await;
+    assert!(core.is_sending_suspended("john").await);
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nSubject: hi\r\n\r\nHello.\r\n",
+            "550 5.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Lift the suspension, the recipient spike alone throttles the account
+    assert!(core.unsuspend_sending("john").await.unwrap());
+    assert!(!core.unsuspend_sending("john").await.unwrap());
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "From: john@doe.org\r\nSubject: hi\r\n\r\nHello.\r\n",
+            "451 4.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Unauthenticated senders are not assessed
+    session.data.authenticated_as.clear();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("From: john@doe.org\r\nSubject: hi\r\n\r\nLog in at {PHISHING}\r\n"),
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+}
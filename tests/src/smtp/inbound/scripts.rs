@@ -22,7 +22,13 @@
 */
 
 use core::panic;
-use std::{fmt::Write, fs, path::PathBuf, sync::Arc};
+use std::{
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::smtp::{
     inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
@@ -30,6 +36,7 @@ use crate::smtp::{
     TestConfig, TestSMTP,
 };
 use directory::core::config::ConfigDirectory;
+use mail_auth::{dmarc::Dmarc, spf::Spf};
 use smtp::{
     config::{scripts::ConfigSieve, session::ConfigSession, ConfigContext},
     core::{eval::V_REMOTE_IP, Session, SMTP},
@@ -69,6 +76,7 @@ from-addr = "sieve@foobar.org"
 return-path = ""
 hostname = "mx.foobar.org"
 sign = ["rsa"]
+seal = "ed"
 
 [sieve.trusted.limits]
 redirects = 3
@@ -144,6 +152,15 @@ async fn sieve_scripts() {
         .directories;
     let pipes = config.parse_pipes(&[V_REMOTE_IP]).unwrap();
     core.sieve = config.parse_sieve(&mut ctx).unwrap();
+    assert!(
+        Config::new("[sieve.trusted]\nhostname = \"mx.foobar.org\"\n")
+            .unwrap()
+            .parse_sieve(&mut ctx)
+            .unwrap()
+            .seal
+            .is_none(),
+        "ARC sealing of forwarded messages is opt-in"
+    );
     core.shared.signers = ctx.signers;
     core.shared.scripts = ctx.scripts.clone();
     let config = &mut core.session.config;
@@ -154,6 +171,18 @@ async fn sieve_scripts() {
     config.data.script = IfBlock::new("stage_data".to_string());
     config.rcpt.relay = IfBlock::new(true);
     config.data.pipe_commands = pipes;
+
+    // Add SPF and DMARC records, their results are preserved when sealing
+    core.resolvers.dns.txt_add(
+        "example.net",
+        Spf::parse(b"v=spf1 ip4:10.0.0.5 -all").unwrap(),
+        Instant::now() + Duration::from_secs(3600),
+    );
+    core.resolvers.dns.txt_add(
+        "_dmarc.football.example.com",
+        Dmarc::parse(b"v=DMARC1; p=none").unwrap(),
+        Instant::now() + Duration::from_secs(3600),
+    );
     let core = Arc::new(core);
 
     // Build session
@@ -387,7 +416,15 @@ async fn sieve_scripts() {
         .assert_contains("To: Suzie Q <suzie@shopping.example.net>")
         .assert_contains("Subject: Is dinner ready?")
         .assert_contains("Message-ID: <20030712040037.46341.5F8J@football.example.com>")
-        .assert_contains("From: Joe SixPack <joe@football.example.com>");
+        .assert_contains("From: Joe SixPack <joe@football.example.com>")
+        .assert_contains("ARC-Seal: i=1; a=ed25519-sha256; s=ed; d=example.com; cv=none;")
+        .assert_contains(
+            "ARC-Message-Signature: i=1; a=ed25519-sha256; s=ed; d=example.com; c=relaxed/simple;",
+        )
+        .assert_contains("ARC-Authentication-Results: i=1; mx.foobar.org")
+        .assert_contains("spf=pass")
+        .assert_contains("smtp.mailfrom=test@example.net")
+        .assert_contains("dmarc=");
     qr.assert_no_events();
 
     // Test pipes
@@ -25,8 +25,9 @@ use std::time::{Duration, Instant};
 
 use directory::core::config::ConfigDirectory;
 use mail_auth::{
-    common::{parse::TxtRecordParser, verify::DomainKey},
+    common::{headers::HeaderWriter, parse::TxtRecordParser, verify::DomainKey},
     spf::Spf,
+    AuthenticatedMessage, DkimResult,
 };
 use store::Store;
 use utils::config::{if_block::IfBlock, Config};
@@ -37,8 +38,9 @@ use crate::smtp::{
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{auth::ConfigAuth, ConfigContext, VerifyStrategy},
+    config::{auth::ConfigAuth, ConfigContext, DkimSigner, VerifyStrategy},
     core::{Session, SMTP},
+    inbound::{message_chunks, DKIM_CHUNK_SIZE},
 };
 
 const SIGNATURES: &str = "
@@ -217,6 +219,83 @@ async fn sign_and_seal() {
         );
 }
 
+#[tokio::test]
+async fn sign_large_message() {
+    let core = SMTP::test();
+    core.resolvers.dns.txt_add(
+        "ed._domainkey.example.com",
+        DomainKey::parse(
+            concat!(
+                "v=DKIM1; k=ed25519; ",
+                "p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+            )
+            .as_bytes(),
+        )
+        .unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+    let ctx = ConfigContext::new(&[]).parse_signatures();
+
+    // Build a 5MB message with whitespace runs and empty lines, so that the
+    // body slices end in the middle of sequences collapsed by canonicalization
+    let mut message =
+        b"From: bill@example.com\r\nTo: jdoe@example.com\r\nSubject: Large message\r\n\r\n"
+            .to_vec();
+    let body_offset = message.len();
+    let mut line_num = 0;
+    while message.len() < 5 * 1024 * 1024 {
+        message
+            .extend_from_slice(format!("Line {line_num} \t  with   whitespace \t \r\n").as_bytes());
+        if line_num % 97 == 0 {
+            message.extend_from_slice(b"\r\n\r\n");
+        }
+        line_num += 1;
+    }
+    message.extend_from_slice(b"\r\n\r\n\r\n");
+    assert!((body_offset..message.len())
+        .step_by(DKIM_CHUNK_SIZE)
+        .skip(1)
+        .any(|pos| matches!(message[pos - 1], b' ' | b'\t' | b'\r')));
+
+    // The header section is kept whole and the body is split in slices
+    let chunks = message_chunks(&message).collect::<Vec<_>>();
+    assert_eq!(chunks[0].len(), body_offset);
+    assert!(chunks.len() > 80);
+    assert_eq!(chunks.concat(), message);
+
+    // Hashing the body in slices produces the same body hash
+    for name in ["rsa", "ed"] {
+        let signer = ctx.signers.get(name).unwrap();
+        let chunked = signer.sign(&message).unwrap().to_header();
+        let single = match signer.as_ref() {
+            DkimSigner::RsaSha256(signer) => signer.sign_chained([message.as_slice()]),
+            DkimSigner::Ed25519Sha256(signer) => signer.sign_chained([message.as_slice()]),
+        }
+        .unwrap()
+        .to_header();
+        assert_eq!(body_hash(&chunked), body_hash(&single), "{name}");
+
+        // Signatures over the streamed body verify
+        if name == "ed" {
+            let signed = [chunked.as_bytes(), &message].concat();
+            let auth_message = AuthenticatedMessage::parse(&signed).unwrap();
+            let output = core.resolvers.dns.verify_dkim(&auth_message).await;
+            assert_eq!(output.len(), 1);
+            assert!(matches!(output[0].result(), DkimResult::Pass));
+        }
+    }
+}
+
+fn body_hash(header: &str) -> String {
+    header
+        .split(';')
+        .find_map(|tag| tag.trim().strip_prefix("bh="))
+        .unwrap()
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .collect()
+}
+
 pub trait TextConfigContext<'x> {
     fn parse_signatures(self) -> ConfigContext<'x>;
 }
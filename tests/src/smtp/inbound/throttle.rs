@@ -21,10 +21,13 @@
  * for more details.
 */
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use crate::smtp::{session::TestSession, ParseTestConfig, TestConfig, TestSMTP};
-use smtp::core::{Session, SessionAddress, SMTP};
+use smtp::{
+    config::SessionThrottle,
+    core::{Session, SessionAddress, SMTP},
+};
 
 #[tokio::test]
 async fn throttle_inbound() {
@@ -39,24 +42,28 @@ async fn throttle_inbound() {
 
     let mut core = SMTP::test();
     let _qr = core.init_test_queue("smtp_inbound_throttle");
-    let config = &mut core.session.config;
-    config.throttle.connect = r#"[[throttle]]
+    core.session
+        .config
+        .throttle
+        .store(Arc::new(SessionThrottle {
+            connect: r#"[[throttle]]
     match = "remote_ip = '10.0.0.1'"
     key = 'remote_ip'
     concurrency = 2
     rate = '3/1s'
     "#
-    .parse_throttle();
-    config.throttle.mail_from = r#"[[throttle]]
+            .parse_throttle(),
+            mail_from: r#"[[throttle]]
     key = 'sender'
     rate = '2/1s'
     "#
-    .parse_throttle();
-    config.throttle.rcpt_to = r#"[[throttle]]
+            .parse_throttle(),
+            rcpt_to: r#"[[throttle]]
     key = ['remote_ip', 'rcpt']
     rate = '2/1s'
     "#
-    .parse_throttle();
+            .parse_throttle(),
+        }));
 
     // Test connection concurrency limit
     let mut session = Session::test(core);
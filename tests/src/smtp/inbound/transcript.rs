@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use utils::{
+    config::Config,
+    listener::{transcript::TranscriptStream, SessionStream},
+};
+
+use crate::smtp::{make_temp_dir, session::DummyIo};
+
+const CONFIG: &str = r#"
+[server.transcript]
+enable = true
+path = "{TMP}"
+listeners = ["smtp"]
+remote-ips = ["10.0.0.0/8"]
+"#;
+
+#[tokio::test]
+async fn transcript() {
+    let temp_dir = make_temp_dir("smtp_transcript_test", true);
+    let transcripts =
+        Config::new(&CONFIG.replace("{TMP}", temp_dir.temp_dir.as_path().to_str().unwrap()))
+            .unwrap()
+            .parse_transcripts()
+            .unwrap();
+
+    // Sessions not matching the filter are not recorded
+    for (listener, ip) in [("smtp", "192.168.1.1"), ("imap", "10.0.0.1")] {
+        assert!(transcripts
+            .start(listener, ip.parse().unwrap(), 1234)
+            .is_none());
+    }
+
+    // Record a session
+    let mut stream = TranscriptStream::new(
+        DummyIo {
+            tx_buf: vec![],
+            rx_buf: vec![],
+            tls: false,
+        },
+        transcripts.start("smtp", "10.0.0.1".parse().unwrap(), 1234),
+    );
+    let mut buf = vec![0u8; 1024];
+    for (command, reply) in [
+        ("EHLO mx.foobar.org\r\n", "250 mx.example.org\r\n"),
+        (
+            "AUTH PLAIN AGpvaG4Ac2VjcmV0\r\n",
+            "535 5.7.8 Authentication credentials invalid.\r\n",
+        ),
+        ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+        ("am9obg==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+        ("c2VjcmV0\r\n", "235 2.7.0 Authentication succeeded.\r\n"),
+        ("a1 LOGIN john secret\r\n", "a1 OK\r\n"),
+        ("a2 LOGIN john {14+}\r\nliteral-secret\r\n", "a2 OK\r\n"),
+        (
+            "a3 LOGIN {4+}\r\njohn {14+}\r\nliteral-secret\r\n",
+            "a3 OK\r\n",
+        ),
+        (
+            "AUTHENTICATE \"PLAIN\" {20+}\r\nAGpvaG4AbGl0ZXJhbA==\r\n",
+            "OK\r\n",
+        ),
+        (
+            "GET / HTTP/1.1\r\nAuthorization: Basic am9objpzZWNyZXQ=\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\n",
+        ),
+        ("QUIT\r\n", "221 2.0.0 Bye.\r\n"),
+    ] {
+        stream.get_mut().rx_buf = command.as_bytes().to_vec();
+        assert_eq!(stream.read(&mut buf).await.unwrap(), command.len());
+        stream.write_all(reply.as_bytes()).await.unwrap();
+    }
+    assert!(stream.take_transcript().is_some());
+    assert!(stream.take_transcript().is_none());
+    drop(stream);
+
+    // Credentials should be redacted
+    let files = transcripts.list().unwrap();
+    assert_eq!(files.len(), 1, "{files:?}");
+    let contents = String::from_utf8(transcripts.read(&files[0].0).unwrap().unwrap()).unwrap();
+    for expected in [
+        "C: EHLO mx.foobar.org",
+        "C: AUTH PLAIN [redacted]",
+        "C: AUTH LOGIN",
+        "C: [redacted]",
+        "C: a1 LOGIN john [redacted]",
+        "C: a2 LOGIN john [redacted]",
+        "C: a3 LOGIN {4+}",
+        "C: AUTHENTICATE \"PLAIN\" [redacted]",
+        "C: Authorization: [redacted]",
+        "C: QUIT",
+        "S: 221 2.0.0 Bye.",
+        "S: 334 VXNlcm5hbWU6",
+        "S: 235 2.7.0 Authentication succeeded.",
+        "# Session closed",
+    ] {
+        assert!(contents.contains(expected), "{expected:?} in {contents}");
+    }
+    for secret in [
+        "AGpvaG4Ac2VjcmV0",
+        "c2VjcmV0",
+        "am9objpzZWNyZXQ=",
+        " secret",
+        "literal-secret",
+        "AGpvaG4AbGl0ZXJhbA==",
+    ] {
+        assert!(!contents.contains(secret), "{secret:?} in {contents}");
+    }
+
+    // Path traversal is not allowed
+    assert!(transcripts.read("../transcript.txt").is_none());
+
+    // Disable recording at runtime
+    transcripts.set_filter(false, vec![], vec![]).unwrap();
+    assert!(transcripts
+        .start("smtp", "10.0.0.1".parse().unwrap(), 1234)
+        .is_none());
+    assert!(transcripts
+        .set_filter(true, vec![], vec!["invalid".to_string()])
+        .is_err());
+}
@@ -22,13 +22,14 @@
 */
 
 use directory::core::config::ConfigDirectory;
+use sieve::runtime::Variable;
 use store::Store;
 use utils::config::{if_block::IfBlock, Config};
 
 use crate::smtp::{
-    inbound::dummy_stores,
+    inbound::{dummy_stores, rcpt::reputation},
     session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::core::{Session, SMTP};
 
@@ -121,3 +122,71 @@ async fn vrfy_expn() {
     // Non-existent EXPN
     session.cmd("EXPN procurement", "550 5.1.2").await;
 }
+
+#[tokio::test]
+async fn vrfy_expn_harvesting() {
+    let mut core = SMTP::test();
+    core.init_test_queue("smtp_vrfy_harvesting");
+
+    core.shared.directories = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap()
+        .directories;
+    let config = &mut core.session.config.rcpt;
+    config.directory = IfBlock::new("local".to_string());
+
+    let config = &mut core.session.config.extensions;
+    config.vrfy = "remote_ip = '10.0.0.1'".parse_if();
+    config.expn = config.vrfy.clone();
+    config.enumeration.rate = "\"[3, 1h]\"".parse_if();
+    config.enumeration.honey = "remote_ip = '10.0.0.2'".parse_if();
+    config.enumeration.score = 5.0;
+
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Requests within the rate are answered
+    session.cmd("VRFY john", "250 john@foobar.org").await;
+    session.cmd("VRFY robert", "550 5.1.2").await;
+    session.cmd("EXPN sales@foobar.org", "250").await;
+    assert_eq!(reputation(&session, "i:10.0.0.1").await, None);
+
+    // Once the rate is exceeded requests are rejected and the IP penalized once
+    session.cmd("VRFY jane", "451 4.4.5").await;
+    session.cmd("EXPN sales@foobar.org", "451 4.4.5").await;
+    assert_eq!(
+        reputation(&session, "i:10.0.0.1").await,
+        Some(Variable::from(vec![
+            Variable::Float(5.0),
+            Variable::Integer(1),
+        ]))
+    );
+
+    // Honey responses accept any address, whether VRFY is disabled or rate limited
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.data.enumeration_limited = false;
+    session.eval_session_params().await;
+    session
+        .cmd("VRFY robert@foobar.org", "250 <robert@foobar.org>")
+        .await;
+    session
+        .cmd(
+            "EXPN procurement@foobar.org",
+            "250 <procurement@foobar.org>",
+        )
+        .await;
+    session
+        .cmd("VRFY <tom@foobar.org>", "250 <tom@foobar.org>")
+        .await;
+    assert_eq!(reputation(&session, "i:10.0.0.2").await, None);
+    session
+        .cmd("VRFY jane@foobar.org", "250 <jane@foobar.org>")
+        .await;
+    assert!(reputation(&session, "i:10.0.0.2").await.is_some());
+}
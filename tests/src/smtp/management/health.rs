@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use directory::{backend::internal::manage::ManageDirectory, DirectoryInner};
+use mail_auth::{common::parse::TxtRecordParser, dmarc::Dmarc, spf::Spf, MX};
+use smtp::{
+    core::SMTP,
+    reporting::health::{
+        DnsCheck, HEALTH_BOUNCED, HEALTH_COMPLAINTS, HEALTH_DMARC_FAIL, HEALTH_DMARC_PASS,
+        HEALTH_SENT, HEALTH_TLS_FAILURE,
+    },
+};
+
+use crate::smtp::TestConfig;
+
+#[tokio::test]
+async fn domain_health() {
+    let mut core = SMTP::test();
+    if let DirectoryInner::Internal(store) = &core.shared.default_directory.store {
+        store.create_domain("example.org").await.unwrap();
+    }
+    core.resolvers.dns.mx_add(
+        "example.org",
+        vec![MX {
+            exchanges: vec!["mx.example.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.txt_add(
+        "example.org",
+        Spf::parse(b"v=spf1 mx -all").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.txt_add(
+        "_dmarc.example.org",
+        Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Only local domains are tracked
+    for _ in 0..2 {
+        core.record_domain_health(
+            "Example.org",
+            &[
+                (HEALTH_SENT, 9),
+                (HEALTH_BOUNCED, 1),
+                (HEALTH_COMPLAINTS, 1),
+                (HEALTH_DMARC_PASS, 3),
+                (HEALTH_DMARC_FAIL, 1),
+                (HEALTH_TLS_FAILURE, 2),
+            ],
+        )
+        .await;
+    }
+    core.record_domain_health("foreign.org", &[(HEALTH_SENT, 5)])
+        .await;
+
+    let health = core.domain_health("example.org", 7, &[]).await.unwrap();
+    assert_eq!(health.outbound.sent, 18);
+    assert_eq!(health.outbound.bounced, 2);
+    assert_eq!(health.outbound.complaints, 2);
+    assert_eq!(health.outbound.bounce_rate, Some(0.1));
+    assert_eq!(health.dmarc.pass, 6);
+    assert_eq!(health.dmarc.fail, 2);
+    assert_eq!(health.dmarc.pass_rate, Some(0.75));
+    assert_eq!(health.tls.success, 0);
+    assert_eq!(health.tls.failure, 4);
+    assert_eq!(health.queue.messages, 0);
+    assert!(matches!(health.dns.mx, DnsCheck::Valid));
+    assert!(matches!(health.dns.spf, DnsCheck::Valid));
+    assert!(matches!(health.dns.dmarc, DnsCheck::Valid));
+
+    let health = core.domain_health("foreign.org", 7, &[]).await.unwrap();
+    assert_eq!(health.outbound.sent, 0);
+    assert_eq!(health.outbound.bounce_rate, None);
+}
@@ -26,8 +26,10 @@ use std::time::Duration;
 use reqwest::header::AUTHORIZATION;
 use serde::{de::DeserializeOwned, Deserialize};
 
+pub mod health;
 pub mod queue;
 pub mod report;
+pub mod simulate;
 
 #[derive(Deserialize)]
 #[serde(untagged)]
@@ -35,8 +35,11 @@ use store::Store;
 use utils::config::{if_block::IfBlock, Config, ServerProtocol};
 
 use crate::smtp::{
-    inbound::dummy_stores, management::send_manage_request, outbound::start_test_server,
-    session::TestSession, TestConfig, TestSMTP,
+    inbound::dummy_stores,
+    management::{send_manage_request, send_manage_request_raw},
+    outbound::start_test_server,
+    session::TestSession,
+    TestConfig, TestSMTP,
 };
 use smtp::{
     core::{management::Message, Session, SMTP},
@@ -288,6 +291,16 @@ async fn manage_queue() {
             format!("/admin/queue/list?after={test_search}"),
             vec!["d", "e", "f", "c"],
         ),
+        (
+            "/admin/queue/list?domain=example1.org".to_string(),
+            vec!["a"],
+        ),
+        ("/admin/queue/list?domain=com".to_string(), vec!["c"]),
+        ("/admin/queue/list?domain=ample1.org".to_string(), vec![]),
+        (
+            "/admin/queue/list?domain=example2.com&status=scheduled".to_string(),
+            vec!["c"],
+        ),
     ] {
         let expected_ids = HashSet::from_iter(expected_ids.into_iter().map(|s| s.to_string()));
         let ids = send_manage_request::<Vec<QueueId>>(&query)
@@ -300,6 +313,33 @@ async fn manage_queue() {
         assert_eq!(ids, expected_ids, "failed for {query}");
     }
 
+    assert_eq!(
+        send_manage_request::<Vec<QueueId>>("/admin/queue/list?status=pending")
+            .await
+            .unwrap()
+            .unwrap_error()
+            .0,
+        "bad-parameters"
+    );
+
+    // Fetch raw message
+    let raw_message =
+        send_manage_request_raw(&format!("/admin/queue/raw?id={}", id_map.get("b").unwrap()))
+            .await
+            .unwrap();
+    assert!(
+        raw_message.contains("Subject: Is dinner ready?"),
+        "{raw_message}"
+    );
+    assert_eq!(
+        send_manage_request::<String>("/admin/queue/raw?id=1234")
+            .await
+            .unwrap()
+            .unwrap_error()
+            .0,
+        "not-found"
+    );
+
     // Retry delivery
     assert_eq!(
         send_manage_request::<Vec<bool>>(&format!(
@@ -42,7 +42,7 @@ use crate::smtp::{
     inbound::dummy_stores, management::send_manage_request, outbound::start_test_server, TestConfig,
 };
 use smtp::{
-    config::AggregateFrequency,
+    config::{AggregateFrequency, ReportConfig},
     core::{management::Report, SMTP},
     reporting::{scheduler::SpawnReport, DmarcEvent, TlsEvent},
 };
@@ -75,9 +75,10 @@ async fn manage_reports() {
 
     // Start reporting service
     let mut core = SMTP::test();
-    let config = &mut core.report.config;
+    let mut config = ReportConfig::test();
     config.dmarc_aggregate.max_size = IfBlock::new(1024);
     config.tls.max_size = IfBlock::new(1024);
+    core.report.config.store(Arc::new(config));
     let directory = Config::new(DIRECTORY)
         .unwrap()
         .parse_directory(&dummy_stores(), Store::default())
@@ -0,0 +1,342 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use directory::core::config::ConfigDirectory;
+use store::{write::now, Store};
+use utils::config::{if_block::IfBlock, Config, ServerProtocol};
+
+use crate::smtp::{
+    inbound::dummy_stores, management::send_manage_request, outbound::start_test_server,
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{BatvKey, SessionThrottle},
+    core::{
+        simulate::{SimulatedRule, SimulatedStage, Simulation},
+        SMTP,
+    },
+    queue::dsn::batv_sign,
+};
+
+const DIRECTORY: &str = r#"
+[storage]
+lookup = "dummy"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "admin"
+type = "admin"
+description = "Superuser"
+secret = "secret"
+member-of = ["superusers"]
+
+[[directory."local".principals]]
+name = "john"
+description = "John Doe"
+secret = "secret"
+email = "john@foobar.org"
+
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn manage_simulate() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::DEBUG)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+    let _qr = core.init_test_queue("smtp_manage_simulate");
+    let directory = Config::new(DIRECTORY)
+        .unwrap()
+        .parse_directory(&dummy_stores(), Store::default())
+        .await
+        .unwrap();
+    core.shared.default_directory = directory.directories.get("local").unwrap().clone();
+    core.shared.directories = directory.directories;
+    let config = &mut core.session.config;
+    config.rcpt.directory = IfBlock::new("local".to_string());
+    config.rcpt.relay = r#"[{if = "remote_ip = '10.0.0.1'", then = false},
+    {else = true}]"#
+        .parse_if();
+    config.auth.require = r#"[{if = "remote_ip = '10.0.0.2'", then = true},
+    {else = false}]"#
+        .parse_if();
+    config.rcpt.greylist.enable = "remote_ip = '10.0.0.4'".parse_if();
+    config.rcpt.greylist.delay = Duration::from_secs(60);
+    config.rcpt.honeypot.enable = "rcpt_domain = 'spamtrap.org'".parse_if();
+    config.rcpt.errors_wait = IfBlock::new(Duration::from_millis(10));
+    config.rcpt.tarpit_after = IfBlock::new(1);
+    config.rcpt.tarpit_max_delay = IfBlock::new(Duration::from_secs(1));
+    config.throttle.store(Arc::new(SessionThrottle {
+        rcpt_to: r#"[[throttle]]
+    match = "remote_ip = '10.0.0.1'"
+    key = 'sender'
+    rate = '2/1s'
+    "#
+        .parse_throttle(),
+        ..Default::default()
+    }));
+    let config = &mut core.session.config.responses;
+    config.user_unknown = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'Unknown user, see {support-url} (ref {tracking-id})'"},
+    {else = false}]"#
+        .parse_if();
+    config.policy_reject = "'Relaying denied by {hostname}'".parse_if();
+    config.support_url = "'https://help.foobar.org'".parse_if();
+    core.queue.config.dsn.batv.keys = vec![BatvKey {
+        id: 0,
+        secret: b"secret".to_vec(),
+    }];
+    core.queue.config.dsn.batv.verify = IfBlock::new(true);
+    core.session.rcpt_cache.insert(
+        "tom@foobar.org".to_string(),
+        (),
+        Instant::now() + Duration::from_secs(3600),
+    );
+    let core = Arc::new(core);
+    let _rx_manage = start_test_server(core.clone(), &[ServerProtocol::Http]);
+
+    // Missing remote IP
+    assert_eq!(
+        send_manage_request::<Simulation>("/admin/policy/simulate?helo=mx.foobar.org")
+            .await
+            .unwrap()
+            .unwrap_error()
+            .0,
+        "bad-parameters"
+    );
+
+    // Non-FQDN EHLO domains are rejected
+    let result = simulate("ip=10.0.0.1&helo=foobar&from=bill@example.org&to=john@foobar.org").await;
+    assert!(!result.accepted);
+    assert_eq!(result.stages.last().unwrap().stage, "ehlo");
+    assert!(result
+        .stages
+        .last()
+        .unwrap()
+        .response
+        .starts_with("550 5.5.0"));
+
+    // Local recipients are accepted, relaying is denied for 10.0.0.1
+    let result = simulate(
+        "ip=10.0.0.1&helo=mx.example.org&from=bill@example.org&to=john@foobar.org,jane@foobar.org&to=jane@example.net",
+    )
+    .await;
+    assert!(result.accepted);
+    let rcpts = result
+        .stages
+        .iter()
+        .filter(|stage| stage.stage == "rcpt")
+        .map(|stage| {
+            (
+                stage.address.as_deref().unwrap(),
+                &stage.response[..3],
+                stage.throttles.len(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rcpts,
+        vec![
+            ("john@foobar.org", "250", 1),
+            ("jane@foobar.org", "550", 0),
+            ("jane@example.net", "550", 0)
+        ]
+    );
+    assert_eq!(result.stages.last().unwrap().stage, "data");
+
+    // Custom responses replace the default text
+    let responses = result
+        .stages
+        .iter()
+        .filter(|stage| stage.stage == "rcpt" && !stage.accepted)
+        .map(|stage| stage.response.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        responses,
+        vec![
+            "550 5.1.2 Unknown user, see https://help.foobar.org (ref {tracking-id})",
+            "550 5.1.2 Relaying denied by localhost"
+        ]
+    );
+
+    // Simulations do not consume rate limits
+    for _ in 0..3 {
+        let result =
+            simulate("ip=10.0.0.1&helo=mx.example.org&from=bill@example.org&to=john@foobar.org")
+                .await;
+        assert!(result.accepted);
+        assert!(result
+            .stages
+            .iter()
+            .flat_map(|stage| stage.throttles.iter())
+            .all(|throttle| throttle.allowed));
+    }
+
+    // Relaying is allowed from other addresses
+    let result =
+        simulate("ip=10.0.0.3&helo=mx.example.org&from=bill@example.org&to=jane@example.net").await;
+    assert!(result.accepted);
+
+    // Authentication is required for 10.0.0.2
+    let result =
+        simulate("ip=10.0.0.2&helo=mx.example.org&from=bill@example.org&to=john@foobar.org").await;
+    assert!(!result.accepted);
+    assert_eq!(
+        result.stages.last().unwrap().response,
+        "503 5.5.1 You must authenticate first."
+    );
+    let result = simulate(
+        "ip=10.0.0.2&helo=mx.example.org&auth=john&from=bill@example.org&to=jane@example.net",
+    )
+    .await;
+    assert!(!result.accepted);
+    assert!(result
+        .stages
+        .last()
+        .unwrap()
+        .response
+        .starts_with("501 5.5.4"));
+    let result = simulate(
+        "ip=10.0.0.2&helo=mx.example.org&auth=john&from=john@foobar.org&to=jane@example.net",
+    )
+    .await;
+    assert!(result.accepted);
+
+    // Honeypot hits are accepted, unknown recipients are read from the cache and tarpitted
+    let result = simulate(
+        "ip=10.0.0.3&helo=mx.example.org&from=bill@example.org&to=trap@spamtrap.org,tom@foobar.org,sam@foobar.org",
+    )
+    .await;
+    assert!(!result.accepted);
+    let rcpts = result
+        .stages
+        .iter()
+        .filter(|stage| stage.stage == "rcpt")
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rcpts
+            .iter()
+            .map(|stage| (stage.address.as_deref().unwrap(), &stage.response[..3]))
+            .collect::<Vec<_>>(),
+        vec![
+            ("trap@spamtrap.org", "250"),
+            ("tom@foobar.org", "550"),
+            ("sam@foobar.org", "550")
+        ]
+    );
+    let rule = |stage: &SimulatedStage, property: &str| {
+        stage
+            .rules
+            .iter()
+            .find(|rule| rule.property == property)
+            .map(|rule| rule.value.clone())
+    };
+    assert_eq!(rcpts[0].rules.last().unwrap().value, "true");
+    assert_eq!(rule(rcpts[0], "is_local_domain"), None);
+    assert_eq!(rule(rcpts[1], "rcpt_cache").as_deref(), Some("unknown"));
+    assert_eq!(rule(rcpts[1], "tarpit_delay"), None);
+    assert_eq!(rule(rcpts[2], "rcpt_cache"), None);
+    assert_eq!(rule(rcpts[2], "tarpit_delay").as_deref(), Some("20ms"));
+
+    // Bounces must be sent to tagged addresses
+    let signed = batv_sign(&core.queue.config.dsn.batv.keys[0], "john@foobar.org");
+    for (to, response) in [
+        (signed.clone(), "250 2.1.5 OK"),
+        (
+            "john@foobar.org".to_string(),
+            "550 5.7.1 Bounce address tag missing.",
+        ),
+        (
+            signed.replace("john@", "jane@"),
+            "550 5.7.1 Invalid bounce address tag.",
+        ),
+    ] {
+        let result = simulate(&format!(
+            "ip=10.0.0.3&helo=mx.example.org&from=&to={}",
+            to.replace('=', "%3D")
+        ))
+        .await;
+        let stage = result
+            .stages
+            .iter()
+            .find(|stage| stage.stage == "rcpt")
+            .unwrap();
+        assert_eq!(stage.response, response, "{to}");
+        assert_eq!(result.accepted, response.starts_with("250"), "{to}");
+    }
+
+    // Tagged addresses are accepted from any sender
+    let result = simulate(&format!(
+        "ip=10.0.0.3&helo=mx.example.org&from=bill@example.org&to={}",
+        signed.replace('=', "%3D")
+    ))
+    .await;
+    assert!(result.accepted);
+
+    // First-time triplets are greylisted without being recorded
+    for _ in 0..2 {
+        let result =
+            simulate("ip=10.0.0.4&helo=mx.example.org&from=bill@example.org&to=jane@example.net")
+                .await;
+        assert!(!result.accepted);
+        let stage = result.stages.last().unwrap();
+        assert_eq!(stage.stage, "rcpt");
+        assert!(stage.response.starts_with("451 4.7.1"), "{stage:?}");
+        assert!(stage.rules.contains(&SimulatedRule {
+            property: "greylist".to_string(),
+            value: "first-seen".to_string(),
+        }));
+    }
+
+    // Triplets seen before the delay pass greylisting
+    core.shared
+        .default_lookup_store
+        .key_set(
+            b"gt:10.0.0.4:bill@example.org:jane@example.net".to_vec(),
+            ((now() - 3600) as i64).to_be_bytes().to_vec(),
+            None,
+        )
+        .await
+        .unwrap();
+    let result =
+        simulate("ip=10.0.0.4&helo=mx.example.org&from=bill@example.org&to=jane@example.net").await;
+    assert!(result.accepted);
+}
+
+async fn simulate(query: &str) -> Simulation {
+    send_manage_request::<Simulation>(&format!("/admin/policy/simulate?{query}"))
+        .await
+        .unwrap()
+        .unwrap_data()
+}
@@ -23,6 +23,7 @@
 
 use std::{path::PathBuf, sync::Arc, time::Duration};
 
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
 use directory::{AddressMapping, Directory, DirectoryInner};
 use mail_auth::{
@@ -45,21 +46,26 @@ use smtp::{
         scripts::SieveContext,
         session::{ConfigSession, Mechanism},
         throttle::ConfigThrottle,
-        AggregateReport, ArcAuthConfig, Auth, Connect, Data, DkimAuthConfig, DmarcAuthConfig, Dsn,
-        Ehlo, Extensions, IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueConfig,
-        QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle,
-        Rcpt, Report, ReportAnalysis, ReportConfig, SessionConfig, SessionThrottle, SpfAuthConfig,
-        Throttle, VerifyStrategy,
+        AggregateReport, ArcAuthConfig, Auth, Batv, Connect, Data, DkimAuthConfig, Dlp,
+        DmarcAuthConfig, Dnsbl, DnsblAction, Dsn, Ehlo, Enumeration, Extensions, Greylist,
+        Honeypot, IpRevAuthConfig, IpRotation, Mail, MailAuthConfig, Milter, Monitor, Quarantine,
+        QueueConfig, QueueFairness, QueueIndexConfig, QueueLanes, QueueOutboundSourceIp,
+        QueueOutboundTimeout, QueueOutboundTls, QueuePool, QueueQuotas, QueueThrottle, Rcpt,
+        Report, ReportAnalysis, ReportConfig, Responses, SenderRisk, SessionConfig,
+        SessionThrottle, Shadow, SpfAuthConfig, Spool, Throttle, TrafficShaping, VerifyStrategy,
     },
     core::{
-        eval::*, throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, Resolvers, SessionCore,
-        Shared, SieveCore, TlsConnectors, SMTP,
+        eval::*,
+        http::{HttpClient, HttpRetry},
+        throttle::ThrottleKeyHasherBuilder,
+        QueueCore, ReportCore, Resolvers, SessionCore, Shared, SieveCore, TlsConnectors, SMTP,
     },
     outbound::dane::DnssecResolver,
 };
 use utils::{
     config::{if_block::IfBlock, utils::ConstantValue, Config},
     snowflake::SnowflakeIdGenerator,
+    worker::WorkerPool,
 };
 
 pub mod config;
@@ -169,10 +175,7 @@ impl TestConfig for SMTP {
     fn test() -> Self {
         let store = Store::default();
         SMTP {
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(num_cpus::get())
-                .build()
-                .unwrap(),
+            worker_pool: WorkerPool::new("smtp", num_cpus::get(), 0).unwrap(),
             session: SessionCore::test(),
             queue: QueueCore::test(),
             resolvers: Resolvers {
@@ -186,6 +189,7 @@ impl TestConfig for SMTP {
                     tlsa: LruCache::with_capacity(100),
                     mta_sts: LruCache::with_capacity(100),
                 },
+                overrides: Default::default(),
             },
             mail_auth: MailAuthConfig::test(),
             report: ReportCore::test(),
@@ -198,15 +202,31 @@ impl TestConfig for SMTP {
                 directories: Default::default(),
                 lookup_stores: Default::default(),
                 relay_hosts: Default::default(),
+                pipes: Default::default(),
+                footers: Default::default(),
+                journals: Default::default(),
+                http: HttpClient {
+                    client: reqwest::Client::new(),
+                    client_verified: reqwest::Client::new(),
+                    retry: HttpRetry {
+                        attempts: 1,
+                        backoff: Duration::from_millis(100),
+                        max_backoff: Duration::from_secs(1),
+                    },
+                },
+                transcripts: Default::default(),
                 default_directory: Arc::new(Directory {
                     store: DirectoryInner::Internal(store.clone()),
                     catch_all: AddressMapping::Disable,
                     subaddressing: AddressMapping::Disable,
                     cache: None,
                     blocked_ips: Arc::new(BlockedIps::new(store.clone().into())),
+                    breach_check: None,
+                    circuit_breaker: None,
+                    password_rehash: false,
                 }),
                 default_lookup_store: LookupStore::Store(store.clone()),
-                default_blob_store: store::BlobStore::Store(store.clone()),
+                default_blob_store: store.clone().into(),
                 default_data_store: store,
             },
         }
@@ -222,6 +242,8 @@ impl TestConfig for SessionCore {
                 ThrottleKeyHasherBuilder::default(),
                 16,
             ),
+            auth_usage: DashMap::new(),
+            rcpt_cache: LruCache::with_capacity(128),
         }
     }
 }
@@ -232,11 +254,11 @@ impl TestConfig for SessionConfig {
             timeout: IfBlock::new(Duration::from_secs(10)),
             duration: IfBlock::new(Duration::from_secs(10)),
             transfer_limit: IfBlock::new(1024 * 1024),
-            throttle: SessionThrottle {
+            throttle: ArcSwap::from_pointee(SessionThrottle {
                 connect: vec![],
                 mail_from: vec![],
                 rcpt_to: vec![],
-            },
+            }),
             connect: Connect {
                 script: IfBlock::default(),
             },
@@ -256,10 +278,29 @@ impl TestConfig for SessionConfig {
                 dsn: IfBlock::new(true),
                 expn: IfBlock::new(true),
                 vrfy: IfBlock::new(true),
+                enumeration: Enumeration {
+                    rate: IfBlock::default(),
+                    honey: IfBlock::new(false),
+                    score: 5.0,
+                },
+            },
+            responses: Responses {
+                user_unknown: IfBlock::default(),
+                policy_reject: IfBlock::default(),
+                rate_limited: IfBlock::default(),
+                over_quota: IfBlock::default(),
+                support_url: IfBlock::default(),
+            },
+            dnsbl: Dnsbl {
+                enable: IfBlock::new(false),
+                action: IfBlock::new(DnsblAction::Reject),
+                zones: vec![],
+                cache_ttl: Duration::from_secs(3600),
             },
             auth: Auth {
                 directory: IfBlock::default(),
                 mechanisms: IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN)),
+                require_tls: IfBlock::new(Mechanism::from(AUTH_PLAIN | AUTH_LOGIN)),
                 require: IfBlock::new(false),
                 errors_max: IfBlock::new(10),
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
@@ -278,6 +319,23 @@ impl TestConfig for SessionConfig {
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
                 max_recipients: IfBlock::new(3),
                 rewrite: IfBlock::default(),
+                cache_ttl: Duration::ZERO,
+                tarpit_after: IfBlock::default(),
+                tarpit_max_delay: IfBlock::new(Duration::from_secs(60)),
+                honeypot: Honeypot {
+                    enable: IfBlock::new(false),
+                    score: 10.0,
+                    window: Duration::from_secs(86400),
+                    penalize_after: 1,
+                    ban_after: 0,
+                },
+                greylist: Greylist {
+                    enable: IfBlock::new(false),
+                    delay: Duration::from_secs(300),
+                    ttl: Duration::from_secs(30 * 86400),
+                    whitelist_spf: true,
+                    whitelist_networks: vec![],
+                },
             },
             data: Data {
                 script: IfBlock::default(),
@@ -290,6 +348,13 @@ impl TestConfig for SessionConfig {
                 add_auth_results: IfBlock::new(true),
                 add_message_id: IfBlock::new(true),
                 add_date: IfBlock::new(true),
+                footer: IfBlock::default(),
+                moderate: IfBlock::default(),
+                dlp: Dlp::default(),
+                journal: IfBlock::default(),
+                risk: SenderRisk::default(),
+                monitor: Monitor::default(),
+                spool: Spool::default(),
                 pipe_commands: vec![],
                 milters: vec![],
             },
@@ -312,6 +377,9 @@ impl TestConfig for QueueCore {
                 pki_verify: build_tls_connector(false),
                 dummy_verify: build_tls_connector(true),
             },
+            reputation: Default::default(),
+            pool: Default::default(),
+            index: Default::default(),
         }
     }
 }
@@ -341,6 +409,15 @@ impl TestConfig for QueueConfig {
                 name: IfBlock::new("Mail Delivery Subsystem".to_string()),
                 address: IfBlock::new("MAILER-DAEMON@example.org".to_string()),
                 sign: IfBlock::default(),
+                require_auth: IfBlock::default(),
+                suppress: IfBlock::default(),
+                rate: None,
+                batv: Batv {
+                    keys: vec![],
+                    sign: IfBlock::default(),
+                    signing_key: IfBlock::default(),
+                    verify: IfBlock::default(),
+                },
             },
             timeout: QueueOutboundTimeout {
                 connect: IfBlock::new(Duration::from_secs(1)),
@@ -352,16 +429,63 @@ impl TestConfig for QueueConfig {
                 data: IfBlock::new(Duration::from_secs(1)),
                 mta_sts: IfBlock::new(Duration::from_secs(1)),
             },
-            throttle: QueueThrottle {
+            throttle: ArcSwap::from_pointee(QueueThrottle {
                 sender: vec![],
                 rcpt: vec![],
                 host: vec![],
-            },
+            }),
             quota: QueueQuotas {
                 sender: vec![],
                 rcpt: vec![],
                 rcpt_domain: vec![],
             },
+            priority: IfBlock::default(),
+            lanes: QueueLanes {
+                weight_high: 8,
+                weight_normal: 4,
+                weight_low: 1,
+                max_dispatch: 0,
+            },
+            fairness: QueueFairness {
+                bucket: IfBlock::default(),
+                weight: IfBlock::new(1),
+                max_in_flight: 0,
+            },
+            index: QueueIndexConfig {
+                max_entries: 0,
+                rebuild: Duration::from_secs(900),
+            },
+            quarantine: Quarantine {
+                reviewers: vec![],
+                approvers: vec![],
+                digest_frequency: Duration::from_secs(86400),
+                from_name: "Quarantine Review".to_string(),
+                from_address: "MAILER-DAEMON@example.org".to_string(),
+                sign: IfBlock::default(),
+                link_url: String::new(),
+                link_secret: vec![],
+                link_expiry: Duration::from_secs(7 * 86400),
+            },
+            shadow: Shadow {
+                relay: IfBlock::default(),
+                until: None,
+            },
+            ip_rotation: IpRotation {
+                duration: None,
+                threshold: 3,
+                patterns: vec![],
+            },
+            shaping: TrafficShaping {
+                rate: IfBlock::default(),
+                burst: IfBlock::default(),
+                backoff: None,
+                backoff_max: Duration::from_secs(3600),
+            },
+            pool: QueuePool {
+                enable: false,
+                idle_timeout: Duration::from_secs(15),
+                max_messages: 100,
+            },
         }
     }
 }
@@ -394,7 +518,7 @@ impl TestConfig for MailAuthConfig {
 impl TestConfig for ReportCore {
     fn test() -> Self {
         Self {
-            config: ReportConfig::test(),
+            config: ArcSwap::from_pointee(ReportConfig::test()),
             tx: mpsc::channel(1024).0,
         }
     }
@@ -408,6 +532,7 @@ impl TestConfig for ReportConfig {
                 addresses: vec![],
                 forward: true,
                 store: None,
+                retention: Duration::from_secs(30 * 86400),
                 report_id: 0.into(),
             },
             dkim: Report::test(),
@@ -415,6 +540,8 @@ impl TestConfig for ReportConfig {
             dmarc: Report::test(),
             dmarc_aggregate: AggregateReport::test(),
             tls: AggregateReport::test(),
+            dedup_window: None,
+            max_concurrent: 4,
         }
     }
 }
@@ -441,6 +568,7 @@ impl TestConfig for AggregateReport {
             send: IfBlock::default(),
             sign: IfBlock::default(),
             max_size: IfBlock::default(),
+            delivery_window: Duration::ZERO,
         }
     }
 }
@@ -452,7 +580,9 @@ impl TestConfig for SieveCore {
             from_addr: "MAILER-DAEMON@example.org".to_string(),
             from_name: "Mailer Daemon".to_string(),
             return_path: "".to_string(),
+            hostname: "localhost".to_string(),
             sign: vec![],
+            seal: None,
         }
     }
 }
@@ -54,7 +54,7 @@ use crate::smtp::{
     TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, RequireOptional},
+    config::{AggregateFrequency, ReportConfig, RequireOptional},
     core::{Resolvers, Session, SMTP},
     outbound::dane::{DnssecResolver, Tlsa, TlsaEntry},
     queue::{Error, ErrorDetails, Status},
@@ -103,7 +103,9 @@ async fn dane_verify() {
     let mut rr = core.init_test_report();
     core.session.config.rcpt.relay = IfBlock::new(true);
     core.queue.config.tls.dane = IfBlock::new(RequireOptional::Require);
-    core.report.config.tls.send = IfBlock::new(AggregateFrequency::Weekly);
+    let mut config = ReportConfig::test();
+    config.tls.send = IfBlock::new(AggregateFrequency::Weekly);
+    core.report.config.store(Arc::new(config));
 
     let core = Arc::new(core);
     let mut session = Session::test(core.clone());
@@ -246,6 +248,7 @@ async fn dane_test() {
             tlsa: LruCache::with_capacity(10),
             mta_sts: LruCache::with_capacity(10),
         },
+        overrides: Default::default(),
     };
 
     // Add dns entries
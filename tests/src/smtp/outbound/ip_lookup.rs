@@ -22,6 +22,7 @@
 */
 
 use std::{
+    net::IpAddr,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -30,7 +31,12 @@ use mail_auth::{IpLookupStrategy, MX};
 use utils::config::{if_block::IfBlock, ServerProtocol};
 
 use crate::smtp::{outbound::start_test_server, session::TestSession, TestConfig, TestSMTP};
-use smtp::core::{Session, SMTP};
+use smtp::{
+    config::IpRotation,
+    core::{Session, SMTP},
+    outbound::reputation::{provider_name, IpReputation},
+};
+use smtp_proto::Response;
 
 #[tokio::test]
 #[serial_test::serial]
@@ -105,3 +111,61 @@ async fn ip_lookup_strategy() {
         }
     }
 }
+
+#[test]
+fn ip_rotation() {
+    let config = IpRotation {
+        duration: Some(Duration::from_secs(3600)),
+        threshold: 2,
+        patterns: vec!["poor sender score".to_string()],
+    };
+
+    // Classify responses
+    for (code, message, expected) in [
+        (
+            421,
+            "4.7.0 Service unavailable, client host blocked using zen.spamhaus.org",
+            true,
+        ),
+        (550, "5.7.1 Your IP is listed on a blacklist", true),
+        (554, "5.7.1 Rejected due to poor sender score", true),
+        (550, "5.1.1 Mailbox does not exist", false),
+        (452, "4.2.2 Mailbox full, blacklist later", false),
+    ] {
+        assert_eq!(
+            config.is_reputation_block(&Response {
+                code,
+                esc: [0, 0, 0],
+                message: message.to_string(),
+            }),
+            expected,
+            "{message}"
+        );
+    }
+
+    // Providers are grouped by the parent domain of the MX
+    assert_eq!(provider_name("mx1.mail.provider.com"), "mail.provider.com");
+    assert_eq!(provider_name("mx.provider.com."), "provider.com");
+    assert_eq!(provider_name("provider.com"), "provider.com");
+
+    // Source IPs are only blocked after reaching the threshold
+    let reputation = IpReputation::default();
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(!reputation.add_block(ip, "provider.com", &config));
+    assert!(!reputation.is_blocked(ip, "provider.com"));
+    assert!(reputation.add_block(ip, "provider.com", &config));
+    assert!(!reputation.add_block(ip, "provider.com", &config));
+    assert!(reputation.is_blocked(ip, "provider.com"));
+    assert!(!reputation.is_blocked(ip, "other.com"));
+    assert!(!reputation.is_blocked(other_ip, "provider.com"));
+
+    // Disabled rotation never blocks
+    let config = IpRotation {
+        duration: None,
+        threshold: 1,
+        patterns: vec![],
+    };
+    assert!(!reputation.add_block(other_ip, "provider.com", &config));
+    assert!(!reputation.is_blocked(other_ip, "provider.com"));
+}
@@ -79,10 +79,18 @@ async fn lmtp_delivery() {
 
     // Multiple delivery attempts
     let mut local_qr = core.init_test_queue("lmtp_delivery_local");
-    core.shared.relay_hosts.insert(
-        "lmtp".to_string(),
-        Config::new(REMOTE).unwrap().parse_host("lmtp").unwrap(),
-    );
+    // Pipelining is opt-in, and only used when the relay advertises it
+    let remote_host = Config::new(REMOTE).unwrap().parse_host("lmtp").unwrap();
+    assert!(!remote_host.pipelining);
+    let remote_host =
+        Config::new(&REMOTE.replace("concurrency = 5", "concurrency = 5\npipelining = true"))
+            .unwrap()
+            .parse_host("lmtp")
+            .unwrap();
+    assert!(remote_host.pipelining);
+    core.shared
+        .relay_hosts
+        .insert("lmtp".to_string(), remote_host);
     core.queue.config.next_hop = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'lmtp'"},
     {else = false}]"#
         .parse_if();
@@ -35,6 +35,9 @@ pub mod extensions;
 pub mod ip_lookup;
 pub mod lmtp;
 pub mod mta_sts;
+pub mod pipe;
+pub mod pool;
+pub mod shaping;
 pub mod smtp;
 pub mod throttle;
 pub mod tls;
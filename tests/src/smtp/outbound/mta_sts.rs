@@ -41,7 +41,7 @@ use crate::smtp::{
     TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, RequireOptional},
+    config::{AggregateFrequency, ReportConfig, RequireOptional},
     core::{Session, SMTP},
     outbound::mta_sts::{lookup::STS_TEST_POLICY, Policy},
     reporting::PolicyType,
@@ -89,7 +89,9 @@ async fn mta_sts_verify() {
     let mut rr = core.init_test_report();
     core.session.config.rcpt.relay = IfBlock::new(true);
     core.queue.config.tls.mta_sts = IfBlock::new(RequireOptional::Require);
-    core.report.config.tls.send = IfBlock::new(AggregateFrequency::Weekly);
+    let mut config = ReportConfig::test();
+    config.tls.send = IfBlock::new(AggregateFrequency::Weekly);
+    core.report.config.store(Arc::new(config));
 
     let core = Arc::new(core);
     //let mut queue = Queue::default();
@@ -157,6 +159,9 @@ async fn mta_sts_verify() {
         report.failure.as_ref().unwrap().result_type,
         ResultType::StsPolicyInvalid
     );
+    let entry = core.read_mta_sts_entry("foobar.org").await.unwrap();
+    assert!(entry.policy.is_none());
+    assert!(entry.last_error.unwrap().contains("No 'mx' entries found"));
 
     // MTA-STS policy does not authorize mx.foobar.org
     let policy = concat!(
@@ -240,4 +245,29 @@ async fn mta_sts_verify() {
         )
     );
     assert!(report.failure.is_none());
+
+    // The fetched policy should be persisted in the store
+    let entry = core.read_mta_sts_entry("foobar.org").await.unwrap();
+    let policy = Policy::parse(policy, "policy_will_work".to_string()).unwrap();
+    assert_eq!(entry.policy.as_ref(), Some(&policy));
+    assert!(entry.last_error.is_none());
+    assert_eq!(entry.expires_at - entry.fetched_at, 604800);
+
+    // After a restart, the persisted policy is used even if fetching fails
+    let mut restarted = SMTP::test();
+    restarted.shared.default_data_store = core.shared.default_data_store.clone();
+    restarted.resolvers.dns.txt_add(
+        "_mta-sts.foobar.org",
+        MtaSts::parse(b"v=STSv1; id=policy_will_work;").unwrap(),
+        Instant::now() + Duration::from_secs(10),
+    );
+    STS_TEST_POLICY.lock().clear();
+    assert_eq!(
+        restarted
+            .lookup_mta_sts_policy("foobar.org", Duration::from_secs(1))
+            .await
+            .unwrap()
+            .as_ref(),
+        &policy
+    );
 }
@@ -0,0 +1,209 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{shared::ConfigShared, PipeCommand},
+    core::{Session, SMTP},
+    outbound::pipe::PipeResult,
+    queue::Status,
+};
+use utils::config::{if_block::IfBlock, Config};
+
+const PIPE: &str = r#"
+[pipe.local]
+command = "/bin/sh"
+arguments = ["-c", "case \"$1\" in
+    ok@*) cat > \"$2\"; exit 0;;
+    unknown@*) echo 'No such user here' >&2; exit 67;;
+    *) echo 'Mailbox locked' >&2; exit 75;;
+esac", "sh", "{rcpt}", "{path}"]
+per-recipient = true
+timeout = "5s"
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn pipe_delivery() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let delivered_path = std::env::temp_dir().join("stalwart_pipe_delivery.eml");
+    let _ = std::fs::remove_file(&delivered_path);
+
+    // Configure pipe
+    let mut core = SMTP::test();
+    let mut local_qr = core.init_test_queue("pipe_delivery");
+    core.shared.pipes.insert(
+        "local".to_string(),
+        Config::new(&PIPE.replace("{path}", delivered_path.to_str().unwrap()))
+            .unwrap()
+            .parse_pipe("local")
+            .unwrap(),
+    );
+    core.queue.config.next_hop = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'local'"},
+    {else = false}]"#
+        .parse_if();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(100);
+    core.queue.config.retry = IfBlock::new(Duration::from_secs(1));
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &[
+                "<ok@foobar.org>",
+                "<unknown@foobar.org>",
+                "<busy@foobar.org>",
+            ],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = local_qr.expect_message().await;
+    local_qr
+        .delivery_attempt(message.id)
+        .await
+        .try_deliver(core.clone())
+        .await;
+
+    // Wait for the DSN to be queued and the delivery attempt to finish
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_secs(5), local_qr.queue_rx.recv())
+            .await
+            .expect("No queue event received.")
+            .unwrap()
+            .assert_reload();
+    }
+
+    // Make sure the message was piped to the command
+    assert!(std::fs::read_to_string(&delivered_path)
+        .unwrap()
+        .contains("Subject: Is dinner ready?"));
+    let _ = std::fs::remove_file(&delivered_path);
+
+    // Check recipient status
+    let message = core.read_message(message.id).await.unwrap();
+    let mut recipients = message.recipients.iter();
+    let rcpt = recipients.next().unwrap();
+    assert!(matches!(&rcpt.status, Status::Completed(_)), "{rcpt:?}");
+    let rcpt = recipients.next().unwrap();
+    match &rcpt.status {
+        Status::PermanentFailure(err) => {
+            assert_eq!(err.response.code, 550);
+            assert_eq!(err.response.esc, [5, 1, 1]);
+            assert_eq!(err.response.message, "No such user here");
+        }
+        _ => panic!("Unexpected status {rcpt:?}"),
+    }
+    let rcpt = recipients.next().unwrap();
+    match &rcpt.status {
+        Status::TemporaryFailure(err) => {
+            assert_eq!(err.response.code, 451);
+            assert_eq!(err.response.message, "Mailbox locked");
+        }
+        _ => panic!("Unexpected status {rcpt:?}"),
+    }
+    assert!(matches!(
+        message.domains[0].status,
+        Status::TemporaryFailure(_)
+    ));
+
+    // A DSN should be sent for the rejected recipient
+    local_qr
+        .read_queued_messages()
+        .await
+        .into_iter()
+        .find(|message| message.return_path.is_empty())
+        .expect("No DSN found in queue")
+        .read_lines(&local_qr)
+        .await
+        .assert_contains("<unknown@foobar.org> (host 'local' rejected command");
+}
+
+#[tokio::test]
+async fn pipe_command() {
+    let pipe = |script: &str, argument: &str| PipeCommand {
+        command: "/bin/sh".to_string(),
+        arguments: vec![
+            "-c".to_string(),
+            script.to_string(),
+            "sh".to_string(),
+            argument.to_string(),
+        ],
+        per_recipient: true,
+        timeout: Duration::from_secs(5),
+    };
+
+    // Addresses starting with a dash are not passed to commands
+    assert!(matches!(
+        pipe("exit 0", "{rcpt}")
+            .run(
+                b"test",
+                "john@test.org",
+                ["-oQ/tmp@foobar.org"].into_iter(),
+                1
+            )
+            .await,
+        PipeResult::PermanentFailure([5, 1, 3], _)
+    ));
+
+    // Placeholders within substituted values are not expanded
+    assert_eq!(
+        pipe(
+            "case \"$1\" in ?rcpt?@test.org) exit 0;; esac; exit 1",
+            "{sender}"
+        )
+        .run(b"test", "{rcpt}@test.org", ["ok@foobar.org"].into_iter(), 1)
+        .await,
+        PipeResult::Success
+    );
+
+    // Commands writing more than a pipe buffer before reading do not block
+    assert_eq!(
+        pipe("head -c 1048576 /dev/zero >&2; cat > /dev/null", "{rcpt}")
+            .run(
+                &vec![b'a'; 1024 * 1024],
+                "john@test.org",
+                ["ok@foobar.org"].into_iter(),
+                1
+            )
+            .await,
+        PipeResult::Success
+    );
+}
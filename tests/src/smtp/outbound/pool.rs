@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::MX;
+use utils::config::{if_block::IfBlock, ServerProtocol};
+
+use crate::smtp::{outbound::start_test_server, session::TestSession, TestConfig, TestSMTP};
+use smtp::core::{Session, SMTP};
+
+#[tokio::test]
+#[serial_test::serial]
+async fn connection_pool() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_pool_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    // Add mock DNS entries
+    let mut core = SMTP::test();
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Enable connection pooling
+    let mut local_qr = core.init_test_queue("smtp_pool_local");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.queue.config.pool.enable = true;
+    core.queue.config.pool.max_messages = 3;
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+
+    // Messages to the same host reuse the open session until the
+    // maximum number of messages per connection is reached
+    for (num, expected_idle) in [(1, 1), (2, 1), (3, 0), (4, 1)] {
+        session
+            .send_message(
+                "john@test.org",
+                &[format!("bill{num}@foobar.org").as_str()],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        local_qr
+            .expect_message_then_deliver()
+            .await
+            .try_deliver(core.clone())
+            .await;
+        assert_eq!(
+            remote_qr
+                .expect_message()
+                .await
+                .recipients
+                .last()
+                .unwrap()
+                .address,
+            format!("bill{num}@foobar.org")
+        );
+        assert_eq!(
+            core.queue.pool.idle_sessions(),
+            expected_idle,
+            "message {num}"
+        );
+    }
+
+    // Expired sessions are closed
+    core.queue.pool.purge(Duration::from_secs(60)).await;
+    assert_eq!(core.queue.pool.idle_sessions(), 1);
+    core.queue.pool.purge(Duration::ZERO).await;
+    assert_eq!(core.queue.pool.idle_sessions(), 0);
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use store::write::now;
+use utils::config::if_block::IfBlock;
+
+use crate::smtp::{
+    outbound::throttle::TestQueueEnvelope, queue::manager::new_message, ParseTestConfig,
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    core::SMTP,
+    queue::{throttle, ErrorDetails, HostResponse, QueueEnvelope, Status},
+};
+use smtp_proto::Response;
+
+#[tokio::test]
+async fn traffic_shaping() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+    let _local_qr = core.init_test_queue("smtp_traffic_shaping");
+    core.queue.config.shaping.rate = r#"[{if = "rcpt_domain = 'example.org'", then = "[2, 1h]"},
+    {if = "rcpt_domain = 'example.com'", then = "[100, 1m]"},
+    {else = false}]"#
+        .parse_if();
+    core.queue.config.shaping.burst = IfBlock::new(2);
+    core.queue.config.shaping.backoff = Some(Duration::from_secs(60));
+    core.queue.config.shaping.backoff_max = Duration::from_secs(300);
+    let message = new_message(0);
+    let span = tracing::info_span!("test");
+
+    // Burst is allowed, then the domain is shaped
+    let envelope = QueueEnvelope::test(&message, "example.org", "");
+    for _ in 0..2 {
+        core.take_shaping_token(&envelope, &span).await.unwrap();
+    }
+    match core.take_shaping_token(&envelope, &span).await {
+        Err(throttle::Error::Rate { retry_at }) => {
+            assert!(retry_at > now() && retry_at <= now() + 1800, "{retry_at}");
+        }
+        result => panic!("Unexpected result {result:?}"),
+    }
+
+    // Unshaped domains are not limited
+    let envelope = QueueEnvelope::test(&message, "example.net", "");
+    for _ in 0..10 {
+        core.take_shaping_token(&envelope, &span).await.unwrap();
+    }
+
+    // Deferrals push back deliveries to the domain
+    let envelope = QueueEnvelope::test(&message, "example.com", "mx.example.com");
+    core.take_shaping_token(&envelope, &span).await.unwrap();
+    for (code, delay) in [(452, 0), (421, 60), (450, 120)] {
+        core.update_shaping_backoff(
+            &Status::TemporaryFailure(smtp::queue::Error::UnexpectedResponse(HostResponse {
+                hostname: ErrorDetails {
+                    entity: "mx.example.com".to_string(),
+                    details: "DATA".to_string(),
+                },
+                response: Response {
+                    code,
+                    esc: [4, 7, 0],
+                    message: "Too many messages, slow down".to_string(),
+                },
+            })),
+            std::iter::empty(),
+            &envelope,
+            &span,
+        )
+        .await;
+
+        if delay == 0 {
+            core.take_shaping_token(&envelope, &span).await.unwrap();
+            continue;
+        }
+        match core.take_shaping_token(&envelope, &span).await {
+            Err(throttle::Error::Rate { retry_at }) => {
+                assert!(retry_at + 5 >= now() + delay, "{retry_at} {delay}");
+                assert!(retry_at <= now() + delay * 2, "{retry_at} {delay}");
+            }
+            result => panic!("Unexpected result for {code}: {result:?}"),
+        }
+    }
+}
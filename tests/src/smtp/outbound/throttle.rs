@@ -87,7 +87,10 @@ async fn throttle_outbound() {
     let mut core = SMTP::test();
     let mut local_qr = core.init_test_queue("smtp_throttle_outbound");
     core.session.config.rcpt.relay = IfBlock::new(true);
-    core.queue.config.throttle = THROTTLE.parse_queue_throttle();
+    core.queue
+        .config
+        .throttle
+        .store(Arc::new(THROTTLE.parse_queue_throttle()));
     core.queue.config.retry = IfBlock::new(Duration::from_secs(86400));
     core.queue.config.notify = IfBlock::new(Duration::from_secs(86400));
     core.queue.config.expire = IfBlock::new(Duration::from_secs(86400));
@@ -105,7 +108,7 @@ async fn throttle_outbound() {
     // Throttle sender
     let span = tracing::info_span!("test");
     let mut in_flight = vec![];
-    let throttle = &core.queue.config.throttle;
+    let throttle = core.queue.config.throttle.load_full();
     for t in &throttle.sender {
         core.is_allowed(
             t,
@@ -39,6 +39,7 @@ use crate::smtp::{
 use smtp::{
     config::RequireOptional,
     core::{Session, SMTP},
+    outbound::trace::TlsSource,
 };
 
 #[tokio::test]
@@ -94,6 +95,20 @@ async fn starttls_optional() {
         .await;
     let mut retry = local_qr.expect_message().await;
     assert!(retry.domains[0].disable_tls);
+    let trace = retry.domains[0].tls_trace.last().unwrap();
+    let entry = trace
+        .entries
+        .iter()
+        .find(|entry| entry.source == TlsSource::StartTls)
+        .unwrap();
+    assert_eq!(entry.mx.as_deref(), Some("mx.foobar.org"));
+    assert_eq!(entry.requires, "optional");
+    assert!(
+        entry
+            .outcome
+            .starts_with("Failed, plain-text on next attempt"),
+        "{entry:?}"
+    );
     let prev_due = retry.domains[0].retry.due;
     let next_due = now();
     let queue_id = retry.id;
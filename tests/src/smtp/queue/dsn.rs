@@ -21,19 +21,28 @@
  * for more details.
 */
 
-use std::{fs, path::PathBuf, time::SystemTime};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use smtp_proto::{Response, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
 use store::write::now;
-use utils::BlobHash;
+use utils::{config::Rate, BlobHash};
 
 use crate::smtp::{
     inbound::sign::TextConfigContext, ParseTestConfig, QueueReceiver, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::ConfigContext,
+    config::{BatvKey, ConfigContext, ReportConfig},
     core::SMTP,
-    queue::{Domain, Error, ErrorDetails, HostResponse, Message, Recipient, Schedule, Status},
+    queue::{
+        dsn::{batv_sign, batv_verify, BatvResult},
+        Domain, Error, ErrorDetails, HostResponse, Message, Recipient, Schedule, Status,
+        MAIL_DSN_SUPPRESSED,
+    },
 };
 
 #[tokio::test]
@@ -84,6 +93,7 @@ async fn generate_dsn() {
                 details: "Connection timeout".to_string(),
             })),
             disable_tls: false,
+            tls_trace: vec![],
         }],
         flags: 0,
         env_id: None,
@@ -164,6 +174,134 @@ async fn generate_dsn() {
     assert_eq!(queue.len(), 4);
 }
 
+#[tokio::test]
+async fn dsn_backscatter_protection() {
+    let original = "From: sender@foobar.org\r\nSubject: test\r\n\r\ntest\r\n";
+    let mut core = SMTP::test();
+    core.queue.config.dsn.rate = Some(Rate {
+        requests: 1,
+        period: Duration::from_secs(3600),
+    });
+    let mut qr = core.init_test_queue("smtp_dsn_protection_test");
+    let span = tracing::span!(tracing::Level::INFO, "hi");
+    let new_message = |flags: u64| Message {
+        size: original.len(),
+        id: 0,
+        created: now(),
+        return_path: "sender@foobar.org".to_string(),
+        return_path_lcase: "sender@foobar.org".to_string(),
+        return_path_domain: "foobar.org".to_string(),
+        recipients: vec![Recipient {
+            domain_idx: 0,
+            address: "foobar@example.org".to_string(),
+            address_lcase: "foobar@example.org".to_string(),
+            status: Status::PermanentFailure(HostResponse {
+                hostname: ErrorDetails {
+                    entity: "mx.example.org".to_string(),
+                    details: "RCPT TO:<foobar@example.org>".to_string(),
+                },
+                response: Response {
+                    code: 550,
+                    esc: [5, 1, 2],
+                    message: "User does not exist".to_string(),
+                },
+            }),
+            flags: RCPT_NOTIFY_FAILURE,
+            orcpt: None,
+        }],
+        domains: vec![Domain {
+            domain: "example.org".to_string(),
+            retry: Schedule::now(),
+            notify: Schedule::now(),
+            expires: now() + 10,
+            status: Status::Scheduled,
+            disable_tls: false,
+            tls_trace: vec![],
+        }],
+        flags,
+        env_id: None,
+        priority: 0,
+        blob_hash: BlobHash::from(original.as_bytes()),
+        quota_keys: vec![],
+    };
+    qr.blob_store
+        .put_blob(
+            BlobHash::from(original.as_bytes()).as_slice(),
+            original.as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    // Messages that failed authentication should not generate DSNs
+    core.send_dsn(&mut new_message(MAIL_DSN_SUPPRESSED), &span)
+        .await;
+    qr.assert_no_events();
+
+    // Only one DSN per sender and hour should be sent
+    core.send_dsn(&mut new_message(0), &span).await;
+    qr.expect_message().await;
+    core.send_dsn(&mut new_message(0), &span).await;
+    qr.assert_no_events();
+
+    // Identical notifications for the same message should only be sent once,
+    // even when generated by different nodes sharing the lookup store
+    core.queue.config.dsn.rate = None;
+    let mut config = ReportConfig::test();
+    config.dedup_window = Some(Duration::from_secs(3600));
+    core.report.config.store(Arc::new(config));
+    core.send_dsn(&mut new_message(0), &span).await;
+    qr.expect_message().await;
+    core.send_dsn(&mut new_message(0), &span).await;
+    qr.assert_no_events();
+
+    // Test BATV signatures
+    let key = |id: u8, secret: &str| BatvKey {
+        id,
+        secret: secret.as_bytes().to_vec(),
+    };
+    let keys = vec![key(0, "secret")];
+    let signed = batv_sign(&keys[0], "sender@foobar.org");
+    assert!(signed.starts_with("prvs=0"));
+    assert!(signed.ends_with("=sender@foobar.org"));
+    assert_eq!(batv_sign(&keys[0], &signed), signed);
+    assert_eq!(batv_sign(&keys[0], ""), "");
+    assert_eq!(
+        batv_verify(&keys, &signed),
+        BatvResult::Valid("sender@foobar.org".to_string())
+    );
+    assert_eq!(
+        batv_verify(&keys, &signed.to_uppercase()),
+        BatvResult::Valid("SENDER@FOOBAR.ORG".to_string())
+    );
+    assert_eq!(
+        batv_verify(&[key(0, "other")], &signed),
+        BatvResult::Invalid
+    );
+    assert_eq!(
+        batv_verify(&keys, &signed.replace("sender@", "other@")),
+        BatvResult::Invalid
+    );
+    assert_eq!(
+        batv_verify(&keys, "sender@foobar.org"),
+        BatvResult::Unsigned
+    );
+
+    // Tags signed with a previous key should verify until the key is removed
+    let rotated = vec![key(0, "secret"), key(1, "new-secret")];
+    let new_signed = batv_sign(&rotated[1], "sender@foobar.org");
+    assert!(new_signed.starts_with("prvs=1"));
+    assert_eq!(
+        batv_verify(&rotated, &signed),
+        BatvResult::Valid("sender@foobar.org".to_string())
+    );
+    assert_eq!(
+        batv_verify(&rotated, &new_signed),
+        BatvResult::Valid("sender@foobar.org".to_string())
+    );
+    assert_eq!(batv_verify(&rotated[1..], &signed), BatvResult::Invalid);
+    assert_eq!(batv_verify(&keys, &new_signed), BatvResult::Invalid);
+}
+
 impl QueueReceiver {
     async fn compare_dsn(&self, message: Message, test: &str) {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -225,6 +363,8 @@ fn remove_ids(message: Vec<u8>) -> String {
             message.push_str("Arrival-Date: <date goes here>");
         } else if line.starts_with("Will-Retry-Until:") {
             message.push_str("Will-Retry-Until: <date goes here>");
+        } else if line.starts_with("Delivery will be retried until") {
+            message.push_str("Delivery will be retried until <date goes here>.");
         } else {
             message.push_str(line);
         }
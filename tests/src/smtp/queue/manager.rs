@@ -27,7 +27,11 @@ use mail_auth::hickory_resolver::proto::op::ResponseCode;
 
 use smtp::{
     core::SMTP,
-    queue::{Domain, Message, Schedule, Status},
+    queue::{
+        manager::{FairQueue, Lane, Queue},
+        spool::QueueEventLock,
+        Domain, Message, OnHold, Schedule, Status,
+    },
 };
 use store::write::now;
 
@@ -74,6 +78,50 @@ async fn queue_due() {
     qr.assert_queue_is_empty().await;
 }
 
+#[tokio::test]
+async fn queue_index() {
+    for max_entries in [10, 2] {
+        let mut core = SMTP::test();
+        core.queue.config.index.max_entries = max_entries;
+        let qr = core.init_test_queue("smtp_queue_index_test");
+        let core = Arc::new(core);
+
+        for id in 0..3 {
+            let mut message = new_message(id);
+            message.domains.push(domain("a", 0, 4, 5));
+            let due = message.next_delivery_event();
+            message.save_changes(&core, 0.into(), due.into()).await;
+        }
+
+        // The index is built on the first scheduler run
+        let mut events = core.next_event().await;
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            core.queue.index.len(),
+            if max_entries == 10 { 3 } else { 0 }
+        );
+
+        // Writes are reflected in the index
+        let mut message = new_message(3);
+        message.domains.push(domain("a", 0, 4, 5));
+        let due = message.next_delivery_event();
+        message.save_changes(&core, 0.into(), due.into()).await;
+        events = core.next_event().await;
+        assert_eq!(events.len(), 4);
+
+        let locked = core.try_lock_event(events.pop().unwrap()).await.unwrap();
+        assert_eq!(core.next_event().await.len(), 3);
+
+        for event in events.into_iter().chain([locked]) {
+            let message = core.read_message(event.queue_id).await.unwrap();
+            message.remove(&core, event.due).await;
+        }
+        assert!(core.next_event().await.is_empty());
+        assert!(core.queue.index.is_empty());
+        qr.assert_queue_is_empty().await;
+    }
+}
+
 #[test]
 fn delivery_events() {
     let mut message = new_message(0);
@@ -135,6 +183,96 @@ fn delivery_events() {
     assert!(message.next_event().is_none());
 }
 
+#[tokio::test]
+async fn queue_lanes() {
+    assert_eq!(Lane::from(3), Lane::High);
+    assert_eq!(Lane::from(0), Lane::Normal);
+    assert_eq!(Lane::from(-4), Lane::Low);
+
+    // Messages on hold are released by priority
+    let mut queue = Queue::new(Arc::new(SMTP::test()));
+    for (queue_id, priority) in [(0, -1), (1, 0), (2, 4), (3, 0)] {
+        queue.on_hold(OnHold {
+            next_due: Some(0),
+            priority,
+            limiters: vec![],
+            message: QueueEventLock {
+                due: 0,
+                queue_id,
+                lock_expiry: 0,
+            },
+        });
+    }
+    let mut released = Vec::new();
+    while let Some(event) = queue.next_on_hold() {
+        released.push(event.queue_id);
+    }
+    assert_eq!(released, vec![2, 1, 3, 0]);
+}
+
+#[test]
+fn queue_fairness() {
+    // A flooding bucket does not starve other senders
+    let mut queue = FairQueue::new();
+    for id in 0..6 {
+        queue.push("bulk.org", 1, ("bulk.org", id));
+    }
+    queue.push("a.org", 1, ("a.org", 0));
+    queue.push("b.org", 2, ("b.org", 0));
+    queue.push("b.org", 2, ("b.org", 1));
+    queue.push("b.org", 2, ("b.org", 2));
+
+    let mut dispatched = Vec::new();
+    while let Some((item, _)) = queue.pop(|_| Some(())) {
+        dispatched.push(item);
+    }
+    assert_eq!(
+        dispatched,
+        vec![
+            ("bulk.org", 0),
+            ("a.org", 0),
+            ("b.org", 0),
+            ("b.org", 1),
+            ("bulk.org", 1),
+            ("b.org", 2),
+            ("bulk.org", 2),
+            ("bulk.org", 3),
+            ("bulk.org", 4),
+            ("bulk.org", 5),
+        ]
+    );
+    assert!(queue.is_empty());
+
+    // Buckets without available dispatch slots are skipped
+    let mut queue = FairQueue::new();
+    for id in 0..3 {
+        queue.push("bulk.org", 1, ("bulk.org", id));
+    }
+    queue.push("a.org", 1, ("a.org", 0));
+    let mut dispatched = Vec::new();
+    let mut bulk_in_flight = 0;
+    while let Some((item, _)) = queue.pop(|bucket| {
+        if bucket == "bulk.org" {
+            if bulk_in_flight < 2 {
+                bulk_in_flight += 1;
+                Some(())
+            } else {
+                None
+            }
+        } else {
+            Some(())
+        }
+    }) {
+        dispatched.push(item);
+    }
+    assert_eq!(
+        dispatched,
+        vec![("bulk.org", 0), ("a.org", 0), ("bulk.org", 1)]
+    );
+    assert!(!queue.is_empty());
+    assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&("bulk.org", 2)]);
+}
+
 pub fn new_message(id: u64) -> Message {
     Message {
         size: 0,
@@ -161,6 +299,7 @@ fn domain(domain: &str, retry: u64, notify: u64, expires: u64) -> Domain {
         expires: now() + expires,
         status: Status::Scheduled,
         disable_tls: false,
+        tls_trace: vec![],
     }
 }
 
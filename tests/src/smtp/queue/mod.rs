@@ -24,4 +24,7 @@
 pub mod concurrent;
 pub mod dsn;
 pub mod manager;
+pub mod quarantine;
+pub mod replay;
 pub mod retry;
+pub mod shadow;
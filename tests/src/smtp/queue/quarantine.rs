@@ -0,0 +1,389 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use hyper::{header::CONTENT_TYPE, Method, StatusCode};
+use mail_parser::MessageParser;
+use store::write::now;
+use utils::config::{if_block::IfBlock, Config};
+
+use crate::smtp::{
+    inbound::TestMessage,
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::session::ConfigSession,
+    core::{eval::V_SENDER, Session, SMTP},
+    queue::{
+        quarantine::{AuditAction, LinkAction, QuarantineSource},
+        MAIL_QUARANTINED,
+    },
+};
+
+const CONFIG: &str = r#"
+[session.data.dlp.dictionary]
+confidential = ["(?i)project\\s+falcon"]
+
+[session.data.dlp.rule."internal"]
+patterns = ["dictionary:confidential"]
+action = "quarantine"
+"#;
+
+#[tokio::test]
+async fn quarantine() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_quarantine_test");
+    let mut dlp = Config::new(CONFIG).unwrap().parse_dlp(&[V_SENDER]).unwrap();
+    dlp.enable = IfBlock::new(true);
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.dlp = dlp;
+    let config = &mut core.queue.config.quarantine;
+    config.reviewers = vec!["reviewer@example.org".to_string()];
+    config.link_url = "https://mx.example.org".to_string();
+    config.link_secret = b"secret".to_vec();
+    config.link_expiry = Duration::from_secs(3600);
+
+    // Test link signatures
+    let now = now();
+    let link = config
+        .build_link(LinkAction::Release, 123, "reviewer@example.org", now)
+        .unwrap();
+    let sig = link.rsplit_once("sig=").unwrap().1;
+    let expires = now + 3600;
+    for (action, queue_id, reviewer, expires, sig, time, expected) in [
+        (
+            LinkAction::Release,
+            123,
+            "reviewer@example.org",
+            expires,
+            sig,
+            now,
+            true,
+        ),
+        (
+            LinkAction::Discard,
+            123,
+            "reviewer@example.org",
+            expires,
+            sig,
+            now,
+            false,
+        ),
+        (
+            LinkAction::Release,
+            124,
+            "reviewer@example.org",
+            expires,
+            sig,
+            now,
+            false,
+        ),
+        (
+            LinkAction::Release,
+            123,
+            "other@example.org",
+            expires,
+            sig,
+            now,
+            false,
+        ),
+        (
+            LinkAction::Release,
+            123,
+            "reviewer@example.org",
+            expires + 1,
+            sig,
+            now,
+            false,
+        ),
+        (
+            LinkAction::Release,
+            123,
+            "reviewer@example.org",
+            expires,
+            "abc",
+            now,
+            false,
+        ),
+        (
+            LinkAction::Release,
+            123,
+            "reviewer@example.org",
+            expires,
+            sig,
+            expires + 1,
+            false,
+        ),
+    ] {
+        assert_eq!(
+            config.verify_link(action, queue_id, reviewer, expires, sig, time),
+            expected,
+            "{action:?} {queue_id} {reviewer} {expires} {sig} {time}"
+        );
+    }
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Quarantine two messages
+    let mut queue_ids = Vec::new();
+    for subject in ["first", "second"] {
+        session
+            .send_message(
+                "john@doe.org",
+                &["bill@foobar.org"],
+                &format!(
+                    "From: john@doe.org\r\nSubject: {subject}\r\n\r\nAbout Project Falcon.\r\n"
+                ),
+                "250",
+            )
+            .await;
+        let message = qr.expect_message().await;
+        assert_ne!(message.flags & MAIL_QUARANTINED, 0);
+        let entry = core.read_quarantined(message.id).await.unwrap();
+        assert_eq!(entry.source, QuarantineSource::Dlp);
+        assert_eq!(entry.subject, subject);
+        assert_eq!(entry.return_path, "john@doe.org");
+        assert_eq!(entry.recipients, vec!["bill@foobar.org".to_string()]);
+        assert!(!entry.notified);
+        queue_ids.push(message.id);
+    }
+    assert!(qr.read_queued_events().await.is_empty());
+    assert_eq!(core.list_quarantined(|_| true).await.unwrap().len(), 2);
+
+    // Send digest to reviewers
+    core.send_quarantine_digest().await;
+    let digest = qr.expect_message().await;
+    assert_eq!(
+        digest.recipients.last().unwrap().address,
+        "reviewer@example.org"
+    );
+    let raw_digest = digest.read_message(&qr).await;
+    let text = MessageParser::new()
+        .parse(raw_digest.as_bytes())
+        .unwrap()
+        .body_text(0)
+        .unwrap()
+        .into_owned();
+    for queue_id in &queue_ids {
+        assert!(text.contains(&format!("Queue ID: {queue_id}")), "{text}");
+    }
+    let links = text
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("Release: ")
+                .or_else(|| line.strip_prefix("Delete: "))
+        })
+        .map(|link| link.trim().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(links.len(), 4);
+    let due = qr.message_due(digest.id).await;
+    digest.remove(&core, due).await;
+    assert!(core
+        .list_quarantined(|entry| !entry.notified)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Entries are only included in one digest
+    core.send_quarantine_digest().await;
+    qr.assert_no_events();
+
+    // Tampered links are rejected
+    let tampered = links[0].replace("sig=", "sig=0");
+    let response = core
+        .handle_quarantine_link(&Method::POST, &tampered.parse().unwrap(), "release")
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Opening a link only asks for confirmation
+    let uri = links[0].parse::<hyper::Uri>().unwrap();
+    let action = uri.path().strip_prefix("/quarantine/").unwrap();
+    let response = core
+        .handle_quarantine_link(&Method::GET, &uri, action)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+    assert!(core.read_quarantined(queue_ids[0]).await.is_some());
+    qr.assert_no_events();
+
+    // Release the first message using its link
+    let response = core
+        .handle_quarantine_link(&Method::POST, &uri, action)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    qr.read_event().await.assert_reload();
+    assert!(core.read_quarantined(queue_ids[0]).await.is_none());
+    assert_eq!(qr.read_queued_events().await.len(), 1);
+
+    // Links can only be used once
+    let response = core
+        .handle_quarantine_link(&Method::POST, &uri, action)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Delete the second message using its link
+    let uri = links[3].parse::<hyper::Uri>().unwrap();
+    assert!(uri.path().ends_with("/discard"));
+    let response = core
+        .handle_quarantine_link(&Method::POST, &uri, "discard")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(core.read_quarantined(queue_ids[1]).await.is_none());
+    assert!(core.read_message(queue_ids[1]).await.is_none());
+
+    // Verify audit trail
+    let audit = core.list_quarantine_audit(|_| true).await.unwrap();
+    for (queue_id, actions) in [
+        (
+            queue_ids[0],
+            [
+                AuditAction::Quarantined,
+                AuditAction::Notified,
+                AuditAction::Released,
+            ],
+        ),
+        (
+            queue_ids[1],
+            [
+                AuditAction::Quarantined,
+                AuditAction::Notified,
+                AuditAction::Discarded,
+            ],
+        ),
+    ] {
+        let entries = audit
+            .iter()
+            .filter(|entry| entry.queue_id == queue_id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries.iter().map(|entry| entry.action).collect::<Vec<_>>(),
+            actions
+        );
+        assert_eq!(entries[2].actor, "link:reviewer@example.org");
+    }
+}
+
+#[tokio::test]
+async fn moderation() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_moderation_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.moderate = r#"[{if = "authenticated_as = 'intern'", then = true},
+    {else = false}]"#
+        .parse_if();
+    let config = &mut core.queue.config.quarantine;
+    config.reviewers = vec!["security@example.org".to_string()];
+    config.approvers = vec!["manager@example.org".to_string()];
+    config.link_url = "https://mx.example.org".to_string();
+    config.link_secret = b"secret".to_vec();
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages from other accounts are delivered
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    assert_eq!(qr.expect_message().await.flags & MAIL_QUARANTINED, 0);
+    qr.clear_queue(&core).await;
+
+    // Messages submitted by moderated accounts are held
+    session.data.authenticated_as = "intern".to_string();
+    session
+        .send_message(
+            "intern@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_QUARANTINED, 0);
+    assert!(qr.read_queued_events().await.is_empty());
+    let entry = core.read_quarantined(message.id).await.unwrap();
+    assert_eq!(entry.source, QuarantineSource::Moderation);
+    assert!(entry.reason.contains("intern"), "{}", entry.reason);
+
+    // Only approvers are notified of moderated messages
+    core.send_quarantine_digest().await;
+    let digest = qr.expect_message().await;
+    assert_eq!(
+        digest.recipients.last().unwrap().address,
+        "manager@example.org"
+    );
+    qr.assert_no_events();
+
+    // Reviewers cannot approve moderated messages
+    let config = &core.queue.config.quarantine;
+    let uri = config
+        .build_link(
+            LinkAction::Release,
+            message.id,
+            "security@example.org",
+            now(),
+        )
+        .unwrap()
+        .parse::<hyper::Uri>()
+        .unwrap();
+    let response = core.handle_quarantine_link(&uri, "release").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(core.read_quarantined(message.id).await.is_some());
+
+    // Approvers can release them
+    let uri = config
+        .build_link(
+            LinkAction::Release,
+            message.id,
+            "manager@example.org",
+            now(),
+        )
+        .unwrap()
+        .parse::<hyper::Uri>()
+        .unwrap();
+    let response = core.handle_quarantine_link(&uri, "release").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    qr.read_event().await.assert_reload();
+    assert!(core.read_quarantined(message.id).await.is_none());
+    assert_eq!(
+        core.list_quarantine_audit(|entry| entry.queue_id == message.id)
+            .await
+            .unwrap()
+            .last()
+            .unwrap()
+            .actor,
+        "link:manager@example.org"
+    );
+}
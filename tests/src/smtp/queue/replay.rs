@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use crate::smtp::{
+    inbound::TestMessage, session::TestSession, ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    core::{Session, SMTP},
+    queue::{quarantine::AuditAction, MAIL_QUARANTINED},
+};
+use utils::config::if_block::IfBlock;
+
+const SCRIPT: &str = r#"
+require ["variables", "envelope", "editheader"];
+
+set "envelope.from" "bounces@foobar.org";
+set "envelope.to" "jane@foobar.org";
+addheader "X-Replayed" "yes";
+"#;
+
+#[tokio::test]
+async fn replay() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_replay_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.moderate = r#"[{if = "authenticated_as = 'intern'", then = true},
+    {else = false}]"#
+        .parse_if();
+    core.shared.scripts.insert(
+        "replay".to_string(),
+        Arc::new(sieve::Compiler::new().compile(SCRIPT.as_bytes()).unwrap()),
+    );
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Envelope changes are applied to the redelivered copy
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let message = qr.expect_message().await;
+    let replay = core
+        .replay_message(message.id, Some("replay".to_string()), true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(replay.verdict, "accept");
+    assert_eq!(replay.modifications.len(), 3, "{:?}", replay.modifications);
+    let new_message = qr.expect_message().await;
+    assert_eq!(replay.redelivered_id, Some(new_message.id));
+    assert_eq!(new_message.return_path, "bounces@foobar.org");
+    assert_eq!(new_message.recipients.len(), 1);
+    assert_eq!(new_message.recipients[0].address, "jane@foobar.org");
+    assert!(new_message
+        .read_message(&qr)
+        .await
+        .contains("X-Replayed: yes"));
+    assert!(core.read_message(message.id).await.is_none());
+    qr.clear_queue(&core).await;
+
+    // Replaying a quarantined message releases it
+    session.data.authenticated_as = "intern".to_string();
+    session
+        .send_message(
+            "intern@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_ne!(message.flags & MAIL_QUARANTINED, 0);
+    assert!(core.read_quarantined(message.id).await.is_some());
+    let replay = core
+        .replay_message(message.id, Some("replay".to_string()), true)
+        .await
+        .unwrap()
+        .unwrap();
+    let new_message = qr.expect_message().await;
+    assert_eq!(replay.redelivered_id, Some(new_message.id));
+    assert_eq!(new_message.flags & MAIL_QUARANTINED, 0);
+    assert_eq!(new_message.return_path, "bounces@foobar.org");
+    assert!(core.read_quarantined(message.id).await.is_none());
+    let audit = core
+        .list_quarantine_audit(|entry| entry.queue_id == message.id)
+        .await
+        .unwrap();
+    let entry = audit.last().unwrap();
+    assert_eq!(entry.action, AuditAction::Released);
+    assert_eq!(entry.actor, "replay");
+    assert_eq!(qr.read_queued_events().await.len(), 1);
+}
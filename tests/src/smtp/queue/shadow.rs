@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, QueueReceiver, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::shared::ConfigShared,
+    core::{Session, SMTP},
+    queue::{shadow::ShadowOutcome, Status, MAIL_SHADOW},
+};
+use utils::config::{if_block::IfBlock, Config};
+
+const PIPES: &str = r#"
+[pipe.primary]
+command = "/bin/sh"
+arguments = ["-c", "case \"$1\" in
+    gone@*) echo 'No such user here' >&2; exit 67;;
+    *) cat > /dev/null; exit 0;;
+esac", "sh", "{rcpt}"]
+
+[pipe.legacy]
+command = "/bin/sh"
+arguments = ["-c", "cat > /dev/null; exit 0"]
+per-recipient = false
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn shadow_delivery() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_shadow_test");
+    let config = Config::new(PIPES).unwrap();
+    for id in ["primary", "legacy"] {
+        core.shared
+            .pipes
+            .insert(id.to_string(), config.parse_pipe(id).unwrap());
+    }
+    core.queue.config.next_hop = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'primary'"},
+    {else = false}]"#
+        .parse_if();
+    core.queue.config.shadow.relay = r#"[{if = "rcpt_domain = 'foobar.org'", then = "'legacy'"},
+    {else = false}]"#
+        .parse_if();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(100);
+
+    let core = Arc::new(core);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &["<ok@foobar.org>", "<gone@foobar.org>"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+
+    // Primary delivery should queue a shadow copy and a DSN for the rejected recipient
+    let message = qr.expect_message().await;
+    qr.delivery_attempt(message.id)
+        .await
+        .try_deliver(core.clone())
+        .await;
+    wait_for_events(&mut qr, 3).await;
+    let entries = core.list_shadow_entries(|_| true).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = entries.into_iter().next().unwrap();
+    assert_eq!(entry.primary_id, message.id);
+    assert_eq!(entry.domain, "foobar.org");
+    assert_eq!(entry.relay, "legacy");
+    assert!(!entry.completed);
+    assert_eq!(
+        entry
+            .recipients
+            .iter()
+            .map(|rcpt| (rcpt.address.as_str(), rcpt.primary, rcpt.shadow))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "ok@foobar.org",
+                ShadowOutcome::Delivered,
+                ShadowOutcome::Pending
+            ),
+            (
+                "gone@foobar.org",
+                ShadowOutcome::Rejected,
+                ShadowOutcome::Pending
+            )
+        ]
+    );
+    let shadow = qr
+        .read_queued_messages()
+        .await
+        .into_iter()
+        .find(|message| message.id == entry.queue_id)
+        .expect("Shadow copy not found in queue");
+    assert_ne!(shadow.flags & MAIL_SHADOW, 0);
+    assert_eq!(shadow.return_path, "john@test.org");
+
+    // Deliver the shadow copy, the legacy system accepts both recipients
+    qr.delivery_attempt(shadow.id)
+        .await
+        .try_deliver(core.clone())
+        .await;
+    wait_for_events(&mut qr, 1).await;
+    let entry = core.read_shadow_entry(shadow.id).await.unwrap();
+    assert!(entry.completed);
+    assert!(entry.diverged);
+    assert_eq!(
+        entry
+            .recipients
+            .iter()
+            .map(|rcpt| rcpt.shadow)
+            .collect::<Vec<_>>(),
+        vec![ShadowOutcome::Delivered, ShadowOutcome::Delivered]
+    );
+    assert!(core.read_message(shadow.id).await.is_none());
+
+    // No bounces should be generated for shadow copies
+    let messages = qr.read_queued_messages().await;
+    assert_eq!(messages.len(), 1, "{messages:?}");
+    assert!(messages[0].return_path.is_empty());
+    assert!(matches!(messages[0].domains[0].status, Status::Scheduled));
+
+    // Remove the shadow delivery entry once reviewed
+    assert!(core.delete_shadow_entry(shadow.id).await);
+    assert!(core.list_shadow_entries(|_| true).await.unwrap().is_empty());
+}
+
+async fn wait_for_events(qr: &mut QueueReceiver, count: usize) {
+    for _ in 0..count {
+        tokio::time::timeout(Duration::from_secs(5), qr.queue_rx.recv())
+            .await
+            .expect("No queue event received.")
+            .unwrap()
+            .assert_reload();
+    }
+}
@@ -27,7 +27,7 @@ use crate::smtp::{
     inbound::TestQueueEvent, make_temp_dir, session::TestSession, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::AddressMatch,
+    config::{AddressMatch, ReportConfig},
     core::{Session, SMTP},
 };
 use utils::config::if_block::IfBlock;
@@ -44,14 +44,15 @@ async fn report_analyze() {
     config.relay = IfBlock::new(true);
     let config = &mut core.session.config.data;
     config.max_messages = IfBlock::new(1024);
-    let config = &mut core.report.config.analysis;
-    config.addresses = vec![
+    let mut config = ReportConfig::test();
+    config.analysis.addresses = vec![
         AddressMatch::StartsWith("reports@".to_string()),
         AddressMatch::EndsWith("@dmarc.foobar.org".to_string()),
         AddressMatch::Equals("feedback@foobar.org".to_string()),
     ];
-    config.forward = false;
-    config.store = report_dir.temp_dir.clone().into();
+    config.analysis.forward = false;
+    config.analysis.store = report_dir.temp_dir.clone().into();
+    core.report.config.store(Arc::new(config));
 
     // Create test message
     let core = Arc::new(core);
@@ -92,6 +93,64 @@ async fn report_analyze() {
     }
     assert_eq!(total_reports, total_reports_received);
 
+    // Check that DMARC aggregate reports were summarized
+    let dmarc_reports = core.list_incoming_dmarc_reports(|_| true).await.unwrap();
+    assert_eq!(dmarc_reports.len(), 5);
+    for report in &dmarc_reports {
+        assert!(!report.domain.is_empty(), "{report:?}");
+        assert_eq!(
+            report.total,
+            report.sources.iter().map(|s| s.count).sum::<u64>(),
+            "{report:?}"
+        );
+        assert!(report.dmarc_pass <= report.total, "{report:?}");
+    }
+    assert!(core.delete_incoming_dmarc_report(dmarc_reports[0].id).await);
+    assert_eq!(
+        core.read_incoming_dmarc_report(dmarc_reports[0].id).await,
+        None
+    );
+
+    // Reports with an already seen organization and report ID are not stored again
+    session
+        .send_message(
+            "john@test.org",
+            &["reports@foobar.org"],
+            "report:dmarc2",
+            "250",
+        )
+        .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(
+        core.list_incoming_dmarc_reports(|_| true)
+            .await
+            .unwrap()
+            .len(),
+        4
+    );
+
+    // Reports are purged after the retention period
+    core.purge_incoming_dmarc_reports().await;
+    assert_eq!(
+        core.list_incoming_dmarc_reports(|_| true)
+            .await
+            .unwrap()
+            .len(),
+        4
+    );
+    let mut config = ReportConfig::test();
+    config.analysis.addresses = vec![AddressMatch::StartsWith("reports@".to_string())];
+    config.analysis.forward = false;
+    config.analysis.retention = Duration::ZERO;
+    core.report.config.store(Arc::new(config));
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    core.purge_incoming_dmarc_reports().await;
+    assert!(core
+        .list_incoming_dmarc_reports(|_| true)
+        .await
+        .unwrap()
+        .is_empty());
+
     // Test delivery to non-report addresses
     session
         .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
@@ -41,7 +41,7 @@ use crate::smtp::{
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, ConfigContext},
+    config::{AggregateFrequency, ConfigContext, ReportConfig},
     core::SMTP,
     reporting::DmarcEvent,
 };
@@ -58,13 +58,14 @@ async fn report_dmarc() {
     // Create scheduler
     let mut core = SMTP::test();
     core.shared.signers = ConfigContext::new(&[]).parse_signatures().signers;
-    let config = &mut core.report.config;
+    let mut config = ReportConfig::test();
     config.dmarc_aggregate.sign = "\"['rsa']\"".parse_if();
     config.dmarc_aggregate.max_size = IfBlock::new(4096);
     config.submitter = IfBlock::new("mx.example.org".to_string());
     config.dmarc_aggregate.address = IfBlock::new("reports@example.org".to_string());
     config.dmarc_aggregate.org_name = IfBlock::new("Foobar, Inc.".to_string());
     config.dmarc_aggregate.contact_info = IfBlock::new("https://foobar.org/contact".to_string());
+    core.report.config.store(Arc::new(config));
 
     // Authorize external report for foobar.org
     core.resolvers.dns.txt_add(
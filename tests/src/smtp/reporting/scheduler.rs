@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use mail_auth::{
     common::parse::TxtRecordParser,
@@ -29,14 +29,17 @@ use mail_auth::{
     mta_sts::TlsRpt,
     report::{ActionDisposition, Alignment, Disposition, DmarcResult, PolicyPublished, Record},
 };
-use store::write::QueueClass;
+use store::write::{QueueClass, ReportEvent};
 use utils::config::if_block::IfBlock;
 
 use crate::smtp::{TestConfig, TestSMTP};
 use smtp::{
-    config::AggregateFrequency,
+    config::{AggregateFrequency, ReportConfig},
     core::SMTP,
-    reporting::{dmarc::DmarcFormat, DmarcEvent, PolicyType, TlsEvent},
+    reporting::{
+        dmarc::DmarcFormat, part_report_id, scheduler::delivery_jitter, split_by_size, DmarcEvent,
+        PolicyType, TlsEvent,
+    },
 };
 
 #[tokio::test]
@@ -51,9 +54,10 @@ async fn report_scheduler() {
     // Create scheduler
     let mut core = SMTP::test();
     let qr = core.init_test_queue("smtp_report_queue_test");
-    let config = &mut core.report.config;
+    let mut config = ReportConfig::test();
     config.dmarc_aggregate.max_size = IfBlock::new(500);
     config.tls.max_size = IfBlock::new(550);
+    core.report.config.store(Arc::new(config));
 
     // Schedule two events with a same policy and another one with a different policy
     let dmarc_record =
@@ -210,3 +214,71 @@ fn report_strip_json() {
         d
     );
 }
+
+#[tokio::test]
+async fn report_delivery_window() {
+    let mut core = SMTP::test();
+    let window = Duration::from_secs(3600);
+
+    // Jitter is stable for a domain and stays within the window
+    for domain in ["example.org", "foobar.org", "otherdomain.net"] {
+        let jitter = delivery_jitter(domain, window);
+        assert!(jitter < window.as_secs());
+        assert_eq!(jitter, delivery_jitter(domain, window));
+        assert_eq!(delivery_jitter(domain, Duration::ZERO), 0);
+    }
+    assert_ne!(
+        delivery_jitter("example.org", window),
+        delivery_jitter("foobar.org", window)
+    );
+
+    // Reports are generated once the period ends plus the domain's jitter
+    let event = ReportEvent {
+        due: 1000,
+        policy_hash: 0,
+        seq_id: 1,
+        domain: "example.org".to_string(),
+    };
+    assert_eq!(
+        core.report_ready_at(&QueueClass::DmarcReportHeader(event.clone())),
+        Some(1000)
+    );
+    let mut config = ReportConfig::test();
+    config.dmarc_aggregate.delivery_window = window;
+    core.report.config.store(Arc::new(config));
+    assert_eq!(
+        core.report_ready_at(&QueueClass::DmarcReportHeader(event.clone())),
+        Some(1000 + delivery_jitter("example.org", window))
+    );
+    assert_eq!(
+        core.report_ready_at(&QueueClass::TlsReportHeader(event)),
+        Some(1000)
+    );
+}
+
+#[test]
+fn report_split_by_size() {
+    // Header serializes to 3 bytes, each record to 4 bytes
+    let records = ["aa", "bb", "cc"];
+    assert_eq!(
+        split_by_size(&"h", records, 15),
+        vec![vec!["aa", "bb", "cc"]]
+    );
+    assert_eq!(
+        split_by_size(&"h", records, 14),
+        vec![vec!["aa", "bb"], vec!["cc"]]
+    );
+    for max_size in [7, 1] {
+        assert_eq!(
+            split_by_size(&"h", records, max_size),
+            vec![vec!["aa"], vec!["bb"], vec!["cc"]]
+        );
+    }
+    assert_eq!(
+        split_by_size(&"h", Vec::<&str>::new(), 15),
+        vec![Vec::<&str>::new()]
+    );
+
+    assert_eq!(part_report_id("x".to_string(), 0, 1), "x");
+    assert_eq!(part_report_id("x".to_string(), 1, 3), "x_2");
+}
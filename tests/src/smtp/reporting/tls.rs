@@ -23,6 +23,7 @@
 
 use std::{io::Read, sync::Arc, time::Duration};
 
+use ahash::AHashSet;
 use mail_auth::{
     common::parse::TxtRecordParser,
     flate2::read::GzDecoder,
@@ -33,12 +34,12 @@ use store::write::QueueClass;
 use utils::config::if_block::IfBlock;
 
 use crate::smtp::{
-    inbound::{sign::TextConfigContext, TestMessage},
+    inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
     session::VerifyResponse,
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, ConfigContext},
+    config::{AggregateFrequency, ConfigContext, ReportConfig},
     core::SMTP,
     reporting::{tls::TLS_HTTP_REPORT, TlsEvent},
 };
@@ -56,13 +57,14 @@ async fn report_tls() {
     // Create scheduler
     let mut core = SMTP::test();
     core.shared.signers = ConfigContext::new(&[]).parse_signatures().signers;
-    let config = &mut core.report.config;
+    let mut config = ReportConfig::test();
     config.tls.sign = "\"['rsa']\"".parse_if();
-    config.tls.max_size = IfBlock::new(1532);
+    config.tls.max_size = IfBlock::new(1024);
     config.submitter = IfBlock::new("mx.example.org".to_string());
     config.tls.address = IfBlock::new("reports@example.org".to_string());
     config.tls.org_name = IfBlock::new("Foobar, Inc.".to_string());
     config.tls.contact_info = IfBlock::new("https://foobar.org/contact".to_string());
+    core.report.config.store(Arc::new(config));
 
     // Create temp dir for queue
     let mut qr = core.init_test_queue("smtp_report_tls_test");
@@ -101,7 +103,7 @@ async fn report_tls() {
             ResultType::StsPolicyInvalid,
         ),
         (
-            smtp::reporting::PolicyType::Sts(None), // Quota limited at 1532 bytes, this should not be included in the report.
+            smtp::reporting::PolicyType::Sts(None),
             ResultType::StsWebpkiInvalid,
         ),
     ] {
@@ -131,7 +133,7 @@ async fn report_tls() {
     core.generate_tls_report(tls_reports.first().unwrap().domain.clone(), tls_reports)
         .await;
 
-    // Expect report
+    // Expect the report to be split in multiple parts (limited at 1024 bytes)
     let message = qr.expect_message().await;
     assert_eq!(
         message.recipients.last().unwrap().address,
@@ -145,59 +147,47 @@ async fn report_tls() {
         .assert_contains("To: <reports@foobar.org>")
         .assert_contains("Report Domain: foobar.org")
         .assert_contains("Submitter: mx.example.org");
+    let mut reports =
+        vec![TlsReport::parse_rfc5322(message.read_message(&qr).await.as_bytes()).unwrap()];
+    while let Some(event) = qr.try_read_event().await {
+        event.assert_reload();
+        let message = qr.last_queued_message().await;
+        reports.push(TlsReport::parse_rfc5322(message.read_message(&qr).await.as_bytes()).unwrap());
+    }
+    assert!(reports.len() > 1, "expected a split report");
 
-    // Verify generated report
-    let report = TlsReport::parse_rfc5322(message.read_message(&qr).await.as_bytes()).unwrap();
-    assert_eq!(report.organization_name.unwrap(), "Foobar, Inc.");
-    assert_eq!(report.contact_info.unwrap(), "https://foobar.org/contact");
-    assert_eq!(report.policies.len(), 3);
-    let mut seen = [false; 3];
-    for policy in report.policies {
-        match policy.policy.policy_type {
-            PolicyType::Tlsa => {
-                seen[0] = true;
-                assert_eq!(policy.summary.total_failure, 1);
-                assert_eq!(policy.summary.total_success, 0);
-                assert_eq!(policy.policy.policy_domain, "foobar.org");
-                assert_eq!(policy.failure_details.len(), 1);
-                assert_eq!(
-                    policy.failure_details.first().unwrap().result_type,
-                    ResultType::TlsaInvalid
-                );
-            }
-            PolicyType::Sts => {
-                seen[1] = true;
-                assert_eq!(policy.summary.total_failure, 2);
-                assert_eq!(policy.summary.total_success, 0);
-                assert_eq!(policy.policy.policy_domain, "foobar.org");
-                assert_eq!(policy.failure_details.len(), 2);
-                assert!(policy
-                    .failure_details
-                    .iter()
-                    .any(|d| d.result_type == ResultType::StsPolicyFetchError));
-                assert!(policy
-                    .failure_details
-                    .iter()
-                    .any(|d| d.result_type == ResultType::StsPolicyInvalid));
-            }
-            PolicyType::NoPolicyFound => {
-                seen[2] = true;
-                assert_eq!(policy.summary.total_failure, 1);
-                assert_eq!(policy.summary.total_success, 2);
-                assert_eq!(policy.policy.policy_domain, "foobar.org");
-                assert_eq!(policy.failure_details.len(), 1);
-                assert_eq!(
-                    policy.failure_details.first().unwrap().result_type,
-                    ResultType::CertificateExpired
-                );
-            }
-            PolicyType::Other => unreachable!(),
+    // Verify generated reports
+    let mut report_ids = AHashSet::new();
+    let mut summaries = [(0, 0); 3];
+    let mut failures = [vec![], vec![], vec![]];
+    for report in reports {
+        assert_eq!(report.organization_name.unwrap(), "Foobar, Inc.");
+        assert_eq!(report.contact_info.unwrap(), "https://foobar.org/contact");
+        assert!(report_ids.insert(report.report_id));
+        for policy in report.policies {
+            let idx = match policy.policy.policy_type {
+                PolicyType::Tlsa => 0,
+                PolicyType::Sts => 1,
+                PolicyType::NoPolicyFound => 2,
+                PolicyType::Other => unreachable!(),
+            };
+            assert_eq!(policy.policy.policy_domain, "foobar.org");
+            summaries[idx].0 += policy.summary.total_success;
+            summaries[idx].1 += policy.summary.total_failure;
+            failures[idx].extend(policy.failure_details.into_iter().map(|d| d.result_type));
         }
     }
-
-    assert!(seen[0]);
-    assert!(seen[1]);
-    assert!(seen[2]);
+    assert_eq!(summaries, [(0, 1), (0, 3), (2, 1)]);
+    assert_eq!(failures[0], vec![ResultType::TlsaInvalid]);
+    assert_eq!(failures[1].len(), 3);
+    for rt in [
+        ResultType::StsPolicyFetchError,
+        ResultType::StsPolicyInvalid,
+        ResultType::StsWebpkiInvalid,
+    ] {
+        assert!(failures[1].contains(&rt));
+    }
+    assert_eq!(failures[2], vec![ResultType::CertificateExpired]);
 
     // Schedule TLS reports to be delivered via https
     let tls_record = Arc::new(TlsRpt::parse(b"v=TLSRPTv1;rua=https://127.0.0.1/tls").unwrap());
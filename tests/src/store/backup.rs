@@ -0,0 +1,71 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use store::{config::ConfigStore, BlobStore, Store};
+use utils::config::Config;
+
+use super::TempDir;
+
+pub async fn test(store: Store, temp_dir: &TempDir) {
+    println!("Running backup and restore tests...");
+
+    let blob_store = BlobStore::from(store.clone());
+    let mut backup = Vec::new();
+    let stats = store.export_all(&blob_store, &mut backup).await.unwrap();
+    assert!(stats.values > 0, "{stats:?}");
+    assert!(stats.bitmaps > 0, "{stats:?}");
+    assert!(stats.indexes > 0, "{stats:?}");
+
+    // Restore the backup into an empty SQLite store
+    let config = Config::new(&format!(
+        "[store.\"restore\"]\ntype = \"sqlite\"\npath = \"{}/restore.db\"\n",
+        temp_dir.path.to_string_lossy()
+    ))
+    .unwrap();
+    let target = config
+        .parse_stores()
+        .await
+        .unwrap()
+        .stores
+        .remove("restore")
+        .unwrap();
+    let target_blob_store = BlobStore::from(target.clone());
+    assert_eq!(
+        target
+            .import_all(&target_blob_store, &mut backup.as_slice())
+            .await
+            .unwrap(),
+        stats
+    );
+
+    // Exporting the restored store should produce an identical backup
+    let mut restored = Vec::new();
+    assert_eq!(
+        target
+            .export_all(&target_blob_store, &mut restored)
+            .await
+            .unwrap(),
+        stats
+    );
+    assert!(backup == restored, "Restored store contents differ");
+}
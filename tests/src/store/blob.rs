@@ -21,11 +21,14 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use ahash::AHashMap;
 use store::{
     config::ConfigStore,
-    write::{blob::BlobQuota, now, BatchBuilder, BlobOp},
-    BlobClass, BlobStore, Serialize,
+    dispatch::blob::{chunk_boundaries, chunk_hash, BlobHealth},
+    write::{blob::BlobQuota, now, BatchBuilder, BlobOp, ValueClass},
+    BlobChunking, BlobClass, BlobStore, CompressionAlgo, Serialize, Store, ValueKey,
 };
 use utils::{config::Config, BlobHash};
 
@@ -41,8 +44,17 @@ pub async fn blob_tests() {
     for (store_id, blob_store) in &stores.blob_stores {
         println!("Testing blob store {}...", store_id);
         test_store(blob_store.clone()).await;
+
+        println!("Testing compressed blob store {}...", store_id);
+        let blob_store = blob_store.clone().with_compression(CompressionAlgo::Zstd {
+            level: 3,
+            min_size: 64,
+        });
+        test_store(blob_store.clone()).await;
+        test_compression(blob_store).await;
     }
 
+    let replica = stores.blob_stores.get("fs").unwrap().clone();
     for (store_id, store) in stores.stores {
         println!("Testing blob management on store {}...", store_id);
 
@@ -417,6 +429,12 @@ pub async fn blob_tests() {
                     ^ ct
             );
         }
+
+        println!("Testing blob chunking on store {}...", store_id);
+        test_chunking(store.clone()).await;
+
+        println!("Testing blob scrubbing on store {}...", store_id);
+        test_scrub(store, replica.clone()).await;
     }
     temp_dir.delete();
 }
@@ -495,3 +513,315 @@ async fn test_store(store: BlobStore) {
         .unwrap()
         .is_none());
 }
+
+async fn test_compression(store: BlobStore) {
+    // Blobs are readable regardless of the compression settings they were written with
+    let uncompressed = store.clone().with_compression(CompressionAlgo::None);
+    let data = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(100);
+    let hash = BlobHash::from(data.as_slice());
+    store.put_blob(hash.as_slice(), &data).await.unwrap();
+    for store in [&store, &uncompressed] {
+        assert_eq!(
+            store
+                .get_blob(hash.as_slice(), 0..u32::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            data
+        );
+    }
+    for store in [&store, &uncompressed] {
+        assert_eq!(
+            store
+                .get_blob(hash.as_slice(), 1000..2000)
+                .await
+                .unwrap()
+                .unwrap(),
+            &data[1000..2000]
+        );
+    }
+    assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+
+    // Raw blobs that look like compressed blobs are escaped
+    for data in [
+        vec![1u8, 0x28, 0xb5, 0x2f, 0xfd, b'a', b'b', b'c'],
+        vec![0u8, 0x28, 0xb5, 0x2f, 0xfd, b'a', b'b', b'c'],
+    ] {
+        let hash = BlobHash::from(data.as_slice());
+        store.put_blob(hash.as_slice(), &data).await.unwrap();
+        for store in [&store, &uncompressed] {
+            assert_eq!(
+                store
+                    .get_blob(hash.as_slice(), 0..u32::MAX)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                data
+            );
+            assert_eq!(
+                store
+                    .get_blob(hash.as_slice(), 2..6)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                &data[2..6]
+            );
+        }
+        assert!(store.delete_blob(hash.as_slice()).await.unwrap());
+    }
+}
+
+async fn test_chunking(store: Store) {
+    // Chunk boundaries depend on content, not on offsets
+    let mut attachment = Vec::with_capacity(512 * 1024);
+    let mut seed = 1u64;
+    while attachment.len() < 512 * 1024 {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        attachment.extend_from_slice(&seed.to_be_bytes());
+    }
+    let chunks = chunk_boundaries(&attachment, 1024, 4096, 16384);
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|c| c.len() <= 16384));
+    assert_eq!(chunks.last().unwrap().end, attachment.len());
+
+    let blob_store = BlobStore::from(store.clone()).with_chunking(Some(BlobChunking {
+        store: store.clone(),
+        threshold: 64 * 1024,
+        min_size: 1024,
+        avg_size: 4096,
+        max_size: 16384,
+    }));
+
+    // Messages sharing the same attachment share its chunks
+    let mut messages = Vec::new();
+    for header in ["Subject: first message\r\n\r\n", "Subject: second\r\n\r\n"] {
+        let mut message = header.as_bytes().to_vec();
+        message.extend_from_slice(&attachment);
+        let hash = BlobHash::from(message.as_slice());
+        blob_store.put_blob(hash.as_ref(), &message).await.unwrap();
+        messages.push((hash, message));
+    }
+    for (hash, message) in &messages {
+        assert_eq!(
+            blob_store
+                .get_blob(hash.as_ref(), 0..u32::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            *message
+        );
+        assert_eq!(
+            blob_store
+                .get_blob(hash.as_ref(), 20000..90000)
+                .await
+                .unwrap()
+                .unwrap(),
+            &message[20000..90000]
+        );
+    }
+
+    // Deleting one message keeps the shared chunks
+    assert!(blob_store
+        .delete_blob(messages[0].0.as_ref())
+        .await
+        .unwrap());
+    assert!(blob_store
+        .get_blob(messages[0].0.as_ref(), 0..u32::MAX)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        blob_store
+            .get_blob(messages[1].0.as_ref(), 0..u32::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+        messages[1].1
+    );
+
+    // Deleting the last reference removes the chunks
+    assert!(blob_store
+        .delete_blob(messages[1].0.as_ref())
+        .await
+        .unwrap());
+    store.assert_is_empty(blob_store.clone()).await;
+
+    // Blobs sharing chunks can be written and deleted concurrently
+    let shared = chunk_hash(&attachment[chunks[chunks.len() / 2].clone()]);
+    let mut previous: Option<(BlobHash, Vec<u8>)> = None;
+    for round in 0..20 {
+        let mut message = format!("Subject: round {round}\r\n\r\n").into_bytes();
+        message.extend_from_slice(&attachment);
+        let hash = BlobHash::from(message.as_slice());
+
+        let delete = previous.take().map(|(hash, _)| {
+            let blob_store = blob_store.clone();
+            tokio::spawn(async move { blob_store.delete_blob(hash.as_ref()).await })
+        });
+        let put = {
+            let blob_store = blob_store.clone();
+            let hash = hash.clone();
+            let message = message.clone();
+            tokio::spawn(async move { blob_store.put_blob(hash.as_ref(), &message).await })
+        };
+        if let Some(delete) = delete {
+            assert!(delete.await.unwrap().unwrap());
+        }
+        put.await.unwrap().unwrap();
+
+        assert_eq!(
+            blob_store
+                .get_blob(hash.as_ref(), 0..u32::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            message
+        );
+        assert_eq!(chunk_refs(&store, &shared).await, 1);
+        previous = Some((hash, message));
+    }
+    let (hash, _) = previous.unwrap();
+    assert!(blob_store.delete_blob(hash.as_ref()).await.unwrap());
+    assert_eq!(chunk_refs(&store, &shared).await, 0);
+    store.assert_is_empty(blob_store.clone()).await;
+
+    // Chunks left marked for deletion by an interrupted deletion are stored again
+    let mut batch = BatchBuilder::new();
+    batch.set(
+        ValueClass::Blob(BlobOp::ChunkRef {
+            hash: shared.clone(),
+        }),
+        ((1u64 << 63) | (now() - 3600)).serialize(),
+    );
+    store.write(batch.build()).await.unwrap();
+    let mut message = b"Subject: interrupted\r\n\r\n".to_vec();
+    message.extend_from_slice(&attachment);
+    let hash = BlobHash::from(message.as_slice());
+    blob_store.put_blob(hash.as_ref(), &message).await.unwrap();
+    assert_eq!(
+        blob_store
+            .get_blob(hash.as_ref(), 0..u32::MAX)
+            .await
+            .unwrap()
+            .unwrap(),
+        message
+    );
+    assert_eq!(chunk_refs(&store, &shared).await, 1);
+    assert!(blob_store.delete_blob(hash.as_ref()).await.unwrap());
+    store.assert_is_empty(blob_store).await;
+}
+
+async fn test_scrub(store: Store, replica: BlobStore) {
+    let blob_store = BlobStore::from(store.clone());
+    let healthy = BlobHash::from(b"healthy blob".as_slice());
+    let corrupt = BlobHash::from(b"corrupt blob".as_slice());
+    let missing = BlobHash::from(b"missing blob".as_slice());
+    blob_store
+        .put_blob(healthy.as_ref(), b"healthy blob")
+        .await
+        .unwrap();
+    blob_store
+        .put_blob(corrupt.as_ref(), b"c0rrupt blob")
+        .await
+        .unwrap();
+    let mut batch = BatchBuilder::new();
+    for hash in [&healthy, &corrupt, &missing] {
+        batch.set(BlobOp::Commit { hash: hash.clone() }, Vec::new());
+    }
+    store.write(batch.build_batch()).await.unwrap();
+
+    // Damaged blobs are quarantined when there is no replica,
+    // verification resumes where the previous batch left off
+    let first = store
+        .scrub_blobs(&blob_store, None, 2, Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(!first.complete);
+    let second = store
+        .scrub_blobs(&blob_store, None, 2, Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(second.complete);
+    assert_eq!(first.verified + second.verified, 1);
+    let mut quarantined = first
+        .quarantined
+        .into_iter()
+        .chain(second.quarantined)
+        .collect::<Vec<_>>();
+    quarantined.sort_by_key(|(hash, _)| hash.as_slice().to_vec());
+    let mut expected = vec![
+        (corrupt.clone(), BlobHealth::Corrupt),
+        (missing.clone(), BlobHealth::Missing),
+    ];
+    expected.sort_by_key(|(hash, _)| hash.as_slice().to_vec());
+    assert_eq!(quarantined, expected);
+    let mut listed = store
+        .list_quarantined_blobs()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect::<Vec<_>>();
+    listed.sort_by_key(|hash| hash.as_slice().to_vec());
+    assert_eq!(
+        listed,
+        expected
+            .iter()
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>()
+    );
+
+    // Damaged blobs are repaired from a healthy copy in the replica
+    replica
+        .put_blob(corrupt.as_ref(), b"corrupt blob")
+        .await
+        .unwrap();
+    replica
+        .put_blob(missing.as_ref(), b"missing blob")
+        .await
+        .unwrap();
+    let scrub = store
+        .scrub_blobs(&blob_store, Some(&replica), 10, Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(scrub.complete);
+    assert_eq!(scrub.verified, 1);
+    assert_eq!(scrub.repaired.len(), 2);
+    assert!(scrub.quarantined.is_empty());
+    for (hash, data) in [(&corrupt, b"corrupt blob"), (&missing, b"missing blob")] {
+        assert_eq!(
+            blob_store
+                .get_blob(hash.as_ref(), 0..u32::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+            data
+        );
+        assert!(replica.delete_blob(hash.as_ref()).await.unwrap());
+    }
+    assert!(store.list_quarantined_blobs().await.unwrap().is_empty());
+
+    // Quarantine entries are removed along with their blobs
+    blob_store
+        .put_blob(healthy.as_ref(), b"damaged")
+        .await
+        .unwrap();
+    let scrub = store
+        .scrub_blobs(&blob_store, None, 10, Duration::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(scrub.quarantined, vec![(healthy, BlobHealth::Corrupt)]);
+    store.assert_is_empty(blob_store).await;
+}
+
+async fn chunk_refs(store: &Store, hash: &BlobHash) -> u64 {
+    store
+        .get_value::<u64>(ValueKey::from(ValueClass::Blob(BlobOp::ChunkRef {
+            hash: hash.clone(),
+        })))
+        .await
+        .unwrap()
+        .unwrap_or_default()
+}
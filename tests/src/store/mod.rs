@@ -22,6 +22,7 @@
 */
 
 pub mod assign_id;
+pub mod backup;
 pub mod blob;
 pub mod lookup;
 pub mod ops;
@@ -103,7 +104,8 @@ pub async fn store_tests() {
     }
     ops::test(store.clone()).await;
     query::test(store.clone(), FtsStore::Store(store.clone()), insert).await;
-    assign_id::test(store).await;
+    assign_id::test(store.clone()).await;
+    backup::test(store, &temp_dir).await;
 
     if insert {
         temp_dir.delete();
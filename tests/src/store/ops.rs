@@ -23,7 +23,7 @@
 
 use store::{
     write::{BatchBuilder, ValueClass},
-    Store, ValueKey,
+    IterateParams, Store, ValueKey,
 };
 
 // FDB max value
@@ -142,4 +142,90 @@ pub async fn test(db: Store) {
         // Make sure everything is deleted
         db.assert_is_empty(db.clone().into()).await;
     }
+
+    // Paged iteration
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(0).with_collection(0);
+    for document_id in 0..100 {
+        batch.update_document(document_id).set(
+            ValueClass::Property(0),
+            format!("doc{document_id}").as_bytes(),
+        );
+    }
+    db.write(batch.build_batch()).await.unwrap();
+
+    for ascending in [true, false] {
+        for page_size in [1, 7, 50, 100, 1000] {
+            let mut values = Vec::new();
+            let mut cursor = None;
+            let mut pages = 0;
+            loop {
+                cursor = db
+                    .iterate_paged(
+                        IterateParams::new(
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: 0,
+                                class: ValueClass::Property(0),
+                            },
+                            ValueKey {
+                                account_id: 0,
+                                collection: 0,
+                                document_id: u32::MAX,
+                                class: ValueClass::Property(0),
+                            },
+                        )
+                        .set_ascending(ascending),
+                        cursor.as_deref(),
+                        page_size,
+                        |_, value| {
+                            values.push(String::from_utf8(value.to_vec()).unwrap());
+                            Ok(true)
+                        },
+                    )
+                    .await
+                    .unwrap();
+                pages += 1;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+
+            assert_eq!(values.len(), 100, "page_size {page_size}");
+            assert!(pages >= 100 / page_size, "page_size {page_size}");
+            let mut sorted_values = values.clone();
+            sorted_values.sort_unstable();
+            sorted_values.dedup();
+            assert_eq!(sorted_values.len(), 100, "page_size {page_size}");
+        }
+    }
+
+    // Chunked range deletion
+    let mut progress = Vec::new();
+    assert_eq!(
+        db.delete_range_chunked(
+            ValueKey {
+                account_id: 0,
+                collection: 0,
+                document_id: 0,
+                class: ValueClass::Property(0),
+            },
+            ValueKey {
+                account_id: 0,
+                collection: 0,
+                document_id: u32::MAX,
+                class: ValueClass::Property(0),
+            },
+            7,
+            3,
+            |deleted| progress.push(deleted),
+        )
+        .await
+        .unwrap(),
+        100
+    );
+    assert_eq!(progress.last(), Some(&100));
+    assert!(progress.windows(2).all(|w| w[0] < w[1]));
+    db.assert_is_empty(db.clone().into()).await;
 }